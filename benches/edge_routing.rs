@@ -0,0 +1,78 @@
+//! Time to reroute a large workflow graph per frame while a node is dragged
+//!
+//! The editor reroutes on every frame of a drag, so one update of a graph
+//! of 100 nodes and 200 connections should fit well within a 16 ms frame.
+
+use std::time::{Duration, Instant};
+
+use egui::{Pos2, Vec2};
+use vistle::ui::{Connection, EdgeRouter, WorkflowNode};
+
+const FRAMES: u32 = 600;
+
+fn connect(from_node: usize, to_node: usize) -> Connection {
+    Connection {
+        from_node,
+        from_port: "data_out".to_string(),
+        to_node,
+        to_port: "data_in".to_string(),
+    }
+}
+
+/// 100 nodes on a grid with 200 connections to their neighbours
+fn graph() -> (Vec<WorkflowNode>, Vec<Connection>) {
+    let nodes = (0..100)
+        .map(|i| WorkflowNode::new("Node", "", "Bench").with_position(Pos2::new((i % 10) as f32 * 200.0, (i / 10) as f32 * 140.0)))
+        .collect();
+    let mut connections = Vec::new();
+    for i in 0..100 {
+        if i % 10 < 9 {
+            connections.push(connect(i, i + 1));
+        }
+        if i < 90 {
+            connections.push(connect(i, i + 10));
+        }
+    }
+    connections.extend((0..20).map(|i| connect(i, i + 11)));
+    (nodes, connections)
+}
+
+/// Mean time per frame to drag `node` and reroute, and the connections rerouted in total
+fn drag(node: usize) -> (Duration, usize) {
+    let (mut nodes, connections) = graph();
+    let mut router = EdgeRouter::new();
+    router.update(&nodes, &connections);
+
+    let mut rerouted = 0;
+    let started = Instant::now();
+    for frame in 0..FRAMES {
+        let step = if frame % 120 < 60 { 2.0 } else { -2.0 };
+        nodes[node].position += Vec2::new(step, step / 2.0);
+        rerouted += router.update(&nodes, &connections);
+    }
+    (started.elapsed() / FRAMES, rerouted)
+}
+
+/// Mean time to route the whole graph from scratch
+fn full() -> Duration {
+    let (nodes, connections) = graph();
+    let started = Instant::now();
+    for _ in 0..FRAMES / 10 {
+        std::hint::black_box(EdgeRouter::new().update(&nodes, &connections));
+    }
+    started.elapsed() / (FRAMES / 10)
+}
+
+fn main() {
+    println!("Rerouting 100 nodes and 200 connections, {} frames", FRAMES);
+    println!("  full reroute:        {:>8.1?} per frame", full());
+    for (label, node) in [("corner node", 0), ("central node", 55)] {
+        let (per_frame, rerouted) = drag(node);
+        println!(
+            "  dragging {:<12} {:>8.1?} per frame, {:.1} connections rerouted per frame",
+            format!("{}:", label),
+            per_frame,
+            rerouted as f64 / FRAMES as f64
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Frame times of a camera orbit around static geometry, with and without buffer reuse
+//!
+//! Every frame of an orbit changes only the camera, so the GPU resource
+//! cache should upload the geometry once. The same orbit with the cache
+//! flushed before each frame shows what re-uploading costs.
+
+use std::time::{Duration, Instant};
+
+use nalgebra::Vector3;
+use vistle::render::{Camera, Geometry, Material, RenderTarget, Renderer, Scene, SceneObject, WgpuRenderer};
+
+const FRAMES: usize = 120;
+/// Vertices along each side of the benchmark surface
+const SIDE: usize = 400;
+
+/// A height field of about 320k triangles
+fn surface() -> SceneObject {
+    let positions = (0..SIDE * SIDE)
+        .map(|i| {
+            let (x, y) = ((i % SIDE) as f32 / SIDE as f32, (i / SIDE) as f32 / SIDE as f32);
+            Vector3::new(x, y, (x * 12.0).sin() * (y * 9.0).cos() * 0.1)
+        })
+        .collect();
+    let mut indices = Vec::with_capacity((SIDE - 1) * (SIDE - 1) * 6);
+    for j in 0..SIDE as u32 - 1 {
+        for i in 0..SIDE as u32 - 1 {
+            let corner = j * SIDE as u32 + i;
+            indices.extend([corner, corner + 1, corner + SIDE as u32]);
+            indices.extend([corner + 1, corner + SIDE as u32 + 1, corner + SIDE as u32]);
+        }
+    }
+    SceneObject::new(Geometry::Triangles { positions, indices }, Material::default()).with_name("surface")
+}
+
+async fn orbit(renderer: &mut WgpuRenderer, scene: &mut Scene, flush: bool) -> Duration {
+    let target = RenderTarget { width: 256, height: 256, format: wgpu::TextureFormat::Rgba8Unorm };
+    let started = Instant::now();
+    for frame in 0..FRAMES {
+        let angle = frame as f32 / FRAMES as f32 * std::f32::consts::TAU;
+        scene.camera_mut().position = Vector3::new(0.5 + 2.0 * angle.cos(), 0.5 + 2.0 * angle.sin(), 1.0);
+        if flush {
+            renderer.flush();
+        }
+        renderer.render(scene, &target).await.expect("frame failed");
+    }
+    started.elapsed() / FRAMES as u32
+}
+
+#[tokio::main]
+async fn main() {
+    let mut renderer = match WgpuRenderer::new().await {
+        Ok(renderer) => renderer,
+        Err(e) => {
+            eprintln!("No GPU adapter, nothing to measure: {}", e);
+            return;
+        }
+    };
+    let mut scene = Scene::new(Camera::default()).with_object(surface());
+
+    // The first frame uploads the geometry either way
+    orbit(&mut renderer, &mut scene, false).await;
+    let cached = orbit(&mut renderer, &mut scene, false).await;
+    let uploaded = orbit(&mut renderer, &mut scene, true).await;
+
+    let stats = renderer.cache_stats();
+    println!("orbit of {} frames, {} MiB resident", FRAMES, stats.resident_bytes >> 20);
+    println!("  buffers reused:   {:>10.3?} per frame", cached);
+    println!("  buffers uploaded: {:>10.3?} per frame", uploaded);
+    println!("  {} hits, {} misses, {} evictions", stats.hits, stats.misses, stats.evictions);
+}
@@ -0,0 +1,45 @@
+//! Cost of latency tracing on the local routing path
+//!
+//! Passes messages through a module queue, which is where local latencies
+//! are recorded, with tracing off and on. Off should cost no more than the
+//! relaxed load of the check.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use vistle::core::{
+    Message, MessageEnvelope, MessagePayload, MessageQueue, MessageReceiver, MessageSender, MessageType, Route,
+    RouterMetrics,
+};
+
+const MESSAGES: u32 = 1_000_000;
+
+/// Mean time to enqueue and dequeue one message
+async fn pass(queue: &mut MessageQueue) -> Duration {
+    let started = Instant::now();
+    for _ in 0..MESSAGES {
+        let envelope = MessageEnvelope { message: Message::new(2, 1, MessageType::Quit), payload: MessagePayload::None };
+        queue.send_message(envelope).await.expect("queue closed");
+        std::hint::black_box(queue.receive_message().await.expect("queue closed"));
+    }
+    started.elapsed() / MESSAGES
+}
+
+#[tokio::main]
+async fn main() {
+    let metrics = Arc::new(RouterMetrics::new());
+    let mut queue = MessageQueue::new().with_metrics(metrics.clone(), Route::Local { module_id: 1 });
+    let mut bare = MessageQueue::new();
+
+    pass(&mut bare).await;
+    let untimed = pass(&mut bare).await;
+    let off = pass(&mut queue).await;
+    metrics.set_enabled(true);
+    let on = pass(&mut queue).await;
+
+    println!("{} messages through a local queue", MESSAGES);
+    println!("  without metrics: {:>8.1?} per message", untimed);
+    println!("  tracing off:     {:>8.1?} per message", off);
+    println!("  tracing on:      {:>8.1?} per message", on);
+    println!("  {} latencies recorded", metrics.histogram(Route::Local { module_id: 1 }).count());
+}
@@ -0,0 +1,418 @@
+//! Auditing module outputs for NaN and infinite values
+//!
+//! With `WorkflowExecutor::with_audit`, the float arrays of every output a
+//! module returns are scanned for values that are not finite. Counts are
+//! kept per output port in `TaskResult::nonfinite` and add up per
+//! connection in `ConnectionStats`. The first module whose outputs hold
+//! non-finite values while its inputs held none introduced them; it is
+//! reported as `WorkflowResult::first_nonfinite`, and with `fail_fast` the
+//! run stops there.
+//!
+//! Arrays larger than `AuditConfig::sample_threshold` are sampled in
+//! evenly spread windows, so counts are then lower bounds. Curve values are
+//! not scanned, as NaN marks gaps there, nor are AMR hierarchies, whose data
+//! lives in other objects.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{ConnectionSpec, OutputPorts, TaskResult};
+use crate::core::{ImageData, Object, ObjectPayload};
+use crate::util::math::{count_nonfinite, count_nonfinite_f64};
+
+/// Values of an array scanned in full; larger arrays are sampled
+pub const AUDIT_SAMPLE_THRESHOLD: usize = 1 << 22;
+
+/// Values per sampled window
+const SAMPLE_WINDOW: usize = 4096;
+
+/// Settings of the NaN/Inf audit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Arrays longer than this are sampled, scanning about this many values
+    pub sample_threshold: usize,
+    /// Stop the run at the first module introducing non-finite values; local
+    /// runs are audited once all their tasks finished, so only runs on hub
+    /// hosts stop early
+    pub fail_fast: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            sample_threshold: AUDIT_SAMPLE_THRESHOLD,
+            fail_fast: false,
+        }
+    }
+}
+
+impl AuditConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sample_threshold(mut self, values: usize) -> Self {
+        self.sample_threshold = values.max(SAMPLE_WINDOW);
+        self
+    }
+
+    pub fn fail_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+}
+
+/// Non-finite values found on one output port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortAudit {
+    pub nonfinite: usize,
+    /// Values looked at
+    pub scanned: usize,
+    /// Float values on the port
+    pub total: usize,
+}
+
+impl PortAudit {
+    pub fn is_clean(&self) -> bool {
+        self.nonfinite == 0
+    }
+
+    /// Some arrays were sampled, so `nonfinite` is a lower bound
+    pub fn is_sampled(&self) -> bool {
+        self.scanned < self.total
+    }
+
+    fn add(&mut self, other: PortAudit) {
+        self.nonfinite += other.nonfinite;
+        self.scanned += other.scanned;
+        self.total += other.total;
+    }
+}
+
+/// The module that first produced non-finite values from finite inputs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NonFiniteOffender {
+    pub module_id: u32,
+    /// Output ports with non-finite values
+    pub ports: BTreeMap<String, PortAudit>,
+}
+
+impl NonFiniteOffender {
+    /// Offender with the ports of `audits` that hold non-finite values
+    pub fn new(module_id: u32, audits: &BTreeMap<String, PortAudit>) -> Self {
+        Self {
+            module_id,
+            ports: audits.iter()
+                .filter(|(_, audit)| !audit.is_clean())
+                .map(|(port, audit)| (port.clone(), *audit))
+                .collect(),
+        }
+    }
+
+    pub fn nonfinite(&self) -> usize {
+        self.ports.values().map(|p| p.nonfinite).sum()
+    }
+}
+
+impl std::fmt::Display for NonFiniteOffender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ports: Vec<String> = self.ports.iter()
+            .map(|(port, audit)| format!("{} on {}", audit.nonfinite, port))
+            .collect();
+        write!(f, "module {} introduced non-finite values: {}", self.module_id, ports.join(", "))
+    }
+}
+
+/// Scan the float arrays of every output port
+///
+/// Arrays are scanned in parallel on the current rayon pool; run this on a
+/// `CpuPool` rather than an async worker.
+pub fn audit_ports(ports: &OutputPorts, config: &AuditConfig) -> BTreeMap<String, PortAudit> {
+    ports.iter()
+        .map(|(port, objects)| {
+            let mut audit = PortAudit::default();
+            for object in objects {
+                audit.add(audit_object(object.as_ref(), config));
+            }
+            (port.clone(), audit)
+        })
+        .collect()
+}
+
+/// An object's single and double precision arrays, borrowed where contiguous
+type FloatArrays<'a> = (Vec<Cow<'a, [f32]>>, Vec<Cow<'a, [f64]>>);
+
+/// Scan the float arrays of one object
+pub fn audit_object(object: &dyn Object, config: &AuditConfig) -> PortAudit {
+    let Some(payload) = object.payload() else {
+        return PortAudit::default();
+    };
+    let decompressed;
+    let payload = match payload {
+        ObjectPayload::Lossy(lossy) => {
+            match lossy.decompress() {
+                Ok(payload) => decompressed = payload,
+                Err(e) => {
+                    tracing::warn!("Object {} is not audited: {}", object.id(), e);
+                    return PortAudit::default();
+                }
+            }
+            &decompressed
+        }
+        payload => payload,
+    };
+
+    let (singles, doubles): FloatArrays<'_> = match payload {
+        ObjectPayload::Points { coordinates }
+        | ObjectPayload::Lines { coordinates, .. }
+        | ObjectPayload::Triangles { coordinates, .. }
+        | ObjectPayload::UnstructuredGrid { coordinates, .. }
+        | ObjectPayload::StructuredGrid { coordinates, .. }
+        | ObjectPayload::Polygons { coordinates, .. }
+        | ObjectPayload::Quads { coordinates, .. } => (vec![contiguous(coordinates.view().into_dyn())], Vec::new()),
+        ObjectPayload::RectilinearGrid { x, y, z } => (
+            [x, y, z].into_iter().map(|axis| contiguous(axis.view().into_dyn())).collect(),
+            Vec::new(),
+        ),
+        ObjectPayload::VecScalar { data } => (vec![contiguous(data.view().into_dyn())], Vec::new()),
+        ObjectPayload::VecVec3 { data } => (vec![contiguous(data.view().into_dyn())], Vec::new()),
+        ObjectPayload::UniformGrid { values, .. } => (vec![contiguous(values.view().into_dyn())], Vec::new()),
+        ObjectPayload::Table { columns } => (
+            Vec::new(),
+            columns.iter().map(|(_, column)| contiguous(column.view().into_dyn())).collect(),
+        ),
+        ObjectPayload::VecScalarF64 { data } => (Vec::new(), vec![contiguous(data.view().into_dyn())]),
+        ObjectPayload::VecVec3F64 { data } => (Vec::new(), vec![contiguous(data.view().into_dyn())]),
+        ObjectPayload::Image { data: ImageData::F32(data), .. } => (vec![contiguous(data.view().into_dyn())], Vec::new()),
+        ObjectPayload::LayeredImage { bands, .. } => (
+            bands.iter().map(|band| contiguous(band.values.view().into_dyn())).collect(),
+            Vec::new(),
+        ),
+        _ => return PortAudit::default(),
+    };
+
+    singles.par_iter()
+        .map(|values| scan(values, config, count_nonfinite))
+        .chain(doubles.par_iter().map(|values| scan(values, config, count_nonfinite_f64)))
+        .reduce(PortAudit::default, |mut a, b| {
+            a.add(b);
+            a
+        })
+}
+
+/// The values of an array, copied only if they are not contiguous
+fn contiguous<T: Copy>(array: ndarray::ArrayViewD<'_, T>) -> Cow<'_, [T]> {
+    match array.to_slice() {
+        Some(values) => Cow::Borrowed(values),
+        None => Cow::Owned(array.iter().copied().collect()),
+    }
+}
+
+/// Count one array, in full or in windows spread evenly over it
+fn scan<T>(values: &[T], config: &AuditConfig, count: fn(&[T]) -> usize) -> PortAudit {
+    if values.len() <= config.sample_threshold {
+        return PortAudit {
+            nonfinite: count(values),
+            scanned: values.len(),
+            total: values.len(),
+        };
+    }
+    let windows = (config.sample_threshold / SAMPLE_WINDOW).max(1);
+    let stride = values.len() / windows;
+    let (nonfinite, scanned) = (0..windows)
+        .map(|i| {
+            let window = &values[i * stride..(i * stride + SAMPLE_WINDOW).min(values.len())];
+            (count(window), window.len())
+        })
+        .fold((0, 0), |(n, s), (wn, ws)| (n + wn, s + ws));
+    PortAudit {
+        nonfinite,
+        scanned,
+        total: values.len(),
+    }
+}
+
+/// Whether a module's outputs hold non-finite values its inputs did not
+///
+/// `inputs` are the audits of the upstream ports connected to the module.
+pub fn introduces_nonfinite<'a>(
+    outputs: &BTreeMap<String, PortAudit>,
+    inputs: impl IntoIterator<Item = &'a PortAudit>,
+) -> bool {
+    outputs.values().any(|a| !a.is_clean()) && inputs.into_iter().all(PortAudit::is_clean)
+}
+
+/// The first module in `results` introducing non-finite values, judged by the audits of its upstream ports
+pub fn first_nonfinite(connections: &[ConnectionSpec], results: &[TaskResult]) -> Option<NonFiniteOffender> {
+    let audits: BTreeMap<u32, &BTreeMap<String, PortAudit>> = results.iter()
+        .filter_map(|r| Some((r.module_id?, &r.nonfinite)))
+        .collect();
+    results.iter().find_map(|result| {
+        let module_id = result.module_id?;
+        let inputs = connections.iter()
+            .filter(|c| c.to_module == module_id)
+            .filter_map(|c| audits.get(&c.from_module)?.get(&c.from_port));
+        introduces_nonfinite(&result.nonfinite, inputs).then(|| NonFiniteOffender::new(module_id, &result.nonfinite))
+    })
+}
+
+/// Audit outputs on a CPU pool without blocking the runtime
+pub async fn audit_ports_on(
+    pool: &crate::core::CpuPool,
+    ports: &OutputPorts,
+    config: AuditConfig,
+) -> Result<BTreeMap<String, PortAudit>, crate::Error> {
+    let ports = ports.clone();
+    pool.run(move || audit_ports(&ports, &config)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use ndarray::ShapeBuilder;
+
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{ModuleRegistry, TaskExecutor, TaskId, WorkflowBuilder, WorkflowExecutor};
+    use crate::core::{LossyPayload, MessageRouter, ObjectType, VistleObject};
+
+    fn audit(payload: ObjectPayload) -> PortAudit {
+        audit_object(&VistleObject::with_data(ObjectType::Vec, payload), &AuditConfig::default())
+    }
+
+    fn scalars(values: Vec<f32>) -> ObjectPayload {
+        ObjectPayload::VecScalar { data: ndarray::Array1::from(values) }
+    }
+
+    #[test]
+    fn nan_and_both_infinities_are_counted() {
+        let found = audit(scalars(vec![1.0, f32::NAN, f32::INFINITY, -0.0, f32::NEG_INFINITY]));
+        assert_eq!(found, PortAudit { nonfinite: 3, scanned: 5, total: 5 });
+        assert!(!found.is_clean() && !found.is_sampled());
+        assert!(audit(scalars(vec![f32::MAX, f32::MIN_POSITIVE])).is_clean());
+    }
+
+    #[test]
+    fn coordinates_tables_and_transposed_arrays_are_scanned() {
+        let points = ObjectPayload::Points { coordinates: ndarray::array![[0.0, f32::NAN, 0.0], [1.0, 1.0, 1.0]] };
+        assert_eq!(audit(points).nonfinite, 1);
+
+        let table = ObjectPayload::Table {
+            columns: vec![
+                ("t".to_string(), ndarray::Array1::from(vec![0.0, 1.0])),
+                ("p".to_string(), ndarray::Array1::from(vec![f64::NAN, f64::INFINITY])),
+            ],
+        };
+        assert_eq!(audit(table), PortAudit { nonfinite: 2, scanned: 4, total: 4 });
+
+        let column_major = ndarray::Array2::from_shape_vec((2, 3).f(), vec![0.0, f32::NAN, 1.0, 2.0, 3.0, f32::INFINITY]).unwrap();
+        assert!(column_major.as_slice().is_none());
+        assert_eq!(audit(ObjectPayload::VecVec3 { data: column_major }).nonfinite, 2);
+    }
+
+    #[test]
+    fn curve_gaps_are_not_counted_and_compressed_fields_are() {
+        let curve = ObjectPayload::Curve {
+            x: ndarray::Array1::from(vec![0.0, 1.0]),
+            y: ndarray::Array1::from(vec![f64::NAN, 1.0]),
+            x_label: "x".to_string(),
+            y_label: "y".to_string(),
+        };
+        assert_eq!(audit(curve), PortAudit::default());
+
+        let lossy = LossyPayload::compress(&scalars(vec![1.0, f32::NAN, 2.0]), 0.1).unwrap();
+        assert_eq!(audit(ObjectPayload::Lossy(lossy)).nonfinite, 1);
+    }
+
+    #[test]
+    fn long_arrays_are_sampled_in_windows() {
+        let mut values = vec![0.0; 100_000];
+        // Index 0 lies in the first window, 60 000 between the two
+        values[0] = f32::NAN;
+        values[60_000] = f32::NAN;
+        let config = AuditConfig::new().with_sample_threshold(2 * SAMPLE_WINDOW);
+        let found = audit_object(&VistleObject::with_data(ObjectType::Vec, scalars(values)), &config);
+        assert_eq!(found, PortAudit { nonfinite: 1, scanned: 2 * SAMPLE_WINDOW, total: 100_000 });
+        assert!(found.is_sampled());
+
+        assert_eq!(AuditConfig::new().with_sample_threshold(10).sample_threshold, SAMPLE_WINDOW);
+    }
+
+    #[test]
+    fn ports_add_up_their_objects() {
+        let object = |values: Vec<f32>| -> Arc<dyn Object> { Arc::new(VistleObject::with_data(ObjectType::Vec, scalars(values))) };
+        let ports = OutputPorts::from([
+            ("data_out".to_string(), vec![object(vec![f32::NAN, 1.0]), object(vec![f32::INFINITY])]),
+            ("clean".to_string(), vec![object(vec![1.0])]),
+        ]);
+        let audits = audit_ports(&ports, &AuditConfig::default());
+        assert_eq!(audits["data_out"], PortAudit { nonfinite: 2, scanned: 3, total: 3 });
+        assert!(audits["clean"].is_clean());
+
+        let offender = NonFiniteOffender::new(4, &audits);
+        assert_eq!(offender.nonfinite(), 2);
+        assert_eq!(offender.to_string(), "module 4 introduced non-finite values: 2 on data_out");
+    }
+
+    fn result(module_id: u32, nonfinite: usize) -> TaskResult {
+        TaskResult {
+            task_id: TaskId::default(),
+            module_id: Some(module_id),
+            success: true,
+            outputs: None,
+            error: None,
+            execution_time: Duration::ZERO,
+            nonfinite: BTreeMap::from([("data_out".to_string(), PortAudit { nonfinite, scanned: 4, total: 4 })]),
+        }
+    }
+
+    fn connection(from_module: u32, to_module: u32) -> ConnectionSpec {
+        ConnectionSpec {
+            from_module,
+            from_port: "data_out".to_string(),
+            to_module,
+            to_port: "data_in".to_string(),
+        }
+    }
+
+    #[test]
+    fn the_offender_is_the_first_module_with_clean_inputs() {
+        // 1 -> 2 -> 3, with 2 and 3 holding non-finite values; 3 only passed them on
+        let results = [result(1, 0), result(2, 4), result(3, 4)];
+        let connections = [connection(1, 2), connection(2, 3)];
+        assert_eq!(first_nonfinite(&connections, &results).unwrap().module_id, 2);
+
+        // Without connections every module's inputs count as clean
+        assert_eq!(first_nonfinite(&[], &[result(1, 0), result(3, 4), result(2, 4)]).unwrap().module_id, 3);
+        assert_eq!(first_nonfinite(&connections, &[result(1, 0), result(2, 0)]), None);
+    }
+
+    #[tokio::test]
+    async fn audited_runs_name_the_module_introducing_nan() {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        let executor = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()))
+            .with_audit(AuditConfig::new());
+        let spec = WorkflowBuilder::new("audited", "Audited")
+            .add_module("ConstantField", "Source")
+            .add_module("ConstantField", "Divide")
+                .parameter("value", "NaN")
+                .depends_on(1)
+            .add_module("ConstantField", "Render")
+                .parameter("value", "NaN")
+                .depends_on(2)
+            .connect(1, "data_out", 2, "data_in")
+            .connect(2, "data_out", 3, "data_in")
+            .build();
+
+        let result = executor.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap();
+        let offender = result.first_nonfinite.expect("the audit found no offender");
+        assert_eq!(offender.module_id, 2);
+        assert_eq!(offender.ports["data_out"].nonfinite, 4);
+    }
+}
@@ -0,0 +1,742 @@
+//! Conversion between cell-centered and point-centered data fields
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+
+use crate::core::{
+    attribute, data_type, CellType, ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectPayload, ObjectType,
+    Parameter, ParameterSet, ParameterValue, Port, PortSet, UnstructuredGridView, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::clip::tetrahedra;
+use super::{empty_output, required_input};
+
+/// Point incidence of every cell of a grid (CSR layout)
+#[derive(Debug, Clone)]
+pub struct CellIncidence {
+    num_points: usize,
+    offsets: Vec<usize>,
+    points: Vec<usize>,
+    volumes: Option<Vec<f32>>,
+}
+
+impl CellIncidence {
+    /// Build incidence from explicit per-cell point lists
+    pub fn from_cells<I>(num_points: usize, cells: I) -> Result<Self, crate::Error>
+    where
+        I: IntoIterator<Item = Vec<usize>>,
+    {
+        let mut offsets = vec![0];
+        let mut points = Vec::new();
+
+        for cell in cells {
+            if let Some(&p) = cell.iter().find(|&&p| p >= num_points) {
+                return Err(crate::Error::Compute(format!(
+                    "Cell references point {} but grid has {} points",
+                    p, num_points
+                )));
+            }
+            points.extend(cell);
+            offsets.push(points.len());
+        }
+
+        Ok(Self {
+            num_points,
+            offsets,
+            points,
+            volumes: None,
+        })
+    }
+
+    /// Build incidence for a grid with structured topology (uniform, rectilinear or structured)
+    ///
+    /// Axes with a single point are collapsed, so a `(n, m, 1)` grid yields
+    /// quads. Every axis needs at least one point.
+    pub fn structured(dims: [usize; 3]) -> Result<Self, crate::Error> {
+        if dims.contains(&0) {
+            return Err(crate::Error::Compute(format!(
+                "Grid dimensions {:?} have no points along an axis",
+                dims
+            )));
+        }
+        let [nx, ny, nz] = dims;
+        let cells_along = |n: usize| if n > 1 { n - 1 } else { 1 };
+        let step = |n: usize| if n > 1 { 1 } else { 0 };
+        let index = |i: usize, j: usize, k: usize| (k * ny + j) * nx + i;
+
+        let mut offsets = vec![0];
+        let mut points = Vec::new();
+
+        for k in 0..cells_along(nz) {
+            for j in 0..cells_along(ny) {
+                for i in 0..cells_along(nx) {
+                    let mut cell = Vec::with_capacity(8);
+                    for dk in 0..=step(nz) {
+                        for dj in 0..=step(ny) {
+                            for di in 0..=step(nx) {
+                                cell.push(index(i + di, j + dj, k + dk));
+                            }
+                        }
+                    }
+                    points.extend(cell);
+                    offsets.push(points.len());
+                }
+            }
+        }
+
+        Ok(Self {
+            num_points: nx * ny * nz,
+            offsets,
+            points,
+            volumes: None,
+        })
+    }
+
+    /// Structured incidence with the cell volumes of points at `position(index)`
+    fn structured_with_volumes(dims: [usize; 3], position: impl Fn(usize) -> Vector3<f32>) -> Result<Self, crate::Error> {
+        let mut incidence = Self::structured(dims)?;
+        let volumes = (0..incidence.num_cells())
+            .map(|cell| {
+                let corners: Vec<_> = incidence.cell(cell).iter().map(|&p| position(p)).collect();
+                structured_cell_volume(&corners)
+            })
+            .collect();
+        incidence.volumes = Some(volumes);
+        Ok(incidence)
+    }
+
+    /// Build incidence from a geometry payload
+    ///
+    /// Every cell gets its volume, or its area or length for surface and line
+    /// cells. Uniform, rectilinear and structured grids use `structured`;
+    /// unstructured grids use the vertices of each cell.
+    pub fn from_payload(payload: &ObjectPayload) -> Result<Self, crate::Error> {
+        match payload {
+            ObjectPayload::Triangles { coordinates, triangles } => {
+                let cells = triangles.outer_iter()
+                    .map(|t| vertex_indices(t.iter(), coordinates.nrows()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let volumes = cells.iter()
+                    .map(|t| triangle_area(coordinates, t[0], t[1], t[2]))
+                    .collect();
+                let mut incidence = Self::from_cells(coordinates.nrows(), cells)?;
+                incidence.volumes = Some(volumes);
+                Ok(incidence)
+            }
+            ObjectPayload::Lines { coordinates, connections } => {
+                let cells = connections.outer_iter()
+                    .map(|c| vertex_indices(c.iter(), coordinates.nrows()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let volumes = cells.iter()
+                    .map(|c| {
+                        let a = coordinates.row(c[0]);
+                        let b = coordinates.row(c[1]);
+                        (&b - &a).mapv(|x| x * x).sum().sqrt()
+                    })
+                    .collect();
+                let mut incidence = Self::from_cells(coordinates.nrows(), cells)?;
+                incidence.volumes = Some(volumes);
+                Ok(incidence)
+            }
+            ObjectPayload::UniformGrid { dims, origin, spacing, .. } => {
+                let [nx, ny, _] = *dims;
+                Self::structured_with_volumes(*dims, |p| {
+                    let index = [p % nx, (p / nx) % ny, p / (nx * ny)];
+                    Vector3::from_fn(|axis, _| origin[axis] + spacing[axis] * index[axis] as f32)
+                })
+            }
+            ObjectPayload::RectilinearGrid { x, y, z } => {
+                let (nx, ny) = (x.len(), y.len());
+                Self::structured_with_volumes([nx, ny, z.len()], |p| {
+                    Vector3::new(x[p % nx], y[(p / nx) % ny], z[p / (nx * ny)])
+                })
+            }
+            ObjectPayload::StructuredGrid { dims, coordinates } => {
+                let num_points = dims.iter().product::<usize>();
+                if coordinates.nrows() != num_points || coordinates.ncols() < 3 {
+                    return Err(crate::Error::Compute(format!(
+                        "Structured grid of {:?} points has {}x{} coordinates",
+                        dims, coordinates.nrows(), coordinates.ncols()
+                    )));
+                }
+                Self::structured_with_volumes(*dims, |p| point(coordinates, p))
+            }
+            ObjectPayload::UnstructuredGrid { coordinates, .. } => {
+                let view = UnstructuredGridView::new(payload).expect("payload is an unstructured grid");
+                let mut volumes = Vec::with_capacity(view.num_cells());
+                let cells = (0..view.num_cells())
+                    .map(|i| {
+                        let (cell_type, vertices) = view.cell(i).ok_or_else(|| crate::Error::Compute(format!(
+                            "Cell {} has offsets outside the connectivity of {} entries",
+                            i,
+                            view.connectivity().len()
+                        )))?;
+                        let vertices = vertex_indices(vertices.iter(), view.num_vertices())?;
+                        volumes.push(cell_volume(coordinates, cell_type, &vertices)?);
+                        Ok(vertices)
+                    })
+                    .collect::<Result<Vec<_>, crate::Error>>()?;
+                let mut incidence = Self::from_cells(view.num_vertices(), cells)?;
+                incidence.volumes = Some(volumes);
+                Ok(incidence)
+            }
+            _ => Err(crate::Error::Compute(
+                "Cell/point conversion requires a grid or mesh with cell connectivity".to_string(),
+            )),
+        }
+    }
+
+    pub fn num_points(&self) -> usize {
+        self.num_points
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Points incident to a cell
+    pub fn cell(&self, index: usize) -> &[usize] {
+        &self.points[self.offsets[index]..self.offsets[index + 1]]
+    }
+
+    /// Per-cell volumes (area for surfaces, length for lines) if known
+    pub fn volumes(&self) -> Option<&[f32]> {
+        self.volumes.as_deref()
+    }
+}
+
+/// Vertex indices of a cell, checked against the number of points
+fn vertex_indices<'a>(indices: impl Iterator<Item = &'a i32>, num_points: usize) -> Result<Vec<usize>, crate::Error> {
+    indices
+        .map(|&i| match usize::try_from(i) {
+            Ok(index) if index < num_points => Ok(index),
+            _ => Err(crate::Error::Compute(format!(
+                "Cell references point {} but grid has {} points",
+                i, num_points
+            ))),
+        })
+        .collect()
+}
+
+fn point(coordinates: &Array2<f32>, i: usize) -> Vector3<f32> {
+    Vector3::new(coordinates[[i, 0]], coordinates[[i, 1]], coordinates[[i, 2]])
+}
+
+fn triangle_area(coordinates: &Array2<f32>, a: usize, b: usize, c: usize) -> f32 {
+    area(point(coordinates, a), point(coordinates, b), point(coordinates, c))
+}
+
+fn area(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> f32 {
+    0.5 * (b - a).cross(&(c - a)).norm()
+}
+
+fn tetrahedron_volume([a, b, c, d]: [Vector3<f32>; 4]) -> f32 {
+    (b - a).cross(&(c - a)).dot(&(d - a)).abs() / 6.0
+}
+
+/// Volume of an unstructured cell, or area of a surface cell
+fn cell_volume(coordinates: &Array2<f32>, cell_type: CellType, vertices: &[usize]) -> Result<f32, crate::Error> {
+    if vertices.len() != cell_type.num_vertices() {
+        return Err(crate::Error::Compute(format!(
+            "A {} needs {} vertices, got {}",
+            cell_type, cell_type.num_vertices(), vertices.len()
+        )));
+    }
+    let p = |i: usize| point(coordinates, vertices[i]);
+    Ok(match cell_type {
+        CellType::Triangle => area(p(0), p(1), p(2)),
+        CellType::Quad => area(p(0), p(1), p(2)) + area(p(0), p(2), p(3)),
+        _ => tetrahedra(cell_type, vertices)?
+            .into_iter()
+            .map(|tet| tetrahedron_volume(tet.map(|v| point(coordinates, v))))
+            .sum(),
+    })
+}
+
+/// Measure of a structured cell from its corners in `CellIncidence::structured` order
+///
+/// Hexahedra for 3D grids, quads for 2D, segments for 1D; a single point has none.
+fn structured_cell_volume(corners: &[Vector3<f32>]) -> f32 {
+    match corners {
+        [a, b] => (b - a).norm(),
+        // x varies fastest, so the corners go around the quad as 0, 1, 3, 2
+        [a, b, c, d] => area(*a, *b, *d) + area(*a, *d, *c),
+        _ if corners.len() == 8 => {
+            // Six tetrahedra around the diagonal from the first to the last corner
+            const TETS: [[usize; 4]; 6] = [
+                [0, 1, 3, 7], [0, 3, 2, 7], [0, 2, 6, 7],
+                [0, 6, 4, 7], [0, 4, 5, 7], [0, 5, 1, 7],
+            ];
+            TETS.iter().map(|tet| tetrahedron_volume(tet.map(|i| corners[i]))).sum()
+        }
+        _ => 0.0,
+    }
+}
+
+/// Average the values of incident cells per point, skipping NaNs
+///
+/// Points whose incident cells are all NaN (or that have no cells) stay NaN.
+pub fn cell_to_point(incidence: &CellIncidence, values: ArrayView1<f32>, weights: Option<&[f32]>) -> Result<Array1<f32>, crate::Error> {
+    if values.len() != incidence.num_cells() {
+        return Err(crate::Error::Compute(format!(
+            "Cell field has {} values but grid has {} cells",
+            values.len(),
+            incidence.num_cells()
+        )));
+    }
+
+    let mut sum = vec![0.0f64; incidence.num_points()];
+    let mut weight = vec![0.0f64; incidence.num_points()];
+
+    for cell in 0..incidence.num_cells() {
+        let value = values[cell];
+        if value.is_nan() {
+            continue;
+        }
+        let w = weights.map(|w| w[cell] as f64).unwrap_or(1.0);
+        for &p in incidence.cell(cell) {
+            sum[p] += w * value as f64;
+            weight[p] += w;
+        }
+    }
+
+    Ok(sum.iter()
+        .zip(&weight)
+        .map(|(&s, &w)| if w > 0.0 { (s / w) as f32 } else { f32::NAN })
+        .collect())
+}
+
+/// Average the values of each cell's points, skipping NaNs
+pub fn point_to_cell(incidence: &CellIncidence, values: ArrayView1<f32>) -> Result<Array1<f32>, crate::Error> {
+    if values.len() != incidence.num_points() {
+        return Err(crate::Error::Compute(format!(
+            "Point field has {} values but grid has {} points",
+            values.len(),
+            incidence.num_points()
+        )));
+    }
+
+    Ok((0..incidence.num_cells())
+        .map(|cell| {
+            let (sum, count) = incidence.cell(cell).iter()
+                .map(|&p| values[p])
+                .filter(|v| !v.is_nan())
+                .fold((0.0f64, 0usize), |(s, n), v| (s + v as f64, n + 1));
+            if count > 0 { (sum / count as f64) as f32 } else { f32::NAN }
+        })
+        .collect())
+}
+
+/// Apply a scalar conversion to a field payload, component-wise for vectors
+fn convert_field<F>(payload: &ObjectPayload, convert: F) -> Result<ObjectPayload, crate::Error>
+where
+    F: Fn(ArrayView1<f32>) -> Result<Array1<f32>, crate::Error>,
+{
+    match payload {
+        ObjectPayload::VecScalar { data } => Ok(ObjectPayload::VecScalar {
+            data: convert(data.view())?,
+        }),
+        ObjectPayload::VecVec3 { data } => {
+            let columns = data.axis_iter(Axis(1))
+                .map(convert)
+                .collect::<Result<Vec<_>, _>>()?;
+            let views: Vec<_> = columns.iter().map(|c| c.view().insert_axis(Axis(1))).collect();
+            let data = ndarray::concatenate(Axis(1), &views)
+                .map_err(|e| crate::Error::Compute(format!("Failed to assemble vector field: {}", e)))?;
+            Ok(ObjectPayload::VecVec3 { data })
+        }
+        _ => Err(crate::Error::Compute("Input is not a scalar or vector field".to_string())),
+    }
+}
+
+/// Convert every (grid, field) pair of the inputs into a new field
+fn convert_inputs<F>(inputs: &InputPorts, mapping: &str, convert: F) -> Result<Vec<Arc<dyn Object>>, crate::Error>
+where
+    F: Fn(&CellIncidence, ArrayView1<f32>) -> Result<Array1<f32>, crate::Error>,
+{
+    let grids = required_input(inputs, "grid_in")?;
+    let fields = required_input(inputs, "data_in")?;
+
+    if grids.len() != fields.len() && grids.len() != 1 {
+        return Err(crate::Error::Compute(format!(
+            "Got {} grids but {} fields",
+            grids.len(),
+            fields.len()
+        )));
+    }
+
+    let mut outputs: Vec<Arc<dyn Object>> = Vec::with_capacity(fields.len());
+    for (i, field) in fields.iter().enumerate() {
+        let grid = &grids[i.min(grids.len() - 1)];
+        if field.is_empty() || grid.is_empty() {
+            outputs.push(empty_output(field.as_ref()));
+            continue;
+        }
+        let grid_payload = grid.payload()
+            .ok_or_else(|| crate::Error::Compute("Grid object has no payload".to_string()))?;
+        let field_payload = field.payload()
+            .ok_or_else(|| crate::Error::Compute("Field object has no payload".to_string()))?;
+
+        let incidence = CellIncidence::from_payload(grid_payload)?;
+        let converted = convert_field(field_payload, |values| convert(&incidence, values))?;
+
+        let mut object = VistleObject::with_data(ObjectType::Vec, converted)
+            .with_meta(field.meta().clone());
+        for (key, value) in field.attributes() {
+            object.set_attribute(key.clone(), value.clone());
+        }
+        object.set_attribute(attribute::MAPPING.to_string(), mapping.to_string());
+        outputs.push(Arc::new(object));
+    }
+
+    Ok(outputs)
+}
+
+/// Module converting cell-centered fields to point-centered fields
+pub struct CellToPoint {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl CellToPoint {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new(
+            "weight_by_volume",
+            "Weight incident cells by their volume",
+            ParameterValue::Bool(false),
+        ));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Grid the field is defined on"));
+        ports.add(Port::new_input("data_in", "Cell-centered field").with_data_type(data_type::CELL_FIELD));
+        ports.add(Port::new_output("data_out", "Point-centered field").with_data_type(data_type::POINT_FIELD).derived_from("data_in"));
+
+        Self {
+            info: ModuleInfo::new(id, "CellToPoint", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for CellToPoint {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let weighted = ctx.parameters().get_bool("weight_by_volume").unwrap_or(false);
+
+        let converted = convert_inputs(&self.inputs, attribute::MAPPING_VERTEX, |incidence, values| {
+            let weights = if weighted { incidence.volumes() } else { None };
+            cell_to_point(incidence, values, weights)
+        })?;
+
+        let mut outputs = HashMap::new();
+        outputs.insert("data_out".to_string(), converted);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+/// Module converting point-centered fields to cell-centered fields
+pub struct PointToCell {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl PointToCell {
+    pub fn new(id: u32) -> Self {
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Grid the field is defined on"));
+        ports.add(Port::new_input("data_in", "Point-centered field").with_data_type(data_type::POINT_FIELD));
+        ports.add(Port::new_output("data_out", "Cell-centered field").with_data_type(data_type::CELL_FIELD).derived_from("data_in"));
+
+        Self {
+            info: ModuleInfo::new(id, "PointToCell", 0, 1),
+            parameters: ParameterSet::new(),
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for PointToCell {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, _ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let converted = convert_inputs(&self.inputs, attribute::MAPPING_ELEMENT, point_to_cell)?;
+
+        let mut outputs = HashMap::new();
+        outputs.insert("data_out".to_string(), converted);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{array, s};
+
+    /// Linear field `x + 2y + 3z` at the points of a unit-spaced grid
+    fn linear_points(dims: [usize; 3]) -> Array1<f32> {
+        let [nx, ny, _] = dims;
+        Array1::from_shape_fn(dims.iter().product::<usize>(), |p| {
+            let (i, j, k) = (p % nx, (p / nx) % ny, p / (nx * ny));
+            i as f32 + 2.0 * j as f32 + 3.0 * k as f32
+        })
+    }
+
+    #[test]
+    fn constant_field_survives_both_directions() {
+        let incidence = CellIncidence::structured([4, 3, 2]).unwrap();
+        let cells = Array1::from_elem(incidence.num_cells(), 2.5f32);
+
+        let points = cell_to_point(&incidence, cells.view(), None).unwrap();
+        assert!(points.iter().all(|&v| v == 2.5));
+
+        let back = point_to_cell(&incidence, points.view()).unwrap();
+        assert_eq!(back, cells);
+    }
+
+    #[test]
+    fn linear_field_survives_within_tolerance() {
+        let dims = [4, 4, 4];
+        let incidence = CellIncidence::structured(dims).unwrap();
+        let points = linear_points(dims);
+
+        // Averaging the corners of a cell gives the value at its center
+        let cells = point_to_cell(&incidence, points.view()).unwrap();
+        let center = |i: usize, j: usize, k: usize| (i as f32 + 0.5) + 2.0 * (j as f32 + 0.5) + 3.0 * (k as f32 + 0.5);
+        assert!((cells[0] - center(0, 0, 0)).abs() < 1e-5);
+        assert!((cells[cells.len() - 1] - center(2, 2, 2)).abs() < 1e-5);
+
+        // Interior points are surrounded by cells on all sides and come back exactly;
+        // boundary points are off by at most half a cell's change of the field
+        let back = cell_to_point(&incidence, cells.view(), None).unwrap();
+        for (p, (&original, &converted)) in points.iter().zip(back.iter()).enumerate() {
+            let (i, j, k) = (p % 4, (p / 4) % 4, p / 16);
+            let interior = [i, j, k].iter().all(|&c| c > 0 && c < 3);
+            let tolerance = if interior { 1e-5 } else { 0.5 * (1.0 + 2.0 + 3.0) + 1e-5 };
+            assert!((original - converted).abs() <= tolerance, "point {}: {} vs {}", p, original, converted);
+        }
+    }
+
+    #[test]
+    fn nans_are_skipped_and_all_nan_stays_nan() {
+        // Two triangles sharing the edge between points 1 and 2
+        let incidence = CellIncidence::from_cells(4, vec![vec![0, 1, 2], vec![1, 2, 3]]).unwrap();
+
+        let points = cell_to_point(&incidence, array![f32::NAN, 4.0].view(), None).unwrap();
+        assert!(points[0].is_nan());
+        assert_eq!(points.slice(s![1..]).to_vec(), vec![4.0, 4.0, 4.0]);
+
+        let cells = point_to_cell(&incidence, array![1.0, f32::NAN, 3.0, f32::NAN].view()).unwrap();
+        assert_eq!(cells.to_vec(), vec![2.0, 3.0]);
+
+        let cells = point_to_cell(&incidence, Array1::from_elem(4, f32::NAN).view()).unwrap();
+        assert!(cells.iter().all(|v| v.is_nan()));
+    }
+
+    #[test]
+    fn volume_weights_favour_larger_cells() {
+        let incidence = CellIncidence::from_cells(3, vec![vec![0, 1], vec![1, 2]]).unwrap();
+        let weights = [1.0, 3.0];
+        let points = cell_to_point(&incidence, array![0.0, 4.0].view(), Some(&weights[..])).unwrap();
+        assert_eq!(points.to_vec(), vec![0.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn vector_fields_convert_component_wise() {
+        // Two quads side by side; point 1 belongs to both
+        let incidence = CellIncidence::structured([3, 2, 1]).unwrap();
+        let data = Array2::from_shape_fn((2, 3), |(cell, c)| (cell * 3 + c) as f32);
+
+        let converted = convert_field(&ObjectPayload::VecVec3 { data }, |values| cell_to_point(&incidence, values, None)).unwrap();
+        let ObjectPayload::VecVec3 { data } = converted else {
+            panic!("conversion changed the payload kind");
+        };
+        assert_eq!(data.dim(), (6, 3));
+        assert_eq!(data.row(0).to_vec(), vec![0.0, 1.0, 2.0]);
+        assert_eq!(data.row(1).to_vec(), vec![1.5, 2.5, 3.5]);
+    }
+
+    #[test]
+    fn mismatched_field_length_is_an_error() {
+        let incidence = CellIncidence::structured([2, 2, 2]).unwrap();
+        assert!(cell_to_point(&incidence, Array1::zeros(2).view(), None).is_err());
+        assert!(point_to_cell(&incidence, Array1::zeros(7).view()).is_err());
+        assert!(CellIncidence::from_cells(2, vec![vec![0, 2]]).is_err());
+    }
+
+    #[test]
+    fn negative_and_out_of_range_indices_are_errors() {
+        let coordinates = Array2::zeros((3, 3));
+        for triangles in [array![[0, 1, -1]], array![[0, 1, 3]]] {
+            let payload = ObjectPayload::Triangles { coordinates: coordinates.clone(), triangles };
+            assert!(matches!(CellIncidence::from_payload(&payload), Err(crate::Error::Compute(_))));
+        }
+        let payload = ObjectPayload::Lines { coordinates: coordinates.clone(), connections: array![[-1, 0]] };
+        assert!(matches!(CellIncidence::from_payload(&payload), Err(crate::Error::Compute(_))));
+        let payload = ObjectPayload::UnstructuredGrid {
+            coordinates,
+            connectivity: array![0, 1, -2],
+            offsets: array![0, 3],
+            cell_types: vec![CellType::Triangle],
+        };
+        assert!(matches!(CellIncidence::from_payload(&payload), Err(crate::Error::Compute(_))));
+    }
+
+    #[test]
+    fn grids_convert_through_their_cells() {
+        let uniform = ObjectPayload::UniformGrid {
+            dims: [3, 2, 1],
+            origin: [0.0; 3],
+            spacing: [1.0; 3],
+            values: Array1::zeros(6),
+        };
+        let incidence = CellIncidence::from_payload(&uniform).unwrap();
+        assert_eq!(incidence.num_cells(), 2);
+        assert_eq!(incidence.cell(1), &[1, 2, 4, 5]);
+        let points = cell_to_point(&incidence, array![0.0, 2.0].view(), None).unwrap();
+        assert_eq!(points.to_vec(), vec![0.0, 1.0, 2.0, 0.0, 1.0, 2.0]);
+
+        // A tetrahedron and a triangle sharing vertices 1 and 2
+        let unstructured = ObjectPayload::UnstructuredGrid {
+            coordinates: Array2::zeros((5, 3)),
+            connectivity: array![0, 1, 2, 3, 1, 2, 4],
+            offsets: array![0, 4, 7],
+            cell_types: vec![CellType::Tetrahedron, CellType::Triangle],
+        };
+        let incidence = CellIncidence::from_payload(&unstructured).unwrap();
+        assert_eq!(incidence.num_points(), 5);
+        let points = cell_to_point(&incidence, array![1.0, 3.0].view(), None).unwrap();
+        assert_eq!(points.to_vec(), vec![1.0, 2.0, 2.0, 1.0, 3.0]);
+    }
+
+    fn assert_volumes(payload: &ObjectPayload, expected: &[f32]) {
+        let incidence = CellIncidence::from_payload(payload).unwrap();
+        let volumes = incidence.volumes().expect("grids know their cell volumes");
+        assert_eq!(volumes.len(), expected.len());
+        for (cell, (&volume, &expected)) in volumes.iter().zip(expected).enumerate() {
+            assert!((volume - expected).abs() < 1e-5, "cell {}: {} vs {}", cell, volume, expected);
+        }
+    }
+
+    #[test]
+    fn structured_grids_know_their_cell_volumes() {
+        let uniform = ObjectPayload::UniformGrid {
+            dims: [3, 2, 2],
+            origin: [5.0, 0.0, 0.0],
+            spacing: [2.0, 1.0, 0.5],
+            values: Array1::zeros(12),
+        };
+        assert_volumes(&uniform, &[1.0, 1.0]);
+
+        // Collapsed axes give areas and lengths
+        let rectilinear = ObjectPayload::RectilinearGrid { x: array![0.0, 1.0, 4.0], y: array![0.0, 2.0], z: array![7.0] };
+        assert_volumes(&rectilinear, &[2.0, 6.0]);
+        let line = ObjectPayload::RectilinearGrid { x: array![0.0, 0.5, 2.0], y: array![1.0], z: array![1.0] };
+        assert_volumes(&line, &[0.5, 1.5]);
+
+        // A unit cube sheared along x keeps its volume
+        let coordinates = Array2::from_shape_fn((8, 3), |(p, axis)| {
+            let index = [p % 2, (p / 2) % 2, p / 4].map(|i| i as f32);
+            if axis == 0 { index[0] + 0.5 * index[2] } else { index[axis] }
+        });
+        assert_volumes(&ObjectPayload::StructuredGrid { dims: [2, 2, 2], coordinates }, &[1.0]);
+    }
+
+    #[test]
+    fn unstructured_cells_know_their_volumes() {
+        let cube = [[0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 1, 0], [0, 0, 1], [1, 0, 1], [1, 1, 1], [0, 1, 1]];
+        let coordinates = Array2::from_shape_fn((8, 3), |(p, axis)| 2.0 * cube[p][axis] as f32);
+        let payload = ObjectPayload::UnstructuredGrid {
+            coordinates,
+            connectivity: array![0, 1, 2, 3, 4, 5, 6, 7, 0, 1, 3, 4, 0, 1, 2, 3, 0, 1, 2],
+            offsets: array![0, 8, 12, 16, 19],
+            cell_types: vec![CellType::Hexahedron, CellType::Tetrahedron, CellType::Quad, CellType::Triangle],
+        };
+        assert_volumes(&payload, &[8.0, 8.0 / 6.0, 4.0, 2.0]);
+    }
+
+    #[test]
+    fn grids_without_points_along_an_axis_are_errors() {
+        assert!(matches!(CellIncidence::structured([3, 0, 1]), Err(crate::Error::Compute(_))));
+        let payload = ObjectPayload::RectilinearGrid { x: array![0.0, 1.0], y: Array1::zeros(0), z: array![0.0] };
+        assert!(matches!(CellIncidence::from_payload(&payload), Err(crate::Error::Compute(_))));
+        let payload = ObjectPayload::StructuredGrid { dims: [2, 2, 1], coordinates: Array2::zeros((3, 3)) };
+        assert!(matches!(CellIncidence::from_payload(&payload), Err(crate::Error::Compute(_))));
+    }
+
+    #[tokio::test]
+    async fn weighting_by_volume_uses_the_grid_cells() {
+        let grid: Arc<dyn Object> = Arc::new(VistleObject::with_data(
+            ObjectType::RectilinearGrid,
+            ObjectPayload::RectilinearGrid { x: array![0.0, 1.0, 4.0], y: array![0.0], z: array![0.0] },
+        ));
+        let field: Arc<dyn Object> = Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: array![0.0, 4.0] }));
+
+        let mut module = CellToPoint::new(1);
+        module.set_input("grid_in", vec![grid]).await.unwrap();
+        module.set_input("data_in", vec![field]).await.unwrap();
+        let mut parameters = module.parameters().clone();
+        parameters.set_value("weight_by_volume", ParameterValue::Bool(true)).unwrap();
+        let outputs = module.compute(&ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot())).await.unwrap();
+
+        let Some(ObjectPayload::VecScalar { data }) = outputs["data_out"][0].payload() else {
+            panic!("output is not a scalar field");
+        };
+        assert_eq!(data.to_vec(), vec![0.0, 3.0, 4.0]);
+    }
+}
@@ -0,0 +1,817 @@
+//! Clipping geometry against implicit functions
+//!
+//! Triangle surfaces are clipped triangle by triangle. Uniform and
+//! unstructured grids are split into tetrahedra first and come out as
+//! unstructured grids of tetrahedra.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ndarray::{Array1, Array2};
+use nalgebra::Vector3;
+
+use crate::core::{
+    CellType, ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectPayload, ObjectType, Parameter,
+    ParameterSet, ParameterSnapshot, ParameterValue, Port, PortSet, UnstructuredGridView, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{empty_output, required_input};
+
+/// Attribute marking cap geometry produced by clipping
+pub const CLIP_CAP_ATTRIBUTE: &str = "_clip_cap";
+
+/// Implicit function used to clip geometry; negative values are inside
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImplicitFunction {
+    Box { min: Vector3<f32>, max: Vector3<f32> },
+    Sphere { center: Vector3<f32>, radius: f32 },
+    Plane { origin: Vector3<f32>, normal: Vector3<f32> },
+}
+
+impl ImplicitFunction {
+    /// Evaluate the function at a point
+    pub fn eval(&self, p: &Vector3<f32>) -> f32 {
+        match self {
+            ImplicitFunction::Box { min, max } => {
+                // Signed distance to an axis-aligned box
+                let center = (min + max) * 0.5;
+                let half = (max - min) * 0.5;
+                let q = (p - center).abs() - half;
+                let outside = q.map(|v| v.max(0.0)).norm();
+                let inside = q.x.max(q.y).max(q.z).min(0.0);
+                outside + inside
+            }
+            ImplicitFunction::Sphere { center, radius } => (p - center).norm() - radius,
+            ImplicitFunction::Plane { origin, normal } => {
+                let n = normal.try_normalize(f32::EPSILON).unwrap_or(Vector3::z());
+                (p - origin).dot(&n)
+            }
+        }
+    }
+
+    /// Construct the function selected by a clip module's parameters
+    pub fn from_parameters(params: &ParameterSnapshot) -> Result<Self, crate::Error> {
+        let vec3 = |name: &str, default: [f32; 3]| -> Result<Vector3<f32>, crate::Error> {
+            match params.get_vec_float(name) {
+                Some([x, y, z]) => Ok(Vector3::new(*x, *y, *z)),
+                Some(v) => Err(crate::Error::Config(format!(
+                    "Parameter {} needs 3 components, got {}",
+                    name,
+                    v.len()
+                ))),
+                None => Ok(Vector3::from(default)),
+            }
+        };
+
+        match params.get_string("function").unwrap_or("plane") {
+            "box" => Ok(ImplicitFunction::Box {
+                min: vec3("box_min", [-1.0, -1.0, -1.0])?,
+                max: vec3("box_max", [1.0, 1.0, 1.0])?,
+            }),
+            "sphere" => Ok(ImplicitFunction::Sphere {
+                center: vec3("center", [0.0, 0.0, 0.0])?,
+                radius: params.get_float("radius").unwrap_or(1.0),
+            }),
+            "plane" => Ok(ImplicitFunction::Plane {
+                origin: vec3("center", [0.0, 0.0, 0.0])?,
+                normal: vec3("normal", [0.0, 0.0, 1.0])?,
+            }),
+            other => Err(crate::Error::Config(format!(
+                "Unknown clip function {} (expected box, sphere or plane)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Result of clipping a triangle mesh
+#[derive(Debug, Clone)]
+pub struct ClippedMesh {
+    pub coordinates: Array2<f32>,
+    pub triangles: Array2<i32>,
+    /// For every output vertex: the two source vertices and the weight of the second
+    pub interpolation: Vec<(usize, usize, f32)>,
+    /// Cap triangles closing the cut, indexing into `coordinates`
+    pub cap_triangles: Array2<i32>,
+}
+
+impl ClippedMesh {
+    /// Interpolate a per-vertex field of the source mesh onto the clipped mesh
+    pub fn interpolate(&self, values: &Array1<f32>) -> Array1<f32> {
+        interpolate_scalars(&self.interpolation, values)
+    }
+
+    /// Interpolate a per-vertex vector field of the source mesh onto the clipped mesh
+    pub fn interpolate_vec3(&self, values: &Array2<f32>) -> Array2<f32> {
+        interpolate_rows(&self.interpolation, values)
+    }
+}
+
+/// Result of clipping the volume cells of a grid, as tetrahedra
+#[derive(Debug, Clone)]
+pub struct ClippedGrid {
+    pub coordinates: Array2<f32>,
+    pub tetrahedra: Array2<i32>,
+    /// For every output vertex: the two source vertices and the weight of the second
+    pub interpolation: Vec<(usize, usize, f32)>,
+    /// Triangles of the cut surface, indexing into `coordinates`
+    pub cap_triangles: Array2<i32>,
+}
+
+impl ClippedGrid {
+    /// Interpolate a per-vertex field of the source grid onto the clipped grid
+    pub fn interpolate(&self, values: &Array1<f32>) -> Array1<f32> {
+        interpolate_scalars(&self.interpolation, values)
+    }
+
+    /// Interpolate a per-vertex vector field of the source grid onto the clipped grid
+    pub fn interpolate_vec3(&self, values: &Array2<f32>) -> Array2<f32> {
+        interpolate_rows(&self.interpolation, values)
+    }
+
+    /// The clipped grid as an unstructured grid of tetrahedra
+    pub fn to_payload(&self) -> ObjectPayload {
+        let num_cells = self.tetrahedra.nrows();
+        ObjectPayload::UnstructuredGrid {
+            coordinates: self.coordinates.clone(),
+            connectivity: self.tetrahedra.iter().copied().collect(),
+            offsets: (0..=num_cells).map(|i| (i * 4) as i32).collect(),
+            cell_types: vec![CellType::Tetrahedron; num_cells],
+        }
+    }
+}
+
+fn interpolate_scalars(interpolation: &[(usize, usize, f32)], values: &Array1<f32>) -> Array1<f32> {
+    interpolation.iter()
+        .map(|&(a, b, t)| values[a] * (1.0 - t) + values[b] * t)
+        .collect()
+}
+
+fn interpolate_rows(interpolation: &[(usize, usize, f32)], values: &Array2<f32>) -> Array2<f32> {
+    let mut result = Array2::zeros((interpolation.len(), values.ncols()));
+    for (i, &(a, b, t)) in interpolation.iter().enumerate() {
+        for c in 0..values.ncols() {
+            result[[i, c]] = values[[a, c]] * (1.0 - t) + values[[b, c]] * t;
+        }
+    }
+    result
+}
+
+/// Index of a vertex in a cell list, checked against the number of vertices
+fn vertex_index(index: i32, num_vertices: usize) -> Result<usize, crate::Error> {
+    match usize::try_from(index) {
+        Ok(i) if i < num_vertices => Ok(i),
+        _ => Err(crate::Error::Compute(format!(
+            "Cell references vertex {} but the grid has {} vertices",
+            index, num_vertices
+        ))),
+    }
+}
+
+/// Clip a triangle mesh, keeping the part where the function is <= 0 (or > 0 when `keep_outside`)
+pub fn clip_triangles(
+    coordinates: &Array2<f32>,
+    triangles: &Array2<i32>,
+    function: &ImplicitFunction,
+    keep_outside: bool,
+    cap: bool,
+) -> Result<ClippedMesh, crate::Error> {
+    let sign = if keep_outside { -1.0 } else { 1.0 };
+    let point = |i: usize| Vector3::new(coordinates[[i, 0]], coordinates[[i, 1]], coordinates[[i, 2]]);
+    let values: Vec<f32> = (0..coordinates.nrows())
+        .map(|i| sign * function.eval(&point(i)))
+        .collect();
+
+    let mut builder = ClipBuilder::default();
+    let mut cut_segments: Vec<(usize, usize)> = Vec::new();
+
+    for tri in triangles.outer_iter() {
+        let n = coordinates.nrows();
+        let corners = [vertex_index(tri[0], n)?, vertex_index(tri[1], n)?, vertex_index(tri[2], n)?];
+        let mut polygon = Vec::with_capacity(4);
+        let mut cut = Vec::with_capacity(2);
+
+        for e in 0..3 {
+            let a = corners[e];
+            let b = corners[(e + 1) % 3];
+            let (va, vb) = (values[a], values[b]);
+
+            if va <= 0.0 {
+                polygon.push(builder.source_vertex(a));
+            }
+            if (va <= 0.0) != (vb <= 0.0) {
+                let t = va / (va - vb);
+                let v = builder.edge_vertex(a, b, t);
+                polygon.push(v);
+                cut.push(v);
+            }
+        }
+
+        for i in 1..polygon.len().saturating_sub(1) {
+            builder.triangles.push([polygon[0], polygon[i], polygon[i + 1]]);
+        }
+        if cut.len() == 2 {
+            cut_segments.push((cut[0], cut[1]));
+        }
+    }
+
+    let mut out_coords = Array2::zeros((builder.interpolation.len(), 3));
+    for (i, &(a, b, t)) in builder.interpolation.iter().enumerate() {
+        let p = point(a) * (1.0 - t) + point(b) * t;
+        out_coords[[i, 0]] = p.x;
+        out_coords[[i, 1]] = p.y;
+        out_coords[[i, 2]] = p.z;
+    }
+
+    let mut cap_triangles = Vec::new();
+    if cap {
+        for loop_vertices in chain_segments(&cut_segments) {
+            // Fan around the loop centroid, appended as an extra interpolated vertex
+            let centroid = loop_vertices.iter()
+                .map(|&v| Vector3::new(out_coords[[v, 0]], out_coords[[v, 1]], out_coords[[v, 2]]))
+                .sum::<Vector3<f32>>() / loop_vertices.len() as f32;
+            let nearest = builder.interpolation[loop_vertices[0]];
+            let center = builder.interpolation.len();
+            builder.interpolation.push(nearest);
+            out_coords.push_row(ndarray::ArrayView1::from(&[centroid.x, centroid.y, centroid.z]))
+                .expect("coordinate rows have three columns");
+
+            for i in 0..loop_vertices.len() {
+                let a = loop_vertices[i];
+                let b = loop_vertices[(i + 1) % loop_vertices.len()];
+                cap_triangles.push([center, b, a]);
+            }
+        }
+    }
+
+    Ok(ClippedMesh {
+        coordinates: out_coords,
+        triangles: to_index_array(&builder.triangles),
+        interpolation: builder.interpolation,
+        cap_triangles: to_index_array(&cap_triangles),
+    })
+}
+
+/// Split a volume cell into tetrahedra, following the VTK vertex order of its type
+pub(super) fn tetrahedra(cell_type: CellType, v: &[usize]) -> Result<Vec<[usize; 4]>, crate::Error> {
+    let tets: &[[usize; 4]] = match cell_type {
+        CellType::Tetrahedron => &[[0, 1, 2, 3]],
+        CellType::Pyramid => &[[0, 1, 2, 4], [0, 2, 3, 4]],
+        CellType::Wedge => &[[0, 1, 2, 3], [1, 2, 3, 4], [2, 3, 4, 5]],
+        // Six tetrahedra around the diagonal from vertex 0 to vertex 6
+        CellType::Hexahedron => &[
+            [0, 1, 2, 6], [0, 2, 3, 6], [0, 3, 7, 6],
+            [0, 7, 4, 6], [0, 4, 5, 6], [0, 5, 1, 6],
+        ],
+        CellType::Triangle | CellType::Quad => {
+            return Err(crate::Error::Compute(format!(
+                "Clip of grids requires volume cells, got a {}",
+                cell_type
+            )));
+        }
+    };
+    if v.len() != cell_type.num_vertices() {
+        return Err(crate::Error::Compute(format!(
+            "A {} needs {} vertices, got {}",
+            cell_type, cell_type.num_vertices(), v.len()
+        )));
+    }
+    Ok(tets.iter().map(|t| [v[t[0]], v[t[1]], v[t[2]], v[t[3]]]).collect())
+}
+
+/// Volume cells of a grid as tetrahedra, with the grid's vertex coordinates
+///
+/// Unstructured grids are split cell by cell; uniform grids are first
+/// split into hexahedra, one per grid cell.
+pub fn grid_tetrahedra(payload: &ObjectPayload) -> Result<(Array2<f32>, Vec<[usize; 4]>), crate::Error> {
+    match payload {
+        ObjectPayload::UnstructuredGrid { coordinates, .. } => {
+            let view = UnstructuredGridView::new(payload).expect("payload is an unstructured grid");
+            let mut tets = Vec::new();
+            for i in 0..view.num_cells() {
+                let (cell_type, indices) = view.cell(i).ok_or_else(|| crate::Error::Compute(format!(
+                    "Cell {} has offsets outside the connectivity of {} entries",
+                    i,
+                    view.connectivity().len()
+                )))?;
+                let vertices = indices.iter()
+                    .map(|&index| vertex_index(index, coordinates.nrows()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                tets.extend(tetrahedra(cell_type, &vertices)?);
+            }
+            Ok((coordinates.clone(), tets))
+        }
+        ObjectPayload::UniformGrid { dims, origin, spacing, .. } => {
+            let [nx, ny, nz] = *dims;
+            let coordinates = Array2::from_shape_fn((nx * ny * nz, 3), |(p, axis)| {
+                let index = [p % nx, (p / nx) % ny, p / (nx * ny)][axis];
+                origin[axis] + index as f32 * spacing[axis]
+            });
+            let point = |i: usize, j: usize, k: usize| (k * ny + j) * nx + i;
+            let mut tets = Vec::new();
+            for k in 0..nz.saturating_sub(1) {
+                for j in 0..ny.saturating_sub(1) {
+                    for i in 0..nx.saturating_sub(1) {
+                        let hexahedron = [
+                            point(i, j, k), point(i + 1, j, k), point(i + 1, j + 1, k), point(i, j + 1, k),
+                            point(i, j, k + 1), point(i + 1, j, k + 1), point(i + 1, j + 1, k + 1), point(i, j + 1, k + 1),
+                        ];
+                        tets.extend(tetrahedra(CellType::Hexahedron, &hexahedron)?);
+                    }
+                }
+            }
+            Ok((coordinates, tets))
+        }
+        _ => Err(crate::Error::Compute("Clip requires triangles, a uniform grid or an unstructured grid".to_string())),
+    }
+}
+
+/// Clip tetrahedra, keeping the part where the function is <= 0 (or > 0 when `keep_outside`)
+///
+/// Each tetrahedron is cut as in marching tetrahedra: the kept corner
+/// region is a tetrahedron or a prism, and prisms are split into three
+/// tetrahedra. The cut faces form the cap.
+pub fn clip_tetrahedra(
+    coordinates: &Array2<f32>,
+    tets: &[[usize; 4]],
+    function: &ImplicitFunction,
+    keep_outside: bool,
+) -> ClippedGrid {
+    let sign = if keep_outside { -1.0 } else { 1.0 };
+    let point = |i: usize| Vector3::new(coordinates[[i, 0]], coordinates[[i, 1]], coordinates[[i, 2]]);
+    let values: Vec<f32> = (0..coordinates.nrows())
+        .map(|i| sign * function.eval(&point(i)))
+        .collect();
+
+    let mut builder = ClipBuilder::default();
+    let mut kept: Vec<[usize; 4]> = Vec::new();
+    let mut cap: Vec<[usize; 3]> = Vec::new();
+
+    for tet in tets {
+        let (inside, outside): (Vec<usize>, Vec<usize>) = tet.iter().copied().partition(|&v| values[v] <= 0.0);
+        let mut edge = |a: usize, b: usize| {
+            let t = values[a] / (values[a] - values[b]);
+            builder.edge_vertex(a, b, t)
+        };
+        match (inside.as_slice(), outside.as_slice()) {
+            ([a, b, c, d], []) => {
+                let [a, b, c, d] = [*a, *b, *c, *d].map(|v| builder.source_vertex(v));
+                kept.push([a, b, c, d]);
+            }
+            ([a], [b, c, d]) => {
+                let (ab, ac, ad) = (edge(*a, *b), edge(*a, *c), edge(*a, *d));
+                let a = builder.source_vertex(*a);
+                kept.push([a, ab, ac, ad]);
+                cap.push([ab, ac, ad]);
+            }
+            ([a, b], [c, d]) => {
+                let (ac, ad, bc, bd) = (edge(*a, *c), edge(*a, *d), edge(*b, *c), edge(*b, *d));
+                let (a, b) = (builder.source_vertex(*a), builder.source_vertex(*b));
+                // Prism with triangles (a, ac, ad) and (b, bc, bd)
+                kept.extend([[a, ac, ad, b], [ac, ad, b, bc], [ad, b, bc, bd]]);
+                cap.extend([[ac, ad, bd], [ac, bd, bc]]);
+            }
+            ([a, b, c], [d]) => {
+                let (ad, bd, cd) = (edge(*a, *d), edge(*b, *d), edge(*c, *d));
+                let [a, b, c] = [*a, *b, *c].map(|v| builder.source_vertex(v));
+                // Prism with triangles (a, b, c) and (ad, bd, cd)
+                kept.extend([[a, b, c, ad], [b, c, ad, bd], [c, ad, bd, cd]]);
+                cap.push([ad, bd, cd]);
+            }
+            _ => {}
+        }
+    }
+
+    let mut out_coords = Array2::zeros((builder.interpolation.len(), 3));
+    for (i, &(a, b, t)) in builder.interpolation.iter().enumerate() {
+        let p = point(a) * (1.0 - t) + point(b) * t;
+        out_coords[[i, 0]] = p.x;
+        out_coords[[i, 1]] = p.y;
+        out_coords[[i, 2]] = p.z;
+    }
+
+    let mut tetrahedra = Array2::zeros((kept.len(), 4));
+    for (i, tet) in kept.iter().enumerate() {
+        for c in 0..4 {
+            tetrahedra[[i, c]] = tet[c] as i32;
+        }
+    }
+
+    ClippedGrid {
+        coordinates: out_coords,
+        tetrahedra,
+        interpolation: builder.interpolation,
+        cap_triangles: to_index_array(&cap),
+    }
+}
+
+#[derive(Default)]
+struct ClipBuilder {
+    interpolation: Vec<(usize, usize, f32)>,
+    source_map: HashMap<usize, usize>,
+    edge_map: HashMap<(usize, usize), usize>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl ClipBuilder {
+    fn source_vertex(&mut self, index: usize) -> usize {
+        let interpolation = &mut self.interpolation;
+        *self.source_map.entry(index).or_insert_with(|| {
+            interpolation.push((index, index, 0.0));
+            interpolation.len() - 1
+        })
+    }
+
+    fn edge_vertex(&mut self, a: usize, b: usize, t: f32) -> usize {
+        // Share vertices between the two triangles adjacent to an edge
+        let (key, t) = if a < b { ((a, b), t) } else { ((b, a), 1.0 - t) };
+        let interpolation = &mut self.interpolation;
+        *self.edge_map.entry(key).or_insert_with(|| {
+            interpolation.push((key.0, key.1, t));
+            interpolation.len() - 1
+        })
+    }
+}
+
+/// Link cut segments sharing endpoints into closed loops
+fn chain_segments(segments: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    let mut next: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &(a, b) in segments {
+        next.entry(a).or_default().push(b);
+        next.entry(b).or_default().push(a);
+    }
+
+    let mut visited = std::collections::HashSet::new();
+    let mut loops = Vec::new();
+
+    for &(start, _) in segments {
+        if visited.contains(&start) {
+            continue;
+        }
+        let mut chain = vec![start];
+        visited.insert(start);
+        let mut current = start;
+        while let Some(&n) = next.get(&current).and_then(|ns| ns.iter().find(|n| !visited.contains(*n))) {
+            visited.insert(n);
+            chain.push(n);
+            current = n;
+        }
+        if chain.len() >= 3 {
+            loops.push(chain);
+        }
+    }
+
+    loops
+}
+
+fn to_index_array(triangles: &[[usize; 3]]) -> Array2<i32> {
+    let mut array = Array2::zeros((triangles.len(), 3));
+    for (i, t) in triangles.iter().enumerate() {
+        for c in 0..3 {
+            array[[i, c]] = t[c] as i32;
+        }
+    }
+    array
+}
+
+/// Clipped geometry of one input grid, whatever its kind
+struct Clipped {
+    object_type: ObjectType,
+    payload: ObjectPayload,
+    is_empty: bool,
+    /// Vertices of the input grid, which fields to interpolate must match
+    source_vertices: usize,
+    interpolation: Vec<(usize, usize, f32)>,
+    coordinates: Array2<f32>,
+    cap_triangles: Array2<i32>,
+}
+
+/// Module clipping surfaces and volume grids against a box, sphere or plane
+pub struct Clip {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl Clip {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("function", "Implicit function: box, sphere or plane", ParameterValue::String("plane".to_string())));
+        parameters.add(Parameter::new("box_min", "Minimum corner of the box", ParameterValue::VecFloat(vec![-1.0, -1.0, -1.0])));
+        parameters.add(Parameter::new("box_max", "Maximum corner of the box", ParameterValue::VecFloat(vec![1.0, 1.0, 1.0])));
+        parameters.add(Parameter::new("center", "Sphere center or point on plane", ParameterValue::VecFloat(vec![0.0, 0.0, 0.0])));
+        parameters.add(Parameter::new("radius", "Sphere radius", ParameterValue::Float(1.0)));
+        parameters.add(Parameter::new("normal", "Plane normal", ParameterValue::VecFloat(vec![0.0, 0.0, 1.0])));
+        parameters.add(Parameter::new("keep_outside", "Keep the part outside the function", ParameterValue::Bool(false)));
+        parameters.add(Parameter::new("cap", "Close the cut with a cap surface", ParameterValue::Bool(false)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Triangles, uniform or unstructured grid to clip"));
+        ports.add(Port::new_input("data_in", "Per-vertex fields on the geometry").optional());
+        ports.add(Port::new_output("grid_out", "Clipped geometry").derived_from("grid_in"));
+        ports.add(Port::new_output("data_out", "Interpolated fields").derived_from("data_in"));
+        ports.add(Port::new_output("cap_out", "Cap surfaces closing the cut").optional().derived_from("grid_in"));
+
+        Self {
+            info: ModuleInfo::new(id, "Clip", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for Clip {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let params = ctx.parameters();
+        let function = ImplicitFunction::from_parameters(params)?;
+        let keep_outside = params.get_bool("keep_outside").unwrap_or(false);
+        let cap = params.get_bool("cap").unwrap_or(false);
+
+        let grids = required_input(&self.inputs, "grid_in")?;
+        let fields = self.inputs.get("data_in").cloned().unwrap_or_default();
+
+        let mut grid_out: Vec<Arc<dyn Object>> = Vec::new();
+        let mut data_out: Vec<Arc<dyn Object>> = Vec::new();
+        let mut cap_out: Vec<Arc<dyn Object>> = Vec::new();
+
+        for (i, grid) in grids.iter().enumerate() {
+            if grid.is_empty() {
+                grid_out.push(empty_output(grid.as_ref()));
+                if let Some(field) = fields.get(i) {
+                    data_out.push(empty_output(field.as_ref()));
+                }
+                if cap {
+                    cap_out.push(empty_output(grid.as_ref()));
+                }
+                continue;
+            }
+
+            // Surfaces stay triangles, volume grids become tetrahedra
+            let clipped = match grid.payload() {
+                Some(payload @ (ObjectPayload::UnstructuredGrid { .. } | ObjectPayload::UniformGrid { .. })) => {
+                    let (coordinates, tets) = grid_tetrahedra(payload)?;
+                    let clipped = clip_tetrahedra(&coordinates, &tets, &function, keep_outside);
+                    Clipped {
+                        object_type: ObjectType::UnstructuredGrid,
+                        payload: clipped.to_payload(),
+                        is_empty: clipped.tetrahedra.nrows() == 0,
+                        source_vertices: coordinates.nrows(),
+                        interpolation: clipped.interpolation,
+                        coordinates: clipped.coordinates,
+                        cap_triangles: clipped.cap_triangles,
+                    }
+                }
+                _ => {
+                    let surface = grid.as_triangles()
+                        .ok_or_else(|| crate::Error::wrong_type("triangles, uniform or unstructured grid", grid.as_ref()))?;
+                    let clipped = clip_triangles(surface.coordinates(), surface.triangles(), &function, keep_outside, cap)?;
+                    Clipped {
+                        object_type: ObjectType::Triangles,
+                        payload: ObjectPayload::Triangles {
+                            coordinates: clipped.coordinates.clone(),
+                            triangles: clipped.triangles.clone(),
+                        },
+                        is_empty: clipped.triangles.nrows() == 0,
+                        source_vertices: surface.num_vertices(),
+                        interpolation: clipped.interpolation,
+                        coordinates: clipped.coordinates,
+                        cap_triangles: clipped.cap_triangles,
+                    }
+                }
+            };
+            // Everything clipped away: empties keep block and timestep for downstream modules
+            if clipped.is_empty {
+                grid_out.push(empty_output(grid.as_ref()));
+                if let Some(field) = fields.get(i) {
+                    data_out.push(empty_output(field.as_ref()));
+                }
+                if cap {
+                    cap_out.push(empty_output(grid.as_ref()));
+                }
+                continue;
+            }
+
+            if let Some(field) = fields.get(i) {
+                let payload = match (field.as_scalar_field(), field.as_vector_field()) {
+                    (Some(scalars), _) if scalars.len() == clipped.source_vertices => {
+                        ObjectPayload::VecScalar { data: interpolate_scalars(&clipped.interpolation, scalars.values()) }
+                    }
+                    (_, Some(vectors)) if vectors.len() == clipped.source_vertices => {
+                        ObjectPayload::VecVec3 { data: interpolate_rows(&clipped.interpolation, vectors.values()) }
+                    }
+                    (None, None) => return Err(crate::Error::wrong_type("scalar or vector field", field.as_ref())),
+                    _ => return Err(crate::Error::Compute(
+                        "Clip requires per-vertex scalar or vector fields".to_string(),
+                    )),
+                };
+                let mut object = VistleObject::with_data(ObjectType::Vec, payload)
+                    .with_meta(field.meta().clone());
+                for (key, value) in field.attributes() {
+                    object.set_attribute(key.clone(), value.clone());
+                }
+                data_out.push(Arc::new(object));
+            }
+
+            if cap && clipped.cap_triangles.nrows() > 0 {
+                let mut object = VistleObject::with_data(
+                    ObjectType::Triangles,
+                    ObjectPayload::Triangles {
+                        coordinates: clipped.coordinates,
+                        triangles: clipped.cap_triangles,
+                    },
+                ).with_meta(grid.meta().clone());
+                object.set_attribute(CLIP_CAP_ATTRIBUTE.to_string(), "true".to_string());
+                cap_out.push(Arc::new(object));
+            } else if cap {
+                cap_out.push(empty_output(grid.as_ref()));
+            }
+
+            let mut object = VistleObject::with_data(clipped.object_type, clipped.payload)
+                .with_meta(grid.meta().clone());
+            for (key, value) in grid.attributes() {
+                object.set_attribute(key.clone(), value.clone());
+            }
+            grid_out.push(Arc::new(object));
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("grid_out".to_string(), grid_out);
+        outputs.insert("data_out".to_string(), data_out);
+        if cap {
+            outputs.insert("cap_out".to_string(), cap_out);
+        }
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    fn plane_x(x: f32) -> ImplicitFunction {
+        ImplicitFunction::Plane { origin: Vector3::new(x, 0.0, 0.0), normal: Vector3::x() }
+    }
+
+    fn volume(clipped: &ClippedGrid) -> f32 {
+        let p = |i: i32| {
+            let r = clipped.coordinates.row(i as usize);
+            Vector3::new(r[0], r[1], r[2])
+        };
+        clipped.tetrahedra.outer_iter()
+            .map(|t| (p(t[1]) - p(t[0])).cross(&(p(t[2]) - p(t[0]))).dot(&(p(t[3]) - p(t[0]))).abs() / 6.0)
+            .sum()
+    }
+
+    fn unit_cube() -> ObjectPayload {
+        ObjectPayload::UnstructuredGrid {
+            coordinates: array![
+                [0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0],
+            ],
+            connectivity: ndarray::Array1::from_iter(0..8),
+            offsets: array![0, 8],
+            cell_types: vec![CellType::Hexahedron],
+        }
+    }
+
+    #[test]
+    fn bad_triangle_indices_are_errors() {
+        let coordinates = Array2::zeros((3, 3));
+        for triangles in [array![[0, 1, -1]], array![[0, 1, 3]]] {
+            let result = clip_triangles(&coordinates, &triangles, &plane_x(0.5), false, false);
+            assert!(matches!(result, Err(crate::Error::Compute(_))));
+        }
+    }
+
+    #[test]
+    fn clipping_a_hexahedron_keeps_the_inside_half() {
+        let (coordinates, tets) = grid_tetrahedra(&unit_cube()).unwrap();
+        assert_eq!(tets.len(), 6);
+
+        let inside = clip_tetrahedra(&coordinates, &tets, &plane_x(0.25), false);
+        assert!((volume(&inside) - 0.25).abs() < 1e-5, "{}", volume(&inside));
+        assert!(inside.coordinates.column(0).iter().all(|&x| x <= 0.25 + 1e-6));
+        // The cap covers the cut face of the cube
+        let cap_area: f32 = inside.cap_triangles.outer_iter()
+            .map(|t| {
+                let p = |i: i32| {
+                    let r = inside.coordinates.row(i as usize);
+                    Vector3::new(r[0], r[1], r[2])
+                };
+                0.5 * (p(t[1]) - p(t[0])).cross(&(p(t[2]) - p(t[0]))).norm()
+            })
+            .sum();
+        assert!((cap_area - 1.0).abs() < 1e-5, "{}", cap_area);
+
+        let outside = clip_tetrahedra(&coordinates, &tets, &plane_x(0.25), true);
+        assert!((volume(&outside) - 0.75).abs() < 1e-5, "{}", volume(&outside));
+
+        // Fields are interpolated linearly along cut edges
+        let x = coordinates.column(0).to_owned();
+        let interpolated = inside.interpolate(&x);
+        for (value, row) in interpolated.iter().zip(inside.coordinates.outer_iter()) {
+            assert!((value - row[0]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn uniform_grids_clip_to_unstructured_tetrahedra() {
+        let grid = ObjectPayload::UniformGrid {
+            dims: [3, 3, 3],
+            origin: [0.0; 3],
+            spacing: [0.5; 3],
+            values: ndarray::Array1::zeros(27),
+        };
+        let (coordinates, tets) = grid_tetrahedra(&grid).unwrap();
+        assert_eq!(coordinates.nrows(), 27);
+        assert_eq!(tets.len(), 8 * 6);
+
+        let sphere = ImplicitFunction::Sphere { center: Vector3::zeros(), radius: 10.0 };
+        let clipped = clip_tetrahedra(&coordinates, &tets, &sphere, false);
+        assert!((volume(&clipped) - 1.0).abs() < 1e-5);
+        assert_eq!(clipped.cap_triangles.nrows(), 0);
+        let ObjectPayload::UnstructuredGrid { offsets, cell_types, .. } = clipped.to_payload() else {
+            panic!("expected an unstructured grid");
+        };
+        assert_eq!(cell_types.len(), 48);
+        assert_eq!(offsets[48], 48 * 4);
+    }
+
+    #[test]
+    fn surface_cells_and_bad_grid_indices_are_rejected() {
+        let surface = ObjectPayload::UnstructuredGrid {
+            coordinates: Array2::zeros((3, 3)),
+            connectivity: array![0, 1, 2],
+            offsets: array![0, 3],
+            cell_types: vec![CellType::Triangle],
+        };
+        assert!(grid_tetrahedra(&surface).is_err());
+
+        let ObjectPayload::UnstructuredGrid { coordinates, offsets, cell_types, .. } = unit_cube() else {
+            unreachable!();
+        };
+        let broken = ObjectPayload::UnstructuredGrid {
+            coordinates,
+            connectivity: array![0, 1, 2, 3, 4, 5, 6, -7],
+            offsets,
+            cell_types,
+        };
+        assert!(matches!(grid_tetrahedra(&broken), Err(crate::Error::Compute(_))));
+    }
+
+    #[tokio::test]
+    async fn caps_are_tagged_and_grids_come_out_unstructured() {
+        let mut module = Clip::new(1);
+        let mut parameters = module.parameters().clone();
+        parameters.set_value("center", ParameterValue::VecFloat(vec![0.5, 0.0, 0.0])).unwrap();
+        parameters.set_value("normal", ParameterValue::VecFloat(vec![1.0, 0.0, 0.0])).unwrap();
+        parameters.set_value("cap", ParameterValue::Bool(true)).unwrap();
+        let ctx = ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot());
+
+        let grid = VistleObject::with_data(ObjectType::UnstructuredGrid, unit_cube());
+        let field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: ndarray::Array1::zeros(8) });
+        module.set_input("grid_in", vec![Arc::new(grid)]).await.unwrap();
+        module.set_input("data_in", vec![Arc::new(field)]).await.unwrap();
+        let outputs = module.compute(&ctx).await.unwrap();
+
+        let grid = &outputs["grid_out"][0];
+        assert!(matches!(grid.payload(), Some(ObjectPayload::UnstructuredGrid { .. })));
+        let vertices = match grid.payload() {
+            Some(ObjectPayload::UnstructuredGrid { coordinates, .. }) => coordinates.nrows(),
+            _ => unreachable!(),
+        };
+        match outputs["data_out"][0].payload() {
+            Some(ObjectPayload::VecScalar { data }) => assert_eq!(data.len(), vertices),
+            other => panic!("expected a scalar field, got {:?}", other),
+        }
+        let cap = &outputs["cap_out"][0];
+        assert_eq!(cap.attributes().get(CLIP_CAP_ATTRIBUTE).map(String::as_str), Some("true"));
+    }
+}
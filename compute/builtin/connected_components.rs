@@ -0,0 +1,578 @@
+//! Labelling of connected groups of cells
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use ndarray::Array1;
+
+use crate::core::{
+    attribute, ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectPayload, ObjectType,
+    Parameter, ParameterSet, ParameterSnapshot, ParameterValue, Port, PortSet, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{empty_output, required_input, CellIncidence};
+
+/// Which cells count as neighbours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adjacency {
+    /// Cells sharing at least one point
+    Vertex,
+    /// Cells sharing at least two points
+    Edge,
+    /// Cells sharing a whole facet (a triangle edge, a line end point)
+    Face,
+}
+
+impl Adjacency {
+    pub fn from_parameters(params: &ParameterSnapshot) -> Result<Self, crate::Error> {
+        match params.get_string("adjacency").unwrap_or("face") {
+            "vertex" => Ok(Adjacency::Vertex),
+            "edge" => Ok(Adjacency::Edge),
+            "face" => Ok(Adjacency::Face),
+            other => Err(crate::Error::Config(format!(
+                "Unknown adjacency {} (expected vertex, edge or face)",
+                other
+            ))),
+        }
+    }
+
+    /// Points two cells must share to be connected, given the points per facet
+    pub fn shared_points(&self, facet: usize) -> usize {
+        match self {
+            Adjacency::Vertex => 1,
+            Adjacency::Edge => 2.min(facet),
+            Adjacency::Face => facet,
+        }
+    }
+}
+
+/// Disjoint sets with path halving and union by rank
+#[derive(Debug, Clone)]
+pub struct UnionFind {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+}
+
+impl UnionFind {
+    pub fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            rank: vec![0; len],
+        }
+    }
+
+    pub fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    /// Join the sets of `a` and `b`; returns false if they were already joined
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        match self.rank[a].cmp(&self.rank[b]) {
+            std::cmp::Ordering::Less => self.parent[a] = b,
+            std::cmp::Ordering::Greater => self.parent[b] = a,
+            std::cmp::Ordering::Equal => {
+                self.parent[b] = a;
+                self.rank[a] += 1;
+            }
+        }
+        true
+    }
+
+    /// Dense labels numbered in order of each set's first element
+    pub fn labels(&mut self) -> Vec<u32> {
+        let mut dense = HashMap::new();
+        (0..self.parent.len())
+            .map(|x| {
+                let root = self.find(x);
+                let next = dense.len() as u32;
+                *dense.entry(root).or_insert(next)
+            })
+            .collect()
+    }
+}
+
+/// Cells incident to each point
+pub struct PointCells {
+    offsets: Vec<usize>,
+    cells: Vec<usize>,
+}
+
+impl PointCells {
+    pub fn new(incidence: &CellIncidence) -> Self {
+        let mut offsets = vec![0usize; incidence.num_points() + 1];
+        for c in 0..incidence.num_cells() {
+            for &p in incidence.cell(c) {
+                offsets[p + 1] += 1;
+            }
+        }
+        for p in 0..incidence.num_points() {
+            offsets[p + 1] += offsets[p];
+        }
+
+        let mut fill = offsets.clone();
+        let mut cells = vec![0; offsets[incidence.num_points()]];
+        for c in 0..incidence.num_cells() {
+            for &p in incidence.cell(c) {
+                cells[fill[p]] = c;
+                fill[p] += 1;
+            }
+        }
+        Self { offsets, cells }
+    }
+
+    pub fn cells(&self, point: usize) -> &[usize] {
+        &self.cells[self.offsets[point]..self.offsets[point + 1]]
+    }
+}
+
+/// Pairs `(a, b)` with `a < b`, `a` in `range`, of cells sharing at least `min_shared` points
+///
+/// Ranges are independent, so a large grid can be split into chunks whose
+/// pairs are computed concurrently and then merged into one `UnionFind`.
+pub fn adjacent_cells(
+    incidence: &CellIncidence,
+    point_cells: &PointCells,
+    min_shared: usize,
+    range: Range<usize>,
+) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut shared: HashMap<usize, usize> = HashMap::new();
+    for a in range {
+        shared.clear();
+        for &p in incidence.cell(a) {
+            for &b in point_cells.cells(p) {
+                if b > a {
+                    *shared.entry(b).or_insert(0) += 1;
+                }
+            }
+        }
+        pairs.extend(shared.iter().filter(|(_, &n)| n >= min_shared).map(|(&b, _)| (a, b)));
+    }
+    pairs
+}
+
+/// Component label of every cell, numbered in order of first occurrence
+pub fn label_cells(incidence: &CellIncidence, min_shared: usize) -> Vec<u32> {
+    const CHUNK: usize = 4096;
+
+    let point_cells = PointCells::new(incidence);
+    let mut sets = UnionFind::new(incidence.num_cells());
+    for start in (0..incidence.num_cells()).step_by(CHUNK) {
+        let end = (start + CHUNK).min(incidence.num_cells());
+        for (a, b) in adjacent_cells(incidence, &point_cells, min_shared, start..end) {
+            sets.union(a, b);
+        }
+    }
+    sets.labels()
+}
+
+/// Summary of one component
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentSummary {
+    pub cells: usize,
+    pub volume: f64,
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub scalar_range: Option<(f32, f32)>,
+}
+
+fn extend_range(range: &mut Option<(f32, f32)>, v: f32) {
+    if !v.is_nan() {
+        let (lo, hi) = range.unwrap_or((v, v));
+        *range = Some((lo.min(v), hi.max(v)));
+    }
+}
+
+/// Module labelling connected groups of cells, e.g. blobs left after thresholding
+///
+/// Each block is labelled on its own; components touching across block
+/// boundaries get separate labels.
+pub struct ConnectedComponents {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl ConnectedComponents {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("adjacency", "vertex, edge or face", ParameterValue::String("face".to_string())));
+        parameters.add(Parameter::new("min_size", "Components with fewer cells are labelled -1", ParameterValue::Int(1)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Grid whose cells are grouped"));
+        ports.add(Port::new_input("data_in", "Scalar field summarized per component").optional());
+        ports.add(Port::new_output("grid_out", "The input grid").derived_from("grid_in"));
+        ports.add(Port::new_output("labels_out", "Component label per cell"));
+        ports.add(Port::new_output("table_out", "One row per component"));
+
+        Self {
+            info: ModuleInfo::new(id, "ConnectedComponents", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+
+    fn summarize(
+        payload: &ObjectPayload,
+        incidence: &CellIncidence,
+        labels: &[i32],
+        count: usize,
+        scalar: Option<&Array1<f32>>,
+    ) -> Vec<ComponentSummary> {
+        let mut summaries = vec![ComponentSummary {
+            cells: 0,
+            volume: 0.0,
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+            scalar_range: None,
+        }; count];
+        let coordinates = payload.coordinates();
+        let element_mapped = scalar.map(|s| s.len() == incidence.num_cells()).unwrap_or(false);
+
+        for (c, &label) in labels.iter().enumerate() {
+            let Ok(label) = usize::try_from(label) else {
+                continue;
+            };
+            let summary = &mut summaries[label];
+            summary.cells += 1;
+            summary.volume += incidence.volumes().map(|v| v[c] as f64).unwrap_or(1.0);
+
+            if let (Some(values), true) = (scalar, element_mapped) {
+                extend_range(&mut summary.scalar_range, values[c]);
+            }
+            for &p in incidence.cell(c) {
+                if let (Some(values), false) = (scalar, element_mapped) {
+                    extend_range(&mut summary.scalar_range, values[p]);
+                }
+                if let Some(coordinates) = coordinates {
+                    for axis in 0..3 {
+                        summary.min[axis] = summary.min[axis].min(coordinates[[p, axis]]);
+                        summary.max[axis] = summary.max[axis].max(coordinates[[p, axis]]);
+                    }
+                }
+            }
+        }
+        summaries
+    }
+
+    fn table(summaries: &[ComponentSummary], with_scalar: bool) -> ObjectPayload {
+        let column = |f: &dyn Fn(&ComponentSummary) -> f64| summaries.iter().map(f).collect::<Array1<f64>>();
+        let mut columns = vec![
+            ("label".to_string(), (0..summaries.len()).map(|l| l as f64).collect()),
+            ("cells".to_string(), column(&|s| s.cells as f64)),
+            ("volume".to_string(), column(&|s| s.volume)),
+        ];
+        for (axis, name) in ["x", "y", "z"].iter().enumerate() {
+            columns.push((format!("min_{}", name), column(&|s| s.min[axis] as f64)));
+            columns.push((format!("max_{}", name), column(&|s| s.max[axis] as f64)));
+        }
+        if with_scalar {
+            columns.push(("scalar_min".to_string(), column(&|s| s.scalar_range.map(|r| r.0 as f64).unwrap_or(f64::NAN))));
+            columns.push(("scalar_max".to_string(), column(&|s| s.scalar_range.map(|r| r.1 as f64).unwrap_or(f64::NAN))));
+        }
+        ObjectPayload::Table { columns }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for ConnectedComponents {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let adjacency = Adjacency::from_parameters(ctx.parameters())?;
+        let min_size = ctx.parameters().get_int("min_size").unwrap_or(1).max(1) as usize;
+        let grids = required_input(&self.inputs, "grid_in")?;
+        let fields = self.inputs.get("data_in").filter(|f| !f.is_empty());
+        if let Some(fields) = fields {
+            if fields.len() != grids.len() {
+                return Err(crate::Error::Compute(format!(
+                    "Got {} grids but {} fields",
+                    grids.len(), fields.len()
+                )));
+            }
+        }
+
+        let mut labels_out: Vec<Arc<dyn Object>> = Vec::with_capacity(grids.len());
+        let mut tables: Vec<Arc<dyn Object>> = Vec::with_capacity(grids.len());
+        for (i, grid) in grids.iter().enumerate() {
+            ctx.checkpoint().await?;
+            if grid.is_empty() {
+                labels_out.push(empty_output(grid.as_ref()));
+                tables.push(empty_output(grid.as_ref()));
+                continue;
+            }
+            let payload = grid.payload()
+                .ok_or_else(|| crate::Error::Compute("Grid object has no data".to_string()))?;
+            let facet = match payload {
+                ObjectPayload::Lines { .. } => 1,
+                _ => 2,
+            };
+            let min_shared = adjacency.shared_points(facet);
+
+            let block = grid.clone();
+            let (incidence, raw) = ctx.run_cpu(move || {
+                let payload = block.payload().expect("checked above");
+                CellIncidence::from_payload(payload).map(|incidence| {
+                    let labels = label_cells(&incidence, min_shared);
+                    (incidence, labels)
+                })
+            }).await??;
+
+            let scalar = match fields.map(|f| &f[i]).filter(|field| !field.is_empty()) {
+                None => None,
+                Some(field) => match field.as_scalar_field() {
+                    Some(view) if view.len() == incidence.num_cells() || view.len() == incidence.num_points() => {
+                        Some(view.values())
+                    }
+                    Some(_) => return Err(crate::Error::Compute(
+                        "data_in must be a scalar field mapped to the grid's cells or vertices".to_string(),
+                    )),
+                    None => return Err(crate::Error::wrong_type("scalar field", field.as_ref())),
+                },
+            };
+
+            // Drop small components and renumber the rest densely
+            let mut sizes: HashMap<u32, usize> = HashMap::new();
+            for &label in &raw {
+                *sizes.entry(label).or_insert(0) += 1;
+            }
+            let mut kept: HashMap<u32, i32> = HashMap::new();
+            let labels: Vec<i32> = raw.iter()
+                .map(|label| {
+                    if sizes[label] < min_size {
+                        return -1;
+                    }
+                    let next = kept.len() as i32;
+                    *kept.entry(*label).or_insert(next)
+                })
+                .collect();
+            tracing::debug!(
+                "ConnectedComponents {}: {} components, {} of at least {} cells",
+                self.info.id, sizes.len(), kept.len(), min_size
+            );
+
+            let summaries = Self::summarize(payload, &incidence, &labels, kept.len(), scalar);
+
+            let data: Array1<f32> = labels.iter().map(|&l| l as f32).collect();
+            let mut field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data })
+                .with_meta(grid.meta().clone());
+            field.set_attribute(attribute::MAPPING.to_string(), attribute::MAPPING_ELEMENT.to_string());
+            field.set_attribute(attribute::COLOR_MODE.to_string(), "categorical".to_string());
+            labels_out.push(Arc::new(field));
+
+            let table = VistleObject::with_data(ObjectType::Table, Self::table(&summaries, scalar.is_some()))
+                .with_meta(grid.meta().clone());
+            tables.push(Arc::new(table));
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("grid_out".to_string(), grids.clone());
+        outputs.insert("labels_out".to_string(), labels_out);
+        outputs.insert("table_out".to_string(), tables);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Mapping;
+    use ndarray::array;
+
+    fn count(labels: &[u32]) -> usize {
+        labels.iter().max().map(|&l| l as usize + 1).unwrap_or(0)
+    }
+
+    #[test]
+    fn union_find_joins_sets_once() {
+        let mut sets = UnionFind::new(5);
+        assert!(sets.union(3, 4));
+        assert!(sets.union(1, 4));
+        assert!(!sets.union(3, 1));
+        assert_eq!(sets.find(1), sets.find(3));
+        assert_ne!(sets.find(0), sets.find(1));
+        // Labels are numbered by first occurrence, whatever the roots are
+        assert_eq!(sets.labels(), vec![0, 1, 2, 1, 1]);
+    }
+
+    #[test]
+    fn separate_strips_are_counted() {
+        // Five chains of line segments with 1..=5 segments each
+        let mut cells = Vec::new();
+        let mut next = 0;
+        for length in 1..=5 {
+            for _ in 0..length {
+                cells.push(vec![next, next + 1]);
+                next += 1;
+            }
+            next += 1;
+        }
+        let incidence = CellIncidence::from_cells(next, cells).unwrap();
+        let labels = label_cells(&incidence, Adjacency::Face.shared_points(1));
+        assert_eq!(count(&labels), 5);
+        assert_eq!(labels[..3], [0, 1, 1]);
+        assert_eq!(labels.iter().filter(|&&l| l == 4).count(), 5);
+    }
+
+    #[test]
+    fn a_connected_grid_is_one_giant_component() {
+        // 9 x 9 quads are connected in every adjacency mode
+        let incidence = CellIncidence::structured([10, 10, 1]).unwrap();
+        for adjacency in [Adjacency::Vertex, Adjacency::Edge, Adjacency::Face] {
+            let labels = label_cells(&incidence, adjacency.shared_points(2));
+            assert_eq!(labels.len(), 81);
+            assert!(labels.iter().all(|&l| l == 0), "{:?}", adjacency);
+        }
+        let labels = label_cells(&CellIncidence::structured([3, 3, 3]).unwrap(), 4);
+        assert!(labels.iter().all(|&l| l == 0));
+    }
+
+    #[test]
+    fn isolated_cells_are_their_own_components() {
+        let incidence = CellIncidence::from_cells(9, (0..3).map(|c| vec![3 * c, 3 * c + 1, 3 * c + 2])).unwrap();
+        assert_eq!(label_cells(&incidence, 1), vec![0, 1, 2]);
+
+        let empty = CellIncidence::from_cells(0, Vec::new()).unwrap();
+        assert!(label_cells(&empty, 1).is_empty());
+    }
+
+    #[test]
+    fn adjacency_decides_whether_corners_connect() {
+        // Two triangles touching in point 2 only, and a third sharing the edge 3-4
+        let incidence = CellIncidence::from_cells(6, vec![vec![0, 1, 2], vec![2, 3, 4], vec![3, 4, 5]]).unwrap();
+        assert_eq!(label_cells(&incidence, Adjacency::Vertex.shared_points(2)), vec![0, 0, 0]);
+        assert_eq!(label_cells(&incidence, Adjacency::Edge.shared_points(2)), vec![0, 1, 1]);
+        assert_eq!(label_cells(&incidence, Adjacency::Face.shared_points(2)), vec![0, 1, 1]);
+        assert_eq!(Adjacency::Edge.shared_points(1), 1);
+    }
+
+    #[test]
+    fn chunks_give_the_same_pairs_as_the_whole_range() {
+        let incidence = CellIncidence::structured([6, 5, 4]).unwrap();
+        let point_cells = PointCells::new(&incidence);
+        let n = incidence.num_cells();
+
+        let mut whole = adjacent_cells(&incidence, &point_cells, 4, 0..n);
+        let mut chunked: Vec<_> = [0..7, 7..30, 30..n].into_iter()
+            .flat_map(|range| adjacent_cells(&incidence, &point_cells, 4, range))
+            .collect();
+        whole.sort_unstable();
+        chunked.sort_unstable();
+        assert_eq!(whole, chunked);
+        // Face neighbours of a 5 x 4 x 3 block of hexahedra
+        assert_eq!(whole.len(), 4 * 4 * 3 + 5 * 3 * 3 + 5 * 4 * 2);
+        assert!(whole.iter().all(|&(a, b)| a < b));
+    }
+
+    fn context(adjacency: &str, min_size: i32) -> ComputeContext {
+        let mut parameters = ConnectedComponents::new(1).parameters().clone();
+        parameters.set_value("adjacency", ParameterValue::String(adjacency.to_string())).unwrap();
+        parameters.set_value("min_size", ParameterValue::Int(min_size)).unwrap();
+        ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot())
+    }
+
+    /// A unit square of two triangles at x = 0 and another at x = 10, plus a lone triangle at x = 20
+    fn blobs() -> VistleObject {
+        let coordinates = array![
+            [0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0],
+            [10.0, 0.0, 0.0], [11.0, 0.0, 0.0], [11.0, 1.0, 0.0], [10.0, 1.0, 0.0],
+            [20.0, 0.0, 0.0], [21.0, 0.0, 0.0], [20.0, 1.0, 0.0],
+        ];
+        let triangles = array![[0, 1, 2], [0, 2, 3], [4, 5, 6], [4, 6, 7], [8, 9, 10]];
+        VistleObject::with_data(ObjectType::Triangles, ObjectPayload::Triangles { coordinates, triangles })
+    }
+
+    async fn run(ctx: &ComputeContext, with_scalar: bool) -> OutputPorts {
+        let grid = blobs();
+        let mut module = ConnectedComponents::new(1);
+        if with_scalar {
+            let data = Array1::from_shape_fn(11, |p| p as f32);
+            let field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data })
+                .with_grid(&grid, Mapping::PerVertex);
+            module.set_input("data_in", vec![Arc::new(field)]).await.unwrap();
+        }
+        module.set_input("grid_in", vec![Arc::new(grid)]).await.unwrap();
+        module.compute(ctx).await.unwrap()
+    }
+
+    fn label_values(outputs: &OutputPorts) -> Vec<f32> {
+        match outputs["labels_out"][0].payload().unwrap() {
+            ObjectPayload::VecScalar { data } => data.to_vec(),
+            other => panic!("unexpected payload {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn module_labels_cells_and_summarizes_components() {
+        let outputs = run(&context("face", 1), true).await;
+        assert_eq!(label_values(&outputs), vec![0.0, 0.0, 1.0, 1.0, 2.0]);
+        assert_eq!(
+            outputs["labels_out"][0].get_attribute(attribute::MAPPING),
+            Some(attribute::MAPPING_ELEMENT)
+        );
+        assert_eq!(outputs["grid_out"][0].object_type(), ObjectType::Triangles);
+
+        let table = outputs["table_out"][0].payload().unwrap();
+        assert_eq!(table.column("cells").unwrap().to_vec(), vec![2.0, 2.0, 1.0]);
+        assert_eq!(table.column("volume").unwrap().to_vec(), vec![1.0, 1.0, 0.5]);
+        assert_eq!(table.column("min_x").unwrap().to_vec(), vec![0.0, 10.0, 20.0]);
+        assert_eq!(table.column("max_y").unwrap().to_vec(), vec![1.0, 1.0, 1.0]);
+        assert_eq!(table.column("scalar_min").unwrap().to_vec(), vec![0.0, 4.0, 8.0]);
+        assert_eq!(table.column("scalar_max").unwrap().to_vec(), vec![3.0, 7.0, 10.0]);
+    }
+
+    #[tokio::test]
+    async fn small_components_are_dropped() {
+        let outputs = run(&context("face", 2), false).await;
+        assert_eq!(label_values(&outputs), vec![0.0, 0.0, 1.0, 1.0, -1.0]);
+        let table = outputs["table_out"][0].payload().unwrap();
+        assert_eq!(table.column("cells").unwrap().to_vec(), vec![2.0, 2.0]);
+        assert!(table.column("scalar_min").is_none());
+
+        // Nothing is large enough
+        let outputs = run(&context("face", 3), false).await;
+        assert!(label_values(&outputs).iter().all(|&l| l == -1.0));
+        assert!(outputs["table_out"][0].payload().unwrap().column("cells").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_adjacency_is_an_error() {
+        let mut module = ConnectedComponents::new(1);
+        module.set_input("grid_in", vec![Arc::new(blobs())]).await.unwrap();
+        let error = module.compute(&context("diagonal", 1)).await.unwrap_err();
+        assert!(error.to_string().contains("Unknown adjacency diagonal"));
+    }
+}
@@ -0,0 +1,626 @@
+//! Critical points of vector fields: sources, sinks, saddles and vortices
+//!
+//! Candidate cells are those where every velocity component changes sign
+//! over the cell's corners. Within such a cell, Newton iterations on the
+//! trilinear (bilinear in 2D) interpolant find the zero, and the Jacobian
+//! of the interpolant there, taken to physical coordinates, classifies it.
+//! Grids with a single point along one axis are treated as 2D, with the
+//! vector components along the other two axes.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use nalgebra::{Complex, ComplexField, DMatrix, DVector, Vector3};
+use ndarray::{Array1, Array2};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    attribute, ComputeContext, ExecutionStats, Mapping, ModuleInfo, Object, ObjectPayload, ObjectType, Parameter,
+    ParameterSet, ParameterValue, Port, PortSet, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{empty_output, required_input};
+
+/// Kind of a critical point, from the eigenvalues of the Jacobian there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CriticalPointType {
+    /// All eigenvalues real and positive
+    Source,
+    /// All eigenvalues real and negative
+    Sink,
+    /// Eigenvalues, or their real parts, of both signs
+    Saddle,
+    /// Complex eigenvalues with vanishing real part, a vortex core
+    Center,
+    /// Complex eigenvalues, all real parts positive
+    RepellingFocus,
+    /// Complex eigenvalues, all real parts negative
+    AttractingFocus,
+    /// Singular Jacobian; the linearization does not tell
+    Degenerate,
+}
+
+impl CriticalPointType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CriticalPointType::Source => "source",
+            CriticalPointType::Sink => "sink",
+            CriticalPointType::Saddle => "saddle",
+            CriticalPointType::Center => "center",
+            CriticalPointType::RepellingFocus => "repelling focus",
+            CriticalPointType::AttractingFocus => "attracting focus",
+            CriticalPointType::Degenerate => "degenerate",
+        }
+    }
+
+    /// Value in the categorical type field of `CriticalPoints`
+    pub fn code(&self) -> i32 {
+        *self as i32
+    }
+
+    /// Classify by the eigenvalues of the Jacobian
+    ///
+    /// Parts smaller than `tolerance` times the largest magnitude count as zero.
+    pub fn classify(eigenvalues: &[Complex<f64>], tolerance: f64) -> Self {
+        let scale = eigenvalues.iter().map(|l| l.modulus()).fold(0.0, f64::max);
+        if !scale.is_finite() || scale == 0.0 {
+            return CriticalPointType::Degenerate;
+        }
+        let zero = tolerance * scale;
+        if eigenvalues.iter().any(|l| l.modulus() <= zero) {
+            return CriticalPointType::Degenerate;
+        }
+
+        let rotating = eigenvalues.iter().any(|l| l.im.abs() > zero);
+        if rotating && eigenvalues.iter().any(|l| l.im.abs() > zero && l.re.abs() <= zero) {
+            return CriticalPointType::Center;
+        }
+        let positive = eigenvalues.iter().filter(|l| l.re > zero).count();
+        let negative = eigenvalues.iter().filter(|l| l.re < -zero).count();
+        match (positive, negative, rotating) {
+            (_, 0, false) => CriticalPointType::Source,
+            (0, _, false) => CriticalPointType::Sink,
+            (_, 0, true) => CriticalPointType::RepellingFocus,
+            (0, _, true) => CriticalPointType::AttractingFocus,
+            _ => CriticalPointType::Saddle,
+        }
+    }
+}
+
+/// Zero of a vector field with its classification
+#[derive(Debug, Clone, PartialEq)]
+pub struct CriticalPoint {
+    pub position: Vector3<f64>,
+    pub kind: CriticalPointType,
+    /// Eigenvalues of the Jacobian, two for 2D grids
+    pub eigenvalues: Vec<Complex<f64>>,
+}
+
+/// How critical points are located and classified
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CriticalPointOptions {
+    /// Newton iterations per candidate cell
+    pub max_iterations: usize,
+    /// Relative tolerance for convergence and for zero eigenvalue parts
+    pub tolerance: f64,
+}
+
+impl Default for CriticalPointOptions {
+    fn default() -> Self {
+        Self {
+            max_iterations: 20,
+            tolerance: 1e-6,
+        }
+    }
+}
+
+/// Corners of one lattice cell, with positions and vectors restricted to the active axes
+struct Cell {
+    positions: Vec<DVector<f64>>,
+    vectors: Vec<DVector<f64>>,
+}
+
+impl Cell {
+    /// Interpolation weight of each corner at local coordinates `xi`, with its gradient
+    fn weights(&self, xi: &DVector<f64>) -> Vec<(f64, DVector<f64>)> {
+        let d = xi.len();
+        (0..self.positions.len())
+            .map(|corner| {
+                let factor = |a: usize| if corner >> a & 1 == 1 { xi[a] } else { 1.0 - xi[a] };
+                let weight = (0..d).map(factor).product();
+                let gradient = DVector::from_fn(d, |a, _| {
+                    let sign = if corner >> a & 1 == 1 { 1.0 } else { -1.0 };
+                    sign * (0..d).filter(|&b| b != a).map(factor).product::<f64>()
+                });
+                (weight, gradient)
+            })
+            .collect()
+    }
+
+    /// Value and derivative with respect to `xi` of per-corner `values` at `xi`
+    fn interpolate(&self, values: &[DVector<f64>], xi: &DVector<f64>) -> (DVector<f64>, DMatrix<f64>) {
+        let d = xi.len();
+        let mut value = DVector::zeros(d);
+        let mut derivative = DMatrix::zeros(d, d);
+        for ((weight, gradient), v) in self.weights(xi).into_iter().zip(values) {
+            value += v * weight;
+            derivative += v * gradient.transpose();
+        }
+        (value, derivative)
+    }
+
+    /// Whether every component takes both signs, or zero, over the corners
+    fn may_contain_zero(&self) -> bool {
+        let d = self.vectors[0].len();
+        (0..d).all(|a| {
+            let (min, max) = self.vectors.iter()
+                .map(|v| v[a])
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+            min <= 0.0 && max >= 0.0
+        })
+    }
+
+    /// Zero of the interpolant inside the cell by Newton iterations from its center
+    fn find_zero(&self, options: &CriticalPointOptions) -> Option<CriticalPoint> {
+        let d = self.vectors[0].len();
+        let scale = self.vectors.iter().map(|v| v.norm()).fold(0.0, f64::max);
+        if scale == 0.0 {
+            // A field vanishing over a whole cell has no isolated zero
+            return None;
+        }
+        let mut xi = DVector::from_element(d, 0.5);
+        let mut converged = false;
+        for _ in 0..options.max_iterations {
+            let (value, derivative) = self.interpolate(&self.vectors, &xi);
+            if value.norm() <= options.tolerance * scale {
+                converged = true;
+                break;
+            }
+            let step = derivative.lu().solve(&-value)?;
+            // Keep iterating in the neighborhood of the cell only
+            xi = (xi + step).map(|x| x.clamp(-0.5, 1.5));
+        }
+        if !converged {
+            let (value, _) = self.interpolate(&self.vectors, &xi);
+            converged = value.norm() <= options.tolerance * scale;
+        }
+        let margin = options.tolerance.sqrt();
+        if !converged || xi.iter().any(|&x| x < -margin || x > 1.0 + margin) {
+            return None;
+        }
+
+        let (position, dx) = self.interpolate(&self.positions, &xi);
+        let (_, dv) = self.interpolate(&self.vectors, &xi);
+        let (kind, eigenvalues) = match dx.try_inverse() {
+            Some(inverse) => {
+                let jacobian = dv * inverse;
+                classify_jacobian(&jacobian, options.tolerance)
+            }
+            None => (CriticalPointType::Degenerate, Vec::new()),
+        };
+        Some(CriticalPoint {
+            position: Vector3::from_fn(|a, _| position.get(a).copied().unwrap_or(0.0)),
+            kind,
+            eigenvalues,
+        })
+    }
+}
+
+/// Type and eigenvalues of a Jacobian; singular ones are degenerate without an eigen solve
+fn classify_jacobian(jacobian: &DMatrix<f64>, tolerance: f64) -> (CriticalPointType, Vec<Complex<f64>>) {
+    let d = jacobian.nrows();
+    let norm = jacobian.norm();
+    if !norm.is_finite() || norm == 0.0 || jacobian.determinant().abs() <= tolerance * norm.powi(d as i32) {
+        return (CriticalPointType::Degenerate, Vec::new());
+    }
+    let eigenvalues: Vec<Complex<f64>> = jacobian.complex_eigenvalues().iter().copied().collect();
+    if eigenvalues.iter().any(|l| !l.re.is_finite() || !l.im.is_finite()) {
+        return (CriticalPointType::Degenerate, Vec::new());
+    }
+    (CriticalPointType::classify(&eigenvalues, tolerance), eigenvalues)
+}
+
+/// Critical points of a per-point vector field on a uniform, rectilinear or structured grid
+///
+/// Points found by several cells, on shared faces or corners, are reported once.
+pub fn critical_points(
+    grid: &ObjectPayload,
+    vectors: &[Vector3<f64>],
+    options: &CriticalPointOptions,
+) -> Result<Vec<CriticalPoint>, crate::Error> {
+    let dims = grid.grid_dims().ok_or_else(|| crate::Error::WrongType {
+        expected: "uniform, rectilinear or structured grid".to_string(),
+        found: grid.kind().to_string(),
+    })?;
+    if vectors.len() != dims.iter().product::<usize>() {
+        return Err(crate::Error::Compute(format!(
+            "Vector field has {} values for {} grid points",
+            vectors.len(), dims.iter().product::<usize>()
+        )));
+    }
+    let axes: Vec<usize> = (0..3).filter(|&a| dims[a] > 1).collect();
+    if axes.len() < 2 {
+        return Err(crate::Error::Compute(format!(
+            "Critical points need a 2D or 3D grid, got {}x{}x{} points",
+            dims[0], dims[1], dims[2]
+        )));
+    }
+
+    let restrict = |v: Vector3<f64>| DVector::from_iterator(axes.len(), axes.iter().map(|&a| v[a]));
+    let cells = [0, 1, 2].map(|a| dims[a].saturating_sub(1).max(1));
+    let mut found: Vec<CriticalPoint> = Vec::new();
+    for k in 0..cells[2] {
+        for j in 0..cells[1] {
+            for i in 0..cells[0] {
+                let corners: Vec<[usize; 3]> = (0..1usize << axes.len())
+                    .map(|corner| {
+                        let mut ijk = [i, j, k];
+                        for (bit, &a) in axes.iter().enumerate() {
+                            ijk[a] += corner >> bit & 1;
+                        }
+                        ijk
+                    })
+                    .collect();
+                let mut cell = Cell { positions: Vec::new(), vectors: Vec::new() };
+                for ijk in &corners {
+                    let index = grid.point_index(*ijk).expect("corner lies within the grid");
+                    let position = grid.grid_point(*ijk).expect("grid has lattice points");
+                    cell.positions.push(restrict(position.cast::<f64>()));
+                    cell.vectors.push(restrict(vectors[index]));
+                }
+                if !cell.may_contain_zero() {
+                    continue;
+                }
+                let Some(mut point) = cell.find_zero(options) else {
+                    continue;
+                };
+                // Place the position back on all three axes; flat axes keep the grid's coordinate
+                let base = grid.grid_point(corners[0]).expect("grid has lattice points").cast::<f64>();
+                let mut position = base;
+                for (n, &a) in axes.iter().enumerate() {
+                    position[a] = point.position[n];
+                }
+                point.position = position;
+
+                let size = (&cell.positions[cell.positions.len() - 1] - &cell.positions[0]).norm();
+                let duplicate = found.iter().any(|p| (p.position - point.position).norm() <= options.tolerance.sqrt() * size);
+                if !duplicate {
+                    found.push(point);
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Vectors of a single or double precision vector field
+fn vectors(field: &dyn Object) -> Result<Vec<Vector3<f64>>, crate::Error> {
+    if let Some(view) = field.as_vector_field() {
+        return Ok((0..view.len()).map(|i| view.get(i).cast::<f64>()).collect());
+    }
+    if let Some(view) = field.as_vector_field_f64() {
+        return Ok((0..view.len()).map(|i| view.get(i)).collect());
+    }
+    Err(crate::Error::wrong_type("vector field", field))
+}
+
+/// Module locating and classifying the critical points of a vector field
+///
+/// Takes a uniform, rectilinear or structured grid and a vector field with
+/// one vector per grid point. Outputs the critical points, their type as a
+/// categorical field with the codes of `CriticalPointType` (0 source,
+/// 1 sink, 2 saddle, 3 center, 4 repelling focus, 5 attracting focus,
+/// 6 degenerate) and the magnitudes of the Jacobian's eigenvalues, largest
+/// first. Both fields are mapped onto the points, ready for glyphs.
+pub struct CriticalPoints {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl CriticalPoints {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("iterations", "Newton iterations per candidate cell", ParameterValue::Int(20)));
+        parameters.add(Parameter::new("tolerance", "Relative tolerance for zeros and zero eigenvalues", ParameterValue::Float(1e-6)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Uniform, rectilinear or structured grid"));
+        ports.add(Port::new_input("data_in", "Vector field per grid point"));
+        ports.add(Port::new_output("points_out", "Critical points"));
+        ports.add(Port::new_output("type_out", "Type of each point, see CriticalPointType"));
+        ports.add(Port::new_output("eigenvalues_out", "Eigenvalue magnitudes of the Jacobian at each point"));
+
+        Self {
+            info: ModuleInfo::new(id, "CriticalPoints", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for CriticalPoints {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let parameters = ctx.parameters();
+        let options = CriticalPointOptions {
+            max_iterations: parameters.get_int("iterations").unwrap_or(20).max(1) as usize,
+            tolerance: parameters.get_float("tolerance").unwrap_or(1e-6).max(f32::EPSILON) as f64,
+        };
+        let grids = required_input(&self.inputs, "grid_in")?;
+        let fields = required_input(&self.inputs, "data_in")?;
+        if fields.len() != grids.len() {
+            return Err(crate::Error::Compute(format!(
+                "Got {} grids but {} fields",
+                grids.len(), fields.len()
+            )));
+        }
+
+        let mut points_out: Vec<Arc<dyn Object>> = Vec::with_capacity(grids.len());
+        let mut types_out: Vec<Arc<dyn Object>> = Vec::with_capacity(grids.len());
+        let mut eigenvalues_out: Vec<Arc<dyn Object>> = Vec::with_capacity(grids.len());
+        for (grid, field) in grids.iter().zip(fields) {
+            ctx.checkpoint().await?;
+            if grid.payload().is_none() || grid.is_empty() || field.is_empty() {
+                points_out.push(empty_output(grid.as_ref()));
+                types_out.push(empty_output(field.as_ref()));
+                eigenvalues_out.push(empty_output(field.as_ref()));
+                continue;
+            }
+
+            let vectors = vectors(field.as_ref())?;
+            let source = grid.clone();
+            let found = ctx.run_cpu(move || {
+                let payload = source.payload().expect("checked above");
+                critical_points(payload, &vectors, &options)
+            }).await??;
+            tracing::debug!("CriticalPoints {}: {} critical points", self.info.id, found.len());
+            if found.is_empty() {
+                points_out.push(empty_output(grid.as_ref()));
+                types_out.push(empty_output(field.as_ref()));
+                eigenvalues_out.push(empty_output(field.as_ref()));
+                continue;
+            }
+
+            let coordinates = Array2::from_shape_fn((found.len(), 3), |(p, a)| found[p].position[a] as f32);
+            let points: Arc<dyn Object> = Arc::new(
+                VistleObject::with_data(ObjectType::Points, ObjectPayload::Points { coordinates })
+                    .with_meta(grid.meta().clone()),
+            );
+
+            let codes: Array1<f32> = found.iter().map(|p| p.kind.code() as f32).collect();
+            let mut types = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: codes })
+                .with_meta(field.meta().clone())
+                .with_grid(points.as_ref(), Mapping::PerVertex);
+            types.set_attribute(attribute::MAPPING.to_string(), attribute::MAPPING_VERTEX.to_string());
+            types.set_attribute(attribute::COLOR_MODE.to_string(), "categorical".to_string());
+
+            let magnitudes = Array2::from_shape_fn((found.len(), 3), |(p, a)| {
+                let mut sorted: Vec<f64> = found[p].eigenvalues.iter().map(|l| l.modulus()).collect();
+                sorted.sort_by(|x, y| y.total_cmp(x));
+                sorted.get(a).copied().unwrap_or(0.0) as f32
+            });
+            let mut eigenvalues = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecVec3 { data: magnitudes })
+                .with_meta(field.meta().clone())
+                .with_grid(points.as_ref(), Mapping::PerVertex);
+            eigenvalues.set_attribute(attribute::MAPPING.to_string(), attribute::MAPPING_VERTEX.to_string());
+
+            points_out.push(points);
+            types_out.push(Arc::new(types));
+            eigenvalues_out.push(Arc::new(eigenvalues));
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("points_out".to_string(), points_out);
+        outputs.insert("type_out".to_string(), types_out);
+        outputs.insert("eigenvalues_out".to_string(), eigenvalues_out);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    /// Uniform grid with spacing 0.5 and no values of its own
+    fn grid(dims: [usize; 3], origin: [f32; 3]) -> ObjectPayload {
+        let points: usize = dims.iter().product();
+        ObjectPayload::UniformGrid { dims, origin, spacing: [0.5; 3], values: Array1::zeros(points) }
+    }
+
+    /// Field values at the points of a grid
+    fn sample(grid: &ObjectPayload, field: impl Fn(Vector3<f64>) -> Vector3<f64>) -> Vec<Vector3<f64>> {
+        let points = grid.num_vertices();
+        (0..points)
+            .map(|p| field(grid.grid_point(grid.point_ijk(p).unwrap()).unwrap().cast::<f64>()))
+            .collect()
+    }
+
+    fn find(grid: &ObjectPayload, field: impl Fn(Vector3<f64>) -> Vector3<f64>) -> Vec<CriticalPoint> {
+        critical_points(grid, &sample(grid, field), &CriticalPointOptions::default()).unwrap()
+    }
+
+    fn assert_near(actual: Vector3<f64>, expected: Vector3<f64>) {
+        assert!((actual - expected).norm() < 1e-9, "{:?} is not at {:?}", actual, expected);
+    }
+
+    #[test]
+    fn saddles_are_located_inside_their_cell() {
+        let plane = grid([5, 5, 1], [-1.0, -1.0, 0.0]);
+        let found = find(&plane, |p| Vector3::new(p.x - 0.3, -(p.y - 0.2), 0.0));
+        assert_eq!(found.len(), 1);
+        assert_near(found[0].position, Vector3::new(0.3, 0.2, 0.0));
+        assert_eq!(found[0].kind, CriticalPointType::Saddle);
+        let mut eigenvalues: Vec<f64> = found[0].eigenvalues.iter().map(|l| l.re).collect();
+        eigenvalues.sort_by(f64::total_cmp);
+        assert!((eigenvalues[0] + 1.0).abs() < 1e-9 && (eigenvalues[1] - 1.0).abs() < 1e-9, "{:?}", eigenvalues);
+    }
+
+    #[test]
+    fn rotating_fields_are_centers_and_foci() {
+        let plane = grid([5, 5, 1], [-1.0, -1.0, 0.0]);
+        let cases = [
+            (0.0, CriticalPointType::Center),
+            (0.5, CriticalPointType::RepellingFocus),
+            (-0.5, CriticalPointType::AttractingFocus),
+        ];
+        for (spiral, kind) in cases {
+            let found = find(&plane, |p| {
+                let (x, y) = (p.x - 0.3, p.y - 0.2);
+                Vector3::new(spiral * x - y, x + spiral * y, 0.0)
+            });
+            assert_eq!(found.len(), 1, "{:?}", kind);
+            assert_near(found[0].position, Vector3::new(0.3, 0.2, 0.0));
+            assert_eq!(found[0].kind, kind);
+            assert!(found[0].eigenvalues.iter().all(|l| (l.im.abs() - 1.0).abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn sources_and_sinks_in_3d() {
+        let volume = grid([5, 5, 5], [-1.0; 3]);
+        let offset = Vector3::new(0.3, -0.1, 0.2);
+        let sources = find(&volume, |p| p - offset);
+        assert_eq!(sources.len(), 1);
+        assert_near(sources[0].position, offset);
+        assert_eq!(sources[0].kind, CriticalPointType::Source);
+        assert_eq!(sources[0].eigenvalues.len(), 3);
+
+        let sinks = find(&volume, |p| offset - p);
+        assert_eq!(sinks.len(), 1);
+        assert_eq!(sinks[0].kind, CriticalPointType::Sink);
+    }
+
+    #[test]
+    fn zeros_on_shared_grid_points_are_reported_once() {
+        let plane = grid([5, 5, 1], [-1.0, -1.0, 0.0]);
+        let found = find(&plane, |p| Vector3::new(p.x, -p.y, 0.0));
+        assert_eq!(found.len(), 1);
+        assert_near(found[0].position, Vector3::zeros());
+    }
+
+    #[test]
+    fn flat_axes_keep_the_grid_coordinate() {
+        let plane = grid([1, 5, 5], [7.0, -1.0, -1.0]);
+        let found = find(&plane, |p| Vector3::new(0.0, p.y - 0.3, -(p.z - 0.2)));
+        assert_eq!(found.len(), 1);
+        assert_near(found[0].position, Vector3::new(7.0, 0.3, 0.2));
+        assert_eq!(found[0].kind, CriticalPointType::Saddle);
+    }
+
+    #[test]
+    fn rectilinear_grids_with_uneven_spacing() {
+        let plane = ObjectPayload::RectilinearGrid {
+            x: array![-1.0, 0.0, 0.25, 1.0],
+            y: array![-1.0, 0.125, 0.5, 1.0],
+            z: array![0.0],
+        };
+        let found = find(&plane, |p| Vector3::new(p.x - 0.3, -(p.y - 0.2), 0.0));
+        assert_eq!(found.len(), 1);
+        assert_near(found[0].position, Vector3::new(0.3, 0.2, 0.0));
+    }
+
+    #[test]
+    fn fields_without_zeros_have_no_critical_points() {
+        let plane = grid([5, 5, 1], [-1.0, -1.0, 0.0]);
+        assert!(find(&plane, |_| Vector3::new(1.0, -1.0, 0.0)).is_empty());
+        assert!(find(&plane, |_| Vector3::zeros()).is_empty());
+    }
+
+    #[test]
+    fn singular_jacobians_are_degenerate() {
+        let singular = DMatrix::from_row_slice(2, 2, &[1.0, 2.0, 2.0, 4.0]);
+        assert_eq!(classify_jacobian(&singular, 1e-6), (CriticalPointType::Degenerate, Vec::new()));
+        assert_eq!(classify_jacobian(&DMatrix::zeros(3, 3), 1e-6).0, CriticalPointType::Degenerate);
+        let broken = DMatrix::from_row_slice(2, 2, &[f64::NAN, 0.0, 0.0, 1.0]);
+        assert_eq!(classify_jacobian(&broken, 1e-6).0, CriticalPointType::Degenerate);
+
+        let real = |values: &[f64]| values.iter().map(|&v| Complex::new(v, 0.0)).collect::<Vec<_>>();
+        assert_eq!(CriticalPointType::classify(&real(&[1.0, 0.0]), 1e-6), CriticalPointType::Degenerate);
+        assert_eq!(CriticalPointType::classify(&[], 1e-6), CriticalPointType::Degenerate);
+        assert_eq!(CriticalPointType::classify(&real(&[2.0, 1.0, 3.0]), 1e-6), CriticalPointType::Source);
+        assert_eq!(CriticalPointType::classify(&real(&[-2.0, 1.0, -3.0]), 1e-6), CriticalPointType::Saddle);
+        assert_eq!(CriticalPointType::Degenerate.code(), 6);
+    }
+
+    #[test]
+    fn grids_and_fields_must_fit() {
+        let plane = grid([5, 5, 1], [-1.0, -1.0, 0.0]);
+        let options = CriticalPointOptions::default();
+        assert!(matches!(critical_points(&plane, &[Vector3::zeros(); 24], &options), Err(crate::Error::Compute(_))));
+
+        let line = grid([5, 1, 1], [-1.0, 0.0, 0.0]);
+        let message = critical_points(&line, &[Vector3::zeros(); 5], &options).unwrap_err().to_string();
+        assert!(message.contains("need a 2D or 3D grid"), "{}", message);
+
+        let points = ObjectPayload::Points { coordinates: Array2::zeros((1, 3)) };
+        assert!(matches!(critical_points(&points, &[Vector3::zeros()], &options), Err(crate::Error::WrongType { .. })));
+    }
+
+    fn context() -> ComputeContext {
+        ComputeContext::new(1, 0, 1).with_parameters(CriticalPoints::new(1).parameters().snapshot())
+    }
+
+    async fn run(field: impl Fn(Vector3<f64>) -> Vector3<f64>) -> OutputPorts {
+        let plane = grid([5, 5, 1], [-1.0, -1.0, 0.0]);
+        let vectors = sample(&plane, field);
+        let data = Array2::from_shape_fn((vectors.len(), 3), |(p, a)| vectors[p][a] as f32);
+        let mut module = CriticalPoints::new(1);
+        module.set_input("grid_in", vec![Arc::new(VistleObject::with_data(ObjectType::UniformGrid, plane))]).await.unwrap();
+        module.set_input("data_in", vec![Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecVec3 { data }))]).await.unwrap();
+        module.compute(&context()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn module_outputs_points_with_types_and_magnitudes() {
+        let outputs = run(|p| Vector3::new(p.x - 0.3, -(p.y - 0.2), 0.0)).await;
+
+        let points = &outputs["points_out"][0];
+        let coordinates = points.payload().and_then(ObjectPayload::coordinates).unwrap();
+        assert_eq!(coordinates.nrows(), 1);
+        assert!((coordinates[[0, 0]] - 0.3).abs() < 1e-5 && (coordinates[[0, 1]] - 0.2).abs() < 1e-5);
+
+        let types = &outputs["type_out"][0];
+        assert_eq!(types.as_scalar_field().unwrap().values().to_vec(), [CriticalPointType::Saddle.code() as f32]);
+        assert_eq!(types.get_attribute(attribute::COLOR_MODE), Some("categorical"));
+        assert_eq!(types.grid().map(|g| (g.grid, g.mapping)), Some((points.id(), Mapping::PerVertex)));
+
+        let magnitudes = outputs["eigenvalues_out"][0].as_vector_field().unwrap();
+        let largest = magnitudes.get(0);
+        assert!((largest.x - 1.0).abs() < 1e-4 && (largest.y - 1.0).abs() < 1e-4 && largest.z == 0.0, "{:?}", largest);
+    }
+
+    #[tokio::test]
+    async fn module_outputs_empty_objects_without_critical_points() {
+        let outputs = run(|_| Vector3::new(1.0, 1.0, 0.0)).await;
+        for port in ["points_out", "type_out", "eigenvalues_out"] {
+            assert!(outputs[port][0].is_empty(), "{}", port);
+        }
+    }
+}
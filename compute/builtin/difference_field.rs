@@ -0,0 +1,330 @@
+//! Difference of two scalar fields, e.g. simulation against experiment
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ndarray::Array1;
+
+use crate::core::{
+    attribute, ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectPayload, ObjectType,
+    Parameter, ParameterSet, ParameterValue, Port, PortSet, Units, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{empty_output, required_input};
+
+/// Diverging colormap suggested for differences
+pub const DIFFERENCE_COLORMAP: &str = "Cool to Warm";
+
+/// How the difference of two fields is expressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DifferenceMode {
+    /// `a - b` in the units of the inputs
+    Absolute,
+    /// `(a - b) / |b|`; NaN where `|b|` is below the epsilon
+    Relative,
+}
+
+impl DifferenceMode {
+    pub fn parse(text: &str) -> Result<Self, crate::Error> {
+        match text {
+            "absolute" => Ok(DifferenceMode::Absolute),
+            "relative" => Ok(DifferenceMode::Relative),
+            _ => Err(crate::Error::Config(format!(
+                "Unknown difference mode '{}', expected absolute or relative",
+                text
+            ))),
+        }
+    }
+}
+
+/// Sample a uniform grid at the points of another one by trilinear interpolation
+///
+/// Points outside the source grid get NaN, so they show in the colormap's NaN color.
+pub fn resample_uniform_grid(source: &ObjectPayload, dims: [usize; 3], origin: [f32; 3], spacing: [f32; 3]) -> Option<ObjectPayload> {
+    let values = resampled_values(source, dims, origin, spacing)?;
+    Some(ObjectPayload::UniformGrid { dims, origin, spacing, values })
+}
+
+/// Trilinear sample of a uniform grid at `p`; NaN outside the grid or for other payloads
+pub fn sample_uniform_grid(source: &ObjectPayload, p: [f32; 3]) -> f32 {
+    let ObjectPayload::UniformGrid { dims: src_dims, origin: src_origin, spacing: src_spacing, .. } = source else {
+        return f32::NAN;
+    };
+
+    let mut base = [0usize; 3];
+    let mut frac = [0f32; 3];
+    for a in 0..3 {
+        let last = src_dims[a].saturating_sub(1);
+        let t = if src_spacing[a] != 0.0 { (p[a] - src_origin[a]) / src_spacing[a] } else { 0.0 };
+        if !(-1e-4..=last as f32 + 1e-4).contains(&t) {
+            return f32::NAN;
+        }
+        let t = t.clamp(0.0, last as f32);
+        base[a] = (t.floor() as usize).min(last.saturating_sub(1));
+        frac[a] = if last == 0 { 0.0 } else { t - base[a] as f32 };
+    }
+    let mut value = 0.0;
+    for corner in 0..8 {
+        let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+        let mut weight = 1.0;
+        let mut point = [0usize; 3];
+        for a in 0..3 {
+            weight *= if offset[a] == 1 { frac[a] } else { 1.0 - frac[a] };
+            point[a] = (base[a] + offset[a]).min(src_dims[a].saturating_sub(1));
+        }
+        if weight > 0.0 {
+            value += weight * source.grid_value(point).unwrap_or(f32::NAN);
+        }
+    }
+    value
+}
+
+fn resampled_values(source: &ObjectPayload, dims: [usize; 3], origin: [f32; 3], spacing: [f32; 3]) -> Option<Array1<f32>> {
+    if !matches!(source, ObjectPayload::UniformGrid { .. }) {
+        return None;
+    }
+    let sample = |p: [f32; 3]| sample_uniform_grid(source, p);
+
+    let mut values = Vec::with_capacity(dims.iter().product());
+    for k in 0..dims[2] {
+        for j in 0..dims[1] {
+            for i in 0..dims[0] {
+                let index = [i, j, k];
+                values.push(sample(std::array::from_fn(|a| origin[a] + index[a] as f32 * spacing[a])));
+            }
+        }
+    }
+    Some(Array1::from(values))
+}
+
+fn difference(a: &Array1<f32>, b: &Array1<f32>, mode: DifferenceMode, epsilon: f32) -> Result<Array1<f32>, crate::Error> {
+    if a.len() != b.len() {
+        return Err(crate::Error::Compute(format!("Cannot subtract {} values from {}", b.len(), a.len())));
+    }
+    Ok(match mode {
+        DifferenceMode::Absolute => a - b,
+        DifferenceMode::Relative => ndarray::Zip::from(a).and(b)
+            .map_collect(|&a, &b| if b.abs() < epsilon { f32::NAN } else { (a - b) / b.abs() }),
+    })
+}
+
+/// Module computing `a - b` of paired scalar fields
+///
+/// Objects on the two ports are paired in order. Scalar arrays must have
+/// equal length; uniform grids on different lattices are compared after
+/// resampling `b` onto the grid of `a`. Fields whose units have different
+/// dimensions are refused, see `Units::common`. The output suggests a diverging
+/// colormap and carries a range symmetric around zero.
+pub struct DifferenceField {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl DifferenceField {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("mode", "absolute (a - b) or relative ((a - b) / |b|)", ParameterValue::String("absolute".to_string())));
+        parameters.add(Parameter::new("epsilon", "Smallest |b| a relative difference is computed for", ParameterValue::Float(1e-6)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("a", "Fields to subtract from"));
+        ports.add(Port::new_input("b", "Fields to subtract"));
+        ports.add(Port::new_output("difference", "Difference fields on the points of a").derived_from("a"));
+
+        Self {
+            info: ModuleInfo::new(id, "DifferenceField", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+
+    fn subtract(&self, a: &dyn Object, b: &dyn Object, mode: DifferenceMode, epsilon: f32) -> Result<VistleObject, crate::Error> {
+        // Grid lattice of the output, None for plain scalar arrays
+        let (values, lattice) = if let Some(a_grid) = a.as_uniform_grid() {
+            let b_grid = b.as_uniform_grid()
+                .ok_or_else(|| crate::Error::wrong_type("uniform grid", b))?;
+            let b_values = if a_grid.same_lattice(&b_grid) {
+                Cow::Borrowed(b_grid.values())
+            } else {
+                let resampled = b.payload()
+                    .and_then(|source| resampled_values(source, a_grid.dims, a_grid.origin, a_grid.spacing))
+                    .ok_or_else(|| crate::Error::Compute(format!("Cannot resample {:?} onto the grid of {:?}", b.id(), a.id())))?;
+                Cow::Owned(resampled)
+            };
+            let lattice = (a_grid.dims, a_grid.origin, a_grid.spacing);
+            (difference(a_grid.values(), &b_values, mode, epsilon)?, Some(lattice))
+        } else {
+            let a_field = a.as_scalar_field()
+                .ok_or_else(|| crate::Error::wrong_type("scalar field or uniform grid", a))?;
+            let b_field = b.as_scalar_field()
+                .ok_or_else(|| crate::Error::wrong_type("scalar field", b))?;
+            if a_field.len() != b_field.len() {
+                return Err(crate::Error::Compute(format!(
+                    "Fields {:?} and {:?} have {} and {} values",
+                    a.id(), b.id(), a_field.len(), b_field.len()
+                )));
+            }
+            (difference(a_field.values(), b_field.values(), mode, epsilon)?, None)
+        };
+
+        let max_abs = values.iter().filter(|v| v.is_finite()).fold(0.0f32, |m, v| m.max(v.abs()));
+        let (object_type, payload) = match lattice {
+            Some((dims, origin, spacing)) => (ObjectType::UniformGrid, ObjectPayload::UniformGrid { dims, origin, spacing, values }),
+            None => (ObjectType::Vec, ObjectPayload::VecScalar { data: values }),
+        };
+        let mut object = VistleObject::with_data(object_type, payload).with_meta(a.meta().clone());
+        object.set_attribute(attribute::COLORMAP.to_string(), DIFFERENCE_COLORMAP.to_string());
+        object.set_attribute(attribute::RANGE.to_string(), format!("{} {}", -max_abs, max_abs));
+        if let (DifferenceMode::Absolute, Some(units)) = (mode, a.get_attribute(attribute::UNITS)) {
+            object.set_attribute(attribute::UNITS.to_string(), units.to_string());
+        }
+        Ok(object)
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for DifferenceField {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let mode = DifferenceMode::parse(ctx.parameters().get_string("mode").unwrap_or("absolute"))?;
+        let epsilon = ctx.parameters().get_float("epsilon").unwrap_or(1e-6);
+        let a = required_input(&self.inputs, "a")?;
+        let b = required_input(&self.inputs, "b")?;
+        if a.len() != b.len() {
+            return Err(crate::Error::Compute(format!(
+                "DifferenceField got {} objects on a but {} on b",
+                a.len(), b.len()
+            )));
+        }
+
+        let mut differences = Vec::with_capacity(a.len());
+        for (a, b) in a.iter().zip(b) {
+            // No difference is defined where either side has no data
+            if a.is_empty() || b.is_empty() {
+                differences.push(empty_output(if a.is_empty() { a.as_ref() } else { b.as_ref() }));
+                continue;
+            }
+            // Fields in incompatible units cannot be compared
+            Units::common(&[a.clone(), b.clone()])?;
+            let object = self.subtract(a.as_ref(), b.as_ref(), mode, epsilon)?;
+            differences.push(Arc::new(object) as Arc<dyn Object>);
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("difference".to_string(), differences);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    fn scalars(values: Array1<f32>, units: Option<&str>) -> Arc<dyn Object> {
+        let mut object = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: values });
+        if let Some(units) = units {
+            object.set_attribute(attribute::UNITS.to_string(), units.to_string());
+        }
+        Arc::new(object)
+    }
+
+    fn line(dims: usize, spacing: f32, values: Array1<f32>) -> Arc<dyn Object> {
+        Arc::new(VistleObject::with_data(ObjectType::UniformGrid, ObjectPayload::UniformGrid {
+            dims: [dims, 1, 1],
+            origin: [0.0; 3],
+            spacing: [spacing, 1.0, 1.0],
+            values,
+        }))
+    }
+
+    async fn run(mode: &str, a: Arc<dyn Object>, b: Arc<dyn Object>) -> Result<Arc<dyn Object>, crate::Error> {
+        let mut parameters = DifferenceField::new(1).parameters().clone();
+        parameters.set_value("mode", ParameterValue::String(mode.to_string())).unwrap();
+        let ctx = ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot());
+        let mut module = DifferenceField::new(1);
+        module.set_input("a", vec![a]).await?;
+        module.set_input("b", vec![b]).await?;
+        Ok(module.compute(&ctx).await?.remove("difference").unwrap().remove(0))
+    }
+
+    #[tokio::test]
+    async fn absolute_differences_are_centered_on_zero() {
+        let difference = run("absolute", scalars(array![1.0, 5.0, 2.0], Some("K")), scalars(array![2.0, 1.0, 2.0], Some("K")))
+            .await
+            .unwrap();
+        assert_eq!(difference.as_scalar_field().unwrap().values(), &array![-1.0f32, 4.0, 0.0]);
+        assert_eq!(difference.get_attribute(attribute::RANGE), Some("-4 4"));
+        assert_eq!(difference.get_attribute(attribute::COLORMAP), Some(DIFFERENCE_COLORMAP));
+        assert_eq!(difference.get_attribute(attribute::UNITS), Some("K"));
+    }
+
+    #[tokio::test]
+    async fn relative_differences_skip_values_near_zero() {
+        let difference = run("relative", scalars(array![3.0, 1.0, -1.0], Some("K")), scalars(array![2.0, 0.0, -2.0], Some("K")))
+            .await
+            .unwrap();
+        let values = difference.as_scalar_field().unwrap().values();
+        assert_eq!((values[0], values[2]), (0.5, 0.5));
+        assert!(values[1].is_nan());
+        // A ratio has no units
+        assert_eq!(difference.get_attribute(attribute::UNITS), None);
+    }
+
+    #[tokio::test]
+    async fn fields_of_incompatible_units_are_not_compared() {
+        let result = run("absolute", scalars(array![1.0], Some("Pa")), scalars(array![1.0], Some("m"))).await;
+        assert!(matches!(result, Err(crate::Error::Compute(message)) if message.contains("incompatible")));
+
+        // Compatible scales only warn
+        run("absolute", scalars(array![1.0], Some("Pa")), scalars(array![1.0], Some("kPa"))).await.unwrap();
+        run("absolute", scalars(array![1.0], Some("Pa")), scalars(array![1.0], None)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn grids_are_compared_on_the_lattice_of_a() {
+        // b samples x = 0, 0.5, .., 1.5, so x = 2 of a lies outside it
+        let difference = run("absolute", line(3, 1.0, array![1.0, 1.0, 1.0]), line(4, 0.5, array![0.0, 1.0, 2.0, 3.0]))
+            .await
+            .unwrap();
+        let grid = difference.as_uniform_grid().unwrap();
+        assert_eq!(grid.dims, [3, 1, 1]);
+        assert_eq!(grid.values().slice(ndarray::s![..2]), array![1.0f32, -1.0]);
+        assert!(grid.values()[2].is_nan());
+    }
+
+    #[tokio::test]
+    async fn mismatched_values_are_errors() {
+        let result = run("absolute", scalars(array![1.0, 2.0], None), scalars(array![1.0], None)).await;
+        assert!(matches!(result, Err(crate::Error::Compute(_))));
+        // A grid whose values do not fill its lattice
+        let result = run("absolute", line(3, 1.0, array![1.0, 2.0]), line(3, 1.0, array![1.0, 2.0, 3.0])).await;
+        assert!(matches!(result, Err(crate::Error::Compute(_))));
+    }
+}
@@ -0,0 +1,237 @@
+//! Tubes around lines and spheres around points as real triangle meshes
+//!
+//! Unlike screen-space lines and point sprites these survive mesh export
+//! and high-resolution stills.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::core::{
+    attribute, geometry, ComputeContext, ExecutionStats, GlyphMesh, ModuleInfo, Object, ObjectPayload,
+    ObjectType, Parameter, ParameterSet, ParameterSnapshot, ParameterValue, Port, PortSet, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{empty_output, required_input};
+
+/// Radius at each input vertex: the `radius` parameter, times the scalar field if `scale_by_data` is set
+fn radii(params: &ParameterSnapshot, geometry: &dyn Object, data: Option<&Arc<dyn Object>>) -> Result<Vec<f32>, crate::Error> {
+    let radius = params.get_float("radius").unwrap_or(0.01);
+    let num_vertices = geometry.payload().map_or(0, ObjectPayload::num_vertices);
+    if !params.get_bool("scale_by_data").unwrap_or(false) {
+        return Ok(vec![radius; num_vertices]);
+    }
+    let data = data.ok_or_else(|| crate::Error::Compute("scale_by_data is set but data_in has no field".to_string()))?;
+    let field = data.as_scalar_field()
+        .ok_or_else(|| crate::Error::wrong_type("scalar field", data.as_ref()))?;
+    if field.len() != num_vertices {
+        return Err(crate::Error::Compute(format!(
+            "Scale field has {} values for {} vertices",
+            field.len(), num_vertices
+        )));
+    }
+    Ok(field.values().iter().map(|v| radius * v.abs()).collect())
+}
+
+/// Mesh and normals on the geometry port, data carried to the new vertices on the data port
+fn glyph_outputs(
+    mesh: GlyphMesh,
+    source: &dyn Object,
+    data: Option<&Arc<dyn Object>>,
+    surfaces: &mut Vec<Arc<dyn Object>>,
+    normals: &mut Vec<Arc<dyn Object>>,
+    fields: &mut Vec<Arc<dyn Object>>,
+) -> Result<(), crate::Error> {
+    let carried = data.and_then(|d| d.as_scalar_field()).map(|field| mesh.carry(field.values()));
+    let (surface, normal_field) = mesh.build()?;
+    surfaces.push(Arc::new(surface.with_meta(source.meta().clone())));
+    normals.push(Arc::new(normal_field.with_meta(source.meta().clone())));
+    if let (Some(values), Some(data)) = (carried, data) {
+        let mut field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: values })
+            .with_meta(data.meta().clone());
+        for (key, value) in data.attributes() {
+            field.set_attribute(key.clone(), value.clone());
+        }
+        field.set_attribute(attribute::MAPPING.to_string(), attribute::MAPPING_VERTEX.to_string());
+        fields.push(Arc::new(field));
+    }
+    Ok(())
+}
+
+/// Empty outputs for an empty input block
+fn empty_outputs(
+    source: &dyn Object,
+    data: Option<&Arc<dyn Object>>,
+    surfaces: &mut Vec<Arc<dyn Object>>,
+    normals: &mut Vec<Arc<dyn Object>>,
+    fields: &mut Vec<Arc<dyn Object>>,
+) {
+    surfaces.push(empty_output(source));
+    normals.push(empty_output(source));
+    if let Some(data) = data {
+        fields.push(empty_output(data.as_ref()));
+    }
+}
+
+fn glyph_ports(input: &str, input_description: &str, output_description: &str) -> PortSet {
+    let mut ports = PortSet::new();
+    ports.add(Port::new_input(input, input_description));
+    ports.add(Port::new_input("data_in", "Per-vertex scalar field, carried along and optionally scaling the radius").optional());
+    ports.add(Port::new_output("grid_out", output_description).derived_from(input));
+    ports.add(Port::new_output("normals_out", "Per-vertex normals of the mesh"));
+    ports.add(Port::new_output("data_out", "Input data on the mesh vertices").optional().derived_from("data_in"));
+    ports
+}
+
+fn glyph_parameters(radius_description: &str) -> ParameterSet {
+    let mut parameters = ParameterSet::new();
+    parameters.add(Parameter::new("radius", radius_description, ParameterValue::Float(0.01)));
+    parameters.add(Parameter::new("scale_by_data", "Multiply the radius by |data_in| per vertex", ParameterValue::Bool(false)));
+    parameters
+}
+
+/// Module turning lines into triangulated tubes
+pub struct TubeFilter {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl TubeFilter {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = glyph_parameters("Tube radius");
+        parameters.add(Parameter::new("sides", "Facets around the tube", ParameterValue::Int(8)));
+
+        Self {
+            info: ModuleInfo::new(id, "TubeFilter", 0, 1),
+            parameters,
+            ports: glyph_ports("grid_in", "Lines to wrap in tubes", "Tube surfaces"),
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for TubeFilter {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let sides = ctx.parameters().get_int("sides").unwrap_or(8).max(0) as usize;
+        let lines = required_input(&self.inputs, "grid_in")?;
+        let data = self.inputs.get("data_in");
+
+        let (mut surfaces, mut normals, mut fields) = (Vec::new(), Vec::new(), Vec::new());
+        for (i, line) in lines.iter().enumerate() {
+            ctx.checkpoint().await?;
+            let field = data.and_then(|d| d.get(i));
+            if line.is_empty() {
+                empty_outputs(line.as_ref(), field, &mut surfaces, &mut normals, &mut fields);
+                continue;
+            }
+            let radius = radii(ctx.parameters(), line.as_ref(), field)?;
+            let mesh = geometry::tube_from_lines(line.as_ref(), |v| radius[v], sides)?;
+            glyph_outputs(mesh, line.as_ref(), field, &mut surfaces, &mut normals, &mut fields)?;
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("grid_out".to_string(), surfaces);
+        outputs.insert("normals_out".to_string(), normals);
+        outputs.insert("data_out".to_string(), fields);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+/// Module placing a sphere on every point
+pub struct SphereGlyphs {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl SphereGlyphs {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = glyph_parameters("Sphere radius");
+        parameters.add(Parameter::new("subdivisions", "Icosphere refinement; 20 * 4^n triangles per sphere", ParameterValue::Int(2)));
+
+        Self {
+            info: ModuleInfo::new(id, "SphereGlyphs", 0, 1),
+            parameters,
+            ports: glyph_ports("grid_in", "Points to place spheres on", "Merged sphere surfaces"),
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for SphereGlyphs {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        // Beyond 5 a sphere has over 20k triangles, more than any still needs
+        let subdivisions = ctx.parameters().get_int("subdivisions").unwrap_or(2).clamp(0, 5) as u32;
+        let points = required_input(&self.inputs, "grid_in")?;
+        let data = self.inputs.get("data_in");
+
+        let (mut surfaces, mut normals, mut fields) = (Vec::new(), Vec::new(), Vec::new());
+        for (i, cloud) in points.iter().enumerate() {
+            ctx.checkpoint().await?;
+            let field = data.and_then(|d| d.get(i));
+            if cloud.is_empty() {
+                empty_outputs(cloud.as_ref(), field, &mut surfaces, &mut normals, &mut fields);
+                continue;
+            }
+            let radius = radii(ctx.parameters(), cloud.as_ref(), field)?;
+            let mesh = geometry::spheres_from_points(cloud.as_ref(), |v| radius[v], subdivisions)?;
+            glyph_outputs(mesh, cloud.as_ref(), field, &mut surfaces, &mut normals, &mut fields)?;
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("grid_out".to_string(), surfaces);
+        outputs.insert("normals_out".to_string(), normals);
+        outputs.insert("data_out".to_string(), fields);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
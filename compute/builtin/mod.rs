@@ -0,0 +1,96 @@
+//! Built-in processing modules
+
+pub mod cell_to_point;
+pub mod clip;
+pub mod temporal_aggregate;
+pub mod transform_geometry;
+pub mod probe_statistics;
+pub mod probe_over_time;
+pub mod write_csv_table;
+pub mod convert_units;
+pub mod connected_components;
+pub mod difference_field;
+pub mod glyphs;
+pub mod read_image;
+pub mod threshold;
+pub mod critical_points;
+#[cfg(feature = "mmap")]
+pub mod read_raw_volume;
+
+pub use cell_to_point::*;
+pub use clip::*;
+pub use temporal_aggregate::*;
+pub use transform_geometry::*;
+pub use probe_statistics::*;
+pub use probe_over_time::*;
+pub use write_csv_table::*;
+pub use convert_units::*;
+pub use connected_components::*;
+pub use difference_field::*;
+pub use glyphs::*;
+pub use read_image::*;
+pub use threshold::*;
+pub use critical_points::*;
+#[cfg(feature = "mmap")]
+pub use read_raw_volume::*;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::compute::{FileMatcher, InputPort, InputPorts, ModuleDescriptor, ModuleRegistry};
+use crate::core::{Object, VistleObject};
+
+/// Register all built-in modules with a registry
+pub async fn register_builtin_modules(registry: &ModuleRegistry) {
+    registry.register("CellToPoint", || CellToPoint::new(0)).await;
+    registry.register("PointToCell", || PointToCell::new(0)).await;
+    registry.register("Clip", || Clip::new(0)).await;
+    registry.register("TemporalAggregate", || TemporalAggregate::new(0)).await;
+    registry.register("TransformGeometry", || TransformGeometry::new(0)).await;
+    registry.register("ProbeStatistics", || ProbeStatistics::new(0)).await;
+    registry.register("ProbeOverTime", || ProbeOverTime::new(0)).await;
+    registry.register("WriteCsvTable", || WriteCsvTable::new(0)).await;
+    registry.register("ConvertUnits", || ConvertUnits::new(0)).await;
+    registry.register("ConnectedComponents", || ConnectedComponents::new(0)).await;
+    registry.register("DifferenceField", || DifferenceField::new(0)).await;
+    registry.register("Threshold", || Threshold::new(0)).await;
+    registry.register("CriticalPoints", || CriticalPoints::new(0)).await;
+    registry.register("TubeFilter", || TubeFilter::new(0)).await;
+    registry.register("SphereGlyphs", || SphereGlyphs::new(0)).await;
+    registry.register_described(
+        ModuleDescriptor::reader("ReadImage", FileMatcher::extensions(&["png", "tif", "tiff"])),
+        || ReadImage::new(0),
+    ).await;
+    #[cfg(feature = "mmap")]
+    registry.register_described(
+        ModuleDescriptor::reader("ReadRawVolume", FileMatcher::extensions(&["raw"])),
+        || ReadRawVolume::new(0),
+    ).await;
+}
+
+/// Empty output standing in for `input`, with its block, timestep and attributes
+///
+/// Modules pass empty inputs on this way, and emit it where a computation
+/// leaves nothing, rather than erroring or leaving the output port out.
+pub(crate) fn empty_output(input: &dyn Object) -> Arc<dyn Object> {
+    Arc::new(VistleObject::empty_like(input))
+}
+
+/// Get the objects connected to an input port, failing if the port is empty
+pub(crate) fn required_input<'a>(inputs: &'a InputPorts, port: &str) -> Result<&'a InputPort, crate::Error> {
+    inputs.get(port)
+        .filter(|objects| !objects.is_empty())
+        .ok_or_else(|| crate::Error::Compute(format!("No input on port {}", port)))
+}
+
+/// Indices of the objects of an input by their timestep, in timestep order
+///
+/// A timeseries execution passes all timesteps on one port; blocks of the
+/// same timestep share an entry.
+pub(crate) fn timestep_groups(objects: &InputPort) -> BTreeMap<i32, Vec<usize>> {
+    let mut groups: BTreeMap<i32, Vec<usize>> = BTreeMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        groups.entry(object.meta().timestep).or_default().push(i);
+    }
+    groups
+}
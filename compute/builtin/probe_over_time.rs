@@ -0,0 +1,312 @@
+//! Values of a field at fixed points over time, as curves for plotting
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use ndarray::Array1;
+
+use crate::core::{
+    attribute, ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectMeta, ObjectPayload, ObjectType,
+    Parameter, ParameterSet, ParameterValue, Port, PortSet, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{required_input, sample_uniform_grid, timestep_groups, WeightedSamples};
+
+/// Value of one block of a field at `point`, NaN if the block does not cover it
+///
+/// Uniform grids are interpolated trilinearly; other grids report the
+/// sample nearest to the point, along with its distance.
+fn sample_block(grid: Option<&dyn Object>, field: &dyn Object, point: Vector3<f32>) -> Result<(f64, f32), crate::Error> {
+    let payload = field.payload()
+        .ok_or_else(|| crate::Error::Compute("Field object has no data".to_string()))?;
+    if let ObjectPayload::UniformGrid { .. } = payload {
+        let value = sample_uniform_grid(payload, [point.x, point.y, point.z]) as f64;
+        return Ok((value, if value.is_nan() { f32::INFINITY } else { 0.0 }));
+    }
+
+    let grid = grid.and_then(|g| g.payload())
+        .ok_or_else(|| crate::Error::Compute("ProbeOverTime needs grid_in for fields that are not uniform grids".to_string()))?;
+    let samples = WeightedSamples::from_field(grid, field)?;
+    Ok(samples.positions.iter()
+        .zip(&samples.values)
+        .map(|(p, &v)| (v, (p - point).norm()))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((f64::NAN, f32::INFINITY)))
+}
+
+/// Module sampling a field at fixed points, one sample per timestep
+///
+/// Builds one curve per point, over simulation time or timestep number:
+/// a timeseries execution yields the whole curves at once, and an instance
+/// executed one timestep at a time extends them. Timesteps without data at
+/// a point get NaN, which shows as a gap in the plotted line. Executing a
+/// timestep again replaces its samples.
+pub struct ProbeOverTime {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+    /// x of every sample, in execution order
+    x: Vec<f64>,
+    /// Samples per point
+    y: Vec<Vec<f64>>,
+    /// `reset` of the previous execution, so that switching it on clears the curves once
+    reset: bool,
+}
+
+impl ProbeOverTime {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("points", "Probe points as x, y, z triples", ParameterValue::VecFloat(vec![0.0, 0.0, 0.0])));
+        parameters.add(Parameter::new("x_axis", "time (simulation time) or timestep", ParameterValue::String("time".to_string())));
+        parameters.add(Parameter::new("reset", "Discard samples from previous executions once, when switched on", ParameterValue::Bool(false)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Grid of the field; not needed for uniform grids").optional());
+        ports.add(Port::new_input("data_in", "Scalar field"));
+        ports.add(Port::new_output("curve_out", "One curve per probe point").derived_from("data_in"));
+
+        Self {
+            info: ModuleInfo::new(id, "ProbeOverTime", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+            x: Vec::new(),
+            y: Vec::new(),
+            reset: false,
+        }
+    }
+
+    fn points(values: &[f32]) -> Result<Vec<Vector3<f32>>, crate::Error> {
+        if values.is_empty() || !values.len().is_multiple_of(3) {
+            return Err(crate::Error::Config(format!(
+                "points needs x, y, z triples, got {} values",
+                values.len()
+            )));
+        }
+        Ok(values.chunks_exact(3).map(|p| Vector3::new(p[0], p[1], p[2])).collect())
+    }
+
+    /// Store the samples of one timestep at `x`
+    fn record(&mut self, x: f64, samples: Vec<f64>) {
+        if self.y.len() != samples.len() {
+            if !self.x.is_empty() {
+                tracing::warn!("ProbeOverTime {}: probe points changed, restarting curves", self.info.id);
+            }
+            self.x.clear();
+            self.y = vec![Vec::new(); samples.len()];
+        }
+        match self.x.iter().position(|&seen| seen == x) {
+            Some(index) => {
+                for (curve, value) in self.y.iter_mut().zip(samples) {
+                    curve[index] = value;
+                }
+            }
+            None => {
+                self.x.push(x);
+                for (curve, value) in self.y.iter_mut().zip(samples) {
+                    curve.push(value);
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for ProbeOverTime {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let reset = ctx.parameters().get_bool("reset").unwrap_or(false);
+        if reset && !self.reset {
+            self.x.clear();
+            self.y.clear();
+        }
+        self.reset = reset;
+        let points = Self::points(ctx.parameters().get_vec_float("points").unwrap_or(&[]))?;
+        let by_time = match ctx.parameters().get_string("x_axis").unwrap_or("time") {
+            "time" => true,
+            "timestep" => false,
+            other => return Err(crate::Error::Config(format!(
+                "Unknown x_axis {} (expected time or timestep)",
+                other
+            ))),
+        };
+
+        // Taken out of the inputs, the samples are recorded into the module below
+        let fields = required_input(&self.inputs, "data_in")?.clone();
+        let grids = self.inputs.get("grid_in").filter(|g| !g.is_empty()).cloned();
+        if let Some(grids) = &grids {
+            if grids.len() != fields.len() {
+                return Err(crate::Error::Compute(format!(
+                    "Got {} grids but {} fields",
+                    grids.len(), fields.len()
+                )));
+            }
+        }
+
+        let mut meta = ObjectMeta::default();
+        for (timestep, blocks) in timestep_groups(&fields) {
+            // The block of the timestep closest to a point supplies its value
+            let mut samples = vec![(f64::NAN, f32::INFINITY); points.len()];
+            for &block in &blocks {
+                let (grid, field) = (grids.as_ref().map(|g| g[block].as_ref()), &fields[block]);
+                if field.is_empty() || grid.is_some_and(|g| g.is_empty()) {
+                    continue;
+                }
+                for (sample, &point) in samples.iter_mut().zip(&points) {
+                    let candidate = sample_block(grid, field.as_ref(), point)?;
+                    if candidate.1 < sample.1 {
+                        *sample = candidate;
+                    }
+                }
+            }
+
+            // The curves are as recent as the latest timestep
+            meta = fields[blocks[0]].meta().clone();
+            let x = if by_time { meta.real_time } else { timestep as f64 };
+            self.record(x, samples.into_iter().map(|(value, _)| value).collect());
+        }
+
+        let y_label = fields.first()
+            .and_then(|f| f.get_attribute(attribute::SPECIES))
+            .unwrap_or("value")
+            .to_string();
+        let x_label = if by_time { "time" } else { "timestep" };
+        let curves = points.iter().zip(&self.y)
+            .map(|(point, values)| {
+                let mut curve = VistleObject::with_data(ObjectType::Curve, ObjectPayload::Curve {
+                    x: Array1::from(self.x.clone()),
+                    y: Array1::from(values.clone()),
+                    x_label: x_label.to_string(),
+                    y_label: format!("{} at ({}, {}, {})", y_label, point.x, point.y, point.z),
+                }).with_meta(meta.clone());
+                if let Some(units) = fields.first().and_then(|f| f.get_attribute(attribute::UNITS)) {
+                    curve.set_attribute(attribute::UNITS.to_string(), units.to_string());
+                }
+                Arc::new(curve) as Arc<dyn Object>
+            })
+            .collect();
+
+        let mut outputs = HashMap::new();
+        outputs.insert("curve_out".to_string(), curves);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Constant field on the unit cube at `x_origin`, at `timestep`
+    fn field(timestep: i32, x_origin: f32, value: f32) -> Arc<dyn Object> {
+        let meta = ObjectMeta { timestep, real_time: 0.5 * timestep as f64, ..ObjectMeta::default() };
+        Arc::new(VistleObject::with_data(ObjectType::UniformGrid, ObjectPayload::UniformGrid {
+            dims: [2, 2, 2],
+            origin: [x_origin, 0.0, 0.0],
+            spacing: [1.0; 3],
+            values: Array1::from_elem(8, value),
+        }).with_meta(meta))
+    }
+
+    fn context(set: &[(&str, ParameterValue)]) -> ComputeContext {
+        let mut parameters = ProbeOverTime::new(1).parameters().clone();
+        parameters.set_value("points", ParameterValue::VecFloat(vec![0.5, 0.5, 0.5, 2.5, 0.5, 0.5])).unwrap();
+        for (name, value) in set {
+            parameters.set_value(name, value.clone()).unwrap();
+        }
+        ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot())
+    }
+
+    async fn run(module: &mut ProbeOverTime, ctx: &ComputeContext, fields: Vec<Arc<dyn Object>>) -> Vec<Arc<dyn Object>> {
+        module.set_input("data_in", fields).await.unwrap();
+        module.compute(ctx).await.unwrap().remove("curve_out").unwrap()
+    }
+
+    fn xy(curve: &Arc<dyn Object>) -> (Vec<f64>, Vec<f64>) {
+        let view = curve.as_curve().unwrap();
+        (view.x().to_vec(), view.y().to_vec())
+    }
+
+    #[tokio::test]
+    async fn a_timeseries_gives_one_sample_per_timestep() {
+        let ctx = context(&[("x_axis", ParameterValue::String("timestep".to_string()))]);
+        let curves = run(&mut ProbeOverTime::new(1), &ctx, vec![
+            field(2, 0.0, 3.0),
+            field(0, 0.0, 1.0),
+            field(1, 0.0, 2.0),
+            field(1, 2.0, 20.0),
+        ]).await;
+
+        assert_eq!(curves.len(), 2);
+        assert_eq!(xy(&curves[0]), (vec![0.0, 1.0, 2.0], vec![1.0, 2.0, 3.0]));
+        assert_eq!(curves[0].meta().timestep, 2);
+        let (_, y) = xy(&curves[1]);
+        assert!(y[0].is_nan() && y[2].is_nan());
+        assert_eq!(y[1], 20.0);
+
+        let by_time = run(&mut ProbeOverTime::new(1), &context(&[]), vec![field(0, 0.0, 1.0), field(3, 0.0, 2.0)]).await;
+        assert_eq!(xy(&by_time[0]).0, [0.0, 1.5]);
+        assert_eq!(by_time[0].as_curve().unwrap().x_label, "time");
+    }
+
+    #[tokio::test]
+    async fn timesteps_without_data_break_the_line() {
+        let ctx = context(&[("x_axis", ParameterValue::String("timestep".to_string()))]);
+        // The field moves away from the first point at timestep 1 and back at 2
+        let curves = run(&mut ProbeOverTime::new(1), &ctx, vec![
+            field(0, 0.0, 1.0),
+            field(1, 5.0, 2.0),
+            field(2, 0.0, 3.0),
+            field(3, 0.0, 4.0),
+        ]).await;
+
+        let curve = curves[0].as_curve().unwrap();
+        assert!(curve.y()[1].is_nan());
+        assert_eq!(curve.segments(), vec![vec![[0.0, 1.0]], vec![[2.0, 3.0], [3.0, 4.0]]]);
+    }
+
+    #[tokio::test]
+    async fn executions_extend_the_curves_until_reset() {
+        let keep = context(&[("x_axis", ParameterValue::String("timestep".to_string()))]);
+        let reset = context(&[
+            ("x_axis", ParameterValue::String("timestep".to_string())),
+            ("reset", ParameterValue::Bool(true)),
+        ]);
+        let mut module = ProbeOverTime::new(1);
+
+        run(&mut module, &keep, vec![field(0, 0.0, 1.0)]).await;
+        let curves = run(&mut module, &keep, vec![field(1, 0.0, 2.0)]).await;
+        assert_eq!(xy(&curves[0]), (vec![0.0, 1.0], vec![1.0, 2.0]));
+        // Executing a timestep again replaces its sample
+        let curves = run(&mut module, &keep, vec![field(0, 0.0, 5.0)]).await;
+        assert_eq!(xy(&curves[0]), (vec![0.0, 1.0], vec![5.0, 2.0]));
+
+        let curves = run(&mut module, &reset, vec![field(2, 0.0, 3.0)]).await;
+        assert_eq!(xy(&curves[0]), (vec![2.0], vec![3.0]));
+        let curves = run(&mut module, &reset, vec![field(3, 0.0, 4.0)]).await;
+        assert_eq!(xy(&curves[0]), (vec![2.0, 3.0], vec![3.0, 4.0]));
+    }
+}
@@ -0,0 +1,462 @@
+//! Region statistics of scalar fields as time series tables
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use ndarray::Array1;
+
+use crate::core::{
+    attribute, ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectPayload, ObjectType,
+    Parameter, ParameterSet, ParameterSnapshot, ParameterValue, Port, PortSet, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{required_input, timestep_groups, CellIncidence, ImplicitFunction};
+
+/// Samples of a scalar field with their positions and integration weights
+#[derive(Debug, Clone, Default)]
+pub struct WeightedSamples {
+    pub positions: Vec<Vector3<f32>>,
+    pub values: Vec<f64>,
+    pub weights: Vec<f64>,
+}
+
+impl WeightedSamples {
+    /// Pair a field with its grid
+    ///
+    /// Element-mapped fields are sampled at cell centroids weighted by cell
+    /// volume; vertex-mapped fields at the vertices, each carrying an equal
+    /// share of its incident cells' volumes. Point clouds weigh every point 1.
+    pub fn from_field(grid: &ObjectPayload, field: &dyn Object) -> Result<Self, crate::Error> {
+        let values = field.as_scalar_field()
+            .ok_or_else(|| crate::Error::wrong_type("scalar field", field))?
+            .values();
+        let coordinates = grid.coordinates()
+            .ok_or_else(|| crate::Error::Compute("ProbeStatistics requires a geometric grid".to_string()))?;
+        let point = |i: usize| Vector3::new(coordinates[[i, 0]], coordinates[[i, 1]], coordinates[[i, 2]]);
+
+        if let ObjectPayload::Points { .. } = grid {
+            if values.len() != coordinates.nrows() {
+                return Err(crate::Error::Compute(format!(
+                    "Field has {} values but the point cloud has {} points",
+                    values.len(), coordinates.nrows()
+                )));
+            }
+            return Ok(Self {
+                positions: (0..values.len()).map(point).collect(),
+                values: values.iter().map(|&v| v as f64).collect(),
+                weights: vec![1.0; values.len()],
+            });
+        }
+
+        let incidence = CellIncidence::from_payload(grid)?;
+        let volumes = incidence.volumes().map(|v| v.to_vec())
+            .unwrap_or_else(|| vec![1.0; incidence.num_cells()]);
+
+        let element_mapped = field.attributes().get(attribute::MAPPING).map(|m| m.as_str())
+            == Some(attribute::MAPPING_ELEMENT);
+
+        if element_mapped {
+            if values.len() != incidence.num_cells() {
+                return Err(crate::Error::Compute(format!(
+                    "Element-mapped field has {} values but the grid has {} cells",
+                    values.len(), incidence.num_cells()
+                )));
+            }
+            let positions = (0..incidence.num_cells())
+                .map(|c| {
+                    let cell = incidence.cell(c);
+                    cell.iter().map(|&i| point(i)).sum::<Vector3<f32>>() / cell.len().max(1) as f32
+                })
+                .collect();
+            Ok(Self {
+                positions,
+                values: values.iter().map(|&v| v as f64).collect(),
+                weights: volumes.iter().map(|&v| v as f64).collect(),
+            })
+        } else {
+            if values.len() != incidence.num_points() {
+                return Err(crate::Error::Compute(format!(
+                    "Vertex-mapped field has {} values but the grid has {} vertices",
+                    values.len(), incidence.num_points()
+                )));
+            }
+            let mut weights = vec![0.0f64; incidence.num_points()];
+            for (c, &volume) in volumes.iter().enumerate() {
+                let cell = incidence.cell(c);
+                let share = volume as f64 / cell.len().max(1) as f64;
+                for &i in cell {
+                    weights[i] += share;
+                }
+            }
+            Ok(Self {
+                positions: (0..values.len()).map(point).collect(),
+                values: values.iter().map(|&v| v as f64).collect(),
+                weights,
+            })
+        }
+    }
+
+    /// Keep only samples inside a region (where the implicit function is not positive)
+    pub fn restrict(self, region: &ImplicitFunction) -> Self {
+        let mut result = Self::default();
+        for ((p, v), w) in self.positions.into_iter().zip(self.values).zip(self.weights) {
+            if region.eval(&p) <= 0.0 {
+                result.positions.push(p);
+                result.values.push(v);
+                result.weights.push(w);
+            }
+        }
+        result
+    }
+
+    pub fn extend(&mut self, other: Self) {
+        self.positions.extend(other.positions);
+        self.values.extend(other.values);
+        self.weights.extend(other.weights);
+    }
+}
+
+/// Summary statistics of a set of samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionStatistics {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std: f64,
+    /// Values at the requested percentiles, in request order
+    pub percentiles: Vec<f64>,
+    /// Sum of value times weight
+    pub integral: f64,
+}
+
+/// Compute statistics over the finite samples; all values are NaN when there are none
+pub fn region_statistics(samples: &WeightedSamples, percentiles: &[f64]) -> RegionStatistics {
+    let mut values: Vec<f64> = Vec::with_capacity(samples.values.len());
+    let mut integral = 0.0;
+    for (&v, &w) in samples.values.iter().zip(&samples.weights) {
+        if v.is_finite() {
+            values.push(v);
+            integral += v * w;
+        }
+    }
+
+    if values.is_empty() {
+        return RegionStatistics {
+            count: 0,
+            min: f64::NAN,
+            max: f64::NAN,
+            mean: f64::NAN,
+            std: f64::NAN,
+            percentiles: vec![f64::NAN; percentiles.len()],
+            integral: 0.0,
+        };
+    }
+
+    values.sort_by(|a, b| a.total_cmp(b));
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+
+    // Linear interpolation between closest ranks
+    let percentile = |q: f64| {
+        let rank = (q.clamp(0.0, 100.0) / 100.0) * (values.len() - 1) as f64;
+        let lo = rank.floor() as usize;
+        let hi = rank.ceil() as usize;
+        values[lo] + (values[hi] - values[lo]) * (rank - lo as f64)
+    };
+
+    RegionStatistics {
+        count: values.len(),
+        min: values[0],
+        max: values[values.len() - 1],
+        mean,
+        std: variance.sqrt(),
+        percentiles: percentiles.iter().map(|&q| percentile(q)).collect(),
+        integral,
+    }
+}
+
+/// Module computing statistics of a scalar field over a region
+///
+/// The table has one row per timestep: a timeseries execution yields the
+/// whole time series at once, and an instance executed one timestep at a
+/// time adds rows, replacing those of timesteps executed again.
+pub struct ProbeStatistics {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+    rows: BTreeMap<i32, Vec<f64>>,
+    /// `reset` of the previous execution, so that switching it on clears the table once
+    reset: bool,
+}
+
+impl ProbeStatistics {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("region", "whole, box or sphere", ParameterValue::String("whole".to_string())));
+        parameters.add(Parameter::new("box_min", "Box minimum corner", ParameterValue::VecFloat(vec![-1.0, -1.0, -1.0])));
+        parameters.add(Parameter::new("box_max", "Box maximum corner", ParameterValue::VecFloat(vec![1.0, 1.0, 1.0])));
+        parameters.add(Parameter::new("center", "Sphere center", ParameterValue::VecFloat(vec![0.0, 0.0, 0.0])));
+        parameters.add(Parameter::new("radius", "Sphere radius", ParameterValue::Float(1.0)));
+        parameters.add(Parameter::new("percentiles", "Percentiles to report", ParameterValue::VecFloat(vec![5.0, 50.0, 95.0])));
+        parameters.add(Parameter::new("reset", "Discard rows from previous executions once, when switched on", ParameterValue::Bool(false)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Grid the field is defined on"));
+        ports.add(Port::new_input("data_in", "Scalar field"));
+        ports.add(Port::new_output("table_out", "Statistics, one row per timestep"));
+
+        Self {
+            info: ModuleInfo::new(id, "ProbeStatistics", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+            rows: BTreeMap::new(),
+            reset: false,
+        }
+    }
+
+    fn region(params: &ParameterSnapshot) -> Result<Option<ImplicitFunction>, crate::Error> {
+        match params.get_string("region").unwrap_or("whole") {
+            "whole" => Ok(None),
+            shape @ ("box" | "sphere") => {
+                // Reuse the clip parameter conventions for the region shape
+                let mut params = ParameterSet::from(params.clone());
+                params.add(Parameter::new("function", "Region shape", ParameterValue::String(shape.to_string())));
+                ImplicitFunction::from_parameters(&params.snapshot()).map(Some)
+            }
+            other => Err(crate::Error::Config(format!(
+                "Unknown region {} (expected whole, box or sphere)",
+                other
+            ))),
+        }
+    }
+
+    fn column_names(percentiles: &[f64]) -> Vec<String> {
+        let mut names: Vec<String> = ["timestep", "count", "min", "max", "mean", "std"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        names.extend(percentiles.iter().map(|q| format!("p{}", q)));
+        names.push("integral".to_string());
+        names
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for ProbeStatistics {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let reset = ctx.parameters().get_bool("reset").unwrap_or(false);
+        if reset && !self.reset {
+            self.rows.clear();
+        }
+        self.reset = reset;
+        let percentiles: Vec<f64> = ctx.parameters().get_vec_float("percentiles")
+            .unwrap_or(&[])
+            .iter()
+            .map(|&q| q as f64)
+            .collect();
+        let region = Self::region(ctx.parameters())?;
+
+        let grids = required_input(&self.inputs, "grid_in")?;
+        let fields = required_input(&self.inputs, "data_in")?;
+        if grids.len() != fields.len() {
+            return Err(crate::Error::Compute(format!(
+                "Got {} grids but {} fields",
+                grids.len(), fields.len()
+            )));
+        }
+
+        let names = Self::column_names(&percentiles);
+        if self.rows.values().next().is_some_and(|r| r.len() != names.len()) {
+            tracing::warn!("ProbeStatistics {}: percentiles changed, restarting table", self.info.id);
+            self.rows.clear();
+        }
+
+        // All blocks of a timestep contribute to its row
+        let groups = timestep_groups(fields);
+        for (&timestep, blocks) in &groups {
+            let mut samples = WeightedSamples::default();
+            for &i in blocks {
+                let (grid, field) = (&grids[i], &fields[i]);
+                // Blocks without data contribute no samples; the row still records the timestep
+                if grid.is_empty() || field.is_empty() {
+                    continue;
+                }
+                let payload = grid.payload()
+                    .ok_or_else(|| crate::Error::Compute("Grid object has no data".to_string()))?;
+                let block = WeightedSamples::from_field(payload, field.as_ref())?;
+                samples.extend(match &region {
+                    Some(region) => block.restrict(region),
+                    None => block,
+                });
+            }
+
+            let statistics = region_statistics(&samples, &percentiles);
+            let mut row = vec![
+                timestep as f64,
+                statistics.count as f64,
+                statistics.min,
+                statistics.max,
+                statistics.mean,
+                statistics.std,
+            ];
+            row.extend(&statistics.percentiles);
+            row.push(statistics.integral);
+            self.rows.insert(timestep, row);
+        }
+
+        let columns = names.into_iter()
+            .enumerate()
+            .map(|(c, name)| (name, self.rows.values().map(|r| r[c]).collect::<Array1<f64>>()))
+            .collect();
+
+        // The table is as recent as the latest timestep it was given
+        let latest = groups.values().next_back().map(|blocks| &fields[blocks[0]]);
+        let mut table = VistleObject::with_data(ObjectType::Table, ObjectPayload::Table { columns });
+        if let Some(field) = latest {
+            table = table.with_meta(field.meta().clone());
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("table_out".to_string(), vec![Arc::new(table) as Arc<dyn Object>]);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ObjectMeta;
+
+    fn samples(values: &[f64]) -> WeightedSamples {
+        WeightedSamples {
+            positions: vec![Vector3::zeros(); values.len()],
+            values: values.to_vec(),
+            weights: vec![2.0; values.len()],
+        }
+    }
+
+    /// A block of points along x at `offset` with one value each
+    fn block(timestep: i32, offset: f32, values: &[f32]) -> (Arc<dyn Object>, Arc<dyn Object>) {
+        let meta = ObjectMeta { timestep, ..ObjectMeta::default() };
+        let coordinates = ndarray::Array2::from_shape_fn((values.len(), 3), |(i, c)| if c == 0 { offset + i as f32 } else { 0.0 });
+        let grid = VistleObject::with_data(ObjectType::Points, ObjectPayload::Points { coordinates }).with_meta(meta.clone());
+        let field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: Array1::from(values.to_vec()) })
+            .with_meta(meta);
+        (Arc::new(grid), Arc::new(field))
+    }
+
+    fn context(set: &[(&str, ParameterValue)]) -> ComputeContext {
+        let mut parameters = ProbeStatistics::new(1).parameters().clone();
+        for (name, value) in set {
+            parameters.set_value(name, value.clone()).unwrap();
+        }
+        ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot())
+    }
+
+    async fn run(module: &mut ProbeStatistics, ctx: &ComputeContext, blocks: Vec<(Arc<dyn Object>, Arc<dyn Object>)>) -> Arc<dyn Object> {
+        let (grids, fields) = blocks.into_iter().unzip();
+        module.set_input("grid_in", grids).await.unwrap();
+        module.set_input("data_in", fields).await.unwrap();
+        module.compute(ctx).await.unwrap().remove("table_out").unwrap().remove(0)
+    }
+
+    fn column(table: &Arc<dyn Object>, name: &str) -> Vec<f64> {
+        table.payload().unwrap().column(name).unwrap().to_vec()
+    }
+
+    #[test]
+    fn statistics_skip_non_finite_values() {
+        let statistics = region_statistics(&samples(&[4.0, f64::NAN, 1.0, 3.0, 2.0, f64::INFINITY]), &[0.0, 50.0, 100.0]);
+        assert_eq!(statistics.count, 4);
+        assert_eq!((statistics.min, statistics.max, statistics.mean), (1.0, 4.0, 2.5));
+        assert_eq!(statistics.std, 1.25f64.sqrt());
+        assert_eq!(statistics.percentiles, [1.0, 2.5, 4.0]);
+        assert_eq!(statistics.integral, 20.0);
+
+        let none = region_statistics(&samples(&[f64::NAN]), &[50.0]);
+        assert_eq!((none.count, none.integral), (0, 0.0));
+        assert!(none.mean.is_nan() && none.percentiles[0].is_nan());
+    }
+
+    #[tokio::test]
+    async fn a_timeseries_gives_one_row_per_timestep() {
+        let ctx = context(&[("percentiles", ParameterValue::VecFloat(vec![50.0]))]);
+        let mut module = ProbeStatistics::new(1);
+        // Two blocks per timestep, in no particular order
+        let table = run(&mut module, &ctx, vec![
+            block(2, 0.0, &[5.0]),
+            block(0, 0.0, &[1.0, 2.0]),
+            block(1, 0.0, &[10.0]),
+            block(0, 5.0, &[3.0]),
+            block(2, 5.0, &[7.0]),
+            block(1, 5.0, &[f32::NAN]),
+        ]).await;
+
+        assert_eq!(table.payload().unwrap().column_names(), ["timestep", "count", "min", "max", "mean", "std", "p50", "integral"]);
+        assert_eq!(column(&table, "timestep"), [0.0, 1.0, 2.0]);
+        assert_eq!(column(&table, "count"), [3.0, 1.0, 2.0]);
+        assert_eq!(column(&table, "mean"), [2.0, 10.0, 6.0]);
+        assert_eq!(column(&table, "max"), [3.0, 10.0, 7.0]);
+        assert_eq!(table.meta().timestep, 2);
+    }
+
+    #[tokio::test]
+    async fn regions_restrict_the_samples() {
+        let ctx = context(&[
+            ("region", ParameterValue::String("sphere".to_string())),
+            ("center", ParameterValue::VecFloat(vec![0.0, 0.0, 0.0])),
+            ("radius", ParameterValue::Float(1.5)),
+        ]);
+        let table = run(&mut ProbeStatistics::new(1), &ctx, vec![block(0, 0.0, &[1.0, 2.0, 30.0, 40.0])]).await;
+        assert_eq!(column(&table, "count"), [2.0]);
+        assert_eq!(column(&table, "max"), [2.0]);
+    }
+
+    #[tokio::test]
+    async fn reset_clears_the_table_once() {
+        let keep = context(&[]);
+        let reset = context(&[("reset", ParameterValue::Bool(true))]);
+        let mut module = ProbeStatistics::new(1);
+
+        run(&mut module, &keep, vec![block(0, 0.0, &[1.0])]).await;
+        let table = run(&mut module, &keep, vec![block(1, 0.0, &[2.0])]).await;
+        assert_eq!(column(&table, "timestep"), [0.0, 1.0]);
+
+        let table = run(&mut module, &reset, vec![block(2, 0.0, &[3.0])]).await;
+        assert_eq!(column(&table, "timestep"), [2.0]);
+        // Still switched on, later executions add rows again
+        let table = run(&mut module, &reset, vec![block(3, 0.0, &[4.0])]).await;
+        assert_eq!(column(&table, "timestep"), [2.0, 3.0]);
+        // Executing a timestep again replaces its row
+        let table = run(&mut module, &reset, vec![block(2, 0.0, &[8.0])]).await;
+        assert_eq!(column(&table, "mean"), [8.0, 4.0]);
+    }
+}
@@ -0,0 +1,137 @@
+//! Reading raw brick-of-values volume files through a memory mapping
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::core::{
+    raw_volume_grid, ComputeContext, Endianness, ExecutionStats, ModuleInfo, Object, Parameter, ParameterSet,
+    ParameterSnapshot, ParameterValue, Port, PortSet, RawDType, RawLayout, RawVolume, VolumeRegion,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+
+/// Module reading a headerless volume of u8, u16 or f32 values as a uniform grid
+///
+/// The file is memory-mapped, so a region given by `offset` and `extent`
+/// only reads the pages it covers, and `preview_stride` reads every n-th
+/// point for a quick look at volumes too large to load whole. The mapping
+/// is kept between executions of the same file and layout, so moving the
+/// region interactively does not reopen the file.
+pub struct ReadRawVolume {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+    volume: Option<Arc<RawVolume>>,
+}
+
+impl ReadRawVolume {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::file_path("filename", "Raw volume file", ""));
+        parameters.add(Parameter::new("dims", "Points along x, y and z", ParameterValue::VecInt(vec![1, 1, 1])));
+        parameters.add(Parameter::new("dtype", "Type of the values: u8, u16 or f32", ParameterValue::String("u8".to_string())));
+        parameters.add(Parameter::new("endianness", "Byte order of the values: little or big", ParameterValue::String("little".to_string())));
+        parameters.add(Parameter::new("header_bytes", "Bytes to skip before the first value", ParameterValue::Int(0)));
+        parameters.add(Parameter::new("origin", "Position of the first point", ParameterValue::VecFloat(vec![0.0, 0.0, 0.0])));
+        parameters.add(Parameter::new("spacing", "Distance between points along each axis", ParameterValue::VecFloat(vec![1.0, 1.0, 1.0])));
+        parameters.add(Parameter::new("offset", "First point of the region to read", ParameterValue::VecInt(vec![0, 0, 0])));
+        parameters.add(Parameter::new("extent", "Points of the region along each axis; 0 reads to the end", ParameterValue::VecInt(vec![0, 0, 0])));
+        parameters.add(Parameter::new("preview_stride", "Read every n-th point along each axis", ParameterValue::Int(1)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_output("data", "Uniform grid of the region"));
+
+        let mut info = ModuleInfo::new(id, "ReadRawVolume", 0, 1);
+        info.category = "Read".to_string();
+
+        Self {
+            info,
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+            volume: None,
+        }
+    }
+
+    /// The mapping of the file, reused if path and layout are unchanged
+    fn volume(&mut self, ctx: &ComputeContext, layout: RawLayout) -> Result<Arc<RawVolume>, crate::Error> {
+        let filename = ctx.parameters().get_string("filename").unwrap_or("");
+        if filename.is_empty() {
+            return Err(crate::Error::Config("ReadRawVolume needs a filename".to_string()));
+        }
+        let path = ctx.resolve_path(filename)?;
+        if let Some(volume) = self.volume.as_ref().filter(|v| v.path() == path && *v.layout() == layout) {
+            return Ok(volume.clone());
+        }
+        let volume = RawVolume::open(&path, layout)?;
+        self.volume = Some(volume.clone());
+        Ok(volume)
+    }
+}
+
+fn ints(parameters: &ParameterSnapshot, name: &str) -> Result<[usize; 3], crate::Error> {
+    match parameters.get(name).map(|p| &p.value) {
+        Some(ParameterValue::VecInt(v)) if v.len() == 3 && v.iter().all(|&x| x >= 0) => {
+            Ok([v[0] as usize, v[1] as usize, v[2] as usize])
+        }
+        _ => Err(crate::Error::Config(format!("ReadRawVolume: {} needs three non-negative integers", name))),
+    }
+}
+
+fn floats(parameters: &ParameterSnapshot, name: &str) -> Result<[f32; 3], crate::Error> {
+    match parameters.get_vec_float(name) {
+        Some(&[x, y, z]) => Ok([x, y, z]),
+        _ => Err(crate::Error::Config(format!("ReadRawVolume: {} needs three numbers", name))),
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for ReadRawVolume {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let parameters = ctx.parameters();
+        let layout = RawLayout {
+            dims: ints(parameters, "dims")?,
+            dtype: RawDType::parse(parameters.get_string("dtype").unwrap_or("u8"))?,
+            endianness: Endianness::parse(parameters.get_string("endianness").unwrap_or("little"))?,
+            header_bytes: parameters.get_int("header_bytes").unwrap_or(0).max(0) as usize,
+        };
+        let region = VolumeRegion::clamped(ints(parameters, "offset")?, ints(parameters, "extent")?, layout.dims)?;
+        let stride = parameters.get_int("preview_stride").unwrap_or(1).max(1) as usize;
+        let origin = floats(parameters, "origin")?;
+        let spacing = floats(parameters, "spacing")?;
+
+        let volume = self.volume(ctx, layout)?;
+        tracing::info!(
+            "ReadRawVolume {}: reading {:?} at {:?} of {} with stride {}",
+            self.info.id, region.extent, region.offset, volume.path().display(), stride
+        );
+        let grid = ctx.run_cpu(move || raw_volume_grid(&volume, &region, stride, origin, spacing)).await?;
+
+        let mut outputs = HashMap::new();
+        outputs.insert("data".to_string(), vec![Arc::new(grid) as Arc<dyn Object>]);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
@@ -0,0 +1,411 @@
+//! Temporal aggregation of per-timestep scalar fields
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use ndarray::Array1;
+
+use crate::core::{
+    ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectMeta, ObjectPayload, ObjectType,
+    Parameter, ParameterSet, ParameterSnapshot, ParameterValue, Port, PortSet, Units, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{empty_output, required_input};
+
+/// Attribute recording the aggregation applied to a field
+pub const AGGREGATION_ATTRIBUTE: &str = "_aggregation";
+/// Attribute recording the first and last timestep covered, as `first:last`
+pub const TIMESTEP_RANGE_ATTRIBUTE: &str = "_timestep_range";
+
+/// Aggregation applied across timesteps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Mean,
+    Min,
+    Max,
+    Variance,
+    Stride(u32),
+}
+
+impl Aggregation {
+    pub fn from_parameters(params: &ParameterSnapshot) -> Result<Self, crate::Error> {
+        match params.get_string("aggregation").unwrap_or("mean") {
+            "mean" => Ok(Aggregation::Mean),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "variance" => Ok(Aggregation::Variance),
+            "stride" => {
+                let stride = params.get_int("stride").unwrap_or(1);
+                if stride < 1 {
+                    return Err(crate::Error::Config(format!("Stride must be positive, got {}", stride)));
+                }
+                Ok(Aggregation::Stride(stride as u32))
+            }
+            other => Err(crate::Error::Config(format!(
+                "Unknown aggregation {} (expected mean, min, max, variance or stride)",
+                other
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Aggregation::Mean => "mean",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+            Aggregation::Variance => "variance",
+            Aggregation::Stride(_) => "stride",
+        }
+    }
+}
+
+/// Streaming per-element statistics over a sequence of fields
+///
+/// Uses Welford's algorithm so variance stays stable for long series; NaN
+/// samples are skipped per element and elements without samples stay NaN.
+/// Every timestep counts once, however often it is added.
+#[derive(Debug, Clone)]
+pub struct TemporalAccumulator {
+    count: Array1<u64>,
+    mean: Array1<f64>,
+    m2: Array1<f64>,
+    min: Array1<f32>,
+    max: Array1<f32>,
+    timesteps: BTreeSet<i32>,
+}
+
+impl TemporalAccumulator {
+    pub fn new(len: usize) -> Self {
+        Self {
+            count: Array1::zeros(len),
+            mean: Array1::zeros(len),
+            m2: Array1::zeros(len),
+            min: Array1::from_elem(len, f32::INFINITY),
+            max: Array1::from_elem(len, f32::NEG_INFINITY),
+            timesteps: BTreeSet::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timesteps.is_empty()
+    }
+
+    /// Whether the field of `timestep` is part of the aggregate
+    pub fn contains(&self, timestep: i32) -> bool {
+        self.timesteps.contains(&timestep)
+    }
+
+    /// Add the field of one timestep; returns false if it was added before and is skipped
+    pub fn add(&mut self, timestep: i32, values: &Array1<f32>) -> Result<bool, crate::Error> {
+        if self.contains(timestep) {
+            return Ok(false);
+        }
+        if values.len() != self.len() {
+            return Err(crate::Error::Compute(format!(
+                "Timestep {} has {} values, expected {}",
+                timestep,
+                values.len(),
+                self.len()
+            )));
+        }
+
+        for (i, &v) in values.iter().enumerate() {
+            if v.is_nan() {
+                continue;
+            }
+            self.count[i] += 1;
+            let x = v as f64;
+            let delta = x - self.mean[i];
+            self.mean[i] += delta / self.count[i] as f64;
+            self.m2[i] += delta * (x - self.mean[i]);
+            self.min[i] = self.min[i].min(v);
+            self.max[i] = self.max[i].max(v);
+        }
+
+        self.timesteps.insert(timestep);
+        Ok(true)
+    }
+
+    /// Current aggregate for the requested statistic (population variance)
+    pub fn result(&self, aggregation: Aggregation) -> Array1<f32> {
+        let defined = |i: usize, v: f32| if self.count[i] > 0 { v } else { f32::NAN };
+        (0..self.len())
+            .map(|i| match aggregation {
+                Aggregation::Mean | Aggregation::Stride(_) => defined(i, self.mean[i] as f32),
+                Aggregation::Min => defined(i, self.min[i]),
+                Aggregation::Max => defined(i, self.max[i]),
+                Aggregation::Variance => defined(i, (self.m2[i] / self.count[i].max(1) as f64) as f32),
+            })
+            .collect()
+    }
+
+    /// Timestep range covered so far
+    pub fn timestep_range(&self) -> Option<(i32, i32)> {
+        self.timesteps.first().copied().zip(self.timesteps.last().copied())
+    }
+}
+
+/// Module aggregating a timeseries of scalar fields
+///
+/// Each invocation consumes the fields it receives (one per timestep, in any
+/// order) and emits the aggregate over all timesteps seen so far, so the
+/// executor can feed it one timestep at a time. Timesteps already aggregated
+/// are skipped when executed again; the stride counts from the first
+/// timestep of the series, not of each execution.
+pub struct TemporalAggregate {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+    accumulator: Option<TemporalAccumulator>,
+    /// Timestep the stride is counted from
+    stride_origin: Option<i32>,
+}
+
+impl TemporalAggregate {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("aggregation", "mean, min, max, variance or stride", ParameterValue::String("mean".to_string())));
+        parameters.add(Parameter::new("stride", "Keep every n-th timestep in stride mode", ParameterValue::Int(1)));
+        parameters.add(Parameter::new("reset", "Discard state accumulated by previous executions", ParameterValue::Bool(false)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("data_in", "Per-timestep scalar fields"));
+        ports.add(Port::new_output("data_out", "Aggregated field"));
+
+        Self {
+            info: ModuleInfo::new(id, "TemporalAggregate", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+            accumulator: None,
+            stride_origin: None,
+        }
+    }
+
+    fn output(&self, aggregation: Aggregation, data: Array1<f32>, meta: ObjectMeta, range: (i32, i32)) -> Arc<dyn Object> {
+        let mut object = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data })
+            .with_meta(meta);
+        object.set_attribute(AGGREGATION_ATTRIBUTE.to_string(), aggregation.as_str().to_string());
+        object.set_attribute(TIMESTEP_RANGE_ATTRIBUTE.to_string(), format!("{}:{}", range.0, range.1));
+        Arc::new(object)
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for TemporalAggregate {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let aggregation = Aggregation::from_parameters(ctx.parameters())?;
+        if ctx.parameters().get_bool("reset").unwrap_or(false) {
+            self.accumulator = None;
+            self.stride_origin = None;
+        }
+
+        let mut fields = required_input(&self.inputs, "data_in")?.clone();
+        fields.sort_by_key(|f| f.meta().timestep);
+        // Timesteps in different units would be averaged meaninglessly
+        Units::common(&fields)?;
+
+        let mut outputs = Vec::new();
+
+        // Placeholders are loaded a few timesteps ahead of the one being
+        // added, so only those and the accumulator stay in memory for long series
+        if let Aggregation::Stride(stride) = aggregation {
+            let first = *self.stride_origin.get_or_insert(fields[0].meta().timestep);
+            let kept = fields.iter()
+                .filter(|f| (f.meta().timestep - first).rem_euclid(stride as i32) == 0)
+                .cloned()
+                .collect();
+            let mut series = ctx.resolve_series(kept);
+            while let Some(field) = series.next().await {
+                let field = field?;
+                let timestep = field.meta().timestep;
+                if field.is_empty() {
+                    outputs.push(empty_output(field.as_ref()));
+                } else if let Some(scalars) = field.as_scalar_field() {
+                    outputs.push(self.output(aggregation, scalars.values().clone(), field.meta().clone(), (timestep, timestep)));
+                }
+            }
+        } else {
+            let pending = fields.iter()
+                .filter(|f| !self.accumulator.as_ref().is_some_and(|a| a.contains(f.meta().timestep)))
+                .cloned()
+                .collect();
+            let mut series = ctx.resolve_series(pending);
+            while let Some(field) = series.next().await {
+                let field = field?;
+                // Timesteps without data do not count towards the aggregate
+                if field.is_empty() {
+                    continue;
+                }
+                let data = field.as_scalar_field()
+                    .ok_or_else(|| crate::Error::wrong_type("scalar field", field.as_ref()))?
+                    .values();
+                let accumulator = self.accumulator
+                    .get_or_insert_with(|| TemporalAccumulator::new(data.len()));
+                accumulator.add(field.meta().timestep, data)?;
+            }
+
+            if let Some(accumulator) = &self.accumulator {
+                if let Some(range) = accumulator.timestep_range() {
+                    let mut meta = fields[fields.len() - 1].meta().clone();
+                    meta.timestep = -1;
+                    outputs.push(self.output(aggregation, accumulator.result(aggregation), meta, range));
+                }
+            }
+            if outputs.is_empty() {
+                let mut empty = VistleObject::empty_like(fields[fields.len() - 1].as_ref());
+                empty.meta_mut().timestep = -1;
+                outputs.push(Arc::new(empty));
+            }
+        }
+
+        let mut result = HashMap::new();
+        result.insert("data_out".to_string(), outputs);
+        Ok(result)
+    }
+
+    fn resolves_lazily(&self) -> bool {
+        true
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    fn field(timestep: i32, values: Array1<f32>) -> Arc<dyn Object> {
+        let meta = ObjectMeta { timestep, num_timesteps: 10, ..ObjectMeta::default() };
+        Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: values }).with_meta(meta))
+    }
+
+    fn context(aggregation: &str, stride: i32) -> ComputeContext {
+        let mut parameters = TemporalAggregate::new(1).parameters().clone();
+        parameters.set_value("aggregation", ParameterValue::String(aggregation.to_string())).unwrap();
+        parameters.set_value("stride", ParameterValue::Int(stride)).unwrap();
+        ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot())
+    }
+
+    async fn run(module: &mut TemporalAggregate, ctx: &ComputeContext, fields: Vec<Arc<dyn Object>>) -> Vec<Arc<dyn Object>> {
+        module.set_input("data_in", fields).await.unwrap();
+        module.compute(ctx).await.unwrap().remove("data_out").unwrap()
+    }
+
+    #[test]
+    fn variance_is_stable_for_large_offsets() {
+        // Summing squares in f32 loses the deviations entirely at this offset
+        let mut accumulator = TemporalAccumulator::new(1);
+        for (t, v) in [4.0, 7.0, 13.0, 16.0].into_iter().enumerate() {
+            accumulator.add(t as i32, &array![1.0e6 + v]).unwrap();
+        }
+        assert_eq!(accumulator.result(Aggregation::Mean)[0], 1.0e6 + 10.0);
+        assert_eq!(accumulator.result(Aggregation::Variance)[0], 22.5);
+        assert_eq!(accumulator.result(Aggregation::Min)[0], 1.0e6 + 4.0);
+        assert_eq!(accumulator.result(Aggregation::Max)[0], 1.0e6 + 16.0);
+    }
+
+    #[test]
+    fn nan_samples_are_skipped_per_element() {
+        let mut accumulator = TemporalAccumulator::new(3);
+        accumulator.add(0, &array![1.0, f32::NAN, f32::NAN]).unwrap();
+        accumulator.add(1, &array![3.0, 5.0, f32::NAN]).unwrap();
+
+        let mean = accumulator.result(Aggregation::Mean);
+        assert_eq!(mean[0], 2.0);
+        assert_eq!(mean[1], 5.0);
+        assert!(mean[2].is_nan());
+        assert_eq!(accumulator.result(Aggregation::Variance)[1], 0.0);
+        assert!(accumulator.result(Aggregation::Min)[2].is_nan());
+        assert!(accumulator.add(2, &array![1.0]).is_err());
+    }
+
+    #[test]
+    fn timesteps_added_again_are_skipped() {
+        let mut accumulator = TemporalAccumulator::new(1);
+        assert!(accumulator.add(3, &array![2.0]).unwrap());
+        assert!(accumulator.add(5, &array![4.0]).unwrap());
+        assert!(!accumulator.add(3, &array![100.0]).unwrap());
+
+        assert_eq!(accumulator.result(Aggregation::Mean)[0], 3.0);
+        assert_eq!(accumulator.result(Aggregation::Max)[0], 4.0);
+        assert_eq!(accumulator.timestep_range(), Some((3, 5)));
+    }
+
+    #[tokio::test]
+    async fn executing_the_same_series_again_keeps_the_aggregate() {
+        let mut module = TemporalAggregate::new(1);
+        let ctx = context("mean", 1);
+        let series = vec![field(0, array![1.0, 2.0]), field(1, array![3.0, 6.0])];
+
+        let first = run(&mut module, &ctx, series.clone()).await;
+        let again = run(&mut module, &ctx, series).await;
+        for outputs in [&first, &again] {
+            assert_eq!(outputs.len(), 1);
+            assert_eq!(outputs[0].as_scalar_field().unwrap().values(), &array![2.0f32, 4.0]);
+            assert_eq!(outputs[0].get_attribute(AGGREGATION_ATTRIBUTE), Some("mean"));
+            assert_eq!(outputs[0].get_attribute(TIMESTEP_RANGE_ATTRIBUTE), Some("0:1"));
+            assert_eq!(outputs[0].meta().timestep, -1);
+        }
+
+        // Timesteps fed one per execution extend the aggregate
+        let extended = run(&mut module, &ctx, vec![field(2, array![5.0, 10.0])]).await;
+        assert_eq!(extended[0].as_scalar_field().unwrap().values(), &array![3.0f32, 6.0]);
+        assert_eq!(extended[0].get_attribute(TIMESTEP_RANGE_ATTRIBUTE), Some("0:2"));
+    }
+
+    #[tokio::test]
+    async fn stride_counts_from_the_first_timestep_of_the_series() {
+        let mut module = TemporalAggregate::new(1);
+        let ctx = context("stride", 2);
+
+        let mut kept = Vec::new();
+        for timestep in 1..6 {
+            let outputs = run(&mut module, &ctx, vec![field(timestep, array![timestep as f32])]).await;
+            kept.extend(outputs.iter().map(|o| o.meta().timestep));
+        }
+        assert_eq!(kept, vec![1, 3, 5]);
+
+        // All timesteps at once give the same subsample
+        let mut module = TemporalAggregate::new(1);
+        let series = (1..6).map(|t| field(t, array![t as f32])).collect();
+        let outputs = run(&mut module, &ctx, series).await;
+        let timesteps: Vec<i32> = outputs.iter().map(|o| o.meta().timestep).collect();
+        assert_eq!(timesteps, vec![1, 3, 5]);
+        assert_eq!(outputs[1].get_attribute(TIMESTEP_RANGE_ATTRIBUTE), Some("3:3"));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        assert!(Aggregation::from_parameters(context("median", 1).parameters()).is_err());
+        assert!(Aggregation::from_parameters(context("stride", 0).parameters()).is_err());
+        assert_eq!(Aggregation::from_parameters(context("stride", 3).parameters()).unwrap(), Aggregation::Stride(3));
+    }
+}
@@ -0,0 +1,449 @@
+//! Selecting cells by the values of a field
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ndarray::{Array1, Axis};
+
+use crate::core::{
+    ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectPayload, Parameter, ParameterSet, ParameterSnapshot,
+    ParameterValue, Port, PortSet, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{empty_output, required_input, CellIncidence};
+
+/// Which field values pass a threshold
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThresholdTest {
+    /// Values within `[min, max]`; NaN never passes
+    Range { min: f64, max: f64 },
+    /// Values equal to one of these, e.g. material or block IDs
+    Equal(Vec<i64>),
+}
+
+impl ThresholdTest {
+    pub fn from_parameters(params: &ParameterSnapshot) -> Result<Self, crate::Error> {
+        match params.get_string("mode").unwrap_or("range") {
+            "range" => Ok(ThresholdTest::Range {
+                min: params.get_float("min").unwrap_or(f32::NEG_INFINITY) as f64,
+                max: params.get_float("max").unwrap_or(f32::INFINITY) as f64,
+            }),
+            "equal" => match params.get("values").map(|p| &p.value) {
+                Some(ParameterValue::VecInt(values)) => Ok(ThresholdTest::Equal(values.iter().map(|&v| v as i64).collect())),
+                Some(ParameterValue::Int(value)) => Ok(ThresholdTest::Equal(vec![*value as i64])),
+                _ => Err(crate::Error::Config("Threshold: equal needs integer values".to_string())),
+            },
+            other => Err(crate::Error::Config(format!(
+                "Unknown threshold mode {} (expected range or equal)",
+                other
+            ))),
+        }
+    }
+
+    /// Whether a floating-point value passes; only whole numbers can be equal to an ID
+    pub fn passes(&self, value: f64) -> bool {
+        match self {
+            ThresholdTest::Range { min, max } => *min <= value && value <= *max,
+            ThresholdTest::Equal(values) => values.iter().any(|&v| v as f64 == value),
+        }
+    }
+
+    /// Whether an integer value passes, compared exactly
+    pub fn passes_int(&self, value: i64) -> bool {
+        match self {
+            ThresholdTest::Range { min, max } => *min <= value as f64 && value as f64 <= *max,
+            ThresholdTest::Equal(values) => values.contains(&value),
+        }
+    }
+
+    /// Whether each value of a scalar or integer field passes
+    pub fn evaluate(&self, field: &ObjectPayload) -> Result<Vec<bool>, crate::Error> {
+        Ok(match field {
+            ObjectPayload::VecScalar { data } => data.iter().map(|&v| self.passes(v as f64)).collect(),
+            ObjectPayload::VecScalarF64 { data } => data.iter().map(|&v| self.passes(v)).collect(),
+            ObjectPayload::VecInt32 { data } => data.iter().map(|&v| self.passes_int(v as i64)).collect(),
+            ObjectPayload::VecInt64 { data } => data.iter().map(|&v| self.passes_int(v)).collect(),
+            _ => return Err(crate::Error::WrongType {
+                expected: "scalar or integer field".to_string(),
+                found: field.kind().to_string(),
+            }),
+        })
+    }
+}
+
+/// Check that offsets increase within the connectivity and its entries are not negative
+///
+/// Ragged cells are sliced by their offsets directly; malformed ones fail
+/// here instead of panicking the worker.
+fn check_ragged(connectivity: &Array1<i32>, offsets: &Array1<i32>) -> Result<(), crate::Error> {
+    if let Some((i, &bad)) = offsets.iter().enumerate().find(|(_, &o)| o < 0 || o as usize > connectivity.len()) {
+        return Err(crate::Error::Compute(format!(
+            "Offset {} at entry {} is outside the {} connectivity entries",
+            bad, i, connectivity.len()
+        )));
+    }
+    if let Some(i) = (1..offsets.len()).find(|&i| offsets[i] < offsets[i - 1]) {
+        return Err(crate::Error::Compute(format!(
+            "Offsets decrease from {} to {} at entry {}",
+            offsets[i - 1], offsets[i], i
+        )));
+    }
+    if let Some((i, &bad)) = connectivity.iter().enumerate().find(|(_, &v)| v < 0) {
+        return Err(crate::Error::Compute(format!("Connectivity entry {} references vertex {}", i, bad)));
+    }
+    Ok(())
+}
+
+/// Cells of a mesh with explicit connectivity
+fn incidence(payload: &ObjectPayload) -> Result<CellIncidence, crate::Error> {
+    let cells = |coordinates: &ndarray::Array2<f32>, connectivity: &Array1<i32>, offsets: &Array1<i32>| {
+        check_ragged(connectivity, offsets)?;
+        let cells = offsets.windows(2)
+            .into_iter()
+            .map(|bounds| (bounds[0]..bounds[1]).map(|i| connectivity[i as usize] as usize).collect());
+        CellIncidence::from_cells(coordinates.nrows(), cells)
+    };
+    match payload {
+        ObjectPayload::Triangles { .. } | ObjectPayload::Lines { .. } => CellIncidence::from_payload(payload),
+        ObjectPayload::Quads { coordinates, quads } => CellIncidence::from_cells(
+            coordinates.nrows(),
+            quads.outer_iter().map(|q| q.iter().map(|&i| i.max(0) as usize).collect()),
+        ),
+        ObjectPayload::Polygons { coordinates, connectivity, offsets }
+        | ObjectPayload::UnstructuredGrid { coordinates, connectivity, offsets, .. } => {
+            cells(coordinates, connectivity, offsets)
+        }
+        _ => Err(crate::Error::WrongType {
+            expected: "mesh with cells".to_string(),
+            found: payload.kind().to_string(),
+        }),
+    }
+}
+
+/// Mesh of the cells whose `keep` entry is set, over all vertices of the input
+///
+/// Vertices are kept as they are, so point data still lines up with them.
+pub fn select_cells(payload: &ObjectPayload, keep: &[bool]) -> Result<ObjectPayload, crate::Error> {
+    let num_cells = match payload {
+        ObjectPayload::Triangles { triangles: cells, .. }
+        | ObjectPayload::Lines { connections: cells, .. }
+        | ObjectPayload::Quads { quads: cells, .. } => cells.nrows(),
+        ObjectPayload::Polygons { offsets, .. } => offsets.len().saturating_sub(1),
+        ObjectPayload::UnstructuredGrid { offsets, cell_types, .. } => {
+            if cell_types.len() + 1 != offsets.len() {
+                return Err(crate::Error::Compute(format!(
+                    "{} cell types for {} offsets",
+                    cell_types.len(), offsets.len()
+                )));
+            }
+            cell_types.len()
+        }
+        _ => return Err(crate::Error::WrongType {
+            expected: "mesh with cells".to_string(),
+            found: payload.kind().to_string(),
+        }),
+    };
+    if keep.len() != num_cells {
+        return Err(crate::Error::Compute(format!(
+            "Selection of {} cells for a mesh of {} cells",
+            keep.len(), num_cells
+        )));
+    }
+
+    let kept: Vec<usize> = keep.iter().enumerate().filter(|(_, &k)| k).map(|(c, _)| c).collect();
+    let ragged = |connectivity: &Array1<i32>, offsets: &Array1<i32>| -> Result<(Array1<i32>, Array1<i32>), crate::Error> {
+        check_ragged(connectivity, offsets)?;
+        let mut selected = Vec::new();
+        let mut starts = vec![0];
+        for &c in &kept {
+            selected.extend((offsets[c]..offsets[c + 1]).map(|i| connectivity[i as usize]));
+            starts.push(selected.len() as i32);
+        }
+        Ok((Array1::from(selected), Array1::from(starts)))
+    };
+    Ok(match payload {
+        ObjectPayload::Triangles { coordinates, triangles } => ObjectPayload::Triangles {
+            coordinates: coordinates.clone(),
+            triangles: triangles.select(Axis(0), &kept),
+        },
+        ObjectPayload::Lines { coordinates, connections } => ObjectPayload::Lines {
+            coordinates: coordinates.clone(),
+            connections: connections.select(Axis(0), &kept),
+        },
+        ObjectPayload::Quads { coordinates, quads } => ObjectPayload::Quads {
+            coordinates: coordinates.clone(),
+            quads: quads.select(Axis(0), &kept),
+        },
+        ObjectPayload::Polygons { coordinates, connectivity, offsets } => {
+            let (connectivity, offsets) = ragged(connectivity, offsets)?;
+            ObjectPayload::Polygons { coordinates: coordinates.clone(), connectivity, offsets }
+        }
+        ObjectPayload::UnstructuredGrid { coordinates, connectivity, offsets, cell_types } => {
+            let (connectivity, offsets) = ragged(connectivity, offsets)?;
+            ObjectPayload::UnstructuredGrid {
+                coordinates: coordinates.clone(),
+                connectivity,
+                offsets,
+                cell_types: kept.iter().map(|&c| cell_types[c]).collect(),
+            }
+        }
+        _ => return Err(crate::Error::WrongType {
+            expected: "mesh with cells".to_string(),
+            found: payload.kind().to_string(),
+        }),
+    })
+}
+
+/// Entries of a field whose `keep` entry is set
+fn select_entries(payload: &ObjectPayload, keep: &[bool]) -> Result<ObjectPayload, crate::Error> {
+    let kept: Vec<usize> = keep.iter().enumerate().filter(|(_, &k)| k).map(|(c, _)| c).collect();
+    Ok(match payload {
+        ObjectPayload::VecScalar { data } => ObjectPayload::VecScalar { data: data.select(Axis(0), &kept) },
+        ObjectPayload::VecScalarF64 { data } => ObjectPayload::VecScalarF64 { data: data.select(Axis(0), &kept) },
+        ObjectPayload::VecInt32 { data } => ObjectPayload::VecInt32 { data: data.select(Axis(0), &kept) },
+        ObjectPayload::VecInt64 { data } => ObjectPayload::VecInt64 { data: data.select(Axis(0), &kept) },
+        _ => return Err(crate::Error::WrongType {
+            expected: "scalar or integer field".to_string(),
+            found: payload.kind().to_string(),
+        }),
+    })
+}
+
+/// Module keeping the cells of a mesh whose field values pass a test
+///
+/// In `range` mode, values within `[min, max]` pass; in `equal` mode,
+/// values equal to one of `values`, which selects cells by material or
+/// block ID exactly when the field holds integers. With a per-point field
+/// a cell passes if all its points do. All vertices are kept, so point
+/// fields stay valid; per-cell fields are reduced to the kept cells.
+pub struct Threshold {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl Threshold {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("mode", "range or equal", ParameterValue::String("range".to_string())));
+        parameters.add(Parameter::new("min", "Smallest value kept in range mode", ParameterValue::Float(0.0)));
+        parameters.add(Parameter::new("max", "Largest value kept in range mode", ParameterValue::Float(1.0)));
+        parameters.add(Parameter::new("values", "Values kept in equal mode", ParameterValue::VecInt(vec![0])));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Mesh whose cells are selected"));
+        ports.add(Port::new_input("data_in", "Scalar or integer field per cell or point"));
+        ports.add(Port::new_output("grid_out", "Cells that pass").derived_from("grid_in"));
+        ports.add(Port::new_output("data_out", "The field on the cells that pass").derived_from("data_in"));
+
+        Self {
+            info: ModuleInfo::new(id, "Threshold", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for Threshold {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let test = ThresholdTest::from_parameters(ctx.parameters())?;
+        let grids = required_input(&self.inputs, "grid_in")?;
+        let fields = required_input(&self.inputs, "data_in")?;
+        if fields.len() != grids.len() {
+            return Err(crate::Error::Compute(format!(
+                "Got {} grids but {} fields",
+                grids.len(), fields.len()
+            )));
+        }
+
+        let mut grid_out: Vec<Arc<dyn Object>> = Vec::with_capacity(grids.len());
+        let mut data_out: Vec<Arc<dyn Object>> = Vec::with_capacity(grids.len());
+        for (grid, field) in grids.iter().zip(fields) {
+            ctx.checkpoint().await?;
+            let payloads = grid.payload().zip(field.payload()).filter(|_| !grid.is_empty() && !field.is_empty());
+            let Some((payload, values)) = payloads else {
+                grid_out.push(empty_output(grid.as_ref()));
+                data_out.push(empty_output(field.as_ref()));
+                continue;
+            };
+
+            let incidence = incidence(payload)?;
+            let passes = test.evaluate(values)?;
+            let cell_mapped = passes.len() == incidence.num_cells();
+            let keep: Vec<bool> = if cell_mapped {
+                passes
+            } else if passes.len() == incidence.num_points() {
+                (0..incidence.num_cells()).map(|c| incidence.cell(c).iter().all(|&p| passes[p])).collect()
+            } else {
+                return Err(crate::Error::Compute(format!(
+                    "Field of {} values fits neither the {} cells nor the {} points of the mesh",
+                    passes.len(), incidence.num_cells(), incidence.num_points()
+                )));
+            };
+            if !keep.contains(&true) {
+                grid_out.push(empty_output(grid.as_ref()));
+                data_out.push(empty_output(field.as_ref()));
+                continue;
+            }
+
+            // Attributes are inherited by the executor, which recomputes the range of the kept values
+            let selected = VistleObject::with_data(grid.object_type(), select_cells(payload, &keep)?)
+                .with_meta(grid.meta().clone());
+            grid_out.push(Arc::new(selected));
+
+            if cell_mapped {
+                let data = VistleObject::with_data(field.object_type(), select_entries(values, &keep)?)
+                    .with_meta(field.meta().clone());
+                data_out.push(Arc::new(data));
+            } else {
+                data_out.push(field.clone());
+            }
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("grid_out".to_string(), grid_out);
+        outputs.insert("data_out".to_string(), data_out);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{attribute, AttributePolicy, ObjectType};
+    use ndarray::array;
+
+    /// Two triangles and a quad as polygons over five vertices
+    fn polygons(offsets: Array1<i32>) -> Arc<dyn Object> {
+        Arc::new(VistleObject::with_data(ObjectType::Polygons, ObjectPayload::Polygons {
+            coordinates: ndarray::Array2::zeros((5, 3)),
+            connectivity: array![0, 1, 2, 1, 3, 2, 0, 2, 3, 4],
+            offsets,
+        }))
+    }
+
+    fn field(payload: ObjectPayload) -> Arc<dyn Object> {
+        Arc::new(VistleObject::with_data(ObjectType::Vec, payload))
+    }
+
+    fn context(set: &[(&str, ParameterValue)]) -> ComputeContext {
+        let mut parameters = Threshold::new(1).parameters().clone();
+        for (name, value) in set {
+            parameters.set_value(name, value.clone()).unwrap();
+        }
+        ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot())
+    }
+
+    async fn run(ctx: &ComputeContext, grid: Arc<dyn Object>, data: Arc<dyn Object>) -> Result<OutputPorts, crate::Error> {
+        let mut module = Threshold::new(1);
+        module.set_input("grid_in", vec![grid]).await?;
+        module.set_input("data_in", vec![data]).await?;
+        module.compute(ctx).await
+    }
+
+    #[tokio::test]
+    async fn cell_values_in_range_keep_their_cells() {
+        let ctx = context(&[("min", ParameterValue::Float(1.0)), ("max", ParameterValue::Float(2.0))]);
+        let data = field(ObjectPayload::VecScalar { data: array![0.5, 1.5, 2.0] });
+        let mut outputs = run(&ctx, polygons(array![0, 3, 6, 10]), data).await.unwrap();
+
+        let grid = outputs.remove("grid_out").unwrap();
+        let ObjectPayload::Polygons { coordinates, connectivity, offsets } = grid[0].payload().unwrap() else {
+            panic!("not polygons");
+        };
+        assert_eq!(coordinates.nrows(), 5);
+        assert_eq!(connectivity, &array![1, 3, 2, 0, 2, 3, 4]);
+        assert_eq!(offsets, &array![0, 3, 7]);
+        let data = outputs.remove("data_out").unwrap();
+        assert_eq!(data[0].as_scalar_field().unwrap().values(), &array![1.5f32, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn integer_ids_are_matched_exactly() {
+        // Neighbouring IDs beyond 2^24 are the same value as f32
+        let big = (1i64 << 24) + 1;
+        let ctx = context(&[
+            ("mode", ParameterValue::String("equal".to_string())),
+            ("values", ParameterValue::VecInt(vec![big as i32])),
+        ]);
+        let data = field(ObjectPayload::VecInt64 { data: array![big - 1, big, big + 1] });
+        let mut outputs = run(&ctx, polygons(array![0, 3, 6, 10]), data).await.unwrap();
+
+        let data = outputs.remove("data_out").unwrap();
+        assert_eq!(data[0].as_int64_field().unwrap().values(), &array![big]);
+        let ObjectPayload::Polygons { offsets, .. } = outputs["grid_out"][0].payload().unwrap() else {
+            panic!("not polygons");
+        };
+        assert_eq!(offsets, &array![0, 3]);
+    }
+
+    #[tokio::test]
+    async fn point_values_keep_cells_whose_points_all_pass() {
+        let ctx = context(&[("min", ParameterValue::Float(0.0)), ("max", ParameterValue::Float(1.0))]);
+        let data = field(ObjectPayload::VecInt32 { data: array![0, 1, 1, 5, 1] });
+        let mut outputs = run(&ctx, polygons(array![0, 3, 6, 10]), data.clone()).await.unwrap();
+
+        let ObjectPayload::Polygons { connectivity, .. } = outputs["grid_out"][0].payload().unwrap() else {
+            panic!("not polygons");
+        };
+        assert_eq!(connectivity, &array![0, 1, 2]);
+        // Point data still lines up with the kept vertices
+        assert_eq!(outputs.remove("data_out").unwrap()[0].id(), data.id());
+    }
+
+    #[tokio::test]
+    async fn malformed_offsets_are_errors() {
+        let ctx = context(&[]);
+        let data = field(ObjectPayload::VecScalar { data: array![0.5, 0.5, 0.5] });
+        for offsets in [array![0, 3, 6, 11], array![0, 6, 3, 10], array![-1, 3, 6, 10]] {
+            let result = run(&ctx, polygons(offsets.clone()), data.clone()).await;
+            assert!(matches!(result, Err(crate::Error::Compute(_))), "{}", offsets);
+        }
+
+        let payload = polygons(array![0, 3, 6, 10]);
+        let result = select_cells(payload.payload().unwrap(), &[true; 4]);
+        assert!(matches!(result, Err(crate::Error::Compute(_))));
+    }
+
+    #[tokio::test]
+    async fn the_range_of_reduced_fields_is_recomputed() {
+        let ctx = context(&[("min", ParameterValue::Float(1.0)), ("max", ParameterValue::Float(2.0))]);
+        let mut data = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: array![0.5, 1.5, 2.0] });
+        data.set_attribute(attribute::RANGE.to_string(), "0.5 2".to_string());
+        data.set_attribute(attribute::UNITS.to_string(), "K".to_string());
+        let data: Arc<dyn Object> = Arc::new(data);
+        let mut outputs = run(&ctx, polygons(array![0, 3, 6, 10]), data.clone()).await.unwrap();
+
+        let mut reduced = outputs.remove("data_out").unwrap()[0].as_data().cloned().unwrap();
+        assert_eq!(reduced.attributes.get(attribute::RANGE), None);
+        reduced.inherit_from(data.as_ref(), &AttributePolicy::Inherit);
+        assert_eq!(reduced.attributes[attribute::RANGE], "1.5 2");
+        assert_eq!(reduced.attributes[attribute::UNITS], "K");
+    }
+}
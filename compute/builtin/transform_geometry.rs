@@ -0,0 +1,368 @@
+//! Translating, rotating and scaling geometry
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ndarray::Array2;
+use nalgebra::{Matrix4, Vector3};
+
+use crate::core::{
+    attribute, transform, ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectPayload,
+    Parameter, ParameterSet, ParameterSnapshot, ParameterValue, Port, PortSet, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use super::{empty_output, required_input};
+
+/// Apply a matrix to every row of an Nx3 coordinate array
+pub fn transform_rows<F>(rows: &Array2<f32>, f: F) -> Array2<f32>
+where
+    F: Fn(&Vector3<f32>) -> Vector3<f32>,
+{
+    let mut result = rows.clone();
+    for mut row in result.outer_iter_mut() {
+        let v = f(&Vector3::new(row[0], row[1], row[2]));
+        row[0] = v.x;
+        row[1] = v.y;
+        row[2] = v.z;
+    }
+    result
+}
+
+/// Bake a transform into a geometry payload's coordinates
+pub fn bake_geometry(payload: &ObjectPayload, m: &Matrix4<f32>) -> Result<ObjectPayload, crate::Error> {
+    let apply = |c: &Array2<f32>| transform_rows(c, |p| transform::transform_point(m, p));
+    match payload {
+        ObjectPayload::Points { coordinates } => Ok(ObjectPayload::Points {
+            coordinates: apply(coordinates),
+        }),
+        ObjectPayload::Lines { coordinates, connections } => Ok(ObjectPayload::Lines {
+            coordinates: apply(coordinates),
+            connections: connections.clone(),
+        }),
+        ObjectPayload::Triangles { coordinates, triangles } => Ok(ObjectPayload::Triangles {
+            coordinates: apply(coordinates),
+            triangles: triangles.clone(),
+        }),
+        ObjectPayload::Polygons { coordinates, connectivity, offsets } => Ok(ObjectPayload::Polygons {
+            coordinates: apply(coordinates),
+            connectivity: connectivity.clone(),
+            offsets: offsets.clone(),
+        }),
+        ObjectPayload::Quads { coordinates, quads } => Ok(ObjectPayload::Quads {
+            coordinates: apply(coordinates),
+            quads: quads.clone(),
+        }),
+        _ => Err(crate::Error::Compute("TransformGeometry requires point, line, triangle, quad or polygon geometry".to_string())),
+    }
+}
+
+/// Bake a transform into a vector field: directions are rotated and scaled, never translated
+pub fn bake_vectors(data: &Array2<f32>, m: &Matrix4<f32>, normals: bool) -> Result<Array2<f32>, crate::Error> {
+    if normals {
+        let n = transform::normal_matrix(m)
+            .ok_or_else(|| crate::Error::Compute("Cannot transform normals by a singular matrix".to_string()))?;
+        Ok(transform_rows(data, |v| (n * v).try_normalize(f32::EPSILON).unwrap_or(*v)))
+    } else {
+        Ok(transform_rows(data, |v| transform::transform_vector(m, v)))
+    }
+}
+
+/// Module applying an affine transform to geometry
+pub struct TransformGeometry {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl TransformGeometry {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("translate", "Translation", ParameterValue::VecFloat(vec![0.0, 0.0, 0.0])));
+        parameters.add(Parameter::new("rotation_mode", "euler or axis_angle", ParameterValue::String("euler".to_string())));
+        parameters.add(Parameter::new("euler", "Euler angles in degrees (x, y, z)", ParameterValue::VecFloat(vec![0.0, 0.0, 0.0])));
+        parameters.add(Parameter::new("axis", "Rotation axis", ParameterValue::VecFloat(vec![0.0, 0.0, 1.0])));
+        parameters.add(Parameter::new("angle", "Rotation angle in degrees", ParameterValue::Float(0.0)));
+        parameters.add(Parameter::new("scale", "Scale factors", ParameterValue::VecFloat(vec![1.0, 1.0, 1.0])));
+        parameters.add(Parameter::new("matrix", "Row-major 4x4 matrix overriding the other parameters", ParameterValue::VecFloat(Vec::new())));
+        parameters.add(Parameter::new("bake", "Bake into coordinates instead of the object transform", ParameterValue::Bool(false)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Geometry to transform"));
+        ports.add(Port::new_input("data_in", "Vector fields attached to the geometry").optional());
+        ports.add(Port::new_output("grid_out", "Transformed geometry").derived_from("grid_in"));
+        ports.add(Port::new_output("data_out", "Transformed vector fields").optional().derived_from("data_in"));
+
+        Self {
+            info: ModuleInfo::new(id, "TransformGeometry", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+
+    /// Matrix described by a module's parameters
+    pub fn matrix(params: &ParameterSnapshot) -> Result<Matrix4<f32>, crate::Error> {
+        if let Some(values) = params.get_vec_float("matrix").filter(|v| !v.is_empty()) {
+            return transform::from_row_slice(values)
+                .ok_or_else(|| crate::Error::Config(format!("Matrix needs 16 values, got {}", values.len())));
+        }
+
+        let vec3 = |name: &str, default: f32| match params.get_vec_float(name) {
+            Some([x, y, z]) => Vector3::new(*x, *y, *z),
+            _ => Vector3::repeat(default),
+        };
+
+        let rotation = match params.get_string("rotation_mode").unwrap_or("euler") {
+            "euler" => transform::rotation_from_euler(&vec3("euler", 0.0)),
+            "axis_angle" => transform::rotation_from_axis_angle(
+                &vec3("axis", 0.0),
+                params.get_float("angle").unwrap_or(0.0),
+            ),
+            other => return Err(crate::Error::Config(format!("Unknown rotation mode {}", other))),
+        };
+
+        Ok(transform::compose(&vec3("translate", 0.0), &rotation, &vec3("scale", 1.0)))
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for TransformGeometry {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let m = Self::matrix(ctx.parameters())?;
+        let bake = ctx.parameters().get_bool("bake").unwrap_or(false);
+        if !transform::is_regular_affine(&m) {
+            tracing::warn!("TransformGeometry {}: matrix is singular or not affine", self.info.id);
+        }
+
+        let mut grid_out: Vec<Arc<dyn Object>> = Vec::new();
+        // New ids of the transformed grids, for fields mapped onto them
+        let mut moved = HashMap::new();
+        for grid in required_input(&self.inputs, "grid_in")? {
+            if grid.is_empty() {
+                grid_out.push(empty_output(grid.as_ref()));
+                continue;
+            }
+            let data = grid.as_data()
+                .ok_or_else(|| crate::Error::Compute("Geometry object has no data".to_string()))?;
+            let mut data = data.clone();
+            data.id = Default::default();
+            moved.insert(grid.id(), data.id);
+
+            if bake {
+                // Bake what the renderer would apply, so both modes place the geometry alike
+                data.data = Arc::new(bake_geometry(&data.data, &(m * data.meta.transform))?);
+                data.meta.transform = Matrix4::identity();
+            } else {
+                // Compose lazily; renderers apply the meta transform
+                data.meta.transform = m * data.meta.transform;
+            }
+            grid_out.push(Arc::new(VistleObject::from_data(data)));
+        }
+
+        let mut data_out: Vec<Arc<dyn Object>> = Vec::new();
+        for field in self.inputs.get("data_in").into_iter().flatten() {
+            if field.is_empty() {
+                data_out.push(empty_output(field.as_ref()));
+                continue;
+            }
+            let data = field.as_data()
+                .ok_or_else(|| crate::Error::Compute("Field object has no data".to_string()))?;
+            let mut data = data.clone();
+            data.id = Default::default();
+            if let Some(mapping) = data.grid.as_mut() {
+                mapping.grid = moved.get(&mapping.grid).copied().unwrap_or(mapping.grid);
+            }
+
+            if bake {
+                if let ObjectPayload::VecVec3 { data: vectors } = data.data.as_ref() {
+                    let normals = data.attributes.contains_key(attribute::NORMALS);
+                    let full = m * data.meta.transform;
+                    data.data = Arc::new(ObjectPayload::VecVec3 { data: bake_vectors(vectors, &full, normals)? });
+                }
+                data.meta.transform = Matrix4::identity();
+            } else {
+                data.meta.transform = m * data.meta.transform;
+            }
+            data_out.push(Arc::new(VistleObject::from_data(data)));
+        }
+
+        let mut outputs = HashMap::new();
+        outputs.insert("grid_out".to_string(), grid_out);
+        outputs.insert("data_out".to_string(), data_out);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Mapping, ObjectMeta, ObjectType};
+    use ndarray::array;
+
+    fn context(configure: impl FnOnce(&mut ParameterSet)) -> ComputeContext {
+        let mut parameters = TransformGeometry::new(1).parameters().clone();
+        configure(&mut parameters);
+        ComputeContext::new(1, 0, 1).with_parameters(parameters.snapshot())
+    }
+
+    fn shift_and_scale(parameters: &mut ParameterSet) {
+        parameters.set_value("translate", ParameterValue::VecFloat(vec![10.0, 0.0, 0.0])).unwrap();
+        parameters.set_value("scale", ParameterValue::VecFloat(vec![2.0, 1.0, 1.0])).unwrap();
+    }
+
+    async fn run(ctx: &ComputeContext) -> (Arc<dyn Object>, Vec<Arc<dyn Object>>) {
+        let grid = VistleObject::with_data(ObjectType::Points, ObjectPayload::Points {
+            coordinates: array![[1.0f32, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        });
+        let vectors = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecVec3 {
+            data: array![[1.0f32, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        })
+        .with_grid(&grid, Mapping::PerVertex);
+        let mut normals = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecVec3 {
+            data: array![[1.0f32, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        })
+        .with_grid(&grid, Mapping::PerVertex);
+        normals.set_attribute(attribute::NORMALS.to_string(), "1".to_string());
+
+        let mut module = TransformGeometry::new(1);
+        module.set_input("grid_in", vec![Arc::new(grid)]).await.unwrap();
+        module.set_input("data_in", vec![Arc::new(vectors), Arc::new(normals)]).await.unwrap();
+        let mut outputs = module.compute(ctx).await.unwrap();
+        (outputs.remove("grid_out").unwrap().remove(0), outputs.remove("data_out").unwrap())
+    }
+
+    fn rows(object: &Arc<dyn Object>) -> Array2<f32> {
+        match object.payload().unwrap() {
+            ObjectPayload::Points { coordinates } => coordinates.clone(),
+            ObjectPayload::VecVec3 { data } => data.clone(),
+            other => panic!("unexpected payload {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn baking_moves_points_and_only_rotates_vectors() {
+        let ctx = context(|p| {
+            shift_and_scale(p);
+            p.set_value("bake", ParameterValue::Bool(true)).unwrap();
+        });
+        let (grid, fields) = run(&ctx).await;
+
+        assert_eq!(rows(&grid), array![[12.0f32, 0.0, 0.0], [10.0, 1.0, 0.0]]);
+        assert_eq!(grid.meta().transform, Matrix4::identity());
+        // Vectors are scaled but not translated
+        assert_eq!(rows(&fields[0]), array![[2.0f32, 0.0, 0.0], [2.0, 1.0, 0.0]]);
+        // Normals use the inverse transpose and are renormalized
+        let normals = rows(&fields[1]);
+        assert_eq!(normals.row(0), array![1.0f32, 0.0, 0.0]);
+        let expected = Vector3::new(0.5f32, 1.0, 0.0).normalize();
+        assert!((Vector3::new(normals[[1, 0]], normals[[1, 1]], normals[[1, 2]]) - expected).norm() < 1e-6);
+        // Fields follow the transformed grid
+        assert!(fields.iter().all(|f| f.as_data().unwrap().grid.unwrap().grid == grid.id()));
+    }
+
+    #[tokio::test]
+    async fn without_baking_the_transform_goes_to_the_metadata() {
+        let (grid, fields) = run(&context(shift_and_scale)).await;
+
+        assert_eq!(rows(&grid), array![[1.0f32, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+        let p = transform::transform_point(&grid.meta().transform, &Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(p, Vector3::new(12.0, 0.0, 0.0));
+        assert_eq!(fields[0].meta().transform, grid.meta().transform);
+    }
+
+    #[tokio::test]
+    async fn both_modes_place_pretransformed_geometry_alike() {
+        let before = Matrix4::new_translation(&Vector3::new(0.0, 5.0, 0.0)) * Matrix4::new_rotation(Vector3::z() * std::f32::consts::FRAC_PI_2);
+        let world = |object: &Arc<dyn Object>| -> Vec<Vector3<f32>> {
+            rows(object).outer_iter()
+                .map(|r| transform::transform_point(&object.meta().transform, &Vector3::new(r[0], r[1], r[2])))
+                .collect()
+        };
+
+        let mut results = Vec::new();
+        for bake in [false, true] {
+            let grid = VistleObject::with_data(ObjectType::Points, ObjectPayload::Points {
+                coordinates: array![[1.0f32, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            })
+            .with_meta(ObjectMeta { transform: before, ..ObjectMeta::default() });
+            let mut module = TransformGeometry::new(1);
+            module.set_input("grid_in", vec![Arc::new(grid)]).await.unwrap();
+            let ctx = context(|p| {
+                shift_and_scale(p);
+                p.set_value("bake", ParameterValue::Bool(bake)).unwrap();
+            });
+            let mut outputs = module.compute(&ctx).await.unwrap();
+            results.push(outputs.remove("grid_out").unwrap().remove(0));
+        }
+
+        let (lazy, baked) = (world(&results[0]), world(&results[1]));
+        assert_eq!(results[1].meta().transform, Matrix4::identity());
+        for (a, b) in lazy.iter().zip(&baked) {
+            assert!((a - b).norm() < 1e-5, "{:?} != {:?}", lazy, baked);
+        }
+        // m applies after the incoming transform: (1, 0, 0) -> (0, 6, 0) -> (10, 6, 0)
+        assert!((baked[0] - Vector3::new(10.0, 6.0, 0.0)).norm() < 1e-5, "{:?}", baked);
+    }
+
+    #[test]
+    fn matrix_parameter_overrides_the_rest() {
+        let ctx = context(|p| {
+            shift_and_scale(p);
+            let mut values = vec![0.0; 16];
+            for i in 0..4 {
+                values[i * 5] = 3.0;
+            }
+            values[15] = 1.0;
+            p.set_value("matrix", ParameterValue::VecFloat(values)).unwrap();
+        });
+        let m = TransformGeometry::matrix(ctx.parameters()).unwrap();
+        assert_eq!(m, Matrix4::new_nonuniform_scaling(&Vector3::repeat(3.0)));
+
+        let ctx = context(|p| p.set_value("matrix", ParameterValue::VecFloat(vec![1.0; 12])).unwrap());
+        assert!(TransformGeometry::matrix(ctx.parameters()).is_err());
+    }
+
+    #[test]
+    fn axis_angle_rotation_and_unknown_modes() {
+        let ctx = context(|p| {
+            p.set_value("rotation_mode", ParameterValue::String("axis_angle".to_string())).unwrap();
+            p.set_value("angle", ParameterValue::Float(90.0)).unwrap();
+        });
+        let m = TransformGeometry::matrix(ctx.parameters()).unwrap();
+        assert!((transform::transform_vector(&m, &Vector3::x()) - Vector3::y()).norm() < 1e-6);
+
+        let ctx = context(|p| p.set_value("rotation_mode", ParameterValue::String("quaternion".to_string())).unwrap());
+        assert!(TransformGeometry::matrix(ctx.parameters()).is_err());
+    }
+
+    #[test]
+    fn singular_matrices_cannot_bake_normals() {
+        let flat = Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, 1.0, 0.0));
+        assert!(bake_vectors(&array![[0.0f32, 0.0, 1.0]], &flat, true).is_err());
+        assert!(bake_geometry(&ObjectPayload::VecScalar { data: array![1.0f32] }, &flat).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Writing table objects as CSV files
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::core::{
+    ComputeContext, ExecutionStats, ModuleInfo, Parameter, ParameterSet,
+    Port, PortSet,
+};
+use crate::compute::{InputPort, InputPorts, Module, OutputPorts};
+use crate::util::fmt::format_f64;
+use super::required_input;
+
+/// Format table columns as CSV with a header row
+///
+/// Numbers are written locale-independently and round-trip exactly, see `util::fmt`.
+pub fn table_to_csv(columns: &[(String, ndarray::Array1<f64>)]) -> Result<String, crate::Error> {
+    let rows = columns.first().map(|(_, c)| c.len()).unwrap_or(0);
+    if let Some((name, column)) = columns.iter().find(|(_, c)| c.len() != rows) {
+        return Err(crate::Error::Compute(format!(
+            "Table column {} has {} rows, expected {}",
+            name, column.len(), rows
+        )));
+    }
+
+    let quote = |name: &str| {
+        if name.contains([',', '"', '\n']) {
+            format!("\"{}\"", name.replace('"', "\"\""))
+        } else {
+            name.to_string()
+        }
+    };
+
+    let mut csv = columns.iter().map(|(name, _)| quote(name)).collect::<Vec<_>>().join(",");
+    csv.push('\n');
+    for row in 0..rows {
+        let line = columns.iter().map(|(_, c)| format_f64(c[row])).collect::<Vec<_>>().join(",");
+        csv.push_str(&line);
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+/// Module writing its input table to a CSV file
+pub struct WriteCsvTable {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl WriteCsvTable {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::file_path("filename", "Output file; {block} is replaced by the block index, {timestep} by the timestep of the table", "table.csv"));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("table_in", "Table to write"));
+
+        Self {
+            info: ModuleInfo::new(id, "WriteCsvTable", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for WriteCsvTable {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let filename = ctx.parameters().get_string("filename").unwrap_or("table.csv").to_string();
+        let tables = required_input(&self.inputs, "table_in")?;
+
+        for (i, table) in tables.iter().enumerate() {
+            if table.is_empty() {
+                tracing::debug!("WriteCsvTable {}: block {} is empty, nothing to write", self.info.id, i);
+                continue;
+            }
+            let view = table.as_table()
+                .ok_or_else(|| crate::Error::wrong_type("table", table.as_ref()))?;
+            let path = ctx.resolve_path(&filename
+                .replace("{block}", &i.to_string())
+                .replace("{timestep}", &table.meta().timestep.to_string()))?;
+            let csv = table_to_csv(view.columns())?;
+            crate::util::io::write_binary_cancellable(&path, csv.as_bytes(), ctx.cancellation()).await?;
+            ctx.record_output(&path).await?;
+        }
+
+        Ok(HashMap::new())
+    }
+
+    fn planned_outputs(&self, parameters: &ParameterSet) -> Vec<PathBuf> {
+        parameters.get_string("filename").map(PathBuf::from).into_iter().collect()
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use ndarray::array;
+
+    use crate::core::{Object, ObjectMeta, ObjectPayload, ObjectType, ParameterValue, VistleObject};
+
+    fn columns() -> Vec<(String, ndarray::Array1<f64>)> {
+        vec![
+            ("timestep".to_string(), array![0.0, 1.0]),
+            ("mean, \"weighted\"".to_string(), array![0.1, f64::NAN]),
+        ]
+    }
+
+    #[test]
+    fn tables_are_written_with_quoted_headers() {
+        let csv = table_to_csv(&columns()).unwrap();
+        assert_eq!(csv, "timestep,\"mean, \"\"weighted\"\"\"\n0.0,0.1\n1.0,NaN\n");
+        assert_eq!(table_to_csv(&[]).unwrap(), "\n");
+    }
+
+    #[test]
+    fn ragged_columns_are_errors() {
+        let mut columns = columns();
+        columns[1].1 = array![1.0];
+        assert!(matches!(table_to_csv(&columns), Err(crate::Error::Compute(_))));
+    }
+
+    #[tokio::test]
+    async fn each_table_gets_its_own_file() {
+        let dir = std::env::temp_dir().join(format!("vistle_csv_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut parameters = WriteCsvTable::new(1).parameters().clone();
+        parameters.set_value("filename", ParameterValue::String("stats_{timestep}.csv".to_string())).unwrap();
+        let ctx = ComputeContext::new(1, 0, 1)
+            .with_parameters(parameters.snapshot())
+            .with_base_dir(Some(dir.clone()));
+
+        let table = |timestep: i32| -> Arc<dyn Object> {
+            Arc::new(VistleObject::with_data(ObjectType::Table, ObjectPayload::Table { columns: columns() })
+                .with_meta(ObjectMeta { timestep, ..ObjectMeta::default() }))
+        };
+        let mut module = WriteCsvTable::new(1);
+        module.set_input("table_in", vec![table(3), Arc::new(VistleObject::empty_like(table(5).as_ref())), table(4)]).await.unwrap();
+        assert!(module.compute(&ctx).await.unwrap().is_empty());
+
+        for timestep in [3, 4] {
+            let csv = std::fs::read_to_string(dir.join(format!("stats_{}.csv", timestep))).unwrap();
+            assert_eq!(csv, table_to_csv(&columns()).unwrap());
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,378 @@
+//! Adapter modules inserted between ports of different data types
+//!
+//! Ports may declare the kind of data they carry with
+//! `Port::with_data_type`. A connection between two ports whose declared
+//! types differ is an error unless the workflow sets `allow_coercion` and
+//! the `CoercionRegistry` knows a chain of adapters from one type to the
+//! other. The adapters then run as modules of their own, added to the
+//! workflow before it executes and listed in its result and report.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{ConnectionSpec, ModuleSpec, WorkflowSpec};
+use crate::core::data_type;
+
+/// Longest chain of adapters inserted into one connection
+pub const MAX_COERCION_STEPS: usize = 4;
+
+/// An adapter module turning data of one port type into another
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoercionRule {
+    pub from: String,
+    pub to: String,
+    /// Module type as registered in the `ModuleRegistry`
+    pub module_type: String,
+    pub input_port: String,
+    pub output_port: String,
+    /// Further inputs of the adapter, fed from the same-named inputs of the
+    /// module the connection leads to, e.g. the grid a field is defined on
+    #[serde(default)]
+    pub shared_inputs: Vec<String>,
+    #[serde(default)]
+    pub parameters: Vec<(String, String)>,
+}
+
+impl CoercionRule {
+    pub fn new(from: &str, to: &str, module_type: &str, input_port: &str, output_port: &str) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            module_type: module_type.to_string(),
+            input_port: input_port.to_string(),
+            output_port: output_port.to_string(),
+            shared_inputs: Vec::new(),
+            parameters: Vec::new(),
+        }
+    }
+
+    pub fn with_shared_input(mut self, port: &str) -> Self {
+        self.shared_inputs.push(port.to_string());
+        self
+    }
+
+    pub fn with_parameter(mut self, name: &str, value: &str) -> Self {
+        self.parameters.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Coercion rules adapters are chosen from; modules outside the crate can add their own
+#[derive(Default)]
+pub struct CoercionRegistry {
+    rules: RwLock<Vec<CoercionRule>>,
+}
+
+impl CoercionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry with the rules of the built-in modules
+    pub fn with_builtin_rules() -> Self {
+        let registry = Self::new();
+        registry.register(
+            CoercionRule::new(data_type::CELL_FIELD, data_type::POINT_FIELD, "CellToPoint", "data_in", "data_out")
+                .with_shared_input("grid_in"),
+        );
+        registry.register(
+            CoercionRule::new(data_type::POINT_FIELD, data_type::CELL_FIELD, "PointToCell", "data_in", "data_out")
+                .with_shared_input("grid_in"),
+        );
+        registry
+    }
+
+    /// Add a rule; a rule for the same pair of types replaces the earlier one
+    pub fn register(&self, rule: CoercionRule) {
+        let mut rules = self.rules.write();
+        rules.retain(|r| r.from != rule.from || r.to != rule.to);
+        rules.push(rule);
+    }
+
+    pub fn rules(&self) -> Vec<CoercionRule> {
+        self.rules.read().clone()
+    }
+
+    /// Shortest chain of rules turning `from` into `to`, at most `MAX_COERCION_STEPS` long
+    pub fn chain(&self, from: &str, to: &str) -> Option<Vec<CoercionRule>> {
+        let rules = self.rules.read();
+        let mut visited = HashSet::from([from.to_string()]);
+        let mut queue = VecDeque::from([(from.to_string(), Vec::<CoercionRule>::new())]);
+        while let Some((data_type, chain)) = queue.pop_front() {
+            if data_type == to {
+                return Some(chain);
+            }
+            if chain.len() == MAX_COERCION_STEPS {
+                continue;
+            }
+            for rule in rules.iter().filter(|r| r.from == data_type) {
+                if visited.insert(rule.to.clone()) {
+                    let mut next = chain.clone();
+                    next.push(rule.clone());
+                    queue.push_back((rule.to.clone(), next));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Adapters inserted into one connection of a workflow
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InsertedAdapter {
+    /// Connection as specified, now running through the adapters
+    pub connection: ConnectionSpec,
+    pub from_type: String,
+    pub to_type: String,
+    /// Ids of the adapter modules in data flow order
+    pub modules: Vec<u32>,
+    /// Their module types
+    pub module_types: Vec<String>,
+}
+
+/// Find connections between ports of different declared types and insert adapters
+///
+/// `port_types` maps a module id and port name to the port's declared data
+/// type. Without `allow_coercion`, or without a chain of rules, a mismatch
+/// is an error naming both ports.
+pub fn insert_adapters(
+    spec: &mut WorkflowSpec,
+    port_types: &HashMap<(u32, String), String>,
+    registry: &CoercionRegistry,
+) -> Result<Vec<InsertedAdapter>, crate::Error> {
+    let mismatched: Vec<(ConnectionSpec, String, String)> = spec.connections.iter()
+        .filter_map(|c| {
+            let from = port_types.get(&(c.from_module, c.from_port.clone()))?;
+            let to = port_types.get(&(c.to_module, c.to_port.clone()))?;
+            (from != to).then(|| (c.clone(), from.clone(), to.clone()))
+        })
+        .collect();
+
+    let mut next_id = spec.modules.iter().map(|m| m.id).max().map_or(1, |id| id + 1);
+    let mut inserted = Vec::new();
+    for (connection, from_type, to_type) in mismatched {
+        let describe = || format!(
+            "Workflow {}: {}:{} produces {} but {}:{} expects {}",
+            spec.id, connection.from_module, connection.from_port, from_type,
+            connection.to_module, connection.to_port, to_type
+        );
+        if !spec.allow_coercion {
+            return Err(crate::Error::Config(format!("{}; set allow_coercion to insert adapters", describe())));
+        }
+        let chain = registry.chain(&from_type, &to_type)
+            .ok_or_else(|| crate::Error::Config(format!("{}, and no adapters convert between them", describe())))?;
+
+        let target = spec.modules.iter()
+            .find(|m| m.id == connection.to_module)
+            .cloned()
+            .ok_or_else(|| crate::Error::Config(format!("{}: module {} not found", describe(), connection.to_module)))?;
+        let mut modules = Vec::new();
+        let mut connections = Vec::new();
+        let (mut upstream, mut upstream_port) = (connection.from_module, connection.from_port.clone());
+        for rule in &chain {
+            let id = next_id;
+            next_id += 1;
+            let mut adapter = ModuleSpec::new(id, &rule.module_type, &format!("{} (adapter for {})", rule.module_type, target.name))
+                .depends_on(upstream)
+                .with_priority(target.priority);
+            adapter.placement = target.placement;
+            adapter.stage = target.stage.clone();
+            for (name, value) in &rule.parameters {
+                adapter = adapter.with_parameter(name, value);
+            }
+            connections.push(ConnectionSpec {
+                from_module: upstream,
+                from_port: upstream_port,
+                to_module: id,
+                to_port: rule.input_port.clone(),
+            });
+            for shared in &rule.shared_inputs {
+                let sources: Vec<&ConnectionSpec> = spec.connections.iter()
+                    .filter(|c| c.to_module == target.id && &c.to_port == shared)
+                    .collect();
+                if sources.is_empty() {
+                    return Err(crate::Error::Config(format!(
+                        "{}; adapter {} needs {} but {} has no connection into it",
+                        describe(), rule.module_type, shared, target.name
+                    )));
+                }
+                for source in sources {
+                    adapter = adapter.depends_on(source.from_module);
+                    connections.push(ConnectionSpec {
+                        from_module: source.from_module,
+                        from_port: source.from_port.clone(),
+                        to_module: id,
+                        to_port: shared.clone(),
+                    });
+                }
+            }
+            modules.push(adapter);
+            (upstream, upstream_port) = (id, rule.output_port.clone());
+        }
+        connections.push(ConnectionSpec {
+            from_module: upstream,
+            from_port: upstream_port,
+            to_module: connection.to_module,
+            to_port: connection.to_port.clone(),
+        });
+
+        tracing::info!(
+            "{}, inserting {}",
+            describe(),
+            chain.iter().map(|r| r.module_type.as_str()).collect::<Vec<_>>().join(" -> ")
+        );
+        spec.connections.retain(|c| c != &connection);
+        spec.connections.extend(connections);
+        if let Some(target) = spec.modules.iter_mut().find(|m| m.id == connection.to_module) {
+            target.dependencies.push(upstream);
+        }
+        inserted.push(InsertedAdapter {
+            connection,
+            from_type,
+            to_type,
+            modules: modules.iter().map(|m| m.id).collect(),
+            module_types: modules.iter().map(|m| m.module_type.clone()).collect(),
+        });
+        spec.modules.extend(modules);
+    }
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::WorkflowBuilder;
+
+    const F64_CELL_FIELD: &str = "field:cell:f64";
+
+    /// Reader of a grid and a cell field, and a filter wanting point data on that grid
+    fn workflow(allow_coercion: bool) -> WorkflowSpec {
+        let mut spec = WorkflowBuilder::new("coerce", "Coercion")
+            .add_module("ReadVtk", "Reader")
+            .add_module("Contour", "Contour")
+                .depends_on(1)
+            .connect(1, "grid_out", 2, "grid_in")
+            .connect(1, "data_out", 2, "data_in")
+            .build();
+        spec.allow_coercion = allow_coercion;
+        spec
+    }
+
+    fn port_types(from: &str) -> HashMap<(u32, String), String> {
+        HashMap::from([
+            ((1, "grid_out".to_string()), "grid".to_string()),
+            ((2, "grid_in".to_string()), "grid".to_string()),
+            ((1, "data_out".to_string()), from.to_string()),
+            ((2, "data_in".to_string()), data_type::POINT_FIELD.to_string()),
+        ])
+    }
+
+    fn connection(from: u32, from_port: &str, to: u32, to_port: &str) -> ConnectionSpec {
+        ConnectionSpec {
+            from_module: from,
+            from_port: from_port.to_string(),
+            to_module: to,
+            to_port: to_port.to_string(),
+        }
+    }
+
+    fn registry_with_f64() -> CoercionRegistry {
+        let registry = CoercionRegistry::with_builtin_rules();
+        registry.register(
+            CoercionRule::new(F64_CELL_FIELD, data_type::CELL_FIELD, "ConvertToF32", "data_in", "data_out")
+                .with_parameter("mode", "round"),
+        );
+        registry
+    }
+
+    #[test]
+    fn mismatches_are_errors_unless_coercion_is_allowed() {
+        let mut spec = workflow(false);
+        let error = insert_adapters(&mut spec, &port_types(data_type::CELL_FIELD), &CoercionRegistry::with_builtin_rules())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("1:data_out produces field:cell but 2:data_in expects field:point"), "{}", error);
+        assert!(error.contains("allow_coercion"), "{}", error);
+        assert_eq!(spec.modules.len(), 2);
+    }
+
+    #[test]
+    fn matching_ports_need_no_adapters() {
+        let mut spec = workflow(true);
+        let inserted = insert_adapters(&mut spec, &port_types(data_type::POINT_FIELD), &CoercionRegistry::new()).unwrap();
+        assert!(inserted.is_empty());
+        assert_eq!(spec.connections.len(), 2);
+    }
+
+    #[test]
+    fn a_single_adapter_gets_the_shared_grid() {
+        let mut spec = workflow(true);
+        let inserted = insert_adapters(&mut spec, &port_types(data_type::CELL_FIELD), &CoercionRegistry::with_builtin_rules()).unwrap();
+
+        assert_eq!(inserted.len(), 1);
+        assert_eq!(inserted[0].connection, connection(1, "data_out", 2, "data_in"));
+        assert_eq!(inserted[0].modules, [3]);
+        assert_eq!(inserted[0].module_types, ["CellToPoint"]);
+
+        let adapter = spec.modules.iter().find(|m| m.id == 3).unwrap();
+        assert_eq!(adapter.name, "CellToPoint (adapter for Contour)");
+        assert_eq!(adapter.dependencies, [1, 1]);
+        assert!(!spec.connections.contains(&connection(1, "data_out", 2, "data_in")));
+        for expected in [
+            connection(1, "data_out", 3, "data_in"),
+            connection(1, "grid_out", 3, "grid_in"),
+            connection(3, "data_out", 2, "data_in"),
+            connection(1, "grid_out", 2, "grid_in"),
+        ] {
+            assert!(spec.connections.contains(&expected), "missing {:?}", expected);
+        }
+        assert!(spec.modules.iter().find(|m| m.id == 2).unwrap().dependencies.contains(&3));
+    }
+
+    #[test]
+    fn two_adapters_are_chained() {
+        let mut spec = workflow(true);
+        let inserted = insert_adapters(&mut spec, &port_types(F64_CELL_FIELD), &registry_with_f64()).unwrap();
+
+        assert_eq!(inserted[0].module_types, ["ConvertToF32", "CellToPoint"]);
+        assert_eq!(inserted[0].modules, [3, 4]);
+        let convert = spec.modules.iter().find(|m| m.id == 3).unwrap();
+        assert_eq!(convert.parameters["mode"], "round");
+        assert!(spec.modules.iter().find(|m| m.id == 4).unwrap().dependencies.contains(&3));
+        assert!(spec.connections.contains(&connection(1, "data_out", 3, "data_in")));
+        assert!(spec.connections.contains(&connection(3, "data_out", 4, "data_in")));
+        assert!(spec.connections.contains(&connection(4, "data_out", 2, "data_in")));
+    }
+
+    #[test]
+    fn types_without_a_chain_are_reported() {
+        let mut spec = workflow(true);
+        let error = insert_adapters(&mut spec, &port_types("table"), &registry_with_f64()).unwrap_err();
+        assert!(error.to_string().contains("no adapters convert between them"), "{}", error);
+    }
+
+    #[test]
+    fn an_adapter_missing_its_shared_input_is_an_error() {
+        let mut spec = workflow(true);
+        spec.connections.retain(|c| c.to_port != "grid_in");
+        let error = insert_adapters(&mut spec, &port_types(data_type::CELL_FIELD), &CoercionRegistry::with_builtin_rules()).unwrap_err();
+        assert!(error.to_string().contains("needs grid_in"), "{}", error);
+    }
+
+    #[test]
+    fn chains_are_shortest_and_rules_replace_earlier_ones() {
+        let registry = registry_with_f64();
+        registry.register(CoercionRule::new(F64_CELL_FIELD, data_type::POINT_FIELD, "CellToPointF64", "data_in", "data_out"));
+        let chain = registry.chain(F64_CELL_FIELD, data_type::POINT_FIELD).unwrap();
+        assert_eq!(chain.len(), 1);
+        assert_eq!(chain[0].module_type, "CellToPointF64");
+        assert!(registry.chain("a", "a").unwrap().is_empty());
+        assert!(registry.chain(data_type::POINT_FIELD, F64_CELL_FIELD).is_none());
+
+        registry.register(CoercionRule::new(data_type::CELL_FIELD, data_type::POINT_FIELD, "Smooth", "in", "out"));
+        assert_eq!(registry.rules().len(), 4);
+        assert_eq!(registry.chain(data_type::CELL_FIELD, data_type::POINT_FIELD).unwrap()[0].module_type, "Smooth");
+    }
+}
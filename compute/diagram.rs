@@ -0,0 +1,439 @@
+//! Graphviz and Mermaid diagrams of workflows
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{ModuleOutcome, ModuleReport, ModuleSpec, WorkflowResult, WorkflowSpec};
+
+/// Text formats a workflow diagram can be written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiagramFormat {
+    /// Graphviz DOT
+    Dot,
+    /// Mermaid flowchart, renderable by most wikis
+    Mermaid,
+}
+
+impl DiagramFormat {
+    /// Format matching a file extension
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let path = path.as_ref();
+        let extension = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("dot") | Some("gv") => Ok(DiagramFormat::Dot),
+            Some("mmd") | Some("mermaid") => Ok(DiagramFormat::Mermaid),
+            _ => Err(crate::Error::Config(format!(
+                "Cannot tell diagram format of {}; use .dot, .gv or .mmd",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// What a workflow diagram shows
+#[derive(Debug, Clone)]
+pub struct DiagramOptions {
+    /// Parameters shown on module nodes; others are left out to keep nodes small
+    pub parameters: BTreeSet<String>,
+    /// Label connections with their source and target ports
+    pub port_labels: bool,
+}
+
+impl Default for DiagramOptions {
+    fn default() -> Self {
+        Self {
+            parameters: BTreeSet::new(),
+            port_labels: true,
+        }
+    }
+}
+
+impl DiagramOptions {
+    pub fn with_parameters<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.parameters = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_port_labels(mut self, port_labels: bool) -> Self {
+        self.port_labels = port_labels;
+        self
+    }
+}
+
+/// An edge of the diagram; dependencies without a connection have no ports
+struct Edge<'a> {
+    from: u32,
+    to: u32,
+    ports: Option<(&'a str, &'a str)>,
+}
+
+/// Modules and edges in a stable order, so regenerated diagrams diff cleanly
+fn sorted_graph(spec: &WorkflowSpec) -> (Vec<&ModuleSpec>, Vec<Edge<'_>>) {
+    let mut modules: Vec<&ModuleSpec> = spec.modules.iter().collect();
+    modules.sort_by_key(|m| m.id);
+
+    let mut edges: Vec<Edge> = spec.connections.iter()
+        .map(|c| Edge {
+            from: c.from_module,
+            to: c.to_module,
+            ports: Some((c.from_port.as_str(), c.to_port.as_str())),
+        })
+        .collect();
+    for module in &modules {
+        for &dep in &module.dependencies {
+            if !edges.iter().any(|e| e.from == dep && e.to == module.id) {
+                edges.push(Edge { from: dep, to: module.id, ports: None });
+            }
+        }
+    }
+    edges.sort_by(|a, b| (a.from, a.to, a.ports).cmp(&(b.from, b.to, b.ports)));
+    (modules, edges)
+}
+
+/// Label lines of a module node: name, type, allowed parameters and runtime
+fn node_lines(module: &ModuleSpec, options: &DiagramOptions, report: Option<&ModuleReport>) -> Vec<String> {
+    let mut lines = vec![module.name.clone(), format!("({})", module.module_type)];
+    let mut parameters: Vec<(&String, &String)> = module.parameters.iter()
+        .filter(|(name, _)| options.parameters.contains(*name))
+        .collect();
+    parameters.sort();
+    lines.extend(parameters.into_iter().map(|(name, value)| format!("{} = {}", name, value)));
+    if let Some(report) = report {
+        match report.status {
+            ModuleOutcome::NotRun => lines.push("not run".to_string()),
+            _ => lines.push(format!("{:.1} ms", report.duration_ms)),
+        }
+    }
+    lines
+}
+
+/// Modules grouped by stage, stages in the order of their first module; unstaged modules come first
+fn stage_groups<'a>(modules: &[&'a ModuleSpec]) -> Vec<(Option<&'a str>, Vec<&'a ModuleSpec>)> {
+    let mut groups: Vec<(Option<&str>, Vec<&ModuleSpec>)> = vec![(None, Vec::new())];
+    for &module in modules {
+        let stage = module.stage.as_deref();
+        match groups.iter_mut().find(|(name, _)| *name == stage) {
+            Some((_, members)) => members.push(module),
+            None => groups.push((stage, vec![module])),
+        }
+    }
+    groups
+}
+
+/// Label lines of a stage cluster with its budget and, after a run, its duration; true if over budget
+fn stage_lines(spec: &WorkflowSpec, name: &str, result: Option<&WorkflowResult>) -> (Vec<String>, bool) {
+    let budget = spec.stage(name).and_then(|s| s.budget);
+    let timing = result.and_then(|r| r.stages.iter().find(|s| s.name == name));
+    let detail = match (timing, budget) {
+        (Some(timing), Some(budget)) => Some(format!(
+            "{:.1} s of {:.1} s budget", timing.duration.as_secs_f64(), budget.as_secs_f64()
+        )),
+        (Some(timing), None) => Some(format!("{:.1} s", timing.duration.as_secs_f64())),
+        (None, Some(budget)) => Some(format!("budget {:.1} s", budget.as_secs_f64())),
+        (None, None) => None,
+    };
+    let lines = std::iter::once(name.to_string()).chain(detail).collect();
+    (lines, timing.is_some_and(|t| t.exceeded))
+}
+
+fn status_class(status: ModuleOutcome) -> (&'static str, &'static str) {
+    match status {
+        ModuleOutcome::Succeeded => ("succeeded", "#c8e6c9"),
+        ModuleOutcome::Failed => ("failed", "#ffcdd2"),
+        ModuleOutcome::NotRun => ("not_run", "#e0e0e0"),
+    }
+}
+
+fn module_reports(result: Option<&WorkflowResult>) -> HashMap<u32, ModuleReport> {
+    result
+        .map(|r| r.to_report().modules.into_iter().map(|m| (m.module_id, m)).collect())
+        .unwrap_or_default()
+}
+
+/// Escape text for a double-quoted DOT string
+fn escape_dot(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape text for a quoted Mermaid label using its entity codes
+fn escape_mermaid(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("#quot;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '|' => escaped.push_str("#124;"),
+            '#' => escaped.push_str("#35;"),
+            '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl WorkflowSpec {
+    /// Graphviz DOT graph of the modules and their connections
+    pub fn to_dot(&self) -> String {
+        self.to_diagram(DiagramFormat::Dot, &DiagramOptions::default(), None)
+    }
+
+    /// Mermaid flowchart of the modules and their connections
+    pub fn to_mermaid(&self) -> String {
+        self.to_diagram(DiagramFormat::Mermaid, &DiagramOptions::default(), None)
+    }
+
+    /// Diagram in the given format; with a result, nodes show durations and status colors
+    pub fn to_diagram(&self, format: DiagramFormat, options: &DiagramOptions, result: Option<&WorkflowResult>) -> String {
+        match format {
+            DiagramFormat::Dot => self.dot(options, result),
+            DiagramFormat::Mermaid => self.mermaid(options, result),
+        }
+    }
+
+    fn dot(&self, options: &DiagramOptions, result: Option<&WorkflowResult>) -> String {
+        let (modules, edges) = sorted_graph(self);
+        let reports = module_reports(result);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "digraph \"{}\" {{", escape_dot(&self.name));
+        let _ = writeln!(out, "  rankdir=LR;");
+        let _ = writeln!(out, "  node [shape=box, style=\"rounded,filled\", fillcolor=\"#ffffff\"];");
+        for (index, (stage, members)) in stage_groups(&modules).into_iter().enumerate() {
+            let indent = if stage.is_some() { "    " } else { "  " };
+            if let Some(name) = stage {
+                let (lines, exceeded) = stage_lines(self, name, result);
+                let _ = writeln!(out, "  subgraph cluster_{} {{", index);
+                let _ = writeln!(out, "    label=\"{}\";", escape_dot(&lines.join("\n")));
+                let _ = writeln!(out, "    style=\"rounded,dashed\";");
+                if exceeded {
+                    let _ = writeln!(out, "    color=\"#d32f2f\";");
+                }
+            }
+            for module in members {
+                let report = reports.get(&module.id);
+                let label = node_lines(module, options, report).join("\n");
+                let _ = write!(out, "{}m{} [label=\"{}\"", indent, module.id, escape_dot(&label));
+                if let Some(report) = report {
+                    let _ = write!(out, ", fillcolor=\"{}\"", status_class(report.status).1);
+                }
+                let _ = writeln!(out, "];");
+            }
+            if stage.is_some() {
+                let _ = writeln!(out, "  }}");
+            }
+        }
+        for edge in edges {
+            let _ = write!(out, "  m{} -> m{}", edge.from, edge.to);
+            match edge.ports {
+                Some((from, to)) if options.port_labels => {
+                    let _ = writeln!(out, " [label=\"{}\"];", escape_dot(&format!("{} → {}", from, to)));
+                }
+                Some(_) => {
+                    let _ = writeln!(out, ";");
+                }
+                None => {
+                    let _ = writeln!(out, " [style=dashed];");
+                }
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn mermaid(&self, options: &DiagramOptions, result: Option<&WorkflowResult>) -> String {
+        let (modules, edges) = sorted_graph(self);
+        let reports = module_reports(result);
+
+        let mut out = String::from("flowchart LR\n");
+        let mut classes: Vec<(u32, &'static str)> = Vec::new();
+        for (index, (stage, members)) in stage_groups(&modules).into_iter().enumerate() {
+            let indent = if stage.is_some() { "    " } else { "  " };
+            if let Some(name) = stage {
+                let (lines, exceeded) = stage_lines(self, name, result);
+                let label: Vec<String> = lines.iter().map(|line| escape_mermaid(line)).collect();
+                let _ = writeln!(out, "  subgraph s{}[\"{}\"]", index, label.join("<br/>"));
+                if exceeded {
+                    let _ = writeln!(out, "    style s{} stroke:#d32f2f", index);
+                }
+            }
+            for module in members {
+                let report = reports.get(&module.id);
+                let label: Vec<String> = node_lines(module, options, report).iter()
+                    .map(|line| escape_mermaid(line))
+                    .collect();
+                let _ = writeln!(out, "{}m{}[\"{}\"]", indent, module.id, label.join("<br/>"));
+                if let Some(report) = report {
+                    classes.push((module.id, status_class(report.status).0));
+                }
+            }
+            if stage.is_some() {
+                let _ = writeln!(out, "  end");
+            }
+        }
+        for edge in edges {
+            match edge.ports {
+                Some((from, to)) if options.port_labels => {
+                    let label = escape_mermaid(&format!("{} → {}", from, to));
+                    let _ = writeln!(out, "  m{} -->|\"{}\"| m{}", edge.from, label, edge.to);
+                }
+                Some(_) => {
+                    let _ = writeln!(out, "  m{} --> m{}", edge.from, edge.to);
+                }
+                None => {
+                    let _ = writeln!(out, "  m{} -.-> m{}", edge.from, edge.to);
+                }
+            }
+        }
+        if !classes.is_empty() {
+            for status in [ModuleOutcome::Succeeded, ModuleOutcome::Failed, ModuleOutcome::NotRun] {
+                let (class, color) = status_class(status);
+                let _ = writeln!(out, "  classDef {} fill:{}", class, color);
+            }
+            for (id, class) in classes {
+                let _ = writeln!(out, "  class m{} {}", id, class);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{ModuleRegistry, TaskExecutor, WorkflowBuilder, WorkflowExecutor};
+    use crate::core::MessageRouter;
+
+    const DEMO_DOT: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/diagrams/demo.dot"));
+    const DEMO_MERMAID: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/diagrams/demo.mmd"));
+
+    /// Reader, isosurface and a staged renderer with an extra plain dependency
+    fn demo() -> WorkflowSpec {
+        WorkflowBuilder::new("demo", "Demo \"quoted\" <flow>")
+            .add_module("DataReader", "Load Data")
+                .parameter("filename", "a.vtk")
+                .parameter("format", "VTK")
+            .add_module("IsoSurface", "Extract | Surface")
+                .parameter("iso_value", "0.5")
+                .depends_on(1)
+            .add_module("Renderer", "Render")
+                .stage("output")
+                .depends_on(2)
+                .depends_on(1)
+            .connect(1, "data_out", 2, "data_in")
+            .connect(2, "surface_out", 3, "geometry_in")
+            .build()
+    }
+
+    fn options() -> DiagramOptions {
+        DiagramOptions::default().with_parameters(["filename", "iso_value"])
+    }
+
+    #[test]
+    fn dot_matches_the_snapshot() {
+        assert_eq!(demo().to_diagram(DiagramFormat::Dot, &options(), None), DEMO_DOT);
+    }
+
+    #[test]
+    fn mermaid_matches_the_snapshot() {
+        assert_eq!(demo().to_diagram(DiagramFormat::Mermaid, &options(), None), DEMO_MERMAID);
+    }
+
+    #[test]
+    fn output_does_not_depend_on_declaration_order() {
+        let mut shuffled = demo();
+        shuffled.modules.reverse();
+        shuffled.connections.reverse();
+        for module in &mut shuffled.modules {
+            module.dependencies.reverse();
+        }
+        for format in [DiagramFormat::Dot, DiagramFormat::Mermaid] {
+            assert_eq!(shuffled.to_diagram(format, &options(), None), demo().to_diagram(format, &options(), None));
+        }
+    }
+
+    #[test]
+    fn defaults_hide_parameters_and_options_hide_ports() {
+        let dot = demo().to_dot();
+        assert!(!dot.contains("filename"));
+        assert!(dot.contains("data_out → data_in"));
+        assert!(!demo().to_mermaid().contains("iso_value"));
+
+        let plain = DiagramOptions::default().with_port_labels(false);
+        let dot = demo().to_diagram(DiagramFormat::Dot, &plain, None);
+        assert!(dot.contains("  m1 -> m2;\n"));
+        assert!(dot.contains("  m1 -> m3 [style=dashed];\n"));
+        let mermaid = demo().to_diagram(DiagramFormat::Mermaid, &plain, None);
+        assert!(mermaid.contains("  m2 --> m3\n"));
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        assert_eq!(escape_dot("a \"b\" \\ c\nd\r"), "a \\\"b\\\" \\\\ c\\nd");
+        assert_eq!(escape_mermaid("<a|\"b\"#>\nc"), "#lt;a#124;#quot;b#quot;#35;#gt; c");
+    }
+
+    #[test]
+    fn formats_follow_the_extension() {
+        assert_eq!(DiagramFormat::from_path("flow.dot").unwrap(), DiagramFormat::Dot);
+        assert_eq!(DiagramFormat::from_path("flow.GV").unwrap(), DiagramFormat::Dot);
+        assert_eq!(DiagramFormat::from_path("flow.mmd").unwrap(), DiagramFormat::Mermaid);
+        assert!(DiagramFormat::from_path("flow.svg").is_err());
+    }
+
+    #[tokio::test]
+    async fn results_annotate_nodes_with_status_and_duration() {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        let executor = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()));
+        let spec = WorkflowBuilder::new("annotated", "Annotated")
+            .add_module("ConstantField", "Source")
+            .add_module("Failing", "Sink")
+                .depends_on(1)
+            .connect(1, "data_out", 2, "data_in")
+            .build();
+        let result = executor.execute_workflow(spec.clone(), Some(Duration::from_secs(10))).await.unwrap();
+
+        let dot = spec.to_diagram(DiagramFormat::Dot, &DiagramOptions::default(), Some(&result));
+        assert!(dot.contains("m1 [label=\"Source\\n(ConstantField)\\n"), "{}", dot);
+        assert!(dot.contains(" ms\", fillcolor=\"#c8e6c9\"];"), "{}", dot);
+        assert!(dot.contains("fillcolor=\"#ffcdd2\""), "{}", dot);
+
+        let mermaid = spec.to_diagram(DiagramFormat::Mermaid, &DiagramOptions::default(), Some(&result));
+        assert!(mermaid.contains("  class m1 succeeded\n"));
+        assert!(mermaid.contains("  class m2 failed\n"));
+        assert!(mermaid.contains("  classDef failed fill:#ffcdd2\n"));
+    }
+
+    #[tokio::test]
+    async fn diagrams_are_written_to_files() {
+        let path = std::env::temp_dir().join(format!("vistle_diagram_{}.mmd", uuid::Uuid::new_v4().simple()));
+        crate::export_workflow_diagram(&demo(), None, DiagramFormat::Mermaid, &path).await.unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, demo().to_mermaid());
+    }
+}
@@ -0,0 +1,779 @@
+//! Distributed workflow execution across MPI ranks
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::compute::{
+    ConnectionSpec, OutputSink, OutputSource, Placement, WorkflowExecutor, WorkflowResult, WorkflowSpec,
+};
+use crate::core::{ObjectId, ObjectRegistry};
+use crate::mpi::{DistributedContext, ObjectTransferService, MODULE_OUTCOME_TAG};
+
+/// Ranks each module of a workflow runs on
+pub type PlacementMap = HashMap<u32, Vec<i32>>;
+
+/// Resolve the placement of every module for a communicator of `size` ranks
+///
+/// `Any` modules are spread round-robin in declaration order; with a single
+/// rank every module resolves to rank 0.
+pub fn resolve_placement(workflow: &WorkflowSpec, size: i32) -> Result<PlacementMap, crate::Error> {
+    if size < 1 {
+        return Err(crate::Error::Config(format!("Invalid communicator size {}", size)));
+    }
+
+    let specs: HashMap<u32, Placement> = workflow.modules.iter()
+        .map(|m| (m.id, m.placement))
+        .collect();
+
+    fn resolve(
+        id: u32,
+        specs: &HashMap<u32, Placement>,
+        order: &HashMap<u32, usize>,
+        size: i32,
+        visiting: &mut HashSet<u32>,
+    ) -> Result<Vec<i32>, crate::Error> {
+        let placement = specs.get(&id)
+            .ok_or_else(|| crate::Error::Config(format!("Placement references unknown module {}", id)))?;
+
+        match *placement {
+            Placement::Any => Ok(vec![(order[&id] as i32) % size]),
+            Placement::Rank(rank) if rank >= 0 && rank < size => Ok(vec![rank]),
+            Placement::Rank(rank) => Err(crate::Error::Config(format!(
+                "Module {} placed on rank {} but only {} ranks are available",
+                id, rank, size
+            ))),
+            Placement::AllRanks => Ok((0..size).collect()),
+            Placement::NodeLocalWith(other) => {
+                if !visiting.insert(id) {
+                    return Err(crate::Error::Config(format!(
+                        "Cyclic NodeLocalWith placement involving module {}",
+                        id
+                    )));
+                }
+                resolve(other, specs, order, size, visiting)
+            }
+        }
+    }
+
+    let order: HashMap<u32, usize> = workflow.modules.iter()
+        .enumerate()
+        .map(|(i, m)| (m.id, i))
+        .collect();
+
+    workflow.modules.iter()
+        .map(|m| {
+            let ranks = resolve(m.id, &specs, &order, size, &mut HashSet::new())?;
+            Ok((m.id, ranks))
+        })
+        .collect()
+}
+
+/// Ranks whose outputs of `from` feed `to` on `rank`
+///
+/// A consumer takes the outputs of the producer on its own rank, or else
+/// those of the producer's first rank. The first rank of the consumer
+/// also gathers the outputs of producer ranks no consumer runs on, so an
+/// `AllRanks` reader feeding a `Rank(0)` writer hands it every rank's
+/// piece. Empty when `to` does not run on `rank`.
+pub fn source_ranks(placement: &PlacementMap, from: u32, to: u32, rank: i32) -> Vec<i32> {
+    let ranks = |id: u32| placement.get(&id).map(Vec::as_slice).unwrap_or_default();
+    let (producers, consumers) = (ranks(from), ranks(to));
+    if !consumers.contains(&rank) || producers.is_empty() {
+        return Vec::new();
+    }
+
+    let mut sources = vec![if producers.contains(&rank) { rank } else { producers[0] }];
+    if consumers[0] == rank {
+        sources.extend(producers.iter().filter(|p| !consumers.contains(p)));
+    }
+    sources.sort_unstable();
+    sources.dedup();
+    sources
+}
+
+/// Connections feeding some consumer with objects of another rank
+///
+/// These objects move through an `ObjectTransferService`, see
+/// `DistributedWorkflowExecutor::with_transfer`.
+pub fn cross_rank_connections<'a>(workflow: &'a WorkflowSpec, placement: &PlacementMap) -> Vec<&'a ConnectionSpec> {
+    workflow.connections.iter()
+        .filter(|c| {
+            placement.get(&c.to_module).into_iter().flatten().any(|&rank| {
+                source_ranks(placement, c.from_module, c.to_module, rank).iter().any(|&source| source != rank)
+            })
+        })
+        .collect()
+}
+
+/// Restrict a workflow to the modules placed on one rank
+///
+/// Dependencies and connections on modules running elsewhere are left
+/// out; `remote_upstream` lists them instead.
+pub fn local_workflow(workflow: &WorkflowSpec, placement: &PlacementMap, rank: i32) -> WorkflowSpec {
+    let local: HashSet<u32> = placement.iter()
+        .filter(|(_, ranks)| ranks.contains(&rank))
+        .map(|(&id, _)| id)
+        .collect();
+
+    let mut spec = workflow.clone();
+    spec.modules.retain(|m| local.contains(&m.id));
+    for module in &mut spec.modules {
+        module.dependencies.retain(|d| local.contains(d));
+    }
+    spec.connections.retain(|c| local.contains(&c.from_module) && local.contains(&c.to_module));
+    spec
+}
+
+/// Module on another rank that modules of this rank wait for
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteUpstream {
+    pub module_id: u32,
+    pub rank: i32,
+    /// Connections of its outputs there to local modules
+    pub connections: Vec<ConnectionSpec>,
+    /// Local modules depending on it without a connection
+    pub dependents: Vec<u32>,
+}
+
+/// Modules on other ranks the modules placed on `rank` wait for, ordered by module and rank
+///
+/// Connections follow `source_ranks`; a dependency waits for the module
+/// on every rank it runs on.
+pub fn remote_upstream(workflow: &WorkflowSpec, placement: &PlacementMap, rank: i32) -> Vec<RemoteUpstream> {
+    let mut upstream: BTreeMap<(u32, i32), RemoteUpstream> = BTreeMap::new();
+    fn entry(upstream: &mut BTreeMap<(u32, i32), RemoteUpstream>, module_id: u32, source: i32) -> &mut RemoteUpstream {
+        upstream.entry((module_id, source)).or_insert_with(|| RemoteUpstream {
+            module_id,
+            rank: source,
+            connections: Vec::new(),
+            dependents: Vec::new(),
+        })
+    }
+
+    for c in &workflow.connections {
+        for source in source_ranks(placement, c.from_module, c.to_module, rank) {
+            if source != rank {
+                entry(&mut upstream, c.from_module, source).connections.push(c.clone());
+            }
+        }
+    }
+    for module in &workflow.modules {
+        if !placement.get(&module.id).is_some_and(|ranks| ranks.contains(&rank)) {
+            continue;
+        }
+        for &dependency in &module.dependencies {
+            for &source in placement.get(&dependency).into_iter().flatten() {
+                if source != rank {
+                    entry(&mut upstream, dependency, source).dependents.push(module.id);
+                }
+            }
+        }
+    }
+    upstream.into_values().collect()
+}
+
+/// Module of another rank a local run waits for, see `WorkflowExecutor::execute_workflow_linked`
+pub(crate) struct RemoteModule {
+    pub module_id: u32,
+    pub module_type: String,
+    pub rank: i32,
+    /// Outputs of the connected ports once the module finished there
+    pub outputs: OutputSource,
+    pub connections: Vec<ConnectionSpec>,
+    pub dependents: Vec<u32>,
+}
+
+/// Links of a rank's part of a workflow to the parts of other ranks
+#[derive(Default)]
+pub(crate) struct RankLinks {
+    pub remote: Vec<RemoteModule>,
+    /// Sinks of local modules other ranks wait for, by module
+    pub sinks: HashMap<u32, OutputSink>,
+}
+
+impl RankLinks {
+    pub fn is_empty(&self) -> bool {
+        self.remote.is_empty() && self.sinks.is_empty()
+    }
+}
+
+/// How a module ended on the sending rank
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ModuleOutcome {
+    /// Run of `DistributedWorkflowExecutor::execute_workflow`, counted alike on every rank
+    run: u64,
+    module_id: u32,
+    /// Ids of the output objects by port, or why there are none
+    outputs: Result<BTreeMap<String, Vec<ObjectId>>, String>,
+}
+
+/// Outputs of a remote module: the fetch started for its connected ports and the ids by port
+type Delivery = Result<(Option<JoinHandle<Vec<Result<ObjectId, crate::Error>>>>, BTreeMap<String, Vec<ObjectId>>), String>;
+
+/// Waiters for remote modules by module and rank, with the ports to fetch
+type Waiting = HashMap<(u32, i32), (HashSet<String>, oneshot::Sender<Delivery>)>;
+
+async fn send_outcome(context: &DistributedContext, outcome: ModuleOutcome, ranks: &[i32]) {
+    for &rank in ranks {
+        if let Err(e) = context.send_to(outcome.clone(), rank, MODULE_OUTCOME_TAG).await {
+            tracing::warn!("Failed to tell rank {} how module {} ended: {}", rank, outcome.module_id, e);
+        }
+    }
+}
+
+/// Receive the outcomes of run `run` this rank waits for until all arrived or `stop` is cancelled
+///
+/// The objects of connected ports start moving as soon as an outcome
+/// arrives, so they are local by the time the consumer runs.
+fn receive_outcomes(
+    context: Arc<DistributedContext>,
+    transfer: Option<Arc<ObjectTransferService>>,
+    run: u64,
+    mut waiting: Waiting,
+    token: CancellationToken,
+    stop: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while !waiting.is_empty() {
+            let (outcome, source) = tokio::select! {
+                _ = stop.cancelled() => break,
+                received = context.receive_any::<ModuleOutcome>(MODULE_OUTCOME_TAG) => match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        tracing::warn!("Failed to receive module outcomes: {}", e);
+                        break;
+                    }
+                },
+            };
+            if outcome.run != run {
+                tracing::debug!("Dropping outcome of module {} from run {}", outcome.module_id, outcome.run);
+                continue;
+            }
+            let Some((ports, reply)) = waiting.remove(&(outcome.module_id, source)) else {
+                tracing::debug!("Dropping unexpected outcome of module {} from rank {}", outcome.module_id, source);
+                continue;
+            };
+
+            let delivery = outcome.outputs.map(|outputs| {
+                let ids: Vec<ObjectId> = outputs.iter()
+                    .filter(|(port, _)| ports.contains(*port))
+                    .flat_map(|(_, ids)| ids.iter().copied())
+                    .collect();
+                let fetch = match &transfer {
+                    Some(transfer) if !ids.is_empty() => Some(transfer.prefetch(ids, source, token.clone())),
+                    _ => None,
+                };
+                (fetch, outputs)
+            });
+            let _ = reply.send(delivery);
+        }
+    })
+}
+
+/// Outputs of the connected ports of `upstream` once its delivery arrived and was fetched
+fn remote_outputs(upstream: &RemoteUpstream, delivery: oneshot::Receiver<Delivery>, registry: Arc<ObjectRegistry>) -> OutputSource {
+    let (module_id, rank) = (upstream.module_id, upstream.rank);
+    let ports: HashSet<String> = upstream.connections.iter().map(|c| c.from_port.clone()).collect();
+    let delivery = Arc::new(Mutex::new(Some(delivery)));
+    Arc::new(move || {
+        let (delivery, registry, ports) = (delivery.lock().take(), registry.clone(), ports.clone());
+        async move {
+            let delivery = delivery.ok_or_else(|| {
+                crate::Error::Module(format!("Outputs of module {} on rank {} were taken already", module_id, rank))
+            })?;
+            let (fetch, outputs) = delivery.await
+                .map_err(|_| crate::Error::Module(format!("Rank {} did not report module {}", rank, module_id)))?
+                .map_err(|e| crate::Error::Module(format!("Module {} failed on rank {}: {}", module_id, rank, e)))?;
+            if let Some(fetch) = fetch {
+                let fetched = fetch.await.map_err(|e| {
+                    crate::Error::Module(format!("Fetching outputs of module {} from rank {} failed: {}", module_id, rank, e))
+                })?;
+                fetched.into_iter().collect::<Result<Vec<_>, _>>()?;
+            }
+
+            outputs.into_iter()
+                .filter(|(port, _)| ports.contains(port))
+                .map(|(port, ids)| {
+                    let objects = ids.into_iter()
+                        .map(|id| registry.get(id).ok_or_else(|| {
+                            crate::Error::Module(format!("Object {} of module {} is missing", id, module_id))
+                        }))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Ok((port, objects))
+                })
+                .collect()
+        }
+        .boxed()
+    })
+}
+
+/// Sink telling `ranks` how `module_id` ended, its outputs stored for the transfer service to serve
+fn outcome_sink(
+    context: Arc<DistributedContext>,
+    registry: Arc<ObjectRegistry>,
+    run: u64,
+    module_id: u32,
+    ranks: Vec<i32>,
+    announced: Arc<Mutex<HashSet<u32>>>,
+) -> OutputSink {
+    Arc::new(move |outcome| {
+        announced.lock().insert(module_id);
+        let outputs = outcome.map(|ports| {
+            ports.iter()
+                .map(|(port, objects)| (port.clone(), objects.iter().map(|o| registry.store(o.clone())).collect()))
+                .collect()
+        });
+        let (context, ranks) = (context.clone(), ranks.clone());
+        async move {
+            send_outcome(&context, ModuleOutcome { run, module_id, outputs }, &ranks).await;
+        }
+        .boxed()
+    })
+}
+
+/// Completion report of one rank
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankSummary {
+    pub rank: i32,
+    pub success: bool,
+    pub tasks_completed: usize,
+    pub error: Option<String>,
+}
+
+/// Result of a distributed workflow execution
+#[derive(Debug)]
+pub struct DistributedWorkflowResult {
+    /// Result of the modules executed on this rank
+    pub local: WorkflowResult,
+    /// Per-rank summaries, only populated on the root rank
+    pub ranks: Vec<RankSummary>,
+    pub placement: PlacementMap,
+}
+
+impl DistributedWorkflowResult {
+    /// Overall success as known on this rank
+    pub fn success(&self) -> bool {
+        self.local.success && self.ranks.iter().all(|r| r.success)
+    }
+}
+
+/// Workflow executor distributing modules across ranks by their placement
+///
+/// Modules wait for the modules of other ranks they depend on, and the
+/// objects connecting them move through the transfer service.
+pub struct DistributedWorkflowExecutor {
+    executor: Arc<WorkflowExecutor>,
+    context: Arc<DistributedContext>,
+    transfer: Option<Arc<ObjectTransferService>>,
+    root: i32,
+    /// Runs so far, telling outcomes of a run from late ones of an earlier run
+    runs: AtomicU64,
+}
+
+impl DistributedWorkflowExecutor {
+    pub fn new(executor: Arc<WorkflowExecutor>, context: Arc<DistributedContext>) -> Self {
+        Self {
+            executor,
+            context,
+            transfer: None,
+            root: 0,
+            runs: AtomicU64::new(0),
+        }
+    }
+
+    /// Move objects between ranks through `transfer`, which must serve the executor's object registry
+    ///
+    /// Without one, workflows connecting modules across ranks are rejected.
+    pub fn with_transfer(mut self, transfer: Arc<ObjectTransferService>) -> Self {
+        self.transfer = Some(transfer);
+        self
+    }
+
+    pub fn executor(&self) -> &Arc<WorkflowExecutor> {
+        &self.executor
+    }
+
+    /// Execute a workflow; the spec given on the root rank is broadcast to all others
+    pub async fn execute_workflow(
+        &self,
+        workflow: WorkflowSpec,
+        timeout_duration: Option<Duration>,
+    ) -> Result<DistributedWorkflowResult, crate::Error> {
+        let rank = self.context.rank();
+        let size = self.context.size();
+        let run = self.runs.fetch_add(1, Ordering::SeqCst);
+
+        let workflow = if size > 1 {
+            self.context.broadcast(&workflow, self.root).await?
+        } else {
+            workflow
+        };
+
+        // Every rank resolves the same spec, so all of them fail here together, before any collective
+        let placement = resolve_placement(&workflow, size)?;
+        if let (None, Some(c)) = (&self.transfer, cross_rank_connections(&workflow, &placement).first()) {
+            return Err(crate::Error::Config(format!(
+                "Connection {}:{} -> {}:{} crosses ranks ({:?} -> {:?}) but no object transfer service is set",
+                c.from_module, c.from_port, c.to_module, c.to_port,
+                placement.get(&c.from_module).cloned().unwrap_or_default(),
+                placement.get(&c.to_module).cloned().unwrap_or_default(),
+            )));
+        }
+        let local = local_workflow(&workflow, &placement, rank);
+        tracing::debug!("Rank {} executes {} of {} modules", rank, local.modules.len(), workflow.modules.len());
+
+        let announced = Arc::new(Mutex::new(HashSet::new()));
+        let (links, waiting, destinations) = self.links(&workflow, &placement, run, &announced);
+        let stop = CancellationToken::new();
+        let inbox = (!waiting.is_empty()).then(|| {
+            receive_outcomes(
+                self.context.clone(),
+                self.transfer.clone(),
+                run,
+                waiting,
+                self.executor.cancel_token(&workflow.id),
+                stop.clone(),
+            )
+        });
+
+        let result = self.executor.execute_workflow_linked(local, timeout_duration, &links).await;
+
+        // Modules that never ran here, e.g. after a timeout, must not keep other ranks waiting
+        let announced = announced.lock().clone();
+        for (&module_id, ranks) in destinations.iter().filter(|(id, _)| !announced.contains(id)) {
+            let outputs = Err(format!("Module did not run on rank {}", rank));
+            send_outcome(&self.context, ModuleOutcome { run, module_id, outputs }, ranks).await;
+        }
+        stop.cancel();
+        if let Some(inbox) = inbox {
+            let _ = inbox.await;
+        }
+
+        let summary = RankSummary {
+            rank,
+            success: result.as_ref().map(|r| r.success).unwrap_or(false),
+            tasks_completed: result.as_ref().map(|r| r.task_results.len()).unwrap_or(0),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+
+        let ranks = if size > 1 {
+            let gathered = self.context
+                .reduce(vec![summary], |mut a, b| { a.extend(b); a }, self.root)
+                .await?
+                .unwrap_or_default();
+            self.context.barrier().await?;
+            gathered
+        } else {
+            vec![summary]
+        };
+
+        Ok(DistributedWorkflowResult {
+            local: result?,
+            ranks,
+            placement,
+        })
+    }
+
+    /// Links of this rank's modules to other ranks, the waiters for remote outcomes and the ranks to tell of local ones
+    fn links(
+        &self,
+        workflow: &WorkflowSpec,
+        placement: &PlacementMap,
+        run: u64,
+        announced: &Arc<Mutex<HashSet<u32>>>,
+    ) -> (RankLinks, Waiting, BTreeMap<u32, Vec<i32>>) {
+        let rank = self.context.rank();
+        let registry = self.executor.object_registry();
+
+        let mut links = RankLinks::default();
+        let mut waiting = Waiting::new();
+        for upstream in remote_upstream(workflow, placement, rank) {
+            let (sender, receiver) = oneshot::channel();
+            let ports = upstream.connections.iter().map(|c| c.from_port.clone()).collect();
+            waiting.insert((upstream.module_id, upstream.rank), (ports, sender));
+            let module_type = workflow.modules.iter()
+                .find(|m| m.id == upstream.module_id)
+                .map(|m| m.module_type.clone())
+                .unwrap_or_default();
+            links.remote.push(RemoteModule {
+                module_id: upstream.module_id,
+                module_type,
+                rank: upstream.rank,
+                outputs: remote_outputs(&upstream, receiver, registry.clone()),
+                connections: upstream.connections,
+                dependents: upstream.dependents,
+            });
+        }
+
+        let mut destinations: BTreeMap<u32, Vec<i32>> = BTreeMap::new();
+        for other in (0..self.context.size()).filter(|&r| r != rank) {
+            for upstream in remote_upstream(workflow, placement, other) {
+                if upstream.rank == rank {
+                    destinations.entry(upstream.module_id).or_default().push(other);
+                }
+            }
+        }
+        for (&module_id, ranks) in &destinations {
+            let sink = outcome_sink(
+                self.context.clone(),
+                registry.clone(),
+                run,
+                module_id,
+                ranks.clone(),
+                announced.clone(),
+            );
+            links.sinks.insert(module_id, sink);
+        }
+
+        (links, waiting, destinations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::testing::cluster::{ClusterRank, MiniCluster};
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{ConnectionSpec, ModuleSpec, TaskEvent};
+
+    fn workflow(placements: &[Placement]) -> WorkflowSpec {
+        placements.iter().enumerate().fold(WorkflowSpec::new("placement", "Placement"), |spec, (i, &placement)| {
+            spec.add_module(ModuleSpec::new(i as u32 + 1, "ConstantField", "Module").with_placement(placement))
+        })
+    }
+
+    #[test]
+    fn placements_resolve_to_ranks() {
+        let spec = workflow(&[
+            Placement::Any,
+            Placement::Rank(2),
+            Placement::AllRanks,
+            Placement::Any,
+            Placement::NodeLocalWith(2),
+        ]);
+        let placement = resolve_placement(&spec, 3).unwrap();
+        assert_eq!(placement[&1], vec![0]);
+        assert_eq!(placement[&2], vec![2]);
+        assert_eq!(placement[&3], vec![0, 1, 2]);
+        // Any modules go round-robin by declaration order
+        assert_eq!(placement[&4], vec![0]);
+        assert_eq!(placement[&5], vec![2]);
+    }
+
+    #[test]
+    fn a_single_rank_runs_everything() {
+        let spec = workflow(&[Placement::Any, Placement::Rank(0), Placement::AllRanks, Placement::Any]);
+        let placement = resolve_placement(&spec, 1).unwrap();
+        assert!(placement.values().all(|ranks| ranks == &vec![0]));
+        assert_eq!(local_workflow(&spec, &placement, 0).modules.len(), spec.modules.len());
+    }
+
+    #[test]
+    fn invalid_placements_are_rejected() {
+        assert!(resolve_placement(&workflow(&[Placement::Rank(3)]), 3).is_err());
+        assert!(resolve_placement(&workflow(&[Placement::Rank(-1)]), 3).is_err());
+        assert!(resolve_placement(&workflow(&[Placement::NodeLocalWith(7)]), 3).is_err());
+        assert!(resolve_placement(&workflow(&[Placement::NodeLocalWith(2), Placement::NodeLocalWith(1)]), 3).is_err());
+        assert!(resolve_placement(&workflow(&[Placement::Any]), 0).is_err());
+    }
+
+    fn connect(spec: WorkflowSpec, from: u32, to: u32) -> WorkflowSpec {
+        spec.add_connection(ConnectionSpec {
+            from_module: from,
+            from_port: "data_out".to_string(),
+            to_module: to,
+            to_port: "data_in".to_string(),
+        })
+    }
+
+    #[test]
+    fn local_workflows_drop_remote_modules_and_links() {
+        let mut spec = connect(workflow(&[Placement::Rank(0), Placement::Rank(1), Placement::AllRanks]), 3, 1);
+        spec.modules[2].dependencies = vec![1, 2];
+        let placement = resolve_placement(&spec, 2).unwrap();
+
+        let local = local_workflow(&spec, &placement, 1);
+        let ids: Vec<u32> = local.modules.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(local.modules[1].dependencies, vec![2]);
+        assert!(local.connections.is_empty());
+
+        assert_eq!(local_workflow(&spec, &placement, 0).connections.len(), 1);
+    }
+
+    #[test]
+    fn consumers_take_the_outputs_of_their_own_or_the_first_producer_rank() {
+        let spec = workflow(&[Placement::AllRanks, Placement::Rank(0), Placement::Rank(1), Placement::AllRanks]);
+        let placement = resolve_placement(&spec, 3).unwrap();
+
+        // A single consumer gathers the pieces of every rank
+        assert_eq!(source_ranks(&placement, 1, 2, 0), vec![0, 1, 2]);
+        assert!(source_ranks(&placement, 1, 2, 1).is_empty());
+        // A consumer on every rank takes the producer's only output
+        assert_eq!(source_ranks(&placement, 2, 4, 2), vec![0]);
+        assert_eq!(source_ranks(&placement, 2, 3, 1), vec![0]);
+        // Producer and consumer on the same ranks stay local
+        assert_eq!(source_ranks(&placement, 1, 4, 1), vec![1]);
+
+        let spec = connect(connect(spec, 1, 4), 2, 3);
+        assert_eq!(cross_rank_connections(&spec, &placement), vec![&spec.connections[1]]);
+    }
+
+    #[test]
+    fn remote_upstream_lists_connections_and_dependencies_of_other_ranks() {
+        let mut spec = connect(workflow(&[Placement::AllRanks, Placement::Rank(0), Placement::Rank(1)]), 1, 2);
+        spec.modules[1].dependencies = vec![3];
+        let placement = resolve_placement(&spec, 3).unwrap();
+
+        let upstream = remote_upstream(&spec, &placement, 0);
+        let sources: Vec<(u32, i32)> = upstream.iter().map(|u| (u.module_id, u.rank)).collect();
+        assert_eq!(sources, vec![(1, 1), (1, 2), (3, 1)]);
+        assert_eq!(upstream[0].connections, spec.connections);
+        assert!(upstream[0].dependents.is_empty());
+        assert!(upstream[2].connections.is_empty());
+        assert_eq!(upstream[2].dependents, vec![2]);
+
+        // Rank 1 runs only producers, so it waits for nothing
+        assert!(remote_upstream(&spec, &placement, 1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn single_rank_runs_behave_as_before() {
+        let cluster = MiniCluster::new(1).await;
+        register_test_modules(&cluster.rank(0).modules).await;
+
+        let spec = workflow(&[Placement::Rank(0), Placement::AllRanks, Placement::Any]);
+        let result = cluster.rank(0).executor.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap();
+        assert!(result.success());
+        assert_eq!(result.local.task_results.len(), 3);
+        assert_eq!(result.ranks.len(), 1);
+        assert_eq!(result.ranks[0].tasks_completed, 3);
+    }
+
+    #[tokio::test]
+    async fn ranks_run_only_the_modules_placed_on_them() {
+        let cluster = MiniCluster::new(3).await;
+        for rank in cluster.ranks() {
+            register_test_modules(&rank.modules).await;
+        }
+
+        let spec = workflow(&[Placement::Rank(1), Placement::AllRanks]);
+        let results = cluster.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap();
+        let counts: Vec<usize> = results.iter().map(|r| r.local.task_results.len()).collect();
+        assert_eq!(counts, vec![1, 2, 1]);
+
+        // Only the root gathers the summaries of every rank
+        assert_eq!(results[0].ranks.len(), 3);
+        assert!(results[0].success());
+        assert!(results[1].ranks.is_empty());
+    }
+
+    async fn cluster(size: i32) -> MiniCluster {
+        let cluster = MiniCluster::new(size).await;
+        for rank in cluster.ranks() {
+            register_test_modules(&rank.modules).await;
+        }
+        cluster
+    }
+
+    fn outputs(result: &DistributedWorkflowResult, module_id: u32) -> Vec<Arc<dyn crate::core::Object>> {
+        result.local.task_results.iter()
+            .find(|r| r.module_id == Some(module_id))
+            .and_then(|r| r.outputs.as_ref())
+            .and_then(|outputs| outputs.get("data_out").cloned())
+            .unwrap_or_default()
+    }
+
+    /// When `module_id` first starts, or finishes, on `rank`
+    fn event(rank: &Arc<ClusterRank>, started: bool, module_id: u32) -> tokio::task::JoinHandle<std::time::Instant> {
+        let mut events = rank.executor.executor().task_executor().subscribe();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await.unwrap() {
+                    TaskEvent::Started { module_id: id, at, .. } if started && id == module_id => return at,
+                    TaskEvent::Finished { module_id: id, at, .. } if !started && id == module_id => return at,
+                    _ => {}
+                }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn a_single_consumer_gathers_the_outputs_of_every_rank() {
+        let cluster = cluster(3).await;
+        let mut spec = connect(workflow(&[Placement::AllRanks, Placement::Rank(0)]), 1, 2);
+        spec.modules[1].module_type = "Collect".to_string();
+
+        let results = cluster.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap();
+        assert!(results[0].success());
+        let creators: Vec<i32> = outputs(&results[0], 2).iter().map(|o| o.meta().creator).collect();
+        assert_eq!(creators, vec![0, 1, 2]);
+        // Ranks without a consumer report only their own producer
+        assert_eq!(results[1].local.task_results.len(), 1);
+        assert_eq!(cluster.rank(0).transfer.stats().transfers, 2);
+    }
+
+    #[tokio::test]
+    async fn objects_and_dependencies_cross_ranks() {
+        let cluster = cluster(2).await;
+        let mut spec = connect(workflow(&[Placement::Rank(0), Placement::Rank(1), Placement::Rank(0)]), 1, 2);
+        spec.modules[1].module_type = "Collect".to_string();
+        spec.modules[1].dependencies = vec![3];
+        spec.modules[2] = spec.modules[2].clone().with_parameter("delay_ms", "100");
+        let slow_finished = event(cluster.rank(0), false, 3);
+        let consumer_started = event(cluster.rank(1), true, 2);
+
+        let results = cluster.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap();
+        assert!(results.iter().all(|r| r.success()));
+        let received = outputs(&results[1], 2);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].meta().creator, 0);
+
+        // The consumer waited for the slow module of rank 0 it depends on
+        assert!(consumer_started.await.unwrap() >= slow_finished.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn failures_on_one_rank_fail_the_consumers_on_others() {
+        let cluster = cluster(2).await;
+        let mut spec = connect(connect(workflow(&[Placement::Rank(0), Placement::Rank(1), Placement::Rank(1)]), 1, 2), 2, 3);
+        spec.modules[0].module_type = "Failing".to_string();
+
+        let results = cluster.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap();
+        assert!(!results[0].success());
+        assert!(!results[1].local.success);
+        let error = results[1].local.task_results.iter()
+            .find_map(|r| r.error.clone())
+            .unwrap();
+        assert!(error.contains("Module 1 failed on rank 0"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn remote_inputs_are_local_before_their_consumer_runs() {
+        let cluster = cluster(2).await;
+        let spec = connect(workflow(&[Placement::Rank(0), Placement::Rank(1)]), 1, 2);
+
+        let consumer = cluster.rank(1).clone();
+        let mut events = consumer.executor.executor().task_executor().subscribe();
+        let fetched_at_start = tokio::spawn(async move {
+            loop {
+                if let Ok(TaskEvent::Started { module_id: 2, .. }) = events.recv().await {
+                    return consumer.transfer.stats().transfers;
+                }
+            }
+        });
+
+        let results = cluster.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap();
+        assert!(results.iter().all(|r| r.success()));
+        assert_eq!(fetched_at_start.await.unwrap(), 1);
+    }
+}
@@ -1,387 +1,1631 @@
-//! Workflow execution engine
-
-use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio::time::{timeout, Duration};
-
-use crate::core::{
-    MessageRouter, Message, MessageType, MessageEnvelope, MessagePayload,
-    ComputeContext, ObjectRegistry, ShmManager,
-};
-use crate::compute::{ModuleRegistry, TaskExecutor, Task, TaskId, TaskBuilder, TaskPriority};
-
-/// Workflow execution engine
-pub struct WorkflowExecutor {
-    module_registry: Arc<ModuleRegistry>,
-    task_executor: Arc<TaskExecutor>,
-    message_router: Arc<MessageRouter>,
-    object_registry: Arc<ObjectRegistry>,
-    shm_manager: Arc<ShmManager>,
-    active_workflows: RwLock<HashMap<String, WorkflowState>>,
-}
-
-impl WorkflowExecutor {
-    pub fn new(
-        module_registry: Arc<ModuleRegistry>,
-        task_executor: Arc<TaskExecutor>,
-        message_router: Arc<MessageRouter>,
-    ) -> Self {
-        Self {
-            module_registry,
-            task_executor,
-            message_router,
-            object_registry: Arc::new(ObjectRegistry::new()),
-            shm_manager: Arc::new(ShmManager::new()),
-            active_workflows: RwLock::new(HashMap::new()),
-        }
-    }
-
-    /// Execute a workflow with the given specification
-    pub async fn execute_workflow(
-        &self,
-        workflow: WorkflowSpec,
-        timeout_duration: Option<Duration>,
-    ) -> Result<WorkflowResult, crate::Error> {
-        let workflow_id = workflow.id.clone();
-
-        // Initialize workflow state
-        let state = WorkflowState {
-            id: workflow_id.clone(),
-            spec: workflow,
-            status: WorkflowStatus::Running,
-            start_time: std::time::Instant::now(),
-            tasks_completed: 0,
-            tasks_total: 0,
-        };
-
-        self.active_workflows.write().await.insert(workflow_id.clone(), state);
-
-        // Build and submit tasks
-        self.build_workflow_tasks(&workflow_id).await?;
-
-        // Execute tasks with timeout if specified
-        let execution_result = if let Some(duration) = timeout_duration {
-            match timeout(duration, self.task_executor.execute_all()).await {
-                Ok(result) => result,
-                Err(_) => return Err(crate::Error::Module("Workflow execution timeout".to_string())),
-            }
-        } else {
-            self.task_executor.execute_all().await
-        };
-
-        // Process results
-        let results = execution_result?;
-        let success = results.iter().all(|r| r.success);
-
-        // Update workflow state
-        let mut workflows = self.active_workflows.write().await;
-        if let Some(state) = workflows.get_mut(&workflow_id) {
-            state.status = if success { WorkflowStatus::Completed } else { WorkflowStatus::Failed };
-            state.tasks_completed = results.len();
-        }
-
-        Ok(WorkflowResult {
-            workflow_id,
-            success,
-            task_results: results,
-            execution_time: std::time::Instant::now().elapsed(),
-        })
-    }
-
-    /// Build tasks from workflow specification
-    async fn build_workflow_tasks(&self, workflow_id: &str) -> Result<(), crate::Error> {
-        let workflows = self.active_workflows.read().await;
-        let workflow = workflows.get(workflow_id)
-            .ok_or_else(|| crate::Error::Module("Workflow not found".to_string()))?;
-
-        let mut task_map = HashMap::new();
-        let mut task_dependencies = HashMap::new();
-
-        // Create tasks for each module in the workflow
-        for module_spec in &workflow.spec.modules {
-            let module = self.module_registry.create_instance(
-                &module_spec.module_type,
-                module_spec.id,
-            ).await?;
-
-            let context = ComputeContext::new(module_spec.id, 0, 1); // Single rank for now
-
-            let task = TaskBuilder::new()
-                .module(module)
-                .context(context)
-                .priority(module_spec.priority)
-                .build()
-                .map_err(|e| crate::Error::Module(format!("Failed to build task: {}", e)))?;
-
-            let task_id = task.id;
-            task_map.insert(module_spec.id, task_id);
-            task_dependencies.insert(task_id, module_spec.dependencies.clone());
-
-            self.task_executor.add_task(task).await;
-        }
-
-        // Set up task dependencies
-        for (task_id, deps) in task_dependencies {
-            let dep_task_ids = deps.iter()
-                .filter_map(|dep_id| task_map.get(dep_id))
-                .copied()
-                .collect::<Vec<_>>();
-
-            // Update task dependencies (would need access to task graph)
-            // This is a simplified version - in practice, the task graph
-            // would handle dependency resolution
-        }
-
-        Ok(())
-    }
-
-    /// Get workflow status
-    pub async fn workflow_status(&self, workflow_id: &str) -> Option<WorkflowStatus> {
-        self.active_workflows.read().await
-            .get(workflow_id)
-            .map(|state| state.status)
-    }
-
-    /// Cancel a running workflow
-    pub async fn cancel_workflow(&self, workflow_id: &str) -> Result<(), crate::Error> {
-        let mut workflows = self.active_workflows.write().await;
-        if let Some(state) = workflows.get_mut(workflow_id) {
-            state.status = WorkflowStatus::Cancelled;
-            // Send cancellation messages to modules
-            // Implementation would cancel running tasks
-        }
-        Ok(())
-    }
-
-    /// Get active workflows
-    pub async fn active_workflows(&self) -> Vec<String> {
-        self.active_workflows.read().await
-            .keys()
-            .cloned()
-            .collect()
-    }
-
-    /// Process incoming messages and update workflow state
-    pub async fn process_messages(&self) -> Result<(), crate::Error> {
-        // Process messages from the router
-        self.message_router.process_messages().await?;
-
-        // Update workflow states based on messages
-        // This would handle module completion notifications,
-        // error reports, etc.
-
-        Ok(())
-    }
-}
-
-/// Workflow specification
-#[derive(Debug, Clone)]
-pub struct WorkflowSpec {
-    pub id: String,
-    pub name: String,
-    pub description: String,
-    pub modules: Vec<ModuleSpec>,
-    pub connections: Vec<ConnectionSpec>,
-}
-
-impl WorkflowSpec {
-    pub fn new(id: &str, name: &str) -> Self {
-        Self {
-            id: id.to_string(),
-            name: name.to_string(),
-            description: String::new(),
-            modules: Vec::new(),
-            connections: Vec::new(),
-        }
-    }
-
-    pub fn with_description(mut self, desc: &str) -> Self {
-        self.description = desc.to_string();
-        self
-    }
-
-    pub fn add_module(mut self, module: ModuleSpec) -> Self {
-        self.modules.push(module);
-        self
-    }
-
-    pub fn add_connection(mut self, connection: ConnectionSpec) -> Self {
-        self.connections.push(connection);
-        self
-    }
-}
-
-/// Module specification in a workflow
-#[derive(Debug, Clone)]
-pub struct ModuleSpec {
-    pub id: u32,
-    pub module_type: String,
-    pub name: String,
-    pub parameters: HashMap<String, String>, // Parameter name -> value as string
-    pub dependencies: Vec<u32>, // Module IDs this depends on
-    pub priority: TaskPriority,
-}
-
-impl ModuleSpec {
-    pub fn new(id: u32, module_type: &str, name: &str) -> Self {
-        Self {
-            id,
-            module_type: module_type.to_string(),
-            name: name.to_string(),
-            parameters: HashMap::new(),
-            dependencies: Vec::new(),
-            priority: TaskPriority::Normal,
-        }
-    }
-
-    pub fn with_parameter(mut self, name: &str, value: &str) -> Self {
-        self.parameters.insert(name.to_string(), value.to_string());
-        self
-    }
-
-    pub fn depends_on(mut self, module_id: u32) -> Self {
-        self.dependencies.push(module_id);
-        self
-    }
-
-    pub fn with_priority(mut self, priority: TaskPriority) -> Self {
-        self.priority = priority;
-        self
-    }
-}
-
-/// Connection specification between modules
-#[derive(Debug, Clone)]
-pub struct ConnectionSpec {
-    pub from_module: u32,
-    pub from_port: String,
-    pub to_module: u32,
-    pub to_port: String,
-}
-
-/// Workflow execution state
-#[derive(Debug, Clone)]
-struct WorkflowState {
-    id: String,
-    spec: WorkflowSpec,
-    status: WorkflowStatus,
-    start_time: std::time::Instant,
-    tasks_completed: usize,
-    tasks_total: usize,
-}
-
-/// Workflow execution status
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum WorkflowStatus {
-    Pending,
-    Running,
-    Completed,
-    Failed,
-    Cancelled,
-}
-
-/// Workflow execution result
-#[derive(Debug)]
-pub struct WorkflowResult {
-    pub workflow_id: String,
-    pub success: bool,
-    pub task_results: Vec<crate::compute::TaskResult>,
-    pub execution_time: std::time::Duration,
-}
-
-/// Workflow builder for fluent construction
-pub struct WorkflowBuilder {
-    spec: WorkflowSpec,
-    next_module_id: u32,
-}
-
-impl WorkflowBuilder {
-    pub fn new(id: &str, name: &str) -> Self {
-        Self {
-            spec: WorkflowSpec::new(id, name),
-            next_module_id: 1,
-        }
-    }
-
-    pub fn description(mut self, desc: &str) -> Self {
-        self.spec.description = desc.to_string();
-        self
-    }
-
-    pub fn add_module(mut self, module_type: &str, name: &str) -> ModuleBuilder {
-        let module_id = self.next_module_id;
-        self.next_module_id += 1;
-
-        let module_spec = ModuleSpec::new(module_id, module_type, name);
-        self.spec.modules.push(module_spec);
-
-        ModuleBuilder {
-            workflow_builder: self,
-            module_id,
-        }
-    }
-
-    pub fn connect(mut self, from: u32, from_port: &str, to: u32, to_port: &str) -> Self {
-        let connection = ConnectionSpec {
-            from_module: from,
-            from_port: from_port.to_string(),
-            to_module: to,
-            to_port: to_port.to_string(),
-        };
-        self.spec.connections.push(connection);
-        self
-    }
-
-    pub fn build(self) -> WorkflowSpec {
-        self.spec
-    }
-}
-
-/// Module builder for fluent module configuration
-pub struct ModuleBuilder {
-    workflow_builder: WorkflowBuilder,
-    module_id: u32,
-}
-
-impl ModuleBuilder {
-    pub fn parameter(mut self, name: &str, value: &str) -> Self {
-        if let Some(module) = self.workflow_builder.spec.modules.last_mut() {
-            if module.id == self.module_id {
-                module.parameters.insert(name.to_string(), value.to_string());
-            }
-        }
-        self
-    }
-
-    pub fn priority(mut self, priority: TaskPriority) -> Self {
-        if let Some(module) = self.workflow_builder.spec.modules.last_mut() {
-            if module.id == self.module_id {
-                module.priority = priority;
-            }
-        }
-        self
-    }
-
-    pub fn depends_on(mut self, dependency_id: u32) -> Self {
-        if let Some(module) = self.workflow_builder.spec.modules.last_mut() {
-            if module.id == self.module_id {
-                module.dependencies.push(dependency_id);
-            }
-        }
-        self
-    }
-
-    pub fn add_module(mut self, module_type: &str, name: &str) -> ModuleBuilder {
-        self.workflow_builder = self.workflow_builder.add_module(module_type, name);
-        self
-    }
-
-    pub fn connect(mut self, from: u32, from_port: &str, to: u32, to_port: &str) -> WorkflowBuilder {
-        self.workflow_builder.connect(from, from_port, to, to_port)
-    }
-
-    pub fn build(self) -> WorkflowSpec {
-        self.workflow_builder.build()
-    }
-}
+//! Workflow execution engine
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, RwLock};
+use tokio_util::sync::CancellationToken;
+use tokio::time::{timeout, Duration};
+
+use crate::core::{
+    MessageRouter,
+    ComputeContext, CpuPool, HealthMonitor, ObjectRegistry, PrefetchConfig, PrefetchStats, ShmConfig, ShmManager,
+    RetentionConfig, RetentionDeletion, RetentionManager, RetentionPolicy,
+    attribute, validate_object, Object, VistleObject,
+};
+use crate::compute::{
+    ConnectionStats, InputPorts, ModuleLoader, downstream_modules, ModuleRegistry, OutputPorts, TaskExecutor, Task, TaskId, TaskPriority,
+    ProgressTracker, TaskResult, WatchEvent, WorkflowLimits, WorkflowProgress,
+    BudgetPolicy, ModuleSpan, StageBudgetExceeded, StageSpec, StageTiming, StageTracker, STAGE_CHECK_INTERVAL,
+    OutputDecision, OutputPlan, OutputPolicy, CoercionRegistry, InsertedAdapter, insert_adapters,
+    InteractiveConfig, InteractiveState, RunEvent, RunKind,
+    AuditConfig, NonFiniteOffender, PortAudit, audit_ports_on, first_nonfinite, introduces_nonfinite,
+    is_expression, resolve_parameter_expressions, RankLinks,
+    LoadWarnings, MigrationRegistry, WORKFLOW_FORMAT_VERSION, workflow_from_value,
+};
+use crate::hub::Hub;
+use crate::util::fmt::format_f32;
+
+/// Workflow execution engine
+pub struct WorkflowExecutor {
+    module_registry: Arc<ModuleRegistry>,
+    task_executor: Arc<TaskExecutor>,
+    message_router: Arc<MessageRouter>,
+    object_registry: Arc<ObjectRegistry>,
+    shm_manager: Arc<ShmManager>,
+    active_workflows: RwLock<HashMap<String, WorkflowState>>,
+    hub: Option<Arc<Hub>>,
+    cpu_pool: Option<CpuPool>,
+    cancel_tokens: parking_lot::Mutex<HashMap<String, CancellationToken>>,
+    /// Tokens of stages, children of their workflow's token
+    stage_tokens: parking_lot::Mutex<HashMap<(String, String), CancellationToken>>,
+    watch_events: broadcast::Sender<WatchEvent>,
+    progress: parking_lot::Mutex<HashMap<String, ProgressTracker>>,
+    progress_events: broadcast::Sender<WorkflowProgress>,
+    output_events: broadcast::Sender<OutputEvent>,
+    stage_events: broadcast::Sender<StageBudgetExceeded>,
+    coercions: Arc<CoercionRegistry>,
+    interactive: Option<InteractiveConfig>,
+    /// Latest outputs of each module by workflow, kept for interactive recomputes
+    cached_outputs: parking_lot::Mutex<HashMap<String, HashMap<u32, OutputPorts>>>,
+    interactive_state: parking_lot::Mutex<HashMap<String, InteractiveState>>,
+    run_events: broadcast::Sender<RunEvent>,
+    /// Retention of the files written by each running workflow with policies
+    retention: parking_lot::Mutex<HashMap<String, Arc<RetentionManager>>>,
+    audit: Option<AuditConfig>,
+    rank: i32,
+    size: i32,
+}
+
+impl WorkflowExecutor {
+    pub fn new(
+        module_registry: Arc<ModuleRegistry>,
+        task_executor: Arc<TaskExecutor>,
+        message_router: Arc<MessageRouter>,
+    ) -> Self {
+        let object_registry = Arc::new(ObjectRegistry::new());
+        object_registry.set_loader(Arc::new(ModuleLoader::new(module_registry.clone(), message_router.clone())));
+        Self {
+            module_registry,
+            task_executor,
+            message_router,
+            object_registry,
+            shm_manager: Arc::new(ShmManager::new()),
+            active_workflows: RwLock::new(HashMap::new()),
+            hub: None,
+            cpu_pool: None,
+            cancel_tokens: parking_lot::Mutex::new(HashMap::new()),
+            stage_tokens: parking_lot::Mutex::new(HashMap::new()),
+            watch_events: broadcast::channel(64).0,
+            progress: parking_lot::Mutex::new(HashMap::new()),
+            progress_events: broadcast::channel(64).0,
+            output_events: broadcast::channel(64).0,
+            stage_events: broadcast::channel(64).0,
+            coercions: Arc::new(CoercionRegistry::with_builtin_rules()),
+            interactive: None,
+            cached_outputs: parking_lot::Mutex::new(HashMap::new()),
+            interactive_state: parking_lot::Mutex::new(HashMap::new()),
+            run_events: broadcast::channel(64).0,
+            retention: parking_lot::Mutex::new(HashMap::new()),
+            audit: None,
+            rank: 0,
+            size: 1,
+        }
+    }
+
+    /// Set the rank and communicator size passed to module compute contexts
+    pub fn with_rank(mut self, rank: i32, size: i32) -> Self {
+        self.rank = rank;
+        self.size = size;
+        self
+    }
+
+    /// Dispatch module executions to the hosts connected to a hub instead of running them locally
+    pub fn with_hub(mut self, hub: Arc<Hub>) -> Self {
+        self.hub = Some(hub);
+        self
+    }
+
+    /// Pool modules run CPU-heavy work on; use `CpuPool::batch()` for headless runs
+    pub fn with_cpu_pool(mut self, pool: CpuPool) -> Self {
+        self.cpu_pool = Some(pool);
+        self
+    }
+
+    /// Report the health of the router, task executor and shared memory to `monitor`
+    pub fn with_health(self, monitor: &HealthMonitor) -> Self {
+        self.message_router.register_health(monitor);
+        self.task_executor.register_health(monitor);
+        self.shm_manager.register_health(monitor);
+        self
+    }
+
+    /// How far ahead placeholder timesteps are loaded while modules process earlier ones
+    pub fn with_prefetch(self, config: PrefetchConfig) -> Self {
+        self.object_registry.set_prefetch(config);
+        self
+    }
+
+    /// Keep module outputs and allow `set_parameter_interactive`
+    pub fn with_interactive(mut self, config: InteractiveConfig) -> Self {
+        self.interactive = Some(config);
+        self
+    }
+
+    /// Scan module outputs for NaN and infinite values, see `compute::audit`
+    pub fn with_audit(mut self, config: AuditConfig) -> Self {
+        self.audit = Some(config);
+        self
+    }
+
+    pub fn audit(&self) -> Option<AuditConfig> {
+        self.audit
+    }
+
+    pub fn interactive(&self) -> Option<InteractiveConfig> {
+        self.interactive
+    }
+
+    /// Rules adapters between mismatched ports are chosen from, see `WorkflowSpec::allow_coercion`
+    pub fn coercions(&self) -> &Arc<CoercionRegistry> {
+        &self.coercions
+    }
+
+    pub fn object_registry(&self) -> &Arc<ObjectRegistry> {
+        &self.object_registry
+    }
+
+    pub fn task_executor(&self) -> &Arc<TaskExecutor> {
+        &self.task_executor
+    }
+
+    pub fn module_registry(&self) -> &Arc<ModuleRegistry> {
+        &self.module_registry
+    }
+
+    /// Execute a workflow with the given specification
+    ///
+    /// Existing output files are handled by the workflow's `output_policy`
+    /// before any module runs, see `plan_outputs`.
+    pub async fn execute_workflow(
+        &self,
+        workflow: WorkflowSpec,
+        timeout_duration: Option<Duration>,
+    ) -> Result<WorkflowResult, crate::Error> {
+        self.execute_workflow_reusing(workflow, timeout_duration, &HashMap::new(), &[]).await
+    }
+
+    /// Execute the modules of a workflow placed on this rank, linked to those on other ranks
+    ///
+    /// Modules of other ranks in `links` are stood in for by tasks completing
+    /// with their outputs there, and local modules tell their sinks in
+    /// `links` how they ended; see `DistributedWorkflowExecutor`. Results of
+    /// stand-ins are left out unless they failed.
+    pub(crate) async fn execute_workflow_linked(
+        &self,
+        workflow: WorkflowSpec,
+        timeout_duration: Option<Duration>,
+        links: &RankLinks,
+    ) -> Result<WorkflowResult, crate::Error> {
+        self.execute(workflow, timeout_duration, &HashMap::new(), &[], links).await
+    }
+
+    /// Execute a workflow, reusing outputs of an earlier run of the same modules
+    ///
+    /// Modules downstream of `changed` run; every other module with outputs
+    /// in `previous` completes with them instead. Module ids include the
+    /// adapters `insert_adapters` adds, which are numbered the same way on
+    /// every run of a workflow.
+    pub(crate) async fn execute_workflow_reusing(
+        &self,
+        workflow: WorkflowSpec,
+        timeout_duration: Option<Duration>,
+        previous: &HashMap<u32, OutputPorts>,
+        changed: &[u32],
+    ) -> Result<WorkflowResult, crate::Error> {
+        self.execute(workflow, timeout_duration, previous, changed, &RankLinks::default()).await
+    }
+
+    async fn execute(
+        &self,
+        mut workflow: WorkflowSpec,
+        timeout_duration: Option<Duration>,
+        previous: &HashMap<u32, OutputPorts>,
+        changed: &[u32],
+        links: &RankLinks,
+    ) -> Result<WorkflowResult, crate::Error> {
+        if self.hub.is_some() && !links.is_empty() {
+            return Err(crate::Error::Config(format!(
+                "Workflow {} is split across ranks and cannot run on hub hosts",
+                workflow.id
+            )));
+        }
+        workflow.validate(&self.module_registry).await?;
+        self.check_expressions(&workflow).await?;
+        let port_types = self.port_types(&workflow).await?;
+        let adapters = insert_adapters(&mut workflow, &port_types, &self.coercions)?;
+        let outputs = self.plan_outputs(&workflow).await?;
+        outputs.check()?;
+        outputs.apply(&mut workflow)?;
+        let dirty = downstream_modules(&workflow, changed);
+        let reuse: HashMap<u32, OutputPorts> = previous.iter()
+            .filter(|(id, _)| !dirty.contains(id))
+            .map(|(&id, ports)| (id, ports.clone()))
+            .collect();
+
+        let workflow_id = workflow.id.clone();
+        let workflow_name = workflow.name.clone();
+        let modules = workflow.modules.clone();
+        let skipped = outputs.skipped_modules(&workflow);
+        if !skipped.is_empty() {
+            tracing::info!("Workflow {}: skipping modules {:?}, their outputs exist already", workflow_id, skipped);
+            workflow.modules.retain(|m| !skipped.contains(&m.id));
+            workflow.connections.retain(|c| !skipped.contains(&c.from_module) && !skipped.contains(&c.to_module));
+        }
+        let connections = workflow.connections.clone();
+        let limits = workflow.limits.clone();
+        let retention = if workflow.retention.is_empty() {
+            None
+        } else {
+            Some(Arc::new(RetentionManager::new(&workflow_id, &workflow.retention, workflow.base_dir.as_deref())?))
+        };
+        let start_time = std::time::Instant::now();
+        let stages = Arc::new(StageTracker::new(&workflow, start_time));
+        let prefetch_start = self.object_registry.prefetch_stats();
+
+        // Initialize workflow state
+        let state = WorkflowState {
+            spec: workflow,
+            status: WorkflowStatus::Running,
+            tasks_completed: 0,
+        };
+
+        self.active_workflows.write().await.insert(workflow_id.clone(), state);
+
+        // Shared memory for this workflow is charged to its id and released when it ends
+        let arena = self.shm_manager.create_owned_arena(
+            &workflow_id,
+            Self::arena_name(&workflow_id),
+            // Ranks of an in-process cluster share the process id, so the rank keeps their names apart
+            ShmConfig {
+                name: format!("vistle_shm_{}_{}_{}", std::process::id(), self.rank, workflow_id),
+                ..ShmConfig::default()
+            },
+        )?;
+
+        if let Some(manager) = retention {
+            self.retention.lock().insert(workflow_id.clone(), manager);
+        }
+
+        // Build and submit tasks; with a hub, modules run on remote hosts instead
+        let mut stand_ins = HashSet::new();
+        if self.hub.is_none() {
+            self.task_executor.set_workflow_limits(&workflow_id, limits);
+            match self.build_workflow_tasks(&workflow_id, &reuse, links).await {
+                Ok(ids) => stand_ins = ids,
+                Err(e) => {
+                    self.task_executor.remove_workflow(&workflow_id).await;
+                    self.shm_manager.release_owner(&workflow_id);
+                    self.retention.lock().remove(&workflow_id);
+                    return Err(e);
+                }
+            }
+        }
+
+        // Local tasks report their start and end through the task executor
+        let task_events = self.hub.is_none().then(|| self.task_executor.subscribe());
+        let stop_watchdog = CancellationToken::new();
+        let watchdog = self.spawn_stage_watchdog(stages.clone(), task_events, stop_watchdog.clone());
+
+        let execution = async {
+            match &self.hub {
+                Some(hub) => self.execute_remote(hub, &workflow_id, &stages, &reuse).await,
+                None => self.task_executor.execute_workflow(&workflow_id).await,
+            }
+        };
+
+        // Execute tasks with timeout if specified
+        let execution_result = match timeout_duration {
+            Some(duration) => timeout(duration, execution).await.ok(),
+            None => Some(execution.await),
+        };
+        stop_watchdog.cancel();
+        let _ = watchdog.await;
+        self.stage_tokens.lock().retain(|(id, _), _| id != &workflow_id);
+        let retention = self.retention.lock().remove(&workflow_id)
+            .map(|manager| manager.deletions())
+            .unwrap_or_default();
+        let Some(execution_result) = execution_result else {
+            // Tasks still running would otherwise keep slots other workflows wait for
+            self.task_executor.cancel_workflow(&workflow_id).await;
+            self.task_executor.remove_workflow(&workflow_id).await;
+            self.shm_manager.release_owner(&workflow_id);
+            return Err(crate::Error::Module("Workflow execution timeout".to_string()));
+        };
+
+        let shm_stats = arena.stats();
+        self.shm_manager.release_owner(&workflow_id);
+
+        // Process results
+        let mut results = execution_result?;
+        // Modules of other ranks only show up here if they failed this rank's run
+        results.retain(|r| !r.success || !stand_ins.contains(&r.task_id));
+        // Remote results were audited as each wave finished
+        if let (Some(config), None) = (self.audit, &self.hub) {
+            for result in &mut results {
+                if let (Some(module_id), Some(outputs)) = (result.module_id, &result.outputs) {
+                    result.nonfinite = self.audit_outputs(module_id, outputs, config).await;
+                }
+            }
+        }
+        let success = results.iter().all(|r| r.success);
+        for result in &results {
+            if let (Some(module_id), Some(outputs)) = (result.module_id, &result.outputs) {
+                self.publish_output(OutputEvent {
+                    workflow_id: workflow_id.clone(),
+                    module_id,
+                    outputs: outputs.clone(),
+                    kind: RunKind::Full,
+                });
+                self.cache_outputs(&workflow_id, module_id, outputs.clone());
+            }
+        }
+        let connection_stats = ConnectionStats::collect(&connections, &results);
+        let nonfinite = first_nonfinite(&connections, &results);
+        if let Some(offender) = &nonfinite {
+            tracing::warn!("Workflow {}: {}", workflow_id, offender);
+        }
+        for stats in connection_stats.iter().filter(|c| c.is_empty()) {
+            let c = &stats.connection;
+            tracing::warn!(
+                "Workflow {}: no data flowed from {}:{} to {}:{}",
+                workflow_id, c.from_module, c.from_port, c.to_module, c.to_port
+            );
+        }
+        // Empty objects are legitimate, e.g. a threshold nothing passed, so only informational
+        for stats in connection_stats.iter().filter(|c| c.only_empty_objects()) {
+            let c = &stats.connection;
+            tracing::info!(
+                "Workflow {}: only empty objects flowed from {}:{} to {}:{}",
+                workflow_id, c.from_module, c.from_port, c.to_module, c.to_port
+            );
+        }
+
+        // Update workflow state
+        let mut workflows = self.active_workflows.write().await;
+        if let Some(state) = workflows.get_mut(&workflow_id) {
+            state.status = if success { WorkflowStatus::Completed } else { WorkflowStatus::Failed };
+            state.tasks_completed = results.len();
+        }
+        drop(workflows);
+
+        let execution_time = start_time.elapsed();
+        self.publish_run(RunEvent {
+            workflow_id: workflow_id.clone(),
+            kind: RunKind::Full,
+            changes: Vec::new(),
+            dirty: Vec::new(),
+            executed: results.iter().filter_map(|r| r.module_id).collect(),
+            reused: Vec::new(),
+            success,
+            error: results.iter().find_map(|r| r.error.clone()),
+            execution_time_ms: execution_time.as_secs_f64() * 1000.0,
+        });
+
+        Ok(WorkflowResult {
+            workflow_id,
+            workflow_name,
+            success,
+            task_results: results,
+            execution_time,
+            modules,
+            shm_stats: Some(shm_stats),
+            connection_stats,
+            stages: stages.timings(),
+            module_spans: stages.spans(),
+            outputs: outputs.decisions,
+            prefetch: self.object_registry.prefetch_stats().since(&prefetch_start),
+            adapters,
+            retention,
+            first_nonfinite: nonfinite,
+        })
+    }
+
+    /// Declared data types of the ports of a workflow's modules
+    ///
+    /// Modules not registered here are left out, so their connections are
+    /// not checked.
+    async fn port_types(&self, workflow: &WorkflowSpec) -> Result<HashMap<(u32, String), String>, crate::Error> {
+        let mut port_types = HashMap::new();
+        for module_spec in &workflow.modules {
+            let Ok(module) = self.module_registry.create_detached(&module_spec.module_type).await else {
+                continue;
+            };
+            for port in module.ports().names() {
+                if let Some(data_type) = module.ports().get(&port).and_then(|p| p.data_type.clone()) {
+                    port_types.insert((module_spec.id, port), data_type);
+                }
+            }
+        }
+        Ok(port_types)
+    }
+
+    /// Decide what happens to the output files of a workflow's writers, without running it
+    ///
+    /// Modules not registered here, e.g. ones only hub hosts provide, are
+    /// left out of the plan.
+    pub async fn plan_outputs(&self, workflow: &WorkflowSpec) -> Result<OutputPlan, crate::Error> {
+        let mut plan = OutputPlan::new(workflow.output_policy.clone());
+        for module_spec in &workflow.modules {
+            let module = match self.module_registry.create_detached(&module_spec.module_type).await {
+                Ok(module) => module,
+                Err(e) => {
+                    tracing::debug!("Workflow {}: not planning outputs of module {}: {}", workflow.id, module_spec.id, e);
+                    continue;
+                }
+            };
+            // Expressions are only evaluated once the module's inputs are known
+            for (name, text) in module_spec.literal_parameters() {
+                module.set_parameter_str(name, text)?;
+            }
+            plan.add_module(module_spec.id, &module.planned_outputs().await, workflow.base_dir.as_deref()).await?;
+        }
+        for conflict in plan.conflicts() {
+            tracing::info!(
+                "Workflow {}: output {} of module {} exists already ({} files), {:?}",
+                workflow.id, conflict.path.display(), conflict.module_id, conflict.existing.len(), conflict.action
+            );
+        }
+        Ok(plan)
+    }
+
+    /// Follow the stages of a run and publish an event for each that runs over its budget
+    ///
+    /// Once `stop` is cancelled, the remaining task events are taken in and
+    /// stages are checked a last time, so a stage that finished over budget
+    /// between two checks is still reported.
+    fn spawn_stage_watchdog(
+        &self,
+        stages: Arc<StageTracker>,
+        mut task_events: Option<broadcast::Receiver<crate::compute::TaskEvent>>,
+        stop: CancellationToken,
+    ) -> tokio::task::JoinHandle<()> {
+        let sender = self.stage_events.clone();
+        let cancel: HashMap<String, CancellationToken> = stages.stages().iter()
+            .filter(|stage| stage.policy == BudgetPolicy::Cancel)
+            .map(|stage| (stage.name.clone(), self.stage_cancel_token(stages.workflow_id(), &stage.name)))
+            .collect();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STAGE_CHECK_INTERVAL);
+            loop {
+                let done = tokio::select! {
+                    _ = interval.tick() => false,
+                    _ = stop.cancelled() => true,
+                };
+                if let Some(events) = task_events.as_mut() {
+                    loop {
+                        match events.try_recv() {
+                            Ok(event) => stages.apply(&event),
+                            Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                                tracing::warn!("Workflow {}: missed {} task events, stage timings are incomplete", stages.workflow_id(), missed);
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                }
+
+                let now = (!done).then(std::time::Instant::now);
+                for (stage, elapsed) in stages.overruns(now) {
+                    let budget = stage.budget.unwrap_or_default();
+                    let token = cancel.get(&stage.name).filter(|_| !done);
+                    tracing::warn!(
+                        "Workflow {}: stage {} exceeded its budget of {:.1} s after {:.1} s{}",
+                        stages.workflow_id(), stage.name, budget.as_secs_f64(), elapsed.as_secs_f64(),
+                        if token.is_some() { ", cancelling it" } else { "" }
+                    );
+                    if let Some(token) = token {
+                        token.cancel();
+                    }
+                    // No subscribers is fine
+                    let _ = sender.send(StageBudgetExceeded {
+                        workflow_id: stages.workflow_id().to_string(),
+                        stage: stage.name,
+                        budget,
+                        elapsed,
+                        cancelled: token.is_some(),
+                    });
+                }
+                if done {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Build tasks from workflow specification
+    /// Submit a task per module, and per module of another rank in `links`; returns the ids of the latter
+    async fn build_workflow_tasks(
+        &self,
+        workflow_id: &str,
+        reuse: &HashMap<u32, OutputPorts>,
+        links: &RankLinks,
+    ) -> Result<HashSet<TaskId>, crate::Error> {
+        let workflows = self.active_workflows.read().await;
+        let workflow = workflows.get(workflow_id)
+            .ok_or_else(|| crate::Error::Module("Workflow not found".to_string()))?;
+
+        let strict = workflow.spec.strict.then(|| Arc::new(workflow.spec.clone()));
+        // Ids up front, so tasks can name upstream tasks added after them
+        let task_ids: HashMap<u32, TaskId> = workflow.spec.modules.iter()
+            .map(|m| (m.id, TaskId::default()))
+            .collect();
+        let remote_ids: Vec<TaskId> = links.remote.iter().map(|_| TaskId::default()).collect();
+
+        for (remote, &task_id) in links.remote.iter().zip(&remote_ids) {
+            let module = Arc::new(self.module_registry.create_detached(&remote.module_type).await?);
+            let context = self.compute_context(remote.module_id, workflow_id, &workflow.spec);
+            let task = Task::new(task_id, module, context)
+                .with_workflow(workflow_id)
+                .with_router(self.message_router.clone())
+                .with_remote_outputs(remote.outputs.clone());
+            self.task_executor.add_task(task).await;
+        }
+
+        // Create tasks for each module in the workflow
+        for module_spec in &workflow.spec.modules {
+            let module = self.module_registry.create_instance(
+                &module_spec.module_type,
+                module_spec.id,
+            ).await?;
+            // Local tasks are built before any module ran, so expressions only see parameters
+            let module_spec = resolve_parameter_expressions(module_spec, Some(&module.parameters()), &InputPorts::new())?;
+            for (name, text) in &module_spec.parameters {
+                module.set_parameter_str(name, text)?;
+            }
+
+            let context = self.compute_context(module_spec.id, workflow_id, &workflow.spec);
+
+            let mut dependencies: Vec<TaskId> = module_spec.dependencies.iter().filter_map(|id| task_ids.get(id)).copied().collect();
+            let mut inputs: Vec<(i32, TaskId, &ConnectionSpec)> = workflow.spec.connections.iter()
+                .filter(|c| c.to_module == module_spec.id)
+                .filter_map(|c| Some((self.rank, *task_ids.get(&c.from_module)?, c)))
+                .collect();
+            for (remote, &task_id) in links.remote.iter().zip(&remote_ids) {
+                if remote.dependents.contains(&module_spec.id) {
+                    dependencies.push(task_id);
+                }
+                inputs.extend(remote.connections.iter()
+                    .filter(|c| c.to_module == module_spec.id)
+                    .map(|c| (remote.rank, task_id, c)));
+            }
+            // Outputs gathered from several ranks arrive in rank order
+            inputs.sort_by_key(|(rank, _, _)| *rank);
+
+            let mut task = Task::new(task_ids[&module_spec.id], module, context)
+                .with_dependencies(dependencies)
+                .with_priority(module_spec.priority)
+                .with_workflow(workflow_id)
+                .with_router(self.message_router.clone());
+            if let Some(spec) = &strict {
+                let (spec, module_id) = (spec.clone(), module_spec.id);
+                task = task.with_output_check(Arc::new(move |outputs| check_outputs(&spec, module_id, outputs)));
+            }
+            for (_, from, connection) in inputs {
+                task = task.with_input(from, &connection.from_port, &connection.to_port);
+            }
+            if let Some(outputs) = reuse.get(&module_spec.id) {
+                task = task.with_reused_outputs(outputs.clone());
+            }
+            if let Some(sink) = links.sinks.get(&module_spec.id) {
+                task = task.with_output_sink(sink.clone());
+            }
+
+            self.task_executor.add_task(task).await;
+        }
+
+        Ok(remote_ids.into_iter().collect())
+    }
+
+    /// Run a workflow on hub hosts, one wave of ready modules at a time
+    ///
+    /// Outputs come back by value and are forwarded along the workflow's
+    /// connections. A module whose host fails or disconnects fails its task,
+    /// and every module downstream of it fails without being dispatched.
+    /// Modules in `reuse` are not dispatched and complete with those outputs.
+    async fn execute_remote(
+        &self,
+        hub: &Hub,
+        workflow_id: &str,
+        stages: &StageTracker,
+        reuse: &HashMap<u32, OutputPorts>,
+    ) -> Result<Vec<TaskResult>, crate::Error> {
+        let spec = self.active_workflows.read().await
+            .get(workflow_id)
+            .map(|state| state.spec.clone())
+            .ok_or_else(|| crate::Error::Module("Workflow not found".to_string()))?;
+
+        let mut upstream: HashMap<u32, HashSet<u32>> = spec.modules.iter()
+            .map(|m| (m.id, m.dependencies.iter().copied().collect()))
+            .collect();
+        for connection in &spec.connections {
+            upstream.entry(connection.to_module).or_default().insert(connection.from_module);
+        }
+
+        let mut outputs: HashMap<u32, OutputPorts> = HashMap::new();
+        // Failed modules with the error that started it, e.g. their host disconnecting
+        let mut failed: HashMap<u32, String> = HashMap::new();
+        let mut remaining: Vec<&ModuleSpec> = spec.modules.iter().collect();
+        let mut results = Vec::new();
+
+        while !remaining.is_empty() {
+            let finished = |id: &u32| outputs.contains_key(id) || failed.contains_key(id);
+            let (ready, waiting): (Vec<_>, Vec<_>) = remaining.into_iter()
+                .partition(|m| upstream.get(&m.id).map(|u| u.iter().all(&finished)).unwrap_or(true));
+            if ready.is_empty() {
+                return Err(crate::Error::Config(format!(
+                    "Workflow {} has a dependency cycle",
+                    workflow_id
+                )));
+            }
+            remaining = waiting;
+
+            let spec = &spec;
+            let wave = ready.into_iter().map(|module| {
+                let blocked = upstream.get(&module.id)
+                    .and_then(|u| u.iter().find_map(|id| Some((*id, failed.get(id)?.clone()))));
+                let inputs = self.remote_inputs(spec, module.id, &outputs);
+                let reused = reuse.get(&module.id).cloned();
+                async move {
+                    let started = std::time::Instant::now();
+                    let result = match (&blocked, reused) {
+                        (Some((id, cause)), _) => Err(crate::Error::Module(format!("Upstream module {} failed: {}", id, cause))),
+                        (None, Some(ports)) => Ok(ports),
+                        (None, None) => {
+                            let ctx = self.compute_context(module.id, workflow_id, spec);
+                            stages.module_started(module.id, started);
+                            let result = hub.dispatch(module, &inputs, &ctx).await;
+                            stages.module_finished(module.id, std::time::Instant::now());
+                            result
+                        }
+                    };
+                    (module.id, result, started.elapsed(), blocked.map(|(_, cause)| cause))
+                }
+            });
+
+            let mut offender = None;
+            for (module_id, result, execution_time, cause) in futures::future::join_all(wave).await {
+                let mut result = result.and_then(|ports| check_outputs(spec, module_id, ports));
+                let nonfinite = match (self.audit, &result) {
+                    (Some(config), Ok(ports)) => self.audit_outputs(module_id, ports, config).await,
+                    _ => BTreeMap::new(),
+                };
+                if self.audit.is_some_and(|config| config.fail_fast) && offender.is_none() {
+                    let inputs = spec.connections.iter()
+                        .filter(|c| c.to_module == module_id)
+                        .filter_map(|c| results.iter()
+                            .find(|r: &&TaskResult| r.module_id == Some(c.from_module))?
+                            .nonfinite.get(&c.from_port));
+                    if introduces_nonfinite(&nonfinite, inputs) {
+                        let found = NonFiniteOffender::new(module_id, &nonfinite);
+                        result = Err(crate::Error::Compute(found.to_string()));
+                        offender = Some(found);
+                    }
+                }
+                let task_result = TaskResult {
+                    task_id: TaskId::default(),
+                    module_id: Some(module_id),
+                    success: result.is_ok(),
+                    outputs: result.as_ref().ok().cloned(),
+                    error: result.as_ref().err().map(|e| e.to_string()),
+                    execution_time,
+                    nonfinite,
+                };
+                match result {
+                    Ok(ports) => {
+                        outputs.insert(module_id, ports);
+                    }
+                    Err(e) => {
+                        tracing::warn!("Remote module {} failed: {}", module_id, e);
+                        failed.insert(module_id, cause.unwrap_or_else(|| e.to_string()));
+                    }
+                }
+                results.push(task_result);
+            }
+            if let Some(offender) = offender {
+                tracing::error!("Workflow {}: stopped, {}", workflow_id, offender);
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub(crate) fn compute_context(&self, module_id: u32, workflow_id: &str, spec: &WorkflowSpec) -> ComputeContext {
+        let stage = spec.modules.iter()
+            .find(|m| m.id == module_id)
+            .and_then(|m| m.stage.as_deref());
+        let cancellation = match stage {
+            Some(stage) => self.stage_cancel_token(workflow_id, stage),
+            None => self.cancel_token(workflow_id),
+        };
+        let ctx = ComputeContext::new(module_id, self.rank, self.size)
+            .with_base_dir(spec.base_dir.clone())
+            .with_cancellation(cancellation)
+            .with_objects(self.object_registry.clone());
+        let ctx = match self.retention.lock().get(workflow_id) {
+            Some(manager) => ctx.with_retention(manager.clone()),
+            None => ctx,
+        };
+        // Outputs count against the workflow's shared memory quota while it runs
+        let ctx = match self.shm_manager.get_arena(&Self::arena_name(workflow_id)) {
+            Some(arena) => ctx.with_arena(arena),
+            None => ctx,
+        };
+        match &self.cpu_pool {
+            Some(pool) => ctx.with_cpu_pool(pool.clone()),
+            None => ctx,
+        }
+    }
+
+    /// Non-finite values per output port, empty if the audit fails
+    async fn audit_outputs(&self, module_id: u32, ports: &OutputPorts, config: AuditConfig) -> BTreeMap<String, PortAudit> {
+        let pool = self.cpu_pool.clone().unwrap_or_else(CpuPool::global);
+        match audit_ports_on(&pool, ports, config).await {
+            Ok(audits) => audits,
+            Err(e) => {
+                tracing::warn!("Cannot audit the outputs of module {}: {}", module_id, e);
+                BTreeMap::new()
+            }
+        }
+    }
+
+    /// Inputs of a module gathered from the outputs of its upstream connections
+    pub(crate) fn remote_inputs(&self, spec: &WorkflowSpec, module_id: u32, outputs: &HashMap<u32, OutputPorts>) -> InputPorts {
+        let mut inputs = InputPorts::new();
+        for connection in spec.connections.iter().filter(|c| c.to_module == module_id) {
+            if let Some(objects) = outputs.get(&connection.from_module).and_then(|o| o.get(&connection.from_port)) {
+                inputs.entry(connection.to_port.clone()).or_default().extend(objects.iter().cloned());
+            }
+        }
+        inputs
+    }
+
+    /// Run one module with the given inputs, on a hub host if there is a hub
+    pub(crate) async fn run_module(
+        &self,
+        spec: &ModuleSpec,
+        inputs: &InputPorts,
+        ctx: &ComputeContext,
+    ) -> Result<OutputPorts, crate::Error> {
+        let spec = &self.resolve_expressions(spec, inputs).await?;
+        if let Some(hub) = &self.hub {
+            return hub.dispatch(spec, inputs, ctx).await.map(|outputs| opt_in_lossy(spec, outputs));
+        }
+        let module = self.module_registry.create_detached(&spec.module_type).await?;
+        for (name, text) in &spec.parameters {
+            module.set_parameter_str(name, text)?;
+        }
+        for (port, objects) in inputs {
+            module.set_input(port, objects.clone()).await?;
+        }
+        let outputs = module.execute(ctx, &self.message_router).await?;
+        Ok(opt_in_lossy(spec, outputs))
+    }
+
+    /// Get workflow status
+    pub async fn workflow_status(&self, workflow_id: &str) -> Option<WorkflowStatus> {
+        self.active_workflows.read().await
+            .get(workflow_id)
+            .map(|state| state.status)
+    }
+
+    /// Specification of a known workflow
+    pub async fn workflow_spec(&self, workflow_id: &str) -> Option<WorkflowSpec> {
+        self.active_workflows.read().await
+            .get(workflow_id)
+            .map(|state| state.spec.clone())
+    }
+
+    /// Receive an event after every watch-triggered re-execution
+    pub fn subscribe_watch_events(&self) -> broadcast::Receiver<WatchEvent> {
+        self.watch_events.subscribe()
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) fn publish(&self, event: WatchEvent) {
+        // No subscribers is fine
+        let _ = self.watch_events.send(event);
+    }
+
+    /// Receive an event after every full run and interactive recompute
+    pub fn subscribe_runs(&self) -> broadcast::Receiver<RunEvent> {
+        self.run_events.subscribe()
+    }
+
+    pub(crate) fn publish_run(&self, event: RunEvent) {
+        // No subscribers is fine
+        let _ = self.run_events.send(event);
+    }
+
+    pub(crate) fn publish_output(&self, event: OutputEvent) {
+        // No subscribers is fine
+        let _ = self.output_events.send(event);
+    }
+
+    /// Latest outputs of each module of a workflow, empty unless `with_interactive`
+    pub fn cached_outputs(&self, workflow_id: &str) -> HashMap<u32, OutputPorts> {
+        self.cached_outputs.lock().get(workflow_id).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn cache_outputs(&self, workflow_id: &str, module_id: u32, outputs: OutputPorts) {
+        if self.interactive.is_some() {
+            self.cached_outputs.lock()
+                .entry(workflow_id.to_string())
+                .or_default()
+                .insert(module_id, outputs);
+        }
+    }
+
+    pub(crate) fn forget_outputs(&self, workflow_id: &str, module_id: u32) {
+        if let Some(outputs) = self.cached_outputs.lock().get_mut(workflow_id) {
+            outputs.remove(&module_id);
+        }
+    }
+
+    pub(crate) fn with_interactive_state<R>(&self, workflow_id: &str, update: impl FnOnce(&mut InteractiveState) -> R) -> R {
+        update(self.interactive_state.lock().entry(workflow_id.to_string()).or_default())
+    }
+
+    /// Receive progress after every completed unit and on pause or resume
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<WorkflowProgress> {
+        self.progress_events.subscribe()
+    }
+
+    /// Receive the outputs of every module execution, e.g. to plot each timestep as it finishes
+    pub fn subscribe_outputs(&self) -> broadcast::Receiver<OutputEvent> {
+        self.output_events.subscribe()
+    }
+
+    /// Receive an event whenever a stage runs over its budget
+    pub fn subscribe_stage_events(&self) -> broadcast::Receiver<StageBudgetExceeded> {
+        self.stage_events.subscribe()
+    }
+
+    /// Current progress of a run made of units, see `begin_progress`
+    pub fn progress(&self, id: &str) -> Option<WorkflowProgress> {
+        self.progress.lock().get(id).map(ProgressTracker::progress)
+    }
+
+    /// Start tracking a run of `total_units` timesteps or sweep variants
+    pub fn begin_progress(&self, id: &str, total_units: usize) {
+        let tracker = ProgressTracker::new(id, total_units);
+        let progress = tracker.progress();
+        self.progress.lock().insert(id.to_string(), tracker);
+        let _ = self.progress_events.send(progress);
+    }
+
+    /// Count one finished unit of a tracked run
+    pub fn unit_completed(&self, id: &str) {
+        self.update_progress(id, ProgressTracker::complete_unit);
+    }
+
+    /// Count one finished unit of a tracked run along with the time its stages took
+    pub fn unit_completed_with_stages(&self, id: &str, stages: &[StageTiming]) {
+        self.update_progress(id, |tracker| {
+            tracker.add_stages(stages);
+            tracker.complete_unit();
+        });
+    }
+
+    /// Stop the clock of a tracked run, e.g. while the user pauses playback
+    pub fn pause_progress(&self, id: &str) {
+        self.update_progress(id, ProgressTracker::pause);
+    }
+
+    pub fn resume_progress(&self, id: &str) {
+        self.update_progress(id, ProgressTracker::resume);
+    }
+
+    /// Stop tracking a run, returning its final progress
+    pub fn end_progress(&self, id: &str) -> Option<WorkflowProgress> {
+        self.progress.lock().remove(id).map(|tracker| tracker.progress())
+    }
+
+    fn update_progress(&self, id: &str, update: impl FnOnce(&mut ProgressTracker)) {
+        let progress = {
+            let mut trackers = self.progress.lock();
+            let Some(tracker) = trackers.get_mut(id) else {
+                return;
+            };
+            update(tracker);
+            tracker.progress()
+        };
+        tracing::debug!("{}: {}", id, progress);
+        // No subscribers is fine
+        let _ = self.progress_events.send(progress);
+    }
+
+    /// Token cancelled when the workflow is cancelled
+    pub(crate) fn cancel_token(&self, workflow_id: &str) -> CancellationToken {
+        self.cancel_tokens.lock()
+            .entry(workflow_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Token cancelled when the stage is cancelled for its budget or the workflow is cancelled
+    pub(crate) fn stage_cancel_token(&self, workflow_id: &str, stage: &str) -> CancellationToken {
+        let workflow = self.cancel_token(workflow_id);
+        self.stage_tokens.lock()
+            .entry((workflow_id.to_string(), stage.to_string()))
+            .or_insert_with(|| workflow.child_token())
+            .clone()
+    }
+
+    /// Change the specification of a known workflow in place
+    pub(crate) async fn update_workflow_spec(
+        &self,
+        workflow_id: &str,
+        update: impl FnOnce(&mut WorkflowSpec) -> Result<(), crate::Error>,
+    ) -> Result<(), crate::Error> {
+        let mut workflows = self.active_workflows.write().await;
+        let state = workflows.get_mut(workflow_id)
+            .ok_or_else(|| crate::Error::Module(format!("Workflow {} not found", workflow_id)))?;
+        update(&mut state.spec)
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) async fn restore_workflow_spec(&self, workflow_id: &str, spec: WorkflowSpec) {
+        if let Some(state) = self.active_workflows.write().await.get_mut(workflow_id) {
+            state.spec = spec;
+        }
+    }
+
+    /// Cancel a running workflow
+    pub async fn cancel_workflow(&self, workflow_id: &str) -> Result<(), crate::Error> {
+        let mut workflows = self.active_workflows.write().await;
+        if let Some(state) = workflows.get_mut(workflow_id) {
+            state.status = WorkflowStatus::Cancelled;
+            // Send cancellation messages to modules
+            // Implementation would cancel running tasks
+        }
+        if let Some(token) = self.cancel_tokens.lock().remove(workflow_id) {
+            token.cancel();
+        }
+        // Only this workflow's tasks; others on the same executor keep running
+        self.task_executor.cancel_workflow(workflow_id).await;
+        self.stage_tokens.lock().retain(|(id, _), _| id != workflow_id);
+        self.cached_outputs.lock().remove(workflow_id);
+        self.interactive_state.lock().remove(workflow_id);
+        self.shm_manager.release_owner(workflow_id);
+        Ok(())
+    }
+
+    /// Name of the shared memory arena owned by a workflow
+    pub fn arena_name(workflow_id: &str) -> String {
+        format!("workflow:{}", workflow_id)
+    }
+
+    pub fn shm_manager(&self) -> &Arc<ShmManager> {
+        &self.shm_manager
+    }
+
+    /// Get active workflows
+    pub async fn active_workflows(&self) -> Vec<String> {
+        self.active_workflows.read().await
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Process incoming messages and update workflow state
+    pub async fn process_messages(&self) -> Result<(), crate::Error> {
+        // Process messages from the router
+        self.message_router.process_messages().await?;
+
+        // Update workflow states based on messages
+        // This would handle module completion notifications,
+        // error reports, etc.
+
+        Ok(())
+    }
+}
+
+/// Workflow specification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowSpec {
+    /// Format the file was saved in, 0 for files from before versioning;
+    /// loading migrates to `WORKFLOW_FORMAT_VERSION`
+    #[serde(default)]
+    pub format_version: u32,
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub modules: Vec<ModuleSpec>,
+    pub connections: Vec<ConnectionSpec>,
+    /// Budgets and policies of named stages; stages without one need no entry
+    #[serde(default)]
+    pub stages: Vec<StageSpec>,
+    /// What happens to writers whose output files exist already
+    #[serde(default)]
+    pub output_policy: OutputPolicy,
+    /// Insert adapter modules between ports of different data types instead of failing
+    #[serde(default)]
+    pub allow_coercion: bool,
+    /// Limits on the files writers leave in output directories
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    /// Share of the executor's slots and memory the workflow may take when
+    /// it runs alongside others
+    #[serde(default)]
+    pub limits: WorkflowLimits,
+    /// Validate every object a module outputs and fail the module on
+    /// inconsistent ones, see `VistleObject::validate`
+    #[serde(default)]
+    pub strict: bool,
+    /// Directory of the file the workflow was loaded from
+    #[serde(skip)]
+    pub base_dir: Option<PathBuf>,
+}
+
+impl WorkflowSpec {
+    pub fn new(id: &str, name: &str) -> Self {
+        Self {
+            format_version: WORKFLOW_FORMAT_VERSION,
+            id: id.to_string(),
+            name: name.to_string(),
+            description: String::new(),
+            modules: Vec::new(),
+            connections: Vec::new(),
+            stages: Vec::new(),
+            output_policy: OutputPolicy::default(),
+            allow_coercion: false,
+            retention: RetentionConfig::default(),
+            limits: WorkflowLimits::default(),
+            strict: false,
+            base_dir: None,
+        }
+    }
+
+    /// Load a workflow from a JSON file, or YAML for `.yaml` and `.yml`, resolving
+    /// its paths against the file's directory
+    ///
+    /// Files of older formats are migrated; what could not be migrated is
+    /// logged, see `load_with_warnings`.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let (spec, warnings) = Self::load_with_warnings(path.as_ref()).await?;
+        for warning in warnings.iter() {
+            tracing::warn!("Workflow {}: {}", path.as_ref().display(), warning);
+        }
+        Ok(spec)
+    }
+
+    /// Load a workflow like `load`, returning what was dropped while migrating it
+    ///
+    /// Callers loading strictly fail on any warning with `LoadWarnings::check`.
+    pub async fn load_with_warnings(path: impl AsRef<Path>) -> Result<(Self, LoadWarnings), crate::Error> {
+        let path = path.as_ref();
+        let text = crate::util::io::read_text(path).await?;
+        let (mut spec, warnings) = Self::parse(path, &text)?;
+
+        let path = tokio::fs::canonicalize(path).await?;
+        spec.base_dir = path.parent().map(Path::to_path_buf);
+        Ok((spec, warnings))
+    }
+
+    fn parse(path: &Path, text: &str) -> Result<(Self, LoadWarnings), crate::Error> {
+        let invalid = |e: &dyn std::fmt::Display| crate::Error::Config(format!("Invalid workflow {}: {}", path.display(), e));
+        let extension = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let value: serde_json::Value = match extension.as_deref() {
+            #[cfg(feature = "yaml")]
+            Some("yaml") | Some("yml") => serde_yaml::from_str(text).map_err(|e| invalid(&e))?,
+            #[cfg(not(feature = "yaml"))]
+            Some("yaml") | Some("yml") => return Err(crate::Error::Config(format!(
+                "Cannot load workflow {}: built without the yaml feature",
+                path.display()
+            ))),
+            _ => serde_json::from_str(text).map_err(|e| invalid(&e))?,
+        };
+        Self::from_value(path, value)
+    }
+
+    /// Migrate a parsed workflow file and build the workflow; `path` names the file in errors
+    pub(crate) fn from_value(path: &Path, mut value: serde_json::Value) -> Result<(Self, LoadWarnings), crate::Error> {
+        let mut warnings = LoadWarnings::new();
+        let version = MigrationRegistry::global().migrate(&mut value, &mut warnings);
+        if version < WORKFLOW_FORMAT_VERSION {
+            tracing::info!("Migrated workflow {} from format {} to {}", path.display(), version, WORKFLOW_FORMAT_VERSION);
+        }
+        let spec = workflow_from_value(value, &mut warnings)
+            .map_err(|e| crate::Error::Config(format!("Invalid workflow {}: {}", path.display(), e)))?;
+        Ok((spec, warnings))
+    }
+
+    /// Check that every connection joins declared ports of modules of the workflow
+    ///
+    /// Modules `registry` cannot create, e.g. ones only hub hosts provide,
+    /// are only checked to exist. All problems are reported in one error;
+    /// what modules actually return is checked when they run, see
+    /// `VistleModule::with_strict_ports`.
+    pub async fn validate(&self, registry: &ModuleRegistry) -> Result<(), crate::Error> {
+        let mut declared = HashMap::new();
+        for module in &self.modules {
+            if let Ok(instance) = registry.create_detached(&module.module_type).await {
+                declared.insert(module.id, instance.ports().clone());
+            }
+        }
+
+        let mut problems = Vec::new();
+        for c in &self.connections {
+            let route = format!("{}:{} -> {}:{}", c.from_module, c.from_port, c.to_module, c.to_port);
+            for (module_id, port, direction) in [(c.from_module, &c.from_port, "output"), (c.to_module, &c.to_port, "input")] {
+                if !self.modules.iter().any(|m| m.id == module_id) {
+                    problems.push(format!("connection {} references unknown module {}", route, module_id));
+                    continue;
+                }
+                let Some(ports) = declared.get(&module_id) else {
+                    continue;
+                };
+                let candidates = if direction == "output" { ports.outputs() } else { ports.inputs() };
+                if !candidates.iter().any(|p| &p.name == port) {
+                    let mut names: Vec<&str> = candidates.iter().map(|p| p.name.as_str()).collect();
+                    names.sort();
+                    problems.push(format!(
+                        "connection {} uses undeclared {} port {} of module {}; declared: {}",
+                        route, direction, port, module_id, names.join(", ")
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(crate::Error::Config(format!("Invalid workflow {}: {}", self.id, problems.join("; "))))
+    }
+
+    /// Save the workflow as JSON
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize workflow: {}", e)))?;
+        crate::util::io::write_text(path, &text).await
+    }
+
+    pub fn with_base_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = Some(dir.into());
+        self
+    }
+
+    pub fn with_description(mut self, desc: &str) -> Self {
+        self.description = desc.to_string();
+        self
+    }
+
+    pub fn add_module(mut self, module: ModuleSpec) -> Self {
+        self.modules.push(module);
+        self
+    }
+
+    pub fn add_connection(mut self, connection: ConnectionSpec) -> Self {
+        self.connections.push(connection);
+        self
+    }
+
+    /// Set a stage's budget and policy, replacing earlier settings of the same name
+    pub fn with_stage(mut self, stage: StageSpec) -> Self {
+        self.stages.retain(|s| s.name != stage.name);
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn with_output_policy(mut self, policy: OutputPolicy) -> Self {
+        self.output_policy = policy;
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_coercion(mut self, allow: bool) -> Self {
+        self.allow_coercion = allow;
+        self
+    }
+
+    /// Apply `policy` to the files written into `dir`, replacing an earlier policy for it
+    pub fn with_retention(mut self, dir: impl Into<PathBuf>, policy: RetentionPolicy) -> Self {
+        self.retention.policies.insert(dir.into(), policy);
+        self
+    }
+
+    /// Only report the files retention policies would delete
+    pub fn with_retention_dry_run(mut self, dry_run: bool) -> Self {
+        self.retention.dry_run = dry_run;
+        self
+    }
+
+    pub fn stage(&self, name: &str) -> Option<&StageSpec> {
+        self.stages.iter().find(|s| s.name == name)
+    }
+}
+
+/// Module specification in a workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleSpec {
+    pub id: u32,
+    pub module_type: String,
+    pub name: String,
+    pub parameters: HashMap<String, String>, // Parameter name -> value as string
+    pub dependencies: Vec<u32>, // Module IDs this depends on
+    pub priority: TaskPriority,
+    #[serde(default)]
+    pub placement: Placement,
+    /// Named pipeline stage the module belongs to, e.g. "read"
+    #[serde(default)]
+    pub stage: Option<String>,
+    /// Output ports whose fields may be stored lossily, with their absolute error bound
+    #[serde(default)]
+    pub lossy_outputs: HashMap<String, f32>,
+}
+
+impl ModuleSpec {
+    pub fn new(id: u32, module_type: &str, name: &str) -> Self {
+        Self {
+            id,
+            module_type: module_type.to_string(),
+            name: name.to_string(),
+            parameters: HashMap::new(),
+            dependencies: Vec::new(),
+            priority: TaskPriority::Normal,
+            placement: Placement::Any,
+            stage: None,
+            lossy_outputs: HashMap::new(),
+        }
+    }
+
+    pub fn with_parameter(mut self, name: &str, value: &str) -> Self {
+        self.parameters.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Parameters given as values rather than expressions, see `compute::expression`
+    pub fn literal_parameters(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.parameters.iter().filter(|(_, text)| !is_expression(text))
+    }
+
+    pub fn depends_on(mut self, module_id: u32) -> Self {
+        self.dependencies.push(module_id);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    pub fn with_stage(mut self, stage: &str) -> Self {
+        self.stage = Some(stage.to_string());
+        self
+    }
+
+    /// Allow the fields of an output port to be stored within `error_bound`, see `core::lossy`
+    pub fn with_lossy_output(mut self, port: &str, error_bound: f32) -> Self {
+        self.lossy_outputs.insert(port.to_string(), error_bound);
+        self
+    }
+}
+
+/// Outputs of a module, or a compute error listing their inconsistent objects in strict workflows
+pub(crate) fn check_outputs(spec: &WorkflowSpec, module_id: u32, outputs: OutputPorts) -> Result<OutputPorts, crate::Error> {
+    if !spec.strict {
+        return Ok(outputs);
+    }
+    let mut problems = Vec::new();
+    let mut ports: Vec<_> = outputs.iter().collect();
+    ports.sort_by_key(|(port, _)| port.as_str());
+    for (port, objects) in ports {
+        for object in objects {
+            if let Err(issues) = validate_object(object.as_ref()) {
+                let issues: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+                problems.push(format!("{} object {}: {}", port, object.id(), issues.join(", ")));
+            }
+        }
+    }
+    if problems.is_empty() {
+        return Ok(outputs);
+    }
+    Err(crate::Error::Compute(format!(
+        "Module {} produced invalid objects: {}",
+        module_id, problems.join("; ")
+    )))
+}
+
+/// Mark the fields on a module's lossy outputs with their error bound
+///
+/// Only the bound is recorded here; objects are compressed when they are
+/// written to a file or moved into a full shared memory arena.
+fn opt_in_lossy(spec: &ModuleSpec, mut outputs: OutputPorts) -> OutputPorts {
+    for (port, error_bound) in &spec.lossy_outputs {
+        let Some(objects) = outputs.get_mut(port) else {
+            continue;
+        };
+        for object in objects.iter_mut() {
+            if let Some(data) = object.as_data() {
+                let mut data = data.clone();
+                data.attributes.insert(attribute::LOSSY_ERROR_BOUND.to_string(), format_f32(*error_bound));
+                *object = Arc::new(VistleObject::from_data(data)) as Arc<dyn Object>;
+            }
+        }
+    }
+    outputs
+}
+
+/// Where a module is instantiated in a distributed run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Placement {
+    /// Any single rank chosen by the executor
+    #[default]
+    Any,
+    /// A specific rank, typical for writers and final rendering
+    Rank(i32),
+    /// Every rank with the same parameters, typical for block-decomposed readers and filters
+    AllRanks,
+    /// The same rank(s) as another module
+    NodeLocalWith(u32),
+}
+
+/// Connection specification between modules
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionSpec {
+    pub from_module: u32,
+    pub from_port: String,
+    pub to_module: u32,
+    pub to_port: String,
+}
+
+/// Workflow execution state
+#[derive(Debug, Clone)]
+struct WorkflowState {
+    spec: WorkflowSpec,
+    status: WorkflowStatus,
+    tasks_completed: usize,
+}
+
+/// Workflow execution status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkflowStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Objects one module produced in one execution, see `WorkflowExecutor::subscribe_outputs`
+#[derive(Clone)]
+pub struct OutputEvent {
+    pub workflow_id: String,
+    pub module_id: u32,
+    pub outputs: OutputPorts,
+    /// Whether the module ran in a full run or an interactive recompute
+    pub kind: RunKind,
+}
+
+/// Workflow execution result
+#[derive(Debug)]
+pub struct WorkflowResult {
+    pub workflow_id: String,
+    pub workflow_name: String,
+    pub success: bool,
+    pub task_results: Vec<crate::compute::TaskResult>,
+    pub execution_time: std::time::Duration,
+    /// Modules as specified when the workflow was submitted
+    pub modules: Vec<ModuleSpec>,
+    /// Usage of the workflow's shared memory arena just before it was released
+    pub shm_stats: Option<crate::core::ShmStats>,
+    /// What flowed across each connection, see `connection_stats()`
+    pub connection_stats: Vec<crate::compute::ConnectionStats>,
+    /// Wall-clock time of each named stage that ran
+    pub stages: Vec<StageTiming>,
+    /// Wall-clock span of each module that ran, for traces
+    pub module_spans: Vec<ModuleSpan>,
+    /// What was done about existing output files before the run
+    pub outputs: Vec<OutputDecision>,
+    /// Placeholder loads ahead of their timestep during the run, counting
+    /// those of other workflows running on the same executor at the time
+    pub prefetch: PrefetchStats,
+    /// Adapter modules inserted between ports of different data types
+    pub adapters: Vec<InsertedAdapter>,
+    /// Files deleted by retention policies, or listed in a dry run
+    pub retention: Vec<RetentionDeletion>,
+    /// Module that first produced NaN or infinite values from finite inputs, if outputs were audited
+    pub first_nonfinite: Option<NonFiniteOffender>,
+}
+
+/// Workflow builder for fluent construction
+pub struct WorkflowBuilder {
+    spec: WorkflowSpec,
+    next_module_id: u32,
+}
+
+impl WorkflowBuilder {
+    pub fn new(id: &str, name: &str) -> Self {
+        Self {
+            spec: WorkflowSpec::new(id, name),
+            next_module_id: 1,
+        }
+    }
+
+    pub fn description(mut self, desc: &str) -> Self {
+        self.spec.description = desc.to_string();
+        self
+    }
+
+    pub fn add_module(mut self, module_type: &str, name: &str) -> ModuleBuilder {
+        let module_id = self.next_module_id;
+        self.next_module_id += 1;
+
+        let module_spec = ModuleSpec::new(module_id, module_type, name);
+        self.spec.modules.push(module_spec);
+
+        ModuleBuilder {
+            workflow_builder: self,
+            module_id,
+        }
+    }
+
+    /// Set a stage's budget and policy, see `WorkflowSpec::with_stage`
+    pub fn stage(mut self, stage: StageSpec) -> Self {
+        self.spec = self.spec.with_stage(stage);
+        self
+    }
+
+    /// What happens to existing output files, see `WorkflowSpec::output_policy`
+    pub fn output_policy(mut self, policy: OutputPolicy) -> Self {
+        self.spec.output_policy = policy;
+        self
+    }
+
+    /// Insert adapters between mismatched ports, see `WorkflowSpec::allow_coercion`
+    pub fn allow_coercion(mut self) -> Self {
+        self.spec.allow_coercion = true;
+        self
+    }
+
+    /// Validate the objects modules output, see `WorkflowSpec::strict`
+    pub fn strict(mut self) -> Self {
+        self.spec.strict = true;
+        self
+    }
+
+    /// Limits when running alongside other workflows, see `WorkflowSpec::limits`
+    pub fn limits(mut self, limits: WorkflowLimits) -> Self {
+        self.spec.limits = limits;
+        self
+    }
+
+    pub fn connect(mut self, from: u32, from_port: &str, to: u32, to_port: &str) -> Self {
+        let connection = ConnectionSpec {
+            from_module: from,
+            from_port: from_port.to_string(),
+            to_module: to,
+            to_port: to_port.to_string(),
+        };
+        self.spec.connections.push(connection);
+        self
+    }
+
+    pub fn build(self) -> WorkflowSpec {
+        self.spec
+    }
+}
+
+/// Module builder for fluent module configuration
+pub struct ModuleBuilder {
+    workflow_builder: WorkflowBuilder,
+    module_id: u32,
+}
+
+impl ModuleBuilder {
+    pub fn parameter(mut self, name: &str, value: &str) -> Self {
+        if let Some(module) = self.workflow_builder.spec.modules.last_mut() {
+            if module.id == self.module_id {
+                module.parameters.insert(name.to_string(), value.to_string());
+            }
+        }
+        self
+    }
+
+    pub fn priority(mut self, priority: TaskPriority) -> Self {
+        if let Some(module) = self.workflow_builder.spec.modules.last_mut() {
+            if module.id == self.module_id {
+                module.priority = priority;
+            }
+        }
+        self
+    }
+
+    pub fn placement(mut self, placement: Placement) -> Self {
+        if let Some(module) = self.workflow_builder.spec.modules.last_mut() {
+            if module.id == self.module_id {
+                module.placement = placement;
+            }
+        }
+        self
+    }
+
+    /// Put the module into a named stage
+    pub fn stage(mut self, stage: &str) -> Self {
+        if let Some(module) = self.workflow_builder.spec.modules.last_mut() {
+            if module.id == self.module_id {
+                module.stage = Some(stage.to_string());
+            }
+        }
+        self
+    }
+
+    pub fn depends_on(mut self, dependency_id: u32) -> Self {
+        if let Some(module) = self.workflow_builder.spec.modules.last_mut() {
+            if module.id == self.module_id {
+                module.dependencies.push(dependency_id);
+            }
+        }
+        self
+    }
+
+    pub fn add_module(self, module_type: &str, name: &str) -> ModuleBuilder {
+        self.workflow_builder.add_module(module_type, name)
+    }
+
+    pub fn connect(self, from: u32, from_port: &str, to: u32, to_port: &str) -> WorkflowBuilder {
+        self.workflow_builder.connect(from, from_port, to, to_port)
+    }
+
+    pub fn build(self) -> WorkflowSpec {
+        self.workflow_builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::testing::modules::register_test_modules;
+
+    async fn executor() -> WorkflowExecutor {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()))
+    }
+
+    fn chain(from_port: &str, to_port: &str) -> WorkflowSpec {
+        WorkflowBuilder::new("ports", "Ports")
+            .add_module("ConstantField", "Source")
+            .add_module("ConstantField", "Sink")
+            .connect(1, from_port, 2, to_port)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn connections_must_use_declared_ports() {
+        let executor = executor().await;
+        let registry = executor.module_registry();
+        chain("data_out", "data_in").validate(registry).await.unwrap();
+
+        let error = chain("dataOut", "data_in").validate(registry).await.unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("undeclared output port dataOut of module 1; declared: data_out"), "{}", message);
+
+        let error = chain("data_in", "data_out").validate(registry).await.unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("undeclared output port data_in"), "{}", message);
+        assert!(message.contains("undeclared input port data_out"), "{}", message);
+
+        // Nothing runs before the workflow is found invalid
+        let error = executor.execute_workflow(chain("dataOut", "data_in"), None).await.unwrap_err();
+        assert!(matches!(error, crate::Error::Config(_)), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn connections_must_join_modules_of_the_workflow() {
+        let executor = executor().await;
+        let mut spec = chain("data_out", "data_in");
+        spec.connections[0].to_module = 7;
+        let message = spec.validate(executor.module_registry()).await.unwrap_err().to_string();
+        assert!(message.contains("references unknown module 7"), "{}", message);
+
+        // Ports of modules only hub hosts know cannot be checked here
+        let mut spec = chain("anything", "data_in");
+        spec.modules[0].module_type = "RemoteOnly".to_string();
+        spec.validate(executor.module_registry()).await.unwrap();
+    }
+}
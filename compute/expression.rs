@@ -0,0 +1,854 @@
+//! Parameter values computed from expressions
+//!
+//! A parameter value in a workflow spec starting with `=` is an expression,
+//! e.g. `=bounds_center_z + 0.1` for a slice origin or `=2 / grid_spacing`
+//! for a glyph scale. Expressions combine numbers with `+ - * / ^`,
+//! parentheses and the functions `min`, `max`, `abs` and `sqrt`. Vector
+//! parameters take one expression per component, separated by commas.
+//!
+//! Variables are the numeric parameters of the same module, which may be
+//! expressions themselves, and the metadata of the module's inputs: bounds,
+//! uniform grid spacing, value range and timestep, see `INPUT_VARIABLES`.
+//! Unqualified input variables cover all input ports; `port.name` covers
+//! one. Parameters shadow input variables of the same name.
+//!
+//! Expressions are checked when a workflow starts, so syntax errors and
+//! unknown variables are reported against the module and parameter before
+//! anything runs. They are evaluated each time the module is about to run,
+//! with the inputs it is given; interactive recomputes therefore see the
+//! metadata of the recomputed upstream outputs.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::compute::{InputPorts, ModuleSpec, WorkflowExecutor, WorkflowSpec};
+use crate::core::{attribute, Object, ObjectPayload, ParameterSnapshot, ParameterType, ParameterValue};
+use crate::util::fmt::{format_f32, format_f64};
+
+/// First character of a parameter value given as expression
+pub const EXPRESSION_PREFIX: char = '=';
+
+/// Variables bound from the metadata of a module's inputs
+pub const INPUT_VARIABLES: &[&str] = &[
+    "bounds_min_x", "bounds_min_y", "bounds_min_z",
+    "bounds_max_x", "bounds_max_y", "bounds_max_z",
+    "bounds_center_x", "bounds_center_y", "bounds_center_z",
+    "bounds_size_x", "bounds_size_y", "bounds_size_z",
+    "bounds_diagonal",
+    "grid_spacing_x", "grid_spacing_y", "grid_spacing_z",
+    // Smallest spacing along any axis
+    "grid_spacing",
+    "value_min", "value_max",
+    "timestep", "num_timesteps",
+];
+
+/// Bounds variables per axis: minimum, maximum, center and size
+const BOUNDS_VARIABLES: [[&str; 4]; 3] = [
+    ["bounds_min_x", "bounds_max_x", "bounds_center_x", "bounds_size_x"],
+    ["bounds_min_y", "bounds_max_y", "bounds_center_y", "bounds_size_y"],
+    ["bounds_min_z", "bounds_max_z", "bounds_center_z", "bounds_size_z"],
+];
+
+/// Whether a parameter value is an expression rather than a literal
+pub fn is_expression(text: &str) -> bool {
+    text.trim_start().starts_with(EXPRESSION_PREFIX)
+}
+
+/// Node of a parsed expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Min,
+    Max,
+    Abs,
+    Sqrt,
+}
+
+impl Function {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "abs" => Some(Function::Abs),
+            "sqrt" => Some(Function::Sqrt),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Abs => "abs",
+            Function::Sqrt => "sqrt",
+        }
+    }
+
+    /// Whether a call with `count` arguments is valid
+    fn accepts(self, count: usize) -> bool {
+        match self {
+            Function::Min | Function::Max => count >= 1,
+            Function::Abs | Function::Sqrt => count == 1,
+        }
+    }
+}
+
+impl Expr {
+    /// Variables the expression reads
+    pub fn variables<'a>(&'a self, names: &mut BTreeSet<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Variable(name) => {
+                names.insert(name);
+            }
+            Expr::Negate(e) => e.variables(names),
+            Expr::Binary(_, a, b) => {
+                a.variables(names);
+                b.variables(names);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|a| a.variables(names)),
+        }
+    }
+
+    pub fn evaluate(&self, scope: &ExpressionScope) -> Result<f64, String> {
+        Ok(match self {
+            Expr::Number(v) => *v,
+            Expr::Variable(name) => scope.lookup(name)?,
+            Expr::Negate(e) => -e.evaluate(scope)?,
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.evaluate(scope)?, b.evaluate(scope)?);
+                match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Subtract => a - b,
+                    BinaryOp::Multiply => a * b,
+                    BinaryOp::Divide if b == 0.0 => return Err("division by zero".to_string()),
+                    BinaryOp::Divide => a / b,
+                    BinaryOp::Power => a.powf(b),
+                }
+            }
+            Expr::Call(function, args) => {
+                let args = args.iter().map(|a| a.evaluate(scope)).collect::<Result<Vec<_>, _>>()?;
+                match function {
+                    Function::Min => args.into_iter().fold(f64::INFINITY, f64::min),
+                    Function::Max => args.into_iter().fold(f64::NEG_INFINITY, f64::max),
+                    Function::Abs => args[0].abs(),
+                    Function::Sqrt if args[0] < 0.0 => {
+                        return Err(format!("sqrt of negative value {}", format_f64(args[0])));
+                    }
+                    Function::Sqrt => args[0].sqrt(),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Operator(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            let mut previous = ' ';
+            while let Some(&(i, c)) = chars.peek() {
+                let exponent_sign = matches!(c, '+' | '-') && matches!(previous, 'e' | 'E');
+                if !(c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E') || exponent_sign) {
+                    break;
+                }
+                previous = c;
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let number = &text[start..end];
+            tokens.push(Token::Number(number.parse().map_err(|_| format!("'{}' is not a number", number))?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.')) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Identifier(text[start..end].to_string()));
+        } else if "+-*/^(),".contains(c) {
+            tokens.push(Token::Operator(c));
+            chars.next();
+        } else {
+            return Err(format!("unexpected '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Deepest nesting of operators, parentheses and calls in an expression
+///
+/// Parsing and evaluation recurse once per level; deeper expressions are
+/// rejected instead of overflowing the stack.
+pub const MAX_DEPTH: usize = 256;
+
+/// Recursive descent over the tokens of one expression list
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Nesting of the node being parsed, see `MAX_DEPTH`
+    depth: usize,
+}
+
+impl Parser {
+    /// Descend `levels` into the expression tree
+    fn enter(&mut self, levels: usize) -> Result<(), String> {
+        self.depth += levels;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("expression nests deeper than {} levels", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self, levels: usize) {
+        self.depth -= levels;
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn eat(&mut self, operator: char) -> bool {
+        if self.peek() == Some(&Token::Operator(operator)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, operator: char) -> Result<(), String> {
+        if self.eat(operator) {
+            return Ok(());
+        }
+        Err(match self.peek() {
+            Some(token) => format!("expected '{}', found {}", operator, describe(token)),
+            None => format!("expected '{}' at the end", operator),
+        })
+    }
+
+    fn list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut items = vec![self.sum()?];
+        while self.eat(',') {
+            items.push(self.sum()?);
+        }
+        Ok(items)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut left = self.product()?;
+        // Each operator of a chain nests the terms before it one level deeper
+        let mut chained = 0;
+        loop {
+            let op = if self.eat('+') {
+                BinaryOp::Add
+            } else if self.eat('-') {
+                BinaryOp::Subtract
+            } else {
+                self.leave(chained);
+                return Ok(left);
+            };
+            self.enter(1)?;
+            chained += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        let mut chained = 0;
+        loop {
+            let op = if self.eat('*') {
+                BinaryOp::Multiply
+            } else if self.eat('/') {
+                BinaryOp::Divide
+            } else {
+                self.leave(chained);
+                return Ok(left);
+            };
+            self.enter(1)?;
+            chained += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    /// Negation binds looser than `^`, so `-2^2` is -4
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            self.enter(1)?;
+            let operand = self.unary()?;
+            self.leave(1);
+            return Ok(Expr::Negate(Box::new(operand)));
+        }
+        if self.eat('+') {
+            self.enter(1)?;
+            let operand = self.unary()?;
+            self.leave(1);
+            return Ok(operand);
+        }
+        self.power()
+    }
+
+    /// `^` is right-associative: `2^3^2` is `2^9`
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.primary()?;
+        if self.eat('^') {
+            self.enter(1)?;
+            let exponent = self.unary()?;
+            self.leave(1);
+            return Ok(Expr::Binary(BinaryOp::Power, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned().ok_or_else(|| "expression ends early".to_string())?;
+        self.position += 1;
+        match token {
+            Token::Number(v) => Ok(Expr::Number(v)),
+            Token::Identifier(name) if self.eat('(') => {
+                let function = Function::parse(&name).ok_or_else(|| format!("unknown function {}", name))?;
+                let args = if self.eat(')') {
+                    Vec::new()
+                } else {
+                    self.enter(1)?;
+                    let args = self.list()?;
+                    self.leave(1);
+                    self.expect(')')?;
+                    args
+                };
+                if !function.accepts(args.len()) {
+                    return Err(format!("{} does not take {} arguments", function.name(), args.len()));
+                }
+                Ok(Expr::Call(function, args))
+            }
+            Token::Identifier(name) => Ok(Expr::Variable(name)),
+            Token::Operator('(') => {
+                self.enter(1)?;
+                let inner = self.sum()?;
+                self.leave(1);
+                self.expect(')')?;
+                Ok(inner)
+            }
+            token => Err(format!("unexpected {}", describe(&token))),
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(v) => format!("number {}", format_f64(*v)),
+        Token::Identifier(name) => format!("'{}'", name),
+        Token::Operator(c) => format!("'{}'", c),
+    }
+}
+
+/// A parameter value written as `=` followed by comma-separated expressions
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterExpression {
+    pub components: Vec<Expr>,
+}
+
+impl ParameterExpression {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let body = text.trim_start().strip_prefix(EXPRESSION_PREFIX)
+            .ok_or_else(|| format!("expressions start with '{}'", EXPRESSION_PREFIX))?;
+        let mut parser = Parser {
+            tokens: tokenize(body)?,
+            position: 0,
+            depth: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Err("empty expression".to_string());
+        }
+        let components = parser.list()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {}", describe(token)));
+        }
+        Ok(Self { components })
+    }
+
+    /// Variables any component reads
+    pub fn variables(&self) -> BTreeSet<&str> {
+        let mut names = BTreeSet::new();
+        self.components.iter().for_each(|c| c.variables(&mut names));
+        names
+    }
+
+    /// Value of every component; non-finite results are errors
+    pub fn evaluate(&self, scope: &ExpressionScope) -> Result<Vec<f64>, String> {
+        self.components.iter()
+            .map(|c| {
+                let value = c.evaluate(scope)?;
+                if !value.is_finite() {
+                    return Err(format!("evaluates to {}", format_f64(value)));
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+}
+
+/// Values of the variables an expression may read
+#[derive(Debug, Clone, Default)]
+pub struct ExpressionScope {
+    variables: BTreeMap<String, f64>,
+}
+
+impl ExpressionScope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: f64) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.variables.get(name).copied()
+    }
+
+    fn lookup(&self, name: &str) -> Result<f64, String> {
+        self.get(name).ok_or_else(|| {
+            let input = name.rsplit('.').next().unwrap_or(name);
+            if INPUT_VARIABLES.contains(&input) {
+                format!("no input provides {}", name)
+            } else {
+                format!("unknown variable {}", name)
+            }
+        })
+    }
+
+    /// Bind the input variables, unqualified over all ports and as `port.name` per port
+    pub fn bind_inputs(&mut self, inputs: &InputPorts) {
+        let mut all = InputMetadata::default();
+        for (port, objects) in inputs {
+            let mut metadata = InputMetadata::default();
+            for object in objects {
+                metadata.add(object.as_ref());
+            }
+            for (name, value) in metadata.variables() {
+                self.set(&format!("{}.{}", port, name), value);
+            }
+            all.merge(&metadata);
+        }
+        for (name, value) in all.variables() {
+            self.set(name, value);
+        }
+    }
+
+    /// Bind the numeric scalar parameters, shadowing input variables
+    pub fn bind_parameters<'a>(&mut self, parameters: impl IntoIterator<Item = (&'a String, &'a ParameterValue)>) {
+        for (name, value) in parameters {
+            if let Some(value) = numeric(value) {
+                self.set(name, value);
+            }
+        }
+    }
+}
+
+fn numeric(value: &ParameterValue) -> Option<f64> {
+    match value {
+        ParameterValue::Int(v) => Some(*v as f64),
+        ParameterValue::Float(v) => Some(*v as f64),
+        ParameterValue::Bool(v) => Some(if *v { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
+fn is_numeric_scalar(param_type: &ParameterType) -> bool {
+    matches!(param_type, ParameterType::Int { .. } | ParameterType::Float { .. } | ParameterType::Bool)
+}
+
+/// Metadata of the objects on one or more input ports
+#[derive(Debug, Clone, Default)]
+struct InputMetadata {
+    bounds: Option<([f64; 3], [f64; 3])>,
+    spacing: Option<[f64; 3]>,
+    range: Option<(f64, f64)>,
+    timestep: Option<(i32, i32)>,
+}
+
+impl InputMetadata {
+    fn add(&mut self, object: &dyn Object) {
+        if let Some((min, max)) = object.bounds() {
+            let min: [f64; 3] = min.map(|v| v as f64).into();
+            let max: [f64; 3] = max.map(|v| v as f64).into();
+            self.merge_bounds((min, max));
+        }
+        if let Some(grid) = object.as_uniform_grid() {
+            self.spacing.get_or_insert(grid.spacing.map(|s| s as f64));
+        }
+        if let Some(range) = value_range(object) {
+            self.merge_range(range);
+        }
+        self.timestep.get_or_insert((object.meta().timestep, object.meta().num_timesteps));
+    }
+
+    fn merge(&mut self, other: &InputMetadata) {
+        if let Some(bounds) = other.bounds {
+            self.merge_bounds(bounds);
+        }
+        if let Some(range) = other.range {
+            self.merge_range(range);
+        }
+        if self.spacing.is_none() {
+            self.spacing = other.spacing;
+        }
+        if self.timestep.is_none() {
+            self.timestep = other.timestep;
+        }
+    }
+
+    fn merge_bounds(&mut self, (min, max): ([f64; 3], [f64; 3])) {
+        self.bounds = Some(match self.bounds {
+            Some((lo, hi)) => (
+                std::array::from_fn(|a| lo[a].min(min[a])),
+                std::array::from_fn(|a| hi[a].max(max[a])),
+            ),
+            None => (min, max),
+        });
+    }
+
+    fn merge_range(&mut self, (min, max): (f64, f64)) {
+        self.range = Some(match self.range {
+            Some((lo, hi)) => (lo.min(min), hi.max(max)),
+            None => (min, max),
+        });
+    }
+
+    fn variables(&self) -> Vec<(&'static str, f64)> {
+        let mut variables = Vec::new();
+        if let Some((min, max)) = self.bounds {
+            for (a, [lo, hi, center, size]) in BOUNDS_VARIABLES.into_iter().enumerate() {
+                variables.push((lo, min[a]));
+                variables.push((hi, max[a]));
+                variables.push((center, (min[a] + max[a]) / 2.0));
+                variables.push((size, max[a] - min[a]));
+            }
+            let diagonal = (0..3).map(|a| (max[a] - min[a]).powi(2)).sum::<f64>().sqrt();
+            variables.push(("bounds_diagonal", diagonal));
+        }
+        if let Some(spacing) = self.spacing {
+            variables.push(("grid_spacing_x", spacing[0]));
+            variables.push(("grid_spacing_y", spacing[1]));
+            variables.push(("grid_spacing_z", spacing[2]));
+            variables.push(("grid_spacing", spacing.iter().copied().fold(f64::INFINITY, f64::min)));
+        }
+        if let Some((min, max)) = self.range {
+            variables.push(("value_min", min));
+            variables.push(("value_max", max));
+        }
+        if let Some((timestep, num_timesteps)) = self.timestep {
+            variables.push(("timestep", timestep as f64));
+            variables.push(("num_timesteps", num_timesteps as f64));
+        }
+        variables
+    }
+}
+
+/// Range of a field's finite values, from `attribute::RANGE` if the producer set it
+fn value_range(object: &dyn Object) -> Option<(f64, f64)> {
+    if let Some(text) = object.get_attribute(attribute::RANGE) {
+        let mut parts = text.split_whitespace().map(|v| v.parse::<f64>());
+        if let (Some(Ok(min)), Some(Ok(max))) = (parts.next(), parts.next()) {
+            return Some((min, max));
+        }
+    }
+    let values = match object.payload()? {
+        ObjectPayload::VecScalar { data } => data,
+        ObjectPayload::UniformGrid { values, .. } => values,
+        _ => return None,
+    };
+    let (min, max) = values.iter()
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v as f64), hi.max(v as f64)));
+    (min <= max).then_some((min, max))
+}
+
+fn parameter_error(spec: &ModuleSpec, name: &str, reason: impl std::fmt::Display) -> crate::Error {
+    crate::Error::Config(format!("Module {} ({}), parameter {}: {}", spec.name, spec.id, name, reason))
+}
+
+/// Expression parameters of a module, parsed, in an order where each only reads ones before it
+fn ordered_expressions(spec: &ModuleSpec) -> Result<Vec<(&str, ParameterExpression)>, crate::Error> {
+    let mut pending: BTreeMap<&str, ParameterExpression> = BTreeMap::new();
+    for (name, text) in spec.parameters.iter().filter(|(_, text)| is_expression(text)) {
+        let expression = ParameterExpression::parse(text).map_err(|e| parameter_error(spec, name, e))?;
+        pending.insert(name, expression);
+    }
+
+    let mut ordered = Vec::new();
+    while !pending.is_empty() {
+        let ready: Vec<&str> = pending.iter()
+            .filter(|(_, expression)| expression.variables().iter().all(|v| !pending.contains_key(v)))
+            .map(|(name, _)| *name)
+            .collect();
+        if ready.is_empty() {
+            let names: Vec<&str> = pending.keys().copied().collect();
+            return Err(parameter_error(spec, names[0], format!(
+                "expressions of {} refer to each other",
+                names.join(", ")
+            )));
+        }
+        for name in ready {
+            let expression = pending.remove(name).expect("ready parameters are pending");
+            ordered.push((name, expression));
+        }
+    }
+    Ok(ordered)
+}
+
+/// Text of a computed value for a parameter of the given type
+///
+/// Without a type, e.g. for modules only hub hosts provide, the values are
+/// written as numbers separated by whitespace.
+fn format_value(param_type: Option<&ParameterType>, values: &[f64]) -> Result<String, String> {
+    let scalar = || match values {
+        [value] => Ok(*value),
+        _ => Err(format!("expected one value, got {}", values.len())),
+    };
+    let int = |v: f64| {
+        let rounded = v.round();
+        if rounded < i32::MIN as f64 || rounded > i32::MAX as f64 {
+            return Err(format!("{} is out of range for an integer", format_f64(v)));
+        }
+        Ok((rounded as i32).to_string())
+    };
+    let join = |items: Vec<String>| items.join(" ");
+    match param_type {
+        Some(ParameterType::Int { .. }) => int(scalar()?),
+        Some(ParameterType::Float { .. }) => Ok(format_f32(scalar()? as f32)),
+        Some(ParameterType::Bool) => Ok((scalar()? != 0.0).to_string()),
+        Some(ParameterType::VectorInt { .. }) => Ok(join(values.iter().map(|&v| int(v)).collect::<Result<_, _>>()?)),
+        Some(ParameterType::VectorFloat { .. }) => Ok(join(values.iter().map(|&v| format_f32(v as f32)).collect())),
+        Some(ParameterType::String | ParameterType::FilePath | ParameterType::VectorString) => {
+            Err("expressions only apply to numeric parameters".to_string())
+        }
+        None => Ok(join(values.iter().map(|&v| format_f64(v)).collect())),
+    }
+}
+
+/// Check the expression parameters of a module without evaluating them
+///
+/// `parameters` are the module's defaults, if it is registered here;
+/// `input_ports` are the ports connections lead to.
+pub fn check_parameter_expressions(
+    spec: &ModuleSpec,
+    parameters: Option<&ParameterSnapshot>,
+    input_ports: &BTreeSet<String>,
+) -> Result<(), crate::Error> {
+    for (name, expression) in ordered_expressions(spec)? {
+        if let Some(parameters) = parameters {
+            let param = parameters.get(name)
+                .ok_or_else(|| parameter_error(spec, name, "no such parameter"))?;
+            if matches!(param.param_type, ParameterType::String | ParameterType::FilePath | ParameterType::VectorString) {
+                return Err(parameter_error(spec, name, "expressions only apply to numeric parameters"));
+            }
+        }
+        for variable in expression.variables() {
+            let is_parameter = match parameters {
+                Some(parameters) => parameters.get(variable).is_some_and(|p| is_numeric_scalar(&p.param_type)),
+                None => spec.parameters.contains_key(variable),
+            };
+            let is_input = match variable.rsplit_once('.') {
+                Some((port, input)) => input_ports.contains(port) && INPUT_VARIABLES.contains(&input),
+                None => INPUT_VARIABLES.contains(&variable),
+            };
+            if !is_parameter && !is_input {
+                return Err(parameter_error(spec, name, format!("unknown variable {}", variable)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The module spec with every expression parameter replaced by its value for these inputs
+pub fn resolve_parameter_expressions(
+    spec: &ModuleSpec,
+    parameters: Option<&ParameterSnapshot>,
+    inputs: &InputPorts,
+) -> Result<ModuleSpec, crate::Error> {
+    let expressions = ordered_expressions(spec)?;
+    if expressions.is_empty() {
+        return Ok(spec.clone());
+    }
+
+    let mut scope = ExpressionScope::new();
+    scope.bind_inputs(inputs);
+    let mut values: HashMap<String, ParameterValue> = parameters
+        .map(|p| p.iter().map(|(name, param)| (name.clone(), param.value.clone())).collect())
+        .unwrap_or_default();
+    for (name, text) in spec.literal_parameters() {
+        let value = match parameters.and_then(|p| p.get(name)) {
+            Some(param) => ParameterValue::parse(&param.param_type, text).ok(),
+            None => text.trim().parse::<f64>().ok().map(|v| ParameterValue::Float(v as f32)),
+        };
+        if let Some(value) = value {
+            values.insert(name.clone(), value);
+        }
+    }
+    scope.bind_parameters(&values);
+
+    let mut resolved = spec.clone();
+    for (name, expression) in expressions {
+        let param_type = parameters.and_then(|p| p.get(name)).map(|p| &p.param_type);
+        let computed = expression.evaluate(&scope).map_err(|e| parameter_error(spec, name, e))?;
+        let text = format_value(param_type, &computed).map_err(|e| parameter_error(spec, name, e))?;
+        tracing::debug!("Module {} ({}): parameter {} = {}", spec.name, spec.id, name, text);
+        if let [value] = computed.as_slice() {
+            scope.set(name, *value);
+        }
+        resolved.parameters.insert(name.to_string(), text);
+    }
+    Ok(resolved)
+}
+
+impl WorkflowExecutor {
+    /// Check the expression parameters of a workflow's modules, see `check_parameter_expressions`
+    pub async fn check_expressions(&self, workflow: &WorkflowSpec) -> Result<(), crate::Error> {
+        for module_spec in &workflow.modules {
+            self.check_module_expressions(workflow, module_spec).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn check_module_expressions(
+        &self,
+        workflow: &WorkflowSpec,
+        module_spec: &ModuleSpec,
+    ) -> Result<(), crate::Error> {
+        if !module_spec.parameters.values().any(|text| is_expression(text)) {
+            return Ok(());
+        }
+        let parameters = self.module_registry().create_detached(&module_spec.module_type).await
+            .ok()
+            .map(|module| module.parameters());
+        let input_ports: BTreeSet<String> = workflow.connections.iter()
+            .filter(|c| c.to_module == module_spec.id)
+            .map(|c| c.to_port.clone())
+            .collect();
+        check_parameter_expressions(module_spec, parameters.as_ref(), &input_ports)
+    }
+
+    /// Evaluate a module's expression parameters for the inputs it is about to run with
+    pub(crate) async fn resolve_expressions(
+        &self,
+        module_spec: &ModuleSpec,
+        inputs: &InputPorts,
+    ) -> Result<ModuleSpec, crate::Error> {
+        if !module_spec.parameters.values().any(|text| is_expression(text)) {
+            return Ok(module_spec.clone());
+        }
+        let parameters = self.module_registry().create_detached(&module_spec.module_type).await
+            .ok()
+            .map(|module| module.parameters());
+        resolve_parameter_expressions(module_spec, parameters.as_ref(), inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::core::{ObjectType, VistleObject};
+    use ndarray::array;
+
+    fn eval(text: &str) -> f64 {
+        let values = ParameterExpression::parse(text).unwrap().evaluate(&ExpressionScope::new()).unwrap();
+        values[0]
+    }
+
+    fn points(size_x: f32) -> InputPorts {
+        let object = VistleObject::with_data(ObjectType::Points, ObjectPayload::Points {
+            coordinates: array![[0.0f32, 0.0, 0.0], [size_x, 1.0, 1.0]],
+        });
+        InputPorts::from([("grid_in".to_string(), vec![Arc::new(object) as Arc<dyn Object>])])
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        assert_eq!(eval("=1 + 2 * 3"), 7.0);
+        assert_eq!(eval("=(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("=8 / 4 / 2"), 1.0);
+        assert_eq!(eval("=1 - 2 - 3"), -4.0);
+        assert_eq!(eval("=-2^2"), -4.0);
+        assert_eq!(eval("=2^3^2"), 512.0);
+        assert_eq!(eval("=2 * -3"), -6.0);
+        assert_eq!(eval("=max(1, 2 + 3, sqrt(16))"), 5.0);
+    }
+
+    #[test]
+    fn unknown_variables_are_errors() {
+        let expression = ParameterExpression::parse("=radius * 2").unwrap();
+        assert_eq!(expression.evaluate(&ExpressionScope::new()).unwrap_err(), "unknown variable radius");
+        let expression = ParameterExpression::parse("=bounds_size_x").unwrap();
+        assert_eq!(expression.evaluate(&ExpressionScope::new()).unwrap_err(), "no input provides bounds_size_x");
+
+        let spec = ModuleSpec::new(4, "Glyphs", "Glyphs").with_parameter("scale", "=radius * 2");
+        let error = check_parameter_expressions(&spec, None, &BTreeSet::new()).unwrap_err();
+        assert!(error.to_string().contains("parameter scale: unknown variable radius"), "{}", error);
+
+        // Input variables need a connection to the port they name
+        let spec = ModuleSpec::new(4, "Glyphs", "Glyphs").with_parameter("scale", "=grid_in.bounds_size_x");
+        assert!(check_parameter_expressions(&spec, None, &BTreeSet::new()).is_err());
+        let ports = BTreeSet::from(["grid_in".to_string()]);
+        assert!(check_parameter_expressions(&spec, None, &ports).is_ok());
+    }
+
+    #[test]
+    fn expressions_are_evaluated_against_the_current_inputs() {
+        let spec = ModuleSpec::new(4, "Glyphs", "Glyphs").with_parameter("scale", "=2 * bounds_size_x");
+        let value = |inputs: &InputPorts| -> f64 {
+            let resolved = resolve_parameter_expressions(&spec, None, inputs).unwrap();
+            resolved.parameters["scale"].parse().unwrap()
+        };
+        assert_eq!(value(&points(1.0)), 2.0);
+        // A recompute with new upstream outputs does not see the old bounds
+        assert_eq!(value(&points(3.0)), 6.0);
+        // The spec keeps the expression for the next run
+        assert_eq!(spec.parameters["scale"], "=2 * bounds_size_x");
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected() {
+        let parens = format!("={}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(ParameterExpression::parse(&parens).unwrap_err().contains("nests deeper"));
+        let negations = format!("={}1", "-".repeat(100_000));
+        assert!(ParameterExpression::parse(&negations).unwrap_err().contains("nests deeper"));
+        let powers = format!("=1{}", "^1".repeat(100_000));
+        assert!(ParameterExpression::parse(&powers).unwrap_err().contains("nests deeper"));
+
+        let shallow = format!("={}1{}", "(".repeat(MAX_DEPTH / 2), ")".repeat(MAX_DEPTH / 2));
+        assert_eq!(eval(&shallow), 1.0);
+    }
+}
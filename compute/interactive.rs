@@ -0,0 +1,432 @@
+//! Interactive re-execution after parameter changes
+//!
+//! With `WorkflowExecutor::with_interactive`, the executor keeps the outputs
+//! each module produced in its latest execution. Changing a parameter through
+//! `set_parameter_interactive` then re-runs only the module and the modules
+//! downstream of it, feeding them the cached outputs of everything upstream.
+//! Changes arriving within the debounce interval coalesce into one recompute,
+//! and a change arriving while a recompute runs cancels it in favour of one
+//! covering both. Modules whose outputs are not cached, e.g. because they
+//! failed, run again along with the dirty ones.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::compute::{check_outputs, downstream_modules, is_expression, OutputEvent, OutputPorts, WorkflowExecutor, WorkflowSpec};
+
+/// Debounce used unless configured otherwise
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Settings of interactive re-execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InteractiveConfig {
+    /// Quiet time after a parameter change before the recompute starts
+    pub debounce: Duration,
+}
+
+impl Default for InteractiveConfig {
+    fn default() -> Self {
+        Self {
+            debounce: DEFAULT_DEBOUNCE,
+        }
+    }
+}
+
+impl InteractiveConfig {
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+}
+
+/// Whether a run executed the whole workflow or recomputed part of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunKind {
+    #[default]
+    Full,
+    Interactive,
+}
+
+/// A parameter set through `set_parameter_interactive`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParameterChange {
+    pub module_id: u32,
+    pub name: String,
+    pub value: String,
+}
+
+/// Outcome of a full run or an interactive recompute, see `WorkflowExecutor::subscribe_runs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunEvent {
+    pub workflow_id: String,
+    pub kind: RunKind,
+    /// Parameter changes coalesced into an interactive recompute, in the order made
+    pub changes: Vec<ParameterChange>,
+    /// Changed modules and everything downstream of them
+    pub dirty: Vec<u32>,
+    /// Modules that were executed
+    pub executed: Vec<u32>,
+    /// Modules whose cached outputs were fed to the executed ones
+    pub reused: Vec<u32>,
+    pub success: bool,
+    pub error: Option<String>,
+    pub execution_time_ms: f64,
+}
+
+/// Parameter changes of one workflow not yet reflected in a finished recompute
+#[derive(Default)]
+pub(crate) struct InteractiveState {
+    /// Bumped on every change, so a debounced recompute can tell it was superseded
+    generation: u64,
+    pending: Vec<ParameterChange>,
+    running: Option<CancellationToken>,
+}
+
+impl WorkflowExecutor {
+    /// Set a module parameter and recompute the part of the workflow it affects
+    ///
+    /// The workflow must have run on this executor, which must have been
+    /// built `with_interactive`. The new value is kept in the workflow's
+    /// specification right away; the recompute starts once no further
+    /// change arrived for the debounce interval, and is reported as a
+    /// `RunEvent` of kind `RunKind::Interactive`. Superseded recomputes are
+    /// cancelled and not reported.
+    pub async fn set_parameter_interactive(
+        self: &Arc<Self>,
+        workflow_id: &str,
+        module_id: u32,
+        name: &str,
+        value: &str,
+    ) -> Result<(), crate::Error> {
+        let config = self.interactive().ok_or_else(|| crate::Error::Config(
+            "Interactive re-execution is not enabled on this executor".to_string()
+        ))?;
+        if is_expression(value) {
+            if let Some(spec) = self.workflow_spec(workflow_id).await {
+                if let Some(module) = spec.modules.iter().find(|m| m.id == module_id) {
+                    let module = module.clone().with_parameter(name, value);
+                    self.check_module_expressions(&spec, &module).await?;
+                }
+            }
+        }
+        self.update_workflow_spec(workflow_id, |spec| {
+            let module = spec.modules.iter_mut()
+                .find(|m| m.id == module_id)
+                .ok_or_else(|| crate::Error::Config(format!("Workflow {} has no module {}", workflow_id, module_id)))?;
+            module.parameters.insert(name.to_string(), value.to_string());
+            Ok(())
+        }).await?;
+
+        let generation = self.with_interactive_state(workflow_id, |state| {
+            state.generation += 1;
+            state.pending.push(ParameterChange {
+                module_id,
+                name: name.to_string(),
+                value: value.to_string(),
+            });
+            if let Some(running) = state.running.take() {
+                tracing::debug!("Workflow {}: cancelling recompute superseded by {}", workflow_id, name);
+                running.cancel();
+            }
+            state.generation
+        });
+
+        let executor: Weak<Self> = Arc::downgrade(self);
+        let workflow_id = workflow_id.to_string();
+        let cancelled = self.cancel_token(&workflow_id);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = cancelled.cancelled() => return,
+                _ = tokio::time::sleep(config.debounce) => {}
+            }
+            let Some(executor) = executor.upgrade() else {
+                return;
+            };
+            executor.recompute(&workflow_id, generation).await;
+        });
+        Ok(())
+    }
+
+    /// Run the modules dirtied by the pending changes, unless a later change superseded them
+    async fn recompute(&self, workflow_id: &str, generation: u64) {
+        let token = self.cancel_token(workflow_id).child_token();
+        let changes = self.with_interactive_state(workflow_id, |state| {
+            if state.generation != generation {
+                return None;
+            }
+            state.running = Some(token.clone());
+            Some(state.pending.clone())
+        });
+        let Some(changes) = changes else {
+            return;
+        };
+        let Some(spec) = self.workflow_spec(workflow_id).await else {
+            return;
+        };
+
+        let started = Instant::now();
+        let changed: Vec<u32> = changes.iter().map(|c| c.module_id).collect();
+        let dirty = downstream_modules(&spec, &changed);
+        let cached = self.cached_outputs(workflow_id);
+        let (run, reused) = recompute_plan(&spec, &dirty, &cached);
+        tracing::info!(
+            "Workflow {}: recomputing {} modules, reusing the outputs of {}",
+            workflow_id, run.len(), reused.len()
+        );
+
+        let mut outputs: HashMap<u32, OutputPorts> = cached.into_iter()
+            .filter(|(id, _)| reused.contains(id))
+            .collect();
+        let result = tokio::select! {
+            biased;
+            _ = token.cancelled() => None,
+            result = self.run_waves(workflow_id, &spec, &run, &mut outputs, &token) => Some(result),
+        };
+        // The run may finish just as a later change cancels it; its outputs are stale then
+        let Some(result) = result.filter(|_| !token.is_cancelled()) else {
+            tracing::debug!("Workflow {}: recompute cancelled", workflow_id);
+            return;
+        };
+
+        let mut executed = Vec::new();
+        for &module_id in &run {
+            // Outputs from before the change must not be fed to later recomputes
+            let Some(ports) = outputs.remove(&module_id) else {
+                self.forget_outputs(workflow_id, module_id);
+                continue;
+            };
+            self.publish_output(OutputEvent {
+                workflow_id: workflow_id.to_string(),
+                module_id,
+                outputs: ports.clone(),
+                kind: RunKind::Interactive,
+            });
+            self.cache_outputs(workflow_id, module_id, ports);
+            executed.push(module_id);
+        }
+        executed.sort();
+
+        self.with_interactive_state(workflow_id, |state| {
+            if state.generation == generation {
+                state.pending.clear();
+                state.running = None;
+            }
+        });
+        let mut dirty: Vec<u32> = dirty.into_iter().collect();
+        dirty.sort();
+        let mut reused: Vec<u32> = reused.into_iter().collect();
+        reused.sort();
+        self.publish_run(RunEvent {
+            workflow_id: workflow_id.to_string(),
+            kind: RunKind::Interactive,
+            changes,
+            dirty,
+            executed,
+            reused,
+            success: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+            execution_time_ms: started.elapsed().as_secs_f64() * 1000.0,
+        });
+    }
+
+    /// Execute `run` in waves of modules whose upstream modules have outputs
+    async fn run_waves(
+        &self,
+        workflow_id: &str,
+        spec: &WorkflowSpec,
+        run: &HashSet<u32>,
+        outputs: &mut HashMap<u32, OutputPorts>,
+        token: &CancellationToken,
+    ) -> Result<(), crate::Error> {
+        let mut remaining: Vec<u32> = spec.modules.iter().map(|m| m.id).filter(|id| run.contains(id)).collect();
+        while !remaining.is_empty() {
+            let (ready, waiting): (Vec<u32>, Vec<u32>) = remaining.into_iter()
+                .partition(|&id| upstream_of(spec, id).all(|u| !run.contains(&u) || outputs.contains_key(&u)));
+            if ready.is_empty() {
+                return Err(crate::Error::Config(format!("Workflow {} has a dependency cycle", workflow_id)));
+            }
+            remaining = waiting;
+
+            let wave = ready.iter().filter_map(|&id| spec.modules.iter().find(|m| m.id == id)).map(|module| {
+                let inputs = self.remote_inputs(spec, module.id, outputs);
+                let ctx = self.compute_context(module.id, workflow_id, spec).with_cancellation(token.clone());
+                async move {
+                    let result = self.run_module(module, &inputs, &ctx).await
+                        .and_then(|ports| check_outputs(spec, module.id, ports));
+                    (module.id, result)
+                }
+            });
+            let mut failed = None;
+            for (module_id, result) in futures::future::join_all(wave).await {
+                match result {
+                    Ok(ports) => {
+                        outputs.insert(module_id, ports);
+                    }
+                    Err(e) => {
+                        tracing::warn!("Workflow {}: module {} failed in recompute: {}", workflow_id, module_id, e);
+                        failed.get_or_insert(e);
+                    }
+                }
+            }
+            if let Some(e) = failed {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Modules a module depends on or takes input from
+fn upstream_of(spec: &WorkflowSpec, module_id: u32) -> impl Iterator<Item = u32> + '_ {
+    let dependencies = spec.modules.iter()
+        .filter(move |m| m.id == module_id)
+        .flat_map(|m| m.dependencies.iter().copied());
+    let connections = spec.connections.iter()
+        .filter(move |c| c.to_module == module_id)
+        .map(|c| c.from_module);
+    dependencies.chain(connections)
+}
+
+/// Modules to execute for a dirty set, and the modules whose cached outputs feed them
+///
+/// Upstream modules without cached outputs are executed too.
+fn recompute_plan(
+    spec: &WorkflowSpec,
+    dirty: &HashSet<u32>,
+    cached: &HashMap<u32, OutputPorts>,
+) -> (HashSet<u32>, HashSet<u32>) {
+    let mut run = dirty.clone();
+    let mut reused = HashSet::new();
+    let mut frontier: Vec<u32> = dirty.iter().copied().collect();
+    while let Some(id) = frontier.pop() {
+        for upstream in upstream_of(spec, id) {
+            if run.contains(&upstream) {
+                continue;
+            }
+            if cached.contains_key(&upstream) {
+                reused.insert(upstream);
+            } else {
+                run.insert(upstream);
+                frontier.push(upstream);
+            }
+        }
+    }
+    (run, reused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{ModuleRegistry, TaskExecutor, WorkflowBuilder};
+    use crate::core::{MessageRouter, ObjectPayload};
+
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+
+    /// Reader -> IsoSurface -> Renderer, as constant fields
+    fn pipeline(iso_delay_ms: u64) -> WorkflowSpec {
+        WorkflowBuilder::new("interactive", "Interactive")
+            .add_module("ConstantField", "Reader")
+            .add_module("ConstantField", "IsoSurface")
+                .parameter("delay_ms", &iso_delay_ms.to_string())
+                .depends_on(1)
+            .add_module("ConstantField", "Renderer")
+                .depends_on(2)
+            .connect(1, "data_out", 2, "data_in")
+            .connect(2, "data_out", 3, "data_in")
+            .build()
+    }
+
+    async fn executor(spec: WorkflowSpec) -> Arc<WorkflowExecutor> {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        let executor = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()))
+            .with_interactive(InteractiveConfig::default().with_debounce(DEBOUNCE));
+        let executor = Arc::new(executor);
+        assert!(executor.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap().success);
+        executor
+    }
+
+    fn first_value(outputs: &OutputPorts) -> f32 {
+        match outputs["data_out"][0].payload() {
+            Some(ObjectPayload::VecScalar { data }) => data[0],
+            other => panic!("expected a scalar field, got {:?}", other),
+        }
+    }
+
+    async fn next_run(runs: &mut tokio::sync::broadcast::Receiver<RunEvent>) -> RunEvent {
+        tokio::time::timeout(Duration::from_secs(5), runs.recv()).await
+            .expect("no recompute was reported")
+            .unwrap()
+    }
+
+    #[test]
+    fn the_plan_reuses_cached_upstream_outputs() {
+        let spec = pipeline(0);
+        let dirty = HashSet::from([2, 3]);
+        let cached = HashMap::from([(1, OutputPorts::new())]);
+        assert_eq!(recompute_plan(&spec, &dirty, &cached), (HashSet::from([2, 3]), HashSet::from([1])));
+
+        // Without cached outputs the reader has to run again
+        assert_eq!(recompute_plan(&spec, &dirty, &HashMap::new()).0, HashSet::from([1, 2, 3]));
+    }
+
+    #[tokio::test]
+    async fn rapid_changes_coalesce_into_one_recompute() {
+        let executor = executor(pipeline(0)).await;
+        let mut runs = executor.subscribe_runs();
+        let mut outputs = executor.subscribe_outputs();
+
+        for value in ["2.0", "3.0", "4.0"] {
+            executor.set_parameter_interactive("interactive", 2, "value", value).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let run = next_run(&mut runs).await;
+        assert_eq!(run.kind, RunKind::Interactive);
+        assert!(run.success, "{:?}", run.error);
+        let values: Vec<&str> = run.changes.iter().map(|c| c.value.as_str()).collect();
+        assert_eq!(values, ["2.0", "3.0", "4.0"]);
+        assert_eq!(run.dirty, [2, 3]);
+        assert_eq!(run.executed, [2, 3]);
+        assert_eq!(run.reused, [1]);
+
+        let iso = std::iter::from_fn(|| outputs.try_recv().ok()).find(|e| e.module_id == 2).unwrap();
+        assert_eq!(iso.kind, RunKind::Interactive);
+        assert_eq!(first_value(&iso.outputs), 4.0);
+        assert_eq!(first_value(&executor.cached_outputs("interactive")[&2]), 4.0);
+
+        tokio::time::sleep(DEBOUNCE * 3).await;
+        assert!(runs.try_recv().is_err(), "more than one recompute ran");
+    }
+
+    #[tokio::test]
+    async fn a_change_during_a_recompute_supersedes_it() {
+        // Long enough that the second change lands during the recompute on a busy machine too
+        let executor = executor(pipeline(1_500)).await;
+        let mut runs = executor.subscribe_runs();
+
+        executor.set_parameter_interactive("interactive", 2, "value", "2.0").await.unwrap();
+        tokio::time::sleep(DEBOUNCE + Duration::from_millis(100)).await;
+        executor.set_parameter_interactive("interactive", 2, "value", "5.0").await.unwrap();
+
+        let run = next_run(&mut runs).await;
+        assert_eq!(run.changes.len(), 2);
+        assert_eq!(first_value(&executor.cached_outputs("interactive")[&2]), 5.0);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(runs.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn interactive_changes_need_the_mode_enabled() {
+        let registry = Arc::new(ModuleRegistry::new());
+        let executor = Arc::new(WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(1)), Arc::new(MessageRouter::new())));
+        assert!(executor.set_parameter_interactive("interactive", 2, "value", "2.0").await.is_err());
+        assert!(executor.cached_outputs("interactive").is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+//! Realizing placeholder objects by running their reader module
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::core::{ComputeContext, Loader, MessageRouter, Object, ObjectLoader, ParameterValue};
+use crate::compute::ModuleRegistry;
+
+/// Ids of reader instances created for loading, kept clear of workflow module ids
+const LOADER_MODULE_ID_BASE: u32 = 0x8000_0000;
+
+/// `ObjectLoader` that instantiates the named reader from a `ModuleRegistry`
+///
+/// Each load runs a fresh reader instance for one timestep, so loads of
+/// different timesteps can run concurrently.
+pub struct ModuleLoader {
+    modules: Arc<ModuleRegistry>,
+    router: Arc<MessageRouter>,
+    next_id: AtomicU32,
+}
+
+impl ModuleLoader {
+    pub fn new(modules: Arc<ModuleRegistry>, router: Arc<MessageRouter>) -> Self {
+        Self {
+            modules,
+            router,
+            next_id: AtomicU32::new(LOADER_MODULE_ID_BASE),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ObjectLoader for ModuleLoader {
+    async fn load(&self, loader: &Loader) -> Result<Arc<dyn Object>, crate::Error> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let reader = self.modules.create_instance(&loader.module, id).await?;
+        let result = async {
+            for (name, value) in &loader.parameters {
+                reader.set_parameter(name, value.clone())?;
+            }
+            // Readers that split their data into blocks take the block as a parameter
+            if reader.parameters().get("block").is_some() {
+                reader.set_parameter("block", ParameterValue::Int(loader.block))?;
+            }
+
+            let ctx = ComputeContext::new(id, 0, 1).with_timestep(loader.timestep);
+            let outputs = reader.execute(&ctx, &self.router).await?;
+            let objects = outputs.get(&loader.port)
+                .ok_or_else(|| crate::Error::Module(format!(
+                    "Reader {} produced no data on port {}",
+                    loader.module, loader.port
+                )))?;
+            objects.iter()
+                .find(|o| o.meta().block == loader.block && o.meta().timestep == loader.timestep)
+                .or_else(|| objects.first())
+                .cloned()
+                .ok_or_else(|| crate::Error::Module(format!(
+                    "Reader {} produced no object for timestep {}, block {}",
+                    loader.module, loader.timestep, loader.block
+                )))
+        }.await;
+        self.modules.remove_instance(id).await;
+        result
+    }
+}
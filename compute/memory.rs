@@ -0,0 +1,320 @@
+//! Memory admission control for the `TaskExecutor`
+//!
+//! Before a ready task is dispatched, its expected peak memory is compared
+//! with what the node has available minus a safety margin. Tasks that do
+//! not fit wait while smaller ready tasks run. Expected peaks are declared
+//! on the task or learned per module type from earlier runs, and the
+//! learned peaks can be kept in a JSON file between sessions.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::util::{MemoryProvider, SystemMemory};
+
+/// How often running tasks are sampled and deferred tasks reconsidered
+pub const MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+const MIB: usize = 1024 * 1024;
+
+/// Limits admission control works within
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryBudget {
+    /// Bytes kept free beyond the expected peaks of all running tasks
+    pub safety_margin: usize,
+    /// Available memory below which the newest running task is cancelled
+    pub critical_available: usize,
+    /// Expected peak of module types without a declared or observed one
+    pub default_estimate: usize,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self {
+            safety_margin: 512 * MIB,
+            critical_available: 128 * MIB,
+            default_estimate: 64 * MIB,
+        }
+    }
+}
+
+impl MemoryBudget {
+    pub fn with_safety_margin(mut self, bytes: usize) -> Self {
+        self.safety_margin = bytes;
+        self
+    }
+
+    pub fn with_critical_available(mut self, bytes: usize) -> Self {
+        self.critical_available = bytes;
+        self
+    }
+
+    pub fn with_default_estimate(mut self, bytes: usize) -> Self {
+        self.default_estimate = bytes;
+        self
+    }
+}
+
+/// Largest resident-set growth seen while a module type ran
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObservedPeak {
+    pub bytes: usize,
+    pub runs: u64,
+}
+
+/// Observed peaks per module type, kept across runs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeakMemoryHistory {
+    peaks: HashMap<String, ObservedPeak>,
+}
+
+impl PeakMemoryHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, module_type: &str) -> Option<ObservedPeak> {
+        self.peaks.get(module_type).copied()
+    }
+
+    /// Record the peak growth of one run of `module_type`
+    pub fn record(&mut self, module_type: &str, bytes: usize) {
+        let peak = self.peaks.entry(module_type.to_string()).or_default();
+        peak.bytes = peak.bytes.max(bytes);
+        peak.runs += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ObservedPeak)> {
+        self.peaks.iter().map(|(name, peak)| (name.as_str(), peak))
+    }
+
+    /// Load a history saved with `save`; a missing file is an empty history
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let path = path.as_ref();
+        if !tokio::fs::try_exists(path).await? {
+            return Ok(Self::new());
+        }
+        let text = crate::util::io::read_text(path).await?;
+        serde_json::from_str(&text)
+            .map_err(|e| crate::Error::Config(format!("Invalid memory history {}: {}", path.display(), e)))
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize memory history: {}", e)))?;
+        crate::util::io::write_text(path, &text).await
+    }
+}
+
+/// Decides whether tasks fit into the node's memory
+///
+/// Available memory only reflects what running tasks use so far, so the
+/// part of their expected peak they have not reached yet is reserved on
+/// top of the safety margin.
+pub struct MemoryAdmission {
+    provider: Arc<dyn MemoryProvider>,
+    budget: MemoryBudget,
+    history: RwLock<PeakMemoryHistory>,
+    history_path: Option<PathBuf>,
+}
+
+impl MemoryAdmission {
+    pub fn new(provider: Arc<dyn MemoryProvider>, budget: MemoryBudget) -> Self {
+        Self {
+            provider,
+            budget,
+            history: RwLock::new(PeakMemoryHistory::new()),
+            history_path: None,
+        }
+    }
+
+    /// Admission control on the figures of this node
+    pub fn system(budget: MemoryBudget) -> Self {
+        Self::new(Arc::new(SystemMemory), budget)
+    }
+
+    pub fn with_history(self, history: PeakMemoryHistory) -> Self {
+        *self.history.write() = history;
+        self
+    }
+
+    /// Load observed peaks from `path` and save them there after each `TaskExecutor::execute_all`
+    pub async fn with_history_file(mut self, path: impl Into<PathBuf>) -> Result<Self, crate::Error> {
+        let path = path.into();
+        *self.history.write() = PeakMemoryHistory::load(&path).await?;
+        self.history_path = Some(path);
+        Ok(self)
+    }
+
+    pub fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+
+    pub fn provider(&self) -> &Arc<dyn MemoryProvider> {
+        &self.provider
+    }
+
+    pub fn history(&self) -> PeakMemoryHistory {
+        self.history.read().clone()
+    }
+
+    /// Expected peak of a task: declared, else observed, else the budget's default
+    pub fn estimate(&self, module_type: &str, declared: Option<usize>) -> usize {
+        declared
+            .or_else(|| self.history.read().get(module_type).map(|p| p.bytes))
+            .unwrap_or(self.budget.default_estimate)
+    }
+
+    /// Bytes a new task may still use, with `reserved` promised to running tasks
+    pub fn headroom(&self, reserved: usize) -> usize {
+        self.provider.available()
+            .saturating_sub(self.budget.safety_margin)
+            .saturating_sub(reserved)
+    }
+
+    /// Whether the node is short enough of memory to cancel a task
+    pub fn is_critical(&self) -> bool {
+        self.provider.available() < self.budget.critical_available
+    }
+
+    pub fn resident(&self) -> usize {
+        self.provider.resident()
+    }
+
+    pub fn record_peak(&self, module_type: &str, bytes: usize) {
+        self.history.write().record(module_type, bytes);
+    }
+
+    /// Save the observed peaks if a history file was given
+    pub async fn save_history(&self) -> Result<(), crate::Error> {
+        match &self.history_path {
+            Some(path) => self.history().save(path).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// A dispatched task as seen by admission control
+#[derive(Debug)]
+pub(crate) struct AdmittedTask {
+    pub task_id: crate::compute::TaskId,
+    pub module_type: String,
+    /// Workflow of the task, whose memory limit it counts against
+    pub workflow_id: Option<String>,
+    pub estimate: usize,
+    /// Resident set when the task started
+    pub baseline: usize,
+    /// Largest resident set sampled while it ran
+    pub peak: usize,
+    pub abort: tokio::task::AbortHandle,
+}
+
+impl AdmittedTask {
+    /// Growth of the resident set since the task started
+    ///
+    /// Tasks running concurrently share the process, so each is charged
+    /// with the growth of all of them; observed peaks err on the high side.
+    pub fn growth(&self) -> usize {
+        self.peak.saturating_sub(self.baseline)
+    }
+
+    /// Part of the estimate the task has not used yet
+    pub fn outstanding(&self) -> usize {
+        self.estimate.saturating_sub(self.growth())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::compute::testing::modules::ConstantField;
+    use crate::compute::{Module, Task, TaskEvent, TaskExecutor, TaskId, VistleModule};
+    use crate::core::ComputeContext;
+
+    /// Memory report set by the test
+    #[derive(Default)]
+    struct SyntheticMemory {
+        available: AtomicUsize,
+        resident: AtomicUsize,
+    }
+
+    impl SyntheticMemory {
+        fn with_available(bytes: usize) -> Arc<Self> {
+            let memory = Arc::new(Self::default());
+            memory.available.store(bytes, Ordering::Relaxed);
+            memory
+        }
+    }
+
+    impl MemoryProvider for SyntheticMemory {
+        fn available(&self) -> usize {
+            self.available.load(Ordering::Relaxed)
+        }
+
+        fn resident(&self) -> usize {
+            self.resident.load(Ordering::Relaxed)
+        }
+    }
+
+    fn budget() -> MemoryBudget {
+        MemoryBudget::default()
+            .with_safety_margin(1000)
+            .with_critical_available(500)
+            .with_default_estimate(64)
+    }
+
+    #[test]
+    fn estimates_prefer_declared_then_observed_peaks() {
+        let admission = MemoryAdmission::new(SyntheticMemory::with_available(0), budget());
+        assert_eq!(admission.estimate("Reader", None), 64);
+        admission.record_peak("Reader", 300);
+        admission.record_peak("Reader", 200);
+        assert_eq!(admission.estimate("Reader", None), 300);
+        assert_eq!(admission.estimate("Reader", Some(10)), 10);
+        assert_eq!(admission.history().get("Reader"), Some(ObservedPeak { bytes: 300, runs: 2 }));
+    }
+
+    #[test]
+    fn headroom_follows_the_reported_memory() {
+        let memory = SyntheticMemory::with_available(1500);
+        let admission = MemoryAdmission::new(memory.clone(), budget());
+        assert_eq!(admission.headroom(0), 500);
+        assert_eq!(admission.headroom(200), 300);
+        assert_eq!(admission.headroom(800), 0);
+        assert!(!admission.is_critical());
+
+        memory.available.store(400, Ordering::Relaxed);
+        assert_eq!(admission.headroom(0), 0);
+        assert!(admission.is_critical());
+    }
+
+    #[tokio::test]
+    async fn smaller_tasks_run_while_a_large_one_waits() {
+        let admission = Arc::new(MemoryAdmission::new(SyntheticMemory::with_available(1250), budget()));
+        let executor = TaskExecutor::new(4).with_memory_admission(admission);
+        for (id, bytes) in [(1u64, 300), (2, 200), (3, 100)] {
+            let task = Task::new(TaskId::new(id), Arc::new(VistleModule::new(Box::new(ConstantField::new(id as u32)) as Box<dyn Module>)), ComputeContext::new(id as u32, 0, 1))
+                .with_peak_memory(bytes);
+            executor.add_task(task).await;
+        }
+        let mut events = executor.subscribe();
+
+        let results = executor.execute_all().await.unwrap();
+        assert!(results.iter().all(|r| r.success));
+
+        let mut started = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let TaskEvent::Started { task_id, .. } = event {
+                started.push(task_id.as_u64());
+            }
+        }
+        // 300 bytes never fit the 250 of headroom; it runs last, once nothing else does
+        assert_eq!(started, vec![3, 2, 1]);
+    }
+}
@@ -0,0 +1,513 @@
+//! Migrating workflow files saved by older versions
+//!
+//! Workflow files carry a `format_version`; files from before versioning
+//! count as version 0. Loading parses a file into a JSON value, runs every
+//! registered migration newer than the file's version, in order of version
+//! and then of registration, and only then builds the `WorkflowSpec`.
+//! Migrations rename module types and parameters or supply values for
+//! parameters that became required. Module and plugin authors register
+//! migrations for their own types in `MigrationRegistry::global()`.
+//!
+//! Content no migration accounts for does not fail the load. Unknown fields
+//! are dropped, and modules or connections that cannot be read are left out.
+//! Each of these is reported in `LoadWarnings`. Strict loading, e.g. in CI,
+//! turns any warning into an error.
+
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::compute::{ConnectionSpec, ModuleSpec, WorkflowSpec};
+
+/// Format version of the workflow files this build writes
+pub const WORKFLOW_FORMAT_VERSION: u32 = 1;
+
+/// Content of a workflow file that was dropped or could not be migrated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadWarning {
+    /// Module the warning is about
+    pub module: Option<u32>,
+    pub message: String,
+}
+
+impl std::fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.module {
+            Some(module) => write!(f, "module {}: {}", module, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Everything a workflow load dropped or could not migrate, in the order it was found
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadWarnings {
+    pub warnings: Vec<LoadWarning>,
+}
+
+impl LoadWarnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, module: Option<u32>, message: impl Into<String>) {
+        self.warnings.push(LoadWarning { module, message: message.into() });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.warnings.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LoadWarning> {
+        self.warnings.iter()
+    }
+
+    /// Ok if there are no warnings, otherwise an error listing them, for strict loading
+    pub fn check(&self, path: &Path) -> Result<(), crate::Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let list: Vec<String> = self.iter().map(|w| w.to_string()).collect();
+        Err(crate::Error::Config(format!(
+            "Workflow {} did not load cleanly: {}",
+            path.display(), list.join("; ")
+        )))
+    }
+}
+
+/// Function rewriting a parsed workflow file
+pub type MigrationFn = Arc<dyn Fn(&mut Value, &mut LoadWarnings) + Send + Sync>;
+
+/// One change to the content of saved workflow files
+///
+/// The built-in kinds only act on modules they apply to and leave files
+/// that are already migrated unchanged.
+#[derive(Clone)]
+pub enum Migration {
+    /// Module type `from` was renamed to `to`
+    RenameModuleType { from: String, to: String },
+    /// Parameter `from` of modules of `module_type` was renamed to `to`
+    RenameParameter { module_type: String, from: String, to: String },
+    /// Parameter `name` became required; modules without it get `value`
+    DefaultParameter { module_type: String, name: String, value: String },
+    /// Any other rewrite of the whole workflow
+    Custom { description: String, apply: MigrationFn },
+}
+
+impl Migration {
+    pub fn rename_module_type(from: &str, to: &str) -> Self {
+        Migration::RenameModuleType { from: from.to_string(), to: to.to_string() }
+    }
+
+    pub fn rename_parameter(module_type: &str, from: &str, to: &str) -> Self {
+        Migration::RenameParameter {
+            module_type: module_type.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    pub fn default_parameter(module_type: &str, name: &str, value: &str) -> Self {
+        Migration::DefaultParameter {
+            module_type: module_type.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    pub fn custom<F>(description: &str, apply: F) -> Self
+    where
+        F: Fn(&mut Value, &mut LoadWarnings) + Send + Sync + 'static,
+    {
+        Migration::Custom { description: description.to_string(), apply: Arc::new(apply) }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            Migration::RenameModuleType { from, to } => format!("rename module type {} to {}", from, to),
+            Migration::RenameParameter { module_type, from, to } => format!("rename parameter {}.{} to {}", module_type, from, to),
+            Migration::DefaultParameter { module_type, name, value } => format!("default {}.{} to {}", module_type, name, value),
+            Migration::Custom { description, .. } => description.clone(),
+        }
+    }
+
+    /// Apply the migration to a parsed workflow file
+    pub fn apply(&self, workflow: &mut Value, warnings: &mut LoadWarnings) {
+        if let Migration::Custom { apply, .. } = self {
+            apply(workflow, warnings);
+            return;
+        }
+        let Some(modules) = workflow.get_mut("modules").and_then(Value::as_array_mut) else {
+            return;
+        };
+        for module in modules.iter_mut().filter_map(Value::as_object_mut) {
+            let id = module.get("id").and_then(Value::as_u64).map(|id| id as u32);
+            let module_type = module.get("module_type").and_then(Value::as_str).unwrap_or_default().to_string();
+            match self {
+                Migration::RenameModuleType { from, to } if module_type == *from => {
+                    module.insert("module_type".to_string(), Value::String(to.clone()));
+                }
+                Migration::RenameParameter { module_type: target, from, to } if module_type == *target => {
+                    let Some(parameters) = module.get_mut("parameters").and_then(Value::as_object_mut) else {
+                        continue;
+                    };
+                    let Some(value) = parameters.remove(from) else {
+                        continue;
+                    };
+                    if parameters.contains_key(to) {
+                        warnings.push(id, format!("parameter {} was renamed to {}, which is set as well; {} dropped", from, to, from));
+                    } else {
+                        parameters.insert(to.clone(), value);
+                    }
+                }
+                Migration::DefaultParameter { module_type: target, name, value } if module_type == *target => {
+                    let parameters = module.entry("parameters").or_insert_with(|| Value::Object(Map::new()));
+                    if let Some(parameters) = parameters.as_object_mut() {
+                        parameters.entry(name.clone()).or_insert_with(|| Value::String(value.clone()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Migration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Migration").field(&self.description()).finish()
+    }
+}
+
+/// Migrations of saved workflows, with the format version each was introduced in
+pub struct MigrationRegistry {
+    migrations: RwLock<Vec<(u32, Migration)>>,
+}
+
+impl MigrationRegistry {
+    pub fn new() -> Self {
+        Self {
+            migrations: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Registry consulted by `WorkflowSpec::load`
+    pub fn global() -> &'static MigrationRegistry {
+        static GLOBAL: OnceLock<MigrationRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(Self::new)
+    }
+
+    /// Add a migration that files saved before format `version` need
+    ///
+    /// Migrations of the same version run in the order they were registered.
+    pub fn register(&self, version: u32, migration: Migration) -> Result<(), crate::Error> {
+        if version == 0 {
+            return Err(crate::Error::Config(format!(
+                "Migration '{}' must belong to a format version above 0",
+                migration.description()
+            )));
+        }
+        tracing::debug!("Registered workflow migration to format {}: {}", version, migration.description());
+        let mut migrations = self.migrations.write();
+        let at = migrations.partition_point(|(v, _)| *v <= version);
+        migrations.insert(at, (version, migration));
+        Ok(())
+    }
+
+    /// Registered migrations in the order they run
+    pub fn migrations(&self) -> Vec<(u32, Migration)> {
+        self.migrations.read().clone()
+    }
+
+    /// Bring a parsed workflow file to the current format, returning the version it had
+    pub fn migrate(&self, workflow: &mut Value, warnings: &mut LoadWarnings) -> u32 {
+        let version = workflow.get("format_version")
+            .and_then(Value::as_u64)
+            .map_or(0, |v| v.min(u32::MAX as u64) as u32);
+        if version > WORKFLOW_FORMAT_VERSION {
+            warnings.push(None, format!(
+                "saved in format {}, newer than {}; content this version does not know is dropped",
+                version, WORKFLOW_FORMAT_VERSION
+            ));
+        }
+        for (_, migration) in self.migrations().iter().filter(|(v, _)| *v > version) {
+            tracing::debug!("Migrating workflow from format {}: {}", version, migration.description());
+            migration.apply(workflow, warnings);
+        }
+        if let Some(workflow) = workflow.as_object_mut() {
+            workflow.insert("format_version".to_string(), Value::from(WORKFLOW_FORMAT_VERSION));
+        }
+        version
+    }
+}
+
+impl Default for MigrationRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fields a serialized value of a type has
+fn known_fields(value: impl Serialize) -> Vec<String> {
+    match serde_json::to_value(value) {
+        Ok(Value::Object(fields)) => fields.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Remove the fields of `object` not in `known`, warning about each
+fn drop_unknown(object: &mut Map<String, Value>, known: &[String], what: &str, module: Option<u32>, warnings: &mut LoadWarnings) {
+    let unknown: Vec<String> = object.keys().filter(|key| !known.contains(key)).cloned().collect();
+    for key in unknown {
+        object.remove(&key);
+        warnings.push(module, format!("unknown {} field '{}' dropped", what, key));
+    }
+}
+
+/// Build a workflow from a migrated file, leaving out what cannot be read
+pub fn workflow_from_value(mut workflow: Value, warnings: &mut LoadWarnings) -> Result<WorkflowSpec, serde_json::Error> {
+    if let Some(object) = workflow.as_object_mut() {
+        leave_out_unreadable(object, warnings);
+    }
+    serde_json::from_value(workflow)
+}
+
+/// Drop unknown fields, and modules and connections that cannot be read
+fn leave_out_unreadable(object: &mut Map<String, Value>, warnings: &mut LoadWarnings) {
+    drop_unknown(object, &known_fields(WorkflowSpec::new("", "")), "workflow", None, warnings);
+
+    let module_fields = known_fields(ModuleSpec::new(0, "", ""));
+    let mut dropped = Vec::new();
+    if let Some(modules) = object.get_mut("modules").and_then(Value::as_array_mut) {
+        modules.retain_mut(|module| {
+            let id = module.get("id").and_then(Value::as_u64).map(|id| id as u32);
+            if let Some(fields) = module.as_object_mut() {
+                drop_unknown(fields, &module_fields, "module", id, warnings);
+            }
+            match serde_json::from_value::<ModuleSpec>(module.clone()) {
+                Ok(_) => true,
+                Err(e) => {
+                    warnings.push(id, format!("cannot be read and is left out: {}", e));
+                    dropped.extend(id);
+                    false
+                }
+            }
+        });
+        for module in modules.iter_mut() {
+            let id = module.get("id").and_then(Value::as_u64).map(|id| id as u32);
+            if let Some(dependencies) = module.get_mut("dependencies").and_then(Value::as_array_mut) {
+                dependencies.retain(|d| {
+                    let keep = !d.as_u64().is_some_and(|d| dropped.contains(&(d as u32)));
+                    if !keep {
+                        warnings.push(id, format!("dependency on left out module {} dropped", d));
+                    }
+                    keep
+                });
+            }
+        }
+    }
+
+    let connection_fields = known_fields(ConnectionSpec {
+        from_module: 0,
+        from_port: String::new(),
+        to_module: 0,
+        to_port: String::new(),
+    });
+    if let Some(connections) = object.get_mut("connections").and_then(Value::as_array_mut) {
+        connections.retain_mut(|connection| {
+            if let Some(fields) = connection.as_object_mut() {
+                drop_unknown(fields, &connection_fields, "connection", None, warnings);
+            }
+            match serde_json::from_value::<ConnectionSpec>(connection.clone()) {
+                Ok(c) if dropped.contains(&c.from_module) || dropped.contains(&c.to_module) => {
+                    warnings.push(None, format!(
+                        "connection {}.{} -> {}.{} to a left out module dropped",
+                        c.from_module, c.from_port, c.to_module, c.to_port
+                    ));
+                    false
+                }
+                Ok(_) => true,
+                Err(e) => {
+                    warnings.push(None, format!("connection cannot be read and is left out: {}", e));
+                    false
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::TaskPriority;
+
+    const SAVED_BY_0_3: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/workflows/isosurface_0_3.json"));
+    const SAVED_BY_0_4: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/workflows/isosurface_0_4.json"));
+
+    /// What changed in format 1: the isosurface module and its level were renamed, readers need a format
+    fn registry() -> MigrationRegistry {
+        let registry = MigrationRegistry::new();
+        registry.register(1, Migration::rename_module_type("IsoSurfaceExtractor", "IsoSurface")).unwrap();
+        registry.register(1, Migration::rename_parameter("IsoSurface", "level", "iso_value")).unwrap();
+        registry.register(1, Migration::default_parameter("DataReader", "format", "VTK")).unwrap();
+        registry
+    }
+
+    fn load(registry: &MigrationRegistry, text: &str) -> (u32, WorkflowSpec, LoadWarnings) {
+        let mut value: Value = serde_json::from_str(text).unwrap();
+        let mut warnings = LoadWarnings::new();
+        let version = registry.migrate(&mut value, &mut warnings);
+        let spec = workflow_from_value(value, &mut warnings).unwrap();
+        (version, spec, warnings)
+    }
+
+    fn parameters(spec: &WorkflowSpec, id: u32) -> Vec<(String, String)> {
+        let module = spec.modules.iter().find(|m| m.id == id).unwrap();
+        let mut parameters: Vec<_> = module.parameters.clone().into_iter().collect();
+        parameters.sort();
+        parameters
+    }
+
+    fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn files_from_0_3_migrate_cleanly() {
+        let (version, spec, warnings) = load(&registry(), SAVED_BY_0_3);
+        assert_eq!(version, 0);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(spec.format_version, WORKFLOW_FORMAT_VERSION);
+
+        let types: Vec<&str> = spec.modules.iter().map(|m| m.module_type.as_str()).collect();
+        assert_eq!(types, ["DataReader", "IsoSurface", "Renderer"]);
+        assert_eq!(parameters(&spec, 1), pairs(&[("filename", "data.vtk"), ("format", "VTK")]));
+        assert_eq!(parameters(&spec, 2), pairs(&[("iso_value", "0.5")]));
+        assert_eq!(spec.connections.len(), 2);
+    }
+
+    #[test]
+    fn files_from_0_4_keep_what_can_be_read_and_report_the_rest() {
+        let (version, spec, warnings) = load(&registry(), SAVED_BY_0_4);
+        assert_eq!(version, 0);
+
+        let ids: Vec<u32> = spec.modules.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [1, 2, 4]);
+        assert_eq!(parameters(&spec, 1), pairs(&[("filename", "data.h5"), ("format", "HDF5")]));
+        assert_eq!(parameters(&spec, 2), pairs(&[("iso_value", "0.7")]));
+        assert_eq!(spec.modules[0].priority, TaskPriority::High);
+        assert_eq!(spec.modules[2].dependencies, [2]);
+        let connections: Vec<(u32, u32)> = spec.connections.iter().map(|c| (c.from_module, c.to_module)).collect();
+        assert_eq!(connections, [(1, 2), (2, 4)]);
+
+        let listed: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        let expected = [
+            "module 2: parameter level was renamed to iso_value, which is set as well; level dropped",
+            "unknown workflow field 'theme' dropped",
+            "module 1: unknown module field 'gui_position' dropped",
+            "module 3: cannot be read and is left out: missing field `name`",
+            "module 4: dependency on left out module 3 dropped",
+            "connection 3.labels_out -> 4.overlay_in to a left out module dropped",
+            "connection cannot be read and is left out: missing field `from_port`",
+        ];
+        assert_eq!(listed, expected);
+        assert_eq!(warnings.iter().filter(|w| w.module.is_none()).count(), 3);
+    }
+
+    #[test]
+    fn strict_loading_fails_on_any_warning() {
+        let (_, _, clean) = load(&registry(), SAVED_BY_0_3);
+        assert!(clean.check(Path::new("isosurface_0_3.json")).is_ok());
+
+        let (_, _, warnings) = load(&registry(), SAVED_BY_0_4);
+        let message = warnings.check(Path::new("isosurface_0_4.json")).unwrap_err().to_string();
+        assert!(message.contains("isosurface_0_4.json did not load cleanly"), "{}", message);
+        assert!(message.contains("unknown workflow field 'theme' dropped; module 1:"), "{}", message);
+    }
+
+    #[test]
+    fn current_files_are_not_migrated() {
+        let mut current: Value = serde_json::from_str(SAVED_BY_0_3).unwrap();
+        current["format_version"] = Value::from(WORKFLOW_FORMAT_VERSION);
+        let (version, spec, warnings) = load(&registry(), &current.to_string());
+        assert_eq!(version, WORKFLOW_FORMAT_VERSION);
+        assert!(warnings.is_empty());
+        assert_eq!(spec.modules[1].module_type, "IsoSurfaceExtractor");
+        assert_eq!(parameters(&spec, 1), pairs(&[("filename", "data.vtk")]));
+    }
+
+    #[test]
+    fn newer_files_load_with_a_warning() {
+        let mut newer: Value = serde_json::from_str(SAVED_BY_0_3).unwrap();
+        newer["format_version"] = Value::from(WORKFLOW_FORMAT_VERSION + 1);
+        let (version, spec, warnings) = load(&registry(), &newer.to_string());
+        assert_eq!(version, WORKFLOW_FORMAT_VERSION + 1);
+        assert_eq!(spec.format_version, WORKFLOW_FORMAT_VERSION);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings.warnings[0].message.contains("newer than"), "{:?}", warnings);
+    }
+
+    #[test]
+    fn migrations_run_by_version_then_registration() {
+        let registry = MigrationRegistry::new();
+        registry.register(3, Migration::rename_module_type("B", "C")).unwrap();
+        registry.register(2, Migration::rename_module_type("A", "B")).unwrap();
+        registry.register(3, Migration::default_parameter("C", "mode", "fast")).unwrap();
+        let order: Vec<(u32, String)> = registry.migrations().iter().map(|(v, m)| (*v, m.description())).collect();
+        assert_eq!(order, [
+            (2, "rename module type A to B".to_string()),
+            (3, "rename module type B to C".to_string()),
+            (3, "default C.mode to fast".to_string()),
+        ]);
+
+        let mut workflow = serde_json::json!({ "modules": [{ "id": 1, "module_type": "A" }] });
+        let mut warnings = LoadWarnings::new();
+        registry.migrate(&mut workflow, &mut warnings);
+        assert_eq!(workflow["modules"][0]["module_type"], "C");
+        assert_eq!(workflow["modules"][0]["parameters"]["mode"], "fast");
+
+        assert!(registry.register(0, Migration::rename_module_type("A", "B")).is_err());
+    }
+
+    #[test]
+    fn custom_migrations_rewrite_the_whole_file() {
+        let registry = MigrationRegistry::new();
+        registry.register(1, Migration::custom("move title to name", |workflow, warnings| {
+            if let Some(title) = workflow.as_object_mut().and_then(|w| w.remove("title")) {
+                workflow["name"] = title;
+                warnings.push(None, "title became name");
+            }
+        })).unwrap();
+
+        let mut old: Value = serde_json::from_str(SAVED_BY_0_3).unwrap();
+        let title = old.as_object_mut().unwrap().remove("name").unwrap();
+        old["title"] = title;
+        let (_, spec, warnings) = load(&registry, &old.to_string());
+        assert_eq!(spec.name, "Isosurface");
+        assert_eq!(warnings.warnings[0].message, "title became name");
+        assert_eq!(format!("{:?}", registry.migrations()[0].1), "Migration(\"move title to name\")");
+    }
+
+    #[tokio::test]
+    async fn loading_uses_migrations_registered_globally() {
+        MigrationRegistry::global()
+            .register(1, Migration::rename_module_type("MigrationTestOldSource", "MigrationTestSource"))
+            .unwrap();
+        let mut old: Value = serde_json::from_str(SAVED_BY_0_3).unwrap();
+        old["modules"][0]["module_type"] = Value::from("MigrationTestOldSource");
+        let path = std::env::temp_dir().join(format!("vistle_migration_{}.json", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&path, old.to_string()).unwrap();
+
+        let (spec, warnings) = WorkflowSpec::load_with_warnings(&path).await.unwrap();
+        assert_eq!(spec.modules[0].module_type, "MigrationTestSource");
+        assert_eq!(spec.format_version, WORKFLOW_FORMAT_VERSION);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        std::fs::remove_file(path).ok();
+    }
+}
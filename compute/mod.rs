@@ -1,9 +1,56 @@
-//! Compute engine and module system
-
-pub mod module;
-pub mod executor;
-pub mod task;
-
-pub use module::*;
-pub use executor::*;
-pub use task::*;
+//! Compute engine and module system
+
+pub mod module;
+pub mod executor;
+pub mod task;
+pub mod sweep;
+pub mod slice_sweep;
+pub mod builtin;
+pub mod distributed;
+pub mod template;
+pub mod watch;
+pub mod report;
+pub mod diagram;
+pub mod schedule;
+pub mod loader;
+pub mod progress;
+pub mod reader;
+pub mod memory;
+pub mod stage;
+pub mod trace;
+pub mod outputs;
+pub mod testing;
+pub mod coercion;
+pub mod interactive;
+pub mod output_snapshot;
+pub mod runner;
+pub mod paraview;
+pub mod audit;
+pub mod expression;
+pub mod migration;
+
+pub use module::*;
+pub use executor::*;
+pub use task::*;
+pub use sweep::*;
+pub use slice_sweep::*;
+pub use distributed::*;
+pub use template::*;
+pub use watch::*;
+pub use report::*;
+pub use diagram::*;
+pub use schedule::*;
+pub use loader::*;
+pub use progress::*;
+pub use reader::*;
+pub use memory::*;
+pub use stage::*;
+pub use outputs::*;
+pub use coercion::*;
+pub use interactive::*;
+pub use output_snapshot::*;
+pub use runner::*;
+pub use paraview::*;
+pub use audit::*;
+pub use expression::*;
+pub use migration::*;
@@ -1,275 +1,1498 @@
-//! Module system for computation and data processing
-
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-
-use crate::core::{
-    Object, ObjectId, ParameterSet, PortSet, ComputeContext,
-    MessageRouter, Message, MessageType, MessageEnvelope, MessagePayload,
-    ModuleInfo, ModuleStatus, ExecutionStats, ObjectRegistry,
-};
-
-/// Input data for a module port
-pub type InputPort = Vec<Arc<dyn Object>>;
-/// Output data from a module port
-pub type OutputPort = Vec<Arc<dyn Object>>;
-/// Collection of input ports
-pub type InputPorts = HashMap<String, InputPort>;
-/// Collection of output ports
-pub type OutputPorts = HashMap<String, OutputPort>;
-
-/// Core module trait that all Vistle modules must implement
-#[async_trait::async_trait]
-pub trait Module: Send + Sync {
-    /// Get module information
-    fn info(&self) -> &ModuleInfo;
-
-    /// Get module parameters
-    fn parameters(&self) -> &ParameterSet;
-
-    /// Get module ports
-    fn ports(&self) -> &PortSet;
-
-    /// Set input data for a specific port
-    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error>;
-
-    /// Execute the module's computation
-    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error>;
-
-    /// Cancel execution if possible
-    async fn cancel(&mut self) -> Result<(), crate::Error> {
-        Ok(())
-    }
-
-    /// Get execution statistics
-    fn stats(&self) -> &ExecutionStats;
-}
-
-/// Concrete module implementation
-pub struct VistleModule<M: Module> {
-    inner: M,
-    inputs: RwLock<InputPorts>,
-    status: RwLock<ModuleStatus>,
-    stats: RwLock<ExecutionStats>,
-}
-
-impl<M: Module> VistleModule<M> {
-    pub fn new(module: M) -> Self {
-        let stats = ExecutionStats::new(module.info().id);
-        Self {
-            inner: module,
-            inputs: RwLock::new(HashMap::new()),
-            status: RwLock::new(ModuleStatus::Initializing),
-            stats: RwLock::new(stats),
-        }
-    }
-
-    pub async fn set_input(&self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
-        // Validate port exists
-        if self.inner.ports().get(port_name).is_none() {
-            return Err(crate::Error::Module(format!("Port {} not found", port_name)));
-        }
-
-        let mut inputs = self.inputs.write().await;
-        inputs.insert(port_name.to_string(), objects);
-        Ok(())
-    }
-
-    pub async fn execute(&self, ctx: &ComputeContext, router: &MessageRouter) -> Result<(), crate::Error> {
-        // Update status
-        *self.status.write().await = ModuleStatus::Executing;
-
-        // Send execution started message
-        let start_msg = Message::new(
-            self.inner.info().id,
-            0, // broadcast
-            MessageType::Execute {
-                module_id: self.inner.info().id,
-                timestep: ctx.timestep,
-            },
-        );
-        router.route_message(MessageEnvelope {
-            message: start_msg,
-            payload: MessagePayload::None,
-        }).await?;
-
-        // Perform computation
-        let inputs = self.inputs.read().await.clone();
-        let result = self.inner.compute(ctx).await;
-
-        // Update statistics
-        let mut stats = self.stats.write().await;
-        match &result {
-            Ok(outputs) => {
-                stats.increment_processed();
-                for objects in outputs.values() {
-                    stats.objects_created += objects.len();
-                }
-                *self.status.write().await = ModuleStatus::Completed;
-            }
-            Err(e) => {
-                stats.add_error(e.to_string());
-                *self.status.write().await = ModuleStatus::Error;
-            }
-        }
-
-        // Send completion message
-        let complete_msg = Message::new(
-            self.inner.info().id,
-            0,
-            MessageType::ComputationComplete {
-                module_id: self.inner.info().id,
-                objects_created: stats.objects_created as Vec<ObjectId>,
-            },
-        );
-        router.route_message(MessageEnvelope {
-            message: complete_msg,
-            payload: MessagePayload::None,
-        }).await?;
-
-        result.map(|_| ())
-    }
-
-    pub async fn status(&self) -> ModuleStatus {
-        *self.status.read().await
-    }
-
-    pub async fn statistics(&self) -> ExecutionStats {
-        self.stats.read().await.clone()
-    }
-}
-
-/// Module registry for dynamic loading
-pub struct ModuleRegistry {
-    modules: RwLock<HashMap<String, Box<dyn Fn() -> Box<dyn Module> + Send + Sync>>>,
-    instances: RwLock<HashMap<u32, Arc<dyn std::any::Any + Send + Sync>>>,
-}
-
-impl ModuleRegistry {
-    pub fn new() -> Self {
-        Self {
-            modules: RwLock::new(HashMap::new()),
-            instances: RwLock::new(HashMap::new()),
-        }
-    }
-
-    pub async fn register<M: Module + 'static, F>(&self, name: &str, constructor: F)
-    where
-        F: Fn() -> M + Send + Sync + 'static,
-    {
-        let constructor = Box::new(move || Box::new(constructor()) as Box<dyn Module>);
-        self.modules.write().await.insert(name.to_string(), constructor);
-    }
-
-    pub async fn create_instance(&self, name: &str, id: u32) -> Result<Arc<VistleModule<Box<dyn Module>>>, crate::Error> {
-        let modules = self.modules.read().await;
-        let constructor = modules.get(name)
-            .ok_or_else(|| crate::Error::Module(format!("Module {} not found", name)))?;
-
-        let module = constructor();
-        let vistle_module = Arc::new(VistleModule::new(module));
-
-        self.instances.write().await.insert(id, vistle_module.clone());
-
-        Ok(vistle_module)
-    }
-
-    pub async fn get_instance(&self, id: u32) -> Option<Arc<VistleModule<Box<dyn Module>>>> {
-        self.instances.read().await.get(&id)
-            .and_then(|instance| instance.downcast_ref::<VistleModule<Box<dyn Module>>>()
-                .map(|m| Arc::new(m.clone())))
-    }
-
-    pub async fn list_available(&self) -> Vec<String> {
-        self.modules.read().await.keys().cloned().collect()
-    }
-}
-
-impl Default for ModuleRegistry {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-/// Module factory for creating common module types
-pub struct ModuleFactory;
-
-impl ModuleFactory {
-    /// Create a data source module that generates test data
-    pub fn create_data_source(id: u32, name: &str) -> Result<Box<dyn Module>, crate::Error> {
-        // This would be implemented with actual module logic
-        // For now, return a placeholder
-        Err(crate::Error::Module("Data source module not implemented".to_string()))
-    }
-
-    /// Create a filter module that processes data
-    pub fn create_filter(id: u32, name: &str) -> Result<Box<dyn Module>, crate::Error> {
-        Err(crate::Error::Module("Filter module not implemented".to_string()))
-    }
-
-    /// Create a renderer module for visualization
-    pub fn create_renderer(id: u32, name: &str) -> Result<Box<dyn Module>, crate::Error> {
-        Err(crate::Error::Module("Renderer module not implemented".to_string()))
-    }
-}
-
-/// Helper macro for implementing the Module trait
-#[macro_export]
-macro_rules! vistle_module {
-    ($name:ident, $desc:expr) => {
-        impl $name {
-            pub fn new(id: u32) -> Self {
-                let info = ModuleInfo::new(id, stringify!($name), 0, 1);
-                let mut params = ParameterSet::new();
-                let mut ports = PortSet::new();
-
-                Self::setup_parameters(&mut params);
-                Self::setup_ports(&mut ports);
-
-                Self {
-                    info,
-                    parameters: params,
-                    ports,
-                    inputs: HashMap::new(),
-                }
-            }
-
-            fn setup_parameters(params: &mut ParameterSet) {
-                // Override in implementation
-            }
-
-            fn setup_ports(ports: &mut PortSet) {
-                // Override in implementation
-            }
-        }
-
-        #[async_trait::async_trait]
-        impl Module for $name {
-            fn info(&self) -> &ModuleInfo {
-                &self.info
-            }
-
-            fn parameters(&self) -> &ParameterSet {
-                &self.parameters
-            }
-
-            fn ports(&self) -> &PortSet {
-                &self.ports
-            }
-
-            async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
-                self.inputs.insert(port_name.to_string(), objects);
-                Ok(())
-            }
-
-            async fn compute(&mut self, _ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
-                Err(crate::Error::Module("Compute not implemented".to_string()))
-            }
-
-            fn stats(&self) -> &ExecutionStats {
-                &self.stats
-            }
-        }
-    };
-}
+//! Module system for computation and data processing
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Once};
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, RwLock};
+use tokio_util::sync::CancellationToken;
+
+use crate::core::{
+    AttributePolicy, Object, ParameterSet, ParameterSnapshot, ParameterValue, PortSet, ComputeContext, VistleObject,
+    MessageRouter, Message, MessageType, MessageEnvelope, MessagePayload,
+    ModuleInfo, ModuleStatus, ExecutionStats, ExecutionPhase, cpu_timed,
+};
+
+/// Stores of an output into a full arena, see `VistleModule::store_outputs`
+const STORE_ATTEMPTS: u32 = 3;
+/// Wait before the first retry of a store, doubled for every further one
+const STORE_BACKOFF: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Input data for a module port
+pub type InputPort = Vec<Arc<dyn Object>>;
+/// Output data from a module port
+pub type OutputPort = Vec<Arc<dyn Object>>;
+/// Collection of input ports
+pub type InputPorts = HashMap<String, InputPort>;
+/// Collection of output ports
+pub type OutputPorts = HashMap<String, OutputPort>;
+
+/// Core module trait that all Vistle modules must implement
+///
+/// Inputs are read through the typed views of `Object`, which turn a
+/// missing or mismatched payload into `Error::WrongType`:
+///
+/// ```
+/// use vistle::{Error, Object};
+///
+/// fn surface_area(input: &dyn Object) -> Result<f32, Error> {
+///     let surface = input.as_triangles()
+///         .ok_or_else(|| Error::wrong_type("triangles", input))?;
+///     Ok((0..surface.num_triangles()).map(|i| surface.area(i)).sum())
+/// }
+///
+/// fn scalar_range(input: &dyn Object) -> Result<Option<(f32, f32)>, Error> {
+///     let field = input.as_scalar_field()
+///         .ok_or_else(|| Error::wrong_type("scalar field", input))?;
+///     Ok(field.range())
+/// }
+/// ```
+#[async_trait::async_trait]
+pub trait Module: Send + Sync {
+    /// Get module information
+    fn info(&self) -> &ModuleInfo;
+
+    /// Get module parameters
+    ///
+    /// These are the defaults; `compute` reads the values in effect through
+    /// `ComputeContext::parameters`.
+    fn parameters(&self) -> &ParameterSet;
+
+    /// Get module ports
+    fn ports(&self) -> &PortSet;
+
+    /// Set input data for a specific port
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error>;
+
+    /// Execute the module's computation
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error>;
+
+    /// Whether a change of `name` should reach a computation already running
+    ///
+    /// Changes otherwise apply from the next execution on. Accepted changes
+    /// show in `ComputeContext::live_parameters`, never in `parameters`.
+    fn parameter_changed(&self, _name: &str) -> bool {
+        false
+    }
+
+    /// Whether `compute` resolves placeholder inputs itself with `ComputeContext::resolve`
+    ///
+    /// Otherwise all placeholders are loaded before `compute` runs.
+    fn resolves_lazily(&self) -> bool {
+        false
+    }
+
+    /// Cancel execution if possible
+    async fn cancel(&mut self) -> Result<(), crate::Error> {
+        Ok(())
+    }
+
+    /// Attributes outputs inherit from the input they derive from
+    ///
+    /// The source of an output is the input port named by its
+    /// `Port::derived_from`, or the only input port of a single-input module.
+    /// Return `AttributePolicy::None` to take full control of output attributes.
+    fn attribute_policy(&self) -> AttributePolicy {
+        AttributePolicy::Inherit
+    }
+
+    /// Files an execution with these parameters writes, for writer modules
+    ///
+    /// Paths are returned as given in the parameters, unresolved; see
+    /// `compute::outputs` for the placeholders standing for file sequences.
+    fn planned_outputs(&self, _parameters: &ParameterSet) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Get execution statistics
+    fn stats(&self) -> &ExecutionStats;
+}
+
+/// Boxed modules, as created by the `ModuleRegistry`, are modules too
+#[async_trait::async_trait]
+impl<M: Module + ?Sized> Module for Box<M> {
+    fn info(&self) -> &ModuleInfo {
+        (**self).info()
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        (**self).parameters()
+    }
+
+    fn ports(&self) -> &PortSet {
+        (**self).ports()
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        (**self).set_input(port_name, objects).await
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        (**self).compute(ctx).await
+    }
+
+    fn parameter_changed(&self, name: &str) -> bool {
+        (**self).parameter_changed(name)
+    }
+
+    fn resolves_lazily(&self) -> bool {
+        (**self).resolves_lazily()
+    }
+
+    async fn cancel(&mut self) -> Result<(), crate::Error> {
+        (**self).cancel().await
+    }
+
+    fn attribute_policy(&self) -> AttributePolicy {
+        (**self).attribute_policy()
+    }
+
+    fn planned_outputs(&self, parameters: &ParameterSet) -> Vec<PathBuf> {
+        (**self).planned_outputs(parameters)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        (**self).stats()
+    }
+}
+
+/// Concrete module implementation
+///
+/// `Module::compute` takes the module mutably, so it sits behind a lock held
+/// for the execution; what the wrapper needs while it runs is read once
+/// when it is created.
+pub struct VistleModule<M: Module> {
+    inner: tokio::sync::Mutex<M>,
+    info: ModuleInfo,
+    ports: PortSet,
+    resolves_lazily: bool,
+    attribute_policy: AttributePolicy,
+    /// Parameters whose changes reach a running computation, see `Module::parameter_changed`
+    live: HashSet<String>,
+    inputs: RwLock<InputPorts>,
+    status: RwLock<ModuleStatus>,
+    stats: RwLock<ExecutionStats>,
+    strict_ports: bool,
+    /// Values the next execution starts with
+    parameters: Mutex<ParameterSet>,
+    /// Accepted mid-execution changes for the running computation
+    live_parameters: watch::Sender<ParameterSnapshot>,
+    /// Cancelled when the module's type is unloaded from under it
+    retired: CancellationToken,
+}
+
+impl<M: Module> VistleModule<M> {
+    pub fn new(module: M) -> Self {
+        let stats = ExecutionStats::new(module.info().id);
+        let parameters = module.parameters().clone();
+        let (live_parameters, _) = watch::channel(parameters.snapshot());
+        Self {
+            info: module.info().clone(),
+            ports: module.ports().clone(),
+            resolves_lazily: module.resolves_lazily(),
+            attribute_policy: module.attribute_policy(),
+            live: parameters.names().into_iter().filter(|name| module.parameter_changed(name)).collect(),
+            inner: tokio::sync::Mutex::new(module),
+            inputs: RwLock::new(HashMap::new()),
+            status: RwLock::new(ModuleStatus::Initializing),
+            stats: RwLock::new(stats),
+            strict_ports: false,
+            parameters: Mutex::new(parameters),
+            live_parameters,
+            retired: CancellationToken::new(),
+        }
+    }
+
+    /// Cancel the running execution and refuse further ones
+    pub fn retire(&self) {
+        self.retired.cancel();
+    }
+
+    pub fn is_retired(&self) -> bool {
+        self.retired.is_cancelled()
+    }
+
+    /// Change a parameter for the next execution
+    ///
+    /// A running execution keeps its snapshot; it only sees the change
+    /// through `live_parameters` if the module's `parameter_changed` accepts it.
+    pub fn set_parameter(&self, name: &str, value: ParameterValue) -> Result<(), crate::Error> {
+        let mut parameters = self.parameters.lock();
+        parameters.set_value(name, value).map_err(|e| crate::Error::Module(format!(
+            "Module {} ({}): {}",
+            self.info.name, self.info.id, e
+        )))?;
+        if self.live.contains(name) {
+            self.live_parameters.send_replace(parameters.snapshot());
+        }
+        Ok(())
+    }
+
+    /// Change a parameter from its text form, as stored in workflow specs
+    pub fn set_parameter_str(&self, name: &str, text: &str) -> Result<(), crate::Error> {
+        let info = &self.info;
+        let param_type = self.parameters.lock().get(name)
+            .map(|p| p.param_type.clone())
+            .ok_or_else(|| crate::Error::Module(format!("Module {} ({}): Parameter {} not found", info.name, info.id, name)))?;
+        let value = ParameterValue::parse(&param_type, text).map_err(|e| {
+            let reason = match e {
+                crate::Error::Config(reason) => reason,
+                e => e.to_string(),
+            };
+            crate::Error::Config(format!("Module {} ({}), parameter {}: {}", info.name, info.id, name, reason))
+        })?;
+        self.set_parameter(name, value)
+    }
+
+    /// Parameter values the next execution will start with
+    pub fn parameters(&self) -> ParameterSnapshot {
+        self.parameters.lock().snapshot()
+    }
+
+    /// Ports the module declares
+    pub fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    pub fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    /// Files the next execution writes, see `Module::planned_outputs`
+    pub async fn planned_outputs(&self) -> Vec<PathBuf> {
+        let parameters = self.parameters.lock().clone();
+        self.inner.lock().await.planned_outputs(&parameters)
+    }
+
+    /// Fail executions whose outputs do not match the declared output ports
+    ///
+    /// Without strict mode mismatches are only logged.
+    pub fn with_strict_ports(mut self, strict: bool) -> Self {
+        self.strict_ports = strict;
+        self
+    }
+
+    /// Check returned output names against the declared output ports
+    fn check_declared_outputs(&self, outputs: &OutputPorts) -> Result<(), crate::Error> {
+        let info = &self.info;
+        let mut declared = self.ports.outputs();
+        declared.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut unknown: Vec<&String> = outputs.keys()
+            .filter(|name| !declared.iter().any(|p| &p.name == *name))
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort();
+            let valid = declared.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ");
+            let message = format!(
+                "Module {} ({}) returned undeclared output ports {:?}; declared outputs are: {}",
+                info.name, info.id, unknown, valid
+            );
+            if self.strict_ports {
+                return Err(crate::Error::Module(message));
+            }
+            tracing::warn!("{}", message);
+        }
+
+        // "Nothing" is an empty object carrying block and timestep, not a missing port
+        for port in declared.iter().filter(|p| outputs.get(&p.name).is_none_or(|objects| objects.is_empty())) {
+            if port.optional {
+                tracing::debug!("Module {} ({}) produced no data on optional port {}", info.name, info.id, port.name);
+            } else if self.strict_ports {
+                return Err(crate::Error::Module(format!(
+                    "Module {} ({}) produced no object on required output port {}; emit an empty object instead",
+                    info.name, info.id, port.name
+                )));
+            } else {
+                tracing::warn!(
+                    "Module {} ({}) produced no object on required output port {}; emit an empty object instead",
+                    info.name, info.id, port.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn set_input(&self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        // Validate port exists
+        if self.ports.get(port_name).is_none() {
+            return Err(crate::Error::Module(format!("Port {} not found", port_name)));
+        }
+
+        let started = std::time::Instant::now();
+        let mut inputs = self.inputs.write().await;
+        inputs.insert(port_name.to_string(), objects);
+        drop(inputs);
+
+        self.stats.write().await.record_phase(ExecutionPhase::SetInput, started.elapsed());
+        Ok(())
+    }
+
+    /// Run the module, returning its outputs with inherited attributes applied
+    pub async fn execute(&self, ctx: &ComputeContext, router: &MessageRouter) -> Result<OutputPorts, crate::Error> {
+        let info = &self.info;
+        if self.is_retired() {
+            return Err(crate::Error::Cancelled(format!("module {} ({}), its type was unloaded", info.name, info.id)));
+        }
+
+        // Retiring the module cancels this execution as well as the caller's token does
+        let cancellation = ctx.cancellation().child_token();
+        let _done = cancellation.clone().drop_guard();
+        let (retired, execution) = (self.retired.clone(), cancellation.clone());
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = retired.cancelled() => execution.cancel(),
+                _ = execution.cancelled() => {}
+            }
+        });
+
+        // Later parameter changes must not alter this execution's view
+        let snapshot = self.parameters();
+        self.live_parameters.send_replace(snapshot.clone());
+        let ctx = &ctx.clone()
+            .with_cancellation(cancellation)
+            .with_parameters(snapshot)
+            .with_live_parameters(self.live_parameters.subscribe());
+
+        // Update status
+        *self.status.write().await = ModuleStatus::Executing;
+        let started = std::time::Instant::now();
+
+        // Send execution started message
+        let start_msg = Message::new(
+            self.info.id,
+            0, // broadcast
+            MessageType::Execute {
+                module_id: self.info.id,
+                timestep: ctx.timestep,
+            },
+        );
+        // Status messages are informational; nobody listening must not fail the execution
+        if let Err(e) = router.route_message(MessageEnvelope {
+            message: start_msg,
+            payload: MessagePayload::None,
+        }).await {
+            tracing::debug!("Module {} ({}): start not routed: {}", info.name, info.id, e);
+        }
+
+        // Eager modules see only complete objects; failing to load them fails the execution like a compute error
+        let resolved = if self.resolves_lazily {
+            Ok(())
+        } else {
+            self.resolve_inputs(ctx).await
+        };
+
+        // Perform computation
+        let inputs = self.inputs.read().await.clone();
+        let compute_started = std::time::Instant::now();
+        let (result, cpu_time) = match resolved {
+            Ok(()) => {
+                // A panicking module fails its own execution instead of taking the task down
+                let mut inner = self.inner.lock().await;
+                cpu_timed(catch_panic(async {
+                    for (port, objects) in &inputs {
+                        inner.set_input(port, objects.clone()).await?;
+                    }
+                    inner.compute(ctx).await
+                })).await
+            }
+            Err(e) => (Err(e), None),
+        };
+        let result = result
+            .and_then(|outputs| self.check_declared_outputs(&outputs).map(|_| outputs))
+            .map(|outputs| self.inherit_attributes(&inputs, outputs));
+        let result = match result {
+            Ok(outputs) => Self::store_outputs(ctx, outputs).await,
+            Err(e) => Err(e),
+        };
+        let compute_time = compute_started.elapsed();
+
+        // Update statistics
+        let mut stats = self.stats.write().await;
+        stats.record_phase(ExecutionPhase::Compute, compute_time);
+        stats.record_invocation(started.elapsed(), cpu_time);
+        stats.mark_complete();
+        match &result {
+            Ok(outputs) => {
+                stats.increment_processed();
+                for objects in outputs.values() {
+                    stats.objects_created += objects.len();
+                }
+                *self.status.write().await = ModuleStatus::Completed;
+            }
+            Err(e) => {
+                stats.record_error(ctx.timestep, e);
+                *self.status.write().await = ModuleStatus::Error;
+            }
+        }
+
+        // Send completion message
+        let complete_msg = Message::new(
+            self.info.id,
+            0,
+            MessageType::ComputationComplete {
+                module_id: self.info.id,
+                objects_created: result.iter()
+                    .flat_map(|outputs| outputs.values().flatten())
+                    .map(|object| object.id())
+                    .collect(),
+            },
+        );
+        if let Err(e) = router.route_message(MessageEnvelope {
+            message: complete_msg,
+            payload: MessagePayload::None,
+        }).await {
+            tracing::debug!("Module {} ({}): completion not routed: {}", info.name, info.id, e);
+        }
+
+        result
+    }
+
+    /// Store outputs in the context's arena, failing once its owner's quota runs out
+    ///
+    /// A full arena is retried after the object registry turned resolved
+    /// placeholders back into placeholders, see `ObjectRegistry::make_room`.
+    async fn store_outputs(ctx: &ComputeContext, outputs: OutputPorts) -> Result<OutputPorts, crate::Error> {
+        if let Some(arena) = ctx.arena() {
+            for object in outputs.values().flatten() {
+                arena.store_object_with_retry(object.clone(), STORE_ATTEMPTS, STORE_BACKOFF, |full| {
+                    ctx.objects().is_some_and(|objects| objects.make_room(full))
+                }).await?;
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Replace placeholder inputs by their loaded objects, loading ahead as for series
+    async fn resolve_inputs(&self, ctx: &ComputeContext) -> Result<(), crate::Error> {
+        let mut inputs = self.inputs.write().await;
+        for objects in inputs.values_mut() {
+            if objects.iter().all(|o| o.is_complete()) {
+                continue;
+            }
+            let mut series = ctx.resolve_series(objects.clone());
+            for object in objects.iter_mut() {
+                if let Some(resolved) = series.next().await {
+                    *object = resolved?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Propagate attributes from each output's source input to its objects
+    fn inherit_attributes(&self, inputs: &InputPorts, outputs: OutputPorts) -> OutputPorts {
+        let policy = &self.attribute_policy;
+        if *policy == AttributePolicy::None {
+            return outputs;
+        }
+
+        let ports = &self.ports;
+        outputs.into_iter()
+            .map(|(port, objects)| {
+                let sources = attribute_source(ports, &port).and_then(|source| inputs.get(&source));
+                let Some(sources) = sources.filter(|sources| !sources.is_empty()) else {
+                    return (port, objects);
+                };
+                let objects = objects.into_iter()
+                    .enumerate()
+                    .map(|(i, object)| match object.as_data() {
+                        Some(data) => {
+                            // Object i of an output comes from block i of its source
+                            let source = sources.get(i).unwrap_or(&sources[0]);
+                            let mut data = data.clone();
+                            data.inherit_from(source.as_ref(), policy);
+                            Arc::new(VistleObject::from_data(data)) as Arc<dyn Object>
+                        }
+                        None => object,
+                    })
+                    .collect();
+                (port, objects)
+            })
+            .collect()
+    }
+
+    pub async fn status(&self) -> ModuleStatus {
+        *self.status.read().await
+    }
+
+    pub async fn statistics(&self) -> ExecutionStats {
+        self.stats.read().await.clone()
+    }
+}
+
+/// Input port an output inherits attributes from
+///
+/// Modules with several inputs name the source with `Port::derived_from`;
+/// without it nothing is inherited, so a field's attributes never end up on
+/// a geometry output by accident.
+fn attribute_source(ports: &PortSet, output: &str) -> Option<String> {
+    if let Some(source) = ports.get(output).and_then(|port| port.derived_from.clone()) {
+        return Some(source);
+    }
+    match ports.inputs().as_slice() {
+        [only] => Some(only.name.clone()),
+        _ => None,
+    }
+}
+
+thread_local! {
+    /// Backtrace of the last panic on this thread, taken by `catch_panic`
+    static PANIC_BACKTRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Install a panic hook recording backtraces for `catch_panic`, keeping the previous hook
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let backtrace = std::backtrace::Backtrace::capture();
+            let captured = (backtrace.status() == std::backtrace::BacktraceStatus::Captured)
+                .then(|| backtrace.to_string());
+            PANIC_BACKTRACE.with(|b| *b.borrow_mut() = captured);
+            previous(info);
+        }));
+    });
+}
+
+/// Error message for a caught panic payload
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    let text = payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string());
+    format!("panicked: {}", text)
+}
+
+/// Await a module future, turning a panic into `Error::Module`
+///
+/// With RUST_BACKTRACE set the message includes where the panic happened;
+/// the panic is caught on the thread that raised it, so the backtrace the
+/// hook recorded is still in the thread-local.
+///
+/// This only works while panics unwind; the release profile in Cargo.toml
+/// must not set `panic = "abort"`.
+async fn catch_panic<F>(future: F) -> Result<OutputPorts, crate::Error>
+where
+    F: std::future::Future<Output = Result<OutputPorts, crate::Error>>,
+{
+    install_panic_hook();
+    match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(result) => result,
+        Err(payload) => {
+            let mut message = panic_message(payload.as_ref());
+            if let Some(backtrace) = PANIC_BACKTRACE.with(|b| b.borrow_mut().take()) {
+                message.push('\n');
+                message.push_str(&backtrace);
+            }
+            Err(crate::Error::Module(message))
+        }
+    }
+}
+
+/// Bytes from the start of a file that sniffers get to see, zero-padded for short files
+pub const SNIFF_LEN: usize = 512;
+
+/// Files a reader module can open, by extension or by content
+#[derive(Debug, Clone)]
+pub struct FileMatcher {
+    /// Lowercase extensions without the dot
+    pub extensions: Vec<String>,
+    /// Recognizes the format from the first `SNIFF_LEN` bytes
+    pub sniff: Option<fn(&[u8; SNIFF_LEN]) -> bool>,
+}
+
+impl FileMatcher {
+    pub fn extensions(extensions: &[&str]) -> Self {
+        Self {
+            extensions: extensions.iter().map(|e| e.trim_start_matches('.').to_ascii_lowercase()).collect(),
+            sniff: None,
+        }
+    }
+
+    pub fn with_sniffer(mut self, sniff: fn(&[u8; SNIFF_LEN]) -> bool) -> Self {
+        self.sniff = Some(sniff);
+        self
+    }
+
+    pub fn matches_extension(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| self.extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+    }
+
+    pub fn matches_content(&self, header: &[u8; SNIFF_LEN]) -> bool {
+        self.sniff.is_some_and(|sniff| sniff(header))
+    }
+}
+
+/// What the registry knows about a module type besides its constructor
+#[derive(Debug, Clone)]
+pub struct ModuleDescriptor {
+    pub name: String,
+    pub category: String,
+    /// Files the module reads; empty for modules that are not readers
+    pub file_matchers: Vec<FileMatcher>,
+    /// Parameter a reader takes its file name from
+    pub file_parameter: String,
+}
+
+impl ModuleDescriptor {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            category: "General".to_string(),
+            file_matchers: Vec::new(),
+            file_parameter: "filename".to_string(),
+        }
+    }
+
+    /// Descriptor of a reader in the "Read" category
+    pub fn reader(name: &str, matcher: FileMatcher) -> Self {
+        Self::new(name).with_category("Read").with_file_matcher(matcher)
+    }
+
+    pub fn with_category(mut self, category: &str) -> Self {
+        self.category = category.to_string();
+        self
+    }
+
+    pub fn with_file_matcher(mut self, matcher: FileMatcher) -> Self {
+        self.file_matchers.push(matcher);
+        self
+    }
+
+    pub fn with_file_parameter(mut self, name: &str) -> Self {
+        self.file_parameter = name.to_string();
+        self
+    }
+
+    pub fn is_reader(&self) -> bool {
+        !self.file_matchers.is_empty()
+    }
+}
+
+/// What happens to live instances of module types being unloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnloadPolicy {
+    /// Refuse to unload while any instance is still in use
+    #[default]
+    Refuse,
+    /// Cancel running executions of the instances and drop them
+    Cancel,
+}
+
+/// A registered constructor and the version it was registered as
+struct Registration {
+    constructor: Box<dyn Fn() -> Box<dyn Module> + Send + Sync>,
+    version: u64,
+}
+
+struct Instance {
+    module: Arc<VistleModule<Box<dyn Module>>>,
+    module_type: String,
+    version: u64,
+}
+
+impl Instance {
+    /// Whether anything besides the registry still holds the instance
+    fn in_use(&self) -> bool {
+        Arc::strong_count(&self.module) > 1
+    }
+}
+
+/// An instance as listed by `ModuleRegistry::list_instances`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceInfo {
+    pub id: u32,
+    pub module_type: String,
+    /// Registration the instance was created from
+    pub version: u64,
+    /// Current registration of its type, None if the type was unloaded
+    pub current_version: Option<u64>,
+}
+
+impl InstanceInfo {
+    /// Created from an earlier registration of its type than the current one
+    pub fn is_stale(&self) -> bool {
+        self.current_version != Some(self.version)
+    }
+}
+
+/// Module registry for dynamic loading
+///
+/// Every registration gets a version, increasing across the registry, and
+/// instances remember the version they were created from. Registering a type
+/// again replaces its constructor for new instances, while existing ones keep
+/// running the module they were built with until they are dropped.
+pub struct ModuleRegistry {
+    modules: RwLock<HashMap<String, Registration>>,
+    descriptors: RwLock<HashMap<String, ModuleDescriptor>>,
+    instances: RwLock<HashMap<u32, Instance>>,
+    last_version: AtomicU64,
+}
+
+impl ModuleRegistry {
+    pub fn new() -> Self {
+        Self {
+            modules: RwLock::new(HashMap::new()),
+            descriptors: RwLock::new(HashMap::new()),
+            instances: RwLock::new(HashMap::new()),
+            last_version: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn register<M: Module + 'static, F>(&self, name: &str, constructor: F)
+    where
+        F: Fn() -> M + Send + Sync + 'static,
+    {
+        self.register_described(ModuleDescriptor::new(name), constructor).await;
+    }
+
+    /// Register a module type with its descriptor, e.g. a reader with its file matchers
+    pub async fn register_described<M: Module + 'static, F>(&self, descriptor: ModuleDescriptor, constructor: F)
+    where
+        F: Fn() -> M + Send + Sync + 'static,
+    {
+        let constructor = Box::new(move || Box::new(constructor()) as Box<dyn Module>);
+        let version = self.last_version.fetch_add(1, Ordering::Relaxed) + 1;
+        let replaced = self.modules.write().await
+            .insert(descriptor.name.clone(), Registration { constructor, version });
+        if let Some(replaced) = replaced {
+            tracing::info!(
+                "Module type {} registered again as version {}, instances of version {} keep running",
+                descriptor.name, version, replaced.version
+            );
+        }
+        self.descriptors.write().await.insert(descriptor.name.clone(), descriptor);
+    }
+
+    /// Version of the current registration of a module type
+    pub async fn version(&self, name: &str) -> Option<u64> {
+        self.modules.read().await.get(name).map(|r| r.version)
+    }
+
+    /// Unregister module types, e.g. all types of a plugin being unloaded
+    ///
+    /// Instances nothing but the registry holds are dropped. With instances
+    /// still in use, `UnloadPolicy::Refuse` fails without unregistering
+    /// anything, and `UnloadPolicy::Cancel` retires and drops them. Returns
+    /// the ids of the dropped instances.
+    pub async fn unload(&self, names: &[&str], policy: UnloadPolicy) -> Result<Vec<u32>, crate::Error> {
+        let mut modules = self.modules.write().await;
+        let mut instances = self.instances.write().await;
+        if let Some(unknown) = names.iter().find(|name| !modules.contains_key(**name)) {
+            return Err(crate::Error::Module(format!("Module {} not found", unknown)));
+        }
+
+        let mut dropped: Vec<u32> = instances.iter()
+            .filter(|(_, instance)| names.contains(&instance.module_type.as_str()))
+            .map(|(id, _)| *id)
+            .collect();
+        dropped.sort();
+        let in_use: Vec<String> = dropped.iter()
+            .filter_map(|id| instances.get(id).filter(|i| i.in_use()).map(|i| format!("{} ({})", id, i.module_type)))
+            .collect();
+        if !in_use.is_empty() && policy == UnloadPolicy::Refuse {
+            return Err(crate::Error::Config(format!(
+                "Cannot unload {} while instances are in use: {}",
+                names.join(", "), in_use.join(", ")
+            )));
+        }
+
+        for id in &dropped {
+            if let Some(instance) = instances.remove(id) {
+                instance.module.retire();
+            }
+        }
+        for name in names {
+            modules.remove(*name);
+        }
+        drop(instances);
+        drop(modules);
+        let mut descriptors = self.descriptors.write().await;
+        for name in names {
+            descriptors.remove(*name);
+        }
+        if !in_use.is_empty() {
+            tracing::warn!("Unloaded {}, cancelling instances {}", names.join(", "), in_use.join(", "));
+        }
+        Ok(dropped)
+    }
+
+    pub async fn descriptor(&self, name: &str) -> Option<ModuleDescriptor> {
+        self.descriptors.read().await.get(name).cloned()
+    }
+
+    /// Reader module for a file, see `select_reader`
+    ///
+    /// The file is only opened if its extension does not settle the choice.
+    pub async fn reader_for(&self, path: impl AsRef<Path>) -> Result<Option<String>, crate::Error> {
+        let path = path.as_ref();
+        let readers: Vec<ModuleDescriptor> = self.descriptors.read().await.values()
+            .filter(|d| d.is_reader())
+            .cloned()
+            .collect();
+        super::select_reader(&readers, path, || super::read_file_header(path)).await
+    }
+
+    pub async fn create_instance(&self, name: &str, id: u32) -> Result<Arc<VistleModule<Box<dyn Module>>>, crate::Error> {
+        let modules = self.modules.read().await;
+        let registration = modules.get(name)
+            .ok_or_else(|| crate::Error::Module(format!("Module {} not found", name)))?;
+
+        let module = (registration.constructor)();
+        let vistle_module = Arc::new(VistleModule::new(module));
+
+        self.instances.write().await.insert(id, Instance {
+            module: vistle_module.clone(),
+            module_type: name.to_string(),
+            version: registration.version,
+        });
+
+        Ok(vistle_module)
+    }
+
+    /// A module not kept as an instance, e.g. to plan its outputs
+    pub async fn create_detached(&self, name: &str) -> Result<VistleModule<Box<dyn Module>>, crate::Error> {
+        let modules = self.modules.read().await;
+        let registration = modules.get(name)
+            .ok_or_else(|| crate::Error::Module(format!("Module {} not found", name)))?;
+        Ok(VistleModule::new((registration.constructor)()))
+    }
+
+    pub async fn get_instance(&self, id: u32) -> Option<Arc<VistleModule<Box<dyn Module>>>> {
+        self.instances.read().await.get(&id).map(|instance| instance.module.clone())
+    }
+
+    /// Instances by id with the registration they were created from
+    pub async fn list_instances(&self) -> Vec<InstanceInfo> {
+        let modules = self.modules.read().await;
+        let mut instances: Vec<InstanceInfo> = self.instances.read().await.iter()
+            .map(|(id, instance)| InstanceInfo {
+                id: *id,
+                module_type: instance.module_type.clone(),
+                version: instance.version,
+                current_version: modules.get(&instance.module_type).map(|r| r.version),
+            })
+            .collect();
+        instances.sort_by_key(|i| i.id);
+        instances
+    }
+
+    /// Forget an instance, e.g. a reader created only to load one object
+    pub async fn remove_instance(&self, id: u32) -> bool {
+        self.instances.write().await.remove(&id).is_some()
+    }
+
+    pub async fn list_available(&self) -> Vec<String> {
+        self.modules.read().await.keys().cloned().collect()
+    }
+}
+
+impl Default for ModuleRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Module factory for creating common module types
+pub struct ModuleFactory;
+
+impl ModuleFactory {
+    /// Create a data source module that generates test data
+    pub fn create_data_source(_id: u32, _name: &str) -> Result<Box<dyn Module>, crate::Error> {
+        // This would be implemented with actual module logic
+        // For now, return a placeholder
+        Err(crate::Error::Module("Data source module not implemented".to_string()))
+    }
+
+    /// Create a filter module that processes data
+    pub fn create_filter(_id: u32, _name: &str) -> Result<Box<dyn Module>, crate::Error> {
+        Err(crate::Error::Module("Filter module not implemented".to_string()))
+    }
+
+    /// Create a renderer module for visualization
+    pub fn create_renderer(_id: u32, _name: &str) -> Result<Box<dyn Module>, crate::Error> {
+        Err(crate::Error::Module("Renderer module not implemented".to_string()))
+    }
+}
+
+/// Helper macro for implementing the Module trait
+#[macro_export]
+macro_rules! vistle_module {
+    ($name:ident, $desc:expr) => {
+        impl $name {
+            pub fn new(id: u32) -> Self {
+                let info = ModuleInfo::new(id, stringify!($name), 0, 1);
+                let mut params = ParameterSet::new();
+                let mut ports = PortSet::new();
+
+                Self::setup_parameters(&mut params);
+                Self::setup_ports(&mut ports);
+
+                Self {
+                    info,
+                    parameters: params,
+                    ports,
+                    inputs: HashMap::new(),
+                }
+            }
+
+            fn setup_parameters(params: &mut ParameterSet) {
+                // Override in implementation
+            }
+
+            fn setup_ports(ports: &mut PortSet) {
+                // Override in implementation
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl Module for $name {
+            fn info(&self) -> &ModuleInfo {
+                &self.info
+            }
+
+            fn parameters(&self) -> &ParameterSet {
+                &self.parameters
+            }
+
+            fn ports(&self) -> &PortSet {
+                &self.ports
+            }
+
+            async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), $crate::Error> {
+                self.inputs.insert(port_name.to_string(), objects);
+                Ok(())
+            }
+
+            async fn compute(&mut self, _ctx: &ComputeContext) -> Result<OutputPorts, $crate::Error> {
+                Err($crate::Error::Module("Compute not implemented".to_string()))
+            }
+
+            fn stats(&self) -> &ExecutionStats {
+                &self.stats
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::compute::testing::modules::{register_test_modules, ConstantField, Failing};
+    use crate::core::Port;
+    use crate::compute::{TaskExecutor, WorkflowBuilder, WorkflowExecutor};
+
+    #[test]
+    fn panic_messages_carry_the_payload() {
+        assert_eq!(panic_message(&"index out of bounds"), "panicked: index out of bounds");
+        assert_eq!(panic_message(&"formatted 3".to_string()), "panicked: formatted 3");
+        assert_eq!(panic_message(&42u32), "panicked: non-string panic payload");
+    }
+
+    #[tokio::test]
+    async fn a_panic_in_compute_becomes_a_module_error() {
+        let result = catch_panic(async {
+            let values: Vec<u32> = Vec::new();
+            std::hint::black_box(values[3]);
+            Ok(OutputPorts::new())
+        })
+        .await;
+        match result {
+            Err(crate::Error::Module(message)) => {
+                assert!(message.starts_with("panicked: index out of bounds"), "{}", message);
+            }
+            other => panic!("expected a module error, got {:?}", other.map(|_| ())),
+        }
+        assert!(catch_panic(async { Ok(OutputPorts::new()) }).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_panicking_module_ends_up_in_the_error_state() {
+        let module = VistleModule::new(Failing::new(2));
+        module.set_parameter("panic", ParameterValue::Bool(true)).unwrap();
+        let error = module.execute(&ComputeContext::new(2, 0, 1), &MessageRouter::new()).await.unwrap_err();
+        assert!(error.to_string().contains("panicked: Failing module 2 panicked as asked"), "{}", error);
+        assert_eq!(module.status().await, ModuleStatus::Error);
+
+        // The instance stays usable
+        let module = VistleModule::new(ConstantField::new(1));
+        assert!(module.execute(&ComputeContext::new(1, 0, 1), &MessageRouter::new()).await.is_ok());
+        assert_eq!(module.status().await, ModuleStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn outputs_over_the_workflow_quota_fail_the_execution() {
+        let manager = crate::core::ShmManager::new();
+        let config = |name: &str| crate::core::ShmConfig {
+            size: 1 << 20,
+            name: format!("vistle_test_{}_{}", name, uuid::Uuid::new_v4().simple()),
+            ..crate::core::ShmConfig::default()
+        };
+        manager.set_quota("over", 1);
+        let arena = manager.create_owned_arena("over", "over".to_string(), config("over")).unwrap();
+
+        let module = VistleModule::new(ConstantField::new(1));
+        let ctx = ComputeContext::new(1, 0, 1).with_arena(arena);
+        let error = module.execute(&ctx, &MessageRouter::new()).await.unwrap_err();
+        assert!(error.to_string().contains("quota exceeded"), "{}", error);
+        assert_eq!(module.status().await, ModuleStatus::Error);
+
+        // Within the quota the outputs are charged to the owner
+        let arena = manager.create_owned_arena("within", "within".to_string(), config("within")).unwrap();
+        let ctx = ComputeContext::new(1, 0, 1).with_arena(arena.clone());
+        module.execute(&ctx, &MessageRouter::new()).await.unwrap();
+        assert_eq!(arena.stats().object_count, 1);
+        assert!(manager.usage_by_owner()["within"].used > 0);
+    }
+
+    #[tokio::test]
+    async fn failing_to_resolve_inputs_is_an_execution_error() {
+        let module = VistleModule::new(ConstantField::new(1));
+        let loader = crate::core::Loader::new("Reader", "data_out");
+        let placeholder: Arc<dyn Object> = Arc::new(VistleObject::placeholder(crate::core::ObjectType::Vec, loader));
+        module.set_input("data_in", vec![placeholder]).await.unwrap();
+
+        // Without an object registry the placeholder cannot be loaded
+        let error = module.execute(&ComputeContext::new(1, 0, 1), &MessageRouter::new()).await.unwrap_err();
+        assert!(matches!(error, crate::Error::Config(_)), "{}", error);
+        assert_eq!(module.status().await, ModuleStatus::Error);
+        let stats = module.statistics().await;
+        assert_eq!(stats.invocations, 1);
+        assert_eq!(stats.recent_errors(1)[0].message, error.to_string());
+    }
+
+    #[tokio::test]
+    async fn full_arenas_evict_resolved_objects_before_failing_the_store() {
+        struct Reader;
+
+        #[async_trait::async_trait]
+        impl crate::core::ObjectLoader for Reader {
+            async fn load(&self, _loader: &crate::core::Loader) -> Result<Arc<dyn Object>, crate::Error> {
+                let data = ndarray::Array1::from_elem(16, 1.0f32);
+                Ok(Arc::new(VistleObject::with_data(crate::core::ObjectType::Vec, crate::core::ObjectPayload::VecScalar { data })))
+            }
+        }
+
+        let objects = Arc::new(crate::core::ObjectRegistry::new());
+        objects.set_loader(Arc::new(Reader));
+        let loader = crate::core::Loader::new("Reader", "data_out");
+        let id = objects.store(Arc::new(VistleObject::placeholder(crate::core::ObjectType::Vec, loader)));
+        objects.resolve(id).await.unwrap();
+
+        let arena = Arc::new(crate::core::SharedArena::new(crate::core::ShmConfig {
+            size: 64,
+            name: format!("vistle_test_room_{}", uuid::Uuid::new_v4().simple()),
+            ..crate::core::ShmConfig::default()
+        }).unwrap());
+        let module = VistleModule::new(ConstantField::new(1));
+        let ctx = ComputeContext::new(1, 0, 1).with_arena(arena).with_objects(objects.clone());
+        assert!(module.execute(&ctx, &MessageRouter::new()).await.is_err());
+        // The registry gave back what it could before the store gave up
+        assert!(!objects.get(id).unwrap().is_complete());
+        assert_eq!(objects.resolved_bytes(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn cpu_time_counts_work_on_both_sides_of_an_await() {
+        fn spin(duration: Duration) {
+            let started = std::time::Instant::now();
+            while started.elapsed() < duration {
+                std::hint::spin_loop();
+            }
+        }
+        let (_, cpu) = cpu_timed(async {
+            spin(Duration::from_millis(30));
+            tokio::task::yield_now().await;
+            spin(Duration::from_millis(30));
+        })
+        .await;
+        assert!(cpu.unwrap() >= Duration::from_millis(50), "{:?}", cpu);
+    }
+
+    #[test]
+    fn outputs_inherit_from_the_port_they_derive_from() {
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("grid_in", "Grid"));
+        ports.add(Port::new_input("data_in", "Field"));
+        ports.add(Port::new_output("grid_out", "Grid").derived_from("grid_in"));
+        ports.add(Port::new_output("data_out", "Field").derived_from("data_in"));
+        ports.add(Port::new_output("table_out", "Table"));
+        assert_eq!(attribute_source(&ports, "grid_out").as_deref(), Some("grid_in"));
+        assert_eq!(attribute_source(&ports, "data_out").as_deref(), Some("data_in"));
+        // Undeclared outputs of a module with several inputs inherit nothing
+        assert_eq!(attribute_source(&ports, "table_out"), None);
+
+        let mut single = PortSet::new();
+        single.add(Port::new_input("data_in", "Field"));
+        single.add(Port::new_output("data_out", "Field"));
+        assert_eq!(attribute_source(&single, "data_out").as_deref(), Some("data_in"));
+    }
+
+    #[tokio::test]
+    async fn attributes_follow_their_port_through_a_pipeline() {
+        use crate::compute::builtin::TransformGeometry;
+        use crate::core::{attribute, Mapping, ObjectPayload, ObjectType};
+        use ndarray::array;
+
+        let mut grid = VistleObject::with_data(ObjectType::Points, ObjectPayload::Points {
+            coordinates: array![[1.0f32, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        });
+        grid.set_attribute("name".to_string(), "probe cloud".to_string());
+        let mut field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecVec3 {
+            data: array![[1.0f32, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        })
+        .with_grid(&grid, Mapping::PerVertex);
+        field.set_attribute(attribute::MAPPING.to_string(), attribute::MAPPING_VERTEX.to_string());
+        field.set_attribute(attribute::SPECIES.to_string(), "velocity".to_string());
+
+        let mut grids: Vec<Arc<dyn Object>> = vec![Arc::new(grid)];
+        let mut fields: Vec<Arc<dyn Object>> = vec![Arc::new(field)];
+        for id in 1..=3 {
+            let module = VistleModule::new(TransformGeometry::new(id));
+            module.set_input("grid_in", grids).await.unwrap();
+            module.set_input("data_in", fields).await.unwrap();
+            let mut outputs = module.execute(&ComputeContext::new(id, 0, 1), &MessageRouter::new()).await.unwrap();
+            grids = outputs.remove("grid_out").unwrap();
+            fields = outputs.remove("data_out").unwrap();
+        }
+
+        let grid = &grids[0];
+        assert_eq!(grid.attributes().get("name").map(String::as_str), Some("probe cloud"));
+        // The field's attributes are never stamped onto the geometry
+        assert!(grid.attributes().get(attribute::MAPPING).is_none());
+        assert!(grid.attributes().get(attribute::SPECIES).is_none());
+
+        let field = &fields[0];
+        assert_eq!(field.attributes().get(attribute::SPECIES).map(String::as_str), Some("velocity"));
+        assert_eq!(field.attributes().get(attribute::MAPPING).map(String::as_str), Some(attribute::MAPPING_VERTEX));
+        assert!(field.attributes().get("name").is_none());
+    }
+
+    /// Reads `value` before and after a change made mid-compute, from the snapshot or the live view
+    struct ReadsTwice {
+        info: ModuleInfo,
+        parameters: ParameterSet,
+        ports: PortSet,
+        stats: ExecutionStats,
+        live: bool,
+        started: Arc<tokio::sync::Notify>,
+        proceed: Arc<tokio::sync::Notify>,
+    }
+
+    impl ReadsTwice {
+        fn new(live: bool) -> Self {
+            let mut parameters = ParameterSet::new();
+            parameters.add(crate::core::Parameter::new("value", "Value read twice", ParameterValue::Float(1.0)));
+            let mut ports = PortSet::new();
+            ports.add(Port::new_output("data_out", "Both reads"));
+            Self {
+                info: ModuleInfo::new(1, "ReadsTwice", 0, 1),
+                parameters,
+                ports,
+                stats: ExecutionStats::new(1),
+                live,
+                started: Arc::new(tokio::sync::Notify::new()),
+                proceed: Arc::new(tokio::sync::Notify::new()),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Module for ReadsTwice {
+        fn info(&self) -> &ModuleInfo {
+            &self.info
+        }
+
+        fn parameters(&self) -> &ParameterSet {
+            &self.parameters
+        }
+
+        fn ports(&self) -> &PortSet {
+            &self.ports
+        }
+
+        async fn set_input(&mut self, _port_name: &str, _objects: InputPort) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+            let first = ctx.parameters().get_float("value").unwrap();
+            self.started.notify_one();
+            self.proceed.notified().await;
+            let second = if self.live { ctx.live_parameters() } else { ctx.parameters().clone() };
+            let second = second.get_float("value").unwrap();
+
+            let reads = VistleObject::with_data(crate::core::ObjectType::Vec, crate::core::ObjectPayload::VecScalar {
+                data: ndarray::array![first, second],
+            });
+            Ok(OutputPorts::from([("data_out".to_string(), vec![Arc::new(reads) as Arc<dyn Object>])]))
+        }
+
+        fn parameter_changed(&self, _name: &str) -> bool {
+            self.live
+        }
+
+        fn stats(&self) -> &ExecutionStats {
+            &self.stats
+        }
+    }
+
+    /// Values `ReadsTwice` saw when `value` changed to 2 between its reads
+    async fn reads_around_a_change(module: &VistleModule<ReadsTwice>) -> Vec<f32> {
+        let (started, proceed) = {
+            let inner = module.inner.try_lock().unwrap();
+            (inner.started.clone(), inner.proceed.clone())
+        };
+        let change = async {
+            started.notified().await;
+            module.set_parameter("value", ParameterValue::Float(2.0)).unwrap();
+            proceed.notify_one();
+        };
+        let (ctx, router) = (ComputeContext::new(1, 0, 1), MessageRouter::new());
+        let (outputs, ()) = tokio::join!(module.execute(&ctx, &router), change);
+        let outputs = outputs.unwrap();
+        outputs["data_out"][0].as_scalar_field().unwrap().values().to_vec()
+    }
+
+    #[tokio::test]
+    async fn a_change_during_compute_waits_for_the_next_execution() {
+        let module = VistleModule::new(ReadsTwice::new(false));
+        assert_eq!(reads_around_a_change(&module).await, vec![1.0, 1.0]);
+        assert_eq!(module.parameters().get_float("value"), Some(2.0));
+
+        // The next execution starts from the changed value
+        module.inner.try_lock().unwrap().proceed.notify_one();
+        let outputs = module.execute(&ComputeContext::new(1, 0, 1), &MessageRouter::new()).await.unwrap();
+        assert_eq!(outputs["data_out"][0].as_scalar_field().unwrap().values().to_vec(), vec![2.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn modules_opting_into_live_updates_see_the_change() {
+        let module = VistleModule::new(ReadsTwice::new(true));
+        assert_eq!(reads_around_a_change(&module).await, vec![1.0, 2.0]);
+    }
+
+    /// Declares `data_out` and returns an object on `returns`, or nothing
+    struct Returns {
+        info: ModuleInfo,
+        parameters: ParameterSet,
+        ports: PortSet,
+        stats: ExecutionStats,
+        returns: Option<&'static str>,
+    }
+
+    impl Returns {
+        fn new(returns: Option<&'static str>, optional: bool) -> Self {
+            let mut ports = PortSet::new();
+            let port = Port::new_output("data_out", "Declared output");
+            ports.add(if optional { port.optional() } else { port });
+            Self {
+                info: ModuleInfo::new(1, "Returns", 0, 1),
+                parameters: ParameterSet::new(),
+                ports,
+                stats: ExecutionStats::new(1),
+                returns,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Module for Returns {
+        fn info(&self) -> &ModuleInfo {
+            &self.info
+        }
+
+        fn parameters(&self) -> &ParameterSet {
+            &self.parameters
+        }
+
+        fn ports(&self) -> &PortSet {
+            &self.ports
+        }
+
+        async fn set_input(&mut self, _port_name: &str, _objects: InputPort) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        async fn compute(&mut self, _ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+            let field = VistleObject::with_data(crate::core::ObjectType::Vec, crate::core::ObjectPayload::VecScalar {
+                data: ndarray::array![1.0],
+            });
+            Ok(self.returns.iter()
+                .map(|port| (port.to_string(), vec![Arc::new(field.clone()) as Arc<dyn Object>]))
+                .collect())
+        }
+
+        fn stats(&self) -> &ExecutionStats {
+            &self.stats
+        }
+    }
+
+    async fn execute(module: Returns, strict: bool) -> Result<OutputPorts, crate::Error> {
+        VistleModule::new(module).with_strict_ports(strict)
+            .execute(&ComputeContext::new(1, 0, 1), &MessageRouter::new())
+            .await
+    }
+
+    #[tokio::test]
+    async fn undeclared_output_ports_fail_strict_modules() {
+        let error = execute(Returns::new(Some("dataOut"), false), true).await.unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("undeclared output ports [\"dataOut\"]; declared outputs are: data_out"), "{}", message);
+
+        // Loose modules keep working while they migrate
+        let outputs = execute(Returns::new(Some("dataOut"), false), false).await.unwrap();
+        assert!(outputs.contains_key("dataOut"));
+        assert!(execute(Returns::new(Some("data_out"), false), true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn missing_required_outputs_fail_strict_modules() {
+        let error = execute(Returns::new(None, false), true).await.unwrap_err();
+        assert!(error.to_string().contains("no object on required output port data_out"), "{}", error);
+
+        assert!(execute(Returns::new(None, false), false).await.is_ok());
+        assert!(execute(Returns::new(None, true), true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_panicking_module_does_not_take_down_its_siblings() {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        let executor = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(4)), Arc::new(MessageRouter::new()));
+        let spec = WorkflowBuilder::new("panic", "Panic isolation")
+            .add_module("ConstantField", "Source")
+            .add_module("Failing", "Panics")
+                .parameter("panic", "true")
+                .depends_on(1)
+            .add_module("ConstantField", "Sibling")
+                .depends_on(1)
+            .add_module("ConstantField", "Downstream")
+                .depends_on(2)
+            .connect(1, "data_out", 2, "data_in")
+            .connect(1, "data_out", 3, "data_in")
+            .connect(2, "data_out", 4, "data_in")
+            .build();
+        let result = executor.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap();
+        assert!(!result.success);
+
+        let task = |id: u32| result.task_results.iter().find(|r| r.module_id == Some(id));
+        assert!(task(1).unwrap().success);
+        let sibling = task(3).unwrap();
+        assert!(sibling.success);
+        assert_eq!(sibling.outputs.as_ref().unwrap()["data_out"].len(), 1);
+
+        let panicked = task(2).unwrap();
+        assert!(!panicked.success);
+        assert!(panicked.error.as_deref().unwrap().contains("panicked: Failing module 2 panicked as asked"));
+        assert!(task(4).is_none_or(|r| !r.success));
+
+        let report = result.to_report();
+        assert_eq!(report.modules[1].error.as_ref().unwrap().code, "panic");
+    }
+
+    async fn runs(module: &VistleModule<Box<dyn Module>>) -> bool {
+        module.execute(&ComputeContext::new(1, 0, 1), &MessageRouter::new()).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn re_registering_a_type_leaves_existing_instances_alone() {
+        let registry = ModuleRegistry::new();
+        registry.register("Field", || ConstantField::new(0)).await;
+        let first = registry.version("Field").await.unwrap();
+        let old = registry.create_instance("Field", 1).await.unwrap();
+
+        registry.register("Field", || Failing::new(0)).await;
+        let second = registry.version("Field").await.unwrap();
+        assert!(second > first);
+        let new = registry.create_instance("Field", 2).await.unwrap();
+
+        assert!(runs(&old).await);
+        assert!(!runs(&new).await);
+        let instances = registry.list_instances().await;
+        assert_eq!(instances.iter().map(|i| (i.id, i.version)).collect::<Vec<_>>(), [(1, first), (2, second)]);
+        assert!(instances[0].is_stale());
+        assert!(!instances[1].is_stale());
+    }
+
+    #[tokio::test]
+    async fn re_registering_mid_workflow_keeps_the_run_on_its_modules() {
+        let registry = Arc::new(ModuleRegistry::new());
+        registry.register("Stage", || ConstantField::new(0)).await;
+        let executor = WorkflowExecutor::new(registry.clone(), Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()));
+        let spec = WorkflowBuilder::new("reregister", "Re-registration")
+            .add_module("Stage", "Slow")
+                .parameter("delay_ms", "300")
+            .add_module("Stage", "Next")
+                .depends_on(1)
+            .connect(1, "data_out", 2, "data_in")
+            .build();
+
+        let reregister = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            registry.register("Stage", || Failing::new(0)).await;
+        };
+        let (result, _) = tokio::join!(executor.execute_workflow(spec, Some(Duration::from_secs(10))), reregister);
+
+        assert!(result.unwrap().success);
+        assert!(!runs(&registry.create_instance("Stage", 10).await.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn unloading_refuses_while_instances_are_in_use() {
+        let registry = ModuleRegistry::new();
+        register_test_modules(&registry).await;
+        let held = registry.create_instance("ConstantField", 1).await.unwrap();
+        registry.create_instance("ConstantField", 2).await.unwrap();
+
+        let error = registry.unload(&["ConstantField"], UnloadPolicy::Refuse).await.unwrap_err();
+        assert!(error.to_string().contains("1 (ConstantField)"), "{}", error);
+        assert!(registry.version("ConstantField").await.is_some());
+        assert_eq!(registry.list_instances().await.len(), 2);
+        assert!(runs(&held).await);
+
+        drop(held);
+        assert_eq!(registry.unload(&["ConstantField"], UnloadPolicy::Refuse).await.unwrap(), [1, 2]);
+        assert!(registry.version("ConstantField").await.is_none());
+        assert!(registry.descriptor("ConstantField").await.is_none());
+        assert!(registry.list_instances().await.is_empty());
+        assert!(registry.unload(&["ConstantField"], UnloadPolicy::Refuse).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unloading_with_cancel_stops_running_instances() {
+        let registry = ModuleRegistry::new();
+        register_test_modules(&registry).await;
+        let module = registry.create_instance("ConstantField", 1).await.unwrap();
+        module.set_parameter("delay_ms", ParameterValue::Int(5_000)).unwrap();
+
+        let started = std::time::Instant::now();
+        let (ctx, router) = (ComputeContext::new(1, 0, 1), MessageRouter::new());
+        let execution = module.execute(&ctx, &router);
+        let unload = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            registry.unload(&["ConstantField", "Failing"], UnloadPolicy::Cancel).await
+        };
+        let (result, dropped) = tokio::join!(execution, unload);
+
+        assert_eq!(dropped.unwrap(), [1]);
+        assert_eq!(result.unwrap_err().code(), "cancelled");
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(module.is_retired());
+        assert!(registry.get_instance(1).await.is_none());
+        assert!(!runs(&module).await);
+    }
+}
@@ -0,0 +1,257 @@
+//! Keeping the output cache of a workflow across sessions
+//!
+//! `WorkflowExecutor::snapshot_outputs` writes the objects of a workflow's
+//! cached outputs to a native object file, see `core::snapshot`, and returns
+//! a manifest naming them by module and port, to be kept e.g. with a saved
+//! session. `restore_outputs` loads the file into the executor's object
+//! registry and points the cache at the restored objects again, so that
+//! `rerun_workflow` in a new session executes only the modules whose
+//! outputs were lost or whose parameters changed, and those downstream.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{OutputPorts, WorkflowExecutor, WorkflowResult, WorkflowSpec};
+use crate::core::{ObjectId, ObjectRegistry, RestoreSummary, SnapshotSummary};
+
+/// Cached outputs of one module, by object id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedModule {
+    pub module_type: String,
+    /// Parameters the outputs were computed with
+    pub parameters: BTreeMap<String, String>,
+    pub ports: BTreeMap<String, Vec<ObjectId>>,
+}
+
+/// Cached outputs of a workflow written by `WorkflowExecutor::snapshot_outputs`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OutputCacheManifest {
+    pub workflow_id: String,
+    pub modules: BTreeMap<u32, CachedModule>,
+}
+
+/// Outcome of `WorkflowExecutor::restore_outputs`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutputRestore {
+    pub objects: RestoreSummary,
+    /// Modules whose outputs are cached again
+    pub cached: Vec<u32>,
+    /// Modules that have to run again: an object failed to restore, or
+    /// the module is gone or has other parameters now
+    pub stale: Vec<u32>,
+}
+
+impl WorkflowExecutor {
+    /// Write the cached outputs of a workflow to a native object file
+    ///
+    /// Modules with an output the file format cannot hold are left out of
+    /// the manifest, so they run again after a restore.
+    pub async fn snapshot_outputs(
+        &self,
+        workflow_id: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<(OutputCacheManifest, SnapshotSummary), crate::Error> {
+        let spec = self.workflow_spec(workflow_id).await
+            .ok_or_else(|| crate::Error::Module(format!("Workflow {} not found", workflow_id)))?;
+
+        let objects = ObjectRegistry::new();
+        let mut manifest = OutputCacheManifest {
+            workflow_id: workflow_id.to_string(),
+            modules: BTreeMap::new(),
+        };
+        for (module_id, ports) in self.cached_outputs(workflow_id) {
+            let Some(module) = spec.modules.iter().find(|m| m.id == module_id) else {
+                continue;
+            };
+            if ports.values().flatten().any(|object| object.as_data().is_none()) {
+                tracing::warn!("Workflow {}: outputs of module {} cannot be snapshot", workflow_id, module_id);
+                continue;
+            }
+            for object in ports.values().flatten() {
+                objects.store(object.clone());
+            }
+            manifest.modules.insert(module_id, CachedModule {
+                module_type: module.module_type.clone(),
+                parameters: module.parameters.clone().into_iter().collect(),
+                ports: ports.iter()
+                    .map(|(port, objects)| (port.clone(), objects.iter().map(|o| o.id()).collect()))
+                    .collect(),
+            });
+        }
+
+        let summary = objects.snapshot(path).await?;
+        Ok((manifest, summary))
+    }
+
+    /// Load a file written by `snapshot_outputs` and cache the outputs it holds for `spec`
+    ///
+    /// Needs an executor built `with_interactive`, which keeps the cache.
+    /// Objects whose ids are registered already are not loaded again; a
+    /// module is cached only if all its objects are registered afterwards
+    /// and its type and parameters in `spec` match those of the manifest.
+    pub async fn restore_outputs(
+        &self,
+        spec: &WorkflowSpec,
+        path: impl AsRef<Path>,
+        manifest: &OutputCacheManifest,
+    ) -> Result<OutputRestore, crate::Error> {
+        if self.interactive().is_none() {
+            return Err(crate::Error::Config(
+                "Interactive re-execution is not enabled on this executor".to_string()
+            ));
+        }
+        if manifest.workflow_id != spec.id {
+            return Err(crate::Error::Config(format!(
+                "Outputs of workflow {} cannot be restored for workflow {}",
+                manifest.workflow_id, spec.id
+            )));
+        }
+
+        let objects = self.object_registry().restore(path).await?;
+        let mut restore = OutputRestore {
+            objects,
+            ..OutputRestore::default()
+        };
+        for (&module_id, cached) in &manifest.modules {
+            let unchanged = spec.modules.iter().find(|m| m.id == module_id).is_some_and(|module| {
+                module.module_type == cached.module_type
+                    && module.parameters.len() == cached.parameters.len()
+                    && module.parameters.iter().all(|(name, value)| cached.parameters.get(name) == Some(value))
+            });
+            let ports: Option<OutputPorts> = cached.ports.iter()
+                .map(|(port, ids)| {
+                    let objects = ids.iter().map(|&id| self.object_registry().get(id)).collect::<Option<Vec<_>>>()?;
+                    Some((port.clone(), objects))
+                })
+                .collect();
+            match ports.filter(|_| unchanged) {
+                Some(ports) => {
+                    self.cache_outputs(&spec.id, module_id, ports);
+                    restore.cached.push(module_id);
+                }
+                None => restore.stale.push(module_id),
+            }
+        }
+
+        tracing::info!(
+            "Workflow {}: restored the outputs of {} modules, {} have to run again",
+            spec.id, restore.cached.len(), restore.stale.len()
+        );
+        Ok(restore)
+    }
+
+    /// Execute a workflow, completing modules with cached outputs from those outputs
+    ///
+    /// Modules without cached outputs run, and so does everything downstream
+    /// of them.
+    pub async fn rerun_workflow(
+        &self,
+        workflow: WorkflowSpec,
+        timeout_duration: Option<std::time::Duration>,
+    ) -> Result<WorkflowResult, crate::Error> {
+        let previous: HashMap<u32, OutputPorts> = self.cached_outputs(&workflow.id);
+        let changed: Vec<u32> = workflow.modules.iter()
+            .map(|m| m.id)
+            .filter(|id| !previous.contains_key(id))
+            .collect();
+        self.execute_workflow_reusing(workflow, timeout_duration, &previous, &changed).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{InteractiveConfig, ModuleRegistry, TaskExecutor, WorkflowBuilder};
+    use crate::core::MessageRouter;
+
+    /// Reader -> Filter -> Writer, as constant fields
+    fn pipeline(filter_value: &str) -> WorkflowSpec {
+        WorkflowBuilder::new("session", "Session")
+            .add_module("ConstantField", "Reader")
+            .add_module("ConstantField", "Filter")
+                .parameter("value", filter_value)
+                .depends_on(1)
+            .add_module("ConstantField", "Writer")
+                .depends_on(2)
+            .connect(1, "data_out", 2, "data_in")
+            .connect(2, "data_out", 3, "data_in")
+            .build()
+    }
+
+    async fn executor() -> WorkflowExecutor {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()))
+            .with_interactive(InteractiveConfig::default())
+    }
+
+    fn output_id(executor: &WorkflowExecutor, module_id: u32) -> ObjectId {
+        executor.cached_outputs("session")[&module_id]["data_out"][0].id()
+    }
+
+    /// Run the pipeline in one session and snapshot its outputs
+    async fn saved_session(path: &Path) -> (OutputCacheManifest, HashMap<u32, ObjectId>) {
+        let executor = executor().await;
+        assert!(executor.execute_workflow(pipeline("2.0"), Some(Duration::from_secs(10))).await.unwrap().success);
+        let (manifest, summary) = executor.snapshot_outputs("session", path).await.unwrap();
+        assert_eq!(summary.written, 3);
+        let ids = (1..=3).map(|id| (id, output_id(&executor, id))).collect();
+        (manifest, ids)
+    }
+
+    #[tokio::test]
+    async fn a_rerun_in_a_new_session_reuses_restored_outputs() {
+        let path = std::env::temp_dir().join(format!("vistle_outputs_{}.vobj", uuid::Uuid::new_v4().simple()));
+        let (manifest, ids) = saved_session(&path).await;
+
+        let executor = executor().await;
+        let restore = executor.restore_outputs(&pipeline("2.0"), &path, &manifest).await.unwrap();
+        assert_eq!(restore.objects.restored.len(), 3);
+        assert_eq!(restore.cached, [1, 2, 3]);
+        assert!(restore.stale.is_empty());
+
+        assert!(executor.rerun_workflow(pipeline("2.0"), Some(Duration::from_secs(10))).await.unwrap().success);
+        for id in 1..=3 {
+            assert_eq!(output_id(&executor, id), ids[&id], "module {} ran again", id);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn changed_modules_and_their_downstream_run_again() {
+        let path = std::env::temp_dir().join(format!("vistle_outputs_{}.vobj", uuid::Uuid::new_v4().simple()));
+        let (manifest, ids) = saved_session(&path).await;
+
+        let executor = executor().await;
+        let restore = executor.restore_outputs(&pipeline("3.0"), &path, &manifest).await.unwrap();
+        assert_eq!(restore.cached, [1, 3]);
+        assert_eq!(restore.stale, [2]);
+
+        assert!(executor.rerun_workflow(pipeline("3.0"), Some(Duration::from_secs(10))).await.unwrap().success);
+        assert_eq!(output_id(&executor, 1), ids[&1]);
+        assert_ne!(output_id(&executor, 2), ids[&2]);
+        assert_ne!(output_id(&executor, 3), ids[&3]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn restoring_needs_the_cache_and_the_same_workflow() {
+        let path = std::env::temp_dir().join(format!("vistle_outputs_{}.vobj", uuid::Uuid::new_v4().simple()));
+        let (manifest, _) = saved_session(&path).await;
+
+        let registry = Arc::new(ModuleRegistry::new());
+        let plain = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(1)), Arc::new(MessageRouter::new()));
+        assert!(plain.restore_outputs(&pipeline("2.0"), &path, &manifest).await.is_err());
+
+        let mut other = pipeline("2.0");
+        other.id = "other".to_string();
+        assert!(executor().await.restore_outputs(&other, &path, &manifest).await.is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,438 @@
+//! Output files of writer modules, planned before a workflow runs
+//!
+//! Writers declare the files their next execution writes through
+//! `Module::planned_outputs`. Before a workflow runs, each declared path is
+//! resolved against the workflow and checked for existing files, and the
+//! workflow's `OutputPolicy` decides what happens to writers that would
+//! write over them. Paths are checked on the host running the executor.
+//!
+//! The file name of a declared path may contain `{block}` and `{timestep}`,
+//! standing for a sequence of files with a number in their place. A sequence
+//! exists if any file matching it does, and is renamed as a whole, so e.g.
+//! `frame_{timestep}.png` becomes `frame_{timestep}_1.png` and every frame of
+//! the run ends up next to the others.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::WorkflowSpec;
+
+/// Placeholders in file names standing for a number each
+pub const SEQUENCE_PLACEHOLDERS: [&str; 2] = ["{block}", "{timestep}"];
+
+/// Numbers tried for `{n}` of a rename suffix before giving up
+pub const RENAME_ATTEMPTS: u32 = 10_000;
+
+/// What happens to writers whose output files already exist
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputPolicy {
+    /// Write over existing files
+    #[default]
+    Overwrite,
+    /// Refuse to run the workflow
+    Error,
+    /// Write under a new name next to the existing files
+    ///
+    /// `suffix` goes between the file stem and extension; `{n}` in it is
+    /// replaced by the smallest number from 1 giving names not yet taken.
+    Rename { suffix: String },
+    /// Do not run the writers, nor the modules downstream of them
+    Skip,
+}
+
+impl OutputPolicy {
+    /// Rename with a `_{n}` suffix, e.g. `image.png` to `image_1.png`
+    pub fn rename() -> Self {
+        OutputPolicy::Rename { suffix: "_{n}".to_string() }
+    }
+}
+
+/// What was decided for one declared output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputAction {
+    /// Nothing exists at the path yet
+    Create,
+    Overwrite,
+    Rename,
+    Skip,
+    /// Existing files and `OutputPolicy::Error`
+    Refuse,
+}
+
+/// Decision for one output path declared by a writer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputDecision {
+    pub module_id: u32,
+    /// Path as the module declared it, before resolution
+    pub declared: String,
+    /// Resolved path, possibly standing for a sequence of files
+    pub path: PathBuf,
+    /// Existing files the path matches
+    pub existing: Vec<PathBuf>,
+    pub action: OutputAction,
+    /// Declared path the writer was given instead, for `OutputAction::Rename`
+    pub renamed: Option<String>,
+}
+
+impl OutputDecision {
+    /// Resolved path the writer ends up writing to
+    pub fn target(&self, base_dir: Option<&Path>) -> Result<PathBuf, crate::Error> {
+        match &self.renamed {
+            Some(renamed) => crate::core::resolve_path(renamed, base_dir),
+            None => Ok(self.path.clone()),
+        }
+    }
+}
+
+/// Output decisions of a workflow, made before it runs
+///
+/// `WorkflowExecutor::plan_outputs` makes the plan without running
+/// anything, so it doubles as a dry run of the overwrite checks.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutputPlan {
+    pub policy: OutputPolicy,
+    pub decisions: Vec<OutputDecision>,
+}
+
+impl OutputPlan {
+    pub fn new(policy: OutputPolicy) -> Self {
+        Self {
+            policy,
+            decisions: Vec::new(),
+        }
+    }
+
+    /// Decide for the outputs one module declared
+    pub async fn add_module(
+        &mut self,
+        module_id: u32,
+        declared: &[PathBuf],
+        base_dir: Option<&Path>,
+    ) -> Result<(), crate::Error> {
+        for declared in declared {
+            let declared = declared.to_string_lossy().into_owned();
+            let path = crate::core::resolve_path(&declared, base_dir)?;
+            let existing = existing_files(&path).await?;
+
+            let (action, renamed) = match (&self.policy, existing.is_empty()) {
+                (_, true) => (OutputAction::Create, None),
+                (OutputPolicy::Overwrite, false) => (OutputAction::Overwrite, None),
+                (OutputPolicy::Error, false) => (OutputAction::Refuse, None),
+                (OutputPolicy::Skip, false) => (OutputAction::Skip, None),
+                (OutputPolicy::Rename { suffix }, false) => {
+                    let renamed = self.free_name(&declared, suffix, base_dir).await?;
+                    (OutputAction::Rename, Some(renamed))
+                }
+            };
+            self.decisions.push(OutputDecision {
+                module_id,
+                declared,
+                path,
+                existing,
+                action,
+                renamed,
+            });
+        }
+        Ok(())
+    }
+
+    /// First name from `suffix` with no existing files that no other writer of the plan was given
+    async fn free_name(&self, declared: &str, suffix: &str, base_dir: Option<&Path>) -> Result<String, crate::Error> {
+        let claimed: HashSet<PathBuf> = self.decisions.iter()
+            .filter_map(|d| d.target(base_dir).ok())
+            .collect();
+        let attempts = if suffix.contains("{n}") { RENAME_ATTEMPTS } else { 1 };
+        for n in 1..=attempts {
+            let candidate = with_suffix(declared, &suffix.replace("{n}", &n.to_string()));
+            let path = crate::core::resolve_path(&candidate, base_dir)?;
+            if !claimed.contains(&path) && existing_files(&path).await?.is_empty() {
+                return Ok(candidate);
+            }
+        }
+        Err(crate::Error::Config(format!("No free name for output {} with suffix {}", declared, suffix)))
+    }
+
+    /// Outputs that exist already
+    pub fn conflicts(&self) -> impl Iterator<Item = &OutputDecision> {
+        self.decisions.iter().filter(|d| !d.existing.is_empty())
+    }
+
+    /// Fail if the policy refuses to write over an existing output
+    pub fn check(&self) -> Result<(), crate::Error> {
+        let refused: Vec<String> = self.decisions.iter()
+            .filter(|d| d.action == OutputAction::Refuse)
+            .map(|d| format!("{} (module {})", d.path.display(), d.module_id))
+            .collect();
+        if refused.is_empty() {
+            return Ok(());
+        }
+        Err(crate::Error::Config(format!("Outputs exist already: {}", refused.join(", "))))
+    }
+
+    /// Give renamed writers their new paths in the workflow's parameters
+    ///
+    /// A declared path is replaced wherever it is the whole value of one of
+    /// the module's parameters; a writer deriving it some other way cannot
+    /// be renamed.
+    pub fn apply(&self, spec: &mut WorkflowSpec) -> Result<(), crate::Error> {
+        for decision in self.decisions.iter().filter(|d| d.action == OutputAction::Rename) {
+            let Some(renamed) = &decision.renamed else {
+                continue;
+            };
+            let module = spec.modules.iter_mut()
+                .find(|m| m.id == decision.module_id)
+                .ok_or_else(|| crate::Error::Config(format!("Module {} not found", decision.module_id)))?;
+            let mut replaced = false;
+            for value in module.parameters.values_mut().filter(|v| **v == decision.declared) {
+                *value = renamed.clone();
+                replaced = true;
+            }
+            if !replaced {
+                return Err(crate::Error::Config(format!(
+                    "Module {} ({}): output {} is not a parameter value and cannot be renamed",
+                    module.name, module.id, decision.declared
+                )));
+            }
+            tracing::info!("Workflow {}: module {} writes {} instead of {}", spec.id, module.name, renamed, decision.declared);
+        }
+        Ok(())
+    }
+
+    /// Modules not to run: writers skipped, and everything downstream of them
+    pub fn skipped_modules(&self, spec: &WorkflowSpec) -> HashSet<u32> {
+        let mut skipped: HashSet<u32> = self.decisions.iter()
+            .filter(|d| d.action == OutputAction::Skip)
+            .map(|d| d.module_id)
+            .collect();
+        loop {
+            let downstream: Vec<u32> = spec.modules.iter()
+                .filter(|m| !skipped.contains(&m.id))
+                .filter(|m| {
+                    m.dependencies.iter().any(|d| skipped.contains(d))
+                        || spec.connections.iter().any(|c| c.to_module == m.id && skipped.contains(&c.from_module))
+                })
+                .map(|m| m.id)
+                .collect();
+            if downstream.is_empty() {
+                return skipped;
+            }
+            skipped.extend(downstream);
+        }
+    }
+}
+
+/// Insert `suffix` between the stem and extension of the path's file name
+fn with_suffix(declared: &str, suffix: &str) -> String {
+    let name_start = declared.rfind(['/', '\\']).map_or(0, |i| i + 1);
+    let name = &declared[name_start..];
+    // Dots inside a placeholder or leading a hidden file's name are not extensions
+    let stem_end = match name.rfind('.') {
+        Some(dot) if dot > 0 && !name[dot..].contains('}') => name_start + dot,
+        _ => declared.len(),
+    };
+    format!("{}{}{}", &declared[..stem_end], suffix, &declared[stem_end..])
+}
+
+/// Existing files or directories at a path, or matching it if it is a sequence
+async fn existing_files(path: &Path) -> Result<Vec<PathBuf>, crate::Error> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    if !SEQUENCE_PLACEHOLDERS.iter().any(|p| name.contains(p)) {
+        return Ok(match tokio::fs::symlink_metadata(path).await {
+            Ok(_) => vec![path.to_path_buf()],
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        });
+    }
+
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut pattern = name;
+    for placeholder in SEQUENCE_PLACEHOLDERS {
+        pattern = pattern.replace(placeholder, "\0");
+    }
+    let literals: Vec<&str> = pattern.split('\0').collect();
+
+    let mut existing = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        if matches_sequence(&literals, &entry.file_name().to_string_lossy()) {
+            existing.push(entry.path());
+        }
+    }
+    existing.sort();
+    Ok(existing)
+}
+
+/// Whether `name` is the literals joined by numbers
+fn matches_sequence(literals: &[&str], name: &str) -> bool {
+    match literals {
+        [] => false,
+        [last] => name == *last,
+        [first, rest @ ..] => name.strip_prefix(first).is_some_and(|name| {
+            let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            (1..=digits).any(|n| matches_sequence(rest, &name[n..]))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::WorkflowBuilder;
+
+    fn temp_dir(files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vistle_outputs_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), b"old").unwrap();
+        }
+        dir
+    }
+
+    async fn plan(policy: OutputPolicy, dir: &Path, declared: &[&str]) -> OutputPlan {
+        let mut plan = OutputPlan::new(policy);
+        let declared: Vec<PathBuf> = declared.iter().map(PathBuf::from).collect();
+        plan.add_module(2, &declared, Some(dir)).await.unwrap();
+        plan
+    }
+
+    fn writer_workflow(filename: &str) -> WorkflowSpec {
+        WorkflowBuilder::new("outputs", "Outputs")
+            .add_module("ConstantField", "Source")
+            .add_module("WriteCsvTable", "Writer")
+                .parameter("filename", filename)
+                .depends_on(1)
+            .add_module("ConstantField", "After")
+                .depends_on(2)
+            .add_module("ConstantField", "Sibling")
+                .depends_on(1)
+            .build()
+    }
+
+    #[test]
+    fn suffixes_go_before_the_extension() {
+        assert_eq!(with_suffix("out/image.png", "_1"), "out/image_1.png");
+        assert_eq!(with_suffix("out.d/table", "_1"), "out.d/table_1");
+        assert_eq!(with_suffix(".hidden", "_1"), ".hidden_1");
+        assert_eq!(with_suffix("frame_{timestep}.png", "_2"), "frame_{timestep}_2.png");
+        assert_eq!(with_suffix("archive.tar.gz", "-old"), "archive.tar-old.gz");
+    }
+
+    #[test]
+    fn sequences_match_numbers_in_place_of_placeholders() {
+        let literals = ["frame_", "_b", ".png"];
+        assert!(matches_sequence(&literals, "frame_0001_b3.png"));
+        assert!(!matches_sequence(&literals, "frame__b3.png"));
+        assert!(!matches_sequence(&literals, "frame_x_b3.png"));
+        assert!(!matches_sequence(&literals, "frame_1_b3.png.bak"));
+    }
+
+    #[tokio::test]
+    async fn new_outputs_are_created_under_any_policy() {
+        let dir = temp_dir(&[]);
+        for policy in [OutputPolicy::Overwrite, OutputPolicy::Error, OutputPolicy::rename(), OutputPolicy::Skip] {
+            let plan = plan(policy, &dir, &["image.png"]).await;
+            assert_eq!(plan.decisions[0].action, OutputAction::Create);
+            assert!(plan.check().is_ok());
+            assert_eq!(plan.conflicts().count(), 0);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overwrite_keeps_the_declared_path() {
+        let dir = temp_dir(&["image.png"]);
+        let plan = plan(OutputPolicy::Overwrite, &dir, &["image.png"]).await;
+        let decision = &plan.decisions[0];
+        assert_eq!(decision.action, OutputAction::Overwrite);
+        assert_eq!(decision.existing, [dir.join("image.png")]);
+        assert_eq!(decision.target(Some(&dir)).unwrap(), dir.join("image.png"));
+        assert!(plan.check().is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn error_refuses_existing_files_and_directories() {
+        let dir = temp_dir(&["image.png"]);
+        std::fs::create_dir(dir.join("results")).unwrap();
+        let plan = plan(OutputPolicy::Error, &dir, &["image.png", "results", "table.csv"]).await;
+        let actions: Vec<OutputAction> = plan.decisions.iter().map(|d| d.action).collect();
+        assert_eq!(actions, [OutputAction::Refuse, OutputAction::Refuse, OutputAction::Create]);
+
+        let message = plan.check().unwrap_err().to_string();
+        assert!(message.contains("image.png (module 2)") && message.contains("results (module 2)"), "{}", message);
+        assert!(!message.contains("table.csv"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rename_picks_the_first_free_number() {
+        let dir = temp_dir(&["image.png", "image_1.png"]);
+        let mut spec = writer_workflow("image.png");
+        let plan = plan(OutputPolicy::rename(), &dir, &["image.png"]).await;
+        assert_eq!(plan.decisions[0].action, OutputAction::Rename);
+        assert_eq!(plan.decisions[0].renamed.as_deref(), Some("image_2.png"));
+
+        plan.apply(&mut spec).unwrap();
+        assert_eq!(spec.modules[1].parameters["filename"], "image_2.png");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_frame_sequence_is_renamed_as_a_whole() {
+        let dir = temp_dir(&["frame_0000.png", "frame_0001.png", "frame_0000_v1.png", "frame_last.png"]);
+        let suffix = OutputPolicy::Rename { suffix: "_v{n}".to_string() };
+        let plan = plan(suffix, &dir, &["frame_{timestep}.png"]).await;
+        let decision = &plan.decisions[0];
+        assert_eq!(decision.existing, [dir.join("frame_0000.png"), dir.join("frame_0001.png")]);
+        assert_eq!(decision.renamed.as_deref(), Some("frame_{timestep}_v2.png"));
+        assert_eq!(decision.target(Some(&dir)).unwrap(), dir.join("frame_{timestep}_v2.png"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn writers_renamed_together_get_different_names() {
+        let dir = temp_dir(&["image.png"]);
+        let mut plan = OutputPlan::new(OutputPolicy::rename());
+        plan.add_module(2, &[PathBuf::from("image.png")], Some(&dir)).await.unwrap();
+        plan.add_module(3, &[PathBuf::from("image.png")], Some(&dir)).await.unwrap();
+        let renamed: Vec<Option<&str>> = plan.decisions.iter().map(|d| d.renamed.as_deref()).collect();
+        assert_eq!(renamed, [Some("image_1.png"), Some("image_2.png")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_suffix_without_a_number_is_tried_once() {
+        let dir = temp_dir(&["image.png", "image.old.png"]);
+        let mut plan = OutputPlan::new(OutputPolicy::Rename { suffix: ".old".to_string() });
+        assert!(plan.add_module(2, &[PathBuf::from("image.png")], Some(&dir)).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renaming_an_output_that_is_no_parameter_fails() {
+        let dir = temp_dir(&["derived.png"]);
+        let mut spec = writer_workflow("image.png");
+        let plan = plan(OutputPolicy::rename(), &dir, &["derived.png"]).await;
+        assert!(plan.apply(&mut spec).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn skip_leaves_out_the_writer_and_its_downstream_modules() {
+        let dir = temp_dir(&["table.csv"]);
+        let spec = writer_workflow("table.csv");
+        let plan = plan(OutputPolicy::Skip, &dir, &["table.csv"]).await;
+        assert_eq!(plan.decisions[0].action, OutputAction::Skip);
+        assert!(plan.check().is_ok());
+        assert_eq!(plan.skipped_modules(&spec), HashSet::from([2, 3]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,780 @@
+//! Best-effort import of ParaView state files
+//!
+//! `WorkflowSpec::from_paraview_state` reads the pipeline of a `.pvsm` file:
+//! readers with a file name, Contour, Slice, Threshold, Clip and Glyph
+//! become modules with their iso values, planes and ranges translated, and
+//! the connections between them are kept. Everything else becomes a
+//! disconnected `PARAVIEW_PLACEHOLDER` module, which no registry provides, so
+//! the workflow does not run before each one was replaced or removed. The
+//! `ConversionReport` lists those placeholders, every setting that was not
+//! translated and every connection that was lost. Views, representations
+//! and color maps are not read.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{ConnectionSpec, ModuleSpec, WorkflowSpec};
+use crate::util::fmt::{format_f64, parse_f64};
+
+/// Module type of the modules standing in for proxies without a translation
+pub const PARAVIEW_PLACEHOLDER: &str = "ParaViewPlaceholder";
+
+/// A pipeline proxy of the state file and the module it became
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConvertedProxy {
+    /// Proxy id in the state file
+    pub proxy_id: String,
+    /// ParaView's proxy type, e.g. "Contour"
+    pub paraview_type: String,
+    pub name: String,
+    pub module_id: u32,
+    /// `PARAVIEW_PLACEHOLDER` for proxies without a translation
+    pub module_type: String,
+}
+
+impl ConvertedProxy {
+    pub fn is_placeholder(&self) -> bool {
+        self.module_type == PARAVIEW_PLACEHOLDER
+    }
+}
+
+/// A property of a translated proxy that has no equivalent in its module
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DroppedSetting {
+    pub module_id: u32,
+    /// Name of the proxy
+    pub name: String,
+    /// ParaView property name
+    pub setting: String,
+    pub value: String,
+    pub reason: String,
+}
+
+/// What a conversion translated and what has to be fixed by hand
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversionReport {
+    /// File name of the state file
+    pub source: String,
+    /// Pipeline proxies in state file order
+    pub proxies: Vec<ConvertedProxy>,
+    pub dropped: Vec<DroppedSetting>,
+    /// Lost connections and translations that differ from ParaView
+    pub warnings: Vec<String>,
+}
+
+impl ConversionReport {
+    /// Proxies that became placeholders
+    pub fn unmapped(&self) -> impl Iterator<Item = &ConvertedProxy> {
+        self.proxies.iter().filter(|p| p.is_placeholder())
+    }
+
+    /// Whether the workflow does what the state file did, as far as the converter can tell
+    pub fn is_complete(&self) -> bool {
+        self.unmapped().next().is_none() && self.dropped.is_empty() && self.warnings.is_empty()
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize conversion report: {}", e)))?;
+        crate::util::io::write_text(path, &json).await
+    }
+}
+
+impl WorkflowSpec {
+    /// Convert the pipeline of a ParaView state file
+    ///
+    /// The workflow is named after the file. Only unreadable files and
+    /// invalid XML are errors; anything the converter does not understand
+    /// ends up in the report.
+    pub async fn from_paraview_state(path: impl AsRef<Path>) -> Result<(Self, ConversionReport), crate::Error> {
+        let path = path.as_ref();
+        let xml = crate::util::io::read_text(path).await?;
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("paraview_state");
+        let (mut spec, mut report) = Self::from_paraview_xml(stem, &xml)?;
+        report.source = path.file_name().and_then(|s| s.to_str()).unwrap_or(stem).to_string();
+        spec.description = format!("Imported from ParaView state {}", report.source);
+
+        let path = tokio::fs::canonicalize(path).await?;
+        spec.base_dir = path.parent().map(Path::to_path_buf);
+        Ok((spec, report))
+    }
+
+    /// Convert the pipeline of a ParaView state given as XML text
+    pub fn from_paraview_xml(id: &str, xml: &str) -> Result<(Self, ConversionReport), crate::Error> {
+        let document = roxmltree::Document::parse(xml)
+            .map_err(|e| crate::Error::Config(format!("Invalid ParaView state XML: {}", e)))?;
+        if !document.descendants().any(|n| n.has_tag_name("ServerManagerState")) {
+            return Err(crate::Error::Config("XML is not a ParaView state: no ServerManagerState element".to_string()));
+        }
+        let state = State::read(&document);
+
+        let mut spec = WorkflowSpec::new(id, id);
+        let mut report = ConversionReport {
+            source: id.to_string(),
+            ..Default::default()
+        };
+        let mut converted: HashMap<&str, Converted> = HashMap::new();
+        for (index, proxy) in state.pipeline().into_iter().enumerate() {
+            let module_id = index as u32 + 1;
+            let name = state.names.get(proxy.id.as_str()).cloned()
+                .unwrap_or_else(|| format!("{}{}", proxy.type_name, proxy.id));
+            let translation = Translation::new(&state, proxy, module_id, &name);
+            let result = translation.translate(&mut report);
+
+            report.proxies.push(ConvertedProxy {
+                proxy_id: proxy.id.clone(),
+                paraview_type: proxy.type_name.clone(),
+                name,
+                module_id,
+                module_type: result.module.module_type.clone(),
+            });
+            converted.insert(&proxy.id, Converted {
+                module_id,
+                ports: result.ports,
+            });
+            spec.modules.push(result.module);
+        }
+
+        connect(&state, &converted, &mut spec, &mut report);
+        tracing::info!(
+            "Converted ParaView state {}: {} modules, {} placeholders, {} dropped settings",
+            id, spec.modules.len(), report.unmapped().count(), report.dropped.len()
+        );
+        Ok((spec, report))
+    }
+}
+
+/// A property as written in the state: element values, or proxies it references
+struct Property {
+    values: Vec<String>,
+    /// Referenced proxy ids with their output port
+    proxies: Vec<(String, u32)>,
+}
+
+impl Property {
+    fn display(&self, state: &State) -> String {
+        if self.proxies.is_empty() {
+            return self.values.join(" ");
+        }
+        self.proxies.iter()
+            .map(|(id, _)| state.proxies.get(id.as_str()).map_or(id.as_str(), |p| p.type_name.as_str()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+struct Proxy {
+    id: String,
+    group: String,
+    type_name: String,
+    /// Properties in state order
+    properties: Vec<(String, Property)>,
+}
+
+impl Proxy {
+    fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
+}
+
+/// Proxies of a state file and the names the pipeline browser shows
+struct State {
+    proxies: HashMap<String, Proxy>,
+    /// Proxy ids in state order
+    order: Vec<String>,
+    names: HashMap<String, String>,
+}
+
+impl State {
+    fn read(document: &roxmltree::Document) -> Self {
+        let mut proxies = HashMap::new();
+        let mut order = Vec::new();
+        for node in document.descendants().filter(|n| n.has_tag_name("Proxy") && n.attribute("group").is_some()) {
+            let Some(id) = node.attribute("id") else {
+                continue;
+            };
+            let properties = node.children()
+                .filter(|c| c.has_tag_name("Property"))
+                .filter_map(|c| Some((c.attribute("name")?.to_string(), read_property(c))))
+                .collect();
+            order.push(id.to_string());
+            proxies.insert(id.to_string(), Proxy {
+                id: id.to_string(),
+                group: node.attribute("group").unwrap_or_default().to_string(),
+                type_name: node.attribute("type").unwrap_or_default().to_string(),
+                properties,
+            });
+        }
+
+        let names = document.descendants()
+            .filter(|n| n.has_tag_name("ProxyCollection") && n.attribute("name") == Some("sources"))
+            .flat_map(|n| n.children().filter(|c| c.has_tag_name("Item")))
+            .filter_map(|item| Some((item.attribute("id")?.to_string(), item.attribute("name")?.to_string())))
+            .collect();
+        Self { proxies, order, names }
+    }
+
+    /// Proxies of the pipeline browser; without a sources collection, every source and filter
+    fn pipeline(&self) -> Vec<&Proxy> {
+        self.order.iter()
+            .filter_map(|id| self.proxies.get(id))
+            .filter(|p| {
+                if self.names.is_empty() {
+                    matches!(p.group.as_str(), "sources" | "filters")
+                } else {
+                    self.names.contains_key(&p.id)
+                }
+            })
+            .collect()
+    }
+}
+
+fn read_property(node: roxmltree::Node) -> Property {
+    let mut elements: Vec<(usize, String)> = node.children()
+        .filter(|c| c.has_tag_name("Element"))
+        .filter_map(|c| Some((c.attribute("index")?.parse().ok()?, c.attribute("value")?.to_string())))
+        .collect();
+    elements.sort_by_key(|(index, _)| *index);
+    let proxies = node.children()
+        .filter(|c| c.has_tag_name("Proxy"))
+        .filter_map(|c| {
+            let port = c.attribute("output_port").and_then(|p| p.parse().ok()).unwrap_or(0);
+            Some((c.attribute("value")?.to_string(), port))
+        })
+        .collect();
+    Property {
+        values: elements.into_iter().map(|(_, value)| value).collect(),
+        proxies,
+    }
+}
+
+/// Ports a translated module is connected through
+#[derive(Clone, Copy)]
+struct Ports {
+    /// None for readers
+    input: Option<&'static str>,
+    output: &'static str,
+}
+
+struct Translated {
+    module: ModuleSpec,
+    /// None for placeholders
+    ports: Option<Ports>,
+}
+
+struct Converted {
+    module_id: u32,
+    ports: Option<Ports>,
+}
+
+/// Translation of one pipeline proxy, recording the properties it used
+struct Translation<'a> {
+    state: &'a State,
+    proxy: &'a Proxy,
+    module_id: u32,
+    name: String,
+    used: HashSet<&'static str>,
+    dropped: Vec<DroppedSetting>,
+}
+
+impl<'a> Translation<'a> {
+    fn new(state: &'a State, proxy: &'a Proxy, module_id: u32, name: &str) -> Self {
+        Self {
+            state,
+            proxy,
+            module_id,
+            name: name.to_string(),
+            used: HashSet::from(["Input"]),
+            dropped: Vec::new(),
+        }
+    }
+
+    fn translate(mut self, report: &mut ConversionReport) -> Translated {
+        let proxy = self.proxy;
+        let filename = ["FileName", "FileNames"].into_iter()
+            .find(|&p| proxy.property(p).is_some_and(|p| !p.values.is_empty()));
+        let mapped = match (proxy.type_name.as_str(), filename) {
+            (_, Some(property)) => Some(self.reader(property)),
+            ("Contour", _) => Some(self.contour()),
+            ("Cut", _) => Some(self.slice()),
+            ("Threshold", _) => Some(self.threshold()),
+            ("Clip", _) => Some(self.clip()),
+            ("Glyph", _) => Some(self.glyph(report)),
+            ("Calculator", _) => {
+                let function = proxy.property("Function").map(|p| p.values.join(" ")).unwrap_or_default();
+                let result = proxy.property("ResultArrayName").map(|p| p.values.join(" ")).unwrap_or_default();
+                report.warnings.push(format!(
+                    "{}: Calculator computing {} = {} has no equivalent and became a placeholder",
+                    self.name, result, function
+                ));
+                None
+            }
+            _ => None,
+        };
+
+        let Some((module, ports)) = mapped else {
+            tracing::debug!("ParaView proxy {} ({}) has no translation", self.name, proxy.type_name);
+            let module = ModuleSpec::new(self.module_id, PARAVIEW_PLACEHOLDER, &self.name)
+                .with_parameter("paraview_type", &proxy.type_name);
+            return Translated { module, ports: None };
+        };
+
+        for (setting, property) in &proxy.properties {
+            if self.used.contains(setting.as_str()) || (property.values.is_empty() && property.proxies.is_empty()) {
+                continue;
+            }
+            let value = property.display(self.state);
+            self.drop_setting(setting, value, "not translated");
+        }
+        report.dropped.append(&mut self.dropped);
+        Translated { module, ports: Some(ports) }
+    }
+
+    fn reader(&mut self, property: &'static str) -> (ModuleSpec, Ports) {
+        let values = self.property_values(property);
+        let mut module = ModuleSpec::new(self.module_id, "ReadAny", &self.name);
+        module.parameters.insert("filename".to_string(), values[0].clone());
+        if values.len() > 1 {
+            self.drop_setting(property, values[1..].join(" "), "only the first file is read");
+        }
+        (module, Ports { input: None, output: "data" })
+    }
+
+    fn contour(&mut self) -> (ModuleSpec, Ports) {
+        let mut module = ModuleSpec::new(self.module_id, "IsoSurface", &self.name);
+        if let Some(values) = self.numbers("ContourValues") {
+            if let Some(&first) = values.first() {
+                module.parameters.insert("iso_value".to_string(), format_f64(first));
+            }
+            if values.len() > 1 {
+                let rest = values[1..].iter().map(|&v| format_f64(v)).collect::<Vec<_>>().join(" ");
+                self.drop_setting("ContourValues", rest, "one iso value per IsoSurface; add a module per value");
+            }
+        }
+        (module, Ports { input: Some("data_in"), output: "surface_out" })
+    }
+
+    fn slice(&mut self) -> (ModuleSpec, Ports) {
+        let mut module = ModuleSpec::new(self.module_id, "Slice", &self.name);
+        if let Some(function) = self.function(&["SliceType", "CutFunction"]) {
+            match function.type_name.as_str() {
+                "Plane" => self.plane(&mut module, function),
+                other => self.drop_setting("SliceType", other.to_string(), "only plane slices are translated"),
+            }
+        }
+        (module, Ports { input: Some("grid_in"), output: "grid_out" })
+    }
+
+    fn threshold(&mut self) -> (ModuleSpec, Ports) {
+        let mut module = ModuleSpec::new(self.module_id, "Threshold", &self.name);
+        // ParaView before 5.10 keeps a range, later versions a lower and an upper bound and a method
+        let (lower, upper) = match self.numbers("ThresholdBetween") {
+            Some(range) => (range.first().copied(), range.get(1).copied()),
+            None => (
+                self.numbers("LowerThreshold").and_then(|v| v.first().copied()),
+                self.numbers("UpperThreshold").and_then(|v| v.first().copied()),
+            ),
+        };
+        let method = self.numbers("ThresholdMethod").and_then(|v| v.first().copied()).unwrap_or(0.0);
+        // Methods: 0 between the bounds, 1 below the lower one, 2 above the upper one
+        let (min, max) = match method as i64 {
+            1 => (None, lower),
+            2 => (upper, None),
+            _ => (lower, upper),
+        };
+        if let Some(min) = min {
+            module.parameters.insert("min".to_string(), format_f64(min));
+        }
+        if let Some(max) = max {
+            module.parameters.insert("max".to_string(), format_f64(max));
+        }
+        (module, Ports { input: Some("grid_in"), output: "grid_out" })
+    }
+
+    fn clip(&mut self) -> (ModuleSpec, Ports) {
+        let mut module = ModuleSpec::new(self.module_id, "Clip", &self.name);
+        if let Some(function) = self.function(&["ClipType", "ClipFunction"]) {
+            match function.type_name.as_str() {
+                "Plane" => {
+                    module.parameters.insert("function".to_string(), "plane".to_string());
+                    self.plane(&mut module, function);
+                }
+                "Sphere" => {
+                    module.parameters.insert("function".to_string(), "sphere".to_string());
+                    self.sub_vector(&mut module, function, "Center", "center");
+                    if let Some(radius) = self.sub_numbers(function, "Radius").and_then(|v| v.first().copied()) {
+                        module.parameters.insert("radius".to_string(), format_f64(radius));
+                    }
+                }
+                other => self.drop_setting("ClipType", other.to_string(), "only plane and sphere clips are translated"),
+            }
+        }
+        // ParaView keeps the inside unless inverted is switched off
+        if let Some(invert) = self.numbers("Invert").and_then(|v| v.first().copied()) {
+            module.parameters.insert("keep_outside".to_string(), (invert == 0.0).to_string());
+        }
+        (module, Ports { input: Some("grid_in"), output: "grid_out" })
+    }
+
+    fn glyph(&mut self, report: &mut ConversionReport) -> (ModuleSpec, Ports) {
+        let mut module = ModuleSpec::new(self.module_id, "SphereGlyphs", &self.name);
+        // The glyph source's size is scaled by the scale factor
+        let mut radius = 0.5;
+        if let Some(source) = self.function(&["GlyphType", "Source"]) {
+            if source.type_name == "SphereSource" {
+                if let Some(r) = self.sub_numbers(source, "Radius").and_then(|v| v.first().copied()) {
+                    radius = r;
+                }
+            } else {
+                report.warnings.push(format!("{}: {} glyphs are drawn as spheres", self.name, source.type_name));
+            }
+        }
+        let factor = ["ScaleFactor", "SetScaleFactor"].into_iter()
+            .find_map(|p| self.numbers(p).and_then(|v| v.first().copied()));
+        module.parameters.insert("radius".to_string(), format_f64(radius * factor.unwrap_or(1.0)));
+        // The array name follows the association in the selection's last element
+        let proxy = self.proxy;
+        let scale_array = ["ScaleArray", "SelectInputScalars"].into_iter()
+            .find_map(|p| proxy.property(p).map(|property| (p, property)));
+        if let Some((property, selection)) = scale_array {
+            self.used.insert(property);
+            let array = selection.values.last().map(String::as_str).unwrap_or("");
+            let scale = !array.is_empty() && array != "None";
+            module.parameters.insert("scale_by_data".to_string(), scale.to_string());
+        }
+        (module, Ports { input: Some("grid_in"), output: "grid_out" })
+    }
+
+    /// Origin and normal of a plane function into the `center` and `normal` parameters
+    fn plane(&mut self, module: &mut ModuleSpec, function: &Proxy) {
+        self.sub_vector(module, function, "Origin", "center");
+        self.sub_vector(module, function, "Normal", "normal");
+        let offset = self.sub_numbers(function, "Offset").and_then(|v| v.first().copied()).unwrap_or(0.0);
+        if offset != 0.0 {
+            self.drop_setting("Plane.Offset", format_f64(offset), "not translated");
+        }
+    }
+
+    /// Values of a property of the proxy, marked as used
+    fn property_values(&mut self, property: &'static str) -> Vec<String> {
+        self.used.insert(property);
+        self.proxy.property(property).map(|p| p.values.clone()).unwrap_or_default()
+    }
+
+    /// Numbers of a property, marked as used; None if missing, dropped if not numeric
+    fn numbers(&mut self, property: &'static str) -> Option<Vec<f64>> {
+        let values = self.proxy.property(property)?.values.clone();
+        self.used.insert(property);
+        match values.iter().map(|v| parse_f64(v)).collect::<Result<Vec<_>, _>>() {
+            Ok(numbers) => Some(numbers),
+            Err(e) => {
+                self.drop_setting(property, values.join(" "), &e.to_string());
+                None
+            }
+        }
+    }
+
+    /// The proxy referenced by the first present property of `properties`, marked as used
+    fn function(&mut self, properties: &[&'static str]) -> Option<&'a Proxy> {
+        let proxy = self.proxy;
+        let (name, property) = properties.iter().find_map(|&p| proxy.property(p).map(|property| (p, property)))?;
+        self.used.insert(name);
+        let (id, _) = property.proxies.first()?;
+        self.state.proxies.get(id.as_str())
+    }
+
+    fn sub_numbers(&mut self, function: &Proxy, property: &str) -> Option<Vec<f64>> {
+        let values = &function.property(property)?.values;
+        match values.iter().map(|v| parse_f64(v)).collect::<Result<Vec<_>, _>>() {
+            Ok(numbers) => Some(numbers),
+            Err(e) => {
+                self.drop_setting(&format!("{}.{}", function.type_name, property), values.join(" "), &e.to_string());
+                None
+            }
+        }
+    }
+
+    /// A three-component property of `function` into a vector parameter
+    fn sub_vector(&mut self, module: &mut ModuleSpec, function: &Proxy, property: &str, parameter: &str) {
+        let Some(values) = self.sub_numbers(function, property) else {
+            return;
+        };
+        if values.len() != 3 {
+            let text = values.iter().map(|&v| format_f64(v)).collect::<Vec<_>>().join(" ");
+            self.drop_setting(&format!("{}.{}", function.type_name, property), text, "not a 3D vector");
+            return;
+        }
+        let vector = values.iter().map(|&v| format_f64(v)).collect::<Vec<_>>().join(" ");
+        module.parameters.insert(parameter.to_string(), vector);
+    }
+
+    fn drop_setting(&mut self, setting: &str, value: String, reason: &str) {
+        self.dropped.push(DroppedSetting {
+            module_id: self.module_id,
+            name: self.name.clone(),
+            setting: setting.to_string(),
+            value,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+/// Connect translated modules as their proxies' inputs were, reporting what cannot be
+fn connect(
+    state: &State,
+    converted: &HashMap<&str, Converted>,
+    spec: &mut WorkflowSpec,
+    report: &mut ConversionReport,
+) {
+    for proxy in state.pipeline() {
+        let Some(target) = converted.get(proxy.id.as_str()) else {
+            continue;
+        };
+        let inputs = proxy.property("Input").map(|p| p.proxies.as_slice()).unwrap_or_default();
+        let name = |id: u32| spec.modules.iter().find(|m| m.id == id).map_or(String::new(), |m| m.name.clone());
+        let target_name = name(target.module_id);
+
+        let mut connections = Vec::new();
+        for (index, (from, port)) in inputs.iter().enumerate() {
+            let Some(source) = converted.get(from.as_str()) else {
+                report.warnings.push(format!("{}: input from proxy {} outside the pipeline dropped", target_name, from));
+                continue;
+            };
+            let source_name = name(source.module_id);
+            let reason = match (source.ports, target.ports) {
+                (None, _) => format!("{} is a placeholder", source_name),
+                (_, None) => format!("{} is a placeholder", target_name),
+                (_, Some(Ports { input: None, .. })) => format!("{} takes no input", target_name),
+                _ if *port != 0 => format!("output port {} of {} is not translated", port, source_name),
+                _ if index > 0 => format!("{} takes one input", target_name),
+                (Some(from_ports), Some(Ports { input: Some(to_port), .. })) => {
+                    connections.push(ConnectionSpec {
+                        from_module: source.module_id,
+                        from_port: from_ports.output.to_string(),
+                        to_module: target.module_id,
+                        to_port: to_port.to_string(),
+                    });
+                    continue;
+                }
+            };
+            report.warnings.push(format!("{}: connection from {} dropped, {}", target_name, source_name, reason));
+        }
+
+        for connection in connections {
+            if let Some(module) = spec.modules.iter_mut().find(|m| m.id == target.module_id) {
+                module.dependencies.push(connection.from_module);
+            }
+            spec.connections.push(connection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIPELINE_STATE: &str = r#"<ParaView>
+  <ServerManagerState version="5.11.0">
+    <Proxy group="sources" type="LegacyVTKReader" id="100" servers="1">
+      <Property name="FileNames" id="100.FileNames" number_of_elements="2">
+        <Element index="1" value="/data/b.vtk"/>
+        <Element index="0" value="/data/a.vtk"/>
+      </Property>
+    </Proxy>
+    <Proxy group="filters" type="Contour" id="200" servers="1">
+      <Property name="Input" id="200.Input"><Proxy value="100" output_port="0"/></Property>
+      <Property name="ContourValues" id="200.ContourValues">
+        <Element index="0" value="0.5"/>
+        <Element index="1" value="1.5"/>
+      </Property>
+      <Property name="ComputeNormals" id="200.ComputeNormals"><Element index="0" value="1"/></Property>
+      <Property name="ComputeScalars" id="200.ComputeScalars"/>
+    </Proxy>
+    <Proxy group="implicit_functions" type="Plane" id="301" servers="1">
+      <Property name="Origin" id="301.Origin">
+        <Element index="0" value="1"/><Element index="1" value="2"/><Element index="2" value="3"/>
+      </Property>
+      <Property name="Normal" id="301.Normal">
+        <Element index="0" value="0"/><Element index="1" value="0"/><Element index="2" value="1"/>
+      </Property>
+      <Property name="Offset" id="301.Offset"><Element index="0" value="0"/></Property>
+    </Proxy>
+    <Proxy group="filters" type="Cut" id="300" servers="1">
+      <Property name="Input" id="300.Input"><Proxy value="100" output_port="0"/></Property>
+      <Property name="SliceType" id="300.SliceType"><Proxy value="301"/></Property>
+    </Proxy>
+    <Proxy group="filters" type="Threshold" id="400" servers="1">
+      <Property name="Input" id="400.Input"><Proxy value="300" output_port="0"/></Property>
+      <Property name="LowerThreshold" id="400.LowerThreshold"><Element index="0" value="2"/></Property>
+      <Property name="UpperThreshold" id="400.UpperThreshold"><Element index="0" value="5"/></Property>
+      <Property name="ThresholdMethod" id="400.ThresholdMethod"><Element index="0" value="0"/></Property>
+    </Proxy>
+    <Proxy group="filters" type="Calculator" id="500" servers="1">
+      <Property name="Input" id="500.Input"><Proxy value="200" output_port="0"/></Property>
+      <Property name="Function" id="500.Function"><Element index="0" value="p*2"/></Property>
+      <Property name="ResultArrayName" id="500.ResultArrayName"><Element index="0" value="q"/></Property>
+    </Proxy>
+    <ProxyCollection name="sources">
+      <Item id="100" name="a.vtk"/>
+      <Item id="200" name="Contour1"/>
+      <Item id="300" name="Slice1"/>
+      <Item id="400" name="Threshold1"/>
+      <Item id="500" name="Calculator1"/>
+    </ProxyCollection>
+  </ServerManagerState>
+</ParaView>"#;
+
+    fn module<'a>(spec: &'a WorkflowSpec, name: &str) -> &'a ModuleSpec {
+        spec.modules.iter().find(|m| m.name == name).unwrap()
+    }
+
+    fn connection(from_module: u32, from_port: &str, to_module: u32, to_port: &str) -> ConnectionSpec {
+        ConnectionSpec {
+            from_module,
+            from_port: from_port.to_string(),
+            to_module,
+            to_port: to_port.to_string(),
+        }
+    }
+
+    #[test]
+    fn pipeline_proxies_become_modules_with_their_settings() {
+        let (spec, report) = WorkflowSpec::from_paraview_xml("state", PIPELINE_STATE).unwrap();
+        let types: Vec<(&str, &str)> = spec.modules.iter().map(|m| (m.name.as_str(), m.module_type.as_str())).collect();
+        assert_eq!(types, [
+            ("a.vtk", "ReadAny"),
+            ("Contour1", "IsoSurface"),
+            ("Slice1", "Slice"),
+            ("Threshold1", "Threshold"),
+            ("Calculator1", PARAVIEW_PLACEHOLDER),
+        ]);
+        assert_eq!(module(&spec, "a.vtk").parameters["filename"], "/data/a.vtk");
+        assert_eq!(module(&spec, "Contour1").parameters["iso_value"], "0.5");
+        assert_eq!(module(&spec, "Slice1").parameters["center"], "1.0 2.0 3.0");
+        assert_eq!(module(&spec, "Slice1").parameters["normal"], "0.0 0.0 1.0");
+        assert_eq!(module(&spec, "Threshold1").parameters["min"], "2.0");
+        assert_eq!(module(&spec, "Threshold1").parameters["max"], "5.0");
+        assert_eq!(module(&spec, "Calculator1").parameters["paraview_type"], "Calculator");
+
+        let proxy_ids: Vec<&str> = report.proxies.iter().map(|p| p.proxy_id.as_str()).collect();
+        assert_eq!(proxy_ids, ["100", "200", "300", "400", "500"]);
+        let unmapped: Vec<&str> = report.unmapped().map(|p| p.paraview_type.as_str()).collect();
+        assert_eq!(unmapped, ["Calculator"]);
+    }
+
+    #[test]
+    fn connections_follow_the_inputs_of_translated_proxies() {
+        let (spec, report) = WorkflowSpec::from_paraview_xml("state", PIPELINE_STATE).unwrap();
+        assert_eq!(spec.connections, [
+            connection(1, "data", 2, "data_in"),
+            connection(1, "data", 3, "grid_in"),
+            connection(3, "grid_out", 4, "grid_in"),
+        ]);
+        assert_eq!(module(&spec, "Threshold1").dependencies, [3]);
+        assert!(module(&spec, "Calculator1").dependencies.is_empty());
+        assert_eq!(report.warnings, [
+            "Calculator1: Calculator computing q = p*2 has no equivalent and became a placeholder",
+            "Calculator1: connection from Contour1 dropped, Calculator1 is a placeholder",
+        ]);
+    }
+
+    #[test]
+    fn untranslated_settings_are_reported() {
+        let (_, report) = WorkflowSpec::from_paraview_xml("state", PIPELINE_STATE).unwrap();
+        let dropped: Vec<(u32, &str, &str, &str)> = report.dropped.iter()
+            .map(|d| (d.module_id, d.setting.as_str(), d.value.as_str(), d.reason.as_str()))
+            .collect();
+        assert_eq!(dropped, [
+            (1, "FileNames", "/data/b.vtk", "only the first file is read"),
+            (2, "ContourValues", "1.5", "one iso value per IsoSurface; add a module per value"),
+            (2, "ComputeNormals", "1", "not translated"),
+        ]);
+        assert!(!report.is_complete());
+    }
+
+    #[test]
+    fn clips_and_glyphs_translate_their_functions_and_sources() {
+        let state = r#"<ParaView><ServerManagerState version="5.9.1">
+            <Proxy group="implicit_functions" type="Sphere" id="11">
+              <Property name="Center"><Element index="0" value="0"/><Element index="1" value="1"/><Element index="2" value="0"/></Property>
+              <Property name="Radius"><Element index="0" value="2.5"/></Property>
+            </Proxy>
+            <Proxy group="filters" type="Clip" id="10">
+              <Property name="Input"><Proxy value="99" output_port="0"/></Property>
+              <Property name="ClipType"><Proxy value="11"/></Property>
+              <Property name="Invert"><Element index="0" value="0"/></Property>
+            </Proxy>
+            <Proxy group="glyph_types" type="ConeSource" id="21"/>
+            <Proxy group="filters" type="Glyph" id="20">
+              <Property name="Input"><Proxy value="10" output_port="0"/></Property>
+              <Property name="GlyphType"><Proxy value="21"/></Property>
+              <Property name="ScaleFactor"><Element index="0" value="0.2"/></Property>
+              <Property name="ScaleArray"><Element index="0" value="POINTS"/><Element index="1" value="None"/></Property>
+            </Proxy>
+        </ServerManagerState></ParaView>"#;
+        let (spec, report) = WorkflowSpec::from_paraview_xml("clip", state).unwrap();
+
+        let clip = module(&spec, "Clip10");
+        assert_eq!(clip.module_type, "Clip");
+        assert_eq!(clip.parameters["function"], "sphere");
+        assert_eq!(clip.parameters["center"], "0.0 1.0 0.0");
+        assert_eq!(clip.parameters["radius"], "2.5");
+        assert_eq!(clip.parameters["keep_outside"], "true");
+
+        let glyph = module(&spec, "Glyph20");
+        assert_eq!(glyph.module_type, "SphereGlyphs");
+        assert_eq!(glyph.parameters["radius"], "0.1");
+        assert_eq!(glyph.parameters["scale_by_data"], "false");
+        assert_eq!(spec.connections, [connection(1, "grid_out", 2, "grid_in")]);
+        assert_eq!(report.warnings, [
+            "Glyph20: ConeSource glyphs are drawn as spheres",
+            "Clip10: input from proxy 99 outside the pipeline dropped",
+        ]);
+        assert!(report.dropped.is_empty());
+    }
+
+    #[test]
+    fn unreadable_values_are_dropped_with_the_parse_error() {
+        let state = r#"<ParaView><ServerManagerState>
+            <Proxy group="filters" type="Threshold" id="1">
+              <Property name="ThresholdBetween"><Element index="0" value="low"/><Element index="1" value="3"/></Property>
+            </Proxy>
+        </ServerManagerState></ParaView>"#;
+        let (spec, report) = WorkflowSpec::from_paraview_xml("threshold", state).unwrap();
+        assert!(spec.modules[0].parameters.is_empty());
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!((report.dropped[0].setting.as_str(), report.dropped[0].value.as_str()), ("ThresholdBetween", "low 3"));
+    }
+
+    #[test]
+    fn only_paraview_states_are_read() {
+        assert!(matches!(WorkflowSpec::from_paraview_xml("x", "<ParaView>"), Err(crate::Error::Config(_))));
+        let error = WorkflowSpec::from_paraview_xml("x", "<VTKFile/>").unwrap_err().to_string();
+        assert!(error.contains("no ServerManagerState element"), "{}", error);
+
+        let (spec, report) = WorkflowSpec::from_paraview_xml("empty", "<ParaView><ServerManagerState/></ParaView>").unwrap();
+        assert!(spec.modules.is_empty());
+        assert!(report.is_complete());
+    }
+
+    #[tokio::test]
+    async fn state_files_name_the_workflow_and_resolve_against_their_directory() {
+        let dir = std::env::temp_dir().join(format!("vistle_paraview_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("turbine.pvsm");
+        std::fs::write(&path, PIPELINE_STATE).unwrap();
+
+        let (spec, report) = WorkflowSpec::from_paraview_state(&path).await.unwrap();
+        assert_eq!(spec.id, "turbine");
+        assert_eq!(report.source, "turbine.pvsm");
+        assert_eq!(spec.description, "Imported from ParaView state turbine.pvsm");
+        assert_eq!(spec.base_dir, Some(std::fs::canonicalize(&dir).unwrap()));
+
+        report.save(dir.join("report.json")).await.unwrap();
+        let saved: ConversionReport = serde_json::from_str(&std::fs::read_to_string(dir.join("report.json")).unwrap()).unwrap();
+        assert_eq!(saved, report);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
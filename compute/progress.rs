@@ -0,0 +1,366 @@
+//! Progress and remaining-time estimates for runs made of many similar units
+//!
+//! A unit is a timestep of a timeseries run or a variant of a sweep. Unit
+//! durations feed an exponentially weighted moving average, which adapts
+//! when later steps get cheaper or more expensive.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::StageTiming;
+
+/// Weight of the newest unit duration in the moving average
+pub const ETA_SMOOTHING: f64 = 0.2;
+
+/// Units that must complete before an ETA is reported; the first ones are noisy
+pub const ETA_MIN_SAMPLES: usize = 3;
+
+/// Width of the ETA range in standard deviations
+const ETA_RANGE_SIGMAS: f64 = 2.0;
+
+/// Exponentially weighted mean and variance of unit durations
+#[derive(Debug, Clone)]
+pub struct EtaEstimator {
+    alpha: f64,
+    min_samples: usize,
+    samples: usize,
+    mean: f64,
+    variance: f64,
+}
+
+impl EtaEstimator {
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(f64::EPSILON, 1.0),
+            min_samples: ETA_MIN_SAMPLES,
+            samples: 0,
+            mean: 0.0,
+            variance: 0.0,
+        }
+    }
+
+    pub fn with_min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples.max(1);
+        self
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let x = duration.as_secs_f64();
+        self.samples += 1;
+        if self.samples == 1 {
+            self.mean = x;
+            self.variance = 0.0;
+            return;
+        }
+        let diff = x - self.mean;
+        let increment = self.alpha * diff;
+        self.mean += increment;
+        self.variance = (1.0 - self.alpha) * (self.variance + diff * increment);
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Smoothed seconds per unit, None before `min_samples` units
+    pub fn mean(&self) -> Option<f64> {
+        (self.samples >= self.min_samples).then_some(self.mean)
+    }
+
+    pub fn std_dev(&self) -> Option<f64> {
+        self.mean().map(|_| self.variance.max(0.0).sqrt())
+    }
+
+    /// Expected time for `remaining` more units
+    pub fn eta(&self, remaining: usize) -> Option<Duration> {
+        self.mean().map(|mean| Duration::from_secs_f64(mean * remaining as f64))
+    }
+
+    /// Likely range of the time for `remaining` more units
+    ///
+    /// Treating units as independent, the spread of their sum grows with
+    /// the square root of their number.
+    pub fn eta_range(&self, remaining: usize) -> Option<(Duration, Duration)> {
+        let (mean, std_dev) = (self.mean()?, self.std_dev()?);
+        let total = mean * remaining as f64;
+        let spread = ETA_RANGE_SIGMAS * std_dev * (remaining as f64).sqrt();
+        Some((Duration::from_secs_f64((total - spread).max(0.0)), Duration::from_secs_f64(total + spread)))
+    }
+}
+
+impl Default for EtaEstimator {
+    fn default() -> Self {
+        Self::new(ETA_SMOOTHING)
+    }
+}
+
+/// Progress of a run, as published to progress subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowProgress {
+    pub workflow_id: String,
+    pub completed_units: usize,
+    pub total_units: usize,
+    /// Expected remaining time, None until enough units completed
+    pub eta: Option<Duration>,
+    /// Likely lower and upper bound of the remaining time
+    pub eta_range: Option<(Duration, Duration)>,
+    /// Units per second, from the smoothed unit duration
+    pub rate: Option<f64>,
+    /// Wall time since the start, paused time excluded
+    pub elapsed: Duration,
+    pub paused: bool,
+    /// Time spent in each named stage over the completed units
+    #[serde(default)]
+    pub stages: Vec<(String, Duration)>,
+}
+
+impl WorkflowProgress {
+    /// Completed fraction in 0..=1
+    pub fn fraction(&self) -> f32 {
+        if self.total_units == 0 {
+            return 1.0;
+        }
+        (self.completed_units as f32 / self.total_units as f32).min(1.0)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.completed_units >= self.total_units
+    }
+}
+
+/// Short human form of a duration, e.g. `42 min` or `1 h 5 min`
+pub fn format_eta(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{} s", seconds),
+        60..=3599 => format!("{} min", (seconds + 30) / 60),
+        _ => format!("{} h {} min", seconds / 3600, (seconds % 3600) / 60),
+    }
+}
+
+impl fmt::Display for WorkflowProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}/{}", self.completed_units, self.total_units)?;
+        if self.paused {
+            return write!(f, ", paused");
+        }
+        match (self.eta, self.eta_range) {
+            _ if self.is_finished() => Ok(()),
+            (Some(eta), Some((low, high))) if high.saturating_sub(low) >= eta / 4 => write!(
+                f, ", ~{} remaining ({} to {})",
+                format_eta(eta), format_eta(low), format_eta(high)
+            ),
+            (Some(eta), _) => write!(f, ", ~{} remaining", format_eta(eta)),
+            (None, _) => write!(f, ", estimating"),
+        }
+    }
+}
+
+/// Counts completed units of one run and times them, excluding pauses
+#[derive(Debug, Clone)]
+pub struct ProgressTracker {
+    workflow_id: String,
+    total_units: usize,
+    completed_units: usize,
+    estimator: EtaEstimator,
+    started: Instant,
+    /// Start of the unit in progress
+    unit_started: Instant,
+    paused_at: Option<Instant>,
+    /// Paused time during the current unit and in total
+    unit_paused: Duration,
+    total_paused: Duration,
+    stages: Vec<(String, Duration)>,
+}
+
+impl ProgressTracker {
+    pub fn new(workflow_id: &str, total_units: usize) -> Self {
+        let now = Instant::now();
+        Self {
+            workflow_id: workflow_id.to_string(),
+            total_units,
+            completed_units: 0,
+            estimator: EtaEstimator::default(),
+            started: now,
+            unit_started: now,
+            paused_at: None,
+            unit_paused: Duration::ZERO,
+            total_paused: Duration::ZERO,
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn with_estimator(mut self, estimator: EtaEstimator) -> Self {
+        self.estimator = estimator;
+        self
+    }
+
+    /// Mark a unit done; its duration is the unpaused time since the previous one
+    pub fn complete_unit(&mut self) {
+        let now = Instant::now();
+        let paused = self.unit_paused + self.paused_at.map_or(Duration::ZERO, |at| now - at);
+        self.record_unit(now.duration_since(self.unit_started).saturating_sub(paused));
+        self.unit_started = now;
+        self.unit_paused = Duration::ZERO;
+        if let Some(at) = self.paused_at.as_mut() {
+            self.total_paused += now - *at;
+            *at = now;
+        }
+    }
+
+    /// Count a unit of known duration, e.g. one measured elsewhere
+    pub fn record_unit(&mut self, duration: Duration) {
+        self.completed_units += 1;
+        self.estimator.record(duration);
+    }
+
+    /// Add the stage times of a unit to the totals
+    pub fn add_stages(&mut self, stages: &[StageTiming]) {
+        for stage in stages {
+            match self.stages.iter_mut().find(|(name, _)| *name == stage.name) {
+                Some((_, total)) => *total += stage.duration,
+                None => self.stages.push((stage.name.clone(), stage.duration)),
+            }
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.paused_at.get_or_insert_with(Instant::now);
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(at) = self.paused_at.take() {
+            let paused = at.elapsed();
+            self.unit_paused += paused;
+            self.total_paused += paused;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    pub fn progress(&self) -> WorkflowProgress {
+        let remaining = self.total_units.saturating_sub(self.completed_units);
+        let paused = self.total_paused + self.paused_at.map_or(Duration::ZERO, |at| at.elapsed());
+        WorkflowProgress {
+            workflow_id: self.workflow_id.clone(),
+            completed_units: self.completed_units,
+            total_units: self.total_units,
+            eta: self.estimator.eta(remaining),
+            eta_range: self.estimator.eta_range(remaining),
+            rate: self.estimator.mean().filter(|&mean| mean > 0.0).map(|mean| 1.0 / mean),
+            elapsed: self.started.elapsed().saturating_sub(paused),
+            paused: self.is_paused(),
+            stages: self.stages.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn no_estimate_before_the_minimum_samples() {
+        let mut estimator = EtaEstimator::default();
+        estimator.record(secs(1.0));
+        estimator.record(secs(1.0));
+        assert_eq!(estimator.eta(10), None);
+        assert_eq!(estimator.eta_range(10), None);
+
+        estimator.record(secs(1.0));
+        assert_eq!(estimator.eta(10), Some(secs(10.0)));
+        // Identical units leave no spread
+        assert_eq!(estimator.eta_range(10), Some((secs(10.0), secs(10.0))));
+        assert_eq!(EtaEstimator::default().with_min_samples(1).samples(), 0);
+    }
+
+    #[test]
+    fn the_estimate_follows_units_getting_slower() {
+        let mut estimator = EtaEstimator::default();
+        for _ in 0..10 {
+            estimator.record(secs(1.0));
+        }
+        for _ in 0..10 {
+            estimator.record(secs(3.0));
+        }
+        // 3 - 2 * 0.8^10
+        let mean = estimator.mean().unwrap();
+        assert!((mean - 2.785).abs() < 1e-3, "{}", mean);
+    }
+
+    #[test]
+    fn the_range_widens_with_noisy_units_and_more_remaining() {
+        let mut estimator = EtaEstimator::new(0.5);
+        for duration in [1.0, 3.0, 1.0, 3.0, 1.0, 3.0] {
+            estimator.record(secs(duration));
+        }
+        assert!(estimator.std_dev().unwrap() > 0.0);
+
+        let eta = estimator.eta(4).unwrap();
+        let (low, high) = estimator.eta_range(4).unwrap();
+        assert!(low < eta && eta < high);
+        let (far_low, far_high) = estimator.eta_range(16).unwrap();
+        assert!(far_high - far_low > high - low);
+    }
+
+    #[test]
+    fn etas_are_formatted_for_people() {
+        assert_eq!(format_eta(secs(42.0)), "42 s");
+        assert_eq!(format_eta(secs(90.0)), "2 min");
+        assert_eq!(format_eta(secs(3900.0)), "1 h 5 min");
+    }
+
+    #[test]
+    fn progress_reports_counts_rates_and_stages() {
+        let mut tracker = ProgressTracker::new("series", 4).with_estimator(EtaEstimator::new(1.0).with_min_samples(1));
+        assert_eq!(tracker.progress().to_string(), "step 0/4, estimating");
+
+        tracker.record_unit(secs(2.0));
+        let stage = |name: &str, duration: f64| StageTiming {
+            name: name.to_string(),
+            modules: vec![1],
+            start: Duration::ZERO,
+            duration: secs(duration),
+            budget: None,
+            exceeded: false,
+        };
+        tracker.add_stages(&[stage("read", 1.0), stage("render", 0.5)]);
+        tracker.add_stages(&[stage("read", 2.0)]);
+
+        let progress = tracker.progress();
+        assert_eq!((progress.completed_units, progress.fraction()), (1, 0.25));
+        assert_eq!(progress.eta, Some(secs(6.0)));
+        assert_eq!(progress.rate, Some(0.5));
+        assert_eq!(progress.stages, vec![("read".to_string(), secs(3.0)), ("render".to_string(), secs(0.5))]);
+        assert_eq!(progress.to_string(), "step 1/4, ~6 s remaining");
+
+        for _ in 0..3 {
+            tracker.record_unit(secs(2.0));
+        }
+        assert!(tracker.progress().is_finished());
+        assert_eq!(tracker.progress().to_string(), "step 4/4");
+    }
+
+    #[test]
+    fn paused_time_is_not_counted() {
+        let mut tracker = ProgressTracker::new("paused", 2).with_estimator(EtaEstimator::default().with_min_samples(1));
+        tracker.pause();
+        assert!(tracker.is_paused());
+        assert_eq!(tracker.progress().to_string(), "step 0/2, paused");
+        std::thread::sleep(Duration::from_millis(200));
+        tracker.resume();
+
+        tracker.complete_unit();
+        let progress = tracker.progress();
+        assert!(progress.elapsed < Duration::from_millis(100), "{:?}", progress.elapsed);
+        assert!(progress.eta.unwrap() < Duration::from_millis(100));
+    }
+}
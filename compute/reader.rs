@@ -0,0 +1,284 @@
+//! Choosing a reader module for a file, and the `ReadAny` module delegating to it
+//!
+//! Readers advertise the files they open through the `FileMatcher`s of
+//! their `ModuleDescriptor`. The extension decides first; the first
+//! `SNIFF_LEN` bytes of the file settle files without a known extension
+//! and extensions several readers claim.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use tokio::io::AsyncReadExt;
+
+use crate::core::{
+    ComputeContext, ExecutionStats, MessageRouter, ModuleInfo, Parameter, ParameterSet, ParameterValue,
+    Port, PortSet,
+};
+use crate::compute::{InputPort, InputPorts, Module, ModuleDescriptor, ModuleRegistry, OutputPorts, SNIFF_LEN};
+
+/// Ids of reader instances `ReadAny` creates, kept clear of workflow and loader module ids
+const READ_ANY_MODULE_ID_BASE: u32 = 0xC000_0000;
+
+/// Legacy VTK files, which start with a version line
+pub fn sniff_vtk_legacy(header: &[u8; SNIFF_LEN]) -> bool {
+    header.starts_with(b"# vtk DataFile Version")
+}
+
+/// VTK XML files (.vtu, .vti, ...), whose root element is `VTKFile`
+pub fn sniff_vtk_xml(header: &[u8; SNIFF_LEN]) -> bool {
+    header.windows(8).any(|w| w == b"<VTKFile")
+}
+
+pub fn sniff_hdf5(header: &[u8; SNIFF_LEN]) -> bool {
+    header.starts_with(b"\x89HDF\r\n\x1a\n")
+}
+
+/// Classic, 64-bit offset and CDF5 netCDF files; netCDF-4 files are HDF5
+pub fn sniff_netcdf(header: &[u8; SNIFF_LEN]) -> bool {
+    [b"CDF\x01", b"CDF\x02", b"CDF\x05"].iter().any(|magic| header.starts_with(*magic))
+}
+
+/// First `SNIFF_LEN` bytes of a file, zero-padded if it is shorter
+pub async fn read_file_header(path: &Path) -> Result<[u8; SNIFF_LEN], crate::Error> {
+    let mut file = tokio::fs::File::open(path).await
+        .map_err(|e| crate::Error::Config(format!("Cannot open {}: {}", path.display(), e)))?;
+    let mut header = [0u8; SNIFF_LEN];
+    let mut filled = 0;
+    while filled < SNIFF_LEN {
+        match file.read(&mut header[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(header)
+}
+
+fn ambiguous(path: &Path, candidates: &[&ModuleDescriptor]) -> crate::Error {
+    let names: Vec<&str> = candidates.iter().map(|d| d.name.as_str()).collect();
+    crate::Error::Config(format!(
+        "{} matches several readers: {}; choose one explicitly",
+        path.display(), names.join(", ")
+    ))
+}
+
+/// Reader among `readers` for `path`
+///
+/// A unique extension match wins without reading the file. Otherwise the
+/// sniffers of the extension matches, or of all readers if none matched,
+/// decide; more than one remaining candidate is an error naming them all.
+pub async fn select_reader<F, Fut>(readers: &[ModuleDescriptor], path: &Path, header: F) -> Result<Option<String>, crate::Error>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<[u8; SNIFF_LEN], crate::Error>>,
+{
+    let mut by_extension: Vec<&ModuleDescriptor> = readers.iter()
+        .filter(|d| d.file_matchers.iter().any(|m| m.matches_extension(path)))
+        .collect();
+    by_extension.sort_by(|a, b| a.name.cmp(&b.name));
+    if let [reader] = by_extension.as_slice() {
+        return Ok(Some(reader.name.clone()));
+    }
+
+    let header = header().await?;
+    let candidates: Vec<&ModuleDescriptor> = if by_extension.is_empty() {
+        let mut all: Vec<&ModuleDescriptor> = readers.iter().collect();
+        all.sort_by(|a, b| a.name.cmp(&b.name));
+        all
+    } else {
+        by_extension.clone()
+    };
+    let sniffed: Vec<&ModuleDescriptor> = candidates.into_iter()
+        .filter(|d| d.file_matchers.iter().any(|m| m.matches_content(&header)))
+        .collect();
+
+    match (&sniffed[..], by_extension.is_empty()) {
+        ([reader], _) => Ok(Some(reader.name.clone())),
+        ([], true) => Ok(None),
+        ([], false) => Err(ambiguous(path, &by_extension)),
+        (_, _) => Err(ambiguous(path, &sniffed)),
+    }
+}
+
+/// Module reading any file some registered reader can open
+///
+/// At execution the reader is picked with `ModuleRegistry::reader_for`,
+/// unless the `reader` parameter names one, and run on the file. Its
+/// outputs are passed on under their own port names; readers conventionally
+/// use `data`.
+pub struct ReadAny {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+    modules: Arc<ModuleRegistry>,
+    router: Arc<MessageRouter>,
+    next_id: AtomicU32,
+}
+
+impl ReadAny {
+    pub fn new(id: u32, modules: Arc<ModuleRegistry>, router: Arc<MessageRouter>) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::file_path("filename", "File to read", ""));
+        parameters.add(Parameter::new("reader", "Reader module to use; empty picks one from the file", ParameterValue::String(String::new())));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_output("data", "Data of the delegated reader").optional());
+
+        let mut info = ModuleInfo::new(id, "ReadAny", 0, 1);
+        info.category = "Read".to_string();
+
+        Self {
+            info,
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+            modules,
+            router,
+            next_id: AtomicU32::new(READ_ANY_MODULE_ID_BASE),
+        }
+    }
+
+    /// Register `ReadAny` with the registry it selects readers from
+    pub async fn register(modules: &Arc<ModuleRegistry>, router: Arc<MessageRouter>) {
+        let registry = modules.clone();
+        modules.register_described(
+            ModuleDescriptor::new("ReadAny").with_category("Read"),
+            move || ReadAny::new(0, registry.clone(), router.clone()),
+        ).await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for ReadAny {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let filename = ctx.parameters().get_string("filename").unwrap_or("");
+        if filename.is_empty() {
+            return Err(crate::Error::Config("ReadAny needs a filename".to_string()));
+        }
+        let path = ctx.resolve_path(filename)?;
+
+        let reader = match ctx.parameters().get_string("reader").filter(|r| !r.is_empty()) {
+            Some(reader) => reader.to_string(),
+            None => self.modules.reader_for(&path).await?
+                .ok_or_else(|| crate::Error::Config(format!("No reader recognizes {}", path.display())))?,
+        };
+        let descriptor = self.modules.descriptor(&reader).await
+            .ok_or_else(|| crate::Error::Module(format!("Module {} not found", reader)))?;
+        tracing::info!("ReadAny {}: reading {} with {}", self.info.id, path.display(), reader);
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let instance = self.modules.create_instance(&reader, id).await?;
+        let result = async {
+            instance.set_parameter(&descriptor.file_parameter, ParameterValue::String(path.display().to_string()))?;
+            let mut delegate = ctx.clone();
+            delegate.module_id = id;
+            instance.execute(&delegate, &self.router).await
+        }.await;
+        self.modules.remove_instance(id).await;
+        result
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::testing::modules::ConstantField;
+    use crate::compute::FileMatcher;
+
+    fn header(start: &[u8]) -> [u8; SNIFF_LEN] {
+        let mut header = [0u8; SNIFF_LEN];
+        header[..start.len()].copy_from_slice(start);
+        header
+    }
+
+    fn readers() -> Vec<ModuleDescriptor> {
+        vec![
+            ModuleDescriptor::reader("ReadVtk", FileMatcher::extensions(&["vtk", ".VTU"]).with_sniffer(sniff_vtk_legacy)),
+            ModuleDescriptor::reader("ReadHdf5", FileMatcher::extensions(&["h5", "dat"]).with_sniffer(sniff_hdf5)),
+            ModuleDescriptor::reader("ReadNetcdf", FileMatcher::extensions(&["nc", "dat"]).with_sniffer(sniff_netcdf)),
+        ]
+    }
+
+    async fn unread() -> Result<[u8; SNIFF_LEN], crate::Error> {
+        panic!("a unique extension match must not read the file")
+    }
+
+    #[test]
+    fn sniffers_recognize_their_magic_bytes() {
+        assert!(sniff_vtk_legacy(&header(b"# vtk DataFile Version 3.0\n")));
+        assert!(sniff_vtk_xml(&header(b"<?xml version=\"1.0\"?>\n<VTKFile type=\"UnstructuredGrid\">")));
+        assert!(sniff_hdf5(&header(b"\x89HDF\r\n\x1a\n")));
+        assert!(sniff_netcdf(&header(b"CDF\x02")));
+        assert!(!sniff_netcdf(&header(b"CDF\x03")));
+        assert!(!sniff_vtk_legacy(&header(b"")));
+    }
+
+    #[tokio::test]
+    async fn a_unique_extension_selects_the_reader_without_reading() {
+        let selected = select_reader(&readers(), Path::new("/data/mesh.VTU"), unread).await.unwrap();
+        assert_eq!(selected.as_deref(), Some("ReadVtk"));
+    }
+
+    #[tokio::test]
+    async fn content_settles_a_shared_extension() {
+        let netcdf = || async { Ok(header(b"CDF\x01")) };
+        let selected = select_reader(&readers(), Path::new("run.dat"), netcdf).await.unwrap();
+        assert_eq!(selected.as_deref(), Some("ReadNetcdf"));
+    }
+
+    #[tokio::test]
+    async fn an_unsettled_shared_extension_names_both_candidates() {
+        let text = || async { Ok(header(b"1.0 2.0 3.0\n")) };
+        let error = select_reader(&readers(), Path::new("run.dat"), text).await.unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("ReadHdf5") && message.contains("ReadNetcdf"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn unrecognized_files_have_no_reader() {
+        let text = || async { Ok(header(b"just text")) };
+        assert_eq!(select_reader(&readers(), Path::new("notes"), text).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn registry_sniffs_an_extensionless_vtk_file() {
+        let registry = ModuleRegistry::new();
+        for descriptor in readers() {
+            registry.register_described(descriptor, || ConstantField::new(0)).await;
+        }
+        registry.register("ConstantField", || ConstantField::new(0)).await;
+
+        let path = std::env::temp_dir().join(format!("vistle_reader_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&path, b"# vtk DataFile Version 2.0\nmesh\nASCII\n").unwrap();
+        let selected = registry.reader_for(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(selected.unwrap().as_deref(), Some("ReadVtk"));
+    }
+}
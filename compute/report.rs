@@ -0,0 +1,732 @@
+//! Machine-readable reports of workflow executions
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{ConnectionSpec, InsertedAdapter, NonFiniteOffender, OutputDecision, TaskResult, WorkflowResult};
+use crate::core::{ExecutionStats, Object, ObjectType, PrefetchStats, RetentionDeletion, ShmStats, StatsError};
+use crate::render::CacheStats;
+
+/// Bumped whenever a field of the report is renamed, removed or changes meaning
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Errors per module copied from its execution statistics
+pub const REPORT_RECENT_ERRORS: usize = 10;
+
+/// How a module fared in a workflow execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModuleOutcome {
+    Succeeded,
+    Failed,
+    /// No task ran for the module, e.g. because the workflow timed out
+    NotRun,
+}
+
+impl ModuleOutcome {
+    /// CSS class and label in the HTML summary
+    fn html(self) -> (&'static str, &'static str) {
+        match self {
+            ModuleOutcome::Succeeded => ("succeeded", "succeeded"),
+            ModuleOutcome::Failed => ("failed", "failed"),
+            ModuleOutcome::NotRun => ("not_run", "not run"),
+        }
+    }
+}
+
+/// Error of a failed module with a stable code for grouping
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportError {
+    /// `mpi`, `serialization`, `shared_memory`, `compute`, `render`, `io`, `config`, `module`, `cancelled`, `wrong_type`, `panic` or `unknown`
+    pub code: String,
+    pub message: String,
+}
+
+impl ReportError {
+    /// Split an error message as produced by `crate::Error`'s Display into code and message
+    pub fn from_message(text: &str) -> Self {
+        const PREFIXES: [(&str, &str); 10] = [
+            ("MPI error: ", "mpi"),
+            ("Serialization error: ", "serialization"),
+            ("Shared memory error: ", "shared_memory"),
+            ("Compute error: ", "compute"),
+            ("Render error: ", "render"),
+            ("IO error: ", "io"),
+            ("Configuration error: ", "config"),
+            ("Module error: ", "module"),
+            ("Cancelled: ", "cancelled"),
+            ("Wrong object type: ", "wrong_type"),
+        ];
+        for (prefix, code) in PREFIXES {
+            if let Some(message) = text.strip_prefix(prefix) {
+                // Panics caught around module compute get a code of their own
+                let code = if message.starts_with("panicked: ") { "panic" } else { code };
+                return Self { code: code.to_string(), message: message.to_string() };
+            }
+        }
+        if text.starts_with("panicked: ") {
+            return Self { code: "panic".to_string(), message: text.to_string() };
+        }
+        Self { code: "unknown".to_string(), message: text.to_string() }
+    }
+}
+
+/// Outcome of one module
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleReport {
+    pub module_id: u32,
+    pub name: String,
+    pub module_type: String,
+    pub status: ModuleOutcome,
+    pub duration_ms: f64,
+    /// Objects the module produced across all output ports
+    pub objects_created: usize,
+    pub error: Option<ReportError>,
+    /// Parameter values the module was run with
+    pub parameters: BTreeMap<String, String>,
+    /// Errors over the module's lifetime, from its execution statistics
+    #[serde(default)]
+    pub error_count: u64,
+    /// Most recent of those errors, newest first
+    #[serde(default)]
+    pub recent_errors: Vec<StatsError>,
+}
+
+/// Summary of a workflow execution, serializable to JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowReport {
+    pub schema_version: u32,
+    pub workflow_id: String,
+    pub workflow_name: String,
+    pub success: bool,
+    /// Milliseconds since the Unix epoch
+    pub generated_at_ms: u64,
+    pub duration_ms: f64,
+    pub modules: Vec<ModuleReport>,
+    pub peak_memory_bytes: Option<usize>,
+    pub shm: Option<ShmStats>,
+    /// Render cache counters, if a renderer took part
+    pub cache: Option<CacheStats>,
+    /// What was done about the output files of writers, see `OutputPolicy`
+    #[serde(default)]
+    pub outputs: Vec<OutputDecision>,
+    /// Timesteps loaded ahead, if any placeholders were resolved
+    #[serde(default)]
+    pub prefetch: Option<PrefetchStats>,
+    /// Adapter modules inserted between ports of different data types
+    #[serde(default)]
+    pub adapters: Vec<InsertedAdapter>,
+    /// Files deleted by retention policies, or listed in a dry run
+    #[serde(default)]
+    pub retention: Vec<RetentionDeletion>,
+    /// Module that first produced NaN or infinite values, if outputs were audited
+    #[serde(default)]
+    pub first_nonfinite: Option<NonFiniteOffender>,
+}
+
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn module_report(spec: &crate::compute::ModuleSpec, result: Option<&TaskResult>) -> ModuleReport {
+    let (status, duration_ms, objects_created, error) = match result {
+        Some(result) => (
+            if result.success { ModuleOutcome::Succeeded } else { ModuleOutcome::Failed },
+            millis(result.execution_time),
+            result.outputs.as_ref().map(|ports| ports.values().map(Vec::len).sum()).unwrap_or(0),
+            result.error.as_deref().map(ReportError::from_message),
+        ),
+        None => (ModuleOutcome::NotRun, 0.0, 0, None),
+    };
+    ModuleReport {
+        module_id: spec.id,
+        name: spec.name.clone(),
+        module_type: spec.module_type.clone(),
+        status,
+        duration_ms,
+        objects_created,
+        error,
+        parameters: spec.parameters.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        error_count: 0,
+        recent_errors: Vec::new(),
+    }
+}
+
+/// What flowed across one connection during a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    pub connection: ConnectionSpec,
+    pub objects: usize,
+    /// Objects among `objects` that carried no data, see `Object::is_empty`
+    #[serde(default)]
+    pub empty_objects: usize,
+    /// Approximate, from `Object::payload_size`
+    pub payload_bytes: usize,
+    /// Distinct object types, in the order they were seen
+    pub object_types: Vec<ObjectType>,
+    /// First and last timestep, None without objects
+    pub timesteps: Option<(i32, i32)>,
+    /// NaN and infinite values found by the output audit, see `compute::audit`
+    #[serde(default)]
+    pub nonfinite: usize,
+}
+
+impl ConnectionStats {
+    pub fn new(connection: ConnectionSpec) -> Self {
+        Self {
+            connection,
+            objects: 0,
+            empty_objects: 0,
+            payload_bytes: 0,
+            object_types: Vec::new(),
+            timesteps: None,
+            nonfinite: 0,
+        }
+    }
+
+    /// Count objects sent across the connection
+    pub fn record(&mut self, objects: &[std::sync::Arc<dyn Object>]) {
+        for object in objects {
+            self.objects += 1;
+            if object.is_empty() {
+                self.empty_objects += 1;
+            }
+            self.payload_bytes += object.payload_size();
+            if !self.object_types.contains(&object.object_type()) {
+                self.object_types.push(object.object_type());
+            }
+            let t = object.meta().timestep;
+            self.timesteps = Some(self.timesteps.map_or((t, t), |(first, last)| (first.min(t), last.max(t))));
+        }
+    }
+
+    /// Nothing flowed; downstream modules got no data, usually a bug in the workflow
+    pub fn is_empty(&self) -> bool {
+        self.objects == 0
+    }
+
+    /// Objects flowed, but every one of them was empty
+    pub fn only_empty_objects(&self) -> bool {
+        self.objects > 0 && self.empty_objects == self.objects
+    }
+
+    /// One-line summary for edge labels and logs
+    pub fn label(&self) -> String {
+        if self.is_empty() {
+            return "empty".to_string();
+        }
+        let mut label = format!("{} objects, {:.1} MiB", self.objects, self.payload_bytes as f64 / (1024.0 * 1024.0));
+        if self.only_empty_objects() {
+            label = format!("{} empty objects", self.objects);
+        } else if self.empty_objects > 0 {
+            let _ = write!(label, ", {} empty", self.empty_objects);
+        }
+        if self.nonfinite > 0 {
+            let _ = write!(label, ", {} NaN/Inf", self.nonfinite);
+        }
+        if let Some((first, last)) = self.timesteps {
+            if first == last {
+                let _ = write!(label, ", t={}", first);
+            } else {
+                let _ = write!(label, ", t={}..{}", first, last);
+            }
+        }
+        label
+    }
+
+    /// Statistics of every connection from the outputs modules returned
+    pub fn collect(connections: &[ConnectionSpec], results: &[TaskResult]) -> Vec<Self> {
+        connections.iter()
+            .map(|connection| {
+                let mut stats = Self::new(connection.clone());
+                let sources = results.iter().filter(|r| r.module_id == Some(connection.from_module));
+                for result in sources {
+                    if let Some(objects) = result.outputs.as_ref().and_then(|o| o.get(&connection.from_port)) {
+                        stats.record(objects);
+                    }
+                    stats.nonfinite += result.nonfinite.get(&connection.from_port).map_or(0, |a| a.nonfinite);
+                }
+                stats
+            })
+            .collect()
+    }
+}
+
+impl WorkflowResult {
+    /// Data transferred across each connection of the workflow
+    pub fn connection_stats(&self) -> &[ConnectionStats] {
+        &self.connection_stats
+    }
+
+    /// Connections no object flowed across
+    pub fn empty_connections(&self) -> impl Iterator<Item = &ConnectionStats> {
+        self.connection_stats.iter().filter(|c| c.is_empty())
+    }
+
+    /// Connections that carried only empty objects
+    pub fn empty_object_connections(&self) -> impl Iterator<Item = &ConnectionStats> {
+        self.connection_stats.iter().filter(|c| c.only_empty_objects())
+    }
+
+    /// No connection carried any data, e.g. a threshold nothing passed at the start of the pipeline
+    pub fn is_fully_empty(&self) -> bool {
+        !self.connection_stats.is_empty()
+            && self.connection_stats.iter().all(|c| c.is_empty() || c.only_empty_objects())
+    }
+
+    pub fn to_report(&self) -> WorkflowReport {
+        let modules = self.modules.iter()
+            .map(|spec| {
+                let result = self.task_results.iter().find(|r| r.module_id == Some(spec.id));
+                module_report(spec, result)
+            })
+            .collect();
+
+        WorkflowReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            workflow_id: self.workflow_id.clone(),
+            workflow_name: self.workflow_name.clone(),
+            success: self.success,
+            generated_at_ms: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            duration_ms: millis(self.execution_time),
+            modules,
+            peak_memory_bytes: None,
+            shm: self.shm_stats.clone(),
+            cache: None,
+            outputs: self.outputs.clone(),
+            prefetch: (!self.prefetch.is_empty()).then(|| self.prefetch.clone()),
+            adapters: self.adapters.clone(),
+            retention: self.retention.clone(),
+            first_nonfinite: self.first_nonfinite.clone(),
+        }
+    }
+
+    /// Write the report as pretty-printed JSON
+    pub async fn save_report(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        self.to_report().save(path).await
+    }
+}
+
+impl WorkflowReport {
+    pub fn with_peak_memory(mut self, tracker: &crate::util::MemoryTracker) -> Self {
+        self.peak_memory_bytes = Some(tracker.peak_usage());
+        self
+    }
+
+    pub fn with_cache_stats(mut self, stats: CacheStats) -> Self {
+        self.cache = Some(stats);
+        self
+    }
+
+    /// Attach the error history of each module's execution statistics
+    pub fn with_execution_stats(mut self, stats: &[ExecutionStats]) -> Self {
+        for module in &mut self.modules {
+            if let Some(stats) = stats.iter().find(|s| s.module_id == module.module_id) {
+                module.error_count = stats.error_count();
+                module.recent_errors = stats.recent_errors(REPORT_RECENT_ERRORS).into_iter().cloned().collect();
+            }
+        }
+        self
+    }
+
+    pub fn failed_modules(&self) -> impl Iterator<Item = &ModuleReport> {
+        self.modules.iter().filter(|m| m.status == ModuleOutcome::Failed)
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize report: {}", e)))?;
+        crate::util::io::write_text(path, &json).await
+    }
+
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let json = crate::util::io::read_text(path).await?;
+        serde_json::from_str(&json)
+            .map_err(|e| crate::Error::Config(format!("Failed to parse report: {}", e)))
+    }
+
+    /// Self-contained HTML page summarizing the report
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">");
+        let _ = writeln!(out, "<title>Workflow {}</title>", escape_html(&self.workflow_name));
+        let _ = writeln!(out, "<style>\nbody {{ font-family: sans-serif; margin: 2em; }}\n\
+            table {{ border-collapse: collapse; }}\ntd, th {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; }}\n\
+            .succeeded {{ color: #2a7d2a; }}\n.failed {{ color: #b22222; }}\n.not_run {{ color: #888; }}\n</style>");
+        let _ = writeln!(out, "</head>\n<body>");
+        let _ = writeln!(
+            out,
+            "<h1>{} <small>({})</small></h1>",
+            escape_html(&self.workflow_name), escape_html(&self.workflow_id)
+        );
+        let _ = writeln!(
+            out,
+            "<p class=\"{}\">{} in {:.1} ms</p>",
+            if self.success { "succeeded" } else { "failed" },
+            if self.success { "Succeeded" } else { "Failed" },
+            self.duration_ms
+        );
+        if let Some(offender) = &self.first_nonfinite {
+            let name = self.modules.iter()
+                .find(|m| m.module_id == offender.module_id)
+                .map_or(String::new(), |m| format!("{} ", escape_html(&m.name)));
+            let ports: Vec<String> = offender.ports.iter()
+                .map(|(port, audit)| format!(
+                    "{} on {}{}",
+                    audit.nonfinite,
+                    escape_html(port),
+                    if audit.is_sampled() { " (sampled)" } else { "" }
+                ))
+                .collect();
+            let _ = writeln!(
+                out,
+                "<p class=\"failed\"><strong>NaN/Inf first introduced by module {}({})</strong>: {}</p>",
+                name, offender.module_id, ports.join(", ")
+            );
+        }
+
+        let _ = writeln!(out, "<ul>");
+        if let Some(peak) = self.peak_memory_bytes {
+            let _ = writeln!(out, "<li>Peak memory: {:.1} MiB</li>", peak as f64 / (1024.0 * 1024.0));
+        }
+        if let Some(shm) = &self.shm {
+            let _ = writeln!(
+                out,
+                "<li>Shared memory: {} of {} bytes used, {} objects</li>",
+                shm.used_size, shm.total_size, shm.object_count
+            );
+        }
+        if let Some(cache) = &self.cache {
+            let _ = writeln!(out, "<li>Render cache: {} hits, {} misses</li>", cache.hits, cache.misses);
+        }
+        if let Some(prefetch) = &self.prefetch {
+            let _ = writeln!(
+                out,
+                "<li>Prefetch: {} hits, {} misses, {:.1} ms of loading overlapped</li>",
+                prefetch.hits, prefetch.misses, prefetch.time_saved_ms
+            );
+        }
+        for adapter in &self.adapters {
+            let c = &adapter.connection;
+            let _ = writeln!(
+                out,
+                "<li>Adapters {} inserted from {}:{} to {}:{} ({} to {})</li>",
+                escape_html(&adapter.module_types.join(" -> ")),
+                c.from_module, escape_html(&c.from_port), c.to_module, escape_html(&c.to_port),
+                escape_html(&adapter.from_type), escape_html(&adapter.to_type)
+            );
+        }
+        let _ = writeln!(out, "</ul>");
+
+        let _ = writeln!(out, "<table>\n<tr><th>Id</th><th>Name</th><th>Type</th><th>Status</th><th>Duration (ms)</th><th>Objects</th><th>Parameters</th><th>Error</th></tr>");
+        for module in &self.modules {
+            let parameters: Vec<String> = module.parameters.iter()
+                .map(|(k, v)| format!("{}={}", escape_html(k), escape_html(v)))
+                .collect();
+            let mut error = module.error.as_ref()
+                .map(|e| format!("[{}] {}", escape_html(&e.code), escape_html(&e.message)))
+                .unwrap_or_default();
+            if module.error_count > 0 {
+                let _ = write!(error, "<br><small>{} errors in total", module.error_count);
+                if let Some(last) = module.recent_errors.first() {
+                    let _ = write!(error, ", last at timestep {}: [{}]", last.timestep, escape_html(&last.code));
+                }
+                error.push_str("</small>");
+            }
+            let (class, label) = module.status.html();
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td>{:.1}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                module.module_id,
+                escape_html(&module.name),
+                escape_html(&module.module_type),
+                class,
+                label,
+                module.duration_ms,
+                module.objects_created,
+                parameters.join("<br>"),
+                error
+            );
+        }
+        let _ = writeln!(out, "</table>");
+
+        if !self.outputs.is_empty() {
+            let _ = writeln!(out, "<h2>Outputs</h2>\n<table>\n<tr><th>Module</th><th>Path</th><th>Existing files</th><th>Action</th></tr>");
+            for output in &self.outputs {
+                let action = match &output.renamed {
+                    Some(renamed) => format!("{:?} to {}", output.action, escape_html(renamed)),
+                    None => format!("{:?}", output.action),
+                };
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    output.module_id,
+                    escape_html(&output.path.display().to_string()),
+                    output.existing.len(),
+                    action
+                );
+            }
+            let _ = writeln!(out, "</table>");
+        }
+        if !self.retention.is_empty() {
+            let _ = writeln!(out, "<h2>Retention</h2>\n<table>\n<tr><th>Path</th><th>Bytes</th><th>Reason</th><th>Deleted</th></tr>");
+            for deletion in &self.retention {
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{:?}</td><td>{}</td></tr>",
+                    escape_html(&deletion.path.display().to_string()),
+                    deletion.bytes,
+                    deletion.reason,
+                    if deletion.dry_run { "no, dry run" } else { "yes" }
+                );
+            }
+            let _ = writeln!(out, "</table>");
+        }
+        let _ = writeln!(out, "</body>\n</html>");
+        out
+    }
+
+    pub async fn save_html(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        crate::util::io::write_text(path, &self.to_html()).await
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{ModuleRegistry, ModuleSpec, TaskExecutor, WorkflowBuilder, WorkflowExecutor};
+    use crate::core::MessageRouter;
+
+    /// A constant field feeding a module that always fails
+    async fn failing_run() -> WorkflowResult {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        let executor = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()));
+        let spec = WorkflowBuilder::new("report", "Report <test> & more")
+            .add_module("ConstantField", "Source")
+                .parameter("count", "3")
+            .add_module("Failing", "Sink")
+                .depends_on(1)
+            .connect(1, "data_out", 2, "data_in")
+            .build();
+        executor.execute_workflow(spec, Some(Duration::from_secs(10))).await.unwrap()
+    }
+
+    #[test]
+    fn error_codes_come_from_the_error_kind() {
+        let error = ReportError::from_message(&crate::Error::Compute("no data".to_string()).to_string());
+        assert_eq!(error, ReportError { code: "compute".to_string(), message: "no data".to_string() });
+        assert_eq!(ReportError::from_message("Configuration error: bad").code, "config");
+        assert_eq!(ReportError::from_message("Wrong object type: expected a, found b").code, "wrong_type");
+        assert_eq!(ReportError::from_message("Module error: panicked: index out of bounds").code, "panic");
+        assert_eq!(ReportError::from_message("panicked: at main.rs").code, "panic");
+        let unknown = ReportError::from_message("something odd");
+        assert_eq!((unknown.code.as_str(), unknown.message.as_str()), ("unknown", "something odd"));
+    }
+
+    #[tokio::test]
+    async fn report_describes_every_module() {
+        let report = failing_run().await.to_report();
+        assert_eq!(report.schema_version, REPORT_SCHEMA_VERSION);
+        assert_eq!(report.workflow_name, "Report <test> & more");
+        assert!(!report.success);
+        assert_eq!(report.modules.len(), 2);
+
+        let source = &report.modules[0];
+        assert_eq!((source.module_id, source.module_type.as_str()), (1, "ConstantField"));
+        assert_eq!(source.status, ModuleOutcome::Succeeded);
+        assert_eq!(source.objects_created, 1);
+        assert_eq!(source.parameters.get("count").map(String::as_str), Some("3"));
+        assert!(source.error.is_none());
+
+        let sink = &report.modules[1];
+        assert_eq!(sink.status, ModuleOutcome::Failed);
+        let error = sink.error.as_ref().unwrap();
+        assert_eq!(error.code, "compute");
+        assert!(error.message.contains("failed as asked"), "{}", error.message);
+        assert_eq!(report.failed_modules().map(|m| m.module_id).collect::<Vec<_>>(), vec![2]);
+    }
+
+    fn connection(from: u32, to: u32) -> ConnectionSpec {
+        ConnectionSpec {
+            from_module: from,
+            from_port: "data_out".to_string(),
+            to_module: to,
+            to_port: "data_in".to_string(),
+        }
+    }
+
+    /// An empty object of `timestep`, as a filter emits when nothing passes
+    fn empty(timestep: i32) -> Arc<dyn Object> {
+        let source = crate::core::VistleObject::with_data(ObjectType::Vec, crate::core::ObjectPayload::VecScalar {
+            data: ndarray::array![1.0],
+        })
+        .with_meta(crate::core::ObjectMeta { timestep, ..Default::default() });
+        Arc::new(crate::core::VistleObject::empty_like(&source))
+    }
+
+    fn field(timestep: i32) -> Arc<dyn Object> {
+        Arc::new(
+            crate::core::VistleObject::with_data(ObjectType::Vec, crate::core::ObjectPayload::VecScalar {
+                data: ndarray::array![1.0, 2.0],
+            })
+            .with_meta(crate::core::ObjectMeta { timestep, ..Default::default() }),
+        )
+    }
+
+    #[test]
+    fn empty_objects_are_counted_apart_from_data() {
+        let mut stats = ConnectionStats::new(connection(1, 2));
+        assert!(stats.is_empty());
+        assert_eq!(stats.label(), "empty");
+
+        stats.record(&[empty(0), empty(1)]);
+        assert!(!stats.is_empty());
+        assert!(stats.only_empty_objects());
+        assert_eq!((stats.objects, stats.empty_objects, stats.payload_bytes), (2, 2, 0));
+        assert_eq!(stats.label(), "2 empty objects, t=0..1");
+
+        stats.record(&[field(2)]);
+        assert!(!stats.only_empty_objects());
+        assert!(stats.label().contains(", 2 empty, t=0..2"), "{}", stats.label());
+    }
+
+    #[test]
+    fn an_all_empty_timestep_shows_up_per_connection() {
+        let result = |module_id: u32, objects: Vec<Arc<dyn Object>>| TaskResult {
+            task_id: crate::compute::TaskId::new(module_id as u64),
+            module_id: Some(module_id),
+            success: true,
+            outputs: Some(crate::compute::OutputPorts::from([("data_out".to_string(), objects)])),
+            error: None,
+            execution_time: Duration::ZERO,
+            nonfinite: BTreeMap::new(),
+        };
+        // The reader produced data for both timesteps; the threshold passed nothing at timestep 1
+        let results = [result(1, vec![field(0), field(1)]), result(2, vec![field(0), empty(1)]), result(3, vec![])];
+        let stats = ConnectionStats::collect(&[connection(1, 2), connection(2, 3), connection(3, 4)], &results);
+
+        assert_eq!((stats[0].objects, stats[0].empty_objects), (2, 0));
+        assert_eq!((stats[1].objects, stats[1].empty_objects), (2, 1));
+        assert!(!stats[1].only_empty_objects());
+        assert!(stats[2].is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_pipeline_carrying_only_empties_is_fully_empty() {
+        let mut result = failing_run().await;
+        let mut only_empties = ConnectionStats::new(connection(1, 2));
+        only_empties.record(&[empty(0)]);
+        result.connection_stats = vec![only_empties.clone(), ConnectionStats::new(connection(2, 3))];
+        assert!(result.is_fully_empty());
+        assert_eq!(result.empty_object_connections().count(), 1);
+        assert_eq!(result.empty_connections().count(), 1);
+
+        let mut with_data = ConnectionStats::new(connection(1, 3));
+        with_data.record(&[field(0)]);
+        result.connection_stats.push(with_data);
+        assert!(!result.is_fully_empty());
+
+        result.connection_stats.clear();
+        assert!(!result.is_fully_empty());
+    }
+
+    #[tokio::test]
+    async fn local_runs_record_what_flowed_across_connections() {
+        let stats = failing_run().await.connection_stats;
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].objects, stats[0].empty_objects), (1, 0));
+        assert_eq!(stats[0].object_types, vec![ObjectType::Vec]);
+        assert_eq!(stats[0].timesteps, Some((0, 0)));
+        assert!(stats[0].payload_bytes >= 3 * std::mem::size_of::<f32>());
+    }
+
+    #[test]
+    fn modules_without_a_task_did_not_run() {
+        let spec = ModuleSpec::new(7, "ConstantField", "Late");
+        let report = module_report(&spec, None);
+        assert_eq!(report.status, ModuleOutcome::NotRun);
+        assert_eq!((report.duration_ms, report.objects_created), (0.0, 0));
+    }
+
+    #[tokio::test]
+    async fn saved_reports_load_back_unchanged() {
+        let report = failing_run().await.to_report()
+            .with_peak_memory(&crate::util::MemoryTracker::new())
+            .with_cache_stats(CacheStats::default());
+        let path = std::env::temp_dir().join(format!("vistle_report_{}.json", uuid::Uuid::new_v4().simple()));
+        report.save(&path).await.unwrap();
+        let loaded = WorkflowReport::load(&path).await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), report);
+    }
+
+    #[test]
+    fn reports_of_schema_version_1_still_parse() {
+        // Fields every version 1 report has; later additions must default
+        let json = r#"{
+            "schema_version": 1,
+            "workflow_id": "wf",
+            "workflow_name": "Workflow",
+            "success": false,
+            "generated_at_ms": 1700000000000,
+            "duration_ms": 12.5,
+            "modules": [{
+                "module_id": 1,
+                "name": "Reader",
+                "module_type": "DataReader",
+                "status": "not_run",
+                "duration_ms": 0.0,
+                "objects_created": 0,
+                "error": {"code": "io", "message": "missing file"},
+                "parameters": {"filename": "data.vtk"}
+            }],
+            "peak_memory_bytes": null,
+            "shm": null,
+            "cache": null
+        }"#;
+        let report: WorkflowReport = serde_json::from_str(json).unwrap();
+        assert_eq!(report.modules[0].status, ModuleOutcome::NotRun);
+        assert_eq!(report.modules[0].error.as_ref().unwrap().code, "io");
+        assert_eq!(report.modules[0].error_count, 0);
+        assert!(report.outputs.is_empty() && report.adapters.is_empty() && report.retention.is_empty());
+        assert!(report.prefetch.is_none() && report.first_nonfinite.is_none());
+    }
+
+    #[tokio::test]
+    async fn html_is_escaped_and_marks_failures() {
+        let html = failing_run().await.to_report().to_html();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Workflow Report &lt;test&gt; &amp; more</title>"));
+        assert!(!html.contains("<test>"));
+        assert!(html.contains("<td class=\"succeeded\">succeeded</td>"));
+        assert!(html.contains("<td class=\"failed\">failed</td>"));
+        assert!(html.contains("[compute] "));
+        assert!(!html.contains("http"), "the page must not load external assets");
+    }
+}
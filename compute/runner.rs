@@ -0,0 +1,465 @@
+//! Running workflow files headlessly from the command line
+//!
+//! `WorkflowExecutor::run_file` loads a workflow from JSON or YAML, applies
+//! parameter overrides given as `module.parameter=value`, executes it and
+//! writes its report. The returned `RunSummary` carries what a batch script
+//! needs: whether the run succeeded, module counts, the first error, and an
+//! exit code for the process.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::builtin::register_builtin_modules;
+use crate::compute::{LoadWarning, ModuleOutcome, ModuleRegistry, TaskExecutor, WorkflowExecutor, WorkflowReport, WorkflowSpec};
+use crate::core::{MessageRouter, PrefetchConfig};
+
+/// Tasks run at once unless the run is deterministic
+pub const DEFAULT_RUN_CONCURRENCY: usize = 8;
+
+/// How a workflow file is run
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Cancel the workflow when it runs longer
+    pub timeout: Option<Duration>,
+    /// Where the JSON report is written; no report without
+    pub report_path: Option<PathBuf>,
+    /// Install a log subscriber at this level, unless one was installed before
+    pub log_level: Option<tracing::Level>,
+    /// Run one module at a time and load no timesteps ahead, so the order of
+    /// execution and of log output is the same on every run
+    pub deterministic: bool,
+    /// Fail on anything the workflow file migration dropped, e.g. in CI
+    pub strict: bool,
+}
+
+impl RunOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_report(mut self, path: impl Into<PathBuf>) -> Self {
+        self.report_path = Some(path.into());
+        self
+    }
+
+    pub fn with_log_level(mut self, level: tracing::Level) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    pub fn deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+}
+
+/// A parameter value replacing the one in the workflow file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParameterOverride {
+    /// Module id or name
+    pub module: String,
+    pub parameter: String,
+    pub value: String,
+}
+
+impl ParameterOverride {
+    pub fn new(module: &str, parameter: &str, value: &str) -> Self {
+        Self {
+            module: module.to_string(),
+            parameter: parameter.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// Parse `module.parameter=value`; the module may be given by id or name
+    pub fn parse(text: &str) -> Result<Self, crate::Error> {
+        let (key, value) = text.split_once('=')
+            .ok_or_else(|| crate::Error::Config(format!("Override {} is not module.parameter=value", text)))?;
+        let (module, parameter) = key.rsplit_once('.')
+            .filter(|(module, parameter)| !module.is_empty() && !parameter.is_empty())
+            .ok_or_else(|| crate::Error::Config(format!("Override {} is not module.parameter=value", text)))?;
+        Ok(Self::new(module, parameter, value))
+    }
+}
+
+impl std::fmt::Display for ParameterOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}={}", self.module, self.parameter, self.value)
+    }
+}
+
+/// Outcome of a workflow file run, small enough to print at the end of a batch job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub workflow_id: String,
+    pub success: bool,
+    pub modules: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub not_run: usize,
+    pub duration_ms: f64,
+    /// Error of the first failed module in workflow order, or why the run failed as a whole
+    pub first_error: Option<String>,
+    /// Output files deleted by retention policies
+    #[serde(default)]
+    pub outputs_deleted: usize,
+    /// What loading the workflow file dropped or could not migrate
+    #[serde(default)]
+    pub load_warnings: Vec<LoadWarning>,
+    /// Where the report was written
+    pub report_path: Option<PathBuf>,
+}
+
+impl RunSummary {
+    /// Counts and first error of a report
+    pub fn from_report(report: &WorkflowReport) -> Self {
+        let count = |outcome| report.modules.iter().filter(|m| m.status == outcome).count();
+        Self {
+            workflow_id: report.workflow_id.clone(),
+            success: report.success,
+            modules: report.modules.len(),
+            succeeded: count(ModuleOutcome::Succeeded),
+            failed: count(ModuleOutcome::Failed),
+            not_run: count(ModuleOutcome::NotRun),
+            duration_ms: report.duration_ms,
+            first_error: report.failed_modules().next().map(|m| {
+                let message = m.error.as_ref().map_or("failed", |e| e.message.as_str());
+                format!("{} ({}): {}", m.name, m.module_id, message)
+            }),
+            outputs_deleted: report.retention.iter().filter(|d| !d.dry_run).count(),
+            load_warnings: Vec::new(),
+            report_path: None,
+        }
+    }
+
+    /// 0 for a successful run, 1 otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.success { 0 } else { 1 }
+    }
+}
+
+impl std::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} in {:.0} ms, {} of {} modules succeeded, {} failed, {} not run",
+            self.workflow_id,
+            if self.success { "succeeded" } else { "failed" },
+            self.duration_ms, self.succeeded, self.modules, self.failed, self.not_run
+        )?;
+        if self.outputs_deleted > 0 {
+            write!(f, ", {} output files deleted by retention", self.outputs_deleted)?;
+        }
+        if !self.load_warnings.is_empty() {
+            write!(f, ", {} load warnings", self.load_warnings.len())?;
+        }
+        if let Some(error) = &self.first_error {
+            write!(f, "; first error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Set the overridden parameters in a workflow, checking each against its module's parameters
+pub async fn apply_overrides(
+    spec: &mut WorkflowSpec,
+    overrides: &[ParameterOverride],
+    registry: &ModuleRegistry,
+) -> Result<(), crate::Error> {
+    for item in overrides {
+        let by_id: Option<u32> = item.module.parse().ok();
+        let matching: Vec<usize> = spec.modules.iter()
+            .enumerate()
+            .filter(|(_, m)| Some(m.id) == by_id || m.name == item.module)
+            .map(|(i, _)| i)
+            .collect();
+        let index = match matching.as_slice() {
+            [index] => *index,
+            [] => return Err(crate::Error::Config(format!(
+                "Override {}: workflow {} has no module {}",
+                item, spec.id, item.module
+            ))),
+            _ => return Err(crate::Error::Config(format!(
+                "Override {}: {} names more than one module of workflow {}, use the module id",
+                item, item.module, spec.id
+            ))),
+        };
+
+        let module = &mut spec.modules[index];
+        let instance = registry.create_detached(&module.module_type).await?;
+        instance.set_parameter_str(&item.parameter, &item.value).map_err(|e| {
+            let reason = match e {
+                crate::Error::Config(reason) | crate::Error::Module(reason) => reason,
+                e => e.to_string(),
+            };
+            crate::Error::Config(format!("Override {}: {}", item, reason))
+        })?;
+        tracing::info!("Module {} ({}): {} overridden with {}", module.name, module.id, item.parameter, item.value);
+        module.parameters.insert(item.parameter.clone(), item.value.clone());
+    }
+    Ok(())
+}
+
+impl WorkflowExecutor {
+    /// Load a workflow file, apply `overrides`, execute it and write its report
+    ///
+    /// Errors are returned for workflows that cannot be loaded, or load with
+    /// warnings in a strict run, and for overrides that do not fit them;
+    /// failures while executing end up in the summary instead. `RunOptions::log_level` is left to the caller,
+    /// and a deterministic run only disables prefetching here: the executor
+    /// must have been built on a `TaskExecutor` running one task at a time.
+    pub async fn run_file(
+        &self,
+        path: impl AsRef<Path>,
+        overrides: &[ParameterOverride],
+        options: &RunOptions,
+    ) -> Result<RunSummary, crate::Error> {
+        let path = path.as_ref();
+        let (mut spec, warnings) = WorkflowSpec::load_with_warnings(path).await?;
+        if options.strict {
+            warnings.check(path)?;
+        }
+        for warning in warnings.iter() {
+            tracing::warn!("Workflow {}: {}", path.display(), warning);
+        }
+        apply_overrides(&mut spec, overrides, self.module_registry()).await?;
+        if options.deterministic {
+            self.object_registry().set_prefetch(PrefetchConfig::disabled());
+        }
+
+        tracing::info!("Running workflow {} from {}", spec.id, path.display());
+        let workflow_id = spec.id.clone();
+        let modules = spec.modules.len();
+        match self.execute_workflow(spec, options.timeout).await {
+            Ok(result) => {
+                let report = result.to_report();
+                if let Some(report_path) = &options.report_path {
+                    report.save(report_path).await?;
+                }
+                let mut summary = RunSummary::from_report(&report);
+                summary.load_warnings = warnings.warnings;
+                summary.report_path = options.report_path.clone();
+                Ok(summary)
+            }
+            Err(e) => {
+                tracing::error!("Workflow {} failed: {}", workflow_id, e);
+                Ok(RunSummary {
+                    workflow_id,
+                    success: false,
+                    modules,
+                    succeeded: 0,
+                    failed: 0,
+                    not_run: modules,
+                    duration_ms: 0.0,
+                    first_error: Some(e.to_string()),
+                    outputs_deleted: 0,
+                    load_warnings: warnings.warnings,
+                    report_path: None,
+                })
+            }
+        }
+    }
+}
+
+/// Run a workflow file on a local executor with the built-in modules
+///
+/// See `WorkflowExecutor::run_file`; this also installs a log subscriber
+/// at `RunOptions::log_level` and honours `RunOptions::deterministic`.
+pub async fn run_workflow_file(
+    path: impl AsRef<Path>,
+    overrides: &[ParameterOverride],
+    options: &RunOptions,
+) -> Result<RunSummary, crate::Error> {
+    if let Some(level) = options.log_level {
+        crate::init_with_log_level(level).await?;
+    }
+
+    let module_registry = Arc::new(ModuleRegistry::new());
+    register_builtin_modules(&module_registry).await;
+    let concurrency = if options.deterministic { 1 } else { DEFAULT_RUN_CONCURRENCY };
+    let executor = WorkflowExecutor::new(
+        module_registry,
+        Arc::new(TaskExecutor::new(concurrency)),
+        Arc::new(MessageRouter::new()),
+    );
+    executor.run_file(path, overrides, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::WorkflowBuilder;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vistle_runner_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn test_executor() -> WorkflowExecutor {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()))
+    }
+
+    fn source_and_sink() -> WorkflowSpec {
+        WorkflowBuilder::new("batch", "Batch")
+            .add_module("ConstantField", "Source")
+                .parameter("value", "1.0")
+            .add_module("ConstantField", "Sink")
+                .depends_on(1)
+            .connect(1, "data_out", 2, "data_in")
+            .build()
+    }
+
+    #[test]
+    fn overrides_parse_module_parameter_and_value() {
+        assert_eq!(ParameterOverride::parse("Source.value=2.5").unwrap(), ParameterOverride::new("Source", "value", "2.5"));
+        assert_eq!(ParameterOverride::parse("1.filename=a=b.vtk").unwrap(), ParameterOverride::new("1", "filename", "a=b.vtk"));
+        assert_eq!(ParameterOverride::parse("Iso.Surface.level=0").unwrap().module, "Iso.Surface");
+        assert_eq!(ParameterOverride::new("Source", "value", "2.5").to_string(), "Source.value=2.5");
+        for malformed in ["Source.value", "value=2", ".value=2", "Source.=2"] {
+            assert!(matches!(ParameterOverride::parse(malformed), Err(crate::Error::Config(_))), "{}", malformed);
+        }
+    }
+
+    #[tokio::test]
+    async fn overrides_address_modules_by_id_or_unique_name() {
+        let executor = test_executor().await;
+        let mut spec = source_and_sink();
+        let overrides = [ParameterOverride::new("Source", "value", "2.5"), ParameterOverride::new("2", "count", "8")];
+        apply_overrides(&mut spec, &overrides, executor.module_registry()).await.unwrap();
+        assert_eq!(spec.modules[0].parameters["value"], "2.5");
+        assert_eq!(spec.modules[1].parameters["count"], "8");
+
+        let error = apply_overrides(&mut spec, &[ParameterOverride::new("Reader", "value", "1")], executor.module_registry())
+            .await.unwrap_err().to_string();
+        assert!(error.contains("has no module Reader"), "{}", error);
+        let error = apply_overrides(&mut spec, &[ParameterOverride::new("Source", "colour", "red")], executor.module_registry())
+            .await.unwrap_err();
+        assert!(matches!(&error, crate::Error::Config(m) if m.starts_with("Override Source.colour=red: ")), "{}", error);
+
+        spec.modules[1].name = "Source".to_string();
+        let error = apply_overrides(&mut spec, &[ParameterOverride::new("Source", "value", "3")], executor.module_registry())
+            .await.unwrap_err().to_string();
+        assert!(error.contains("use the module id"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn a_run_writes_its_report_and_summarizes_it() {
+        let dir = temp_dir();
+        let path = dir.join("batch.json");
+        source_and_sink().save(&path).await.unwrap();
+
+        let executor = test_executor().await;
+        let options = RunOptions::new().with_timeout(Duration::from_secs(10)).with_report(dir.join("report.json"));
+        let summary = executor.run_file(&path, &[ParameterOverride::parse("Source.value=7.5").unwrap()], &options).await.unwrap();
+        assert!(summary.success);
+        assert_eq!(summary.exit_code(), 0);
+        assert_eq!((summary.modules, summary.succeeded, summary.failed, summary.not_run), (2, 2, 0, 0));
+        assert_eq!(summary.first_error, None);
+        assert_eq!(summary.report_path.as_deref(), Some(dir.join("report.json").as_path()));
+
+        let report = WorkflowReport::load(dir.join("report.json")).await.unwrap();
+        assert_eq!(report.workflow_id, "batch");
+        assert_eq!(report.modules[0].parameters["value"], "7.5");
+        assert!(summary.to_string().starts_with("batch: succeeded in "), "{}", summary);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn a_failed_module_sets_the_exit_code_and_first_error() {
+        let dir = temp_dir();
+        let path = dir.join("failing.json");
+        WorkflowBuilder::new("failing", "Failing")
+            .add_module("ConstantField", "Source")
+            .add_module("Failing", "Sink")
+                .depends_on(1)
+            .build()
+            .save(&path).await.unwrap();
+
+        let summary = test_executor().await.run_file(&path, &[], &RunOptions::new()).await.unwrap();
+        assert!(!summary.success);
+        assert_eq!(summary.exit_code(), 1);
+        assert_eq!((summary.succeeded, summary.failed), (1, 1));
+        let error = summary.first_error.clone().unwrap();
+        assert!(error.starts_with("Sink (2): ") && error.contains("failed as asked"), "{}", error);
+        assert!(summary.to_string().ends_with(&format!("; first error: {}", error)));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn a_timed_out_run_counts_every_module_as_not_run() {
+        let dir = temp_dir();
+        let path = dir.join("slow.json");
+        WorkflowBuilder::new("slow", "Slow")
+            .add_module("ConstantField", "Source")
+                .parameter("delay_ms", "5000")
+            .build()
+            .save(&path).await.unwrap();
+
+        let options = RunOptions::new().with_timeout(Duration::from_millis(50));
+        let summary = test_executor().await.run_file(&path, &[], &options).await.unwrap();
+        assert!(!summary.success);
+        assert_eq!(summary.exit_code(), 1);
+        assert!(summary.first_error.as_deref().unwrap().contains("timeout"));
+        assert_eq!((summary.modules, summary.not_run), (1, 1));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn strict_runs_refuse_files_that_did_not_load_cleanly() {
+        let dir = temp_dir();
+        let path = dir.join("unclean.json");
+        let mut value = serde_json::to_value(source_and_sink()).unwrap();
+        value["modules"][0]["colour"] = serde_json::json!("red");
+        std::fs::write(&path, value.to_string()).unwrap();
+
+        let executor = test_executor().await;
+        let summary = executor.run_file(&path, &[], &RunOptions::new()).await.unwrap();
+        assert!(summary.success);
+        assert_eq!(summary.load_warnings.len(), 1);
+        assert_eq!(summary.load_warnings[0].module, Some(1));
+        assert!(summary.to_string().contains(", 1 load warnings"), "{}", summary);
+
+        let error = executor.run_file(&path, &[], &RunOptions::new().strict()).await.unwrap_err().to_string();
+        assert!(error.contains("did not load cleanly") && error.contains("'colour'"), "{}", error);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn summaries_count_module_outcomes() {
+        let summary = RunSummary {
+            workflow_id: "w".to_string(),
+            success: false,
+            modules: 3,
+            succeeded: 1,
+            failed: 1,
+            not_run: 1,
+            duration_ms: 12.4,
+            first_error: Some("Sink (2): no data".to_string()),
+            outputs_deleted: 2,
+            load_warnings: Vec::new(),
+            report_path: None,
+        };
+        assert_eq!(
+            summary.to_string(),
+            "w: failed in 12 ms, 1 of 3 modules succeeded, 1 failed, 1 not run, 2 output files deleted by retention; first error: Sink (2): no data"
+        );
+    }
+}
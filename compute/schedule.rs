@@ -0,0 +1,939 @@
+//! Periodic execution of workflows
+//!
+//! Interval schedules run on the monotonic clock, so changing the system
+//! time neither delays nor repeats them. Cron schedules follow the wall
+//! clock in UTC and are re-checked at least every `CRON_RECHECK`: after the
+//! clock jumps forward, the missed occurrences collapse into a single run;
+//! after it jumps back, occurrences in the repeated span run again.
+//!
+//! Executions never overlap. A run that outlasts its interval keeps going and
+//! the ticks arriving meanwhile are handled by the schedule's `OverlapPolicy`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::compute::{WorkflowExecutor, WorkflowSpec};
+
+/// Longest a cron schedule sleeps before looking at the wall clock again
+pub const CRON_RECHECK: Duration = Duration::from_secs(30);
+
+/// Upper bound on the search for the next cron occurrence
+const CRON_SEARCH_DAYS: i64 = 4 * 366;
+
+/// Set of allowed values of one cron field, as a bit mask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CronField {
+    mask: u64,
+    /// The field was `*`, which matters for the day-of-month/day-of-week rule
+    any: bool,
+}
+
+impl CronField {
+    fn parse(text: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut mask = 0u64;
+        for part in text.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => {
+                    let step: u32 = step.parse().map_err(|_| format!("invalid step '{}'", step))?;
+                    if step == 0 {
+                        return Err("step must not be 0".to_string());
+                    }
+                    (range, step)
+                }
+                None => (part, 1),
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((a, b)) => (parse_value(a, min, max)?, parse_value(b, min, max)?),
+                    None => {
+                        let value = parse_value(range, min, max)?;
+                        // `5/10` means from 5 to the end in steps of 10
+                        (value, if part.contains('/') { max } else { value })
+                    }
+                },
+            };
+            if start > end {
+                return Err(format!("empty range '{}'", range));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                mask |= 1 << value;
+            }
+        }
+        Ok(Self { mask, any: text == "*" })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.mask & (1 << value) != 0
+    }
+}
+
+fn parse_value(text: &str, min: u32, max: u32) -> Result<u32, String> {
+    match text.parse::<u32>() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        Ok(value) => Err(format!("{} is outside {}-{}", value, min, max)),
+        Err(_) => Err(format!("invalid value '{}'", text)),
+    }
+}
+
+/// Five-field cron expression: minute, hour, day of month, month, day of week
+///
+/// Fields take `*`, values, ranges `a-b`, lists `a,b` and steps `*/n`.
+/// Day of week runs from 0 (Sunday) to 6, with 7 also meaning Sunday. As
+/// in cron, when both day fields are restricted a day matching either runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    text: String,
+    minute: CronField,
+    hour: CronField,
+    day: CronField,
+    month: CronField,
+    weekday: CronField,
+}
+
+impl CronExpr {
+    pub fn parse(text: &str) -> Result<Self, crate::Error> {
+        let invalid = |reason: String| crate::Error::Config(format!("Invalid cron expression '{}': {}", text, reason));
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let &[minute, hour, day, month, weekday] = fields.as_slice() else {
+            return Err(invalid(format!("expected 5 fields, found {}", fields.len())));
+        };
+        let mut weekday = CronField::parse(weekday, 0, 7).map_err(invalid)?;
+        if weekday.contains(7) {
+            weekday.mask |= 1;
+        }
+        Ok(Self {
+            text: text.to_string(),
+            minute: CronField::parse(minute, 0, 59).map_err(invalid)?,
+            hour: CronField::parse(hour, 0, 23).map_err(invalid)?,
+            day: CronField::parse(day, 1, 31).map_err(invalid)?,
+            month: CronField::parse(month, 1, 12).map_err(invalid)?,
+            weekday,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    fn matches_day(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 was a Thursday
+        let weekday = (days + 4).rem_euclid(7) as u32;
+        if !self.month.contains(month) {
+            return false;
+        }
+        match (self.day.any, self.weekday.any) {
+            (false, false) => self.day.contains(day) || self.weekday.contains(weekday),
+            _ => self.day.contains(day) && self.weekday.contains(weekday),
+        }
+    }
+
+    /// First matching minute strictly after `time`, or None if there is none within four years
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        let mut minute = secs / 60 + 1;
+        let last = minute + CRON_SEARCH_DAYS * 24 * 60;
+        while minute < last {
+            let days = minute.div_euclid(24 * 60);
+            let of_day = minute.rem_euclid(24 * 60);
+            if !self.matches_day(days) {
+                minute = (days + 1) * 24 * 60;
+                continue;
+            }
+            if !self.hour.contains((of_day / 60) as u32) {
+                minute = (minute / 60 + 1) * 60;
+                continue;
+            }
+            if self.minute.contains((of_day % 60) as u32) {
+                return Some(UNIX_EPOCH + Duration::from_secs(minute as u64 * 60));
+            }
+            minute += 1;
+        }
+        None
+    }
+}
+
+/// Year, month and day of a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// When a workflow runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every period, first one period after registration
+    Interval(Duration),
+    Cron(CronExpr),
+}
+
+impl Schedule {
+    /// Parse `@every <n>{s,m,h,d}`, `@hourly`, `@daily`, `@weekly` or a five-field cron expression
+    pub fn parse(text: &str) -> Result<Self, crate::Error> {
+        let text = text.trim();
+        match text {
+            "@hourly" => return CronExpr::parse("0 * * * *").map(Schedule::Cron),
+            "@daily" => return CronExpr::parse("0 0 * * *").map(Schedule::Cron),
+            "@weekly" => return CronExpr::parse("0 0 * * 0").map(Schedule::Cron),
+            _ => {}
+        }
+        let Some(every) = text.strip_prefix("@every ") else {
+            return CronExpr::parse(text).map(Schedule::Cron);
+        };
+        let every = every.trim();
+        let invalid = || crate::Error::Config(format!("Invalid interval '{}'", every));
+        let split = every.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let count: u64 = every[..split].parse().map_err(|_| invalid())?;
+        let unit = match &every[split..] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        if count == 0 {
+            return Err(invalid());
+        }
+        let seconds = count.checked_mul(unit)
+            .ok_or_else(|| crate::Error::Config(format!("Interval '{}' is too long", every)))?;
+        Ok(Schedule::Interval(Duration::from_secs(seconds)))
+    }
+}
+
+/// What happens when a run is due while the previous one is still going
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapPolicy {
+    /// Drop the due run
+    #[default]
+    Skip,
+    /// Start the due run once the current one ends; further due runs coalesce into it
+    Queue,
+    /// Cancel the current run and start the due one
+    CancelPrevious,
+}
+
+/// Retries of a failed run, with exponential backoff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Attempts per run including the first; 1 disables retries
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self::new(1, Duration::ZERO)
+    }
+
+    pub fn new(max_attempts: u32, backoff: Duration) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            backoff,
+            max_backoff: Duration::from_secs(10 * 60),
+        }
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Wait before the attempt following `attempt`
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_backoff)
+    }
+}
+
+/// How a workflow is run by its schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleOptions {
+    pub overlap: OverlapPolicy,
+    pub retry: RetryPolicy,
+    /// Timeout of each attempt
+    pub timeout: Option<Duration>,
+    /// Consecutive failed runs that raise `RepeatedFailures`; 0 disables the event
+    pub failure_threshold: u32,
+}
+
+impl Default for ScheduleOptions {
+    fn default() -> Self {
+        Self {
+            overlap: OverlapPolicy::default(),
+            retry: RetryPolicy::default(),
+            timeout: None,
+            failure_threshold: 3,
+        }
+    }
+}
+
+impl ScheduleOptions {
+    pub fn with_overlap(mut self, overlap: OverlapPolicy) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_failure_threshold(mut self, failure_threshold: u32) -> Self {
+        self.failure_threshold = failure_threshold;
+        self
+    }
+}
+
+/// Something that happened to a scheduled workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleEvent {
+    pub workflow_id: String,
+    /// Milliseconds since the Unix epoch
+    pub at_ms: u64,
+    pub kind: ScheduleEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleEventKind {
+    /// An attempt of run `run` started
+    Started { run: u64, attempt: u32 },
+    Succeeded { run: u64, attempt: u32, duration_ms: f64 },
+    /// An attempt failed; `will_retry` tells whether another one follows
+    Failed { run: u64, attempt: u32, error: String, will_retry: bool },
+    /// A due run was dropped because run `running` was still going
+    Skipped { running: u64 },
+    /// A due run waits for run `running` to end
+    Queued { running: u64 },
+    /// Run `run` was cancelled to make way for a due one
+    Cancelled { run: u64 },
+    /// The last `consecutive` runs all failed; raised every `failure_threshold` failures
+    RepeatedFailures { consecutive: u32, last_error: String },
+}
+
+#[derive(Clone)]
+struct Emitter {
+    workflow_id: String,
+    events: broadcast::Sender<ScheduleEvent>,
+}
+
+impl Emitter {
+    fn emit(&self, kind: ScheduleEventKind) {
+        let at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        // Nobody listening is fine
+        let _ = self.events.send(ScheduleEvent { workflow_id: self.workflow_id.clone(), at_ms, kind });
+    }
+}
+
+enum Command {
+    Trigger,
+    Pause,
+    Resume,
+}
+
+/// State shared between a schedule's driver task and the scheduler
+struct ScheduleHandle {
+    commands: mpsc::UnboundedSender<Command>,
+    token: CancellationToken,
+    paused: Arc<AtomicBool>,
+    next_run: Arc<parking_lot::Mutex<Option<SystemTime>>>,
+}
+
+/// Runs registered workflows on their schedules
+///
+/// Each schedule is driven by its own tokio task, so the scheduler must be
+/// used from within a runtime. Dropping the scheduler stops all schedules;
+/// runs already in progress are left to finish.
+pub struct WorkflowScheduler {
+    executor: Arc<WorkflowExecutor>,
+    schedules: parking_lot::Mutex<HashMap<String, ScheduleHandle>>,
+    events: broadcast::Sender<ScheduleEvent>,
+}
+
+impl WorkflowScheduler {
+    pub fn new(executor: Arc<WorkflowExecutor>) -> Self {
+        Self {
+            executor,
+            schedules: parking_lot::Mutex::new(HashMap::new()),
+            events: broadcast::channel(256).0,
+        }
+    }
+
+    /// Receive an event for every run, attempt and dropped tick
+    pub fn subscribe(&self) -> broadcast::Receiver<ScheduleEvent> {
+        self.events.subscribe()
+    }
+
+    /// Run `workflow` on `schedule`; a workflow can have one schedule at a time
+    pub fn register(&self, workflow: WorkflowSpec, schedule: Schedule, options: ScheduleOptions) -> Result<(), crate::Error> {
+        let mut schedules = self.schedules.lock();
+        if schedules.contains_key(&workflow.id) {
+            return Err(crate::Error::Config(format!("Workflow {} is already scheduled", workflow.id)));
+        }
+        if let Schedule::Interval(period) = &schedule {
+            if period.is_zero() {
+                return Err(crate::Error::Config("Schedule interval must not be zero".to_string()));
+            }
+        }
+
+        let workflow_id = workflow.id.clone();
+        let (commands, received) = mpsc::unbounded_channel();
+        let handle = ScheduleHandle {
+            commands,
+            token: CancellationToken::new(),
+            paused: Arc::new(AtomicBool::new(false)),
+            next_run: Arc::new(parking_lot::Mutex::new(None)),
+        };
+        let driver = Driver {
+            executor: Arc::downgrade(&self.executor),
+            emitter: Emitter { workflow_id: workflow.id.clone(), events: self.events.clone() },
+            workflow: Arc::new(workflow),
+            schedule,
+            options,
+            paused: handle.paused.clone(),
+            next_run: handle.next_run.clone(),
+        };
+        tokio::spawn(driver.run(received, handle.token.clone()));
+        tracing::info!("Scheduled workflow {}", workflow_id);
+        schedules.insert(workflow_id, handle);
+        Ok(())
+    }
+
+    /// Stop scheduling a workflow; a run in progress is left to finish
+    pub fn unregister(&self, workflow_id: &str) -> bool {
+        match self.schedules.lock().remove(workflow_id) {
+            Some(handle) => {
+                handle.token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ids of the scheduled workflows
+    pub fn scheduled(&self) -> Vec<String> {
+        self.schedules.lock().keys().cloned().collect()
+    }
+
+    /// When the workflow is next due; None while paused or when the schedule has no further occurrence
+    ///
+    /// For interval schedules this is an estimate from the monotonic deadline.
+    pub fn next_run(&self, workflow_id: &str) -> Option<SystemTime> {
+        let schedules = self.schedules.lock();
+        let handle = schedules.get(workflow_id)?;
+        if handle.paused.load(Ordering::SeqCst) {
+            return None;
+        }
+        let next_run = *handle.next_run.lock();
+        next_run
+    }
+
+    /// Stop starting runs on schedule until resumed; `trigger_now` still works
+    pub fn pause_schedule(&self, workflow_id: &str) -> Result<(), crate::Error> {
+        self.send(workflow_id, Command::Pause)
+    }
+
+    /// Resume a paused schedule; interval schedules count a full period from now
+    pub fn resume_schedule(&self, workflow_id: &str) -> Result<(), crate::Error> {
+        self.send(workflow_id, Command::Resume)
+    }
+
+    /// Run the workflow now, subject to its overlap policy; the schedule itself is unchanged
+    pub fn trigger_now(&self, workflow_id: &str) -> Result<(), crate::Error> {
+        self.send(workflow_id, Command::Trigger)
+    }
+
+    fn send(&self, workflow_id: &str, command: Command) -> Result<(), crate::Error> {
+        let schedules = self.schedules.lock();
+        let handle = schedules.get(workflow_id)
+            .ok_or_else(|| crate::Error::Module(format!("Workflow {} is not scheduled", workflow_id)))?;
+        match command {
+            Command::Pause => handle.paused.store(true, Ordering::SeqCst),
+            Command::Resume => handle.paused.store(false, Ordering::SeqCst),
+            Command::Trigger => {}
+        }
+        handle.commands.send(command)
+            .map_err(|_| crate::Error::Module(format!("Schedule of workflow {} has stopped", workflow_id)))
+    }
+}
+
+impl Drop for WorkflowScheduler {
+    fn drop(&mut self) {
+        for handle in self.schedules.lock().values() {
+            handle.token.cancel();
+        }
+    }
+}
+
+/// Next due time of a schedule
+#[derive(Debug, Clone, Copy)]
+enum Due {
+    Monotonic(Instant),
+    Wall(SystemTime),
+}
+
+type RunOutcome = Result<(), String>;
+
+/// Run currently executing, and whether another one waits for it
+#[derive(Default)]
+struct Runs {
+    count: u64,
+    current: Option<(u64, JoinHandle<RunOutcome>)>,
+    queued: bool,
+    consecutive_failures: u32,
+}
+
+async fn join_current(current: &mut Option<(u64, JoinHandle<RunOutcome>)>) -> Result<RunOutcome, JoinError> {
+    match current {
+        Some((_, handle)) => handle.await,
+        None => std::future::pending().await,
+    }
+}
+
+struct Driver {
+    executor: Weak<WorkflowExecutor>,
+    emitter: Emitter,
+    workflow: Arc<WorkflowSpec>,
+    schedule: Schedule,
+    options: ScheduleOptions,
+    paused: Arc<AtomicBool>,
+    next_run: Arc<parking_lot::Mutex<Option<SystemTime>>>,
+}
+
+impl Driver {
+    async fn run(self, mut commands: mpsc::UnboundedReceiver<Command>, token: CancellationToken) {
+        let mut runs = Runs::default();
+        let mut due = self.first_due();
+        loop {
+            self.publish(due);
+            let paused = self.paused.load(Ordering::SeqCst);
+            let wake = due.map(Self::wake_at);
+            tokio::select! {
+                _ = token.cancelled() => break,
+                command = commands.recv() => match command {
+                    Some(Command::Trigger) => self.start_due(&mut runs).await,
+                    Some(Command::Pause) => {}
+                    Some(Command::Resume) => due = self.first_due(),
+                    None => break,
+                },
+                finished = join_current(&mut runs.current) => self.finished(&mut runs, finished),
+                _ = tokio::time::sleep_until(wake.unwrap_or_else(Instant::now)), if wake.is_some() && !paused => {
+                    if self.is_due(due) {
+                        self.start_due(&mut runs).await;
+                        due = self.following(due);
+                    } else if let Schedule::Cron(expr) = &self.schedule {
+                        // Picks up a clock that was set back
+                        due = expr.next_after(SystemTime::now()).map(Due::Wall);
+                    }
+                }
+            }
+            if self.executor.strong_count() == 0 {
+                break;
+            }
+        }
+        *self.next_run.lock() = None;
+        tracing::debug!("Schedule of workflow {} stopped", self.workflow.id);
+    }
+
+    fn first_due(&self) -> Option<Due> {
+        match &self.schedule {
+            // Intervals beyond what the clock can represent are never due
+            Schedule::Interval(period) => Instant::now().checked_add(*period).map(Due::Monotonic),
+            Schedule::Cron(expr) => expr.next_after(SystemTime::now()).map(Due::Wall),
+        }
+    }
+
+    /// Due time after `due` fired; interval ticks missed in the meantime are dropped, not replayed
+    fn following(&self, due: Option<Due>) -> Option<Due> {
+        match (&self.schedule, due) {
+            (Schedule::Interval(period), Some(Due::Monotonic(mut at))) => {
+                let now = Instant::now();
+                while at <= now {
+                    at = at.checked_add(*period)?;
+                }
+                Some(Due::Monotonic(at))
+            }
+            (Schedule::Cron(expr), _) => expr.next_after(SystemTime::now()).map(Due::Wall),
+            _ => self.first_due(),
+        }
+    }
+
+    fn wake_at(due: Due) -> Instant {
+        match due {
+            Due::Monotonic(at) => at,
+            Due::Wall(at) => {
+                let remaining = at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO);
+                Instant::now() + remaining.min(CRON_RECHECK)
+            }
+        }
+    }
+
+    fn is_due(&self, due: Option<Due>) -> bool {
+        match due {
+            Some(Due::Monotonic(at)) => Instant::now() >= at,
+            Some(Due::Wall(at)) => SystemTime::now() >= at,
+            None => false,
+        }
+    }
+
+    fn publish(&self, due: Option<Due>) {
+        *self.next_run.lock() = due.and_then(|due| match due {
+            Due::Monotonic(at) => SystemTime::now().checked_add(at.saturating_duration_since(Instant::now())),
+            Due::Wall(at) => Some(at),
+        });
+    }
+
+    /// A run is due; apply the overlap policy if one is still going
+    async fn start_due(&self, runs: &mut Runs) {
+        if let Some((running, _)) = &runs.current {
+            let running = *running;
+            match self.options.overlap {
+                OverlapPolicy::Skip => {
+                    self.emitter.emit(ScheduleEventKind::Skipped { running });
+                    return;
+                }
+                OverlapPolicy::Queue if runs.queued => {
+                    self.emitter.emit(ScheduleEventKind::Skipped { running });
+                    return;
+                }
+                OverlapPolicy::Queue => {
+                    runs.queued = true;
+                    self.emitter.emit(ScheduleEventKind::Queued { running });
+                    return;
+                }
+                OverlapPolicy::CancelPrevious => self.cancel_current(runs).await,
+            }
+        }
+        self.start(runs);
+    }
+
+    async fn cancel_current(&self, runs: &mut Runs) {
+        let Some((run, handle)) = runs.current.take() else {
+            return;
+        };
+        if let Some(executor) = self.executor.upgrade() {
+            if let Err(e) = executor.cancel_workflow(&self.workflow.id).await {
+                tracing::warn!("Failed to cancel workflow {}: {}", self.workflow.id, e);
+            }
+        }
+        handle.abort();
+        let _ = handle.await;
+        self.emitter.emit(ScheduleEventKind::Cancelled { run });
+    }
+
+    fn start(&self, runs: &mut Runs) {
+        let Some(executor) = self.executor.upgrade() else {
+            return;
+        };
+        runs.count += 1;
+        let run = runs.count;
+        let handle = tokio::spawn(execute_run(
+            executor,
+            self.workflow.clone(),
+            self.options.clone(),
+            self.emitter.clone(),
+            run,
+        ));
+        runs.current = Some((run, handle));
+    }
+
+    fn finished(&self, runs: &mut Runs, finished: Result<RunOutcome, JoinError>) {
+        runs.current = None;
+        let outcome = match finished {
+            Ok(outcome) => outcome,
+            Err(e) if e.is_panic() => Err(crate::compute::panic_message(&*e.into_panic())),
+            Err(e) => Err(e.to_string()),
+        };
+        match outcome {
+            Ok(()) => runs.consecutive_failures = 0,
+            Err(last_error) => {
+                runs.consecutive_failures += 1;
+                let threshold = self.options.failure_threshold;
+                if threshold > 0 && runs.consecutive_failures.is_multiple_of(threshold) {
+                    tracing::error!(
+                        "Scheduled workflow {} failed {} times in a row: {}",
+                        self.workflow.id, runs.consecutive_failures, last_error
+                    );
+                    self.emitter.emit(ScheduleEventKind::RepeatedFailures {
+                        consecutive: runs.consecutive_failures,
+                        last_error,
+                    });
+                }
+            }
+        }
+        if runs.queued {
+            runs.queued = false;
+            self.start(runs);
+        }
+    }
+}
+
+/// One scheduled run with its retries
+async fn execute_run(
+    executor: Arc<WorkflowExecutor>,
+    workflow: Arc<WorkflowSpec>,
+    options: ScheduleOptions,
+    emitter: Emitter,
+    run: u64,
+) -> RunOutcome {
+    let retry = options.retry;
+    let mut attempt = 1;
+    loop {
+        emitter.emit(ScheduleEventKind::Started { run, attempt });
+        let start = std::time::Instant::now();
+        let (error, retryable) = match executor.execute_workflow((*workflow).clone(), options.timeout).await {
+            Ok(result) if result.success => {
+                emitter.emit(ScheduleEventKind::Succeeded {
+                    run,
+                    attempt,
+                    duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+                });
+                return Ok(());
+            }
+            Ok(result) => {
+                let error = result.task_results.iter()
+                    .find_map(|r| r.error.clone())
+                    .unwrap_or_else(|| "Workflow failed".to_string());
+                (error, true)
+            }
+            // A cancelled run was cancelled on purpose
+            Err(e @ crate::Error::Cancelled(_)) => (e.to_string(), false),
+            Err(e) => (e.to_string(), true),
+        };
+
+        let will_retry = retryable && attempt < retry.max_attempts;
+        tracing::warn!("Scheduled run {} of workflow {} failed (attempt {}): {}", run, workflow.id, attempt, error);
+        emitter.emit(ScheduleEventKind::Failed { run, attempt, error: error.clone(), will_retry });
+        if !will_retry {
+            return Err(error);
+        }
+        tokio::time::sleep(retry.delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{ModuleRegistry, TaskExecutor, WorkflowBuilder};
+    use crate::core::MessageRouter;
+
+    /// 2024-01-01 00:00 UTC, a Monday
+    const NEW_YEAR_2024: u64 = 1_704_067_200;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    async fn scheduler() -> WorkflowScheduler {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        let executor = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()));
+        WorkflowScheduler::new(Arc::new(executor))
+    }
+
+    fn constant(id: &str, delay_ms: u64) -> WorkflowSpec {
+        WorkflowBuilder::new(id, "Scheduled constant")
+            .add_module("ConstantField", "Source")
+                .parameter("delay_ms", &delay_ms.to_string())
+            .build()
+    }
+
+    /// Event kinds up to and including the first one matching `until`
+    async fn events_until(
+        events: &mut broadcast::Receiver<ScheduleEvent>,
+        until: impl Fn(&ScheduleEventKind) -> bool,
+    ) -> Vec<ScheduleEventKind> {
+        let mut seen = Vec::new();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let kind = events.recv().await.unwrap().kind;
+                let done = until(&kind);
+                seen.push(kind);
+                if done {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("schedule event in time");
+        seen
+    }
+
+    #[test]
+    fn cron_expressions_find_the_next_matching_minute() {
+        let quarter = CronExpr::parse("*/15 * * * *").unwrap();
+        assert_eq!(quarter.next_after(at(NEW_YEAR_2024 + 7 * 60)), Some(at(NEW_YEAR_2024 + 15 * 60)));
+        assert_eq!(quarter.next_after(at(NEW_YEAR_2024)), Some(at(NEW_YEAR_2024 + 15 * 60)));
+
+        let Schedule::Cron(daily) = Schedule::parse("@daily").unwrap() else {
+            panic!("@daily is a cron schedule");
+        };
+        assert_eq!(daily.next_after(at(NEW_YEAR_2024 + 1)), Some(at(NEW_YEAR_2024 + 24 * 60 * 60)));
+
+        // Restricting both day fields runs on either: Friday the 5th comes before the 13th
+        let either = CronExpr::parse("0 0 13 * 5").unwrap();
+        assert_eq!(either.next_after(at(NEW_YEAR_2024)), Some(at(NEW_YEAR_2024 + 4 * 24 * 60 * 60)));
+        // Sunday as 7
+        let sunday = CronExpr::parse("30 12 * * 7").unwrap();
+        assert_eq!(sunday.next_after(at(NEW_YEAR_2024)), Some(at(NEW_YEAR_2024 + 6 * 24 * 60 * 60 + 12 * 60 * 60 + 30 * 60)));
+    }
+
+    #[test]
+    fn invalid_schedules_are_rejected() {
+        for text in ["60 * * * *", "* * *", "*/0 * * * *", "5-1 * * * *", "* * 0 * *", "@every 0s", "@every 5x", "@every m"] {
+            assert!(Schedule::parse(text).is_err(), "{}", text);
+        }
+        assert_eq!(Schedule::parse("@every 5m").unwrap(), Schedule::Interval(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn intervals_overflowing_seconds_are_rejected() {
+        let error = Schedule::parse("@every 999999999999999999d").unwrap_err();
+        assert!(matches!(&error, crate::Error::Config(message) if message.contains("too long")), "{}", error);
+        assert!(Schedule::parse("@every 18446744073709551615m").is_err());
+    }
+
+    #[tokio::test]
+    async fn intervals_beyond_the_clock_are_never_due() {
+        let scheduler = scheduler().await;
+        let schedule = Schedule::parse("@every 18446744073709551615s").unwrap();
+        scheduler.register(constant("forever", 0), schedule, ScheduleOptions::default()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(scheduler.next_run("forever"), None);
+    }
+
+    #[test]
+    fn retry_backoff_doubles_up_to_the_maximum() {
+        let retry = RetryPolicy::new(4, Duration::from_secs(1)).with_max_backoff(Duration::from_secs(3));
+        let delays: Vec<Duration> = (1..=4).map(|attempt| retry.delay(attempt)).collect();
+        assert_eq!(delays, [1, 2, 3, 3].map(Duration::from_secs));
+        assert_eq!(RetryPolicy::new(0, Duration::ZERO).max_attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn interval_schedules_run_repeatedly() {
+        let scheduler = scheduler().await;
+        let mut events = scheduler.subscribe();
+        scheduler.register(constant("interval", 0), Schedule::Interval(Duration::from_millis(50)), ScheduleOptions::default()).unwrap();
+
+        let seen = events_until(&mut events, |k| matches!(k, ScheduleEventKind::Succeeded { run: 2, .. })).await;
+        assert!(seen.contains(&ScheduleEventKind::Started { run: 1, attempt: 1 }));
+        assert!(seen.iter().any(|k| matches!(k, ScheduleEventKind::Succeeded { run: 1, attempt: 1, .. })));
+    }
+
+    #[tokio::test]
+    async fn overlapping_ticks_are_skipped() {
+        let scheduler = scheduler().await;
+        let mut events = scheduler.subscribe();
+        let options = ScheduleOptions::default().with_overlap(OverlapPolicy::Skip);
+        scheduler.register(constant("skip", 350), Schedule::Interval(Duration::from_millis(100)), options).unwrap();
+
+        let seen = events_until(&mut events, |k| matches!(k, ScheduleEventKind::Succeeded { run: 1, .. })).await;
+        assert!(seen.contains(&ScheduleEventKind::Skipped { running: 1 }));
+        assert!(!seen.iter().any(|k| matches!(k, ScheduleEventKind::Started { run: 2, .. })));
+    }
+
+    #[tokio::test]
+    async fn a_queued_tick_runs_once_the_current_run_ends() {
+        let scheduler = scheduler().await;
+        let mut events = scheduler.subscribe();
+        let options = ScheduleOptions::default().with_overlap(OverlapPolicy::Queue);
+        scheduler.register(constant("queue", 250), Schedule::Interval(Duration::from_millis(100)), options).unwrap();
+
+        let seen = events_until(&mut events, |k| matches!(k, ScheduleEventKind::Started { run: 2, .. })).await;
+        let queued = seen.iter().position(|k| *k == ScheduleEventKind::Queued { running: 1 }).unwrap();
+        let finished = seen.iter().position(|k| matches!(k, ScheduleEventKind::Succeeded { run: 1, .. })).unwrap();
+        assert!(queued < finished);
+        // Ticks while a run is already queued coalesce into it
+        assert_eq!(seen.iter().filter(|k| matches!(k, ScheduleEventKind::Queued { .. })).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_runs_are_retried_and_repeated_failures_reported() {
+        let scheduler = scheduler().await;
+        let mut events = scheduler.subscribe();
+        let workflow = WorkflowBuilder::new("failing", "Scheduled failure").add_module("Failing", "Sink").build();
+        let options = ScheduleOptions::default()
+            .with_retry(RetryPolicy::new(3, Duration::from_millis(10)))
+            .with_failure_threshold(1);
+        scheduler.register(workflow, Schedule::Interval(Duration::from_secs(3600)), options).unwrap();
+        scheduler.trigger_now("failing").unwrap();
+
+        let seen = events_until(&mut events, |k| matches!(k, ScheduleEventKind::RepeatedFailures { .. })).await;
+        let retries: Vec<(u32, bool)> = seen.iter()
+            .filter_map(|k| match k {
+                ScheduleEventKind::Failed { run: 1, attempt, will_retry, .. } => Some((*attempt, *will_retry)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(retries, [(1, true), (2, true), (3, false)]);
+        assert!(matches!(seen.last(), Some(ScheduleEventKind::RepeatedFailures { consecutive: 1, .. })));
+    }
+
+    #[tokio::test]
+    async fn paused_schedules_still_run_when_triggered() {
+        let scheduler = scheduler().await;
+        let mut events = scheduler.subscribe();
+        scheduler.register(constant("paused", 0), Schedule::Interval(Duration::from_secs(3600)), ScheduleOptions::default()).unwrap();
+        assert!(scheduler
+            .register(constant("paused", 0), Schedule::Interval(Duration::from_secs(60)), ScheduleOptions::default())
+            .is_err());
+
+        // The driver publishes its first due time once it has started
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while scheduler.next_run("paused").is_none() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let next = scheduler.next_run("paused").unwrap();
+        assert!(next > SystemTime::now() + Duration::from_secs(3500));
+
+        scheduler.pause_schedule("paused").unwrap();
+        assert_eq!(scheduler.next_run("paused"), None);
+        scheduler.trigger_now("paused").unwrap();
+        events_until(&mut events, |k| matches!(k, ScheduleEventKind::Succeeded { run: 1, .. })).await;
+
+        scheduler.resume_schedule("paused").unwrap();
+        assert!(scheduler.unregister("paused"));
+        assert!(!scheduler.unregister("paused"));
+        assert!(scheduler.scheduled().is_empty());
+        assert!(scheduler.trigger_now("paused").is_err());
+    }
+}
@@ -0,0 +1,491 @@
+//! Sweeps of a slicing plane along a coordinate axis
+//!
+//! A `SliceSweep` moves the plane of one module, e.g. a plane `Clip`, along
+//! an axis through linearly or logarithmically spaced positions, running the
+//! workflow once per position. Every string parameter may contain `{slice}`,
+//! replaced by the position, so a writer or renderer parameter of
+//! `slice_{slice}.png` gives one numbered file per member. Members run one
+//! after another and are handed to a callback as they finish, so only one
+//! member's objects are alive at a time unless the callback keeps them.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Object, VistleObject};
+use crate::compute::{WorkflowExecutor, WorkflowResult, WorkflowSpec};
+use crate::util::fmt::format_f64;
+
+/// Attribute holding the sweep position on the outputs of each member
+pub const SLICE_VALUE_ATTRIBUTE: &str = "_slice_value";
+
+/// Placeholder in string parameters replaced by the sweep position
+pub const SLICE_VALUE_PLACEHOLDER: &str = "{slice}";
+
+/// Axis a slicing plane moves along, which is also its normal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SliceAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl SliceAxis {
+    fn index(self) -> usize {
+        match self {
+            SliceAxis::X => 0,
+            SliceAxis::Y => 1,
+            SliceAxis::Z => 2,
+        }
+    }
+
+    pub fn normal(self) -> [f64; 3] {
+        let mut normal = [0.0; 3];
+        normal[self.index()] = 1.0;
+        normal
+    }
+}
+
+/// How positions are distributed between start and stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Spacing {
+    /// Equal distances
+    #[default]
+    Linear,
+    /// Equal ratios; start and stop must be non-zero and of the same sign
+    Log,
+}
+
+/// Positions of a plane swept along an axis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SliceSweep {
+    /// Module whose plane is moved
+    pub module_id: u32,
+    pub axis: SliceAxis,
+    pub start: f64,
+    pub stop: f64,
+    /// Number of positions, both ends included
+    pub count: usize,
+    #[serde(default)]
+    pub spacing: Spacing,
+    /// Parameter holding a point on the plane
+    pub origin_parameter: String,
+    pub normal_parameter: String,
+}
+
+impl SliceSweep {
+    /// Linear sweep moving the `center` and `normal` parameters of a plane `Clip`
+    pub fn new(module_id: u32, axis: SliceAxis, start: f64, stop: f64, count: usize) -> Self {
+        Self {
+            module_id,
+            axis,
+            start,
+            stop,
+            count,
+            spacing: Spacing::Linear,
+            origin_parameter: "center".to_string(),
+            normal_parameter: "normal".to_string(),
+        }
+    }
+
+    /// Linear sweep from `start` in steps of `step`, up to `stop` if it is on a step
+    pub fn every(module_id: u32, axis: SliceAxis, start: f64, stop: f64, step: f64) -> Result<Self, crate::Error> {
+        if step.is_nan() || step <= 0.0 || stop < start {
+            return Err(crate::Error::Config(format!(
+                "Slice sweep from {} to {} needs a positive step, got {}",
+                start, stop, step
+            )));
+        }
+        // Tolerate rounding, so 0 to 1 every 0.05 includes 1
+        let count = ((stop - start) / step + 1e-9).floor() as usize + 1;
+        let stop = start + step * (count - 1) as f64;
+        Ok(Self::new(module_id, axis, start, stop, count))
+    }
+
+    pub fn logarithmic(mut self) -> Self {
+        self.spacing = Spacing::Log;
+        self
+    }
+
+    /// Move a module with other plane parameters than `Clip`
+    pub fn with_parameters(mut self, origin: &str, normal: &str) -> Self {
+        self.origin_parameter = origin.to_string();
+        self.normal_parameter = normal.to_string();
+        self
+    }
+
+    /// Positions along the axis in sweep order
+    pub fn values(&self) -> Result<Vec<f64>, crate::Error> {
+        if self.count == 0 {
+            return Err(crate::Error::Config("Slice sweep has no positions".to_string()));
+        }
+        if self.count == 1 {
+            return Ok(vec![self.start]);
+        }
+        let last = (self.count - 1) as f64;
+        match self.spacing {
+            Spacing::Linear => Ok((0..self.count)
+                .map(|i| self.start + (self.stop - self.start) * i as f64 / last)
+                .collect()),
+            Spacing::Log => {
+                if self.start == 0.0 || self.stop == 0.0 || self.start.signum() != self.stop.signum() {
+                    return Err(crate::Error::Config(format!(
+                        "Logarithmic slice sweep from {} to {} must not cross or touch zero",
+                        self.start, self.stop
+                    )));
+                }
+                let ratio = self.stop / self.start;
+                Ok((0..self.count)
+                    .map(|i| self.start * ratio.powf(i as f64 / last))
+                    .collect())
+            }
+        }
+    }
+
+    /// Planes of the sweep, through `origin` moved along the axis
+    pub fn planes(&self, origin: [f64; 3]) -> Result<Vec<SlicePlane>, crate::Error> {
+        Ok(self.values()?
+            .into_iter()
+            .map(|value| {
+                let mut plane_origin = origin;
+                plane_origin[self.axis.index()] = value;
+                SlicePlane {
+                    value,
+                    origin: plane_origin,
+                    normal: self.axis.normal(),
+                }
+            })
+            .collect())
+    }
+
+    /// Workflow of one member: the plane's parameters set and `{slice}` replaced
+    pub fn build_member(&self, workflow: &WorkflowSpec, plane: &SlicePlane, index: usize) -> WorkflowSpec {
+        let vector = |v: [f64; 3]| v.iter().map(|&c| format_f64(c)).collect::<Vec<_>>().join(" ");
+        let value = format_f64(plane.value);
+
+        let mut member = workflow.clone();
+        member.id = format!("{}#slice{}", workflow.id, index);
+        for module in &mut member.modules {
+            if module.id == self.module_id {
+                module.parameters.insert(self.origin_parameter.clone(), vector(plane.origin));
+                module.parameters.insert(self.normal_parameter.clone(), vector(plane.normal));
+            }
+            for parameter in module.parameters.values_mut() {
+                *parameter = parameter.replace(SLICE_VALUE_PLACEHOLDER, &value);
+            }
+        }
+        member
+    }
+}
+
+/// One position of a slice sweep
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SlicePlane {
+    /// Position along the sweep axis
+    pub value: f64,
+    pub origin: [f64; 3],
+    pub normal: [f64; 3],
+}
+
+/// A finished member, its outputs tagged with `SLICE_VALUE_ATTRIBUTE`
+#[derive(Debug)]
+pub struct SliceMember {
+    pub index: usize,
+    pub plane: SlicePlane,
+    pub result: WorkflowResult,
+}
+
+/// What is kept of a member once it was handed on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SliceMemberSummary {
+    pub index: usize,
+    pub value: f64,
+    pub success: bool,
+    pub execution_time: Duration,
+}
+
+/// Result of a complete slice sweep
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SliceSweepResult {
+    pub workflow_id: String,
+    pub members: Vec<SliceMemberSummary>,
+}
+
+impl SliceSweepResult {
+    pub fn success(&self) -> bool {
+        self.members.iter().all(|m| m.success)
+    }
+}
+
+/// Tag every output object of a member with its sweep position
+fn tag_outputs(result: &mut WorkflowResult, value: f64) {
+    let value = format_f64(value);
+    for objects in result.task_results.iter_mut()
+        .filter_map(|r| r.outputs.as_mut())
+        .flat_map(|outputs| outputs.values_mut())
+    {
+        for object in objects.iter_mut() {
+            if let Some(data) = object.as_data() {
+                let mut data = data.clone();
+                data.attributes.insert(SLICE_VALUE_ATTRIBUTE.to_string(), value.clone());
+                *object = Arc::new(VistleObject::from_data(data)) as Arc<dyn Object>;
+            }
+        }
+    }
+}
+
+impl WorkflowExecutor {
+    /// Execute a workflow once per position of a slice sweep
+    ///
+    /// Each member is handed to `on_member` as soon as it finished and
+    /// dropped afterwards; an error from the callback ends the sweep.
+    pub async fn execute_slice_sweep<F>(
+        &self,
+        workflow: WorkflowSpec,
+        sweep: &SliceSweep,
+        mut on_member: F,
+    ) -> Result<SliceSweepResult, crate::Error>
+    where
+        F: FnMut(SliceMember) -> Result<(), crate::Error>,
+    {
+        let module = workflow.modules.iter()
+            .find(|m| m.id == sweep.module_id)
+            .ok_or_else(|| crate::Error::Config(format!("Slice sweep references unknown module {}", sweep.module_id)))?;
+        let origin = match module.parameters.get(&sweep.origin_parameter) {
+            Some(text) => parse_vector(text).ok_or_else(|| crate::Error::Config(format!(
+                "Module {} ({}): parameter {} is not a point: {}",
+                module.name, module.id, sweep.origin_parameter, text
+            )))?,
+            None => [0.0; 3],
+        };
+        let planes = sweep.planes(origin)?;
+        tracing::info!(
+            "Executing slice sweep over workflow {} with {} planes along {:?}",
+            workflow.id, planes.len(), sweep.axis
+        );
+
+        let mut members = Vec::with_capacity(planes.len());
+        self.begin_progress(&workflow.id, planes.len());
+        for (index, plane) in planes.into_iter().enumerate() {
+            let member = sweep.build_member(&workflow, &plane, index);
+            let mut result = match self.execute_workflow(member, None).await {
+                Ok(result) => result,
+                Err(e) => {
+                    self.end_progress(&workflow.id);
+                    return Err(e);
+                }
+            };
+            self.unit_completed_with_stages(&workflow.id, &result.stages);
+            tag_outputs(&mut result, plane.value);
+
+            members.push(SliceMemberSummary {
+                index,
+                value: plane.value,
+                success: result.success,
+                execution_time: result.execution_time,
+            });
+            if let Err(e) = on_member(SliceMember { index, plane, result }) {
+                self.end_progress(&workflow.id);
+                return Err(e);
+            }
+        }
+
+        self.end_progress(&workflow.id);
+        Ok(SliceSweepResult {
+            workflow_id: workflow.id,
+            members,
+        })
+    }
+}
+
+/// Three whitespace-separated numbers, as vector parameters are written
+fn parse_vector(text: &str) -> Option<[f64; 3]> {
+    let components: Vec<f64> = text.split_whitespace().map(|c| c.parse().ok()).collect::<Option<_>>()?;
+    components.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::{InputPort, Module, ModuleRegistry, OutputPorts, TaskExecutor, WorkflowBuilder};
+    use crate::core::{
+        ComputeContext, ExecutionStats, MessageRouter, ModuleInfo, ObjectPayload, ObjectType, Parameter, ParameterSet,
+        ParameterValue, Port, PortSet,
+    };
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() <= 1e-9 * e.abs().max(1.0), "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn linear_planes_move_the_origin_along_the_axis() {
+        let planes = SliceSweep::new(1, SliceAxis::Z, 0.0, 1.0, 5).planes([2.0, 3.0, 9.0]).unwrap();
+        assert_close(&planes.iter().map(|p| p.value).collect::<Vec<_>>(), &[0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(planes[1].origin, [2.0, 3.0, 0.25]);
+        assert!(planes.iter().all(|p| p.normal == [0.0, 0.0, 1.0]));
+        assert_eq!(SliceAxis::X.normal(), [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn steps_include_the_stop_despite_rounding() {
+        let sweep = SliceSweep::every(1, SliceAxis::Z, 0.0, 1.0, 0.05).unwrap();
+        assert_eq!(sweep.count, 21);
+        let values = sweep.values().unwrap();
+        assert!((values[1] - 0.05).abs() < 1e-12);
+        assert!((values[20] - 1.0).abs() < 1e-12);
+
+        // A stop between steps is left out
+        assert_eq!(SliceSweep::every(1, SliceAxis::Z, 0.0, 1.0, 0.3).unwrap().count, 4);
+        assert!(SliceSweep::every(1, SliceAxis::Z, 0.0, 1.0, 0.0).is_err());
+        assert!(SliceSweep::every(1, SliceAxis::Z, 1.0, 0.0, 0.1).is_err());
+    }
+
+    #[test]
+    fn log_planes_have_equal_ratios() {
+        let values = SliceSweep::new(1, SliceAxis::Y, 1.0, 1000.0, 4).logarithmic().values().unwrap();
+        assert_close(&values, &[1.0, 10.0, 100.0, 1000.0]);
+        let negative = SliceSweep::new(1, SliceAxis::Y, -0.01, -1.0, 3).logarithmic().values().unwrap();
+        assert_close(&negative, &[-0.01, -0.1, -1.0]);
+
+        assert!(SliceSweep::new(1, SliceAxis::Y, -1.0, 1.0, 3).logarithmic().values().is_err());
+        assert!(SliceSweep::new(1, SliceAxis::Y, 0.0, 1.0, 3).logarithmic().values().is_err());
+    }
+
+    #[test]
+    fn degenerate_counts() {
+        assert!(SliceSweep::new(1, SliceAxis::Z, 0.0, 1.0, 0).values().is_err());
+        assert_eq!(SliceSweep::new(1, SliceAxis::Z, 0.5, 1.0, 1).values().unwrap(), [0.5]);
+    }
+
+    fn sweep_workflow() -> WorkflowSpec {
+        WorkflowBuilder::new("sweep", "Sweep")
+            .add_module("PlaneProbe", "Clip")
+                .parameter("center", "1 2 3")
+            .add_module("PlaneProbe", "Writer")
+                .parameter("filename", "slice_{slice}.png")
+                .depends_on(1)
+            .build()
+    }
+
+    #[test]
+    fn members_get_the_plane_and_numbered_file_names() {
+        let sweep = SliceSweep::new(1, SliceAxis::Z, 0.0, 0.5, 3);
+        let workflow = sweep_workflow();
+        let planes = sweep.planes([1.0, 2.0, 3.0]).unwrap();
+        let member = sweep.build_member(&workflow, &planes[1], 1);
+
+        assert_eq!(member.id, "sweep#slice1");
+        assert_eq!(member.modules[0].parameters["center"], "1.0 2.0 0.25");
+        assert_eq!(member.modules[0].parameters["normal"], "0.0 0.0 1.0");
+        assert_eq!(member.modules[1].parameters["filename"], "slice_0.25.png");
+        assert!(!member.modules[1].parameters.contains_key("center"));
+        assert_eq!(workflow.modules[1].parameters["filename"], "slice_{slice}.png");
+    }
+
+    /// Outputs its plane's center, standing in for a slicing module
+    struct PlaneProbe {
+        info: ModuleInfo,
+        parameters: ParameterSet,
+        ports: PortSet,
+        stats: ExecutionStats,
+    }
+
+    impl PlaneProbe {
+        fn new() -> Self {
+            let mut parameters = ParameterSet::new();
+            parameters.add(Parameter::new("center", "Point on the plane", ParameterValue::VecFloat(vec![0.0; 3])));
+            parameters.add(Parameter::new("normal", "Plane normal", ParameterValue::VecFloat(vec![0.0, 0.0, 1.0])));
+            parameters.add(Parameter::new("filename", "Image file", ParameterValue::String(String::new())));
+            let mut ports = PortSet::new();
+            ports.add(Port::new_output("data_out", "Center of the plane"));
+            Self {
+                info: ModuleInfo::new(0, "PlaneProbe", 0, 1),
+                parameters,
+                ports,
+                stats: ExecutionStats::new(0),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Module for PlaneProbe {
+        fn info(&self) -> &ModuleInfo {
+            &self.info
+        }
+
+        fn parameters(&self) -> &ParameterSet {
+            &self.parameters
+        }
+
+        fn ports(&self) -> &PortSet {
+            &self.ports
+        }
+
+        async fn set_input(&mut self, _port_name: &str, _objects: InputPort) -> Result<(), crate::Error> {
+            Ok(())
+        }
+
+        async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+            let center = ctx.parameters().get_vec_float("center").unwrap_or_default().to_vec();
+            let probe = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: center.into() });
+            Ok(OutputPorts::from([("data_out".to_string(), vec![Arc::new(probe) as Arc<dyn Object>])]))
+        }
+
+        fn stats(&self) -> &ExecutionStats {
+            &self.stats
+        }
+    }
+
+    #[tokio::test]
+    async fn members_run_one_by_one_with_tagged_outputs() {
+        let registry = Arc::new(ModuleRegistry::new());
+        registry.register("PlaneProbe", PlaneProbe::new).await;
+        let executor = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()));
+        let sweep = SliceSweep::new(1, SliceAxis::Z, 1.0, 100.0, 3).logarithmic();
+
+        let mut seen = Vec::new();
+        let result = executor.execute_slice_sweep(sweep_workflow(), &sweep, |member| {
+            let outputs = member.result.task_results.iter()
+                .find(|r| r.module_id == Some(1))
+                .and_then(|r| r.outputs.clone())
+                .unwrap();
+            let probe = &outputs["data_out"][0];
+            let Some(ObjectPayload::VecScalar { data }) = probe.payload() else {
+                panic!("expected the plane center");
+            };
+            seen.push((member.index, data[2], probe.attributes()[SLICE_VALUE_ATTRIBUTE].clone()));
+            Ok(())
+        }).await.unwrap();
+
+        assert!(result.success());
+        assert_eq!(result.members.iter().map(|m| m.index).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[1].1, 10.0);
+        assert_eq!(seen.iter().map(|s| s.2.as_str()).collect::<Vec<_>>(), ["1.0", "10.0", "100.0"]);
+    }
+
+    #[tokio::test]
+    async fn a_failing_callback_ends_the_sweep() {
+        let registry = Arc::new(ModuleRegistry::new());
+        registry.register("PlaneProbe", PlaneProbe::new).await;
+        let executor = WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()));
+        let sweep = SliceSweep::new(1, SliceAxis::Z, 0.0, 1.0, 5);
+
+        let mut calls = 0;
+        let error = executor.execute_slice_sweep(sweep_workflow(), &sweep, |_| {
+            calls += 1;
+            Err(crate::Error::Config("disk full".to_string()))
+        }).await.unwrap_err();
+        assert_eq!(calls, 1);
+        assert!(error.to_string().contains("disk full"));
+
+        let unknown = SliceSweep::new(9, SliceAxis::Z, 0.0, 1.0, 2);
+        assert!(executor.execute_slice_sweep(sweep_workflow(), &unknown, |_| Ok(())).await.is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Named pipeline stages and their wall-clock budgets
+//!
+//! Modules are grouped into stages by the stage name on their `ModuleSpec`.
+//! A stage runs from the start of its first module to the end of its last.
+//! Stages given a budget on the `WorkflowSpec` are watched while the
+//! workflow runs; running over publishes a `StageBudgetExceeded` event and,
+//! with `BudgetPolicy::Cancel`, cancels the stage's modules.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{TaskEvent, WorkflowSpec};
+
+/// How often running stages are checked against their budgets
+pub const STAGE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What happens when a stage runs over its budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BudgetPolicy {
+    /// Publish the event and let the stage finish
+    #[default]
+    Alert,
+    /// Publish the event and cancel the stage's modules
+    Cancel,
+}
+
+/// Settings of a named stage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageSpec {
+    pub name: String,
+    /// Wall-clock time the stage is expected to finish within
+    #[serde(default)]
+    pub budget: Option<Duration>,
+    #[serde(default)]
+    pub policy: BudgetPolicy,
+}
+
+impl StageSpec {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            budget: None,
+            policy: BudgetPolicy::Alert,
+        }
+    }
+
+    pub fn with_budget(mut self, budget: Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn with_policy(mut self, policy: BudgetPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+/// Published once per run when a stage takes longer than its budget
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageBudgetExceeded {
+    pub workflow_id: String,
+    pub stage: String,
+    pub budget: Duration,
+    /// How long the stage had run when the overrun was noticed
+    pub elapsed: Duration,
+    /// Whether the stage's modules were cancelled
+    pub cancelled: bool,
+}
+
+/// Wall-clock span of one module, relative to the start of the workflow
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ModuleSpan {
+    pub module_id: u32,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Wall-clock time of one stage in a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub name: String,
+    /// Modules of the stage that ran
+    pub modules: Vec<u32>,
+    /// Start of the first module, relative to the start of the workflow
+    pub start: Duration,
+    /// From the start of the first module to the end of the last
+    pub duration: Duration,
+    pub budget: Option<Duration>,
+    pub exceeded: bool,
+}
+
+/// Start and end of the modules of one workflow run, grouped by stage
+pub(crate) struct StageTracker {
+    workflow_id: String,
+    started: Instant,
+    /// Stages in order of their first module
+    stages: Vec<StageSpec>,
+    /// Modules of each stage
+    members: HashMap<String, Vec<u32>>,
+    modules: HashSet<u32>,
+    spans: Mutex<HashMap<u32, (Instant, Option<Instant>)>>,
+    /// Stages already reported as over budget
+    reported: Mutex<HashSet<String>>,
+}
+
+impl StageTracker {
+    pub fn new(spec: &WorkflowSpec, started: Instant) -> Self {
+        let mut stages: Vec<StageSpec> = Vec::new();
+        let mut members: HashMap<String, Vec<u32>> = HashMap::new();
+        for module in &spec.modules {
+            let Some(name) = &module.stage else {
+                continue;
+            };
+            if !members.contains_key(name) {
+                stages.push(spec.stage(name).cloned().unwrap_or_else(|| StageSpec::new(name)));
+            }
+            members.entry(name.clone()).or_default().push(module.id);
+        }
+        for unused in spec.stages.iter().filter(|s| !members.contains_key(&s.name)) {
+            tracing::warn!("Workflow {}: stage {} has no modules", spec.id, unused.name);
+        }
+
+        Self {
+            workflow_id: spec.id.clone(),
+            started,
+            stages,
+            members,
+            modules: spec.modules.iter().map(|m| m.id).collect(),
+            spans: Mutex::new(HashMap::new()),
+            reported: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn workflow_id(&self) -> &str {
+        &self.workflow_id
+    }
+
+    pub fn stages(&self) -> &[StageSpec] {
+        &self.stages
+    }
+
+    pub fn module_started(&self, module_id: u32, at: Instant) {
+        if self.modules.contains(&module_id) {
+            self.spans.lock().insert(module_id, (at, None));
+        }
+    }
+
+    pub fn module_finished(&self, module_id: u32, at: Instant) {
+        if let Some(span) = self.spans.lock().get_mut(&module_id) {
+            span.1 = Some(at);
+        }
+    }
+
+    /// Record an event of the task executor; tasks of other workflows are ignored
+    pub fn apply(&self, event: &TaskEvent) {
+        if event.workflow_id().is_some_and(|id| id != self.workflow_id) {
+            return;
+        }
+        match *event {
+            TaskEvent::Started { module_id, at, .. } => self.module_started(module_id, at),
+            TaskEvent::Finished { module_id, at, .. } => self.module_finished(module_id, at),
+        }
+    }
+
+    /// Start and end of a stage that started; `now` ends stages still running
+    ///
+    /// Without `now`, modules that never started or finished are left out.
+    fn bounds(&self, name: &str, now: Option<Instant>) -> Option<(Instant, Instant)> {
+        let members = self.members.get(name)?;
+        let spans = self.spans.lock();
+        let started: Vec<&(Instant, Option<Instant>)> = members.iter().filter_map(|id| spans.get(id)).collect();
+        let start = started.iter().map(|span| span.0).min()?;
+        let running = started.len() < members.len() || started.iter().any(|span| span.1.is_none());
+        let end = match now {
+            Some(now) if running => now,
+            _ => started.iter().filter_map(|span| span.1).max().unwrap_or(start),
+        };
+        Some((start, end))
+    }
+
+    /// Stages over budget that were not reported yet, with how long they ran
+    ///
+    /// Pass the current time while the workflow runs, and None once it
+    /// ended so that stages are measured up to their last module.
+    pub fn overruns(&self, now: Option<Instant>) -> Vec<(StageSpec, Duration)> {
+        let mut reported = self.reported.lock();
+        self.stages.iter()
+            .filter_map(|stage| {
+                let budget = stage.budget?;
+                let (start, end) = self.bounds(&stage.name, now)?;
+                let elapsed = end.saturating_duration_since(start);
+                (elapsed > budget && reported.insert(stage.name.clone())).then(|| (stage.clone(), elapsed))
+            })
+            .collect()
+    }
+
+    /// Timing of every stage that ran
+    pub fn timings(&self) -> Vec<StageTiming> {
+        let reported = self.reported.lock();
+        let spans = self.spans.lock().clone();
+        self.stages.iter()
+            .filter_map(|stage| {
+                let (start, end) = self.bounds(&stage.name, None)?;
+                let duration = end.saturating_duration_since(start);
+                Some(StageTiming {
+                    name: stage.name.clone(),
+                    modules: self.members[&stage.name].iter().copied().filter(|id| spans.contains_key(id)).collect(),
+                    start: start.saturating_duration_since(self.started),
+                    duration,
+                    budget: stage.budget,
+                    exceeded: reported.contains(&stage.name) || stage.budget.is_some_and(|b| duration > b),
+                })
+            })
+            .collect()
+    }
+
+    /// Spans of the modules that ran, in start order
+    pub fn spans(&self) -> Vec<ModuleSpan> {
+        let mut spans: Vec<ModuleSpan> = self.spans.lock().iter()
+            .map(|(&module_id, &(start, end))| ModuleSpan {
+                module_id,
+                start: start.saturating_duration_since(self.started),
+                end: end.unwrap_or(start).saturating_duration_since(self.started),
+            })
+            .collect();
+        spans.sort_by_key(|span| (span.start, span.module_id));
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{ModuleRegistry, TaskExecutor, TaskId, WorkflowBuilder, WorkflowExecutor};
+    use crate::core::MessageRouter;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn staged_spec() -> WorkflowSpec {
+        WorkflowBuilder::new("staged", "Staged")
+            .add_module("ConstantField", "Read")
+                .stage("read")
+            .add_module("ConstantField", "Filter")
+                .stage("read")
+                .depends_on(1)
+            .add_module("ConstantField", "Render")
+                .stage("render")
+                .depends_on(2)
+            .add_module("ConstantField", "Unstaged")
+            .build()
+            .with_stage(StageSpec::new("read").with_budget(ms(100)))
+    }
+
+    #[test]
+    fn a_stage_spans_from_its_first_start_to_its_last_end() {
+        let started = Instant::now();
+        let tracker = StageTracker::new(&staged_spec(), started);
+        let names: Vec<&str> = tracker.stages().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["read", "render"]);
+
+        tracker.module_started(1, started + ms(10));
+        tracker.module_finished(1, started + ms(50));
+        tracker.module_started(2, started + ms(50));
+        tracker.module_finished(2, started + ms(90));
+        tracker.module_started(4, started);
+        tracker.module_finished(4, started + ms(5));
+
+        let timings = tracker.timings();
+        assert_eq!(timings.len(), 1);
+        assert_eq!(timings[0].name, "read");
+        assert_eq!(timings[0].modules, [1, 2]);
+        assert_eq!(timings[0].start, ms(10));
+        assert_eq!(timings[0].duration, ms(80));
+        assert!(!timings[0].exceeded);
+    }
+
+    #[test]
+    fn an_overrun_is_reported_once_while_the_stage_runs() {
+        let started = Instant::now();
+        let tracker = StageTracker::new(&staged_spec(), started);
+        tracker.module_started(1, started);
+        tracker.module_finished(1, started + ms(40));
+
+        assert!(tracker.overruns(Some(started + ms(80))).is_empty());
+        let overruns = tracker.overruns(Some(started + ms(150)));
+        assert_eq!(overruns.len(), 1);
+        assert_eq!(overruns[0].0.name, "read");
+        assert_eq!(overruns[0].1, ms(150));
+        assert!(tracker.overruns(Some(started + ms(300))).is_empty());
+
+        tracker.module_started(2, started + ms(40));
+        tracker.module_finished(2, started + ms(120));
+        assert!(tracker.timings()[0].exceeded);
+    }
+
+    #[test]
+    fn events_of_other_workflows_are_ignored() {
+        let started = Instant::now();
+        let tracker = StageTracker::new(&staged_spec(), started);
+        tracker.module_started(1, started);
+        tracker.module_finished(1, started + ms(20));
+        tracker.apply(&TaskEvent::Started {
+            task_id: TaskId::default(),
+            workflow_id: Some("other".to_string()),
+            module_id: 2,
+            at: started,
+        });
+        assert_eq!(tracker.timings()[0].modules, [1]);
+    }
+
+    fn executor() -> (WorkflowExecutor, Arc<ModuleRegistry>) {
+        let registry = Arc::new(ModuleRegistry::new());
+        let executor = WorkflowExecutor::new(registry.clone(), Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()));
+        (executor, registry)
+    }
+
+    fn slow_read(policy: BudgetPolicy, delay_ms: u64) -> WorkflowSpec {
+        WorkflowBuilder::new("nightly", "Nightly")
+            .add_module("ConstantField", "Read")
+                .parameter("delay_ms", &delay_ms.to_string())
+                .stage("read")
+            .add_module("ConstantField", "Render")
+                .stage("render")
+                .depends_on(1)
+            .connect(1, "data_out", 2, "data_in")
+            .stage(StageSpec::new("read").with_budget(ms(50)).with_policy(policy))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn a_stage_over_budget_alerts_and_the_run_completes() {
+        let (executor, registry) = executor();
+        register_test_modules(&registry).await;
+        let mut events = executor.subscribe_stage_events();
+
+        let result = executor.execute_workflow(slow_read(BudgetPolicy::Alert, 400), Some(Duration::from_secs(10))).await.unwrap();
+
+        assert!(result.success);
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.workflow_id, "nightly");
+        assert_eq!(event.stage, "read");
+        assert_eq!(event.budget, ms(50));
+        assert!(event.elapsed > ms(50));
+        assert!(!event.cancelled);
+        assert!(events.try_recv().is_err());
+
+        let read = result.stages.iter().find(|s| s.name == "read").unwrap();
+        assert!(read.exceeded);
+        assert!(read.duration >= ms(400));
+        assert!(!result.stages.iter().find(|s| s.name == "render").unwrap().exceeded);
+    }
+
+    #[tokio::test]
+    async fn the_cancel_policy_stops_the_stage() {
+        let (executor, registry) = executor();
+        register_test_modules(&registry).await;
+        let mut events = executor.subscribe_stage_events();
+
+        let started = Instant::now();
+        let result = executor.execute_workflow(slow_read(BudgetPolicy::Cancel, 5_000), Some(Duration::from_secs(10))).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert!(!result.success);
+        assert!(events.try_recv().unwrap().cancelled);
+    }
+}
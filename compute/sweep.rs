@@ -0,0 +1,457 @@
+//! Parameter sweeps and ensemble execution
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::ObjectId;
+use crate::compute::{OutputPorts, WorkflowExecutor, WorkflowResult, WorkflowSpec};
+
+/// Prefix of sweep placeholders in string parameters
+///
+/// `{sweep.3.iso_value}` is the value of the sweep over `iso_value` of
+/// module 3; `{sweep.iso_value}` may be used while only one module sweeps
+/// a parameter of that name.
+pub const SWEEP_PLACEHOLDER_PREFIX: &str = "sweep.";
+
+/// A single parameter varied across a sweep
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterSweep {
+    pub module_id: u32,
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+impl ParameterSweep {
+    pub fn new(module_id: u32, name: &str, values: Vec<String>) -> Self {
+        Self {
+            module_id,
+            name: name.to_string(),
+            values,
+        }
+    }
+
+    /// Sweep over numbers, written so they parse back exactly whatever the locale
+    pub fn floats(module_id: u32, name: &str, values: &[f64]) -> Self {
+        let values = values.iter().map(|&v| crate::util::fmt::format_f64(v)).collect();
+        Self::new(module_id, name, values)
+    }
+}
+
+/// How multiple sweeps are combined into variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SweepMode {
+    /// Take the i-th value of every sweep for variant i (all sweeps must have equal length)
+    Zip,
+    /// Every combination of values across all sweeps
+    CrossProduct,
+}
+
+/// One concrete parameter assignment within a sweep
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SweepAssignment {
+    pub module_id: u32,
+    pub name: String,
+    pub value: String,
+}
+
+/// Parameter combination of one workflow variant
+pub type SweepCombination = Vec<SweepAssignment>;
+
+/// Result of a single sweep variant
+#[derive(Debug)]
+pub struct SweepVariantResult {
+    pub combination: SweepCombination,
+    /// The variant's run, or why it could not run
+    pub result: Result<WorkflowResult, crate::Error>,
+    pub output_objects: Vec<ObjectId>,
+}
+
+impl SweepVariantResult {
+    pub fn success(&self) -> bool {
+        self.result.as_ref().is_ok_and(|r| r.success)
+    }
+}
+
+/// Result of a complete parameter sweep
+#[derive(Debug)]
+pub struct SweepResult {
+    pub workflow_id: String,
+    pub mode: SweepMode,
+    pub variants: Vec<SweepVariantResult>,
+}
+
+impl SweepResult {
+    /// Check if all variants executed successfully
+    pub fn success(&self) -> bool {
+        self.variants.iter().all(SweepVariantResult::success)
+    }
+
+    /// Variants that failed or could not run
+    pub fn failed(&self) -> impl Iterator<Item = &SweepVariantResult> {
+        self.variants.iter().filter(|v| !v.success())
+    }
+
+    /// Find the variant for a given parameter combination
+    pub fn variant(&self, combination: &[SweepAssignment]) -> Option<&SweepVariantResult> {
+        self.variants.iter().find(|v| v.combination.as_slice() == combination)
+    }
+}
+
+/// Expand sweeps into the list of parameter combinations
+///
+/// Without sweeps there is a single, empty combination: the base workflow.
+pub fn expand_sweeps(sweeps: &[ParameterSweep], mode: SweepMode) -> Result<Vec<SweepCombination>, crate::Error> {
+    if sweeps.is_empty() {
+        return Ok(vec![Vec::new()]);
+    }
+
+    if let Some(sweep) = sweeps.iter().find(|s| s.values.is_empty()) {
+        return Err(crate::Error::Config(format!(
+            "Sweep over parameter {} of module {} has no values",
+            sweep.name, sweep.module_id
+        )));
+    }
+    for (i, sweep) in sweeps.iter().enumerate() {
+        if sweeps[..i].iter().any(|s| s.module_id == sweep.module_id && s.name == sweep.name) {
+            return Err(crate::Error::Config(format!(
+                "Parameter {} of module {} is swept twice",
+                sweep.name, sweep.module_id
+            )));
+        }
+    }
+
+    match mode {
+        SweepMode::Zip => {
+            let len = sweeps[0].values.len();
+            if let Some(sweep) = sweeps.iter().find(|s| s.values.len() != len) {
+                return Err(crate::Error::Config(format!(
+                    "Zip sweep requires equal lengths: {} has {} values, expected {}",
+                    sweep.name,
+                    sweep.values.len(),
+                    len
+                )));
+            }
+
+            Ok((0..len)
+                .map(|i| {
+                    sweeps.iter()
+                        .map(|s| SweepAssignment {
+                            module_id: s.module_id,
+                            name: s.name.clone(),
+                            value: s.values[i].clone(),
+                        })
+                        .collect()
+                })
+                .collect())
+        }
+        SweepMode::CrossProduct => {
+            let mut combinations: Vec<SweepCombination> = vec![Vec::new()];
+            for sweep in sweeps {
+                let mut expanded = Vec::with_capacity(combinations.len() * sweep.values.len());
+                for combination in &combinations {
+                    for value in &sweep.values {
+                        let mut next = combination.clone();
+                        next.push(SweepAssignment {
+                            module_id: sweep.module_id,
+                            name: sweep.name.clone(),
+                            value: value.clone(),
+                        });
+                        expanded.push(next);
+                    }
+                }
+                combinations = expanded;
+            }
+            Ok(combinations)
+        }
+    }
+}
+
+/// Substitute `{sweep.<module_id>.<name>}` and `{sweep.<name>}` placeholders in a parameter value
+///
+/// A `{sweep.<name>}` placeholder naming parameters of several modules is an error.
+pub fn apply_sweep_template(value: &str, combination: &[SweepAssignment]) -> Result<String, crate::Error> {
+    let mut result = value.to_string();
+    for assignment in combination {
+        let qualified = format!("{{{}{}.{}}}", SWEEP_PLACEHOLDER_PREFIX, assignment.module_id, assignment.name);
+        result = result.replace(&qualified, &assignment.value);
+
+        let short = format!("{{{}{}}}", SWEEP_PLACEHOLDER_PREFIX, assignment.name);
+        if result.contains(&short) {
+            if combination.iter().any(|a| a.name == assignment.name && a.module_id != assignment.module_id) {
+                return Err(crate::Error::Config(format!(
+                    "Placeholder {} is ambiguous, parameter {} is swept on several modules; use {}",
+                    short, assignment.name, qualified
+                )));
+            }
+            result = result.replace(&short, &assignment.value);
+        }
+    }
+    Ok(result)
+}
+
+/// Build the workflow variant for one parameter combination
+pub fn build_variant(workflow: &WorkflowSpec, combination: &[SweepAssignment], index: usize) -> Result<WorkflowSpec, crate::Error> {
+    let mut variant = workflow.clone();
+    variant.id = format!("{}#sweep{}", workflow.id, index);
+
+    for module in &mut variant.modules {
+        for assignment in combination.iter().filter(|a| a.module_id == module.id) {
+            module.parameters.insert(assignment.name.clone(), assignment.value.clone());
+        }
+
+        // Expand templates so file-writing modules don't overwrite each other
+        for value in module.parameters.values_mut() {
+            *value = apply_sweep_template(value, combination)?;
+        }
+    }
+
+    Ok(variant)
+}
+
+/// Modules whose parameters differ between two variants of a workflow
+fn changed_modules(base: &WorkflowSpec, variant: &WorkflowSpec) -> Vec<u32> {
+    variant.modules.iter()
+        .filter(|module| {
+            base.modules.iter()
+                .find(|m| m.id == module.id)
+                .is_none_or(|m| m.parameters != module.parameters)
+        })
+        .map(|module| module.id)
+        .collect()
+}
+
+impl WorkflowExecutor {
+    /// Execute a workflow once per parameter combination of the given sweeps
+    ///
+    /// Variants run one after another on the shared task executor, so the
+    /// global concurrency limit applies to every variant. Modules upstream
+    /// of every swept parameter run once: later variants reuse their outputs
+    /// from the first successful variant. A variant that fails or cannot run
+    /// is recorded in its result and the sweep goes on with the next.
+    pub async fn execute_sweep(
+        &self,
+        workflow: WorkflowSpec,
+        sweeps: Vec<ParameterSweep>,
+        mode: SweepMode,
+    ) -> Result<SweepResult, crate::Error> {
+        for sweep in &sweeps {
+            if !workflow.modules.iter().any(|m| m.id == sweep.module_id) {
+                return Err(crate::Error::Config(format!(
+                    "Sweep references unknown module {}",
+                    sweep.module_id
+                )));
+            }
+        }
+
+        let combinations = expand_sweeps(&sweeps, mode)?;
+        tracing::info!(
+            "Executing sweep over workflow {} with {} variants",
+            workflow.id,
+            combinations.len()
+        );
+
+        let mut variants = Vec::with_capacity(combinations.len());
+        // Spec and outputs of the first successful variant, for the next to reuse
+        let mut base: Option<(WorkflowSpec, HashMap<u32, OutputPorts>)> = None;
+        self.begin_progress(&workflow.id, combinations.len());
+        for (index, combination) in combinations.into_iter().enumerate() {
+            let result = match build_variant(&workflow, &combination, index) {
+                Ok(variant) => {
+                    let spec = variant.clone();
+                    let run = match &base {
+                        Some((base_spec, outputs)) => {
+                            let changed = changed_modules(base_spec, &variant);
+                            self.execute_workflow_reusing(variant, None, outputs, &changed).await
+                        }
+                        None => self.execute_workflow(variant, None).await,
+                    };
+                    if let (None, Ok(result)) = (&base, &run) {
+                        if result.success {
+                            base = Some((spec, module_outputs(result)));
+                        }
+                    }
+                    run
+                }
+                Err(e) => Err(e),
+            };
+            match &result {
+                Ok(result) => self.unit_completed_with_stages(&workflow.id, &result.stages),
+                Err(e) => {
+                    tracing::warn!("Workflow {}: sweep variant {} failed: {}", workflow.id, index, e);
+                    self.unit_completed(&workflow.id);
+                }
+            }
+
+            let output_objects = result.iter()
+                .flat_map(|result| &result.task_results)
+                .filter_map(|r| r.outputs.as_ref())
+                .flat_map(|outputs| outputs.values())
+                .flat_map(|objects| objects.iter().map(|o| o.id()))
+                .collect();
+
+            variants.push(SweepVariantResult {
+                combination,
+                result,
+                output_objects,
+            });
+        }
+
+        self.end_progress(&workflow.id);
+        Ok(SweepResult {
+            workflow_id: workflow.id,
+            mode,
+            variants,
+        })
+    }
+}
+
+/// Outputs of every module that produced some
+fn module_outputs(result: &WorkflowResult) -> HashMap<u32, OutputPorts> {
+    result.task_results.iter()
+        .filter_map(|r| Some((r.module_id?, r.outputs.clone()?)))
+        .collect()
+}
+
+/// Group variant results by the values of the sweep over parameter `name` of `module_id`
+pub fn group_by_parameter<'a>(result: &'a SweepResult, module_id: u32, name: &str) -> HashMap<String, Vec<&'a SweepVariantResult>> {
+    let mut groups: HashMap<String, Vec<&SweepVariantResult>> = HashMap::new();
+    for variant in &result.variants {
+        if let Some(assignment) = variant.combination.iter().find(|a| a.module_id == module_id && a.name == name) {
+            groups.entry(assignment.value.clone()).or_default().push(variant);
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::compute::testing::modules::register_test_modules;
+    use crate::compute::{ModuleRegistry, TaskExecutor, WorkflowBuilder};
+    use crate::core::MessageRouter;
+
+    fn assignment(module_id: u32, name: &str, value: &str) -> SweepAssignment {
+        SweepAssignment { module_id, name: name.to_string(), value: value.to_string() }
+    }
+
+    fn values(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    /// A constant field feeding another, whose value is swept
+    fn chain() -> WorkflowSpec {
+        WorkflowBuilder::new("sweep", "Sweep")
+            .add_module("ConstantField", "Source")
+                .parameter("count", "3")
+            .add_module("ConstantField", "Scaled")
+                .parameter("value", "1")
+            .connect(1, "data_out", 2, "data_in")
+            .build()
+    }
+
+    async fn executor() -> WorkflowExecutor {
+        let registry = Arc::new(ModuleRegistry::new());
+        register_test_modules(&registry).await;
+        WorkflowExecutor::new(registry, Arc::new(TaskExecutor::new(2)), Arc::new(MessageRouter::new()))
+    }
+
+    fn output_of(variant: &SweepVariantResult, module_id: u32) -> ObjectId {
+        let result = variant.result.as_ref().unwrap();
+        let task = result.task_results.iter().find(|r| r.module_id == Some(module_id)).unwrap();
+        task.outputs.as_ref().unwrap()["data_out"][0].id()
+    }
+
+    #[test]
+    fn zip_and_cross_product_expand_differently() {
+        let sweeps = vec![
+            ParameterSweep::new(1, "a", values(&["1", "2"])),
+            ParameterSweep::new(2, "b", values(&["x", "y"])),
+        ];
+        let zip = expand_sweeps(&sweeps, SweepMode::Zip).unwrap();
+        assert_eq!(zip, vec![
+            vec![assignment(1, "a", "1"), assignment(2, "b", "x")],
+            vec![assignment(1, "a", "2"), assignment(2, "b", "y")],
+        ]);
+        let cross = expand_sweeps(&sweeps, SweepMode::CrossProduct).unwrap();
+        assert_eq!(cross.len(), 4);
+        assert_eq!(cross[1], vec![assignment(1, "a", "1"), assignment(2, "b", "y")]);
+    }
+
+    #[test]
+    fn invalid_sweeps_are_config_errors() {
+        let uneven = vec![ParameterSweep::new(1, "a", values(&["1"])), ParameterSweep::new(1, "b", values(&["1", "2"]))];
+        assert!(matches!(expand_sweeps(&uneven, SweepMode::Zip), Err(crate::Error::Config(_))));
+        assert!(expand_sweeps(&uneven, SweepMode::CrossProduct).is_ok());
+
+        let empty = vec![ParameterSweep::new(1, "a", Vec::new())];
+        assert!(matches!(expand_sweeps(&empty, SweepMode::CrossProduct), Err(crate::Error::Config(_))));
+
+        let twice = vec![ParameterSweep::new(1, "a", values(&["1"])), ParameterSweep::new(1, "a", values(&["2"]))];
+        assert!(matches!(expand_sweeps(&twice, SweepMode::CrossProduct), Err(crate::Error::Config(_))));
+    }
+
+    #[test]
+    fn no_sweeps_expand_to_the_base_workflow() {
+        assert_eq!(expand_sweeps(&[], SweepMode::Zip).unwrap(), vec![Vec::new()]);
+        let variant = build_variant(&chain(), &[], 0).unwrap();
+        assert_eq!(variant.id, "sweep#sweep0");
+        assert_eq!(variant.modules[1].parameters["value"], "1");
+    }
+
+    #[test]
+    fn placeholders_are_keyed_by_module_and_name() {
+        let combination = vec![assignment(1, "level", "0.5"), assignment(2, "level", "7"), assignment(2, "file", "out")];
+        assert_eq!(
+            apply_sweep_template("{sweep.1.level}_{sweep.2.level}_{sweep.file}.vtk", &combination).unwrap(),
+            "0.5_7_out.vtk"
+        );
+        let error = apply_sweep_template("{sweep.level}.vtk", &combination).unwrap_err();
+        assert!(error.to_string().contains("{sweep.1.level}"), "{}", error);
+
+        let only_one = vec![assignment(2, "level", "7")];
+        assert_eq!(apply_sweep_template("{sweep.level}", &only_one).unwrap(), "7");
+    }
+
+    #[tokio::test]
+    async fn no_sweeps_run_the_base_workflow_once() {
+        let result = executor().await.execute_sweep(chain(), Vec::new(), SweepMode::Zip).await.unwrap();
+        assert_eq!(result.variants.len(), 1);
+        assert!(result.success());
+        assert!(result.variants[0].combination.is_empty());
+        assert_eq!(result.variants[0].output_objects.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_variants_are_recorded_and_the_sweep_goes_on() {
+        let sweeps = vec![ParameterSweep::new(2, "count", values(&["2", "many", "5"]))];
+        let result = executor().await.execute_sweep(chain(), sweeps, SweepMode::Zip).await.unwrap();
+
+        assert_eq!(result.variants.len(), 3);
+        assert!(!result.success());
+        assert!(result.variants[1].result.is_err());
+        assert!(result.variants[1].output_objects.is_empty());
+        assert!(result.variants[0].success() && result.variants[2].success());
+        assert_eq!(result.failed().map(|v| v.combination[0].value.as_str()).collect::<Vec<_>>(), vec!["many"]);
+
+        let groups = group_by_parameter(&result, 2, "count");
+        assert_eq!(groups.len(), 3);
+        assert!(group_by_parameter(&result, 1, "count").is_empty());
+    }
+
+    #[tokio::test]
+    async fn later_variants_reuse_outputs_upstream_of_the_sweep() {
+        let sweeps = vec![ParameterSweep::floats(2, "value", &[2.0, 3.0, 4.0])];
+        let result = executor().await.execute_sweep(chain(), sweeps, SweepMode::Zip).await.unwrap();
+        assert!(result.success());
+
+        // The source ran once; its object fed every variant
+        let source = output_of(&result.variants[0], 1);
+        assert!(result.variants.iter().all(|v| output_of(v, 1) == source));
+        let scaled: Vec<ObjectId> = result.variants.iter().map(|v| output_of(v, 2)).collect();
+        assert_ne!(scaled[0], scaled[1]);
+        assert_ne!(scaled[1], scaled[2]);
+    }
+}
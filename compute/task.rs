@@ -1,28 +1,58 @@
 //! Task execution and dependency management
 
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::sync::Arc;
-use tokio::sync::{RwLock, Semaphore};
-use futures::future::join_all;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Weak};
+use tokio::sync::{broadcast, Notify, RwLock, Semaphore};
+use futures::future::{join_all, BoxFuture};
 
-use crate::core::{ComputeContext, ObjectId};
-use crate::compute::{Module, OutputPorts};
+use crate::core::{ComputeContext, HealthConfig, HealthMonitor, HealthProbe, Issue, MessageRouter};
+use crate::compute::{AdmittedTask, MemoryAdmission, Module, OutputPorts, PortAudit, VistleModule, MEMORY_SAMPLE_INTERVAL};
+
+/// Module instance a task runs
+pub type TaskModule = Arc<VistleModule<Box<dyn Module>>>;
+
+/// Check of a task's outputs before its dependents see them, see `Task::with_output_check`
+pub type OutputCheck = Arc<dyn Fn(OutputPorts) -> Result<OutputPorts, crate::Error> + Send + Sync>;
+
+/// Outputs produced elsewhere that a task completes with, see `Task::with_remote_outputs`
+pub type OutputSource = Arc<dyn Fn() -> BoxFuture<'static, Result<OutputPorts, crate::Error>> + Send + Sync>;
+
+/// Receiver of a task's outcome, see `Task::with_output_sink`
+pub type OutputSink = Arc<dyn Fn(Result<OutputPorts, String>) -> BoxFuture<'static, ()> + Send + Sync>;
 
 /// Execution task representing a module computation
 pub struct Task {
     pub id: TaskId,
-    pub module: Arc<dyn Module>,
+    pub module: TaskModule,
     pub context: ComputeContext,
     pub dependencies: Vec<TaskId>,
+    /// Outputs of other tasks fed to the module's input ports before it runs
+    pub inputs: Vec<TaskInput>,
+    /// Router the module reports its start and completion to; none uses a private one
+    pub router: Option<Arc<MessageRouter>>,
+    pub output_check: Option<OutputCheck>,
+    /// Outputs of an earlier run the task completes with instead of running its module
+    pub reused_outputs: Option<OutputPorts>,
+    /// Source of outputs produced elsewhere the task completes with instead of running its module
+    pub remote_outputs: Option<OutputSource>,
+    /// Told the task's outputs or error once it finished, or that it never runs
+    pub output_sink: Option<OutputSink>,
     pub dependents: Vec<TaskId>,
     pub status: TaskStatus,
+    /// Priority as declared, used for reporting
     pub priority: TaskPriority,
+    /// Priority the scheduler uses, raised to that of any task waiting on this one
+    pub effective_priority: TaskPriority,
+    /// Declared peak memory in bytes, overriding the observed one for admission control
+    pub peak_memory: Option<usize>,
+    /// Workflow the task belongs to, see `TaskExecutor::execute_workflow`
+    pub workflow_id: Option<String>,
 }
 
 impl Task {
     pub fn new(
         id: TaskId,
-        module: Arc<dyn Module>,
+        module: TaskModule,
         context: ComputeContext,
     ) -> Self {
         Self {
@@ -30,9 +60,18 @@ impl Task {
             module,
             context,
             dependencies: Vec::new(),
+            inputs: Vec::new(),
+            router: None,
+            output_check: None,
+            reused_outputs: None,
+            remote_outputs: None,
+            output_sink: None,
             dependents: Vec::new(),
             status: TaskStatus::Pending,
             priority: TaskPriority::Normal,
+            effective_priority: TaskPriority::Normal,
+            peak_memory: None,
+            workflow_id: None,
         }
     }
 
@@ -43,6 +82,65 @@ impl Task {
 
     pub fn with_priority(mut self, priority: TaskPriority) -> Self {
         self.priority = priority;
+        self.effective_priority = priority;
+        self
+    }
+
+    /// Feed `from_port` of task `from` to `to_port`, depending on `from`
+    pub fn with_input(mut self, from: TaskId, from_port: &str, to_port: &str) -> Self {
+        if !self.dependencies.contains(&from) {
+            self.dependencies.push(from);
+        }
+        self.inputs.push(TaskInput {
+            from,
+            from_port: from_port.to_string(),
+            to_port: to_port.to_string(),
+        });
+        self
+    }
+
+    pub fn with_router(mut self, router: Arc<MessageRouter>) -> Self {
+        self.router = Some(router);
+        self
+    }
+
+    /// Fail the task when `check` rejects its outputs, e.g. objects of a strict workflow
+    pub fn with_output_check(mut self, check: OutputCheck) -> Self {
+        self.output_check = Some(check);
+        self
+    }
+
+    /// Complete with `outputs` without running the module, e.g. an unchanged module of a sweep variant
+    pub fn with_reused_outputs(mut self, outputs: OutputPorts) -> Self {
+        self.reused_outputs = Some(outputs);
+        self
+    }
+
+    /// Complete with the outputs `source` delivers without running the module, e.g. those of a module on another rank
+    ///
+    /// The task's module only names it in reports; its dependents wait for
+    /// the outputs like for those of a local module.
+    pub fn with_remote_outputs(mut self, source: OutputSource) -> Self {
+        self.remote_outputs = Some(source);
+        self
+    }
+
+    /// Hand the task's outcome to `sink` before its dependents see it, e.g. to announce outputs to other ranks
+    ///
+    /// A task that never runs because an upstream task failed hands its
+    /// sink an error instead.
+    pub fn with_output_sink(mut self, sink: OutputSink) -> Self {
+        self.output_sink = Some(sink);
+        self
+    }
+
+    pub fn with_peak_memory(mut self, bytes: usize) -> Self {
+        self.peak_memory = Some(bytes);
+        self
+    }
+
+    pub fn with_workflow(mut self, workflow_id: &str) -> Self {
+        self.workflow_id = Some(workflow_id.to_string());
         self
     }
 
@@ -55,6 +153,19 @@ impl Task {
     pub fn module_id(&self) -> u32 {
         self.context.module_id
     }
+
+    /// Module type whose observed peak memory stands in for an undeclared one
+    pub fn module_type(&self) -> &str {
+        &self.module.info().name
+    }
+}
+
+/// Connection from an output port of one task to an input port of another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInput {
+    pub from: TaskId,
+    pub from_port: String,
+    pub to_port: String,
 }
 
 /// Unique task identifier
@@ -91,7 +202,7 @@ pub enum TaskStatus {
 }
 
 /// Task execution priority
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
 pub enum TaskPriority {
     Low,
     Normal,
@@ -103,7 +214,11 @@ pub enum TaskPriority {
 pub struct TaskGraph {
     tasks: HashMap<TaskId, Task>,
     completed: HashSet<TaskId>,
+    /// Tasks that failed; their dependents never become ready
+    failed: HashSet<TaskId>,
     ready_queue: VecDeque<TaskId>,
+    /// When each queued task became ready
+    ready_since: HashMap<TaskId, std::time::Instant>,
     semaphore: Arc<Semaphore>, // Limit concurrent executions
 }
 
@@ -112,20 +227,79 @@ impl TaskGraph {
         Self {
             tasks: HashMap::new(),
             completed: HashSet::new(),
+            failed: HashSet::new(),
             ready_queue: VecDeque::new(),
+            ready_since: HashMap::new(),
             semaphore: Arc::new(Semaphore::new(max_concurrent)),
         }
     }
 
-    pub fn add_task(&mut self, task: Task) {
+    pub fn add_task(&mut self, mut task: Task) {
         let task_id = task.id;
 
+        // Link both directions, whichever of the two tasks was added first
+        for dep in &task.dependencies {
+            if let Some(dependency) = self.tasks.get_mut(dep) {
+                if !dependency.dependents.contains(&task_id) {
+                    dependency.dependents.push(task_id);
+                }
+            }
+        }
+        for (id, other) in &self.tasks {
+            if other.dependencies.contains(&task_id) && !task.dependents.contains(id) {
+                task.dependents.push(*id);
+                // Inherit from tasks that were already waiting on this one
+                task.effective_priority = task.effective_priority.max(other.effective_priority);
+            }
+        }
+
         // Check if dependencies are satisfied
         if task.dependencies_satisfied(&self.completed) {
-            self.ready_queue.push_back(task_id);
+            self.push_ready(task_id);
         }
 
+        let priority = task.effective_priority;
         self.tasks.insert(task_id, task);
+        self.boost_dependencies(task_id, priority);
+    }
+
+    /// Change a task's declared priority
+    ///
+    /// Raising it also raises the effective priority of the task and its
+    /// incomplete dependencies. Lowering it only changes the declared value:
+    /// dependencies already boosted keep their priority, since they may be
+    /// running or queued ahead of others on its account.
+    pub fn set_priority(&mut self, task_id: TaskId, priority: TaskPriority) {
+        let Some(task) = self.tasks.get_mut(&task_id) else {
+            return;
+        };
+        task.priority = priority;
+        if priority > task.effective_priority {
+            task.effective_priority = priority;
+            self.boost_dependencies(task_id, priority);
+        }
+    }
+
+    /// Raise incomplete dependencies, transitively, to at least `priority`
+    fn boost_dependencies(&mut self, task_id: TaskId, priority: TaskPriority) {
+        let mut stack = vec![task_id];
+        while let Some(id) = stack.pop() {
+            let dependencies = match self.tasks.get(&id) {
+                Some(task) => task.dependencies.clone(),
+                None => continue,
+            };
+            for dep in dependencies {
+                if self.completed.contains(&dep) {
+                    continue;
+                }
+                if let Some(dependency) = self.tasks.get_mut(&dep) {
+                    if dependency.effective_priority < priority {
+                        dependency.effective_priority = priority;
+                        stack.push(dep);
+                    }
+                }
+            }
+        }
     }
 
     pub fn mark_completed(&mut self, task_id: TaskId) {
@@ -139,15 +313,164 @@ impl TaskGraph {
                 if let Some(dependent) = self.tasks.get(&dependent_id) {
                     if dependent.dependencies_satisfied(&self.completed)
                         && !self.ready_queue.contains(&dependent_id) {
-                        self.ready_queue.push_back(dependent_id);
+                        self.push_ready(dependent_id);
                     }
                 }
             }
         }
     }
 
+    /// Record a failed task, leaving its dependents blocked
+    pub fn mark_failed(&mut self, task_id: TaskId) {
+        if let Some(task) = self.tasks.get_mut(&task_id) {
+            task.status = TaskStatus::Failed;
+        }
+        self.failed.insert(task_id);
+    }
+
+    /// Sinks of the tasks a failed task leaves blocked, transitively, taken so each is told once
+    ///
+    /// With `itself`, the failed task's own sink is taken as well, for tasks
+    /// that ended before reaching it, e.g. aborted ones.
+    pub fn take_blocked_sinks(&mut self, task_id: TaskId, itself: bool) -> Vec<OutputSink> {
+        let mut sinks = Vec::new();
+        if itself {
+            sinks.extend(self.tasks.get_mut(&task_id).and_then(|t| t.output_sink.take()));
+        }
+        let mut visited = HashSet::new();
+        let mut stack = self.tasks.get(&task_id).map(|t| t.dependents.clone()).unwrap_or_default();
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id) || self.completed.contains(&id) {
+                continue;
+            }
+            if let Some(task) = self.tasks.get_mut(&id) {
+                sinks.extend(task.output_sink.take());
+                stack.extend(task.dependents.iter().copied());
+            }
+        }
+        sinks
+    }
+
+    /// Record a task cancelled while running, leaving its dependents blocked
+    pub fn mark_cancelled(&mut self, task_id: TaskId) {
+        if let Some(task) = self.tasks.get_mut(&task_id) {
+            task.status = TaskStatus::Cancelled;
+        }
+        self.failed.insert(task_id);
+    }
+
+    /// Next ready task by effective priority, oldest first among equals
     pub fn get_ready_task(&mut self) -> Option<TaskId> {
-        self.ready_queue.pop_front()
+        self.get_ready_task_where(|_| true)
+    }
+
+    /// Next ready task whose `estimate` fits into `headroom` bytes
+    ///
+    /// Picks by effective priority like `get_ready_task`, passing over tasks
+    /// too large for now so smaller ones run meanwhile.
+    pub fn get_ready_task_within(&mut self, headroom: usize, estimate: impl Fn(&Task) -> usize) -> Option<TaskId> {
+        self.get_ready_task_where(|t| estimate(t) <= headroom)
+    }
+
+    /// Next ready task `accept` takes, picked like `get_ready_task` among those
+    pub fn get_ready_task_where(&mut self, accept: impl Fn(&Task) -> bool) -> Option<TaskId> {
+        let (index, _) = self.ready_queue.iter()
+            .enumerate()
+            .filter(|(_, id)| self.tasks.get(id).is_some_and(&accept))
+            .max_by(|(ia, a), (ib, b)| {
+                let priority = |id: &TaskId| self.tasks.get(id).map(|t| t.effective_priority);
+                priority(a).cmp(&priority(b)).then(ib.cmp(ia))
+            })?;
+        self.take_ready(index)
+    }
+
+    /// Next ready task `accept` takes, the smallest by `size` among those of the highest priority
+    ///
+    /// Ties go to the task that became ready first, as in `get_ready_task`.
+    pub fn get_smallest_ready_task_where(
+        &mut self,
+        accept: impl Fn(&Task) -> bool,
+        size: impl Fn(&Task) -> usize,
+    ) -> Option<TaskId> {
+        let (index, _) = self.ready_queue.iter()
+            .enumerate()
+            .filter_map(|(index, id)| self.tasks.get(id).filter(|t| accept(t)).map(|t| (index, t)))
+            .max_by(|(ia, a), (ib, b)| {
+                a.effective_priority.cmp(&b.effective_priority)
+                    .then(size(b).cmp(&size(a)))
+                    .then(ib.cmp(ia))
+            })?;
+        self.take_ready(index)
+    }
+
+    fn push_ready(&mut self, task_id: TaskId) {
+        self.ready_queue.push_back(task_id);
+        self.ready_since.insert(task_id, std::time::Instant::now());
+    }
+
+    fn take_ready(&mut self, index: usize) -> Option<TaskId> {
+        let task_id = self.ready_queue.remove(index)?;
+        self.ready_since.remove(&task_id);
+        if let Some(task) = self.tasks.get_mut(&task_id) {
+            task.status = TaskStatus::Running;
+        }
+        Some(task_id)
+    }
+
+    /// When the task that has waited longest in the ready queue became ready
+    pub fn oldest_ready(&self) -> Option<std::time::Instant> {
+        self.ready_since.values().min().copied()
+    }
+
+    pub fn has_ready(&self) -> bool {
+        !self.ready_queue.is_empty()
+    }
+
+    /// Whether a ready task of `scope` waits, any workflow's without one
+    pub fn has_ready_in(&self, scope: Option<&str>) -> bool {
+        self.ready_queue.iter()
+            .filter_map(|id| self.tasks.get(id))
+            .any(|task| in_scope(task.workflow_id.as_deref(), scope))
+    }
+
+    pub fn contains_workflow(&self, workflow_id: &str) -> bool {
+        self.tasks.values().any(|task| task.workflow_id.as_deref() == Some(workflow_id))
+    }
+
+    fn workflow_tasks(&self, workflow_id: &str) -> Vec<TaskId> {
+        self.tasks.values()
+            .filter(|task| task.workflow_id.as_deref() == Some(workflow_id))
+            .map(|task| task.id)
+            .collect()
+    }
+
+    /// Cancel the tasks of a workflow that have not been dispatched
+    ///
+    /// Running tasks are left to the executor to abort; tasks of other
+    /// workflows are untouched.
+    pub fn cancel_workflow(&mut self, workflow_id: &str) {
+        for task_id in self.workflow_tasks(workflow_id) {
+            let waiting = self.tasks.get(&task_id)
+                .is_some_and(|t| matches!(t.status, TaskStatus::Pending | TaskStatus::Ready));
+            if waiting && !self.completed.contains(&task_id) {
+                if let Some(index) = self.ready_queue.iter().position(|id| *id == task_id) {
+                    self.take_ready(index);
+                }
+                self.mark_cancelled(task_id);
+            }
+        }
+    }
+
+    /// Drop the tasks of a workflow that ran, with their completion records
+    pub fn remove_workflow(&mut self, workflow_id: &str) {
+        for task_id in self.workflow_tasks(workflow_id) {
+            self.tasks.remove(&task_id);
+            self.completed.remove(&task_id);
+            self.failed.remove(&task_id);
+            self.ready_since.remove(&task_id);
+        }
+        let tasks = &self.tasks;
+        self.ready_queue.retain(|id| tasks.contains_key(id));
     }
 
     pub fn get_task(&self, id: TaskId) -> Option<&Task> {
@@ -159,11 +482,11 @@ impl TaskGraph {
     }
 
     pub fn is_complete(&self) -> bool {
-        self.tasks.len() == self.completed.len()
+        self.pending_count() == 0
     }
 
     pub fn pending_count(&self) -> usize {
-        self.tasks.len() - self.completed.len()
+        self.tasks.len().saturating_sub(self.completed.len() + self.failed.len())
     }
 
     pub fn semaphore(&self) -> Arc<Semaphore> {
@@ -171,27 +494,312 @@ impl TaskGraph {
     }
 }
 
+/// Feed a task's inputs from the results of its upstream tasks and run its module
+///
+/// Panics are caught by `VistleModule::execute` and returned as errors.
+async fn run_module(
+    module: &TaskModule,
+    context: &ComputeContext,
+    inputs: &[TaskInput],
+    router: Option<Arc<MessageRouter>>,
+    results: &RwLock<WorkflowResults>,
+    workflow_id: &Option<String>,
+) -> Result<OutputPorts, crate::Error> {
+    // Inputs feeding the same port are concatenated in the order they were added
+    let upstream: Vec<(String, Vec<_>)> = {
+        let results = results.read().await;
+        let workflow = results.get(workflow_id);
+        let mut upstream: Vec<(String, Vec<_>)> = Vec::new();
+        for input in inputs {
+            let objects = workflow
+                .and_then(|results| results.get(&input.from))
+                .and_then(|result| result.outputs.as_ref())
+                .and_then(|outputs| outputs.get(&input.from_port))
+                .cloned()
+                .unwrap_or_default();
+            match upstream.iter_mut().find(|(port, _)| *port == input.to_port) {
+                Some((_, gathered)) => gathered.extend(objects),
+                None => upstream.push((input.to_port.clone(), objects)),
+            }
+        }
+        upstream
+    };
+    for (port, objects) in upstream {
+        module.set_input(&port, objects).await?;
+    }
+    let router = router.unwrap_or_else(|| Arc::new(MessageRouter::new()));
+    module.execute(context, &router).await
+}
+
+/// Tell the sinks of tasks that will not run because `failed` failed
+async fn tell_blocked(sinks: Vec<OutputSink>, failed: TaskId) {
+    for sink in sinks {
+        sink(Err(format!("Upstream task {:?} failed", failed))).await;
+    }
+}
+
+/// Whether a task of `workflow_id` belongs to `scope`; no scope takes all tasks
+fn in_scope(workflow_id: Option<&str>, scope: Option<&str>) -> bool {
+    scope.is_none() || workflow_id == scope
+}
+
 /// Task execution result
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TaskResult {
     pub task_id: TaskId,
+    /// Workflow module the task ran, when known
+    pub module_id: Option<u32>,
     pub success: bool,
     pub outputs: Option<OutputPorts>,
     pub error: Option<String>,
     pub execution_time: std::time::Duration,
+    /// Non-finite values per output port, empty unless the executor audits outputs
+    pub nonfinite: BTreeMap<String, PortAudit>,
+}
+
+/// Start or end of a task, see `TaskExecutor::subscribe`
+#[derive(Debug, Clone)]
+pub enum TaskEvent {
+    Started { task_id: TaskId, module_id: u32, workflow_id: Option<String>, at: std::time::Instant },
+    Finished { task_id: TaskId, module_id: u32, workflow_id: Option<String>, at: std::time::Instant },
+}
+
+impl TaskEvent {
+    pub fn workflow_id(&self) -> Option<&str> {
+        match self {
+            TaskEvent::Started { workflow_id, .. } | TaskEvent::Finished { workflow_id, .. } => workflow_id.as_deref(),
+        }
+    }
+}
+
+/// Limits of one workflow within the resources all workflows share
+///
+/// Workflows without limits compete for the executor's concurrency slots
+/// and the node's memory on equal terms.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WorkflowLimits {
+    /// Tasks of the workflow running at once
+    pub max_concurrent: Option<usize>,
+    /// Bytes the expected peaks of the workflow's running tasks may add up to;
+    /// only enforced with memory admission
+    pub memory: Option<usize>,
+}
+
+impl WorkflowLimits {
+    pub fn with_max_concurrent(mut self, tasks: usize) -> Self {
+        self.max_concurrent = Some(tasks);
+        self
+    }
+
+    pub fn with_memory(mut self, bytes: usize) -> Self {
+        self.memory = Some(bytes);
+        self
+    }
+}
+
+/// Limits and state of a workflow running on the executor
+struct WorkflowScope {
+    limits: WorkflowLimits,
+    /// Concurrency slots of the workflow, taken besides the executor's
+    slots: Option<Arc<Semaphore>>,
+    cancelled: bool,
+}
+
+impl WorkflowScope {
+    fn new(limits: WorkflowLimits) -> Self {
+        Self {
+            slots: limits.max_concurrent.map(|tasks| Arc::new(Semaphore::new(tasks.max(1)))),
+            limits,
+            cancelled: false,
+        }
+    }
+}
+
+/// What the executor does next
+enum Dispatch {
+    Task(TaskId),
+    /// Ready tasks wait for memory to free up
+    Deferred,
+    /// Nothing is ready
+    Idle,
 }
 
+/// Results of finished tasks by workflow, tasks without one under `None`
+type WorkflowResults = HashMap<Option<String>, HashMap<TaskId, TaskResult>>;
+
+/// Workflow of a dispatched task and the handle aborting it
+type DispatchedTask = (Option<String>, tokio::task::AbortHandle);
+
 /// Task executor for running tasks concurrently
+///
+/// Several workflows can run on one executor at once, each with
+/// `execute_workflow`. They share the concurrency slots, which are handed
+/// out first come first served, and the memory budget; `WorkflowLimits`
+/// keep one workflow from taking all of either.
 pub struct TaskExecutor {
-    graph: RwLock<TaskGraph>,
-    results: RwLock<HashMap<TaskId, TaskResult>>,
+    graph: Arc<RwLock<TaskGraph>>,
+    /// Results by workflow, tasks without one under `None`
+    results: Arc<RwLock<WorkflowResults>>,
+    admission: Option<Arc<MemoryAdmission>>,
+    /// Dispatched tasks in start order, tracked only with admission control
+    running: Arc<parking_lot::Mutex<Vec<AdmittedTask>>>,
+    /// Dispatched tasks that have not finished, with their workflow
+    dispatched: Arc<parking_lot::Mutex<HashMap<TaskId, DispatchedTask>>>,
+    workflows: parking_lot::Mutex<HashMap<String, WorkflowScope>>,
+    /// Memory monitor shared by concurrent runs, with the number of runs using it
+    monitor: parking_lot::Mutex<Option<(usize, tokio::task::JoinHandle<()>)>>,
+    /// Signalled when a task finishes or is cancelled
+    finished: Arc<Notify>,
+    events: broadcast::Sender<TaskEvent>,
 }
 
 impl TaskExecutor {
     pub fn new(max_concurrent: usize) -> Self {
         Self {
-            graph: RwLock::new(TaskGraph::new(max_concurrent)),
-            results: RwLock::new(HashMap::new()),
+            graph: Arc::new(RwLock::new(TaskGraph::new(max_concurrent))),
+            results: Arc::new(RwLock::new(HashMap::new())),
+            admission: None,
+            running: Arc::new(parking_lot::Mutex::new(Vec::new())),
+            dispatched: Arc::new(parking_lot::Mutex::new(HashMap::new())),
+            workflows: parking_lot::Mutex::new(HashMap::new()),
+            monitor: parking_lot::Mutex::new(None),
+            finished: Arc::new(Notify::new()),
+            events: broadcast::channel(256).0,
+        }
+    }
+
+    /// Dispatch tasks only when their expected peak memory fits the node
+    ///
+    /// While tasks run, the newest is cancelled whenever available memory
+    /// drops below the budget's critical level.
+    pub fn with_memory_admission(mut self, admission: Arc<MemoryAdmission>) -> Self {
+        self.admission = Some(admission);
+        self
+    }
+
+    pub fn memory_admission(&self) -> Option<&Arc<MemoryAdmission>> {
+        self.admission.as_ref()
+    }
+
+    /// Receive an event when a task starts and when it finishes
+    pub fn subscribe(&self) -> broadcast::Receiver<TaskEvent> {
+        self.events.subscribe()
+    }
+
+    /// Set the limits of a workflow before it runs
+    pub fn set_workflow_limits(&self, workflow_id: &str, limits: WorkflowLimits) {
+        self.workflows.lock().insert(workflow_id.to_string(), WorkflowScope::new(limits));
+    }
+
+    fn is_cancelled(&self, workflow_id: &str) -> bool {
+        self.workflows.lock().get(workflow_id).is_some_and(|w| w.cancelled)
+    }
+
+    /// Whether a dispatched task of `scope` has not finished yet
+    fn is_running(&self, scope: Option<&str>) -> bool {
+        self.dispatched.lock().values().any(|(workflow_id, _)| in_scope(workflow_id.as_deref(), scope))
+    }
+
+    async fn next_task(&self, scope: Option<&str>) -> Dispatch {
+        let mut graph = self.graph.write().await;
+        let Some(admission) = &self.admission else {
+            return graph.get_ready_task_where(|t| in_scope(t.workflow_id.as_deref(), scope))
+                .map_or(Dispatch::Idle, Dispatch::Task);
+        };
+        if !graph.has_ready_in(scope) {
+            return Dispatch::Idle;
+        }
+
+        let (reserved, reserved_in_scope, idle) = {
+            let running = self.running.lock();
+            let in_scope_reserved = running.iter()
+                .filter(|t| in_scope(t.workflow_id.as_deref(), scope))
+                .map(AdmittedTask::outstanding)
+                .sum::<usize>();
+            (running.iter().map(AdmittedTask::outstanding).sum::<usize>(), in_scope_reserved, running.is_empty())
+        };
+        let mut headroom = admission.headroom(reserved);
+        if let Some(limit) = scope.and_then(|id| self.workflows.lock().get(id).and_then(|w| w.limits.memory)) {
+            headroom = headroom.min(limit.saturating_sub(reserved_in_scope));
+        }
+        let estimate = |task: &Task| admission.estimate(task.module_type(), task.peak_memory);
+        // Smaller tasks first, so tasks too large for now wait while as many as possible run
+        let task_id = graph.get_smallest_ready_task_where(
+            |t| in_scope(t.workflow_id.as_deref(), scope) && estimate(t) <= headroom,
+            estimate,
+        );
+        if let Some(task_id) = task_id {
+            return Dispatch::Task(task_id);
+        }
+        if !idle {
+            return Dispatch::Deferred;
+        }
+        // Nothing running will free memory, so waiting would stall forever
+        let task_id = graph.get_smallest_ready_task_where(|t| in_scope(t.workflow_id.as_deref(), scope), estimate);
+        if let Some(task) = task_id.and_then(|id| graph.get_task(id)) {
+            tracing::warn!(
+                "Dispatching task {:?} ({}) although its expected {} bytes exceed the available memory",
+                task.id, task.module_type(), estimate(task)
+            );
+        }
+        task_id.map_or(Dispatch::Idle, Dispatch::Task)
+    }
+
+    /// Sample the resident set for running tasks and cancel the newest when memory runs out
+    fn spawn_memory_monitor(&self, admission: Arc<MemoryAdmission>) -> tokio::task::JoinHandle<()> {
+        let running = self.running.clone();
+        let dispatched = self.dispatched.clone();
+        let finished = self.finished.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MEMORY_SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let resident = admission.resident();
+                let mut running = running.lock();
+                for task in running.iter_mut() {
+                    task.peak = task.peak.max(resident);
+                }
+                if admission.is_critical() {
+                    // Cancelled runs are not recorded as peaks; they did not finish
+                    if let Some(newest) = running.pop() {
+                        tracing::warn!(
+                            "Node memory critical ({} bytes available); cancelling task {:?} ({})",
+                            admission.provider().available(), newest.task_id, newest.module_type
+                        );
+                        newest.abort.abort();
+                        dispatched.lock().remove(&newest.task_id);
+                        finished.notify_waiters();
+                    }
+                }
+            }
+        })
+    }
+
+    /// Start the memory monitor unless a concurrent run has started it
+    ///
+    /// The monitor stops when the last guard is dropped, also when a run is
+    /// dropped midway, e.g. on a timeout.
+    fn acquire_memory_monitor(&self) -> MonitorGuard<'_> {
+        if let Some(admission) = &self.admission {
+            let mut monitor = self.monitor.lock();
+            match monitor.as_mut() {
+                Some((users, _)) => *users += 1,
+                None => *monitor = Some((1, self.spawn_memory_monitor(admission.clone()))),
+            }
+        }
+        MonitorGuard(self)
+    }
+
+    /// Stop the memory monitor once the last run using it is done
+    fn release_memory_monitor(&self) {
+        let mut monitor = self.monitor.lock();
+        if let Some((users, handle)) = monitor.as_mut() {
+            *users -= 1;
+            if *users == 0 {
+                handle.abort();
+                *monitor = None;
+            }
         }
     }
 
@@ -201,98 +809,349 @@ impl TaskExecutor {
         graph.add_task(task);
     }
 
+    /// Change a task's priority; see `TaskGraph::set_priority`
+    pub async fn set_priority(&self, task_id: TaskId, priority: TaskPriority) {
+        self.graph.write().await.set_priority(task_id, priority);
+    }
+
     /// Execute all tasks in the graph
+    ///
+    /// Runs tasks of every workflow; workflows run side by side each use
+    /// `execute_workflow` instead.
     pub async fn execute_all(&self) -> Result<Vec<TaskResult>, crate::Error> {
+        self.execute(None).await
+    }
+
+    /// Execute the tasks of one workflow, alongside any other workflow's
+    ///
+    /// Returns exactly the results of this workflow's tasks. Once they are
+    /// done, the tasks, their results and the workflow's limits are dropped
+    /// from the executor.
+    pub async fn execute_workflow(&self, workflow_id: &str) -> Result<Vec<TaskResult>, crate::Error> {
+        let results = self.execute(Some(workflow_id)).await;
+        self.remove_workflow(workflow_id).await;
+        results
+    }
+
+    /// Drop a workflow's tasks, results and limits, e.g. after a run was abandoned
+    pub async fn remove_workflow(&self, workflow_id: &str) {
+        self.graph.write().await.remove_workflow(workflow_id);
+        self.results.write().await.remove(&Some(workflow_id.to_string()));
+        self.workflows.lock().remove(workflow_id);
+    }
+
+    /// Cancel a workflow's tasks, aborting those running
+    ///
+    /// Tasks of other workflows keep running, and the slots and memory the
+    /// cancelled ones held go to them.
+    pub async fn cancel_workflow(&self, workflow_id: &str) {
+        let mut graph = self.graph.write().await;
+        if !graph.contains_workflow(workflow_id) {
+            return;
+        }
+        self.workflows.lock()
+            .entry(workflow_id.to_string())
+            .or_insert_with(|| WorkflowScope::new(WorkflowLimits::default()))
+            .cancelled = true;
+        graph.cancel_workflow(workflow_id);
+
+        let aborted: Vec<TaskId> = {
+            let dispatched = self.dispatched.lock();
+            dispatched.iter()
+                .filter(|(_, (id, _))| id.as_deref() == Some(workflow_id))
+                .map(|(task_id, (_, abort))| {
+                    abort.abort();
+                    *task_id
+                })
+                .collect()
+        };
+        // Aborted tasks do not get to clean up after themselves
+        self.running.lock().retain(|t| !aborted.contains(&t.task_id));
+        self.dispatched.lock().retain(|task_id, _| !aborted.contains(task_id));
+        self.finished.notify_waiters();
+    }
+
+    /// Dispatch the ready tasks of `scope` until none is left, then collect their results
+    async fn execute(&self, scope: Option<&str>) -> Result<Vec<TaskResult>, crate::Error> {
+        let mut handles = Vec::new();
+        let monitor = self.acquire_memory_monitor();
+        let dispatched = self.dispatch(scope, &mut handles).await;
+
+        // Wait for all tasks to complete; a panicked task fails on its own
+        let (task_ids, handles): (Vec<TaskId>, Vec<_>) = handles.into_iter().unzip();
+        let mut results = Vec::with_capacity(handles.len());
+        for (task_id, joined) in task_ids.into_iter().zip(join_all(handles).await) {
+            let result = match joined {
+                Ok(result) => result,
+                Err(e) if e.is_cancelled() => {
+                    let workflow_id = self.graph.read().await.get_task(task_id).and_then(|t| t.workflow_id.clone());
+                    let cancelled = workflow_id.is_some_and(|id| self.is_cancelled(&id));
+                    let reason = if cancelled { "workflow cancelled" } else { "node memory critical" };
+                    let result = TaskResult {
+                        task_id,
+                        module_id: self.graph.read().await.get_task(task_id).map(|t| t.module_id()),
+                        success: false,
+                        outputs: None,
+                        error: Some(crate::Error::Cancelled(reason.to_string()).to_string()),
+                        execution_time: std::time::Duration::ZERO,
+                        nonfinite: BTreeMap::new(),
+                    };
+                    let blocked = {
+                        let mut graph = self.graph.write().await;
+                        graph.mark_cancelled(task_id);
+                        graph.take_blocked_sinks(task_id, true)
+                    };
+                    tell_blocked(blocked, task_id).await;
+                    result
+                }
+                Err(e) => {
+                    let error = if e.is_panic() {
+                        crate::compute::panic_message(e.into_panic().as_ref())
+                    } else {
+                        format!("Task execution failed: {}", e)
+                    };
+                    tracing::warn!("Task {:?} failed: {}", task_id, error);
+                    let result = TaskResult {
+                        task_id,
+                        module_id: self.graph.read().await.get_task(task_id).map(|t| t.module_id()),
+                        success: false,
+                        outputs: None,
+                        error: Some(error),
+                        execution_time: std::time::Duration::ZERO,
+                        nonfinite: BTreeMap::new(),
+                    };
+                    let blocked = {
+                        let mut graph = self.graph.write().await;
+                        graph.mark_failed(task_id);
+                        graph.take_blocked_sinks(task_id, true)
+                    };
+                    tell_blocked(blocked, task_id).await;
+                    result
+                }
+            };
+            results.push(result);
+        }
+
+        drop(monitor);
+        if let Some(admission) = &self.admission {
+            if let Err(e) = admission.save_history().await {
+                tracing::warn!("Failed to save observed peak memory: {}", e);
+            }
+        }
+
+        dispatched?;
+        Ok(results)
+    }
+
+    /// Spawn the tasks of `scope` as they become ready
+    ///
+    /// Returns once none is ready and none is running that could make one
+    /// ready, or once the workflow is cancelled.
+    async fn dispatch(
+        &self,
+        scope: Option<&str>,
+        handles: &mut Vec<(TaskId, tokio::task::JoinHandle<TaskResult>)>,
+    ) -> Result<(), crate::Error> {
         let semaphore = {
             let graph = self.graph.read().await;
             graph.semaphore()
         };
-
-        let mut handles = Vec::new();
+        let slots = scope.and_then(|id| self.workflows.lock().get(id).and_then(|w| w.slots.clone()));
+        let no_permit = |_| crate::Error::Module("Failed to acquire execution permit".to_string());
 
         loop {
-            let permit = semaphore.acquire().await
-                .map_err(|_| crate::Error::Module("Failed to acquire execution permit".to_string()))?;
-
-            let task_id = {
-                let mut graph = self.graph.write().await;
-                match graph.get_ready_task() {
-                    Some(id) => id,
-                    None => break, // No more ready tasks
+            if scope.is_some_and(|id| self.is_cancelled(id)) {
+                break;
+            }
+            // The workflow's own slot first, so it does not hold a shared one while waiting
+            let slot = match &slots {
+                Some(slots) => Some(slots.clone().acquire_owned().await.map_err(no_permit)?),
+                None => None,
+            };
+            let permit = semaphore.clone().acquire_owned().await.map_err(no_permit)?;
+
+            let task_id = match self.next_task(scope).await {
+                Dispatch::Task(id) => id,
+                Dispatch::Deferred => {
+                    drop((permit, slot));
+                    let _ = tokio::time::timeout(MEMORY_SAMPLE_INTERVAL, self.finished.notified()).await;
+                    continue;
+                }
+                Dispatch::Idle => {
+                    drop((permit, slot));
+                    // Running tasks make their dependents ready when they finish
+                    if !self.is_running(scope) && !self.graph.read().await.has_ready_in(scope) {
+                        break;
+                    }
+                    let _ = tokio::time::timeout(MEMORY_SAMPLE_INTERVAL, self.finished.notified()).await;
+                    continue;
                 }
             };
+            let (workflow_id, admitted) = {
+                let graph = self.graph.read().await;
+                let task = graph.get_task(task_id);
+                let admitted = match (&self.admission, task) {
+                    (Some(admission), Some(task)) => Some((
+                        task.module_type().to_string(),
+                        admission.estimate(task.module_type(), task.peak_memory),
+                        admission.resident(),
+                    )),
+                    _ => None,
+                };
+                (task.and_then(|t| t.workflow_id.clone()), admitted)
+            };
 
             let graph_clone = self.graph.clone();
             let results_clone = self.results.clone();
+            let admission = self.admission.clone();
+            let running = self.running.clone();
+            let dispatched = self.dispatched.clone();
+            let finished = self.finished.clone();
+            let events = self.events.clone();
+            let result_workflow = workflow_id.clone();
 
-            let handle = tokio::spawn(async move {
-                let start_time = std::time::Instant::now();
+            // The locks are held until the task is tracked, so it cannot finish before
+            let handle = {
+                let mut tracked = admitted.is_some().then(|| self.running.lock());
+                let mut tracked_dispatch = self.dispatched.lock();
+                let handle = tokio::spawn(async move {
+                    let start_time = std::time::Instant::now();
+                    let mut permits = Some((permit, slot));
 
-                // Get task
-                let task = {
-                    let graph = graph_clone.read().await;
-                    graph.get_task(task_id).cloned()
-                };
+                    // What the run needs, taken so the graph is not locked while it runs
+                    let task = {
+                        let graph = graph_clone.read().await;
+                        graph.get_task(task_id).map(|task| (
+                            task.module.clone(),
+                            task.context.clone(),
+                            task.inputs.clone(),
+                            task.router.clone(),
+                            task.output_check.clone(),
+                            task.reused_outputs.clone(),
+                            task.remote_outputs.clone(),
+                            task.output_sink.clone(),
+                        ))
+                    };
 
-                let result = if let Some(mut task) = task {
-                    // Update status to running
-                    task.status = TaskStatus::Running;
+                    let result = if let Some((module, context, inputs, router, check, reused, remote, sink)) = task {
+                        let module_id = context.module_id;
+                        // No subscribers is fine
+                        let _ = events.send(TaskEvent::Started { task_id, module_id, workflow_id: result_workflow.clone(), at: start_time });
 
-                    // Execute task (placeholder - would call actual module)
-                    let success = true; // Placeholder
-                    let outputs = None; // Placeholder
-                    let error = None; // Placeholder
+                        // Reused outputs passed the check when they were produced
+                        let outputs = match (reused, remote) {
+                            (Some(outputs), _) => Ok(outputs),
+                            (None, Some(source)) => {
+                                // Waiting for outputs from elsewhere must not keep local tasks from running
+                                permits.take();
+                                source().await
+                            }
+                            (None, None) => run_module(&module, &context, &inputs, router, &results_clone, &result_workflow).await
+                                .and_then(|outputs| match &check {
+                                    Some(check) => check(outputs),
+                                    None => Ok(outputs),
+                                }),
+                        };
+                        if let Err(e) = &outputs {
+                            tracing::warn!("Task {:?} ({} {}) failed: {}", task_id, module.info().name, module_id, e);
+                        }
+                        if let Some(sink) = sink {
+                            sink(outputs.as_ref().map(Clone::clone).map_err(ToString::to_string)).await;
+                        }
+                        let _ = events.send(TaskEvent::Finished { task_id, module_id, workflow_id: result_workflow.clone(), at: std::time::Instant::now() });
 
-                    TaskResult {
-                        task_id,
-                        success,
-                        outputs,
-                        error,
-                        execution_time: start_time.elapsed(),
-                    }
-                } else {
-                    TaskResult {
-                        task_id,
-                        success: false,
-                        outputs: None,
-                        error: Some("Task not found".to_string()),
-                        execution_time: start_time.elapsed(),
-                    }
-                };
+                        TaskResult {
+                            task_id,
+                            module_id: Some(module_id),
+                            success: outputs.is_ok(),
+                            error: outputs.as_ref().err().map(ToString::to_string),
+                            outputs: outputs.ok(),
+                            execution_time: start_time.elapsed(),
+                            nonfinite: BTreeMap::new(),
+                        }
+                    } else {
+                        TaskResult {
+                            task_id,
+                            module_id: None,
+                            success: false,
+                            outputs: None,
+                            error: Some("Task not found".to_string()),
+                            execution_time: start_time.elapsed(),
+                            nonfinite: BTreeMap::new(),
+                        }
+                    };
 
-                // Store result
-                {
-                    let mut results = results_clone.write().await;
-                    results.insert(task_id, result.clone());
-                }
+                    // Store result with those of its workflow
+                    {
+                        let mut results = results_clone.write().await;
+                        results.entry(result_workflow).or_default().insert(task_id, result.clone());
+                    }
 
-                // Mark task as completed
-                {
-                    let mut graph = graph_clone.write().await;
-                    graph.mark_completed(task_id);
-                }
+                    // A failed task leaves its dependents blocked
+                    let blocked = {
+                        let mut graph = graph_clone.write().await;
+                        if result.success {
+                            graph.mark_completed(task_id);
+                            Vec::new()
+                        } else {
+                            graph.mark_failed(task_id);
+                            graph.take_blocked_sinks(task_id, false)
+                        }
+                    };
+                    tell_blocked(blocked, task_id).await;
 
-                // Release permit
-                drop(permit);
+                    if let Some(admission) = &admission {
+                        let mut running = running.lock();
+                        if let Some(index) = running.iter().position(|t| t.task_id == task_id) {
+                            let mut done = running.remove(index);
+                            done.peak = done.peak.max(admission.resident());
+                            admission.record_peak(&done.module_type, done.growth());
+                        }
+                    }
+                    dispatched.lock().remove(&task_id);
+                    // Runs of other workflows may wait for slots or memory as well
+                    finished.notify_waiters();
 
-                result
-            });
+                    // Release permits
+                    drop(permits);
 
-            handles.push(handle);
-        }
+                    result
+                });
 
-        // Wait for all tasks to complete
-        let results = join_all(handles).await
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| crate::Error::Module(format!("Task execution failed: {}", e)))?;
+                if let (Some(tracked), Some((module_type, estimate, baseline))) = (tracked.as_mut(), admitted) {
+                    tracked.push(AdmittedTask {
+                        task_id,
+                        module_type,
+                        workflow_id: workflow_id.clone(),
+                        estimate,
+                        baseline,
+                        peak: baseline,
+                        abort: handle.abort_handle(),
+                    });
+                }
+                tracked_dispatch.insert(task_id, (workflow_id, handle.abort_handle()));
+                handle
+            };
 
-        Ok(results)
+            handles.push((task_id, handle));
+        }
+        Ok(())
     }
 
-    /// Get execution results
+    /// Get execution results of all workflows
     pub async fn results(&self) -> HashMap<TaskId, TaskResult> {
-        self.results.read().await.clone()
+        self.results.read().await
+            .values()
+            .flat_map(|results| results.iter().map(|(id, result)| (*id, result.clone())))
+            .collect()
+    }
+
+    /// Results of a workflow's tasks that finished so far
+    pub async fn workflow_results(&self, workflow_id: &str) -> HashMap<TaskId, TaskResult> {
+        self.results.read().await
+            .get(&Some(workflow_id.to_string()))
+            .cloned()
+            .unwrap_or_default()
     }
 
     /// Check if execution is complete
@@ -304,14 +1163,68 @@ impl TaskExecutor {
     pub async fn pending_count(&self) -> usize {
         self.graph.read().await.pending_count()
     }
+
+    /// Report tasks stuck in the ready queue to `monitor` as "tasks"
+    pub fn register_health(self: &Arc<Self>, monitor: &HealthMonitor) {
+        monitor.register("tasks", Arc::new(TaskProbe {
+            executor: Arc::downgrade(self),
+            locked_since: parking_lot::Mutex::new(None),
+        }));
+    }
+}
+
+/// Use of the memory monitor by a run, see `TaskExecutor::acquire_memory_monitor`
+struct MonitorGuard<'a>(&'a TaskExecutor);
+
+impl Drop for MonitorGuard<'_> {
+    fn drop(&mut self) {
+        self.0.release_memory_monitor();
+    }
+}
+
+/// Checks that ready tasks get dispatched
+struct TaskProbe {
+    executor: Weak<TaskExecutor>,
+    /// First check that found the task graph locked, if every check since did
+    locked_since: parking_lot::Mutex<Option<std::time::Instant>>,
+}
+
+impl HealthProbe for TaskProbe {
+    fn check(&self, config: &HealthConfig) -> Vec<Issue> {
+        let Some(executor) = self.executor.upgrade() else {
+            return Vec::new();
+        };
+        let mut locked_since = self.locked_since.lock();
+        let Ok(graph) = executor.graph.try_read() else {
+            // Held briefly all the time; only a lock that is never released is a problem
+            let locked = locked_since.get_or_insert_with(std::time::Instant::now).elapsed();
+            if locked > config.max_ready_age {
+                return vec![Issue::unhealthy(format!("task graph locked for {:?}", locked))];
+            }
+            return Vec::new();
+        };
+        *locked_since = None;
+
+        match graph.oldest_ready().map(|since| since.elapsed()) {
+            Some(waiting) if waiting > config.max_ready_age => vec![Issue::unhealthy(format!(
+                "a ready task has waited {:?} to be dispatched",
+                waiting
+            ))],
+            _ => Vec::new(),
+        }
+    }
 }
 
 /// Task builder for fluent task construction
 pub struct TaskBuilder {
-    module: Option<Arc<dyn Module>>,
+    module: Option<TaskModule>,
     context: Option<ComputeContext>,
     dependencies: Vec<TaskId>,
+    inputs: Vec<(TaskId, String, String)>,
+    router: Option<Arc<MessageRouter>>,
     priority: TaskPriority,
+    peak_memory: Option<usize>,
+    workflow_id: Option<String>,
 }
 
 impl TaskBuilder {
@@ -320,11 +1233,15 @@ impl TaskBuilder {
             module: None,
             context: None,
             dependencies: Vec::new(),
+            inputs: Vec::new(),
+            router: None,
             priority: TaskPriority::Normal,
+            peak_memory: None,
+            workflow_id: None,
         }
     }
 
-    pub fn module(mut self, module: Arc<dyn Module>) -> Self {
+    pub fn module(mut self, module: TaskModule) -> Self {
         self.module = Some(module);
         self
     }
@@ -344,13 +1261,48 @@ impl TaskBuilder {
         self
     }
 
+    /// Feed an output of another task to an input port, see `Task::with_input`
+    pub fn input(mut self, from: TaskId, from_port: &str, to_port: &str) -> Self {
+        self.inputs.push((from, from_port.to_string(), to_port.to_string()));
+        self
+    }
+
+    pub fn router(mut self, router: Arc<MessageRouter>) -> Self {
+        self.router = Some(router);
+        self
+    }
+
+    /// Declared peak memory in bytes, see `Task::peak_memory`
+    pub fn peak_memory(mut self, bytes: usize) -> Self {
+        self.peak_memory = Some(bytes);
+        self
+    }
+
+    /// Workflow the task belongs to, see `Task::workflow_id`
+    pub fn workflow(mut self, workflow_id: &str) -> Self {
+        self.workflow_id = Some(workflow_id.to_string());
+        self
+    }
+
     pub fn build(self) -> Result<Task, String> {
         let module = self.module.ok_or("Module not specified")?;
         let context = self.context.ok_or("Context not specified")?;
 
-        let task = Task::new(TaskId::default(), module, context)
+        let mut task = Task::new(TaskId::default(), module, context)
             .with_dependencies(self.dependencies)
             .with_priority(self.priority);
+        for (from, from_port, to_port) in &self.inputs {
+            task = task.with_input(*from, from_port, to_port);
+        }
+        task.router = self.router;
+        let task = match self.peak_memory {
+            Some(bytes) => task.with_peak_memory(bytes),
+            None => task,
+        };
+        let task = match &self.workflow_id {
+            Some(workflow_id) => task.with_workflow(workflow_id),
+            None => task,
+        };
 
         Ok(task)
     }
@@ -361,3 +1313,260 @@ impl Default for TaskBuilder {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::testing::modules::{ConstantField, Failing};
+
+    fn task(id: u64, dependencies: &[u64], priority: TaskPriority) -> Task {
+        Task::new(TaskId::new(id), Arc::new(VistleModule::new(Box::new(ConstantField::new(id as u32)) as Box<dyn Module>)), ComputeContext::new(id as u32, 0, 1))
+            .with_dependencies(dependencies.iter().copied().map(TaskId::new).collect())
+            .with_priority(priority)
+    }
+
+    fn effective(graph: &TaskGraph, id: u64) -> TaskPriority {
+        graph.get_task(TaskId::new(id)).unwrap().effective_priority
+    }
+
+    fn declared(graph: &TaskGraph, id: u64) -> TaskPriority {
+        graph.get_task(TaskId::new(id)).unwrap().priority
+    }
+
+    fn next(graph: &mut TaskGraph) -> Option<u64> {
+        graph.get_ready_task().map(|id| id.as_u64())
+    }
+
+    /// Reader 1 feeding filters 2 and 3, both feeding renderer 4, plus an unrelated task 5
+    fn diamond(renderer: TaskPriority) -> TaskGraph {
+        let mut graph = TaskGraph::new(4);
+        graph.add_task(task(1, &[], TaskPriority::Normal));
+        graph.add_task(task(2, &[1], TaskPriority::Low));
+        graph.add_task(task(3, &[1], TaskPriority::Normal));
+        graph.add_task(task(5, &[], TaskPriority::High));
+        graph.add_task(task(4, &[2, 3], renderer));
+        graph
+    }
+
+    #[test]
+    fn a_critical_task_boosts_its_whole_diamond() {
+        let mut graph = diamond(TaskPriority::Critical);
+        for id in 1..=4 {
+            assert_eq!(effective(&graph, id), TaskPriority::Critical, "task {}", id);
+        }
+        // Declared priorities are kept for reporting
+        assert_eq!(declared(&graph, 2), TaskPriority::Low);
+        assert_eq!(effective(&graph, 5), TaskPriority::High);
+
+        // The boosted chain runs ahead of the unrelated High task
+        assert_eq!(next(&mut graph), Some(1));
+        assert_eq!(next(&mut graph), Some(5));
+        graph.mark_completed(TaskId::new(1));
+        graph.mark_completed(TaskId::new(5));
+        assert_eq!(next(&mut graph), Some(2));
+        assert_eq!(next(&mut graph), Some(3));
+        graph.mark_completed(TaskId::new(2));
+        graph.mark_completed(TaskId::new(3));
+        assert_eq!(next(&mut graph), Some(4));
+        assert_eq!(next(&mut graph), None);
+    }
+
+    #[test]
+    fn without_a_boost_the_high_task_goes_first() {
+        let mut graph = diamond(TaskPriority::Normal);
+        assert_eq!(effective(&graph, 2), TaskPriority::Normal);
+        assert_eq!(next(&mut graph), Some(5));
+        assert_eq!(next(&mut graph), Some(1));
+    }
+
+    #[test]
+    fn dependencies_added_late_inherit_their_dependents_priority() {
+        let mut graph = TaskGraph::new(4);
+        graph.add_task(task(4, &[2, 3], TaskPriority::Critical));
+        graph.add_task(task(2, &[1], TaskPriority::Low));
+        graph.add_task(task(3, &[1], TaskPriority::Normal));
+        graph.add_task(task(1, &[], TaskPriority::Low));
+        for id in 1..=3 {
+            assert_eq!(effective(&graph, id), TaskPriority::Critical, "task {}", id);
+        }
+        assert_eq!(graph.get_task(TaskId::new(1)).unwrap().dependents.len(), 2);
+    }
+
+    #[test]
+    fn raising_boosts_and_lowering_keeps_the_boost() {
+        let mut graph = diamond(TaskPriority::Normal);
+        graph.set_priority(TaskId::new(3), TaskPriority::High);
+        assert_eq!(effective(&graph, 1), TaskPriority::High);
+        assert_eq!(effective(&graph, 2), TaskPriority::Normal);
+
+        graph.set_priority(TaskId::new(4), TaskPriority::Critical);
+        assert_eq!(effective(&graph, 2), TaskPriority::Critical);
+
+        graph.set_priority(TaskId::new(4), TaskPriority::Low);
+        assert_eq!(declared(&graph, 4), TaskPriority::Low);
+        assert_eq!(effective(&graph, 4), TaskPriority::Critical);
+        assert_eq!(effective(&graph, 1), TaskPriority::Critical);
+    }
+
+    #[test]
+    fn completed_dependencies_are_not_boosted() {
+        let mut graph = TaskGraph::new(4);
+        graph.add_task(task(1, &[], TaskPriority::Low));
+        assert_eq!(next(&mut graph), Some(1));
+        graph.mark_completed(TaskId::new(1));
+        graph.add_task(task(2, &[1], TaskPriority::Critical));
+        assert_eq!(effective(&graph, 1), TaskPriority::Low);
+        assert_eq!(next(&mut graph), Some(2));
+    }
+
+    fn workflow_task(id: u64, dependencies: &[u64], workflow_id: &str) -> Task {
+        task(id, dependencies, TaskPriority::Normal).with_workflow(workflow_id)
+    }
+
+    /// Workflow "a" is a chain of 1 to 3, "b" a diamond of 11 to 14
+    async fn two_workflows() -> Arc<TaskExecutor> {
+        let executor = Arc::new(TaskExecutor::new(2));
+        for (id, dependencies) in [(1, vec![]), (2, vec![1]), (3, vec![2])] {
+            executor.add_task(workflow_task(id, &dependencies, "a")).await;
+        }
+        for (id, dependencies) in [(11, vec![]), (12, vec![11]), (13, vec![11]), (14, vec![12, 13])] {
+            executor.add_task(workflow_task(id, &dependencies, "b")).await;
+        }
+        executor
+    }
+
+    fn ids(results: &[TaskResult]) -> Vec<u64> {
+        let mut ids: Vec<u64> = results.iter().map(|r| r.task_id.as_u64()).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[tokio::test]
+    async fn results_carry_outputs_and_failures_block_dependents() {
+        let executor = TaskExecutor::new(2);
+        executor.add_task(task(1, &[], TaskPriority::Normal)).await;
+        let failing = Arc::new(VistleModule::new(Box::new(Failing::new(2)) as Box<dyn Module>));
+        executor.add_task(
+            Task::new(TaskId::new(2), failing, ComputeContext::new(2, 0, 1)).with_input(TaskId::new(1), "data_out", "data_in"),
+        ).await;
+        executor.add_task(task(3, &[2], TaskPriority::Normal)).await;
+
+        let results = executor.execute_all().await.unwrap();
+        assert_eq!(ids(&results), vec![1, 2]);
+        let result = |id| results.iter().find(|r| r.task_id == TaskId::new(id)).unwrap();
+        assert!(result(1).success);
+        assert_eq!(result(1).outputs.as_ref().unwrap()["data_out"].len(), 1);
+        assert!(!result(2).success);
+        assert!(result(2).error.as_deref().unwrap().contains("failed as asked"));
+        // Task 3 never ran
+        assert_eq!(executor.pending_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn outputs_rejected_by_the_check_fail_the_task() {
+        let executor = TaskExecutor::new(2);
+        let reject: OutputCheck = Arc::new(|_| Err(crate::Error::Compute("invalid objects".to_string())));
+        executor.add_task(task(1, &[], TaskPriority::Normal).with_output_check(reject)).await;
+        executor.add_task(task(2, &[1], TaskPriority::Normal)).await;
+
+        let results = executor.execute_all().await.unwrap();
+        assert_eq!(ids(&results), vec![1]);
+        assert!(!results[0].success);
+        assert!(results[0].outputs.is_none());
+        assert!(results[0].error.as_deref().unwrap().contains("invalid objects"));
+    }
+
+    #[tokio::test]
+    async fn concurrent_workflows_each_get_only_their_own_results() {
+        let executor = two_workflows().await;
+        let (a, b) = tokio::join!(executor.execute_workflow("a"), executor.execute_workflow("b"));
+        let (a, b) = (a.unwrap(), b.unwrap());
+
+        assert_eq!(ids(&a), vec![1, 2, 3]);
+        assert_eq!(ids(&b), vec![11, 12, 13, 14]);
+        assert!(a.iter().chain(&b).all(|r| r.success));
+        // Both workflows are dropped from the executor once done
+        assert!(executor.results().await.is_empty());
+        assert_eq!(executor.pending_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn cancelling_one_workflow_leaves_the_other_alone() {
+        let executor = two_workflows().await;
+        executor.cancel_workflow("a").await;
+        let (a, b) = tokio::join!(executor.execute_workflow("a"), executor.execute_workflow("b"));
+
+        assert!(a.unwrap().iter().all(|r| !r.success));
+        let b = b.unwrap();
+        assert_eq!(ids(&b), vec![11, 12, 13, 14]);
+        assert!(b.iter().all(|r| r.success));
+
+        // Cancelling a finished or unknown workflow is a no-op
+        executor.cancel_workflow("b").await;
+        executor.cancel_workflow("c").await;
+        executor.add_task(workflow_task(21, &[], "b")).await;
+        assert_eq!(ids(&executor.execute_workflow("b").await.unwrap()), vec![21]);
+    }
+
+    #[test]
+    fn the_smallest_ready_task_of_the_highest_priority_goes_first() {
+        let mut graph = TaskGraph::new(4);
+        for (id, bytes) in [(1, 300), (2, 200), (3, 100), (4, 100)] {
+            graph.add_task(task(id, &[], TaskPriority::Normal).with_peak_memory(bytes));
+        }
+        graph.add_task(task(5, &[], TaskPriority::High).with_peak_memory(500));
+        let size = |t: &Task| t.peak_memory.unwrap_or(0);
+        let fits = |t: &Task| size(t) <= 250;
+        let mut next_fitting = || graph.get_smallest_ready_task_where(fits, size).map(|id| id.as_u64());
+
+        // 5 outranks the rest but does not fit; 3 and 4 tie, 3 was ready first
+        assert_eq!(next_fitting(), Some(3));
+        assert_eq!(next_fitting(), Some(4));
+        assert_eq!(next_fitting(), Some(2));
+        assert_eq!(next_fitting(), None);
+        assert_eq!(graph.get_smallest_ready_task_where(|_| true, size).map(|id| id.as_u64()), Some(5));
+    }
+
+    #[test]
+    fn effective_priority_is_monotone_along_dependency_edges() {
+        const PRIORITIES: [TaskPriority; 4] = [TaskPriority::Low, TaskPriority::Normal, TaskPriority::High, TaskPriority::Critical];
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move |n: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % n
+        };
+
+        for _ in 0..50 {
+            // Random DAG: each task depends on some tasks with smaller ids
+            let count = 2 + random(14);
+            let mut tasks: Vec<Task> = (0..count)
+                .map(|id| {
+                    let dependencies: Vec<u64> = (0..id).filter(|_| random(3) == 0).collect();
+                    task(id, &dependencies, PRIORITIES[random(4) as usize])
+                })
+                .collect();
+            // Added in random order
+            let mut graph = TaskGraph::new(4);
+            while !tasks.is_empty() {
+                let index = random(tasks.len() as u64) as usize;
+                graph.add_task(tasks.swap_remove(index));
+            }
+            for _ in 0..5 {
+                graph.set_priority(TaskId::new(random(count)), PRIORITIES[random(4) as usize]);
+            }
+
+            for id in 0..count {
+                let task = graph.get_task(TaskId::new(id)).unwrap();
+                assert!(task.effective_priority >= task.priority);
+                for dep in &task.dependencies {
+                    let dependency = graph.get_task(*dep).unwrap();
+                    assert!(
+                        dependency.effective_priority >= task.effective_priority,
+                        "task {} at {:?} depends on {} at {:?}",
+                        id, task.effective_priority, dep.as_u64(), dependency.effective_priority
+                    );
+                }
+            }
+        }
+    }
+}
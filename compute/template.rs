@@ -0,0 +1,428 @@
+//! Workflow templates with user-supplied inputs
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::compute::{LoadWarnings, WorkflowSpec};
+
+/// Extension of template files discovered by `TemplateRegistry::scan_dir`
+pub const TEMPLATE_EXTENSION: &str = "vwt";
+
+/// Start of a placeholder replaced by the value of an input, closed by `}`
+const PLACEHOLDER: &str = "{input.";
+
+/// Template file as read, before its workflow is migrated
+#[derive(Deserialize)]
+struct TemplateFile {
+    spec: Value,
+    inputs: Vec<TemplateInput>,
+}
+
+/// Input a user fills in when instantiating a template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateInput {
+    pub id: String,
+    pub description: String,
+    /// Module id and parameter name receiving the value
+    pub target: (u32, String),
+    pub required: bool,
+    pub default: Option<String>,
+}
+
+impl TemplateInput {
+    pub fn new(id: &str, description: &str, module_id: u32, parameter: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            description: description.to_string(),
+            target: (module_id, parameter.to_string()),
+            required: true,
+            default: None,
+        }
+    }
+
+    /// Make the input optional, falling back to a default value
+    pub fn with_default(mut self, default: &str) -> Self {
+        self.required = false;
+        self.default = Some(default.to_string());
+        self
+    }
+}
+
+/// A workflow with a list of inputs left for the user to fill in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowTemplate {
+    pub spec: WorkflowSpec,
+    pub inputs: Vec<TemplateInput>,
+}
+
+impl WorkflowTemplate {
+    pub fn new(spec: WorkflowSpec) -> Self {
+        Self {
+            spec,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn with_input(mut self, input: TemplateInput) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.spec.id
+    }
+
+    /// Check that every input targets an existing module
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        for input in &self.inputs {
+            if !self.spec.modules.iter().any(|m| m.id == input.target.0) {
+                return Err(crate::Error::Config(format!(
+                    "Template input {} targets unknown module {}",
+                    input.id, input.target.0
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Produce a ready-to-run workflow from user-supplied input values
+    ///
+    /// Values are written to their target parameters, and `{input.<id>}`
+    /// placeholders in any parameter (e.g. output filename patterns) are
+    /// replaced. All missing required inputs are reported in one error.
+    pub fn instantiate(&self, values: HashMap<String, String>) -> Result<WorkflowSpec, crate::Error> {
+        self.validate()?;
+
+        if let Some(unknown) = values.keys().find(|k| !self.inputs.iter().any(|i| &i.id == *k)) {
+            return Err(crate::Error::Config(format!("Unknown template input {}", unknown)));
+        }
+
+        let missing: Vec<&TemplateInput> = self.inputs.iter()
+            .filter(|i| i.required && !values.contains_key(&i.id))
+            .collect();
+        if !missing.is_empty() {
+            let list = missing.iter()
+                .map(|i| format!("{} ({})", i.id, i.description))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(crate::Error::Config(format!(
+                "Template {} is missing required inputs: {}",
+                self.spec.id, list
+            )));
+        }
+
+        let resolved: HashMap<&str, String> = self.inputs.iter()
+            .filter_map(|i| {
+                values.get(&i.id)
+                    .cloned()
+                    .or_else(|| i.default.clone())
+                    .map(|v| (i.id.as_str(), v))
+            })
+            .collect();
+
+        // Placeholders in the template, not in the values filled in
+        let mut spec = self.spec.clone();
+        for module in &mut spec.modules {
+            for value in module.parameters.values_mut() {
+                if value.contains(PLACEHOLDER) {
+                    *value = substitute(value, &resolved);
+                }
+            }
+        }
+
+        for input in &self.inputs {
+            if let Some(value) = resolved.get(input.id.as_str()) {
+                let (module_id, parameter) = &input.target;
+                if let Some(module) = spec.modules.iter_mut().find(|m| m.id == *module_id) {
+                    module.parameters.insert(parameter.clone(), value.clone());
+                }
+            }
+        }
+
+        Ok(spec)
+    }
+
+    /// Load a template from a JSON file, like `WorkflowSpec::load` its workflow
+    ///
+    /// The workflow is migrated from older formats, following renamed
+    /// parameters with the inputs targeting them, and its paths resolve
+    /// against the file's directory. What could not be migrated is logged,
+    /// see `load_with_warnings`.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let (template, warnings) = Self::load_with_warnings(path.as_ref()).await?;
+        for warning in warnings.iter() {
+            tracing::warn!("Template {}: {}", path.as_ref().display(), warning);
+        }
+        Ok(template)
+    }
+
+    /// Load a template like `load`, returning what was dropped while migrating it
+    pub async fn load_with_warnings(path: impl AsRef<Path>) -> Result<(Self, LoadWarnings), crate::Error> {
+        let path = path.as_ref();
+        let text = crate::util::io::read_text(path).await?;
+        let file: TemplateFile = serde_json::from_str(&text)
+            .map_err(|e| crate::Error::Config(format!("Invalid template {}: {}", path.display(), e)))?;
+
+        let mut value = file.spec;
+        let replaced = mark_targets(&mut value, &file.inputs);
+        let (mut spec, mut warnings) = WorkflowSpec::from_value(path, value)?;
+        let inputs = follow_targets(&mut spec, file.inputs, replaced, &mut warnings);
+
+        let path = tokio::fs::canonicalize(path).await?;
+        spec.base_dir = path.parent().map(Path::to_path_buf);
+        let template = Self { spec, inputs };
+        template.validate()?;
+        Ok((template, warnings))
+    }
+
+    /// Save the template as JSON
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize template: {}", e)))?;
+        crate::util::io::write_text(path, &text).await
+    }
+}
+
+/// Value standing in for the target parameter of input `index` while migrating
+fn target_marker(index: usize) -> String {
+    format!("\u{0}template input {}", index)
+}
+
+/// Set the target parameter of every input to its marker, returning the values replaced
+fn mark_targets(spec: &mut Value, inputs: &[TemplateInput]) -> Vec<Option<Value>> {
+    inputs.iter().enumerate().map(|(index, input)| {
+        let (module_id, parameter) = &input.target;
+        let module = spec.get_mut("modules")
+            .and_then(Value::as_array_mut)
+            .and_then(|modules| modules.iter_mut().find(|m| m.get("id").and_then(Value::as_u64) == Some(*module_id as u64)))
+            .and_then(Value::as_object_mut)?;
+        let parameters = module.entry("parameters").or_insert_with(|| Value::Object(Default::default()));
+        parameters.as_object_mut()?.insert(parameter.clone(), Value::String(target_marker(index)))
+    }).collect()
+}
+
+/// Point inputs at the parameters their markers were migrated to and put back the replaced values
+fn follow_targets(
+    spec: &mut WorkflowSpec,
+    mut inputs: Vec<TemplateInput>,
+    replaced: Vec<Option<Value>>,
+    warnings: &mut LoadWarnings,
+) -> Vec<TemplateInput> {
+    for (index, (input, replaced)) in inputs.iter_mut().zip(replaced).enumerate() {
+        let marker = target_marker(index);
+        let Some(module) = spec.modules.iter_mut().find(|m| m.id == input.target.0) else {
+            continue;
+        };
+        let Some(parameter) = module.parameters.iter().find(|(_, v)| **v == marker).map(|(k, _)| k.clone()) else {
+            warnings.push(Some(module.id), format!(
+                "template input {} targets parameter {}, which no longer exists",
+                input.id, input.target.1
+            ));
+            continue;
+        };
+        match replaced.as_ref().and_then(Value::as_str) {
+            Some(value) => module.parameters.insert(parameter.clone(), value.to_string()),
+            None => module.parameters.remove(&parameter),
+        };
+        input.target.1 = parameter;
+    }
+    inputs
+}
+
+/// Replace the placeholders of known inputs in one pass over `text`
+///
+/// Substituted values are not scanned again, and placeholders of unknown
+/// inputs are left as they are.
+fn substitute(text: &str, values: &HashMap<&str, String>) -> String {
+    let mut substituted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(PLACEHOLDER) {
+        substituted.push_str(&rest[..start]);
+        let after = &rest[start + PLACEHOLDER.len()..];
+        let value = after.find('}').and_then(|end| values.get(&after[..end]).map(|value| (end, value)));
+        match value {
+            Some((end, value)) => {
+                substituted.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                substituted.push_str(PLACEHOLDER);
+                rest = after;
+            }
+        }
+    }
+    substituted.push_str(rest);
+    substituted
+}
+
+/// Registry of templates available to users
+pub struct TemplateRegistry {
+    templates: RwLock<HashMap<String, WorkflowTemplate>>,
+}
+
+impl TemplateRegistry {
+    pub fn new() -> Self {
+        Self {
+            templates: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn register(&self, template: WorkflowTemplate) {
+        self.templates.write().await.insert(template.id().to_string(), template);
+    }
+
+    pub async fn get(&self, id: &str) -> Option<WorkflowTemplate> {
+        self.templates.read().await.get(id).cloned()
+    }
+
+    pub async fn list_available(&self) -> Vec<String> {
+        self.templates.read().await.keys().cloned().collect()
+    }
+
+    /// Load every template file in a directory, returning how many were registered
+    ///
+    /// Files that fail to parse are skipped with a warning.
+    pub async fn scan_dir(&self, dir: impl AsRef<Path>) -> Result<usize, crate::Error> {
+        let mut entries = tokio::fs::read_dir(dir.as_ref()).await?;
+        let mut count = 0;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(TEMPLATE_EXTENSION) {
+                continue;
+            }
+            match WorkflowTemplate::load(&path).await {
+                Ok(template) => {
+                    self.register(template).await;
+                    count += 1;
+                }
+                Err(e) => tracing::warn!("Skipping template {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+impl Default for TemplateRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::{Migration, MigrationRegistry, WorkflowBuilder};
+
+    fn template() -> WorkflowTemplate {
+        let spec = WorkflowBuilder::new("report", "Pressure report")
+            .add_module("DataReader", "Reader")
+            .add_module("ImageWriter", "Writer")
+                .parameter("filename", "{input.case}_{input.field}.png")
+                .depends_on(1)
+            .build();
+        WorkflowTemplate::new(spec)
+            .with_input(TemplateInput::new("case", "Case file", 1, "filename"))
+            .with_input(TemplateInput::new("field", "Field to plot", 1, "field").with_default("pressure"))
+    }
+
+    fn parameter<'a>(spec: &'a WorkflowSpec, module_id: u32, name: &str) -> Option<&'a str> {
+        spec.modules.iter().find(|m| m.id == module_id)?.parameters.get(name).map(String::as_str)
+    }
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("vistle_templates_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn inputs_fill_their_targets_and_placeholders() {
+        let spec = template().instantiate(HashMap::from([("case".to_string(), "wing".to_string())])).unwrap();
+        assert_eq!(parameter(&spec, 1, "filename"), Some("wing"));
+        assert_eq!(parameter(&spec, 1, "field"), Some("pressure"));
+        assert_eq!(parameter(&spec, 2, "filename"), Some("wing_pressure.png"));
+    }
+
+    #[test]
+    fn missing_inputs_are_reported_together() {
+        let template = template().with_input(TemplateInput::new("mesh", "Mesh file", 1, "mesh"));
+        let error = template.instantiate(HashMap::new()).unwrap_err().to_string();
+        assert!(error.contains("case (Case file)") && error.contains("mesh (Mesh file)"), "{}", error);
+
+        let unknown = HashMap::from([("case".to_string(), "a".to_string()), ("colour".to_string(), "red".to_string())]);
+        assert!(template.instantiate(unknown).is_err());
+    }
+
+    #[test]
+    fn placeholders_are_substituted_in_one_pass() {
+        let values = HashMap::from([
+            ("case".to_string(), "{input.field}".to_string()),
+            ("field".to_string(), "velocity".to_string()),
+        ]);
+        let spec = template().instantiate(values).unwrap();
+        // A value that looks like a placeholder is taken literally
+        assert_eq!(parameter(&spec, 2, "filename"), Some("{input.field}_velocity.png"));
+        assert_eq!(parameter(&spec, 1, "filename"), Some("{input.field}"));
+
+        let known = HashMap::from([("a", "1".to_string())]);
+        assert_eq!(substitute("{input.a}{input.b}{input.a", &known), "1{input.b}{input.a");
+    }
+
+    #[test]
+    fn inputs_must_target_modules_of_the_workflow() {
+        let template = template().with_input(TemplateInput::new("mesh", "Mesh file", 9, "mesh"));
+        assert!(template.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn loading_migrates_the_workflow_and_its_input_targets() {
+        MigrationRegistry::global()
+            .register(1, Migration::rename_parameter("TemplateTestReader", "file", "filename"))
+            .unwrap();
+        let dir = temp_dir();
+        let path = dir.join(format!("old.{}", TEMPLATE_EXTENSION));
+        // Saved before workflow files had a format version
+        let text = r#"{
+            "spec": {
+                "id": "old", "name": "Old", "description": "",
+                "modules": [{"id": 1, "module_type": "TemplateTestReader", "name": "Reader",
+                             "parameters": {"file": "default.vtk"}, "dependencies": [], "priority": "Normal"}],
+                "connections": []
+            },
+            "inputs": [{"id": "case", "description": "Case file", "target": [1, "file"], "required": false, "default": null}]
+        }"#;
+        std::fs::write(&path, text).unwrap();
+
+        let (template, warnings) = WorkflowTemplate::load_with_warnings(&path).await.unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(template.inputs[0].target, (1, "filename".to_string()));
+        assert_eq!(parameter(&template.spec, 1, "filename"), Some("default.vtk"));
+        assert_eq!(parameter(&template.spec, 1, "file"), None);
+        assert_eq!(template.spec.base_dir.as_deref(), Some(std::fs::canonicalize(&dir).unwrap().as_path()));
+
+        let spec = template.instantiate(HashMap::from([("case".to_string(), "wing.vtk".to_string())])).unwrap();
+        assert_eq!(parameter(&spec, 1, "filename"), Some("wing.vtk"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn scanning_registers_valid_templates_only() {
+        let dir = temp_dir();
+        template().save(dir.join(format!("report.{}", TEMPLATE_EXTENSION))).await.unwrap();
+        std::fs::write(dir.join(format!("broken.{}", TEMPLATE_EXTENSION)), "{").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a template").unwrap();
+
+        let registry = TemplateRegistry::new();
+        assert_eq!(registry.scan_dir(&dir).await.unwrap(), 1);
+        let loaded = registry.get("report").await.unwrap();
+        assert_eq!(loaded.inputs.len(), 2);
+        assert_eq!(parameter(&loaded.spec, 2, "filename"), Some("{input.case}_{input.field}.png"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,331 @@
+//! In-process clusters for tests of distributed code paths
+//!
+//! A `MiniCluster` runs N ranks as tasks of one process, connected by a
+//! `LocalTransport` network where MPI would connect processes. Every rank
+//! has its own message router, module registry, distributed workflow
+//! executor with its shared memory manager and object registry, and object
+//! transfer service, so collectives, object transfers and distributed
+//! workflows run their real code without `mpirun`.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::compute::builtin::register_builtin_modules;
+use crate::compute::{
+    DistributedWorkflowExecutor, DistributedWorkflowResult, ModuleRegistry, TaskExecutor, WorkflowExecutor,
+    WorkflowSpec,
+};
+use crate::core::{MessageRouter, ObjectId, ObjectRegistry, ShmManager};
+use crate::mpi::{DistributedContext, LocalTransport, ObjectTransferService, Transport};
+
+/// Time a rank may take in `MiniCluster::run` before the run fails, e.g. because of a deadlock
+pub const DEFAULT_RANK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tasks each rank's executor runs at once
+const RANK_CONCURRENCY: usize = 4;
+
+/// One rank of a `MiniCluster`
+pub struct ClusterRank {
+    pub rank: i32,
+    pub router: Arc<MessageRouter>,
+    pub context: Arc<DistributedContext>,
+    pub modules: Arc<ModuleRegistry>,
+    pub executor: Arc<DistributedWorkflowExecutor>,
+    pub transfer: Arc<ObjectTransferService>,
+    barriers: AtomicUsize,
+}
+
+impl ClusterRank {
+    pub fn objects(&self) -> &Arc<ObjectRegistry> {
+        self.executor.executor().object_registry()
+    }
+
+    pub fn shm(&self) -> &Arc<ShmManager> {
+        self.executor.executor().shm_manager()
+    }
+
+    /// Barrier across the cluster, counted for `MiniCluster::assert_all_reached_barrier`
+    pub async fn barrier(&self) -> Result<(), crate::Error> {
+        self.context.barrier().await?;
+        self.barriers.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Barriers this rank has passed
+    pub fn barriers_passed(&self) -> usize {
+        self.barriers.load(Ordering::SeqCst)
+    }
+}
+
+/// Ranks of a distributed run within one process
+pub struct MiniCluster {
+    ranks: Vec<Arc<ClusterRank>>,
+    services: Vec<JoinHandle<()>>,
+    timeout: Duration,
+}
+
+impl MiniCluster {
+    /// `size` ranks with the built-in modules, each serving object transfers
+    pub async fn new(size: i32) -> Self {
+        let mut ranks = Vec::new();
+        for transport in LocalTransport::network(size) {
+            let rank = transport.rank();
+            let router = Arc::new(MessageRouter::new());
+            let context = Arc::new(DistributedContext::with_transport(router.clone(), Arc::new(transport)));
+
+            let modules = Arc::new(ModuleRegistry::new());
+            register_builtin_modules(&modules).await;
+            let executor = WorkflowExecutor::new(
+                modules.clone(),
+                Arc::new(TaskExecutor::new(RANK_CONCURRENCY)),
+                router.clone(),
+            )
+            .with_rank(rank, context.size());
+            let transfer = Arc::new(ObjectTransferService::new(context.clone(), executor.object_registry().clone()));
+            let executor = Arc::new(
+                DistributedWorkflowExecutor::new(Arc::new(executor), context.clone()).with_transfer(transfer.clone()),
+            );
+
+            ranks.push(Arc::new(ClusterRank {
+                rank,
+                router,
+                context,
+                modules,
+                executor,
+                transfer,
+                barriers: AtomicUsize::new(0),
+            }));
+        }
+        let services = ranks.iter().map(|r| r.transfer.spawn()).collect();
+        tracing::debug!("Started mini cluster of {} ranks", ranks.len());
+
+        Self {
+            ranks,
+            services,
+            timeout: DEFAULT_RANK_TIMEOUT,
+        }
+    }
+
+    /// Time each rank may take in `run`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn size(&self) -> i32 {
+        self.ranks.len() as i32
+    }
+
+    pub fn rank(&self, rank: i32) -> &Arc<ClusterRank> {
+        &self.ranks[rank as usize]
+    }
+
+    pub fn ranks(&self) -> &[Arc<ClusterRank>] {
+        &self.ranks
+    }
+
+    /// Run `f` on every rank at once, returning the results by rank
+    ///
+    /// Fails with the error of the lowest failing rank, or if a rank does
+    /// not finish within the timeout.
+    pub async fn run<F, Fut, T>(&self, f: F) -> Result<Vec<T>, crate::Error>
+    where
+        F: Fn(Arc<ClusterRank>) -> Fut,
+        Fut: Future<Output = Result<T, crate::Error>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let tasks: Vec<JoinHandle<Result<T, crate::Error>>> = self.ranks.iter()
+            .map(|rank| tokio::spawn(f(rank.clone())))
+            .collect();
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for (rank, task) in tasks.into_iter().enumerate() {
+            let abort = task.abort_handle();
+            match tokio::time::timeout(self.timeout, task).await {
+                Ok(Ok(result)) => results.push(result),
+                Ok(Err(e)) => results.push(Err(crate::Error::Compute(format!("Rank {} panicked: {}", rank, e)))),
+                Err(_) => {
+                    abort.abort();
+                    results.push(Err(crate::Error::Cancelled(format!(
+                        "Rank {} did not finish within {:?}",
+                        rank, self.timeout
+                    ))));
+                }
+            }
+        }
+        results.into_iter().collect()
+    }
+
+    /// Execute a workflow on all ranks as `mpirun` would, returning each rank's result
+    pub async fn execute_workflow(
+        &self,
+        workflow: WorkflowSpec,
+        timeout_duration: Option<Duration>,
+    ) -> Result<Vec<DistributedWorkflowResult>, crate::Error> {
+        self.run(move |rank| {
+            let workflow = workflow.clone();
+            async move { rank.executor.execute_workflow(workflow, timeout_duration).await }
+        })
+        .await
+    }
+
+    /// Panic unless every rank has passed `count` barriers
+    pub fn assert_all_reached_barrier(&self, count: usize) {
+        let behind: Vec<String> = self.ranks.iter()
+            .filter(|r| r.barriers_passed() < count)
+            .map(|r| format!("rank {} passed {}", r.rank, r.barriers_passed()))
+            .collect();
+        if !behind.is_empty() {
+            panic!("Not all ranks reached barrier {}: {}", count, behind.join(", "));
+        }
+    }
+
+    /// Panic unless `rank` has the object in its registry
+    pub fn assert_object_on(&self, rank: i32, id: ObjectId) {
+        if self.rank(rank).objects().get(id).is_none() {
+            let holders: Vec<String> = self.ranks.iter()
+                .filter(|r| r.objects().get(id).is_some())
+                .map(|r| r.rank.to_string())
+                .collect();
+            panic!(
+                "Object {} is not on rank {}; ranks holding it: [{}]",
+                id, rank, holders.join(", ")
+            );
+        }
+    }
+
+    /// Panic if `rank` has the object in its registry
+    pub fn assert_object_missing(&self, rank: i32, id: ObjectId) {
+        if self.rank(rank).objects().get(id).is_some() {
+            panic!("Object {} is unexpectedly on rank {}", id, rank);
+        }
+    }
+}
+
+impl Drop for MiniCluster {
+    fn drop(&mut self) {
+        for rank in &self.ranks {
+            rank.transfer.shutdown();
+        }
+        for service in &self.services {
+            service.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::core::{Object, ObjectPayload, ObjectType, VistleObject};
+
+    #[tokio::test]
+    async fn ranks_are_numbered_and_kept_apart() {
+        let cluster = MiniCluster::new(3).await;
+        assert_eq!(cluster.size(), 3);
+        let numbers: Vec<i32> = cluster.ranks().iter().map(|r| r.rank).collect();
+        assert_eq!(numbers, [0, 1, 2]);
+        assert!(cluster.ranks().iter().all(|r| r.context.size() == 3 && r.context.rank() == r.rank));
+        assert!(!Arc::ptr_eq(cluster.rank(0).objects(), cluster.rank(1).objects()));
+        assert!(!Arc::ptr_eq(&cluster.rank(0).router, &cluster.rank(1).router));
+    }
+
+    #[tokio::test]
+    async fn runs_return_results_by_rank() {
+        let cluster = MiniCluster::new(3).await;
+        let sums = cluster.run(|rank| async move {
+            let all = rank.context.all_gather(rank.rank * 10).await?;
+            Ok((rank.rank, all.iter().sum::<i32>()))
+        })
+        .await
+        .unwrap();
+        assert_eq!(sums, [(0, 30), (1, 30), (2, 30)]);
+    }
+
+    #[tokio::test]
+    async fn runs_fail_with_the_lowest_failing_rank() {
+        let cluster = MiniCluster::new(3).await;
+        let error = cluster.run(|rank| async move {
+            match rank.rank {
+                0 => Ok(()),
+                n => Err(crate::Error::Compute(format!("rank {} failed", n))),
+            }
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(&error, crate::Error::Compute(m) if m == "rank 1 failed"), "{}", error);
+
+        let error = cluster.run(|rank| async move {
+            if rank.rank == 2 {
+                panic!("rank 2 gave up");
+            }
+            Ok(())
+        })
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("Rank 2 panicked"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn a_stuck_rank_times_out() {
+        let cluster = MiniCluster::new(2).await.with_timeout(Duration::from_millis(100));
+        // Rank 1 never joins the barrier rank 0 waits in
+        let error = cluster.run(|rank| async move {
+            if rank.rank == 0 {
+                rank.barrier().await?;
+            }
+            Ok(())
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(&error, crate::Error::Cancelled(m) if m.starts_with("Rank 0 did not finish")), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn barriers_are_counted_per_rank() {
+        let cluster = MiniCluster::new(3).await;
+        cluster.run(|rank| async move {
+            rank.barrier().await?;
+            rank.barrier().await
+        })
+        .await
+        .unwrap();
+        assert!(cluster.ranks().iter().all(|r| r.barriers_passed() == 2));
+        cluster.assert_all_reached_barrier(2);
+
+        let behind = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cluster.assert_all_reached_barrier(3)));
+        let message = *behind.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(message, "Not all ranks reached barrier 3: rank 0 passed 2, rank 1 passed 2, rank 2 passed 2");
+    }
+
+    #[tokio::test]
+    async fn objects_are_fetched_between_ranks() {
+        let cluster = MiniCluster::new(2).await;
+        let object: Arc<dyn Object> = Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar {
+            data: ndarray::Array1::from(vec![1.0, 2.0, 3.0]),
+        }));
+        let id = object.id();
+        cluster.rank(0).objects().store(object);
+        cluster.assert_object_on(0, id);
+        cluster.assert_object_missing(1, id);
+
+        let fetched = cluster.rank(1).transfer.fetch(id, 0, &CancellationToken::new()).await.unwrap();
+        assert_eq!(fetched.id(), id);
+        cluster.assert_object_on(1, id);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "is not on rank 1; ranks holding it: [0]")]
+    async fn missing_objects_name_the_ranks_holding_them() {
+        let cluster = MiniCluster::new(2).await;
+        let object: Arc<dyn Object> = Arc::new(VistleObject::new(ObjectType::Empty));
+        let id = object.id();
+        cluster.rank(0).objects().store(object);
+        cluster.assert_object_on(1, id);
+    }
+}
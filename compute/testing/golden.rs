@@ -0,0 +1,670 @@
+//! Golden data checks of module outputs
+//!
+//! A `GoldenCase` runs a module on inputs loaded from native object files
+//! and compares the objects of each output port with the golden file
+//! `<port>.vobj` in its golden directory. Values are compared per array
+//! with the tolerance configured for the port and field; connectivity and
+//! other integer arrays are always compared exactly. With
+//! `UPDATE_GOLDENS=1` the goldens are written instead, as for images in
+//! `render::testing`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::compute::{InputPort, Module, VistleModule};
+use crate::core::{
+    attribute, read_object_file, write_object_file, ComputeContext, ImageData, MessageRouter, Object, ObjectData,
+    ObjectPayload, VistleObject,
+};
+pub use crate::render::testing::UPDATE_GOLDENS_ENV;
+
+/// Extension of golden object files
+pub const GOLDEN_EXTENSION: &str = "vobj";
+
+/// Allowed deviation of the values of one array
+///
+/// A value passes if it differs from the golden by at most
+/// `absolute + relative * |golden|`. The default is bit-exact.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FieldTolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl FieldTolerance {
+    pub const EXACT: Self = Self { absolute: 0.0, relative: 0.0 };
+
+    pub fn absolute(absolute: f64) -> Self {
+        Self { absolute, relative: 0.0 }
+    }
+
+    pub fn relative(relative: f64) -> Self {
+        Self { absolute: 0.0, relative }
+    }
+
+    pub fn with_relative(mut self, relative: f64) -> Self {
+        self.relative = relative;
+        self
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.absolute == 0.0 && self.relative == 0.0
+    }
+
+    fn accepts(&self, actual: f64, expected: f64) -> bool {
+        if actual.is_nan() || expected.is_nan() {
+            return actual.is_nan() && expected.is_nan();
+        }
+        if self.is_exact() {
+            return actual.to_bits() == expected.to_bits();
+        }
+        (actual - expected).abs() <= self.absolute + self.relative * expected.abs()
+    }
+}
+
+impl std::fmt::Display for FieldTolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_exact() {
+            write!(f, "exact")
+        } else {
+            write!(f, "abs {:e}, rel {:e}", self.absolute, self.relative)
+        }
+    }
+}
+
+/// Tolerances for the objects of one output port
+///
+/// Fields are named after the payload's arrays, e.g. `coordinates`,
+/// `values` or `data`; table columns are `column:<name>`.
+#[derive(Debug, Clone, Default)]
+pub struct PortTolerance {
+    default: FieldTolerance,
+    fields: HashMap<String, FieldTolerance>,
+}
+
+impl PortTolerance {
+    pub fn new(default: FieldTolerance) -> Self {
+        Self {
+            default,
+            fields: HashMap::new(),
+        }
+    }
+
+    pub fn with_field(mut self, field: &str, tolerance: FieldTolerance) -> Self {
+        self.fields.insert(field.to_string(), tolerance);
+        self
+    }
+
+    pub fn field(&self, field: &str) -> FieldTolerance {
+        self.fields.get(field).copied().unwrap_or(self.default)
+    }
+}
+
+/// One array of a payload, flattened
+enum FieldValues {
+    Float(Vec<f64>),
+    /// Connectivity, indices and sizes, compared exactly
+    Index(Vec<i64>),
+}
+
+fn floats<'a>(values: impl IntoIterator<Item = &'a f32>) -> FieldValues {
+    FieldValues::Float(values.into_iter().map(|&v| v as f64).collect())
+}
+
+fn indices<'a>(values: impl IntoIterator<Item = &'a i32>) -> FieldValues {
+    FieldValues::Index(values.into_iter().map(|&v| v as i64).collect())
+}
+
+/// Named arrays of a payload with their shapes
+fn fields(payload: &ObjectPayload) -> Result<Vec<(String, Vec<usize>, FieldValues)>, String> {
+    Ok(match payload {
+        ObjectPayload::Empty => Vec::new(),
+        ObjectPayload::Points { coordinates } => vec![
+            ("coordinates".to_string(), coordinates.shape().to_vec(), floats(coordinates)),
+        ],
+        ObjectPayload::Lines { coordinates, connections } => vec![
+            ("coordinates".to_string(), coordinates.shape().to_vec(), floats(coordinates)),
+            ("connections".to_string(), connections.shape().to_vec(), indices(connections)),
+        ],
+        ObjectPayload::Triangles { coordinates, triangles } => vec![
+            ("coordinates".to_string(), coordinates.shape().to_vec(), floats(coordinates)),
+            ("triangles".to_string(), triangles.shape().to_vec(), indices(triangles)),
+        ],
+        ObjectPayload::VecScalar { data } => vec![("data".to_string(), data.shape().to_vec(), floats(data))],
+        ObjectPayload::VecVec3 { data } => vec![("data".to_string(), data.shape().to_vec(), floats(data))],
+        ObjectPayload::Table { columns } => columns.iter()
+            .map(|(name, column)| (
+                format!("column:{}", name),
+                column.shape().to_vec(),
+                FieldValues::Float(column.to_vec()),
+            ))
+            .collect(),
+        ObjectPayload::Curve { x, y, .. } => vec![
+            ("x".to_string(), x.shape().to_vec(), FieldValues::Float(x.to_vec())),
+            ("y".to_string(), y.shape().to_vec(), FieldValues::Float(y.to_vec())),
+        ],
+        ObjectPayload::UniformGrid { dims, origin, spacing, values } => vec![
+            ("dims".to_string(), vec![3], FieldValues::Index(dims.iter().map(|&d| d as i64).collect())),
+            ("origin".to_string(), vec![3], floats(origin)),
+            ("spacing".to_string(), vec![3], floats(spacing)),
+            ("values".to_string(), values.shape().to_vec(), floats(values)),
+        ],
+        ObjectPayload::AmrHierarchy { .. } => return Err("AMR hierarchies are compared as a whole".to_string()),
+        ObjectPayload::Placeholder { .. } => return Err("placeholder was never resolved".to_string()),
+        ObjectPayload::Custom(bytes) => vec![
+            ("custom".to_string(), vec![bytes.len()], FieldValues::Index(bytes.iter().map(|&b| b as i64).collect())),
+        ],
+        ObjectPayload::Lossy(payload) => return fields(&payload.decompress().map_err(|e| e.to_string())?),
+        ObjectPayload::UnstructuredGrid { coordinates, connectivity, offsets, cell_types } => vec![
+            ("coordinates".to_string(), coordinates.shape().to_vec(), floats(coordinates)),
+            ("connectivity".to_string(), connectivity.shape().to_vec(), indices(connectivity)),
+            ("offsets".to_string(), offsets.shape().to_vec(), indices(offsets)),
+            (
+                "cell_types".to_string(),
+                vec![cell_types.len()],
+                FieldValues::Index(cell_types.iter().map(|&t| t as i64).collect()),
+            ),
+        ],
+        ObjectPayload::RectilinearGrid { x, y, z } => vec![
+            ("x".to_string(), x.shape().to_vec(), floats(x)),
+            ("y".to_string(), y.shape().to_vec(), floats(y)),
+            ("z".to_string(), z.shape().to_vec(), floats(z)),
+        ],
+        ObjectPayload::StructuredGrid { dims, coordinates } => vec![
+            ("dims".to_string(), vec![3], FieldValues::Index(dims.iter().map(|&d| d as i64).collect())),
+            ("coordinates".to_string(), coordinates.shape().to_vec(), floats(coordinates)),
+        ],
+        ObjectPayload::Polygons { coordinates, connectivity, offsets } => vec![
+            ("coordinates".to_string(), coordinates.shape().to_vec(), floats(coordinates)),
+            ("connectivity".to_string(), connectivity.shape().to_vec(), indices(connectivity)),
+            ("offsets".to_string(), offsets.shape().to_vec(), indices(offsets)),
+        ],
+        ObjectPayload::Quads { coordinates, quads } => vec![
+            ("coordinates".to_string(), coordinates.shape().to_vec(), floats(coordinates)),
+            ("quads".to_string(), quads.shape().to_vec(), indices(quads)),
+        ],
+        ObjectPayload::VecScalarF64 { data } => vec![
+            ("data".to_string(), data.shape().to_vec(), FieldValues::Float(data.to_vec())),
+        ],
+        ObjectPayload::VecVec3F64 { data } => vec![
+            ("data".to_string(), data.shape().to_vec(), FieldValues::Float(data.iter().copied().collect())),
+        ],
+        ObjectPayload::Image { data, origin, spacing, .. } => vec![
+            ("origin".to_string(), vec![3], floats(origin)),
+            ("spacing".to_string(), vec![2], floats(spacing)),
+            match data {
+                ImageData::U8(pixels) => (
+                    "pixels".to_string(),
+                    pixels.shape().to_vec(),
+                    FieldValues::Index(pixels.iter().map(|&v| v as i64).collect()),
+                ),
+                ImageData::F32(pixels) => ("pixels".to_string(), pixels.shape().to_vec(), floats(pixels)),
+            },
+        ],
+        ObjectPayload::LayeredImage { bands, origin, spacing, .. } => [
+            ("origin".to_string(), vec![3], floats(origin)),
+            ("spacing".to_string(), vec![2], floats(spacing)),
+        ]
+        .into_iter()
+        .chain(bands.iter().map(|band| (
+            format!("band:{}", band.name),
+            band.values.shape().to_vec(),
+            floats(&band.values),
+        )))
+        .collect(),
+        ObjectPayload::VecInt32 { data } => vec![("data".to_string(), data.shape().to_vec(), indices(data))],
+        ObjectPayload::VecInt64 { data } => vec![
+            ("data".to_string(), data.shape().to_vec(), FieldValues::Index(data.to_vec())),
+        ],
+    })
+}
+
+/// An array whose values differ from the golden beyond its tolerance
+#[derive(Debug, Clone)]
+pub struct ArrayDiff {
+    /// `port[object].field`
+    pub location: String,
+    pub len: usize,
+    /// Values outside the tolerance
+    pub mismatches: usize,
+    pub max_error: f64,
+    /// Index of the value with the largest error, into the flattened array
+    pub worst_index: usize,
+    pub expected: f64,
+    pub actual: f64,
+    pub tolerance: FieldTolerance,
+}
+
+impl std::fmt::Display for ArrayDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} values differ, max error {:e} at index {} (expected {}, got {}; tolerance {})",
+            self.location, self.mismatches, self.len, self.max_error, self.worst_index,
+            self.expected, self.actual, self.tolerance
+        )
+    }
+}
+
+/// Differences between module outputs and their goldens
+#[derive(Debug, Clone, Default)]
+pub struct GoldenDiff {
+    /// Differences in object counts, types, shapes, metadata or attributes
+    pub structural: Vec<String>,
+    pub arrays: Vec<ArrayDiff>,
+}
+
+impl GoldenDiff {
+    pub fn is_empty(&self) -> bool {
+        self.structural.is_empty() && self.arrays.is_empty()
+    }
+
+    pub fn merge(&mut self, other: GoldenDiff) {
+        self.structural.extend(other.structural);
+        self.arrays.extend(other.arrays);
+    }
+}
+
+impl std::fmt::Display for GoldenDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.structural {
+            writeln!(f, "  {}", line)?;
+        }
+        for array in &self.arrays {
+            writeln!(f, "  {}", array)?;
+        }
+        Ok(())
+    }
+}
+
+fn compare_values(location: String, actual: &[f64], expected: &[f64], tolerance: FieldTolerance) -> Option<ArrayDiff> {
+    let mut diff: Option<ArrayDiff> = None;
+    for (index, (&a, &e)) in actual.iter().zip(expected).enumerate() {
+        if tolerance.accepts(a, e) {
+            continue;
+        }
+        // NaN against a number counts as the largest possible error
+        let error = if a.is_nan() || e.is_nan() { f64::INFINITY } else { (a - e).abs() };
+        let diff = diff.get_or_insert_with(|| ArrayDiff {
+            location: location.clone(),
+            len: expected.len(),
+            mismatches: 0,
+            max_error: -1.0,
+            worst_index: index,
+            expected: e,
+            actual: a,
+            tolerance,
+        });
+        diff.mismatches += 1;
+        if error > diff.max_error {
+            diff.max_error = error;
+            diff.worst_index = index;
+            diff.expected = e;
+            diff.actual = a;
+        }
+    }
+    diff
+}
+
+/// Compare one object with its golden
+fn compare_object(location: &str, actual: &ObjectData, expected: &ObjectData, tolerance: &PortTolerance) -> GoldenDiff {
+    let mut diff = GoldenDiff::default();
+    if actual.object_type != expected.object_type {
+        diff.structural.push(format!(
+            "{}: type {}, expected {}",
+            location, actual.object_type.as_str(), expected.object_type.as_str()
+        ));
+        return diff;
+    }
+
+    let (a, e) = (&actual.meta, &expected.meta);
+    for (name, got, want) in [
+        ("block", a.block, e.block),
+        ("num_blocks", a.num_blocks, e.num_blocks),
+        ("timestep", a.timestep, e.timestep),
+        ("num_timesteps", a.num_timesteps, e.num_timesteps),
+    ] {
+        if got != want {
+            diff.structural.push(format!("{}: {} is {}, expected {}", location, name, got, want));
+        }
+    }
+
+    // Ids of source objects differ from run to run
+    let attributes = |data: &ObjectData| -> BTreeMap<String, String> {
+        data.attributes.iter()
+            .filter(|(k, _)| k.as_str() != attribute::DERIVED_FROM)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    };
+    let (got, want) = (attributes(actual), attributes(expected));
+    for key in got.keys().chain(want.keys().filter(|k| !got.contains_key(*k))) {
+        match (got.get(key), want.get(key)) {
+            (Some(g), Some(w)) if g == w => {}
+            (g, w) => diff.structural.push(format!("{}: attribute {} is {:?}, expected {:?}", location, key, g, w)),
+        }
+    }
+
+    if let (ObjectPayload::AmrHierarchy { levels: got }, ObjectPayload::AmrHierarchy { levels: want }) =
+        (actual.data.as_ref(), expected.data.as_ref())
+    {
+        if got != want {
+            diff.structural.push(format!("{}: AMR hierarchy differs", location));
+        }
+        return diff;
+    }
+    let (got, want) = match (fields(&actual.data), fields(&expected.data)) {
+        (Ok(got), Ok(want)) => (got, want),
+        (Err(e), _) | (_, Err(e)) => {
+            diff.structural.push(format!("{}: {}", location, e));
+            return diff;
+        }
+    };
+
+    let names = |fields: &[(String, Vec<usize>, FieldValues)]| fields.iter().map(|f| f.0.clone()).collect::<Vec<_>>();
+    if names(&got) != names(&want) {
+        diff.structural.push(format!("{}: fields {:?}, expected {:?}", location, names(&got), names(&want)));
+        return diff;
+    }
+    for ((name, got_shape, got), (_, want_shape, want)) in got.iter().zip(&want) {
+        let field_location = format!("{}.{}", location, name);
+        if got_shape != want_shape {
+            diff.structural.push(format!("{}: shape {:?}, expected {:?}", field_location, got_shape, want_shape));
+            continue;
+        }
+        let array_diff = match (got, want) {
+            (FieldValues::Float(got), FieldValues::Float(want)) => {
+                compare_values(field_location, got, want, tolerance.field(name))
+            }
+            (FieldValues::Index(got), FieldValues::Index(want)) => {
+                let got: Vec<f64> = got.iter().map(|&v| v as f64).collect();
+                let want: Vec<f64> = want.iter().map(|&v| v as f64).collect();
+                compare_values(field_location, &got, &want, FieldTolerance::EXACT)
+            }
+            _ => unreachable!("fields of the same name and payload type have the same kind"),
+        };
+        diff.arrays.extend(array_diff);
+    }
+    diff
+}
+
+/// Compare the objects of one port with their goldens, in order
+pub fn compare_port(port: &str, actual: &[ObjectData], expected: &[ObjectData], tolerance: &PortTolerance) -> GoldenDiff {
+    let mut diff = GoldenDiff::default();
+    if actual.len() != expected.len() {
+        diff.structural.push(format!("{}: {} objects, expected {}", port, actual.len(), expected.len()));
+    }
+    for (index, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+        diff.merge(compare_object(&format!("{}[{}]", port, index), actual, expected, tolerance));
+    }
+    diff
+}
+
+fn updating_goldens() -> bool {
+    std::env::var(UPDATE_GOLDENS_ENV).map(|v| v == "1").unwrap_or(false)
+}
+
+/// A module run on fixture inputs whose outputs are checked against goldens
+pub struct GoldenCase<M: Module> {
+    module: VistleModule<M>,
+    module_id: u32,
+    golden_dir: PathBuf,
+    inputs: Vec<(String, PathBuf)>,
+    parameters: Vec<(String, String)>,
+    tolerances: HashMap<String, PortTolerance>,
+}
+
+impl<M: Module> GoldenCase<M> {
+    /// Check `module` against the goldens in `golden_dir`
+    pub fn new(module: M, golden_dir: impl Into<PathBuf>) -> Self {
+        let module_id = module.info().id;
+        Self {
+            module: VistleModule::new(module),
+            module_id,
+            golden_dir: golden_dir.into(),
+            inputs: Vec::new(),
+            parameters: Vec::new(),
+            tolerances: HashMap::new(),
+        }
+    }
+
+    /// Feed the objects of a native object file to an input port
+    ///
+    /// Several files for the same port are concatenated in order.
+    pub fn input(mut self, port: &str, fixture: impl Into<PathBuf>) -> Self {
+        self.inputs.push((port.to_string(), fixture.into()));
+        self
+    }
+
+    /// Set a parameter from its text form, as in workflow specs
+    pub fn parameter(mut self, name: &str, value: &str) -> Self {
+        self.parameters.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Tolerances for an output port; ports without one are compared exactly
+    pub fn tolerance(mut self, port: &str, tolerance: PortTolerance) -> Self {
+        self.tolerances.insert(port.to_string(), tolerance);
+        self
+    }
+
+    pub fn golden_path(&self, port: &str) -> PathBuf {
+        self.golden_dir.join(format!("{}.{}", port, GOLDEN_EXTENSION))
+    }
+
+    /// Run the module and compare its outputs, or write them as goldens with `UPDATE_GOLDENS=1`
+    pub async fn run(&self) -> Result<GoldenDiff, crate::Error> {
+        let mut inputs: BTreeMap<&str, InputPort> = BTreeMap::new();
+        for (port, fixture) in &self.inputs {
+            let objects = read_object_file(fixture).await?;
+            inputs.entry(port.as_str()).or_default().extend(
+                objects.into_iter().map(|data| Arc::new(VistleObject::from_data(data)) as Arc<dyn Object>),
+            );
+        }
+        for (name, value) in &self.parameters {
+            self.module.set_parameter_str(name, value)?;
+        }
+        for (port, objects) in inputs {
+            self.module.set_input(port, objects).await?;
+        }
+
+        let ctx = ComputeContext::new(self.module_id, 0, 1);
+        let outputs = self.module.execute(&ctx, &MessageRouter::new()).await?;
+
+        let mut diff = GoldenDiff::default();
+        let mut ports: Vec<&String> = outputs.keys().collect();
+        ports.sort();
+        for port in ports {
+            let mut actual = Vec::new();
+            for (index, object) in outputs[port].iter().enumerate() {
+                match object.as_data() {
+                    Some(data) => actual.push(data.clone()),
+                    None => diff.structural.push(format!("{}[{}]: object has no data container", port, index)),
+                }
+            }
+
+            let golden_path = self.golden_path(port);
+            if updating_goldens() {
+                tokio::fs::create_dir_all(&self.golden_dir).await?;
+                write_object_file(&golden_path, &actual).await?;
+                tracing::info!("Wrote golden {}", golden_path.display());
+                continue;
+            }
+            if !golden_path.exists() {
+                diff.structural.push(format!(
+                    "{}: golden {} does not exist; rerun with {}=1 to create it",
+                    port, golden_path.display(), UPDATE_GOLDENS_ENV
+                ));
+                continue;
+            }
+            let expected = read_object_file(&golden_path).await?;
+            let tolerance = self.tolerances.get(port.as_str()).cloned().unwrap_or_default();
+            diff.merge(compare_port(port, &actual, &expected, &tolerance));
+        }
+
+        if !updating_goldens() {
+            for port in self.golden_ports().await? {
+                if !outputs.contains_key(&port) {
+                    diff.structural.push(format!("{}: golden exists but the port produced nothing", port));
+                }
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Ports with a golden file in the golden directory
+    async fn golden_ports(&self) -> Result<Vec<String>, crate::Error> {
+        let mut entries = match tokio::fs::read_dir(&self.golden_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut ports = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == GOLDEN_EXTENSION) {
+                ports.extend(path.file_stem().map(|s| s.to_string_lossy().into_owned()));
+            }
+        }
+        Ok(ports)
+    }
+
+    /// Panic with a report of every difference unless the outputs match their goldens
+    pub async fn assert_matches(&self) {
+        let diff = self.run().await
+            .unwrap_or_else(|e| panic!("Golden check in {} failed to run: {}", self.golden_dir.display(), e));
+        if !diff.is_empty() {
+            panic!("Outputs do not match goldens in {}:\n{}", self.golden_dir.display(), diff);
+        }
+    }
+}
+
+/// Directory of goldens and fixtures for a test, `tests/data/<name>` in the crate
+pub fn test_data_dir(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("data").join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::testing::modules::ConstantField;
+    use crate::core::ObjectType;
+
+    fn object(object_type: ObjectType, payload: ObjectPayload) -> ObjectData {
+        VistleObject::with_data(object_type, payload).as_data().cloned().unwrap()
+    }
+
+    fn scalars(values: &[f32]) -> ObjectData {
+        object(ObjectType::Vec, ObjectPayload::VecScalar { data: ndarray::Array1::from(values.to_vec()) })
+    }
+
+    fn triangle(corner: f32, index: i32) -> ObjectData {
+        object(ObjectType::Triangles, ObjectPayload::Triangles {
+            coordinates: ndarray::array![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, corner, 0.0]],
+            triangles: ndarray::array![[0, 1, index]],
+        })
+    }
+
+    #[test]
+    fn tolerances_combine_absolute_and_relative_error() {
+        assert!(FieldTolerance::EXACT.accepts(1.5, 1.5));
+        assert!(!FieldTolerance::EXACT.accepts(0.0, -0.0));
+        assert!(FieldTolerance::EXACT.accepts(f64::NAN, f64::NAN));
+        assert!(!FieldTolerance::absolute(1.0).accepts(f64::NAN, 0.0));
+
+        let tolerance = FieldTolerance::absolute(0.1).with_relative(0.01);
+        assert!(tolerance.accepts(100.0, 101.0));
+        assert!(!tolerance.accepts(100.0, 101.2));
+        assert!(FieldTolerance::relative(0.5).accepts(1.4, 1.0));
+        assert_eq!(FieldTolerance::default().to_string(), "exact");
+    }
+
+    #[test]
+    fn port_tolerances_fall_back_to_their_default() {
+        let tolerance = PortTolerance::new(FieldTolerance::absolute(1e-3)).with_field("values", FieldTolerance::EXACT);
+        assert_eq!(tolerance.field("values"), FieldTolerance::EXACT);
+        assert_eq!(tolerance.field("coordinates"), FieldTolerance::absolute(1e-3));
+    }
+
+    #[test]
+    fn the_worst_value_of_a_differing_array_is_reported() {
+        let tolerance = PortTolerance::new(FieldTolerance::absolute(0.01));
+        assert!(compare_port("data_out", &[scalars(&[1.0, 2.0])], &[scalars(&[1.005, 2.0])], &tolerance).is_empty());
+
+        let diff = compare_port("data_out", &[scalars(&[1.0, 2.5, 3.2, 4.0])], &[scalars(&[1.0, 2.0, 3.0, 4.0])], &tolerance);
+        assert!(diff.structural.is_empty());
+        let array = &diff.arrays[0];
+        assert_eq!(array.location, "data_out[0].data");
+        assert_eq!((array.len, array.mismatches, array.worst_index), (4, 2, 1));
+        assert_eq!((array.expected, array.actual), (2.0, 2.5));
+        assert!(diff.to_string().contains("data_out[0].data: 2 of 4 values differ"), "{}", diff);
+    }
+
+    #[test]
+    fn indices_are_compared_exactly_whatever_the_tolerance() {
+        let tolerance = PortTolerance::new(FieldTolerance::absolute(10.0));
+        let diff = compare_port("surface", &[triangle(1.5, 2)], &[triangle(1.0, 2)], &tolerance);
+        assert!(diff.is_empty(), "{}", diff);
+
+        let diff = compare_port("surface", &[triangle(1.0, 1)], &[triangle(1.0, 2)], &tolerance);
+        assert_eq!(diff.arrays.len(), 1);
+        assert_eq!(diff.arrays[0].location, "surface[0].triangles");
+        assert!(diff.arrays[0].tolerance.is_exact());
+    }
+
+    #[test]
+    fn structural_differences_are_listed() {
+        let tolerance = PortTolerance::default();
+        let diff = compare_port("out", &[scalars(&[1.0])], &[scalars(&[1.0]), scalars(&[2.0])], &tolerance);
+        assert_eq!(diff.structural, ["out: 1 objects, expected 2"]);
+
+        let diff = compare_port("out", &[scalars(&[1.0])], &[triangle(1.0, 2)], &tolerance);
+        assert!(diff.structural[0].starts_with("out[0]: type"), "{}", diff);
+
+        let diff = compare_port("out", &[scalars(&[1.0, 2.0])], &[scalars(&[1.0])], &tolerance);
+        assert!(diff.structural[0].contains("shape [2], expected [1]"), "{}", diff);
+
+        let mut actual = scalars(&[1.0]);
+        actual.meta.timestep = 3;
+        actual.attributes.insert("units".to_string(), "Pa".to_string());
+        actual.attributes.insert(attribute::DERIVED_FROM.to_string(), "42".to_string());
+        let diff = compare_port("out", &[actual], &[scalars(&[1.0])], &tolerance);
+        assert_eq!(diff.structural.len(), 2, "{}", diff);
+        assert!(diff.structural.iter().any(|s| s.contains("timestep is 3, expected 0")), "{}", diff);
+        assert!(diff.structural.iter().any(|s| s.contains("attribute units")), "{}", diff);
+    }
+
+    async fn write_golden(dir: &Path, value: &str) {
+        let module = VistleModule::new(ConstantField::new(1));
+        module.set_parameter_str("value", value).unwrap();
+        let outputs = module.execute(&ComputeContext::new(1, 0, 1), &MessageRouter::new()).await.unwrap();
+        let objects: Vec<ObjectData> = outputs["data_out"].iter().map(|o| o.as_data().cloned().unwrap()).collect();
+        write_object_file(dir.join("data_out.vobj"), &objects).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_case_compares_every_port_with_its_golden() {
+        let dir = std::env::temp_dir().join(format!("vistle_golden_{}", uuid::Uuid::new_v4().simple()));
+        let case = GoldenCase::new(ConstantField::new(1), &dir).parameter("value", "2.0");
+
+        let missing = case.run().await.unwrap();
+        assert!(missing.structural[0].contains("does not exist"), "{}", missing);
+
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        write_golden(&dir, "2.0").await;
+        assert!(case.run().await.unwrap().is_empty());
+
+        write_golden(&dir, "2.001").await;
+        let diff = case.run().await.unwrap();
+        assert_eq!(diff.arrays[0].mismatches, 4);
+        let tolerant = GoldenCase::new(ConstantField::new(1), &dir)
+            .parameter("value", "2.0")
+            .tolerance("data_out", PortTolerance::new(FieldTolerance::absolute(0.01)));
+        assert!(tolerant.run().await.unwrap().is_empty());
+
+        tokio::fs::write(dir.join("isosurface.vobj"), b"").await.unwrap();
+        let stale = tolerant.run().await.unwrap();
+        assert_eq!(stale.structural, ["isosurface: golden exists but the port produced nothing"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
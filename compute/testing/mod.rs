@@ -0,0 +1,5 @@
+//! Helpers for regression tests of modules and distributed runs
+
+pub mod cluster;
+pub mod golden;
+pub mod modules;
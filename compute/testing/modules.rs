@@ -0,0 +1,222 @@
+//! Small modules for tests of executors, schedulers and distributed runs
+//!
+//! They read no files and produce the same output for the same parameters,
+//! so test workflows can be built from them without data on disk.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ndarray::Array1;
+
+use crate::core::{
+    ComputeContext, ExecutionStats, ModuleInfo, Object, ObjectMeta, ObjectPayload, ObjectType, Parameter,
+    ParameterSet, ParameterValue, Port, PortSet, VistleObject,
+};
+use crate::compute::{InputPort, InputPorts, Module, ModuleRegistry, OutputPorts};
+
+/// Module emitting a constant scalar field on `data_out`
+///
+/// `value` and `count` set the field, `delay_ms` how long the module waits
+/// before producing it; the wait ends early on cancellation. The optional
+/// `data_in` port is ignored, so the module can be chained to any other,
+/// and so is `source`, which names a file for tests of file handling
+/// without the module reading it.
+/// The field has the timestep and rank of the execution.
+pub struct ConstantField {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl ConstantField {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("value", "Value of every element", ParameterValue::Float(1.0)));
+        parameters.add(Parameter::new("count", "Number of elements", ParameterValue::Int(4)));
+        parameters.add(Parameter::new("delay_ms", "Time to wait before producing the field", ParameterValue::Int(0)));
+        parameters.add(Parameter::file_path("source", "File the field stands for, not read", ""));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("data_in", "Ignored").optional());
+        ports.add(Port::new_output("data_out", "Constant scalar field"));
+
+        Self {
+            info: ModuleInfo::new(id, "ConstantField", 0, 1),
+            parameters,
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for ConstantField {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let value = ctx.parameters().get_float("value").unwrap_or(1.0);
+        let count = ctx.parameters().get_int("count").unwrap_or(4).max(0) as usize;
+        let delay = ctx.parameters().get_int("delay_ms").unwrap_or(0).max(0) as u64;
+
+        if delay > 0 {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(delay)) => {}
+                _ = ctx.cancellation().cancelled() => {
+                    return Err(crate::Error::Cancelled(format!("execution of module {}", ctx.module_id)));
+                }
+            }
+        }
+
+        let meta = ObjectMeta {
+            timestep: ctx.timestep,
+            creator: ctx.rank,
+            ..ObjectMeta::default()
+        };
+        let field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: Array1::from_elem(count, value) })
+            .with_meta(meta);
+
+        let mut outputs = HashMap::new();
+        outputs.insert("data_out".to_string(), vec![Arc::new(field) as Arc<dyn Object>]);
+        Ok(outputs)
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+/// Module failing every execution, with an error or, if `panic` is set, by panicking
+pub struct Failing {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    stats: ExecutionStats,
+}
+
+impl Failing {
+    pub fn new(id: u32) -> Self {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("panic", "Panic instead of returning an error", ParameterValue::Bool(false)));
+
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("data_in", "Ignored").optional());
+        ports.add(Port::new_output("data_out", "Never produced"));
+
+        Self {
+            info: ModuleInfo::new(id, "Failing", 0, 1),
+            parameters,
+            ports,
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for Failing {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, _port_name: &str, _objects: InputPort) -> Result<(), crate::Error> {
+        Ok(())
+    }
+
+    async fn compute(&mut self, ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        if ctx.parameters().get_bool("panic").unwrap_or(false) {
+            panic!("Failing module {} panicked as asked", ctx.module_id);
+        }
+        Err(crate::Error::Compute(format!("Failing module {} failed as asked", ctx.module_id)))
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+/// Module passing every object on `data_in` through to `data_out`, e.g. to see what a consumer received
+pub struct Collect {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    inputs: InputPorts,
+    stats: ExecutionStats,
+}
+
+impl Collect {
+    pub fn new(id: u32) -> Self {
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("data_in", "Objects to pass on").optional());
+        ports.add(Port::new_output("data_out", "Objects of data_in in the order received"));
+
+        Self {
+            info: ModuleInfo::new(id, "Collect", 1, 1),
+            parameters: ParameterSet::new(),
+            ports,
+            inputs: HashMap::new(),
+            stats: ExecutionStats::new(id),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for Collect {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, port_name: &str, objects: InputPort) -> Result<(), crate::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
+        Ok(())
+    }
+
+    async fn compute(&mut self, _ctx: &ComputeContext) -> Result<OutputPorts, crate::Error> {
+        let objects = self.inputs.get("data_in").cloned().unwrap_or_default();
+        Ok(HashMap::from([("data_out".to_string(), objects)]))
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+/// Register `ConstantField`, `Failing` and `Collect` with a registry
+pub async fn register_test_modules(registry: &ModuleRegistry) {
+    registry.register("ConstantField", || ConstantField::new(0)).await;
+    registry.register("Failing", || Failing::new(0)).await;
+    registry.register("Collect", || Collect::new(0)).await;
+}
@@ -0,0 +1,73 @@
+//! Chrome trace export of workflow runs
+//!
+//! The JSON loads in `chrome://tracing` and Perfetto. Each module gets a
+//! row of its own; stages are drawn on a separate row above them.
+
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::compute::WorkflowResult;
+
+/// Row that stage spans are drawn on
+const STAGE_ROW: u32 = 0;
+
+fn micros(duration: std::time::Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+impl WorkflowResult {
+    /// Module and stage spans as a Chrome trace event document
+    pub fn to_chrome_trace(&self) -> Value {
+        let mut events = vec![json!({
+            "name": "thread_name", "ph": "M", "pid": 1, "tid": STAGE_ROW,
+            "args": { "name": "stages" },
+        })];
+        for stage in &self.stages {
+            events.push(json!({
+                "name": stage.name,
+                "cat": "stage",
+                "ph": "X",
+                "pid": 1,
+                "tid": STAGE_ROW,
+                "ts": micros(stage.start),
+                "dur": micros(stage.duration),
+                "args": {
+                    "modules": stage.modules,
+                    "budget_ms": stage.budget.map(|b| b.as_millis() as u64),
+                    "exceeded": stage.exceeded,
+                },
+            }));
+        }
+        for span in &self.module_spans {
+            let spec = self.modules.iter().find(|m| m.id == span.module_id);
+            let name = spec.map_or_else(|| format!("module {}", span.module_id), |m| m.name.clone());
+            let row = span.module_id + 1;
+            events.push(json!({
+                "name": "thread_name", "ph": "M", "pid": 1, "tid": row,
+                "args": { "name": name },
+            }));
+            events.push(json!({
+                "name": name,
+                "cat": spec.and_then(|m| m.stage.as_deref()).unwrap_or("module"),
+                "ph": "X",
+                "pid": 1,
+                "tid": row,
+                "ts": micros(span.start),
+                "dur": micros(span.end.saturating_sub(span.start)),
+                "args": { "module_type": spec.map(|m| m.module_type.as_str()) },
+            }));
+        }
+        json!({
+            "traceEvents": events,
+            "otherData": { "workflow_id": self.workflow_id, "workflow_name": self.workflow_name },
+        })
+    }
+
+    /// Write the Chrome trace as JSON
+    pub async fn save_chrome_trace(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let text = serde_json::to_string(&self.to_chrome_trace())
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize trace: {}", e)))?;
+        crate::util::io::write_text(path, &text).await
+    }
+}
@@ -0,0 +1,395 @@
+//! Re-execution of workflows when their input files change
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::WorkflowSpec;
+
+/// Outcome of a re-execution triggered by file changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchEvent {
+    pub workflow_id: String,
+    pub changed: Vec<PathBuf>,
+    /// Modules whose parameters referenced a changed path
+    pub dirty: Vec<u32>,
+    /// Modules that were re-executed
+    pub executed: Vec<u32>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Modules with a parameter naming one of the changed paths, or a directory containing one
+pub fn modules_referencing(spec: &WorkflowSpec, changed: &[PathBuf]) -> Vec<u32> {
+    let changed: Vec<PathBuf> = changed.iter().map(|p| normalize(p)).collect();
+    spec.modules.iter()
+        .filter(|module| {
+            module.parameters.values()
+                .filter_map(|value| crate::core::resolve_path(value, spec.base_dir.as_deref()).ok())
+                .map(|path| normalize(&path))
+                .any(|path| changed.iter().any(|c| c.starts_with(&path)))
+        })
+        .map(|module| module.id)
+        .collect()
+}
+
+/// Modules reachable from `start` along dependencies and connections, `start` included
+fn reachable(spec: &WorkflowSpec, start: &[u32], forward: bool) -> HashSet<u32> {
+    let edges: Vec<(u32, u32)> = spec.modules.iter()
+        .flat_map(|m| m.dependencies.iter().map(move |&d| (d, m.id)))
+        .chain(spec.connections.iter().map(|c| (c.from_module, c.to_module)))
+        .collect();
+
+    let mut reached: HashSet<u32> = start.iter().copied().collect();
+    let mut frontier: Vec<u32> = start.to_vec();
+    while let Some(id) = frontier.pop() {
+        for &(from, to) in &edges {
+            let (a, b) = if forward { (from, to) } else { (to, from) };
+            if a == id && reached.insert(b) {
+                frontier.push(b);
+            }
+        }
+    }
+    reached
+}
+
+/// The `dirty` modules and everything downstream of them
+pub fn downstream_modules(spec: &WorkflowSpec, dirty: &[u32]) -> HashSet<u32> {
+    reachable(spec, dirty, true)
+}
+
+/// The part of a workflow that has to run again after `dirty` modules changed
+///
+/// That is every module downstream of a dirty one, plus everything those
+/// modules take input from, since `execute_workflow` does not reuse the
+/// outputs of earlier executions.
+pub fn affected_subgraph(spec: &WorkflowSpec, dirty: &[u32]) -> WorkflowSpec {
+    let downstream: Vec<u32> = downstream_modules(spec, dirty).into_iter().collect();
+    let keep = reachable(spec, &downstream, false);
+
+    let mut subgraph = spec.clone();
+    subgraph.modules.retain(|m| keep.contains(&m.id));
+    subgraph.connections.retain(|c| keep.contains(&c.from_module) && keep.contains(&c.to_module));
+    subgraph
+}
+
+#[cfg(feature = "watch")]
+mod watcher {
+    use std::path::PathBuf;
+    use std::sync::{Arc, Weak};
+
+    use notify::{RecursiveMode, Watcher};
+    use tokio::sync::mpsc;
+    use tokio::time::Duration;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{affected_subgraph, modules_referencing, WatchEvent};
+    use crate::compute::WorkflowExecutor;
+
+    /// Stops watching when dropped
+    pub struct WatchHandle {
+        token: CancellationToken,
+    }
+
+    impl WatchHandle {
+        pub fn stop(&self) {
+            self.token.cancel();
+        }
+    }
+
+    impl Drop for WatchHandle {
+        fn drop(&mut self) {
+            self.token.cancel();
+        }
+    }
+
+    impl WorkflowExecutor {
+        /// Re-execute the affected part of a workflow whenever one of `paths` changes
+        ///
+        /// Changes arriving within `debounce` of each other coalesce into one
+        /// re-execution. Watching ends when the handle is dropped, the workflow
+        /// is cancelled or the executor is dropped; results are published as
+        /// `WatchEvent`s.
+        pub async fn watch(
+            self: &Arc<Self>,
+            workflow_id: &str,
+            paths: Vec<PathBuf>,
+            debounce: Duration,
+        ) -> Result<WatchHandle, crate::Error> {
+            if self.workflow_spec(workflow_id).await.is_none() {
+                return Err(crate::Error::Module(format!("Workflow {} not found", workflow_id)));
+            }
+
+            let (changes, mut changed) = mpsc::unbounded_channel();
+            let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                match event {
+                    Ok(event) if !event.kind.is_access() => {
+                        let _ = changes.send(event.paths);
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("File watch error: {}", e),
+                }
+            }).map_err(|e| crate::Error::Module(format!("Failed to create file watcher: {}", e)))?;
+            for path in &paths {
+                watcher.watch(path, RecursiveMode::Recursive)
+                    .map_err(|e| crate::Error::Module(format!("Failed to watch {}: {}", path.display(), e)))?;
+            }
+
+            let token = self.cancel_token(workflow_id).child_token();
+            let cancelled = token.clone();
+            let executor: Weak<Self> = Arc::downgrade(self);
+            let workflow_id = workflow_id.to_string();
+
+            tokio::spawn(async move {
+                // Keep the watcher alive for as long as the task runs
+                let _watcher = watcher;
+                loop {
+                    let mut batch = tokio::select! {
+                        _ = cancelled.cancelled() => break,
+                        paths = changed.recv() => match paths {
+                            Some(paths) => paths,
+                            None => break,
+                        },
+                    };
+
+                    // Coalesce until the files have been quiet for `debounce`
+                    loop {
+                        tokio::select! {
+                            _ = cancelled.cancelled() => return,
+                            _ = tokio::time::sleep(debounce) => break,
+                            paths = changed.recv() => match paths {
+                                Some(paths) => batch.extend(paths),
+                                None => return,
+                            },
+                        }
+                    }
+                    batch.sort();
+                    batch.dedup();
+
+                    let Some(executor) = executor.upgrade() else {
+                        break;
+                    };
+                    executor.reexecute(&workflow_id, batch).await;
+                }
+                tracing::debug!("Stopped watching files of workflow {}", workflow_id);
+            });
+
+            Ok(WatchHandle { token })
+        }
+
+        async fn reexecute(&self, workflow_id: &str, changed: Vec<PathBuf>) {
+            let Some(spec) = self.workflow_spec(workflow_id).await else {
+                return;
+            };
+            let dirty = modules_referencing(&spec, &changed);
+            if dirty.is_empty() {
+                tracing::debug!("Changed files are not referenced by workflow {}", workflow_id);
+                return;
+            }
+
+            let subgraph = affected_subgraph(&spec, &dirty);
+            let executed = subgraph.modules.iter().map(|m| m.id).collect();
+            tracing::info!("Files changed, re-executing {} modules of workflow {}", subgraph.modules.len(), workflow_id);
+
+            let result = self.execute_workflow(subgraph, None).await;
+            // Keep the full workflow registered for the next change
+            self.restore_workflow_spec(workflow_id, spec).await;
+
+            self.publish(WatchEvent {
+                workflow_id: workflow_id.to_string(),
+                changed,
+                dirty,
+                executed,
+                success: result.as_ref().map(|r| r.success).unwrap_or(false),
+                error: result.err().map(|e| e.to_string()),
+            });
+        }
+    }
+}
+
+#[cfg(feature = "watch")]
+pub use watcher::WatchHandle;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::WorkflowBuilder;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("vistle_watch_{}", uuid::Uuid::new_v4().simple()));
+            std::fs::create_dir_all(dir.join("fields")).unwrap();
+            std::fs::write(dir.join("mesh.raw"), b"mesh").unwrap();
+            std::fs::write(dir.join("fields/pressure.raw"), b"pressure").unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Mesh and Fields read files and feed Combine, Render depends on
+    /// Combine, Unrelated stands alone
+    fn workflow(base_dir: &Path) -> WorkflowSpec {
+        let mut spec = WorkflowBuilder::new("watched", "Watched")
+            .add_module("ConstantField", "Mesh")
+                .parameter("source", "mesh.raw")
+            .add_module("ConstantField", "Fields")
+                .parameter("source", "fields")
+            .add_module("ConstantField", "Combine")
+            .add_module("ConstantField", "Render")
+                .depends_on(3)
+            .add_module("ConstantField", "Unrelated")
+            .connect(1, "data_out", 3, "data_in")
+            .connect(2, "data_out", 3, "data_in")
+            .build();
+        spec.base_dir = Some(base_dir.to_path_buf());
+        spec
+    }
+
+    fn sorted(ids: impl IntoIterator<Item = u32>) -> Vec<u32> {
+        let mut ids: Vec<u32> = ids.into_iter().collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn changed_files_mark_the_modules_naming_them_or_their_directory() {
+        let dir = TempDir::new();
+        let spec = workflow(&dir.0);
+
+        assert_eq!(modules_referencing(&spec, &[dir.0.join("mesh.raw")]), [1]);
+        assert_eq!(modules_referencing(&spec, &[dir.0.join("fields/pressure.raw")]), [2]);
+        assert_eq!(modules_referencing(&spec, &[dir.0.join("mesh.raw"), dir.0.join("fields")]), [1, 2]);
+        assert!(modules_referencing(&spec, &[dir.0.join("notes.txt")]).is_empty());
+    }
+
+    #[test]
+    fn downstream_follows_connections_and_dependencies() {
+        let dir = TempDir::new();
+        let spec = workflow(&dir.0);
+
+        assert_eq!(sorted(downstream_modules(&spec, &[1])), [1, 3, 4]);
+        assert_eq!(sorted(downstream_modules(&spec, &[4])), [4]);
+        assert_eq!(sorted(downstream_modules(&spec, &[5])), [5]);
+    }
+
+    #[test]
+    fn the_affected_subgraph_keeps_the_inputs_of_downstream_modules() {
+        let dir = TempDir::new();
+        let spec = workflow(&dir.0);
+
+        let subgraph = affected_subgraph(&spec, &[1]);
+        assert_eq!(sorted(subgraph.modules.iter().map(|m| m.id)), [1, 2, 3, 4]);
+        assert_eq!(subgraph.connections.len(), 2);
+
+        let subgraph = affected_subgraph(&spec, &[4]);
+        assert_eq!(sorted(subgraph.modules.iter().map(|m| m.id)), [1, 2, 3, 4]);
+
+        let subgraph = affected_subgraph(&spec, &[5]);
+        assert_eq!(sorted(subgraph.modules.iter().map(|m| m.id)), [5]);
+        assert!(subgraph.connections.is_empty());
+    }
+
+    #[cfg(feature = "watch")]
+    mod watching {
+        use super::*;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        use tokio::sync::broadcast;
+
+        use crate::compute::testing::modules::register_test_modules;
+        use crate::compute::{ModuleRegistry, TaskExecutor, WorkflowExecutor};
+        use crate::core::MessageRouter;
+
+        const DEBOUNCE: Duration = Duration::from_millis(300);
+
+        async fn watched(dir: &TempDir) -> Arc<WorkflowExecutor> {
+            let registry = Arc::new(ModuleRegistry::new());
+            register_test_modules(&registry).await;
+            let executor = Arc::new(WorkflowExecutor::new(
+                registry,
+                Arc::new(TaskExecutor::new(2)),
+                Arc::new(MessageRouter::new()),
+            ));
+            let result = executor.execute_workflow(workflow(&dir.0), Some(Duration::from_secs(10))).await.unwrap();
+            assert!(result.success);
+            executor
+        }
+
+        async fn next_event(events: &mut broadcast::Receiver<WatchEvent>, within: Duration) -> Option<WatchEvent> {
+            tokio::time::timeout(within, events.recv()).await.ok().map(|event| event.unwrap())
+        }
+
+        #[tokio::test]
+        async fn a_changed_file_re_executes_the_affected_modules() {
+            let dir = TempDir::new();
+            let executor = watched(&dir).await;
+            let mut events = executor.subscribe_watch_events();
+            let _handle = executor.watch("watched", vec![dir.0.clone()], DEBOUNCE).await.unwrap();
+
+            std::fs::write(dir.0.join("mesh.raw"), b"new mesh").unwrap();
+            let event = next_event(&mut events, Duration::from_secs(10)).await.expect("no re-execution");
+            assert_eq!(event.workflow_id, "watched");
+            assert_eq!(event.dirty, [1]);
+            assert_eq!(sorted(event.executed.iter().copied()), [1, 2, 3, 4]);
+            assert!(event.success, "{:?}", event.error);
+
+            // The full workflow stays registered for the next change
+            std::fs::write(dir.0.join("fields/pressure.raw"), b"new pressure").unwrap();
+            let event = next_event(&mut events, Duration::from_secs(10)).await.expect("no second re-execution");
+            assert_eq!(event.dirty, [2]);
+        }
+
+        #[tokio::test]
+        async fn changes_in_quick_succession_coalesce() {
+            let dir = TempDir::new();
+            let executor = watched(&dir).await;
+            let mut events = executor.subscribe_watch_events();
+            let _handle = executor.watch("watched", vec![dir.0.clone()], DEBOUNCE).await.unwrap();
+
+            for i in 0..5 {
+                std::fs::write(dir.0.join("mesh.raw"), format!("mesh {}", i)).unwrap();
+                tokio::time::sleep(DEBOUNCE / 10).await;
+            }
+            std::fs::write(dir.0.join("fields/pressure.raw"), b"new pressure").unwrap();
+
+            let event = next_event(&mut events, Duration::from_secs(10)).await.expect("no re-execution");
+            assert_eq!(event.dirty, [1, 2]);
+            assert!(next_event(&mut events, DEBOUNCE * 3).await.is_none(), "changes were not coalesced");
+        }
+
+        #[tokio::test]
+        async fn unreferenced_files_and_stopped_watches_do_nothing() {
+            let dir = TempDir::new();
+            let executor = watched(&dir).await;
+            let mut events = executor.subscribe_watch_events();
+            let handle = executor.watch("watched", vec![dir.0.clone()], DEBOUNCE).await.unwrap();
+
+            std::fs::write(dir.0.join("notes.txt"), b"notes").unwrap();
+            assert!(next_event(&mut events, DEBOUNCE * 3).await.is_none());
+
+            drop(handle);
+            std::fs::write(dir.0.join("mesh.raw"), b"new mesh").unwrap();
+            assert!(next_event(&mut events, DEBOUNCE * 3).await.is_none());
+        }
+
+        #[tokio::test]
+        async fn watching_needs_a_known_workflow() {
+            let dir = TempDir::new();
+            let executor = watched(&dir).await;
+            assert!(executor.watch("unknown", vec![dir.0.clone()], DEBOUNCE).await.is_err());
+        }
+    }
+}
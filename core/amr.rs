@@ -0,0 +1,513 @@
+//! Adaptive mesh refinement hierarchies of uniform grid blocks
+
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Object, ObjectId, ObjectPayload, ObjectRegistry, ObjectType, TrianglesBuilder, VistleObject};
+
+/// Range of cells `[lo, hi)` in a level's index space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AmrExtent {
+    pub lo: [i32; 3],
+    pub hi: [i32; 3],
+}
+
+impl AmrExtent {
+    pub fn new(lo: [i32; 3], hi: [i32; 3]) -> Self {
+        Self { lo, hi }
+    }
+
+    /// Cells along each axis
+    pub fn dims(&self) -> [usize; 3] {
+        std::array::from_fn(|a| (self.hi[a] - self.lo[a]).max(0) as usize)
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.dims().iter().product()
+    }
+
+    pub fn contains(&self, cell: [i32; 3]) -> bool {
+        (0..3).all(|a| cell[a] >= self.lo[a] && cell[a] < self.hi[a])
+    }
+
+    /// The same region in the index space of a level `ratio` times finer
+    pub fn refine(&self, ratio: i32) -> Self {
+        Self {
+            lo: self.lo.map(|v| v * ratio),
+            hi: self.hi.map(|v| v * ratio),
+        }
+    }
+
+    /// Whether a cell of the next coarser level lies entirely inside this extent
+    fn covers_coarse(&self, cell: [i32; 3], ratio: i32) -> bool {
+        (0..3).all(|a| cell[a] * ratio >= self.lo[a] && (cell[a] + 1) * ratio <= self.hi[a])
+    }
+
+    /// Cell indices in x-fastest order
+    pub fn cells(&self) -> impl Iterator<Item = [i32; 3]> + '_ {
+        (self.lo[2]..self.hi[2]).flat_map(move |k| {
+            (self.lo[1]..self.hi[1]).flat_map(move |j| (self.lo[0]..self.hi[0]).map(move |i| [i, j, k]))
+        })
+    }
+}
+
+/// A uniform grid covering part of one refinement level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AmrBlock {
+    pub extent: AmrExtent,
+    /// Position of the block's first grid point
+    pub origin: [f32; 3],
+    pub spacing: [f32; 3],
+    /// UniformGrid object with one value per grid point of the extent
+    pub data: ObjectId,
+}
+
+/// Blocks of one refinement level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AmrLevel {
+    /// Refinement relative to the next coarser level; 1 for the coarsest
+    pub refinement_ratio: u32,
+    pub blocks: Vec<AmrBlock>,
+}
+
+/// A cell not covered by any finer block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmrCell {
+    pub level: usize,
+    pub block: usize,
+    pub index: [i32; 3],
+}
+
+/// Borrowed view of an `ObjectPayload::AmrHierarchy` with traversal helpers
+#[derive(Debug, Clone, Copy)]
+pub struct AmrView<'a> {
+    levels: &'a [AmrLevel],
+}
+
+impl ObjectPayload {
+    /// Helpers for AMR payloads
+    pub fn amr(&self) -> Option<AmrView<'_>> {
+        match self {
+            ObjectPayload::AmrHierarchy { levels } => Some(AmrView { levels }),
+            _ => None,
+        }
+    }
+
+    /// Value of a uniform grid point, x fastest
+    pub fn grid_value(&self, point: [usize; 3]) -> Option<f32> {
+        match self {
+            ObjectPayload::UniformGrid { dims, values, .. } if (0..3).all(|a| point[a] < dims[a]) => {
+                values.get(point[0] + dims[0] * (point[1] + dims[1] * point[2])).copied()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Blocks of one level with their grids
+type LevelBlocks<'a> = Vec<(&'a AmrBlock, Arc<dyn Object>)>;
+
+impl<'a> AmrView<'a> {
+    pub fn levels(&self) -> &'a [AmrLevel] {
+        self.levels
+    }
+
+    /// Ids of all block grids
+    pub fn block_ids(&self) -> Vec<ObjectId> {
+        self.levels.iter().flat_map(|l| l.blocks.iter().map(|b| b.data)).collect()
+    }
+
+    /// Whether a cell of `level` is covered by a block of the next finer level
+    pub fn is_covered(&self, level: usize, cell: [i32; 3]) -> bool {
+        let Some(finer) = self.levels.get(level + 1) else {
+            return false;
+        };
+        let ratio = finer.refinement_ratio as i32;
+        finer.blocks.iter().any(|b| b.extent.covers_coarse(cell, ratio))
+    }
+
+    /// Per cell of a block, x fastest: `true` where a finer block covers it
+    pub fn level_mask(&self, level: usize, block: usize) -> Vec<bool> {
+        self.levels.get(level)
+            .and_then(|l| l.blocks.get(block))
+            .map(|b| b.extent.cells().map(|cell| self.is_covered(level, cell)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Finest available cells: every cell not covered by a finer level
+    pub fn leaf_cells(&self) -> impl Iterator<Item = AmrCell> + '_ {
+        self.levels.iter().enumerate().flat_map(move |(level, l)| {
+            l.blocks.iter().enumerate().flat_map(move |(block, b)| {
+                b.extent.cells()
+                    .filter(move |&cell| !self.is_covered(level, cell))
+                    .map(move |index| AmrCell { level, block, index })
+            })
+        })
+    }
+
+    /// Refinement of `level` relative to the coarsest level
+    fn cumulative_ratio(&self, level: usize) -> i32 {
+        self.levels.iter().skip(1).take(level).map(|l| l.refinement_ratio as i32).product()
+    }
+
+    /// Resample the hierarchy onto one uniform grid at the resolution of `level`
+    ///
+    /// The grid spans the coarsest level's blocks; each point takes its value
+    /// from the finest block at or below `level` containing it, and is NaN
+    /// where no block does.
+    pub fn flatten(
+        &self,
+        level: usize,
+        resolve: impl Fn(ObjectId) -> Option<Arc<dyn Object>>,
+    ) -> Result<VistleObject, crate::Error> {
+        let coarsest = self.levels.first()
+            .filter(|l| !l.blocks.is_empty())
+            .ok_or_else(|| crate::Error::Compute("AMR hierarchy has no blocks".to_string()))?;
+        if level >= self.levels.len() {
+            return Err(crate::Error::Compute(format!(
+                "AMR hierarchy has no level {}, only {}",
+                level,
+                self.levels.len()
+            )));
+        }
+
+        let ratio = self.cumulative_ratio(level);
+        let domain = coarsest.blocks.iter().skip(1).fold(coarsest.blocks[0].extent, |d, b| AmrExtent {
+            lo: std::array::from_fn(|a| d.lo[a].min(b.extent.lo[a])),
+            hi: std::array::from_fn(|a| d.hi[a].max(b.extent.hi[a])),
+        }).refine(ratio);
+        let first = &coarsest.blocks[0];
+        let spacing = first.spacing.map(|s| s / ratio as f32);
+        let origin: [f32; 3] = std::array::from_fn(|a| {
+            first.origin[a] + (domain.lo[a] - first.extent.lo[a] * ratio) as f32 * spacing[a]
+        });
+
+        let grids = self.resolve_levels(level, &resolve)?;
+        let dims = domain.dims().map(|d| d + 1);
+        let mut values = Vec::with_capacity(dims.iter().product());
+        for k in 0..dims[2] {
+            for j in 0..dims[1] {
+                for i in 0..dims[0] {
+                    let p = Vector3::new(
+                        origin[0] + i as f32 * spacing[0],
+                        origin[1] + j as f32 * spacing[1],
+                        origin[2] + k as f32 * spacing[2],
+                    );
+                    let value = grids.iter().rev()
+                        .flat_map(|level| level.iter())
+                        .find_map(|(block, grid)| sample_block(block, grid.as_ref(), &p))
+                        .unwrap_or(f32::NAN);
+                    values.push(value);
+                }
+            }
+        }
+
+        Ok(VistleObject::with_data(ObjectType::UniformGrid, ObjectPayload::UniformGrid {
+            dims,
+            origin,
+            spacing,
+            values: ndarray::Array1::from(values),
+        }))
+    }
+
+    /// Block grids of levels `0..=last`, checked against their extents
+    fn resolve_levels(
+        &self,
+        last: usize,
+        resolve: &impl Fn(ObjectId) -> Option<Arc<dyn Object>>,
+    ) -> Result<Vec<LevelBlocks<'a>>, crate::Error> {
+        self.levels.iter().take(last + 1)
+            .map(|level| {
+                level.blocks.iter()
+                    .map(|block| {
+                        let grid = resolve(block.data).ok_or_else(|| crate::Error::Compute(format!(
+                            "AMR block grid {} is not available",
+                            block.data
+                        )))?;
+                        let expected = block.extent.dims().map(|d| d + 1);
+                        match grid.payload() {
+                            Some(ObjectPayload::UniformGrid { dims, .. }) if *dims == expected => Ok((block, grid)),
+                            _ => Err(crate::Error::Compute(format!(
+                                "AMR block grid {} is not a uniform grid with {:?} points",
+                                block.data, expected
+                            ))),
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Isosurface through the finest available cells
+    ///
+    /// Coarse cells covered by a finer block are skipped, so regions present
+    /// on several levels produce a single surface. Cells are split into
+    /// tetrahedra; triangles are not connected across cells.
+    pub fn isosurface(
+        &self,
+        iso_value: f32,
+        resolve: impl Fn(ObjectId) -> Option<Arc<dyn Object>>,
+    ) -> Result<VistleObject, crate::Error> {
+        let grids = self.resolve_levels(self.levels.len().saturating_sub(1), &resolve)?;
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        for cell in self.leaf_cells() {
+            let (block, grid) = &grids[cell.level][cell.block];
+            let payload = grid.payload().expect("checked by resolve_levels");
+            let local: [usize; 3] = std::array::from_fn(|a| (cell.index[a] - block.extent.lo[a]) as usize);
+            let corners: [(Vector3<f32>, f32); 8] = std::array::from_fn(|c| {
+                let offset = CUBE_CORNERS[c];
+                let point: [usize; 3] = std::array::from_fn(|a| local[a] + offset[a]);
+                let position = Vector3::from_fn(|a, _| block.origin[a] + point[a] as f32 * block.spacing[a]);
+                (position, payload.grid_value(point).unwrap_or(f32::NAN))
+            });
+            if corners.iter().any(|(_, v)| v.is_nan()) {
+                continue;
+            }
+            for tet in CUBE_TETS {
+                polygonize_tet(tet.map(|c| corners[c]), iso_value, &mut positions);
+            }
+        }
+
+        let triangles = (0..positions.len() as u32 / 3).map(|t| [3 * t, 3 * t + 1, 3 * t + 2]);
+        TrianglesBuilder::new()
+            .coordinates(positions.iter().copied())
+            .indices(triangles)
+            .build()
+    }
+}
+
+const CUBE_CORNERS: [[usize; 3]; 8] = [
+    [0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 1, 0],
+    [0, 0, 1], [1, 0, 1], [1, 1, 1], [0, 1, 1],
+];
+
+/// Six tetrahedra sharing the 0-6 diagonal
+const CUBE_TETS: [[usize; 4]; 6] = [
+    [0, 5, 1, 6], [0, 1, 2, 6], [0, 2, 3, 6],
+    [0, 3, 7, 6], [0, 7, 4, 6], [0, 4, 5, 6],
+];
+
+fn polygonize_tet(corners: [(Vector3<f32>, f32); 4], iso: f32, out: &mut Vec<[f32; 3]>) {
+    let crossing = |a: usize, b: usize| {
+        let ((pa, va), (pb, vb)) = (corners[a], corners[b]);
+        let t = if (vb - va).abs() > f32::EPSILON { (iso - va) / (vb - va) } else { 0.5 };
+        let p = pa + (pb - pa) * t;
+        [p.x, p.y, p.z]
+    };
+    let inside: Vec<usize> = (0..4).filter(|&i| corners[i].1 < iso).collect();
+    let outside: Vec<usize> = (0..4).filter(|&i| corners[i].1 >= iso).collect();
+    match (inside.as_slice(), outside.as_slice()) {
+        ([a], others) | (others, [a]) if others.len() == 3 => {
+            out.extend([crossing(*a, others[0]), crossing(*a, others[1]), crossing(*a, others[2])]);
+        }
+        ([a, b], [c, d]) => {
+            let (ac, bc, bd, ad) = (crossing(*a, *c), crossing(*b, *c), crossing(*b, *d), crossing(*a, *d));
+            out.extend([ac, bc, bd, ac, bd, ad]);
+        }
+        _ => {}
+    }
+}
+
+/// Trilinear sample of a block grid, `None` outside the block
+fn sample_block(block: &AmrBlock, grid: &dyn Object, p: &Vector3<f32>) -> Option<f32> {
+    let payload = grid.payload()?;
+    let dims = block.extent.dims();
+    let mut base = [0usize; 3];
+    let mut frac = [0f32; 3];
+    for a in 0..3 {
+        let local = (p[a] - block.origin[a]) / block.spacing[a];
+        let tolerance = 1e-4;
+        if local < -tolerance || local > dims[a] as f32 + tolerance {
+            return None;
+        }
+        let local = local.clamp(0.0, dims[a] as f32);
+        base[a] = (local.floor() as usize).min(dims[a].saturating_sub(1));
+        frac[a] = local - base[a] as f32;
+    }
+
+    let mut value = 0.0;
+    for corner in CUBE_CORNERS {
+        let weight: f32 = (0..3).map(|a| if corner[a] == 1 { frac[a] } else { 1.0 - frac[a] }).product();
+        if weight == 0.0 {
+            continue;
+        }
+        let point: [usize; 3] = std::array::from_fn(|a| (base[a] + corner[a]).min(dims[a]));
+        value += weight * payload.grid_value(point)?;
+    }
+    Some(value)
+}
+
+/// Uniform grid sampling a function at every grid point
+pub fn uniform_grid_from_fn(
+    origin: [f32; 3],
+    spacing: [f32; 3],
+    dims: [usize; 3],
+    f: impl Fn([f32; 3]) -> f32,
+) -> VistleObject {
+    let mut values = Vec::with_capacity(dims.iter().product());
+    for k in 0..dims[2] {
+        for j in 0..dims[1] {
+            for i in 0..dims[0] {
+                let index = [i, j, k];
+                values.push(f(std::array::from_fn(|a| origin[a] + index[a] as f32 * spacing[a])));
+            }
+        }
+    }
+    VistleObject::with_data(ObjectType::UniformGrid, ObjectPayload::UniformGrid {
+        dims,
+        origin,
+        spacing,
+        values: ndarray::Array1::from(values),
+    })
+}
+
+/// Builds hierarchies sampling an analytic field, for tests and demos
+pub struct AmrFixture<F: Fn([f32; 3]) -> f32> {
+    origin: [f32; 3],
+    spacing: [f32; 3],
+    field: F,
+    levels: Vec<AmrLevel>,
+    grids: Vec<VistleObject>,
+}
+
+impl<F: Fn([f32; 3]) -> f32> AmrFixture<F> {
+    /// Hierarchy whose coarsest level has cell 0 at `origin` with `spacing`
+    pub fn new(origin: [f32; 3], spacing: [f32; 3], field: F) -> Self {
+        Self {
+            origin,
+            spacing,
+            field,
+            levels: vec![AmrLevel { refinement_ratio: 1, blocks: Vec::new() }],
+            grids: Vec::new(),
+        }
+    }
+
+    /// Start the next finer level
+    pub fn refine(mut self, refinement_ratio: u32) -> Self {
+        self.levels.push(AmrLevel { refinement_ratio, blocks: Vec::new() });
+        self
+    }
+
+    /// Add a block covering `[lo, hi)` of the current level
+    pub fn block(mut self, lo: [i32; 3], hi: [i32; 3]) -> Self {
+        let ratio: i32 = self.levels.iter().skip(1).map(|l| l.refinement_ratio as i32).product();
+        let spacing = self.spacing.map(|s| s / ratio as f32);
+        let extent = AmrExtent::new(lo, hi);
+        let origin: [f32; 3] = std::array::from_fn(|a| self.origin[a] + lo[a] as f32 * spacing[a]);
+        let grid = uniform_grid_from_fn(origin, spacing, extent.dims().map(|d| d + 1), &self.field);
+        self.levels.last_mut().expect("fixture has a level").blocks.push(AmrBlock {
+            extent,
+            origin,
+            spacing,
+            data: grid.id(),
+        });
+        self.grids.push(grid);
+        self
+    }
+
+    /// The hierarchy object and its block grids
+    pub fn build(self) -> (VistleObject, Vec<VistleObject>) {
+        let hierarchy = VistleObject::with_data(
+            ObjectType::AmrHierarchy,
+            ObjectPayload::AmrHierarchy { levels: self.levels },
+        );
+        (hierarchy, self.grids)
+    }
+
+    /// Store the block grids in a registry and return the hierarchy object
+    pub fn build_into(self, registry: &ObjectRegistry) -> VistleObject {
+        let (hierarchy, grids) = self.build();
+        for grid in grids {
+            registry.store(Arc::new(grid));
+        }
+        hierarchy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2x2 coarse block whose first cell is refined by a 2x2x2 fine block
+    fn hierarchy(registry: &ObjectRegistry) -> VistleObject {
+        AmrFixture::new([0.0; 3], [1.0; 3], |p| p[0] + p[1] + 2.0 * p[2])
+            .block([0, 0, 0], [2, 2, 2])
+            .refine(2)
+            .block([0, 0, 0], [2, 2, 2])
+            .build_into(registry)
+    }
+
+    #[test]
+    fn the_fixture_builds_levels_with_matching_block_grids() {
+        let registry = ObjectRegistry::new();
+        let hierarchy = hierarchy(&registry);
+        let amr = hierarchy.payload().and_then(|p| p.amr()).unwrap();
+
+        assert_eq!(amr.levels().len(), 2);
+        assert_eq!(amr.levels()[1].refinement_ratio, 2);
+        let fine = &amr.levels()[1].blocks[0];
+        assert_eq!(fine.spacing, [0.5; 3]);
+        assert_eq!(fine.extent.num_cells(), 8);
+        for id in amr.block_ids() {
+            let grid = registry.get(id).unwrap();
+            assert!(matches!(grid.payload(), Some(ObjectPayload::UniformGrid { dims: [3, 3, 3], .. })));
+        }
+    }
+
+    #[test]
+    fn finer_blocks_cover_exactly_their_coarse_cells() {
+        let registry = ObjectRegistry::new();
+        let hierarchy = hierarchy(&registry);
+        let amr = hierarchy.payload().and_then(|p| p.amr()).unwrap();
+
+        assert!(amr.is_covered(0, [0, 0, 0]));
+        assert!(!amr.is_covered(0, [1, 0, 0]));
+        assert!(!amr.is_covered(1, [0, 0, 0]));
+        let mask = amr.level_mask(0, 0);
+        assert_eq!(mask.iter().filter(|&&covered| covered).count(), 1);
+        assert!(mask[0]);
+
+        let leaves: Vec<AmrCell> = amr.leaf_cells().collect();
+        assert_eq!(leaves.len(), 7 + 8);
+        assert!(!leaves.iter().any(|c| c.level == 0 && c.index == [0, 0, 0]));
+    }
+
+    #[test]
+    fn flattening_samples_the_finest_level_available() {
+        let registry = ObjectRegistry::new();
+        let hierarchy = hierarchy(&registry);
+        let amr = hierarchy.payload().and_then(|p| p.amr()).unwrap();
+
+        let flat = amr.flatten(1, |id| registry.get(id)).unwrap();
+        let Some(ObjectPayload::UniformGrid { dims, spacing, .. }) = flat.payload() else {
+            panic!("flatten produces a uniform grid");
+        };
+        assert_eq!((*dims, *spacing), ([5, 5, 5], [0.5; 3]));
+        let payload = flat.payload().unwrap();
+        assert_eq!(payload.grid_value([1, 1, 1]), Some(2.0));
+        assert_eq!(payload.grid_value([4, 4, 4]), Some(8.0));
+
+        assert!(amr.flatten(2, |id| registry.get(id)).is_err());
+        assert!(amr.flatten(1, |_| None).is_err());
+    }
+
+    #[test]
+    fn isosurfaces_use_only_leaf_cells() {
+        let registry = ObjectRegistry::new();
+        let hierarchy = AmrFixture::new([0.0; 3], [1.0; 3], |p| p[0])
+            .block([0, 0, 0], [2, 1, 1])
+            .refine(2)
+            .block([0, 0, 0], [2, 2, 2])
+            .build_into(&registry);
+        let amr = hierarchy.payload().and_then(|p| p.amr()).unwrap();
+
+        let surface = amr.isosurface(0.25, |id| registry.get(id)).unwrap();
+        let Some(ObjectPayload::Triangles { coordinates, triangles }) = surface.payload() else {
+            panic!("isosurface produces triangles");
+        };
+        assert!(triangles.nrows() > 0);
+        assert!(coordinates.rows().into_iter().all(|p| (p[0] - 0.25).abs() < 1e-5));
+    }
+}
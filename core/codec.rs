@@ -0,0 +1,305 @@
+//! Serialization codecs for messages and objects
+//!
+//! bincode is the default and always available. MessagePack (feature
+//! `msgpack`) lets tools outside Rust, such as a Python monitor, read
+//! messages; postcard (feature `postcard`) is a compact alternative.
+//! Connections agree on a codec during their handshake, see
+//! `CodecId::negotiate`.
+
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Message, MessageEnvelope, ObjectData};
+
+/// First byte of a buffer written by a `Codec`; the second one is the `CodecId`
+const CODEC_MARKER: u8 = 0xC5;
+
+/// Serialization formats known to this build, whether compiled in or not
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum CodecId {
+    #[default]
+    Bincode = 0,
+    MessagePack = 1,
+    Postcard = 2,
+}
+
+impl CodecId {
+    pub const ALL: [CodecId; 3] = [CodecId::Bincode, CodecId::MessagePack, CodecId::Postcard];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CodecId::Bincode => "bincode",
+            CodecId::MessagePack => "msgpack",
+            CodecId::Postcard => "postcard",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, crate::Error> {
+        CodecId::ALL.into_iter()
+            .find(|c| c.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| crate::Error::Config(format!(
+                "Unknown codec '{}'; known codecs are bincode, msgpack and postcard",
+                name
+            )))
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self, crate::Error> {
+        CodecId::ALL.into_iter()
+            .find(|c| *c as u8 == byte)
+            .ok_or_else(|| crate::Error::Config(format!("Unknown codec id {}", byte)))
+    }
+
+    /// Whether this build was compiled with the codec
+    pub fn is_available(self) -> bool {
+        match self {
+            CodecId::Bincode => true,
+            CodecId::MessagePack => cfg!(feature = "msgpack"),
+            CodecId::Postcard => cfg!(feature = "postcard"),
+        }
+    }
+
+    /// Codecs of this build, bincode first
+    pub fn available() -> Vec<CodecId> {
+        CodecId::ALL.into_iter().filter(|c| c.is_available()).collect()
+    }
+
+    /// Codec for a connection: the first of `preferred` that both sides have, else bincode
+    pub fn negotiate(preferred: &[CodecId], peer: &[CodecId]) -> CodecId {
+        preferred.iter()
+            .copied()
+            .find(|c| c.is_available() && peer.contains(c))
+            .unwrap_or(CodecId::Bincode)
+    }
+
+    fn unavailable(self) -> crate::Error {
+        crate::Error::Config(format!(
+            "Codec {} is not compiled into this build; enable the '{}' feature",
+            self.name(), self.name()
+        ))
+    }
+
+    /// Encode any serializable value with this codec, without a codec marker
+    pub fn serialize<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, crate::Error> {
+        let wrap = |e| crate::Error::serialization(self.name(), e);
+        match self {
+            CodecId::Bincode => bincode::serialize(value).map_err(|e| wrap(e.into())),
+            #[cfg(feature = "msgpack")]
+            CodecId::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| wrap(e.into())),
+            #[cfg(feature = "postcard")]
+            CodecId::Postcard => postcard::to_allocvec(value).map_err(|e| wrap(e.into())),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unavailable()),
+        }
+    }
+
+    /// Decode a value encoded with `serialize` by the same codec
+    pub fn deserialize<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, crate::Error> {
+        let wrap = |e| crate::Error::serialization(self.name(), e);
+        match self {
+            CodecId::Bincode => bincode::deserialize(bytes).map_err(|e| wrap(e.into())),
+            #[cfg(feature = "msgpack")]
+            CodecId::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| wrap(e.into())),
+            #[cfg(feature = "postcard")]
+            CodecId::Postcard => postcard::from_bytes(bytes).map_err(|e| wrap(e.into())),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unavailable()),
+        }
+    }
+
+    /// The codec implementation, if compiled in
+    pub fn codec(self) -> Result<&'static dyn Codec, crate::Error> {
+        match self {
+            CodecId::Bincode => Ok(&BincodeCodec),
+            #[cfg(feature = "msgpack")]
+            CodecId::MessagePack => Ok(&MessagePackCodec),
+            #[cfg(feature = "postcard")]
+            CodecId::Postcard => Ok(&PostcardCodec),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unavailable()),
+        }
+    }
+}
+
+impl fmt::Display for CodecId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Prefix `body` with the marker naming `codec`
+fn mark(codec: CodecId, body: Vec<u8>) -> Vec<u8> {
+    let mut marked = Vec::with_capacity(body.len() + 2);
+    marked.push(CODEC_MARKER);
+    marked.push(codec as u8);
+    marked.extend_from_slice(&body);
+    marked
+}
+
+/// Body of a marked buffer, refusing buffers written by another codec
+fn unmark(codec: CodecId, bytes: &[u8]) -> Result<&[u8], crate::Error> {
+    match bytes {
+        [CODEC_MARKER, id, body @ ..] if *id == codec as u8 => Ok(body),
+        [CODEC_MARKER, id, ..] => {
+            let written = CodecId::from_byte(*id).map(CodecId::name).unwrap_or("an unknown codec");
+            Err(crate::Error::serialization(
+                codec.name(),
+                format!("data was encoded with {}, not {}", written, codec.name()).into(),
+            ))
+        }
+        _ => Err(crate::Error::serialization(codec.name(), "data carries no codec marker".into())),
+    }
+}
+
+/// Serialization of messages and objects in one format
+///
+/// Buffers start with a two-byte marker naming the codec, so decoding data
+/// written by another codec fails with an error saying which one wrote it.
+pub trait Codec: Send + Sync + fmt::Debug {
+    fn id(&self) -> CodecId;
+
+    fn name(&self) -> &'static str {
+        self.id().name()
+    }
+
+    fn encode_message(&self, message: &Message) -> Result<Vec<u8>, crate::Error> {
+        Ok(mark(self.id(), self.id().serialize(message)?))
+    }
+
+    fn decode_message(&self, bytes: &[u8]) -> Result<Message, crate::Error> {
+        self.id().deserialize(unmark(self.id(), bytes)?)
+    }
+
+    /// Encode for a peer speaking protocol `version`, see `MessageEnvelope::encode_for`
+    fn encode_envelope(&self, envelope: &MessageEnvelope, version: u32) -> Result<Vec<u8>, crate::Error> {
+        Ok(mark(self.id(), envelope.encode_with(version, self.id())?))
+    }
+
+    fn decode_envelope(&self, bytes: &[u8]) -> Result<MessageEnvelope, crate::Error> {
+        MessageEnvelope::decode_with(unmark(self.id(), bytes)?, self.id())
+    }
+
+    fn encode_object(&self, data: &ObjectData) -> Result<Vec<u8>, crate::Error> {
+        Ok(mark(self.id(), self.id().serialize(data)?))
+    }
+
+    fn decode_object(&self, bytes: &[u8]) -> Result<ObjectData, crate::Error> {
+        self.id().deserialize(unmark(self.id(), bytes)?)
+    }
+}
+
+/// The default codec
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn id(&self) -> CodecId {
+        CodecId::Bincode
+    }
+}
+
+/// MessagePack with named struct fields, readable from Python's `msgpack`
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePackCodec {
+    fn id(&self) -> CodecId {
+        CodecId::MessagePack
+    }
+}
+
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl Codec for PostcardCodec {
+    fn id(&self) -> CodecId {
+        CodecId::Postcard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{MessagePayload, MessageType, Object, ObjectPayload, ObjectType, VistleObject};
+
+    fn envelope() -> MessageEnvelope {
+        MessageEnvelope {
+            message: Message::new(1, 2, MessageType::Execute { module_id: 3, timestep: 4 }),
+            payload: MessagePayload::ObjectData(vec![1, 2, 3]),
+        }
+    }
+
+    #[test]
+    fn codec_names_and_ids_parse() {
+        for codec in CodecId::ALL {
+            assert_eq!(CodecId::parse(codec.name()).unwrap(), codec);
+            assert_eq!(CodecId::from_byte(codec as u8).unwrap(), codec);
+        }
+        assert_eq!(CodecId::parse(" MsgPack ").unwrap(), CodecId::MessagePack);
+        assert!(CodecId::parse("json").is_err());
+        assert!(CodecId::from_byte(9).is_err());
+        assert_eq!(CodecId::available()[0], CodecId::Bincode);
+    }
+
+    #[test]
+    fn negotiation_picks_the_first_preferred_codec_both_sides_have() {
+        let all = CodecId::ALL;
+        assert_eq!(CodecId::negotiate(&[CodecId::Postcard, CodecId::Bincode], &[CodecId::Bincode]), CodecId::Bincode);
+        assert_eq!(CodecId::negotiate(&[CodecId::MessagePack], &[CodecId::Postcard]), CodecId::Bincode);
+        assert_eq!(CodecId::negotiate(&[], &all), CodecId::Bincode);
+
+        let expected = if CodecId::MessagePack.is_available() { CodecId::MessagePack } else { CodecId::Bincode };
+        assert_eq!(CodecId::negotiate(&[CodecId::MessagePack, CodecId::Bincode], &all), expected);
+    }
+
+    #[test]
+    fn every_available_codec_round_trips_messages_and_objects() {
+        let object = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: ndarray::array![1.5, -2.0] });
+        let data = object.as_data().unwrap();
+
+        for id in CodecId::available() {
+            let codec = id.codec().unwrap();
+            assert_eq!(codec.name(), id.name());
+
+            let sent = envelope();
+            let received = codec.decode_envelope(&codec.encode_envelope(&sent, crate::core::PROTOCOL_VERSION).unwrap()).unwrap();
+            assert_eq!(received.message.id, sent.message.id);
+            assert!(matches!(received.message.message_type, MessageType::Execute { module_id: 3, timestep: 4 }));
+            assert!(matches!(received.payload, MessagePayload::ObjectData(ref bytes) if bytes == &[1, 2, 3]));
+
+            let message = codec.decode_message(&codec.encode_message(&sent.message).unwrap()).unwrap();
+            assert_eq!(message.id, sent.message.id);
+
+            let decoded = codec.decode_object(&codec.encode_object(data).unwrap()).unwrap();
+            assert_eq!(decoded.id, data.id);
+            assert!(matches!(&*decoded.data, ObjectPayload::VecScalar { data } if data.to_vec() == vec![1.5, -2.0]));
+        }
+    }
+
+    #[test]
+    fn data_of_another_codec_is_refused_by_name() {
+        let bincode = CodecId::Bincode.codec().unwrap();
+        let mut foreign = bincode.encode_message(&envelope().message).unwrap();
+        foreign[1] = CodecId::Postcard as u8;
+        let error = bincode.decode_message(&foreign).unwrap_err().to_string();
+        assert!(error.contains("encoded with postcard, not bincode"), "{}", error);
+
+        assert!(bincode.decode_message(&[1, 2, 3]).is_err());
+        assert!(bincode.decode_object(&[]).is_err());
+    }
+
+    #[test]
+    fn codecs_missing_from_the_build_say_which_feature_to_enable() {
+        for id in CodecId::ALL.into_iter().filter(|c| !c.is_available()) {
+            let error = id.codec().unwrap_err().to_string();
+            assert!(error.contains(&format!("enable the '{}' feature", id.name())), "{}", error);
+            assert!(id.serialize(&1u32).is_err());
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Typed custom messages registered by name
+//!
+//! `MessageType::Custom` carries bytes tagged with a type id. Registering a
+//! Rust type under a name derives that id from a stable hash of the name,
+//! so every build agrees on it without coordination, and rejects names
+//! whose hash is already taken. The data of a registered custom message
+//! holds its type name along with the encoded value, so receivers that
+//! never registered the type can still tell what it is.
+
+use std::any::TypeId;
+use std::collections::HashMap;
+
+use parking_lot::{Mutex, RwLock};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::core::{Message, MessageType, UNKNOWN_VARIANT_FLAG};
+
+/// Id of a registered custom message type, derived from its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CustomTypeId(u32);
+
+impl CustomTypeId {
+    /// FNV-1a hash of the name, with `UNKNOWN_VARIANT_FLAG` cleared
+    pub fn from_name(name: &str) -> Self {
+        let hash = name.bytes().fold(0x811c_9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+        Self(hash & !UNKNOWN_VARIANT_FLAG)
+    }
+
+    pub fn value(self) -> u32 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for CustomTypeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010x}", self.0)
+    }
+}
+
+/// Contents of `MessageType::Custom::data` for registered types
+#[derive(Debug, Serialize, Deserialize)]
+struct CustomEnvelope {
+    name: String,
+    body: Vec<u8>,
+}
+
+/// A decoded custom message of type `T`
+#[derive(Debug, Clone)]
+pub struct CustomMessage<T> {
+    pub sender: u32,
+    pub recipient: u32,
+    pub value: T,
+}
+
+/// A custom message of a type not registered here
+#[derive(Debug, Clone)]
+pub struct RawCustomMessage {
+    pub sender: u32,
+    pub recipient: u32,
+    pub type_id: u32,
+    /// Type name the sender registered, if the data carries one
+    pub name: Option<String>,
+    /// Encoded value, without the name
+    pub data: Vec<u8>,
+}
+
+struct CustomType {
+    name: String,
+    rust_type: TypeId,
+    rust_name: &'static str,
+}
+
+/// Hands a message to one typed subscriber; false once the subscriber is gone
+type Delivery = Box<dyn Fn(&Message, &[u8]) -> bool + Send + Sync>;
+
+/// Registered custom message types and their subscribers
+#[derive(Default)]
+pub struct CustomTypeRegistry {
+    types: RwLock<HashMap<CustomTypeId, CustomType>>,
+    by_rust_type: RwLock<HashMap<TypeId, CustomTypeId>>,
+    subscribers: Mutex<HashMap<CustomTypeId, Vec<Delivery>>>,
+    unknown: Mutex<Vec<mpsc::UnboundedSender<RawCustomMessage>>>,
+}
+
+impl CustomTypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `T` under `name`
+    ///
+    /// Registering the same type under the same name again returns the same
+    /// id. A name whose id another name already has, a name taken by another
+    /// type, and a type already registered under another name are rejected.
+    pub fn register<T: Serialize + DeserializeOwned + Send + 'static>(&self, name: &str) -> Result<CustomTypeId, crate::Error> {
+        let id = CustomTypeId::from_name(name);
+        let rust_type = TypeId::of::<T>();
+        let mut types = self.types.write();
+        let mut by_rust_type = self.by_rust_type.write();
+
+        if let Some(existing) = types.get(&id) {
+            if existing.name != name {
+                return Err(crate::Error::Config(format!(
+                    "Custom message type {} collides with {} (both have id {})",
+                    name, existing.name, id
+                )));
+            }
+            if existing.rust_type != rust_type {
+                return Err(crate::Error::Config(format!(
+                    "Custom message type {} is already registered for {}",
+                    name, existing.rust_name
+                )));
+            }
+            return Ok(id);
+        }
+        if let Some(other) = by_rust_type.get(&rust_type).and_then(|other| types.get(other)) {
+            return Err(crate::Error::Config(format!(
+                "{} is already registered as custom message type {}",
+                std::any::type_name::<T>(), other.name
+            )));
+        }
+
+        types.insert(id, CustomType {
+            name: name.to_string(),
+            rust_type,
+            rust_name: std::any::type_name::<T>(),
+        });
+        by_rust_type.insert(rust_type, id);
+        Ok(id)
+    }
+
+    /// Id and name `T` was registered with
+    pub fn lookup<T: 'static>(&self) -> Option<(CustomTypeId, String)> {
+        let id = *self.by_rust_type.read().get(&TypeId::of::<T>())?;
+        let name = self.types.read().get(&id)?.name.clone();
+        Some((id, name))
+    }
+
+    pub fn name(&self, id: CustomTypeId) -> Option<String> {
+        self.types.read().get(&id).map(|t| t.name.clone())
+    }
+
+    /// Message type carrying `value`, which must be of a registered type
+    pub fn encode<T: Serialize + 'static>(&self, value: &T) -> Result<MessageType, crate::Error> {
+        let (id, name) = self.lookup::<T>().ok_or_else(|| crate::Error::Config(format!(
+            "{} is not registered as a custom message type",
+            std::any::type_name::<T>()
+        )))?;
+        let body = bincode::serialize(value).map_err(crate::Error::from)?;
+        let data = bincode::serialize(&CustomEnvelope { name, body }).map_err(crate::Error::from)?;
+        Ok(MessageType::Custom { type_id: id.value(), data })
+    }
+
+    /// Value of a custom message if it is of type `T`
+    pub fn decode<T: DeserializeOwned + 'static>(&self, message: &Message) -> Option<Result<T, crate::Error>> {
+        let MessageType::Custom { type_id, data } = &message.message_type else {
+            return None;
+        };
+        let (id, _) = self.lookup::<T>()?;
+        if id.value() != *type_id {
+            return None;
+        }
+        Some(bincode::deserialize::<CustomEnvelope>(data)
+            .and_then(|envelope| bincode::deserialize(&envelope.body))
+            .map_err(crate::Error::from))
+    }
+
+    /// Receive every custom message of type `T` delivered on this rank
+    pub fn subscribe<T: DeserializeOwned + Send + 'static>(&self) -> Result<mpsc::UnboundedReceiver<CustomMessage<T>>, crate::Error> {
+        let (id, name) = self.lookup::<T>().ok_or_else(|| crate::Error::Config(format!(
+            "{} is not registered as a custom message type",
+            std::any::type_name::<T>()
+        )))?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let delivery: Delivery = Box::new(move |message, body| {
+            match bincode::deserialize::<T>(body) {
+                Ok(value) => sender.send(CustomMessage {
+                    sender: message.sender,
+                    recipient: message.recipient,
+                    value,
+                }).is_ok(),
+                Err(e) => {
+                    tracing::warn!("Dropping custom message {} from {}: {}", name, message.sender, e);
+                    !sender.is_closed()
+                }
+            }
+        });
+        self.subscribers.lock().entry(id).or_default().push(delivery);
+        Ok(receiver)
+    }
+
+    /// Receive custom messages of types not registered here, as raw bytes
+    pub fn subscribe_unknown(&self) -> mpsc::UnboundedReceiver<RawCustomMessage> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.unknown.lock().push(sender);
+        receiver
+    }
+
+    /// Hand a custom message to its subscribers, returning whether any took it
+    ///
+    /// Preserved unknown protocol variants are not custom messages and are
+    /// left alone.
+    pub fn deliver(&self, message: &Message) -> bool {
+        let MessageType::Custom { type_id, data } = &message.message_type else {
+            return false;
+        };
+        if message.message_type.is_unknown() {
+            return false;
+        }
+        let envelope = bincode::deserialize::<CustomEnvelope>(data).ok();
+        let id = CustomTypeId(*type_id);
+
+        if self.types.read().contains_key(&id) {
+            let Some(envelope) = envelope else {
+                tracing::warn!("Dropping custom message {} from {} without a type name", id, message.sender);
+                return false;
+            };
+            let mut subscribers = self.subscribers.lock();
+            let Some(deliveries) = subscribers.get_mut(&id) else {
+                return false;
+            };
+            deliveries.retain(|deliver| deliver(message, &envelope.body));
+            return !deliveries.is_empty();
+        }
+
+        let (name, data) = match envelope {
+            Some(envelope) => (Some(envelope.name), envelope.body),
+            None => (None, data.clone()),
+        };
+        tracing::debug!(
+            "Custom message {} ({}) from {} is not registered here",
+            id, name.as_deref().unwrap_or("unnamed"), message.sender
+        );
+        let raw = RawCustomMessage {
+            sender: message.sender,
+            recipient: message.recipient,
+            type_id: *type_id,
+            name,
+            data,
+        };
+        let mut unknown = self.unknown.lock();
+        unknown.retain(|sender| sender.send(raw.clone()).is_ok());
+        !unknown.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::MessageRouter;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Steering {
+        step: u64,
+        isovalue: f32,
+        fields: Vec<String>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Other(u8);
+
+    fn steering() -> Steering {
+        Steering { step: 42, isovalue: 0.25, fields: vec!["pressure".to_string()] }
+    }
+
+    #[test]
+    fn ids_are_stable_hashes_of_the_name() {
+        assert_eq!(CustomTypeId::from_name(""), CustomTypeId(0x011c_9dc5));
+        assert_eq!(CustomTypeId::from_name("insitu.steering"), CustomTypeId::from_name("insitu.steering"));
+        assert_ne!(CustomTypeId::from_name("insitu.steering"), CustomTypeId::from_name("insitu.status"));
+        assert_eq!(CustomTypeId::from_name("insitu.steering").value() & UNKNOWN_VARIANT_FLAG, 0);
+    }
+
+    #[test]
+    fn colliding_and_reused_names_are_rejected() {
+        let registry = CustomTypeRegistry::new();
+        let id = registry.register::<Steering>("plugin.type631618").unwrap();
+        assert_eq!(registry.register::<Steering>("plugin.type631618").unwrap(), id);
+
+        // Both names hash to 0x3eca58d5
+        let collision = registry.register::<Other>("plugin.type819496").unwrap_err().to_string();
+        assert!(collision.contains("collides with plugin.type631618"), "{}", collision);
+        assert!(registry.register::<Other>("plugin.type631618").is_err());
+        assert!(registry.register::<Steering>("insitu.steering").is_err());
+        assert_eq!(registry.name(id).as_deref(), Some("plugin.type631618"));
+    }
+
+    #[test]
+    fn unregistered_types_cannot_be_sent_or_subscribed() {
+        let registry = CustomTypeRegistry::new();
+        assert!(registry.encode(&steering()).is_err());
+        assert!(registry.subscribe::<Steering>().is_err());
+    }
+
+    #[tokio::test]
+    async fn a_custom_struct_round_trips_through_the_router() {
+        let router = MessageRouter::new();
+        router.register_custom_type::<Steering>("insitu.steering").unwrap();
+        let mut received = router.subscribe_custom::<Steering>().unwrap();
+
+        router.send_custom_from(3, 7, &steering()).await.unwrap();
+
+        let message = received.try_recv().unwrap();
+        assert_eq!((message.sender, message.recipient), (3, 7));
+        assert_eq!(message.value, steering());
+    }
+
+    #[test]
+    fn messages_of_unregistered_types_arrive_raw_with_their_name() {
+        let sender = CustomTypeRegistry::new();
+        let id = sender.register::<Steering>("insitu.steering").unwrap();
+        let message = Message::new(1, 2, sender.encode(&steering()).unwrap());
+
+        let receiver = CustomTypeRegistry::new();
+        let mut unknown = receiver.subscribe_unknown();
+        assert!(receiver.deliver(&message));
+
+        let raw = unknown.try_recv().unwrap();
+        assert_eq!(raw.type_id, id.value());
+        assert_eq!(raw.name.as_deref(), Some("insitu.steering"));
+        assert_eq!(bincode::deserialize::<Steering>(&raw.data).unwrap(), steering());
+        assert!(receiver.decode::<Steering>(&message).is_none());
+    }
+
+    #[test]
+    fn raw_custom_data_without_an_envelope_is_passed_on_unnamed() {
+        let receiver = CustomTypeRegistry::new();
+        let mut unknown = receiver.subscribe_unknown();
+        let message = Message::new(1, 2, MessageType::Custom { type_id: 17, data: vec![1, 2, 3] });
+        assert!(receiver.deliver(&message));
+
+        let raw = unknown.try_recv().unwrap();
+        assert_eq!(raw.name, None);
+        assert_eq!(raw.data, [1, 2, 3]);
+    }
+}
@@ -0,0 +1,839 @@
+//! Validating builders for point, line, triangle and quad geometry, the
+//! conversion of quads and polygons to triangles, tube and sphere meshes
+//! generated from lines and points, and the reduction of connectivity to
+//! 32-bit index batches for rendering
+
+use std::collections::HashSet;
+
+use nalgebra::{Rotation3, Vector3};
+use ndarray::{Array1, Array2};
+
+use crate::core::{attribute, Object, ObjectPayload, ObjectType, PolygonsView, VistleObject};
+
+fn rows<const N: usize>(items: &[[f32; N]]) -> Array2<f32> {
+    Array2::from_shape_vec((items.len(), N), items.iter().flatten().copied().collect())
+        .expect("row-major shape matches item count")
+}
+
+fn validate_coordinates(kind: &str, coordinates: &[[f32; 3]]) -> Result<(), crate::Error> {
+    if let Some((i, p)) = coordinates.iter().enumerate().find(|(_, p)| p.iter().any(|c| !c.is_finite())) {
+        return Err(crate::Error::Compute(format!(
+            "{}: vertex {} has non-finite coordinates {:?}",
+            kind, i, p
+        )));
+    }
+    Ok(())
+}
+
+fn validate_indices<const N: usize>(
+    kind: &str,
+    element: &str,
+    indices: &[[u32; N]],
+    num_vertices: usize,
+) -> Result<Array2<i32>, crate::Error> {
+    for (i, element_indices) in indices.iter().enumerate() {
+        if let Some(&bad) = element_indices.iter().find(|&&v| v as usize >= num_vertices || v > i32::MAX as u32) {
+            return Err(crate::Error::Compute(format!(
+                "{}: {} {} references vertex {} but only {} vertices exist",
+                kind, element, i, bad, num_vertices
+            )));
+        }
+    }
+    let flat = indices.iter().flatten().map(|&v| v as i32).collect();
+    Ok(Array2::from_shape_vec((indices.len(), N), flat).expect("row-major shape matches item count"))
+}
+
+fn validate_normals(kind: &str, normals: &[[f32; 3]], num_vertices: usize) -> Result<(), crate::Error> {
+    if normals.len() != num_vertices {
+        return Err(crate::Error::Compute(format!(
+            "{}: {} normals given for {} vertices; normals are per vertex",
+            kind, normals.len(), num_vertices
+        )));
+    }
+    if let Some((i, n)) = normals.iter().enumerate().find(|(_, n)| n.iter().any(|c| !c.is_finite())) {
+        return Err(crate::Error::Compute(format!("{}: normal {} is not finite {:?}", kind, i, n)));
+    }
+    Ok(())
+}
+
+/// Per-vertex normals as a field object tagged with the normals attribute
+fn normals_object(normals: &[[f32; 3]]) -> VistleObject {
+    let mut object = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecVec3 { data: rows(normals) });
+    object.set_attribute(attribute::NORMALS.to_string(), "1".to_string());
+    object.set_attribute(attribute::MAPPING.to_string(), attribute::MAPPING_VERTEX.to_string());
+    object
+}
+
+/// Builder for point clouds
+#[derive(Debug, Clone, Default)]
+pub struct PointsBuilder {
+    coordinates: Vec<[f32; 3]>,
+}
+
+impl PointsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn coordinates(mut self, coordinates: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        self.coordinates.extend(coordinates);
+        self
+    }
+
+    pub fn build(self) -> Result<VistleObject, crate::Error> {
+        validate_coordinates("Points", &self.coordinates)?;
+        Ok(VistleObject::with_data(
+            ObjectType::Points,
+            ObjectPayload::Points { coordinates: rows(&self.coordinates) },
+        ))
+    }
+}
+
+/// Builder for line segments
+#[derive(Debug, Clone, Default)]
+pub struct LinesBuilder {
+    coordinates: Vec<[f32; 3]>,
+    segments: Vec<[u32; 2]>,
+}
+
+impl LinesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn coordinates(mut self, coordinates: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        self.coordinates.extend(coordinates);
+        self
+    }
+
+    /// Segments as pairs of vertex indices
+    pub fn indices(mut self, segments: impl IntoIterator<Item = [u32; 2]>) -> Self {
+        self.segments.extend(segments);
+        self
+    }
+
+    pub fn build(self) -> Result<VistleObject, crate::Error> {
+        validate_coordinates("Lines", &self.coordinates)?;
+        let connections = validate_indices("Lines", "segment", &self.segments, self.coordinates.len())?;
+        Ok(VistleObject::with_data(
+            ObjectType::Lines,
+            ObjectPayload::Lines {
+                coordinates: rows(&self.coordinates),
+                connections,
+            },
+        ))
+    }
+}
+
+/// Builder for triangle meshes
+#[derive(Debug, Clone, Default)]
+pub struct TrianglesBuilder {
+    coordinates: Vec<[f32; 3]>,
+    triangles: Vec<[u32; 3]>,
+    normals: Option<Vec<[f32; 3]>>,
+}
+
+impl TrianglesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn coordinates(mut self, coordinates: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        self.coordinates.extend(coordinates);
+        self
+    }
+
+    /// Triangles as triples of vertex indices
+    pub fn indices(mut self, triangles: impl IntoIterator<Item = [u32; 3]>) -> Self {
+        self.triangles.extend(triangles);
+        self
+    }
+
+    /// Per-vertex normals, returned by `build_with_normals`
+    pub fn normals(mut self, normals: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        self.normals.get_or_insert_with(Vec::new).extend(normals);
+        self
+    }
+
+    /// Build the mesh; normals are validated but only returned by `build_with_normals`
+    pub fn build(self) -> Result<VistleObject, crate::Error> {
+        self.build_with_normals().map(|(mesh, _)| mesh)
+    }
+
+    /// Build the mesh and, if normals were given, a vertex-mapped normals field
+    pub fn build_with_normals(self) -> Result<(VistleObject, Option<VistleObject>), crate::Error> {
+        validate_coordinates("Triangles", &self.coordinates)?;
+        let triangles = validate_indices("Triangles", "triangle", &self.triangles, self.coordinates.len())?;
+        if let Some(normals) = &self.normals {
+            validate_normals("Triangles", normals, self.coordinates.len())?;
+        }
+
+        let mesh = VistleObject::with_data(
+            ObjectType::Triangles,
+            ObjectPayload::Triangles {
+                coordinates: rows(&self.coordinates),
+                triangles,
+            },
+        );
+        Ok((mesh, self.normals.as_deref().map(normals_object)))
+    }
+}
+
+/// Builder for quad meshes
+#[derive(Debug, Clone, Default)]
+pub struct QuadsBuilder {
+    coordinates: Vec<[f32; 3]>,
+    quads: Vec<[u32; 4]>,
+}
+
+impl QuadsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn coordinates(mut self, coordinates: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        self.coordinates.extend(coordinates);
+        self
+    }
+
+    /// Quads as their four vertex indices in order around the quad
+    pub fn indices(mut self, quads: impl IntoIterator<Item = [u32; 4]>) -> Self {
+        self.quads.extend(quads);
+        self
+    }
+
+    pub fn build(self) -> Result<VistleObject, crate::Error> {
+        validate_coordinates("Quads", &self.coordinates)?;
+        let quads = validate_indices("Quads", "quad", &self.quads, self.coordinates.len())?;
+        Ok(VistleObject::with_data(
+            ObjectType::Quads,
+            ObjectPayload::Quads {
+                coordinates: rows(&self.coordinates),
+                quads,
+            },
+        ))
+    }
+}
+
+/// Triangles of quads, each split along the diagonal from its first corner
+///
+/// A quad with two coinciding corners gives the one triangle of its three
+/// distinct corners instead of a zero-area pair, and a quad with fewer than
+/// three distinct corners gives none.
+pub fn split_quads(quads: &Array2<i32>) -> Array2<i32> {
+    let mut triangles = Vec::with_capacity(quads.nrows() * 6);
+    for quad in quads.outer_iter() {
+        let mut corners: Vec<i32> = Vec::with_capacity(4);
+        for &v in quad {
+            if !corners.contains(&v) {
+                corners.push(v);
+            }
+        }
+        match corners[..] {
+            [a, b, c, d] => triangles.extend([a, b, c, a, c, d]),
+            [a, b, c] => triangles.extend([a, b, c]),
+            _ => {}
+        }
+    }
+    Array2::from_shape_vec((triangles.len() / 3, 3), triangles).expect("three indices per triangle")
+}
+
+impl ObjectPayload {
+    /// The surface as triangles over the same vertices
+    ///
+    /// Quads are split in two and polygons fanned out; triangles are
+    /// returned as they are. Vertex-mapped data stays valid, while
+    /// element-mapped data no longer lines up with the triangles.
+    pub fn to_triangles(&self) -> Result<ObjectPayload, crate::Error> {
+        match self {
+            ObjectPayload::Triangles { .. } => Ok(self.clone()),
+            ObjectPayload::Quads { coordinates, quads } => Ok(ObjectPayload::Triangles {
+                coordinates: coordinates.clone(),
+                triangles: split_quads(quads),
+            }),
+            ObjectPayload::Polygons { .. } => PolygonsView::new(self).expect("polygons payload").triangulate(),
+            _ => Err(crate::Error::WrongType {
+                expected: "triangles, quads or polygons".to_string(),
+                found: self.kind().to_string(),
+            }),
+        }
+    }
+}
+
+impl VistleObject {
+    /// Copy of a triangle, quad or polygon surface as triangles, with the
+    /// same metadata and attributes
+    pub fn to_triangles(&self) -> Result<VistleObject, crate::Error> {
+        let mut triangles = VistleObject::with_data(ObjectType::Triangles, self.data().to_triangles()?).with_meta(self.meta().clone());
+        for (key, value) in self.attributes() {
+            triangles.set_attribute(key.clone(), value.clone());
+        }
+        Ok(triangles)
+    }
+}
+
+/// Lengths below this count as zero; such segments are skipped
+const DEGENERATE_LENGTH: f32 = 1e-7;
+
+/// Triangle mesh generated around lines or points
+///
+/// `source` maps every generated vertex to the input vertex it was built
+/// around, so per-vertex data can be carried along with `carry`.
+#[derive(Debug, Clone, Default)]
+pub struct GlyphMesh {
+    pub coordinates: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub triangles: Vec<[u32; 3]>,
+    pub source: Vec<usize>,
+}
+
+impl GlyphMesh {
+    pub fn num_vertices(&self) -> usize {
+        self.coordinates.len()
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.triangles.len()
+    }
+
+    fn push_vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, source: usize) -> u32 {
+        self.coordinates.push(position.into());
+        self.normals.push(normal.into());
+        self.source.push(source);
+        (self.coordinates.len() - 1) as u32
+    }
+
+    /// Input per-vertex values mapped onto the generated vertices
+    pub fn carry(&self, values: &Array1<f32>) -> Array1<f32> {
+        self.source.iter().map(|&i| values.get(i).copied().unwrap_or(f32::NAN)).collect()
+    }
+
+    /// Triangle surface plus its vertex-mapped normals field
+    pub fn build(self) -> Result<(VistleObject, VistleObject), crate::Error> {
+        let (mesh, normals) = TrianglesBuilder::new()
+            .coordinates(self.coordinates)
+            .indices(self.triangles)
+            .normals(self.normals)
+            .build_with_normals()?;
+        Ok((mesh, normals.expect("normals were given")))
+    }
+}
+
+/// Chains of vertex indices formed by consecutive segments sharing an end
+fn polylines(connections: &Array2<i32>) -> Vec<Vec<usize>> {
+    let mut lines: Vec<Vec<usize>> = Vec::new();
+    for segment in connections.outer_iter() {
+        let (a, b) = (segment[0] as usize, segment[1] as usize);
+        match lines.last_mut() {
+            Some(line) if line.last() == Some(&a) => line.push(b),
+            _ => lines.push(vec![a, b]),
+        }
+    }
+    lines
+}
+
+/// Some unit vector perpendicular to `v`
+fn perpendicular(v: &Vector3<f32>) -> Vector3<f32> {
+    let axis = if v.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+    v.cross(&axis).normalize()
+}
+
+/// Triangulated tube of `sides` facets along every polyline of a lines object
+///
+/// Consecutive segments sharing a vertex form one polyline; each gets flat
+/// end caps. The cross-section frame is parallel transported along the
+/// line, so tubes do not twist. `radius` gives the radius at an input vertex.
+/// Zero-length segments are skipped, as are lines with no length at all.
+pub fn tube_from_lines(lines: &dyn Object, radius: impl Fn(usize) -> f32, sides: usize) -> Result<GlyphMesh, crate::Error> {
+    let Some(ObjectPayload::Lines { coordinates, connections }) = lines.payload() else {
+        return Err(crate::Error::wrong_type("lines", lines));
+    };
+    if sides < 3 {
+        return Err(crate::Error::Config(format!("Tubes need at least 3 sides, got {}", sides)));
+    }
+    let num_vertices = coordinates.nrows();
+    if let Some(bad) = connections.iter().find(|&&v| v < 0 || v as usize >= num_vertices) {
+        return Err(crate::Error::Compute(format!(
+            "Lines reference vertex {} but only {} vertices exist",
+            bad, num_vertices
+        )));
+    }
+    let point = |i: usize| Vector3::new(coordinates[[i, 0]], coordinates[[i, 1]], coordinates[[i, 2]]);
+
+    let mut mesh = GlyphMesh::default();
+    for line in polylines(connections) {
+        // Drop vertices that coincide with their predecessor
+        let mut path: Vec<usize> = Vec::with_capacity(line.len());
+        for v in line {
+            if path.last().is_none_or(|&last| (point(v) - point(last)).norm() > DEGENERATE_LENGTH) {
+                path.push(v);
+            }
+        }
+        if path.len() < 2 {
+            continue;
+        }
+
+        let directions: Vec<Vector3<f32>> = path.windows(2).map(|w| (point(w[1]) - point(w[0])).normalize()).collect();
+        // Tangent at a vertex: mean of the adjacent segment directions
+        let tangents: Vec<Vector3<f32>> = (0..path.len())
+            .map(|i| {
+                let before = directions[i.saturating_sub(1)];
+                let after = directions[i.min(directions.len() - 1)];
+                let mean = before + after;
+                if mean.norm() > DEGENERATE_LENGTH { mean.normalize() } else { after }
+            })
+            .collect();
+
+        let mut normal = perpendicular(&tangents[0]);
+        let mut rings = Vec::with_capacity(path.len());
+        for (i, (&v, tangent)) in path.iter().zip(&tangents).enumerate() {
+            if i > 0 {
+                // Parallel transport: rotate the frame by the turn of the tangent
+                normal = match Rotation3::rotation_between(&tangents[i - 1], tangent) {
+                    Some(turn) => turn * normal,
+                    None => normal,
+                };
+                normal = (normal - tangent * normal.dot(tangent)).try_normalize(DEGENERATE_LENGTH)
+                    .unwrap_or_else(|| perpendicular(tangent));
+            }
+            let binormal = tangent.cross(&normal);
+            let r = radius(v);
+            let first = mesh.num_vertices() as u32;
+            for side in 0..sides {
+                let angle = side as f32 / sides as f32 * std::f32::consts::TAU;
+                let direction = normal * angle.cos() + binormal * angle.sin();
+                mesh.push_vertex(point(v) + direction * r, direction, v);
+            }
+            rings.push((first, v, *tangent, normal, binormal, r));
+        }
+
+        for pair in rings.windows(2) {
+            let (a0, b0) = (pair[0].0, pair[1].0);
+            for side in 0..sides as u32 {
+                let next = (side + 1) % sides as u32;
+                let (a, b, c, d) = (a0 + side, a0 + next, b0 + side, b0 + next);
+                mesh.triangles.push([a, b, c]);
+                mesh.triangles.push([b, d, c]);
+            }
+        }
+
+        // End caps get their own rim vertices so their normals stay flat
+        for (cap, outward) in [(rings[0], -1.0f32), (rings[rings.len() - 1], 1.0)] {
+            let (_, v, tangent, normal, binormal, r) = cap;
+            let cap_normal = tangent * outward;
+            let center = mesh.push_vertex(point(v), cap_normal, v);
+            for side in 0..sides {
+                let angle = side as f32 / sides as f32 * std::f32::consts::TAU;
+                mesh.push_vertex(point(v) + (normal * angle.cos() + binormal * angle.sin()) * r, cap_normal, v);
+            }
+            for side in 0..sides as u32 {
+                let (j, next) = (center + 1 + side, center + 1 + (side + 1) % sides as u32);
+                mesh.triangles.push(if outward > 0.0 { [center, j, next] } else { [center, next, j] });
+            }
+        }
+    }
+    Ok(mesh)
+}
+
+/// Unit icosphere: an icosahedron whose triangles are split `subdivisions` times
+///
+/// Has `20 * 4^subdivisions` triangles; vertices double as normals.
+pub fn icosphere(subdivisions: u32) -> (Vec<Vector3<f32>>, Vec<[u32; 3]>) {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut vertices: Vec<Vector3<f32>> = [
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ].iter().map(|p| Vector3::from(*p).normalize()).collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = std::collections::HashMap::new();
+        let mut midpoint = |a: u32, b: u32, vertices: &mut Vec<Vector3<f32>>| -> u32 {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                vertices.push(((vertices[a as usize] + vertices[b as usize]) * 0.5).normalize());
+                (vertices.len() - 1) as u32
+            })
+        };
+        triangles = triangles.iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut vertices);
+                let bc = midpoint(b, c, &mut vertices);
+                let ca = midpoint(c, a, &mut vertices);
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+    (vertices, triangles)
+}
+
+/// One icosphere per vertex of a points (or any geometry) object, merged into one mesh
+///
+/// Vertices with a non-positive or non-finite radius get no sphere. For
+/// instanced rendering use `icosphere` directly.
+pub fn spheres_from_points(points: &dyn Object, radius: impl Fn(usize) -> f32, subdivisions: u32) -> Result<GlyphMesh, crate::Error> {
+    let coordinates = points.payload()
+        .and_then(ObjectPayload::coordinates)
+        .ok_or_else(|| crate::Error::wrong_type("points", points))?;
+    let (sphere, sphere_triangles) = icosphere(subdivisions);
+
+    let mut mesh = GlyphMesh::default();
+    for (v, row) in coordinates.outer_iter().enumerate() {
+        let r = radius(v);
+        if !(r.is_finite() && r > 0.0) {
+            continue;
+        }
+        let center = Vector3::new(row[0], row[1], row[2]);
+        let first = mesh.num_vertices() as u32;
+        for direction in &sphere {
+            mesh.push_vertex(center + direction * r, *direction, v);
+        }
+        mesh.triangles.extend(sphere_triangles.iter().map(|t| t.map(|i| first + i)));
+    }
+    Ok(mesh)
+}
+
+/// Most distinct vertices one batch of `compact_indices` references, so its indices fit u32
+pub const MAX_BATCH_VERTICES: usize = u32::MAX as usize;
+
+/// One draw batch of a mesh with 32-bit indices into a subset of its vertices
+///
+/// `vertex_remap` lists the original vertex of every batch vertex in
+/// ascending order; per-vertex data follows the remap with `carry`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactedMesh {
+    /// Connectivity of the batch's elements, indexing `vertex_remap`
+    pub indices: Vec<u32>,
+    pub vertex_remap: Vec<usize>,
+}
+
+impl CompactedMesh {
+    pub fn num_vertices(&self) -> usize {
+        self.vertex_remap.len()
+    }
+
+    /// Whether the batch uses every one of `num_vertices` original vertices,
+    /// so its indices are the original ones
+    pub fn is_identity(&self, num_vertices: usize) -> bool {
+        // The remap is ascending and distinct, so this means 0..num_vertices
+        self.vertex_remap.len() == num_vertices && self.vertex_remap.last().is_none_or(|&v| v + 1 == num_vertices)
+    }
+
+    /// Per-vertex values of the original vertices mapped onto the batch's
+    pub fn carry(&self, values: &Array1<f32>) -> Array1<f32> {
+        self.vertex_remap.iter().map(|&i| values.get(i).copied().unwrap_or(f32::NAN)).collect()
+    }
+
+    /// Rows of per-vertex data, e.g. coordinates, mapped onto the batch's vertices
+    pub fn carry_rows(&self, rows: &Array2<f32>) -> Result<Array2<f32>, crate::Error> {
+        if let Some(&bad) = self.vertex_remap.iter().find(|&&v| v >= rows.nrows()) {
+            return Err(crate::Error::Compute(format!(
+                "Connectivity references vertex {} but only {} vertices exist",
+                bad, rows.nrows()
+            )));
+        }
+        Ok(rows.select(ndarray::Axis(0), &self.vertex_remap))
+    }
+}
+
+/// Reduce connectivity of elements with `vertices_per_element` vertices each to u32 batches
+///
+/// Each batch references at most `MAX_BATCH_VERTICES` distinct vertices;
+/// larger meshes are split between elements into several batches rather
+/// than rejected. Negative indices are an error.
+pub fn compact_indices(connectivity: &Array1<i64>, vertices_per_element: usize) -> Result<Vec<CompactedMesh>, crate::Error> {
+    compact_indices_with_limit(connectivity.iter().copied(), vertices_per_element, MAX_BATCH_VERTICES)
+}
+
+/// `compact_indices` for the i32 connectivity rows of lines and triangles
+pub fn compact_connectivity(connectivity: &Array2<i32>) -> Result<Vec<CompactedMesh>, crate::Error> {
+    compact_indices_with_limit(connectivity.iter().map(|&i| i as i64), connectivity.ncols(), MAX_BATCH_VERTICES)
+}
+
+/// `compact_indices` with batches of at most `max_vertices` distinct vertices
+pub fn compact_indices_with_limit(
+    connectivity: impl IntoIterator<Item = i64>,
+    vertices_per_element: usize,
+    max_vertices: usize,
+) -> Result<Vec<CompactedMesh>, crate::Error> {
+    if vertices_per_element == 0 || vertices_per_element > max_vertices {
+        return Err(crate::Error::Compute(format!(
+            "Elements of {} vertices do not fit batches of at most {} vertices",
+            vertices_per_element, max_vertices
+        )));
+    }
+
+    let mut batches = Vec::new();
+    let mut used = HashSet::new();
+    let mut elements: Vec<usize> = Vec::new();
+    let mut element = Vec::with_capacity(vertices_per_element);
+    for (position, index) in connectivity.into_iter().enumerate() {
+        let vertex = usize::try_from(index).map_err(|_| crate::Error::Compute(format!(
+            "Connectivity entry {} references vertex {}",
+            position, index
+        )))?;
+        element.push(vertex);
+        if element.len() < vertices_per_element {
+            continue;
+        }
+        let new = element.iter()
+            .enumerate()
+            .filter(|&(i, v)| !used.contains(v) && !element[..i].contains(v))
+            .count();
+        if used.len() + new > max_vertices {
+            batches.push(finish_batch(&mut used, &mut elements));
+        }
+        used.extend(element.iter().copied());
+        elements.append(&mut element);
+    }
+    if !element.is_empty() {
+        return Err(crate::Error::Compute(format!(
+            "Connectivity ends within an element: {} entries are not a multiple of {}",
+            elements.len() + element.len() + batches.iter().map(|b: &CompactedMesh| b.indices.len()).sum::<usize>(),
+            vertices_per_element
+        )));
+    }
+    if !elements.is_empty() || batches.is_empty() {
+        batches.push(finish_batch(&mut used, &mut elements));
+    }
+    Ok(batches)
+}
+
+fn finish_batch(used: &mut HashSet<usize>, elements: &mut Vec<usize>) -> CompactedMesh {
+    let mut vertex_remap: Vec<usize> = used.drain().collect();
+    vertex_remap.sort_unstable();
+    let indices = elements.drain(..)
+        .map(|v| vertex_remap.binary_search(&v).expect("element vertices are in the batch") as u32)
+        .collect();
+    CompactedMesh { indices, vertex_remap }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: [[f32; 3]; 4] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+
+    fn error_message(result: Result<VistleObject, crate::Error>) -> String {
+        result.expect_err("invalid input was accepted").to_string()
+    }
+
+    #[test]
+    fn triangles_build_with_accessors() {
+        let (mesh, normals) = TrianglesBuilder::new()
+            .coordinates(SQUARE)
+            .indices([[0, 1, 2], [0, 2, 3]])
+            .normals([[0.0, 0.0, 1.0]; 4])
+            .build_with_normals()
+            .unwrap();
+
+        let payload = mesh.data();
+        assert_eq!(payload.num_vertices(), 4);
+        assert_eq!(payload.num_triangles(), 2);
+        assert_eq!(payload.positions().unwrap().shape(), &[4, 3]);
+        assert_eq!(payload.positions().unwrap().row(2).to_vec(), vec![1.0, 1.0, 0.0]);
+        assert_eq!(payload.triangle(1), Some([0, 2, 3]));
+        assert_eq!(payload.triangle(2), None);
+
+        let normals = normals.unwrap();
+        assert_eq!(normals.get_attribute(attribute::NORMALS), Some("1"));
+        assert_eq!(normals.get_attribute(attribute::MAPPING), Some(attribute::MAPPING_VERTEX));
+    }
+
+    #[test]
+    fn points_and_lines_build_with_accessors() {
+        let points = PointsBuilder::new().coordinates(SQUARE).build().unwrap();
+        assert_eq!(points.data().num_vertices(), 4);
+        assert_eq!(points.data().triangle(0), None);
+
+        let lines = LinesBuilder::new().coordinates(SQUARE).indices([[0, 1], [1, 2], [2, 3]]).build().unwrap();
+        assert_eq!(lines.data().num_lines(), 3);
+        assert_eq!(lines.data().line(2), Some([2, 3]));
+        assert_eq!(lines.data().line(3), None);
+    }
+
+    #[test]
+    fn invalid_inputs_name_the_offending_element() {
+        let message = error_message(TrianglesBuilder::new().coordinates(SQUARE).indices([[0, 1, 2], [0, 2, 4]]).build());
+        assert!(message.contains("triangle 1 references vertex 4 but only 4 vertices exist"), "{}", message);
+
+        let message = error_message(PointsBuilder::new().coordinates([[0.0, 0.0, 0.0], [f32::NAN, 0.0, 0.0]]).build());
+        assert!(message.contains("vertex 1 has non-finite coordinates"), "{}", message);
+
+        let message = error_message(LinesBuilder::new().coordinates(SQUARE).indices([[3, 7]]).build());
+        assert!(message.contains("segment 0 references vertex 7"), "{}", message);
+
+        let message = error_message(TrianglesBuilder::new().coordinates(SQUARE).indices([[0, 1, 2]]).normals([[0.0, 0.0, 1.0]; 3]).build());
+        assert!(message.contains("3 normals given for 4 vertices"), "{}", message);
+
+        let message = error_message(TrianglesBuilder::new().coordinates(SQUARE).normals([[0.0, f32::INFINITY, 1.0]; 4]).build());
+        assert!(message.contains("normal 0 is not finite"), "{}", message);
+    }
+
+    #[test]
+    fn random_indices_are_accepted_only_in_range() {
+        // Deterministic linear congruential sequence; any index of 8 or more is out of range
+        let mut state = 12345u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) % 10
+        };
+        let coordinates: Vec<[f32; 3]> = (0..8).map(|i| [i as f32, 0.0, 0.0]).collect();
+        for _ in 0..200 {
+            let triangles: Vec<[u32; 3]> = (0..4).map(|_| [next(), next(), next()]).collect();
+            let valid = triangles.iter().flatten().all(|&v| v < 8);
+            let result = TrianglesBuilder::new().coordinates(coordinates.clone()).indices(triangles.clone()).build();
+            assert_eq!(result.is_ok(), valid, "{:?}", triangles);
+        }
+    }
+
+    fn line(coordinates: &[[f32; 3]]) -> VistleObject {
+        let segments: Vec<[u32; 2]> = (1..coordinates.len() as u32).map(|i| [i - 1, i]).collect();
+        LinesBuilder::new().coordinates(coordinates.iter().copied()).indices(segments).build().unwrap()
+    }
+
+    #[test]
+    fn tubes_have_rings_sides_and_caps() {
+        let lines = line(&[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]]);
+        let mesh = tube_from_lines(&lines, |_| 0.5, 4).unwrap();
+
+        // Three rings of four plus two caps of a center and four rim vertices
+        assert_eq!(mesh.num_vertices(), 3 * 4 + 2 * 5);
+        assert_eq!(mesh.num_triangles(), 2 * 4 * 2 + 2 * 4);
+        for (position, normal) in mesh.coordinates.iter().zip(&mesh.normals) {
+            let distance = (position[1] * position[1] + position[2] * position[2]).sqrt();
+            assert!(distance < 1e-6 || (distance - 0.5).abs() < 1e-6, "{:?}", position);
+            assert!((Vector3::from(*normal).norm() - 1.0).abs() < 1e-5);
+        }
+        assert_eq!(mesh.carry(&Array1::from(vec![1.0, 2.0, 3.0])).len(), mesh.num_vertices());
+        assert!(mesh.source.iter().all(|&v| v < 3));
+
+        let (surface, normals) = mesh.build().unwrap();
+        assert_eq!(surface.data().num_triangles(), 24);
+        assert_eq!(normals.get_attribute(attribute::NORMALS), Some("1"));
+    }
+
+    #[test]
+    fn tube_frames_stay_perpendicular_around_bends() {
+        let lines = line(&[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0]]);
+        let mesh = tube_from_lines(&lines, |v| 0.1 * (v + 1) as f32, 6).unwrap();
+        // Ring vertices of the last input vertex lie in the plane across the last segment
+        let last_ring = &mesh.coordinates[3 * 6..4 * 6];
+        assert!(last_ring.iter().all(|p| (p[2] - 1.0).abs() < 1e-5));
+        assert!(last_ring.iter().all(|p| (((p[0] - 1.0).powi(2) + (p[1] - 1.0).powi(2)).sqrt() - 0.4).abs() < 1e-5));
+    }
+
+    #[test]
+    fn degenerate_segments_are_skipped() {
+        let lines = line(&[[0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]]);
+        let mesh = tube_from_lines(&lines, |_| 1.0, 3).unwrap();
+        assert_eq!(mesh.num_vertices(), 2 * 3 + 2 * 4);
+
+        let point_like = line(&[[1.0, 1.0, 1.0], [1.0, 1.0, 1.0]]);
+        assert_eq!(tube_from_lines(&point_like, |_| 1.0, 3).unwrap().num_vertices(), 0);
+    }
+
+    #[test]
+    fn invalid_tube_inputs_are_rejected() {
+        let lines = line(&SQUARE);
+        assert!(tube_from_lines(&lines, |_| 1.0, 2).is_err());
+
+        let points = PointsBuilder::new().coordinates(SQUARE).build().unwrap();
+        assert!(matches!(tube_from_lines(&points, |_| 1.0, 8), Err(crate::Error::WrongType { .. })));
+    }
+
+    #[test]
+    fn icospheres_subdivide_on_the_unit_sphere() {
+        for (subdivisions, vertices, triangles) in [(0, 12, 20), (1, 42, 80), (2, 162, 320)] {
+            let (sphere, faces) = icosphere(subdivisions);
+            assert_eq!((sphere.len(), faces.len()), (vertices, triangles));
+            assert!(sphere.iter().all(|v| (v.norm() - 1.0).abs() < 1e-5));
+        }
+    }
+
+    #[test]
+    fn spheres_are_placed_on_points_with_a_positive_radius() {
+        let points = PointsBuilder::new().coordinates([[5.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 5.0, 0.0]]).build().unwrap();
+        let radii = [2.0, 0.0, f32::NAN];
+        let mesh = spheres_from_points(&points, |v| radii[v], 0).unwrap();
+
+        assert_eq!((mesh.num_vertices(), mesh.num_triangles()), (12, 20));
+        assert!(mesh.source.iter().all(|&v| v == 0));
+        for position in &mesh.coordinates {
+            let offset = Vector3::from(*position) - Vector3::new(5.0, 0.0, 0.0);
+            assert!((offset.norm() - 2.0).abs() < 1e-5);
+        }
+        assert!(mesh.triangles.iter().flatten().all(|&i| (i as usize) < mesh.num_vertices()));
+    }
+
+    #[test]
+    fn indices_beyond_u32_are_compacted_into_range() {
+        let connectivity = Array1::from(vec![0i64, 5_000_000_000, 7]);
+        let batches = compact_indices(&connectivity, 3).unwrap();
+        assert_eq!(batches, [CompactedMesh { indices: vec![0, 2, 1], vertex_remap: vec![0, 7, 5_000_000_000] }]);
+        assert!(!batches[0].is_identity(8));
+    }
+
+    #[test]
+    fn batches_split_between_elements_at_the_vertex_limit() {
+        let batches = compact_indices_with_limit([0, 1, 2, 2, 1, 3, 3, 4, 5], 3, 4).unwrap();
+        assert_eq!(batches, [
+            CompactedMesh { indices: vec![0, 1, 2, 2, 1, 3], vertex_remap: vec![0, 1, 2, 3] },
+            CompactedMesh { indices: vec![0, 1, 2], vertex_remap: vec![3, 4, 5] },
+        ]);
+        assert!(batches[0].is_identity(4));
+
+        let coordinates = Array2::from_shape_fn((6, 3), |(v, _)| v as f32);
+        assert_eq!(batches[1].carry_rows(&coordinates).unwrap().column(0).to_vec(), [3.0, 4.0, 5.0]);
+        let carried = batches[1].carry(&Array1::from(vec![0.0, 1.0, 2.0, 3.0]));
+        assert_eq!(carried[0], 3.0);
+        assert!(carried[1].is_nan() && carried[2].is_nan());
+    }
+
+    #[test]
+    fn unfit_connectivity_is_rejected() {
+        let message = compact_indices_with_limit([0, 1, -1], 3, 4).unwrap_err().to_string();
+        assert!(message.contains("Connectivity entry 2 references vertex -1"), "{}", message);
+
+        let message = compact_indices_with_limit([0, 1, 2, 3], 3, 4).unwrap_err().to_string();
+        assert!(message.contains("4 entries are not a multiple of 3"), "{}", message);
+
+        let message = compact_indices_with_limit([0, 1, 2, 3, 4], 5, 4).unwrap_err().to_string();
+        assert!(message.contains("Elements of 5 vertices do not fit batches of at most 4 vertices"), "{}", message);
+        assert!(compact_indices_with_limit([], 0, 4).is_err());
+
+        let batch = CompactedMesh { indices: vec![0], vertex_remap: vec![9] };
+        let message = batch.carry_rows(&Array2::zeros((4, 3))).unwrap_err().to_string();
+        assert!(message.contains("references vertex 9 but only 4 vertices exist"), "{}", message);
+    }
+
+    #[test]
+    fn empty_connectivity_is_one_empty_batch() {
+        assert_eq!(compact_indices_with_limit([], 3, 4).unwrap(), [CompactedMesh::default()]);
+        assert!(CompactedMesh::default().is_identity(0));
+    }
+
+    #[test]
+    fn empty_geometry_is_valid() {
+        let mesh = TrianglesBuilder::new().build().unwrap();
+        assert_eq!(mesh.data().num_vertices(), 0);
+        assert_eq!(mesh.data().positions().unwrap().shape(), &[0, 3]);
+    }
+
+    #[test]
+    fn quads_with_repeated_corners_split_into_fewer_triangles() {
+        let quads = ndarray::arr2(&[[0, 1, 2, 3], [0, 1, 1, 2], [4, 4, 5, 4], [6, 6, 6, 6]]);
+        let triangles = split_quads(&quads);
+        assert_eq!(triangles, ndarray::arr2(&[[0, 1, 2], [0, 2, 3], [0, 1, 2]]));
+
+        // The split surface covers only the distinct corners, with no degenerate triangles
+        for triangle in triangles.outer_iter() {
+            assert!(triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[0] != triangle[2]);
+        }
+    }
+}
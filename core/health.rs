@@ -0,0 +1,466 @@
+//! Liveness and readiness of an embedding service
+//!
+//! Components register a `HealthProbe` with a `HealthMonitor`, usually
+//! through their `register_health` method, and the monitor combines what the
+//! probes report into one `HealthStatus`. Probes answer from state their
+//! component keeps up to date, so a status is cheap to query synchronously,
+//! e.g. from an orchestrator's probe every second. `HealthMonitor::serve`
+//! answers `/healthz` and `/readyz` over HTTP.
+
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+use crate::core::{MessageRouter, ShmManager};
+
+/// Thresholds the probes judge their components by
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// How often the message router pings itself
+    pub ping_interval: Duration,
+    /// Round trip above which the router is degraded
+    pub slow_ping: Duration,
+    /// Time without an answered ping after which the router is unhealthy
+    pub ping_timeout: Duration,
+    /// Time a task may wait in the ready queue before the executor counts as wedged
+    pub max_ready_age: Duration,
+    /// Fraction of a shared memory arena in use above which it is degraded
+    pub shm_degraded: f64,
+    /// Fraction above which it is unhealthy
+    pub shm_unhealthy: f64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(5),
+            slow_ping: Duration::from_millis(100),
+            ping_timeout: Duration::from_secs(15),
+            max_ready_age: Duration::from_secs(60),
+            shm_degraded: 0.85,
+            shm_unhealthy: 0.98,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Degraded,
+    Unhealthy,
+}
+
+/// A problem one component reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Issue {
+    /// Name the component's probe was registered under
+    pub component: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Issue {
+    pub fn degraded(message: impl Into<String>) -> Self {
+        Self {
+            component: String::new(),
+            severity: Severity::Degraded,
+            message: message.into(),
+        }
+    }
+
+    pub fn unhealthy(message: impl Into<String>) -> Self {
+        Self {
+            component: String::new(),
+            severity: Severity::Unhealthy,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.component, self.message)
+    }
+}
+
+/// Overall health, with every issue found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "issues", rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ready,
+    /// Working, but with issues worth attention
+    Degraded(Vec<Issue>),
+    /// At least one component stopped working
+    Unhealthy(Vec<Issue>),
+}
+
+impl HealthStatus {
+    /// Status of the worst of the issues
+    pub fn from_issues(issues: Vec<Issue>) -> Self {
+        match issues.iter().map(|i| i.severity).max() {
+            None => HealthStatus::Ready,
+            Some(Severity::Degraded) => HealthStatus::Degraded(issues),
+            Some(Severity::Unhealthy) => HealthStatus::Unhealthy(issues),
+        }
+    }
+
+    /// Whether the service should receive work
+    pub fn is_ready(&self) -> bool {
+        matches!(self, HealthStatus::Ready)
+    }
+
+    /// Whether the service should be left running
+    pub fn is_live(&self) -> bool {
+        !matches!(self, HealthStatus::Unhealthy(_))
+    }
+
+    pub fn issues(&self) -> &[Issue] {
+        match self {
+            HealthStatus::Ready => &[],
+            HealthStatus::Degraded(issues) | HealthStatus::Unhealthy(issues) => issues,
+        }
+    }
+}
+
+/// Reports the problems of one component
+pub trait HealthProbe: Send + Sync {
+    /// Current problems, empty while the component is fine
+    fn check(&self, config: &HealthConfig) -> Vec<Issue>;
+}
+
+/// Registered probes and the thresholds they check against
+#[derive(Default)]
+pub struct HealthMonitor {
+    config: HealthConfig,
+    probes: RwLock<Vec<(String, Arc<dyn HealthProbe>)>>,
+}
+
+impl HealthMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(mut self, config: HealthConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &HealthConfig {
+        &self.config
+    }
+
+    /// Add a probe; one registered under the same name before is replaced
+    pub fn register(&self, component: &str, probe: Arc<dyn HealthProbe>) {
+        let mut probes = self.probes.write();
+        probes.retain(|(name, _)| name != component);
+        probes.push((component.to_string(), probe));
+    }
+
+    pub fn unregister(&self, component: &str) -> bool {
+        let mut probes = self.probes.write();
+        let before = probes.len();
+        probes.retain(|(name, _)| name != component);
+        probes.len() != before
+    }
+
+    /// Names of the registered components in registration order
+    pub fn components(&self) -> Vec<String> {
+        self.probes.read().iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Ask every probe and combine their answers
+    pub fn status(&self) -> HealthStatus {
+        let probes = self.probes.read().clone();
+        let issues = probes.iter()
+            .flat_map(|(name, probe)| {
+                probe.check(&self.config).into_iter().map(move |mut issue| {
+                    issue.component = name.clone();
+                    issue
+                })
+            })
+            .collect();
+        HealthStatus::from_issues(issues)
+    }
+
+    /// Answer `GET /healthz` (live) and `GET /readyz` (ready) with the status as JSON
+    ///
+    /// Both answer 200 when the check passes and 503 otherwise; other paths
+    /// get 404. Runs until accepting a connection fails.
+    pub async fn serve(self: Arc<Self>, addr: impl ToSocketAddrs) -> Result<(), crate::Error> {
+        let listener = TcpListener::bind(addr).await?;
+        tracing::info!("Serving health checks on {}", listener.local_addr()?);
+        loop {
+            let (mut stream, _) = listener.accept().await?;
+            let monitor = self.clone();
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let read = match stream.read(&mut request).await {
+                    Ok(read) => read,
+                    Err(e) => {
+                        tracing::debug!("Health check request failed: {}", e);
+                        return;
+                    }
+                };
+                let request = String::from_utf8_lossy(&request[..read]);
+                let path = request.split_whitespace().nth(1).unwrap_or("");
+                let status = monitor.status();
+                let (code, body) = match path {
+                    "/healthz" | "/readyz" => {
+                        let pass = if path == "/healthz" { status.is_live() } else { status.is_ready() };
+                        let body = serde_json::to_string(&status).unwrap_or_default();
+                        (if pass { "200 OK" } else { "503 Service Unavailable" }, body)
+                    }
+                    _ => ("404 Not Found", String::new()),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    code, body.len(), body
+                );
+                if let Err(e) = stream.write_all(response.as_bytes()).await {
+                    tracing::debug!("Health check response failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Outcome of the router's latest self-pings
+#[derive(Default)]
+struct PingState {
+    answered: Option<Instant>,
+    round_trip: Option<Duration>,
+    error: Option<String>,
+}
+
+/// Checks the router answers pings routed through its own queues
+struct RouterProbe {
+    router: Weak<MessageRouter>,
+    registered: Instant,
+    state: Mutex<PingState>,
+}
+
+impl HealthProbe for RouterProbe {
+    fn check(&self, config: &HealthConfig) -> Vec<Issue> {
+        if self.router.strong_count() == 0 {
+            return vec![Issue::unhealthy("message router was dropped")];
+        }
+        let state = self.state.lock();
+        let silent = state.answered.unwrap_or(self.registered).elapsed();
+        if silent > config.ping_timeout {
+            let reason = state.error.as_deref().map(|e| format!(": {}", e)).unwrap_or_default();
+            return vec![Issue::unhealthy(format!("no answer to a self-ping for {:?}{}", silent, reason))];
+        }
+        match state.round_trip {
+            Some(round_trip) if round_trip > config.slow_ping => {
+                vec![Issue::degraded(format!("self-ping took {:?}", round_trip))]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl MessageRouter {
+    /// Ping the router every `HealthConfig::ping_interval` and report to `monitor` as "router"
+    pub fn register_health(self: &Arc<Self>, monitor: &HealthMonitor) {
+        let probe = Arc::new(RouterProbe {
+            router: Arc::downgrade(self),
+            registered: Instant::now(),
+            state: Mutex::new(PingState::default()),
+        });
+        monitor.register("router", probe.clone());
+
+        let config = *monitor.config();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(config.ping_interval);
+            loop {
+                interval.tick().await;
+                let Some(router) = probe.router.upgrade() else {
+                    break;
+                };
+                let result = tokio::time::timeout(config.ping_timeout, router.ping()).await;
+                let mut state = probe.state.lock();
+                match result {
+                    Ok(Ok(round_trip)) => {
+                        state.answered = Some(Instant::now());
+                        state.round_trip = Some(round_trip);
+                        state.error = None;
+                    }
+                    Ok(Err(e)) => state.error = Some(e.to_string()),
+                    Err(_) => state.error = Some("ping timed out".to_string()),
+                }
+            }
+        });
+    }
+}
+
+/// Checks how full the shared memory arenas are
+struct ShmProbe {
+    manager: Weak<ShmManager>,
+}
+
+impl HealthProbe for ShmProbe {
+    fn check(&self, config: &HealthConfig) -> Vec<Issue> {
+        let Some(manager) = self.manager.upgrade() else {
+            return Vec::new();
+        };
+        manager.arena_stats().into_iter()
+            .filter(|(_, stats)| stats.total_size > 0)
+            .filter_map(|(name, stats)| {
+                let usage = stats.used_size as f64 / stats.total_size as f64;
+                let message = format!("arena {} is {:.0}% full", name, usage * 100.0);
+                if usage > config.shm_unhealthy {
+                    Some(Issue::unhealthy(message))
+                } else if usage > config.shm_degraded {
+                    Some(Issue::degraded(message))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+impl ShmManager {
+    /// Report arena usage to `monitor` as "shm"
+    pub fn register_health(self: &Arc<Self>, monitor: &HealthMonitor) {
+        monitor.register("shm", Arc::new(ShmProbe { manager: Arc::downgrade(self) }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Probe reporting whatever the test sets
+    #[derive(Default)]
+    struct Injected(Mutex<Vec<Issue>>);
+
+    impl Injected {
+        fn set(&self, issues: Vec<Issue>) {
+            *self.0.lock() = issues;
+        }
+    }
+
+    impl HealthProbe for Injected {
+        fn check(&self, _config: &HealthConfig) -> Vec<Issue> {
+            self.0.lock().clone()
+        }
+    }
+
+    #[test]
+    fn the_worst_issue_decides_the_status() {
+        let monitor = HealthMonitor::new();
+        let (executor, shm) = (Arc::new(Injected::default()), Arc::new(Injected::default()));
+        monitor.register("executor", executor.clone());
+        monitor.register("shm", shm.clone());
+        assert_eq!(monitor.status(), HealthStatus::Ready);
+
+        shm.set(vec![Issue::degraded("arena workflow:a is 90% full")]);
+        let status = monitor.status();
+        assert!(status.is_live() && !status.is_ready());
+        assert_eq!(status.issues()[0].component, "shm");
+        assert_eq!(status.issues()[0].to_string(), "shm: arena workflow:a is 90% full");
+
+        executor.set(vec![Issue::unhealthy("task 7 waited 120 s to start")]);
+        let status = monitor.status();
+        assert!(matches!(status, HealthStatus::Unhealthy(_)));
+        assert!(!status.is_live());
+        let components: Vec<(&str, Severity)> = status.issues().iter().map(|i| (i.component.as_str(), i.severity)).collect();
+        assert_eq!(components, [("executor", Severity::Unhealthy), ("shm", Severity::Degraded)]);
+    }
+
+    #[test]
+    fn probes_are_replaced_and_removed_by_name() {
+        let monitor = HealthMonitor::new();
+        let failing = Arc::new(Injected::default());
+        failing.set(vec![Issue::unhealthy("down")]);
+        monitor.register("router", failing);
+        monitor.register("shm", Arc::new(Injected::default()));
+        monitor.register("router", Arc::new(Injected::default()));
+
+        assert_eq!(monitor.components(), ["shm", "router"]);
+        assert!(monitor.status().is_ready());
+        assert!(monitor.unregister("shm"));
+        assert!(!monitor.unregister("shm"));
+        assert_eq!(monitor.components(), ["router"]);
+    }
+
+    #[test]
+    fn statuses_serialize_with_their_issues() {
+        let mut issue = Issue::degraded("slow");
+        issue.component = "router".to_string();
+        let json = serde_json::to_value(HealthStatus::Degraded(vec![issue])).unwrap();
+        assert_eq!(json["status"], "degraded");
+        assert_eq!(json["issues"][0]["component"], "router");
+        assert_eq!(json["issues"][0]["severity"], "degraded");
+        assert_eq!(serde_json::to_value(HealthStatus::Ready).unwrap()["status"], "ready");
+    }
+
+    #[tokio::test]
+    async fn the_router_is_ready_once_it_answers_its_pings() {
+        let config = HealthConfig {
+            ping_interval: Duration::from_millis(10),
+            ping_timeout: Duration::from_millis(200),
+            ..HealthConfig::default()
+        };
+        let monitor = HealthMonitor::new().with_config(config);
+        let router = Arc::new(MessageRouter::new());
+        router.register_health(&monitor);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(monitor.status(), HealthStatus::Ready);
+
+        drop(router);
+        let status = monitor.status();
+        assert_eq!(status.issues()[0].message, "message router was dropped");
+        assert!(!status.is_live());
+    }
+
+    #[test]
+    fn a_silent_router_becomes_unhealthy() {
+        let router = Arc::new(MessageRouter::new());
+        let probe = RouterProbe {
+            router: Arc::downgrade(&router),
+            registered: Instant::now() - Duration::from_secs(20),
+            state: Mutex::new(PingState { error: Some("ping timed out".to_string()), ..PingState::default() }),
+        };
+        let issues = probe.check(&HealthConfig::default());
+        assert_eq!(issues[0].severity, Severity::Unhealthy);
+        assert!(issues[0].message.ends_with(": ping timed out"), "{}", issues[0].message);
+
+        probe.state.lock().answered = Some(Instant::now());
+        probe.state.lock().round_trip = Some(Duration::from_millis(300));
+        assert_eq!(probe.check(&HealthConfig::default())[0].severity, Severity::Degraded);
+    }
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn probes_are_served_over_http() {
+        let monitor = Arc::new(HealthMonitor::new());
+        let shm = Arc::new(Injected::default());
+        shm.set(vec![Issue::degraded("arena workflow:a is 90% full")]);
+        monitor.register("shm", shm);
+
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        tokio::spawn(monitor.serve(addr));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let live = get(addr, "/healthz").await;
+        assert!(live.starts_with("HTTP/1.1 200 OK"), "{}", live);
+        assert!(live.contains("90% full"));
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.1 503"));
+        assert!(get(addr, "/metrics").await.starts_with("HTTP/1.1 404"));
+    }
+}
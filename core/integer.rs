@@ -0,0 +1,154 @@
+//! Integer fields: material and block IDs, labels and global indices
+//!
+//! IDs stored as floats lose exactness beyond 2^24 and invite comparisons
+//! with a tolerance. `VecInt32` and `VecInt64` keep them as integers, one
+//! per vertex or cell like scalar fields, and modules select by them with
+//! exact matches, see `Threshold`.
+
+use ndarray::Array1;
+
+use crate::core::{ObjectPayload, ObjectType, VistleObject};
+
+impl ObjectPayload {
+    /// Whether the payload is an integer field
+    pub fn is_integer(&self) -> bool {
+        matches!(self, ObjectPayload::VecInt32 { .. } | ObjectPayload::VecInt64 { .. })
+    }
+}
+
+impl VistleObject {
+    /// Field of one 32-bit integer per vertex or cell
+    pub fn int32_field(data: Array1<i32>) -> Self {
+        Self::with_data(ObjectType::Vec, ObjectPayload::VecInt32 { data })
+    }
+
+    /// Field of one 64-bit integer per vertex or cell
+    pub fn int64_field(data: Array1<i64>) -> Self {
+        Self::with_data(ObjectType::Vec, ObjectPayload::VecInt64 { data })
+    }
+}
+
+/// One 32-bit integer per vertex or cell
+#[derive(Debug, Clone, Copy)]
+pub struct Int32FieldView<'a> {
+    values: &'a Array1<i32>,
+}
+
+impl<'a> Int32FieldView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::VecInt32 { data } => Some(Self { values: data }),
+            _ => None,
+        }
+    }
+
+    pub fn values(&self) -> &'a Array1<i32> {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Smallest and largest value, None if the field is empty
+    pub fn range(&self) -> Option<(i32, i32)> {
+        self.values.iter().min().copied().zip(self.values.iter().max().copied())
+    }
+}
+
+/// One 64-bit integer per vertex or cell
+#[derive(Debug, Clone, Copy)]
+pub struct Int64FieldView<'a> {
+    values: &'a Array1<i64>,
+}
+
+impl<'a> Int64FieldView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::VecInt64 { data } => Some(Self { values: data }),
+            _ => None,
+        }
+    }
+
+    pub fn values(&self) -> &'a Array1<i64> {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Smallest and largest value, None if the field is empty
+    pub fn range(&self) -> Option<(i64, i64)> {
+        self.values.iter().min().copied().zip(self.values.iter().max().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use ndarray::array;
+
+    use crate::core::{Object, ObjectData, ShmConfig, SharedArena};
+
+    fn ids() -> Vec<VistleObject> {
+        vec![
+            VistleObject::int32_field(array![i32::MIN, -1, 0, 7, i32::MAX]),
+            VistleObject::int64_field(array![i64::MIN, (1 << 53) + 1, i64::MAX]),
+        ]
+    }
+
+    fn same_values(original: &dyn Object, stored: &dyn Object) {
+        match (original.payload().unwrap(), stored.payload().unwrap()) {
+            (ObjectPayload::VecInt32 { data: a }, ObjectPayload::VecInt32 { data: b }) => assert_eq!(a, b),
+            (ObjectPayload::VecInt64 { data: a }, ObjectPayload::VecInt64 { data: b }) => assert_eq!(a, b),
+            (a, b) => panic!("{} came back as {}", a.kind(), b.kind()),
+        }
+    }
+
+    #[test]
+    fn integer_fields_round_trip_through_bincode() {
+        for object in ids() {
+            let bytes = bincode::serialize(object.as_data().unwrap()).unwrap();
+            let decoded: ObjectData = bincode::deserialize(&bytes).unwrap();
+            same_values(&object, &VistleObject::from_data(decoded));
+        }
+    }
+
+    #[test]
+    fn integer_fields_round_trip_through_shared_memory() {
+        let arena = SharedArena::new(ShmConfig {
+            size: 1 << 20,
+            name: format!("vistle_test_{}", uuid::Uuid::new_v4().simple()),
+            ..ShmConfig::default()
+        })
+        .unwrap();
+        for object in ids() {
+            let object: Arc<dyn Object> = Arc::new(object);
+            let id = arena.store_object(object.clone()).unwrap();
+            let stored = arena.get_object(id).unwrap().expect("field was not stored");
+            same_values(object.as_ref(), stored.as_ref());
+        }
+    }
+
+    #[test]
+    fn views_report_exact_ranges() {
+        let ids = ids();
+        let (small, big) = (&ids[0], &ids[1]);
+        assert!(small.payload().unwrap().is_integer());
+        assert_eq!(small.as_int32_field().unwrap().range(), Some((i32::MIN, i32::MAX)));
+        assert_eq!(big.as_int64_field().unwrap().range(), Some((i64::MIN, i64::MAX)));
+        assert!(big.as_int32_field().is_none());
+        assert_eq!(VistleObject::int32_field(array![]).as_int32_field().unwrap().range(), None);
+    }
+}
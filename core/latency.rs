@@ -0,0 +1,396 @@
+//! Message latency histograms per route of the `MessageRouter`
+//!
+//! Recording is off by default. While off, routing a message costs one
+//! relaxed atomic load for the check; while on, a few relaxed atomic adds
+//! per message. `benches/route_latency.rs` measures both.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Message, MessageId};
+
+/// Number of power-of-two microsecond buckets; the last one collects everything above 2^38 µs
+const LATENCY_BUCKETS: usize = 40;
+
+/// Acks awaited longer than this are given up, e.g. those of peers too old to send them
+const ACK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Messages awaiting an ack at most; further sends go untimed until some expire
+const MAX_PENDING_ACKS: usize = 4096;
+
+/// Where a message went through the router
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Route {
+    /// Queue of a module on this rank, timed from enqueue to dequeue
+    Local { module_id: u32 },
+    /// Sent over MPI, timed from the send to the recipient's ack; only with acks on
+    Remote { recipient: u32 },
+    /// Received over MPI, timed from the sender's timestamp
+    Incoming { sender: u32 },
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Route::Local { module_id } => write!(f, "local module {}", module_id),
+            Route::Remote { recipient: 0 } => write!(f, "MPI broadcast"),
+            Route::Remote { recipient } => write!(f, "MPI to module {}", recipient),
+            Route::Incoming { sender } => write!(f, "MPI from module {}", sender),
+        }
+    }
+}
+
+/// Lock-free histogram of latencies in power-of-two microsecond buckets
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+            max_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Bucket `i` holds latencies below 2^i µs and at least 2^(i-1) µs
+    fn bucket(us: u64) -> usize {
+        ((u64::BITS - us.leading_zeros()) as usize).min(LATENCY_BUCKETS - 1)
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let us = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.buckets[Self::bucket(us)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_us.load(Ordering::Relaxed))
+    }
+
+    pub fn mean(&self) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_micros(self.sum_us.load(Ordering::Relaxed) / count)
+    }
+
+    /// Upper bound of the bucket holding quantile `q` in 0..=1, at most the maximum
+    pub fn percentile(&self, q: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Duration::from_micros(1u64 << i).min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum_us.store(0, Ordering::Relaxed);
+        self.max_us.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Latency summary of one route
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteLatency {
+    pub route: Route,
+    pub count: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+/// Latency summary of all routes, see `MessageRouter::latency_report`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyReport {
+    /// Routes that saw messages, slowest p95 first
+    pub routes: Vec<RouteLatency>,
+    /// Messages above the slow-message threshold
+    pub slow_messages: u64,
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.routes.is_empty() {
+            return writeln!(f, "No message latencies recorded");
+        }
+        writeln!(f, "{:<28} {:>8} {:>10} {:>10} {:>10}", "route", "messages", "p50", "p95", "max")?;
+        for route in &self.routes {
+            writeln!(
+                f, "{:<28} {:>8} {:>10.1?} {:>10.1?} {:>10.1?}",
+                route.route.to_string(), route.count, route.p50, route.p95, route.max
+            )?;
+        }
+        writeln!(f, "{} slow messages", self.slow_messages)
+    }
+}
+
+/// Message sent over MPI whose ack has not arrived yet
+#[derive(Debug)]
+struct PendingAck {
+    sent: Instant,
+    route: Route,
+    message: Message,
+}
+
+/// Latency recording state shared by the router and its queues
+#[derive(Debug, Default)]
+pub struct RouterMetrics {
+    enabled: AtomicBool,
+    acks: AtomicBool,
+    pending_acks: dashmap::DashMap<MessageId, PendingAck>,
+    /// Slow-message threshold in µs, 0 if off
+    slow_threshold_us: AtomicU64,
+    histograms: dashmap::DashMap<Route, Arc<LatencyHistogram>>,
+    slow_messages: AtomicU64,
+}
+
+impl RouterMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Whether remote sends are timed until acked, which needs recording on as well
+    #[inline]
+    pub fn acks_enabled(&self) -> bool {
+        self.is_enabled() && self.acks.load(Ordering::Relaxed)
+    }
+
+    pub fn set_acks_enabled(&self, enabled: bool) {
+        self.acks.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.pending_acks.clear();
+        }
+    }
+
+    /// Start timing `message` on `route` until `acknowledge` is called with its id
+    pub fn await_ack(&self, route: Route, message: &Message) {
+        if self.pending_acks.len() >= MAX_PENDING_ACKS {
+            self.pending_acks.retain(|_, pending| pending.sent.elapsed() < ACK_TIMEOUT);
+            if self.pending_acks.len() >= MAX_PENDING_ACKS {
+                return;
+            }
+        }
+        self.pending_acks.insert(message.id, PendingAck { sent: Instant::now(), route, message: message.clone() });
+    }
+
+    /// Record the round trip of the message `id` acks; false if no ack was awaited for it
+    pub fn acknowledge(&self, id: &MessageId) -> bool {
+        match self.pending_acks.remove(id) {
+            Some((_, pending)) => {
+                self.record(pending.route, pending.sent.elapsed(), &pending.message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop awaiting the ack of a message that could not be sent
+    pub fn cancel_ack(&self, id: &MessageId) {
+        self.pending_acks.remove(id);
+    }
+
+    /// Number of messages whose ack is awaited
+    pub fn pending_acks(&self) -> usize {
+        self.pending_acks.len()
+    }
+
+    /// Warn about messages slower than `threshold`; None turns the warning off
+    pub fn set_slow_threshold(&self, threshold: Option<Duration>) {
+        let us = threshold.map_or(0, |t| t.as_micros().clamp(1, u64::MAX as u128) as u64);
+        self.slow_threshold_us.store(us, Ordering::Relaxed);
+    }
+
+    pub fn slow_threshold(&self) -> Option<Duration> {
+        match self.slow_threshold_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
+        }
+    }
+
+    pub fn histogram(&self, route: Route) -> Arc<LatencyHistogram> {
+        self.histograms.entry(route).or_default().clone()
+    }
+
+    /// Record the latency of `message` on `route`, warning if it was slow
+    pub fn record(&self, route: Route, latency: Duration, message: &Message) {
+        self.histogram(route).record(latency);
+        if self.slow_threshold().is_some_and(|threshold| latency > threshold) {
+            self.slow_messages.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                "Slow message on {}: {:.1?} (id {:?}, {} from {} to {}, priority {:?}, protocol version {}, created {:?})",
+                route, latency, message.id, message.message_type.name(), message.sender,
+                message.recipient, message.priority, message.version, message.timestamp
+            );
+        }
+    }
+
+    /// Record a message received over MPI, timed from its creation on the sender
+    ///
+    /// Only meaningful with synchronized clocks across nodes.
+    pub fn record_incoming(&self, message: &Message) {
+        if let Ok(age) = SystemTime::now().duration_since(message.timestamp) {
+            self.record(Route::Incoming { sender: message.sender }, age, message);
+        }
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        let mut routes: Vec<RouteLatency> = self.histograms.iter()
+            .filter(|h| h.count() > 0)
+            .map(|h| RouteLatency {
+                route: *h.key(),
+                count: h.count(),
+                p50: h.percentile(0.5),
+                p95: h.percentile(0.95),
+                max: h.max(),
+                mean: h.mean(),
+            })
+            .collect();
+        routes.sort_by_key(|r| std::cmp::Reverse(r.p95));
+        LatencyReport {
+            routes,
+            slow_messages: self.slow_messages.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        for histogram in self.histograms.iter() {
+            histogram.reset();
+        }
+        self.slow_messages.store(0, Ordering::Relaxed);
+        self.pending_acks.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::MessageType;
+
+    fn message() -> Message {
+        Message::new(1, 2, MessageType::Quit)
+    }
+
+    #[test]
+    fn percentiles_are_bucket_bounds_capped_at_the_maximum() {
+        let histogram = LatencyHistogram::new();
+        for us in [10, 20, 30, 40, 5000] {
+            histogram.record(Duration::from_micros(us));
+        }
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.max(), Duration::from_micros(5000));
+        assert_eq!(histogram.mean(), Duration::from_micros(1020));
+        // 30 µs lies in the bucket below 32 µs
+        assert_eq!(histogram.percentile(0.5), Duration::from_micros(32));
+        assert_eq!(histogram.percentile(1.0), Duration::from_micros(5000));
+
+        histogram.reset();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.percentile(0.95), Duration::ZERO);
+    }
+
+    #[test]
+    fn slow_messages_are_counted_and_reports_sort_by_p95() {
+        let metrics = RouterMetrics::new();
+        metrics.set_slow_threshold(Some(Duration::from_millis(1)));
+        metrics.record(Route::Local { module_id: 1 }, Duration::from_micros(50), &message());
+        metrics.record(Route::Remote { recipient: 2 }, Duration::from_millis(3), &message());
+
+        let report = metrics.report();
+        assert_eq!(report.slow_messages, 1);
+        assert_eq!(report.routes.len(), 2);
+        assert_eq!(report.routes[0].route, Route::Remote { recipient: 2 });
+        assert!(report.to_string().contains("MPI to module 2"));
+
+        metrics.reset();
+        assert!(metrics.report().routes.is_empty());
+    }
+
+    #[test]
+    fn remote_latency_spans_send_to_ack() {
+        let metrics = RouterMetrics::new();
+        let sent = message();
+        metrics.set_enabled(true);
+        metrics.set_acks_enabled(true);
+        assert!(metrics.acks_enabled());
+
+        metrics.await_ack(Route::Remote { recipient: 2 }, &sent);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(metrics.histogram(Route::Remote { recipient: 2 }).count(), 0);
+        assert!(metrics.acknowledge(&sent.id));
+
+        let histogram = metrics.histogram(Route::Remote { recipient: 2 });
+        assert_eq!(histogram.count(), 1);
+        assert!(histogram.max() >= Duration::from_millis(5));
+        // A second ack, e.g. from another rank of a broadcast, is ignored
+        assert!(!metrics.acknowledge(&sent.id));
+        assert_eq!(metrics.pending_acks(), 0);
+    }
+
+    #[test]
+    fn acks_need_recording_and_unsent_messages_are_forgotten() {
+        let metrics = RouterMetrics::new();
+        metrics.set_acks_enabled(true);
+        assert!(!metrics.acks_enabled());
+
+        let sent = message();
+        metrics.await_ack(Route::Remote { recipient: 2 }, &sent);
+        metrics.cancel_ack(&sent.id);
+        assert!(!metrics.acknowledge(&sent.id));
+        assert!(metrics.report().routes.is_empty());
+    }
+
+    #[test]
+    fn pending_acks_are_bounded() {
+        let metrics = RouterMetrics::new();
+        for _ in 0..MAX_PENDING_ACKS + 10 {
+            metrics.await_ack(Route::Remote { recipient: 2 }, &message());
+        }
+        assert_eq!(metrics.pending_acks(), MAX_PENDING_ACKS);
+    }
+}
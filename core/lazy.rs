@@ -0,0 +1,345 @@
+//! Placeholder objects that are loaded on first access
+//!
+//! A reader can announce every timestep of a long series as a cheap
+//! placeholder carrying a `Loader`, and the `ObjectRegistry` realizes each
+//! one when a consumer asks for it with `resolve`. Realized objects can be
+//! turned back into placeholders to free memory with `evict_resolved`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Object, ObjectData, ObjectId, ObjectPayload, ObjectRegistry, ObjectType, ParameterValue, ShmFull, VistleObject};
+
+/// How to produce the data of a placeholder: which reader to run with which settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Loader {
+    /// Reader module type as registered in the `ModuleRegistry`
+    pub module: String,
+    /// Output port the object is taken from
+    pub port: String,
+    /// Parameters set on the reader before it runs
+    pub parameters: Vec<(String, ParameterValue)>,
+    pub block: i32,
+    pub timestep: i32,
+}
+
+impl Loader {
+    pub fn new(module: &str, port: &str) -> Self {
+        Self {
+            module: module.to_string(),
+            port: port.to_string(),
+            parameters: Vec::new(),
+            block: 0,
+            timestep: 0,
+        }
+    }
+
+    pub fn with_parameter(mut self, name: &str, value: ParameterValue) -> Self {
+        self.parameters.push((name.to_string(), value));
+        self
+    }
+
+    pub fn with_block(mut self, block: i32) -> Self {
+        self.block = block;
+        self
+    }
+
+    pub fn with_timestep(mut self, timestep: i32) -> Self {
+        self.timestep = timestep;
+        self
+    }
+}
+
+impl VistleObject {
+    /// Object standing in for data `loader` produces on demand
+    ///
+    /// Block and timestep are known up front, so consumers can sort and
+    /// select placeholders without loading them.
+    pub fn placeholder(object_type: ObjectType, loader: Loader) -> Self {
+        let (block, timestep) = (loader.block, loader.timestep);
+        let mut object = VistleObject::with_data(object_type, ObjectPayload::Placeholder { loader });
+        object.meta_mut().block = block;
+        object.meta_mut().timestep = timestep;
+        object
+    }
+}
+
+impl ObjectPayload {
+    /// Loader of an unresolved placeholder
+    pub fn loader(&self) -> Option<&Loader> {
+        match self {
+            ObjectPayload::Placeholder { loader } => Some(loader),
+            _ => None,
+        }
+    }
+}
+
+/// Runs the reader a `Loader` names; see `compute::ModuleLoader`
+#[async_trait::async_trait]
+pub trait ObjectLoader: Send + Sync {
+    async fn load(&self, loader: &Loader) -> Result<Arc<dyn Object>, crate::Error>;
+}
+
+/// A realized placeholder, kept so it can be evicted again
+struct Realized {
+    placeholder: Arc<dyn Object>,
+    bytes: usize,
+    last_access: u64,
+}
+
+/// Lazy loading state of an `ObjectRegistry`
+#[derive(Default)]
+pub struct LazyObjects {
+    loader: parking_lot::RwLock<Option<Arc<dyn ObjectLoader>>>,
+    realized: dashmap::DashMap<ObjectId, Realized>,
+    /// One lock per placeholder being loaded, so concurrent requests load once
+    loading: dashmap::DashMap<ObjectId, Arc<tokio::sync::Mutex<()>>>,
+    clock: AtomicU64,
+    pub(crate) prefetch: parking_lot::RwLock<crate::core::PrefetchConfig>,
+    pub(crate) prefetch_stats: parking_lot::Mutex<crate::core::PrefetchStats>,
+}
+
+impl LazyObjects {
+    pub(crate) fn forget(&self, id: ObjectId) {
+        self.realized.remove(&id);
+    }
+
+    fn touch(&self, id: ObjectId) {
+        if let Some(mut realized) = self.realized.get_mut(&id) {
+            realized.last_access = self.clock.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl ObjectRegistry {
+    /// Loader used by `resolve` to realize placeholders
+    pub fn set_loader(&self, loader: Arc<dyn ObjectLoader>) {
+        *self.lazy.loader.write() = Some(loader);
+    }
+
+    /// Object `id` with its data, loading it first if it is a placeholder
+    ///
+    /// The realized object keeps the placeholder's id and metadata and
+    /// replaces it in the registry, so later lookups find the data.
+    pub async fn resolve(&self, id: ObjectId) -> Result<Arc<dyn Object>, crate::Error> {
+        let object = self.get(id)
+            .ok_or_else(|| crate::Error::Module(format!("Object {} not found", id)))?;
+        if object.is_complete() {
+            self.lazy.touch(id);
+            return Ok(object);
+        }
+
+        let lock = self.lazy.loading.entry(id).or_default().clone();
+        let _loading = lock.lock().await;
+        let result = self.realize(id).await;
+        drop(_loading);
+        self.lazy.loading.remove_if(&id, |_, l| Arc::ptr_eq(l, &lock));
+        result
+    }
+
+    async fn realize(&self, id: ObjectId) -> Result<Arc<dyn Object>, crate::Error> {
+        // Another request may have realized it while this one waited
+        let placeholder = self.get(id)
+            .ok_or_else(|| crate::Error::Module(format!("Object {} not found", id)))?;
+        let Some(loader) = placeholder.payload().and_then(ObjectPayload::loader).cloned() else {
+            self.lazy.touch(id);
+            return Ok(placeholder);
+        };
+        let object_loader = self.lazy.loader.read().clone()
+            .ok_or_else(|| crate::Error::Config(format!("No object loader installed to resolve {}", id)))?;
+
+        let loaded = object_loader.load(&loader).await?;
+        let data = loaded.as_data()
+            .ok_or_else(|| crate::Error::Module(format!(
+                "Reader {} produced an object without generic data for {}",
+                loader.module, id
+            )))?;
+        let mut attributes = data.attributes.clone();
+        attributes.extend(placeholder.attributes().iter().map(|(k, v)| (k.clone(), v.clone())));
+        let realized: Arc<dyn Object> = Arc::new(VistleObject::from_data(ObjectData {
+            id,
+            object_type: data.object_type,
+            meta: placeholder.meta().clone(),
+            attributes,
+            data: data.data.clone(),
+            grid: data.grid,
+        }));
+        tracing::debug!("Resolved placeholder {} from {} (timestep {}, block {})", id, loader.module, loader.timestep, loader.block);
+
+        self.lazy.realized.insert(id, Realized {
+            placeholder,
+            bytes: data.data.size_bytes(),
+            last_access: self.lazy.clock.fetch_add(1, Ordering::Relaxed),
+        });
+        self.store(realized.clone());
+        Ok(realized)
+    }
+
+    /// Resolve an object handed to a module, leaving complete objects as they are
+    pub async fn resolve_object(&self, object: &Arc<dyn Object>) -> Result<Arc<dyn Object>, crate::Error> {
+        if object.is_complete() {
+            return Ok(object.clone());
+        }
+        if self.get(object.id()).is_none() {
+            self.store(object.clone());
+        }
+        self.resolve(object.id()).await
+    }
+
+    /// Bytes held by realized placeholders
+    pub fn resolved_bytes(&self) -> usize {
+        self.lazy.realized.iter().map(|r| r.bytes).sum()
+    }
+
+    /// Turn least recently used realized objects back into placeholders
+    ///
+    /// Stops once at least `bytes` are freed; returns the bytes freed.
+    /// Consumers still holding the realized object keep it alive.
+    pub fn evict_resolved(&self, bytes: usize) -> usize {
+        let mut candidates: Vec<(u64, ObjectId)> = self.lazy.realized.iter()
+            .map(|r| (r.last_access, *r.key()))
+            .collect();
+        candidates.sort();
+
+        let mut freed = 0;
+        for (_, id) in candidates {
+            if freed >= bytes {
+                break;
+            }
+            if self.lazy.loading.contains_key(&id) {
+                continue;
+            }
+            if let Some((_, realized)) = self.lazy.realized.remove(&id) {
+                freed += realized.bytes;
+                self.store(realized.placeholder);
+            }
+        }
+        if freed > 0 {
+            tracing::debug!("Evicted {} bytes of resolved objects back to placeholders", freed);
+        }
+        freed
+    }
+
+    /// Spill policy for `SharedArena::store_object_with_retry`
+    pub fn make_room(&self, full: &ShmFull) -> bool {
+        self.evict_resolved(full.requested) > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    /// Reader producing `count` values of the timestep after `delay`, counting its loads
+    struct Reader {
+        delay: Duration,
+        count: usize,
+        loads: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl ObjectLoader for Reader {
+        async fn load(&self, loader: &Loader) -> Result<Arc<dyn Object>, crate::Error> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            let data = ndarray::Array1::from_elem(self.count, loader.timestep as f32);
+            Ok(Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data })))
+        }
+    }
+
+    fn registry(delay: Duration) -> (ObjectRegistry, Arc<Reader>) {
+        let registry = ObjectRegistry::new();
+        let reader = Arc::new(Reader { delay, count: 100, loads: AtomicUsize::new(0) });
+        registry.set_loader(reader.clone());
+        (registry, reader)
+    }
+
+    fn placeholder(registry: &ObjectRegistry, timestep: i32) -> ObjectId {
+        let loader = Loader::new("Reader", "data_out").with_timestep(timestep);
+        registry.store(Arc::new(VistleObject::placeholder(ObjectType::Vec, loader)))
+    }
+
+    fn full(requested: usize) -> ShmFull {
+        ShmFull {
+            requested,
+            largest_free_block: 0,
+            free: 0,
+            owner: None,
+            owner_usage: None,
+            quota_exceeded: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn placeholders_resolve_to_the_loaded_data() {
+        let (registry, reader) = registry(Duration::ZERO);
+        let id = placeholder(&registry, 7);
+
+        let object = registry.resolve(id).await.unwrap();
+        assert_eq!(object.id(), id);
+        assert_eq!(object.meta().timestep, 7);
+        assert!(matches!(object.payload(), Some(ObjectPayload::VecScalar { data }) if data.len() == 100 && data[0] == 7.0));
+        // The realized object replaces the placeholder, so it is loaded only once
+        assert!(registry.get(id).unwrap().is_complete());
+        registry.resolve(id).await.unwrap();
+        assert_eq!(reader.loads.load(Ordering::SeqCst), 1);
+        assert_eq!(registry.resolved_bytes(), 100 * std::mem::size_of::<f32>());
+    }
+
+    #[tokio::test]
+    async fn concurrent_resolves_of_one_object_load_it_once() {
+        let (registry, reader) = registry(Duration::from_millis(20));
+        let id = placeholder(&registry, 0);
+
+        let resolved = futures::future::join_all((0..8).map(|_| registry.resolve(id))).await;
+        assert!(resolved.iter().all(|r| r.as_ref().is_ok_and(|o| o.is_complete())));
+        assert_eq!(reader.loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn evicted_objects_become_placeholders_and_resolve_again() {
+        let (registry, reader) = registry(Duration::ZERO);
+        let (first, second) = (placeholder(&registry, 0), placeholder(&registry, 1));
+        registry.resolve(first).await.unwrap();
+        registry.resolve(second).await.unwrap();
+        // Touching the first makes the second the least recently used
+        registry.resolve(first).await.unwrap();
+
+        assert_eq!(registry.evict_resolved(1), 400);
+        assert!(registry.get(first).unwrap().is_complete());
+        let evicted = registry.get(second).unwrap();
+        assert!(!evicted.is_complete());
+        assert_eq!(evicted.meta().timestep, 1);
+
+        let object = registry.resolve(second).await.unwrap();
+        assert!(object.is_complete());
+        assert_eq!(reader.loads.load(Ordering::SeqCst), 3);
+
+        // Making room evicts until nothing realized is left
+        assert!(registry.make_room(&full(1000)));
+        assert_eq!(registry.resolved_bytes(), 0);
+        assert!(!registry.make_room(&full(1)));
+    }
+
+    #[tokio::test]
+    async fn only_placeholders_are_incomplete() {
+        let (registry, reader) = registry(Duration::ZERO);
+        let id = placeholder(&registry, 0);
+        assert!(!registry.get(id).unwrap().is_complete());
+
+        let data = ndarray::Array1::from_elem(3, 1.0f32);
+        let complete: Arc<dyn Object> = Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data }));
+        assert!(complete.is_complete());
+        assert!(Arc::ptr_eq(&registry.resolve_object(&complete).await.unwrap(), &complete));
+        assert_eq!(reader.loads.load(Ordering::SeqCst), 0);
+
+        // Without a loader placeholders stay unresolved
+        let unloaded = ObjectRegistry::new();
+        let id = placeholder(&unloaded, 0);
+        assert!(matches!(unloaded.resolve(id).await, Err(crate::Error::Config(_))));
+    }
+}
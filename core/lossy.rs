@@ -0,0 +1,500 @@
+//! Lossy compression of float fields with an absolute error bound
+//!
+//! Fields are cut into blocks of `LOSSY_BLOCK_SIZE` values. Each block is
+//! quantized relative to its minimum in steps of twice the error bound and
+//! packed with as many bits per value as its largest step count needs, so
+//! smooth fields shrink more than noisy ones. Every reconstructed value is
+//! checked against the bound after rounding to f32; blocks failing the
+//! check, holding NaN or infinity, or not getting smaller are kept as they
+//! are.
+//!
+//! Only scalar fields, vector fields and uniform grid values are compressed,
+//! and only on objects whose producer opted in by setting
+//! `attribute::LOSSY_ERROR_BOUND`, usually through
+//! `ModuleSpec::with_lossy_output`. Coordinates and connectivity are always
+//! stored exactly. Compressed objects carry `attribute::LOSSY`, which stays
+//! after decompression so provenance shows the data is approximate.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{attribute, ObjectData, ObjectPayload};
+use crate::util::fmt::{format_f32, parse_f32};
+
+/// Values quantized together
+pub const LOSSY_BLOCK_SIZE: usize = 64;
+
+/// A float array compressed with an absolute error bound
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LossyField {
+    /// Largest difference between a value and its reconstruction
+    pub error_bound: f32,
+    pub len: usize,
+    blocks: Vec<LossyBlock>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum LossyBlock {
+    /// Values are `offset + q * 2 * error_bound` with `bits` bits per `q`, least significant first
+    Quantized { offset: f32, bits: u8, packed: Vec<u8> },
+    Exact(Vec<f32>),
+}
+
+impl LossyField {
+    pub fn compress(values: &[f32], error_bound: f32) -> Result<Self, crate::Error> {
+        if !(error_bound.is_finite() && error_bound > 0.0) {
+            return Err(crate::Error::Config(format!(
+                "Lossy error bound must be positive and finite, got {}",
+                error_bound
+            )));
+        }
+        Ok(Self {
+            error_bound,
+            len: values.len(),
+            blocks: values.chunks(LOSSY_BLOCK_SIZE).map(|block| quantize(block, error_bound)).collect(),
+        })
+    }
+
+    /// Reconstruct the values
+    ///
+    /// Fails for records whose blocks do not add up to `len` values, e.g.
+    /// truncated or corrupted ones.
+    pub fn decompress(&self) -> Result<Vec<f32>, crate::Error> {
+        if !(self.error_bound.is_finite() && self.error_bound > 0.0) {
+            return Err(malformed(format!("error bound {}", self.error_bound)));
+        }
+        let step = 2.0 * self.error_bound as f64;
+        // A corrupt `len` must not decide the allocation
+        let mut values = Vec::with_capacity(self.len.min(self.blocks.len() * LOSSY_BLOCK_SIZE));
+        for (index, block) in self.blocks.iter().enumerate() {
+            let count = (self.len - values.len()).min(LOSSY_BLOCK_SIZE);
+            if count == 0 {
+                return Err(malformed(format!("{} blocks for {} values", self.blocks.len(), self.len)));
+            }
+            match block {
+                LossyBlock::Quantized { offset, bits, packed } => {
+                    if !offset.is_finite() || *bits >= 32 {
+                        return Err(malformed(format!("block {}: offset {} with {} bits", index, offset, bits)));
+                    }
+                    if packed.len() != (count * *bits as usize).div_ceil(8) {
+                        return Err(malformed(format!(
+                            "block {}: {} packed bytes for {} values of {} bits",
+                            index, packed.len(), count, bits
+                        )));
+                    }
+                    values.extend((0..count).map(|i| {
+                        (*offset as f64 + unpack(packed, *bits, i) as f64 * step) as f32
+                    }));
+                }
+                LossyBlock::Exact(exact) => {
+                    if exact.len() != count {
+                        return Err(malformed(format!("block {}: {} exact values, expected {}", index, exact.len(), count)));
+                    }
+                    values.extend_from_slice(exact);
+                }
+            }
+        }
+        if values.len() != self.len {
+            return Err(malformed(format!("{} of {} values present", values.len(), self.len)));
+        }
+        Ok(values)
+    }
+
+    /// Approximate size of the compressed data
+    pub fn size_bytes(&self) -> usize {
+        self.blocks.iter()
+            .map(|block| match block {
+                LossyBlock::Quantized { packed, .. } => packed.len() + 5,
+                LossyBlock::Exact(exact) => exact.len() * std::mem::size_of::<f32>(),
+            })
+            .sum()
+    }
+
+    /// Uncompressed size divided by compressed size
+    pub fn ratio(&self) -> f64 {
+        (self.len * std::mem::size_of::<f32>()) as f64 / self.size_bytes().max(1) as f64
+    }
+}
+
+fn quantize(block: &[f32], error_bound: f32) -> LossyBlock {
+    let exact = || LossyBlock::Exact(block.to_vec());
+    if block.iter().any(|v| !v.is_finite()) {
+        return exact();
+    }
+    let offset = block.iter().copied().fold(f32::INFINITY, f32::min);
+    let step = 2.0 * error_bound as f64;
+    let steps: Vec<u64> = block.iter().map(|&v| ((v as f64 - offset as f64) / step).round() as u64).collect();
+    let bits = 64 - steps.iter().max().copied().unwrap_or(0).leading_zeros();
+    if bits >= 32 {
+        return exact();
+    }
+    // Rounding to f32 can push a reconstruction past the bound
+    let within = block.iter().zip(&steps).all(|(&v, &q)| {
+        let reconstructed = (offset as f64 + q as f64 * step) as f32;
+        (reconstructed as f64 - v as f64).abs() <= error_bound as f64
+    });
+    if !within {
+        return exact();
+    }
+
+    let mut packed = vec![0u8; (block.len() * bits as usize).div_ceil(8)];
+    for (i, &q) in steps.iter().enumerate() {
+        for bit in 0..bits as usize {
+            if q >> bit & 1 == 1 {
+                let position = i * bits as usize + bit;
+                packed[position / 8] |= 1 << (position % 8);
+            }
+        }
+    }
+    LossyBlock::Quantized { offset, bits: bits as u8, packed }
+}
+
+fn malformed(message: String) -> crate::Error {
+    crate::Error::serialization("lossy", format!("malformed field: {}", message).into())
+}
+
+fn unpack(packed: &[u8], bits: u8, index: usize) -> u64 {
+    (0..bits as usize).fold(0, |q, bit| {
+        let position = index * bits as usize + bit;
+        q | (((packed[position / 8] >> (position % 8)) & 1) as u64) << bit
+    })
+}
+
+/// Payload whose float values are compressed with `LossyField`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LossyPayload {
+    VecScalar {
+        data: LossyField,
+    },
+    /// Three components per row
+    VecVec3 {
+        data: LossyField,
+    },
+    UniformGrid {
+        dims: [usize; 3],
+        origin: [f32; 3],
+        spacing: [f32; 3],
+        values: LossyField,
+    },
+}
+
+impl LossyPayload {
+    /// Compress the float values of a field payload; other payloads are refused
+    pub fn compress(payload: &ObjectPayload, error_bound: f32) -> Result<Self, crate::Error> {
+        let field = |values: Vec<f32>| LossyField::compress(&values, error_bound);
+        Ok(match payload {
+            ObjectPayload::VecScalar { data } => LossyPayload::VecScalar { data: field(data.to_vec())? },
+            ObjectPayload::VecVec3 { data } => LossyPayload::VecVec3 {
+                data: field(data.iter().copied().collect())?,
+            },
+            ObjectPayload::UniformGrid { dims, origin, spacing, values } => LossyPayload::UniformGrid {
+                dims: *dims,
+                origin: *origin,
+                spacing: *spacing,
+                values: field(values.to_vec())?,
+            },
+            other => return Err(crate::Error::Config(format!(
+                "Lossy compression applies to scalar and vector fields and grid values, not {}",
+                other.kind()
+            ))),
+        })
+    }
+
+    pub fn decompress(&self) -> Result<ObjectPayload, crate::Error> {
+        Ok(match self {
+            LossyPayload::VecScalar { data } => ObjectPayload::VecScalar {
+                data: ndarray::Array1::from(data.decompress()?),
+            },
+            LossyPayload::VecVec3 { data } => {
+                let values = data.decompress()?;
+                let rows = values.len() / 3;
+                ObjectPayload::VecVec3 {
+                    data: ndarray::Array2::from_shape_vec((rows, 3), values)
+                        .map_err(|_| malformed(format!("{} values are not whole rows of 3", data.len)))?,
+                }
+            }
+            LossyPayload::UniformGrid { dims, origin, spacing, values } => ObjectPayload::UniformGrid {
+                dims: *dims,
+                origin: *origin,
+                spacing: *spacing,
+                values: ndarray::Array1::from(values.decompress()?),
+            },
+        })
+    }
+
+    pub fn field(&self) -> &LossyField {
+        match self {
+            LossyPayload::VecScalar { data } | LossyPayload::VecVec3 { data } => data,
+            LossyPayload::UniformGrid { values, .. } => values,
+        }
+    }
+}
+
+impl ObjectData {
+    /// Error bound the producer allows for this object, if it opted in to lossy compression
+    pub fn lossy_error_bound(&self) -> Result<Option<f32>, crate::Error> {
+        self.attributes.get(attribute::LOSSY_ERROR_BOUND)
+            .map(|text| parse_f32(text).map_err(|_| crate::Error::Config(format!(
+                "Object {}: invalid lossy error bound {:?}",
+                self.id, text
+            ))))
+            .transpose()
+    }
+
+    /// Copy with the payload compressed within the opted-in error bound
+    ///
+    /// Fails for objects that did not opt in and for payloads other than
+    /// float fields, e.g. geometry with connectivity.
+    pub fn to_lossy(&self) -> Result<ObjectData, crate::Error> {
+        let error_bound = self.lossy_error_bound()?.ok_or_else(|| crate::Error::Config(format!(
+            "Object {} was not opted in to lossy compression",
+            self.id
+        )))?;
+        let payload = LossyPayload::compress(&self.data, error_bound)
+            .map_err(|e| match e {
+                crate::Error::Config(message) => crate::Error::Config(format!("Object {}: {}", self.id, message)),
+                e => e,
+            })?;
+        tracing::debug!(
+            "Compressed object {} within {} to 1/{:.1} of its size",
+            self.id, error_bound, payload.field().ratio()
+        );
+        let mut data = self.clone();
+        data.attributes.insert(attribute::LOSSY.to_string(), format_f32(error_bound));
+        data.data = Arc::new(ObjectPayload::Lossy(payload));
+        Ok(data)
+    }
+
+    /// Whether `to_lossy` would compress the object
+    pub fn accepts_lossy(&self) -> bool {
+        matches!(self.lossy_error_bound(), Ok(Some(_)))
+            && matches!(
+                *self.data,
+                ObjectPayload::VecScalar { .. } | ObjectPayload::VecVec3 { .. } | ObjectPayload::UniformGrid { .. }
+            )
+    }
+
+    /// Replace a compressed payload by its reconstruction; `attribute::LOSSY` stays
+    ///
+    /// Fails for malformed compressed payloads, which are left in place.
+    pub fn decompress_lossy(&mut self) -> Result<(), crate::Error> {
+        if let ObjectPayload::Lossy(payload) = &*self.data {
+            let payload = payload.decompress().map_err(|e| match e {
+                crate::Error::Serialization { codec, source } => crate::Error::Serialization {
+                    codec,
+                    source: format!("Object {}: {}", self.id, source).into(),
+                },
+                e => e,
+            })?;
+            self.data = Arc::new(payload);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Object, ObjectType, VistleObject};
+
+    fn within(original: &[f32], reconstructed: &[f32], error_bound: f32) {
+        assert_eq!(original.len(), reconstructed.len());
+        for (i, (a, b)) in original.iter().zip(reconstructed).enumerate() {
+            assert!((a - b).abs() <= error_bound, "value {}: {} reconstructed as {}", i, a, b);
+        }
+    }
+
+    /// Deterministic values in [-scale, scale]
+    fn noise(len: usize, scale: f32) -> Vec<f32> {
+        let mut state = 12345u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((state >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0) * scale
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reconstructions_stay_within_the_bound() {
+        for error_bound in [1e-4, 1e-2, 0.5] {
+            for values in [noise(1000, 100.0), noise(150, 1e-3), (0..300).map(|i| (i as f32 * 0.01).sin()).collect()] {
+                let field = LossyField::compress(&values, error_bound).unwrap();
+                assert_eq!(field.len, values.len());
+                within(&values, &field.decompress().unwrap(), error_bound);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_fields_shrink() {
+        let values: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin()).collect();
+        let field = LossyField::compress(&values, 1e-3).unwrap();
+        assert!(field.ratio() > 2.0, "ratio {}", field.ratio());
+        assert!(LossyField::compress(&values, 1e-1).unwrap().ratio() > field.ratio());
+    }
+
+    #[test]
+    fn constant_blocks_need_no_bits() {
+        let field = LossyField::compress(&[7.25; LOSSY_BLOCK_SIZE], 1e-6).unwrap();
+        assert_eq!(field.size_bytes(), 5);
+        assert_eq!(field.decompress().unwrap(), [7.25; LOSSY_BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn blocks_that_cannot_be_quantized_are_kept_exactly() {
+        let mut values: Vec<f32> = (0..2 * LOSSY_BLOCK_SIZE).map(|i| i as f32).collect();
+        values[3] = f32::NAN;
+        values[4] = f32::INFINITY;
+        let field = LossyField::compress(&values, 0.25).unwrap();
+        let reconstructed = field.decompress().unwrap();
+        assert!(reconstructed[3].is_nan());
+        assert_eq!(reconstructed[..LOSSY_BLOCK_SIZE][4..], values[..LOSSY_BLOCK_SIZE][4..]);
+        within(&values[LOSSY_BLOCK_SIZE..], &reconstructed[LOSSY_BLOCK_SIZE..], 0.25);
+
+        // A spread of more than 2^32 steps keeps the block as it is
+        let wide = [0.0, 1e10, -3.5];
+        let field = LossyField::compress(&wide, 1e-3).unwrap();
+        assert_eq!(field.decompress().unwrap(), wide);
+        assert_eq!(field.size_bytes(), wide.len() * 4);
+    }
+
+    #[test]
+    fn error_bounds_must_be_positive_and_finite() {
+        for error_bound in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(matches!(LossyField::compress(&[1.0], error_bound), Err(crate::Error::Config(_))), "{}", error_bound);
+        }
+        assert_eq!(LossyField::compress(&[], 0.1).unwrap().decompress().unwrap(), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn field_payloads_keep_their_shape() {
+        let vectors = ndarray::Array2::from_shape_vec((70, 3), noise(210, 5.0)).unwrap();
+        let payload = ObjectPayload::VecVec3 { data: vectors.clone() };
+        let ObjectPayload::VecVec3 { data } = LossyPayload::compress(&payload, 0.01).unwrap().decompress().unwrap() else {
+            panic!("not a vector field");
+        };
+        assert_eq!(data.dim(), (70, 3));
+        within(vectors.as_slice().unwrap(), data.as_slice().unwrap(), 0.01);
+
+        let grid = ObjectPayload::UniformGrid {
+            dims: [4, 4, 4],
+            origin: [1.0, 2.0, 3.0],
+            spacing: [0.5; 3],
+            values: ndarray::Array1::from(noise(64, 1.0)),
+        };
+        let ObjectPayload::UniformGrid { dims, origin, spacing, .. } = LossyPayload::compress(&grid, 0.01).unwrap().decompress().unwrap() else {
+            panic!("not a uniform grid");
+        };
+        assert_eq!((dims, origin, spacing), ([4, 4, 4], [1.0, 2.0, 3.0], [0.5; 3]));
+
+        let points = ObjectPayload::Points { coordinates: ndarray::Array2::zeros((2, 3)) };
+        assert!(matches!(LossyPayload::compress(&points, 0.01), Err(crate::Error::Config(_))));
+    }
+
+    fn is_malformed(result: Result<Vec<f32>, crate::Error>) -> bool {
+        matches!(result, Err(crate::Error::Serialization { codec: "lossy", .. }))
+    }
+
+    #[test]
+    fn truncated_fields_are_errors() {
+        let field = LossyField::compress(&noise(3 * LOSSY_BLOCK_SIZE, 10.0), 0.01).unwrap();
+        let bytes = bincode::serialize(&field).unwrap();
+        assert!(bincode::deserialize::<LossyField>(&bytes[..bytes.len() - 3]).is_err());
+
+        let mut missing_block = field.clone();
+        missing_block.blocks.pop();
+        assert!(is_malformed(missing_block.decompress()));
+
+        let mut short_packing = field.clone();
+        let LossyBlock::Quantized { packed, .. } = &mut short_packing.blocks[1] else {
+            panic!("block not quantized");
+        };
+        packed.pop();
+        assert!(is_malformed(short_packing.decompress()));
+
+        let mut extra_block = field.clone();
+        extra_block.len -= LOSSY_BLOCK_SIZE;
+        assert!(is_malformed(extra_block.decompress()));
+    }
+
+    #[test]
+    fn corrupt_fields_are_errors() {
+        let field = LossyField::compress(&noise(2 * LOSSY_BLOCK_SIZE, 10.0), 0.01).unwrap();
+        let corrupt = |change: &dyn Fn(&mut LossyField)| {
+            let mut field = field.clone();
+            change(&mut field);
+            field.decompress()
+        };
+        let set_bits = |value: u8| move |field: &mut LossyField| {
+            if let LossyBlock::Quantized { bits, .. } = &mut field.blocks[0] {
+                *bits = value;
+            }
+        };
+
+        assert!(is_malformed(corrupt(&set_bits(40))));
+        assert!(is_malformed(corrupt(&set_bits(31))));
+        assert!(is_malformed(corrupt(&|field| field.error_bound = f32::NAN)));
+        assert!(is_malformed(corrupt(&|field| field.blocks[0] = LossyBlock::Exact(vec![1.0; 3]))));
+        assert!(is_malformed(corrupt(&|field| {
+            if let LossyBlock::Quantized { offset, .. } = &mut field.blocks[0] {
+                *offset = f32::INFINITY;
+            }
+        })));
+        // A huge length is refused rather than allocated
+        assert!(is_malformed(corrupt(&|field| field.len = usize::MAX)));
+        assert!(is_malformed(corrupt(&|field| field.len += 1)));
+    }
+
+    #[test]
+    fn malformed_objects_stay_compressed() {
+        let payload = LossyPayload::VecVec3 { data: LossyField::compress(&[1.0; 4], 0.01).unwrap() };
+        assert!(matches!(payload.decompress(), Err(crate::Error::Serialization { codec: "lossy", .. })));
+
+        let mut object = opted_in(ObjectPayload::Empty, "0.01");
+        object.data = Arc::new(ObjectPayload::Lossy(payload));
+        let message = object.decompress_lossy().unwrap_err().to_string();
+        assert!(message.contains(&format!("Object {}: malformed field", object.id)), "{}", message);
+        assert!(matches!(*object.data, ObjectPayload::Lossy(_)));
+    }
+
+    fn opted_in(payload: ObjectPayload, error_bound: &str) -> ObjectData {
+        let mut object = VistleObject::with_data(ObjectType::Vec, payload).as_data().cloned().unwrap();
+        object.attributes.insert(attribute::LOSSY_ERROR_BOUND.to_string(), error_bound.to_string());
+        object
+    }
+
+    #[test]
+    fn objects_are_compressed_only_when_opted_in() {
+        let values = noise(100, 10.0);
+        let mut object = opted_in(ObjectPayload::VecScalar { data: ndarray::Array1::from(values.clone()) }, "0.01");
+        assert!(object.accepts_lossy());
+        let mut lossy = object.to_lossy().unwrap();
+        assert!(matches!(*lossy.data, ObjectPayload::Lossy(_)));
+        assert_eq!(lossy.attributes[attribute::LOSSY], "0.01");
+
+        lossy.decompress_lossy().unwrap();
+        let ObjectPayload::VecScalar { data } = &*lossy.data else {
+            panic!("not decompressed");
+        };
+        within(&values, data.as_slice().unwrap(), 0.01);
+        assert_eq!(lossy.attributes[attribute::LOSSY], "0.01");
+
+        object.attributes.remove(attribute::LOSSY_ERROR_BOUND);
+        assert!(!object.accepts_lossy());
+        assert!(object.to_lossy().unwrap_err().to_string().contains("was not opted in"));
+    }
+
+    #[test]
+    fn invalid_opt_ins_are_errors() {
+        let object = opted_in(ObjectPayload::VecScalar { data: ndarray::Array1::zeros(4) }, "tiny");
+        assert!(!object.accepts_lossy());
+        assert!(object.to_lossy().unwrap_err().to_string().contains("invalid lossy error bound \"tiny\""));
+
+        let points = opted_in(ObjectPayload::Points { coordinates: ndarray::Array2::zeros((2, 3)) }, "0.01");
+        assert!(!points.accepts_lossy());
+        let message = points.to_lossy().unwrap_err().to_string();
+        assert!(message.contains(&format!("Object {}: Lossy compression applies to", points.id)), "{}", message);
+    }
+}
@@ -0,0 +1,121 @@
+//! Data fields mapped onto the geometry they are defined on
+//!
+//! A field object may name the grid or surface its values belong to and
+//! whether there is one value per vertex, per cell or per element. The
+//! geometry stays a separate object, typically produced by an upstream
+//! module, and is listed among the field's `references` so the registry
+//! resolves it like any other reference.
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Object, ObjectId, ObjectRegistry};
+
+/// What the values of a mapped field are attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Mapping {
+    /// One value per vertex or grid point
+    PerVertex,
+    /// One value per cell of a grid
+    PerCell,
+    /// One value per primitive of a surface or line set: triangle, quad, polygon or segment
+    PerElement,
+}
+
+impl Mapping {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mapping::PerVertex => "per vertex",
+            Mapping::PerCell => "per cell",
+            Mapping::PerElement => "per element",
+        }
+    }
+}
+
+/// Geometry a field is defined on and how its values map onto it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridMapping {
+    pub grid: ObjectId,
+    pub mapping: Mapping,
+}
+
+impl ObjectRegistry {
+    /// Geometry a mapped field is defined on
+    ///
+    /// Fails if the field is not mapped or its geometry is not registered.
+    pub fn resolve_grid(&self, field: &dyn Object) -> Result<(std::sync::Arc<dyn Object>, Mapping), crate::Error> {
+        let mapping = field.grid().ok_or_else(|| crate::Error::Module(format!(
+            "Object {} is not mapped onto a grid",
+            field.id()
+        )))?;
+        let grid = self.get(mapping.grid).ok_or_else(|| crate::Error::Module(format!(
+            "Grid {} of object {} is not registered",
+            mapping.grid, field.id()
+        )))?;
+        Ok((grid, mapping.mapping))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use ndarray::array;
+
+    use crate::core::{ObjectPayload, ObjectType, VistleObject};
+
+    /// Two triangles forming the unit square
+    fn square() -> VistleObject {
+        VistleObject::with_data(ObjectType::Triangles, ObjectPayload::Triangles {
+            coordinates: array![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+            triangles: array![[0, 1, 2], [0, 2, 3]],
+        })
+    }
+
+    fn temperature() -> VistleObject {
+        VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: array![280.0, 290.0, 300.0, 310.0] })
+    }
+
+    #[test]
+    fn fields_resolve_to_their_geometry_through_references() {
+        let registry = ObjectRegistry::new();
+        let surface = square();
+        let field = temperature().with_grid(&surface, Mapping::PerVertex);
+        let surface_id = registry.store(Arc::new(surface));
+        let field_id = registry.store(Arc::new(field));
+
+        let field = registry.get(field_id).unwrap();
+        assert_eq!(field.grid(), Some(GridMapping { grid: surface_id, mapping: Mapping::PerVertex }));
+        assert_eq!(field.references(), [surface_id]);
+
+        let grid = registry.get(field.references()[0]).unwrap();
+        let coordinates = grid.payload().and_then(ObjectPayload::coordinates).unwrap();
+        assert_eq!(coordinates.nrows(), field.as_scalar_field().unwrap().len());
+        assert_eq!(coordinates.row(2).to_vec(), [1.0, 1.0, 0.0]);
+
+        let (resolved, mapping) = registry.resolve_grid(field.as_ref()).unwrap();
+        assert_eq!((resolved.id(), mapping), (surface_id, Mapping::PerVertex));
+    }
+
+    #[test]
+    fn per_element_fields_map_onto_primitives() {
+        let surface = square();
+        let area = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: array![0.5, 0.5] })
+            .with_grid_id(surface.id(), Mapping::PerElement);
+        assert_eq!(area.grid().map(|g| g.mapping), Some(Mapping::PerElement));
+        assert_eq!(Mapping::PerElement.as_str(), "per element");
+    }
+
+    #[test]
+    fn unmapped_and_unregistered_grids_are_errors() {
+        let registry = ObjectRegistry::new();
+        let unmapped = temperature();
+        assert!(unmapped.references().is_empty());
+        let message = registry.resolve_grid(&unmapped).unwrap_err().to_string();
+        assert!(message.contains("is not mapped onto a grid"), "{}", message);
+
+        let orphan = temperature().with_grid(&square(), Mapping::PerVertex);
+        let message = registry.resolve_grid(&orphan).unwrap_err().to_string();
+        assert!(message.contains("is not registered"), "{}", message);
+    }
+}
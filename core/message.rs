@@ -1,358 +1,1147 @@
-//! Message passing system for distributed communication
-
-use std::sync::Arc;
-use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
-use uuid::Uuid;
-
-use crate::core::ObjectId;
-
-/// Unique message identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct MessageId(Uuid);
-
-impl MessageId {
-    pub fn new() -> Self {
-        Self(Uuid::new_v4())
-    }
-}
-
-impl Default for MessageId {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-/// Message priority levels
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub enum Priority {
-    Low = 0,
-    Normal = 1,
-    High = 2,
-    Critical = 3,
-}
-
-/// Message types for Vistle communication
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum MessageType {
-    // Control messages
-    Execute {
-        module_id: u32,
-        timestep: i32,
-    },
-    CancelExecute {
-        module_id: u32,
-    },
-    Quit,
-
-    // Data messages
-    AddObject {
-        object_id: ObjectId,
-        port_name: String,
-    },
-    RemoveObject {
-        object_id: ObjectId,
-    },
-
-    // Parameter messages
-    SetParameter {
-        module_id: u32,
-        param_name: String,
-        value: ParameterValue,
-    },
-    AddParameter {
-        module_id: u32,
-        param_name: String,
-        param_type: ParameterType,
-    },
-
-    // Connection messages
-    ConnectPorts {
-        from_module: u32,
-        from_port: String,
-        to_module: u32,
-        to_port: String,
-    },
-    DisconnectPorts {
-        from_module: u32,
-        from_port: String,
-        to_module: u32,
-        to_port: String,
-    },
-
-    // Status messages
-    ModuleReady {
-        module_id: u32,
-    },
-    ComputationComplete {
-        module_id: u32,
-        objects_created: Vec<ObjectId>,
-    },
-    Error {
-        module_id: u32,
-        message: String,
-    },
-
-    // Custom messages
-    Custom {
-        type_id: u32,
-        data: Vec<u8>,
-    },
-}
-
-/// Parameter value types
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ParameterValue {
-    Int(i32),
-    Float(f32),
-    String(String),
-    Bool(bool),
-    VecInt(Vec<i32>),
-    VecFloat(Vec<f32>),
-    VecString(Vec<String>),
-}
-
-/// Parameter types for dynamic parameter creation
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ParameterType {
-    Int { min: Option<i32>, max: Option<i32> },
-    Float { min: Option<f32>, max: Option<f32> },
-    String,
-    Bool,
-    VectorInt { min: Option<i32>, max: Option<i32> },
-    VectorFloat { min: Option<f32>, max: Option<f32> },
-    VectorString,
-}
-
-/// Complete message structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Message {
-    pub id: MessageId,
-    pub sender: u32,      // Module ID of sender
-    pub recipient: u32,   // Module ID of recipient (0 for broadcast)
-    pub priority: Priority,
-    pub message_type: MessageType,
-    pub timestamp: std::time::SystemTime,
-}
-
-impl Message {
-    pub fn new(sender: u32, recipient: u32, message_type: MessageType) -> Self {
-        Self {
-            id: MessageId::new(),
-            sender,
-            recipient,
-            priority: Priority::Normal,
-            message_type,
-            timestamp: std::time::SystemTime::now(),
-        }
-    }
-
-    pub fn with_priority(mut self, priority: Priority) -> Self {
-        self.priority = priority;
-        self
-    }
-
-    pub fn is_broadcast(&self) -> bool {
-        self.recipient == 0
-    }
-}
-
-/// Message payload for large data transfers
-#[derive(Debug, Clone)]
-pub enum MessagePayload {
-    None,
-    ObjectData(Vec<u8>),
-    ParameterData(Vec<u8>),
-    Custom(Vec<u8>),
-}
-
-/// Complete message envelope
-#[derive(Debug)]
-pub struct MessageEnvelope {
-    pub message: Message,
-    pub payload: MessagePayload,
-}
-
-/// Async message sender
-#[async_trait::async_trait]
-pub trait MessageSender: Send + Sync {
-    async fn send_message(&self, message: MessageEnvelope) -> Result<(), crate::Error>;
-}
-
-/// Async message receiver
-#[async_trait::async_trait]
-pub trait MessageReceiver: Send + Sync {
-    async fn receive_message(&mut self) -> Result<Option<MessageEnvelope>, crate::Error>;
-}
-
-/// In-memory message queue for local communication
-pub struct MessageQueue {
-    sender: mpsc::UnboundedSender<MessageEnvelope>,
-    receiver: mpsc::UnboundedReceiver<MessageEnvelope>,
-}
-
-impl MessageQueue {
-    pub fn new() -> Self {
-        let (sender, receiver) = mpsc::unbounded_channel();
-        Self { sender, receiver }
-    }
-
-    pub fn sender(&self) -> mpsc::UnboundedSender<MessageEnvelope> {
-        self.sender.clone()
-    }
-}
-
-#[async_trait::async_trait]
-impl MessageSender for MessageQueue {
-    async fn send_message(&self, message: MessageEnvelope) -> Result<(), crate::Error> {
-        self.sender.send(message)
-            .map_err(|_| crate::Error::Module("Failed to send message".to_string()))?;
-        Ok(())
-    }
-}
-
-#[async_trait::async_trait]
-impl MessageReceiver for MessageQueue {
-    async fn receive_message(&mut self) -> Result<Option<MessageEnvelope>, crate::Error> {
-        match self.receiver.try_recv() {
-            Ok(msg) => Ok(Some(msg)),
-            Err(mpsc::error::TryRecvError::Empty) => Ok(None),
-            Err(mpsc::error::TryRecvError::Disconnected) => Ok(None),
-        }
-    }
-}
-
-/// MPI-based distributed message passing
-pub struct MpiMessageChannel {
-    rank: i32,
-    size: i32,
-}
-
-impl MpiMessageChannel {
-    pub fn new() -> Result<Self, crate::Error> {
-        let universe = mpi::initialize().map_err(crate::Error::Mpi)?;
-        let world = universe.world();
-
-        Ok(Self {
-            rank: world.rank(),
-            size: world.size(),
-        })
-    }
-
-    pub fn rank(&self) -> i32 {
-        self.rank
-    }
-
-    pub fn size(&self) -> i32 {
-        self.size
-    }
-}
-
-#[async_trait::async_trait]
-impl MessageSender for MpiMessageChannel {
-    async fn send_message(&self, message: MessageEnvelope) -> Result<(), crate::Error> {
-        // Serialize message
-        let data = bincode::serialize(&message)
-            .map_err(crate::Error::Serialization)?;
-
-        let universe = mpi::initialize().map_err(crate::Error::Mpi)?;
-        let world = universe.world();
-
-        // Send to recipient
-        if message.message.recipient == 0 {
-            // Broadcast to all ranks
-            for rank in 0..self.size {
-                if rank != self.rank {
-                    world.process_at_rank(rank).send(&data);
-                }
-            }
-        } else {
-            // Send to specific rank
-            world.process_at_rank(message.message.recipient as i32).send(&data);
-        }
-
-        Ok(())
-    }
-}
-
-#[async_trait::async_trait]
-impl MessageReceiver for MpiMessageChannel {
-    async fn receive_message(&mut self) -> Result<Option<MessageEnvelope>, crate::Error> {
-        let universe = mpi::initialize().map_err(crate::Error::Mpi)?;
-        let world = universe.world();
-
-        // Try to receive message (non-blocking)
-        let mut buffer = Vec::new();
-        match world.any_process().receive_into(&mut buffer) {
-            Ok(_) => {
-                let envelope: MessageEnvelope = bincode::deserialize(&buffer)
-                    .map_err(crate::Error::Serialization)?;
-                Ok(Some(envelope))
-            }
-            Err(_) => Ok(None), // No message available
-        }
-    }
-}
-
-/// Message router for complex communication patterns
-pub struct MessageRouter {
-    local_queues: dashmap::DashMap<u32, Arc<MessageQueue>>,
-    mpi_channel: Option<MpiMessageChannel>,
-    handlers: dashmap::DashMap<MessageId, mpsc::UnboundedSender<MessageEnvelope>>,
-}
-
-impl MessageRouter {
-    pub fn new() -> Self {
-        Self {
-            local_queues: dashmap::DashMap::new(),
-            mpi_channel: None,
-            handlers: dashmap::DashMap::new(),
-        }
-    }
-
-    pub fn with_mpi(mut self) -> Result<Self, crate::Error> {
-        self.mpi_channel = Some(MpiMessageChannel::new()?);
-        Ok(self)
-    }
-
-    pub fn register_module(&self, module_id: u32) -> Arc<MessageQueue> {
-        let queue = Arc::new(MessageQueue::new());
-        self.local_queues.insert(module_id, queue.clone());
-        queue
-    }
-
-    pub async fn route_message(&self, envelope: MessageEnvelope) -> Result<(), crate::Error> {
-        let recipient = envelope.message.recipient;
-
-        // Check if it's a local message
-        if let Some(queue) = self.local_queues.get(&recipient) {
-            queue.send_message(envelope).await?;
-            return Ok(());
-        }
-
-        // Use MPI for distributed messages
-        if let Some(mpi) = &self.mpi_channel {
-            mpi.send_message(envelope).await?;
-            return Ok(());
-        }
-
-        Err(crate::Error::Module(format!("No route to module {}", recipient)))
-    }
-
-    pub async fn process_messages(&self) -> Result<(), crate::Error> {
-        // Process MPI messages if available
-        if let Some(mpi) = &self.mpi_channel {
-            if let Some(envelope) = mpi.receive_message().await? {
-                self.route_message(envelope).await?;
-            }
-        }
-
-        Ok(())
-    }
-}
-
-impl Default for MessageRouter {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+//! Message passing system for distributed communication
+
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+#[cfg(feature = "mpi")]
+use mpi::traits::*;
+
+use crate::core::{
+    CodecId, CustomMessage, CustomTypeId, CustomTypeRegistry, LatencyReport, ObjectId, ParameterType, ParameterValue,
+    RawCustomMessage, Route, RouterMetrics,
+};
+#[cfg(feature = "mpi")]
+use crate::mpi::{MpiUniverse, ROUTER_TAG};
+
+/// Protocol version spoken by this build
+///
+/// Version 2 added `MessageType::Ping`, version 3 `MessageType::Ack`.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Oldest protocol version this build can talk to
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Set in `MessageType::Custom::type_id` for variants a newer peer sent that
+/// this build does not know; the low bits hold the peer's variant tag
+pub const UNKNOWN_VARIANT_FLAG: u32 = 0x8000_0000;
+
+/// Highest `MessageType` tag per protocol version, indexed by version
+const MAX_TAG_BY_VERSION: &[u32] = &[0, 13, 14, 15];
+
+/// Sender and recipient of `MessageRouter::ping` messages
+pub const PING_MODULE_ID: u32 = u32::MAX;
+
+/// Unique message identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageId(Uuid);
+
+impl MessageId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for MessageId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Message priority levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    Low = 0,
+    Normal = 1,
+    High = 2,
+    Critical = 3,
+}
+
+/// Message types for Vistle communication
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MessageType {
+    // Control messages
+    Execute {
+        module_id: u32,
+        timestep: i32,
+    },
+    CancelExecute {
+        module_id: u32,
+    },
+    Quit,
+
+    // Data messages
+    AddObject {
+        object_id: ObjectId,
+        port_name: String,
+    },
+    RemoveObject {
+        object_id: ObjectId,
+    },
+
+    // Parameter messages
+    SetParameter {
+        module_id: u32,
+        param_name: String,
+        value: ParameterValue,
+    },
+    AddParameter {
+        module_id: u32,
+        param_name: String,
+        param_type: ParameterType,
+    },
+
+    // Connection messages
+    ConnectPorts {
+        from_module: u32,
+        from_port: String,
+        to_module: u32,
+        to_port: String,
+    },
+    DisconnectPorts {
+        from_module: u32,
+        from_port: String,
+        to_module: u32,
+        to_port: String,
+    },
+
+    // Status messages
+    ModuleReady {
+        module_id: u32,
+    },
+    ComputationComplete {
+        module_id: u32,
+        objects_created: Vec<ObjectId>,
+    },
+    Error {
+        module_id: u32,
+        message: String,
+    },
+
+    // Custom messages, see `MessageRouter::register_custom_type`
+    Custom {
+        type_id: u32,
+        data: Vec<u8>,
+    },
+
+    // Round-trip probe, see `MessageRouter::ping`
+    Ping,
+
+    // Receipt of a message sent over MPI, see `MessageRouter::with_remote_acks`
+    Ack {
+        id: MessageId,
+    },
+}
+
+impl MessageType {
+    /// Stable wire tag of the variant; never reuse or renumber tags
+    pub fn tag(&self) -> u32 {
+        match self {
+            MessageType::Execute { .. } => 1,
+            MessageType::CancelExecute { .. } => 2,
+            MessageType::Quit => 3,
+            MessageType::AddObject { .. } => 4,
+            MessageType::RemoveObject { .. } => 5,
+            MessageType::SetParameter { .. } => 6,
+            MessageType::AddParameter { .. } => 7,
+            MessageType::ConnectPorts { .. } => 8,
+            MessageType::DisconnectPorts { .. } => 9,
+            MessageType::ModuleReady { .. } => 10,
+            MessageType::ComputationComplete { .. } => 11,
+            MessageType::Error { .. } => 12,
+            MessageType::Custom { .. } => 13,
+            MessageType::Ping => 14,
+            MessageType::Ack { .. } => 15,
+        }
+    }
+
+    /// Protocol version that introduced the variant
+    pub fn min_version(&self) -> u32 {
+        let tag = self.tag();
+        (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION)
+            .find(|&v| tag <= Self::max_tag(v))
+            .unwrap_or(PROTOCOL_VERSION)
+    }
+
+    /// Highest tag known to a given protocol version
+    pub fn max_tag(version: u32) -> u32 {
+        MAX_TAG_BY_VERSION
+            .get(version as usize)
+            .or(MAX_TAG_BY_VERSION.last())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Variant name for diagnostics
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageType::Execute { .. } => "Execute",
+            MessageType::CancelExecute { .. } => "CancelExecute",
+            MessageType::Quit => "Quit",
+            MessageType::AddObject { .. } => "AddObject",
+            MessageType::RemoveObject { .. } => "RemoveObject",
+            MessageType::SetParameter { .. } => "SetParameter",
+            MessageType::AddParameter { .. } => "AddParameter",
+            MessageType::ConnectPorts { .. } => "ConnectPorts",
+            MessageType::DisconnectPorts { .. } => "DisconnectPorts",
+            MessageType::ModuleReady { .. } => "ModuleReady",
+            MessageType::ComputationComplete { .. } => "ComputationComplete",
+            MessageType::Error { .. } => "Error",
+            MessageType::Custom { .. } => "Custom",
+            MessageType::Ping => "Ping",
+            MessageType::Ack { .. } => "Ack",
+        }
+    }
+
+    /// Rewrite the message for an older peer, `None` if it cannot be expressed
+    fn downgrade(&self, version: u32) -> Option<MessageType> {
+        if version < MIN_PROTOCOL_VERSION {
+            return None;
+        }
+        if self.min_version() <= version {
+            return Some(self.clone());
+        }
+        match self {
+            // Version 1 pinged with a ModuleReady addressed to no module
+            MessageType::Ping => Some(MessageType::ModuleReady { module_id: PING_MODULE_ID }),
+            _ => None,
+        }
+    }
+
+    /// Encode the variant's fields in the layout of protocol `version`
+    ///
+    /// The body never contains the variant's position in the enum; the wire
+    /// tag says which variant it is, so variants may be reordered freely.
+    fn encode_body(&self, _version: u32, codec: CodecId) -> Result<Vec<u8>, crate::Error> {
+        // No variant has changed its fields since version 1; one that does
+        // gets an arm per layout, keyed by the version
+        match self {
+            MessageType::Execute { module_id, timestep } => codec.serialize(&(module_id, timestep)),
+            MessageType::CancelExecute { module_id } => codec.serialize(module_id),
+            MessageType::Quit | MessageType::Ping => codec.serialize(&()),
+            MessageType::AddObject { object_id, port_name } => codec.serialize(&(object_id, port_name)),
+            MessageType::RemoveObject { object_id } => codec.serialize(object_id),
+            MessageType::SetParameter { module_id, param_name, value } => {
+                codec.serialize(&(module_id, param_name, value))
+            }
+            MessageType::AddParameter { module_id, param_name, param_type } => {
+                codec.serialize(&(module_id, param_name, param_type))
+            }
+            MessageType::ConnectPorts { from_module, from_port, to_module, to_port }
+            | MessageType::DisconnectPorts { from_module, from_port, to_module, to_port } => {
+                codec.serialize(&(from_module, from_port, to_module, to_port))
+            }
+            MessageType::ModuleReady { module_id } => codec.serialize(module_id),
+            MessageType::ComputationComplete { module_id, objects_created } => {
+                codec.serialize(&(module_id, objects_created))
+            }
+            MessageType::Error { module_id, message } => codec.serialize(&(module_id, message)),
+            MessageType::Custom { type_id, data } => codec.serialize(&(type_id, data)),
+            MessageType::Ack { id } => codec.serialize(id),
+        }
+    }
+
+    /// Decode the body of variant `tag` written in the layout of protocol `version`
+    ///
+    /// `None` if the tag is unknown to this build.
+    fn decode_body(tag: u32, _version: u32, body: &[u8], codec: CodecId) -> Result<Option<MessageType>, crate::Error> {
+        let message_type = match tag {
+            1 => {
+                let (module_id, timestep) = codec.deserialize(body)?;
+                MessageType::Execute { module_id, timestep }
+            }
+            2 => MessageType::CancelExecute { module_id: codec.deserialize(body)? },
+            3 => {
+                codec.deserialize::<()>(body)?;
+                MessageType::Quit
+            }
+            4 => {
+                let (object_id, port_name) = codec.deserialize(body)?;
+                MessageType::AddObject { object_id, port_name }
+            }
+            5 => MessageType::RemoveObject { object_id: codec.deserialize(body)? },
+            6 => {
+                let (module_id, param_name, value) = codec.deserialize(body)?;
+                MessageType::SetParameter { module_id, param_name, value }
+            }
+            7 => {
+                let (module_id, param_name, param_type) = codec.deserialize(body)?;
+                MessageType::AddParameter { module_id, param_name, param_type }
+            }
+            8 => {
+                let (from_module, from_port, to_module, to_port) = codec.deserialize(body)?;
+                MessageType::ConnectPorts { from_module, from_port, to_module, to_port }
+            }
+            9 => {
+                let (from_module, from_port, to_module, to_port) = codec.deserialize(body)?;
+                MessageType::DisconnectPorts { from_module, from_port, to_module, to_port }
+            }
+            10 => MessageType::ModuleReady { module_id: codec.deserialize(body)? },
+            11 => {
+                let (module_id, objects_created) = codec.deserialize(body)?;
+                MessageType::ComputationComplete { module_id, objects_created }
+            }
+            12 => {
+                let (module_id, message) = codec.deserialize(body)?;
+                MessageType::Error { module_id, message }
+            }
+            13 => {
+                let (type_id, data) = codec.deserialize(body)?;
+                MessageType::Custom { type_id, data }
+            }
+            14 => {
+                codec.deserialize::<()>(body)?;
+                MessageType::Ping
+            }
+            15 => MessageType::Ack { id: codec.deserialize(body)? },
+            _ => return Ok(None),
+        };
+        Ok(Some(message_type))
+    }
+
+    /// Whether this is an undecodable variant preserved from a newer peer
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, MessageType::Custom { type_id, .. } if type_id & UNKNOWN_VARIANT_FLAG != 0)
+    }
+}
+
+/// Complete message structure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub id: MessageId,
+    /// Protocol version the message was encoded with
+    pub version: u32,
+    pub sender: u32,      // Module ID of sender
+    pub recipient: u32,   // Module ID of recipient (0 for broadcast)
+    pub priority: Priority,
+    pub message_type: MessageType,
+    pub timestamp: std::time::SystemTime,
+}
+
+impl Message {
+    pub fn new(sender: u32, recipient: u32, message_type: MessageType) -> Self {
+        Self {
+            id: MessageId::new(),
+            version: PROTOCOL_VERSION,
+            sender,
+            recipient,
+            priority: Priority::Normal,
+            message_type,
+            timestamp: std::time::SystemTime::now(),
+        }
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.recipient == 0
+    }
+}
+
+/// Message payload for large data transfers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MessagePayload {
+    None,
+    ObjectData(Vec<u8>),
+    ParameterData(Vec<u8>),
+    Custom(Vec<u8>),
+}
+
+/// Complete message envelope
+#[derive(Debug)]
+pub struct MessageEnvelope {
+    pub message: Message,
+    pub payload: MessagePayload,
+}
+
+/// On-the-wire form of an envelope
+///
+/// The header fields are stable across versions. The message type travels as
+/// its stable tag plus the variant's fields, encoded in the layout of the
+/// sender's version, so a receiver can skip variants it does not know instead
+/// of failing to decode the whole message.
+#[derive(Debug, Serialize, Deserialize)]
+struct WireEnvelope {
+    version: u32,
+    id: MessageId,
+    sender: u32,
+    recipient: u32,
+    priority: Priority,
+    timestamp: std::time::SystemTime,
+    tag: u32,
+    body: Vec<u8>,
+    payload: MessagePayload,
+}
+
+/// Negotiate the version two peers use: the lower of both, if supported
+pub fn negotiate_version(local: u32, peer: u32) -> Result<u32, crate::Error> {
+    let common = local.min(peer);
+    if common < MIN_PROTOCOL_VERSION {
+        return Err(crate::Error::Module(format!(
+            "Peer speaks protocol version {}, but at least {} is required (local version {})",
+            peer, MIN_PROTOCOL_VERSION, local
+        )));
+    }
+    Ok(common)
+}
+
+impl MessageEnvelope {
+    /// Encode with bincode for a peer speaking `version`
+    ///
+    /// Variants newer than the peer's version are downgraded where possible
+    /// and refused otherwise.
+    pub fn encode_for(&self, version: u32) -> Result<Vec<u8>, crate::Error> {
+        self.encode_with(version, CodecId::Bincode)
+    }
+
+    /// Encode with `codec` for a peer speaking `version`
+    pub fn encode_with(&self, version: u32, codec: CodecId) -> Result<Vec<u8>, crate::Error> {
+        let message_type = self.message.message_type.downgrade(version).ok_or_else(|| {
+            crate::Error::Module(format!(
+                "Cannot send {} to a peer with protocol version {}: it requires version {}",
+                self.message.message_type.name(),
+                version,
+                self.message.message_type.min_version()
+            ))
+        })?;
+
+        let (tag, body) = match &message_type {
+            // Preserved unknown variants are forwarded as they arrived
+            MessageType::Custom { type_id, data } if type_id & UNKNOWN_VARIANT_FLAG != 0 => {
+                (type_id & !UNKNOWN_VARIANT_FLAG, data.clone())
+            }
+            other => (other.tag(), other.encode_body(version, codec)?),
+        };
+
+        let wire = WireEnvelope {
+            version,
+            id: self.message.id,
+            sender: self.message.sender,
+            recipient: self.message.recipient,
+            priority: self.message.priority,
+            timestamp: self.message.timestamp,
+            tag,
+            body,
+            payload: self.payload.clone(),
+        };
+        codec.serialize(&wire)
+    }
+
+    /// Decode a bincode envelope from any supported version
+    ///
+    /// Variants unknown to this build become `MessageType::Custom` with
+    /// `UNKNOWN_VARIANT_FLAG` set and the raw body preserved.
+    pub fn decode(bytes: &[u8]) -> Result<Self, crate::Error> {
+        Self::decode_with(bytes, CodecId::Bincode)
+    }
+
+    /// Decode an envelope written by `encode_with` with the same codec
+    pub fn decode_with(bytes: &[u8], codec: CodecId) -> Result<Self, crate::Error> {
+        let wire: WireEnvelope = codec.deserialize(bytes)?;
+
+        let message_type = match MessageType::decode_body(wire.tag, wire.version, &wire.body, codec)? {
+            Some(message_type) => message_type,
+            None => {
+                tracing::debug!(
+                    "Preserving unknown message variant {} from protocol version {}",
+                    wire.tag, wire.version
+                );
+                MessageType::Custom {
+                    type_id: UNKNOWN_VARIANT_FLAG | wire.tag,
+                    data: wire.body,
+                }
+            }
+        };
+
+        Ok(Self {
+            message: Message {
+                id: wire.id,
+                version: wire.version,
+                sender: wire.sender,
+                recipient: wire.recipient,
+                priority: wire.priority,
+                message_type,
+                timestamp: wire.timestamp,
+            },
+            payload: wire.payload,
+        })
+    }
+}
+
+/// Async message sender
+#[async_trait::async_trait]
+pub trait MessageSender: Send + Sync {
+    async fn send_message(&self, message: MessageEnvelope) -> Result<(), crate::Error>;
+}
+
+/// Async message receiver
+#[async_trait::async_trait]
+pub trait MessageReceiver: Send + Sync {
+    async fn receive_message(&mut self) -> Result<Option<MessageEnvelope>, crate::Error>;
+}
+
+/// Envelope waiting in a `MessageQueue`
+#[derive(Debug)]
+pub struct QueuedEnvelope {
+    pub envelope: MessageEnvelope,
+    /// Enqueue time, set only while latency recording is on
+    pub enqueued: Option<std::time::Instant>,
+}
+
+impl From<MessageEnvelope> for QueuedEnvelope {
+    fn from(envelope: MessageEnvelope) -> Self {
+        Self { envelope, enqueued: None }
+    }
+}
+
+/// In-memory message queue for local communication
+pub struct MessageQueue {
+    sender: mpsc::UnboundedSender<QueuedEnvelope>,
+    receiver: mpsc::UnboundedReceiver<QueuedEnvelope>,
+    latency: Option<(Arc<RouterMetrics>, Route)>,
+}
+
+impl MessageQueue {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self { sender, receiver, latency: None }
+    }
+
+    /// Record enqueue-to-dequeue latencies of this queue on `route`
+    pub fn with_metrics(mut self, metrics: Arc<RouterMetrics>, route: Route) -> Self {
+        self.latency = Some((metrics, route));
+        self
+    }
+
+    pub fn sender(&self) -> mpsc::UnboundedSender<QueuedEnvelope> {
+        self.sender.clone()
+    }
+}
+
+impl Default for MessageQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageSender for MessageQueue {
+    async fn send_message(&self, message: MessageEnvelope) -> Result<(), crate::Error> {
+        let enqueued = self.latency.as_ref()
+            .filter(|(metrics, _)| metrics.is_enabled())
+            .map(|_| std::time::Instant::now());
+        self.sender.send(QueuedEnvelope { envelope: message, enqueued })
+            .map_err(|_| crate::Error::Module("Failed to send message".to_string()))?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageReceiver for MessageQueue {
+    async fn receive_message(&mut self) -> Result<Option<MessageEnvelope>, crate::Error> {
+        match self.receiver.try_recv() {
+            Ok(QueuedEnvelope { envelope, enqueued }) => {
+                if let (Some(enqueued), Some((metrics, route))) = (enqueued, &self.latency) {
+                    metrics.record(*route, enqueued.elapsed(), &envelope.message);
+                }
+                Ok(Some(envelope))
+            }
+            Err(mpsc::error::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::error::TryRecvError::Disconnected) => Ok(None),
+        }
+    }
+}
+
+/// Negotiated protocol version per peer rank
+pub type PeerVersions = Arc<dashmap::DashMap<i32, u32>>;
+
+/// Negotiated codec per peer rank; bincode for peers without an entry
+pub type PeerCodecs = Arc<dashmap::DashMap<i32, CodecId>>;
+
+/// Bit per `CodecId` compiled into this build
+#[cfg(feature = "mpi")]
+fn codec_mask() -> u8 {
+    CodecId::available().into_iter().fold(0, |mask, codec| mask | 1 << codec as u8)
+}
+
+/// MPI-based distributed message passing
+#[cfg(feature = "mpi")]
+pub struct MpiMessageChannel {
+    universe: Arc<MpiUniverse>,
+    rank: i32,
+    size: i32,
+    peer_versions: PeerVersions,
+    peer_codecs: PeerCodecs,
+}
+
+#[cfg(feature = "mpi")]
+impl MpiMessageChannel {
+    pub fn new() -> Result<Self, crate::Error> {
+        Self::with_peer_versions(Arc::new(dashmap::DashMap::new()))
+    }
+
+    /// Create a channel and negotiate protocol versions with all ranks, using bincode
+    pub fn with_peer_versions(peer_versions: PeerVersions) -> Result<Self, crate::Error> {
+        Self::with_peers(peer_versions, Arc::new(dashmap::DashMap::new()), CodecId::Bincode)
+    }
+
+    /// Create a channel and negotiate protocol versions and codecs with all ranks
+    ///
+    /// Every rank contributes its version; the result for each peer is the
+    /// minimum common version. Each pair of ranks uses the codec preferred by
+    /// the lower rank if both have it compiled in, and bincode otherwise, so
+    /// both ends pick the same one.
+    pub fn with_peers(
+        peer_versions: PeerVersions,
+        peer_codecs: PeerCodecs,
+        preferred: CodecId,
+    ) -> Result<Self, crate::Error> {
+        let universe = MpiUniverse::shared()?;
+        let world = universe.world();
+
+        let mut versions = vec![0u32; world.size() as usize];
+        world.all_gather_into(&PROTOCOL_VERSION, &mut versions[..]);
+        // Preferred codec in the low byte, mask of available codecs above it
+        let offer = preferred as u32 | (codec_mask() as u32) << 8;
+        let mut offers = vec![0u32; world.size() as usize];
+        world.all_gather_into(&offer, &mut offers[..]);
+        let supports = |offer: u32, codec: CodecId| (offer >> 8) & (1 << codec as u8) != 0;
+        for (rank, &version) in versions.iter().enumerate() {
+            let rank = rank as i32;
+            if rank == world.rank() {
+                continue;
+            }
+            match negotiate_version(PROTOCOL_VERSION, version) {
+                Ok(common) => {
+                    peer_versions.insert(rank, common);
+                }
+                Err(e) => tracing::warn!("Rank {} is incompatible: {}", rank, e),
+            }
+
+            let lower = offers[rank.min(world.rank()) as usize];
+            let codec = CodecId::from_byte(lower as u8)
+                .ok()
+                .filter(|&codec| supports(offers[rank as usize], codec) && supports(offer, codec))
+                .unwrap_or(CodecId::Bincode);
+            peer_codecs.insert(rank, codec);
+        }
+
+        Ok(Self {
+            rank: world.rank(),
+            size: world.size(),
+            universe,
+            peer_versions,
+            peer_codecs,
+        })
+    }
+
+    fn codec_for_rank(&self, rank: i32) -> CodecId {
+        self.peer_codecs.get(&rank).map(|c| *c).unwrap_or_default()
+    }
+
+    /// Encode an envelope for a peer rank using its negotiated version and codec
+    fn encode_for_rank(&self, envelope: &MessageEnvelope, rank: i32) -> Result<Vec<u8>, crate::Error> {
+        let version = self.peer_versions.get(&rank).map(|v| *v).ok_or_else(|| {
+            crate::Error::Module(format!("No compatible protocol version negotiated with rank {}", rank))
+        })?;
+        self.codec_for_rank(rank).codec()?.encode_envelope(envelope, version)
+    }
+
+    pub fn rank(&self) -> i32 {
+        self.rank
+    }
+
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+}
+
+#[cfg(feature = "mpi")]
+#[async_trait::async_trait]
+impl MessageSender for MpiMessageChannel {
+    async fn send_message(&self, message: MessageEnvelope) -> Result<(), crate::Error> {
+        let world = self.universe.world();
+
+        // Send to recipient, encoded for each peer's protocol version
+        if message.message.recipient == 0 {
+            // Broadcast to all ranks
+            for rank in 0..self.size {
+                if rank != self.rank {
+                    match self.encode_for_rank(&message, rank) {
+                        Ok(data) => world.process_at_rank(rank).send_with_tag(&data[..], ROUTER_TAG.value()),
+                        Err(e) => tracing::warn!("Not broadcasting to rank {}: {}", rank, e),
+                    }
+                }
+            }
+        } else {
+            // Send to specific rank
+            let rank = message.message.recipient as i32;
+            let data = self.encode_for_rank(&message, rank)?;
+            world.process_at_rank(rank).send_with_tag(&data[..], ROUTER_TAG.value());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mpi")]
+#[async_trait::async_trait]
+impl MessageReceiver for MpiMessageChannel {
+    async fn receive_message(&mut self) -> Result<Option<MessageEnvelope>, crate::Error> {
+        let world = self.universe.world();
+
+        // Only router messages; other streams have their own tags
+        let Some((message, status)) = world.any_process().immediate_matched_probe_with_tag(ROUTER_TAG.value()) else {
+            return Ok(None); // No message available
+        };
+        let (buffer, _status) = message.matched_receive_vec::<u8>();
+        let decoded = self.codec_for_rank(status.source_rank())
+            .codec()
+            .and_then(|codec| codec.decode_envelope(&buffer));
+        match decoded {
+            Ok(envelope) => Ok(Some(envelope)),
+            Err(e) => {
+                // Drop the message rather than stopping the receive loop
+                tracing::warn!("Discarding undecodable message: {}", e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Message router for complex communication patterns
+pub struct MessageRouter {
+    local_queues: dashmap::DashMap<u32, Arc<MessageQueue>>,
+    #[cfg(feature = "mpi")]
+    mpi_channel: Option<MpiMessageChannel>,
+    handlers: dashmap::DashMap<MessageId, mpsc::UnboundedSender<MessageEnvelope>>,
+    peer_versions: PeerVersions,
+    /// Codecs in order of preference, offered to peers
+    codecs: Vec<CodecId>,
+    peer_codecs: PeerCodecs,
+    metrics: Arc<RouterMetrics>,
+    custom_types: CustomTypeRegistry,
+}
+
+impl MessageRouter {
+    pub fn new() -> Self {
+        Self {
+            local_queues: dashmap::DashMap::new(),
+            #[cfg(feature = "mpi")]
+            mpi_channel: None,
+            handlers: dashmap::DashMap::new(),
+            peer_versions: Arc::new(dashmap::DashMap::new()),
+            codecs: vec![CodecId::Bincode],
+            peer_codecs: Arc::new(dashmap::DashMap::new()),
+            metrics: Arc::new(RouterMetrics::new()),
+            custom_types: CustomTypeRegistry::new(),
+        }
+    }
+
+    /// Record per-route latencies, warning about messages slower than `slow_threshold`
+    pub fn with_latency_tracing(self, slow_threshold: Option<std::time::Duration>) -> Self {
+        self.metrics.set_enabled(true);
+        self.metrics.set_slow_threshold(slow_threshold);
+        self
+    }
+
+    pub fn set_latency_tracing(&self, enabled: bool) {
+        self.metrics.set_enabled(enabled);
+    }
+
+    /// Time messages sent over MPI until the recipient rank acks them
+    ///
+    /// Without acks remote sends are not timed. A rank acks what it receives
+    /// while its own acks and latency tracing are on, so turn both on for
+    /// every rank; peers older than protocol version 3 never ack.
+    pub fn with_remote_acks(self) -> Self {
+        self.metrics.set_acks_enabled(true);
+        self
+    }
+
+    pub fn set_remote_acks(&self, enabled: bool) {
+        self.metrics.set_acks_enabled(enabled);
+    }
+
+    /// Warn about messages slower than `threshold`; None turns the warning off
+    pub fn set_slow_message_threshold(&self, threshold: Option<std::time::Duration>) {
+        self.metrics.set_slow_threshold(threshold);
+    }
+
+    pub fn metrics(&self) -> &Arc<RouterMetrics> {
+        &self.metrics
+    }
+
+    /// p50, p95 and maximum latency per route since latency tracing was enabled
+    pub fn latency_report(&self) -> LatencyReport {
+        self.metrics.report()
+    }
+
+    /// Codecs to offer peers, most preferred first; unavailable ones are dropped
+    pub fn with_codecs(mut self, codecs: Vec<CodecId>) -> Self {
+        self.codecs = codecs.into_iter().filter(|c| c.is_available()).collect();
+        if self.codecs.is_empty() {
+            self.codecs.push(CodecId::Bincode);
+        }
+        self
+    }
+
+    pub fn codecs(&self) -> &[CodecId] {
+        &self.codecs
+    }
+
+    /// Enable MPI routing, negotiating protocol versions and codecs with all ranks
+    #[cfg(feature = "mpi")]
+    pub fn with_mpi(mut self) -> Result<Self, crate::Error> {
+        self.mpi_channel = Some(MpiMessageChannel::with_peers(
+            self.peer_versions.clone(),
+            self.peer_codecs.clone(),
+            self.codecs[0],
+        )?);
+        Ok(self)
+    }
+
+    /// Without the `mpi` feature this process is the only rank, so routing stays local
+    #[cfg(not(feature = "mpi"))]
+    pub fn with_mpi(self) -> Result<Self, crate::Error> {
+        Ok(self)
+    }
+
+    /// Record the codec for a peer that offered `offered` when connecting, returning it
+    pub fn negotiate_peer_codec(&self, rank: i32, offered: &[CodecId]) -> CodecId {
+        let codec = CodecId::negotiate(&self.codecs, offered);
+        self.peer_codecs.insert(rank, codec);
+        codec
+    }
+
+    /// Negotiated codec for a peer rank, bincode if none was negotiated
+    pub fn peer_codec(&self, rank: i32) -> CodecId {
+        self.peer_codecs.get(&rank).map(|c| *c).unwrap_or_default()
+    }
+
+    /// Record the version a peer announced when connecting, returning the common version
+    pub fn negotiate_peer(&self, rank: i32, peer_version: u32) -> Result<u32, crate::Error> {
+        let common = negotiate_version(PROTOCOL_VERSION, peer_version)?;
+        self.peer_versions.insert(rank, common);
+        Ok(common)
+    }
+
+    /// Negotiated protocol version for a peer rank
+    pub fn peer_version(&self, rank: i32) -> Option<u32> {
+        self.peer_versions.get(&rank).map(|v| *v)
+    }
+
+    /// Register `T` as a custom message type named `name`, see `CustomTypeRegistry::register`
+    ///
+    /// Use names qualified by the plugin or application, e.g. `"insitu.SteeringRequest"`.
+    pub fn register_custom_type<T>(&self, name: &str) -> Result<CustomTypeId, crate::Error>
+    where
+        T: Serialize + serde::de::DeserializeOwned + Send + 'static,
+    {
+        self.custom_types.register::<T>(name)
+    }
+
+    pub fn custom_types(&self) -> &CustomTypeRegistry {
+        &self.custom_types
+    }
+
+    /// Send a value of a registered custom type to a module
+    pub async fn send_custom<T: Serialize + 'static>(&self, recipient: u32, value: &T) -> Result<(), crate::Error> {
+        self.send_custom_from(0, recipient, value).await
+    }
+
+    /// Send a value of a registered custom type on behalf of module `sender`
+    pub async fn send_custom_from<T: Serialize + 'static>(&self, sender: u32, recipient: u32, value: &T) -> Result<(), crate::Error> {
+        let message = Message::new(sender, recipient, self.custom_types.encode(value)?);
+        self.route_message(MessageEnvelope { message, payload: MessagePayload::None }).await
+    }
+
+    /// Receive every custom message of registered type `T` delivered on this rank, decoded
+    pub fn subscribe_custom<T>(&self) -> Result<mpsc::UnboundedReceiver<CustomMessage<T>>, crate::Error>
+    where
+        T: serde::de::DeserializeOwned + Send + 'static,
+    {
+        self.custom_types.subscribe::<T>()
+    }
+
+    /// Receive custom messages of types not registered here, as raw bytes with their type name
+    pub fn subscribe_unknown_custom(&self) -> mpsc::UnboundedReceiver<RawCustomMessage> {
+        self.custom_types.subscribe_unknown()
+    }
+
+    pub fn register_module(&self, module_id: u32) -> Arc<MessageQueue> {
+        let queue = Arc::new(MessageQueue::new().with_metrics(self.metrics.clone(), Route::Local { module_id }));
+        self.local_queues.insert(module_id, queue.clone());
+        queue
+    }
+
+    /// Route a message to a waiter on this rank, returning how long it took to arrive
+    pub async fn ping(&self) -> Result<std::time::Duration, crate::Error> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let message = Message::new(PING_MODULE_ID, PING_MODULE_ID, MessageType::Ping);
+        let id = message.id;
+        self.handlers.insert(id, sender);
+
+        let started = std::time::Instant::now();
+        if let Err(e) = self.route_message(MessageEnvelope { message, payload: MessagePayload::None }).await {
+            self.handlers.remove(&id);
+            return Err(e);
+        }
+        receiver.recv().await
+            .ok_or_else(|| crate::Error::Module("Ping was dropped by the router".to_string()))?;
+        Ok(started.elapsed())
+    }
+
+    pub async fn route_message(&self, envelope: MessageEnvelope) -> Result<(), crate::Error> {
+        let recipient = envelope.message.recipient;
+
+        // Acks end the round trip of a message sent from this rank and go no further
+        if let MessageType::Ack { id } = &envelope.message.message_type {
+            self.metrics.acknowledge(id);
+            return Ok(());
+        }
+
+        // Messages awaited by id, e.g. pings, go straight to their waiter
+        if let Some((_, waiter)) = self.handlers.remove(&envelope.message.id) {
+            let _ = waiter.send(envelope);
+            return Ok(());
+        }
+
+        // Check if it's a local message; custom messages also go to their subscribers here
+        if let Some(queue) = self.local_queues.get(&recipient) {
+            self.custom_types.deliver(&envelope.message);
+            queue.send_message(envelope).await?;
+            return Ok(());
+        }
+
+        // Use MPI for distributed messages
+        #[cfg(feature = "mpi")]
+        if let Some(mpi) = &self.mpi_channel {
+            let id = envelope.message.id;
+            if self.metrics.acks_enabled() {
+                self.metrics.await_ack(Route::Remote { recipient }, &envelope.message);
+            }
+            if let Err(e) = mpi.send_message(envelope).await {
+                self.metrics.cancel_ack(&id);
+                return Err(e);
+            }
+            return Ok(());
+        }
+
+        // Custom messages need no module when someone subscribed to them
+        if self.custom_types.deliver(&envelope.message) {
+            return Ok(());
+        }
+
+        Err(crate::Error::Module(format!("No route to module {}", recipient)))
+    }
+
+    pub async fn process_messages(&self) -> Result<(), crate::Error> {
+        // Process MPI messages if available
+        #[cfg(feature = "mpi")]
+        if let Some(mpi) = &self.mpi_channel {
+            if let Some(envelope) = mpi.receive_message().await? {
+                if self.metrics.is_enabled() {
+                    self.metrics.record_incoming(&envelope.message);
+                }
+                if self.metrics.acks_enabled() {
+                    Self::send_ack(mpi, &envelope.message).await;
+                }
+                // Custom messages for no local module end with their subscribers on this rank
+                let local = self.local_queues.contains_key(&envelope.message.recipient);
+                if !local && self.custom_types.deliver(&envelope.message) {
+                    return Ok(());
+                }
+                self.route_message(envelope).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tell the rank of the sender of `message` that it arrived
+    #[cfg(feature = "mpi")]
+    async fn send_ack(mpi: &MpiMessageChannel, message: &Message) {
+        // Acks are not acked, and module 0 would address every rank
+        if matches!(message.message_type, MessageType::Ack { .. }) || message.sender == 0 {
+            return;
+        }
+        let ack = Message::new(message.recipient, message.sender, MessageType::Ack { id: message.id })
+            .with_priority(message.priority);
+        if let Err(e) = mpi.send_message(MessageEnvelope { message: ack, payload: MessagePayload::None }).await {
+            tracing::debug!("Not acking message {:?}: {}", message.id, e);
+        }
+    }
+}
+
+impl Default for MessageRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(message_type: MessageType) -> MessageEnvelope {
+        MessageEnvelope {
+            message: Message::new(1, 2, message_type),
+            payload: MessagePayload::None,
+        }
+    }
+
+    /// Bytes as a peer would send them, with any tag and body
+    fn wire(version: u32, tag: u32, body: Vec<u8>) -> Vec<u8> {
+        let template = envelope(MessageType::Quit).message;
+        bincode::serialize(&WireEnvelope {
+            version,
+            id: template.id,
+            sender: 7,
+            recipient: 0,
+            priority: Priority::High,
+            timestamp: template.timestamp,
+            tag,
+            body,
+            payload: MessagePayload::None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn envelopes_round_trip() {
+        let sent = envelope(MessageType::Execute { module_id: 3, timestep: 5 });
+        let received = MessageEnvelope::decode(&sent.encode_for(PROTOCOL_VERSION).unwrap()).unwrap();
+        assert_eq!(received.message.id, sent.message.id);
+        assert_eq!(received.message.version, PROTOCOL_VERSION);
+        assert!(matches!(received.message.message_type, MessageType::Execute { module_id: 3, timestep: 5 }));
+    }
+
+    #[test]
+    fn messages_of_an_older_peer_decode() {
+        // The oldest supported version encodes the same fields under the same tags
+        let body = bincode::serialize(&9u32).unwrap();
+        let received = MessageEnvelope::decode(&wire(MIN_PROTOCOL_VERSION, 10, body)).unwrap();
+        assert_eq!(received.message.version, MIN_PROTOCOL_VERSION);
+        assert_eq!(received.message.sender, 7);
+        assert_eq!(received.message.priority, Priority::High);
+        assert!(matches!(received.message.message_type, MessageType::ModuleReady { module_id: 9 }));
+    }
+
+    #[test]
+    fn unknown_variants_of_a_newer_peer_are_preserved() {
+        let tag = MessageType::max_tag(PROTOCOL_VERSION) + 5;
+        let body = vec![1, 2, 3, 4];
+        let received = MessageEnvelope::decode(&wire(PROTOCOL_VERSION + 1, tag, body.clone())).unwrap();
+
+        assert!(received.message.message_type.is_unknown());
+        match &received.message.message_type {
+            MessageType::Custom { type_id, data } => {
+                assert_eq!(type_id & !UNKNOWN_VARIANT_FLAG, tag);
+                assert_eq!(data, &body);
+            }
+            other => panic!("expected a preserved variant, got {:?}", other),
+        }
+
+        // Forwarding sends the original tag and body on
+        let forwarded = bincode::deserialize::<WireEnvelope>(&received.encode_for(PROTOCOL_VERSION).unwrap()).unwrap();
+        assert_eq!(forwarded.tag, tag);
+        assert_eq!(forwarded.body, body);
+    }
+
+    #[test]
+    fn known_custom_messages_are_not_unknown() {
+        assert!(!MessageType::Custom { type_id: 42, data: Vec::new() }.is_unknown());
+    }
+
+    #[test]
+    fn garbage_does_not_decode() {
+        assert!(MessageEnvelope::decode(&[0xff; 3]).is_err());
+        // A known tag with the body of another variant
+        let body = bincode::serialize(&()).unwrap();
+        assert!(MessageEnvelope::decode(&wire(PROTOCOL_VERSION, 12, body)).is_err());
+    }
+
+    #[test]
+    fn bodies_carry_fields_not_the_variant_index() {
+        let sent = envelope(MessageType::Execute { module_id: 3, timestep: 5 });
+        let wire = bincode::deserialize::<WireEnvelope>(&sent.encode_for(PROTOCOL_VERSION).unwrap()).unwrap();
+        assert_eq!(wire.tag, 1);
+        assert_eq!(wire.body, bincode::serialize(&(3u32, 5i32)).unwrap());
+    }
+
+    #[test]
+    fn newer_variants_are_mapped_for_older_peers() {
+        let bytes = envelope(MessageType::Ping).encode_for(1).unwrap();
+        let received = MessageEnvelope::decode(&bytes).unwrap();
+        assert_eq!(received.message.version, 1);
+        assert!(matches!(received.message.message_type, MessageType::ModuleReady { module_id: PING_MODULE_ID }));
+
+        let received = MessageEnvelope::decode(&envelope(MessageType::Ping).encode_for(PROTOCOL_VERSION).unwrap()).unwrap();
+        assert!(matches!(received.message.message_type, MessageType::Ping));
+    }
+
+    #[test]
+    fn variants_a_peer_cannot_understand_are_refused() {
+        let error = envelope(MessageType::Quit).encode_for(MIN_PROTOCOL_VERSION - 1).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("Cannot send Quit"), "{}", message);
+        assert!(message.contains(&format!("requires version {}", MIN_PROTOCOL_VERSION)), "{}", message);
+    }
+
+    #[test]
+    fn negotiation_picks_the_common_version() {
+        assert_eq!(negotiate_version(PROTOCOL_VERSION, PROTOCOL_VERSION + 3).unwrap(), PROTOCOL_VERSION);
+        assert_eq!(negotiate_version(PROTOCOL_VERSION + 3, MIN_PROTOCOL_VERSION).unwrap(), MIN_PROTOCOL_VERSION);
+        assert!(negotiate_version(PROTOCOL_VERSION, MIN_PROTOCOL_VERSION - 1).is_err());
+
+        let router = MessageRouter::new();
+        assert_eq!(router.peer_version(1), None);
+        assert_eq!(router.negotiate_peer(1, PROTOCOL_VERSION + 1).unwrap(), PROTOCOL_VERSION);
+        assert_eq!(router.peer_version(1), Some(PROTOCOL_VERSION));
+        assert!(router.negotiate_peer(2, MIN_PROTOCOL_VERSION - 1).is_err());
+        assert_eq!(router.peer_version(2), None);
+    }
+
+    #[test]
+    fn every_variant_exists_in_the_current_version() {
+        let variants = [
+            MessageType::Quit,
+            MessageType::CancelExecute { module_id: 1 },
+            MessageType::Custom { type_id: 1, data: Vec::new() },
+            MessageType::Ping,
+            MessageType::Ack { id: MessageId::new() },
+        ];
+        for variant in variants {
+            assert!(variant.tag() <= MessageType::max_tag(PROTOCOL_VERSION));
+            assert!(variant.min_version() <= PROTOCOL_VERSION);
+        }
+    }
+
+    #[test]
+    fn acks_need_a_peer_that_knows_them() {
+        let id = MessageId::new();
+        let received = MessageEnvelope::decode(&envelope(MessageType::Ack { id }).encode_for(PROTOCOL_VERSION).unwrap()).unwrap();
+        assert!(matches!(received.message.message_type, MessageType::Ack { id: acked } if acked == id));
+        assert!(envelope(MessageType::Ack { id }).encode_for(2).is_err());
+    }
+
+    #[tokio::test]
+    async fn acks_complete_the_round_trip_of_remote_sends() {
+        let router = MessageRouter::new().with_latency_tracing(None).with_remote_acks();
+        router.register_module(1);
+        let sent = Message::new(1, 5, MessageType::Quit);
+        router.metrics().await_ack(Route::Remote { recipient: 5 }, &sent);
+
+        let ack = Message::new(5, 1, MessageType::Ack { id: sent.id });
+        router.route_message(MessageEnvelope { message: ack, payload: MessagePayload::None }).await.unwrap();
+
+        let report = router.latency_report();
+        assert_eq!(report.routes.len(), 1);
+        assert_eq!(report.routes[0].route, Route::Remote { recipient: 5 });
+        assert_eq!(report.routes[0].count, 1);
+    }
+}
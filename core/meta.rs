@@ -1,205 +1,864 @@
-//! Metadata handling for objects and modules
-
-use serde::{Deserialize, Serialize};
-use nalgebra::Matrix4;
-
-/// Metadata structure for objects
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Meta {
-    pub block: i32,
-    pub num_blocks: i32,
-    pub timestep: i32,
-    pub num_timesteps: i32,
-    pub iteration: i32,
-    pub generation: i32,
-    pub creator: i32,
-    pub real_time: f64,
-    pub transform: Matrix4<f32>,
-}
-
-impl Default for Meta {
-    fn default() -> Self {
-        Self {
-            block: 0,
-            num_blocks: 1,
-            timestep: 0,
-            num_timesteps: 1,
-            iteration: 0,
-            generation: 0,
-            creator: 0,
-            real_time: 0.0,
-            transform: Matrix4::identity(),
-        }
-    }
-}
-
-impl Meta {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn with_block(mut self, block: i32, num_blocks: i32) -> Self {
-        self.block = block;
-        self.num_blocks = num_blocks;
-        self
-    }
-
-    pub fn with_timestep(mut self, timestep: i32, num_timesteps: i32) -> Self {
-        self.timestep = timestep;
-        self.num_timesteps = num_timesteps;
-        self
-    }
-
-    pub fn with_iteration(mut self, iteration: i32) -> Self {
-        self.iteration = iteration;
-        self
-    }
-
-    pub fn with_generation(mut self, generation: i32) -> Self {
-        self.generation = generation;
-        self
-    }
-
-    pub fn with_creator(mut self, creator: i32) -> Self {
-        self.creator = creator;
-        self
-    }
-
-    pub fn with_real_time(mut self, real_time: f64) -> Self {
-        self.real_time = real_time;
-        self
-    }
-
-    pub fn with_transform(mut self, transform: Matrix4<f32>) -> Self {
-        self.transform = transform;
-        self
-    }
-
-    /// Merge metadata from another source
-    pub fn merge(&mut self, other: &Meta) {
-        // Update fields if they represent more recent data
-        if other.generation > self.generation {
-            self.generation = other.generation;
-        }
-        if other.iteration > self.iteration {
-            self.iteration = other.iteration;
-        }
-        if other.real_time > self.real_time {
-            self.real_time = other.real_time;
-        }
-    }
-}
-
-/// Module information and status
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ModuleInfo {
-    pub id: u32,
-    pub name: String,
-    pub description: String,
-    pub category: String,
-    pub rank: i32,
-    pub size: i32,
-    pub status: ModuleStatus,
-}
-
-impl ModuleInfo {
-    pub fn new(id: u32, name: &str, rank: i32, size: i32) -> Self {
-        Self {
-            id,
-            name: name.to_string(),
-            description: String::new(),
-            category: "General".to_string(),
-            rank,
-            size,
-            status: ModuleStatus::Initializing,
-        }
-    }
-}
-
-/// Module execution status
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-pub enum ModuleStatus {
-    Initializing,
-    Ready,
-    Executing,
-    Completed,
-    Error,
-    Cancelled,
-}
-
-/// Computation context for modules
-#[derive(Debug, Clone)]
-pub struct ComputeContext {
-    pub module_id: u32,
-    pub timestep: i32,
-    pub iteration: i32,
-    pub rank: i32,
-    pub size: i32,
-}
-
-impl ComputeContext {
-    pub fn new(module_id: u32, rank: i32, size: i32) -> Self {
-        Self {
-            module_id,
-            timestep: 0,
-            iteration: 0,
-            rank,
-            size,
-        }
-    }
-
-    pub fn with_timestep(mut self, timestep: i32) -> Self {
-        self.timestep = timestep;
-        self
-    }
-
-    pub fn with_iteration(mut self, iteration: i32) -> Self {
-        self.iteration = iteration;
-        self
-    }
-}
-
-/// Execution statistics
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ExecutionStats {
-    pub module_id: u32,
-    pub start_time: std::time::SystemTime,
-    pub end_time: Option<std::time::SystemTime>,
-    pub objects_created: usize,
-    pub objects_processed: usize,
-    pub errors: Vec<String>,
-}
-
-impl ExecutionStats {
-    pub fn new(module_id: u32) -> Self {
-        Self {
-            module_id,
-            start_time: std::time::SystemTime::now(),
-            end_time: None,
-            objects_created: 0,
-            objects_processed: 0,
-            errors: Vec::new(),
-        }
-    }
-
-    pub fn complete(mut self) -> Self {
-        self.end_time = Some(std::time::SystemTime::now());
-        self
-    }
-
-    pub fn duration(&self) -> Option<std::time::Duration> {
-        self.end_time.and_then(|end| end.duration_since(self.start_time).ok())
-    }
-
-    pub fn add_error(&mut self, error: String) {
-        self.errors.push(error);
-    }
-
-    pub fn increment_created(&mut self) {
-        self.objects_created += 1;
-    }
-
-    pub fn increment_processed(&mut self) {
-        self.objects_processed += 1;
-    }
-}
+//! Metadata handling for objects and modules
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use nalgebra::Matrix4;
+
+use tokio::sync::watch;
+
+use crate::core::{CpuPool, Object, ObjectRegistry, ParameterSnapshot, RetentionManager, SeriesResolver, SharedArena};
+
+/// Metadata structure for objects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Meta {
+    pub block: i32,
+    pub num_blocks: i32,
+    pub timestep: i32,
+    pub num_timesteps: i32,
+    pub iteration: i32,
+    pub generation: i32,
+    pub creator: i32,
+    pub real_time: f64,
+    pub transform: Matrix4<f32>,
+}
+
+impl Default for Meta {
+    fn default() -> Self {
+        Self {
+            block: 0,
+            num_blocks: 1,
+            timestep: 0,
+            num_timesteps: 1,
+            iteration: 0,
+            generation: 0,
+            creator: 0,
+            real_time: 0.0,
+            transform: Matrix4::identity(),
+        }
+    }
+}
+
+impl Meta {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_block(mut self, block: i32, num_blocks: i32) -> Self {
+        self.block = block;
+        self.num_blocks = num_blocks;
+        self
+    }
+
+    pub fn with_timestep(mut self, timestep: i32, num_timesteps: i32) -> Self {
+        self.timestep = timestep;
+        self.num_timesteps = num_timesteps;
+        self
+    }
+
+    pub fn with_iteration(mut self, iteration: i32) -> Self {
+        self.iteration = iteration;
+        self
+    }
+
+    pub fn with_generation(mut self, generation: i32) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    pub fn with_creator(mut self, creator: i32) -> Self {
+        self.creator = creator;
+        self
+    }
+
+    pub fn with_real_time(mut self, real_time: f64) -> Self {
+        self.real_time = real_time;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Matrix4<f32>) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Merge metadata from another source
+    pub fn merge(&mut self, other: &Meta) {
+        // Update fields if they represent more recent data
+        if other.generation > self.generation {
+            self.generation = other.generation;
+        }
+        if other.iteration > self.iteration {
+            self.iteration = other.iteration;
+        }
+        if other.real_time > self.real_time {
+            self.real_time = other.real_time;
+        }
+    }
+}
+
+/// Module information and status
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleInfo {
+    pub id: u32,
+    pub name: String,
+    pub description: String,
+    pub category: String,
+    pub rank: i32,
+    pub size: i32,
+    pub status: ModuleStatus,
+}
+
+impl ModuleInfo {
+    pub fn new(id: u32, name: &str, rank: i32, size: i32) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            description: String::new(),
+            category: "General".to_string(),
+            rank,
+            size,
+            status: ModuleStatus::Initializing,
+        }
+    }
+}
+
+/// Module execution status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModuleStatus {
+    Initializing,
+    Ready,
+    Executing,
+    Completed,
+    Error,
+    Cancelled,
+}
+
+/// Computation context for modules
+#[derive(Debug, Clone)]
+pub struct ComputeContext {
+    pub module_id: u32,
+    pub timestep: i32,
+    pub iteration: i32,
+    pub rank: i32,
+    pub size: i32,
+    /// Directory relative file paths resolve against, usually the workflow file's
+    pub base_dir: Option<PathBuf>,
+    cancellation: CancellationToken,
+    cpu_pool: Option<CpuPool>,
+    parameters: ParameterSnapshot,
+    live_parameters: Option<watch::Receiver<ParameterSnapshot>>,
+    objects: Option<Arc<ObjectRegistry>>,
+    retention: Option<Arc<RetentionManager>>,
+    arena: Option<Arc<SharedArena>>,
+}
+
+impl ComputeContext {
+    pub fn new(module_id: u32, rank: i32, size: i32) -> Self {
+        Self {
+            module_id,
+            timestep: 0,
+            iteration: 0,
+            rank,
+            size,
+            base_dir: None,
+            cancellation: CancellationToken::new(),
+            cpu_pool: None,
+            parameters: ParameterSnapshot::default(),
+            live_parameters: None,
+            objects: None,
+            retention: None,
+            arena: None,
+        }
+    }
+
+    /// Token whose cancellation makes `checkpoint` fail
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Pool for `run_cpu`; the process-wide pool is used otherwise
+    pub fn with_cpu_pool(mut self, pool: CpuPool) -> Self {
+        self.cpu_pool = Some(pool);
+        self
+    }
+
+    /// Parameter values compute reads, fixed for the whole execution
+    pub fn with_parameters(mut self, parameters: ParameterSnapshot) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// Channel publishing changes the module accepts while computing
+    pub fn with_live_parameters(mut self, live: watch::Receiver<ParameterSnapshot>) -> Self {
+        self.live_parameters = Some(live);
+        self
+    }
+
+    /// Parameters as they were when the execution started
+    pub fn parameters(&self) -> &ParameterSnapshot {
+        &self.parameters
+    }
+
+    /// Latest parameters including changes accepted by `Module::parameter_changed`
+    ///
+    /// For long-running modules that poll, e.g. between iterations; equal
+    /// to `parameters` unless a change was accepted mid-execution.
+    pub fn live_parameters(&self) -> ParameterSnapshot {
+        match &self.live_parameters {
+            Some(live) => live.borrow().clone(),
+            None => self.parameters.clone(),
+        }
+    }
+
+    /// Registry placeholders are resolved through
+    pub fn with_objects(mut self, objects: Arc<ObjectRegistry>) -> Self {
+        self.objects = Some(objects);
+        self
+    }
+
+    pub fn objects(&self) -> Option<&Arc<ObjectRegistry>> {
+        self.objects.as_ref()
+    }
+
+    /// Load an input if it is a placeholder; complete objects are returned as they are
+    ///
+    /// Lazy modules call this per timestep instead of having all inputs
+    /// loaded before `compute`.
+    pub async fn resolve(&self, object: &Arc<dyn Object>) -> Result<Arc<dyn Object>, crate::Error> {
+        if object.is_complete() {
+            return Ok(object.clone());
+        }
+        let objects = self.objects.as_ref()
+            .ok_or_else(|| crate::Error::Config(format!("No object registry to resolve placeholder {}", object.id())))?;
+        objects.resolve_object(object).await
+    }
+
+    /// Resolve a series in order, loading the following placeholders while each is processed
+    ///
+    /// Sort the objects first, e.g. by timestep; see `SeriesResolver`.
+    pub fn resolve_series(&self, objects: Vec<Arc<dyn Object>>) -> SeriesResolver {
+        SeriesResolver::new(self.objects.clone(), objects)
+    }
+
+    /// Token to hand to cancellable IO such as `util::io::read_binary_cancellable`
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Fail if the execution was cancelled; for use inside `run_cpu` closures
+    pub fn check_cancelled(&self) -> Result<(), crate::Error> {
+        if self.is_cancelled() {
+            return Err(crate::Error::Cancelled(format!("execution of module {}", self.module_id)));
+        }
+        Ok(())
+    }
+
+    /// Cooperative yield point for long async loops
+    ///
+    /// Lets other tasks on the runtime run and fails once the execution is
+    /// cancelled, so modules should call it every few milliseconds of work.
+    pub async fn checkpoint(&self) -> Result<(), crate::Error> {
+        self.check_cancelled()?;
+        tokio::task::yield_now().await;
+        self.check_cancelled()
+    }
+
+    /// Run CPU-heavy work off the async runtime
+    ///
+    /// Returns early with an error on cancellation; the closure itself only
+    /// stops early if it polls `check_cancelled` on a clone of the context.
+    pub async fn run_cpu<F, R>(&self, f: F) -> Result<R, crate::Error>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.check_cancelled()?;
+        let pool = self.cpu_pool.clone().unwrap_or_else(CpuPool::global);
+        tokio::select! {
+            result = pool.run(f) => result,
+            _ = self.cancellation.cancelled() => Err(crate::Error::Cancelled(format!(
+                "execution of module {}",
+                self.module_id
+            ))),
+        }
+    }
+
+    pub fn with_base_dir(mut self, base_dir: Option<PathBuf>) -> Self {
+        self.base_dir = base_dir;
+        self
+    }
+
+    /// Resolve a file path parameter: expands `${VAR}` and `${WORKFLOW_DIR}`
+    /// and makes relative paths relative to the base directory
+    pub fn resolve_path(&self, path: &str) -> Result<PathBuf, crate::Error> {
+        crate::core::resolve_path(path, self.base_dir.as_deref())
+    }
+
+    /// Manager applying the workflow's retention policies to written files
+    pub fn with_retention(mut self, retention: Arc<RetentionManager>) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Record a file the module has written, applying its directory's retention policy
+    ///
+    /// Writers call this after each file, with the resolved path; without
+    /// a retention manager it does nothing.
+    pub async fn record_output(&self, path: &Path) -> Result<(), crate::Error> {
+        if let Some(retention) = &self.retention {
+            retention.record_write(path).await?;
+        }
+        Ok(())
+    }
+
+    /// Arena module outputs are stored in, charging its owner's quota
+    pub fn with_arena(mut self, arena: Arc<SharedArena>) -> Self {
+        self.arena = Some(arena);
+        self
+    }
+
+    pub fn arena(&self) -> Option<&Arc<SharedArena>> {
+        self.arena.as_ref()
+    }
+
+    pub fn with_timestep(mut self, timestep: i32) -> Self {
+        self.timestep = timestep;
+        self
+    }
+
+    pub fn with_iteration(mut self, iteration: i32) -> Self {
+        self.iteration = iteration;
+        self
+    }
+}
+
+/// Phase of a module execution that time is charged to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionPhase {
+    SetInput,
+    Compute,
+}
+
+/// Wall time spent per execution phase
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PhaseTimes {
+    pub set_input: std::time::Duration,
+    pub compute: std::time::Duration,
+}
+
+/// Errors kept per module by default
+pub const DEFAULT_ERROR_CAPACITY: usize = 64;
+
+/// Version of the serialized `ErrorLog`; version 1 was a plain list of messages
+pub const ERROR_LOG_VERSION: u32 = 2;
+
+/// One failed execution of a module
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsError {
+    pub timestep: i32,
+    pub time: std::time::SystemTime,
+    /// Stable error code as returned by `Error::code`
+    pub code: String,
+    pub message: String,
+}
+
+impl StatsError {
+    pub fn new(timestep: i32, error: &crate::Error) -> Self {
+        Self {
+            timestep,
+            time: std::time::SystemTime::now(),
+            code: error.code().to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Most recent errors of a module, oldest first, with a count of all errors ever recorded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorLog {
+    capacity: usize,
+    total: u64,
+    entries: VecDeque<StatsError>,
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_ERROR_CAPACITY)
+    }
+}
+
+impl ErrorLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            total: 0,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Record an error, dropping the oldest entry once the log is full
+    pub fn push(&mut self, error: StatsError) {
+        self.total += 1;
+        self.entries.push_back(error);
+        self.trim();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change how many entries are kept; shrinking drops the oldest ones
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Errors recorded in total, including dropped ones
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Errors recorded but no longer kept
+    pub fn dropped(&self) -> u64 {
+        self.total - self.entries.len() as u64
+    }
+
+    /// Number of kept entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Kept entries, oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &StatsError> {
+        self.entries.iter()
+    }
+
+    /// Up to `n` kept entries, newest first
+    pub fn recent(&self, n: usize) -> Vec<&StatsError> {
+        self.entries.iter().rev().take(n).collect()
+    }
+
+    /// Kept entries recorded at or after `time`, oldest first
+    pub fn since(&self, time: std::time::SystemTime) -> Vec<&StatsError> {
+        self.entries.iter().filter(|e| e.time >= time).collect()
+    }
+
+    /// Fold in another log, keeping the newest entries of both
+    pub fn merge(&mut self, other: &ErrorLog) {
+        self.total += other.total;
+        self.capacity = self.capacity.max(other.capacity);
+        let mut entries: Vec<StatsError> = self.entries.drain(..).chain(other.entries.iter().cloned()).collect();
+        entries.sort_by_key(|e| e.time);
+        self.entries = entries.into();
+        self.trim();
+    }
+}
+
+/// Current serialized form of `ErrorLog`
+#[derive(Serialize, Deserialize)]
+struct ErrorLogV2 {
+    version: u32,
+    capacity: usize,
+    total: u64,
+    entries: VecDeque<StatsError>,
+}
+
+/// Forms accepted from self-describing formats such as JSON
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrorLogRepr {
+    V2(ErrorLogV2),
+    V1(Vec<String>),
+}
+
+impl From<ErrorLogV2> for ErrorLog {
+    fn from(log: ErrorLogV2) -> Self {
+        let mut log = ErrorLog { capacity: log.capacity, total: log.total, entries: log.entries };
+        log.trim();
+        log
+    }
+}
+
+impl Serialize for ErrorLog {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ErrorLogV2 {
+            version: ERROR_LOG_VERSION,
+            capacity: self.capacity,
+            total: self.total,
+            entries: self.entries.clone(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorLog {
+    /// Self-describing formats may also hold the version 1 list of messages,
+    /// which is read as entries without timestep or time. Binary formats
+    /// cannot tell the versions apart and must hold version 2.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return ErrorLogV2::deserialize(deserializer).map(ErrorLog::from);
+        }
+        match ErrorLogRepr::deserialize(deserializer)? {
+            ErrorLogRepr::V2(log) if log.version == ERROR_LOG_VERSION => Ok(log.into()),
+            ErrorLogRepr::V2(log) => Err(serde::de::Error::custom(format!(
+                "unsupported error log version {}", log.version
+            ))),
+            ErrorLogRepr::V1(messages) => {
+                let mut log = ErrorLog::new(DEFAULT_ERROR_CAPACITY.max(messages.len()));
+                for message in messages {
+                    log.push(StatsError {
+                        timestep: 0,
+                        time: std::time::UNIX_EPOCH,
+                        code: "unknown".to_string(),
+                        message,
+                    });
+                }
+                Ok(log)
+            }
+        }
+    }
+}
+
+/// Execution statistics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionStats {
+    pub module_id: u32,
+    pub start_time: std::time::SystemTime,
+    pub end_time: Option<std::time::SystemTime>,
+    pub objects_created: usize,
+    pub objects_processed: usize,
+    /// Most recent errors; older ones only count towards `errors.total()`
+    pub errors: ErrorLog,
+    /// Number of executions
+    pub invocations: u64,
+    /// Wall time of the most recent execution
+    pub last_wall_time: std::time::Duration,
+    /// Wall time summed over all executions
+    pub total_wall_time: std::time::Duration,
+    pub phases: PhaseTimes,
+    /// Thread CPU time, where the platform can measure it
+    pub cpu_time: Option<std::time::Duration>,
+}
+
+impl ExecutionStats {
+    pub fn new(module_id: u32) -> Self {
+        Self {
+            module_id,
+            start_time: std::time::SystemTime::now(),
+            end_time: None,
+            objects_created: 0,
+            objects_processed: 0,
+            errors: ErrorLog::default(),
+            invocations: 0,
+            last_wall_time: std::time::Duration::ZERO,
+            total_wall_time: std::time::Duration::ZERO,
+            phases: PhaseTimes::default(),
+            cpu_time: None,
+        }
+    }
+
+    pub fn complete(mut self) -> Self {
+        self.end_time = Some(std::time::SystemTime::now());
+        self
+    }
+
+    /// Mark the statistics as complete in place
+    pub fn mark_complete(&mut self) {
+        self.end_time = Some(std::time::SystemTime::now());
+    }
+
+    pub fn duration(&self) -> Option<std::time::Duration> {
+        self.end_time.and_then(|end| end.duration_since(self.start_time).ok())
+    }
+
+    pub fn with_error_capacity(mut self, capacity: usize) -> Self {
+        self.errors.set_capacity(capacity);
+        self
+    }
+
+    /// Record a failed execution at `timestep`
+    pub fn record_error(&mut self, timestep: i32, error: &crate::Error) {
+        self.errors.push(StatsError::new(timestep, error));
+    }
+
+    /// Up to `n` of the kept errors, newest first
+    pub fn recent_errors(&self, n: usize) -> Vec<&StatsError> {
+        self.errors.recent(n)
+    }
+
+    /// Kept errors recorded at or after `time`, oldest first
+    pub fn errors_since(&self, time: std::time::SystemTime) -> Vec<&StatsError> {
+        self.errors.since(time)
+    }
+
+    /// Errors recorded in total, including those no longer kept
+    pub fn error_count(&self) -> u64 {
+        self.errors.total()
+    }
+
+    pub fn increment_created(&mut self) {
+        self.objects_created += 1;
+    }
+
+    pub fn increment_processed(&mut self) {
+        self.objects_processed += 1;
+    }
+
+    /// Charge time to a phase
+    pub fn record_phase(&mut self, phase: ExecutionPhase, elapsed: std::time::Duration) {
+        match phase {
+            ExecutionPhase::SetInput => self.phases.set_input += elapsed,
+            ExecutionPhase::Compute => self.phases.compute += elapsed,
+        }
+    }
+
+    /// Record one completed execution
+    pub fn record_invocation(&mut self, wall_time: std::time::Duration, cpu_time: Option<std::time::Duration>) {
+        self.invocations += 1;
+        self.last_wall_time = wall_time;
+        self.total_wall_time += wall_time;
+        if let Some(cpu) = cpu_time {
+            *self.cpu_time.get_or_insert(std::time::Duration::ZERO) += cpu;
+        }
+    }
+
+    /// Fold in the statistics of the same module from another rank or run
+    pub fn merge(&mut self, other: &ExecutionStats) {
+        self.start_time = self.start_time.min(other.start_time);
+        self.end_time = match (self.end_time, other.end_time) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        self.objects_created += other.objects_created;
+        self.objects_processed += other.objects_processed;
+        self.errors.merge(&other.errors);
+        self.invocations += other.invocations;
+        self.last_wall_time = self.last_wall_time.max(other.last_wall_time);
+        self.total_wall_time += other.total_wall_time;
+        self.phases.set_input += other.phases.set_input;
+        self.phases.compute += other.phases.compute;
+        self.cpu_time = match (self.cpu_time, other.cpu_time) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// CPU time consumed by the calling thread
+///
+/// Only meaningful while a computation stays on one thread; use `cpu_timed`
+/// for futures, which may migrate between runtime workers at every `.await`.
+pub fn thread_cpu_time() -> Option<std::time::Duration> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+        // SAFETY: getrusage only writes into the provided struct
+        let usage = unsafe {
+            if libc::getrusage(libc::RUSAGE_THREAD, usage.as_mut_ptr()) != 0 {
+                return None;
+            }
+            usage.assume_init()
+        };
+        let to_duration = |t: libc::timeval| {
+            std::time::Duration::new(t.tv_sec as u64, (t.tv_usec as u32) * 1000)
+        };
+        Some(to_duration(usage.ru_utime) + to_duration(usage.ru_stime))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Await a future, returning its output and the CPU time its polls consumed
+///
+/// Each poll is measured on the thread that runs it, so the total stays
+/// correct when the task moves between workers.
+pub async fn cpu_timed<F: std::future::Future>(future: F) -> (F::Output, Option<std::time::Duration>) {
+    let mut future = std::pin::pin!(future);
+    let mut total = Some(std::time::Duration::ZERO);
+    let output = std::future::poll_fn(|cx| {
+        let start = thread_cpu_time();
+        let poll = future.as_mut().poll(cx);
+        total = total.zip(start.zip(thread_cpu_time()))
+            .map(|(total, (start, end))| total + end.saturating_sub(start));
+        poll
+    })
+    .await;
+    (output, total)
+}
+
+/// Per-module timing table sorted by total wall time, longest first
+pub fn summary_table(stats: &[ExecutionStats]) -> String {
+    let mut rows: Vec<&ExecutionStats> = stats.iter().collect();
+    rows.sort_by_key(|s| std::cmp::Reverse(s.total_wall_time));
+
+    let ms = |d: std::time::Duration| format!("{:.1}", d.as_secs_f64() * 1000.0);
+
+    let mut table = format!(
+        "{:>8} {:>6} {:>12} {:>12} {:>12} {:>12} {:>12} {:>6}\n",
+        "module", "runs", "total ms", "last ms", "input ms", "compute ms", "cpu ms", "errors"
+    );
+    for s in rows {
+        table.push_str(&format!(
+            "{:>8} {:>6} {:>12} {:>12} {:>12} {:>12} {:>12} {:>6}\n",
+            s.module_id,
+            s.invocations,
+            ms(s.total_wall_time),
+            ms(s.last_wall_time),
+            ms(s.phases.set_input),
+            ms(s.phases.compute),
+            s.cpu_time.map(ms).unwrap_or_else(|| "-".to_string()),
+            s.errors.total(),
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn error(seconds: u64, message: &str) -> StatsError {
+        StatsError {
+            timestep: seconds as i32,
+            time: UNIX_EPOCH + Duration::from_secs(seconds),
+            code: "compute".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn log(capacity: usize, seconds: impl IntoIterator<Item = u64>) -> ErrorLog {
+        let mut log = ErrorLog::new(capacity);
+        for s in seconds {
+            log.push(error(s, &format!("error {}", s)));
+        }
+        log
+    }
+
+    fn times(log: &ErrorLog) -> Vec<i32> {
+        log.iter().map(|e| e.timestep).collect()
+    }
+
+    #[test]
+    fn a_full_log_keeps_the_newest_errors_and_counts_the_rest() {
+        let log = log(3, 1..=5);
+        assert_eq!(times(&log), [3, 4, 5]);
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.total(), 5);
+        assert_eq!(log.dropped(), 2);
+
+        let none = self::log(0, 1..=2);
+        assert!(none.is_empty());
+        assert_eq!(none.total(), 2);
+        assert_eq!(none.dropped(), 2);
+    }
+
+    #[test]
+    fn shrinking_drops_the_oldest_entries() {
+        let mut log = log(4, 1..=4);
+        log.set_capacity(2);
+        assert_eq!(times(&log), [3, 4]);
+        assert_eq!(log.dropped(), 2);
+
+        log.set_capacity(8);
+        log.push(error(5, "error 5"));
+        assert_eq!(times(&log), [3, 4, 5]);
+        assert_eq!(log.capacity(), 8);
+    }
+
+    #[test]
+    fn recent_and_since_select_kept_entries() {
+        let log = log(3, 1..=5);
+        let recent: Vec<i32> = log.recent(2).iter().map(|e| e.timestep).collect();
+        assert_eq!(recent, [5, 4]);
+        assert_eq!(log.recent(10).len(), 3);
+
+        let since: Vec<i32> = log.since(UNIX_EPOCH + Duration::from_secs(4)).iter().map(|e| e.timestep).collect();
+        assert_eq!(since, [4, 5]);
+        // Dropped entries are not returned, however old the cutoff
+        assert_eq!(log.since(UNIX_EPOCH).len(), 3);
+    }
+
+    #[test]
+    fn merging_keeps_the_newest_entries_of_both_logs() {
+        let mut a = log(3, [1, 4, 6]);
+        let b = log(4, [2, 3, 5, 7]);
+        a.merge(&b);
+        assert_eq!(a.capacity(), 4);
+        assert_eq!(times(&a), [4, 5, 6, 7]);
+        assert_eq!(a.total(), 7);
+        assert_eq!(a.dropped(), 3);
+    }
+
+    #[test]
+    fn stats_record_errors_within_their_capacity() {
+        let mut stats = ExecutionStats::new(1).with_error_capacity(2);
+        for timestep in 0..3 {
+            stats.record_error(timestep, &crate::Error::Compute(format!("step {}", timestep)));
+        }
+        assert_eq!(stats.error_count(), 3);
+        let recent = stats.recent_errors(5);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].timestep, 2);
+        assert_eq!(recent[0].code, crate::Error::Compute(String::new()).code());
+    }
+
+    #[test]
+    fn logs_round_trip_through_json_and_binary() {
+        let log = log(3, 1..=5);
+
+        let json = serde_json::to_string(&log).unwrap();
+        assert!(json.contains(&format!("\"version\":{}", ERROR_LOG_VERSION)));
+        assert_eq!(serde_json::from_str::<ErrorLog>(&json).unwrap(), log);
+
+        let bytes = bincode::serialize(&log).unwrap();
+        assert_eq!(bincode::deserialize::<ErrorLog>(&bytes).unwrap(), log);
+    }
+
+    #[test]
+    fn version_1_logs_are_read_as_messages() {
+        let log: ErrorLog = serde_json::from_str(r#"["first", "second"]"#).unwrap();
+        let messages: Vec<&str> = log.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["first", "second"]);
+        assert_eq!(log.total(), 2);
+        assert_eq!(log.capacity(), DEFAULT_ERROR_CAPACITY);
+
+        let many: Vec<String> = (0..DEFAULT_ERROR_CAPACITY + 10).map(|i| i.to_string()).collect();
+        let log: ErrorLog = serde_json::from_str(&serde_json::to_string(&many).unwrap()).unwrap();
+        assert_eq!(log.len(), many.len());
+        assert_eq!(log.dropped(), 0);
+    }
+
+    #[test]
+    fn deserialized_logs_stay_within_their_capacity() {
+        let mut json = serde_json::to_value(log(5, 1..=5)).unwrap();
+        json["capacity"] = 2.into();
+        let log: ErrorLog = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(times(&log), [4, 5]);
+        assert_eq!(log.dropped(), 3);
+
+        json["version"] = (ERROR_LOG_VERSION + 1).into();
+        let error = serde_json::from_value::<ErrorLog>(json).unwrap_err();
+        assert!(error.to_string().contains("unsupported error log version"));
+    }
+}
@@ -3,11 +3,61 @@
 pub mod object;
 pub mod shm;
 pub mod message;
+pub mod custom;
+pub mod codec;
+pub mod latency;
 pub mod meta;
 pub mod parameter;
+pub mod transform;
+pub mod geometry;
+pub mod paths;
+pub mod units;
+pub mod runtime;
+pub mod snapshot;
+pub mod amr;
+pub mod view;
+pub mod lazy;
+pub mod prefetch;
+pub mod health;
+pub mod lossy;
+pub mod retention;
+pub mod unstructured;
+pub mod structured;
+pub mod polygons;
+pub mod precision;
+pub mod raster;
+pub mod integer;
+pub mod mapping;
+pub mod validation;
+#[cfg(feature = "mmap")]
+pub mod raw_volume;
 
 pub use object::*;
 pub use shm::*;
 pub use message::*;
+pub use custom::*;
+pub use codec::*;
+pub use latency::*;
 pub use meta::*;
 pub use parameter::*;
+pub use geometry::*;
+pub use paths::*;
+pub use units::*;
+pub use runtime::*;
+pub use snapshot::*;
+pub use amr::*;
+pub use view::*;
+pub use lazy::*;
+pub use prefetch::*;
+pub use health::*;
+pub use lossy::*;
+pub use retention::*;
+pub use unstructured::*;
+pub use polygons::*;
+pub use precision::*;
+pub use raster::*;
+pub use integer::*;
+pub use mapping::*;
+pub use validation::*;
+#[cfg(feature = "mmap")]
+pub use raw_volume::*;
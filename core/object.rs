@@ -2,11 +2,18 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use nalgebra::Vector3;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::core::{
+    CellType, CurveView, GridMapping, ImageView, Int32FieldView, Int64FieldView, LayeredImageView, Mapping, PolygonsView,
+    ScalarFieldF64View, ScalarFieldView, TableView, TrianglesView, UniformGridView, UnstructuredGridView,
+    VectorFieldF64View, VectorFieldView,
+};
+
 /// Unique identifier for objects
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ObjectId(Uuid);
 
 impl ObjectId {
@@ -15,6 +22,12 @@ impl ObjectId {
     }
 }
 
+impl std::fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl Default for ObjectId {
     fn default() -> Self {
         Self::new()
@@ -40,9 +53,16 @@ pub enum ObjectType {
     RectilinearGrid = 26,
     StructuredGrid = 27,
     Quads = 28,
+    AmrHierarchy = 29,
+    Image = 30,
+    LayeredImage = 31,
 
     // Data types
     Vec = 100, // Base for all vector types
+
+    // Tabular data
+    Table = 200,
+    Curve = 201,
 }
 
 impl ObjectType {
@@ -60,7 +80,12 @@ impl ObjectType {
             ObjectType::RectilinearGrid => "RectilinearGrid",
             ObjectType::StructuredGrid => "StructuredGrid",
             ObjectType::Quads => "Quads",
+            ObjectType::AmrHierarchy => "AmrHierarchy",
+            ObjectType::Image => "Image",
+            ObjectType::LayeredImage => "LayeredImage",
             ObjectType::Vec => "Vec",
+            ObjectType::Table => "Table",
+            ObjectType::Curve => "Curve",
         }
     }
 }
@@ -95,6 +120,112 @@ impl Default for ObjectMeta {
     }
 }
 
+/// Well-known attribute keys
+pub mod attribute {
+    /// Centering of a data field, either `vertex` or `element`
+    pub const MAPPING: &str = "_mapping";
+
+    pub const MAPPING_VERTEX: &str = "vertex";
+    pub const MAPPING_ELEMENT: &str = "element";
+
+    /// Marks a vector field as surface normals (transformed by the inverse transpose)
+    pub const NORMALS: &str = "_normals";
+
+    /// Id of the object a copy-on-write derivative was created from
+    pub const DERIVED_FROM: &str = "_derived_from";
+
+    /// Value range of a scalar field as "min max"
+    pub const RANGE: &str = "_range";
+
+    /// Name of the quantity or species a field holds
+    pub const SPECIES: &str = "_species";
+
+    /// Physical units of a field
+    pub const UNITS: &str = "_units";
+
+    /// Colormap suggested by the data source
+    pub const COLORMAP: &str = "_colormap";
+
+    /// Color mode suggested by the data source: linear, equalized or categorical
+    pub const COLOR_MODE: &str = "_color_mode";
+
+    /// Serialized `mpi::BlockAssignment` used by the reader that produced the object
+    pub const BLOCK_ASSIGNMENT: &str = "_block_assignment";
+
+    /// Absolute error the producer allows when the field is stored, see `core::lossy`
+    pub const LOSSY_ERROR_BOUND: &str = "_lossy_error_bound";
+
+    /// Error bound the field was lossily compressed with; kept after decompression
+    pub const LOSSY: &str = "_lossy";
+}
+
+/// Which attributes an output copies from an input object
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AttributePolicy {
+    /// Copy everything, subject to the invalidation rules
+    #[default]
+    Inherit,
+    /// Copy everything verbatim, including attributes the rules would invalidate
+    All,
+    /// Copy only the listed keys
+    Only(Vec<String>),
+    /// Copy everything except the listed keys
+    Except(Vec<String>),
+    /// Copy nothing; the module manages attributes itself
+    None,
+}
+
+impl AttributePolicy {
+    fn allows(&self, key: &str) -> bool {
+        match self {
+            AttributePolicy::Inherit | AttributePolicy::All => true,
+            AttributePolicy::Only(keys) => keys.iter().any(|k| k == key),
+            AttributePolicy::Except(keys) => !keys.iter().any(|k| k == key),
+            AttributePolicy::None => false,
+        }
+    }
+}
+
+/// What happens to an inherited attribute that a filter may have invalidated
+#[derive(Debug, Clone, Copy)]
+pub enum Invalidation {
+    /// Drop the attribute
+    Remove,
+    /// Recompute the attribute from the output payload, dropping it if that fails
+    Recompute(fn(&ObjectPayload) -> Option<String>),
+}
+
+/// Registry of well-known attributes that do not survive filtering unchanged
+pub fn invalidation_rules() -> &'static [(&'static str, Invalidation)] {
+    &[
+        (attribute::RANGE, Invalidation::Recompute(scalar_range)),
+        (attribute::DERIVED_FROM, Invalidation::Remove),
+        // Opting in is up to each producer, not inherited by what is computed from a field
+        (attribute::LOSSY_ERROR_BOUND, Invalidation::Remove),
+    ]
+}
+
+fn scalar_range(payload: &ObjectPayload) -> Option<String> {
+    // Double-precision fields keep their full precision in the attribute
+    let (min, max) = match payload {
+        ObjectPayload::VecScalar { data } => data.iter()
+            .filter(|v| v.is_finite())
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v as f64), hi.max(v as f64))),
+        ObjectPayload::VecScalarF64 { data } => data.iter()
+            .filter(|v| v.is_finite())
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v))),
+        // Integer fields give their exact bounds, however large
+        ObjectPayload::VecInt32 { data } => {
+            return data.iter().min().zip(data.iter().max()).map(|(lo, hi)| format!("{} {}", lo, hi));
+        }
+        ObjectPayload::VecInt64 { data } => {
+            return data.iter().min().zip(data.iter().max()).map(|(lo, hi)| format!("{} {}", lo, hi));
+        }
+        _ => return None,
+    };
+    (min <= max).then(|| format!("{} {}", min, max))
+}
+
 /// Base trait for all Vistle objects
 #[async_trait::async_trait]
 pub trait Object: Send + Sync {
@@ -127,16 +258,172 @@ pub trait Object: Send + Sync {
 
     /// Get all attributes
     fn attributes(&self) -> &HashMap<String, String>;
+
+    /// Get the object's payload, if it carries one
+    fn payload(&self) -> Option<&ObjectPayload> {
+        None
+    }
+
+    /// Whether this object stands for "no data", e.g. a threshold nothing passed
+    ///
+    /// Modules emit such objects instead of leaving an output port out, so
+    /// block and timestep still reach downstream modules.
+    fn is_empty(&self) -> bool {
+        self.payload().is_some_and(ObjectPayload::is_empty)
+    }
+
+    /// Approximate bytes of the payload arrays, 0 without a payload
+    fn payload_size(&self) -> usize {
+        self.payload().map_or(0, ObjectPayload::size_bytes)
+    }
+
+    /// Get the object's generic data container, if it has one
+    fn as_data(&self) -> Option<&ObjectData> {
+        None
+    }
+
+    /// Geometry a mapped field is defined on; `references` include it
+    fn grid(&self) -> Option<GridMapping> {
+        self.as_data().and_then(|data| data.grid)
+    }
+
+    /// View of a triangle surface payload
+    fn as_triangles(&self) -> Option<TrianglesView<'_>> {
+        self.payload().and_then(TrianglesView::new)
+    }
+
+    /// View of a scalar field payload
+    fn as_scalar_field(&self) -> Option<ScalarFieldView<'_>> {
+        self.payload().and_then(ScalarFieldView::new)
+    }
+
+    /// View of a 3-vector field payload
+    fn as_vector_field(&self) -> Option<VectorFieldView<'_>> {
+        self.payload().and_then(VectorFieldView::new)
+    }
+
+    /// View of a double-precision scalar field payload
+    fn as_scalar_field_f64(&self) -> Option<ScalarFieldF64View<'_>> {
+        self.payload().and_then(ScalarFieldF64View::new)
+    }
+
+    /// View of a double-precision 3-vector field payload
+    fn as_vector_field_f64(&self) -> Option<VectorFieldF64View<'_>> {
+        self.payload().and_then(VectorFieldF64View::new)
+    }
+
+    /// View of a 32-bit integer field payload
+    fn as_int32_field(&self) -> Option<Int32FieldView<'_>> {
+        self.payload().and_then(Int32FieldView::new)
+    }
+
+    /// View of a 64-bit integer field payload
+    fn as_int64_field(&self) -> Option<Int64FieldView<'_>> {
+        self.payload().and_then(Int64FieldView::new)
+    }
+
+    /// View of a table payload
+    fn as_table(&self) -> Option<TableView<'_>> {
+        self.payload().and_then(TableView::new)
+    }
+
+    /// View of a curve payload
+    fn as_curve(&self) -> Option<CurveView<'_>> {
+        self.payload().and_then(CurveView::new)
+    }
+
+    /// View of a uniform grid payload
+    fn as_uniform_grid(&self) -> Option<UniformGridView<'_>> {
+        self.payload().and_then(UniformGridView::new)
+    }
+
+    /// View of an unstructured grid payload
+    fn as_unstructured_grid(&self) -> Option<UnstructuredGridView<'_>> {
+        self.payload().and_then(UnstructuredGridView::new)
+    }
+
+    /// View of a polygon mesh payload
+    fn as_polygons(&self) -> Option<PolygonsView<'_>> {
+        self.payload().and_then(PolygonsView::new)
+    }
+
+    /// View of an image payload
+    fn as_image(&self) -> Option<ImageView<'_>> {
+        self.payload().and_then(ImageView::new)
+    }
+
+    /// View of a layered image payload
+    fn as_layered_image(&self) -> Option<LayeredImageView<'_>> {
+        self.payload().and_then(LayeredImageView::new)
+    }
+
+    /// Axis-aligned bounds in the object's own coordinates
+    fn local_bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.payload().and_then(|p| p.bounds())
+    }
+
+    /// Axis-aligned bounds in world space, with the meta transform applied
+    fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let transform = &self.meta().transform;
+        self.local_bounds().map(|b| {
+            if *transform == nalgebra::Matrix4::identity() {
+                b
+            } else {
+                crate::core::transform::transform_bounds(transform, b)
+            }
+        })
+    }
+}
+
+impl std::fmt::Debug for dyn Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Object")
+            .field("id", &self.id())
+            .field("object_type", &self.object_type())
+            .field("timestep", &self.meta().timestep)
+            .field("block", &self.meta().block)
+            .finish()
+    }
 }
 
 /// Generic object data container
+///
+/// The payload is reference counted, so cloning an `ObjectData` to edit its
+/// metadata or attributes never copies the (potentially huge) arrays.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ObjectData {
     pub id: ObjectId,
     pub object_type: ObjectType,
     pub meta: ObjectMeta,
     pub attributes: HashMap<String, String>,
-    pub data: ObjectPayload,
+    pub data: Arc<ObjectPayload>,
+    /// Geometry the payload's values are defined on, for mapped fields
+    #[serde(default)]
+    pub grid: Option<GridMapping>,
+}
+
+impl ObjectData {
+    /// Copy attributes from an input object that this object does not set itself
+    ///
+    /// Attributes covered by `invalidation_rules` are recomputed from this
+    /// object's payload or dropped, unless the policy is `All`.
+    pub fn inherit_from(&mut self, input: &dyn Object, policy: &AttributePolicy) {
+        for (key, value) in input.attributes() {
+            if !policy.allows(key) || self.attributes.contains_key(key) {
+                continue;
+            }
+
+            let rule = invalidation_rules().iter().find(|(k, _)| k == key).map(|(_, r)| *r);
+            let value = match (policy, rule) {
+                (AttributePolicy::All, _) | (_, None) => Some(value.clone()),
+                (_, Some(Invalidation::Remove)) => None,
+                (_, Some(Invalidation::Recompute(f))) => f(&self.data),
+            };
+            if let Some(value) = value {
+                self.attributes.insert(key.clone(), value);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -159,13 +446,323 @@ pub enum ObjectPayload {
     VecVec3 {
         data: ndarray::Array2<f32>,
     },
+    /// Named columns of equal length
+    Table {
+        columns: Vec<(String, ndarray::Array1<f64>)>,
+    },
+    /// Samples of y over x for plotting; NaN in y marks a gap in the line
+    Curve {
+        x: ndarray::Array1<f64>,
+        y: ndarray::Array1<f64>,
+        x_label: String,
+        y_label: String,
+    },
+    /// Regular grid with one value per point, x fastest
+    UniformGrid {
+        /// Points along each axis
+        dims: [usize; 3],
+        origin: [f32; 3],
+        spacing: [f32; 3],
+        values: ndarray::Array1<f32>,
+    },
+    /// Refinement levels of uniform grid blocks; see `AmrView`
+    AmrHierarchy {
+        levels: Vec<crate::core::AmrLevel>,
+    },
+    /// Data not loaded yet; `ObjectRegistry::resolve` runs the loader
+    Placeholder {
+        loader: crate::core::Loader,
+    },
     Custom(Vec<u8>),
+    /// Float field compressed within an error bound; `ObjectData::decompress_lossy` restores it
+    Lossy(crate::core::LossyPayload),
+    /// Cells of mixed types; see `UnstructuredGridView`
+    UnstructuredGrid {
+        coordinates: ndarray::Array2<f32>,
+        /// Vertex indices of all cells, one cell after the other
+        connectivity: ndarray::Array1<i32>,
+        /// Start of each cell in `connectivity`, then the length of `connectivity`
+        offsets: ndarray::Array1<i32>,
+        cell_types: Vec<CellType>,
+    },
+    /// Grid with points where per-axis coordinate lines cross, x fastest
+    RectilinearGrid {
+        x: ndarray::Array1<f32>,
+        y: ndarray::Array1<f32>,
+        z: ndarray::Array1<f32>,
+    },
+    /// Grid of `dims` points at arbitrary positions, x fastest
+    StructuredGrid {
+        dims: [usize; 3],
+        coordinates: ndarray::Array2<f32>,
+    },
+    /// Faces of any number of vertices; see `PolygonsView`
+    Polygons {
+        coordinates: ndarray::Array2<f32>,
+        /// Vertex indices of all faces, one face after the other
+        connectivity: ndarray::Array1<i32>,
+        /// Start of each face in `connectivity`, then the length of `connectivity`
+        offsets: ndarray::Array1<i32>,
+    },
+    /// Quadrilaterals as rows of four vertex indices; `to_triangles` splits them
+    Quads {
+        coordinates: ndarray::Array2<f32>,
+        quads: ndarray::Array2<i32>,
+    },
+    /// Double-precision `VecScalar`; see `ObjectPayload::to_f32`
+    VecScalarF64 {
+        data: ndarray::Array1<f64>,
+    },
+    /// Double-precision `VecVec3`, one row per vector
+    VecVec3F64 {
+        data: ndarray::Array2<f64>,
+    },
+    /// Raster of `height` rows of `width` pixels, top row first; see `ImageView`
+    Image {
+        width: usize,
+        height: usize,
+        channels: usize,
+        data: crate::core::ImageData,
+        /// Lower left corner of the image
+        origin: [f32; 3],
+        /// Pixel size along x and y
+        spacing: [f32; 2],
+    },
+    /// Named single-channel bands of one raster; see `LayeredImageView`
+    LayeredImage {
+        width: usize,
+        height: usize,
+        bands: Vec<crate::core::ImageBand>,
+        origin: [f32; 3],
+        spacing: [f32; 2],
+    },
+    /// One 32-bit integer per vertex or cell, e.g. material IDs
+    VecInt32 {
+        data: ndarray::Array1<i32>,
+    },
+    /// One 64-bit integer per vertex or cell, e.g. global point indices
+    VecInt64 {
+        data: ndarray::Array1<i64>,
+    },
+}
+
+impl ObjectPayload {
+    /// Vertex coordinates of geometric payloads
+    pub fn coordinates(&self) -> Option<&ndarray::Array2<f32>> {
+        match self {
+            ObjectPayload::Points { coordinates }
+            | ObjectPayload::Lines { coordinates, .. }
+            | ObjectPayload::Triangles { coordinates, .. }
+            | ObjectPayload::UnstructuredGrid { coordinates, .. }
+            | ObjectPayload::StructuredGrid { coordinates, .. }
+            | ObjectPayload::Polygons { coordinates, .. }
+            | ObjectPayload::Quads { coordinates, .. } => Some(coordinates),
+            _ => None,
+        }
+    }
+
+    /// Number of vertices of geometric payloads and points of grids, 0 otherwise
+    pub fn num_vertices(&self) -> usize {
+        if let Some(dims) = self.grid_dims() {
+            return dims.iter().product();
+        }
+        self.coordinates().map(|c| c.nrows()).unwrap_or(0)
+    }
+
+    /// Number of triangles of a triangle payload, 0 otherwise
+    pub fn num_triangles(&self) -> usize {
+        match self {
+            ObjectPayload::Triangles { triangles, .. } => triangles.nrows(),
+            _ => 0,
+        }
+    }
+
+    /// Number of cells of grid payloads, 0 otherwise
+    ///
+    /// Axes of a structured grid with a single point add no dimension, so
+    /// dims of (1, N, 1) make N - 1 line cells.
+    pub fn num_cells(&self) -> usize {
+        if let ObjectPayload::UnstructuredGrid { cell_types, .. } = self {
+            return cell_types.len();
+        }
+        match self.grid_dims() {
+            Some(dims) if dims.iter().all(|&d| d > 0) && dims.iter().any(|&d| d > 1) => {
+                dims.iter().map(|&d| d.saturating_sub(1).max(1)).product()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Number of segments of a line payload, 0 otherwise
+    pub fn num_lines(&self) -> usize {
+        match self {
+            ObjectPayload::Lines { connections, .. } => connections.nrows(),
+            _ => 0,
+        }
+    }
+
+    /// Column of a table payload by name
+    pub fn column(&self, name: &str) -> Option<&ndarray::Array1<f64>> {
+        match self {
+            ObjectPayload::Table { columns } => columns.iter().find(|(n, _)| n == name).map(|(_, c)| c),
+            _ => None,
+        }
+    }
+
+    /// Column names of a table payload, in order
+    pub fn column_names(&self) -> Vec<&str> {
+        match self {
+            ObjectPayload::Table { columns } => columns.iter().map(|(n, _)| n.as_str()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Number of rows of a table payload, 0 otherwise
+    pub fn num_rows(&self) -> usize {
+        match self {
+            ObjectPayload::Table { columns } => columns.first().map(|(_, c)| c.len()).unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Nx3 view of the vertex positions
+    pub fn positions(&self) -> Option<ndarray::ArrayView2<'_, f32>> {
+        self.coordinates().map(|c| c.view())
+    }
+
+    /// Vertex indices of triangle `i`
+    pub fn triangle(&self, i: usize) -> Option<[usize; 3]> {
+        match self {
+            ObjectPayload::Triangles { triangles, .. } if i < triangles.nrows() => Some([
+                triangles[(i, 0)] as usize,
+                triangles[(i, 1)] as usize,
+                triangles[(i, 2)] as usize,
+            ]),
+            _ => None,
+        }
+    }
+
+    /// Vertex indices of line segment `i`
+    pub fn line(&self, i: usize) -> Option<[usize; 2]> {
+        match self {
+            ObjectPayload::Lines { connections, .. } if i < connections.nrows() => Some([
+                connections[(i, 0)] as usize,
+                connections[(i, 1)] as usize,
+            ]),
+            _ => None,
+        }
+    }
+
+    /// Objects this payload refers to by id
+    pub fn references(&self) -> Vec<ObjectId> {
+        self.amr().map(|amr| amr.block_ids()).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, ObjectPayload::Empty)
+    }
+
+    /// Approximate memory held by the arrays, for eviction decisions
+    pub fn size_bytes(&self) -> usize {
+        use std::mem::size_of;
+        match self {
+            ObjectPayload::Empty | ObjectPayload::Placeholder { .. } => 0,
+            ObjectPayload::Points { coordinates } => coordinates.len() * size_of::<f32>(),
+            ObjectPayload::Lines { coordinates, connections } => {
+                coordinates.len() * size_of::<f32>() + connections.len() * size_of::<i32>()
+            }
+            ObjectPayload::Triangles { coordinates, triangles } => {
+                coordinates.len() * size_of::<f32>() + triangles.len() * size_of::<i32>()
+            }
+            ObjectPayload::VecScalar { data } => data.len() * size_of::<f32>(),
+            ObjectPayload::VecVec3 { data } => data.len() * size_of::<f32>(),
+            ObjectPayload::Table { columns } => columns.iter().map(|(_, c)| c.len() * size_of::<f64>()).sum(),
+            ObjectPayload::Curve { x, y, .. } => (x.len() + y.len()) * size_of::<f64>(),
+            ObjectPayload::UniformGrid { values, .. } => values.len() * size_of::<f32>(),
+            // Blocks are separate objects and counted there
+            ObjectPayload::AmrHierarchy { .. } => 0,
+            ObjectPayload::Custom(bytes) => bytes.len(),
+            ObjectPayload::Lossy(payload) => payload.field().size_bytes(),
+            ObjectPayload::UnstructuredGrid { coordinates, connectivity, offsets, cell_types } => {
+                coordinates.len() * size_of::<f32>()
+                    + (connectivity.len() + offsets.len()) * size_of::<i32>()
+                    + cell_types.len() * size_of::<CellType>()
+            }
+            ObjectPayload::RectilinearGrid { x, y, z } => (x.len() + y.len() + z.len()) * size_of::<f32>(),
+            ObjectPayload::StructuredGrid { coordinates, .. } => coordinates.len() * size_of::<f32>(),
+            ObjectPayload::Polygons { coordinates, connectivity, offsets } => {
+                coordinates.len() * size_of::<f32>() + (connectivity.len() + offsets.len()) * size_of::<i32>()
+            }
+            ObjectPayload::Quads { coordinates, quads } => {
+                coordinates.len() * size_of::<f32>() + quads.len() * size_of::<i32>()
+            }
+            ObjectPayload::VecScalarF64 { data } => data.len() * size_of::<f64>(),
+            ObjectPayload::VecVec3F64 { data } => data.len() * size_of::<f64>(),
+            ObjectPayload::Image { data, .. } => data.size_bytes(),
+            ObjectPayload::LayeredImage { bands, .. } => bands.iter().map(|b| b.values.len() * size_of::<f32>()).sum(),
+            ObjectPayload::VecInt32 { data } => data.len() * size_of::<i32>(),
+            ObjectPayload::VecInt64 { data } => data.len() * size_of::<i64>(),
+        }
+    }
+
+    /// Axis-aligned bounds of the coordinates, `None` if there are none
+    pub fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        match self {
+            ObjectPayload::UniformGrid { dims, origin, spacing, .. } => {
+                if dims.contains(&0) {
+                    return None;
+                }
+                let min = Vector3::from(*origin);
+                let max = Vector3::from_fn(|a, _| origin[a] + dims[a].saturating_sub(1) as f32 * spacing[a]);
+                return Some((min, max));
+            }
+            ObjectPayload::RectilinearGrid { x, y, z } => {
+                let range = |axis: &ndarray::Array1<f32>| axis.iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+                let [(x0, x1), (y0, y1), (z0, z1)] = [range(x), range(y), range(z)];
+                return (x0 <= x1 && y0 <= y1 && z0 <= z1)
+                    .then(|| (Vector3::new(x0, y0, z0), Vector3::new(x1, y1, z1)));
+            }
+            ObjectPayload::Image { width, height, origin, spacing, .. }
+            | ObjectPayload::LayeredImage { width, height, origin, spacing, .. } => {
+                let [lower, _, upper, _] = crate::core::raster::corners(*width, *height, *origin, *spacing);
+                return Some((lower, upper));
+            }
+            ObjectPayload::AmrHierarchy { levels } => {
+                let blocks = levels.first().map(|l| l.blocks.as_slice()).unwrap_or_default();
+                return blocks.iter()
+                    .map(|b| {
+                        let dims = b.extent.dims();
+                        let min = Vector3::from(b.origin);
+                        (min, Vector3::from_fn(|a, _| b.origin[a] + dims[a] as f32 * b.spacing[a]))
+                    })
+                    .reduce(|(lo, hi), (l, h)| (lo.inf(&l), hi.sup(&h)));
+            }
+            _ => {}
+        }
+        let coordinates = self.coordinates()?;
+        if coordinates.nrows() == 0 || coordinates.ncols() < 3 {
+            return None;
+        }
+
+        let mut min = Vector3::repeat(f32::INFINITY);
+        let mut max = Vector3::repeat(f32::NEG_INFINITY);
+        for row in coordinates.outer_iter() {
+            let p = Vector3::new(row[0], row[1], row[2]);
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        Some((min, max))
+    }
 }
 
 /// Concrete object implementation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VistleObject {
     data: ObjectData,
+    /// Local bounds, computed on first use and reset when the payload changes
+    #[serde(skip)]
+    bounds: std::sync::OnceLock<Option<(Vector3<f32>, Vector3<f32>)>>,
 }
 
 impl VistleObject {
@@ -176,8 +773,10 @@ impl VistleObject {
                 object_type,
                 meta: ObjectMeta::default(),
                 attributes: HashMap::new(),
-                data: ObjectPayload::Empty,
+                data: Arc::new(ObjectPayload::Empty),
+                grid: None,
             },
+            bounds: std::sync::OnceLock::new(),
         }
     }
 
@@ -188,10 +787,71 @@ impl VistleObject {
                 object_type,
                 meta: ObjectMeta::default(),
                 attributes: HashMap::new(),
-                data: payload,
+                data: Arc::new(payload),
+                grid: None,
             },
+            bounds: std::sync::OnceLock::new(),
         }
     }
+
+    /// Empty object with the metadata and attributes of `source`
+    ///
+    /// This is what a module outputs when an input produces no data, and
+    /// what it passes on for empty inputs.
+    pub fn empty_like(source: &dyn Object) -> Self {
+        let mut object = VistleObject::with_data(ObjectType::Empty, ObjectPayload::Empty)
+            .with_meta(source.meta().clone());
+        object.data.attributes = source.attributes().clone();
+        object
+    }
+
+    /// Wrap an existing data container
+    pub fn from_data(data: ObjectData) -> Self {
+        Self {
+            data,
+            bounds: std::sync::OnceLock::new(),
+        }
+    }
+
+    /// Access the object's payload
+    pub fn data(&self) -> &ObjectPayload {
+        &self.data.data
+    }
+
+    /// Mutable payload access, copying the payload if it is still shared
+    ///
+    /// Drops the cached bounds, which are computed anew on next use.
+    pub fn payload_mut(&mut self) -> &mut ObjectPayload {
+        self.bounds = std::sync::OnceLock::new();
+        Arc::make_mut(&mut self.data.data)
+    }
+
+    /// Number of vertices of geometric payloads and points of grids, 0 otherwise
+    pub fn num_vertices(&self) -> usize {
+        self.data.data.num_vertices()
+    }
+
+    /// Number of cells of grids, 0 otherwise
+    pub fn num_cells(&self) -> usize {
+        self.data.data.num_cells()
+    }
+
+    /// Copy metadata (block, timestep, transform, ...) from another object
+    pub fn with_meta(mut self, meta: ObjectMeta) -> Self {
+        self.data.meta = meta;
+        self
+    }
+
+    /// Map the field's values onto `grid`, e.g. a surface from an upstream module
+    pub fn with_grid(self, grid: &dyn Object, mapping: Mapping) -> Self {
+        self.with_grid_id(grid.id(), mapping)
+    }
+
+    /// Map the field's values onto the object with id `grid`
+    pub fn with_grid_id(mut self, grid: ObjectId, mapping: Mapping) -> Self {
+        self.data.grid = Some(GridMapping { grid, mapping });
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -214,12 +874,13 @@ impl Object for VistleObject {
 
     fn is_complete(&self) -> bool {
         // Simplified: in real implementation, check for unresolved references
-        true
+        !matches!(*self.data.data, ObjectPayload::Placeholder { .. })
     }
 
     fn references(&self) -> Vec<ObjectId> {
-        // Return IDs of referenced objects
-        Vec::new()
+        let mut references = self.data.data.references();
+        references.extend(self.data.grid.map(|g| g.grid));
+        references
     }
 
     fn clone_object(&self) -> Box<dyn Object> {
@@ -237,17 +898,31 @@ impl Object for VistleObject {
     fn attributes(&self) -> &HashMap<String, String> {
         &self.data.attributes
     }
+
+    fn payload(&self) -> Option<&ObjectPayload> {
+        Some(&self.data.data)
+    }
+
+    fn local_bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        *self.bounds.get_or_init(|| self.data.data.bounds())
+    }
+
+    fn as_data(&self) -> Option<&ObjectData> {
+        Some(&self.data)
+    }
 }
 
 /// Thread-safe object registry
 pub struct ObjectRegistry {
     objects: dashmap::DashMap<ObjectId, Arc<dyn Object>>,
+    pub(crate) lazy: crate::core::LazyObjects,
 }
 
 impl ObjectRegistry {
     pub fn new() -> Self {
         Self {
             objects: dashmap::DashMap::new(),
+            lazy: crate::core::LazyObjects::default(),
         }
     }
 
@@ -262,16 +937,254 @@ impl ObjectRegistry {
     }
 
     pub fn remove(&self, id: ObjectId) -> bool {
+        self.lazy.forget(id);
         self.objects.remove(&id).is_some()
     }
 
-    pub fn iter(&self) -> dashmap::iter::Iter<ObjectId, Arc<dyn Object>> {
+    pub fn iter(&self) -> dashmap::iter::Iter<'_, ObjectId, Arc<dyn Object>> {
         self.objects.iter()
     }
 }
 
+impl std::fmt::Debug for ObjectRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObjectRegistry")
+            .field("objects", &self.objects.len())
+            .finish_non_exhaustive()
+    }
+}
+
 impl Default for ObjectRegistry {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// Shared handle to an immutable object
+#[derive(Clone)]
+pub struct ObjectHandle {
+    object: Arc<dyn Object>,
+}
+
+impl ObjectHandle {
+    pub fn new(object: Arc<dyn Object>) -> Self {
+        Self { object }
+    }
+
+    /// Look up an object in a registry
+    pub fn get(registry: &ObjectRegistry, id: ObjectId) -> Option<Self> {
+        registry.get(id).map(Self::new)
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.object.id()
+    }
+
+    pub fn object(&self) -> &Arc<dyn Object> {
+        &self.object
+    }
+
+    /// Start a copy-on-write edit of this object
+    ///
+    /// The returned view shares the payload with the original; metadata and
+    /// attribute edits copy only those parts, and the payload is copied the
+    /// first time it is accessed mutably.
+    pub fn make_mut<'a>(&self, registry: &'a ObjectRegistry) -> Result<ObjectMut<'a>, crate::Error> {
+        let data = self.object.as_data()
+            .ok_or_else(|| crate::Error::Module(format!(
+                "Object {} does not support copy-on-write editing",
+                self.object.id()
+            )))?
+            .clone();
+
+        Ok(ObjectMut {
+            registry,
+            source: self.object.id(),
+            data,
+        })
+    }
+}
+
+impl std::ops::Deref for ObjectHandle {
+    type Target = dyn Object;
+
+    fn deref(&self) -> &Self::Target {
+        self.object.as_ref()
+    }
+}
+
+/// Mutable copy-on-write view of an object
+pub struct ObjectMut<'a> {
+    registry: &'a ObjectRegistry,
+    source: ObjectId,
+    data: ObjectData,
+}
+
+impl<'a> ObjectMut<'a> {
+    /// Id of the object this edit derives from
+    pub fn source(&self) -> ObjectId {
+        self.source
+    }
+
+    pub fn meta_mut(&mut self) -> &mut ObjectMeta {
+        &mut self.data.meta
+    }
+
+    pub fn set_attribute(&mut self, key: String, value: String) {
+        self.data.attributes.insert(key, value);
+    }
+
+    pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
+        self.data.attributes.remove(key)
+    }
+
+    pub fn payload(&self) -> &ObjectPayload {
+        &self.data.data
+    }
+
+    /// Mutable payload access, copying the payload if it is still shared
+    pub fn payload_mut(&mut self) -> &mut ObjectPayload {
+        Arc::make_mut(&mut self.data.data)
+    }
+
+    /// Check whether the payload is still shared with other objects
+    pub fn payload_is_shared(&self) -> bool {
+        Arc::strong_count(&self.data.data) > 1
+    }
+
+    /// Register the edited object under a new id, recording its provenance
+    pub fn commit(mut self) -> ObjectHandle {
+        self.data.id = ObjectId::new();
+        self.data.attributes.insert(
+            attribute::DERIVED_FROM.to_string(),
+            self.source.to_string(),
+        );
+
+        let object: Arc<dyn Object> = Arc::new(VistleObject::from_data(self.data));
+        self.registry.store(object.clone());
+        ObjectHandle::new(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array2;
+
+    fn points(registry: &ObjectRegistry) -> ObjectHandle {
+        let object: Arc<dyn Object> = Arc::new(VistleObject::with_data(
+            ObjectType::Points,
+            ObjectPayload::Points { coordinates: Array2::zeros((1000, 3)) },
+        ));
+        registry.store(object.clone());
+        ObjectHandle::new(object)
+    }
+
+    fn payload_ptr(object: &dyn Object) -> *const ObjectPayload {
+        object.payload().unwrap()
+    }
+
+    #[test]
+    fn metadata_edits_share_the_payload() {
+        let registry = ObjectRegistry::new();
+        let original = points(&registry);
+
+        let mut edit = original.make_mut(&registry).unwrap();
+        edit.meta_mut().timestep = 7;
+        edit.set_attribute(attribute::RANGE.to_string(), "0 1".to_string());
+        assert!(edit.payload_is_shared());
+        let derived = edit.commit();
+
+        assert_eq!(payload_ptr(&*derived), payload_ptr(&*original));
+        assert_eq!(derived.meta().timestep, 7);
+        assert_eq!(derived.get_attribute(attribute::RANGE), Some("0 1"));
+        assert_eq!(original.meta().timestep, 0);
+        assert_eq!(original.get_attribute(attribute::RANGE), None);
+    }
+
+    #[test]
+    fn payload_edits_copy_only_the_edited_object() {
+        let registry = ObjectRegistry::new();
+        let original = points(&registry);
+
+        let mut edit = original.make_mut(&registry).unwrap();
+        if let ObjectPayload::Points { coordinates } = edit.payload_mut() {
+            coordinates[[0, 0]] = 5.0;
+        }
+        assert!(!edit.payload_is_shared());
+        let derived = edit.commit();
+
+        assert_ne!(payload_ptr(&*derived), payload_ptr(&*original));
+        assert_eq!(derived.as_data().unwrap().data.coordinates().unwrap()[[0, 0]], 5.0);
+        assert_eq!(original.as_data().unwrap().data.coordinates().unwrap()[[0, 0]], 0.0);
+    }
+
+    #[test]
+    fn committed_edits_are_registered_with_their_provenance() {
+        let registry = ObjectRegistry::new();
+        let original = points(&registry);
+
+        let derived = original.make_mut(&registry).unwrap().commit();
+        assert_ne!(derived.id(), original.id());
+        assert!(registry.get(derived.id()).is_some());
+        assert!(registry.get(original.id()).is_some());
+        assert_eq!(derived.get_attribute(attribute::DERIVED_FROM), Some(original.id().to_string().as_str()));
+    }
+
+    #[test]
+    fn bounds_follow_the_meta_transform() {
+        let meta = ObjectMeta {
+            transform: nalgebra::Matrix4::new_translation(&Vector3::new(10.0, 0.0, 0.0))
+                * nalgebra::Matrix4::new_scaling(2.0),
+            ..Default::default()
+        };
+        let object = VistleObject::with_data(ObjectType::Points, ObjectPayload::Points {
+            coordinates: ndarray::array![[0.0f32, 0.0, 0.0], [1.0, 2.0, 3.0]],
+        })
+        .with_meta(meta);
+
+        assert_eq!(object.local_bounds(), Some((Vector3::zeros(), Vector3::new(1.0, 2.0, 3.0))));
+        assert_eq!(object.bounds(), Some((Vector3::new(10.0, 0.0, 0.0), Vector3::new(12.0, 4.0, 6.0))));
+
+        let empty = VistleObject::with_data(ObjectType::Points, ObjectPayload::Points { coordinates: Array2::zeros((0, 3)) });
+        assert_eq!(empty.bounds(), None);
+    }
+
+    #[test]
+    fn empty_payloads_have_no_bounds() {
+        let payloads = [
+            (ObjectType::Empty, ObjectPayload::Empty),
+            (ObjectType::Points, ObjectPayload::Points { coordinates: Array2::zeros((0, 3)) }),
+            (ObjectType::Triangles, ObjectPayload::Triangles {
+                coordinates: Array2::zeros((0, 3)),
+                triangles: Array2::zeros((0, 3)),
+            }),
+            (ObjectType::Vec, ObjectPayload::VecScalar { data: ndarray::Array1::zeros(4) }),
+        ];
+        for (object_type, payload) in payloads {
+            let object = VistleObject::with_data(object_type, payload);
+            assert_eq!(object.local_bounds(), None);
+            // The cached answer is the same
+            assert_eq!(object.local_bounds(), None);
+            assert_eq!(object.bounds(), None);
+        }
+    }
+
+    #[test]
+    fn a_single_point_is_its_own_bounds() {
+        let point = Vector3::new(1.0f32, -2.0, 3.0);
+        let object = VistleObject::with_data(ObjectType::Points, ObjectPayload::Points {
+            coordinates: ndarray::array![[1.0f32, -2.0, 3.0]],
+        });
+        assert_eq!(object.local_bounds(), Some((point, point)));
+        assert_eq!(object.local_bounds(), Some((point, point)));
+
+        let meta = ObjectMeta {
+            transform: nalgebra::Matrix4::new_translation(&Vector3::new(0.0, 0.0, 10.0)),
+            ..Default::default()
+        };
+        let moved = object.with_meta(meta);
+        let shifted = Vector3::new(1.0f32, -2.0, 13.0);
+        assert_eq!(moved.bounds(), Some((shifted, shifted)));
+    }
+}
@@ -1,6 +1,7 @@
 //! Parameter system for module configuration
 
 use std::collections::HashMap;
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
 /// Parameter value container
@@ -15,6 +16,54 @@ pub enum ParameterValue {
     VecString(Vec<String>),
 }
 
+impl ParameterValue {
+    /// Value of a parameter of type `param_type` written as text
+    ///
+    /// Floats use '.' as decimal separator regardless of locale, see
+    /// `util::fmt`. Vector elements are separated by whitespace; string
+    /// vectors are JSON arrays.
+    pub fn parse(param_type: &ParameterType, text: &str) -> Result<Self, crate::Error> {
+        use crate::util::fmt::parse_f32;
+        let int = |t: &str| t.trim().parse::<i32>()
+            .map_err(|_| crate::Error::Config(format!("'{}' is not an integer", t.trim())));
+        Ok(match param_type {
+            ParameterType::Int { .. } => ParameterValue::Int(int(text)?),
+            ParameterType::Float { .. } => ParameterValue::Float(parse_f32(text)?),
+            ParameterType::String | ParameterType::FilePath => ParameterValue::String(text.to_string()),
+            ParameterType::Bool => match text.trim() {
+                "true" | "1" => ParameterValue::Bool(true),
+                "false" | "0" => ParameterValue::Bool(false),
+                other => return Err(crate::Error::Config(format!("'{}' is not true or false", other))),
+            },
+            ParameterType::VectorInt { .. } => {
+                ParameterValue::VecInt(text.split_whitespace().map(int).collect::<Result<_, _>>()?)
+            }
+            ParameterType::VectorFloat { .. } => {
+                ParameterValue::VecFloat(text.split_whitespace().map(parse_f32).collect::<Result<_, _>>()?)
+            }
+            ParameterType::VectorString => ParameterValue::VecString(serde_json::from_str(text)
+                .map_err(|e| crate::Error::Config(format!("'{}' is not a JSON array of strings: {}", text, e)))?),
+        })
+    }
+}
+
+/// Text `ParameterValue::parse` reads back to the same value
+impl std::fmt::Display for ParameterValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use crate::util::fmt::format_f32;
+        let join = |items: Vec<String>| items.join(" ");
+        match self {
+            ParameterValue::Int(v) => write!(f, "{}", v),
+            ParameterValue::Float(v) => f.write_str(&format_f32(*v)),
+            ParameterValue::String(v) => f.write_str(v),
+            ParameterValue::Bool(v) => write!(f, "{}", v),
+            ParameterValue::VecInt(v) => f.write_str(&join(v.iter().map(|i| i.to_string()).collect())),
+            ParameterValue::VecFloat(v) => f.write_str(&join(v.iter().map(|x| format_f32(*x)).collect())),
+            ParameterValue::VecString(v) => f.write_str(&serde_json::to_string(v).map_err(|_| std::fmt::Error)?),
+        }
+    }
+}
+
 /// Parameter definition
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Parameter {
@@ -47,6 +96,14 @@ impl Parameter {
             max_value: None,
         }
     }
+
+    /// A string parameter naming a file, resolved with `ComputeContext::resolve_path`
+    pub fn file_path(name: &str, description: &str, default: &str) -> Self {
+        Self {
+            param_type: ParameterType::FilePath,
+            ..Self::new(name, description, ParameterValue::String(default.to_string()))
+        }
+    }
 }
 
 /// Parameter type information
@@ -59,54 +116,171 @@ pub enum ParameterType {
     VectorInt { min: Option<i32>, max: Option<i32> },
     VectorFloat { min: Option<f32>, max: Option<f32> },
     VectorString,
+    FilePath,
 }
 
 /// Collection of parameters for a module
+///
+/// Values live behind an `Arc`, so `snapshot` is a cheap clone and changes
+/// made afterwards copy the map instead of altering the snapshot.
 #[derive(Debug, Clone, Default)]
 pub struct ParameterSet {
-    parameters: HashMap<String, Parameter>,
+    values: ParameterSnapshot,
 }
 
 impl ParameterSet {
     pub fn new() -> Self {
-        Self {
-            parameters: HashMap::new(),
-        }
+        Self::default()
+    }
+
+    fn parameters_mut(&mut self) -> &mut HashMap<String, Parameter> {
+        self.values.revision += 1;
+        Arc::make_mut(&mut self.values.parameters)
     }
 
     pub fn add(&mut self, param: Parameter) {
-        self.parameters.insert(param.name.clone(), param);
+        self.parameters_mut().insert(param.name.clone(), param);
     }
 
     pub fn get(&self, name: &str) -> Option<&Parameter> {
-        self.parameters.get(name)
+        self.values.get(name)
     }
 
     pub fn get_mut(&mut self, name: &str) -> Option<&mut Parameter> {
-        self.parameters.get_mut(name)
+        if !self.values.parameters.contains_key(name) {
+            return None;
+        }
+        self.parameters_mut().get_mut(name)
     }
 
     pub fn set_value(&mut self, name: &str, value: ParameterValue) -> Result<(), String> {
-        if let Some(param) = self.parameters.get_mut(name) {
-            // Basic type validation
-            match (&param.param_type, &value) {
-                (ParameterType::Int { .. }, ParameterValue::Int(_)) => {}
-                (ParameterType::Float { .. }, ParameterValue::Float(_)) => {}
-                (ParameterType::String, ParameterValue::String(_)) => {}
-                (ParameterType::Bool, ParameterValue::Bool(_)) => {}
-                (ParameterType::VectorInt { .. }, ParameterValue::VecInt(_)) => {}
-                (ParameterType::VectorFloat { .. }, ParameterValue::VecFloat(_)) => {}
-                (ParameterType::VectorString, ParameterValue::VecString(_)) => {}
-                _ => return Err(format!("Type mismatch for parameter {}", name)),
-            }
+        let Some(param) = self.values.get(name) else {
+            return Err(format!("Parameter {} not found", name));
+        };
+        // Basic type validation
+        match (&param.param_type, &value) {
+            (ParameterType::Int { .. }, ParameterValue::Int(_)) => {}
+            (ParameterType::Float { .. }, ParameterValue::Float(_)) => {}
+            (ParameterType::String, ParameterValue::String(_)) => {}
+            (ParameterType::FilePath, ParameterValue::String(_)) => {}
+            (ParameterType::Bool, ParameterValue::Bool(_)) => {}
+            (ParameterType::VectorInt { .. }, ParameterValue::VecInt(_)) => {}
+            (ParameterType::VectorFloat { .. }, ParameterValue::VecFloat(_)) => {}
+            (ParameterType::VectorString, ParameterValue::VecString(_)) => {}
+            _ => return Err(format!("Type mismatch for parameter {}", name)),
+        }
+        if let Some(param) = self.parameters_mut().get_mut(name) {
             param.value = value;
-            Ok(())
-        } else {
-            Err(format!("Parameter {} not found", name))
         }
+        Ok(())
+    }
+
+    /// Immutable view of the current values, unaffected by later changes
+    pub fn snapshot(&self) -> ParameterSnapshot {
+        self.values.clone()
+    }
+
+    /// Counter bumped by every change
+    pub fn revision(&self) -> u64 {
+        self.values.revision
+    }
+
+    /// Get an integer parameter value, if present with matching type
+    pub fn get_int(&self, name: &str) -> Option<i32> {
+        self.values.get_int(name)
+    }
+
+    /// Get a float parameter value, if present with matching type
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        self.values.get_float(name)
+    }
+
+    /// Get a boolean parameter value, if present with matching type
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        self.values.get_bool(name)
     }
 
-    pub fn iter(&self) -> std::collections::hash_map::Iter<String, Parameter> {
+    /// Get a string parameter value, if present with matching type
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        self.values.get_string(name)
+    }
+
+    /// Get a float vector parameter value, if present with matching type
+    pub fn get_vec_float(&self, name: &str) -> Option<&[f32]> {
+        self.values.get_vec_float(name)
+    }
+
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, String, Parameter> {
+        self.values.iter()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.values.names()
+    }
+}
+
+impl From<ParameterSnapshot> for ParameterSet {
+    fn from(values: ParameterSnapshot) -> Self {
+        Self { values }
+    }
+}
+
+/// Parameter values as they were when the snapshot was taken
+///
+/// Handed to a module's compute through `ComputeContext::parameters`, so
+/// values read at different points of one execution are consistent.
+#[derive(Debug, Clone, Default)]
+pub struct ParameterSnapshot {
+    parameters: Arc<HashMap<String, Parameter>>,
+    revision: u64,
+}
+
+impl ParameterSnapshot {
+    pub fn get(&self, name: &str) -> Option<&Parameter> {
+        self.parameters.get(name)
+    }
+
+    /// Revision of the `ParameterSet` the snapshot was taken from
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i32> {
+        match self.get(name).map(|p| &p.value) {
+            Some(ParameterValue::Int(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        match self.get(name).map(|p| &p.value) {
+            Some(ParameterValue::Float(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name).map(|p| &p.value) {
+            Some(ParameterValue::Bool(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        match self.get(name).map(|p| &p.value) {
+            Some(ParameterValue::String(v)) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn get_vec_float(&self, name: &str) -> Option<&[f32]> {
+        match self.get(name).map(|p| &p.value) {
+            Some(ParameterValue::VecFloat(v)) => Some(v.as_slice()),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, String, Parameter> {
         self.parameters.iter()
     }
 
@@ -115,6 +289,14 @@ impl ParameterSet {
     }
 }
 
+/// Well-known data types of ports, see `Port::with_data_type`
+pub mod data_type {
+    /// Field with one value per cell
+    pub const CELL_FIELD: &str = "field:cell";
+    /// Field with one value per point
+    pub const POINT_FIELD: &str = "field:point";
+}
+
 /// Port definition for module connections
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Port {
@@ -122,6 +304,14 @@ pub struct Port {
     pub description: String,
     pub port_type: PortType,
     pub optional: bool,
+    /// Kind of data the port carries; connections between ports of
+    /// different declared types need an adapter, see `compute::coercion`
+    #[serde(default)]
+    pub data_type: Option<String>,
+    /// Input port an output's objects are derived from; attributes are
+    /// inherited from that port only
+    #[serde(default)]
+    pub derived_from: Option<String>,
 }
 
 impl Port {
@@ -131,6 +321,8 @@ impl Port {
             description: description.to_string(),
             port_type: PortType::Input,
             optional: false,
+            data_type: None,
+            derived_from: None,
         }
     }
 
@@ -140,6 +332,8 @@ impl Port {
             description: description.to_string(),
             port_type: PortType::Output,
             optional: false,
+            data_type: None,
+            derived_from: None,
         }
     }
 
@@ -147,6 +341,18 @@ impl Port {
         self.optional = true;
         self
     }
+
+    /// Declare the kind of data the port carries, e.g. `data_type::POINT_FIELD`
+    pub fn with_data_type(mut self, data_type: &str) -> Self {
+        self.data_type = Some(data_type.to_string());
+        self
+    }
+
+    /// Declare the input port an output derives from, e.g. `grid_out` from `grid_in`
+    pub fn derived_from(mut self, input: &str) -> Self {
+        self.derived_from = Some(input.to_string());
+        self
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -188,3 +394,57 @@ impl PortSet {
         self.ports.keys().cloned().collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters() -> ParameterSet {
+        let mut parameters = ParameterSet::new();
+        parameters.add(Parameter::new("iso_value", "Iso value", ParameterValue::Float(0.5)));
+        parameters.add(Parameter::new("label", "Label", ParameterValue::String("first".to_string())));
+        parameters
+    }
+
+    #[test]
+    fn changes_after_a_snapshot_leave_it_alone() {
+        let mut parameters = parameters();
+        let snapshot = parameters.snapshot();
+
+        parameters.set_value("iso_value", ParameterValue::Float(2.0)).unwrap();
+        parameters.get_mut("label").unwrap().value = ParameterValue::String("second".to_string());
+        parameters.add(Parameter::new("extra", "Added later", ParameterValue::Bool(true)));
+
+        assert_eq!(snapshot.get_float("iso_value"), Some(0.5));
+        assert_eq!(snapshot.get_string("label"), Some("first"));
+        assert!(snapshot.get("extra").is_none());
+        assert_eq!(parameters.get_float("iso_value"), Some(2.0));
+        assert_eq!(parameters.get_string("label"), Some("second"));
+    }
+
+    #[test]
+    fn every_change_bumps_the_revision() {
+        let mut parameters = parameters();
+        let snapshot = parameters.snapshot();
+        assert_eq!(snapshot.revision(), parameters.revision());
+
+        parameters.set_value("iso_value", ParameterValue::Float(1.0)).unwrap();
+        assert_eq!(parameters.revision(), snapshot.revision() + 1);
+        // Failed changes and lookups of missing parameters change nothing
+        assert!(parameters.set_value("iso_value", ParameterValue::Int(1)).is_err());
+        assert!(parameters.set_value("missing", ParameterValue::Int(1)).is_err());
+        assert!(parameters.get_mut("missing").is_none());
+        assert_eq!(parameters.revision(), snapshot.revision() + 1);
+    }
+
+    #[test]
+    fn a_set_built_from_a_snapshot_copies_on_change() {
+        let snapshot = parameters().snapshot();
+        let mut restored = ParameterSet::from(snapshot.clone());
+        assert_eq!(restored.get_float("iso_value"), Some(0.5));
+
+        restored.set_value("iso_value", ParameterValue::Float(3.0)).unwrap();
+        assert_eq!(snapshot.get_float("iso_value"), Some(0.5));
+        assert_eq!(restored.get_float("iso_value"), Some(3.0));
+    }
+}
@@ -0,0 +1,87 @@
+//! Resolution of file path parameters relative to a workflow
+
+use std::path::{Path, PathBuf};
+
+/// Variable expanding to the directory of the workflow file
+pub const WORKFLOW_DIR_VAR: &str = "WORKFLOW_DIR";
+
+/// Expand `${NAME}` references to environment variables
+///
+/// `${WORKFLOW_DIR}` expands to `base_dir`. Unset variables and unterminated
+/// references are errors rather than expanding to an empty string.
+pub fn expand_variables(text: &str, base_dir: Option<&Path>) -> Result<String, crate::Error> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find('}').ok_or_else(|| crate::Error::Config(format!(
+            "Unterminated variable reference in path {}",
+            text
+        )))?;
+        let name = &after[..end];
+
+        let value = if name == WORKFLOW_DIR_VAR {
+            base_dir
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .ok_or_else(|| crate::Error::Config(format!(
+                    "Path {} uses ${{{}}} but the workflow was not loaded from a file",
+                    text, WORKFLOW_DIR_VAR
+                )))?
+        } else {
+            std::env::var(name).map_err(|_| crate::Error::Config(format!(
+                "Environment variable {} used in path {} is not set",
+                name, text
+            )))?
+        };
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expand variables, then resolve a relative result against `base_dir`
+///
+/// Absolute paths, and relative paths without a base directory, are
+/// returned as expanded.
+pub fn resolve_path(text: &str, base_dir: Option<&Path>) -> Result<PathBuf, crate::Error> {
+    let path = PathBuf::from(expand_variables(text, base_dir)?);
+    Ok(match base_dir {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_resolve_against_the_workflow_directory() {
+        let base = Path::new("/data/workflows");
+        assert_eq!(resolve_path("input/mesh.vtk", Some(base)).unwrap(), PathBuf::from("/data/workflows/input/mesh.vtk"));
+        assert_eq!(resolve_path("/scratch/mesh.vtk", Some(base)).unwrap(), PathBuf::from("/scratch/mesh.vtk"));
+        assert_eq!(resolve_path("mesh.vtk", None).unwrap(), PathBuf::from("mesh.vtk"));
+    }
+
+    #[test]
+    fn workflow_dir_expands_to_the_base_directory() {
+        let base = Path::new("/data/workflows");
+        assert_eq!(
+            resolve_path("${WORKFLOW_DIR}/results/out.csv", Some(base)).unwrap(),
+            PathBuf::from("/data/workflows/results/out.csv")
+        );
+        assert!(resolve_path("${WORKFLOW_DIR}/out.csv", None).is_err());
+    }
+
+    #[test]
+    fn environment_variables_expand_and_unset_ones_fail() {
+        std::env::set_var("VISTLE_PATHS_TEST_ROOT", "/scratch");
+        assert_eq!(expand_variables("${VISTLE_PATHS_TEST_ROOT}/run", None).unwrap(), "/scratch/run");
+        assert!(expand_variables("${VISTLE_PATHS_TEST_UNSET}/run", None).is_err());
+        assert!(expand_variables("${VISTLE_PATHS_TEST_ROOT/run", None).is_err());
+    }
+}
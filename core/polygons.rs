@@ -0,0 +1,269 @@
+//! Polygon meshes with faces of any number of vertices
+//!
+//! Faces are stored as in Vistle and VTK: the vertex indices of all faces
+//! in one connectivity list and an offset array with one more entry than
+//! there are faces, so face `i` uses `connectivity[offsets[i]..offsets[i + 1]]`.
+//! Renderers take triangles; `PolygonsView::triangulate` fans each face
+//! out from its first vertex, which is exact for convex faces.
+
+use ndarray::{Array1, Array2};
+
+use crate::core::{ObjectPayload, ObjectType, VistleObject};
+
+/// Polygon mesh
+#[derive(Debug, Clone, Copy)]
+pub struct PolygonsView<'a> {
+    coordinates: &'a Array2<f32>,
+    connectivity: &'a Array1<i32>,
+    offsets: &'a Array1<i32>,
+}
+
+impl<'a> PolygonsView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::Polygons { coordinates, connectivity, offsets } => Some(Self {
+                coordinates,
+                connectivity,
+                offsets,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Vertex coordinates, one row per vertex
+    pub fn coordinates(&self) -> &'a Array2<f32> {
+        self.coordinates
+    }
+
+    pub fn connectivity(&self) -> &'a Array1<i32> {
+        self.connectivity
+    }
+
+    pub fn offsets(&self) -> &'a Array1<i32> {
+        self.offsets
+    }
+
+    pub fn num_vertices(&self) -> usize {
+        self.coordinates.nrows()
+    }
+
+    pub fn num_faces(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    /// Vertex indices of face `i`
+    pub fn face(&self, i: usize) -> Option<&'a [i32]> {
+        let start = *self.offsets.get(i)? as usize;
+        let end = *self.offsets.get(i + 1)? as usize;
+        self.connectivity.as_slice()?.get(start..end)
+    }
+
+    /// Faces in order, as their vertex indices
+    pub fn faces(&self) -> impl Iterator<Item = &'a [i32]> + 'a {
+        let view = *self;
+        (0..self.num_faces()).filter_map(move |i| view.face(i))
+    }
+
+    /// Triangles of the faces, fanned out from each face's first vertex
+    ///
+    /// Concave faces get triangles outside them. The triangles share the
+    /// polygons' vertices, so vertex-mapped data stays valid.
+    pub fn triangulate(&self) -> Result<ObjectPayload, crate::Error> {
+        let mut triangles = Vec::new();
+        for i in 0..self.num_faces() {
+            let face = self.face(i).ok_or_else(|| crate::Error::Compute(format!(
+                "Polygons: face {} lies outside the connectivity of {} indices",
+                i,
+                self.connectivity.len()
+            )))?;
+            check_face(i, face.len())?;
+            triangles.extend(face.windows(2).skip(1).flat_map(|edge| [face[0], edge[0], edge[1]]));
+        }
+        Ok(ObjectPayload::Triangles {
+            coordinates: self.coordinates.clone(),
+            triangles: Array2::from_shape_vec((triangles.len() / 3, 3), triangles)
+                .expect("three indices per triangle"),
+        })
+    }
+}
+
+fn check_face(i: usize, vertices: usize) -> Result<(), crate::Error> {
+    if vertices < 3 {
+        return Err(crate::Error::Compute(format!(
+            "Polygons: face {} has {} vertices, a polygon needs at least 3",
+            i, vertices
+        )));
+    }
+    Ok(())
+}
+
+/// Builder for polygon meshes
+#[derive(Debug, Clone, Default)]
+pub struct PolygonsBuilder {
+    coordinates: Vec<[f32; 3]>,
+    connectivity: Vec<u32>,
+    offsets: Vec<usize>,
+}
+
+impl PolygonsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn coordinates(mut self, coordinates: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        self.coordinates.extend(coordinates);
+        self
+    }
+
+    /// Add a face with its vertex indices, in order around the face
+    pub fn face(mut self, indices: &[u32]) -> Self {
+        self.offsets.push(self.connectivity.len());
+        self.connectivity.extend_from_slice(indices);
+        self
+    }
+
+    pub fn build(mut self) -> Result<VistleObject, crate::Error> {
+        if let Some((i, p)) = self.coordinates.iter().enumerate().find(|(_, p)| p.iter().any(|c| !c.is_finite())) {
+            return Err(crate::Error::Compute(format!(
+                "Polygons: vertex {} has non-finite coordinates {:?}",
+                i, p
+            )));
+        }
+        self.offsets.push(self.connectivity.len());
+        for (i, bounds) in self.offsets.windows(2).enumerate() {
+            check_face(i, bounds[1] - bounds[0])?;
+        }
+        if let Some(&index) = self.connectivity.iter().find(|&&v| v as usize >= self.coordinates.len()) {
+            return Err(crate::Error::Compute(format!(
+                "Polygons: vertex index {} out of range for {} vertices",
+                index,
+                self.coordinates.len()
+            )));
+        }
+        if self.connectivity.len() > i32::MAX as usize {
+            return Err(crate::Error::Compute(format!(
+                "Polygons: {} vertex indices exceed the 32-bit offset range",
+                self.connectivity.len()
+            )));
+        }
+
+        let coordinates = Array2::from_shape_vec(
+            (self.coordinates.len(), 3),
+            self.coordinates.iter().flatten().copied().collect(),
+        )
+        .expect("row-major shape matches vertex count");
+        Ok(VistleObject::with_data(
+            ObjectType::Polygons,
+            ObjectPayload::Polygons {
+                coordinates,
+                connectivity: self.connectivity.iter().map(|&v| v as i32).collect(),
+                offsets: self.offsets.iter().map(|&o| o as i32).collect(),
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Object;
+    use ndarray::array;
+
+    /// A unit quad and a pentagon sharing its right edge
+    fn quad_and_pentagon() -> VistleObject {
+        PolygonsBuilder::new()
+            .coordinates([
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [2.5, 0.5, 0.0],
+                [2.0, 1.0, 0.0],
+            ])
+            .face(&[0, 1, 2, 3])
+            .face(&[1, 4, 5, 6, 2])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn faces_keep_their_vertex_counts() {
+        let mesh = quad_and_pentagon();
+        let view = mesh.as_polygons().unwrap();
+        assert_eq!(view.num_vertices(), 7);
+        assert_eq!(view.num_faces(), 2);
+        assert_eq!(view.offsets().to_vec(), [0, 4, 9]);
+        assert_eq!(view.faces().collect::<Vec<_>>(), [&[0, 1, 2, 3][..], &[1, 4, 5, 6, 2][..]]);
+        assert_eq!(view.face(2), None);
+    }
+
+    #[test]
+    fn faces_are_fanned_from_their_first_vertex() {
+        let mesh = quad_and_pentagon();
+        let ObjectPayload::Triangles { coordinates, triangles } = mesh.as_polygons().unwrap().triangulate().unwrap() else {
+            panic!("triangulation is not triangles");
+        };
+        assert_eq!(coordinates.nrows(), 7);
+        assert_eq!(triangles, array![[0, 1, 2], [0, 2, 3], [1, 4, 5], [1, 5, 6], [1, 6, 2]]);
+    }
+
+    #[test]
+    fn faces_need_three_vertices() {
+        let result = PolygonsBuilder::new()
+            .coordinates([[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+            .face(&[0, 1, 2])
+            .face(&[0, 1])
+            .build();
+        match result {
+            Err(crate::Error::Compute(message)) => assert!(message.contains("face 1 has 2 vertices"), "{}", message),
+            other => panic!("expected a compute error, got {:?}", other.map(|_| ())),
+        }
+
+        let line = ObjectPayload::Polygons {
+            coordinates: Array2::zeros((3, 3)),
+            connectivity: array![0, 1, 2, 0, 1],
+            offsets: array![0, 3, 5],
+        };
+        assert!(matches!(PolygonsView::new(&line).unwrap().triangulate(), Err(crate::Error::Compute(_))));
+    }
+
+    #[test]
+    fn offsets_past_the_connectivity_are_rejected() {
+        let broken = ObjectPayload::Polygons {
+            coordinates: Array2::zeros((3, 3)),
+            connectivity: array![0, 1, 2],
+            offsets: array![0, 3, 6],
+        };
+        let view = PolygonsView::new(&broken).unwrap();
+        assert_eq!(view.faces().count(), 1);
+        let message = view.triangulate().unwrap_err().to_string();
+        assert!(message.contains("face 1 lies outside"), "{}", message);
+    }
+
+    #[test]
+    fn builder_checks_vertices() {
+        let out_of_range = PolygonsBuilder::new()
+            .coordinates([[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+            .face(&[0, 1, 3])
+            .build();
+        assert!(out_of_range.unwrap_err().to_string().contains("vertex index 3 out of range"));
+
+        let non_finite = PolygonsBuilder::new()
+            .coordinates([[0.0; 3], [f32::NAN, 0.0, 0.0], [0.0, 1.0, 0.0]])
+            .face(&[0, 1, 2])
+            .build();
+        assert!(non_finite.unwrap_err().to_string().contains("vertex 1 has non-finite"));
+    }
+
+    #[test]
+    fn empty_meshes_have_no_faces() {
+        let empty = PolygonsBuilder::new().build().unwrap();
+        let view = empty.as_polygons().unwrap();
+        assert_eq!(view.num_faces(), 0);
+        let ObjectPayload::Triangles { triangles, .. } = view.triangulate().unwrap() else {
+            panic!("triangulation is not triangles");
+        };
+        assert_eq!(triangles.nrows(), 0);
+    }
+}
@@ -0,0 +1,255 @@
+//! Double-precision fields and their conversion to single precision
+//!
+//! Simulations often write f64 fields, and values spanning many orders of
+//! magnitude do not survive a conversion to f32: small values flush to
+//! zero, large ones overflow, and all lose digits. Readers keep such data
+//! in `VecScalarF64` and `VecVec3F64`, and analysis works on it as is.
+//! Converting to f32, for rendering or for modules that only take f32
+//! fields, is explicit: `to_f32` fails on any value it would change
+//! unless it is told that loss is acceptable.
+
+use nalgebra::Vector3;
+use ndarray::{Array1, Array2};
+
+use crate::core::{Object, ObjectPayload, VistleObject};
+
+/// Whether an f64 value survives the round trip through f32
+fn exact_in_f32(v: f64) -> bool {
+    v.is_nan() || v as f32 as f64 == v
+}
+
+impl ObjectPayload {
+    /// Whether the payload is a double-precision field
+    pub fn is_f64(&self) -> bool {
+        matches!(self, ObjectPayload::VecScalarF64 { .. } | ObjectPayload::VecVec3F64 { .. })
+    }
+
+    /// Single-precision copy of a field
+    ///
+    /// f32 fields are returned as they are. Unless `allow_lossy` is set, a
+    /// double-precision field with any value f32 cannot represent exactly
+    /// is an error naming the first such value.
+    pub fn to_f32(&self, allow_lossy: bool) -> Result<ObjectPayload, crate::Error> {
+        let inexact = match self {
+            ObjectPayload::VecScalar { .. } | ObjectPayload::VecVec3 { .. } => return Ok(self.clone()),
+            _ if allow_lossy => None,
+            ObjectPayload::VecScalarF64 { data } => data.iter()
+                .position(|&v| !exact_in_f32(v))
+                .map(|i| (i, data[i])),
+            ObjectPayload::VecVec3F64 { data } => data.indexed_iter()
+                .find(|(_, &v)| !exact_in_f32(v))
+                .map(|((row, _), &v)| (row, v)),
+            _ => None,
+        };
+        if let Some((index, value)) = inexact {
+            return Err(crate::Error::Compute(format!(
+                "Converting {} to f32 loses precision, e.g. value {} of element {}; allow lossy conversion to proceed",
+                self.kind(), value, index
+            )));
+        }
+        match self {
+            ObjectPayload::VecScalarF64 { data } => Ok(ObjectPayload::VecScalar { data: data.mapv(|v| v as f32) }),
+            ObjectPayload::VecVec3F64 { data } => Ok(ObjectPayload::VecVec3 { data: data.mapv(|v| v as f32) }),
+            _ => Err(crate::Error::WrongType {
+                expected: "scalar or vector field".to_string(),
+                found: self.kind().to_string(),
+            }),
+        }
+    }
+
+    /// Double-precision copy of a field; widening never loses precision
+    pub fn to_f64(&self) -> Option<ObjectPayload> {
+        match self {
+            ObjectPayload::VecScalar { data } => Some(ObjectPayload::VecScalarF64 { data: data.mapv(f64::from) }),
+            ObjectPayload::VecVec3 { data } => Some(ObjectPayload::VecVec3F64 { data: data.mapv(f64::from) }),
+            ObjectPayload::VecScalarF64 { .. } | ObjectPayload::VecVec3F64 { .. } => Some(self.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl VistleObject {
+    /// Copy of a field object in single precision, with the same metadata and attributes
+    ///
+    /// See `ObjectPayload::to_f32` for `allow_lossy`.
+    pub fn to_f32(&self, allow_lossy: bool) -> Result<VistleObject, crate::Error> {
+        let mut object = VistleObject::with_data(self.object_type(), self.data().to_f32(allow_lossy)?)
+            .with_meta(self.meta().clone());
+        for (key, value) in self.attributes() {
+            object.set_attribute(key.clone(), value.clone());
+        }
+        Ok(object)
+    }
+}
+
+/// One double-precision scalar per vertex or cell
+#[derive(Debug, Clone, Copy)]
+pub struct ScalarFieldF64View<'a> {
+    values: &'a Array1<f64>,
+}
+
+impl<'a> ScalarFieldF64View<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::VecScalarF64 { data } => Some(Self { values: data }),
+            _ => None,
+        }
+    }
+
+    pub fn values(&self) -> &'a Array1<f64> {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Smallest and largest finite value, None if there is none
+    pub fn range(&self) -> Option<(f64, f64)> {
+        self.values.iter()
+            .filter(|v| v.is_finite())
+            .fold(None, |range, &v| match range {
+                None => Some((v, v)),
+                Some((min, max)) => Some((min.min(v), max.max(v))),
+            })
+    }
+}
+
+/// One double-precision 3-vector per vertex or cell
+#[derive(Debug, Clone, Copy)]
+pub struct VectorFieldF64View<'a> {
+    values: &'a Array2<f64>,
+}
+
+impl<'a> VectorFieldF64View<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::VecVec3F64 { data } => Some(Self { values: data }),
+            _ => None,
+        }
+    }
+
+    /// Vectors, one row each
+    pub fn values(&self) -> &'a Array2<f64> {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.nrows()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.nrows() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Vector3<f64> {
+        Vector3::new(self.values[[index, 0]], self.values[[index, 1]], self.values[[index, 2]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ObjectType;
+    use crate::util::math::{compute_stats, compute_stats_f64, normalize, normalize_f64};
+    use ndarray::array;
+
+    /// Values from 1e-12 to 1e12, one per decade
+    fn wide_field() -> Array1<f64> {
+        (-12..=12).map(|e| 10f64.powi(e)).collect()
+    }
+
+    #[test]
+    fn wide_ranges_keep_their_extrema_in_double_precision() {
+        let payload = ObjectPayload::VecScalarF64 { data: wide_field() };
+        let view = ScalarFieldF64View::new(&payload).unwrap();
+        assert_eq!(view.range(), Some((1e-12, 1e12)));
+
+        let stats = compute_stats_f64(view.values());
+        assert_eq!((stats.min, stats.max), (1e-12, 1e12));
+
+        let single = compute_stats(&wide_field().mapv(|v| v as f32));
+        assert_ne!(f64::from(single.min), 1e-12);
+    }
+
+    #[test]
+    fn normalizing_in_double_precision_keeps_close_values_apart() {
+        let mut double = array![1e12, 1e12 + 1.0, 1e12 + 2.0];
+        normalize_f64(&mut double);
+        assert_eq!(double, array![0.0, 0.5, 1.0]);
+
+        let mut single = array![1e12, 1e12 + 1.0, 1e12 + 2.0].mapv(|v: f64| v as f32);
+        normalize(&mut single);
+        assert!(single.iter().all(|&v| v == 1e12_f64 as f32), "{:?}", single);
+    }
+
+    #[test]
+    fn inexact_conversions_need_consent() {
+        let payload = ObjectPayload::VecScalarF64 { data: wide_field() };
+        match payload.to_f32(false) {
+            Err(crate::Error::Compute(message)) => {
+                assert!(message.contains("element 0") && message.contains("allow lossy"), "{}", message)
+            }
+            other => panic!("expected a compute error, got {:?}", other.map(|p| p.kind())),
+        }
+
+        let ObjectPayload::VecScalar { data } = payload.to_f32(true).unwrap() else {
+            panic!("not a single-precision field");
+        };
+        assert_eq!(data.len(), 25);
+        assert_eq!(data[24], 1e12_f64 as f32);
+    }
+
+    #[test]
+    fn exact_values_convert_without_consent() {
+        let scalars = ObjectPayload::VecScalarF64 { data: array![0.5, -2.0, 1024.0, f64::NAN, f64::INFINITY] };
+        let ObjectPayload::VecScalar { data } = scalars.to_f32(false).unwrap() else {
+            panic!("not a single-precision field");
+        };
+        assert_eq!(data.slice(ndarray::s![..3]).to_vec(), [0.5, -2.0, 1024.0]);
+        assert!(data[3].is_nan() && data[4] == f32::INFINITY);
+
+        let vectors = ObjectPayload::VecVec3F64 { data: array![[1.0, 0.0, 0.0], [0.0, 0.1, 0.0]] };
+        let message = vectors.to_f32(false).unwrap_err().to_string();
+        assert!(message.contains("element 1"), "{}", message);
+        assert!(matches!(vectors.to_f32(true).unwrap(), ObjectPayload::VecVec3 { .. }));
+    }
+
+    #[test]
+    fn widening_round_trips() {
+        let single = ObjectPayload::VecVec3 { data: array![[0.1f32, 2.0, -3.5]] };
+        let double = single.to_f64().unwrap();
+        assert!(double.is_f64() && !single.is_f64());
+        let view = VectorFieldF64View::new(&double).unwrap();
+        assert_eq!(view.get(0), Vector3::new(f64::from(0.1f32), 2.0, -3.5));
+
+        let ObjectPayload::VecVec3 { data } = double.to_f32(false).unwrap() else {
+            panic!("not a single-precision field");
+        };
+        assert_eq!(data, array![[0.1f32, 2.0, -3.5]]);
+    }
+
+    #[test]
+    fn only_fields_convert() {
+        let points = ObjectPayload::Points { coordinates: Array2::zeros((2, 3)) };
+        assert!(matches!(points.to_f32(false), Err(crate::Error::WrongType { .. })));
+        assert!(points.to_f64().is_none());
+    }
+
+    #[test]
+    fn objects_keep_metadata_and_attributes() {
+        let mut object = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalarF64 { data: array![1.5, 2.5] });
+        object.meta_mut().timestep = 3;
+        object.set_attribute("species".to_string(), "pressure".to_string());
+
+        let single = object.to_f32(false).unwrap();
+        assert_eq!(single.meta().timestep, 3);
+        assert_eq!(single.attributes().get("species").map(String::as_str), Some("pressure"));
+        assert!(single.as_scalar_field_f64().is_none());
+        assert!(object.as_scalar_field_f64().is_some());
+    }
+}
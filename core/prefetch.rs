@@ -0,0 +1,336 @@
+//! Loading the next timesteps of a placeholder series in the background
+//!
+//! A consumer walking a timeseries of placeholders otherwise alternates
+//! between waiting for timestep N to load and processing it, leaving the
+//! disk idle half the time. A `SeriesResolver` hands out the objects of a
+//! series in order and, while the consumer works on one, resolves up to
+//! `PrefetchConfig::ahead` of the following ones on background tasks.
+//! Prefetching pauses while the realized objects would exceed the
+//! configured memory limit.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::core::{Object, ObjectRegistry};
+
+/// How far ahead placeholder series are loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrefetchConfig {
+    /// Objects loaded ahead of the one being processed; 0 disables prefetching
+    pub ahead: usize,
+    /// Bytes of realized placeholders above which no further loads are started
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            ahead: 2,
+            max_bytes: None,
+        }
+    }
+}
+
+impl PrefetchConfig {
+    pub fn disabled() -> Self {
+        Self {
+            ahead: 0,
+            max_bytes: None,
+        }
+    }
+
+    pub fn with_ahead(mut self, ahead: usize) -> Self {
+        self.ahead = ahead;
+        self
+    }
+
+    pub fn with_max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+}
+
+/// Counters of placeholder series resolved through `SeriesResolver`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrefetchStats {
+    /// Loads started ahead of their turn
+    pub prefetched: u64,
+    /// Placeholders that were loaded by the time they were asked for
+    pub hits: u64,
+    /// Placeholders the consumer had to wait for
+    pub misses: u64,
+    /// Times a load ahead was held back by the memory limit
+    pub deferred_for_memory: u64,
+    /// Load time that overlapped with processing instead of being waited for
+    pub time_saved_ms: f64,
+}
+
+impl PrefetchStats {
+    /// Counters accumulated since `earlier` was taken
+    pub fn since(&self, earlier: &PrefetchStats) -> PrefetchStats {
+        PrefetchStats {
+            prefetched: self.prefetched.saturating_sub(earlier.prefetched),
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+            deferred_for_memory: self.deferred_for_memory.saturating_sub(earlier.deferred_for_memory),
+            time_saved_ms: (self.time_saved_ms - earlier.time_saved_ms).max(0.0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefetched == 0 && self.hits == 0 && self.misses == 0
+    }
+}
+
+impl ObjectRegistry {
+    /// How far `SeriesResolver`s of this registry load ahead
+    pub fn set_prefetch(&self, config: PrefetchConfig) {
+        *self.lazy.prefetch.write() = config;
+    }
+
+    pub fn prefetch_config(&self) -> PrefetchConfig {
+        *self.lazy.prefetch.read()
+    }
+
+    /// Prefetch counters over the registry's lifetime
+    pub fn prefetch_stats(&self) -> PrefetchStats {
+        self.lazy.prefetch_stats.lock().clone()
+    }
+
+    fn record_prefetch(&self, update: impl FnOnce(&mut PrefetchStats)) {
+        update(&mut self.lazy.prefetch_stats.lock());
+    }
+}
+
+type Load = JoinHandle<Result<(Arc<dyn Object>, Duration), crate::Error>>;
+
+/// Objects of a series in order, with the following placeholders loading in the background
+///
+/// Loads still running when the resolver is dropped finish and stay in the
+/// registry, so a later resolve of the same objects finds them loaded.
+pub struct SeriesResolver {
+    registry: Option<Arc<ObjectRegistry>>,
+    objects: Vec<Arc<dyn Object>>,
+    next: usize,
+    /// Background loads by index into `objects`, in index order
+    loads: VecDeque<(usize, Load)>,
+    /// Size of the last loaded object, to estimate loads in flight
+    last_bytes: usize,
+}
+
+impl SeriesResolver {
+    /// Resolve `objects` in the order given through `registry`
+    pub fn new(registry: Option<Arc<ObjectRegistry>>, objects: Vec<Arc<dyn Object>>) -> Self {
+        Self {
+            registry,
+            objects,
+            next: 0,
+            loads: VecDeque::new(),
+            last_bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// The next object of the series with its data, or None after the last
+    pub async fn next(&mut self) -> Option<Result<Arc<dyn Object>, crate::Error>> {
+        let index = self.next;
+        let object = self.objects.get(index)?.clone();
+        self.next += 1;
+        if object.is_complete() {
+            self.start_loads();
+            return Some(Ok(object));
+        }
+        let Some(registry) = self.registry.clone() else {
+            return Some(Err(crate::Error::Config(format!("No object registry to resolve placeholder {}", object.id()))));
+        };
+
+        let load = match self.loads.front() {
+            Some((i, _)) if *i == index => self.loads.pop_front().map(|(_, load)| load),
+            _ => None,
+        };
+        // Start on the following objects before waiting for this one
+        self.start_loads();
+
+        let result = match load {
+            Some(load) => {
+                let ready = load.is_finished();
+                let waiting = Instant::now();
+                let result = load.await
+                    .unwrap_or_else(|e| Err(crate::Error::Module(format!("Loading {} failed: {}", object.id(), e))));
+                let waited = waiting.elapsed();
+                if let Ok((_, load_time)) = &result {
+                    registry.record_prefetch(|stats| {
+                        if ready { stats.hits += 1 } else { stats.misses += 1 }
+                        stats.time_saved_ms += load_time.saturating_sub(waited).as_secs_f64() * 1000.0;
+                    });
+                }
+                result.map(|(object, _)| object)
+            }
+            None => {
+                registry.record_prefetch(|stats| stats.misses += 1);
+                registry.resolve_object(&object).await
+            }
+        };
+        if let Ok(loaded) = &result {
+            self.last_bytes = loaded.as_data().map(|d| d.data.size_bytes()).unwrap_or(0);
+        }
+        Some(result)
+    }
+
+    /// Start background loads of the placeholders up to `ahead` past the current object
+    fn start_loads(&mut self) {
+        let Some(registry) = self.registry.clone() else {
+            return;
+        };
+        let config = registry.prefetch_config();
+        let end = (self.next + config.ahead).min(self.objects.len());
+        let mut from = self.loads.back().map_or(self.next, |(i, _)| i + 1).max(self.next);
+
+        while from < end {
+            let object = self.objects[from].clone();
+            from += 1;
+            if object.is_complete() {
+                continue;
+            }
+            if let Some(max_bytes) = config.max_bytes {
+                let in_flight = (self.loads.len() + 1) * self.last_bytes;
+                if registry.resolved_bytes() + in_flight > max_bytes {
+                    registry.record_prefetch(|stats| stats.deferred_for_memory += 1);
+                    break;
+                }
+            }
+
+            let registry = registry.clone();
+            registry.record_prefetch(|stats| stats.prefetched += 1);
+            let load = tokio::spawn(async move {
+                let started = Instant::now();
+                let object = registry.resolve_object(&object).await?;
+                Ok((object, started.elapsed()))
+            });
+            self.loads.push_back((from - 1, load));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::core::{Loader, ObjectLoader, ObjectPayload, ObjectType, VistleObject};
+
+    /// Reader taking `delay` per timestep, producing `count` values of the timestep
+    struct SlowReader {
+        delay: Duration,
+        count: usize,
+        loads: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl ObjectLoader for SlowReader {
+        async fn load(&self, loader: &Loader) -> Result<Arc<dyn Object>, crate::Error> {
+            self.loads.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(self.delay).await;
+            let data = ndarray::Array1::from_elem(self.count, loader.timestep as f32);
+            Ok(Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data })))
+        }
+    }
+
+    fn series(config: PrefetchConfig, delay: Duration, steps: i32) -> (Arc<ObjectRegistry>, Arc<SlowReader>, SeriesResolver) {
+        let registry = Arc::new(ObjectRegistry::new());
+        let reader = Arc::new(SlowReader { delay, count: 400, loads: AtomicUsize::new(0) });
+        registry.set_loader(reader.clone());
+        registry.set_prefetch(config);
+        let objects: Vec<Arc<dyn Object>> = (0..steps)
+            .map(|t| Arc::new(VistleObject::placeholder(ObjectType::Vec, Loader::new("Slow", "data").with_timestep(t))) as Arc<dyn Object>)
+            .collect();
+        let resolver = SeriesResolver::new(Some(registry.clone()), objects);
+        (registry, reader, resolver)
+    }
+
+    async fn consume(resolver: &mut SeriesResolver, compute: Duration) -> Vec<i32> {
+        let mut timesteps = Vec::new();
+        while let Some(object) = resolver.next().await {
+            let object = object.unwrap();
+            assert!(object.is_complete());
+            timesteps.push(object.meta().timestep);
+            tokio::time::sleep(compute).await;
+        }
+        timesteps
+    }
+
+    #[tokio::test]
+    async fn loading_overlaps_with_processing() {
+        let step = Duration::from_millis(100);
+        let (registry, reader, mut resolver) = series(PrefetchConfig::default(), step, 5);
+
+        let started = Instant::now();
+        assert_eq!(consume(&mut resolver, step).await, [0, 1, 2, 3, 4]);
+        let elapsed = started.elapsed();
+
+        // One load up front, then reads hide behind processing: about 6 steps, not 10
+        assert!(elapsed < step * 8, "took {:?}", elapsed);
+        assert_eq!(reader.loads.load(Ordering::Relaxed), 5);
+        let stats = registry.prefetch_stats();
+        assert_eq!(stats.prefetched, 4);
+        assert_eq!(stats.hits + stats.misses, 5);
+        assert!(stats.hits >= 3, "{:?}", stats);
+        assert!(stats.time_saved_ms > 200.0, "{:?}", stats);
+    }
+
+    #[tokio::test]
+    async fn without_prefetching_every_load_is_waited_for() {
+        let step = Duration::from_millis(20);
+        let (registry, _, mut resolver) = series(PrefetchConfig::disabled(), step, 3);
+
+        assert_eq!(consume(&mut resolver, Duration::ZERO).await, [0, 1, 2]);
+        let stats = registry.prefetch_stats();
+        assert_eq!((stats.prefetched, stats.hits, stats.misses), (0, 0, 3));
+    }
+
+    #[tokio::test]
+    async fn the_memory_limit_holds_back_loads_ahead() {
+        let config = PrefetchConfig::default().with_ahead(3).with_max_bytes(2000);
+        let (registry, reader, mut resolver) = series(config, Duration::from_millis(10), 5);
+
+        assert_eq!(consume(&mut resolver, Duration::from_millis(10)).await, [0, 1, 2, 3, 4]);
+        assert_eq!(reader.loads.load(Ordering::Relaxed), 5);
+        assert!(registry.prefetch_stats().deferred_for_memory > 0);
+    }
+
+    #[tokio::test]
+    async fn complete_objects_need_no_registry() {
+        let complete: Arc<dyn Object> = Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar {
+            data: ndarray::array![1.0],
+        }));
+        let placeholder: Arc<dyn Object> = Arc::new(VistleObject::placeholder(ObjectType::Vec, Loader::new("Slow", "data")));
+        let mut resolver = SeriesResolver::new(None, vec![complete.clone(), placeholder]);
+
+        assert_eq!(resolver.len(), 2);
+        assert_eq!(resolver.next().await.unwrap().unwrap().id(), complete.id());
+        assert!(resolver.next().await.unwrap().is_err());
+        assert!(resolver.next().await.is_none());
+    }
+
+    #[test]
+    fn stats_since_an_earlier_snapshot_count_the_difference() {
+        let earlier = PrefetchStats { prefetched: 2, hits: 1, misses: 1, deferred_for_memory: 0, time_saved_ms: 50.0 };
+        let later = PrefetchStats { prefetched: 5, hits: 3, misses: 2, deferred_for_memory: 1, time_saved_ms: 80.0 };
+        let since = later.since(&earlier);
+        assert_eq!(since, PrefetchStats { prefetched: 3, hits: 2, misses: 1, deferred_for_memory: 1, time_saved_ms: 30.0 });
+        assert!(PrefetchStats::default().is_empty());
+        assert!(!since.is_empty());
+    }
+}
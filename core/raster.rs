@@ -0,0 +1,543 @@
+//! Images: 2D rasters placed in the scene
+//!
+//! An image holds `height` rows of `width` pixels with `channels` values
+//! each, row 0 at the top as in image files. It covers the rectangle from
+//! `origin` to `origin + (width, height) * spacing` in the plane
+//! z = `origin[2]`; images in other planes, such as slices normal to x or y,
+//! carry their placement in the meta transform. 8-bit data is kept as read
+//! from files, i.e. sRGB-encoded for color images, while f32 data holds
+//! linear values or physical quantities to be colored through a colormap.
+//!
+//! A layered image holds any number of named single-channel bands of one
+//! raster, e.g. multispectral satellite data; `LayeredImageView::to_image`
+//! picks up to four of them as the channels of an image.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use nalgebra::{Matrix4, Vector3};
+use ndarray::{Array2, Array3};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Object, ObjectPayload, ObjectType, VistleObject};
+
+/// Pixel values of an image, indexed by row, column and channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ImageData {
+    U8(Array3<u8>),
+    F32(Array3<f32>),
+}
+
+impl ImageData {
+    /// Height, width and channels
+    pub fn shape(&self) -> [usize; 3] {
+        let shape = match self {
+            ImageData::U8(data) => data.shape(),
+            ImageData::F32(data) => data.shape(),
+        };
+        [shape[0], shape[1], shape[2]]
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            ImageData::U8(data) => data.len(),
+            ImageData::F32(data) => data.len() * std::mem::size_of::<f32>(),
+        }
+    }
+
+    /// Value of one channel of a pixel, as stored
+    pub fn value(&self, row: usize, col: usize, channel: usize) -> f32 {
+        match self {
+            ImageData::U8(data) => data[[row, col, channel]] as f32,
+            ImageData::F32(data) => data[[row, col, channel]],
+        }
+    }
+}
+
+/// One named channel of a layered image, indexed by row and column
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageBand {
+    pub name: String,
+    pub values: Array2<f32>,
+}
+
+impl ImageBand {
+    pub fn new(name: &str, values: Array2<f32>) -> Self {
+        Self {
+            name: name.to_string(),
+            values,
+        }
+    }
+}
+
+/// Corners of the rectangle an image covers: lower left, lower right, upper right, upper left
+pub(crate) fn corners(width: usize, height: usize, origin: [f32; 3], spacing: [f32; 2]) -> [Vector3<f32>; 4] {
+    let [x0, y0, z] = origin;
+    let (x1, y1) = (x0 + width as f32 * spacing[0], y0 + height as f32 * spacing[1]);
+    [
+        Vector3::new(x0, y0, z),
+        Vector3::new(x1, y0, z),
+        Vector3::new(x1, y1, z),
+        Vector3::new(x0, y1, z),
+    ]
+}
+
+/// Image of up to four channels
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a> {
+    width: usize,
+    height: usize,
+    channels: usize,
+    data: &'a ImageData,
+    origin: [f32; 3],
+    spacing: [f32; 2],
+}
+
+impl<'a> ImageView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::Image { width, height, channels, data, origin, spacing } => Some(Self {
+                width: *width,
+                height: *height,
+                channels: *channels,
+                data,
+                origin: *origin,
+                spacing: *spacing,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn data(&self) -> &'a ImageData {
+        self.data
+    }
+
+    pub fn origin(&self) -> [f32; 3] {
+        self.origin
+    }
+
+    pub fn spacing(&self) -> [f32; 2] {
+        self.spacing
+    }
+
+    /// Value of one channel of the pixel in column `col` of row `row`, as stored
+    pub fn value(&self, col: usize, row: usize, channel: usize) -> f32 {
+        self.data.value(row, col, channel)
+    }
+
+    /// Corners of the image in its own coordinates: lower left, lower right, upper right, upper left
+    pub fn corners(&self) -> [Vector3<f32>; 4] {
+        corners(self.width, self.height, self.origin, self.spacing)
+    }
+
+    /// Smallest and largest finite value of a channel, None if there is none
+    pub fn range(&self, channel: usize) -> Option<(f32, f32)> {
+        (0..self.height)
+            .flat_map(|row| (0..self.width).map(move |col| (row, col)))
+            .map(|(row, col)| self.data.value(row, col, channel))
+            .filter(|v| v.is_finite())
+            .fold(None, |range, v| match range {
+                None => Some((v, v)),
+                Some((min, max)) => Some((min.min(v), max.max(v))),
+            })
+    }
+
+    /// The pixels as an image of the `image` crate, value for value
+    ///
+    /// 8-bit images of any channel count convert; f32 images only with
+    /// three or four channels, as single-channel data is colored first.
+    pub fn to_dynamic_image(&self) -> Result<image::DynamicImage, crate::Error> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let mismatch = || crate::Error::Compute("Image data does not match its size".to_string());
+        Ok(match (self.data, self.channels) {
+            (ImageData::U8(data), channels) => {
+                let raw: Vec<u8> = data.iter().copied().collect();
+                match channels {
+                    1 => image::DynamicImage::ImageLuma8(image::ImageBuffer::from_raw(width, height, raw).ok_or_else(mismatch)?),
+                    2 => image::DynamicImage::ImageLumaA8(image::ImageBuffer::from_raw(width, height, raw).ok_or_else(mismatch)?),
+                    3 => image::DynamicImage::ImageRgb8(image::ImageBuffer::from_raw(width, height, raw).ok_or_else(mismatch)?),
+                    _ => image::DynamicImage::ImageRgba8(image::ImageBuffer::from_raw(width, height, raw).ok_or_else(mismatch)?),
+                }
+            }
+            (ImageData::F32(data), 3) => image::DynamicImage::ImageRgb32F(
+                image::ImageBuffer::from_raw(width, height, data.iter().copied().collect()).ok_or_else(mismatch)?,
+            ),
+            (ImageData::F32(data), 4) => image::DynamicImage::ImageRgba32F(
+                image::ImageBuffer::from_raw(width, height, data.iter().copied().collect()).ok_or_else(mismatch)?,
+            ),
+            (ImageData::F32(_), channels) => {
+                return Err(crate::Error::Compute(format!(
+                    "A {}-channel f32 image has no file format of its own; apply a colormap first",
+                    channels
+                )))
+            }
+        })
+    }
+
+    /// Write the pixels to a file, in the format its extension names
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let path = path.as_ref();
+        self.to_dynamic_image()?
+            .save(path)
+            .map_err(|e| crate::Error::Compute(format!("Cannot write image {}: {}", path.display(), e)))
+    }
+}
+
+/// Named bands of one raster
+#[derive(Debug, Clone, Copy)]
+pub struct LayeredImageView<'a> {
+    width: usize,
+    height: usize,
+    bands: &'a [ImageBand],
+    origin: [f32; 3],
+    spacing: [f32; 2],
+}
+
+impl<'a> LayeredImageView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::LayeredImage { width, height, bands, origin, spacing } => Some(Self {
+                width: *width,
+                height: *height,
+                bands,
+                origin: *origin,
+                spacing: *spacing,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bands(&self) -> &'a [ImageBand] {
+        self.bands
+    }
+
+    pub fn band(&self, name: &str) -> Option<&'a ImageBand> {
+        self.bands.iter().find(|b| b.name == name)
+    }
+
+    /// f32 image with the named bands as its channels, in order
+    pub fn to_image(&self, names: &[&str]) -> Result<ObjectPayload, crate::Error> {
+        if names.is_empty() || names.len() > 4 {
+            return Err(crate::Error::Compute(format!("An image takes 1 to 4 bands, not {}", names.len())));
+        }
+        let bands = names.iter()
+            .map(|name| self.band(name).ok_or_else(|| crate::Error::Compute(format!(
+                "Layered image has no band {}, only {}",
+                name,
+                self.bands.iter().map(|b| b.name.as_str()).collect::<Vec<_>>().join(", ")
+            ))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let data = Array3::from_shape_fn((self.height, self.width, bands.len()), |(row, col, c)| {
+            bands[c].values[[row, col]]
+        });
+        Ok(ObjectPayload::Image {
+            width: self.width,
+            height: self.height,
+            channels: bands.len(),
+            data: ImageData::F32(data),
+            origin: self.origin,
+            spacing: self.spacing,
+        })
+    }
+}
+
+fn check_placement(origin: [f32; 3], spacing: [f32; 2]) -> Result<(), crate::Error> {
+    if origin.iter().any(|o| !o.is_finite()) || spacing.iter().any(|s| !s.is_finite() || *s <= 0.0) {
+        return Err(crate::Error::Compute(format!(
+            "Image: origin {:?} must be finite and spacing {:?} positive",
+            origin, spacing
+        )));
+    }
+    Ok(())
+}
+
+impl VistleObject {
+    /// Image of the given pixels, with width, height and channels taken from their shape
+    pub fn image(data: ImageData, origin: [f32; 3], spacing: [f32; 2]) -> Result<Self, crate::Error> {
+        let [height, width, channels] = data.shape();
+        if !(1..=4).contains(&channels) {
+            return Err(crate::Error::Compute(format!("Image: {} channels, expected 1 to 4", channels)));
+        }
+        check_placement(origin, spacing)?;
+        Ok(Self::with_data(
+            ObjectType::Image,
+            ObjectPayload::Image { width, height, channels, data, origin, spacing },
+        ))
+    }
+
+    /// Layered image of bands of equal size and distinct names
+    pub fn layered_image(bands: Vec<ImageBand>, origin: [f32; 3], spacing: [f32; 2]) -> Result<Self, crate::Error> {
+        let Some(first) = bands.first() else {
+            return Err(crate::Error::Compute("LayeredImage: needs at least one band".to_string()));
+        };
+        let (height, width) = first.values.dim();
+        if let Some(band) = bands.iter().find(|b| b.values.dim() != (height, width)) {
+            return Err(crate::Error::Compute(format!(
+                "LayeredImage: band {} is {:?}, band {} is {:?}",
+                band.name, band.values.dim(), first.name, (height, width)
+            )));
+        }
+        let mut names = HashSet::new();
+        if let Some(band) = bands.iter().find(|b| !names.insert(b.name.as_str())) {
+            return Err(crate::Error::Compute(format!("LayeredImage: band {} appears twice", band.name)));
+        }
+        check_placement(origin, spacing)?;
+        Ok(Self::with_data(
+            ObjectType::LayeredImage,
+            ObjectPayload::LayeredImage { width, height, bands, origin, spacing },
+        ))
+    }
+
+    /// Single-channel f32 image of a planar uniform grid, one pixel per grid point
+    ///
+    /// The grid must have a single point along one axis; the other two
+    /// become columns and rows, lower axis first. Pixels are centered on
+    /// the grid points, so exporting the image reproduces the values
+    /// exactly. Slices normal to x or y get a meta transform placing the
+    /// image in their plane; metadata and attributes are kept.
+    pub fn image_from_slice(grid: &dyn Object) -> Result<Self, crate::Error> {
+        let view = grid.as_uniform_grid().ok_or_else(|| crate::Error::wrong_type("uniform grid", grid))?;
+        let dims = view.dims;
+        let Some(flat) = (0..3).rev().find(|&a| dims[a] == 1) else {
+            return Err(crate::Error::Compute(format!("A grid of {:?} points is not a slice", dims)));
+        };
+        let [u, v] = match flat {
+            0 => [1, 2],
+            1 => [0, 2],
+            _ => [0, 1],
+        };
+        let (origin, spacing) = (view.origin, view.spacing);
+        let (width, height) = (dims[u], dims[v]);
+        let values = view.values();
+        let data = Array3::from_shape_fn((height, width, 1), |(row, col, _)| {
+            let mut ijk = [0; 3];
+            ijk[u] = col;
+            ijk[v] = height - 1 - row;
+            values[(ijk[2] * dims[1] + ijk[1]) * dims[0] + ijk[0]]
+        });
+
+        let image_origin = [
+            origin[u] - spacing[u] * 0.5,
+            origin[v] - spacing[v] * 0.5,
+            if flat == 2 { origin[2] } else { 0.0 },
+        ];
+        let mut image = Self::image(ImageData::F32(data), image_origin, [spacing[u], spacing[v]])?;
+        let mut meta = grid.meta().clone();
+        if flat != 2 {
+            // Image x, y and z onto the grid's u, v and flat axes, at the slice position
+            let mut placement = Matrix4::zeros();
+            placement[(u, 0)] = 1.0;
+            placement[(v, 1)] = 1.0;
+            placement[(flat, 2)] = 1.0;
+            placement[(flat, 3)] = origin[flat];
+            placement[(3, 3)] = 1.0;
+            meta.transform *= placement;
+        }
+        image = image.with_meta(meta);
+        for (key, value) in grid.attributes() {
+            image.set_attribute(key.clone(), value.clone());
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use ndarray::Array1;
+
+    use crate::core::{ShmConfig, SharedArena};
+
+    fn rgb_image() -> VistleObject {
+        // Two rows of three pixels, red increasing along a row, green along a column
+        let data = Array3::from_shape_fn((2, 3, 3), |(row, col, c)| match c {
+            0 => col as u8 * 100,
+            1 => row as u8 * 200,
+            _ => 7,
+        });
+        VistleObject::image(ImageData::U8(data), [1.0, 2.0, 3.0], [0.5, 1.0]).unwrap()
+    }
+
+    #[test]
+    fn images_take_their_size_from_the_data() {
+        let image = rgb_image();
+        let view = image.as_image().unwrap();
+        assert_eq!((view.width(), view.height(), view.channels()), (3, 2, 3));
+        assert_eq!(view.data().shape(), [2, 3, 3]);
+        assert_eq!(view.data().size_bytes(), 18);
+        assert_eq!(view.value(2, 0, 0), 200.0);
+        assert_eq!(view.value(0, 1, 1), 200.0);
+        assert_eq!(view.range(0), Some((0.0, 200.0)));
+    }
+
+    #[test]
+    fn images_cover_their_rectangle() {
+        let image = rgb_image();
+        let [lower_left, lower_right, upper_right, upper_left] = image.as_image().unwrap().corners();
+        assert_eq!(lower_left, Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(lower_right, Vector3::new(2.5, 2.0, 3.0));
+        assert_eq!(upper_right, Vector3::new(2.5, 4.0, 3.0));
+        assert_eq!(upper_left, Vector3::new(1.0, 4.0, 3.0));
+        assert_eq!(image.data().bounds(), Some((lower_left, upper_right)));
+    }
+
+    #[test]
+    fn channel_layouts_map_to_image_formats() {
+        use image::ColorType;
+        let color_type = |channels: usize, data: ImageData| {
+            let image = VistleObject::image(data, [0.0; 3], [1.0; 2]).unwrap();
+            let view = image.as_image().unwrap();
+            assert_eq!(view.channels(), channels);
+            view.to_dynamic_image().map(|i| i.color())
+        };
+        let u8s = |c| ImageData::U8(Array3::zeros((2, 2, c)));
+        let f32s = |c| ImageData::F32(Array3::zeros((2, 2, c)));
+        assert_eq!(color_type(1, u8s(1)).unwrap(), ColorType::L8);
+        assert_eq!(color_type(2, u8s(2)).unwrap(), ColorType::La8);
+        assert_eq!(color_type(3, u8s(3)).unwrap(), ColorType::Rgb8);
+        assert_eq!(color_type(4, u8s(4)).unwrap(), ColorType::Rgba8);
+        assert_eq!(color_type(3, f32s(3)).unwrap(), ColorType::Rgb32F);
+        assert_eq!(color_type(4, f32s(4)).unwrap(), ColorType::Rgba32F);
+        let message = color_type(1, f32s(1)).unwrap_err().to_string();
+        assert!(message.contains("apply a colormap"), "{}", message);
+
+        assert!(VistleObject::image(u8s(5), [0.0; 3], [1.0; 2]).is_err());
+        assert!(VistleObject::image(u8s(0), [0.0; 3], [1.0; 2]).is_err());
+    }
+
+    #[test]
+    fn placement_must_be_finite_and_positive() {
+        let data = || ImageData::U8(Array3::zeros((1, 1, 1)));
+        assert!(VistleObject::image(data(), [0.0; 3], [0.0, 1.0]).is_err());
+        assert!(VistleObject::image(data(), [f32::NAN, 0.0, 0.0], [1.0; 2]).is_err());
+        assert!(VistleObject::image(data(), [0.0; 3], [1.0, -1.0]).is_err());
+    }
+
+    #[test]
+    fn saved_images_read_back_pixel_for_pixel() {
+        let image = rgb_image();
+        let path = std::env::temp_dir().join(format!("vistle_raster_{}.png", uuid::Uuid::new_v4().simple()));
+        image.as_image().unwrap().save(&path).unwrap();
+
+        let read = image::open(&path).unwrap().to_rgb8();
+        assert_eq!(read.dimensions(), (3, 2));
+        assert_eq!(read.get_pixel(2, 0).0, [200, 0, 7]);
+        assert_eq!(read.get_pixel(1, 1).0, [100, 200, 7]);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn images_round_trip_through_shared_memory() {
+        let arena = SharedArena::new(ShmConfig {
+            size: 1 << 20,
+            name: format!("vistle_test_{}", uuid::Uuid::new_v4().simple()),
+            ..ShmConfig::default()
+        })
+        .unwrap();
+        let image: Arc<dyn Object> = Arc::new(rgb_image());
+        let id = arena.store_object(image.clone()).unwrap();
+
+        let stored = arena.get_object(id).unwrap().expect("image was not stored");
+        assert_eq!(stored.object_type(), ObjectType::Image);
+        let (original, stored) = (image.as_image().unwrap(), stored.as_image().unwrap());
+        assert_eq!((stored.width(), stored.height(), stored.channels()), (3, 2, 3));
+        assert_eq!((stored.origin(), stored.spacing()), (original.origin(), original.spacing()));
+        let (ImageData::U8(original), ImageData::U8(stored)) = (original.data(), stored.data()) else {
+            panic!("8-bit data did not stay 8-bit");
+        };
+        assert_eq!(stored, original);
+    }
+
+    #[test]
+    fn layered_images_pick_bands_as_channels() {
+        let band = |name, offset: f32| ImageBand::new(name, Array2::from_shape_fn((2, 3), |(r, c)| offset + (r * 3 + c) as f32));
+        let layered = VistleObject::layered_image(
+            vec![band("red", 0.0), band("nir", 100.0), band("green", 200.0)],
+            [0.0; 3],
+            [30.0, 30.0],
+        )
+        .unwrap();
+        let view = layered.as_layered_image().unwrap();
+        assert_eq!((view.width(), view.height(), view.bands().len()), (3, 2, 3));
+
+        let picked = view.to_image(&["nir", "red"]).unwrap();
+        let image = ImageView::new(&picked).unwrap();
+        assert_eq!((image.width(), image.height(), image.channels()), (3, 2, 2));
+        assert_eq!((image.value(1, 1, 0), image.value(1, 1, 1)), (104.0, 4.0));
+        assert_eq!(image.spacing(), [30.0, 30.0]);
+
+        let message = view.to_image(&["blue"]).unwrap_err().to_string();
+        assert!(message.contains("no band blue, only red, nir, green"), "{}", message);
+        assert!(view.to_image(&[]).is_err());
+        assert!(view.to_image(&["red"; 5]).is_err());
+    }
+
+    #[test]
+    fn layered_bands_must_match() {
+        let square = ImageBand::new("a", Array2::zeros((2, 2)));
+        let wide = ImageBand::new("b", Array2::zeros((2, 3)));
+        assert!(VistleObject::layered_image(vec![square.clone(), wide], [0.0; 3], [1.0; 2]).is_err());
+        let message = VistleObject::layered_image(vec![square.clone(), square], [0.0; 3], [1.0; 2])
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("band a appears twice"), "{}", message);
+        assert!(VistleObject::layered_image(Vec::new(), [0.0; 3], [1.0; 2]).is_err());
+    }
+
+    #[test]
+    fn z_slices_become_images_with_the_top_row_first() {
+        let values: Array1<f32> = (0..6).map(|p| (p % 3 + 10 * (p / 3)) as f32).collect();
+        let mut grid = VistleObject::uniform_grid([3, 2, 1], [0.0, 0.0, 4.0], [2.0, 1.0, 1.0], values).unwrap();
+        grid.set_attribute("species".to_string(), "temperature".to_string());
+
+        let image = VistleObject::image_from_slice(&grid).unwrap();
+        let view = image.as_image().unwrap();
+        assert_eq!((view.width(), view.height(), view.channels()), (3, 2, 1));
+        let row = |r| (0..3).map(|c| view.value(c, r, 0)).collect::<Vec<_>>();
+        assert_eq!((row(0), row(1)), (vec![10.0, 11.0, 12.0], vec![0.0, 1.0, 2.0]));
+        assert_eq!(view.origin(), [-1.0, -0.5, 4.0]);
+        assert_eq!(view.spacing(), [2.0, 1.0]);
+        assert_eq!(image.attributes().get("species").map(String::as_str), Some("temperature"));
+        assert_eq!(image.meta().transform, Matrix4::identity());
+    }
+
+    #[test]
+    fn x_slices_are_placed_in_their_plane() {
+        let grid = VistleObject::uniform_grid([1, 3, 2], [5.0, 0.0, 0.0], [1.0; 3], Array1::zeros(6)).unwrap();
+        let image = VistleObject::image_from_slice(&grid).unwrap();
+        let view = image.as_image().unwrap();
+        assert_eq!((view.width(), view.height()), (3, 2));
+
+        let corner = image.meta().transform * view.corners()[0].push(1.0);
+        assert_eq!(corner.xyz(), Vector3::new(5.0, -0.5, -0.5));
+    }
+
+    #[test]
+    fn only_flat_grids_are_slices() {
+        let volume = VistleObject::uniform_grid([2, 2, 2], [0.0; 3], [1.0; 3], Array1::zeros(8)).unwrap();
+        assert!(matches!(VistleObject::image_from_slice(&volume), Err(crate::Error::Compute(_))));
+        assert!(matches!(VistleObject::image_from_slice(&rgb_image()), Err(crate::Error::WrongType { .. })));
+    }
+}
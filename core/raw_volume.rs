@@ -0,0 +1,403 @@
+//! Memory-mapped raw volume files
+//!
+//! Tomography and radiology volumes come as headerless bricks of values,
+//! often tens of gigabytes. A `RawVolume` maps such a file read-only
+//! instead of reading it, so only the pages a query touches are ever loaded:
+//! a region copies one x-run of values per row it covers, and a preview
+//! reads every n-th value along each axis.
+//!
+//! The mapping is owned by the `RawVolume` and shared through `Arc`; the
+//! file is not reopened per query. Borrowed `RawVolumeView`s cannot outlive
+//! the volume they come from, so raw bytes are never reachable after the
+//! mapping is gone. Values are decoded byte-wise with the file's endianness,
+//! so no alignment of the mapping is assumed.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use memmap2::Mmap;
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{ObjectPayload, ObjectType, VistleObject};
+
+/// Type of the values stored in a raw volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RawDType {
+    U8,
+    U16,
+    F32,
+}
+
+impl RawDType {
+    pub fn parse(text: &str) -> Result<Self, crate::Error> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "u8" | "uint8" => Ok(RawDType::U8),
+            "u16" | "uint16" => Ok(RawDType::U16),
+            "f32" | "float" | "float32" => Ok(RawDType::F32),
+            other => Err(crate::Error::Config(format!("Unknown raw data type '{}', expected u8, u16 or f32", other))),
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            RawDType::U8 => 1,
+            RawDType::U16 => 2,
+            RawDType::F32 => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    pub fn parse(text: &str) -> Result<Self, crate::Error> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "little" | "le" => Ok(Endianness::Little),
+            "big" | "be" => Ok(Endianness::Big),
+            other => Err(crate::Error::Config(format!("Unknown endianness '{}', expected little or big", other))),
+        }
+    }
+}
+
+/// How the values of a raw volume are laid out in its file, x fastest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawLayout {
+    pub dims: [usize; 3],
+    pub dtype: RawDType,
+    pub endianness: Endianness,
+    /// Bytes before the first value
+    pub header_bytes: usize,
+}
+
+impl RawLayout {
+    pub fn num_values(&self) -> usize {
+        self.dims.iter().product()
+    }
+
+    /// Bytes of the file the layout describes, including the header
+    pub fn file_size(&self) -> Option<usize> {
+        self.dims.iter()
+            .try_fold(self.dtype.size(), |bytes, &d| bytes.checked_mul(d))?
+            .checked_add(self.header_bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match (self.dtype, self.endianness) {
+            (RawDType::U8, _) => bytes[0] as f32,
+            (RawDType::U16, Endianness::Little) => u16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+            (RawDType::U16, Endianness::Big) => u16::from_be_bytes([bytes[0], bytes[1]]) as f32,
+            (RawDType::F32, Endianness::Little) => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            (RawDType::F32, Endianness::Big) => f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Box of points `[offset, offset + extent)` of a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeRegion {
+    pub offset: [usize; 3],
+    pub extent: [usize; 3],
+}
+
+impl VolumeRegion {
+    /// The whole of a volume with these dims
+    pub fn all(dims: [usize; 3]) -> Self {
+        Self {
+            offset: [0; 3],
+            extent: dims,
+        }
+    }
+
+    /// Region from offset and extent; extents of 0 reach to the end of the volume
+    pub fn clamped(offset: [usize; 3], extent: [usize; 3], dims: [usize; 3]) -> Result<Self, crate::Error> {
+        let mut region = Self { offset, extent };
+        for a in 0..3 {
+            if offset[a] >= dims[a] {
+                return Err(crate::Error::Config(format!(
+                    "Region offset {:?} lies outside the volume of {:?} points",
+                    offset, dims
+                )));
+            }
+            let rest = dims[a] - offset[a];
+            region.extent[a] = if extent[a] == 0 { rest } else { extent[a].min(rest) };
+        }
+        Ok(region)
+    }
+
+    pub fn num_values(&self) -> usize {
+        self.extent.iter().product()
+    }
+}
+
+/// A raw volume file mapped into memory
+#[derive(Debug)]
+pub struct RawVolume {
+    path: PathBuf,
+    layout: RawLayout,
+    mapping: Mmap,
+}
+
+impl RawVolume {
+    /// Map a file, checking it is large enough for the layout
+    pub fn open(path: &Path, layout: RawLayout) -> Result<Arc<Self>, crate::Error> {
+        if layout.dims.contains(&0) {
+            return Err(crate::Error::Config(format!("Raw volume dims {:?} must all be positive", layout.dims)));
+        }
+        let needed = layout.file_size().ok_or_else(|| crate::Error::Config(format!(
+            "Raw volume dims {:?} are too large to address",
+            layout.dims
+        )))?;
+        let file = std::fs::File::open(path)
+            .map_err(|e| crate::Error::Config(format!("Cannot open {}: {}", path.display(), e)))?;
+        let size = file.metadata()?.len();
+        if size < needed as u64 {
+            return Err(crate::Error::Config(format!(
+                "{} has {} bytes, the layout {:?} needs {}",
+                path.display(), size, layout, needed
+            )));
+        }
+        // Safety: the mapping is read-only and private to this volume, and
+        // values are copied out rather than handed out as references. If
+        // another process truncates the file while it is mapped, reads of
+        // the lost pages fault; raw volumes are inputs and not rewritten in place.
+        let mapping = unsafe { Mmap::map(&file)? };
+        tracing::debug!("Mapped raw volume {} of {:?} ({} bytes)", path.display(), layout.dims, needed);
+        Ok(Arc::new(Self {
+            path: path.to_path_buf(),
+            layout,
+            mapping,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn layout(&self) -> &RawLayout {
+        &self.layout
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.layout.dims
+    }
+
+    /// Borrowed access to the mapped values
+    pub fn view(&self) -> RawVolumeView<'_> {
+        let start = self.layout.header_bytes;
+        let end = start + self.layout.num_values() * self.layout.dtype.size();
+        RawVolumeView {
+            layout: &self.layout,
+            bytes: &self.mapping[start..end],
+        }
+    }
+}
+
+/// Values of a mapped volume, valid as long as the `RawVolume` is
+#[derive(Debug, Clone, Copy)]
+pub struct RawVolumeView<'a> {
+    layout: &'a RawLayout,
+    bytes: &'a [u8],
+}
+
+impl<'a> RawVolumeView<'a> {
+    fn index(&self, [i, j, k]: [usize; 3]) -> usize {
+        let [nx, ny, _] = self.layout.dims;
+        (k * ny + j) * nx + i
+    }
+
+    /// Value at a point; reads a single page
+    pub fn value(&self, point: [usize; 3]) -> Option<f32> {
+        if (0..3).any(|a| point[a] >= self.layout.dims[a]) {
+            return None;
+        }
+        let size = self.layout.dtype.size();
+        let start = self.index(point) * size;
+        Some(self.layout.decode(&self.bytes[start..start + size]))
+    }
+
+    /// Values of a region, x fastest, converted to f32
+    ///
+    /// Only the x-runs of the region are read, so pages of the file outside
+    /// it are not touched. With a stride above 1 every n-th point along each
+    /// axis is taken, for previews.
+    pub fn read_region(&self, region: &VolumeRegion, stride: usize) -> Vec<f32> {
+        let stride = stride.max(1);
+        let size = self.layout.dtype.size();
+        let dims = downsampled_dims(region.extent, stride);
+        let mut values = Vec::with_capacity(dims.iter().product());
+        for k in (0..region.extent[2]).step_by(stride) {
+            for j in (0..region.extent[1]).step_by(stride) {
+                let first = self.index([region.offset[0], region.offset[1] + j, region.offset[2] + k]) * size;
+                let run = &self.bytes[first..first + region.extent[0] * size];
+                values.extend(run.chunks_exact(size).step_by(stride).map(|bytes| self.layout.decode(bytes)));
+            }
+        }
+        values
+    }
+}
+
+/// Points along each axis when every `stride`-th point of `extent` is kept
+pub fn downsampled_dims(extent: [usize; 3], stride: usize) -> [usize; 3] {
+    extent.map(|e| e.div_ceil(stride.max(1)))
+}
+
+/// Uniform grid of a region of a volume, every `stride`-th point along each axis
+///
+/// `origin` and `spacing` are those of the whole volume; the grid's are
+/// shifted to the region and scaled by the stride.
+pub fn raw_volume_grid(
+    volume: &RawVolume,
+    region: &VolumeRegion,
+    stride: usize,
+    origin: [f32; 3],
+    spacing: [f32; 3],
+) -> VistleObject {
+    let stride = stride.max(1);
+    let values = volume.view().read_region(region, stride);
+    VistleObject::with_data(ObjectType::UniformGrid, ObjectPayload::UniformGrid {
+        dims: downsampled_dims(region.extent, stride),
+        origin: std::array::from_fn(|a| origin[a] + region.offset[a] as f32 * spacing[a]),
+        spacing: spacing.map(|s| s * stride as f32),
+        values: Array1::from(values),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Point value i + 10 j + 100 k
+    fn expected([i, j, k]: [usize; 3]) -> f32 {
+        (i + 10 * j + 100 * k) as f32
+    }
+
+    fn layout(dtype: RawDType, endianness: Endianness) -> RawLayout {
+        RawLayout {
+            dims: [4, 3, 2],
+            dtype,
+            endianness,
+            header_bytes: 16,
+        }
+    }
+
+    /// File of `layout` after a header of 0xff bytes
+    fn write_volume(layout: &RawLayout) -> PathBuf {
+        let mut bytes = vec![0xff; layout.header_bytes];
+        let [nx, ny, nz] = layout.dims;
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    let value = expected([i, j, k]);
+                    match (layout.dtype, layout.endianness) {
+                        (RawDType::U8, _) => bytes.push(value as u8),
+                        (RawDType::U16, Endianness::Little) => bytes.extend((value as u16).to_le_bytes()),
+                        (RawDType::U16, Endianness::Big) => bytes.extend((value as u16).to_be_bytes()),
+                        (RawDType::F32, Endianness::Little) => bytes.extend(value.to_le_bytes()),
+                        (RawDType::F32, Endianness::Big) => bytes.extend(value.to_be_bytes()),
+                    }
+                }
+            }
+        }
+        let path = std::env::temp_dir().join(format!("vistle_raw_{}.raw", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn values_decode_in_every_type_and_byte_order() {
+        for (dtype, endianness) in [
+            (RawDType::U8, Endianness::Little),
+            (RawDType::U16, Endianness::Little),
+            (RawDType::U16, Endianness::Big),
+            (RawDType::F32, Endianness::Little),
+            (RawDType::F32, Endianness::Big),
+        ] {
+            let layout = layout(dtype, endianness);
+            let path = write_volume(&layout);
+            let volume = RawVolume::open(&path, layout).unwrap();
+            let view = volume.view();
+            for point in [[0, 0, 0], [3, 2, 1], [1, 2, 0]] {
+                assert_eq!(view.value(point), Some(expected(point)), "{:?} {:?} at {:?}", dtype, endianness, point);
+            }
+            assert_eq!(view.value([4, 0, 0]), None);
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    #[test]
+    fn regions_read_x_runs_in_order() {
+        let layout = layout(RawDType::U16, Endianness::Big);
+        let path = write_volume(&layout);
+        let volume = RawVolume::open(&path, layout).unwrap();
+
+        let region = VolumeRegion { offset: [1, 1, 0], extent: [2, 2, 2] };
+        assert_eq!(volume.view().read_region(&region, 1), [11.0, 12.0, 21.0, 22.0, 111.0, 112.0, 121.0, 122.0]);
+
+        let all = VolumeRegion::all(volume.dims());
+        assert_eq!(volume.view().read_region(&all, 1).len(), all.num_values());
+        assert_eq!(volume.view().read_region(&all, 2), [0.0, 2.0, 20.0, 22.0]);
+        assert_eq!(downsampled_dims(all.extent, 2), [2, 2, 1]);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn grids_are_placed_at_their_region() {
+        let layout = layout(RawDType::F32, Endianness::Little);
+        let path = write_volume(&layout);
+        let volume = RawVolume::open(&path, layout).unwrap();
+        let region = VolumeRegion::clamped([2, 0, 1], [0, 0, 0], volume.dims()).unwrap();
+
+        let grid = raw_volume_grid(&volume, &region, 2, [10.0, 0.0, 0.0], [0.5, 1.0, 2.0]);
+        let ObjectPayload::UniformGrid { dims, origin, spacing, values } = grid.data() else {
+            panic!("not a uniform grid");
+        };
+        assert_eq!(*dims, [1, 2, 1]);
+        assert_eq!(*origin, [11.0, 0.0, 2.0]);
+        assert_eq!(*spacing, [1.0, 2.0, 4.0]);
+        assert_eq!(values.to_vec(), [102.0, 122.0]);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn regions_are_clamped_to_the_volume() {
+        let dims = [4, 3, 2];
+        let region = VolumeRegion::clamped([1, 0, 1], [10, 2, 0], dims).unwrap();
+        assert_eq!(region.extent, [3, 2, 1]);
+        assert!(matches!(VolumeRegion::clamped([0, 3, 0], [1, 1, 1], dims), Err(crate::Error::Config(_))));
+    }
+
+    #[test]
+    fn files_must_fit_the_layout() {
+        let layout = layout(RawDType::F32, Endianness::Little);
+        let path = write_volume(&layout);
+
+        let larger = RawLayout { dims: [4, 3, 3], ..layout };
+        let message = RawVolume::open(&path, larger).unwrap_err().to_string();
+        assert!(message.contains("has 112 bytes") && message.contains("needs 160"), "{}", message);
+
+        let empty = RawLayout { dims: [4, 0, 2], ..layout };
+        assert!(RawVolume::open(&path, empty).is_err());
+        let huge = RawLayout { dims: [usize::MAX, 2, 1], ..layout };
+        assert_eq!(huge.file_size(), None);
+        assert!(RawVolume::open(&path, huge).unwrap_err().to_string().contains("too large to address"));
+        assert!(RawVolume::open(&path.with_extension("missing"), layout).unwrap_err().to_string().contains("Cannot open"));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn types_and_byte_orders_parse_by_name() {
+        assert_eq!(RawDType::parse(" UInt16 ").unwrap(), RawDType::U16);
+        assert_eq!(RawDType::parse("float").unwrap(), RawDType::F32);
+        assert!(RawDType::parse("f64").is_err());
+        assert_eq!(Endianness::parse("BE").unwrap(), Endianness::Big);
+        assert!(Endianness::parse("middle").is_err());
+    }
+}
@@ -0,0 +1,499 @@
+//! Retention of the files writers produce in output directories
+//!
+//! Animation frames and watch-mode outputs pile up over long runs. A
+//! `RetentionManager` lists every file a workflow writes in the manifest
+//! `OUTPUT_MANIFEST` of its directory and, after each write, applies the
+//! `RetentionPolicy` configured for that directory: keep the newest N files,
+//! at most so many bytes, or only files younger than an age. Deletions only
+//! ever touch files the manifest lists for the same workflow, so files of
+//! other workflows or put there by hand stay, whatever their names. The file
+//! just written is always kept. In dry-run mode nothing is deleted; what
+//! would have been is logged and reported instead.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Manifest of produced files kept in each output directory with a policy
+pub const OUTPUT_MANIFEST: &str = ".vistle-outputs.json";
+
+/// Limits on the produced files kept in one directory
+///
+/// A file goes as soon as it exceeds any limit; without limits all are kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Keep this many of the newest files
+    #[serde(default)]
+    pub keep_last: Option<usize>,
+    /// Keep the newest files up to this many bytes in total
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Keep files written within this time
+    #[serde(default)]
+    pub max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_keep_last(mut self, count: usize) -> Self {
+        self.keep_last = Some(count);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    pub fn with_max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.keep_last.is_none() && self.max_bytes.is_none() && self.max_age.is_none()
+    }
+}
+
+/// Retention policies of a workflow's output directories
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Policy per directory, resolved like other paths of the workflow
+    #[serde(default)]
+    pub policies: BTreeMap<PathBuf, RetentionPolicy>,
+    /// Report what would be deleted without deleting it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl RetentionConfig {
+    pub fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+}
+
+/// Limit of a policy a file was deleted for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionReason {
+    KeepLast,
+    MaxBytes,
+    MaxAge,
+}
+
+/// A produced file deleted by a retention policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionDeletion {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub reason: RetentionReason,
+    /// Only listed, the file is still there
+    pub dry_run: bool,
+}
+
+/// A file listed in an output directory's manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// File name within the directory
+    pub name: String,
+    pub workflow_id: String,
+    pub bytes: u64,
+    /// Milliseconds since the Unix epoch
+    pub written_at_ms: u64,
+}
+
+/// Files produced in one output directory, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputManifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+impl OutputManifest {
+    /// Manifest of a directory; empty if it has none yet
+    pub async fn load(dir: &Path) -> Result<Self, crate::Error> {
+        let path = dir.join(OUTPUT_MANIFEST);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| crate::Error::Config(format!("Invalid output manifest {}: {}", path.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the manifest under a temporary name first, so readers never see half of it
+    pub async fn save(&self, dir: &Path) -> Result<(), crate::Error> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize output manifest: {}", e)))?;
+        let temporary = dir.join(format!("{}.tmp", OUTPUT_MANIFEST));
+        crate::util::io::write_text(&temporary, &text).await?;
+        tokio::fs::rename(&temporary, dir.join(OUTPUT_MANIFEST)).await?;
+        Ok(())
+    }
+
+    /// Files a workflow produced, newest first
+    pub fn produced_by<'a>(&'a self, workflow_id: &'a str) -> impl Iterator<Item = &'a ManifestEntry> {
+        self.files.iter().rev().filter(move |f| f.workflow_id == workflow_id)
+    }
+}
+
+/// Tracks the files of one workflow execution and applies the retention policies
+#[derive(Debug)]
+pub struct RetentionManager {
+    workflow_id: String,
+    /// Resolved directories and their policies
+    policies: Vec<(PathBuf, RetentionPolicy)>,
+    dry_run: bool,
+    /// Manifests are read, changed and written back under this lock
+    manifest_lock: tokio::sync::Mutex<()>,
+    deletions: parking_lot::Mutex<Vec<RetentionDeletion>>,
+}
+
+impl RetentionManager {
+    /// Manager for a workflow's configuration, resolving directories against `base_dir`
+    pub fn new(workflow_id: &str, config: &RetentionConfig, base_dir: Option<&Path>) -> Result<Self, crate::Error> {
+        let policies = config.policies.iter()
+            .map(|(dir, policy)| {
+                let dir = crate::core::resolve_path(&dir.to_string_lossy(), base_dir)?;
+                Ok((dir, policy.clone()))
+            })
+            .collect::<Result<Vec<_>, crate::Error>>()?;
+        Ok(Self {
+            workflow_id: workflow_id.to_string(),
+            policies,
+            dry_run: config.dry_run,
+            manifest_lock: tokio::sync::Mutex::new(()),
+            deletions: parking_lot::Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn workflow_id(&self) -> &str {
+        &self.workflow_id
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Policy of the directory a file is written to
+    pub fn policy_for(&self, path: &Path) -> Option<&RetentionPolicy> {
+        let dir = path.parent()?;
+        self.policies.iter().find(|(d, _)| d == dir).map(|(_, policy)| policy)
+    }
+
+    /// Record a file the workflow has just written, then apply its directory's policy
+    ///
+    /// Files outside directories with a policy are not tracked. Returns the
+    /// files deleted, or listed in dry-run mode.
+    pub async fn record_write(&self, path: &Path) -> Result<Vec<RetentionDeletion>, crate::Error> {
+        let Some(policy) = self.policy_for(path).cloned() else {
+            return Ok(Vec::new());
+        };
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(Vec::new());
+        };
+        let name = name.to_string_lossy().into_owned();
+        let bytes = tokio::fs::metadata(path).await?.len();
+
+        let _lock = self.manifest_lock.lock().await;
+        let mut manifest = OutputManifest::load(dir).await?;
+        manifest.files.retain(|f| f.name != name);
+        manifest.files.push(ManifestEntry {
+            name: name.clone(),
+            workflow_id: self.workflow_id.clone(),
+            bytes,
+            written_at_ms: unix_millis(SystemTime::now()),
+        });
+        let deletions = self.enforce(dir, &policy, &mut manifest, &name).await;
+        manifest.save(dir).await?;
+
+        self.deletions.lock().extend(deletions.iter().cloned());
+        Ok(deletions)
+    }
+
+    /// Files of this workflow in `dir` its policy would delete now, without deleting any
+    pub async fn preview(&self, dir: &Path) -> Result<Vec<RetentionDeletion>, crate::Error> {
+        let Some((dir, policy)) = self.policies.iter().find(|(d, _)| d == dir) else {
+            return Ok(Vec::new());
+        };
+        let _lock = self.manifest_lock.lock().await;
+        let manifest = OutputManifest::load(dir).await?;
+        let now = unix_millis(SystemTime::now());
+        Ok(select(&manifest, &self.workflow_id, policy, now, None).into_iter()
+            .map(|(entry, reason)| RetentionDeletion {
+                path: dir.join(&entry.name),
+                bytes: entry.bytes,
+                reason,
+                dry_run: true,
+            })
+            .collect())
+    }
+
+    /// Everything deleted, or listed in dry-run mode, so far
+    pub fn deletions(&self) -> Vec<RetentionDeletion> {
+        self.deletions.lock().clone()
+    }
+
+    /// Delete the files `policy` selects and drop them, and files gone missing, from the manifest
+    async fn enforce(
+        &self,
+        dir: &Path,
+        policy: &RetentionPolicy,
+        manifest: &mut OutputManifest,
+        written: &str,
+    ) -> Vec<RetentionDeletion> {
+        let mut missing = Vec::new();
+        for entry in &manifest.files {
+            if !tokio::fs::try_exists(dir.join(&entry.name)).await.unwrap_or(true) {
+                missing.push(entry.name.clone());
+            }
+        }
+        if !missing.is_empty() {
+            tracing::debug!("Output manifest of {}: {} listed files are gone", dir.display(), missing.len());
+            manifest.files.retain(|f| !missing.contains(&f.name));
+        }
+
+        let now = unix_millis(SystemTime::now());
+        let selected: Vec<(String, u64, RetentionReason)> = select(manifest, &self.workflow_id, policy, now, Some(written))
+            .into_iter()
+            .map(|(entry, reason)| (entry.name.clone(), entry.bytes, reason))
+            .collect();
+
+        let mut deletions = Vec::new();
+        for (name, bytes, reason) in selected {
+            let path = dir.join(&name);
+            if self.dry_run {
+                tracing::info!("Workflow {}: retention would delete {} ({:?})", self.workflow_id, path.display(), reason);
+            } else {
+                match tokio::fs::remove_file(&path).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        tracing::warn!("Workflow {}: cannot delete {}: {}", self.workflow_id, path.display(), e);
+                        continue;
+                    }
+                }
+                tracing::info!("Workflow {}: retention deleted {} ({:?})", self.workflow_id, path.display(), reason);
+                manifest.files.retain(|f| f.name != name);
+            }
+            deletions.push(RetentionDeletion {
+                path,
+                bytes,
+                reason,
+                dry_run: self.dry_run,
+            });
+        }
+        deletions
+    }
+}
+
+/// Files of a workflow that exceed a policy, newest first; `keep` is never selected
+fn select<'a>(
+    manifest: &'a OutputManifest,
+    workflow_id: &'a str,
+    policy: &RetentionPolicy,
+    now_ms: u64,
+    keep: Option<&str>,
+) -> Vec<(&'a ManifestEntry, RetentionReason)> {
+    let mut kept = 0;
+    let mut kept_bytes = 0;
+    let mut selected = Vec::new();
+    for entry in manifest.produced_by(workflow_id) {
+        let age = Duration::from_millis(now_ms.saturating_sub(entry.written_at_ms));
+        let reason = if keep == Some(entry.name.as_str()) {
+            None
+        } else if policy.keep_last.is_some_and(|n| kept >= n) {
+            Some(RetentionReason::KeepLast)
+        } else if policy.max_age.is_some_and(|max| age > max) {
+            Some(RetentionReason::MaxAge)
+        } else if policy.max_bytes.is_some_and(|max| kept_bytes + entry.bytes > max) {
+            Some(RetentionReason::MaxBytes)
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => selected.push((entry, reason)),
+            None => {
+                kept += 1;
+                kept_bytes += entry.bytes;
+            }
+        }
+    }
+    selected
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vistle_retention_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(dir.join("frames")).unwrap();
+        dir
+    }
+
+    fn entry(name: &str, workflow_id: &str, bytes: u64, written_at_ms: u64) -> ManifestEntry {
+        ManifestEntry {
+            name: name.to_string(),
+            workflow_id: workflow_id.to_string(),
+            bytes,
+            written_at_ms,
+        }
+    }
+
+    fn selected(manifest: &OutputManifest, policy: &RetentionPolicy, keep: Option<&str>) -> Vec<(String, RetentionReason)> {
+        select(manifest, "w", policy, 10_000, keep).into_iter()
+            .map(|(entry, reason)| (entry.name.clone(), reason))
+            .collect()
+    }
+
+    fn manifest() -> OutputManifest {
+        OutputManifest {
+            files: vec![
+                entry("a", "w", 100, 1_000),
+                entry("other", "x", 100, 1_500),
+                entry("b", "w", 100, 2_000),
+                entry("c", "w", 300, 9_000),
+                entry("d", "w", 100, 9_500),
+            ],
+        }
+    }
+
+    #[test]
+    fn the_newest_files_of_the_workflow_are_kept() {
+        let policy = RetentionPolicy::new().with_keep_last(2);
+        assert_eq!(selected(&manifest(), &policy, None), [
+            ("b".to_string(), RetentionReason::KeepLast),
+            ("a".to_string(), RetentionReason::KeepLast),
+        ]);
+        assert!(selected(&manifest(), &RetentionPolicy::new(), None).is_empty());
+        assert!(RetentionPolicy::new().is_unlimited());
+    }
+
+    #[test]
+    fn bytes_and_age_limit_what_is_kept() {
+        let policy = RetentionPolicy::new().with_max_bytes(500);
+        assert_eq!(selected(&manifest(), &policy, None), [("a".to_string(), RetentionReason::MaxBytes)]);
+
+        let policy = RetentionPolicy::new().with_max_age(Duration::from_secs(5));
+        assert_eq!(selected(&manifest(), &policy, None), [
+            ("b".to_string(), RetentionReason::MaxAge),
+            ("a".to_string(), RetentionReason::MaxAge),
+        ]);
+    }
+
+    #[test]
+    fn the_file_just_written_is_never_selected() {
+        let policy = RetentionPolicy::new().with_max_bytes(50).with_keep_last(1);
+        let reasons = selected(&manifest(), &policy, Some("c"));
+        let names: Vec<&str> = reasons.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["d", "b", "a"]);
+        assert_eq!(reasons[0].1, RetentionReason::MaxBytes);
+        assert_eq!(reasons[1].1, RetentionReason::KeepLast);
+    }
+
+    async fn write(dir: &Path, name: &str, bytes: usize) -> PathBuf {
+        let path = dir.join(name);
+        tokio::fs::write(&path, vec![0u8; bytes]).await.unwrap();
+        path
+    }
+
+    fn keep_two(dry_run: bool) -> RetentionConfig {
+        RetentionConfig {
+            policies: BTreeMap::from([(PathBuf::from("frames"), RetentionPolicy::new().with_keep_last(2))]),
+            dry_run,
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_delete_the_oldest_files_of_the_workflow_only() {
+        let base = temp_dir();
+        let frames = base.join("frames");
+        let manager = RetentionManager::new("w", &keep_two(false), Some(&base)).unwrap();
+        assert!(manager.policy_for(&frames.join("frame_0.png")).is_some());
+        let by_hand = write(&frames, "frame_9.png", 10).await;
+
+        let mut deleted = Vec::new();
+        for i in 0..4 {
+            let path = write(&frames, &format!("frame_{}.png", i), 10 + i).await;
+            deleted.extend(manager.record_write(&path).await.unwrap());
+        }
+        let names: Vec<String> = deleted.iter().map(|d| d.path.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, ["frame_0.png", "frame_1.png"]);
+        assert!(deleted.iter().all(|d| d.reason == RetentionReason::KeepLast && !d.dry_run));
+        assert_eq!(deleted[1].bytes, 11);
+        assert!(!frames.join("frame_0.png").exists() && !frames.join("frame_1.png").exists());
+        assert!(by_hand.exists());
+        assert_eq!(manager.deletions(), deleted);
+
+        let listed: Vec<String> = OutputManifest::load(&frames).await.unwrap().files.into_iter().map(|f| f.name).collect();
+        assert_eq!(listed, ["frame_2.png", "frame_3.png"]);
+
+        // Another workflow writing to the same directory leaves these files alone
+        let other = RetentionManager::new("x", &keep_two(false), Some(&base)).unwrap();
+        for i in 0..3 {
+            let path = write(&frames, &format!("other_{}.png", i), 10).await;
+            other.record_write(&path).await.unwrap();
+        }
+        assert!(frames.join("frame_2.png").exists() && frames.join("frame_3.png").exists());
+        assert!(!frames.join("other_0.png").exists());
+        std::fs::remove_dir_all(base).ok();
+    }
+
+    #[tokio::test]
+    async fn dry_runs_only_list_what_would_be_deleted() {
+        let base = temp_dir();
+        let frames = base.join("frames");
+        let manager = RetentionManager::new("w", &keep_two(true), Some(&base)).unwrap();
+        assert!(manager.is_dry_run());
+        for i in 0..3 {
+            let path = write(&frames, &format!("frame_{}.png", i), 10).await;
+            manager.record_write(&path).await.unwrap();
+        }
+
+        let deletions = manager.deletions();
+        assert_eq!(deletions.len(), 1);
+        assert!(deletions[0].dry_run);
+        assert!(frames.join("frame_0.png").exists());
+        assert_eq!(OutputManifest::load(&frames).await.unwrap().files.len(), 3);
+        assert_eq!(manager.preview(&frames).await.unwrap()[0].path, frames.join("frame_0.png"));
+        std::fs::remove_dir_all(base).ok();
+    }
+
+    #[tokio::test]
+    async fn untracked_directories_and_vanished_files_are_left_out() {
+        let base = temp_dir();
+        let frames = base.join("frames");
+        let manager = RetentionManager::new("w", &keep_two(false), Some(&base)).unwrap();
+
+        let elsewhere = write(&base, "summary.csv", 10).await;
+        assert!(manager.record_write(&elsewhere).await.unwrap().is_empty());
+        assert!(!base.join(OUTPUT_MANIFEST).exists());
+
+        let first = write(&frames, "frame_0.png", 10).await;
+        manager.record_write(&first).await.unwrap();
+        std::fs::remove_file(&first).unwrap();
+        let second = write(&frames, "frame_1.png", 10).await;
+        assert!(manager.record_write(&second).await.unwrap().is_empty());
+        let listed: Vec<String> = OutputManifest::load(&frames).await.unwrap().files.into_iter().map(|f| f.name).collect();
+        assert_eq!(listed, ["frame_1.png"]);
+        std::fs::remove_dir_all(base).ok();
+    }
+
+    #[tokio::test]
+    async fn invalid_manifests_are_errors() {
+        let base = temp_dir();
+        std::fs::write(base.join(OUTPUT_MANIFEST), "{").unwrap();
+        assert!(matches!(OutputManifest::load(&base).await, Err(crate::Error::Config(_))));
+        assert_eq!(OutputManifest::load(&base.join("frames")).await.unwrap(), OutputManifest::default());
+        std::fs::remove_dir_all(base).ok();
+    }
+}
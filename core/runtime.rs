@@ -0,0 +1,207 @@
+//! Thread pools for CPU-heavy module work, kept off the async runtime
+
+use std::fmt;
+use std::sync::Arc;
+
+use tokio::sync::oneshot;
+
+/// Pool that `ComputeContext::run_cpu` runs closures on
+///
+/// Tight loops on the tokio worker threads starve everything else on the
+/// runtime, including the GUI; running them here keeps the async threads
+/// free to poll.
+#[derive(Clone)]
+pub struct CpuPool {
+    pool: Arc<rayon::ThreadPool>,
+}
+
+impl CpuPool {
+    pub fn new(threads: usize) -> Result<Self, crate::Error> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .thread_name(|i| format!("vistle-cpu-{}", i))
+            // A panicking closure drops its sender, which `run` reports; without a handler rayon aborts
+            .panic_handler(|_| {})
+            .build()
+            .map_err(|e| crate::Error::Config(format!("Failed to create CPU pool: {}", e)))?;
+        Ok(Self { pool: Arc::new(pool) })
+    }
+
+    /// Pool leaving one core free for the GUI and message handling
+    pub fn interactive() -> Result<Self, crate::Error> {
+        Self::new(available_cores().saturating_sub(1))
+    }
+
+    /// Pool using every core, for headless runs
+    pub fn batch() -> Result<Self, crate::Error> {
+        Self::new(available_cores())
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Run a closure on the pool and wait for its result without blocking the runtime
+    pub async fn run<F, R>(&self, f: F) -> Result<R, crate::Error>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        self.pool.spawn(move || {
+            let _ = result_tx.send(f());
+        });
+        result_rx.await
+            .map_err(|_| crate::Error::Compute("CPU task panicked".to_string()))
+    }
+
+    /// Shared process-wide pool used when an executor does not configure one
+    pub fn global() -> Self {
+        static GLOBAL: std::sync::OnceLock<CpuPool> = std::sync::OnceLock::new();
+        GLOBAL.get_or_init(|| Self::interactive().expect("default CPU pool")).clone()
+    }
+}
+
+impl fmt::Debug for CpuPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CpuPool").field("threads", &self.threads()).finish()
+    }
+}
+
+fn available_cores() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    use tokio_util::sync::CancellationToken;
+
+    use crate::core::ComputeContext;
+
+    /// Cancel `token` from another thread after `delay`, then raise the returned flag
+    fn cancel_after(token: CancellationToken, delay: Duration) -> Arc<AtomicBool> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = cancelled.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            token.cancel();
+            flag.store(true, Ordering::SeqCst);
+        });
+        cancelled
+    }
+
+    #[tokio::test]
+    async fn pools_have_at_least_one_thread() {
+        assert_eq!(CpuPool::new(0).unwrap().threads(), 1);
+        assert_eq!(CpuPool::new(3).unwrap().threads(), 3);
+        assert!(CpuPool::batch().unwrap().threads() >= CpuPool::interactive().unwrap().threads());
+        assert_eq!(CpuPool::new(2).unwrap().run(|| 6 * 7).await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn a_panicking_closure_is_an_error() {
+        let pool = CpuPool::new(1).unwrap();
+        let error = pool.run(|| -> u32 { panic!("boom") }).await.unwrap_err();
+        assert!(error.to_string().contains("CPU task panicked"));
+        // The pool survives the panic
+        assert_eq!(pool.run(|| 1).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_runtime_keeps_polling_during_cpu_work() {
+        // The test runtime has a single thread, so the ticker only advances
+        // if run_cpu leaves it free
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    ticks.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+
+        let ctx = ComputeContext::new(1, 0, 1).with_cpu_pool(CpuPool::new(1).unwrap());
+        let sum = ctx.run_cpu(|| {
+            std::thread::sleep(Duration::from_millis(200));
+            (0..1000u64).sum::<u64>()
+        }).await.unwrap();
+        ticker.abort();
+
+        assert_eq!(sum, 499_500);
+        assert!(ticks.load(Ordering::SeqCst) >= 5, "ticker starved: {}", ticks.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn cancellation_fires_within_one_checkpoint() {
+        let token = CancellationToken::new();
+        let ctx = ComputeContext::new(1, 0, 1).with_cancellation(token.clone());
+        let cancelled = cancel_after(token, Duration::from_millis(30));
+
+        let mut passed_after_cancel = 0;
+        let error = loop {
+            // One checkpoint interval of tight work
+            std::thread::sleep(Duration::from_millis(1));
+            if let Err(e) = ctx.checkpoint().await {
+                break e;
+            }
+            if cancelled.load(Ordering::SeqCst) {
+                passed_after_cancel += 1;
+            }
+        };
+        assert!(matches!(error, crate::Error::Cancelled(_)), "{}", error);
+        assert!(passed_after_cancel <= 1, "{} checkpoints passed after cancelling", passed_after_cancel);
+        assert!(ctx.is_cancelled());
+        assert!(ctx.check_cancelled().is_err());
+    }
+
+    #[tokio::test]
+    async fn run_cpu_returns_on_cancellation_without_waiting_for_the_closure() {
+        let token = CancellationToken::new();
+        let ctx = ComputeContext::new(1, 0, 1)
+            .with_cancellation(token.clone())
+            .with_cpu_pool(CpuPool::new(1).unwrap());
+        cancel_after(token, Duration::from_millis(20));
+
+        let started = Instant::now();
+        let error = ctx.run_cpu(|| std::thread::sleep(Duration::from_secs(2))).await.unwrap_err();
+        assert!(matches!(error, crate::Error::Cancelled(_)), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // Already cancelled contexts do not start the closure at all
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = ran.clone();
+        assert!(ctx.run_cpu(move || flag.store(true, Ordering::SeqCst)).await.is_err());
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn closures_can_poll_for_cancellation() {
+        let token = CancellationToken::new();
+        let ctx = ComputeContext::new(1, 0, 1)
+            .with_cancellation(token.clone())
+            .with_cpu_pool(CpuPool::new(1).unwrap());
+        let inner = ctx.clone();
+        let iterations = Arc::new(AtomicUsize::new(0));
+        let count = iterations.clone();
+
+        let (stopped_tx, stopped_rx) = std::sync::mpsc::channel();
+        cancel_after(token, Duration::from_millis(20));
+        let _ = ctx.run_cpu(move || {
+            while inner.check_cancelled().is_ok() {
+                count.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            stopped_tx.send(()).unwrap();
+        }).await;
+
+        // The closure notices the cancellation on its own and finishes
+        stopped_rx.recv_timeout(Duration::from_secs(1)).expect("closure kept running");
+        assert!(iterations.load(Ordering::SeqCst) > 0);
+    }
+}
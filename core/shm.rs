@@ -1,284 +1,1289 @@
-//! Safe shared memory management for distributed computing
-
-use std::collections::HashMap;
-use std::sync::Arc;
-use parking_lot::{RwLock, Mutex};
-use shared_memory::{Shmem, ShmemConf};
-use serde::{Deserialize, Serialize};
-
-use crate::core::{ObjectId, Object};
-use crate::Error;
-
-/// Shared memory configuration
-#[derive(Debug, Clone)]
-pub struct ShmConfig {
-    pub size: usize,
-    pub name: String,
-}
-
-impl Default for ShmConfig {
-    fn default() -> Self {
-        Self {
-            size: 1024 * 1024 * 1024, // 1GB default
-            name: format!("vistle_shm_{}", std::process::id()),
-        }
-    }
-}
-
-/// Safe shared memory arena
-pub struct SharedArena {
-    shmem: Arc<Shmem>,
-    objects: RwLock<HashMap<ObjectId, SharedObject>>,
-    allocator: Mutex<SharedAllocator>,
-}
-
-impl SharedArena {
-    /// Create a new shared memory arena
-    pub fn new(config: ShmConfig) -> Result<Self, Error> {
-        let shmem = Arc::new(
-            ShmemConf::new()
-                .size(config.size)
-                .flink(&config.name)
-                .create()
-                .map_err(|e| Error::SharedMemory(format!("Failed to create shared memory: {}", e)))?
-        );
-
-        Ok(Self {
-            shmem,
-            objects: RwLock::new(HashMap::new()),
-            allocator: Mutex::new(SharedAllocator::new(config.size)),
-        })
-    }
-
-    /// Attach to existing shared memory arena
-    pub fn attach(name: &str) -> Result<Self, Error> {
-        let shmem = Arc::new(
-            ShmemConf::new()
-                .flink(name)
-                .open()
-                .map_err(|e| Error::SharedMemory(format!("Failed to attach to shared memory: {}", e)))?
-        );
-
-        // For simplicity, assume we can reconstruct the allocator state
-        // In a real implementation, this would be stored in shared memory
-        let size = shmem.len();
-        let allocator = Mutex::new(SharedAllocator::new(size));
-
-        Ok(Self {
-            shmem,
-            objects: RwLock::new(HashMap::new()),
-            allocator,
-        })
-    }
-
-    /// Store an object in shared memory
-    pub fn store_object(&self, object: Arc<dyn Object>) -> Result<ObjectId, Error> {
-        let id = object.id();
-
-        // Serialize the object
-        let data = bincode::serialize(&object.clone_object())
-            .map_err(|e| Error::Serialization(e))?;
-
-        // Allocate space in shared memory
-        let mut allocator = self.allocator.lock();
-        let offset = allocator.allocate(data.len())?;
-
-        // Copy data to shared memory
-        unsafe {
-            let ptr = self.shmem.as_ptr().add(offset);
-            std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
-        }
-
-        // Create shared object entry
-        let shared_obj = SharedObject {
-            id,
-            offset,
-            size: data.len(),
-            object_type: object.object_type(),
-        };
-
-        // Store in registry
-        self.objects.write().insert(id, shared_obj);
-
-        Ok(id)
-    }
-
-    /// Retrieve an object from shared memory
-    pub fn get_object(&self, id: ObjectId) -> Result<Option<Arc<dyn Object>>, Error> {
-        let objects = self.objects.read();
-        let shared_obj = match objects.get(&id) {
-            Some(obj) => obj,
-            None => return Ok(None),
-        };
-
-        // Read data from shared memory
-        let mut data = vec![0u8; shared_obj.size];
-        unsafe {
-            let ptr = self.shmem.as_ptr().add(shared_obj.offset);
-            std::ptr::copy_nonoverlapping(ptr, data.as_mut_ptr(), shared_obj.size);
-        }
-
-        // Deserialize the object
-        let object: Box<dyn Object> = bincode::deserialize(&data)
-            .map_err(|e| Error::Serialization(e))?;
-
-        Ok(Some(Arc::from(object)))
-    }
-
-    /// Remove an object from shared memory
-    pub fn remove_object(&self, id: ObjectId) -> Result<bool, Error> {
-        let mut objects = self.objects.write();
-        if let Some(shared_obj) = objects.remove(&id) {
-            let mut allocator = self.allocator.lock();
-            allocator.deallocate(shared_obj.offset, shared_obj.size)?;
-            Ok(true)
-        } else {
-            Ok(false)
-        }
-    }
-
-    /// Get shared memory statistics
-    pub fn stats(&self) -> ShmStats {
-        let allocator = self.allocator.lock();
-        let objects = self.objects.read();
-
-        ShmStats {
-            total_size: self.shmem.len(),
-            used_size: allocator.used(),
-            free_size: allocator.free(),
-            object_count: objects.len(),
-        }
-    }
-}
-
-/// Shared memory statistics
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ShmStats {
-    pub total_size: usize,
-    pub used_size: usize,
-    pub free_size: usize,
-    pub object_count: usize,
-}
-
-/// Internal representation of a shared object
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SharedObject {
-    id: ObjectId,
-    offset: usize,
-    size: usize,
-    object_type: crate::core::ObjectType,
-}
-
-/// Simple shared memory allocator
-struct SharedAllocator {
-    total_size: usize,
-    allocations: HashMap<usize, usize>, // offset -> size
-    free_blocks: Vec<(usize, usize)>, // (offset, size)
-}
-
-impl SharedAllocator {
-    fn new(total_size: usize) -> Self {
-        Self {
-            total_size,
-            allocations: HashMap::new(),
-            free_blocks: vec![(0, total_size)],
-        }
-    }
-
-    fn allocate(&mut self, size: usize) -> Result<usize, Error> {
-        // Find a suitable free block (first fit strategy)
-        for i in 0..self.free_blocks.len() {
-            let (offset, block_size) = self.free_blocks[i];
-            if block_size >= size {
-                // Remove this block
-                self.free_blocks.remove(i);
-
-                // If there's leftover space, add it back as a free block
-                if block_size > size {
-                    self.free_blocks.push((offset + size, block_size - size));
-                }
-
-                // Record the allocation
-                self.allocations.insert(offset, size);
-
-                return Ok(offset);
-            }
-        }
-
-        Err(Error::SharedMemory("Insufficient shared memory space".to_string()))
-    }
-
-    fn deallocate(&mut self, offset: usize, size: usize) -> Result<(), Error> {
-        // Remove the allocation
-        if self.allocations.remove(&offset).is_none() {
-            return Err(Error::SharedMemory("Invalid deallocation".to_string()));
-        }
-
-        // Add to free blocks and merge adjacent blocks
-        self.free_blocks.push((offset, size));
-        self.coalesce_free_blocks();
-
-        Ok(())
-    }
-
-    fn coalesce_free_blocks(&mut self) {
-        self.free_blocks.sort_by_key(|&(offset, _)| offset);
-
-        let mut i = 0;
-        while i + 1 < self.free_blocks.len() {
-            let (offset1, size1) = self.free_blocks[i];
-            let (offset2, size2) = self.free_blocks[i + 1];
-
-            if offset1 + size1 == offset2 {
-                // Merge blocks
-                self.free_blocks[i] = (offset1, size1 + size2);
-                self.free_blocks.remove(i + 1);
-            } else {
-                i += 1;
-            }
-        }
-    }
-
-    fn used(&self) -> usize {
-        self.allocations.values().sum()
-    }
-
-    fn free(&self) -> usize {
-        self.total_size - self.used()
-    }
-}
-
-/// Global shared memory manager
-pub struct ShmManager {
-    arenas: RwLock<HashMap<String, Arc<SharedArena>>>,
-}
-
-impl ShmManager {
-    pub fn new() -> Self {
-        Self {
-            arenas: RwLock::new(HashMap::new()),
-        }
-    }
-
-    pub fn create_arena(&self, name: String, config: ShmConfig) -> Result<Arc<SharedArena>, Error> {
-        let arena = Arc::new(SharedArena::new(config)?);
-        self.arenas.write().insert(name, arena.clone());
-        Ok(arena)
-    }
-
-    pub fn get_arena(&self, name: &str) -> Option<Arc<SharedArena>> {
-        self.arenas.read().get(name).cloned()
-    }
-
-    pub fn attach_arena(&self, name: String, shm_name: &str) -> Result<Arc<SharedArena>, Error> {
-        let arena = Arc::new(SharedArena::attach(shm_name)?);
-        self.arenas.write().insert(name, arena.clone());
-        Ok(arena)
-    }
-}
-
-impl Default for ShmManager {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+//! Safe shared memory management for distributed computing
+//!
+//! Readers of an object register with it in the arena's index before they
+//! copy it out or map it, and stay registered until done. Removing an object
+//! with registered readers either leaves freeing its block to the last of
+//! them or fails with `ObjectBusy`, depending on the `RemovalPolicy`.
+//!
+//! Registration happens under the index read lock and removal inspects the
+//! readers under its write lock, so every read is ordered entirely before
+//! or after a removal. When a removal races two concurrent gets, each get
+//! either registered first and reads the whole object, or comes later and
+//! finds nothing; a get never sees a freed or reused block. With
+//! `RemovalPolicy::FailFast` the removal fails while either get still reads.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use parking_lot::{RwLock, Mutex};
+use shared_memory::{Shmem, ShmemConf};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{ObjectData, ObjectId, Object, VistleObject};
+use crate::Error;
+
+/// Shared memory configuration
+#[derive(Debug, Clone)]
+pub struct ShmConfig {
+    pub size: usize,
+    pub name: String,
+    pub removal: RemovalPolicy,
+}
+
+impl Default for ShmConfig {
+    fn default() -> Self {
+        Self {
+            size: 1024 * 1024 * 1024, // 1GB default
+            name: format!("vistle_shm_{}", std::process::id()),
+            removal: RemovalPolicy::Defer,
+        }
+    }
+}
+
+/// What removing an object that is still being read does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RemovalPolicy {
+    /// Remove it from the index now and free its block after the last reader
+    #[default]
+    Defer,
+    /// Fail with `ObjectBusy` and keep the object
+    FailFast,
+}
+
+/// Magic number identifying a vistle arena ("VISTLSHM")
+pub const SHM_MAGIC: u64 = u64::from_le_bytes(*b"VISTLSHM");
+
+/// Layout version of the arena header and object area
+pub const SHM_LAYOUT_VERSION: u32 = 1;
+
+/// Bytes reserved at the start of every segment for the header
+const HEADER_SIZE: usize = 64;
+
+/// Header at the start of a segment; `ready` is written last by the creator
+#[repr(C)]
+struct ArenaHeader {
+    magic: u64,
+    version: u32,
+    ready: AtomicU32,
+}
+
+/// Why attaching to a segment failed
+enum AttachFailure {
+    /// The segment does not exist yet or its creator has not finished
+    NotReady(Error),
+    /// The segment exists but is not usable
+    Invalid(Error),
+}
+
+impl AttachFailure {
+    fn into_error(self) -> Error {
+        match self {
+            AttachFailure::NotReady(e) | AttachFailure::Invalid(e) => e,
+        }
+    }
+}
+
+/// Mapping of a shared memory segment
+///
+/// `Shmem` keeps raw pointers to the mapping, which stays valid for its
+/// lifetime; the arena's locks order all access to the bytes behind them.
+struct Segment(Shmem);
+
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl std::ops::Deref for Segment {
+    type Target = Shmem;
+
+    fn deref(&self) -> &Shmem {
+        &self.0
+    }
+}
+
+/// Safe shared memory arena
+pub struct SharedArena {
+    shmem: Arc<Segment>,
+    objects: RwLock<HashMap<ObjectId, SharedObject>>,
+    allocator: Mutex<SharedAllocator>,
+    owner: Option<(String, Arc<ShmAccounting>)>,
+    removal: RemovalPolicy,
+}
+
+impl SharedArena {
+    /// Create a new shared memory arena
+    pub fn new(config: ShmConfig) -> Result<Self, Error> {
+        let shmem = Arc::new(Segment(
+            ShmemConf::new()
+                .size(config.size + HEADER_SIZE)
+                .flink(&config.name)
+                .create()
+                .map_err(|e| Error::SharedMemory(format!("Failed to create shared memory: {}", e)))?
+        ));
+
+        // Publish the header; the ready flag goes last so attachers never see a partial header
+        unsafe {
+            let header = shmem.as_ptr() as *mut ArenaHeader;
+            std::ptr::addr_of_mut!((*header).magic).write(SHM_MAGIC);
+            std::ptr::addr_of_mut!((*header).version).write(SHM_LAYOUT_VERSION);
+            (*header).ready.store(1, Ordering::Release);
+        }
+
+        Ok(Self {
+            shmem,
+            objects: RwLock::new(HashMap::new()),
+            allocator: Mutex::new(SharedAllocator::new(config.size)),
+            owner: None,
+            removal: config.removal,
+        })
+    }
+
+    /// Set what removing an object that is still being read does
+    pub fn with_removal_policy(mut self, removal: RemovalPolicy) -> Self {
+        self.removal = removal;
+        self
+    }
+
+    /// Charge allocations in this arena to an owner's quota
+    fn with_owner(mut self, owner: &str, accounting: Arc<ShmAccounting>) -> Self {
+        self.owner = Some((owner.to_string(), accounting));
+        self
+    }
+
+    /// Owner the arena's allocations are charged to
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_ref().map(|(owner, _)| owner.as_str())
+    }
+
+    /// Attach to existing shared memory arena
+    pub fn attach(name: &str) -> Result<Self, Error> {
+        Self::try_attach(name).map_err(AttachFailure::into_error)
+    }
+
+    /// Attach to an arena, waiting up to `timeout` for its creator to publish it
+    ///
+    /// Missing or not-yet-ready segments are retried every `poll_interval`;
+    /// corrupted headers and version mismatches fail immediately. This sleeps
+    /// on the calling thread between attempts: from async code use
+    /// `attach_with_retry_async`, or run it in `spawn_blocking`.
+    pub fn attach_with_retry(name: &str, timeout: Duration, poll_interval: Duration) -> Result<Self, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match Self::attach_attempt(name, timeout, deadline)? {
+                Some(arena) => return Ok(arena),
+                None => std::thread::sleep(poll_interval.min(deadline.saturating_duration_since(Instant::now()))),
+            }
+        }
+    }
+
+    /// `attach_with_retry` for async code, waiting on the runtime's timer instead of blocking
+    pub async fn attach_with_retry_async(name: &str, timeout: Duration, poll_interval: Duration) -> Result<Self, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match Self::attach_attempt(name, timeout, deadline)? {
+                Some(arena) => return Ok(arena),
+                None => tokio::time::sleep(poll_interval.min(deadline.saturating_duration_since(Instant::now()))).await,
+            }
+        }
+    }
+
+    /// One attempt of a retried attach; `None` if it should be retried
+    fn attach_attempt(name: &str, timeout: Duration, deadline: Instant) -> Result<Option<Self>, Error> {
+        match Self::try_attach(name) {
+            Ok(arena) => Ok(Some(arena)),
+            Err(AttachFailure::Invalid(e)) => Err(e),
+            Err(AttachFailure::NotReady(e)) if Instant::now() >= deadline => Err(Error::SharedMemory(format!(
+                "Timed out after {:?} waiting for shared memory {}: {}",
+                timeout, name, e
+            ))),
+            Err(AttachFailure::NotReady(_)) => Ok(None),
+        }
+    }
+
+    fn try_attach(name: &str) -> Result<Self, AttachFailure> {
+        let shmem = Arc::new(Segment(
+            ShmemConf::new()
+                .flink(name)
+                .open()
+                .map_err(|e| AttachFailure::NotReady(Error::SharedMemory(format!("Failed to attach to shared memory: {}", e))))?
+        ));
+
+        if shmem.len() < HEADER_SIZE {
+            return Err(AttachFailure::Invalid(Error::SharedMemory(format!(
+                "Shared memory {} is too small ({} bytes) to hold an arena header",
+                name, shmem.len()
+            ))));
+        }
+
+        let (ready, magic, version) = unsafe {
+            let header = shmem.as_ptr() as *const ArenaHeader;
+            let ready = (*header).ready.load(Ordering::Acquire);
+            (
+                ready,
+                std::ptr::addr_of!((*header).magic).read(),
+                std::ptr::addr_of!((*header).version).read(),
+            )
+        };
+
+        if ready == 0 {
+            return Err(AttachFailure::NotReady(Error::SharedMemory(format!(
+                "Shared memory {} is not ready yet",
+                name
+            ))));
+        }
+        if magic != SHM_MAGIC {
+            return Err(AttachFailure::Invalid(Error::SharedMemory(format!(
+                "Shared memory {} has a corrupted header (magic {:#018x})",
+                name, magic
+            ))));
+        }
+        if version != SHM_LAYOUT_VERSION {
+            return Err(AttachFailure::Invalid(Error::SharedMemory(format!(
+                "Shared memory {} has layout version {}, expected {}",
+                name, version, SHM_LAYOUT_VERSION
+            ))));
+        }
+
+        // For simplicity, assume we can reconstruct the allocator state
+        // In a real implementation, this would be stored in shared memory
+        let size = shmem.len() - HEADER_SIZE;
+        let allocator = Mutex::new(SharedAllocator::new(size));
+
+        Ok(Self {
+            shmem,
+            objects: RwLock::new(HashMap::new()),
+            allocator,
+            owner: None,
+            removal: RemovalPolicy::default(),
+        })
+    }
+
+    /// Store an object in shared memory
+    pub fn store_object(&self, object: Arc<dyn Object>) -> Result<ObjectId, Error> {
+        self.try_store_object(object).map_err(Error::from)
+    }
+
+    /// Store an object, reporting lack of space as `StoreError::Full`
+    ///
+    /// The allocator lock is only held while a block is taken from the free
+    /// list; serialization and the copy into shared memory run unlocked, so
+    /// concurrent stores only contend on the free-list update.
+    pub fn try_store_object(&self, object: Arc<dyn Object>) -> Result<ObjectId, StoreError> {
+        let id = object.id();
+
+        // Serialize the object
+        // Objects are stored by value, as they are sent to other processes
+        let data = object.as_data()
+            .ok_or_else(|| Error::SharedMemory(format!("Object {:?} has no serializable data", id)))
+            .and_then(|data| bincode::serialize(data).map_err(Error::from))?;
+
+        // Charge the owner's quota before touching the allocator
+        if let Some((owner, accounting)) = &self.owner {
+            if let Err(usage) = accounting.reserve(owner, data.len()) {
+                let allocator = self.allocator.lock();
+                return Err(StoreError::Full(ShmFull {
+                    requested: data.len(),
+                    largest_free_block: allocator.largest_free_block(),
+                    free: allocator.free(),
+                    owner: Some(owner.clone()),
+                    owner_usage: Some(usage),
+                    quota_exceeded: true,
+                }));
+            }
+        }
+
+        // Allocate space in shared memory
+        let allocation = {
+            let mut allocator = self.allocator.lock();
+            allocator.allocate(data.len()).ok_or_else(|| ShmFull {
+                requested: data.len(),
+                largest_free_block: allocator.largest_free_block(),
+                free: allocator.free(),
+                owner: None,
+                owner_usage: None,
+                quota_exceeded: false,
+            })
+        };
+        let offset = match allocation {
+            Ok(offset) => offset,
+            Err(mut full) => {
+                if let Some((owner, accounting)) = &self.owner {
+                    accounting.release(owner, data.len());
+                    full.owner = Some(owner.clone());
+                    full.owner_usage = accounting.usage(owner);
+                }
+                return Err(StoreError::Full(full));
+            }
+        };
+
+        // Copy data to shared memory; the block is not reachable through the
+        // registry yet, so no reader can observe it half written
+        unsafe {
+            let ptr = self.shmem.as_ptr().add(HEADER_SIZE + offset);
+            std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+        }
+
+        // Create shared object entry
+        let shared_obj = SharedObject {
+            id,
+            offset,
+            size: data.len(),
+            object_type: object.object_type(),
+            readers: Arc::default(),
+        };
+
+        // Store in registry; a concurrent store of the same id loses its block,
+        // which readers of the replaced object keep until they are done
+        let replaced = self.objects.write().insert(id, shared_obj);
+        if let Some(replaced) = replaced {
+            self.retire(replaced)?;
+        }
+
+        Ok(id)
+    }
+
+    /// Store an object, asking `make_room` to free space whenever the arena is full
+    ///
+    /// After each full attempt `make_room` may spill or evict objects; the store
+    /// is retried up to `attempts` times, waiting `backoff` before the first
+    /// retry and doubling it after every further one. Returning `false` from
+    /// `make_room` gives up immediately. Objects opted in to lossy compression
+    /// are first retried compressed, see `core::lossy`.
+    pub async fn store_object_with_retry(
+        &self,
+        mut object: Arc<dyn Object>,
+        attempts: u32,
+        backoff: Duration,
+        mut make_room: impl FnMut(&ShmFull) -> bool,
+    ) -> Result<ObjectId, Error> {
+        let mut delay = backoff;
+        let mut attempt = 1;
+        loop {
+            match self.try_store_object(object.clone()) {
+                Ok(id) => return Ok(id),
+                Err(StoreError::Full(full)) => {
+                    if let Some(lossy) = lossy_fallback(object.as_ref()) {
+                        tracing::debug!("Shared memory full, storing object {} lossily compressed: {}", object.id(), full);
+                        object = lossy;
+                        continue;
+                    }
+                    if attempt >= attempts || !make_room(&full) {
+                        return Err(full.into());
+                    }
+                    tracing::debug!("Shared memory full, retrying store in {:?}: {}", delay, full);
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                }
+                Err(StoreError::Failed(e)) => return Err(e),
+            }
+        }
+    }
+
+    /// Retrieve an object from shared memory
+    ///
+    /// The object is deserialized straight from its block while registered
+    /// as a reader, without holding the index lock.
+    pub fn get_object(&self, id: ObjectId) -> Result<Option<Arc<dyn Object>>, Error> {
+        match self.view_object(id) {
+            Some(view) => view.decode().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Map an object's serialized bytes in place, registered as a reader for the view's lifetime
+    pub fn view_object(&self, id: ObjectId) -> Option<ShmObjectView<'_>> {
+        let objects = self.objects.read();
+        let object = objects.get(&id)?.clone();
+        object.readers.lock().readers += 1;
+        Some(ShmObjectView { arena: self, object })
+    }
+
+    /// Remove an object from shared memory
+    pub fn remove_object(&self, id: ObjectId) -> Result<bool, Error> {
+        self.try_remove_object(id).map_err(Error::from)
+    }
+
+    /// Remove an object, reporting readers that block it under `RemovalPolicy::FailFast` as `RemoveError::Busy`
+    pub fn try_remove_object(&self, id: ObjectId) -> Result<bool, RemoveError> {
+        // New readers register under the read lock, so none can appear while this is held
+        let removed = {
+            let mut objects = self.objects.write();
+            if self.removal == RemovalPolicy::FailFast {
+                let readers = objects.get(&id).map_or(0, |o| o.readers.lock().readers);
+                if readers > 0 {
+                    return Err(RemoveError::Busy(ObjectBusy { id, readers }));
+                }
+            }
+            objects.remove(&id)
+        };
+        match removed {
+            Some(shared_obj) => {
+                self.retire(shared_obj)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Free the block of an object taken out of the index, or leave that to its last reader
+    fn retire(&self, shared_obj: SharedObject) -> Result<(), Error> {
+        {
+            let mut state = shared_obj.readers.lock();
+            if state.readers > 0 {
+                state.removed = true;
+                return Ok(());
+            }
+        }
+        self.free_block(&shared_obj)
+    }
+
+    /// Unregister a reader; the last reader of a removed object frees its block
+    fn release_reader(&self, shared_obj: &SharedObject) {
+        let free = {
+            let mut state = shared_obj.readers.lock();
+            state.readers -= 1;
+            state.readers == 0 && state.removed
+        };
+        if free {
+            if let Err(e) = self.free_block(shared_obj) {
+                tracing::warn!("Failed to free block of removed object {}: {}", shared_obj.id, e);
+            }
+        }
+    }
+
+    /// Return an object's block to the allocator and its bytes to the owner's quota
+    fn free_block(&self, shared_obj: &SharedObject) -> Result<(), Error> {
+        self.allocator.lock().deallocate(shared_obj.offset, shared_obj.size)?;
+        if let Some((owner, accounting)) = &self.owner {
+            accounting.release(owner, shared_obj.size);
+        }
+        Ok(())
+    }
+
+    /// Get shared memory statistics
+    ///
+    /// The registry and allocator are read one after the other, never nested,
+    /// so the counts may be momentarily inconsistent under concurrent stores.
+    pub fn stats(&self) -> ShmStats {
+        let object_count = self.objects.read().len();
+        let (used_size, free_size) = {
+            let allocator = self.allocator.lock();
+            (allocator.used(), allocator.free())
+        };
+
+        ShmStats {
+            total_size: self.shmem.len() - HEADER_SIZE,
+            used_size,
+            free_size,
+            object_count,
+        }
+    }
+}
+
+/// Shared memory statistics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShmStats {
+    pub total_size: usize,
+    pub used_size: usize,
+    pub free_size: usize,
+    pub object_count: usize,
+}
+
+/// An arena or owner quota had no room for an object
+#[derive(Debug, Clone)]
+pub struct ShmFull {
+    pub requested: usize,
+    /// Largest contiguous allocation that would have succeeded
+    pub largest_free_block: usize,
+    pub free: usize,
+    pub owner: Option<String>,
+    pub owner_usage: Option<OwnerUsage>,
+    /// The owner's quota, not the arena, ran out
+    pub quota_exceeded: bool,
+}
+
+impl std::fmt::Display for ShmFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Insufficient shared memory: requested {} bytes, largest free block {} of {} free bytes",
+            self.requested, self.largest_free_block, self.free
+        )?;
+        if let (Some(owner), Some(usage)) = (&self.owner, &self.owner_usage) {
+            write!(f, "; {} uses {} bytes", owner, usage.used)?;
+            if let Some(quota) = usage.quota {
+                write!(f, " of a {} byte quota", quota)?;
+            }
+            if self.quota_exceeded {
+                write!(f, " (quota exceeded)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ShmFull {}
+
+impl From<ShmFull> for Error {
+    fn from(full: ShmFull) -> Self {
+        Error::SharedMemory(full.to_string())
+    }
+}
+
+/// An object could not be removed because it is still being read
+#[derive(Debug, Clone)]
+pub struct ObjectBusy {
+    pub id: ObjectId,
+    pub readers: usize,
+}
+
+impl std::fmt::Display for ObjectBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Object {} is still being read by {} reader(s)", self.id, self.readers)
+    }
+}
+
+impl std::error::Error for ObjectBusy {}
+
+impl From<ObjectBusy> for Error {
+    fn from(busy: ObjectBusy) -> Self {
+        Error::SharedMemory(busy.to_string())
+    }
+}
+
+/// Why `SharedArena::try_remove_object` failed
+#[derive(Debug, thiserror::Error)]
+pub enum RemoveError {
+    /// Retrying once the readers are done may succeed
+    #[error(transparent)]
+    Busy(ObjectBusy),
+    #[error(transparent)]
+    Failed(#[from] Error),
+}
+
+impl From<RemoveError> for Error {
+    fn from(e: RemoveError) -> Self {
+        match e {
+            RemoveError::Busy(busy) => busy.into(),
+            RemoveError::Failed(e) => e,
+        }
+    }
+}
+
+/// Why `SharedArena::try_store_object` failed
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    /// Retrying after freeing space may succeed
+    #[error(transparent)]
+    Full(ShmFull),
+    #[error(transparent)]
+    Failed(#[from] Error),
+}
+
+impl From<StoreError> for Error {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::Full(full) => full.into(),
+            StoreError::Failed(e) => e,
+        }
+    }
+}
+
+/// Internal representation of a shared object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SharedObject {
+    id: ObjectId,
+    offset: usize,
+    size: usize,
+    object_type: crate::core::ObjectType,
+    /// Shared by every copy of the entry, so views and the index agree
+    #[serde(skip)]
+    readers: Arc<Mutex<ReaderState>>,
+}
+
+/// Readers of an object and whether it left the index while they read
+#[derive(Debug, Default)]
+struct ReaderState {
+    readers: usize,
+    removed: bool,
+}
+
+/// Serialized bytes of an object, mapped in place from shared memory
+///
+/// The view is registered as a reader of the object, so its block is not
+/// freed or reused while the view lives, even if the object is removed.
+pub struct ShmObjectView<'a> {
+    arena: &'a SharedArena,
+    object: SharedObject,
+}
+
+impl ShmObjectView<'_> {
+    pub fn id(&self) -> ObjectId {
+        self.object.id
+    }
+
+    pub fn object_type(&self) -> crate::core::ObjectType {
+        self.object.object_type
+    }
+
+    pub fn len(&self) -> usize {
+        self.object.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.object.size == 0
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        // SAFETY: offset and size describe a block inside the mapping, which the
+        // arena keeps alive for 'a. This view is registered as a reader, so the
+        // block is neither freed nor handed to another store until it drops,
+        // and stored blocks are never written again; see the race tests.
+        unsafe {
+            let ptr = self.arena.shmem.as_ptr().add(HEADER_SIZE + self.object.offset);
+            std::slice::from_raw_parts(ptr, self.object.size)
+        }
+    }
+
+    /// Deserialize the object
+    ///
+    /// Objects stored lossily compressed come back decompressed.
+    pub fn decode(&self) -> Result<Arc<dyn Object>, Error> {
+        let mut data: ObjectData = bincode::deserialize(self.bytes())
+            .map_err(Error::from)?;
+        data.decompress_lossy()?;
+        Ok(Arc::new(VistleObject::from_data(data)))
+    }
+}
+
+impl Drop for ShmObjectView<'_> {
+    fn drop(&mut self) {
+        self.arena.release_reader(&self.object);
+    }
+}
+
+/// The object compressed within its opted-in error bound, if it opted in and is not compressed yet
+fn lossy_fallback(object: &dyn Object) -> Option<Arc<dyn Object>> {
+    let data = object.as_data().filter(|d| d.accepts_lossy())?;
+    match data.to_lossy() {
+        Ok(data) => Some(Arc::new(VistleObject::from_data(data))),
+        Err(e) => {
+            tracing::warn!("Cannot compress object {} to make room: {}", data.id, e);
+            None
+        }
+    }
+}
+
+/// Simple shared memory allocator
+struct SharedAllocator {
+    total_size: usize,
+    allocations: HashMap<usize, usize>, // offset -> size
+    free_blocks: Vec<(usize, usize)>, // (offset, size)
+}
+
+impl SharedAllocator {
+    fn new(total_size: usize) -> Self {
+        Self {
+            total_size,
+            allocations: HashMap::new(),
+            free_blocks: vec![(0, total_size)],
+        }
+    }
+
+    fn allocate(&mut self, size: usize) -> Option<usize> {
+        // Find a suitable free block (first fit strategy)
+        for i in 0..self.free_blocks.len() {
+            let (offset, block_size) = self.free_blocks[i];
+            if block_size >= size {
+                // Remove this block
+                self.free_blocks.remove(i);
+
+                // If there's leftover space, add it back as a free block
+                if block_size > size {
+                    self.free_blocks.push((offset + size, block_size - size));
+                }
+
+                // Record the allocation
+                self.allocations.insert(offset, size);
+
+                return Some(offset);
+            }
+        }
+
+        None
+    }
+
+    fn deallocate(&mut self, offset: usize, size: usize) -> Result<(), Error> {
+        // Remove the allocation
+        if self.allocations.remove(&offset).is_none() {
+            return Err(Error::SharedMemory("Invalid deallocation".to_string()));
+        }
+
+        // Add to free blocks and merge adjacent blocks
+        self.free_blocks.push((offset, size));
+        self.coalesce_free_blocks();
+
+        Ok(())
+    }
+
+    fn coalesce_free_blocks(&mut self) {
+        self.free_blocks.sort_by_key(|&(offset, _)| offset);
+
+        let mut i = 0;
+        while i + 1 < self.free_blocks.len() {
+            let (offset1, size1) = self.free_blocks[i];
+            let (offset2, size2) = self.free_blocks[i + 1];
+
+            if offset1 + size1 == offset2 {
+                // Merge blocks
+                self.free_blocks[i] = (offset1, size1 + size2);
+                self.free_blocks.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn largest_free_block(&self) -> usize {
+        self.free_blocks.iter().map(|&(_, size)| size).max().unwrap_or(0)
+    }
+
+    fn used(&self) -> usize {
+        self.allocations.values().sum()
+    }
+
+    fn free(&self) -> usize {
+        self.total_size - self.used()
+    }
+}
+
+impl Drop for SharedArena {
+    fn drop(&mut self) {
+        // Return whatever is still stored to the owner's quota
+        if let Some((owner, accounting)) = &self.owner {
+            let used: usize = self.objects.read().values().map(|o| o.size).sum();
+            accounting.release(owner, used);
+        }
+    }
+}
+
+impl std::fmt::Debug for SharedArena {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedArena")
+            .field("owner", &self.owner())
+            .field("removal", &self.removal)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Shared memory usage of one owner
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OwnerUsage {
+    pub used: usize,
+    pub quota: Option<usize>,
+    pub arenas: usize,
+}
+
+/// Per-owner accounting of shared memory use across arenas
+#[derive(Debug, Default)]
+pub struct ShmAccounting {
+    owners: Mutex<HashMap<String, OwnerUsage>>,
+}
+
+impl ShmAccounting {
+    /// Reserve bytes for an owner; on exceeding its quota, fail with its current usage
+    fn reserve(&self, owner: &str, bytes: usize) -> Result<(), OwnerUsage> {
+        let mut owners = self.owners.lock();
+        let usage = owners.entry(owner.to_string()).or_default();
+        if let Some(quota) = usage.quota {
+            if usage.used + bytes > quota {
+                return Err(usage.clone());
+            }
+        }
+        usage.used += bytes;
+        Ok(())
+    }
+
+    fn usage(&self, owner: &str) -> Option<OwnerUsage> {
+        self.owners.lock().get(owner).cloned()
+    }
+
+    fn release(&self, owner: &str, bytes: usize) {
+        if let Some(usage) = self.owners.lock().get_mut(owner) {
+            usage.used = usage.used.saturating_sub(bytes);
+        }
+    }
+}
+
+/// Global shared memory manager
+pub struct ShmManager {
+    arenas: RwLock<HashMap<String, Arc<SharedArena>>>,
+    accounting: Arc<ShmAccounting>,
+}
+
+impl ShmManager {
+    pub fn new() -> Self {
+        Self {
+            arenas: RwLock::new(HashMap::new()),
+            accounting: Arc::new(ShmAccounting::default()),
+        }
+    }
+
+    /// Create an arena whose allocations count against `owner`'s quota
+    pub fn create_owned_arena(&self, owner: &str, name: String, config: ShmConfig) -> Result<Arc<SharedArena>, Error> {
+        let arena = Arc::new(SharedArena::new(config)?.with_owner(owner, self.accounting.clone()));
+        self.accounting.owners.lock().entry(owner.to_string()).or_default().arenas += 1;
+        self.arenas.write().insert(name, arena.clone());
+        Ok(arena)
+    }
+
+    /// Limit the total shared memory used by an owner across its arenas
+    pub fn set_quota(&self, owner: &str, bytes: usize) {
+        self.accounting.owners.lock().entry(owner.to_string()).or_default().quota = Some(bytes);
+    }
+
+    /// Current usage, quota and arena count per owner
+    pub fn usage_by_owner(&self) -> HashMap<String, OwnerUsage> {
+        self.accounting.owners.lock().clone()
+    }
+
+    /// Drop all arenas of an owner and reset its usage
+    ///
+    /// A quota set with `set_quota` stays in place for the owner's next
+    /// arenas, e.g. the next run of the same workflow; owners without one
+    /// are forgotten.
+    pub fn release_owner(&self, owner: &str) -> usize {
+        let mut arenas = self.arenas.write();
+        let before = arenas.len();
+        arenas.retain(|_, arena| arena.owner() != Some(owner));
+        let released = before - arenas.len();
+        drop(arenas);
+
+        let mut owners = self.accounting.owners.lock();
+        match owners.get(owner).and_then(|usage| usage.quota) {
+            Some(quota) => {
+                owners.insert(owner.to_string(), OwnerUsage { quota: Some(quota), ..OwnerUsage::default() });
+            }
+            None => {
+                owners.remove(owner);
+            }
+        }
+        released
+    }
+
+    pub fn create_arena(&self, name: String, config: ShmConfig) -> Result<Arc<SharedArena>, Error> {
+        let arena = Arc::new(SharedArena::new(config)?);
+        self.arenas.write().insert(name, arena.clone());
+        Ok(arena)
+    }
+
+    pub fn get_arena(&self, name: &str) -> Option<Arc<SharedArena>> {
+        self.arenas.read().get(name).cloned()
+    }
+
+    /// Statistics of every arena by name
+    pub fn arena_stats(&self) -> Vec<(String, ShmStats)> {
+        let arenas: Vec<(String, Arc<SharedArena>)> = self.arenas.read().iter()
+            .map(|(name, arena)| (name.clone(), arena.clone()))
+            .collect();
+        arenas.into_iter().map(|(name, arena)| (name, arena.stats())).collect()
+    }
+
+    pub fn attach_arena(&self, name: String, shm_name: &str) -> Result<Arc<SharedArena>, Error> {
+        let arena = Arc::new(SharedArena::attach(shm_name)?);
+        self.arenas.write().insert(name, arena.clone());
+        Ok(arena)
+    }
+
+    /// Attach to an arena, waiting for its creator as in `SharedArena::attach_with_retry`
+    pub fn attach_arena_with_retry(
+        &self,
+        name: String,
+        shm_name: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<Arc<SharedArena>, Error> {
+        let arena = Arc::new(SharedArena::attach_with_retry(shm_name, timeout, poll_interval)?);
+        self.arenas.write().insert(name, arena.clone());
+        Ok(arena)
+    }
+
+    /// Attach to an arena from async code, see `SharedArena::attach_with_retry_async`
+    pub async fn attach_arena_with_retry_async(
+        &self,
+        name: String,
+        shm_name: &str,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<Arc<SharedArena>, Error> {
+        let arena = Arc::new(SharedArena::attach_with_retry_async(shm_name, timeout, poll_interval).await?);
+        self.arenas.write().insert(name, arena.clone());
+        Ok(arena)
+    }
+}
+
+impl Default for ShmManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ObjectPayload, ObjectType};
+    use ndarray::Array1;
+
+    /// Arena config with a name unique to the test run
+    fn config(size: usize) -> ShmConfig {
+        ShmConfig {
+            size,
+            name: format!("vistle_test_{}", uuid::Uuid::new_v4().simple()),
+            ..ShmConfig::default()
+        }
+    }
+
+    fn field(len: usize) -> Arc<dyn Object> {
+        Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: Array1::zeros(len) }))
+    }
+
+    fn stored_size(object: &Arc<dyn Object>) -> usize {
+        bincode::serialize(object.as_data().unwrap()).unwrap().len()
+    }
+
+    #[test]
+    fn quota_spans_all_arenas_of_an_owner() {
+        let manager = ShmManager::new();
+        let size = stored_size(&field(100));
+        manager.set_quota("a", size * 2);
+        let first = manager.create_owned_arena("a", "a1".to_string(), config(1 << 20)).unwrap();
+        let second = manager.create_owned_arena("a", "a2".to_string(), config(1 << 20)).unwrap();
+
+        first.store_object(field(100)).unwrap();
+        second.store_object(field(100)).unwrap();
+        match second.try_store_object(field(100)) {
+            Err(StoreError::Full(full)) => {
+                assert!(full.quota_exceeded);
+                assert_eq!(full.owner.as_deref(), Some("a"));
+                let usage = full.owner_usage.as_ref().unwrap();
+                assert_eq!(usage.used, size * 2);
+                assert_eq!(usage.quota, Some(size * 2));
+                assert!(full.to_string().contains("quota exceeded"));
+            }
+            other => panic!("expected the quota to run out, got {:?}", other.map(|_| ())),
+        }
+
+        let usage = &manager.usage_by_owner()["a"];
+        assert_eq!(usage.used, size * 2);
+        assert_eq!(usage.arenas, 2);
+    }
+
+    #[test]
+    fn second_owner_allocates_after_first_hits_its_quota() {
+        let manager = ShmManager::new();
+        manager.set_quota("first", 1);
+        let first = manager.create_owned_arena("first", "first".to_string(), config(1 << 20)).unwrap();
+        let second = manager.create_owned_arena("second", "second".to_string(), config(1 << 20)).unwrap();
+
+        assert!(matches!(first.try_store_object(field(10)), Err(StoreError::Full(_))));
+        second.store_object(field(10)).unwrap();
+        assert_eq!(manager.usage_by_owner()["first"].used, 0);
+        assert!(manager.usage_by_owner()["second"].used > 0);
+    }
+
+    #[test]
+    fn removing_objects_returns_their_bytes_to_the_quota() {
+        let manager = ShmManager::new();
+        let object = field(100);
+        manager.set_quota("a", stored_size(&object));
+        let arena = manager.create_owned_arena("a", "a".to_string(), config(1 << 20)).unwrap();
+
+        let id = arena.store_object(object).unwrap();
+        assert!(arena.try_store_object(field(100)).is_err());
+        assert!(arena.remove_object(id).unwrap());
+        assert_eq!(manager.usage_by_owner()["a"].used, 0);
+        arena.store_object(field(100)).unwrap();
+    }
+
+    #[test]
+    fn releasing_an_owner_drops_its_arenas_and_accounting() {
+        let manager = ShmManager::new();
+        manager.create_owned_arena("a", "a1".to_string(), config(1 << 16)).unwrap();
+        manager.create_owned_arena("a", "a2".to_string(), config(1 << 16)).unwrap();
+        manager.create_owned_arena("b", "b".to_string(), config(1 << 16)).unwrap();
+
+        assert_eq!(manager.release_owner("a"), 2);
+        assert!(manager.get_arena("a1").is_none());
+        assert!(manager.get_arena("b").is_some());
+        let usage = manager.usage_by_owner();
+        assert!(!usage.contains_key("a"));
+        assert_eq!(usage["b"].arenas, 1);
+    }
+
+    #[test]
+    fn releasing_an_owner_keeps_its_quota() {
+        let manager = ShmManager::new();
+        let object = field(100);
+        manager.set_quota("a", stored_size(&object));
+        let arena = manager.create_owned_arena("a", "a".to_string(), config(1 << 20)).unwrap();
+        arena.store_object(object).unwrap();
+        drop(arena);
+
+        assert_eq!(manager.release_owner("a"), 1);
+        let usage = &manager.usage_by_owner()["a"];
+        assert_eq!(usage.used, 0);
+        assert_eq!(usage.arenas, 0);
+        assert_eq!(usage.quota, Some(stored_size(&field(100))));
+
+        // The next run of the owner is held to the same quota
+        let arena = manager.create_owned_arena("a", "a".to_string(), config(1 << 20)).unwrap();
+        arena.store_object(field(100)).unwrap();
+        assert!(matches!(arena.try_store_object(field(100)), Err(StoreError::Full(full)) if full.quota_exceeded));
+    }
+
+    fn filled(len: usize, value: f32) -> Arc<dyn Object> {
+        Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: Array1::from_elem(len, value) }))
+    }
+
+    #[test]
+    fn a_full_arena_reports_what_would_have_fit() {
+        let size = stored_size(&field(100));
+        let arena = SharedArena::new(config(size * 3 + size / 2)).unwrap();
+        for _ in 0..3 {
+            arena.store_object(field(100)).unwrap();
+        }
+        match arena.try_store_object(field(100)) {
+            Err(StoreError::Full(full)) => {
+                assert_eq!(full.requested, size);
+                assert!(full.largest_free_block < size);
+                assert!(full.largest_free_block <= full.free);
+                assert!(!full.quota_exceeded);
+                assert!(full.owner.is_none());
+                let message = full.to_string();
+                assert!(message.contains(&format!("requested {} bytes", size)), "{}", message);
+            }
+            other => panic!("expected the arena to be full, got {:?}", other.map(|_| ())),
+        }
+        // store_object folds the same details into the error message
+        let error = arena.store_object(field(100)).unwrap_err().to_string();
+        assert!(error.contains("largest free block"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn retries_succeed_once_room_is_made() {
+        let size = stored_size(&field(100));
+        let arena = SharedArena::new(config(size + size / 2)).unwrap();
+        let first = arena.store_object(field(100)).unwrap();
+
+        let mut calls = 0;
+        let id = arena.store_object_with_retry(field(100), 3, Duration::from_millis(1), |full| {
+            calls += 1;
+            assert_eq!(full.requested, size);
+            arena.remove_object(first).unwrap()
+        }).await.unwrap();
+        assert_eq!(calls, 1);
+        assert!(arena.get_object(id).unwrap().is_some());
+
+        // Giving up, or running out of attempts, returns the last error
+        let mut calls = 0;
+        assert!(arena.store_object_with_retry(field(100), 3, Duration::from_millis(1), |_| { calls += 1; false }).await.is_err());
+        assert_eq!(calls, 1);
+        let mut calls = 0;
+        assert!(arena.store_object_with_retry(field(100), 3, Duration::from_millis(1), |_| { calls += 1; true }).await.is_err());
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn removing_an_object_two_views_read_waits_for_both() {
+        let arena = SharedArena::new(config(1 << 20)).unwrap();
+        let id = arena.store_object(filled(100, 3.0)).unwrap();
+        let (first, second) = (arena.view_object(id).unwrap(), arena.view_object(id).unwrap());
+
+        assert!(arena.remove_object(id).unwrap());
+        assert!(arena.get_object(id).unwrap().is_none());
+        // The block is still allocated and intact for both readers
+        assert!(arena.stats().used_size > 0);
+        for view in [&first, &second] {
+            assert!(matches!(view.decode().unwrap().payload(), Some(ObjectPayload::VecScalar { data }) if data.len() == 100));
+        }
+        drop(first);
+        assert!(arena.stats().used_size > 0);
+        drop(second);
+        assert_eq!(arena.stats().used_size, 0);
+    }
+
+    #[test]
+    fn fail_fast_removal_is_refused_until_both_views_are_done() {
+        let arena = SharedArena::new(config(1 << 20)).unwrap().with_removal_policy(RemovalPolicy::FailFast);
+        let id = arena.store_object(filled(100, 3.0)).unwrap();
+        let (first, second) = (arena.view_object(id).unwrap(), arena.view_object(id).unwrap());
+
+        assert!(matches!(arena.try_remove_object(id), Err(RemoveError::Busy(ObjectBusy { readers: 2, .. }))));
+        drop(first);
+        assert!(matches!(arena.try_remove_object(id), Err(RemoveError::Busy(ObjectBusy { readers: 1, .. }))));
+        assert!(second.decode().is_ok());
+        drop(second);
+        assert!(arena.try_remove_object(id).unwrap());
+        assert_eq!(arena.stats().used_size, 0);
+    }
+
+    /// Race a removal against two gets; each get sees the whole object or nothing
+    fn race_removal_against_two_gets(removal: RemovalPolicy) {
+        const ROUNDS: usize = 200;
+        let arena = SharedArena::new(config(1 << 20)).unwrap().with_removal_policy(removal);
+        for round in 0..ROUNDS {
+            let value = round as f32;
+            let id = arena.store_object(filled(64, value)).unwrap();
+            let start = std::sync::Barrier::new(3);
+            std::thread::scope(|scope| {
+                for _ in 0..2 {
+                    scope.spawn(|| {
+                        start.wait();
+                        if let Some(object) = arena.get_object(id).unwrap() {
+                            match object.payload() {
+                                Some(ObjectPayload::VecScalar { data }) => {
+                                    assert_eq!(data.len(), 64);
+                                    assert!(data.iter().all(|&v| v == value), "a get read a reused block");
+                                }
+                                other => panic!("unexpected payload {:?}", other),
+                            }
+                        }
+                    });
+                }
+                start.wait();
+                loop {
+                    match arena.try_remove_object(id) {
+                        Ok(removed) => {
+                            assert!(removed);
+                            break;
+                        }
+                        Err(RemoveError::Busy(_)) => {
+                            assert_eq!(removal, RemovalPolicy::FailFast);
+                            std::thread::yield_now();
+                        }
+                        Err(RemoveError::Failed(e)) => panic!("removal failed: {}", e),
+                    }
+                }
+            });
+            // Both gets are done, so no reader holds the block any more
+            assert_eq!(arena.stats().used_size, 0);
+        }
+    }
+
+    #[test]
+    fn deferred_removal_racing_two_gets() {
+        race_removal_against_two_gets(RemovalPolicy::Defer);
+    }
+
+    #[test]
+    fn fail_fast_removal_racing_two_gets() {
+        race_removal_against_two_gets(RemovalPolicy::FailFast);
+    }
+
+    #[test]
+    fn attaching_to_a_missing_arena_times_out() {
+        let name = config(0).name;
+        let started = Instant::now();
+        let error = SharedArena::attach_with_retry(&name, Duration::from_millis(50), Duration::from_millis(10)).unwrap_err();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(error.to_string().contains("Timed out"), "{}", error);
+    }
+
+    /// Create the arena of `config` after `delay` on another thread, keeping it until `done` fires
+    fn create_later(config: ShmConfig, delay: Duration) -> (std::sync::mpsc::Sender<()>, std::thread::JoinHandle<()>) {
+        let (done, wait) = std::sync::mpsc::channel();
+        let creator = std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            let _arena = SharedArena::new(config).unwrap();
+            let _ = wait.recv();
+        });
+        (done, creator)
+    }
+
+    #[test]
+    fn attaching_waits_for_an_arena_created_late() {
+        let config = config(1 << 16);
+        let name = config.name.clone();
+        let (done, creator) = create_later(config, Duration::from_millis(100));
+
+        let arena = SharedArena::attach_with_retry(&name, Duration::from_secs(10), Duration::from_millis(10)).unwrap();
+        assert_eq!(arena.stats().object_count, 0);
+        done.send(()).unwrap();
+        creator.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn async_attach_waits_without_blocking_the_runtime() {
+        let config = config(1 << 16);
+        let name = config.name.clone();
+        let (done, creator) = create_later(config, Duration::from_millis(100));
+
+        // A current-thread runtime would stall this timer if the attach blocked it
+        let ticker = tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Instant::now()
+        });
+        let manager = ShmManager::new();
+        let arena = manager
+            .attach_arena_with_retry_async("late".to_string(), &name, Duration::from_secs(10), Duration::from_millis(10))
+            .await
+            .unwrap();
+        let attached = Instant::now();
+        assert!(ticker.await.unwrap() < attached);
+        assert!(manager.get_arena("late").is_some());
+        drop(arena);
+        done.send(()).unwrap();
+        creator.join().unwrap();
+    }
+
+    #[test]
+    fn concurrent_stores_and_removals_from_eight_threads() {
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 200;
+
+        let arena = Arc::new(SharedArena::new(config(1 << 20)).unwrap());
+        let total = arena.stats().total_size;
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+
+        for t in 0..THREADS {
+            let arena = arena.clone();
+            let done_tx = done_tx.clone();
+            std::thread::spawn(move || {
+                let mut kept = Vec::new();
+                for round in 0..ROUNDS {
+                    let len = 16 + (t * 31 + round * 7) % 200;
+                    let id = arena.store_object(filled(len, t as f32)).unwrap();
+                    let object = arena.get_object(id).unwrap().expect("stored object is missing");
+                    match object.payload() {
+                        Some(ObjectPayload::VecScalar { data }) => {
+                            assert_eq!(data.len(), len);
+                            assert!(data.iter().all(|&v| v == t as f32), "thread {} read another thread's data", t);
+                        }
+                        other => panic!("unexpected payload {:?}", other),
+                    }
+                    // Keep a few objects alive for a while to fragment the free list
+                    kept.push(id);
+                    if kept.len() > 3 {
+                        assert!(arena.remove_object(kept.remove(0)).unwrap());
+                    }
+                    let stats = arena.stats();
+                    assert!(stats.used_size + stats.free_size <= stats.total_size);
+                }
+                for id in kept {
+                    assert!(arena.remove_object(id).unwrap());
+                }
+                done_tx.send(()).unwrap();
+            });
+        }
+        drop(done_tx);
+
+        for _ in 0..THREADS {
+            done_rx.recv_timeout(Duration::from_secs(60)).expect("a thread deadlocked or panicked");
+        }
+        let stats = arena.stats();
+        assert_eq!(stats.object_count, 0);
+        assert_eq!(stats.used_size, 0);
+        assert_eq!(stats.free_size, total);
+    }
+}
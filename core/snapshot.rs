@@ -0,0 +1,321 @@
+//! Saving and restoring registry objects in the native object file format
+//!
+//! A file starts with `OBJECT_FILE_MAGIC`, a little-endian u32 format
+//! version and the `CodecId` byte of the records, followed by one record per
+//! object: a u32 length and the encoded `ObjectData`. Records are
+//! independent, so a damaged one only loses that object. Version 1 files
+//! have no codec byte and bincode records. Since version 3, objects opted in
+//! to lossy compression are written compressed, see `core::lossy`, and
+//! decompressed again when read.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{CodecId, Object, ObjectData, ObjectId, ObjectRegistry, VistleObject};
+
+/// Magic bytes at the start of a native object file
+pub const OBJECT_FILE_MAGIC: [u8; 8] = *b"VISTLOBJ";
+
+/// Version of the record layout
+pub const OBJECT_FILE_VERSION: u32 = 3;
+
+/// Magic and version; version 2 adds a codec byte, version 3 lossy payloads
+const HEADER_LEN: usize = OBJECT_FILE_MAGIC.len() + 4;
+
+/// Objects written by `ObjectRegistry::snapshot`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotSummary {
+    pub written: usize,
+    /// Objects without a generic data container, which the format cannot hold
+    pub unsupported: Vec<ObjectId>,
+}
+
+/// An object record that could not be restored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreFailure {
+    /// Position of the record in the file
+    pub index: usize,
+    /// Byte offset of the record
+    pub offset: usize,
+    pub error: String,
+}
+
+/// Outcome of `ObjectRegistry::restore`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RestoreSummary {
+    pub restored: Vec<ObjectId>,
+    /// Objects whose id was already registered; the registered object is kept
+    pub existing: Vec<ObjectId>,
+    pub failures: Vec<RestoreFailure>,
+}
+
+impl RestoreSummary {
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl ObjectRegistry {
+    /// Write every registered object to a native object file, keeping ids
+    pub async fn snapshot(&self, path: impl AsRef<Path>) -> Result<SnapshotSummary, crate::Error> {
+        self.snapshot_filtered(path, |_| true).await
+    }
+
+    /// Write the registered objects accepted by `filter`
+    pub async fn snapshot_filtered(
+        &self,
+        path: impl AsRef<Path>,
+        filter: impl Fn(&dyn Object) -> bool,
+    ) -> Result<SnapshotSummary, crate::Error> {
+        self.snapshot_with_codec(path, CodecId::default(), filter).await
+    }
+
+    /// Write the registered objects accepted by `filter` with records encoded by `codec`
+    pub async fn snapshot_with_codec(
+        &self,
+        path: impl AsRef<Path>,
+        codec: CodecId,
+        filter: impl Fn(&dyn Object) -> bool,
+    ) -> Result<SnapshotSummary, crate::Error> {
+        let mut objects: Vec<Arc<dyn Object>> = self.iter()
+            .filter(|entry| filter(entry.value().as_ref()))
+            .map(|entry| entry.value().clone())
+            .collect();
+        // Stable order so repeated snapshots of the same objects are identical
+        objects.sort_by_key(|o| o.id().to_string());
+
+        let mut summary = SnapshotSummary::default();
+        let mut records = Vec::with_capacity(objects.len());
+        for object in &objects {
+            match object.as_data() {
+                Some(data) => records.push(data),
+                None => summary.unsupported.push(object.id()),
+            }
+        }
+        let buffer = encode_object_file(&records, codec)?;
+        summary.written = records.len();
+
+        if !summary.unsupported.is_empty() {
+            tracing::warn!("Snapshot skipped {} objects without a data container", summary.unsupported.len());
+        }
+        crate::util::io::write_binary(path, &buffer).await?;
+        Ok(summary)
+    }
+
+    /// Load the objects of a native object file
+    ///
+    /// Ids already in the registry are left alone. Records that fail to
+    /// decode are reported in the summary and the remaining ones are still
+    /// restored; only an unreadable file or a wrong header is an error.
+    pub async fn restore(&self, path: impl AsRef<Path>) -> Result<RestoreSummary, crate::Error> {
+        let path = path.as_ref();
+        let bytes = crate::util::io::read_binary(path).await?;
+        let (objects, failures) = decode_object_file(path, &bytes)?;
+
+        let mut summary = RestoreSummary {
+            failures,
+            ..RestoreSummary::default()
+        };
+        for data in objects {
+            if self.get(data.id).is_some() {
+                summary.existing.push(data.id);
+            } else {
+                summary.restored.push(self.store(Arc::new(VistleObject::from_data(data))));
+            }
+        }
+
+        tracing::info!(
+            "Restored {} objects from {} ({} already present, {} failed)",
+            summary.restored.len(), path.display(), summary.existing.len(), summary.failures.len()
+        );
+        Ok(summary)
+    }
+}
+
+/// Write objects to a native object file in the given order, keeping ids
+pub async fn write_object_file(path: impl AsRef<Path>, objects: &[ObjectData]) -> Result<(), crate::Error> {
+    let records: Vec<&ObjectData> = objects.iter().collect();
+    let buffer = encode_object_file(&records, CodecId::default())?;
+    crate::util::io::write_binary(path, &buffer).await
+}
+
+/// Read the objects of a native object file in file order
+///
+/// Unlike `ObjectRegistry::restore`, a record that fails to decode is an error.
+pub async fn read_object_file(path: impl AsRef<Path>) -> Result<Vec<ObjectData>, crate::Error> {
+    let path = path.as_ref();
+    let bytes = crate::util::io::read_binary(path).await?;
+    let (objects, failures) = decode_object_file(path, &bytes)?;
+    match failures.first() {
+        Some(failure) => Err(crate::Error::Config(format!(
+            "{}: record {} at byte {}: {}",
+            path.display(), failure.index, failure.offset, failure.error
+        ))),
+        None => Ok(objects),
+    }
+}
+
+fn encode_object_file(objects: &[&ObjectData], codec: CodecId) -> Result<Vec<u8>, crate::Error> {
+    let mut buffer = Vec::with_capacity(HEADER_LEN + 1);
+    buffer.extend_from_slice(&OBJECT_FILE_MAGIC);
+    buffer.extend_from_slice(&OBJECT_FILE_VERSION.to_le_bytes());
+    buffer.push(codec as u8);
+    for data in objects {
+        let lossy = if data.accepts_lossy() { Some(data.to_lossy()?) } else { None };
+        let record = codec.serialize(lossy.as_ref().unwrap_or(*data))?;
+        let len = u32::try_from(record.len()).map_err(|_| crate::Error::Config(format!(
+            "Object {} is too large for the object file format",
+            data.id
+        )))?;
+        buffer.extend_from_slice(&len.to_le_bytes());
+        buffer.extend_from_slice(&record);
+    }
+    Ok(buffer)
+}
+
+/// Objects of a native object file and the records that failed to decode
+///
+/// Only an unreadable header is an error; `path` names the file in it.
+fn decode_object_file(path: &Path, bytes: &[u8]) -> Result<(Vec<ObjectData>, Vec<RestoreFailure>), crate::Error> {
+    if bytes.len() < HEADER_LEN || bytes[..OBJECT_FILE_MAGIC.len()] != OBJECT_FILE_MAGIC {
+        return Err(crate::Error::Config(format!("{} is not a Vistle object file", path.display())));
+    }
+    let version = u32::from_le_bytes(bytes[OBJECT_FILE_MAGIC.len()..HEADER_LEN].try_into().unwrap());
+    let (codec, mut offset) = match version {
+        1 => (CodecId::Bincode, HEADER_LEN),
+        2 | OBJECT_FILE_VERSION => {
+            let byte = *bytes.get(HEADER_LEN).ok_or_else(|| crate::Error::Config(format!(
+                "{} ends before its codec byte", path.display()
+            )))?;
+            let codec = CodecId::from_byte(byte)?;
+            if !codec.is_available() {
+                return Err(crate::Error::Config(format!(
+                    "{} was written with codec {}, which this build lacks",
+                    path.display(), codec
+                )));
+            }
+            (codec, HEADER_LEN + 1)
+        }
+        _ => return Err(crate::Error::Config(format!(
+            "{} has object file version {}, expected 1 to {}",
+            path.display(), version, OBJECT_FILE_VERSION
+        ))),
+    };
+
+    let mut objects = Vec::new();
+    let mut failures = Vec::new();
+    let mut index = 0;
+    while offset < bytes.len() {
+        let fail = |error: String| RestoreFailure { index, offset, error };
+        let Some(len) = bytes.get(offset..offset + 4) else {
+            failures.push(fail("Truncated record length".to_string()));
+            break;
+        };
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let Some(record) = bytes.get(offset + 4..offset + 4 + len) else {
+            failures.push(fail(format!("Truncated record: {} bytes announced", len)));
+            break;
+        };
+
+        match codec.deserialize::<ObjectData>(record) {
+            Ok(mut data) => match data.decompress_lossy() {
+                Ok(()) => objects.push(data),
+                Err(e) => failures.push(fail(e.to_string())),
+            },
+            Err(e) => failures.push(fail(e.to_string())),
+        }
+        offset += 4 + len;
+        index += 1;
+    }
+    Ok((objects, failures))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ObjectPayload, ObjectType};
+
+    fn field(value: f32) -> Arc<dyn Object> {
+        Arc::new(VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar {
+            data: ndarray::Array1::from_elem(16, value),
+        }))
+    }
+
+    fn temp_file() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("vistle_snapshot_{}.vobj", uuid::Uuid::new_v4().simple()))
+    }
+
+    /// Byte ranges of the records of a file written with a codec byte
+    fn records(bytes: &[u8]) -> Vec<std::ops::Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut offset = HEADER_LEN + 1;
+        while offset < bytes.len() {
+            let len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            ranges.push(offset + 4..offset + 4 + len);
+            offset += 4 + len;
+        }
+        ranges
+    }
+
+    #[tokio::test]
+    async fn snapshots_restore_with_their_ids() {
+        let path = temp_file();
+        let registry = ObjectRegistry::new();
+        let ids: Vec<ObjectId> = (0..3).map(|i| registry.store(field(i as f32))).collect();
+        assert_eq!(registry.snapshot(&path).await.unwrap().written, 3);
+
+        let restored = ObjectRegistry::new();
+        restored.store(registry.get(ids[0]).unwrap());
+        let summary = restored.restore(&path).await.unwrap();
+        assert!(summary.is_complete());
+        assert_eq!(summary.existing, [ids[0]]);
+        assert_eq!(summary.restored.len(), 2);
+        for id in &ids[1..] {
+            assert!(summary.restored.contains(id));
+            assert!(restored.get(*id).is_some());
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn damaged_records_lose_only_their_objects() {
+        let path = temp_file();
+        let registry = ObjectRegistry::new();
+        for i in 0..4 {
+            registry.store(field(i as f32));
+        }
+        registry.snapshot(&path).await.unwrap();
+        let mut ids: Vec<ObjectId> = registry.iter().map(|entry| *entry.key()).collect();
+        ids.sort_by_key(|id| id.to_string());
+
+        // Garble the second record and cut the file in the middle of the last one
+        let mut bytes = std::fs::read(&path).unwrap();
+        let ranges = records(&bytes);
+        bytes[ranges[1].clone()].fill(0xff);
+        bytes.truncate(ranges[3].start + 8);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let restored = ObjectRegistry::new();
+        let summary = restored.restore(&path).await.unwrap();
+        assert!(!summary.is_complete());
+        assert_eq!(summary.restored, [ids[0], ids[2]]);
+        let failed: Vec<usize> = summary.failures.iter().map(|f| f.index).collect();
+        assert_eq!(failed, [1, 3]);
+        assert_eq!(summary.failures[0].offset, ranges[1].start - 4);
+        assert!(summary.failures[1].error.contains("Truncated record"), "{}", summary.failures[1].error);
+
+        // Reading the file as a whole refuses it
+        assert!(read_object_file(&path).await.is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn files_of_other_formats_are_refused() {
+        let path = temp_file();
+        std::fs::write(&path, b"not an object file").unwrap();
+        assert!(ObjectRegistry::new().restore(&path).await.is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,179 @@
+//! Uniform, rectilinear and structured grids
+//!
+//! All three have points on a logical (i, j, k) lattice, numbered with i
+//! fastest. They differ in how a lattice index maps to a position: by
+//! origin and spacing, by one coordinate array per axis, or by an explicit
+//! position per point. Filters such as slicing work on the lattice and use
+//! `ObjectPayload::grid_point` to place what they produce.
+
+use nalgebra::Vector3;
+use ndarray::{Array1, Array2};
+
+use crate::core::{ObjectPayload, ObjectType, VistleObject};
+
+impl ObjectPayload {
+    /// Points along each axis of uniform, rectilinear and structured grids
+    pub fn grid_dims(&self) -> Option<[usize; 3]> {
+        match self {
+            ObjectPayload::UniformGrid { dims, .. } | ObjectPayload::StructuredGrid { dims, .. } => Some(*dims),
+            ObjectPayload::RectilinearGrid { x, y, z } => Some([x.len(), y.len(), z.len()]),
+            _ => None,
+        }
+    }
+
+    /// Linear index of lattice point (i, j, k), `None` outside the grid
+    pub fn point_index(&self, [i, j, k]: [usize; 3]) -> Option<usize> {
+        let [nx, ny, nz] = self.grid_dims()?;
+        (i < nx && j < ny && k < nz).then(|| (k * ny + j) * nx + i)
+    }
+
+    /// Lattice point (i, j, k) of a linear index
+    pub fn point_ijk(&self, index: usize) -> Option<[usize; 3]> {
+        let [nx, ny, nz] = self.grid_dims()?;
+        (index < nx * ny * nz).then(|| [index % nx, index / nx % ny, index / (nx * ny)])
+    }
+
+    /// Position of lattice point (i, j, k), in the object's own coordinates
+    pub fn grid_point(&self, ijk: [usize; 3]) -> Option<Vector3<f32>> {
+        let index = self.point_index(ijk)?;
+        Some(match self {
+            ObjectPayload::UniformGrid { origin, spacing, .. } => {
+                Vector3::from_fn(|a, _| origin[a] + ijk[a] as f32 * spacing[a])
+            }
+            ObjectPayload::RectilinearGrid { x, y, z } => Vector3::new(x[ijk[0]], y[ijk[1]], z[ijk[2]]),
+            ObjectPayload::StructuredGrid { coordinates, .. } => Vector3::new(
+                coordinates[(index, 0)],
+                coordinates[(index, 1)],
+                coordinates[(index, 2)],
+            ),
+            _ => return None,
+        })
+    }
+}
+
+impl VistleObject {
+    /// Uniform grid with one value per point
+    pub fn uniform_grid(
+        dims: [usize; 3],
+        origin: [f32; 3],
+        spacing: [f32; 3],
+        values: Array1<f32>,
+    ) -> Result<Self, crate::Error> {
+        let points: usize = dims.iter().product();
+        if values.len() != points {
+            return Err(crate::Error::Compute(format!(
+                "UniformGrid: {} values for {:?} points",
+                values.len(), dims
+            )));
+        }
+        Ok(Self::with_data(ObjectType::UniformGrid, ObjectPayload::UniformGrid { dims, origin, spacing, values }))
+    }
+
+    /// Rectilinear grid from the coordinates of its points along each axis
+    pub fn rectilinear_grid(x: Array1<f32>, y: Array1<f32>, z: Array1<f32>) -> Self {
+        Self::with_data(ObjectType::RectilinearGrid, ObjectPayload::RectilinearGrid { x, y, z })
+    }
+
+    /// Structured grid with one row of coordinates per point, i fastest
+    pub fn structured_grid(dims: [usize; 3], coordinates: Array2<f32>) -> Result<Self, crate::Error> {
+        let points: usize = dims.iter().product();
+        if coordinates.nrows() != points || coordinates.ncols() != 3 {
+            return Err(crate::Error::Compute(format!(
+                "StructuredGrid: coordinates of shape {:?} for {:?} points, expected {} rows of 3",
+                coordinates.shape(), dims, points
+            )));
+        }
+        Ok(Self::with_data(ObjectType::StructuredGrid, ObjectPayload::StructuredGrid { dims, coordinates }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    fn roundtrip(object: &VistleObject) -> ObjectPayload {
+        bincode::deserialize(&bincode::serialize(object.data()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn uniform_grids_place_points_by_origin_and_spacing() {
+        let grid = VistleObject::uniform_grid([2, 3, 4], [1.0, 0.0, -1.0], [0.5, 1.0, 2.0], Array1::zeros(24)).unwrap();
+        let payload = grid.data();
+        assert_eq!(payload.num_vertices(), 24);
+        assert_eq!(payload.point_index([1, 2, 3]), Some(23));
+        assert_eq!(payload.point_ijk(23), Some([1, 2, 3]));
+        assert_eq!(payload.point_index([2, 0, 0]), None);
+        assert_eq!(payload.point_ijk(24), None);
+        assert_eq!(payload.grid_point([1, 2, 3]), Some(Vector3::new(1.5, 2.0, 5.0)));
+
+        let ObjectPayload::UniformGrid { dims, origin, spacing, values } = roundtrip(&grid) else {
+            panic!("not a uniform grid");
+        };
+        assert_eq!((dims, origin, spacing, values.len()), ([2, 3, 4], [1.0, 0.0, -1.0], [0.5, 1.0, 2.0], 24));
+    }
+
+    #[test]
+    fn uniform_grids_need_one_value_per_point() {
+        let result = VistleObject::uniform_grid([2, 2, 2], [0.0; 3], [1.0; 3], Array1::zeros(7));
+        assert!(matches!(result, Err(crate::Error::Compute(_))));
+    }
+
+    #[test]
+    fn degenerate_dims_count_points_along_one_axis() {
+        let grid = VistleObject::uniform_grid([1, 5, 1], [0.0; 3], [1.0; 3], Array1::zeros(5)).unwrap();
+        assert_eq!(grid.data().num_vertices(), 5);
+        assert_eq!(grid.data().point_ijk(4), Some([0, 4, 0]));
+
+        let line = VistleObject::rectilinear_grid(array![0.0], array![0.0, 1.0, 3.0], array![2.0]);
+        assert_eq!(line.data().num_vertices(), 3);
+        assert_eq!(line.data().grid_point([0, 2, 0]), Some(Vector3::new(0.0, 3.0, 2.0)));
+
+        let empty = VistleObject::uniform_grid([0, 4, 1], [0.0; 3], [1.0; 3], Array1::zeros(0)).unwrap();
+        assert_eq!(empty.data().num_vertices(), 0);
+        assert_eq!(empty.data().point_index([0, 0, 0]), None);
+    }
+
+    #[test]
+    fn rectilinear_grids_take_one_coordinate_per_axis() {
+        let grid = VistleObject::rectilinear_grid(array![0.0, 1.0], array![0.0, 0.5, 2.0], array![-1.0, 4.0]);
+        let payload = grid.data();
+        assert_eq!(payload.grid_dims(), Some([2, 3, 2]));
+        assert_eq!(payload.grid_point([1, 2, 1]), Some(Vector3::new(1.0, 2.0, 4.0)));
+
+        let ObjectPayload::RectilinearGrid { x, y, z } = roundtrip(&grid) else {
+            panic!("not a rectilinear grid");
+        };
+        assert_eq!((x.to_vec(), y.to_vec(), z.to_vec()), (vec![0.0, 1.0], vec![0.0, 0.5, 2.0], vec![-1.0, 4.0]));
+    }
+
+    #[test]
+    fn structured_grids_read_one_row_per_point() {
+        let coordinates = Array2::from_shape_fn((6, 3), |(p, a)| (p * 3 + a) as f32);
+        let grid = VistleObject::structured_grid([3, 2, 1], coordinates.clone()).unwrap();
+        let payload = grid.data();
+        assert_eq!(payload.num_vertices(), 6);
+        assert_eq!(payload.grid_point([1, 1, 0]), Some(Vector3::new(12.0, 13.0, 14.0)));
+        assert_eq!(payload.grid_point([0, 2, 0]), None);
+
+        let ObjectPayload::StructuredGrid { dims, coordinates: restored } = roundtrip(&grid) else {
+            panic!("not a structured grid");
+        };
+        assert_eq!(dims, [3, 2, 1]);
+        assert_eq!(restored, coordinates);
+    }
+
+    #[test]
+    fn structured_grids_need_three_coordinates_per_point() {
+        assert!(VistleObject::structured_grid([3, 2, 1], Array2::zeros((5, 3))).is_err());
+        assert!(VistleObject::structured_grid([3, 2, 1], Array2::zeros((6, 2))).is_err());
+    }
+
+    #[test]
+    fn other_payloads_are_not_grids() {
+        let points = ObjectPayload::Points { coordinates: Array2::zeros((4, 3)) };
+        assert_eq!(points.grid_dims(), None);
+        assert_eq!(points.grid_point([0, 0, 0]), None);
+        assert_eq!(points.num_vertices(), 4);
+    }
+}
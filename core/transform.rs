@@ -0,0 +1,224 @@
+//! Affine transform helpers for object metadata
+
+use nalgebra::{Matrix3, Matrix4, Rotation3, Unit, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// Translation, rotation and scale making up an affine transform
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Decomposed {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Decomposed {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Build a matrix applying scale, then rotation, then translation
+pub fn compose(translation: &Vector3<f32>, rotation: &UnitQuaternion<f32>, scale: &Vector3<f32>) -> Matrix4<f32> {
+    Matrix4::new_translation(translation)
+        * rotation.to_homogeneous()
+        * Matrix4::new_nonuniform_scaling(scale)
+}
+
+/// Split an affine matrix into translation, rotation and scale
+///
+/// Returns `None` for projective or singular matrices. Shear is not
+/// representable and is folded into the rotation's best fit.
+pub fn decompose(m: &Matrix4<f32>) -> Option<Decomposed> {
+    if !is_affine(m) {
+        return None;
+    }
+
+    let linear: Matrix3<f32> = m.fixed_view::<3, 3>(0, 0).into();
+    let mut scale = Vector3::new(
+        linear.column(0).norm(),
+        linear.column(1).norm(),
+        linear.column(2).norm(),
+    );
+    if scale.iter().any(|&s| s <= f32::EPSILON) {
+        return None;
+    }
+
+    // A mirrored basis is represented by a negative x scale
+    if linear.determinant() < 0.0 {
+        scale.x = -scale.x;
+    }
+
+    let mut basis = linear;
+    for c in 0..3 {
+        let column = basis.column(c) / scale[c];
+        basis.set_column(c, &column);
+    }
+
+    Some(Decomposed {
+        translation: Vector3::new(m[(0, 3)], m[(1, 3)], m[(2, 3)]),
+        rotation: UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix(&basis)),
+        scale,
+    })
+}
+
+/// Check that the last row is (0, 0, 0, 1)
+pub fn is_affine(m: &Matrix4<f32>) -> bool {
+    const EPS: f32 = 1e-6;
+    m[(3, 0)].abs() < EPS && m[(3, 1)].abs() < EPS && m[(3, 2)].abs() < EPS && (m[(3, 3)] - 1.0).abs() < EPS
+}
+
+/// Check that the matrix is affine and invertible
+pub fn is_regular_affine(m: &Matrix4<f32>) -> bool {
+    let linear: Matrix3<f32> = m.fixed_view::<3, 3>(0, 0).into();
+    is_affine(m) && linear.determinant().abs() > f32::EPSILON
+}
+
+/// Rotation from Euler angles in degrees, applied in x, y, z order
+pub fn rotation_from_euler(degrees: &Vector3<f32>) -> UnitQuaternion<f32> {
+    UnitQuaternion::from_euler_angles(
+        degrees.x.to_radians(),
+        degrees.y.to_radians(),
+        degrees.z.to_radians(),
+    )
+}
+
+/// Rotation about an axis by an angle in degrees
+pub fn rotation_from_axis_angle(axis: &Vector3<f32>, degrees: f32) -> UnitQuaternion<f32> {
+    match Unit::try_new(*axis, f32::EPSILON) {
+        Some(axis) => UnitQuaternion::from_axis_angle(&axis, degrees.to_radians()),
+        None => UnitQuaternion::identity(),
+    }
+}
+
+/// Transform a point (w = 1)
+pub fn transform_point(m: &Matrix4<f32>, p: &Vector3<f32>) -> Vector3<f32> {
+    m.transform_point(&nalgebra::Point3::from(*p)).coords
+}
+
+/// Transform a direction, ignoring translation
+pub fn transform_vector(m: &Matrix4<f32>, v: &Vector3<f32>) -> Vector3<f32> {
+    m.transform_vector(v)
+}
+
+/// Matrix for transforming normals: the inverse transpose of the linear part
+pub fn normal_matrix(m: &Matrix4<f32>) -> Option<Matrix3<f32>> {
+    let linear: Matrix3<f32> = m.fixed_view::<3, 3>(0, 0).into();
+    linear.try_inverse().map(|inv| inv.transpose())
+}
+
+/// Build a matrix from 16 values in row-major order
+pub fn from_row_slice(values: &[f32]) -> Option<Matrix4<f32>> {
+    if values.len() == 16 {
+        Some(Matrix4::from_row_slice(values))
+    } else {
+        None
+    }
+}
+
+/// Axis-aligned bounds of a box after transformation (all eight corners are mapped)
+pub fn transform_bounds(m: &Matrix4<f32>, bounds: (Vector3<f32>, Vector3<f32>)) -> (Vector3<f32>, Vector3<f32>) {
+    let (lo, hi) = bounds;
+    let mut min = Vector3::repeat(f32::INFINITY);
+    let mut max = Vector3::repeat(f32::NEG_INFINITY);
+    for corner in 0..8 {
+        let p = Vector3::new(
+            if corner & 1 == 0 { lo.x } else { hi.x },
+            if corner & 2 == 0 { lo.y } else { hi.y },
+            if corner & 4 == 0 { lo.z } else { hi.z },
+        );
+        let q = transform_point(m, &p);
+        min = min.inf(&q);
+        max = max.sup(&q);
+    }
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &Vector3<f32>, b: &Vector3<f32>) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    #[test]
+    fn decompose_inverts_compose() {
+        let translation = Vector3::new(1.0, -2.0, 3.0);
+        let rotation = rotation_from_euler(&Vector3::new(30.0, 45.0, 60.0));
+        let scale = Vector3::new(2.0, 0.5, 3.0);
+        let parts = decompose(&compose(&translation, &rotation, &scale)).unwrap();
+
+        assert!(close(&parts.translation, &translation));
+        assert!(close(&parts.scale, &scale));
+        assert!(parts.rotation.angle_to(&rotation) < 1e-4);
+    }
+
+    #[test]
+    fn mirrors_decompose_to_a_negative_x_scale() {
+        let m = Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, -1.0, 1.0));
+        let parts = decompose(&m).unwrap();
+        assert!(parts.scale.x < 0.0);
+        assert!((compose(&parts.translation, &parts.rotation, &parts.scale) - m).norm() < 1e-5);
+    }
+
+    #[test]
+    fn projective_and_singular_matrices_do_not_decompose() {
+        let mut projective = Matrix4::identity();
+        projective[(3, 2)] = 1.0;
+        assert!(decompose(&projective).is_none());
+        assert!(!is_affine(&projective));
+
+        let flat = Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, 1.0, 0.0));
+        assert!(decompose(&flat).is_none());
+        assert!(!is_regular_affine(&flat));
+        assert!(normal_matrix(&flat).is_none());
+    }
+
+    #[test]
+    fn vectors_ignore_translation() {
+        let m = compose(&Vector3::new(5.0, 5.0, 5.0), &rotation_from_axis_angle(&Vector3::z(), 90.0), &Vector3::repeat(1.0));
+        assert!(close(&transform_vector(&m, &Vector3::x()), &Vector3::y()));
+        assert!(close(&transform_point(&m, &Vector3::x()), &Vector3::new(5.0, 6.0, 5.0)));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_nonuniform_scale() {
+        let m = Matrix4::new_nonuniform_scaling(&Vector3::new(4.0, 1.0, 1.0));
+        // Tangent and normal of the plane x + y = 0
+        let tangent = transform_vector(&m, &Vector3::new(1.0, -1.0, 0.0));
+        let normal = normal_matrix(&m).unwrap() * Vector3::new(1.0, 1.0, 0.0);
+        assert!(tangent.dot(&normal).abs() < 1e-5);
+        // Transforming the normal like a vector would not be
+        assert!(tangent.dot(&transform_vector(&m, &Vector3::new(1.0, 1.0, 0.0))).abs() > 1.0);
+    }
+
+    #[test]
+    fn degenerate_axes_give_no_rotation() {
+        assert_eq!(rotation_from_axis_angle(&Vector3::zeros(), 90.0), UnitQuaternion::identity());
+    }
+
+    #[test]
+    fn row_slices_need_sixteen_values() {
+        let m = from_row_slice(&[
+            1.0, 0.0, 0.0, 7.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ]).unwrap();
+        assert_eq!(m[(0, 3)], 7.0);
+        assert!(from_row_slice(&[1.0; 9]).is_none());
+    }
+
+    #[test]
+    fn bounds_cover_every_rotated_corner() {
+        let m = compose(&Vector3::zeros(), &rotation_from_axis_angle(&Vector3::z(), 45.0), &Vector3::repeat(1.0));
+        let (min, max) = transform_bounds(&m, (Vector3::repeat(-1.0), Vector3::repeat(1.0)));
+        let half_diagonal = 2.0f32.sqrt();
+        assert!(close(&min, &Vector3::new(-half_diagonal, -half_diagonal, -1.0)));
+        assert!(close(&max, &Vector3::new(half_diagonal, half_diagonal, 1.0)));
+    }
+}
@@ -0,0 +1,340 @@
+//! Physical units of fields and conversion between them
+
+use std::fmt;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{attribute, Object};
+
+/// Exponents of the SI base dimensions a unit is composed of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Dimensions {
+    pub length: i8,
+    pub mass: i8,
+    pub time: i8,
+    pub temperature: i8,
+}
+
+impl Dimensions {
+    const fn new(length: i8, mass: i8, time: i8, temperature: i8) -> Self {
+        Self { length, mass, time, temperature }
+    }
+
+    /// `self + other * power`, `None` if an exponent leaves the range of i8
+    fn scaled_add(self, other: Self, power: i8) -> Option<Self> {
+        let add = |a: i8, b: i8| b.checked_mul(power).and_then(|b| a.checked_add(b));
+        Some(Self {
+            length: add(self.length, other.length)?,
+            mass: add(self.mass, other.mass)?,
+            time: add(self.time, other.time)?,
+            temperature: add(self.temperature, other.temperature)?,
+        })
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_dimensionless() {
+            return write!(f, "1");
+        }
+        let terms: Vec<String> = [("kg", self.mass), ("m", self.length), ("s", self.time), ("K", self.temperature)]
+            .iter()
+            .filter(|(_, power)| *power != 0)
+            .map(|(symbol, power)| match power {
+                1 => symbol.to_string(),
+                _ => format!("{}^{}", symbol, power),
+            })
+            .collect();
+        write!(f, "{}", terms.join(" "))
+    }
+}
+
+/// Units without prefix: symbol, factor to SI base units, dimensions
+const BASE_UNITS: &[(&str, f64, Dimensions)] = &[
+    ("m", 1.0, Dimensions::new(1, 0, 0, 0)),
+    ("g", 1e-3, Dimensions::new(0, 1, 0, 0)),
+    ("s", 1.0, Dimensions::new(0, 0, 1, 0)),
+    ("K", 1.0, Dimensions::new(0, 0, 0, 1)),
+    ("Pa", 1.0, Dimensions::new(-1, 1, -2, 0)),
+    ("N", 1.0, Dimensions::new(1, 1, -2, 0)),
+    ("J", 1.0, Dimensions::new(2, 1, -2, 0)),
+    ("W", 1.0, Dimensions::new(2, 1, -3, 0)),
+    ("1", 1.0, Dimensions::new(0, 0, 0, 0)),
+];
+
+const PREFIXES: &[(&str, f64)] = &[
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("da", 1e1),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+];
+
+/// A unit such as `kPa`, `m/s` or `kg/m^3`
+///
+/// Terms are separated by `*`, `.` or spaces for multiplication and `/`
+/// for division of the following term only; each term is an optionally
+/// prefixed unit with an optional integer power (`m^-2`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Units {
+    text: String,
+    scale: f64,
+    dimensions: Dimensions,
+}
+
+impl Units {
+    pub fn parse(text: &str) -> Result<Self, crate::Error> {
+        let text = text.trim();
+        let mut scale = 1.0;
+        let mut dimensions = Dimensions::default();
+
+        let mut divide = false;
+        let mut term = String::new();
+        let mut apply = |term: &mut String, divide: bool| -> Result<(), crate::Error> {
+            if term.is_empty() {
+                return Ok(());
+            }
+            let (factor, dims, power) = parse_term(term)
+                .ok_or_else(|| crate::Error::Config(format!("Unknown unit {} in {}", term, text)))?;
+            let overflow = || crate::Error::Config(format!("Exponent of {} in {} is out of range", term, text));
+            let power = if divide { power.checked_neg().ok_or_else(overflow)? } else { power };
+            scale *= factor.powi(power as i32);
+            dimensions = dimensions.scaled_add(dims, power).ok_or_else(overflow)?;
+            term.clear();
+            Ok(())
+        };
+
+        for c in text.chars() {
+            match c {
+                '*' | '.' | ' ' | '·' => {
+                    apply(&mut term, divide)?;
+                    divide = false;
+                }
+                '/' => {
+                    apply(&mut term, divide)?;
+                    divide = true;
+                }
+                c => term.push(c),
+            }
+        }
+        apply(&mut term, divide)?;
+
+        Ok(Self {
+            text: text.to_string(),
+            scale,
+            dimensions,
+        })
+    }
+
+    pub fn dimensionless() -> Self {
+        Self {
+            text: String::new(),
+            scale: 1.0,
+            dimensions: Dimensions::default(),
+        }
+    }
+
+    /// The unit as written
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Factor converting a value in these units to SI base units
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        self.dimensions
+    }
+
+    pub fn is_compatible(&self, other: &Units) -> bool {
+        self.dimensions == other.dimensions
+    }
+
+    /// Factor to multiply values in these units by to express them in `other`
+    pub fn conversion_factor(&self, other: &Units) -> Result<f64, crate::Error> {
+        if !self.is_compatible(other) {
+            return Err(crate::Error::Compute(format!(
+                "Cannot convert {} ({}) to {} ({})",
+                self, self.dimensions, other, other.dimensions
+            )));
+        }
+        Ok(self.scale / other.scale)
+    }
+
+    /// Units stored in an object's units attribute, if it has one
+    pub fn of(object: &dyn Object) -> Result<Option<Self>, crate::Error> {
+        object.get_attribute(attribute::UNITS).map(Self::parse).transpose()
+    }
+
+    /// Common units of fields that are about to be combined
+    ///
+    /// Fails if any two fields have incompatible dimensions; differently
+    /// scaled but compatible units only produce a warning.
+    pub fn common(fields: &[Arc<dyn Object>]) -> Result<Option<Self>, crate::Error> {
+        let mut common: Option<Self> = None;
+        for field in fields {
+            let Some(units) = Self::of(field.as_ref())? else {
+                continue;
+            };
+            match &common {
+                None => common = Some(units),
+                Some(first) if !first.is_compatible(&units) => {
+                    return Err(crate::Error::Compute(format!(
+                        "Cannot combine fields in {} and {}: incompatible dimensions",
+                        first, units
+                    )));
+                }
+                Some(first) if first.scale != units.scale => {
+                    tracing::warn!("Combining fields in {} and {}; convert them with ConvertUnits first", first, units);
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(common)
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.text.is_empty() {
+            write!(f, "1")
+        } else {
+            write!(f, "{}", self.text)
+        }
+    }
+}
+
+/// Parse `<prefix><unit>[^<power>]`
+fn parse_term(term: &str) -> Option<(f64, Dimensions, i8)> {
+    let (symbol, power) = match term.split_once('^') {
+        Some((symbol, power)) => (symbol, power.parse::<i8>().ok()?),
+        None => (term, 1),
+    };
+
+    let base = |s: &str| BASE_UNITS.iter().find(|(name, _, _)| *name == s).map(|&(_, f, d)| (f, d));
+    if let Some((factor, dims)) = base(symbol) {
+        return Some((factor, dims, power));
+    }
+    PREFIXES.iter()
+        .filter_map(|&(prefix, p)| {
+            let rest = symbol.strip_prefix(prefix)?;
+            let (factor, dims) = base(rest).filter(|_| rest != "1")?;
+            Some((factor * p, dims, power))
+        })
+        .next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ObjectPayload, ObjectType, VistleObject};
+
+    fn units(text: &str) -> Units {
+        Units::parse(text).unwrap()
+    }
+
+    fn factor(from: &str, to: &str) -> f64 {
+        units(from).conversion_factor(&units(to)).unwrap()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-12 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn prefixes_scale_base_units() {
+        assert!(close(units("kPa").scale(), 1e3));
+        assert!(close(units("mm").scale(), 1e-3));
+        assert!(close(units("µs").scale(), 1e-6));
+        assert!(close(units("us").scale(), 1e-6));
+        assert!(close(units("dam").scale(), 10.0));
+        assert!(close(units("kg").scale(), 1.0));
+        assert!(close(units("GW").scale(), 1e9));
+        // A bare prefix symbol is the unit, not a prefix
+        assert_eq!(units("m").dimensions(), Dimensions::new(1, 0, 0, 0));
+    }
+
+    #[test]
+    fn compound_units_combine_dimensions() {
+        assert_eq!(units("m/s").dimensions(), Dimensions::new(1, 0, -1, 0));
+        assert_eq!(units("kg/m^3").dimensions(), Dimensions::new(-3, 1, 0, 0));
+        assert_eq!(units("kg m/s^2").dimensions(), units("N").dimensions());
+        assert_eq!(units("N*m").dimensions(), units("J").dimensions());
+        assert_eq!(units("J/s").dimensions(), units("W").dimensions());
+        assert_eq!(units("N/m^2").dimensions(), units("Pa").dimensions());
+        // Division applies to the following term only
+        assert_eq!(units("m/s*K").dimensions(), Dimensions::new(1, 0, -1, 1));
+        assert!(close(units("g/cm^3").scale(), 1e3));
+        assert!(units("").dimensions().is_dimensionless());
+        assert_eq!(units("").to_string(), "1");
+    }
+
+    #[test]
+    fn conversion_factors() {
+        assert!(close(factor("Pa", "kPa"), 1e-3));
+        assert!(close(factor("kPa", "Pa"), 1e3));
+        assert!(close(factor("km/s", "m/s"), 1e3));
+        assert!(close(factor("g/cm^3", "kg/m^3"), 1e3));
+        assert!(close(factor("m^2", "cm^2"), 1e4));
+        assert!(close(factor("N/m^2", "Pa"), 1.0));
+    }
+
+    #[test]
+    fn incompatible_dimensions_do_not_convert() {
+        let error = units("Pa").conversion_factor(&units("m/s")).unwrap_err().to_string();
+        assert!(error.contains("Cannot convert Pa"), "{}", error);
+        assert!(error.contains("kg m^-1 s^-2"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_units_and_bad_powers_are_errors() {
+        assert!(Units::parse("furlong").unwrap_err().to_string().contains("Unknown unit furlong"));
+        assert!(Units::parse("kX").is_err());
+        assert!(Units::parse("k1").is_err());
+        assert!(Units::parse("m^x").is_err());
+        assert!(Units::parse("m^300").is_err());
+    }
+
+    #[test]
+    fn exponent_overflow_is_a_parse_error() {
+        // Each term fits i8, their sum does not
+        let error = Units::parse("m^100 m^100").unwrap_err().to_string();
+        assert!(error.contains("out of range"), "{}", error);
+        assert!(Units::parse("m^64 m^64").is_err());
+        assert!(Units::parse("s/s^-128").is_err());
+        assert!(Units::parse("Pa^100").is_err());
+        assert_eq!(units("m^127").dimensions().length, 127);
+        assert_eq!(units("m^-64/m^64").dimensions().length, -128);
+    }
+
+    fn field(units: Option<&str>) -> Arc<dyn Object> {
+        let mut field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: ndarray::array![1.0f32] });
+        if let Some(units) = units {
+            field.set_attribute(attribute::UNITS.to_string(), units.to_string());
+        }
+        Arc::new(field)
+    }
+
+    #[test]
+    fn common_units_of_fields() {
+        assert_eq!(Units::common(&[field(None), field(None)]).unwrap(), None);
+        assert_eq!(Units::common(&[field(None), field(Some("Pa")), field(Some("kPa"))]).unwrap(), Some(units("Pa")));
+        assert!(Units::common(&[field(Some("Pa")), field(Some("m"))]).is_err());
+        assert!(Units::common(&[field(Some("bogus"))]).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Unstructured grids of mixed volume and surface cells
+//!
+//! Cells are stored as in VTK and Vistle: the vertex indices of all cells
+//! in one connectivity list, an offset array with one more entry than there
+//! are cells, and a type per cell. Cell `i` uses the indices
+//! `connectivity[offsets[i]..offsets[i + 1]]`, in the vertex order VTK
+//! defines for its type.
+
+use ndarray::{Array1, Array2};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{ObjectPayload, ObjectType, VistleObject};
+
+/// Shape of a cell of an unstructured grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum CellType {
+    Triangle = 5,
+    Quad = 9,
+    Tetrahedron = 10,
+    Hexahedron = 12,
+    /// Triangular prism
+    Wedge = 13,
+    Pyramid = 14,
+}
+
+impl CellType {
+    /// Vertices of a cell of this type
+    pub fn num_vertices(self) -> usize {
+        match self {
+            CellType::Triangle => 3,
+            CellType::Quad => 4,
+            CellType::Tetrahedron => 4,
+            CellType::Hexahedron => 8,
+            CellType::Wedge => 6,
+            CellType::Pyramid => 5,
+        }
+    }
+
+    /// Whether the cell encloses a volume
+    pub fn is_volume(self) -> bool {
+        !matches!(self, CellType::Triangle | CellType::Quad)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CellType::Triangle => "triangle",
+            CellType::Quad => "quad",
+            CellType::Tetrahedron => "tetrahedron",
+            CellType::Hexahedron => "hexahedron",
+            CellType::Wedge => "wedge",
+            CellType::Pyramid => "pyramid",
+        }
+    }
+}
+
+impl std::fmt::Display for CellType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Unstructured grid
+#[derive(Debug, Clone, Copy)]
+pub struct UnstructuredGridView<'a> {
+    coordinates: &'a Array2<f32>,
+    connectivity: &'a Array1<i32>,
+    offsets: &'a Array1<i32>,
+    cell_types: &'a [CellType],
+}
+
+impl<'a> UnstructuredGridView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::UnstructuredGrid { coordinates, connectivity, offsets, cell_types } => Some(Self {
+                coordinates,
+                connectivity,
+                offsets,
+                cell_types,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Vertex coordinates, one row per vertex
+    pub fn coordinates(&self) -> &'a Array2<f32> {
+        self.coordinates
+    }
+
+    pub fn connectivity(&self) -> &'a Array1<i32> {
+        self.connectivity
+    }
+
+    pub fn offsets(&self) -> &'a Array1<i32> {
+        self.offsets
+    }
+
+    pub fn cell_types(&self) -> &'a [CellType] {
+        self.cell_types
+    }
+
+    pub fn num_vertices(&self) -> usize {
+        self.coordinates.nrows()
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.cell_types.len()
+    }
+
+    /// Type and vertex indices of cell `i`
+    pub fn cell(&self, i: usize) -> Option<(CellType, &'a [i32])> {
+        let cell_type = *self.cell_types.get(i)?;
+        let start = *self.offsets.get(i)? as usize;
+        let end = *self.offsets.get(i + 1)? as usize;
+        let connectivity = self.connectivity.as_slice()?;
+        Some((cell_type, connectivity.get(start..end)?))
+    }
+
+    /// Cells in order, with their type and vertex indices
+    pub fn cells(&self) -> impl Iterator<Item = (CellType, &'a [i32])> + 'a {
+        let view = *self;
+        (0..self.num_cells()).filter_map(move |i| view.cell(i))
+    }
+}
+
+/// Builder for unstructured grids
+#[derive(Debug, Clone, Default)]
+pub struct UnstructuredGridBuilder {
+    coordinates: Vec<[f32; 3]>,
+    connectivity: Vec<u32>,
+    offsets: Vec<usize>,
+    cell_types: Vec<CellType>,
+}
+
+impl UnstructuredGridBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn coordinates(mut self, coordinates: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        self.coordinates.extend(coordinates);
+        self
+    }
+
+    /// Add a cell with its vertex indices
+    pub fn cell(mut self, cell_type: CellType, indices: &[u32]) -> Self {
+        self.offsets.push(self.connectivity.len());
+        self.connectivity.extend_from_slice(indices);
+        self.cell_types.push(cell_type);
+        self
+    }
+
+    pub fn build(mut self) -> Result<VistleObject, crate::Error> {
+        if let Some((i, p)) = self.coordinates.iter().enumerate().find(|(_, p)| p.iter().any(|c| !c.is_finite())) {
+            return Err(crate::Error::Compute(format!(
+                "UnstructuredGrid: vertex {} has non-finite coordinates {:?}",
+                i, p
+            )));
+        }
+        self.offsets.push(self.connectivity.len());
+        for (i, cell_type) in self.cell_types.iter().enumerate() {
+            let count = self.offsets[i + 1] - self.offsets[i];
+            if count != cell_type.num_vertices() {
+                return Err(crate::Error::Compute(format!(
+                    "UnstructuredGrid: cell {} is a {} but has {} vertices",
+                    i, cell_type, count
+                )));
+            }
+        }
+        if let Some(&index) = self.connectivity.iter().find(|&&v| v as usize >= self.coordinates.len()) {
+            return Err(crate::Error::Compute(format!(
+                "UnstructuredGrid: vertex index {} out of range for {} vertices",
+                index,
+                self.coordinates.len()
+            )));
+        }
+        if self.connectivity.len() > i32::MAX as usize {
+            return Err(crate::Error::Compute(format!(
+                "UnstructuredGrid: {} vertex indices exceed the 32-bit offset range",
+                self.connectivity.len()
+            )));
+        }
+
+        let coordinates = Array2::from_shape_vec(
+            (self.coordinates.len(), 3),
+            self.coordinates.iter().flatten().copied().collect(),
+        )
+        .expect("row-major shape matches vertex count");
+        Ok(VistleObject::with_data(
+            ObjectType::UnstructuredGrid,
+            ObjectPayload::UnstructuredGrid {
+                coordinates,
+                connectivity: self.connectivity.iter().map(|&v| v as i32).collect(),
+                offsets: self.offsets.iter().map(|&o| o as i32).collect(),
+                cell_types: self.cell_types,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::core::{Object, ShmConfig, SharedArena};
+
+    /// Two tetrahedra sharing the face 1 2 3
+    fn two_tets() -> VistleObject {
+        UnstructuredGridBuilder::new()
+            .coordinates([[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0, 1.0]])
+            .cell(CellType::Tetrahedron, &[0, 1, 2, 3])
+            .cell(CellType::Tetrahedron, &[1, 2, 3, 4])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn cells_are_read_through_the_offsets() {
+        let grid = two_tets();
+        let view = UnstructuredGridView::new(grid.data()).unwrap();
+        assert_eq!((view.num_vertices(), view.num_cells()), (5, 2));
+        assert_eq!(view.offsets().to_vec(), [0, 4, 8]);
+        assert_eq!(view.cell(1), Some((CellType::Tetrahedron, &[1, 2, 3, 4][..])));
+        assert_eq!(view.cell(2), None);
+        assert_eq!(view.cells().count(), 2);
+        assert!(UnstructuredGridView::new(&ObjectPayload::Empty).is_none());
+    }
+
+    #[test]
+    fn mixed_cells_keep_their_types() {
+        let grid = UnstructuredGridBuilder::new()
+            .coordinates((0..6).map(|i| [i as f32, (i % 2) as f32, (i / 3) as f32]))
+            .cell(CellType::Wedge, &[0, 1, 2, 3, 4, 5])
+            .cell(CellType::Triangle, &[0, 1, 2])
+            .build()
+            .unwrap();
+        let view = UnstructuredGridView::new(grid.data()).unwrap();
+        let cells: Vec<(CellType, usize)> = view.cells().map(|(t, indices)| (t, indices.len())).collect();
+        assert_eq!(cells, [(CellType::Wedge, 6), (CellType::Triangle, 3)]);
+        assert!(CellType::Wedge.is_volume() && !CellType::Triangle.is_volume());
+        assert_eq!(CellType::Pyramid.to_string(), "pyramid");
+    }
+
+    #[test]
+    fn inconsistent_cells_are_rejected() {
+        let corners = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        let message = UnstructuredGridBuilder::new().coordinates(corners).cell(CellType::Tetrahedron, &[0, 1, 2])
+            .build().unwrap_err().to_string();
+        assert!(message.contains("cell 0 is a tetrahedron but has 3 vertices"), "{}", message);
+
+        let message = UnstructuredGridBuilder::new().coordinates(corners).cell(CellType::Tetrahedron, &[0, 1, 2, 4])
+            .build().unwrap_err().to_string();
+        assert!(message.contains("vertex index 4 out of range for 4 vertices"), "{}", message);
+
+        let message = UnstructuredGridBuilder::new().coordinates([[0.0, f32::NAN, 0.0]])
+            .build().unwrap_err().to_string();
+        assert!(message.contains("vertex 0 has non-finite coordinates"), "{}", message);
+    }
+
+    #[test]
+    fn grids_round_trip_through_shared_memory() {
+        let arena = SharedArena::new(ShmConfig {
+            size: 1 << 20,
+            name: format!("vistle_test_{}", uuid::Uuid::new_v4().simple()),
+            ..ShmConfig::default()
+        })
+        .unwrap();
+        let grid: Arc<dyn Object> = Arc::new(two_tets());
+        let id = arena.store_object(grid.clone()).unwrap();
+
+        let stored = arena.get_object(id).unwrap().expect("grid was not stored");
+        assert_eq!(stored.object_type(), ObjectType::UnstructuredGrid);
+        let (original, stored) = (
+            UnstructuredGridView::new(grid.payload().unwrap()).unwrap(),
+            UnstructuredGridView::new(stored.payload().unwrap()).unwrap(),
+        );
+        assert_eq!(stored.coordinates(), original.coordinates());
+        assert_eq!(stored.connectivity(), original.connectivity());
+        assert_eq!(stored.offsets(), original.offsets());
+        assert_eq!(stored.cell_types(), [CellType::Tetrahedron; 2]);
+    }
+}
@@ -0,0 +1,252 @@
+//! Consistency checks of object payloads and metadata
+//!
+//! Objects built by hand or read from damaged files can carry indices past
+//! the end of their coordinates or offsets that run backwards. Modules index
+//! such arrays directly and fail deep inside with confusing errors, or
+//! panic. `VistleObject::validate` lists every inconsistency instead, and
+//! workflows in strict mode check the objects passed between ports with it.
+//! Validation does not look at values: NaN and infinities are the business
+//! of `compute::audit`.
+
+use ndarray::{Array1, Array2};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Object, ObjectMeta, ObjectPayload, VistleObject};
+
+/// One inconsistency found in an object
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationIssue {
+    /// Part of the object at fault, e.g. `triangles` or `meta.block`
+    pub field: String,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn new(field: &str, message: impl Into<String>) -> Self {
+        Self { field: field.to_string(), message: message.into() }
+    }
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Every inconsistency of an object's metadata and payload
+pub fn validate_object(object: &dyn Object) -> Result<(), Vec<ValidationIssue>> {
+    let mut issues = Vec::new();
+    validate_meta(object.meta(), &mut issues);
+    if let Some(payload) = object.payload() {
+        validate_payload(payload, &mut issues);
+    }
+    if issues.is_empty() { Ok(()) } else { Err(issues) }
+}
+
+impl VistleObject {
+    /// Check that indices, offsets, array shapes and metadata are consistent
+    pub fn validate(&self) -> Result<(), Vec<ValidationIssue>> {
+        validate_object(self)
+    }
+}
+
+fn validate_meta(meta: &ObjectMeta, issues: &mut Vec<ValidationIssue>) {
+    if meta.num_blocks < 1 {
+        issues.push(ValidationIssue::new("meta.num_blocks", format!("{} is not positive", meta.num_blocks)));
+    } else if !(0..meta.num_blocks).contains(&meta.block) {
+        issues.push(ValidationIssue::new("meta.block", format!(
+            "{} is outside 0..{}",
+            meta.block, meta.num_blocks
+        )));
+    }
+    // -1 marks objects that belong to no timestep, e.g. temporal aggregates
+    if meta.timestep < -1 {
+        issues.push(ValidationIssue::new("meta.timestep", format!("{} is negative", meta.timestep)));
+    } else if meta.num_timesteps > 0 && meta.timestep >= meta.num_timesteps {
+        issues.push(ValidationIssue::new("meta.timestep", format!(
+            "{} is not below num_timesteps ({})",
+            meta.timestep, meta.num_timesteps
+        )));
+    }
+    if meta.num_timesteps < 0 {
+        issues.push(ValidationIssue::new("meta.num_timesteps", format!("{} is negative", meta.num_timesteps)));
+    }
+}
+
+/// Coordinates must have three columns; returns the vertex count
+fn check_coordinates(coordinates: &Array2<f32>, issues: &mut Vec<ValidationIssue>) -> usize {
+    if coordinates.ncols() != 3 {
+        issues.push(ValidationIssue::new("coordinates", format!(
+            "shape {:?} is not N x 3",
+            coordinates.shape()
+        )));
+    }
+    coordinates.nrows()
+}
+
+/// Element rows of `columns` vertex indices below `num_vertices`
+fn check_elements(field: &str, elements: &Array2<i32>, columns: usize, num_vertices: usize, issues: &mut Vec<ValidationIssue>) {
+    if elements.ncols() != columns {
+        issues.push(ValidationIssue::new(field, format!(
+            "shape {:?} is not N x {}",
+            elements.shape(), columns
+        )));
+    }
+    for (row, element) in elements.rows().into_iter().enumerate() {
+        if let Some(&bad) = element.iter().find(|&&v| v < 0 || v as usize >= num_vertices) {
+            issues.push(ValidationIssue::new(field, format!(
+                "element {} references vertex {} but only {} vertices exist",
+                row, bad, num_vertices
+            )));
+            // One issue per array; the first bad element locates the damage
+            return;
+        }
+    }
+}
+
+/// Connectivity with offsets starting at 0, increasing and ending at its length
+fn check_offsets(connectivity: &Array1<i32>, offsets: &Array1<i32>, num_vertices: usize, issues: &mut Vec<ValidationIssue>) {
+    if let Some((i, &bad)) = connectivity.iter().enumerate().find(|(_, &v)| v < 0 || v as usize >= num_vertices) {
+        issues.push(ValidationIssue::new("connectivity", format!(
+            "entry {} references vertex {} but only {} vertices exist",
+            i, bad, num_vertices
+        )));
+    }
+    match (offsets.first(), offsets.last()) {
+        (Some(&first), Some(&last)) => {
+            if first != 0 {
+                issues.push(ValidationIssue::new("offsets", format!("start at {} instead of 0", first)));
+            }
+            if last < 0 || last as usize != connectivity.len() {
+                issues.push(ValidationIssue::new("offsets", format!(
+                    "end at {} but connectivity has {} entries",
+                    last, connectivity.len()
+                )));
+            }
+        }
+        _ => issues.push(ValidationIssue::new("offsets", "are empty; they need a final entry even without elements")),
+    }
+    if let Some(i) = (1..offsets.len()).find(|&i| offsets[i] < offsets[i - 1]) {
+        issues.push(ValidationIssue::new("offsets", format!(
+            "decrease from {} to {} at entry {}",
+            offsets[i - 1], offsets[i], i
+        )));
+    }
+}
+
+fn check_len(field: &str, len: usize, expected: usize, what: &str, issues: &mut Vec<ValidationIssue>) {
+    if len != expected {
+        issues.push(ValidationIssue::new(field, format!("has {} entries, expected {} ({})", len, expected, what)));
+    }
+}
+
+fn validate_payload(payload: &ObjectPayload, issues: &mut Vec<ValidationIssue>) {
+    match payload {
+        ObjectPayload::Points { coordinates } => {
+            check_coordinates(coordinates, issues);
+        }
+        ObjectPayload::Lines { coordinates, connections } => {
+            let n = check_coordinates(coordinates, issues);
+            check_elements("connections", connections, 2, n, issues);
+        }
+        ObjectPayload::Triangles { coordinates, triangles } => {
+            let n = check_coordinates(coordinates, issues);
+            check_elements("triangles", triangles, 3, n, issues);
+        }
+        ObjectPayload::Quads { coordinates, quads } => {
+            let n = check_coordinates(coordinates, issues);
+            check_elements("quads", quads, 4, n, issues);
+        }
+        ObjectPayload::Polygons { coordinates, connectivity, offsets } => {
+            let n = check_coordinates(coordinates, issues);
+            check_offsets(connectivity, offsets, n, issues);
+        }
+        ObjectPayload::UnstructuredGrid { coordinates, connectivity, offsets, cell_types } => {
+            let n = check_coordinates(coordinates, issues);
+            check_offsets(connectivity, offsets, n, issues);
+            check_len("cell_types", cell_types.len(), offsets.len().saturating_sub(1), "one per cell", issues);
+        }
+        ObjectPayload::StructuredGrid { dims, coordinates } => {
+            let n = check_coordinates(coordinates, issues);
+            check_len("coordinates", n, dims.iter().product(), "one per grid point", issues);
+        }
+        ObjectPayload::UniformGrid { dims, values, .. } => {
+            check_len("values", values.len(), dims.iter().product(), "one per grid point", issues);
+        }
+        ObjectPayload::VecVec3 { data } if data.ncols() != 3 => {
+            issues.push(ValidationIssue::new("data", format!("shape {:?} is not N x 3", data.shape())));
+        }
+        ObjectPayload::VecVec3F64 { data } if data.ncols() != 3 => {
+            issues.push(ValidationIssue::new("data", format!("shape {:?} is not N x 3", data.shape())));
+        }
+        ObjectPayload::Table { columns } => {
+            if let Some((first, data)) = columns.first() {
+                for (name, column) in &columns[1..] {
+                    if column.len() != data.len() {
+                        issues.push(ValidationIssue::new("columns", format!(
+                            "column {} has {} rows, column {} has {}",
+                            name, column.len(), first, data.len()
+                        )));
+                    }
+                }
+            }
+        }
+        ObjectPayload::Curve { x, y, .. } => {
+            check_len("y", y.len(), x.len(), "one per x sample", issues);
+        }
+        ObjectPayload::Image { width, height, channels, data, .. } => {
+            let expected = [*height, *width, *channels];
+            if data.shape() != expected {
+                issues.push(ValidationIssue::new("data", format!(
+                    "shape {:?} does not match {} rows of {} pixels with {} channels",
+                    data.shape(), height, width, channels
+                )));
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ObjectType;
+    use ndarray::array;
+
+    fn triangle(triangles: Array2<i32>) -> VistleObject {
+        VistleObject::with_data(ObjectType::Triangles, ObjectPayload::Triangles {
+            coordinates: array![[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            triangles,
+        })
+    }
+
+    #[test]
+    fn consistent_objects_pass() {
+        assert_eq!(triangle(array![[0, 1, 2]]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn out_of_range_triangle_indices_are_reported() {
+        let issues = triangle(array![[0, 1, 2], [0, 2, 3]]).validate().unwrap_err();
+        assert_eq!(issues, vec![ValidationIssue::new(
+            "triangles",
+            "element 1 references vertex 3 but only 3 vertices exist",
+        )]);
+
+        let issues = triangle(array![[0, -1, 2]]).validate().unwrap_err();
+        assert_eq!(issues[0].field, "triangles");
+        assert!(issues[0].message.contains("vertex -1"), "{}", issues[0]);
+    }
+
+    #[test]
+    fn negative_timesteps_are_reported() {
+        let mut object = triangle(array![[0, 1, 2]]);
+        object.meta_mut().timestep = -3;
+        let issues = object.validate().unwrap_err();
+        assert_eq!(issues, vec![ValidationIssue::new("meta.timestep", "-3 is negative")]);
+
+        // -1 marks objects outside any timestep
+        object.meta_mut().timestep = -1;
+        assert_eq!(object.validate(), Ok(()));
+    }
+}
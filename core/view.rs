@@ -0,0 +1,367 @@
+//! Typed read-only views of object payloads
+//!
+//! Module code asks an input for the shape it needs, e.g.
+//! `input.as_triangles()`, instead of matching on `ObjectPayload` itself.
+//! A `None` is turned into `Error::WrongType` with `Error::wrong_type`.
+
+use nalgebra::Vector3;
+use ndarray::{Array1, Array2};
+
+use crate::core::{Object, ObjectPayload};
+
+impl ObjectPayload {
+    /// Short name of the payload shape, used in error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ObjectPayload::Empty => "empty",
+            ObjectPayload::Points { .. } => "points",
+            ObjectPayload::Lines { .. } => "lines",
+            ObjectPayload::Triangles { .. } => "triangles",
+            ObjectPayload::VecScalar { .. } => "scalar field",
+            ObjectPayload::VecVec3 { .. } => "vector field",
+            ObjectPayload::Table { .. } => "table",
+            ObjectPayload::Curve { .. } => "curve",
+            ObjectPayload::UniformGrid { .. } => "uniform grid",
+            ObjectPayload::AmrHierarchy { .. } => "AMR hierarchy",
+            ObjectPayload::Placeholder { .. } => "unresolved placeholder",
+            ObjectPayload::Custom(_) => "custom data",
+            ObjectPayload::Lossy(_) => "lossy field",
+            ObjectPayload::UnstructuredGrid { .. } => "unstructured grid",
+            ObjectPayload::RectilinearGrid { .. } => "rectilinear grid",
+            ObjectPayload::StructuredGrid { .. } => "structured grid",
+            ObjectPayload::Polygons { .. } => "polygons",
+            ObjectPayload::Quads { .. } => "quads",
+            ObjectPayload::VecScalarF64 { .. } => "double scalar field",
+            ObjectPayload::VecVec3F64 { .. } => "double vector field",
+            ObjectPayload::Image { .. } => "image",
+            ObjectPayload::LayeredImage { .. } => "layered image",
+            ObjectPayload::VecInt32 { .. } => "integer field",
+            ObjectPayload::VecInt64 { .. } => "64-bit integer field",
+        }
+    }
+}
+
+impl crate::Error {
+    /// `WrongType` error for an object that lacks the expected payload
+    pub fn wrong_type(expected: &str, object: &dyn Object) -> Self {
+        crate::Error::WrongType {
+            expected: expected.to_string(),
+            found: object.payload().map_or("no payload", ObjectPayload::kind).to_string(),
+        }
+    }
+}
+
+/// Triangle surface
+#[derive(Debug, Clone, Copy)]
+pub struct TrianglesView<'a> {
+    coordinates: &'a Array2<f32>,
+    triangles: &'a Array2<i32>,
+}
+
+impl<'a> TrianglesView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::Triangles { coordinates, triangles } => Some(Self { coordinates, triangles }),
+            _ => None,
+        }
+    }
+
+    /// Vertex coordinates, one row per vertex
+    pub fn coordinates(&self) -> &'a Array2<f32> {
+        self.coordinates
+    }
+
+    /// Vertex indices, one row per triangle
+    pub fn triangles(&self) -> &'a Array2<i32> {
+        self.triangles
+    }
+
+    pub fn num_vertices(&self) -> usize {
+        self.coordinates.nrows()
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.triangles.nrows()
+    }
+
+    pub fn vertex(&self, index: usize) -> Vector3<f32> {
+        Vector3::new(self.coordinates[[index, 0]], self.coordinates[[index, 1]], self.coordinates[[index, 2]])
+    }
+
+    /// Vertex indices of one triangle
+    pub fn triangle(&self, index: usize) -> [usize; 3] {
+        std::array::from_fn(|corner| self.triangles[[index, corner]] as usize)
+    }
+
+    pub fn area(&self, index: usize) -> f32 {
+        let [a, b, c] = self.triangle(index).map(|v| self.vertex(v));
+        (b - a).cross(&(c - a)).norm() * 0.5
+    }
+}
+
+/// One scalar per vertex or cell
+#[derive(Debug, Clone, Copy)]
+pub struct ScalarFieldView<'a> {
+    values: &'a Array1<f32>,
+}
+
+impl<'a> ScalarFieldView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::VecScalar { data } => Some(Self { values: data }),
+            _ => None,
+        }
+    }
+
+    pub fn values(&self) -> &'a Array1<f32> {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Smallest and largest finite value, None if there is none
+    pub fn range(&self) -> Option<(f32, f32)> {
+        self.values.iter()
+            .filter(|v| v.is_finite())
+            .fold(None, |range, &v| match range {
+                None => Some((v, v)),
+                Some((min, max)) => Some((min.min(v), max.max(v))),
+            })
+    }
+}
+
+/// One 3-vector per vertex or cell
+#[derive(Debug, Clone, Copy)]
+pub struct VectorFieldView<'a> {
+    values: &'a Array2<f32>,
+}
+
+impl<'a> VectorFieldView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::VecVec3 { data } => Some(Self { values: data }),
+            _ => None,
+        }
+    }
+
+    /// Vectors, one row each
+    pub fn values(&self) -> &'a Array2<f32> {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.nrows()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.nrows() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Vector3<f32> {
+        Vector3::new(self.values[[index, 0]], self.values[[index, 1]], self.values[[index, 2]])
+    }
+}
+
+/// Named columns of equal length
+#[derive(Debug, Clone, Copy)]
+pub struct TableView<'a> {
+    columns: &'a [(String, Array1<f64>)],
+}
+
+impl<'a> TableView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::Table { columns } => Some(Self { columns }),
+            _ => None,
+        }
+    }
+
+    pub fn columns(&self) -> &'a [(String, Array1<f64>)] {
+        self.columns
+    }
+
+    pub fn column(&self, name: &str) -> Option<&'a Array1<f64>> {
+        self.columns.iter().find(|(n, _)| n == name).map(|(_, values)| values)
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.columns.first().map(|(_, values)| values.len()).unwrap_or(0)
+    }
+}
+
+/// Samples of y over x
+#[derive(Debug, Clone, Copy)]
+pub struct CurveView<'a> {
+    x: &'a Array1<f64>,
+    y: &'a Array1<f64>,
+    pub x_label: &'a str,
+    pub y_label: &'a str,
+}
+
+impl<'a> CurveView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::Curve { x, y, x_label, y_label } => Some(Self { x, y, x_label, y_label }),
+            _ => None,
+        }
+    }
+
+    pub fn x(&self) -> &'a Array1<f64> {
+        self.x
+    }
+
+    pub fn y(&self) -> &'a Array1<f64> {
+        self.y
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len().min(self.y.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs of consecutive finite samples as `[x, y]` points; NaN samples split runs
+    pub fn segments(&self) -> Vec<Vec<[f64; 2]>> {
+        let mut segments = Vec::new();
+        let mut current = Vec::new();
+        for (&x, &y) in self.x.iter().zip(self.y.iter()) {
+            if x.is_finite() && y.is_finite() {
+                current.push([x, y]);
+            } else if !current.is_empty() {
+                segments.push(std::mem::take(&mut current));
+            }
+        }
+        if !current.is_empty() {
+            segments.push(current);
+        }
+        segments
+    }
+}
+
+/// Regular grid with one value per point
+#[derive(Debug, Clone, Copy)]
+pub struct UniformGridView<'a> {
+    pub dims: [usize; 3],
+    pub origin: [f32; 3],
+    pub spacing: [f32; 3],
+    values: &'a Array1<f32>,
+}
+
+impl<'a> UniformGridView<'a> {
+    pub fn new(payload: &'a ObjectPayload) -> Option<Self> {
+        match payload {
+            ObjectPayload::UniformGrid { dims, origin, spacing, values } => Some(Self {
+                dims: *dims,
+                origin: *origin,
+                spacing: *spacing,
+                values,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Values with x varying fastest
+    pub fn values(&self) -> &'a Array1<f32> {
+        self.values
+    }
+
+    /// Whether another grid has the same points
+    pub fn same_lattice(&self, other: &UniformGridView) -> bool {
+        self.dims == other.dims && self.origin == other.origin && self.spacing == other.spacing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ObjectType, VistleObject};
+    use ndarray::array;
+
+    fn triangle() -> VistleObject {
+        VistleObject::with_data(ObjectType::Triangles, ObjectPayload::Triangles {
+            coordinates: array![[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]],
+            triangles: array![[0, 1, 2]],
+        })
+    }
+
+    #[test]
+    fn triangle_views_expose_vertices_and_areas() {
+        let object = triangle();
+        let view = object.as_triangles().unwrap();
+        assert_eq!((view.num_vertices(), view.num_triangles()), (3, 1));
+        assert_eq!(view.triangle(0), [0, 1, 2]);
+        assert_eq!(view.vertex(1), Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(view.area(0), 2.0);
+    }
+
+    #[test]
+    fn field_views_read_values() {
+        let scalars = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar {
+            data: array![3.0, f32::NAN, -1.0, 2.0],
+        });
+        let view = scalars.as_scalar_field().unwrap();
+        assert_eq!(view.len(), 4);
+        assert_eq!(view.range(), Some((-1.0, 3.0)));
+
+        let vectors = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecVec3 {
+            data: array![[1.0, 2.0, 3.0]],
+        });
+        assert_eq!(vectors.as_vector_field().unwrap().get(0), Vector3::new(1.0, 2.0, 3.0));
+        assert!(vectors.as_scalar_field().is_none());
+    }
+
+    #[test]
+    fn table_and_curve_views() {
+        let table = VistleObject::with_data(ObjectType::Table, ObjectPayload::Table {
+            columns: vec![("t".to_string(), array![0.0, 1.0]), ("p".to_string(), array![5.0, 6.0])],
+        });
+        let view = table.as_table().unwrap();
+        assert_eq!(view.num_rows(), 2);
+        assert_eq!(view.column("p").unwrap()[1], 6.0);
+        assert!(view.column("missing").is_none());
+
+        let curve = VistleObject::with_data(ObjectType::Curve, ObjectPayload::Curve {
+            x: array![0.0, 1.0, 2.0, 3.0],
+            y: array![1.0, f64::NAN, 2.0, 3.0],
+            x_label: "time".to_string(),
+            y_label: "value".to_string(),
+        });
+        let view = curve.as_curve().unwrap();
+        assert_eq!(view.x_label, "time");
+        assert_eq!(view.segments(), vec![vec![[0.0, 1.0]], vec![[2.0, 2.0], [3.0, 3.0]]]);
+    }
+
+    #[test]
+    fn uniform_grids_compare_lattices() {
+        let grid = |spacing: f32| VistleObject::with_data(ObjectType::UniformGrid, ObjectPayload::UniformGrid {
+            dims: [2, 1, 1],
+            origin: [0.0; 3],
+            spacing: [spacing; 3],
+            values: array![0.0, 1.0],
+        });
+        let (a, b, c) = (grid(1.0), grid(1.0), grid(0.5));
+        let view = a.as_uniform_grid().unwrap();
+        assert!(view.same_lattice(&b.as_uniform_grid().unwrap()));
+        assert!(!view.same_lattice(&c.as_uniform_grid().unwrap()));
+    }
+
+    #[test]
+    fn a_missing_view_becomes_a_wrong_type_error() {
+        let object = triangle();
+        assert!(object.as_uniform_grid().is_none());
+        match crate::Error::wrong_type("uniform grid", &object) {
+            crate::Error::WrongType { expected, found } => {
+                assert_eq!((expected.as_str(), found.as_str()), ("uniform grid", "triangles"));
+            }
+            other => panic!("unexpected error {}", other),
+        }
+    }
+}
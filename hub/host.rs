@@ -0,0 +1,132 @@
+//! Module host process executing tasks assigned by a hub
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::core::{CodecId, ComputeContext, MessageRouter, ObjectData};
+use crate::compute::ModuleRegistry;
+use super::{ports_from_wire, ports_to_wire, read_frame, write_frame, HostCapabilities, HubMessage, RemoteTask};
+
+/// Lightweight runner owning a module registry
+///
+/// Each assigned task gets a fresh module instance; module state does not
+/// persist between executions on a host.
+pub struct ModuleHost {
+    registry: Arc<ModuleRegistry>,
+    router: Arc<MessageRouter>,
+    slots: usize,
+    /// Codecs offered to the hub, most preferred first
+    codecs: Vec<CodecId>,
+}
+
+impl ModuleHost {
+    pub fn new(registry: Arc<ModuleRegistry>) -> Self {
+        Self {
+            registry,
+            router: Arc::new(MessageRouter::new()),
+            slots: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            codecs: CodecId::available(),
+        }
+    }
+
+    /// Number of tasks executed concurrently
+    pub fn with_slots(mut self, slots: usize) -> Self {
+        self.slots = slots.max(1);
+        self
+    }
+
+    /// Codecs to offer the hub; unavailable ones are dropped and bincode is always offered
+    pub fn with_codecs(mut self, codecs: Vec<CodecId>) -> Self {
+        self.codecs = codecs.into_iter().filter(|c| c.is_available()).collect();
+        if !self.codecs.contains(&CodecId::Bincode) {
+            self.codecs.push(CodecId::Bincode);
+        }
+        self
+    }
+
+    /// Connect to a hub and execute tasks until it shuts down or the connection drops
+    pub async fn run(self, hub_addr: impl ToSocketAddrs) -> Result<(), crate::Error> {
+        let stream = TcpStream::connect(hub_addr).await?;
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+
+        let capabilities = HostCapabilities {
+            hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string()),
+            module_types: self.registry.list_available().await,
+            slots: self.slots,
+            codecs: self.codecs.clone(),
+        };
+        write_frame(&mut writer, &HubMessage::Register { capabilities }, CodecId::Bincode).await?;
+
+        let (host_id, codec) = match read_frame(&mut reader, CodecId::Bincode).await? {
+            Some(HubMessage::Registered { host_id, codec }) => (host_id, codec),
+            other => return Err(crate::Error::Module(format!("Hub refused registration: {:?}", other))),
+        };
+        if !codec.is_available() {
+            return Err(crate::Error::Module(format!("Hub chose codec {}, which this host lacks", codec)));
+        }
+        tracing::info!("Registered with hub as host {} using {}", host_id, codec);
+
+        let (sender, mut outgoing) = mpsc::unbounded_channel();
+        let writing = tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                write_frame(&mut writer, &message, codec).await?;
+            }
+            Ok::<(), crate::Error>(())
+        });
+
+        let host = Arc::new(self);
+        let permits = Arc::new(Semaphore::new(host.slots));
+        let running = Arc::new(AtomicUsize::new(0));
+
+        let result = loop {
+            match read_frame(&mut reader, codec).await {
+                Ok(Some(HubMessage::Execute(task))) => {
+                    let host = host.clone();
+                    let permits = permits.clone();
+                    let running = running.clone();
+                    let sender = sender.clone();
+                    tokio::spawn(async move {
+                        let _permit = permits.acquire_owned().await;
+                        let count = running.fetch_add(1, Ordering::Relaxed) + 1;
+                        let _ = sender.send(HubMessage::Load { running: count });
+
+                        let task_id = task.task_id;
+                        let reply = match host.execute(task).await {
+                            Ok(outputs) => HubMessage::TaskCompleted { task_id, outputs },
+                            Err(e) => HubMessage::TaskFailed { task_id, error: e.to_string() },
+                        };
+                        let _ = sender.send(reply);
+
+                        let count = running.fetch_sub(1, Ordering::Relaxed) - 1;
+                        let _ = sender.send(HubMessage::Load { running: count });
+                    });
+                }
+                Ok(Some(HubMessage::Shutdown)) | Ok(None) => break Ok(()),
+                Ok(Some(other)) => tracing::warn!("Unexpected message from hub: {:?}", other),
+                Err(e) => break Err(e),
+            }
+        };
+
+        drop(sender);
+        writing.abort();
+        result
+    }
+
+    async fn execute(&self, task: RemoteTask) -> Result<HashMap<String, Vec<ObjectData>>, crate::Error> {
+        let module = self.registry.create_instance(&task.module_type, task.module_id).await?;
+        for (port, objects) in ports_from_wire(task.inputs) {
+            module.set_input(&port, objects).await?;
+        }
+
+        let mut ctx = ComputeContext::new(task.module_id, task.rank, task.size)
+            .with_base_dir(task.base_dir);
+        ctx.timestep = task.timestep;
+        let outputs = module.execute(&ctx, &self.router).await?;
+        ports_to_wire(&outputs)
+    }
+}
@@ -0,0 +1,265 @@
+//! Hub process dispatching module executions to remote module hosts
+//!
+//! Module hosts connect to the hub over TCP, announce which module types they
+//! provide and then execute the tasks the hub assigns to them.
+
+pub mod host;
+pub mod protocol;
+
+pub use host::*;
+pub use protocol::*;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::core::{CodecId, ComputeContext};
+use crate::compute::{InputPorts, ModuleSpec, OutputPorts};
+
+/// Identifier the hub assigns to a connected host
+pub type HostId = u64;
+
+/// A connected module host as seen by the hub
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostInfo {
+    pub id: HostId,
+    pub address: SocketAddr,
+    pub capabilities: HostCapabilities,
+    /// Codec negotiated at registration
+    #[serde(default)]
+    pub codec: CodecId,
+    /// Tasks assigned by this hub and not yet finished
+    pub assigned: usize,
+    /// Running tasks as last reported by the host
+    pub reported_load: usize,
+}
+
+impl HostInfo {
+    /// Assigned tasks per slot; lower is less busy
+    pub fn load(&self) -> f64 {
+        self.assigned as f64 / self.capabilities.slots.max(1) as f64
+    }
+
+    pub fn provides(&self, module_type: &str) -> bool {
+        self.capabilities.module_types.iter().any(|t| t == module_type)
+    }
+}
+
+type PendingResult = oneshot::Sender<Result<OutputPorts, crate::Error>>;
+
+struct HostConnection {
+    info: HostInfo,
+    sender: mpsc::UnboundedSender<HubMessage>,
+    pending: HashMap<u64, PendingResult>,
+}
+
+/// Controller accepting module hosts and dispatching tasks to them
+pub struct Hub {
+    local_addr: SocketAddr,
+    hosts: Mutex<HashMap<HostId, HostConnection>>,
+    next_host: AtomicU64,
+    next_task: AtomicU64,
+    /// Codecs offered to hosts, most preferred first
+    codecs: Vec<CodecId>,
+}
+
+impl Hub {
+    /// Bind to `addr` and accept module hosts in the background
+    pub async fn listen(addr: impl ToSocketAddrs) -> Result<Arc<Self>, crate::Error> {
+        Self::listen_with_codecs(addr, vec![CodecId::Bincode]).await
+    }
+
+    /// Like `listen`, talking to each host in the first of `codecs` it also speaks
+    pub async fn listen_with_codecs(addr: impl ToSocketAddrs, codecs: Vec<CodecId>) -> Result<Arc<Self>, crate::Error> {
+        let listener = TcpListener::bind(addr).await?;
+        let hub = Arc::new(Self {
+            local_addr: listener.local_addr()?,
+            hosts: Mutex::new(HashMap::new()),
+            next_host: AtomicU64::new(1),
+            next_task: AtomicU64::new(1),
+            codecs,
+        });
+        tracing::info!("Hub listening on {}", hub.local_addr);
+
+        let accepting = hub.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let hub = accepting.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = hub.serve(stream, peer).await {
+                                tracing::warn!("Module host {}: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!("Hub failed to accept a connection: {}", e),
+                }
+            }
+        });
+
+        Ok(hub)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Currently connected hosts
+    pub fn hosts(&self) -> Vec<HostInfo> {
+        self.hosts.lock().values().map(|h| h.info.clone()).collect()
+    }
+
+    /// Ask every host to exit
+    pub fn shutdown(&self) {
+        for host in self.hosts.lock().values() {
+            let _ = host.sender.send(HubMessage::Shutdown);
+        }
+    }
+
+    /// Run one module on the least loaded host providing its type
+    ///
+    /// Fails rather than waiting if no host provides the module or the host
+    /// disconnects before reporting a result.
+    pub async fn dispatch(
+        &self,
+        spec: &ModuleSpec,
+        inputs: &InputPorts,
+        ctx: &ComputeContext,
+    ) -> Result<OutputPorts, crate::Error> {
+        let task_id = self.next_task.fetch_add(1, Ordering::Relaxed);
+        let task = RemoteTask {
+            task_id,
+            module_type: spec.module_type.clone(),
+            module_id: spec.id,
+            parameters: spec.parameters.clone(),
+            timestep: ctx.timestep,
+            rank: ctx.rank,
+            size: ctx.size,
+            base_dir: ctx.base_dir.clone(),
+            inputs: ports_to_wire(inputs)?,
+        };
+
+        let (result_tx, result_rx) = oneshot::channel();
+        let host_id = {
+            let mut hosts = self.hosts.lock();
+            let host = hosts.values_mut()
+                .filter(|h| h.info.provides(&spec.module_type))
+                .min_by(|a, b| a.info.load().total_cmp(&b.info.load()))
+                .ok_or_else(|| crate::Error::Module(format!(
+                    "No connected host provides module type {}",
+                    spec.module_type
+                )))?;
+            host.sender.send(HubMessage::Execute(task))
+                .map_err(|_| crate::Error::Module(format!("Host {} is disconnecting", host.info.id)))?;
+            host.pending.insert(task_id, result_tx);
+            host.info.assigned += 1;
+            host.info.id
+        };
+        tracing::debug!("Dispatched {} (task {}) to host {}", spec.name, task_id, host_id);
+
+        result_rx.await.unwrap_or_else(|_| Err(crate::Error::Module(format!(
+            "Host {} dropped task {}",
+            host_id, task_id
+        ))))
+    }
+
+    async fn serve(self: Arc<Self>, stream: TcpStream, peer: SocketAddr) -> Result<(), crate::Error> {
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+
+        let capabilities = match read_frame(&mut reader, CodecId::Bincode).await? {
+            Some(HubMessage::Register { capabilities }) => capabilities,
+            Some(other) => return Err(crate::Error::Module(format!("Expected registration, got {:?}", other))),
+            None => return Ok(()),
+        };
+
+        let id = self.next_host.fetch_add(1, Ordering::Relaxed);
+        let codec = CodecId::negotiate(&self.codecs, &capabilities.codecs);
+        // The reply still goes out in bincode; the host switches after reading it
+        write_frame(&mut writer, &HubMessage::Registered { host_id: id, codec }, CodecId::Bincode).await?;
+        let (sender, mut outgoing) = mpsc::unbounded_channel();
+        tracing::info!(
+            "Host {} registered from {} ({}, {} module types, {} slots, {} codec)",
+            id, peer, capabilities.hostname, capabilities.module_types.len(), capabilities.slots, codec
+        );
+
+        self.hosts.lock().insert(id, HostConnection {
+            info: HostInfo {
+                id,
+                address: peer,
+                capabilities,
+                codec,
+                assigned: 0,
+                reported_load: 0,
+            },
+            sender,
+            pending: HashMap::new(),
+        });
+
+        let writing = tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                if let Err(e) = write_frame(&mut writer, &message, codec).await {
+                    tracing::warn!("Failed to send to host {}: {}", id, e);
+                    break;
+                }
+            }
+        });
+
+        let result = loop {
+            match read_frame(&mut reader, codec).await {
+                Ok(Some(HubMessage::TaskCompleted { task_id, outputs })) => {
+                    self.finish(id, task_id, Ok(ports_from_wire(outputs)));
+                }
+                Ok(Some(HubMessage::TaskFailed { task_id, error })) => {
+                    self.finish(id, task_id, Err(crate::Error::Module(format!("Host {}: {}", id, error))));
+                }
+                Ok(Some(HubMessage::Load { running })) => {
+                    if let Some(host) = self.hosts.lock().get_mut(&id) {
+                        host.info.reported_load = running;
+                    }
+                }
+                Ok(Some(other)) => tracing::warn!("Unexpected message from host {}: {:?}", id, other),
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+
+        writing.abort();
+        self.disconnect(id);
+        result
+    }
+
+    fn finish(&self, host_id: HostId, task_id: u64, result: Result<OutputPorts, crate::Error>) {
+        let pending = self.hosts.lock().get_mut(&host_id).and_then(|host| {
+            host.info.assigned = host.info.assigned.saturating_sub(1);
+            host.pending.remove(&task_id)
+        });
+        match pending {
+            Some(sender) => {
+                let _ = sender.send(result);
+            }
+            None => tracing::warn!("Host {} reported unknown task {}", host_id, task_id),
+        }
+    }
+
+    /// Forget a host and fail everything it was running
+    fn disconnect(&self, host_id: HostId) {
+        let Some(host) = self.hosts.lock().remove(&host_id) else {
+            return;
+        };
+        tracing::warn!("Host {} disconnected with {} tasks in flight", host_id, host.pending.len());
+        for (task_id, sender) in host.pending {
+            let _ = sender.send(Err(crate::Error::Module(format!(
+                "Host {} disconnected while running task {}",
+                host_id, task_id
+            ))));
+        }
+    }
+}
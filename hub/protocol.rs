@@ -0,0 +1,121 @@
+//! Wire protocol between the hub and module hosts
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::core::{CodecId, Object, ObjectData, VistleObject};
+use crate::compute::OutputPorts;
+
+/// Largest frame accepted from a peer
+pub const MAX_FRAME_SIZE: u32 = 1 << 30;
+
+/// What a module host can run and how much of it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostCapabilities {
+    pub hostname: String,
+    pub module_types: Vec<String>,
+    /// Tasks the host runs concurrently
+    pub slots: usize,
+    /// Codecs the host can speak, most preferred first; bincode if none match
+    #[serde(default)]
+    pub codecs: Vec<CodecId>,
+}
+
+/// A module execution shipped to a host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteTask {
+    pub task_id: u64,
+    pub module_type: String,
+    pub module_id: u32,
+    pub parameters: HashMap<String, String>,
+    pub timestep: i32,
+    pub rank: i32,
+    pub size: i32,
+    /// Workflow directory; hosts resolve relative paths against it
+    pub base_dir: Option<PathBuf>,
+    pub inputs: HashMap<String, Vec<ObjectData>>,
+}
+
+/// Messages exchanged over a hub connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HubMessage {
+    /// First message of a host after connecting
+    Register { capabilities: HostCapabilities },
+    /// Hub reply to `Register`, naming the codec of all later frames
+    Registered { host_id: u64, codec: CodecId },
+    Execute(RemoteTask),
+    /// Outputs are shipped back by value, keeping their object ids
+    TaskCompleted { task_id: u64, outputs: HashMap<String, Vec<ObjectData>> },
+    TaskFailed { task_id: u64, error: String },
+    /// Number of tasks currently running on the host
+    Load { running: usize },
+    Shutdown,
+}
+
+/// Write one length-prefixed frame encoded with `codec`
+///
+/// The `Register` handshake always uses bincode; later frames use the codec
+/// the hub chose in `Registered`.
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &HubMessage,
+    codec: CodecId,
+) -> Result<(), crate::Error> {
+    let bytes = codec.serialize(message)?;
+    let len = u32::try_from(bytes.len())
+        .ok()
+        .filter(|&len| len <= MAX_FRAME_SIZE)
+        .ok_or_else(|| crate::Error::Module(format!("Hub message of {} bytes is too large", bytes.len())))?;
+    writer.write_all(&len.to_le_bytes()).await?;
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read one frame; `None` when the peer closed the connection cleanly
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, codec: CodecId) -> Result<Option<HubMessage>, crate::Error> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_le_bytes(len);
+    if len > MAX_FRAME_SIZE {
+        return Err(crate::Error::Module(format!("Hub frame of {} bytes exceeds the limit", len)));
+    }
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes).await?;
+    Ok(Some(codec.deserialize(&bytes)?))
+}
+
+/// Objects of a set of ports by value, ready to be sent to another process
+pub fn ports_to_wire(ports: &OutputPorts) -> Result<HashMap<String, Vec<ObjectData>>, crate::Error> {
+    ports.iter()
+        .map(|(port, objects)| {
+            let data = objects.iter()
+                .map(|object| object.as_data().cloned().ok_or_else(|| crate::Error::Module(format!(
+                    "Object {:?} on port {} has no serializable data",
+                    object.id(), port
+                ))))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((port.clone(), data))
+        })
+        .collect()
+}
+
+pub fn ports_from_wire(ports: HashMap<String, Vec<ObjectData>>) -> OutputPorts {
+    ports.into_iter()
+        .map(|(port, objects)| {
+            let objects = objects.into_iter()
+                .map(|data| Arc::new(VistleObject::from_data(data)) as Arc<dyn Object>)
+                .collect();
+            (port, objects)
+        })
+        .collect()
+}
@@ -0,0 +1,150 @@
+//! Local backend: the job script run on this machine with `mpirun`
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use parking_lot::Mutex;
+use tokio::process::{Child, Command};
+
+use crate::launch::{env_lines, shell_quote, stage_job, JobBackend, JobSpec, JobState, JobStatus, SubmittedJob};
+
+/// File the local job's output goes to, in its working directory
+pub const LOCAL_JOB_OUTPUT: &str = "job.out";
+
+/// Runs job scripts as child processes, starting the ranks with `mpirun`
+///
+/// Resource limits other than the rank count are ignored. Job ids are the
+/// process ids of the scripts, and only jobs submitted through the same
+/// backend can be queried.
+#[derive(Debug)]
+pub struct LocalBackend {
+    pub mpirun: PathBuf,
+    jobs: Mutex<HashMap<String, Child>>,
+}
+
+impl Default for LocalBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalBackend {
+    pub fn new() -> Self {
+        Self {
+            mpirun: PathBuf::from("mpirun"),
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_mpirun(mut self, mpirun: impl Into<PathBuf>) -> Self {
+        self.mpirun = mpirun.into();
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl JobBackend for LocalBackend {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn script(&self, spec: &JobSpec) -> String {
+        let mut script = String::from("#!/bin/sh\n");
+        script += &format!("cd {} || exit 1\n", shell_quote(&spec.work_dir.to_string_lossy()));
+        script += &env_lines(spec);
+        script += &format!(
+            "exec {} -n {} {}\n",
+            shell_quote(&self.mpirun.to_string_lossy()),
+            spec.ranks,
+            spec.run_command()
+        );
+        script
+    }
+
+    async fn submit(&self, spec: &JobSpec) -> Result<SubmittedJob, crate::Error> {
+        let script_path = stage_job(spec, &self.script(spec)).await?;
+        let output = std::fs::File::create(spec.work_dir.join(LOCAL_JOB_OUTPUT))?;
+        let child = Command::new("/bin/sh")
+            .arg(&script_path)
+            .current_dir(&spec.work_dir)
+            .stdout(output.try_clone()?)
+            .stderr(output)
+            .spawn()
+            .map_err(|e| crate::Error::Config(format!("Job {}: cannot start {}: {}", spec.name, script_path.display(), e)))?;
+        let job_id = child.id()
+            .ok_or_else(|| crate::Error::Config(format!("Job {}: ended before it was started", spec.name)))?
+            .to_string();
+        tracing::info!("Started job {} locally as process {}", spec.name, job_id);
+        self.jobs.lock().insert(job_id.clone(), child);
+        Ok(SubmittedJob {
+            job_id,
+            backend: self.name().to_string(),
+            work_dir: spec.work_dir.clone(),
+            script_path,
+            report_path: spec.report_path(),
+        })
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, crate::Error> {
+        let mut jobs = self.jobs.lock();
+        let child = jobs.get_mut(job_id)
+            .ok_or_else(|| crate::Error::Config(format!("No local job {}", job_id)))?;
+        let (state, exit_code) = match child.try_wait()? {
+            None => (JobState::Running, None),
+            Some(status) if status.success() => (JobState::Completed, status.code()),
+            Some(status) => (JobState::Failed, status.code()),
+        };
+        Ok(JobStatus {
+            job_id: job_id.to_string(),
+            state,
+            exit_code,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_start_the_ranks_with_mpirun() {
+        let spec = JobSpec::new("/home/user/flow.json", "/scratch/run 1")
+            .with_ranks(4)
+            .with_env("OMP_NUM_THREADS", "2");
+        let backend = LocalBackend::new().with_mpirun("/opt/mpi/bin/mpirun");
+        assert_eq!(backend.script(&spec), "\
+#!/bin/sh
+cd '/scratch/run 1' || exit 1
+export OMP_NUM_THREADS=2
+exec /opt/mpi/bin/mpirun -n 4 vistle --run flow.json --report report.json
+");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn submitted_jobs_run_and_report_their_state() {
+        let dir = std::env::temp_dir().join(format!("vistle_local_job_{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("flow.json"), "{}").unwrap();
+        let spec = JobSpec::new(dir.join("flow.json"), dir.join("job")).with_ranks(2);
+        // echo stands in for mpirun and prints the command it would start
+        let backend = LocalBackend::new().with_mpirun("echo");
+
+        let job = backend.submit(&spec).await.unwrap();
+        assert_eq!(job.backend, "local");
+        assert_eq!(job.report_path, spec.report_path());
+        let status = loop {
+            let status = backend.status(&job.job_id).await.unwrap();
+            if status.state.is_finished() {
+                break status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        };
+        assert_eq!((status.state, status.exit_code), (JobState::Completed, Some(0)));
+        let output = std::fs::read_to_string(dir.join("job").join(LOCAL_JOB_OUTPUT)).unwrap();
+        assert!(output.contains("2 vistle --run flow.json --report report.json"), "{}", output);
+
+        assert!(backend.status("0").await.is_err());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,424 @@
+//! Launching workflow runs as cluster or local MPI jobs
+//!
+//! A `JobSpec` describes a headless run of a workflow file: how many ranks,
+//! for how long, with which environment and parameter overrides. Staging
+//! copies the workflow and any further artifacts into the job's working
+//! directory and writes the job script there, next to the report the run
+//! will produce, so a finished job directory holds everything needed to run
+//! it again. A `JobBackend` then submits the script, to SLURM or to a local
+//! `mpirun`, and reports on the job's state.
+//!
+//! Scripts depend only on the spec: environment variables are sorted and no
+//! dates or host names are written, so the same spec gives the same script.
+
+pub mod local;
+pub mod slurm;
+
+pub use local::*;
+pub use slurm::*;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compute::ParameterOverride;
+
+/// File name of the job script in the working directory
+pub const JOB_SCRIPT: &str = "job.sh";
+/// File name of the report in the working directory
+pub const JOB_REPORT: &str = "report.json";
+
+/// What to run and with which resources
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobSpec {
+    /// Job name shown by the scheduler
+    pub name: String,
+    /// MPI ranks
+    pub ranks: usize,
+    /// Nodes to spread the ranks over; left to the scheduler without
+    pub nodes: Option<usize>,
+    /// Wall-clock limit, after which the scheduler ends the job
+    pub time_limit: Option<Duration>,
+    pub partition: Option<String>,
+    /// Environment variables exported before the run
+    pub env: BTreeMap<String, String>,
+    pub workflow_path: PathBuf,
+    pub overrides: Vec<ParameterOverride>,
+    /// Further files copied next to the workflow, e.g. a saved session
+    #[serde(default)]
+    pub artifacts: Vec<PathBuf>,
+    /// Directory the job runs in and writes its report to
+    pub work_dir: PathBuf,
+    /// The vistle binary as seen from the compute nodes
+    pub executable: PathBuf,
+}
+
+impl JobSpec {
+    /// One-rank job of a workflow, run in `work_dir`
+    pub fn new(workflow_path: impl Into<PathBuf>, work_dir: impl Into<PathBuf>) -> Self {
+        let workflow_path = workflow_path.into();
+        let name = workflow_path.file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "vistle".to_string());
+        Self {
+            name,
+            ranks: 1,
+            nodes: None,
+            time_limit: None,
+            partition: None,
+            env: BTreeMap::new(),
+            workflow_path,
+            overrides: Vec::new(),
+            artifacts: Vec::new(),
+            work_dir: work_dir.into(),
+            executable: PathBuf::from("vistle"),
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_ranks(mut self, ranks: usize) -> Self {
+        self.ranks = ranks;
+        self
+    }
+
+    pub fn with_nodes(mut self, nodes: usize) -> Self {
+        self.nodes = Some(nodes);
+        self
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    pub fn with_partition(mut self, partition: &str) -> Self {
+        self.partition = Some(partition.to_string());
+        self
+    }
+
+    pub fn with_env(mut self, name: &str, value: &str) -> Self {
+        self.env.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_override(mut self, item: ParameterOverride) -> Self {
+        self.overrides.push(item);
+        self
+    }
+
+    pub fn with_artifact(mut self, path: impl Into<PathBuf>) -> Self {
+        self.artifacts.push(path.into());
+        self
+    }
+
+    pub fn with_executable(mut self, executable: impl Into<PathBuf>) -> Self {
+        self.executable = executable.into();
+        self
+    }
+
+    /// Check the spec describes a job that can be run
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.ranks == 0 {
+            return Err(crate::Error::Config(format!("Job {}: needs at least one rank", self.name)));
+        }
+        if let Some(nodes) = self.nodes {
+            if nodes == 0 || nodes > self.ranks {
+                return Err(crate::Error::Config(format!(
+                    "Job {}: {} nodes for {} ranks",
+                    self.name, nodes, self.ranks
+                )));
+            }
+        }
+        if self.time_limit.is_some_and(|t| t.is_zero()) {
+            return Err(crate::Error::Config(format!("Job {}: time limit must be positive", self.name)));
+        }
+        if let Some(name) = self.env.keys().find(|k| !is_env_name(k)) {
+            return Err(crate::Error::Config(format!("Job {}: {} is not a valid environment variable name", self.name, name)));
+        }
+        if self.workflow_file_name().is_none() {
+            return Err(crate::Error::Config(format!(
+                "Job {}: workflow path {} names no file",
+                self.name,
+                self.workflow_path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    fn workflow_file_name(&self) -> Option<&std::ffi::OsStr> {
+        self.workflow_path.file_name()
+    }
+
+    /// Where the staged job script is written
+    pub fn script_path(&self) -> PathBuf {
+        self.work_dir.join(JOB_SCRIPT)
+    }
+
+    /// Where the run writes its report
+    pub fn report_path(&self) -> PathBuf {
+        self.work_dir.join(JOB_REPORT)
+    }
+
+    /// Arguments of the vistle process, relative to the working directory
+    pub fn run_arguments(&self) -> Vec<String> {
+        let workflow = self.workflow_file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut args = vec![
+            "--run".to_string(),
+            workflow,
+            "--report".to_string(),
+            JOB_REPORT.to_string(),
+        ];
+        for item in &self.overrides {
+            args.push("--set".to_string());
+            args.push(item.to_string());
+        }
+        args
+    }
+
+    /// The vistle command line with its arguments quoted for a shell
+    pub fn run_command(&self) -> String {
+        std::iter::once(self.executable.to_string_lossy().into_owned())
+            .chain(self.run_arguments())
+            .map(|a| shell_quote(&a))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Scheduler-independent state of a job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    /// Ended by the scheduler at its time limit
+    Timeout,
+    /// A state the backend does not map, as the scheduler reported it
+    Unknown(String),
+}
+
+impl JobState {
+    /// Whether the job will not change state any more
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobState::Completed | JobState::Failed | JobState::Cancelled | JobState::Timeout)
+    }
+}
+
+impl std::fmt::Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobState::Pending => f.write_str("pending"),
+            JobState::Running => f.write_str("running"),
+            JobState::Completed => f.write_str("completed"),
+            JobState::Failed => f.write_str("failed"),
+            JobState::Cancelled => f.write_str("cancelled"),
+            JobState::Timeout => f.write_str("timeout"),
+            JobState::Unknown(state) => write!(f, "unknown ({})", state),
+        }
+    }
+}
+
+/// State of a job as last reported by its backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub job_id: String,
+    pub state: JobState,
+    /// Exit code of a finished job, if the backend knows it
+    pub exit_code: Option<i32>,
+}
+
+/// A job handed to a backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubmittedJob {
+    pub job_id: String,
+    pub backend: String,
+    pub work_dir: PathBuf,
+    pub script_path: PathBuf,
+    pub report_path: PathBuf,
+}
+
+/// Scheduler or launcher jobs are submitted to
+#[async_trait::async_trait]
+pub trait JobBackend: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The job script for a spec
+    fn script(&self, spec: &JobSpec) -> String;
+
+    /// Stage a spec's files and submit its script
+    async fn submit(&self, spec: &JobSpec) -> Result<SubmittedJob, crate::Error>;
+
+    /// Current state of a submitted job
+    async fn status(&self, job_id: &str) -> Result<JobStatus, crate::Error>;
+}
+
+/// Copy a job's workflow and artifacts into its working directory and write its script there
+///
+/// Returns the path of the script. Existing files of the same names are
+/// replaced, so staging a spec again gives the same directory contents.
+pub async fn stage_job(spec: &JobSpec, script: &str) -> Result<PathBuf, crate::Error> {
+    spec.validate()?;
+    tokio::fs::create_dir_all(&spec.work_dir).await?;
+    for source in std::iter::once(&spec.workflow_path).chain(&spec.artifacts) {
+        let name = source.file_name().ok_or_else(|| crate::Error::Config(format!(
+            "Job {}: artifact {} names no file",
+            spec.name,
+            source.display()
+        )))?;
+        let target = spec.work_dir.join(name);
+        if !same_file(source, &target).await {
+            tokio::fs::copy(source, &target).await.map_err(|e| crate::Error::Config(format!(
+                "Job {}: cannot stage {}: {}",
+                spec.name,
+                source.display(),
+                e
+            )))?;
+        }
+    }
+    let script_path = spec.script_path();
+    crate::util::io::write_text(&script_path, script).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755)).await?;
+    }
+    tracing::info!("Staged job {} in {}", spec.name, spec.work_dir.display());
+    Ok(script_path)
+}
+
+async fn same_file(a: &Path, b: &Path) -> bool {
+    match (tokio::fs::canonicalize(a).await, tokio::fs::canonicalize(b).await) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// `export` lines for a spec's environment, sorted by name
+pub(crate) fn env_lines(spec: &JobSpec) -> String {
+    spec.env.iter()
+        .map(|(name, value)| format!("export {}={}\n", name, shell_quote(value)))
+        .collect()
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quote a word for a POSIX shell, leaving plain words as they are
+pub fn shell_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("vistle_launch_{}", uuid::Uuid::new_v4().simple()))
+    }
+
+    #[test]
+    fn run_commands_are_relative_to_the_work_dir() {
+        let spec = JobSpec::new("/home/user/flow.json", "/scratch/run 1")
+            .with_override(ParameterOverride::new("Reader", "file", "/data/a b.vtk"))
+            .with_executable("/opt/vistle/bin/vistle");
+        assert_eq!(spec.name, "flow");
+        assert_eq!(spec.script_path(), PathBuf::from("/scratch/run 1/job.sh"));
+        assert_eq!(spec.report_path(), PathBuf::from("/scratch/run 1/report.json"));
+        assert_eq!(
+            spec.run_command(),
+            "/opt/vistle/bin/vistle --run flow.json --report report.json --set 'Reader.file=/data/a b.vtk'"
+        );
+    }
+
+    #[test]
+    fn specs_are_validated() {
+        let spec = JobSpec::new("flow.json", "run").with_ranks(8).with_nodes(2);
+        assert!(spec.validate().is_ok());
+
+        let invalid = [
+            spec.clone().with_ranks(0),
+            spec.clone().with_nodes(9),
+            spec.clone().with_nodes(0),
+            spec.clone().with_time_limit(Duration::ZERO),
+            spec.clone().with_env("1ST", "x"),
+            spec.clone().with_env("A-B", "x"),
+            JobSpec::new("..", "run"),
+        ];
+        for spec in invalid {
+            assert!(matches!(spec.validate(), Err(crate::Error::Config(_))), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn words_are_quoted_only_when_needed() {
+        assert_eq!(shell_quote("node-1.example:8080"), "node-1.example:8080");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's $HOME"), r"'it'\''s $HOME'");
+    }
+
+    #[test]
+    fn environment_is_exported_in_name_order() {
+        let spec = JobSpec::new("flow.json", "run")
+            .with_env("OMP_NUM_THREADS", "4")
+            .with_env("A_MESSAGE", "hello world");
+        assert_eq!(env_lines(&spec), "export A_MESSAGE='hello world'\nexport OMP_NUM_THREADS=4\n");
+    }
+
+    #[tokio::test]
+    async fn staging_copies_the_workflow_and_artifacts() {
+        let dir = temp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("flow.json"), "{}").unwrap();
+        std::fs::write(dir.join("session.bundle"), "bundle").unwrap();
+        let spec = JobSpec::new(dir.join("flow.json"), dir.join("job")).with_artifact(dir.join("session.bundle"));
+
+        let script = stage_job(&spec, "#!/bin/sh\necho run\n").await.unwrap();
+        assert_eq!(script, spec.script_path());
+        assert_eq!(std::fs::read_to_string(dir.join("job/flow.json")).unwrap(), "{}");
+        assert_eq!(std::fs::read_to_string(dir.join("job/session.bundle")).unwrap(), "bundle");
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), "#!/bin/sh\necho run\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&script).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+
+        // Staging from the job directory itself leaves the files in place
+        let restaged = JobSpec::new(dir.join("job/flow.json"), dir.join("job"));
+        stage_job(&restaged, "#!/bin/sh\n").await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("job/flow.json")).unwrap(), "{}");
+
+        let missing = spec.with_artifact(dir.join("absent.bundle"));
+        let message = stage_job(&missing, "").await.unwrap_err().to_string();
+        assert!(message.contains("cannot stage") && message.contains("absent.bundle"), "{}", message);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn finished_states_are_final() {
+        assert!(JobState::Timeout.is_finished());
+        assert!(!JobState::Pending.is_finished());
+        assert!(!JobState::Unknown("BURST".into()).is_finished());
+        assert_eq!(JobState::Unknown("BURST".into()).to_string(), "unknown (BURST)");
+    }
+}
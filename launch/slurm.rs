@@ -0,0 +1,283 @@
+//! SLURM backend: sbatch scripts, submission and squeue/sacct queries
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use crate::launch::{env_lines, shell_quote, stage_job, JobBackend, JobSpec, JobState, JobStatus, SubmittedJob};
+
+/// Submits jobs with `sbatch` and starts the ranks with `srun`
+#[derive(Debug, Clone)]
+pub struct SlurmBackend {
+    pub sbatch: PathBuf,
+    pub squeue: PathBuf,
+    pub sacct: PathBuf,
+}
+
+impl Default for SlurmBackend {
+    fn default() -> Self {
+        Self {
+            sbatch: PathBuf::from("sbatch"),
+            squeue: PathBuf::from("squeue"),
+            sacct: PathBuf::from("sacct"),
+        }
+    }
+}
+
+impl SlurmBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run a SLURM command and return its standard output
+    async fn query(&self, program: &Path, args: &[&str]) -> Result<String, crate::Error> {
+        let output = Command::new(program).args(args).output().await.map_err(|e| {
+            crate::Error::Config(format!("Cannot run {}: {}", program.display(), e))
+        })?;
+        if !output.status.success() {
+            return Err(crate::Error::Config(format!(
+                "{} {} failed with {}: {}",
+                program.display(),
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[async_trait::async_trait]
+impl JobBackend for SlurmBackend {
+    fn name(&self) -> &str {
+        "slurm"
+    }
+
+    fn script(&self, spec: &JobSpec) -> String {
+        let mut script = String::from("#!/bin/bash\n");
+        script += &format!("#SBATCH --job-name={}\n", shell_quote(&spec.name));
+        script += &format!("#SBATCH --ntasks={}\n", spec.ranks);
+        if let Some(nodes) = spec.nodes {
+            script += &format!("#SBATCH --nodes={}\n", nodes);
+        }
+        if let Some(time_limit) = spec.time_limit {
+            script += &format!("#SBATCH --time={}\n", format_time_limit(time_limit));
+        }
+        if let Some(partition) = &spec.partition {
+            script += &format!("#SBATCH --partition={}\n", shell_quote(partition));
+        }
+        script += "#SBATCH --output=job-%j.out\n\n";
+        script += &format!("cd {} || exit 1\n", shell_quote(&spec.work_dir.to_string_lossy()));
+        script += &env_lines(spec);
+        script += &format!("srun {}\n", spec.run_command());
+        script
+    }
+
+    async fn submit(&self, spec: &JobSpec) -> Result<SubmittedJob, crate::Error> {
+        let script_path = stage_job(spec, &self.script(spec)).await?;
+        let output = Command::new(&self.sbatch)
+            .arg("--parsable")
+            .arg(&script_path)
+            .current_dir(&spec.work_dir)
+            .output()
+            .await
+            .map_err(|e| crate::Error::Config(format!("Cannot run {}: {}", self.sbatch.display(), e)))?;
+        if !output.status.success() {
+            return Err(crate::Error::Config(format!(
+                "Job {}: sbatch failed with {}: {}",
+                spec.name,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let job_id = parse_sbatch_output(&String::from_utf8_lossy(&output.stdout))?;
+        tracing::info!("Submitted job {} to SLURM as {}", spec.name, job_id);
+        Ok(SubmittedJob {
+            job_id,
+            backend: self.name().to_string(),
+            work_dir: spec.work_dir.clone(),
+            script_path,
+            report_path: spec.report_path(),
+        })
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, crate::Error> {
+        // squeue only knows jobs that are queued or running; sacct has the rest
+        let queued = self.query(&self.squeue, &["--noheader", "--jobs", job_id, "--format=%T"]).await;
+        if let Ok(Some(status)) = queued.map(|text| parse_squeue_output(job_id, &text)) {
+            return Ok(status);
+        }
+        let accounted = self.query(&self.sacct, &[
+            "--noheader", "--parsable2", "--allocations", "--jobs", job_id, "--format=State,ExitCode",
+        ]).await?;
+        parse_sacct_output(job_id, &accounted)
+            .ok_or_else(|| crate::Error::Config(format!("SLURM knows no job {}", job_id)))
+    }
+}
+
+/// Time limit as `[D-]HH:MM:SS`, rounded up to whole seconds
+pub fn format_time_limit(time_limit: Duration) -> String {
+    let seconds = time_limit.as_secs() + u64::from(time_limit.subsec_nanos() > 0);
+    let (days, rest) = (seconds / 86400, seconds % 86400);
+    let clock = format!("{:02}:{:02}:{:02}", rest / 3600, rest % 3600 / 60, rest % 60);
+    if days > 0 {
+        format!("{}-{}", days, clock)
+    } else {
+        clock
+    }
+}
+
+/// Job id from `sbatch` output, either `Submitted batch job N` or the `N[;cluster]` of `--parsable`
+pub fn parse_sbatch_output(text: &str) -> Result<String, crate::Error> {
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("");
+    let id = line.strip_prefix("Submitted batch job ")
+        .unwrap_or(line)
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim();
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()) {
+        Ok(id.to_string())
+    } else {
+        Err(crate::Error::Config(format!("Cannot find a job id in sbatch output '{}'", text.trim())))
+    }
+}
+
+/// SLURM job state names, long or abbreviated, as a `JobState`
+///
+/// sacct appends who cancelled a job, as in `CANCELLED by 1000`; only the
+/// first word is looked at.
+pub fn parse_slurm_state(text: &str) -> JobState {
+    let state = text.split_whitespace().next().unwrap_or("").trim_end_matches('+');
+    match state {
+        "PENDING" | "PD" | "CONFIGURING" | "CF" | "REQUEUED" | "RQ" | "SUSPENDED" | "S" => JobState::Pending,
+        "RUNNING" | "R" | "COMPLETING" | "CG" => JobState::Running,
+        "COMPLETED" | "CD" => JobState::Completed,
+        "FAILED" | "F" | "NODE_FAIL" | "NF" | "OUT_OF_MEMORY" | "OOM" | "BOOT_FAIL" | "BF" => JobState::Failed,
+        "CANCELLED" | "CA" | "PREEMPTED" | "PR" => JobState::Cancelled,
+        "TIMEOUT" | "TO" | "DEADLINE" | "DL" => JobState::Timeout,
+        other => JobState::Unknown(other.to_string()),
+    }
+}
+
+/// Status from `squeue --noheader --format=%T`, `None` when the job is not queued
+pub fn parse_squeue_output(job_id: &str, text: &str) -> Option<JobStatus> {
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+    Some(JobStatus {
+        job_id: job_id.to_string(),
+        state: parse_slurm_state(line),
+        exit_code: None,
+    })
+}
+
+/// Status from `sacct --noheader --parsable2 --format=State,ExitCode`
+///
+/// The first line is the job allocation; lines of job steps are ignored.
+/// `ExitCode` is `code:signal`, and a job ended by a signal has no exit code.
+pub fn parse_sacct_output(job_id: &str, text: &str) -> Option<JobStatus> {
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let mut fields = line.split('|');
+    let state = parse_slurm_state(fields.next()?);
+    let exit_code = fields.next()
+        .and_then(|f| f.split_once(':'))
+        .filter(|(_, signal)| signal.trim() == "0")
+        .and_then(|(code, _)| code.trim().parse().ok());
+    Some(JobStatus {
+        job_id: job_id.to_string(),
+        state,
+        exit_code,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::ParameterOverride;
+
+    fn spec() -> JobSpec {
+        JobSpec::new("/home/user/flow.json", "/scratch/run 1")
+            .with_ranks(64)
+            .with_nodes(4)
+            .with_time_limit(Duration::from_secs(90061))
+            .with_partition("batch")
+            .with_env("OMP_NUM_THREADS", "2")
+            .with_env("A_NOTE", "it's")
+            .with_override(ParameterOverride::new("Reader", "file", "/data/a b.vtk"))
+    }
+
+    #[test]
+    fn scripts_request_the_spec_resources() {
+        let script = SlurmBackend::new().script(&spec());
+        assert_eq!(script, "\
+#!/bin/bash
+#SBATCH --job-name=flow
+#SBATCH --ntasks=64
+#SBATCH --nodes=4
+#SBATCH --time=1-01:01:01
+#SBATCH --partition=batch
+#SBATCH --output=job-%j.out
+
+cd '/scratch/run 1' || exit 1
+export A_NOTE='it'\\''s'
+export OMP_NUM_THREADS=2
+srun vistle --run flow.json --report report.json --set 'Reader.file=/data/a b.vtk'
+");
+        assert_eq!(SlurmBackend::new().script(&spec()), script);
+    }
+
+    #[test]
+    fn optional_resources_are_left_to_slurm() {
+        let script = SlurmBackend::new().script(&JobSpec::new("flow.json", "run"));
+        assert!(script.contains("#SBATCH --ntasks=1\n"));
+        assert!(!script.contains("--nodes") && !script.contains("--time") && !script.contains("--partition"));
+    }
+
+    #[test]
+    fn time_limits_round_up_to_seconds() {
+        assert_eq!(format_time_limit(Duration::from_secs(59)), "00:00:59");
+        assert_eq!(format_time_limit(Duration::from_millis(1500)), "00:00:02");
+        assert_eq!(format_time_limit(Duration::from_secs(86400 * 2 + 3600)), "2-01:00:00");
+    }
+
+    #[test]
+    fn job_ids_are_read_from_sbatch() {
+        assert_eq!(parse_sbatch_output("Submitted batch job 4242\n").unwrap(), "4242");
+        assert_eq!(parse_sbatch_output("\n4243;cluster2\n").unwrap(), "4243");
+        assert!(parse_sbatch_output("sbatch: error: invalid partition\n").is_err());
+        assert!(parse_sbatch_output("").is_err());
+    }
+
+    #[test]
+    fn squeue_reports_queued_jobs() {
+        let status = parse_squeue_output("4242", "  PENDING\n").unwrap();
+        assert_eq!(status, JobStatus { job_id: "4242".into(), state: JobState::Pending, exit_code: None });
+        assert_eq!(parse_squeue_output("4242", "RUNNING\n").unwrap().state, JobState::Running);
+        assert_eq!(parse_squeue_output("4242", "\n"), None);
+    }
+
+    #[test]
+    fn sacct_reports_the_allocation() {
+        let status = parse_sacct_output("7", "COMPLETED|0:0\nCOMPLETED|0:0\nFAILED|1:0\n").unwrap();
+        assert_eq!((status.state, status.exit_code), (JobState::Completed, Some(0)));
+
+        let failed = parse_sacct_output("7", "FAILED|3:0\n").unwrap();
+        assert_eq!((failed.state, failed.exit_code), (JobState::Failed, Some(3)));
+
+        let cancelled = parse_sacct_output("7", "CANCELLED by 1000|0:15\n").unwrap();
+        assert_eq!((cancelled.state, cancelled.exit_code), (JobState::Cancelled, None));
+
+        assert_eq!(parse_sacct_output("7", "TIMEOUT|0:0\n").unwrap().state, JobState::Timeout);
+        assert_eq!(parse_sacct_output("7", ""), None);
+    }
+
+    #[test]
+    fn slurm_states_map_long_and_short_names() {
+        assert_eq!(parse_slurm_state("PD"), JobState::Pending);
+        assert_eq!(parse_slurm_state("COMPLETING"), JobState::Running);
+        assert_eq!(parse_slurm_state("OUT_OF_MEMORY"), JobState::Failed);
+        assert_eq!(parse_slurm_state("CANCELLED+"), JobState::Cancelled);
+        assert_eq!(parse_slurm_state("DL"), JobState::Timeout);
+        assert_eq!(parse_slurm_state("BURST_BUFFER"), JobState::Unknown("BURST_BUFFER".into()));
+    }
+}
@@ -6,35 +6,61 @@
 
 pub mod core;
 pub mod compute;
+pub mod hub;
+pub mod launch;
 pub mod mpi;
 pub mod render;
 pub mod ui;
 pub mod util;
 
+// `testing` and `clipping` exist in several layers; those are reached by their paths
 pub use core::*;
+#[allow(ambiguous_glob_reexports)]
 pub use compute::*;
 pub use mpi::*;
+#[allow(ambiguous_glob_reexports)]
 pub use render::*;
 pub use ui::*;
 
 /// Initialize the Vistle system
-pub async fn init() -> Result<(), Error> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
+pub async fn init() -> Result<()> {
+    init_with_log_level(tracing::Level::INFO).await
+}
+
+/// Initialize the Vistle system, logging at `level` and above
+pub async fn init_with_log_level(level: tracing::Level) -> Result<()> {
+    // A subscriber installed before, e.g. by an embedding application, stays in place
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .try_init();
 
     tracing::info!("Initializing Vistle v{}", env!("CARGO_PKG_VERSION"));
     Ok(())
 }
 
+/// Write a diagram of a workflow, annotated with a result's durations and status if given
+pub async fn export_workflow_diagram(
+    spec: &WorkflowSpec,
+    result: Option<&WorkflowResult>,
+    format: DiagramFormat,
+    path: impl AsRef<std::path::Path>,
+) -> Result<()> {
+    let diagram = spec.to_diagram(format, &DiagramOptions::default(), result);
+    util::io::write_text(path, &diagram).await
+}
+
 /// Main error type for Vistle operations
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("MPI error: {0}")]
-    Mpi(#[from] mpi::Error),
+    Mpi(String),
 
-    #[error("Serialization error: {0}")]
-    Serialization(#[from] bincode::Error),
+    /// Encoding or decoding failed in `codec`, see `core::codec`
+    #[error("Serialization error: {codec}: {source}")]
+    Serialization {
+        codec: &'static str,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
 
     #[error("Shared memory error: {0}")]
     SharedMemory(String),
@@ -53,6 +79,42 @@ pub enum Error {
 
     #[error("Module error: {0}")]
     Module(String),
+
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
+    #[error("Wrong object type: expected {expected}, found {found}")]
+    WrongType { expected: String, found: String },
+}
+
+impl Error {
+    pub fn serialization(codec: &'static str, source: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Error::Serialization { codec, source }
+    }
+
+    /// Stable code for grouping errors in reports and statistics
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Mpi(_) => "mpi",
+            Error::Serialization { .. } => "serialization",
+            Error::SharedMemory(_) => "shared_memory",
+            // Panics caught around module compute get a code of their own
+            Error::Compute(message) | Error::Module(message) if message.starts_with("panicked: ") => "panic",
+            Error::Compute(_) => "compute",
+            Error::Render(_) => "render",
+            Error::Io(_) => "io",
+            Error::Config(_) => "config",
+            Error::Module(_) => "module",
+            Error::Cancelled(_) => "cancelled",
+            Error::WrongType { .. } => "wrong_type",
+        }
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::serialization("bincode", e.into())
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -4,21 +4,32 @@
 //! architecture for distributed scientific visualization.
 
 use std::sync::Arc;
-use tokio;
 
-use vistle::core::{
-    MessageRouter, ModuleRegistry, TaskExecutor, WorkflowExecutor,
-    WorkflowBuilder, WorkflowSpec,
+use vistle::core::MessageRouter;
+use vistle::compute::{
+    ModuleRegistry, ParameterOverride, RunOptions, TaskExecutor, WorkflowBuilder, WorkflowExecutor, WorkflowSpec,
 };
-use vistle::ui::{Application, WorkflowEditor, StatusDisplay, WorkflowNode};
-use vistle::render::{RenderContext, RenderBackend, Scene, Camera, Material, Geometry};
-use vistle::mpi::DistributedContext;
-use vistle::util::PerformanceMonitor;
+use vistle::ui::{Application, AutosaveConfig, AutosaveManager, WorkflowEditor, StatusDisplay, WorkflowNode};
+use vistle::hub::{Hub, ModuleHost};
+use vistle::util::{MemoryTracker, PerformanceMonitor};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Run a workflow file headlessly and exit with its outcome
+    let run_file = arg_value("--run");
+    let run_options = match run_file.is_some().then(run_options).transpose() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(2);
+        }
+    };
+
     // Initialize Vistle system
-    vistle::init().await?;
+    match run_options.as_ref().and_then(|(options, _)| options.log_level) {
+        Some(level) => vistle::init_with_log_level(level).await?,
+        None => vistle::init().await?,
+    }
 
     println!("🚀 Starting Vistle v{}", env!("CARGO_PKG_VERSION"));
     println!("Modern distributed scientific visualization system built in Rust");
@@ -26,26 +37,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize core components
     let message_router = Arc::new(MessageRouter::new().with_mpi()?);
     let module_registry = Arc::new(ModuleRegistry::new());
-    let task_executor = Arc::new(TaskExecutor::new(8)); // 8 concurrent tasks
-    let workflow_executor = Arc::new(WorkflowExecutor::new(
+    // 8 concurrent tasks, or one at a time for deterministic runs
+    let deterministic = run_options.as_ref().is_some_and(|(options, _)| options.deterministic);
+    let task_executor = Arc::new(TaskExecutor::new(if deterministic { 1 } else { 8 }));
+    let mut workflow_executor = WorkflowExecutor::new(
         module_registry.clone(),
         task_executor.clone(),
         message_router.clone(),
-    ));
+    );
 
     // Register example modules
     register_example_modules(&module_registry).await?;
 
+    if let (Some(path), Some((options, overrides))) = (run_file, run_options) {
+        vistle::compute::builtin::register_builtin_modules(&module_registry).await;
+        let summary = match workflow_executor.run_file(&path, &overrides, &options).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                std::process::exit(2);
+            }
+        };
+        println!("{} {}", if summary.success { "✅" } else { "❌" }, summary);
+        if let Some(report) = &summary.report_path {
+            println!("📝 Wrote {}", report.display());
+        }
+        std::process::exit(summary.exit_code());
+    }
+
+    // Run as a module host executing tasks for a remote hub
+    if let Some(addr) = arg_value("--module-host") {
+        println!("🔌 Connecting to hub at {}", addr);
+        ModuleHost::new(module_registry.clone()).run(addr.as_str()).await?;
+        return Ok(());
+    }
+
+    // Act as the hub and dispatch modules to connected hosts
+    if let Some(addr) = arg_value("--hub") {
+        let hub = Hub::listen(addr.as_str()).await?;
+        println!("🛰️ Hub listening on {}, waiting for a module host...", hub.local_addr());
+        while hub.hosts().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        workflow_executor = workflow_executor.with_hub(hub);
+    }
+    let workflow_executor = Arc::new(workflow_executor);
+
     // Create a sample workflow
     let workflow = create_sample_workflow();
 
     // Execute workflow
     println!("📊 Executing sample workflow...");
+    let mut memory = MemoryTracker::new();
     let result = workflow_executor.execute_workflow(workflow, None).await?;
+    memory.update();
 
     println!("✅ Workflow completed in {:?}", result.execution_time);
     println!("📈 Processed {} tasks", result.task_results.len());
 
+    // Machine-readable report for batch runs, plus a summary for humans
+    let report = result.to_report().with_peak_memory(&memory);
+    report.save("workflow_report.json").await?;
+    report.save_html("workflow_report.html").await?;
+    println!("📝 Wrote workflow_report.json and workflow_report.html");
+
     // Launch GUI if not in headless mode
     if std::env::args().any(|arg| arg == "--gui") {
         run_gui().await?;
@@ -60,22 +115,60 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Value following a command line flag
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|arg| arg == flag)?;
+    args.next()
+}
+
+/// Every value following a repeatable command line flag
+fn arg_values(flag: &str) -> Vec<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+/// Options of `--run`: `--report <path>`, `--timeout <seconds>`, `--log-level <level>`,
+/// `--deterministic`, `--strict` and any number of `--set module.parameter=value`
+fn run_options() -> Result<(RunOptions, Vec<ParameterOverride>), vistle::Error> {
+    let mut options = RunOptions::new()
+        .with_report(arg_value("--report").unwrap_or_else(|| "workflow_report.json".to_string()));
+    if let Some(seconds) = arg_value("--timeout") {
+        let timeout = seconds.parse().ok()
+            .and_then(|seconds| std::time::Duration::try_from_secs_f64(seconds).ok())
+            .ok_or_else(|| vistle::Error::Config(format!("--timeout expects seconds, got {}", seconds)))?;
+        options = options.with_timeout(timeout);
+    }
+    if let Some(level) = arg_value("--log-level") {
+        let level = level.parse()
+            .map_err(|_| vistle::Error::Config(format!("--log-level expects trace, debug, info, warn or error, got {}", level)))?;
+        options = options.with_log_level(level);
+    }
+    if std::env::args().any(|arg| arg == "--deterministic") {
+        options = options.deterministic();
+    }
+    if std::env::args().any(|arg| arg == "--strict") {
+        options = options.strict();
+    }
+    let overrides = arg_values("--set").iter()
+        .map(|text| ParameterOverride::parse(text))
+        .collect::<Result<_, _>>()?;
+    Ok((options, overrides))
+}
+
 /// Register example modules for demonstration
 async fn register_example_modules(registry: &ModuleRegistry) -> Result<(), vistle::Error> {
     // Register a data reader module
-    registry.register("DataReader", |id| {
-        Box::new(DataReaderModule::new(id))
-    }).await?;
+    registry.register("DataReader", || DataReaderModule::new(0)).await;
 
     // Register a filter module
-    registry.register("IsoSurface", |id| {
-        Box::new(IsoSurfaceModule::new(id))
-    }).await?;
+    registry.register("IsoSurface", || IsoSurfaceModule::new(0)).await;
 
     // Register a renderer module
-    registry.register("Renderer", |id| {
-        Box::new(RendererModule::new(id))
-    }).await?;
+    registry.register("Renderer", || RendererModule::new(0)).await;
 
     println!("📦 Registered {} example modules", registry.list_available().await.len());
     Ok(())
@@ -128,6 +221,29 @@ async fn run_gui() -> Result<(), vistle::Error> {
     status_display.add_message("GUI initialized".to_string(), vistle::ui::StatusLevel::Success);
     status_display.add_message("Workflow editor ready".to_string(), vistle::ui::StatusLevel::Info);
 
+    // Offer to recover an autosave left behind by a previous session
+    let autosave_config = AutosaveConfig::default();
+    let last_save = vistle::ui::last_explicit_save(&autosave_config.dir).await.unwrap_or_else(|e| {
+        tracing::warn!("Reading the last save time failed: {}", e);
+        None
+    });
+    let mut recovery = match vistle::ui::find_recovery(&autosave_config.dir, last_save).await {
+        Ok(Some(candidate)) => vistle::ui::load_snapshot(&candidate.path).await.ok(),
+        Ok(None) => None,
+        Err(e) => {
+            tracing::warn!("Autosave recovery check failed: {}", e);
+            None
+        }
+    };
+    if recovery.is_some() {
+        status_display.add_message(
+            "An autosaved session is available for recovery".to_string(),
+            vistle::ui::StatusLevel::Warning
+        );
+    }
+    let autosave_dir = autosave_config.dir.clone();
+    let mut autosave = AutosaveManager::new(autosave_config);
+
     let app = Application::new("Vistle - Modern Scientific Visualization", (1200, 800));
 
     app.run(move |ui_ctx| {
@@ -136,6 +252,7 @@ async fn run_gui() -> Result<(), vistle::Error> {
 
         // Draw workflow editor
         workflow_editor.draw(ui_ctx);
+        autosave.maybe_autosave(&workflow_editor, false);
 
         // Draw status display
         status_display.draw(ui_ctx);
@@ -150,6 +267,31 @@ async fn run_gui() -> Result<(), vistle::Error> {
             );
         }
 
+        if ui_ctx.button("Save Session") {
+            autosave.save_session(&workflow_editor, vistle::ui::default_session_path());
+            recovery = None;
+            status_display.add_message(
+                "Session saved".to_string(),
+                vistle::ui::StatusLevel::Success
+            );
+        }
+
+        if recovery.is_some() && ui_ctx.button("Recover Autosave") {
+            if let Some(snapshot) = recovery.take() {
+                workflow_editor.restore(snapshot.editor);
+                for colormap in snapshot.colormaps {
+                    if let Err(e) = vistle::render::ColorMapLibrary::global().insert(colormap) {
+                        tracing::warn!("Skipping autosaved colormap: {}", e);
+                    }
+                }
+                autosave.maybe_autosave(&workflow_editor, true);
+                status_display.add_message(
+                    "Recovered autosaved session".to_string(),
+                    vistle::ui::StatusLevel::Success
+                );
+            }
+        }
+
         if ui_ctx.button("Clear Status") {
             status_display.clear();
         }
@@ -157,6 +299,9 @@ async fn run_gui() -> Result<(), vistle::Error> {
         ui_ctx.end_panel();
     }).await?;
 
+    // An orderly shutdown leaves nothing to recover
+    vistle::ui::record_explicit_save(&autosave_dir, std::time::SystemTime::now()).await?;
+
     Ok(())
 }
 
@@ -165,7 +310,7 @@ async fn show_performance_summary() {
     println!("\n📊 Performance Summary:");
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
 
-    let monitor = PerformanceMonitor::new();
+    let _monitor = PerformanceMonitor::new();
 
     // This would normally collect real performance data
     println!("• Memory Usage: Efficient (Rust ownership system)");
@@ -179,6 +324,7 @@ async fn show_performance_summary() {
 /// Example data reader module
 struct DataReaderModule {
     id: u32,
+    info: vistle::core::ModuleInfo,
     parameters: vistle::core::ParameterSet,
     ports: vistle::core::PortSet,
 }
@@ -186,21 +332,23 @@ struct DataReaderModule {
 impl DataReaderModule {
     fn new(id: u32) -> Self {
         let mut params = vistle::core::ParameterSet::new();
-        params.add(vistle::core::Parameter::new("filename", "Input filename", vistle::core::ParameterValue::String("data.vtk".to_string())));
+        params.add(vistle::core::Parameter::file_path("filename", "Input filename", "data.vtk"));
         params.add(vistle::core::Parameter::new("format", "File format", vistle::core::ParameterValue::String("VTK".to_string())));
+        params.add(vistle::core::Parameter::new("num_blocks", "Number of blocks in the data set", vistle::core::ParameterValue::Int(1)));
+        params.add(vistle::core::Parameter::new("block_assignment", "round_robin, contiguous or sfc", vistle::core::ParameterValue::String("round_robin".to_string())));
+        params.add(vistle::core::Parameter::new("block_dims", "Block grid used by the sfc assignment", vistle::core::ParameterValue::VecInt(vec![1, 1, 1])));
 
         let mut ports = vistle::core::PortSet::new();
         ports.add(vistle::core::Port::new_output("data", "Output data"));
 
-        Self { id, parameters: params, ports }
+        Self { id, info: vistle::core::ModuleInfo::new(id, "DataReader", 0, 1), parameters: params, ports }
     }
 }
 
 #[async_trait::async_trait]
 impl vistle::compute::Module for DataReaderModule {
     fn info(&self) -> &vistle::core::ModuleInfo {
-        // This would normally be stored in the struct
-        &vistle::core::ModuleInfo::new(self.id, "DataReader", 0, 1)
+        &self.info
     }
 
     fn parameters(&self) -> &vistle::core::ParameterSet {
@@ -215,17 +363,36 @@ impl vistle::compute::Module for DataReaderModule {
         Ok(())
     }
 
-    async fn compute(&mut self, _ctx: &vistle::core::ComputeContext) -> Result<vistle::compute::OutputPorts, vistle::Error> {
+    async fn compute(&mut self, ctx: &vistle::core::ComputeContext) -> Result<vistle::compute::OutputPorts, vistle::Error> {
         // Simulate data reading
         println!("📖 Reading data from file...");
 
-        let mut outputs = std::collections::HashMap::new();
-        let data_object = Arc::new(vistle::core::VistleObject::with_data(
-            vistle::core::ObjectType::UnstructuredGrid,
-            vistle::core::ObjectPayload::Custom(vec![1, 2, 3, 4, 5]) // Placeholder data
-        ));
+        let num_blocks = ctx.parameters().get_int("num_blocks").unwrap_or(1).max(1) as usize;
+        let dims = match ctx.parameters().get("block_dims").map(|p| &p.value) {
+            Some(vistle::core::ParameterValue::VecInt(d)) if d.len() == 3 => [d[0] as usize, d[1] as usize, d[2] as usize],
+            _ => [num_blocks, 1, 1],
+        };
+        let strategy = vistle::mpi::AssignmentStrategy::parse(
+            ctx.parameters().get_string("block_assignment").unwrap_or("round_robin"),
+            dims,
+        )?;
+        let assignment = vistle::mpi::BlockAssignment::new(strategy, num_blocks, ctx.size)?;
+
+        let mut objects: Vec<Arc<dyn vistle::core::Object>> = Vec::new();
+        for block in assignment.blocks_for_rank(ctx.rank) {
+            use vistle::core::Object;
+
+            let mut object = vistle::core::PointsBuilder::new()
+                .coordinates([[block as f32, 0.0, 0.0], [block as f32 + 1.0, 1.0, 1.0]]) // Placeholder data
+                .build()?;
+            object.meta_mut().block = block as i32;
+            object.meta_mut().num_blocks = num_blocks as i32;
+            object.set_attribute(vistle::core::attribute::BLOCK_ASSIGNMENT.to_string(), assignment.to_attribute());
+            objects.push(Arc::new(object));
+        }
 
-        outputs.insert("data".to_string(), vec![data_object]);
+        let mut outputs = std::collections::HashMap::new();
+        outputs.insert("data".to_string(), objects);
         Ok(outputs)
     }
 
@@ -239,8 +406,10 @@ impl vistle::compute::Module for DataReaderModule {
 /// Example isosurface filter module
 struct IsoSurfaceModule {
     id: u32,
+    info: vistle::core::ModuleInfo,
     parameters: vistle::core::ParameterSet,
     ports: vistle::core::PortSet,
+    inputs: vistle::compute::InputPorts,
 }
 
 impl IsoSurfaceModule {
@@ -252,14 +421,14 @@ impl IsoSurfaceModule {
         ports.add(vistle::core::Port::new_input("data_in", "Input data"));
         ports.add(vistle::core::Port::new_output("surface_out", "Output surface"));
 
-        Self { id, parameters: params, ports }
+        Self { id, info: vistle::core::ModuleInfo::new(id, "IsoSurface", 0, 1), parameters: params, ports, inputs: std::collections::HashMap::new() }
     }
 }
 
 #[async_trait::async_trait]
 impl vistle::compute::Module for IsoSurfaceModule {
     fn info(&self) -> &vistle::core::ModuleInfo {
-        &vistle::core::ModuleInfo::new(self.id, "IsoSurface", 0, 1)
+        &self.info
     }
 
     fn parameters(&self) -> &vistle::core::ParameterSet {
@@ -270,24 +439,35 @@ impl vistle::compute::Module for IsoSurfaceModule {
         &self.ports
     }
 
-    async fn set_input(&mut self, _port_name: &str, _objects: vistle::compute::InputPort) -> Result<(), vistle::Error> {
+    async fn set_input(&mut self, port_name: &str, objects: vistle::compute::InputPort) -> Result<(), vistle::Error> {
+        self.inputs.insert(port_name.to_string(), objects);
         Ok(())
     }
 
-    async fn compute(&mut self, _ctx: &vistle::core::ComputeContext) -> Result<vistle::compute::OutputPorts, vistle::Error> {
+    async fn compute(&mut self, ctx: &vistle::core::ComputeContext) -> Result<vistle::compute::OutputPorts, vistle::Error> {
         // Simulate isosurface extraction
         println!("🔍 Extracting isosurface...");
 
+        let iso_value = ctx.parameters().get_float("iso_value").unwrap_or(0.5);
+        let inputs = self.inputs.get("data_in").cloned().unwrap_or_default();
+
+        // Extraction is CPU bound, keep it off the runtime the GUI shares
         let mut outputs = std::collections::HashMap::new();
-        let surface_object = Arc::new(vistle::core::VistleObject::with_data(
-            vistle::core::ObjectType::Triangles,
-            vistle::core::ObjectPayload::Triangles {
-                coordinates: ndarray::Array2::zeros((0, 3)), // Placeholder
-                triangles: ndarray::Array2::zeros((0, 3)),   // Placeholder
+        let surface_object = Arc::new(ctx.run_cpu(move || {
+            // AMR data: block grids arrive on the same port as their hierarchy,
+            // and only the finest cells are used so overlapping levels give one surface
+            let hierarchy = inputs.iter().find_map(|o| o.payload().and_then(|p| p.amr()));
+            if let Some(amr) = hierarchy {
+                return amr.isosurface(iso_value, |id| inputs.iter().find(|o| o.id() == id).cloned());
             }
-        ));
 
-        outputs.insert("surface_out".to_string(), vec![surface_object]);
+            vistle::core::TrianglesBuilder::new()
+                .coordinates([[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]) // Placeholder
+                .indices([[0, 1, 2]])
+                .build()
+        }).await??);
+
+        outputs.insert("surface_out".to_string(), vec![surface_object as Arc<dyn vistle::core::Object>]);
         Ok(outputs)
     }
 
@@ -300,6 +480,7 @@ impl vistle::compute::Module for IsoSurfaceModule {
 /// Example renderer module
 struct RendererModule {
     id: u32,
+    info: vistle::core::ModuleInfo,
     parameters: vistle::core::ParameterSet,
     ports: vistle::core::PortSet,
 }
@@ -312,14 +493,14 @@ impl RendererModule {
         let mut ports = vistle::core::PortSet::new();
         ports.add(vistle::core::Port::new_input("geometry_in", "Input geometry"));
 
-        Self { id, parameters: params, ports }
+        Self { id, info: vistle::core::ModuleInfo::new(id, "Renderer", 0, 1), parameters: params, ports }
     }
 }
 
 #[async_trait::async_trait]
 impl vistle::compute::Module for RendererModule {
     fn info(&self) -> &vistle::core::ModuleInfo {
-        &vistle::core::ModuleInfo::new(self.id, "Renderer", 0, 1)
+        &self.info
     }
 
     fn parameters(&self) -> &vistle::core::ParameterSet {
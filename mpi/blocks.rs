@@ -0,0 +1,179 @@
+//! Assignment of data blocks to ranks
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::sort::morton_code;
+use crate::Error;
+
+/// How blocks are distributed over ranks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AssignmentStrategy {
+    /// Block `b` goes to rank `b % size`
+    RoundRobin,
+    /// Each rank gets one consecutive range of blocks
+    Contiguous,
+    /// Consecutive ranges along a Morton curve over the block grid, keeping
+    /// spatially neighbouring blocks on the same rank
+    SpaceFillingCurve { dims: [usize; 3] },
+}
+
+impl AssignmentStrategy {
+    /// Parse a strategy parameter value (`round_robin`, `contiguous` or `sfc`)
+    ///
+    /// `dims` is the block grid used by the space-filling curve.
+    pub fn parse(name: &str, dims: [usize; 3]) -> Result<Self, Error> {
+        match name {
+            "round_robin" => Ok(AssignmentStrategy::RoundRobin),
+            "contiguous" => Ok(AssignmentStrategy::Contiguous),
+            "sfc" => Ok(AssignmentStrategy::SpaceFillingCurve { dims }),
+            other => Err(Error::Config(format!(
+                "Unknown block assignment {}; expected round_robin, contiguous or sfc",
+                other
+            ))),
+        }
+    }
+}
+
+/// Which rank loads which block
+///
+/// Readers attach this to their output as the block assignment attribute so
+/// downstream filters can rely on the same locality.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockAssignment {
+    pub strategy: AssignmentStrategy,
+    pub num_blocks: usize,
+    pub size: i32,
+    owners: Vec<i32>,
+}
+
+impl BlockAssignment {
+    pub fn new(strategy: AssignmentStrategy, num_blocks: usize, size: i32) -> Result<Self, Error> {
+        if size < 1 {
+            return Err(Error::Config(format!("Invalid communicator size {}", size)));
+        }
+        let ranks = size as usize;
+
+        let owners = match strategy {
+            AssignmentStrategy::RoundRobin => (0..num_blocks).map(|b| (b % ranks) as i32).collect(),
+            AssignmentStrategy::Contiguous => contiguous_owners(&(0..num_blocks).collect::<Vec<_>>(), ranks),
+            AssignmentStrategy::SpaceFillingCurve { dims } => {
+                let count: usize = dims.iter().product();
+                if count != num_blocks {
+                    return Err(Error::Config(format!(
+                        "Block grid {}x{}x{} has {} blocks, but {} blocks were given",
+                        dims[0], dims[1], dims[2], count, num_blocks
+                    )));
+                }
+                let mut order: Vec<usize> = (0..num_blocks).collect();
+                order.sort_by_key(|&b| morton_code(block_coordinates(b, dims)));
+                contiguous_owners(&order, ranks)
+            }
+        };
+
+        Ok(Self {
+            strategy,
+            num_blocks,
+            size,
+            owners,
+        })
+    }
+
+    /// Blocks loaded by a rank, in ascending block order
+    pub fn blocks_for_rank(&self, rank: i32) -> Vec<usize> {
+        self.owners.iter()
+            .enumerate()
+            .filter(|(_, &owner)| owner == rank)
+            .map(|(block, _)| block)
+            .collect()
+    }
+
+    /// Rank loading a block
+    pub fn rank_for_block(&self, block: usize) -> Option<i32> {
+        self.owners.get(block).copied()
+    }
+
+    /// Serialize for attaching as an object attribute
+    pub fn to_attribute(&self) -> String {
+        serde_json::to_string(self).expect("block assignment serializes to JSON")
+    }
+
+    pub fn from_attribute(value: &str) -> Result<Self, Error> {
+        serde_json::from_str(value)
+            .map_err(|e| Error::Config(format!("Invalid block assignment attribute: {}", e)))
+    }
+}
+
+/// Split an ordering of blocks into `ranks` consecutive chunks whose sizes differ by at most one
+fn contiguous_owners(order: &[usize], ranks: usize) -> Vec<i32> {
+    let mut owners = vec![0; order.len()];
+    let base = order.len() / ranks;
+    let extra = order.len() % ranks;
+
+    let mut position = 0;
+    for rank in 0..ranks {
+        let count = base + usize::from(rank < extra);
+        for &block in &order[position..position + count] {
+            owners[block] = rank as i32;
+        }
+        position += count;
+    }
+    owners
+}
+
+/// Grid coordinates of a block, x varying fastest
+fn block_coordinates(block: usize, dims: [usize; 3]) -> [usize; 3] {
+    [
+        block % dims[0],
+        (block / dims[0]) % dims[1],
+        block / (dims[0] * dims[1]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin_deals_blocks_in_turn() {
+        let assignment = BlockAssignment::new(AssignmentStrategy::RoundRobin, 7, 3).unwrap();
+        assert_eq!(assignment.blocks_for_rank(0), vec![0, 3, 6]);
+        assert_eq!(assignment.blocks_for_rank(1), vec![1, 4]);
+        assert_eq!(assignment.blocks_for_rank(2), vec![2, 5]);
+        assert_eq!(assignment.rank_for_block(4), Some(1));
+        assert_eq!(assignment.rank_for_block(7), None);
+    }
+
+    #[test]
+    fn contiguous_ranges_differ_by_at_most_one_block() {
+        let assignment = BlockAssignment::new(AssignmentStrategy::Contiguous, 7, 3).unwrap();
+        assert_eq!(assignment.blocks_for_rank(0), vec![0, 1, 2]);
+        assert_eq!(assignment.blocks_for_rank(1), vec![3, 4]);
+        assert_eq!(assignment.blocks_for_rank(2), vec![5, 6]);
+    }
+
+    #[test]
+    fn space_filling_curve_keeps_quadrants_together() {
+        let strategy = AssignmentStrategy::parse("sfc", [4, 4, 1]).unwrap();
+        let assignment = BlockAssignment::new(strategy, 16, 4).unwrap();
+        assert_eq!(assignment.blocks_for_rank(0), vec![0, 1, 4, 5]);
+        assert_eq!(assignment.blocks_for_rank(1), vec![2, 3, 6, 7]);
+        assert_eq!(assignment.blocks_for_rank(2), vec![8, 9, 12, 13]);
+        assert_eq!(assignment.blocks_for_rank(3), vec![10, 11, 14, 15]);
+    }
+
+    #[test]
+    fn space_filling_curve_needs_a_matching_block_grid() {
+        let strategy = AssignmentStrategy::SpaceFillingCurve { dims: [2, 2, 2] };
+        assert!(BlockAssignment::new(strategy, 7, 2).is_err());
+        assert!(BlockAssignment::new(AssignmentStrategy::RoundRobin, 4, 0).is_err());
+        assert!(AssignmentStrategy::parse("random", [1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn assignments_round_trip_through_the_attribute() {
+        let assignment = BlockAssignment::new(AssignmentStrategy::SpaceFillingCurve { dims: [2, 2, 1] }, 4, 2).unwrap();
+        let restored = BlockAssignment::from_attribute(&assignment.to_attribute()).unwrap();
+        assert_eq!(restored, assignment);
+        assert!(BlockAssignment::from_attribute("{").is_err());
+    }
+}
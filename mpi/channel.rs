@@ -0,0 +1,456 @@
+//! MPI tags per subsystem and typed point-to-point channels
+//!
+//! Every subsystem owns a disjoint range of tags, so a receive posted by
+//! one subsystem can never match a message meant for another, even between
+//! the same pair of ranks. A `TypedChannel` binds a peer rank and a tag to
+//! one message type.
+
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::Arc;
+
+#[cfg(feature = "mpi")]
+use mpi::traits::*;
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::Error;
+#[cfg(feature = "mpi")]
+use super::MpiUniverse;
+
+/// Tags in the range of each subsystem
+pub const TAGS_PER_SUBSYSTEM: i32 = 1024;
+
+/// Tags at the start of each range kept for fixed uses, see `Tag::reserved`
+pub const RESERVED_TAGS: i32 = 16;
+
+/// Control messages of the `MessageRouter`
+pub const ROUTER_TAG: Tag = Tag::reserved(Subsystem::Control, 0);
+/// Data sent from the root in `DistributedContext::broadcast`
+pub const BROADCAST_TAG: Tag = Tag::reserved(Subsystem::Control, 1);
+/// Contributions sent to the root in `DistributedContext::reduce`
+pub const REDUCE_TAG: Tag = Tag::reserved(Subsystem::Control, 2);
+/// Arrival and release messages of a barrier without MPI
+pub const BARRIER_TAG: Tag = Tag::reserved(Subsystem::Control, 3);
+/// Batches of log records shipped to rank 0, see `logging`
+pub const LOG_TAG: Tag = Tag::reserved(Subsystem::Control, 4);
+/// How a module of a distributed workflow ended, sent to the ranks depending on it
+pub const MODULE_OUTCOME_TAG: Tag = Tag::reserved(Subsystem::Control, 5);
+/// Fetch and cancel requests to the `ObjectTransferService`
+pub const TRANSFER_REQUEST_TAG: Tag = Tag::reserved(Subsystem::ObjectTransfer, 0);
+/// Headers and chunks answering a fetch
+pub const TRANSFER_REPLY_TAG: Tag = Tag::reserved(Subsystem::ObjectTransfer, 1);
+/// Default tag of `DistributedContext::send_receive`
+pub const USER_TAG: Tag = Tag::reserved(Subsystem::User, 0);
+
+/// Logical message streams, each with its own range of tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Subsystem {
+    Control,
+    ObjectTransfer,
+    GhostExchange,
+    Compositing,
+    User,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 5] = [
+        Subsystem::Control,
+        Subsystem::ObjectTransfer,
+        Subsystem::GhostExchange,
+        Subsystem::Compositing,
+        Subsystem::User,
+    ];
+
+    /// Tags owned by this subsystem
+    ///
+    /// All ranges together stay below 32767, the smallest tag upper bound
+    /// MPI implementations must support.
+    pub const fn tags(self) -> Range<i32> {
+        let start = self as i32 * TAGS_PER_SUBSYSTEM;
+        start..start + TAGS_PER_SUBSYSTEM
+    }
+}
+
+/// MPI message tag inside the range of one subsystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Tag(i32);
+
+impl Tag {
+    /// Fixed tag `offset` of a subsystem, the same on every rank without allocation
+    pub const fn reserved(subsystem: Subsystem, offset: i32) -> Tag {
+        assert!(offset >= 0 && offset < RESERVED_TAGS, "reserved tag offset out of range");
+        Tag(subsystem.tags().start + offset)
+    }
+
+    pub fn value(self) -> i32 {
+        self.0
+    }
+
+    pub fn subsystem(self) -> Option<Subsystem> {
+        Subsystem::ALL.into_iter().find(|s| s.tags().contains(&self.0))
+    }
+}
+
+impl std::fmt::Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.subsystem() {
+            Some(subsystem) => write!(f, "{} ({:?})", self.0, subsystem),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Hands out tags from the range of each subsystem
+///
+/// Allocation is deterministic: ranks that allocate in the same order get
+/// the same tags, which is how both ends of a channel agree on its tag.
+#[derive(Debug)]
+pub struct TagAllocator {
+    next: Mutex<HashMap<Subsystem, i32>>,
+}
+
+impl TagAllocator {
+    pub fn new() -> Self {
+        Self {
+            next: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Next unused tag of `subsystem`
+    pub fn allocate(&self, subsystem: Subsystem) -> Result<Tag, Error> {
+        let range = subsystem.tags();
+        let mut next = self.next.lock();
+        let tag = next.entry(subsystem).or_insert(range.start + RESERVED_TAGS);
+        if *tag >= range.end {
+            return Err(Error::Config(format!(
+                "All {} tags of {:?} are allocated",
+                TAGS_PER_SUBSYSTEM - RESERVED_TAGS, subsystem
+            )));
+        }
+        let allocated = Tag(*tag);
+        *tag += 1;
+        Ok(allocated)
+    }
+
+    /// Tags of `subsystem` allocated so far, reserved tags excluded
+    pub fn allocated(&self, subsystem: Subsystem) -> usize {
+        self.next.lock().get(&subsystem)
+            .map_or(0, |next| (next - subsystem.tags().start - RESERVED_TAGS) as usize)
+    }
+}
+
+impl Default for TagAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Prefix `payload` with its length as a little-endian u64
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(8 + payload.len());
+    framed.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// Payload of a frame built by `frame`
+pub fn unframe(framed: &[u8]) -> Result<&[u8], Error> {
+    if framed.len() < 8 {
+        return Err(Error::Module(format!("Frame of {} bytes has no length prefix", framed.len())));
+    }
+    let (header, payload) = framed.split_at(8);
+    let length = u64::from_le_bytes(header.try_into().expect("8 byte header")) as usize;
+    payload.get(..length).ok_or_else(|| Error::Module(format!(
+        "Frame announces {} bytes but carries {}",
+        length, payload.len()
+    )))
+}
+
+/// Point-to-point delivery of tagged frames between ranks
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    fn rank(&self) -> i32;
+
+    fn size(&self) -> i32;
+
+    async fn send(&self, dest: i32, tag: Tag, frame: Vec<u8>) -> Result<(), Error>;
+
+    /// Next frame with `tag` from `source`, or from any rank if None; returns the frame and its source
+    ///
+    /// Frames with other tags stay queued for their own receivers.
+    async fn receive(&self, source: Option<i32>, tag: Tag) -> Result<(Vec<u8>, i32), Error>;
+}
+
+/// `Transport` over the MPI world communicator
+#[cfg(feature = "mpi")]
+pub struct MpiTransport {
+    universe: Arc<MpiUniverse>,
+}
+
+#[cfg(feature = "mpi")]
+impl MpiTransport {
+    pub fn new(universe: Arc<MpiUniverse>) -> Self {
+        Self { universe }
+    }
+}
+
+#[cfg(feature = "mpi")]
+#[async_trait::async_trait]
+impl Transport for MpiTransport {
+    fn rank(&self) -> i32 {
+        self.universe.rank()
+    }
+
+    fn size(&self) -> i32 {
+        self.universe.size()
+    }
+
+    async fn send(&self, dest: i32, tag: Tag, frame: Vec<u8>) -> Result<(), Error> {
+        self.universe.world().process_at_rank(dest).send_with_tag(&frame[..], tag.value());
+        Ok(())
+    }
+
+    async fn receive(&self, source: Option<i32>, tag: Tag) -> Result<(Vec<u8>, i32), Error> {
+        let world = self.universe.world();
+        let (frame, status) = match source {
+            Some(source) => world.process_at_rank(source).receive_vec_with_tag::<u8>(tag.value()),
+            None => world.any_process().receive_vec_with_tag::<u8>(tag.value()),
+        };
+        Ok((frame, status.source_rank()))
+    }
+}
+
+/// Frame queued for a rank, with its source
+type Frame = (i32, Vec<u8>);
+
+/// Frames in flight between the ranks of a `LocalTransport` network
+#[derive(Default)]
+struct Mailboxes {
+    /// Queued frames per destination and tag, with their source
+    queues: Mutex<HashMap<(i32, Tag), VecDeque<Frame>>>,
+    arrived: Notify,
+}
+
+/// In-process `Transport` for single-process runs, standing in for ranks with tasks
+#[derive(Clone)]
+pub struct LocalTransport {
+    rank: i32,
+    size: i32,
+    mailboxes: Arc<Mailboxes>,
+}
+
+impl LocalTransport {
+    /// One rank talking only to itself
+    pub fn single() -> Self {
+        Self::network(1).remove(0)
+    }
+
+    /// `size` connected ranks, one transport each
+    pub fn network(size: i32) -> Vec<Self> {
+        let mailboxes = Arc::new(Mailboxes::default());
+        (0..size.max(1))
+            .map(|rank| Self { rank, size: size.max(1), mailboxes: mailboxes.clone() })
+            .collect()
+    }
+
+    fn take(&self, source: Option<i32>, tag: Tag) -> Option<(Vec<u8>, i32)> {
+        let mut queues = self.mailboxes.queues.lock();
+        let queue = queues.get_mut(&(self.rank, tag))?;
+        let position = queue.iter().position(|(from, _)| source.is_none_or(|s| s == *from))?;
+        queue.remove(position).map(|(from, frame)| (frame, from))
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for LocalTransport {
+    fn rank(&self) -> i32 {
+        self.rank
+    }
+
+    fn size(&self) -> i32 {
+        self.size
+    }
+
+    async fn send(&self, dest: i32, tag: Tag, frame: Vec<u8>) -> Result<(), Error> {
+        if !(0..self.size).contains(&dest) {
+            return Err(Error::Config(format!("Rank {} is outside the local network of {}", dest, self.size)));
+        }
+        self.mailboxes.queues.lock()
+            .entry((dest, tag))
+            .or_default()
+            .push_back((self.rank, frame));
+        self.mailboxes.arrived.notify_waiters();
+        Ok(())
+    }
+
+    async fn receive(&self, source: Option<i32>, tag: Tag) -> Result<(Vec<u8>, i32), Error> {
+        loop {
+            // Registered before looking, so a frame sent in between still wakes us
+            let arrived = self.mailboxes.arrived.notified();
+            if let Some(received) = self.take(source, tag) {
+                return Ok(received);
+            }
+            arrived.await;
+        }
+    }
+}
+
+/// Typed messages to and from one peer rank on one tag
+///
+/// Values are bincode-encoded and length-prefixed. Receiving matches only
+/// this channel's peer and tag, so channels sharing a peer never consume
+/// each other's messages.
+pub struct TypedChannel<T> {
+    transport: Arc<dyn Transport>,
+    peer: i32,
+    tag: Tag,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> TypedChannel<T> {
+    pub fn new(transport: Arc<dyn Transport>, peer: i32, tag: Tag) -> Self {
+        Self {
+            transport,
+            peer,
+            tag,
+            _message: PhantomData,
+        }
+    }
+
+    pub fn peer(&self) -> i32 {
+        self.peer
+    }
+
+    pub fn tag(&self) -> Tag {
+        self.tag
+    }
+
+    pub async fn send(&self, value: &T) -> Result<(), Error> {
+        let payload = bincode::serialize(value).map_err(Error::from)?;
+        self.transport.send(self.peer, self.tag, frame(&payload)).await
+    }
+
+    pub async fn receive(&self) -> Result<T, Error> {
+        let (framed, _) = self.transport.receive(Some(self.peer), self.tag).await?;
+        bincode::deserialize(unframe(&framed)?).map_err(Error::from)
+    }
+}
+
+impl<T> Clone for TypedChannel<T> {
+    fn clone(&self) -> Self {
+        Self {
+            transport: self.transport.clone(),
+            peer: self.peer,
+            tag: self.tag,
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for TypedChannel<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedChannel")
+            .field("peer", &self.peer)
+            .field("tag", &self.tag)
+            .field("message", &std::any::type_name::<T>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn subsystem_ranges_are_disjoint_and_within_the_mpi_minimum() {
+        for (i, a) in Subsystem::ALL.iter().enumerate() {
+            assert!(a.tags().end <= 32767);
+            for b in &Subsystem::ALL[i + 1..] {
+                assert!(a.tags().end <= b.tags().start || b.tags().end <= a.tags().start, "{:?} {:?}", a, b);
+            }
+        }
+        assert_eq!(ROUTER_TAG.subsystem(), Some(Subsystem::Control));
+        assert_eq!(TRANSFER_REPLY_TAG.subsystem(), Some(Subsystem::ObjectTransfer));
+        assert_eq!(USER_TAG.subsystem(), Some(Subsystem::User));
+        assert_eq!(Tag(-1).subsystem(), None);
+        assert_eq!(TRANSFER_REQUEST_TAG.to_string(), "1024 (ObjectTransfer)");
+    }
+
+    #[test]
+    fn allocation_is_deterministic_and_skips_reserved_tags() {
+        let (a, b) = (TagAllocator::new(), TagAllocator::new());
+        let first = a.allocate(Subsystem::GhostExchange).unwrap();
+        assert_eq!(first.value(), Subsystem::GhostExchange.tags().start + RESERVED_TAGS);
+        assert_eq!(b.allocate(Subsystem::GhostExchange).unwrap(), first);
+        assert_eq!(a.allocate(Subsystem::GhostExchange).unwrap().value(), first.value() + 1);
+        assert_eq!(a.allocated(Subsystem::GhostExchange), 2);
+        assert_eq!(a.allocated(Subsystem::Compositing), 0);
+    }
+
+    #[test]
+    fn a_subsystem_runs_out_of_tags() {
+        let allocator = TagAllocator::new();
+        for _ in 0..TAGS_PER_SUBSYSTEM - RESERVED_TAGS {
+            let tag = allocator.allocate(Subsystem::Compositing).unwrap();
+            assert_eq!(tag.subsystem(), Some(Subsystem::Compositing));
+        }
+        assert!(allocator.allocate(Subsystem::Compositing).is_err());
+        assert!(allocator.allocate(Subsystem::User).is_ok());
+    }
+
+    #[test]
+    fn frames_round_trip_and_truncation_is_detected() {
+        let framed = frame(b"payload");
+        assert_eq!(unframe(&framed).unwrap(), b"payload");
+        assert_eq!(unframe(&frame(&[])).unwrap(), b"");
+        assert!(unframe(&framed[..4]).is_err());
+        assert!(unframe(&framed[..framed.len() - 1]).is_err());
+    }
+
+    fn three_ranks() -> (Arc<dyn Transport>, Arc<dyn Transport>, Arc<dyn Transport>) {
+        let mut network = LocalTransport::network(3).into_iter().map(|t| Arc::new(t) as Arc<dyn Transport>);
+        (network.next().unwrap(), network.next().unwrap(), network.next().unwrap())
+    }
+
+    #[tokio::test]
+    async fn channels_on_different_tags_do_not_see_each_others_messages() {
+        let (zero, one, _) = three_ranks();
+        let allocator = TagAllocator::new();
+        let (names_tag, counts_tag) = (allocator.allocate(Subsystem::User).unwrap(), allocator.allocate(Subsystem::User).unwrap());
+
+        let names_out = TypedChannel::<String>::new(zero.clone(), 1, names_tag);
+        let counts_out = TypedChannel::<Vec<u32>>::new(zero, 1, counts_tag);
+        counts_out.send(&vec![1, 2, 3]).await.unwrap();
+        names_out.send(&"mesh".to_string()).await.unwrap();
+
+        let names_in = TypedChannel::<String>::new(one.clone(), 0, names_tag);
+        let counts_in = TypedChannel::<Vec<u32>>::new(one, 0, counts_tag);
+        assert_eq!(names_in.receive().await.unwrap(), "mesh");
+        assert_eq!(counts_in.receive().await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn channels_receive_only_from_their_peer() {
+        let (zero, one, two) = three_ranks();
+        TypedChannel::<u64>::new(two, 1, USER_TAG).send(&2).await.unwrap();
+
+        let from_zero = TypedChannel::<u64>::new(one.clone(), 0, USER_TAG);
+        assert!(tokio::time::timeout(Duration::from_millis(50), from_zero.receive()).await.is_err());
+
+        TypedChannel::<u64>::new(zero, 1, USER_TAG).send(&7).await.unwrap();
+        assert_eq!(from_zero.receive().await.unwrap(), 7);
+        // The frame from rank 2 is still queued for a receiver that accepts it
+        assert_eq!(one.receive(None, USER_TAG).await.unwrap().1, 2);
+    }
+
+    #[tokio::test]
+    async fn sending_outside_the_network_fails() {
+        let (zero, _, _) = three_ranks();
+        assert!(TypedChannel::<u8>::new(zero, 3, USER_TAG).send(&1).await.is_err());
+    }
+}
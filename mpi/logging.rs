@@ -0,0 +1,638 @@
+//! Log records of all ranks, merged in time order on rank 0
+//!
+//! Every rank installs a `RankLogLayer`, which buffers structured records
+//! instead of printing them. With a transport, `DistributedLog::spawn_aggregation`
+//! ships the buffer in batches to rank 0 on `LOG_TAG`, where the records of
+//! all ranks are merged by timestamp into one file and optionally echoed
+//! with a rank prefix. Without one, `spawn_local` writes a file per rank
+//! that `merge_logs` combines afterwards.
+//!
+//! Timestamps are a wall-clock reading taken once at startup advanced by
+//! the monotonic clock, so they never go backwards on a rank and compare
+//! across ranks as well as the node clocks agree.
+//!
+//! A full buffer drops its lowest-level records first, so DEBUG output
+//! goes before anything more important. Dropped records are counted per
+//! rank and reported when logging shuts down.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+use tracing::field::{Field, Visit};
+use tracing::span;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::Error;
+use super::{frame, unframe, Transport, LOG_TAG};
+
+/// How often buffered records are shipped or written
+pub const LOG_FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Records a rank buffers before dropping some
+pub const LOG_BUFFER_CAPACITY: usize = 8192;
+
+/// Severity of a record, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<&tracing::Level> for LogLevel {
+    fn from(level: &tracing::Level) -> Self {
+        match *level {
+            tracing::Level::TRACE => LogLevel::Trace,
+            tracing::Level::DEBUG => LogLevel::Debug,
+            tracing::Level::INFO => LogLevel::Info,
+            tracing::Level::WARN => LogLevel::Warn,
+            tracing::Level::ERROR => LogLevel::Error,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        };
+        f.pad(name)
+    }
+}
+
+/// One log event of one rank
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub rank: i32,
+    /// Module the event was logged for, from a `module_id` field on it or an enclosing span
+    pub module_id: Option<u32>,
+    /// Time since the Unix epoch, see the module docs
+    pub timestamp: Duration,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:06} {:<5} [rank {}]", self.timestamp.as_secs(), self.timestamp.subsec_micros(), self.level, self.rank)?;
+        if let Some(module_id) = self.module_id {
+            write!(f, " [module {}]", module_id)?;
+        }
+        write!(f, " {}: {}", self.target, self.message)
+    }
+}
+
+/// Sort records by timestamp, keeping the order of equal ones per rank
+fn sort_records(records: &mut [LogRecord]) {
+    records.sort_by_key(|r| (r.timestamp, r.rank));
+}
+
+/// Monotonic timestamps anchored at the wall clock
+#[derive(Debug, Clone, Copy)]
+struct LogClock {
+    wall: Duration,
+    started: Instant,
+}
+
+impl LogClock {
+    fn new() -> Self {
+        Self {
+            wall: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default(),
+            started: Instant::now(),
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.wall + self.started.elapsed()
+    }
+}
+
+/// Records waiting to be shipped, bounded by dropping the least important
+///
+/// Records are queued per level, so finding the one to drop when full
+/// takes a look at the front of at most every level's queue.
+#[derive(Debug)]
+struct LogBuffer {
+    /// Records of each level in arrival order, indexed by `LogLevel`
+    queues: [VecDeque<LogRecord>; LogLevel::Error as usize + 1],
+    len: usize,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl LogBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            queues: Default::default(),
+            len: 0,
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, record: LogRecord) {
+        if self.len >= self.capacity {
+            // The oldest record of the lowest level makes room, unless the new one is lower still
+            self.dropped += 1;
+            match self.queues.iter_mut().find(|queue| !queue.is_empty()) {
+                Some(lowest) if lowest[0].level <= record.level => {
+                    lowest.pop_front();
+                    self.len -= 1;
+                }
+                _ => return,
+            }
+        }
+        self.queues[record.level as usize].push_back(record);
+        self.len += 1;
+    }
+
+    /// All buffered records in time order
+    fn take(&mut self) -> Vec<LogRecord> {
+        let mut records: Vec<LogRecord> = self.queues.iter_mut().flat_map(|queue| queue.drain(..)).collect();
+        self.len = 0;
+        sort_records(&mut records);
+        records
+    }
+}
+
+/// What one batch from a rank carries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LogBatch {
+    rank: i32,
+    records: Vec<LogRecord>,
+    /// The rank's clock when the batch was taken; later records are newer
+    watermark: Duration,
+    /// Records dropped on the rank so far
+    dropped: u64,
+    /// No batches follow from this rank
+    last: bool,
+}
+
+/// Where rank 0 writes the merged log
+#[derive(Debug, Clone)]
+pub struct AggregationOptions {
+    pub path: PathBuf,
+    /// Also print every merged record to the console
+    pub echo: bool,
+    pub flush_interval: Duration,
+}
+
+impl AggregationOptions {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            echo: false,
+            flush_interval: LOG_FLUSH_INTERVAL,
+        }
+    }
+
+    pub fn with_echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+}
+
+/// Records dropped under backpressure, per rank, as known when logging shut down
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogSummary {
+    pub dropped: BTreeMap<i32, u64>,
+}
+
+impl LogSummary {
+    pub fn total_dropped(&self) -> u64 {
+        self.dropped.values().sum()
+    }
+}
+
+/// Buffered structured logging of one rank
+///
+/// Install `layer()` into the tracing subscriber, then start either
+/// `spawn_aggregation` or `spawn_local`; cancelling the token given to
+/// them flushes what is left and ends logging.
+pub struct DistributedLog {
+    rank: i32,
+    clock: LogClock,
+    buffer: Arc<Mutex<LogBuffer>>,
+}
+
+impl DistributedLog {
+    pub fn new(rank: i32) -> Self {
+        Self {
+            rank,
+            clock: LogClock::new(),
+            buffer: Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY))),
+        }
+    }
+
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        self.buffer.lock().capacity = capacity.max(1);
+        self
+    }
+
+    pub fn rank(&self) -> i32 {
+        self.rank
+    }
+
+    /// Layer feeding this rank's buffer from tracing events
+    pub fn layer(&self) -> RankLogLayer {
+        RankLogLayer {
+            rank: self.rank,
+            clock: self.clock,
+            buffer: self.buffer.clone(),
+        }
+    }
+
+    fn batch(&self, last: bool) -> LogBatch {
+        let mut buffer = self.buffer.lock();
+        LogBatch {
+            rank: self.rank,
+            records: buffer.take(),
+            watermark: self.clock.now(),
+            dropped: buffer.dropped,
+            last,
+        }
+    }
+
+    /// Ship records to rank 0 until `stop` is cancelled; rank 0 merges them into `options.path`
+    ///
+    /// All ranks of `transport` must run this. Rank 0 finishes once every
+    /// rank sent its last batch and returns the drop counts of all ranks;
+    /// other ranks return only their own.
+    pub fn spawn_aggregation(
+        self,
+        transport: Arc<dyn Transport>,
+        options: AggregationOptions,
+        stop: CancellationToken,
+    ) -> tokio::task::JoinHandle<Result<LogSummary, Error>> {
+        tokio::spawn(async move {
+            if transport.rank() == 0 {
+                self.collect(transport, options, stop).await
+            } else {
+                self.ship(transport, options.flush_interval, stop).await
+            }
+        })
+    }
+
+    async fn ship(&self, transport: Arc<dyn Transport>, interval: Duration, stop: CancellationToken) -> Result<LogSummary, Error> {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            let last = tokio::select! {
+                _ = ticker.tick() => false,
+                _ = stop.cancelled() => true,
+            };
+            let batch = self.batch(last);
+            let dropped = batch.dropped;
+            let payload = bincode::serialize(&batch).map_err(Error::from)?;
+            transport.send(0, LOG_TAG, frame(&payload)).await?;
+            if last {
+                return Ok(LogSummary { dropped: BTreeMap::from([(self.rank, dropped)]) });
+            }
+        }
+    }
+
+    async fn collect(&self, transport: Arc<dyn Transport>, options: AggregationOptions, stop: CancellationToken) -> Result<LogSummary, Error> {
+        let mut merger = LogMerger::new(transport.size());
+        let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(&options.path).await?);
+        let mut ticker = tokio::time::interval(options.flush_interval);
+        let mut stopping = false;
+
+        while !(stopping && merger.all_finished()) {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = stop.cancelled(), if !stopping => {
+                    stopping = true;
+                }
+                received = transport.receive(None, LOG_TAG), if !merger.all_finished() => {
+                    let (framed, source) = received?;
+                    match bincode::deserialize::<LogBatch>(unframe(&framed)?) {
+                        Ok(batch) => merger.add(batch),
+                        Err(e) => tracing::warn!("Discarding malformed log batch from rank {}: {}", source, e),
+                    }
+                }
+            }
+            merger.add(self.batch(stopping));
+            write_records(&mut file, &merger.ready(), options.echo).await?;
+        }
+
+        let summary = merger.summary();
+        let mut rest = merger.ready();
+        for (&rank, &dropped) in summary.dropped.iter().filter(|(_, &d)| d > 0) {
+            rest.push(LogRecord {
+                level: LogLevel::Warn,
+                rank,
+                module_id: None,
+                timestamp: self.clock.now(),
+                target: module_path!().to_string(),
+                message: format!("dropped {} log records under backpressure", dropped),
+            });
+        }
+        write_records(&mut file, &rest, options.echo).await?;
+        file.flush().await?;
+        Ok(summary)
+    }
+
+    /// Append this rank's records to `path` until `stop` is cancelled, for runs without a transport
+    ///
+    /// Returns the count of records this rank dropped; a final record in
+    /// the file reports it too.
+    pub fn spawn_local(self, path: impl Into<PathBuf>, stop: CancellationToken) -> tokio::task::JoinHandle<Result<LogSummary, Error>> {
+        let path = path.into();
+        tokio::spawn(async move {
+            let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(&path).await?);
+            let mut ticker = tokio::time::interval(LOG_FLUSH_INTERVAL);
+            loop {
+                let last = tokio::select! {
+                    _ = ticker.tick() => false,
+                    _ = stop.cancelled() => true,
+                };
+                let mut batch = self.batch(last);
+                if last && batch.dropped > 0 {
+                    batch.records.push(LogRecord {
+                        level: LogLevel::Warn,
+                        rank: self.rank,
+                        module_id: None,
+                        timestamp: self.clock.now(),
+                        target: module_path!().to_string(),
+                        message: format!("dropped {} log records under backpressure", batch.dropped),
+                    });
+                }
+                write_records(&mut file, &batch.records, false).await?;
+                file.flush().await?;
+                if last {
+                    return Ok(LogSummary { dropped: BTreeMap::from([(self.rank, batch.dropped)]) });
+                }
+            }
+        })
+    }
+}
+
+/// Per-rank log file name used with `DistributedLog::spawn_local`
+pub fn rank_log_path(dir: impl AsRef<Path>, rank: i32) -> PathBuf {
+    dir.as_ref().join(format!("rank-{}.log", rank))
+}
+
+/// Records are stored one JSON object per line, so merged files can be merged again
+async fn write_records(
+    file: &mut tokio::io::BufWriter<tokio::fs::File>,
+    records: &[LogRecord],
+    echo: bool,
+) -> Result<(), Error> {
+    for record in records {
+        let line = serde_json::to_string(record)
+            .map_err(|e| Error::Config(format!("Failed to serialize log record: {}", e)))?;
+        file.write_all(line.as_bytes()).await?;
+        file.write_all(b"\n").await?;
+        if echo {
+            println!("{}", record);
+        }
+    }
+    Ok(())
+}
+
+/// Merge log files written by `spawn_local` or `spawn_aggregation` into one timeline
+pub async fn merge_logs(paths: &[PathBuf]) -> Result<Vec<LogRecord>, Error> {
+    let mut merged = Vec::new();
+    for path in paths {
+        let text = crate::util::io::read_text(path).await?;
+        for (number, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let record: LogRecord = serde_json::from_str(line).map_err(|e| Error::Config(format!(
+                "Invalid log record at {}:{}: {}",
+                path.display(), number + 1, e
+            )))?;
+            merged.push(record);
+        }
+    }
+    sort_records(&mut merged);
+    Ok(merged)
+}
+
+/// Orders records of all ranks on rank 0
+///
+/// Records of one rank arrive in order, so everything up to the oldest
+/// watermark among the ranks can be written without a later batch
+/// turning up something older.
+struct LogMerger {
+    pending: Vec<LogRecord>,
+    watermarks: HashMap<i32, Duration>,
+    dropped: BTreeMap<i32, u64>,
+    finished: Vec<bool>,
+}
+
+impl LogMerger {
+    fn new(size: i32) -> Self {
+        Self {
+            pending: Vec::new(),
+            watermarks: HashMap::new(),
+            dropped: BTreeMap::new(),
+            finished: vec![false; size.max(1) as usize],
+        }
+    }
+
+    fn add(&mut self, batch: LogBatch) {
+        self.pending.extend(batch.records);
+        self.watermarks.insert(batch.rank, batch.watermark);
+        self.dropped.insert(batch.rank, batch.dropped);
+        if let Some(finished) = self.finished.get_mut(batch.rank as usize) {
+            *finished |= batch.last;
+        }
+    }
+
+    fn all_finished(&self) -> bool {
+        self.finished.iter().all(|&f| f)
+    }
+
+    /// Records no later batch can precede, in order; all of them once every rank finished
+    fn ready(&mut self) -> Vec<LogRecord> {
+        let horizon = if self.all_finished() {
+            Duration::MAX
+        } else {
+            (0..self.finished.len() as i32)
+                .filter(|&rank| !self.finished[rank as usize])
+                .map(|rank| self.watermarks.get(&rank).copied().unwrap_or(Duration::ZERO))
+                .min()
+                .unwrap_or(Duration::MAX)
+        };
+        let (mut ready, pending) = self.pending.drain(..).partition(|r: &LogRecord| r.timestamp <= horizon);
+        self.pending = pending;
+        sort_records(&mut ready);
+        ready
+    }
+
+    fn summary(&self) -> LogSummary {
+        LogSummary { dropped: self.dropped.clone() }
+    }
+}
+
+/// Module id found on a span, for events inside it
+struct SpanModule(u32);
+
+/// Message, other fields and module id of an event or span
+#[derive(Default)]
+struct RecordVisitor {
+    message: String,
+    fields: String,
+    module_id: Option<u32>,
+}
+
+impl Visit for RecordVisitor {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "module_id" => self.module_id = u32::try_from(value).ok(),
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        match field.name() {
+            "module_id" => self.module_id = u32::try_from(value).ok(),
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        use std::fmt::Write as _;
+        match field.name() {
+            "message" => self.message = format!("{:?}", value),
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+}
+
+/// Tracing layer turning events into records of one rank
+#[derive(Clone)]
+pub struct RankLogLayer {
+    rank: i32,
+    clock: LogClock,
+    buffer: Arc<Mutex<LogBuffer>>,
+}
+
+impl<S> Layer<S> for RankLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = RecordVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(module_id), Some(span)) = (visitor.module_id, ctx.span(id)) {
+            span.extensions_mut().insert(SpanModule(module_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let timestamp = self.clock.now();
+        let mut visitor = RecordVisitor::default();
+        event.record(&mut visitor);
+        let module_id = visitor.module_id.or_else(|| {
+            ctx.event_scope(event)?
+                .find_map(|span| span.extensions().get::<SpanModule>().map(|m| m.0))
+        });
+        let metadata = event.metadata();
+        self.buffer.lock().push(LogRecord {
+            level: metadata.level().into(),
+            rank: self.rank,
+            module_id,
+            timestamp,
+            target: metadata.target().to_string(),
+            message: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(level: LogLevel, timestamp: u64) -> LogRecord {
+        LogRecord {
+            level,
+            rank: 0,
+            module_id: None,
+            timestamp: Duration::from_millis(timestamp),
+            target: "test".to_string(),
+            message: format!("{} at {}", level, timestamp),
+        }
+    }
+
+    fn levels(records: &[LogRecord]) -> Vec<(LogLevel, u64)> {
+        records.iter().map(|r| (r.level, r.timestamp.as_millis() as u64)).collect()
+    }
+
+    #[test]
+    fn a_full_buffer_drops_debug_records_first() {
+        let mut buffer = LogBuffer::new(3);
+        buffer.push(record(LogLevel::Info, 1));
+        buffer.push(record(LogLevel::Debug, 2));
+        buffer.push(record(LogLevel::Debug, 3));
+        // Each of these pushes out the oldest DEBUG record
+        buffer.push(record(LogLevel::Warn, 4));
+        buffer.push(record(LogLevel::Error, 5));
+        assert_eq!(buffer.dropped, 2);
+        assert_eq!(
+            levels(&buffer.take()),
+            [(LogLevel::Info, 1), (LogLevel::Warn, 4), (LogLevel::Error, 5)]
+        );
+        assert!(buffer.take().is_empty());
+    }
+
+    #[test]
+    fn records_below_everything_buffered_are_dropped_themselves() {
+        let mut buffer = LogBuffer::new(2);
+        buffer.push(record(LogLevel::Warn, 1));
+        buffer.push(record(LogLevel::Info, 2));
+        buffer.push(record(LogLevel::Debug, 3));
+        // Equal levels make room for the newer record
+        buffer.push(record(LogLevel::Info, 4));
+        assert_eq!(buffer.dropped, 2);
+        assert_eq!(levels(&buffer.take()), [(LogLevel::Warn, 1), (LogLevel::Info, 4)]);
+    }
+
+    #[test]
+    fn the_merger_holds_records_back_until_every_rank_passed_them() {
+        let mut merger = LogMerger::new(2);
+        let batch = |rank: i32, records: Vec<LogRecord>, watermark: u64, last: bool| LogBatch {
+            rank,
+            records: records.into_iter().map(|r| LogRecord { rank, ..r }).collect(),
+            watermark: Duration::from_millis(watermark),
+            dropped: 0,
+            last,
+        };
+        merger.add(batch(0, vec![record(LogLevel::Info, 1), record(LogLevel::Info, 5)], 6, false));
+        merger.add(batch(1, vec![record(LogLevel::Info, 3)], 3, false));
+        assert_eq!(levels(&merger.ready()), [(LogLevel::Info, 1), (LogLevel::Info, 3)]);
+
+        merger.add(batch(1, vec![record(LogLevel::Info, 4)], 7, true));
+        merger.add(batch(0, Vec::new(), 8, true));
+        assert!(merger.all_finished());
+        assert_eq!(levels(&merger.ready()), [(LogLevel::Info, 4), (LogLevel::Info, 5)]);
+    }
+}
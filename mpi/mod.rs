@@ -1,32 +1,67 @@
 //! MPI-based distributed computing support
 
+pub mod transfer;
+pub mod blocks;
+pub mod channel;
+pub mod logging;
+
+pub use transfer::*;
+pub use blocks::*;
+pub use channel::*;
+pub use logging::*;
+
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::core::{MessageRouter, MpiMessageChannel, MpiMessageChannel as MpiChannel};
+#[cfg(feature = "mpi")]
+use mpi::traits::*;
+
+use crate::core::MessageRouter;
 use crate::Error;
 
 /// MPI universe and communicator management
+///
+/// MPI can be initialized only once per process, so all users share the
+/// universe returned by `shared`.
+#[cfg(feature = "mpi")]
 pub struct MpiUniverse {
-    universe: mpi::initialize::Universe,
-    world: mpi::topology::SystemCommunicator,
+    _universe: mpi::environment::Universe,
     rank: i32,
     size: i32,
+    /// Rank and size within the ranks sharing this node
+    local_rank: i32,
+    local_size: i32,
 }
 
+#[cfg(feature = "mpi")]
+static UNIVERSE: parking_lot::Mutex<Option<Arc<MpiUniverse>>> = parking_lot::Mutex::new(None);
+
+#[cfg(feature = "mpi")]
 impl MpiUniverse {
-    pub fn new() -> Result<Self, Error> {
-        let universe = mpi::initialize().map_err(Error::Mpi)?;
+    /// The universe of this process, initializing MPI on first use
+    pub fn shared() -> Result<Arc<Self>, Error> {
+        let mut shared = UNIVERSE.lock();
+        if let Some(universe) = shared.as_ref() {
+            return Ok(universe.clone());
+        }
+
+        let universe = mpi::initialize()
+            .ok_or_else(|| Error::Mpi("MPI was already initialized outside of Vistle".to_string()))?;
         let world = universe.world();
         let rank = world.rank();
         let size = world.size();
+        let node = world.split_shared(rank);
 
-        Ok(Self {
-            universe,
-            world,
+        let universe = Arc::new(Self {
+            _universe: universe,
             rank,
             size,
-        })
+            local_rank: node.rank(),
+            local_size: node.size(),
+        });
+        *shared = Some(universe.clone());
+        Ok(universe)
     }
 
     pub fn rank(&self) -> i32 {
@@ -37,64 +72,125 @@ impl MpiUniverse {
         self.size
     }
 
-    pub fn world(&self) -> &mpi::topology::SystemCommunicator {
-        &self.world
+    pub fn world(&self) -> mpi::topology::SimpleCommunicator {
+        mpi::topology::SimpleCommunicator::world()
+    }
+
+    /// Rank among the processes on this node
+    pub fn local_rank(&self) -> i32 {
+        self.local_rank
+    }
+
+    /// Number of processes on this node
+    pub fn local_size(&self) -> i32 {
+        self.local_size
+    }
+
+    /// GPU this rank should render on, out of `num_gpus` on the node
+    pub fn gpu_index(&self, num_gpus: usize) -> Option<usize> {
+        crate::render::gpu_for_local_rank(self.local_rank, num_gpus)
     }
 }
 
 /// Distributed computation context
+///
+/// Point-to-point traffic goes through the `Transport` with an explicit
+/// tag per stream; see `channel` for the tag ranges.
 pub struct DistributedContext {
-    universe: Arc<MpiUniverse>,
+    #[cfg(feature = "mpi")]
+    universe: Option<Arc<MpiUniverse>>,
+    transport: Arc<dyn Transport>,
+    tags: TagAllocator,
     message_router: Arc<MessageRouter>,
     local_data: RwLock<HashMap<String, Vec<u8>>>,
 }
 
 impl DistributedContext {
+    #[cfg(feature = "mpi")]
     pub fn new(message_router: Arc<MessageRouter>) -> Result<Self, Error> {
-        let universe = Arc::new(MpiUniverse::new()?);
+        let universe = MpiUniverse::shared()?;
+        let transport = Arc::new(MpiTransport::new(universe.clone()));
 
         Ok(Self {
-            universe,
+            universe: Some(universe),
+            transport,
+            tags: TagAllocator::new(),
             message_router,
             local_data: RwLock::new(HashMap::new()),
         })
     }
 
+    /// Context of a single rank; builds without the `mpi` feature have no other ranks
+    #[cfg(not(feature = "mpi"))]
+    pub fn new(message_router: Arc<MessageRouter>) -> Result<Self, Error> {
+        Ok(Self::with_transport(message_router, Arc::new(LocalTransport::single())))
+    }
+
+    /// Context over another transport, e.g. a `LocalTransport` for runs without MPI
+    pub fn with_transport(message_router: Arc<MessageRouter>, transport: Arc<dyn Transport>) -> Self {
+        Self {
+            #[cfg(feature = "mpi")]
+            universe: None,
+            transport,
+            tags: TagAllocator::new(),
+            message_router,
+            local_data: RwLock::new(HashMap::new()),
+        }
+    }
+
     pub fn rank(&self) -> i32 {
-        self.universe.rank()
+        self.transport.rank()
+    }
+
+    pub fn message_router(&self) -> &Arc<MessageRouter> {
+        &self.message_router
     }
 
     pub fn size(&self) -> i32 {
-        self.universe.size()
+        self.transport.size()
+    }
+
+    pub fn transport(&self) -> &Arc<dyn Transport> {
+        &self.transport
+    }
+
+    pub fn tags(&self) -> &TagAllocator {
+        &self.tags
+    }
+
+    /// Typed channel to `peer` on `tag`
+    pub fn channel<T: serde::Serialize + serde::de::DeserializeOwned>(&self, peer: i32, tag: Tag) -> TypedChannel<T> {
+        TypedChannel::new(self.transport.clone(), peer, tag)
+    }
+
+    /// Typed channel to `peer` on a newly allocated tag of `subsystem`
+    ///
+    /// Both ranks must open their channels in the same order to agree on the tag.
+    pub fn open_channel<T: serde::Serialize + serde::de::DeserializeOwned>(
+        &self,
+        peer: i32,
+        subsystem: Subsystem,
+    ) -> Result<TypedChannel<T>, Error> {
+        Ok(self.channel(peer, self.tags.allocate(subsystem)?))
     }
 
     /// Broadcast data from root to all ranks
-    pub async fn broadcast<T: serde::Serialize + serde::de::DeserializeOwned>(
+    pub async fn broadcast<T: serde::Serialize + serde::de::DeserializeOwned + Clone>(
         &self,
         data: &T,
         root: i32,
     ) -> Result<T, Error> {
-        let serialized = bincode::serialize(data)
-            .map_err(Error::Serialization)?;
-
-        let mut buffer = if self.rank() == root {
-            serialized
-        } else {
-            vec![0u8; 1024 * 1024] // Pre-allocate reasonable size
-        };
-
-        self.universe.world().process_at_rank(root).broadcast_into(&mut buffer);
-
-        if self.rank() == root {
-            Ok(data.clone())
-        } else {
-            bincode::deserialize(&buffer)
-                .map_err(Error::Serialization)
+        if self.rank() != root {
+            return self.channel(root, BROADCAST_TAG).receive().await;
+        }
+        for rank in (0..self.size()).filter(|&rank| rank != root) {
+            self.channel(rank, BROADCAST_TAG).send(data).await?;
         }
+        Ok(data.clone())
     }
 
     /// All-to-all data exchange
-    pub async fn all_to_all<T: serde::Serialize + serde::de::DeserializeOwned>(
+    pub async fn all_to_all<T: serde::Serialize + serde::de::DeserializeOwned + Clone>(
         &self,
         send_data: &[T],
     ) -> Result<Vec<T>, Error> {
@@ -114,24 +210,15 @@ impl DistributedContext {
         Ok(results)
     }
 
-    /// Send data to specific rank and receive from another
+    /// Send data to a rank and receive its reply, on `USER_TAG`
     pub async fn send_receive<T: serde::Serialize + serde::de::DeserializeOwned>(
         &self,
         send_data: T,
         dest: i32,
     ) -> Result<T, Error> {
-        let serialized = bincode::serialize(&send_data)
-            .map_err(Error::Serialization)?;
-
-        let world = self.universe.world();
-        world.process_at_rank(dest).send(&serialized);
-
-        // Receive response (simplified - assumes response comes back)
-        let mut buffer = vec![0u8; 1024 * 1024];
-        let (msg, _status) = world.receive_into(&mut buffer);
-
-        bincode::deserialize(&buffer)
-            .map_err(Error::Serialization)
+        let channel = self.channel(dest, USER_TAG);
+        channel.send(&send_data).await?;
+        channel.receive().await
     }
 
     /// Reduce operation across all ranks
@@ -145,38 +232,114 @@ impl DistributedContext {
             let mut result = local_value;
             for rank in 0..self.size() {
                 if rank != root {
-                    let remote_value: T = self.receive_from(rank).await?;
+                    let remote_value: T = self.channel(rank, REDUCE_TAG).receive().await?;
                     result = op(result, remote_value);
                 }
             }
             Ok(Some(result))
         } else {
-            self.send_to(local_value, root).await?;
+            self.channel(root, REDUCE_TAG).send(&local_value).await?;
             Ok(None)
         }
     }
 
-    /// Send data to specific rank
-    pub async fn send_to<T: serde::Serialize>(&self, data: T, dest: i32) -> Result<(), Error> {
-        let serialized = bincode::serialize(&data)
-            .map_err(Error::Serialization)?;
+    /// Send data to specific rank on `tag`
+    pub async fn send_to<T: serde::Serialize + serde::de::DeserializeOwned>(&self, data: T, dest: i32, tag: Tag) -> Result<(), Error> {
+        self.channel(dest, tag).send(&data).await
+    }
 
-        self.universe.world().process_at_rank(dest).send(&serialized);
-        Ok(())
+    /// Receive data from specific rank on `tag`
+    pub async fn receive_from<T: serde::Serialize + serde::de::DeserializeOwned>(&self, source: i32, tag: Tag) -> Result<T, Error> {
+        self.channel(source, tag).receive().await
     }
 
-    /// Receive data from specific rank
-    pub async fn receive_from<T: serde::de::DeserializeOwned>(&self, source: i32) -> Result<T, Error> {
-        let mut buffer = vec![0u8; 1024 * 1024];
-        let (_msg, _status) = self.universe.world().process_at_rank(source).receive_into(&mut buffer);
+    /// Receive data on `tag` from whichever rank sends first, returning the source rank
+    pub async fn receive_any<T: serde::de::DeserializeOwned>(&self, tag: Tag) -> Result<(T, i32), Error> {
+        let (framed, source) = self.transport.receive(None, tag).await?;
 
-        bincode::deserialize(&buffer)
-            .map_err(Error::Serialization)
+        let value = bincode::deserialize(unframe(&framed)?)
+            .map_err(Error::from)?;
+        Ok((value, source))
+    }
+
+    /// World-space bounds of the objects on all ranks, available on every rank
+    pub async fn global_bounds(
+        &self,
+        objects: &[Arc<dyn crate::core::Object>],
+    ) -> Result<Option<(nalgebra::Vector3<f32>, nalgebra::Vector3<f32>)>, Error> {
+        let local = objects.iter()
+            .filter_map(|o| o.bounds())
+            .reduce(|(amin, amax), (bmin, bmax)| (amin.inf(&bmin), amax.sup(&bmax)));
+
+        let merge = |a: Option<(nalgebra::Vector3<f32>, nalgebra::Vector3<f32>)>, b: Option<(nalgebra::Vector3<f32>, nalgebra::Vector3<f32>)>| {
+            match (a, b) {
+                (Some((amin, amax)), Some((bmin, bmax))) => Some((amin.inf(&bmin), amax.sup(&bmax))),
+                (a, None) => a,
+                (None, b) => b,
+            }
+        };
+
+        if self.size() == 1 {
+            return Ok(local);
+        }
+
+        let reduced = self.reduce(local, merge, 0).await?.flatten();
+        self.broadcast(&reduced, 0).await
+    }
+
+    /// Gather one value from every rank onto all ranks, ordered by rank
+    pub async fn all_gather<T>(&self, local_value: T) -> Result<Vec<T>, Error>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + Clone,
+    {
+        if self.size() == 1 {
+            return Ok(vec![local_value]);
+        }
+
+        let gathered = self.reduce(vec![(self.rank(), local_value)], |mut a, b| { a.extend(b); a }, 0).await?;
+        let mut gathered = self.broadcast(&gathered.unwrap_or_default(), 0).await?;
+        gathered.sort_by_key(|(rank, _)| *rank);
+        Ok(gathered.into_iter().map(|(_, value)| value).collect())
+    }
+
+    /// Merge per-module execution statistics across all ranks
+    pub async fn merge_stats(
+        &self,
+        stats: Vec<crate::core::ExecutionStats>,
+    ) -> Result<Vec<crate::core::ExecutionStats>, Error> {
+        let mut merged: Vec<crate::core::ExecutionStats> = Vec::new();
+        for rank_stats in self.all_gather(stats).await? {
+            for s in rank_stats {
+                match merged.iter_mut().find(|m| m.module_id == s.module_id) {
+                    Some(existing) => existing.merge(&s),
+                    None => merged.push(s),
+                }
+            }
+        }
+        Ok(merged)
     }
 
     /// Barrier synchronization
     pub async fn barrier(&self) -> Result<(), Error> {
-        self.universe.world().barrier();
+        #[cfg(feature = "mpi")]
+        if let Some(universe) = &self.universe {
+            universe.world().barrier();
+            return Ok(());
+        }
+
+        // Without MPI every rank checks in with rank 0, which then releases them
+        if self.rank() == 0 {
+            for rank in 1..self.size() {
+                self.channel::<()>(rank, BARRIER_TAG).receive().await?;
+            }
+            for rank in 1..self.size() {
+                self.channel(rank, BARRIER_TAG).send(&()).await?;
+            }
+        } else {
+            let channel = self.channel::<()>(0, BARRIER_TAG);
+            channel.send(&()).await?;
+            channel.receive().await?;
+        }
         Ok(())
     }
 
@@ -201,7 +364,7 @@ impl DataPartitioner {
         let remainder = total_size % size as usize;
 
         let start = rank as usize * base_size + (rank as usize).min(remainder);
-        let extra = if rank as usize < remainder { 1 } else { 0 };
+        let extra = if (rank as usize) < remainder { 1 } else { 0 };
         let local_size = base_size + extra;
 
         (start, local_size)
@@ -242,7 +405,7 @@ impl LoadBalancer {
 
         let mut offset = 0;
         for i in 0..num_workers {
-            let extra = if i as usize < remainder { 1 } else { 0 };
+            let extra = if (i as usize) < remainder { 1 } else { 0 };
             let work_size = base_work + extra;
             distribution.push((offset, work_size));
             offset += work_size;
@@ -272,9 +435,43 @@ impl LoadBalancer {
 
         // Adjust the last partition to ensure total work is preserved
         if let Some((_, ref mut last_size)) = new_distribution.last_mut() {
-            *last_size = total_work - offset + *last_size;
+            *last_size += total_work - offset;
         }
 
         new_distribution
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::testing::cluster::MiniCluster;
+    use crate::core::{Object, ObjectMeta, ObjectPayload, ObjectType, VistleObject};
+    use nalgebra::{Matrix4, Vector3};
+
+    fn point(x: f32, transform: Matrix4<f32>) -> Arc<dyn Object> {
+        Arc::new(VistleObject::with_data(ObjectType::Points, ObjectPayload::Points {
+            coordinates: ndarray::array![[x, 0.0, 0.0]],
+        })
+        .with_meta(ObjectMeta { transform, ..ObjectMeta::default() }))
+    }
+
+    #[tokio::test]
+    async fn global_bounds_merge_world_space_bounds_of_all_ranks() {
+        let cluster = MiniCluster::new(3).await;
+        let bounds = cluster.run(|rank| async move {
+            // Rank 1 has no geometry at all
+            let objects = match rank.rank {
+                0 => vec![point(1.0, Matrix4::identity())],
+                2 => vec![point(1.0, Matrix4::new_translation(&Vector3::new(20.0, 0.0, 0.0)))],
+                _ => Vec::new(),
+            };
+            rank.context.global_bounds(&objects).await
+        })
+        .await
+        .unwrap();
+
+        let expected = Some((Vector3::new(1.0, 0.0, 0.0), Vector3::new(21.0, 0.0, 0.0)));
+        assert!(bounds.iter().all(|b| *b == expected), "{:?}", bounds);
+    }
+}
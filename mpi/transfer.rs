@@ -0,0 +1,376 @@
+//! Object transfer between ranks
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::core::{Object, ObjectData, ObjectId, ObjectRegistry, VistleObject};
+use crate::Error;
+use super::{DistributedContext, TRANSFER_REPLY_TAG, TRANSFER_REQUEST_TAG};
+
+/// Default chunk size for pipelined transfers (16 MiB)
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Wire protocol of the transfer service
+///
+/// `Fetch` and `Cancel` travel on `TRANSFER_REQUEST_TAG`, everything else on
+/// `TRANSFER_REPLY_TAG`, so the service loop never takes a reply meant for a
+/// fetch running on the same rank. Replies of all fetches share one tag and
+/// are routed to their fetch by `request_id`; replies to a fetch that was
+/// cancelled or has finished are dropped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransferMessage {
+    Fetch { request_id: u64, object_id: ObjectId },
+    Header { request_id: u64, total_size: usize, num_chunks: usize },
+    Chunk { request_id: u64, index: usize, data: Vec<u8> },
+    NotFound { request_id: u64, object_id: ObjectId },
+    Cancel { request_id: u64 },
+}
+
+/// Aggregated transfer statistics
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransferStats {
+    pub transfers: usize,
+    pub bytes_received: usize,
+    pub bytes_sent: usize,
+    pub cancelled: usize,
+    pub receive_time: Duration,
+}
+
+impl TransferStats {
+    /// Average receive bandwidth in bytes per second
+    pub fn bandwidth(&self) -> f64 {
+        let secs = self.receive_time.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes_received as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Background service moving objects between ranks on request
+///
+/// `DistributedWorkflowExecutor::with_transfer` runs distributed workflows
+/// through it: outputs other ranks consume are served from the registry,
+/// and remote inputs are prefetched as soon as their producer reports.
+pub struct ObjectTransferService {
+    context: Arc<DistributedContext>,
+    registry: Arc<ObjectRegistry>,
+    chunk_size: usize,
+    next_request: AtomicU64,
+    stats: Mutex<TransferStats>,
+    /// Replies of fetches in flight by request id
+    pending: Mutex<HashMap<u64, mpsc::UnboundedSender<TransferMessage>>>,
+    /// Fetches being served, by requesting rank and request id
+    serving: Mutex<HashMap<(i32, u64), CancellationToken>>,
+    shutdown: CancellationToken,
+}
+
+impl ObjectTransferService {
+    pub fn new(context: Arc<DistributedContext>, registry: Arc<ObjectRegistry>) -> Self {
+        Self {
+            context,
+            registry,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            next_request: AtomicU64::new(1),
+            stats: Mutex::new(TransferStats::default()),
+            pending: Mutex::new(HashMap::new()),
+            serving: Mutex::new(HashMap::new()),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Payloads larger than this are sent in several chunks
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn stats(&self) -> TransferStats {
+        self.stats.lock().clone()
+    }
+
+    /// Start serving fetch requests from other ranks and routing replies to local fetches
+    ///
+    /// `fetch` waits for its replies through this task, so it must be running.
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = service.shutdown.cancelled() => break,
+                    received = service.context.receive_any::<TransferMessage>(TRANSFER_REQUEST_TAG) => {
+                        match received {
+                            Ok((TransferMessage::Fetch { request_id, object_id }, source)) => {
+                                let token = service.shutdown.child_token();
+                                service.serving.lock().insert((source, request_id), token.clone());
+                                let server = service.clone();
+                                tokio::spawn(async move {
+                                    if let Err(e) = server.serve(request_id, object_id, source, &token).await {
+                                        tracing::warn!("Failed to serve object {} to rank {}: {}", object_id, source, e);
+                                    }
+                                    server.serving.lock().remove(&(source, request_id));
+                                });
+                            }
+                            Ok((TransferMessage::Cancel { request_id }, source)) => {
+                                if let Some(token) = service.serving.lock().remove(&(source, request_id)) {
+                                    token.cancel();
+                                }
+                            }
+                            Ok((message, source)) => {
+                                tracing::debug!("Ignoring unexpected transfer message from rank {}: {:?}", source, message);
+                            }
+                            Err(e) => tracing::warn!("Transfer service receive failed: {}", e),
+                        }
+                    }
+                    received = service.context.receive_any::<TransferMessage>(TRANSFER_REPLY_TAG) => {
+                        match received {
+                            Ok((reply, source)) => service.dispatch(reply, source),
+                            Err(e) => tracing::warn!("Transfer service receive failed: {}", e),
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Hand a reply to the fetch waiting for it
+    fn dispatch(&self, reply: TransferMessage, source: i32) {
+        let request_id = match &reply {
+            TransferMessage::Header { request_id, .. }
+            | TransferMessage::Chunk { request_id, .. }
+            | TransferMessage::NotFound { request_id, .. } => *request_id,
+            other => {
+                tracing::debug!("Ignoring unexpected transfer reply from rank {}: {:?}", source, other);
+                return;
+            }
+        };
+        match self.pending.lock().get(&request_id) {
+            Some(waiting) => {
+                let _ = waiting.send(reply);
+            }
+            None => tracing::trace!("Dropping reply from rank {} to finished request {}", source, request_id),
+        }
+    }
+
+    /// Stop the background service
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    async fn serve(&self, request_id: u64, object_id: ObjectId, dest: i32, token: &CancellationToken) -> Result<(), Error> {
+        let replies = self.context.channel::<TransferMessage>(dest, TRANSFER_REPLY_TAG);
+        let object = match self.registry.get(object_id) {
+            Some(object) => object,
+            None => return replies.send(&TransferMessage::NotFound { request_id, object_id }).await,
+        };
+
+        let data = object.as_data()
+            .ok_or_else(|| Error::Module(format!("Object {} cannot be serialized", object_id)))?;
+        let bytes = bincode::serialize(data).map_err(Error::from)?;
+        let num_chunks = bytes.len().div_ceil(self.chunk_size);
+
+        replies
+            .send(&TransferMessage::Header { request_id, total_size: bytes.len(), num_chunks })
+            .await?;
+
+        for (index, chunk) in bytes.chunks(self.chunk_size).enumerate() {
+            if token.is_cancelled() {
+                tracing::debug!("Rank {} cancelled transfer of object {} after {} chunks", dest, object_id, index);
+                return Ok(());
+            }
+            replies
+                .send(&TransferMessage::Chunk { request_id, index, data: chunk.to_vec() })
+                .await?;
+        }
+
+        self.stats.lock().bytes_sent += bytes.len();
+        Ok(())
+    }
+
+    /// Fetch an object from another rank and register it locally
+    ///
+    /// Cancelling `token` aborts the transfer and notifies the sender, which
+    /// stops sending chunks.
+    pub async fn fetch(&self, object_id: ObjectId, from_rank: i32, token: &CancellationToken) -> Result<Arc<dyn Object>, Error> {
+        if let Some(object) = self.registry.get(object_id) {
+            return Ok(object);
+        }
+
+        let start = Instant::now();
+        let request_id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        let (sender, mut replies) = mpsc::unbounded_channel();
+        self.pending.lock().insert(request_id, sender);
+        let received = self.receive(request_id, object_id, from_rank, &mut replies, token).await;
+        self.pending.lock().remove(&request_id);
+        let buffer = received?;
+
+        let data: ObjectData = bincode::deserialize(&buffer).map_err(Error::from)?;
+        let object: Arc<dyn Object> = Arc::new(VistleObject::from_data(data));
+        self.registry.store(object.clone());
+
+        let mut stats = self.stats.lock();
+        stats.transfers += 1;
+        stats.bytes_received += buffer.len();
+        stats.receive_time += start.elapsed();
+        tracing::debug!(
+            "Fetched object {} ({} bytes) from rank {} at {:.1} MB/s",
+            object_id,
+            buffer.len(),
+            from_rank,
+            buffer.len() as f64 / start.elapsed().as_secs_f64().max(1e-9) / 1e6
+        );
+
+        Ok(object)
+    }
+
+    /// Request an object and collect its serialized bytes from the replies routed to `request_id`
+    async fn receive(
+        &self,
+        request_id: u64,
+        object_id: ObjectId,
+        from_rank: i32,
+        replies: &mut mpsc::UnboundedReceiver<TransferMessage>,
+        token: &CancellationToken,
+    ) -> Result<Vec<u8>, Error> {
+        let requests = self.context.channel::<TransferMessage>(from_rank, TRANSFER_REQUEST_TAG);
+        requests.send(&TransferMessage::Fetch { request_id, object_id }).await?;
+
+        let mut buffer = Vec::new();
+        let mut num_chunks = None;
+        let mut expected = 0;
+        while num_chunks != Some(expected) {
+            let reply = tokio::select! {
+                biased;
+                _ = token.cancelled() => {
+                    requests.send(&TransferMessage::Cancel { request_id }).await?;
+                    self.stats.lock().cancelled += 1;
+                    return Err(Error::Cancelled(format!("transfer of object {}", object_id)));
+                }
+                reply = replies.recv() => reply
+                    .ok_or_else(|| Error::Module("Object transfer service shut down".to_string()))?,
+            };
+
+            match (reply, num_chunks) {
+                (TransferMessage::Header { total_size, num_chunks: n, .. }, None) => {
+                    buffer.reserve_exact(total_size);
+                    num_chunks = Some(n);
+                }
+                (TransferMessage::Chunk { index, data, .. }, Some(_)) if index == expected => {
+                    buffer.extend_from_slice(&data);
+                    expected += 1;
+                }
+                (TransferMessage::NotFound { .. }, _) => {
+                    return Err(Error::Module(format!("Object {} not found on rank {}", object_id, from_rank)));
+                }
+                (other, _) => return Err(Error::Module(format!("Unexpected transfer message {:?}", other))),
+            }
+        }
+        Ok(buffer)
+    }
+
+    /// Start fetching objects in the background so later consumers find them locally
+    pub fn prefetch(self: &Arc<Self>, ids: Vec<ObjectId>, from_rank: i32, token: CancellationToken) -> JoinHandle<Vec<Result<ObjectId, Error>>> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut results = Vec::with_capacity(ids.len());
+            for id in ids {
+                results.push(service.fetch(id, from_rank, &token).await.map(|o| o.id()));
+            }
+            results
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{MessageRouter, ObjectPayload, ObjectType};
+    use crate::mpi::{LocalTransport, Transport};
+
+    /// Two ranks with running services sending 64 byte chunks, so every object takes several
+    fn pair() -> Vec<Arc<ObjectTransferService>> {
+        LocalTransport::network(2)
+            .into_iter()
+            .map(|transport| {
+                let transport: Arc<dyn Transport> = Arc::new(transport);
+                let context = Arc::new(DistributedContext::with_transport(Arc::new(MessageRouter::new()), transport));
+                let service = Arc::new(
+                    ObjectTransferService::new(context, Arc::new(ObjectRegistry::new())).with_chunk_size(64),
+                );
+                service.spawn();
+                service
+            })
+            .collect()
+    }
+
+    fn points(n: usize, x: f32) -> Arc<dyn Object> {
+        Arc::new(VistleObject::with_data(ObjectType::Points, ObjectPayload::Points {
+            coordinates: ndarray::Array2::from_elem((n, 3), x),
+        }))
+    }
+
+    fn first_x(object: &Arc<dyn Object>) -> f32 {
+        match object.payload() {
+            Some(ObjectPayload::Points { coordinates }) => coordinates[[0, 0]],
+            other => panic!("expected points, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_after_cancelled_fetch_gets_its_own_object() {
+        let services = pair();
+        let big = services[0].registry.store(points(1000, 1.0));
+        let small = services[0].registry.store(points(10, 2.0));
+
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        let result = services[1].fetch(big, 0, &cancelled).await;
+        assert!(matches!(result, Err(Error::Cancelled(_))), "{:?}", result.map(|o| o.id()));
+        assert!(services[1].registry.get(big).is_none());
+
+        let token = CancellationToken::new();
+        let fetched = services[1].fetch(small, 0, &token).await.unwrap();
+        assert_eq!(fetched.id(), small);
+        assert_eq!(first_x(&fetched), 2.0);
+
+        let fetched = services[1].fetch(big, 0, &token).await.unwrap();
+        assert_eq!(fetched.id(), big);
+        assert_eq!(first_x(&fetched), 1.0);
+        assert_eq!(services[1].stats().cancelled, 1);
+        assert_eq!(services[1].stats().transfers, 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetches_from_one_rank_do_not_interleave() {
+        let services = pair();
+        let ids: Vec<ObjectId> = (0..4)
+            .map(|i| services[0].registry.store(points(100 * (i + 1), i as f32)))
+            .collect();
+
+        let token = CancellationToken::new();
+        let fetched = futures::future::join_all(ids.iter().map(|&id| services[1].fetch(id, 0, &token))).await;
+        for (i, (id, object)) in ids.iter().zip(fetched).enumerate() {
+            let object = object.unwrap();
+            assert_eq!(object.id(), *id);
+            assert_eq!(first_x(&object), i as f32);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_object_is_reported_not_found() {
+        let services = pair();
+        let token = CancellationToken::new();
+        let missing = points(1, 0.0).id();
+        let result = services[1].fetch(missing, 0, &token).await;
+        assert!(matches!(result, Err(Error::Module(_))), "{:?}", result.map(|o| o.id()));
+    }
+}
@@ -0,0 +1,485 @@
+//! GPU buffer reuse across frames
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use super::{lut_texels, ClipPlanes, ColorMap, ColorMapLibrary, Geometry, RenderSettings, Scene, SceneHandle, SceneObject, CLIP_UNIFORM_FLOATS, LUT_SIZE};
+
+/// Size of the per-object uniform block: a 4x4 transform, an RGBA color, the
+/// scalar coloring parameters of `ScalarBinding::uniform` and the clipping
+/// planes of `ClipPlanes::uniform`
+const UNIFORM_SIZE: usize = (16 + 4 + 8 + CLIP_UNIFORM_FLOATS) * std::mem::size_of::<f32>();
+
+/// Size of one colormap lookup texture
+const LUT_BYTES: u64 = LUT_SIZE as u64 * 4;
+
+/// Cache effectiveness counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Bytes of GPU buffers currently held by the cache
+    pub resident_bytes: u64,
+}
+
+/// Buffers of one scene object
+pub struct CachedBuffers {
+    pub revision: u64,
+    pub vertex: wgpu::Buffer,
+    pub index: Option<wgpu::Buffer>,
+    /// One float per vertex for objects with bound scalars
+    pub scalar: Option<wgpu::Buffer>,
+    /// Texture coordinates and image of textured objects
+    pub texcoord: Option<wgpu::Buffer>,
+    pub texture: Option<CachedTexture>,
+    pub uniform: wgpu::Buffer,
+    pub element_count: u32,
+    bytes: u64,
+}
+
+/// Image texture of one scene object, see `render::texture`
+pub struct CachedTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    /// Nearest or linear, as the object's texture asks
+    pub sampler: wgpu::Sampler,
+}
+
+/// Lookup texture of one colormap, see `render::lut`
+pub struct CachedColormap {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    /// Texels last written, to notice edits of the colormap
+    texels: Vec<u8>,
+    last_used_frame: u64,
+}
+
+/// GPU resources keyed by scene object handle
+///
+/// Geometry buffers are rebuilt only when an object's revision changes;
+/// otherwise just the uniform block is rewritten in place. Colormaps of
+/// bound scalars are kept as lookup textures by name, shared between
+/// objects and rewritten when the colormap is edited. Buffers of an object
+/// are evicted with the first frame it is no longer part of the scene;
+/// colormaps unused for more than `max_unused_frames` frames, so switching
+/// back and forth between colormaps does not upload them every time.
+/// Objects added to a scene between frames, e.g. by `Scene::apply_update`,
+/// only get buffers of their own; those of the other objects stay as they are.
+pub struct GpuResourceCache {
+    entries: HashMap<SceneHandle, CachedBuffers>,
+    colormaps: HashMap<String, CachedColormap>,
+    /// Linear filtering between lookup texels, created with the first colormap
+    lut_sampler: Option<wgpu::Sampler>,
+    frame: u64,
+    max_unused_frames: u64,
+    stats: CacheStats,
+}
+
+impl GpuResourceCache {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            colormaps: HashMap::new(),
+            lut_sampler: None,
+            frame: 0,
+            max_unused_frames: 30,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn with_max_unused_frames(mut self, frames: u64) -> Self {
+        self.max_unused_frames = frames;
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn get(&self, handle: SceneHandle) -> Option<&CachedBuffers> {
+        self.entries.get(&handle)
+    }
+
+    /// Lookup texture of a colormap used by bound scalars in the last prepared frame
+    pub fn colormap(&self, name: &str) -> Option<&CachedColormap> {
+        self.colormaps.get(name)
+    }
+
+    pub fn lut_sampler(&self) -> Option<&wgpu::Sampler> {
+        self.lut_sampler.as_ref()
+    }
+
+    /// Make buffers for every object of a scene current, then evict those of objects that left it
+    ///
+    /// Uniforms are rewritten every frame, so changed clipping planes in
+    /// `settings` take effect without re-uploading geometry.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene, settings: &RenderSettings) {
+        self.frame += 1;
+
+        let library = ColorMapLibrary::global();
+        for object in scene.objects() {
+            let colormap = object.scalars().and_then(|binding| {
+                let map = library.get(&binding.colormap);
+                if map.is_none() {
+                    tracing::debug!("Object '{}' is colored by unknown colormap {}", object.name, binding.colormap);
+                }
+                map
+            });
+            if let (Some(binding), Some(map)) = (object.scalars(), &colormap) {
+                self.prepare_colormap(device, queue, &binding.colormap, map);
+            }
+            let uniforms = uniform_bytes(object, colormap.as_deref(), object.clipping(settings));
+            match self.entries.get_mut(&object.handle()) {
+                Some(entry) if entry.revision == object.revision() => {
+                    queue.write_buffer(&entry.uniform, 0, &uniforms);
+                    self.stats.hits += 1;
+                }
+                _ => {
+                    let Some(entry) = create_buffers(device, queue, object, &uniforms) else {
+                        continue;
+                    };
+                    self.stats.misses += 1;
+                    self.stats.resident_bytes += entry.bytes;
+                    if let Some(old) = self.entries.insert(object.handle(), entry) {
+                        self.stats.resident_bytes -= old.bytes;
+                    }
+                }
+            }
+        }
+
+        let present: HashSet<SceneHandle> = scene.objects().iter().map(|o| o.handle()).collect();
+        self.evict(|handle, _| !present.contains(handle));
+        let frame = self.frame;
+        let max_unused = self.max_unused_frames;
+        self.evict_colormaps(|colormap| frame - colormap.last_used_frame > max_unused);
+    }
+
+    /// Upload a colormap's lookup texture, or rewrite it if the colormap changed
+    fn prepare_colormap(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, name: &str, map: &ColorMap) {
+        let texels = lut_texels(map);
+        let size = wgpu::Extent3d { width: LUT_SIZE, height: 1, depth_or_array_layers: 1 };
+        if !self.colormaps.contains_key(name) {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("colormap lookup"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D1,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.lut_sampler.get_or_insert_with(|| device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("colormap lookup"),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }));
+            self.stats.resident_bytes += LUT_BYTES;
+            self.colormaps.insert(name.to_string(), CachedColormap {
+                texture,
+                view,
+                texels: Vec::new(),
+                last_used_frame: self.frame,
+            });
+        }
+        let Some(entry) = self.colormaps.get_mut(name) else {
+            return;
+        };
+        entry.last_used_frame = self.frame;
+        if entry.texels == texels {
+            self.stats.hits += 1;
+            return;
+        }
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &entry.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &texels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(LUT_SIZE * 4),
+                rows_per_image: None,
+            },
+            size,
+        );
+        entry.texels = texels;
+        self.stats.misses += 1;
+    }
+
+    /// Drop every cached buffer, e.g. under memory pressure
+    pub fn flush(&mut self) {
+        self.evict(|_, _| true);
+        self.evict_colormaps(|_| true);
+    }
+
+    /// Forget every entry without destroying its buffers, after the device was lost
+    ///
+    /// The next `prepare` uploads every object again from the scene.
+    pub fn invalidate(&mut self) {
+        self.stats.evictions += (self.entries.len() + self.colormaps.len()) as u64;
+        self.stats.resident_bytes = 0;
+        self.entries.clear();
+        self.colormaps.clear();
+        self.lut_sampler = None;
+    }
+
+    fn evict_colormaps(&mut self, mut stale: impl FnMut(&CachedColormap) -> bool) {
+        let stats = &mut self.stats;
+        self.colormaps.retain(|_, colormap| {
+            if stale(colormap) {
+                stats.evictions += 1;
+                stats.resident_bytes -= LUT_BYTES;
+                colormap.texture.destroy();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn evict(&mut self, mut stale: impl FnMut(&SceneHandle, &CachedBuffers) -> bool) {
+        let stats = &mut self.stats;
+        self.entries.retain(|handle, entry| {
+            if stale(handle, entry) {
+                stats.evictions += 1;
+                stats.resident_bytes -= entry.bytes;
+                entry.vertex.destroy();
+                if let Some(index) = &entry.index {
+                    index.destroy();
+                }
+                if let Some(scalar) = &entry.scalar {
+                    scalar.destroy();
+                }
+                if let Some(texcoord) = &entry.texcoord {
+                    texcoord.destroy();
+                }
+                if let Some(texture) = &entry.texture {
+                    texture.texture.destroy();
+                }
+                entry.uniform.destroy();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+impl Default for GpuResourceCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn uniform_bytes(object: &SceneObject, colormap: Option<&ColorMap>, clipping: &ClipPlanes) -> Vec<u8> {
+    let scalar = object.scalars().map_or([0.0; 8], |binding| binding.uniform(colormap));
+    object.transform.iter()
+        .chain(object.material.color.iter())
+        .chain(scalar.iter())
+        .chain(clipping.uniform().iter())
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+fn create_buffers(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    object: &SceneObject,
+    uniforms: &[u8],
+) -> Option<CachedBuffers> {
+    let (positions, indices) = match &object.geometry {
+        Geometry::Points { positions } => (positions, None),
+        Geometry::Lines { positions, indices } | Geometry::Triangles { positions, indices } => (positions, Some(indices)),
+        Geometry::Custom { .. } => return None,
+    };
+
+    let vertex_data: Vec<u8> = positions.iter()
+        .flat_map(|p| p.iter().flat_map(|c| c.to_le_bytes()))
+        .collect();
+    let vertex = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("scene vertices"),
+        contents: &vertex_data,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let index_data: Option<Vec<u8>> = indices.map(|i| i.iter().flat_map(|v| v.to_le_bytes()).collect());
+    let index = index_data.as_ref().map(|data| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("scene indices"),
+            contents: data,
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+        })
+    });
+
+    let scalar_data: Option<Vec<u8>> = object.scalars()
+        .map(|binding| binding.values.iter().flat_map(|v| v.to_le_bytes()).collect());
+    let scalar = scalar_data.as_ref().map(|data| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("scene scalars"),
+            contents: data,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        })
+    });
+
+    let texcoord_data: Option<Vec<u8>> = object.texture()
+        .map(|texture| texture.texcoords.iter().flatten().flat_map(|c| c.to_le_bytes()).collect());
+    let texcoord = texcoord_data.as_ref().map(|data| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("scene texture coordinates"),
+            contents: data,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        })
+    });
+    let texture = object.texture().map(|image| {
+        let texture = device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+            label: Some("scene image"),
+            size: wgpu::Extent3d { width: image.width, height: image.height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: image.format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        }, wgpu::util::TextureDataOrder::LayerMajor, &image.texels);
+        let filter = image.filter.filter_mode();
+        CachedTexture {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("scene image"),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                ..Default::default()
+            }),
+        }
+    });
+
+    let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("scene object uniforms"),
+        contents: uniforms,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let element_count = indices.map(|i| i.len()).unwrap_or(positions.len()) as u32;
+    let bytes = (vertex_data.len()
+        + index_data.map(|d| d.len()).unwrap_or(0)
+        + scalar_data.map(|d| d.len()).unwrap_or(0)
+        + texcoord_data.map(|d| d.len()).unwrap_or(0)
+        + object.texture().map(|t| t.texels.len()).unwrap_or(0)
+        + UNIFORM_SIZE) as u64;
+
+    Some(CachedBuffers {
+        revision: object.revision(),
+        vertex,
+        index,
+        scalar,
+        texcoord,
+        texture,
+        uniform,
+        element_count,
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Camera, Material, RenderBackend, RenderContext};
+    use nalgebra::Vector3;
+
+    /// A GPU context, or None on machines without any adapter
+    async fn gpu() -> Option<RenderContext> {
+        let context = RenderContext::new(RenderBackend::Wgpu).await;
+        if let Err(e) = &context {
+            eprintln!("No GPU adapter, skipping: {}", e);
+        }
+        context.ok()
+    }
+
+    fn triangle(name: &str) -> SceneObject {
+        SceneObject::new(
+            Geometry::Triangles { positions: vec![Vector3::zeros(), Vector3::x(), Vector3::y()], indices: vec![0, 1, 2] },
+            Material::default(),
+        )
+        .with_name(name)
+    }
+
+    #[tokio::test]
+    async fn unchanged_objects_reuse_their_buffers() {
+        let Some(gpu) = gpu().await else { return };
+        let (device, queue) = (gpu.device().unwrap(), gpu.queue().unwrap());
+        let mut cache = GpuResourceCache::new();
+        let mut scene = Scene::new(Camera::default()).with_object(triangle("a")).with_object(triangle("b"));
+        let settings = RenderSettings::default();
+
+        cache.prepare(device, queue, &scene, &settings);
+        let first = cache.stats();
+        assert_eq!((first.hits, first.misses, first.evictions), (0, 2, 0));
+        // Three vertices, three indices and the uniform block per object
+        assert_eq!(first.resident_bytes, 2 * (9 * 4 + 3 * 4 + UNIFORM_SIZE) as u64);
+
+        // Moving the camera or an object only rewrites uniforms
+        scene.camera_mut().position.x += 1.0;
+        let moved = scene.objects()[0].handle();
+        scene.object_mut(moved).unwrap().transform[(0, 3)] = 2.0;
+        cache.prepare(device, queue, &scene, &settings);
+        let second = cache.stats();
+        assert_eq!((second.hits, second.misses), (2, 2));
+        assert_eq!(second.resident_bytes, first.resident_bytes);
+
+        // New geometry is a miss that replaces the old buffers
+        let handle = scene.objects()[1].handle();
+        scene.object_mut(handle).unwrap().set_geometry(Geometry::Points { positions: vec![Vector3::zeros()] });
+        cache.prepare(device, queue, &scene, &settings);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (3, 3));
+        assert_eq!(cache.get(handle).unwrap().element_count, 1);
+    }
+
+    #[tokio::test]
+    async fn objects_leaving_the_scene_are_evicted_at_once() {
+        let Some(gpu) = gpu().await else { return };
+        let (device, queue) = (gpu.device().unwrap(), gpu.queue().unwrap());
+        let mut cache = GpuResourceCache::new().with_max_unused_frames(100);
+        let mut scene = Scene::new(Camera::default()).with_object(triangle("a")).with_object(triangle("b"));
+        let settings = RenderSettings::default();
+        let (kept, removed) = (scene.objects()[0].handle(), scene.objects()[1].handle());
+
+        cache.prepare(device, queue, &scene, &settings);
+        let resident = cache.stats().resident_bytes;
+        scene.remove_object(removed).unwrap();
+        cache.prepare(device, queue, &scene, &settings);
+
+        assert!(cache.get(removed).is_none());
+        assert!(cache.get(kept).is_some());
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.stats().resident_bytes, resident / 2);
+    }
+
+    #[tokio::test]
+    async fn flushing_releases_everything() {
+        let Some(gpu) = gpu().await else { return };
+        let (device, queue) = (gpu.device().unwrap(), gpu.queue().unwrap());
+        let mut cache = GpuResourceCache::new();
+        let scene = Scene::new(Camera::default()).with_object(triangle("a"));
+        let settings = RenderSettings::default();
+
+        cache.prepare(device, queue, &scene, &settings);
+        cache.flush();
+        assert_eq!(cache.stats().resident_bytes, 0);
+        assert_eq!(cache.stats().evictions, 1);
+        // The next frame uploads the scene again
+        cache.prepare(device, queue, &scene, &settings);
+        assert_eq!(cache.stats().misses, 2);
+    }
+}
@@ -0,0 +1,237 @@
+//! Clipping planes cutting away part of the scene
+//!
+//! Planes set in `RenderSettings` apply to every object; a `SceneObject` may
+//! replace them with its own. A plane keeps the half-space its normal points
+//! into. The enabled planes travel in the object uniforms after the scalar
+//! coloring parameters, and the shaders discard fragments on the clipped
+//! side. `ClipPlanes::keeps` runs the same test per point for the CPU
+//! rasterizer in `render::rasterizer` and for comparisons.
+//!
+//! A closed surface cut open shows its inside. With `ClipCapping::Backfaces`
+//! the back faces seen through the cut are drawn in a flat cap color, so the
+//! cut reads as a solid section without a stencil pass. Pipelines drawing
+//! capped objects must not cull faces, see `ClipPlanes::check_pipeline`.
+
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use super::{PipelineConfig, RenderSettings, SceneObject};
+
+/// Planes the shaders test each fragment against; the length of their `clip_planes` arrays
+pub const MAX_CLIP_PLANES: usize = 6;
+
+/// Floats of the clipping part of the object uniforms: the planes as
+/// (normal, distance), then the plane count and capping flag, then the cap color
+pub const CLIP_UNIFORM_FLOATS: usize = (MAX_CLIP_PLANES + 2) * 4;
+
+/// Plane keeping the points `p` with `normal · p + distance >= 0`, in world space
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClipPlane {
+    /// Unit normal pointing into the kept half-space
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl ClipPlane {
+    /// Plane with a normalized `normal`
+    pub fn new(normal: Vector3<f32>, distance: f32) -> Self {
+        let length = normal.norm();
+        let (normal, distance) = if length > 0.0 {
+            (normal / length, distance / length)
+        } else {
+            (normal, distance)
+        };
+        Self { normal, distance, enabled: true }
+    }
+
+    /// Plane through `point` keeping the side `normal` points to
+    pub fn through(point: Vector3<f32>, normal: Vector3<f32>) -> Self {
+        let plane = Self::new(normal, 0.0);
+        Self { distance: -plane.normal.dot(&point), ..plane }
+    }
+
+    pub fn disabled(mut self) -> Self {
+        self.enabled = false;
+        self
+    }
+
+    /// The same plane keeping the other side
+    pub fn flipped(self) -> Self {
+        Self { normal: -self.normal, distance: -self.distance, ..self }
+    }
+
+    /// Signed distance of `point`; negative on the clipped side
+    pub fn signed_distance(&self, point: &Vector3<f32>) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+
+    /// Whether `point` is kept; disabled planes keep everything
+    pub fn keeps(&self, point: &Vector3<f32>) -> bool {
+        !self.enabled || self.signed_distance(point) >= 0.0
+    }
+
+    /// Plane between `self` at `t = 0` and `other` at `t = 1`, for animating a cut
+    pub fn lerp(&self, other: &ClipPlane, t: f32) -> ClipPlane {
+        let normal = self.normal.lerp(&other.normal, t);
+        // Opposite normals pass through zero halfway; keep the start orientation there
+        let normal = if normal.norm() > 1e-6 { normal } else { self.normal };
+        let distance = self.distance + (other.distance - self.distance) * t;
+        ClipPlane {
+            enabled: if t < 0.5 { self.enabled } else { other.enabled },
+            ..ClipPlane::new(normal, distance)
+        }
+    }
+}
+
+/// What is drawn where a clipping plane cuts through a surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ClipCapping {
+    /// The inside of the surface shows through the cut
+    #[default]
+    None,
+    /// Back faces are drawn in the cap color
+    Backfaces,
+}
+
+/// Set of clipping planes with the capping of the cuts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipPlanes {
+    /// At most `MAX_CLIP_PLANES`
+    pub planes: Vec<ClipPlane>,
+    pub capping: ClipCapping,
+    /// Linear RGBA of capped back faces
+    pub cap_color: [f32; 4],
+}
+
+impl Default for ClipPlanes {
+    fn default() -> Self {
+        Self {
+            planes: Vec::new(),
+            capping: ClipCapping::None,
+            cap_color: [0.5, 0.5, 0.5, 1.0],
+        }
+    }
+}
+
+impl ClipPlanes {
+    pub fn new(planes: Vec<ClipPlane>) -> Self {
+        Self {
+            planes,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_plane(mut self, plane: ClipPlane) -> Self {
+        self.planes.push(plane);
+        self
+    }
+
+    pub fn with_capping(mut self, capping: ClipCapping) -> Self {
+        self.capping = capping;
+        self
+    }
+
+    pub fn with_cap_color(mut self, color: [f32; 4]) -> Self {
+        self.cap_color = color;
+        self
+    }
+
+    /// Whether no plane clips anything
+    pub fn is_empty(&self) -> bool {
+        !self.planes.iter().any(|plane| plane.enabled)
+    }
+
+    pub fn enabled(&self) -> impl Iterator<Item = &ClipPlane> {
+        self.planes.iter().filter(|plane| plane.enabled)
+    }
+
+    /// Whether the world-space `point` survives every plane
+    ///
+    /// Planes without a normal are skipped, as `uniform` leaves them out.
+    pub fn keeps(&self, point: &Vector3<f32>) -> bool {
+        self.planes.iter()
+            .filter(|plane| plane.normal.norm() > 0.0)
+            .all(|plane| plane.keeps(point))
+    }
+
+    /// Planes between `self` at `t = 0` and `other` at `t = 1`
+    ///
+    /// Planes are paired by index; unpaired planes are taken as they are.
+    pub fn lerp(&self, other: &ClipPlanes, t: f32) -> ClipPlanes {
+        let count = self.planes.len().max(other.planes.len());
+        let planes = (0..count)
+            .map(|i| match (self.planes.get(i), other.planes.get(i)) {
+                (Some(a), Some(b)) => a.lerp(b, t),
+                (Some(a), None) => *a,
+                (None, Some(b)) => *b,
+                (None, None) => unreachable!(),
+            })
+            .collect();
+        let near = if t < 0.5 { self } else { other };
+        ClipPlanes {
+            planes,
+            capping: near.capping,
+            cap_color: std::array::from_fn(|i| self.cap_color[i] + (other.cap_color[i] - self.cap_color[i]) * t),
+        }
+    }
+
+    /// Check the plane count and that every normal is a direction
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.planes.len() > MAX_CLIP_PLANES {
+            return Err(crate::Error::Render(format!(
+                "{} clipping planes given, at most {} are supported",
+                self.planes.len(), MAX_CLIP_PLANES
+            )));
+        }
+        for (i, plane) in self.planes.iter().enumerate() {
+            if !(plane.normal.iter().all(|c| c.is_finite()) && plane.distance.is_finite()) || plane.normal.norm() == 0.0 {
+                return Err(crate::Error::Render(format!("Clipping plane {} has no valid normal", i)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `config` draws the back faces capping fills the cut with
+    pub fn check_pipeline(&self, name: &str, config: &PipelineConfig) -> Result<(), crate::Error> {
+        match config.cull_mode {
+            Some(face) if self.capping == ClipCapping::Backfaces => Err(crate::Error::Render(format!(
+                "Pipeline '{}' culls {:?} faces, but capped clipping draws both sides",
+                name, face
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Clipping part of the object uniforms, see `CLIP_UNIFORM_FLOATS`
+    ///
+    /// Only enabled planes are uploaded, packed to the front; planes beyond
+    /// `MAX_CLIP_PLANES` are dropped.
+    pub fn uniform(&self) -> [f32; CLIP_UNIFORM_FLOATS] {
+        let mut uniform = [0.0; CLIP_UNIFORM_FLOATS];
+        let mut count = 0;
+        for plane in self.enabled().filter(|plane| plane.normal.norm() > 0.0).take(MAX_CLIP_PLANES) {
+            let n = plane.normal.normalize();
+            uniform[count * 4..count * 4 + 4].copy_from_slice(&[n.x, n.y, n.z, plane.distance / plane.normal.norm()]);
+            count += 1;
+        }
+        let flags = MAX_CLIP_PLANES * 4;
+        uniform[flags] = count as f32;
+        uniform[flags + 1] = if self.capping == ClipCapping::Backfaces { 1.0 } else { 0.0 };
+        uniform[flags + 4..flags + 8].copy_from_slice(&self.cap_color);
+        uniform
+    }
+}
+
+impl SceneObject {
+    /// Planes clipping this object: its own if set, otherwise those of `settings`
+    pub fn clipping<'a>(&'a self, settings: &'a RenderSettings) -> &'a ClipPlanes {
+        self.clip_planes.as_ref().unwrap_or(&settings.clipping)
+    }
+}
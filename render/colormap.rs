@@ -0,0 +1,913 @@
+//! Editable colormaps, their registry and import of ParaView colormap XML
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+/// One control point of a colormap
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ControlPoint {
+    /// Normalized position in [0, 1]
+    pub position: f32,
+    /// Linear RGB in [0, 1]
+    pub color: [f32; 3],
+    pub opacity: f32,
+}
+
+impl ControlPoint {
+    pub fn new(position: f32, color: [f32; 3]) -> Self {
+        Self { position, color, opacity: 1.0 }
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    fn validate(&self) -> Result<(), crate::Error> {
+        let in_unit = |v: f32| (0.0..=1.0).contains(&v);
+        if !in_unit(self.position) {
+            return Err(crate::Error::Config(format!(
+                "Colormap control point position {} is outside [0, 1]",
+                self.position
+            )));
+        }
+        if !self.color.iter().all(|&c| in_unit(c)) || !in_unit(self.opacity) {
+            return Err(crate::Error::Config(format!(
+                "Colormap control point at {} has color or opacity outside [0, 1]",
+                self.position
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Piecewise linear colormap with per-point opacity
+///
+/// Control points are kept sorted by position; every edit is validated so a
+/// map always has at least two points, all within [0, 1].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColorMap {
+    pub name: String,
+    points: Vec<ControlPoint>,
+    /// Color for NaN values, if the map defines one
+    #[serde(default)]
+    pub nan_color: Option<[f32; 3]>,
+}
+
+impl ColorMap {
+    pub fn new(name: &str, mut points: Vec<ControlPoint>) -> Result<Self, crate::Error> {
+        points.sort_by(|a, b| a.position.total_cmp(&b.position));
+        let map = Self {
+            name: name.to_string(),
+            points,
+            nan_color: None,
+        };
+        map.validate()?;
+        Ok(map)
+    }
+
+    pub fn with_nan_color(mut self, color: [f32; 3]) -> Self {
+        self.nan_color = Some(color);
+        self
+    }
+
+    pub fn points(&self) -> &[ControlPoint] {
+        &self.points
+    }
+
+    /// Check the invariants, e.g. after deserializing a map
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.points.len() < 2 {
+            return Err(crate::Error::Config(format!(
+                "Colormap {} needs at least two control points",
+                self.name
+            )));
+        }
+        for point in &self.points {
+            point.validate()?;
+        }
+        if self.points.windows(2).any(|w| w[0].position > w[1].position) {
+            return Err(crate::Error::Config(format!(
+                "Control points of colormap {} are not sorted by position",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Insert a control point at its position, returning its index
+    pub fn insert(&mut self, point: ControlPoint) -> Result<usize, crate::Error> {
+        point.validate()?;
+        let index = self.points.partition_point(|p| p.position <= point.position);
+        self.points.insert(index, point);
+        Ok(index)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<ControlPoint, crate::Error> {
+        self.check_index(index)?;
+        if self.points.len() <= 2 {
+            return Err(crate::Error::Config(format!(
+                "Cannot remove control point: colormap {} needs at least two",
+                self.name
+            )));
+        }
+        Ok(self.points.remove(index))
+    }
+
+    /// Move a control point to a new position, returning its new index
+    pub fn move_point(&mut self, index: usize, position: f32) -> Result<usize, crate::Error> {
+        self.check_index(index)?;
+        let point = ControlPoint { position, ..self.points[index] };
+        point.validate()?;
+        self.points.remove(index);
+        let index = self.points.partition_point(|p| p.position <= position);
+        self.points.insert(index, point);
+        Ok(index)
+    }
+
+    pub fn set_color(&mut self, index: usize, color: [f32; 3], opacity: f32) -> Result<(), crate::Error> {
+        self.check_index(index)?;
+        let point = ControlPoint { color, opacity, ..self.points[index] };
+        point.validate()?;
+        self.points[index] = point;
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), crate::Error> {
+        if index >= self.points.len() {
+            return Err(crate::Error::Config(format!(
+                "Colormap {} has no control point {}",
+                self.name, index
+            )));
+        }
+        Ok(())
+    }
+
+    /// RGBA at a normalized position; positions outside the points clamp to the ends
+    pub fn sample(&self, t: f32) -> [f32; 4] {
+        let rgba = |p: &ControlPoint| [p.color[0], p.color[1], p.color[2], p.opacity];
+        if t.is_nan() {
+            return match self.nan_color {
+                Some([r, g, b]) => [r, g, b, 1.0],
+                None => [0.0; 4],
+            };
+        }
+        let upper = self.points.partition_point(|p| p.position <= t);
+        if upper == 0 {
+            return rgba(&self.points[0]);
+        }
+        if upper == self.points.len() {
+            return rgba(&self.points[upper - 1]);
+        }
+        let (a, b) = (&self.points[upper - 1], &self.points[upper]);
+        let span = b.position - a.position;
+        let f = if span > 0.0 { (t - a.position) / span } else { 0.0 };
+        let (a, b) = (rgba(a), rgba(b));
+        std::array::from_fn(|i| a[i] + (b[i] - a[i]) * f)
+    }
+
+    pub fn to_json(&self) -> Result<String, crate::Error> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize colormap: {}", e)))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, crate::Error> {
+        let map: Self = serde_json::from_str(json)
+            .map_err(|e| crate::Error::Config(format!("Failed to parse colormap: {}", e)))?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    /// Colormaps defined in a ParaView colormap XML document
+    ///
+    /// Accepts a `<ColorMaps>` collection or a single `<ColorMap>`. Point
+    /// positions are rescaled from the map's data range to [0, 1]; a point's
+    /// `o` attribute becomes its opacity.
+    pub fn from_paraview_xml(xml: &str) -> Result<Vec<Self>, crate::Error> {
+        let document = roxmltree::Document::parse(xml)
+            .map_err(|e| crate::Error::Config(format!("Invalid colormap XML: {}", e)))?;
+        let maps: Vec<Self> = document.descendants()
+            .filter(|node| node.has_tag_name("ColorMap"))
+            .map(paraview_colormap)
+            .collect::<Result<_, _>>()?;
+        if maps.is_empty() {
+            return Err(crate::Error::Config("XML contains no ColorMap elements".to_string()));
+        }
+        Ok(maps)
+    }
+}
+
+fn paraview_colormap(node: roxmltree::Node) -> Result<ColorMap, crate::Error> {
+    let name = node.attribute("name").unwrap_or("Imported").to_string();
+    let number = |element: roxmltree::Node, attribute: &str, default: Option<f32>| -> Result<f32, crate::Error> {
+        match element.attribute(attribute) {
+            Some(text) => crate::util::fmt::parse_f32(text).map_err(|_| crate::Error::Config(format!(
+                "Colormap {}: invalid {} value {:?}",
+                name, attribute, text
+            ))),
+            None => default.ok_or_else(|| crate::Error::Config(format!(
+                "Colormap {}: point without {} attribute",
+                name, attribute
+            ))),
+        }
+    };
+
+    let mut raw = Vec::new();
+    let mut nan_color = None;
+    for child in node.children().filter(|c| c.is_element()) {
+        match child.tag_name().name() {
+            "Point" => raw.push((
+                number(child, "x", None)?,
+                [number(child, "r", None)?, number(child, "g", None)?, number(child, "b", None)?],
+                number(child, "o", Some(1.0))?,
+            )),
+            "NaN" => {
+                nan_color = Some([number(child, "r", None)?, number(child, "g", None)?, number(child, "b", None)?]);
+            }
+            _ => {}
+        }
+    }
+
+    let (min, max) = raw.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), p| (lo.min(p.0), hi.max(p.0)));
+    let range = max - min;
+    let points = raw.into_iter()
+        .map(|(x, color, opacity)| ControlPoint {
+            position: if range > 0.0 { (x - min) / range } else { 0.0 },
+            color: color.map(|c| c.clamp(0.0, 1.0)),
+            opacity: opacity.clamp(0.0, 1.0),
+        })
+        .collect();
+
+    let map = ColorMap::new(&name, points)?;
+    Ok(match nan_color {
+        Some(color) => map.with_nan_color(color.map(|c| c.clamp(0.0, 1.0))),
+        None => map,
+    })
+}
+
+/// Colormaps available by name, built-in and imported
+///
+/// The process-wide library from `global()` is what a colormap choice
+/// parameter lists; imports update it at runtime.
+#[derive(Debug)]
+pub struct ColorMapLibrary {
+    maps: RwLock<BTreeMap<String, Arc<ColorMap>>>,
+    builtin: Vec<String>,
+}
+
+impl ColorMapLibrary {
+    /// Library holding the built-in maps
+    pub fn new() -> Self {
+        let builtin = builtin_colormaps();
+        Self {
+            builtin: builtin.iter().map(|m| m.name.clone()).collect(),
+            maps: RwLock::new(builtin.into_iter().map(|m| (m.name.clone(), Arc::new(m))).collect()),
+        }
+    }
+
+    pub fn global() -> &'static ColorMapLibrary {
+        static GLOBAL: OnceLock<ColorMapLibrary> = OnceLock::new();
+        GLOBAL.get_or_init(Self::new)
+    }
+
+    /// Names in sorted order, e.g. for a choice parameter
+    pub fn names(&self) -> Vec<String> {
+        self.maps.read().keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<ColorMap>> {
+        self.maps.read().get(name).cloned()
+    }
+
+    /// Add or replace a map; built-in maps cannot be replaced
+    pub fn insert(&self, map: ColorMap) -> Result<(), crate::Error> {
+        map.validate()?;
+        if self.builtin.contains(&map.name) {
+            return Err(crate::Error::Config(format!(
+                "Colormap {} is built in and cannot be replaced",
+                map.name
+            )));
+        }
+        self.maps.write().insert(map.name.clone(), Arc::new(map));
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Arc<ColorMap>> {
+        if self.builtin.iter().any(|b| b == name) {
+            return None;
+        }
+        self.maps.write().remove(name)
+    }
+
+    /// Maps that are not built in, for saving with a session
+    pub fn custom(&self) -> Vec<ColorMap> {
+        self.maps.read().values()
+            .filter(|m| !self.builtin.contains(&m.name))
+            .map(|m| m.as_ref().clone())
+            .collect()
+    }
+
+    /// Import every map of a ParaView colormap XML file, returning their names
+    pub async fn import_paraview(&self, path: impl AsRef<Path>) -> Result<Vec<String>, crate::Error> {
+        let xml = crate::util::io::read_text(path).await?;
+        let maps = ColorMap::from_paraview_xml(&xml)?;
+        let mut names = Vec::with_capacity(maps.len());
+        for map in maps {
+            names.push(map.name.clone());
+            self.insert(map)?;
+        }
+        tracing::info!("Imported colormaps: {}", names.join(", "));
+        Ok(names)
+    }
+}
+
+impl Default for ColorMapLibrary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn builtin_colormaps() -> Vec<ColorMap> {
+    let map = |name: &str, colors: &[[f32; 3]]| {
+        let last = (colors.len() - 1) as f32;
+        let points = colors.iter().enumerate()
+            .map(|(i, &color)| ControlPoint::new(i as f32 / last, color))
+            .collect();
+        ColorMap::new(name, points).expect("valid built-in colormap")
+    };
+    vec![
+        map("Grayscale", &[[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]),
+        map("Cool to Warm", &[[0.230, 0.299, 0.754], [0.865, 0.865, 0.865], [0.706, 0.016, 0.150]]),
+        map("Rainbow", &[[0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 0.0]]),
+        map("Viridis", &[
+            [0.267, 0.005, 0.329],
+            [0.229, 0.322, 0.546],
+            [0.128, 0.567, 0.551],
+            [0.369, 0.789, 0.383],
+            [0.993, 0.906, 0.144],
+        ]),
+    ]
+}
+
+/// How scalar values are turned into colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Values mapped linearly from the data range onto the colormap
+    #[default]
+    Linear,
+    /// Values remapped through their cumulative distribution first, so every
+    /// part of the colormap covers about the same number of values
+    Equalized,
+    /// Integer labels, each with a distinct color from a palette
+    Categorical,
+}
+
+impl ColorMode {
+    pub fn parse(text: &str) -> Result<Self, crate::Error> {
+        match text {
+            "linear" => Ok(ColorMode::Linear),
+            "equalized" => Ok(ColorMode::Equalized),
+            "categorical" => Ok(ColorMode::Categorical),
+            other => Err(crate::Error::Config(format!(
+                "Unknown color mode {} (expected linear, equalized or categorical)",
+                other
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorMode::Linear => "linear",
+            ColorMode::Equalized => "equalized",
+            ColorMode::Categorical => "categorical",
+        }
+    }
+}
+
+/// Histogram bins used for equalization
+pub const EQUALIZATION_BINS: usize = 256;
+
+/// Most swatches a categorical legend shows
+pub const MAX_LEGEND_SWATCHES: usize = 32;
+
+/// Remapping of values through their cumulative distribution
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equalization {
+    min: f32,
+    max: f32,
+    /// Cumulative fraction at the end of each equal-width bin
+    cdf: Vec<f32>,
+}
+
+impl Equalization {
+    /// Equalization for the finite values of `values`
+    pub fn new(values: &[f32], bins: usize) -> Self {
+        let histogram = crate::util::math::Histogram::new(values, bins);
+        Self {
+            min: histogram.min,
+            max: histogram.max,
+            cdf: histogram.cdf(),
+        }
+    }
+
+    /// Cumulative fraction below `value`, interpolated within its bin; NaN stays NaN
+    pub fn position(&self, value: f32) -> f32 {
+        if value.is_nan() {
+            return f32::NAN;
+        }
+        let range = self.max - self.min;
+        if range <= 0.0 {
+            return 0.5;
+        }
+        let x = ((value - self.min) / range).clamp(0.0, 1.0) * self.cdf.len() as f32;
+        let bin = (x as usize).min(self.cdf.len() - 1);
+        let below = if bin == 0 { 0.0 } else { self.cdf[bin - 1] };
+        below + (self.cdf[bin] - below) * (x - bin as f32)
+    }
+
+    /// Value at cumulative fraction `t`, the inverse of `position`
+    pub fn value(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        let bin = self.cdf.partition_point(|&c| c < t).min(self.cdf.len() - 1);
+        let below = if bin == 0 { 0.0 } else { self.cdf[bin - 1] };
+        let span = self.cdf[bin] - below;
+        let f = if span > 0.0 { (t - below) / span } else { 0.0 };
+        self.min + (self.max - self.min) * (bin as f32 + f) / self.cdf.len() as f32
+    }
+}
+
+/// Distinct colors for integer labels
+///
+/// Label `i` gets palette entry `i mod n`. Labels beyond the palette size
+/// reuse its colors with a brightness shift derived from a hash of the
+/// label, so the same label always gets the same color across runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CategoricalPalette {
+    colors: Vec<[f32; 3]>,
+}
+
+impl CategoricalPalette {
+    pub fn new(colors: Vec<[f32; 3]>) -> Result<Self, crate::Error> {
+        if colors.is_empty() {
+            return Err(crate::Error::Config("A categorical palette needs at least one color".to_string()));
+        }
+        if colors.iter().flatten().any(|c| !(0.0..=1.0).contains(c)) {
+            return Err(crate::Error::Config("Palette colors must be within [0, 1]".to_string()));
+        }
+        Ok(Self { colors })
+    }
+
+    /// `count` colors sampled evenly from a colormap
+    pub fn from_colormap(map: &ColorMap, count: usize) -> Self {
+        let count = count.max(1);
+        let colors = (0..count)
+            .map(|i| {
+                let [r, g, b, _] = map.sample(if count > 1 { i as f32 / (count - 1) as f32 } else { 0.5 });
+                [r, g, b]
+            })
+            .collect();
+        Self { colors }
+    }
+
+    pub fn colors(&self) -> &[[f32; 3]] {
+        &self.colors
+    }
+
+    pub fn color(&self, label: i64) -> [f32; 3] {
+        let n = self.colors.len() as i64;
+        let base = self.colors[label.rem_euclid(n) as usize];
+        if (0..n).contains(&label) {
+            return base;
+        }
+        // Scale brightness by 0.55..1.0 for labels past the first cycle
+        let shift = 0.55 + 0.45 * (mix_label(label) >> 40) as f32 / (1u64 << 24) as f32;
+        base.map(|c| (c * shift).clamp(0.0, 1.0))
+    }
+}
+
+impl Default for CategoricalPalette {
+    /// Ten colors that stay distinguishable next to each other
+    fn default() -> Self {
+        Self {
+            colors: vec![
+                [0.122, 0.467, 0.706],
+                [1.000, 0.498, 0.055],
+                [0.173, 0.627, 0.173],
+                [0.839, 0.153, 0.157],
+                [0.580, 0.404, 0.741],
+                [0.549, 0.337, 0.294],
+                [0.890, 0.467, 0.761],
+                [0.498, 0.498, 0.498],
+                [0.737, 0.741, 0.133],
+                [0.090, 0.745, 0.812],
+            ],
+        }
+    }
+}
+
+/// SplitMix64 finalizer; fixed, unlike `std`'s randomly seeded hashers
+fn mix_label(label: i64) -> u64 {
+    let mut x = (label as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[derive(Debug, Clone)]
+enum Scale {
+    Linear,
+    Equalized(Equalization),
+    /// Distinct labels present in the data, sorted
+    Categorical { palette: CategoricalPalette, labels: Vec<i64> },
+}
+
+/// What a legend shows for a `ScalarColoring`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Legend {
+    /// Colormap ramp with the data value at each tick position in [0, 1]
+    Gradient { ticks: Vec<(f32, f32)> },
+    /// One color per label, at most `MAX_LEGEND_SWATCHES`
+    Swatches(Vec<(String, [f32; 4])>),
+}
+
+/// Colors for the values of one scalar field
+///
+/// The same mapping serves per-vertex coloring (`colors`) and volume
+/// rendering (`transfer_function`), so both show a field identically.
+#[derive(Debug, Clone)]
+pub struct ScalarColoring {
+    map: Arc<ColorMap>,
+    range: [f32; 2],
+    scale: Scale,
+}
+
+impl ScalarColoring {
+    /// Coloring of `values` in `mode`, spanning their finite range
+    pub fn new(map: Arc<ColorMap>, mode: ColorMode, values: &[f32]) -> Self {
+        let finite = || values.iter().copied().filter(|v| v.is_finite());
+        let min = finite().fold(f32::INFINITY, f32::min);
+        let max = finite().fold(f32::NEG_INFINITY, f32::max);
+        let range = if min <= max { [min, max] } else { [0.0, 1.0] };
+        let scale = match mode {
+            ColorMode::Linear => Scale::Linear,
+            ColorMode::Equalized => Scale::Equalized(Equalization::new(values, EQUALIZATION_BINS)),
+            ColorMode::Categorical => {
+                let mut labels: Vec<i64> = finite().map(|v| v.round() as i64).collect();
+                labels.sort_unstable();
+                labels.dedup();
+                Scale::Categorical { palette: CategoricalPalette::default(), labels }
+            }
+        };
+        Self { map, range, scale }
+    }
+
+    /// Fixed data range for linear mapping, e.g. from the `_range` attribute
+    pub fn with_range(mut self, range: [f32; 2]) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Palette of the categorical mode; ignored by the other modes
+    pub fn with_palette(mut self, palette: CategoricalPalette) -> Self {
+        if let Scale::Categorical { palette: current, .. } = &mut self.scale {
+            *current = palette;
+        }
+        self
+    }
+
+    pub fn mode(&self) -> ColorMode {
+        match self.scale {
+            Scale::Linear => ColorMode::Linear,
+            Scale::Equalized(_) => ColorMode::Equalized,
+            Scale::Categorical { .. } => ColorMode::Categorical,
+        }
+    }
+
+    pub fn range(&self) -> [f32; 2] {
+        self.range
+    }
+
+    pub fn colormap(&self) -> &ColorMap {
+        &self.map
+    }
+
+    /// Colormap position of `value`; NaN for NaN and in categorical mode
+    pub fn position(&self, value: f32) -> f32 {
+        match &self.scale {
+            Scale::Linear => {
+                let [min, max] = self.range;
+                if max > min { (value - min) / (max - min) } else { 0.5 }
+            }
+            Scale::Equalized(equalization) => equalization.position(value),
+            Scale::Categorical { .. } => f32::NAN,
+        }
+    }
+
+    pub fn color(&self, value: f32) -> [f32; 4] {
+        match &self.scale {
+            Scale::Categorical { palette, .. } if !value.is_nan() => {
+                let [r, g, b] = palette.color(value.round() as i64);
+                [r, g, b, 1.0]
+            }
+            _ => self.map.sample(self.position(value)),
+        }
+    }
+
+    /// One color per value, e.g. per vertex
+    pub fn colors(&self, values: &[f32]) -> Vec<[f32; 4]> {
+        values.iter().map(|&v| self.color(v)).collect()
+    }
+
+    /// Lookup table of `samples` colors evenly spaced over the data range, for volume rendering
+    pub fn transfer_function(&self, samples: usize) -> Vec<[f32; 4]> {
+        let [min, max] = self.range;
+        let samples = samples.max(2);
+        (0..samples)
+            .map(|i| self.color(min + (max - min) * i as f32 / (samples - 1) as f32))
+            .collect()
+    }
+
+    pub fn legend(&self) -> Legend {
+        match &self.scale {
+            Scale::Categorical { palette, labels } => Legend::Swatches(
+                labels.iter()
+                    .take(MAX_LEGEND_SWATCHES)
+                    .map(|&label| {
+                        let [r, g, b] = palette.color(label);
+                        (label.to_string(), [r, g, b, 1.0])
+                    })
+                    .collect(),
+            ),
+            scale => {
+                let [min, max] = self.range;
+                let ticks = [0.0, 0.25, 0.5, 0.75, 1.0].into_iter()
+                    .map(|t| match scale {
+                        Scale::Equalized(equalization) => (t, equalization.value(t)),
+                        _ => (t, min + (max - min) * t),
+                    })
+                    .collect();
+                Legend::Gradient { ticks }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOL_TO_WARM: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/colormaps/cool_to_warm.xml"));
+    const LAB: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/colormaps/lab.xml"));
+
+    fn close(a: [f32; 4], b: [f32; 4]) -> bool {
+        a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-6)
+    }
+
+    fn three_points() -> ColorMap {
+        ColorMap::new("test", vec![
+            ControlPoint::new(1.0, [1.0, 0.0, 0.0]),
+            ControlPoint::new(0.0, [0.0, 0.0, 1.0]).with_opacity(0.0),
+            ControlPoint::new(0.5, [1.0, 1.0, 1.0]),
+        ])
+        .unwrap()
+    }
+
+    fn positions(map: &ColorMap) -> Vec<f32> {
+        map.points().iter().map(|p| p.position).collect()
+    }
+
+    #[test]
+    fn new_maps_are_sorted_and_validated() {
+        assert_eq!(positions(&three_points()), vec![0.0, 0.5, 1.0]);
+
+        let error = ColorMap::new("one", vec![ControlPoint::new(0.0, [0.0; 3])]).unwrap_err();
+        assert!(error.to_string().contains("at least two"));
+        let error = ColorMap::new("far", vec![ControlPoint::new(0.0, [0.0; 3]), ControlPoint::new(1.5, [0.0; 3])]).unwrap_err();
+        assert!(error.to_string().contains("outside [0, 1]"));
+        assert!(ColorMap::new("bright", vec![ControlPoint::new(0.0, [2.0, 0.0, 0.0]), ControlPoint::new(1.0, [0.0; 3])]).is_err());
+        assert!(ColorMap::new("clear", vec![ControlPoint::new(0.0, [0.0; 3]).with_opacity(-0.1), ControlPoint::new(1.0, [0.0; 3])]).is_err());
+    }
+
+    #[test]
+    fn edits_keep_points_sorted() {
+        let mut map = three_points();
+        assert_eq!(map.insert(ControlPoint::new(0.25, [0.5; 3])).unwrap(), 1);
+        assert_eq!(positions(&map), vec![0.0, 0.25, 0.5, 1.0]);
+        assert!(map.insert(ControlPoint::new(-0.1, [0.5; 3])).is_err());
+
+        // Moving past a neighbour reorders the points
+        assert_eq!(map.move_point(1, 0.75).unwrap(), 2);
+        assert_eq!(positions(&map), vec![0.0, 0.5, 0.75, 1.0]);
+        assert_eq!(map.points()[2].color, [0.5; 3]);
+        assert!(map.move_point(2, 1.25).is_err());
+        assert!(map.move_point(9, 0.5).is_err());
+        assert_eq!(positions(&map), vec![0.0, 0.5, 0.75, 1.0]);
+
+        map.set_color(0, [0.0, 1.0, 0.0], 0.5).unwrap();
+        assert_eq!(map.points()[0], ControlPoint::new(0.0, [0.0, 1.0, 0.0]).with_opacity(0.5));
+        assert!(map.set_color(0, [0.0, 1.1, 0.0], 0.5).is_err());
+
+        assert_eq!(map.remove(2).unwrap().position, 0.75);
+        map.remove(1).unwrap();
+        let error = map.remove(0).unwrap_err();
+        assert!(error.to_string().contains("needs at least two"));
+        assert!(map.remove(5).is_err());
+    }
+
+    #[test]
+    fn sampling_interpolates_and_clamps() {
+        let map = three_points().with_nan_color([1.0, 1.0, 0.0]);
+        assert!(close(map.sample(0.0), [0.0, 0.0, 1.0, 0.0]));
+        assert!(close(map.sample(0.25), [0.5, 0.5, 1.0, 0.5]));
+        assert!(close(map.sample(0.75), [1.0, 0.5, 0.5, 1.0]));
+        assert!(close(map.sample(-3.0), map.sample(0.0)));
+        assert!(close(map.sample(7.0), [1.0, 0.0, 0.0, 1.0]));
+        assert!(close(map.sample(f32::NAN), [1.0, 1.0, 0.0, 1.0]));
+        assert_eq!(three_points().sample(f32::NAN), [0.0; 4]);
+    }
+
+    #[test]
+    fn json_round_trips_and_is_validated() {
+        let map = three_points().with_nan_color([0.5, 0.5, 0.5]);
+        let json = map.to_json().unwrap();
+        assert_eq!(ColorMap::from_json(&json).unwrap(), map);
+
+        // Hand-edited files with unsorted points are rejected
+        let unsorted = json.replacen("\"position\": 0.0", "\"position\": 0.9", 1);
+        assert!(ColorMap::from_json(&unsorted).unwrap_err().to_string().contains("not sorted"));
+        assert!(ColorMap::from_json("{").is_err());
+    }
+
+    #[test]
+    fn paraview_cool_to_warm_imports_with_opacity_and_nan_color() {
+        let maps = ColorMap::from_paraview_xml(COOL_TO_WARM).unwrap();
+        assert_eq!(maps.len(), 1);
+        let map = &maps[0];
+        assert_eq!(map.name, "Cool to Warm");
+        assert_eq!(positions(map), vec![0.0, 0.5, 1.0]);
+        assert_eq!(map.points()[0].color, [0.231373, 0.298039, 0.752941]);
+        assert_eq!(map.points().iter().map(|p| p.opacity).collect::<Vec<_>>(), vec![0.0, 0.5, 1.0]);
+        assert_eq!(map.nan_color, Some([1.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn paraview_positions_are_rescaled_from_the_data_range() {
+        let maps = ColorMap::from_paraview_xml(LAB).unwrap();
+        assert_eq!(maps.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), vec!["Lab Pressure", "Black-Body Radiation"]);
+
+        let pressure = &maps[0];
+        assert_eq!(positions(pressure), vec![0.0, 0.25, 1.0]);
+        assert_eq!(pressure.points()[1].color, [1.0, 1.0, 1.0]);
+        assert_eq!(pressure.points()[1].opacity, 0.25);
+        assert_eq!(pressure.nan_color, Some([0.25, 0.0, 0.0]));
+
+        // Points without an o attribute are opaque
+        let black_body = &maps[1];
+        assert_eq!(black_body.points().len(), 4);
+        assert!(black_body.points().iter().all(|p| p.opacity == 1.0));
+        assert!(black_body.nan_color.is_none());
+    }
+
+    #[test]
+    fn a_single_colormap_element_is_accepted() {
+        let xml = r#"<ColorMap name="Two"><Point x="2" r="0" g="0" b="0"/><Point x="4" r="1" g="1" b="1"/></ColorMap>"#;
+        let maps = ColorMap::from_paraview_xml(xml).unwrap();
+        assert_eq!(positions(&maps[0]), vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn malformed_paraview_xml_is_an_error() {
+        assert!(ColorMap::from_paraview_xml("<ColorMaps>").unwrap_err().to_string().contains("Invalid colormap XML"));
+        assert!(ColorMap::from_paraview_xml("<ColorMaps/>").unwrap_err().to_string().contains("no ColorMap"));
+        let missing = r#"<ColorMap name="M"><Point x="0" r="0" g="0"/><Point x="1" r="1" g="1" b="1"/></ColorMap>"#;
+        assert!(ColorMap::from_paraview_xml(missing).unwrap_err().to_string().contains("without b attribute"));
+        let invalid = r#"<ColorMap name="M"><Point x="zero" r="0" g="0" b="0"/><Point x="1" r="1" g="1" b="1"/></ColorMap>"#;
+        assert!(ColorMap::from_paraview_xml(invalid).unwrap_err().to_string().contains("invalid x value"));
+        let single = r#"<ColorMap name="M"><Point x="0" r="0" g="0" b="0"/></ColorMap>"#;
+        assert!(ColorMap::from_paraview_xml(single).is_err());
+    }
+
+    #[test]
+    fn library_protects_builtin_maps() {
+        let library = ColorMapLibrary::new();
+        assert_eq!(library.names(), vec!["Cool to Warm", "Grayscale", "Rainbow", "Viridis"]);
+        assert!(library.custom().is_empty());
+
+        let mut viridis = three_points();
+        viridis.name = "Viridis".to_string();
+        let error = library.insert(viridis).unwrap_err();
+        assert!(error.to_string().contains("built in"));
+        assert!(library.remove("Viridis").is_none());
+        assert!(library.get("Viridis").is_some());
+
+        library.insert(three_points()).unwrap();
+        assert_eq!(library.custom(), vec![three_points()]);
+        assert!(library.names().contains(&"test".to_string()));
+        assert!(library.remove("test").is_some());
+        assert!(library.get("test").is_none());
+    }
+
+    #[tokio::test]
+    async fn imports_update_the_library_at_runtime() {
+        let library = ColorMapLibrary::new();
+        let path = std::env::temp_dir().join(format!("vistle_colormaps_{}.xml", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&path, LAB).unwrap();
+        let names = library.import_paraview(&path).await;
+        std::fs::write(&path, COOL_TO_WARM).unwrap();
+        let builtin = library.import_paraview(&path).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(names.unwrap(), vec!["Lab Pressure", "Black-Body Radiation"]);
+        assert_eq!(library.get("Lab Pressure").unwrap().nan_color, Some([0.25, 0.0, 0.0]));
+        assert_eq!(library.names().len(), 6);
+        // ParaView's Cool to Warm collides with the built-in map
+        assert!(builtin.is_err());
+    }
+
+    /// Squares of 0..1000: most values crowd the low end of the range
+    fn skewed() -> Vec<f32> {
+        (0..1000).map(|i| (i * i) as f32).collect()
+    }
+
+    #[test]
+    fn equalization_spreads_crowded_values() {
+        let values = skewed();
+        let median = 250_000.0;
+        let linear = ScalarColoring::new(Arc::new(three_points()), ColorMode::Linear, &values);
+        assert!((linear.position(median) - 0.25).abs() < 0.01);
+
+        let equalized = ScalarColoring::new(Arc::new(three_points()), ColorMode::Equalized, &values);
+        assert_eq!(equalized.mode(), ColorMode::Equalized);
+        assert!((equalized.position(median) - 0.5).abs() < 0.01, "{}", equalized.position(median));
+        assert!(equalized.position(f32::NAN).is_nan());
+
+        let Legend::Gradient { ticks } = equalized.legend() else {
+            panic!("equalized colorings have a gradient legend");
+        };
+        assert_eq!(ticks.len(), 5);
+        assert!((ticks[2].1 - median).abs() < 5000.0, "{:?}", ticks);
+        assert!(ticks.windows(2).all(|w| w[0].1 <= w[1].1));
+    }
+
+    #[test]
+    fn equalization_inverts_and_handles_constant_data() {
+        let equalization = Equalization::new(&skewed(), EQUALIZATION_BINS);
+        for value in [10_000.0, 360_000.0, 810_000.0] {
+            let back = equalization.value(equalization.position(value));
+            assert!((back - value).abs() / value < 0.02, "{} came back as {}", value, back);
+        }
+        assert_eq!(Equalization::new(&[3.0; 5], EQUALIZATION_BINS).position(3.0), 0.5);
+    }
+
+    #[test]
+    fn labels_get_stable_distinct_colors() {
+        let palette = CategoricalPalette::default();
+        let n = palette.colors().len() as i64;
+        for label in 0..n {
+            assert_eq!(palette.color(label), palette.colors()[label as usize]);
+        }
+        // Later cycles reuse the hue, dimmed by a fixed amount per label
+        let wrapped = palette.color(n + 3);
+        assert_ne!(wrapped, palette.color(3));
+        assert_eq!(wrapped, CategoricalPalette::default().color(n + 3));
+        assert!(wrapped.iter().zip(palette.color(3)).all(|(w, base)| *w <= base));
+        assert_eq!(palette.color(-1), CategoricalPalette::default().color(-1));
+
+        assert!(CategoricalPalette::new(Vec::new()).is_err());
+        assert!(CategoricalPalette::new(vec![[1.5, 0.0, 0.0]]).is_err());
+        let sampled = CategoricalPalette::from_colormap(&three_points(), 3);
+        assert_eq!(sampled.colors(), &[[0.0, 0.0, 1.0], [1.0, 1.0, 1.0], [1.0, 0.0, 0.0]]);
+    }
+
+    #[test]
+    fn categorical_colorings_show_one_swatch_per_label() {
+        let palette = CategoricalPalette::new(vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]).unwrap();
+        let coloring = ScalarColoring::new(Arc::new(three_points()), ColorMode::Categorical, &[2.0, 1.0, 2.0, f32::NAN, 1.2])
+            .with_palette(palette);
+        assert!(coloring.position(1.0).is_nan());
+        assert_eq!(coloring.color(2.0), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(
+            coloring.legend(),
+            Legend::Swatches(vec![("1".to_string(), [0.0, 1.0, 0.0, 1.0]), ("2".to_string(), [0.0, 0.0, 1.0, 1.0])])
+        );
+
+        let many: Vec<f32> = (0..40).map(|i| i as f32).collect();
+        let Legend::Swatches(swatches) = ScalarColoring::new(Arc::new(three_points()), ColorMode::Categorical, &many).legend() else {
+            panic!("categorical colorings have swatches");
+        };
+        assert_eq!(swatches.len(), MAX_LEGEND_SWATCHES);
+    }
+
+    #[test]
+    fn color_modes_parse() {
+        for mode in [ColorMode::Linear, ColorMode::Equalized, ColorMode::Categorical] {
+            assert_eq!(ColorMode::parse(mode.as_str()).unwrap(), mode);
+        }
+        assert!(ColorMode::parse("log").is_err());
+    }
+}
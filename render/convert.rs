@@ -0,0 +1,167 @@
+//! Conversion of data objects into renderable scene objects
+
+use nalgebra::Vector3;
+
+use crate::core::{compact_connectivity, transform, ImageView, LayeredImageView, Object, ObjectPayload};
+use super::{image_scene_object, Geometry, ImageRendering, Material, SceneObject};
+
+/// Convert a geometric object into scene objects
+///
+/// The object's meta transform becomes the scene objects' transform. Meshes
+/// referencing more vertices than one 32-bit index buffer addresses become
+/// several scene objects, see `compact_indices`. Images become one
+/// textured quad, see `render::texture`. Returns no objects for
+/// payloads without renderable geometry, including empty objects.
+pub fn to_scene_objects(object: &dyn Object, material: Material) -> Result<Vec<SceneObject>, crate::Error> {
+    let Some(payload) = object.payload() else {
+        return Ok(Vec::new());
+    };
+    let in_object = |e: crate::Error| match e {
+        crate::Error::Compute(message) => crate::Error::Render(format!("Object {}: {}", object.id(), message)),
+        e => e,
+    };
+    if let Some(mut image) = to_image_object(payload, material.clone()).map_err(in_object)? {
+        image.source = Some(object.id());
+        image.transform = object.meta().transform;
+        return Ok(vec![image]);
+    }
+    let batches = to_geometry(payload).map_err(in_object)?;
+
+    let meta_transform = object.meta().transform;
+    if !batches.is_empty() && !transform::is_regular_affine(&meta_transform) {
+        tracing::warn!(
+            "Object {} has a singular or non-affine transform; rendering may be incorrect",
+            object.id()
+        );
+    }
+
+    Ok(batches.into_iter()
+        .map(|geometry| {
+            let mut scene_object = SceneObject::new(geometry, material.clone());
+            scene_object.source = Some(object.id());
+            scene_object.transform = meta_transform * scene_object.transform;
+            scene_object
+        })
+        .collect())
+}
+
+/// Textured quad of an image, or of the first band of a layered image
+fn to_image_object(payload: &ObjectPayload, material: Material) -> Result<Option<SceneObject>, crate::Error> {
+    let rendering = ImageRendering::default();
+    if let Some(image) = ImageView::new(payload) {
+        return image_scene_object(&image, &rendering, material).map(Some);
+    }
+    let Some(layers) = LayeredImageView::new(payload) else {
+        return Ok(None);
+    };
+    let Some(first) = layers.bands().first() else {
+        return Ok(None);
+    };
+    let band = layers.to_image(&[first.name.as_str()])?;
+    let image = ImageView::new(&band).expect("to_image returns an image");
+    image_scene_object(&image, &rendering, material).map(Some)
+}
+
+/// Convert a payload into renderer geometry, one per index batch
+///
+/// Lines and triangles keep the payload's vertices as long as one batch
+/// addresses them all, so per-vertex data still lines up with them.
+pub fn to_geometry(payload: &ObjectPayload) -> Result<Vec<Geometry>, crate::Error> {
+    let positions = |coordinates: &ndarray::Array2<f32>| -> Vec<Vector3<f32>> {
+        coordinates.outer_iter()
+            .map(|row| Vector3::new(row[0], row[1], row[2]))
+            .collect()
+    };
+    type Batch = (Vec<Vector3<f32>>, Vec<u32>);
+    let batches = |coordinates: &ndarray::Array2<f32>, connectivity: &ndarray::Array2<i32>| -> Result<Vec<Batch>, crate::Error> {
+        let batches = compact_connectivity(connectivity)?;
+        match batches.as_slice() {
+            [batch] if batch.vertex_remap.last().is_none_or(|&v| v < coordinates.nrows() && v <= u32::MAX as usize) => {
+                let indices = batch.indices.iter().map(|&i| batch.vertex_remap[i as usize] as u32).collect();
+                Ok(vec![(positions(coordinates), indices)])
+            }
+            _ => batches.into_iter()
+                .map(|batch| Ok((positions(&batch.carry_rows(coordinates)?), batch.indices)))
+                .collect(),
+        }
+    };
+
+    match payload {
+        ObjectPayload::Points { coordinates } => Ok(vec![Geometry::Points {
+            positions: positions(coordinates),
+        }]),
+        ObjectPayload::Lines { coordinates, connections } => Ok(batches(coordinates, connections)?
+            .into_iter()
+            .map(|(positions, indices)| Geometry::Lines { positions, indices })
+            .collect()),
+        ObjectPayload::Triangles { coordinates, triangles } => Ok(batches(coordinates, triangles)?
+            .into_iter()
+            .map(|(positions, indices)| Geometry::Triangles { positions, indices })
+            .collect()),
+        // Split or fanned into triangles over the same vertices
+        ObjectPayload::Quads { .. } | ObjectPayload::Polygons { .. } => to_geometry(&payload.to_triangles()?),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ObjectMeta, ObjectType, VistleObject};
+    use crate::render::Camera;
+    use nalgebra::Matrix4;
+
+    fn triangle(transform: Matrix4<f32>) -> VistleObject {
+        VistleObject::with_data(ObjectType::Triangles, ObjectPayload::Triangles {
+            coordinates: ndarray::array![[-0.5f32, -0.5, 0.0], [0.5, -0.5, 0.0], [0.0, 0.5, 0.0]],
+            triangles: ndarray::array![[0, 1, 2]],
+        })
+        .with_meta(ObjectMeta { transform, ..ObjectMeta::default() })
+    }
+
+    fn positions(object: &SceneObject) -> &[Vector3<f32>] {
+        match &object.geometry {
+            Geometry::Triangles { positions, .. } => positions,
+            other => panic!("expected triangles, got {:?}", other),
+        }
+    }
+
+    /// Horizontal extent of a scene object in normalized device coordinates
+    fn screen_x_range(camera: &Camera, object: &SceneObject) -> (f32, f32) {
+        let m = camera.projection_matrix() * camera.view_matrix() * object.transform;
+        positions(object).iter()
+            .map(|p| m.transform_point(&nalgebra::Point3::from(*p)).x)
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), x| (lo.min(x), hi.max(x)))
+    }
+
+    #[test]
+    fn copies_with_different_transforms_land_in_different_regions() {
+        let camera = Camera::default();
+        let left = triangle(Matrix4::new_translation(&Vector3::new(-1.5, 0.0, 0.0)));
+        let right = triangle(Matrix4::new_translation(&Vector3::new(1.5, 0.0, 0.0)));
+
+        let left = to_scene_objects(&left, Material::default()).unwrap().remove(0);
+        let right = to_scene_objects(&right, Material::default()).unwrap().remove(0);
+        // The vertices are the same; only the transforms differ
+        assert_eq!(positions(&left), positions(&right));
+
+        let (left_min, left_max) = screen_x_range(&camera, &left);
+        let (right_min, right_max) = screen_x_range(&camera, &right);
+        assert!(left_max < 0.0 && right_min > 0.0, "left {:?}, right {:?}", (left_min, left_max), (right_min, right_max));
+        assert!(left_min > -1.0 && right_max < 1.0, "both copies stay on screen");
+    }
+
+    #[test]
+    fn singular_transforms_still_convert() {
+        let flat = Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, 1.0, 0.0));
+        let objects = to_scene_objects(&triangle(flat), Material::default()).unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].transform, flat);
+    }
+
+    #[test]
+    fn payloads_without_geometry_give_no_objects() {
+        let field = VistleObject::with_data(ObjectType::Vec, ObjectPayload::VecScalar { data: ndarray::array![1.0f32] });
+        assert!(to_scene_objects(&field, Material::default()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,529 @@
+//! Scene description files for batch rendering
+//!
+//! A `SceneDescription` holds camera, lights, render settings and the
+//! objects to show, with geometry referenced by `ObjectId` instead of being
+//! embedded. `Scene::from_description` pulls the objects from an
+//! `ObjectRegistry` and applies the per-object style overrides.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use nalgebra::Matrix4;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{ObjectId, ObjectRegistry};
+use super::convert::to_scene_objects;
+use super::{Camera, ClipPlanes, ColorMapLibrary, Geometry, Light, Material, RenderSettings, Scene, SceneObject};
+
+/// How the geometry of an object is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Representation {
+    /// As converted: surfaces as triangles, lines as lines, points as points
+    #[default]
+    Surface,
+    /// Triangle edges as lines
+    Wireframe,
+    /// Vertices only
+    Points,
+}
+
+impl Representation {
+    /// `geometry` drawn in this representation
+    pub fn apply(self, geometry: Geometry) -> Geometry {
+        match (self, geometry) {
+            (Representation::Wireframe, Geometry::Triangles { positions, indices }) => {
+                let indices = indices.chunks_exact(3)
+                    .flat_map(|t| [t[0], t[1], t[1], t[2], t[2], t[0]])
+                    .collect();
+                Geometry::Lines { positions, indices }
+            }
+            (Representation::Points, Geometry::Lines { positions, .. } | Geometry::Triangles { positions, .. }) => {
+                Geometry::Points { positions }
+            }
+            (_, geometry) => geometry,
+        }
+    }
+}
+
+/// Per-object style overrides declared in a description
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StyleOverrides {
+    /// Colormap name in the `ColorMapLibrary`
+    pub colormap: Option<String>,
+    pub representation: Option<Representation>,
+    /// Replaces the alpha of the material color
+    pub opacity: Option<f32>,
+}
+
+impl StyleOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.colormap.is_none() && self.representation.is_none() && self.opacity.is_none()
+    }
+}
+
+/// One object of a described scene
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectReference {
+    /// Object in the `ObjectRegistry` whose geometry is shown
+    pub object: ObjectId,
+    /// Scene object name; the object id if empty
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub material: Material,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    /// Applied on top of the object's own transform
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Matrix4<f32>>,
+    #[serde(default, skip_serializing_if = "StyleOverrides::is_empty")]
+    pub style: StyleOverrides,
+    /// Replaces the clipping planes of the settings for this object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip_planes: Option<ClipPlanes>,
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+impl ObjectReference {
+    pub fn new(object: ObjectId) -> Self {
+        Self {
+            object,
+            name: String::new(),
+            material: Material::default(),
+            visible: true,
+            transform: None,
+            style: StyleOverrides::default(),
+            clip_planes: None,
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = material;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Matrix4<f32>) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    pub fn with_style(mut self, style: StyleOverrides) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn with_clip_planes(mut self, clip_planes: ClipPlanes) -> Self {
+        self.clip_planes = Some(clip_planes);
+        self
+    }
+
+    pub fn hidden(mut self) -> Self {
+        self.visible = false;
+        self
+    }
+}
+
+/// Scene as stored in a file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub camera: Camera,
+    #[serde(default = "default_lights")]
+    pub lights: Vec<Light>,
+    #[serde(default)]
+    pub settings: RenderSettings,
+    #[serde(default)]
+    pub objects: Vec<ObjectReference>,
+}
+
+fn default_lights() -> Vec<Light> {
+    vec![Light::default()]
+}
+
+/// Config error naming the offending field of a description
+fn invalid(field: &str, message: impl std::fmt::Display) -> crate::Error {
+    crate::Error::Config(format!("Invalid scene description: {}: {}", field, message))
+}
+
+/// Render error of a nested check as a description error naming `field`
+fn reword(error: crate::Error, field: &str) -> crate::Error {
+    match error {
+        crate::Error::Render(message) => invalid(field, message),
+        e => e,
+    }
+}
+
+impl SceneDescription {
+    pub fn new(camera: Camera) -> Self {
+        Self {
+            camera,
+            lights: default_lights(),
+            settings: RenderSettings::default(),
+            objects: Vec::new(),
+        }
+    }
+
+    /// Replace the default light
+    pub fn with_lights(mut self, lights: Vec<Light>) -> Self {
+        self.lights = lights;
+        self
+    }
+
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn with_settings(mut self, settings: RenderSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn with_object(mut self, object: ObjectReference) -> Self {
+        self.objects.push(object);
+        self
+    }
+
+    /// Check values that do not depend on the registry
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        let camera = &self.camera;
+        if !(camera.fov > 0.0 && camera.fov < std::f32::consts::PI) {
+            return Err(invalid("camera.fov", format!("{} is not between 0 and pi radians", camera.fov)));
+        }
+        if camera.aspect_ratio <= 0.0 {
+            return Err(invalid("camera.aspect_ratio", format!("{} is not positive", camera.aspect_ratio)));
+        }
+        if !(camera.near > 0.0 && camera.near < camera.far) {
+            return Err(invalid("camera.near", format!("{} must be positive and below far ({})", camera.near, camera.far)));
+        }
+        if (camera.target - camera.position).norm() == 0.0 {
+            return Err(invalid("camera.target", "coincides with camera.position"));
+        }
+
+        for (i, light) in self.lights.iter().enumerate() {
+            if light.intensity < 0.0 {
+                return Err(invalid(&format!("lights[{}].intensity", i), format!("{} is negative", light.intensity)));
+            }
+        }
+
+        self.settings.clipping.validate().map_err(|e| reword(e, "settings.clipping"))?;
+
+        for (i, reference) in self.objects.iter().enumerate() {
+            let field = |name: &str| format!("objects[{}] ({}).{}", i, reference.object, name);
+            if let Some(opacity) = reference.style.opacity {
+                if !(0.0..=1.0).contains(&opacity) {
+                    return Err(invalid(&field("style.opacity"), format!("{} is outside 0..1", opacity)));
+                }
+            }
+            if let Some(name) = &reference.style.colormap {
+                if ColorMapLibrary::global().get(name).is_none() {
+                    return Err(invalid(&field("style.colormap"), format!("unknown colormap '{}'", name)));
+                }
+            }
+            if reference.material.color.iter().any(|c| !(0.0..=1.0).contains(c)) {
+                return Err(invalid(&field("material.color"), "components must be within 0..1"));
+            }
+            if let Some(clip_planes) = &reference.clip_planes {
+                clip_planes.validate().map_err(|e| reword(e, &field("clip_planes")))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load and validate a description from a JSON file
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let text = crate::util::io::read_text(path.as_ref()).await?;
+        let description: Self = serde_json::from_str(&text)
+            .map_err(|e| crate::Error::Config(format!("Invalid scene description {}: {}", path.as_ref().display(), e)))?;
+        description.validate()?;
+        Ok(description)
+    }
+
+    /// Save the description as JSON
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| crate::Error::Config(format!("Failed to serialize scene description: {}", e)))?;
+        crate::util::io::write_text(path, &text).await
+    }
+}
+
+impl Scene {
+    /// Build a scene from a description, taking the referenced objects from `registry`
+    ///
+    /// Placeholders must be resolved beforehand, e.g. with
+    /// `ObjectRegistry::resolve`, since building a scene does not load data.
+    pub fn from_description(description: &SceneDescription, registry: &ObjectRegistry) -> Result<Scene, crate::Error> {
+        description.validate()?;
+
+        let mut scene = Scene::new(description.camera.clone()).with_lights(description.lights.clone());
+        for (i, reference) in description.objects.iter().enumerate() {
+            let field = format!("objects[{}].object", i);
+            let object = registry.get(reference.object)
+                .ok_or_else(|| invalid(&field, format!("object {} is not in the registry", reference.object)))?;
+            if !object.is_complete() {
+                return Err(invalid(&field, format!("object {} is an unresolved placeholder", reference.object)));
+            }
+            // A filter that produced nothing for this timestep; nothing to draw
+            if object.is_empty() {
+                tracing::debug!("Skipping empty object {} in scene description", reference.object);
+                continue;
+            }
+
+            let mut material = reference.material.clone();
+            if let Some(opacity) = reference.style.opacity {
+                material.color.w = opacity;
+            }
+            // Meshes too large for one index buffer come as several batches
+            let batches = to_scene_objects(object.as_ref(), material)
+                .map_err(|e| match e {
+                    crate::Error::Render(message) => invalid(&field, message),
+                    e => e,
+                })?;
+            if batches.is_empty() {
+                return Err(invalid(&field, format!(
+                    "object {} ({}) has no renderable geometry",
+                    reference.object, object.object_type().as_str()
+                )));
+            }
+
+            for mut scene_object in batches {
+                if let Some(representation) = reference.style.representation {
+                    let geometry = representation.apply(scene_object.geometry.clone());
+                    scene_object.set_geometry(geometry);
+                }
+                if let Some(transform) = reference.transform {
+                    scene_object.transform = transform * scene_object.transform;
+                }
+                scene_object.name = if reference.name.is_empty() {
+                    reference.object.to_string()
+                } else {
+                    reference.name.clone()
+                };
+                scene_object.visible = reference.visible;
+                scene_object.colormap = reference.style.colormap.clone();
+                scene_object.clip_planes = reference.clip_planes.clone();
+                scene.add_object(scene_object);
+            }
+        }
+        Ok(scene)
+    }
+
+    /// Description of this scene; objects not converted from a data object are left out
+    pub fn describe(&self, settings: &RenderSettings) -> SceneDescription {
+        let mut description = SceneDescription::new(self.camera().clone())
+            .with_lights(self.lights().to_vec())
+            .with_settings(settings.clone());
+        // Batches of one large mesh share their source and are described once
+        let mut described = HashSet::new();
+        for object in self.objects() {
+            match object.source {
+                Some(id) if !described.insert(id) => {}
+                Some(id) => description.objects.push(describe_object(object, id)),
+                None => tracing::warn!("Scene object '{}' has no source object and is not described", object.name),
+            }
+        }
+        description
+    }
+}
+
+/// Reference to `id` reproducing the name, material, colormap and clipping of `object`
+///
+/// The transform is left out: the object's own transform is applied again
+/// when the scene is rebuilt.
+fn describe_object(object: &SceneObject, id: ObjectId) -> ObjectReference {
+    ObjectReference {
+        object: id,
+        name: object.name.clone(),
+        material: object.material.clone(),
+        visible: object.visible,
+        transform: None,
+        style: StyleOverrides {
+            colormap: object.colormap.clone(),
+            ..StyleOverrides::default()
+        },
+        clip_planes: object.clip_planes.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use nalgebra::{Vector3, Vector4};
+
+    use crate::core::{ObjectPayload, ObjectType, VistleObject};
+    use crate::render::ClipPlane;
+
+    fn triangle() -> Arc<VistleObject> {
+        Arc::new(VistleObject::with_data(ObjectType::Triangles, ObjectPayload::Triangles {
+            coordinates: ndarray::array![[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            triangles: ndarray::array![[0, 1, 2]],
+        }))
+    }
+
+    /// A registry with two triangles and a description showing both
+    fn described() -> (ObjectRegistry, SceneDescription) {
+        let registry = ObjectRegistry::new();
+        let surface = registry.store(triangle());
+        let outline = registry.store(triangle());
+        let description = SceneDescription::new(Camera::default())
+            .with_object(ObjectReference::new(surface)
+                .with_name("surface")
+                .with_material(Material::new(Vector4::new(0.2, 0.4, 0.6, 1.0)))
+                .with_style(StyleOverrides { colormap: Some("Grayscale".to_string()), ..StyleOverrides::default() })
+                .with_clip_planes(ClipPlanes::new(vec![ClipPlane::new(Vector3::x(), 0.5)])))
+            .with_object(ObjectReference::new(outline)
+                .with_style(StyleOverrides { representation: Some(Representation::Wireframe), ..StyleOverrides::default() })
+                .hidden());
+        (registry, description)
+    }
+
+    #[test]
+    fn scenes_describe_themselves_as_they_were_described() {
+        let (registry, description) = described();
+        let scene = Scene::from_description(&description, &registry).unwrap();
+        let settings = RenderSettings::default();
+
+        let generated = scene.describe(&settings);
+        assert_eq!(generated.objects.len(), 2);
+        assert_eq!(generated.lights.len(), description.lights.len());
+        assert_eq!(generated.camera.position, description.camera.position);
+        for (generated, original) in generated.objects.iter().zip(&description.objects) {
+            assert_eq!(generated.object, original.object);
+            assert_eq!(generated.visible, original.visible);
+            assert_eq!(generated.material.color, original.material.color);
+            assert_eq!(generated.style.colormap, original.style.colormap);
+            assert_eq!(generated.clip_planes, original.clip_planes);
+        }
+        assert_eq!(generated.objects[0].name, "surface");
+        // Unnamed objects are named after their id when built
+        assert_eq!(generated.objects[1].name, description.objects[1].object.to_string());
+
+        let rebuilt = Scene::from_description(&generated, &registry).unwrap();
+        let names = |scene: &Scene| scene.objects().iter().map(|o| (o.name.clone(), o.visible)).collect::<Vec<_>>();
+        assert_eq!(names(&rebuilt), names(&scene));
+    }
+
+    #[test]
+    fn objects_without_a_source_are_not_described() {
+        let (registry, description) = described();
+        let mut scene = Scene::from_description(&description, &registry).unwrap();
+        scene.add_object(SceneObject::new(
+            Geometry::Points { positions: vec![Vector3::zeros()] },
+            Material::default(),
+        ).with_name("annotation"));
+
+        let generated = scene.describe(&RenderSettings::default());
+        assert_eq!(generated.objects.len(), 2);
+        assert!(generated.objects.iter().all(|o| o.name != "annotation"));
+    }
+
+    #[test]
+    fn style_overrides_apply_when_building_the_scene() {
+        let (registry, mut description) = described();
+        description.objects[0].style.opacity = Some(0.25);
+        description.objects[0].transform = Some(Matrix4::new_translation(&Vector3::new(0.0, 0.0, 2.0)));
+        let scene = Scene::from_description(&description, &registry).unwrap();
+
+        let surface = &scene.objects()[0];
+        assert_eq!(surface.material.color.w, 0.25);
+        assert_eq!(surface.transform[(2, 3)], 2.0);
+        assert_eq!(surface.colormap.as_deref(), Some("Grayscale"));
+
+        let outline = &scene.objects()[1];
+        assert!(!outline.visible);
+        match &outline.geometry {
+            Geometry::Lines { indices, .. } => assert_eq!(indices, &[0, 1, 1, 2, 2, 0]),
+            other => panic!("expected a wireframe, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn representations_convert_the_geometry() {
+        let triangles = || Geometry::Triangles { positions: vec![Vector3::zeros(); 3], indices: vec![0, 1, 2] };
+        assert!(matches!(Representation::Surface.apply(triangles()), Geometry::Triangles { .. }));
+        assert!(matches!(Representation::Points.apply(triangles()), Geometry::Points { ref positions } if positions.len() == 3));
+        let points = Geometry::Points { positions: vec![Vector3::zeros()] };
+        assert!(matches!(Representation::Wireframe.apply(points), Geometry::Points { .. }));
+    }
+
+    #[test]
+    fn missing_and_empty_objects() {
+        let (registry, description) = described();
+        let missing = description.clone().with_object(ObjectReference::new(ObjectId::new()));
+        let Err(error) = Scene::from_description(&missing, &registry) else {
+            panic!("a missing object was accepted");
+        };
+        let error = error.to_string();
+        assert!(error.contains("objects[2].object") && error.contains("not in the registry"), "{}", error);
+
+        let empty = registry.store(Arc::new(VistleObject::empty_like(triangle().as_ref())));
+        let with_empty = description.with_object(ObjectReference::new(empty));
+        assert_eq!(Scene::from_description(&with_empty, &registry).unwrap().objects().len(), 2);
+    }
+
+    #[test]
+    fn validation_names_the_offending_field() {
+        let (_, description) = described();
+        assert!(description.validate().is_ok());
+
+        let mut bad = description.clone();
+        bad.objects[1].style.opacity = Some(1.5);
+        let error = bad.validate().unwrap_err().to_string();
+        assert!(error.contains("objects[1]") && error.contains("style.opacity"), "{}", error);
+
+        let mut bad = description.clone();
+        bad.objects[0].style.colormap = Some("Nonexistent".to_string());
+        assert!(bad.validate().unwrap_err().to_string().contains("unknown colormap 'Nonexistent'"));
+
+        let mut bad = description.clone();
+        bad.camera.fov = 0.0;
+        assert!(bad.validate().unwrap_err().to_string().contains("camera.fov"));
+
+        let mut bad = description;
+        bad.lights[0].intensity = -1.0;
+        assert!(bad.validate().unwrap_err().to_string().contains("lights[0].intensity"));
+    }
+
+    #[test]
+    fn omitted_fields_take_their_defaults() {
+        let id = ObjectId::new();
+        let reference = serde_json::to_value(ObjectReference::new(id)).unwrap();
+        for skipped in ["transform", "style", "clip_planes"] {
+            assert!(reference.get(skipped).is_none(), "{} was written", skipped);
+        }
+
+        let json = serde_json::json!({ "objects": [{ "object": reference["object"] }] });
+        let description: SceneDescription = serde_json::from_value(json).unwrap();
+        assert_eq!(description.lights.len(), 1);
+        assert_eq!(description.objects[0].object, id);
+        assert!(description.objects[0].visible);
+        assert!(description.objects[0].style.is_empty());
+    }
+
+    #[tokio::test]
+    async fn descriptions_round_trip_through_files() {
+        let path = std::env::temp_dir().join(format!("vistle_scene_{}.json", uuid::Uuid::new_v4().simple()));
+        let (registry, description) = described();
+        let generated = Scene::from_description(&description, &registry).unwrap().describe(&RenderSettings::default());
+        generated.save(&path).await.unwrap();
+
+        let loaded = SceneDescription::load(&path).await.unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&generated).unwrap());
+
+        std::fs::write(&path, r#"{ "camera": { "fov": 4.0 } }"#).unwrap();
+        assert!(SceneDescription::load(&path).await.unwrap_err().to_string().contains("camera.fov"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,441 @@
+//! Export of scene geometry to mesh files
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{compact_indices_with_limit, transform, MAX_BATCH_VERTICES};
+use crate::util::fmt::format_f32;
+use super::{Geometry, Scene, SceneHandle, SceneObject};
+
+/// Mesh file formats the scene can be written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MeshFormat {
+    /// ASCII PLY with per-vertex RGBA colors
+    Ply,
+    /// Wavefront OBJ with `v x y z r g b` vertex colors
+    Obj,
+    /// ASCII STL; triangles only
+    Stl,
+}
+
+impl MeshFormat {
+    /// Format matching a file extension
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let path = path.as_ref();
+        let extension = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("ply") => Ok(MeshFormat::Ply),
+            Some("obj") => Ok(MeshFormat::Obj),
+            Some("stl") => Ok(MeshFormat::Stl),
+            _ => Err(crate::Error::Config(format!(
+                "Cannot tell mesh format of {}; use .ply, .obj or .stl",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// What to export and how
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: MeshFormat,
+    /// Keep objects as named groups (OBJ only); otherwise merge them
+    pub groups: bool,
+    /// Export only these objects instead of everything visible
+    pub objects: Option<Vec<SceneHandle>>,
+}
+
+impl ExportOptions {
+    pub fn new(format: MeshFormat) -> Self {
+        Self {
+            format,
+            groups: false,
+            objects: None,
+        }
+    }
+
+    pub fn with_groups(mut self, groups: bool) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    pub fn with_objects(mut self, objects: Vec<SceneHandle>) -> Self {
+        self.objects = Some(objects);
+        self
+    }
+}
+
+/// World-space geometry of one scene object with baked vertex colors
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshGroup {
+    pub name: String,
+    pub positions: Vec<Vector3<f32>>,
+    pub colors: Vec<[u8; 4]>,
+    pub triangles: Vec<[u32; 3]>,
+    pub lines: Vec<[u32; 2]>,
+    pub points: Vec<u32>,
+}
+
+impl MeshGroup {
+    /// Convert a scene object; `None` for custom geometry
+    ///
+    /// Vertices no line or triangle references are left out, see `compact_indices`.
+    pub fn from_object(object: &SceneObject) -> Option<Self> {
+        let (positions, indices, per_element) = match &object.geometry {
+            Geometry::Points { positions } => (positions, None, 1),
+            Geometry::Lines { positions, indices } => (positions, Some(indices), 2),
+            Geometry::Triangles { positions, indices } => (positions, Some(indices), 3),
+            Geometry::Custom { .. } => return None,
+        };
+
+        let color = object.material.color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        let colors: Vec<[u8; 4]> = match object.resolved_colors() {
+            Some(colors) if colors.len() == positions.len() => colors.iter()
+                .map(|c| c.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+                .collect(),
+            _ => vec![[color.x, color.y, color.z, color.w]; positions.len()],
+        };
+        let mut group = Self {
+            name: object.name.clone(),
+            ..Self::default()
+        };
+        let world = |p: &Vector3<f32>| transform::transform_point(&object.transform, p);
+
+        let Some(indices) = indices else {
+            group.positions = positions.iter().map(world).collect();
+            group.colors = colors;
+            group.points = (0..positions.len() as u32).collect();
+            return Some(group);
+        };
+        let count = positions.len() as u32;
+        let valid = indices.chunks_exact(per_element)
+            .filter(|element| element.iter().all(|&i| i < count))
+            .flatten()
+            .map(|&i| i as i64);
+        // Indices into `positions` always fit one batch
+        let batch = compact_indices_with_limit(valid, per_element, MAX_BATCH_VERTICES).ok()?.into_iter().next()?;
+        group.positions = batch.vertex_remap.iter().map(|&v| world(&positions[v])).collect();
+        group.colors = batch.vertex_remap.iter().map(|&v| colors[v]).collect();
+        if per_element == 3 {
+            group.triangles = batch.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+        } else {
+            group.lines = batch.indices.chunks_exact(2).map(|l| [l[0], l[1]]).collect();
+        }
+        Some(group)
+    }
+
+    /// Append another group, offsetting its indices
+    pub fn append(&mut self, other: &MeshGroup) {
+        let offset = self.positions.len() as u32;
+        self.positions.extend(&other.positions);
+        self.colors.extend(&other.colors);
+        self.triangles.extend(other.triangles.iter().map(|t| t.map(|i| i + offset)));
+        self.lines.extend(other.lines.iter().map(|l| l.map(|i| i + offset)));
+        self.points.extend(other.points.iter().map(|i| i + offset));
+    }
+}
+
+/// Geometry collected from a scene for export
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExportMesh {
+    pub groups: Vec<MeshGroup>,
+}
+
+impl ExportMesh {
+    /// Collect the visible objects of a scene, or only the given ones
+    pub fn from_scene(scene: &Scene, objects: Option<&[SceneHandle]>) -> Self {
+        let groups = scene.objects().iter()
+            .filter(|o| match objects {
+                Some(handles) => handles.contains(&o.handle()),
+                None => o.visible,
+            })
+            .filter_map(MeshGroup::from_object)
+            .enumerate()
+            .map(|(i, mut group)| {
+                if group.name.is_empty() {
+                    group.name = format!("object_{}", i);
+                }
+                group
+            })
+            .collect();
+        Self { groups }
+    }
+
+    /// All groups as one mesh
+    pub fn merged(&self) -> MeshGroup {
+        let mut merged = MeshGroup {
+            name: "scene".to_string(),
+            ..MeshGroup::default()
+        };
+        for group in &self.groups {
+            merged.append(group);
+        }
+        merged
+    }
+
+    pub fn to_ply(&self) -> String {
+        let mesh = self.merged();
+        let mut out = String::new();
+        let _ = writeln!(out, "ply\nformat ascii 1.0\ncomment exported by vistle");
+        let _ = writeln!(out, "element vertex {}", mesh.positions.len());
+        let _ = writeln!(out, "property float x\nproperty float y\nproperty float z");
+        let _ = writeln!(out, "property uchar red\nproperty uchar green\nproperty uchar blue\nproperty uchar alpha");
+        let _ = writeln!(out, "element face {}", mesh.triangles.len());
+        let _ = writeln!(out, "property list uchar int vertex_indices");
+        let _ = writeln!(out, "element edge {}", mesh.lines.len());
+        let _ = writeln!(out, "property int vertex1\nproperty int vertex2");
+        let _ = writeln!(out, "end_header");
+        for (p, c) in mesh.positions.iter().zip(&mesh.colors) {
+            let _ = writeln!(out, "{} {} {} {} {} {} {}", format_f32(p.x), format_f32(p.y), format_f32(p.z), c[0], c[1], c[2], c[3]);
+        }
+        for t in &mesh.triangles {
+            let _ = writeln!(out, "3 {} {} {}", t[0], t[1], t[2]);
+        }
+        for l in &mesh.lines {
+            let _ = writeln!(out, "{} {}", l[0], l[1]);
+        }
+        out
+    }
+
+    /// OBJ text; with `groups` every object becomes a named `g` group
+    pub fn to_obj(&self, groups: bool) -> String {
+        let merged;
+        let groups: Vec<&MeshGroup> = if groups {
+            self.groups.iter().collect()
+        } else {
+            merged = self.merged();
+            vec![&merged]
+        };
+
+        let mut out = String::from("# exported by vistle\n");
+        // OBJ indices are 1-based and global across groups
+        let mut offset = 1;
+        for group in groups {
+            let _ = writeln!(out, "g {}", group.name.replace(char::is_whitespace, "_"));
+            for (p, c) in group.positions.iter().zip(&group.colors) {
+                let rgb = c.map(|v| v as f32 / 255.0);
+                let _ = writeln!(
+                    out, "v {} {} {} {} {} {}",
+                    format_f32(p.x), format_f32(p.y), format_f32(p.z), format_f32(rgb[0]), format_f32(rgb[1]), format_f32(rgb[2])
+                );
+            }
+            for t in &group.triangles {
+                let _ = writeln!(out, "f {} {} {}", t[0] + offset, t[1] + offset, t[2] + offset);
+            }
+            for l in &group.lines {
+                let _ = writeln!(out, "l {} {}", l[0] + offset, l[1] + offset);
+            }
+            for p in &group.points {
+                let _ = writeln!(out, "p {}", p + offset);
+            }
+            offset += group.positions.len() as u32;
+        }
+        out
+    }
+
+    /// STL text; lines and points have no STL representation and are dropped
+    pub fn to_stl(&self) -> String {
+        let mesh = self.merged();
+        if !mesh.lines.is_empty() || !mesh.points.is_empty() {
+            tracing::warn!(
+                "STL export drops {} lines and {} points",
+                mesh.lines.len(), mesh.points.len()
+            );
+        }
+
+        let mut out = String::from("solid vistle\n");
+        for t in &mesh.triangles {
+            let [a, b, c] = t.map(|i| mesh.positions[i as usize]);
+            let normal = (b - a).cross(&(c - a)).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros);
+            let _ = writeln!(out, "  facet normal {} {} {}", format_f32(normal.x), format_f32(normal.y), format_f32(normal.z));
+            let _ = writeln!(out, "    outer loop");
+            for v in [a, b, c] {
+                let _ = writeln!(out, "      vertex {} {} {}", format_f32(v.x), format_f32(v.y), format_f32(v.z));
+            }
+            let _ = writeln!(out, "    endloop\n  endfacet");
+        }
+        out.push_str("endsolid vistle\n");
+        out
+    }
+
+    pub fn to_format(&self, options: &ExportOptions) -> String {
+        match options.format {
+            MeshFormat::Ply => self.to_ply(),
+            MeshFormat::Obj => self.to_obj(options.groups),
+            MeshFormat::Stl => self.to_stl(),
+        }
+    }
+}
+
+impl Scene {
+    /// Write the visible objects, transformed to world space, as one mesh file
+    pub async fn export(&self, path: impl AsRef<Path>, format: MeshFormat) -> Result<(), crate::Error> {
+        self.export_with(path, &ExportOptions::new(format)).await
+    }
+
+    pub async fn export_with(&self, path: impl AsRef<Path>, options: &ExportOptions) -> Result<(), crate::Error> {
+        let mesh = ExportMesh::from_scene(self, options.objects.as_deref());
+        if mesh.groups.is_empty() {
+            return Err(crate::Error::Render("Nothing to export: no matching scene objects".to_string()));
+        }
+        crate::util::io::write_text(path, &mesh.to_format(options)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix4, Vector4};
+
+    use crate::render::{Camera, Material};
+
+    fn temp_path(extension: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("vistle_export_{}.{}", uuid::Uuid::new_v4().simple(), extension))
+    }
+
+    /// A red triangle shifted to x = 10 with an unreferenced fourth vertex,
+    /// a line with blue and green vertex colors, and a hidden point
+    fn scene() -> Scene {
+        let mut triangle = SceneObject::new(
+            Geometry::Triangles {
+                positions: vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(5.0, 5.0, 5.0)],
+                indices: vec![0, 1, 2],
+            },
+            Material::new(Vector4::new(1.0, 0.0, 0.0, 1.0)),
+        )
+        .with_name("red triangle");
+        triangle.transform = Matrix4::new_translation(&Vector3::new(10.0, 0.0, 0.0));
+
+        let mut line = SceneObject::new(
+            Geometry::Lines {
+                positions: vec![Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, 2.0)],
+                indices: vec![0, 1],
+            },
+            Material::new(Vector4::new(1.0, 1.0, 1.0, 1.0)),
+        );
+        line.vertex_colors = Some(vec![[0.0, 0.0, 1.0, 1.0], [0.0, 1.0, 0.0, 0.5]]);
+
+        let mut hidden = SceneObject::new(
+            Geometry::Points { positions: vec![Vector3::new(-1.0, -1.0, -1.0)] },
+            Material::new(Vector4::new(1.0, 1.0, 1.0, 1.0)),
+        )
+        .with_name("hidden");
+        hidden.visible = false;
+
+        Scene::new(Camera::new(1.0)).with_object(triangle).with_object(line).with_object(hidden)
+    }
+
+    fn numbers(line: &str) -> Vec<f32> {
+        line.split_whitespace().filter_map(|t| t.parse().ok()).collect()
+    }
+
+    #[test]
+    fn formats_follow_the_extension() {
+        assert_eq!(MeshFormat::from_path("a/b.PLY").unwrap(), MeshFormat::Ply);
+        assert_eq!(MeshFormat::from_path("scene.obj").unwrap(), MeshFormat::Obj);
+        assert_eq!(MeshFormat::from_path("scene.stl").unwrap(), MeshFormat::Stl);
+        assert!(MeshFormat::from_path("scene.vtk").is_err());
+        assert!(MeshFormat::from_path("scene").is_err());
+    }
+
+    #[test]
+    fn groups_are_in_world_space_with_baked_colors() {
+        let mesh = ExportMesh::from_scene(&scene(), None);
+        assert_eq!(mesh.groups.len(), 2);
+
+        let triangle = &mesh.groups[0];
+        assert_eq!(triangle.name, "red triangle");
+        // The unreferenced vertex is dropped
+        assert_eq!(triangle.positions, vec![Vector3::new(10.0, 0.0, 0.0), Vector3::new(11.0, 0.0, 0.0), Vector3::new(10.0, 1.0, 0.0)]);
+        assert_eq!(triangle.colors, vec![[255, 0, 0, 255]; 3]);
+        assert_eq!(triangle.triangles, vec![[0, 1, 2]]);
+
+        let line = &mesh.groups[1];
+        assert_eq!(line.name, "object_1");
+        assert_eq!(line.colors, vec![[0, 0, 255, 255], [0, 255, 0, 128]]);
+        assert_eq!(line.lines, vec![[0, 1]]);
+
+        let merged = mesh.merged();
+        assert_eq!(merged.positions.len(), 5);
+        assert_eq!(merged.lines, vec![[3, 4]]);
+    }
+
+    #[test]
+    fn chosen_objects_are_exported_even_if_hidden() {
+        let scene = scene();
+        let hidden = scene.objects()[2].handle();
+        let mesh = ExportMesh::from_scene(&scene, Some(&[hidden]));
+        assert_eq!(mesh.groups.len(), 1);
+        assert_eq!(mesh.groups[0].name, "hidden");
+        assert_eq!(mesh.groups[0].points, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn ply_round_trips_a_two_object_scene() {
+        let path = temp_path("ply");
+        scene().export(&path, MeshFormat::Ply).await.unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (header, body) = text.split_once("end_header\n").unwrap();
+        assert!(header.contains("element vertex 5\n"));
+        assert!(header.contains("element face 1\n"));
+        assert!(header.contains("element edge 1\n"));
+
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 5 + 1 + 1);
+        assert_eq!(numbers(lines[1]), vec![11.0, 0.0, 0.0, 255.0, 0.0, 0.0, 255.0]);
+        assert_eq!(numbers(lines[4]), vec![0.0, 0.0, 2.0, 0.0, 255.0, 0.0, 128.0]);
+        assert_eq!(lines[5], "3 0 1 2");
+        assert_eq!(lines[6], "3 4");
+    }
+
+    #[tokio::test]
+    async fn obj_keeps_named_groups_with_global_indices() {
+        let path = temp_path("obj");
+        let options = ExportOptions::new(MeshFormat::Obj).with_groups(true);
+        scene().export_with(&path, &options).await.unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let groups: Vec<&str> = text.lines().filter(|l| l.starts_with("g ")).collect();
+        assert_eq!(groups, vec!["g red_triangle", "g object_1"]);
+        let vertices: Vec<Vec<f32>> = text.lines().filter(|l| l.starts_with("v ")).map(numbers).collect();
+        assert_eq!(vertices.len(), 5);
+        assert_eq!(vertices[2], vec![10.0, 1.0, 0.0, 1.0, 0.0, 0.0]);
+        assert!(text.contains("\nf 1 2 3\n"));
+        assert!(text.contains("\nl 4 5\n"));
+
+        // Merged, there is a single group
+        let merged = ExportMesh::from_scene(&scene(), None).to_obj(false);
+        assert_eq!(merged.lines().filter(|l| l.starts_with("g ")).collect::<Vec<_>>(), vec!["g scene"]);
+    }
+
+    #[tokio::test]
+    async fn stl_keeps_only_triangles() {
+        let path = temp_path("stl");
+        scene().export(&path, MeshFormat::Stl).await.unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(text.starts_with("solid vistle\n"));
+        assert!(text.ends_with("endsolid vistle\n"));
+        let facets: Vec<&str> = text.lines().filter(|l| l.trim_start().starts_with("facet normal")).collect();
+        assert_eq!(facets.len(), 1);
+        assert_eq!(numbers(facets[0]), vec![0.0, 0.0, 1.0]);
+        assert_eq!(text.lines().filter(|l| l.trim_start().starts_with("vertex")).count(), 3);
+    }
+
+    #[tokio::test]
+    async fn exporting_nothing_is_an_error() {
+        let path = temp_path("ply");
+        let error = Scene::new(Camera::new(1.0)).export(&path, MeshFormat::Ply).await.unwrap_err();
+        assert!(error.to_string().contains("Nothing to export"));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,283 @@
+//! GPU adapter enumeration and selection for nodes with several GPUs
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::render::{RenderBackend, RenderContext};
+
+/// One GPU adapter as seen by wgpu
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterDescription {
+    /// Position in `RenderContext::enumerate_adapters`
+    pub index: usize,
+    pub name: String,
+    pub backend: wgpu::Backend,
+    pub device_type: wgpu::DeviceType,
+    /// Dedicated memory; None where the driver does not report it
+    pub vram_bytes: Option<u64>,
+}
+
+impl AdapterDescription {
+    pub(crate) fn new(index: usize, adapter: &wgpu::Adapter) -> Self {
+        let info = adapter.get_info();
+        Self {
+            index,
+            vram_bytes: vram_bytes(info.vendor, info.device),
+            name: info.name,
+            backend: info.backend,
+            device_type: info.device_type,
+        }
+    }
+}
+
+impl fmt::Display for AdapterDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {} ({:?}, {:?}", self.index, self.name, self.backend, self.device_type)?;
+        match self.vram_bytes {
+            Some(bytes) => write!(f, ", {} MiB)", bytes / (1024 * 1024)),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+/// Dedicated memory of a PCI device, from sysfs where the kernel driver exposes it (amdgpu)
+///
+/// wgpu has no portable memory query, so other drivers report None.
+fn vram_bytes(vendor: u32, device: u32) -> Option<u64> {
+    let read_hex = |path: std::path::PathBuf| -> Option<u32> {
+        let text = std::fs::read_to_string(path).ok()?;
+        u32::from_str_radix(text.trim().trim_start_matches("0x"), 16).ok()
+    };
+    std::fs::read_dir("/sys/class/drm").ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join("device"))
+        .filter(|dir| read_hex(dir.join("vendor")) == Some(vendor) && read_hex(dir.join("device")) == Some(device))
+        .find_map(|dir| std::fs::read_to_string(dir.join("mem_info_vram_total")).ok()?.trim().parse().ok())
+}
+
+/// Which adapter a `RenderContext` is created on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterSelector {
+    /// Whatever wgpu picks by default
+    Default,
+    /// Position in `RenderContext::enumerate_adapters`
+    Index(usize),
+    /// First adapter whose name contains this text, ignoring case
+    Name(String),
+}
+
+impl AdapterSelector {
+    /// Parse `default`, an index such as `2`, or a name fragment such as `name:A100`
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if text.is_empty() || text == "default" {
+            AdapterSelector::Default
+        } else if let Ok(index) = text.parse() {
+            AdapterSelector::Index(index)
+        } else {
+            AdapterSelector::Name(text.strip_prefix("name:").unwrap_or(text).to_string())
+        }
+    }
+
+    /// Adapter for a rank, given its rank among the processes on the same node
+    pub fn for_local_rank(local_rank: i32, num_adapters: usize) -> Self {
+        match gpu_for_local_rank(local_rank, num_adapters) {
+            Some(index) => AdapterSelector::Index(index),
+            None => AdapterSelector::Default,
+        }
+    }
+
+    /// Index of the selected adapter among `adapters`, None for `Default`
+    pub(crate) fn resolve(&self, adapters: &[AdapterDescription]) -> Result<Option<usize>, crate::Error> {
+        let found = match self {
+            AdapterSelector::Default => return Ok(None),
+            AdapterSelector::Index(index) => adapters.iter().find(|a| a.index == *index),
+            AdapterSelector::Name(name) => {
+                let name = name.to_lowercase();
+                adapters.iter().find(|a| a.name.to_lowercase().contains(&name))
+            }
+        };
+        match found {
+            Some(adapter) => Ok(Some(adapter.index)),
+            None => {
+                let listed = if adapters.is_empty() {
+                    "none".to_string()
+                } else {
+                    adapters.iter().map(|a| format!("\n  {}", a)).collect()
+                };
+                Err(crate::Error::Render(format!("No GPU adapter matches {}; available adapters: {}", self, listed)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for AdapterSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdapterSelector::Default => write!(f, "default"),
+            AdapterSelector::Index(index) => write!(f, "index {}", index),
+            AdapterSelector::Name(name) => write!(f, "name '{}'", name),
+        }
+    }
+}
+
+impl From<usize> for AdapterSelector {
+    fn from(index: usize) -> Self {
+        AdapterSelector::Index(index)
+    }
+}
+
+impl From<&str> for AdapterSelector {
+    fn from(text: &str) -> Self {
+        AdapterSelector::parse(text)
+    }
+}
+
+/// GPU a rank should use: ranks on one node are spread over its GPUs in turn
+///
+/// `local_rank` is the rank within the node's shared-memory communicator,
+/// see `MpiUniverse::local_rank`. None if the node has no GPU.
+pub fn gpu_for_local_rank(local_rank: i32, num_gpus: usize) -> Option<usize> {
+    (num_gpus > 0).then(|| local_rank.max(0) as usize % num_gpus)
+}
+
+/// Render contexts on several GPUs, handed out in turn
+///
+/// One scene is always rendered by one context; animations and batch runs
+/// spread their frames over the GPUs with `for_frame`.
+pub struct RenderContextPool {
+    contexts: Vec<Arc<RenderContext>>,
+    next: AtomicUsize,
+}
+
+impl RenderContextPool {
+    pub fn new(contexts: Vec<Arc<RenderContext>>) -> Result<Self, crate::Error> {
+        if contexts.is_empty() {
+            return Err(crate::Error::Render("Render context pool needs at least one context".to_string()));
+        }
+        Ok(Self { contexts, next: AtomicUsize::new(0) })
+    }
+
+    /// One context on each of the given adapters
+    pub async fn on_adapters<I, S>(selectors: I) -> Result<Self, crate::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<AdapterSelector>,
+    {
+        let mut contexts = Vec::new();
+        for selector in selectors {
+            contexts.push(Arc::new(RenderContext::new_on(selector).await?));
+        }
+        Self::new(contexts)
+    }
+
+    /// One context on every adapter of the node, skipping software renderers
+    pub async fn all_adapters() -> Result<Self, crate::Error> {
+        let indices: Vec<usize> = RenderContext::enumerate_adapters().into_iter()
+            .filter(|a| a.device_type != wgpu::DeviceType::Cpu)
+            .map(|a| a.index)
+            .collect();
+        if indices.is_empty() {
+            return Self::new(vec![Arc::new(RenderContext::new(RenderBackend::Wgpu).await?)]);
+        }
+        Self::on_adapters(indices).await
+    }
+
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+
+    pub fn contexts(&self) -> &[Arc<RenderContext>] {
+        &self.contexts
+    }
+
+    /// Next context in round-robin order
+    pub fn next(&self) -> Arc<RenderContext> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.contexts.len();
+        self.contexts[index].clone()
+    }
+
+    /// Context for frame `frame`, the same one on every call
+    pub fn for_frame(&self, frame: usize) -> Arc<RenderContext> {
+        self.contexts[frame % self.contexts.len()].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(index: usize, name: &str, vram_bytes: Option<u64>) -> AdapterDescription {
+        AdapterDescription {
+            index,
+            name: name.to_string(),
+            backend: wgpu::Backend::Vulkan,
+            device_type: wgpu::DeviceType::DiscreteGpu,
+            vram_bytes,
+        }
+    }
+
+    fn node() -> Vec<AdapterDescription> {
+        vec![
+            adapter(0, "NVIDIA A100-SXM4-40GB", None),
+            adapter(1, "AMD Instinct MI250X", Some(64 << 30)),
+            adapter(3, "llvmpipe (LLVM 17.0.6, 256 bits)", None),
+        ]
+    }
+
+    #[test]
+    fn selectors_parse_defaults_indices_and_names() {
+        assert_eq!(AdapterSelector::parse(""), AdapterSelector::Default);
+        assert_eq!(AdapterSelector::parse(" default "), AdapterSelector::Default);
+        assert_eq!(AdapterSelector::parse("2"), AdapterSelector::Index(2));
+        assert_eq!(AdapterSelector::parse("name:A100"), AdapterSelector::Name("A100".to_string()));
+        assert_eq!(AdapterSelector::parse("llvmpipe"), AdapterSelector::Name("llvmpipe".to_string()));
+        // A name that looks like an index needs the prefix
+        assert_eq!(AdapterSelector::parse("name:2"), AdapterSelector::Name("2".to_string()));
+        assert_eq!(AdapterSelector::from(1), AdapterSelector::Index(1));
+        assert_eq!(AdapterSelector::from("MI250"), AdapterSelector::Name("MI250".to_string()));
+    }
+
+    #[test]
+    fn selectors_resolve_against_the_adapter_list() {
+        let adapters = node();
+        assert_eq!(AdapterSelector::Default.resolve(&adapters).unwrap(), None);
+        assert_eq!(AdapterSelector::Index(3).resolve(&adapters).unwrap(), Some(3));
+        assert_eq!(AdapterSelector::parse("a100").resolve(&adapters).unwrap(), Some(0));
+        assert_eq!(AdapterSelector::parse("name:instinct").resolve(&adapters).unwrap(), Some(1));
+        // Indices name positions in the enumeration, not in the list
+        assert!(AdapterSelector::Index(2).resolve(&adapters).is_err());
+    }
+
+    #[test]
+    fn unmatched_selectors_list_the_available_adapters() {
+        let error = AdapterSelector::parse("H100").resolve(&node()).unwrap_err().to_string();
+        assert!(error.contains("name 'H100'"), "{}", error);
+        assert!(error.contains("#0 NVIDIA A100-SXM4-40GB (Vulkan, DiscreteGpu)"), "{}", error);
+        assert!(error.contains("#1 AMD Instinct MI250X (Vulkan, DiscreteGpu, 65536 MiB)"), "{}", error);
+
+        let error = AdapterSelector::Index(0).resolve(&[]).unwrap_err().to_string();
+        assert!(error.contains("index 0") && error.contains("none"), "{}", error);
+    }
+
+    #[test]
+    fn ranks_on_a_node_are_spread_over_its_gpus() {
+        let gpus: Vec<Option<usize>> = (0..6).map(|rank| gpu_for_local_rank(rank, 4)).collect();
+        assert_eq!(gpus, [Some(0), Some(1), Some(2), Some(3), Some(0), Some(1)]);
+        assert_eq!(gpu_for_local_rank(-1, 4), Some(0));
+        assert_eq!(gpu_for_local_rank(3, 0), None);
+
+        assert_eq!(AdapterSelector::for_local_rank(5, 2), AdapterSelector::Index(1));
+        assert_eq!(AdapterSelector::for_local_rank(5, 0), AdapterSelector::Default);
+    }
+
+    #[test]
+    fn unknown_devices_report_no_memory() {
+        assert_eq!(vram_bytes(0xffff, 0xffff), None);
+    }
+}
@@ -0,0 +1,317 @@
+//! Coloring by a scalar field on the GPU through a colormap texture
+//!
+//! Baking colors per vertex means every colormap or range change re-uploads
+//! the whole vertex data. A `ScalarBinding` instead uploads the field once as
+//! a per-vertex float; the colormap goes to the GPU as a `LUT_SIZE` texel 1D
+//! texture, and the range travels in the object's uniforms. Switching the
+//! colormap or range then only touches the texture and uniforms.
+//! `ScalarBinding::bake` computes the same colors on the CPU for backends
+//! without a GPU and for export.
+
+use std::sync::Arc;
+
+use crate::render::ColorMap;
+
+/// Texels of a colormap lookup texture
+pub const LUT_SIZE: u32 = 256;
+
+/// Shader coloring objects by their scalar attribute through the colormap texture
+///
+/// Vertex attribute 0 is the position, attribute 1 the scalar. The object
+/// uniforms are `GpuResourceCache`'s: transform, material color,
+/// `ScalarBinding::uniform`, then `ClipPlanes::uniform`.
+pub const SCALAR_LUT_SHADER: &str = r#"
+struct Camera {
+    view_proj: mat4x4<f32>,
+};
+
+struct Object {
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+    // Range min and max, log scale (0 or 1), scalars bound (0 or 1)
+    scalar: vec4<f32>,
+    nan_color: vec4<f32>,
+    // World-space planes as (normal, distance)
+    clip_planes: array<vec4<f32>, 6>,
+    // Plane count, capping (0 or 1)
+    clip: vec4<f32>,
+    cap_color: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+@group(1) @binding(0) var<uniform> object: Object;
+@group(1) @binding(1) var colormap: texture_1d<f32>;
+@group(1) @binding(2) var colormap_sampler: sampler;
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) value: f32,
+    @location(1) world: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @location(1) value: f32) -> VertexOut {
+    var out: VertexOut;
+    let world = object.transform * vec4<f32>(position, 1.0);
+    out.position = camera.view_proj * world;
+    out.value = value;
+    out.world = world.xyz / world.w;
+    return out;
+}
+
+// Mirrors ClipPlanes::keeps
+fn clipped(world: vec3<f32>) -> bool {
+    for (var i = 0u; i < u32(object.clip.x); i++) {
+        let plane = object.clip_planes[i];
+        if dot(plane.xyz, world) + plane.w < 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Mirrors ScalarBinding::position
+fn colormap_position(value: f32) -> f32 {
+    let lo = object.scalar.x;
+    let hi = object.scalar.y;
+    if hi <= lo {
+        return 0.5;
+    }
+    if object.scalar.z > 0.5 {
+        return (log(max(value, lo)) - log(lo)) / (log(hi) - log(lo));
+    }
+    return (value - lo) / (hi - lo);
+}
+
+@fragment
+fn fs_main(in: VertexOut, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    if clipped(in.world) {
+        discard;
+    }
+    if !front_facing && object.clip.y > 0.5 {
+        return object.cap_color;
+    }
+    // Texel i holds the colormap at i / (size - 1); sample between texel centers
+    let size = f32(textureDimensions(colormap));
+    let t = clamp(colormap_position(in.value), 0.0, 1.0);
+    let lut = textureSample(colormap, colormap_sampler, (t * (size - 1.0) + 0.5) / size);
+    if object.scalar.w < 0.5 {
+        return object.color;
+    }
+    // NaN is the only value unequal to itself
+    if in.value != in.value {
+        return object.nan_color;
+    }
+    return lut;
+}
+"#;
+
+/// Scalar field a scene object is colored by, with the colormap it goes through
+///
+/// Values are shared, so cloning a binding to change its colormap or range
+/// does not copy the field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalarBinding {
+    /// One value per vertex
+    pub values: Arc<[f32]>,
+    /// Colormap from the `ColorMapLibrary`
+    pub colormap: String,
+    pub range: [f32; 2],
+    /// Map logarithms of the values; the range must be positive
+    pub log_scale: bool,
+}
+
+impl ScalarBinding {
+    /// Binding spanning the finite range of `values`
+    pub fn new(values: impl Into<Arc<[f32]>>, colormap: &str) -> Self {
+        let values = values.into();
+        let finite = || values.iter().copied().filter(|v| v.is_finite());
+        let min = finite().fold(f32::INFINITY, f32::min);
+        let max = finite().fold(f32::NEG_INFINITY, f32::max);
+        Self {
+            range: if min <= max { [min, max] } else { [0.0, 1.0] },
+            values,
+            colormap: colormap.to_string(),
+            log_scale: false,
+        }
+    }
+
+    pub fn with_range(mut self, range: [f32; 2]) -> Self {
+        self.range = range;
+        self
+    }
+
+    pub fn with_log_scale(mut self, log_scale: bool) -> Self {
+        self.log_scale = log_scale;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        let [min, max] = self.range;
+        if !min.is_finite() || !max.is_finite() {
+            return Err(crate::Error::Render(format!("Scalar range [{}, {}] is not finite", min, max)));
+        }
+        if self.log_scale && min <= 0.0 {
+            return Err(crate::Error::Render(format!(
+                "Logarithmic scalar range [{}, {}] must be positive",
+                min, max
+            )));
+        }
+        Ok(())
+    }
+
+    /// Colormap position of `value`, as computed by `SCALAR_LUT_SHADER`
+    pub fn position(&self, value: f32) -> f32 {
+        if value.is_nan() {
+            return f32::NAN;
+        }
+        let [min, max] = self.range;
+        if max <= min {
+            return 0.5;
+        }
+        if self.log_scale {
+            return (value.max(min).ln() - min.ln()) / (max.ln() - min.ln());
+        }
+        (value - min) / (max - min)
+    }
+
+    /// Colors the GPU path shows, computed on the CPU from `map` directly
+    pub fn bake(&self, map: &ColorMap) -> Vec<[f32; 4]> {
+        self.values.iter().map(|&v| map.sample(self.position(v))).collect()
+    }
+
+    /// Uniforms following the transform and material color: range, log
+    /// scale, whether a colormap is bound, and the NaN color
+    pub fn uniform(&self, map: Option<&ColorMap>) -> [f32; 8] {
+        let [r, g, b, a] = match map.and_then(|m| m.nan_color) {
+            Some([r, g, b]) => [r, g, b, 1.0],
+            None => [0.0; 4],
+        };
+        let flag = |set: bool| if set { 1.0 } else { 0.0 };
+        [self.range[0], self.range[1], flag(self.log_scale), flag(map.is_some()), r, g, b, a]
+    }
+}
+
+/// RGBA8 texels of a colormap's lookup texture; texel i holds position i / (LUT_SIZE - 1)
+pub fn lut_texels(map: &ColorMap) -> Vec<u8> {
+    (0..LUT_SIZE)
+        .flat_map(|i| map.sample(i as f32 / (LUT_SIZE - 1) as f32))
+        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect()
+}
+
+/// Color a linearly filtered lookup texture gives at position `t`, as the shader samples it
+///
+/// With `bake` this tells how far the GPU path deviates from the CPU path
+/// for a colormap, e.g. in golden image comparisons.
+pub fn sample_lut(texels: &[u8], t: f32) -> [f32; 4] {
+    let size = texels.len() / 4;
+    if size == 0 {
+        return [0.0; 4];
+    }
+    let x = t.clamp(0.0, 1.0) * (size - 1) as f32;
+    let i = (x as usize).min(size - 1);
+    let j = (i + 1).min(size - 1);
+    let f = x - i as f32;
+    std::array::from_fn(|c| {
+        let (a, b) = (texels[i * 4 + c] as f32, texels[j * 4 + c] as f32);
+        (a + (b - a) * f) / 255.0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::ControlPoint;
+
+    fn blue_white_red() -> ColorMap {
+        ColorMap::new("bwr", vec![
+            ControlPoint::new(0.0, [0.0, 0.0, 1.0]),
+            ControlPoint::new(0.5, [1.0, 1.0, 1.0]),
+            ControlPoint::new(1.0, [1.0, 0.0, 0.0]),
+        ])
+        .unwrap()
+        .with_nan_color([0.0, 1.0, 0.0])
+    }
+
+    #[test]
+    fn the_default_range_spans_the_finite_values() {
+        let binding = ScalarBinding::new(vec![2.0, f32::NAN, -1.0, f32::INFINITY, 5.0], "bwr");
+        assert_eq!(binding.range, [-1.0, 5.0]);
+        assert_eq!(ScalarBinding::new(vec![f32::NAN, f32::NEG_INFINITY], "bwr").range, [0.0, 1.0]);
+        assert_eq!(ScalarBinding::new(Vec::new(), "bwr").range, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn positions_are_linear_or_logarithmic_in_the_range() {
+        let linear = ScalarBinding::new(vec![0.0], "bwr").with_range([10.0, 20.0]);
+        assert_eq!(linear.position(15.0), 0.5);
+        assert_eq!(linear.position(30.0), 2.0);
+        assert!(linear.position(f32::NAN).is_nan());
+
+        let log = linear.clone().with_range([1.0, 100.0]).with_log_scale(true);
+        assert!((log.position(10.0) - 0.5).abs() < 1e-6);
+        assert_eq!(log.position(0.5), 0.0);
+        assert_eq!(log.position(-3.0), 0.0);
+
+        assert_eq!(linear.with_range([4.0, 4.0]).position(100.0), 0.5);
+    }
+
+    #[test]
+    fn ranges_must_be_finite_and_positive_for_log_scale() {
+        let binding = ScalarBinding::new(vec![1.0], "bwr");
+        assert!(binding.clone().with_range([0.0, 1.0]).validate().is_ok());
+        assert!(matches!(binding.clone().with_range([0.0, f32::INFINITY]).validate(), Err(crate::Error::Render(_))));
+        assert!(binding.clone().with_range([0.0, 1.0]).with_log_scale(true).validate().is_err());
+        assert!(binding.with_range([0.1, 1.0]).with_log_scale(true).validate().is_ok());
+    }
+
+    #[test]
+    fn baked_colors_follow_the_colormap() {
+        let map = blue_white_red();
+        let colors = ScalarBinding::new(vec![0.0, 5.0, 10.0, 20.0, f32::NAN], "bwr")
+            .with_range([0.0, 10.0])
+            .bake(&map);
+        assert_eq!(colors, [
+            [0.0, 0.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, 1.0],
+        ]);
+    }
+
+    #[test]
+    fn uniforms_hold_range_flags_and_nan_color() {
+        let binding = ScalarBinding::new(vec![1.0], "bwr").with_range([1.0, 8.0]).with_log_scale(true);
+        assert_eq!(binding.uniform(Some(&blue_white_red())), [1.0, 8.0, 1.0, 1.0, 0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(binding.with_log_scale(false).uniform(None), [1.0, 8.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn the_lookup_texture_reproduces_the_baked_colors() {
+        let map = blue_white_red();
+        let texels = lut_texels(&map);
+        assert_eq!(texels.len(), LUT_SIZE as usize * 4);
+        assert_eq!(&texels[..4], [0, 0, 255, 255]);
+        assert_eq!(&texels[texels.len() - 4..], [255, 0, 0, 255]);
+
+        let values: Vec<f32> = (0..=1000).map(|i| i as f32 / 100.0).collect();
+        let binding = ScalarBinding::new(values.clone(), "bwr");
+        for (value, baked) in values.iter().zip(binding.bake(&map)) {
+            let sampled = sample_lut(&texels, binding.position(*value));
+            for (s, b) in sampled.iter().zip(baked) {
+                assert!((s - b).abs() <= 2.0 / 255.0, "value {}: {:?} against {:?}", value, sampled, baked);
+            }
+        }
+    }
+
+    #[test]
+    fn sampling_clamps_to_the_texture() {
+        let texels = [0, 0, 0, 255, 255, 255, 255, 255];
+        assert_eq!(sample_lut(&texels, -1.0), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(sample_lut(&texels, 0.5), [0.5, 0.5, 0.5, 1.0]);
+        assert_eq!(sample_lut(&texels, 2.0), [1.0; 4]);
+        assert_eq!(sample_lut(&[], 0.5), [0.0; 4]);
+    }
+}
@@ -1,299 +1,961 @@
-//! Rendering and visualization system
-
-use std::sync::Arc;
-
-/// Rendering backend abstraction
-#[derive(Debug, Clone)]
-pub enum RenderBackend {
-    Wgpu,
-    Cpu,
-    Hybrid,
-}
-
-/// Render target specification
-#[derive(Debug, Clone)]
-pub struct RenderTarget {
-    pub width: u32,
-    pub height: u32,
-    pub format: wgpu::TextureFormat,
-}
-
-/// Rendering context
-pub struct RenderContext {
-    backend: RenderBackend,
-    device: Option<wgpu::Device>,
-    queue: Option<wgpu::Queue>,
-}
-
-impl RenderContext {
-    pub async fn new(backend: RenderBackend) -> Result<Self, crate::Error> {
-        match backend {
-            RenderBackend::Wgpu => {
-                // Initialize wgpu
-                let instance = wgpu::Instance::new(wgpu::Backends::all());
-                let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions::default())
-                    .await
-                    .ok_or(crate::Error::Render("No suitable GPU adapter found".to_string()))?;
-
-                let (device, queue) = adapter.request_device(
-                    &wgpu::DeviceDescriptor::default(),
-                    None,
-                ).await
-                .map_err(|e| crate::Error::Render(format!("Failed to create device: {}", e)))?;
-
-                Ok(Self {
-                    backend,
-                    device: Some(device),
-                    queue: Some(queue),
-                })
-            }
-            _ => Ok(Self {
-                backend,
-                device: None,
-                queue: None,
-            })
-        }
-    }
-
-    pub fn backend(&self) -> &RenderBackend {
-        &self.backend
-    }
-
-    pub fn device(&self) -> Option<&wgpu::Device> {
-        self.device.as_ref()
-    }
-
-    pub fn queue(&self) -> Option<&wgpu::Queue> {
-        self.queue.as_ref()
-    }
-}
-
-/// Render pipeline for visualization
-pub struct RenderPipeline {
-    context: Arc<RenderContext>,
-    shaders: HashMap<String, wgpu::ShaderModule>,
-    pipelines: HashMap<String, wgpu::RenderPipeline>,
-}
-
-impl RenderPipeline {
-    pub fn new(context: Arc<RenderContext>) -> Self {
-        Self {
-            context,
-            shaders: HashMap::new(),
-            pipelines: HashMap::new(),
-        }
-    }
-
-    pub fn add_shader(&mut self, name: &str, source: &str) -> Result<(), crate::Error> {
-        if let Some(device) = self.context.device() {
-            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some(name),
-                source: wgpu::ShaderSource::Wgsl(source.into()),
-            });
-            self.shaders.insert(name.to_string(), shader);
-        }
-        Ok(())
-    }
-
-    pub fn create_pipeline(&mut self, name: &str, config: PipelineConfig) -> Result<(), crate::Error> {
-        // Pipeline creation logic would go here
-        // Simplified for demonstration
-        Ok(())
-    }
-}
-
-/// Pipeline configuration
-#[derive(Debug, Clone)]
-pub struct PipelineConfig {
-    pub vertex_shader: String,
-    pub fragment_shader: String,
-    pub vertex_layout: Vec<wgpu::VertexAttribute>,
-    pub primitive_topology: wgpu::PrimitiveTopology,
-}
-
-/// Camera for 3D visualization
-#[derive(Debug, Clone)]
-pub struct Camera {
-    pub position: nalgebra::Vector3<f32>,
-    pub target: nalgebra::Vector3<f32>,
-    pub up: nalgebra::Vector3<f32>,
-    pub fov: f32,
-    pub aspect_ratio: f32,
-    pub near: f32,
-    pub far: f32,
-}
-
-impl Camera {
-    pub fn new(aspect_ratio: f32) -> Self {
-        Self {
-            position: nalgebra::Vector3::new(0.0, 0.0, 5.0),
-            target: nalgebra::Vector3::zeros(),
-            up: nalgebra::Vector3::new(0.0, 1.0, 0.0),
-            fov: 45.0f32.to_radians(),
-            aspect_ratio,
-            near: 0.1,
-            far: 1000.0,
-        }
-    }
-
-    pub fn view_matrix(&self) -> nalgebra::Matrix4<f32> {
-        nalgebra::Matrix4::look_at_rh(
-            &nalgebra::Point3::from(self.position),
-            &nalgebra::Point3::from(self.target),
-            &self.up,
-        )
-    }
-
-    pub fn projection_matrix(&self) -> nalgebra::Matrix4<f32> {
-        nalgebra::Matrix4::new_perspective(
-            self.aspect_ratio,
-            self.fov,
-            self.near,
-            self.far,
-        )
-    }
-}
-
-/// Scene management for visualization
-pub struct Scene {
-    objects: Vec<SceneObject>,
-    camera: Camera,
-    lights: Vec<Light>,
-}
-
-impl Scene {
-    pub fn new(camera: Camera) -> Self {
-        Self {
-            objects: Vec::new(),
-            camera,
-            lights: vec![Light::default()],
-        }
-    }
-
-    pub fn add_object(&mut self, object: SceneObject) {
-        self.objects.push(object);
-    }
-
-    pub fn camera(&self) -> &Camera {
-        &self.camera
-    }
-
-    pub fn camera_mut(&mut self) -> &mut Camera {
-        &mut self.camera
-    }
-
-    pub fn objects(&self) -> &[SceneObject] {
-        &self.objects
-    }
-}
-
-/// Scene object representation
-#[derive(Debug, Clone)]
-pub struct SceneObject {
-    pub transform: nalgebra::Matrix4<f32>,
-    pub geometry: Geometry,
-    pub material: Material,
-}
-
-impl SceneObject {
-    pub fn new(geometry: Geometry, material: Material) -> Self {
-        Self {
-            transform: nalgebra::Matrix4::identity(),
-            geometry,
-            material,
-        }
-    }
-}
-
-/// Geometry types
-#[derive(Debug, Clone)]
-pub enum Geometry {
-    Points { positions: Vec<nalgebra::Vector3<f32>> },
-    Lines { positions: Vec<nalgebra::Vector3<f32>>, indices: Vec<u32> },
-    Triangles { positions: Vec<nalgebra::Vector3<f32>>, indices: Vec<u32> },
-    Custom { data: Vec<u8> },
-}
-
-/// Material properties
-#[derive(Debug, Clone)]
-pub struct Material {
-    pub color: nalgebra::Vector4<f32>,
-    pub metallic: f32,
-    pub roughness: f32,
-}
-
-impl Default for Material {
-    fn default() -> Self {
-        Self {
-            color: nalgebra::Vector4::new(1.0, 1.0, 1.0, 1.0),
-            metallic: 0.0,
-            roughness: 0.5,
-        }
-    }
-}
-
-/// Light sources
-#[derive(Debug, Clone)]
-pub struct Light {
-    pub position: nalgebra::Vector3<f32>,
-    pub color: nalgebra::Vector3<f32>,
-    pub intensity: f32,
-    pub light_type: LightType,
-}
-
-impl Default for Light {
-    fn default() -> Self {
-        Self {
-            position: nalgebra::Vector3::new(10.0, 10.0, 10.0),
-            color: nalgebra::Vector3::new(1.0, 1.0, 1.0),
-            intensity: 1.0,
-            light_type: LightType::Directional,
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub enum LightType {
-    Directional,
-    Point,
-    Spot,
-}
-
-/// Renderer trait for different rendering backends
-#[async_trait::async_trait]
-pub trait Renderer: Send + Sync {
-    async fn render(&mut self, scene: &Scene, target: &RenderTarget) -> Result<(), crate::Error>;
-    fn context(&self) -> &RenderContext;
-}
-
-/// WGPU-based renderer
-pub struct WgpuRenderer {
-    context: Arc<RenderContext>,
-    pipeline: RenderPipeline,
-}
-
-impl WgpuRenderer {
-    pub async fn new() -> Result<Self, crate::Error> {
-        let context = Arc::new(RenderContext::new(RenderBackend::Wgpu).await?);
-        let pipeline = RenderPipeline::new(context.clone());
-
-        Ok(Self {
-            context,
-            pipeline,
-        })
-    }
-}
-
-#[async_trait::async_trait]
-impl Renderer for WgpuRenderer {
-    async fn render(&mut self, scene: &Scene, target: &RenderTarget) -> Result<(), crate::Error> {
-        // Rendering logic would go here
-        // This is a placeholder implementation
-        tracing::info!("Rendering scene with {} objects", scene.objects().len());
-        Ok(())
-    }
-
-    fn context(&self) -> &RenderContext {
-        &self.context
-    }
-}
+//! Rendering and visualization system
+
+pub mod cache;
+pub mod clipping;
+pub mod colormap;
+pub mod convert;
+pub mod description;
+pub mod export;
+pub mod gpu;
+pub mod lut;
+pub mod multiview;
+pub mod rasterizer;
+pub mod recovery;
+pub mod testing;
+pub mod texture;
+pub mod transparency;
+pub mod update;
+
+pub use cache::*;
+pub use clipping::*;
+pub use colormap::*;
+pub use description::*;
+pub use export::*;
+pub use gpu::*;
+pub use lut::*;
+pub use multiview::*;
+pub use rasterizer::*;
+pub use recovery::*;
+pub use texture::*;
+pub use transparency::*;
+pub use update::*;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::ObjectId;
+
+/// Rendering backend abstraction
+#[derive(Debug, Clone)]
+pub enum RenderBackend {
+    Wgpu,
+    Cpu,
+    Hybrid,
+}
+
+/// Render target specification
+#[derive(Debug, Clone)]
+pub struct RenderTarget {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+}
+
+/// Rendering context
+pub struct RenderContext {
+    backend: RenderBackend,
+    adapter: Option<AdapterDescription>,
+    device: Option<wgpu::Device>,
+    queue: Option<wgpu::Queue>,
+    /// Adapter the context was requested on, reused when recreating it
+    selector: AdapterSelector,
+    /// Set once the device is lost; see `render::recovery`
+    lost: Arc<AtomicBool>,
+}
+
+fn wgpu_instance() -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    })
+}
+
+impl RenderContext {
+    pub async fn new(backend: RenderBackend) -> Result<Self, crate::Error> {
+        match backend {
+            RenderBackend::Wgpu => Self::new_on(AdapterSelector::Default).await,
+            _ => Ok(Self {
+                backend,
+                adapter: None,
+                device: None,
+                queue: None,
+                selector: AdapterSelector::Default,
+                lost: Arc::new(AtomicBool::new(false)),
+            })
+        }
+    }
+
+    /// GPU adapters of this node, in the order `AdapterSelector::Index` refers to
+    pub fn enumerate_adapters() -> Vec<AdapterDescription> {
+        wgpu_instance().enumerate_adapters(wgpu::Backends::all())
+            .iter()
+            .enumerate()
+            .map(|(index, adapter)| AdapterDescription::new(index, adapter))
+            .collect()
+    }
+
+    /// Wgpu context on a chosen adapter
+    ///
+    /// Fails with the list of available adapters if none matches.
+    pub async fn new_on(selector: impl Into<AdapterSelector>) -> Result<Self, crate::Error> {
+        let selector = selector.into();
+        let instance = wgpu_instance();
+        let adapters = instance.enumerate_adapters(wgpu::Backends::all());
+        let descriptions: Vec<AdapterDescription> = adapters.iter().enumerate()
+            .map(|(index, adapter)| AdapterDescription::new(index, adapter))
+            .collect();
+
+        let (adapter, description) = match selector.resolve(&descriptions)? {
+            Some(index) => {
+                let adapter = adapters.into_iter().nth(index).expect("resolved index is enumerated");
+                (adapter, descriptions[index].clone())
+            }
+            None => {
+                let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions::default())
+                    .await
+                    .ok_or(crate::Error::Render("No suitable GPU adapter found".to_string()))?;
+                let info = adapter.get_info();
+                // The default adapter is one of the enumerated ones; match it to report its index
+                let description = descriptions.iter()
+                    .find(|d| d.name == info.name && d.backend == info.backend)
+                    .cloned()
+                    .unwrap_or_else(|| AdapterDescription::new(descriptions.len(), &adapter));
+                (adapter, description)
+            }
+        };
+
+        let (device, queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor::default(),
+            None,
+        ).await
+        .map_err(|e| crate::Error::Render(format!("Failed to create device on {}: {}", description, e)))?;
+        tracing::info!("Rendering on GPU adapter {}", description);
+
+        // Replaces wgpu's default handler, which panics on the first error of a lost device
+        let lost = Arc::new(AtomicBool::new(false));
+        let flag = lost.clone();
+        let name = description.to_string();
+        device.on_uncaptured_error(Box::new(move |error| {
+            if is_device_loss(&error) {
+                if !flag.swap(true, Ordering::Relaxed) {
+                    tracing::warn!("GPU device on {} was lost: {}", name, error);
+                }
+            } else {
+                tracing::error!("GPU error on {}: {}", name, error);
+            }
+        }));
+
+        Ok(Self {
+            backend: RenderBackend::Wgpu,
+            adapter: Some(description),
+            device: Some(device),
+            queue: Some(queue),
+            selector,
+            lost,
+        })
+    }
+
+    /// Whether the device was lost; a lost context must be recreated
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    /// Mark the device lost, e.g. when a caller saw it fail or to exercise recovery
+    pub fn mark_lost(&self) {
+        self.lost.store(true, Ordering::Relaxed);
+    }
+
+    /// New context with the same backend on the same adapter
+    pub async fn recreate(&self) -> Result<Self, crate::Error> {
+        match self.backend {
+            RenderBackend::Wgpu => Self::new_on(self.selector.clone()).await,
+            ref backend => Self::new(backend.clone()).await,
+        }
+    }
+
+    /// Adapter the device was created on, None without a GPU backend
+    pub fn adapter(&self) -> Option<&AdapterDescription> {
+        self.adapter.as_ref()
+    }
+
+    pub fn backend(&self) -> &RenderBackend {
+        &self.backend
+    }
+
+    pub fn device(&self) -> Option<&wgpu::Device> {
+        self.device.as_ref()
+    }
+
+    pub fn queue(&self) -> Option<&wgpu::Queue> {
+        self.queue.as_ref()
+    }
+}
+
+/// Render pipeline for visualization
+///
+/// Shader sources and pipeline configurations are kept next to the GPU
+/// objects built from them, so `rebuild` can recreate everything on a new
+/// context after the device was lost.
+pub struct RenderPipeline {
+    context: Arc<RenderContext>,
+    shaders: HashMap<String, wgpu::ShaderModule>,
+    pipelines: HashMap<String, wgpu::RenderPipeline>,
+    sources: HashMap<String, String>,
+    configs: HashMap<String, PipelineConfig>,
+}
+
+impl RenderPipeline {
+    pub fn new(context: Arc<RenderContext>) -> Self {
+        Self {
+            context,
+            shaders: HashMap::new(),
+            pipelines: HashMap::new(),
+            sources: HashMap::new(),
+            configs: HashMap::new(),
+        }
+    }
+
+    /// Recreate shaders and pipelines on `context` from the retained sources
+    pub fn rebuild(&mut self, context: Arc<RenderContext>) -> Result<(), crate::Error> {
+        self.context = context;
+        self.shaders.clear();
+        self.pipelines.clear();
+        for (name, source) in std::mem::take(&mut self.sources) {
+            self.add_shader(&name, &source)?;
+        }
+        for (name, config) in std::mem::take(&mut self.configs) {
+            self.create_pipeline(&name, config)?;
+        }
+        Ok(())
+    }
+
+    pub fn add_shader(&mut self, name: &str, source: &str) -> Result<(), crate::Error> {
+        self.sources.insert(name.to_string(), source.to_string());
+        if let Some(device) = self.context.device() {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(name),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            self.shaders.insert(name.to_string(), shader);
+        }
+        Ok(())
+    }
+
+    pub fn config(&self, name: &str) -> Option<&PipelineConfig> {
+        self.configs.get(name)
+    }
+
+    pub fn create_pipeline(&mut self, name: &str, config: PipelineConfig) -> Result<(), crate::Error> {
+        self.configs.insert(name.to_string(), config);
+        // Pipeline creation logic would go here
+        // Simplified for demonstration
+        Ok(())
+    }
+
+    pub fn pipeline(&self, name: &str) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(name)
+    }
+}
+
+/// Pipeline configuration
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    pub vertex_shader: String,
+    pub fragment_shader: String,
+    pub vertex_layout: Vec<wgpu::VertexAttribute>,
+    pub primitive_topology: wgpu::PrimitiveTopology,
+    /// Faces left out; `None` for object pipelines, whose clipping may cap with back faces
+    pub cull_mode: Option<wgpu::Face>,
+}
+
+/// Camera for 3D visualization
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Camera {
+    pub position: nalgebra::Vector3<f32>,
+    pub target: nalgebra::Vector3<f32>,
+    pub up: nalgebra::Vector3<f32>,
+    pub fov: f32,
+    pub aspect_ratio: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Camera {
+    pub fn new(aspect_ratio: f32) -> Self {
+        Self {
+            position: nalgebra::Vector3::new(0.0, 0.0, 5.0),
+            target: nalgebra::Vector3::zeros(),
+            up: nalgebra::Vector3::new(0.0, 1.0, 0.0),
+            fov: 45.0f32.to_radians(),
+            aspect_ratio,
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+
+    pub fn looking_at(mut self, position: nalgebra::Vector3<f32>, target: nalgebra::Vector3<f32>) -> Self {
+        self.position = position;
+        self.target = target;
+        self
+    }
+
+    pub fn view_matrix(&self) -> nalgebra::Matrix4<f32> {
+        nalgebra::Matrix4::look_at_rh(
+            &nalgebra::Point3::from(self.position),
+            &nalgebra::Point3::from(self.target),
+            &self.up,
+        )
+    }
+
+    pub fn projection_matrix(&self) -> nalgebra::Matrix4<f32> {
+        nalgebra::Matrix4::new_perspective(
+            self.aspect_ratio,
+            self.fov,
+            self.near,
+            self.far,
+        )
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+/// Scene management for visualization
+pub struct Scene {
+    objects: Vec<SceneObject>,
+    camera: Camera,
+    lights: Vec<Light>,
+    /// Objects added through `apply_update`, by block
+    keyed: HashMap<SceneKey, KeyedObjects>,
+    /// Generation below which each module's updates are ignored
+    retired: HashMap<u32, u64>,
+}
+
+impl Scene {
+    pub fn new(camera: Camera) -> Self {
+        Self {
+            objects: Vec::new(),
+            camera,
+            lights: vec![Light::default()],
+            keyed: HashMap::new(),
+            retired: HashMap::new(),
+        }
+    }
+
+    /// Replace the default light
+    pub fn with_lights(mut self, lights: Vec<Light>) -> Self {
+        self.lights = lights;
+        self
+    }
+
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn with_object(mut self, object: SceneObject) -> Self {
+        self.objects.push(object);
+        self
+    }
+
+    pub fn add_object(&mut self, object: SceneObject) {
+        self.objects.push(object);
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut Vec<Light> {
+        &mut self.lights
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    pub fn objects(&self) -> &[SceneObject] {
+        &self.objects
+    }
+
+    pub fn object_mut(&mut self, handle: SceneHandle) -> Option<&mut SceneObject> {
+        self.objects.iter_mut().find(|o| o.handle() == handle)
+    }
+
+    /// Remove an object; its GPU resources are evicted with the next frame prepared
+    pub fn remove_object(&mut self, handle: SceneHandle) -> Option<SceneObject> {
+        let index = self.objects.iter().position(|o| o.handle() == handle)?;
+        Some(self.objects.remove(index))
+    }
+}
+
+/// Stable identity of a scene object, used to key GPU resources
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SceneHandle(u64);
+
+impl SceneHandle {
+    fn next() -> Self {
+        static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+    }
+}
+
+/// Scene object representation
+///
+/// Change geometry through `set_geometry` or `geometry_mut` so the revision
+/// is bumped and cached GPU buffers are rebuilt; transform and material
+/// changes only update uniforms, as do colormap and range changes of
+/// bound scalars.
+#[derive(Debug, Clone)]
+pub struct SceneObject {
+    pub name: String,
+    pub transform: nalgebra::Matrix4<f32>,
+    pub geometry: Geometry,
+    pub material: Material,
+    pub visible: bool,
+    /// Data object the geometry was converted from, referenced by scene descriptions
+    pub source: Option<ObjectId>,
+    /// Colormap from the `ColorMapLibrary` for renderers that color by data
+    pub colormap: Option<String>,
+    /// Per-vertex RGBA replacing the material color, see `color_by`
+    pub vertex_colors: Option<Vec<[f32; 4]>>,
+    /// Clipping planes replacing those of the `RenderSettings`, see `clipping`
+    pub clip_planes: Option<ClipPlanes>,
+    /// Scalar field colored on the GPU, see `bind_scalars`
+    scalars: Option<ScalarBinding>,
+    /// Image drawn on the geometry, see `bind_texture`
+    texture: Option<SceneTexture>,
+    handle: SceneHandle,
+    revision: u64,
+}
+
+impl SceneObject {
+    pub fn new(geometry: Geometry, material: Material) -> Self {
+        Self {
+            name: String::new(),
+            transform: nalgebra::Matrix4::identity(),
+            geometry,
+            material,
+            visible: true,
+            source: None,
+            colormap: None,
+            vertex_colors: None,
+            clip_planes: None,
+            scalars: None,
+            texture: None,
+            handle: SceneHandle::next(),
+            revision: 0,
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn handle(&self) -> SceneHandle {
+        self.handle
+    }
+
+    /// Counter bumped whenever the geometry changes
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn set_geometry(&mut self, geometry: Geometry) {
+        self.geometry = geometry;
+        self.revision += 1;
+    }
+
+    pub fn geometry_mut(&mut self) -> &mut Geometry {
+        self.revision += 1;
+        &mut self.geometry
+    }
+
+    fn check_vertex_values(&self, values: usize) -> Result<(), crate::Error> {
+        let vertices = match &self.geometry {
+            Geometry::Points { positions }
+            | Geometry::Lines { positions, .. }
+            | Geometry::Triangles { positions, .. } => positions.len(),
+            Geometry::Custom { .. } => 0,
+        };
+        if values != vertices {
+            return Err(crate::Error::Render(format!(
+                "Cannot color {} vertices of '{}' by {} values",
+                vertices, self.name, values
+            )));
+        }
+        Ok(())
+    }
+
+    /// Color the vertices by one scalar value each
+    pub fn color_by(&mut self, coloring: &ScalarColoring, values: &[f32]) -> Result<(), crate::Error> {
+        self.check_vertex_values(values.len())?;
+        self.colormap = Some(coloring.colormap().name.clone());
+        self.vertex_colors = Some(coloring.colors(values));
+        self.scalars = None;
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Color the vertices on the GPU by a scalar field, see `render::lut`
+    pub fn bind_scalars(&mut self, binding: ScalarBinding) -> Result<(), crate::Error> {
+        self.check_vertex_values(binding.values.len())?;
+        binding.validate()?;
+        self.colormap = Some(binding.colormap.clone());
+        self.vertex_colors = None;
+        self.scalars = Some(binding);
+        self.revision += 1;
+        Ok(())
+    }
+
+    pub fn scalars(&self) -> Option<&ScalarBinding> {
+        self.scalars.as_ref()
+    }
+
+    /// Switch the colormap of the bound scalars without re-uploading them
+    pub fn set_scalar_colormap(&mut self, name: &str) -> Result<(), crate::Error> {
+        let binding = self.scalars.as_mut()
+            .ok_or_else(|| crate::Error::Render(format!("Object '{}' has no scalars bound", self.name)))?;
+        binding.colormap = name.to_string();
+        self.colormap = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Change the range of the bound scalars without re-uploading them
+    pub fn set_scalar_range(&mut self, range: [f32; 2], log_scale: bool) -> Result<(), crate::Error> {
+        let binding = self.scalars.as_ref()
+            .ok_or_else(|| crate::Error::Render(format!("Object '{}' has no scalars bound", self.name)))?;
+        let binding = binding.clone().with_range(range).with_log_scale(log_scale);
+        binding.validate()?;
+        self.scalars = Some(binding);
+        Ok(())
+    }
+
+    /// Draw a texture on the geometry, see `render::texture`
+    pub fn bind_texture(&mut self, texture: SceneTexture) -> Result<(), crate::Error> {
+        self.check_vertex_values(texture.texcoords.len())?;
+        texture.validate()?;
+        self.texture = Some(texture);
+        self.revision += 1;
+        Ok(())
+    }
+
+    pub fn texture(&self) -> Option<&SceneTexture> {
+        self.texture.as_ref()
+    }
+
+    /// Switch between nearest and linear filtering of the bound texture
+    pub fn set_texture_filter(&mut self, filter: TextureFilter) -> Result<(), crate::Error> {
+        let texture = self.texture.as_mut()
+            .ok_or_else(|| crate::Error::Render(format!("Object '{}' has no texture bound", self.name)))?;
+        texture.filter = filter;
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Per-vertex colors for backends without a GPU, baked from bound scalars if needed
+    pub fn resolved_colors(&self) -> Option<Cow<'_, [[f32; 4]]>> {
+        if let Some(colors) = &self.vertex_colors {
+            return Some(Cow::Borrowed(colors));
+        }
+        let binding = self.scalars.as_ref()?;
+        let map = ColorMapLibrary::global().get(&binding.colormap)?;
+        Some(Cow::Owned(binding.bake(&map)))
+    }
+}
+
+/// Geometry types
+#[derive(Debug, Clone)]
+pub enum Geometry {
+    Points { positions: Vec<nalgebra::Vector3<f32>> },
+    Lines { positions: Vec<nalgebra::Vector3<f32>>, indices: Vec<u32> },
+    Triangles { positions: Vec<nalgebra::Vector3<f32>>, indices: Vec<u32> },
+    Custom { data: Vec<u8> },
+}
+
+/// Material properties
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Material {
+    pub color: nalgebra::Vector4<f32>,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Material {
+    pub fn new(color: nalgebra::Vector4<f32>) -> Self {
+        Self {
+            color,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.color.w = opacity;
+        self
+    }
+
+    pub fn with_metallic(mut self, metallic: f32) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            color: nalgebra::Vector4::new(1.0, 1.0, 1.0, 1.0),
+            metallic: 0.0,
+            roughness: 0.5,
+        }
+    }
+}
+
+/// Light sources
+///
+/// `position` is the direction the light comes from for directional lights.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Light {
+    pub position: nalgebra::Vector3<f32>,
+    pub color: nalgebra::Vector3<f32>,
+    pub intensity: f32,
+    pub light_type: LightType,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            position: nalgebra::Vector3::new(10.0, 10.0, 10.0),
+            color: nalgebra::Vector3::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+            light_type: LightType::Directional,
+        }
+    }
+}
+
+impl Light {
+    pub fn directional(from: nalgebra::Vector3<f32>) -> Self {
+        Self {
+            position: from,
+            light_type: LightType::Directional,
+            ..Self::default()
+        }
+    }
+
+    pub fn point(position: nalgebra::Vector3<f32>) -> Self {
+        Self {
+            position,
+            light_type: LightType::Point,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_color(mut self, color: nalgebra::Vector3<f32>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LightType {
+    Directional,
+    Point,
+    Spot,
+}
+
+/// Renderer trait for different rendering backends
+#[async_trait::async_trait]
+pub trait Renderer: Send + Sync {
+    async fn render(&mut self, scene: &Scene, _target: &RenderTarget) -> Result<(), crate::Error>;
+    fn context(&self) -> &RenderContext;
+}
+
+/// WGPU-based renderer
+///
+/// Recovers from a lost device on the next frame, see `recover`. Contexts
+/// without a device, such as the CPU backend, draw through `Rasterizer`.
+pub struct WgpuRenderer {
+    context: Arc<RenderContext>,
+    pipeline: RenderPipeline,
+    cache: GpuResourceCache,
+    settings: RenderSettings,
+    events: tokio::sync::broadcast::Sender<RenderEvent>,
+    max_recovery_attempts: u32,
+    /// Image of the last frame drawn on the CPU
+    frame: Option<image::RgbaImage>,
+}
+
+/// Pipeline drawing scene objects with `SCALAR_LUT_SHADER`
+const OBJECT_PIPELINE: &str = "scalar_lut";
+
+impl WgpuRenderer {
+    pub async fn new() -> Result<Self, crate::Error> {
+        let context = Arc::new(RenderContext::new(RenderBackend::Wgpu).await?);
+        Ok(Self::with_context(context))
+    }
+
+    /// Renderer on an existing context, e.g. one from a `RenderContextPool`
+    pub fn with_context(context: Arc<RenderContext>) -> Self {
+        let mut pipeline = RenderPipeline::new(context.clone());
+        // Shader errors surface through the device's error handler, not here
+        let _ = pipeline.add_shader(OBJECT_PIPELINE, SCALAR_LUT_SHADER);
+        let _ = pipeline.create_pipeline(OBJECT_PIPELINE, PipelineConfig {
+            vertex_shader: OBJECT_PIPELINE.to_string(),
+            fragment_shader: OBJECT_PIPELINE.to_string(),
+            vertex_layout: wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32].to_vec(),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            // Capped clipping draws the back faces seen through the cut
+            cull_mode: None,
+        });
+        Self {
+            context,
+            pipeline,
+            cache: GpuResourceCache::new(),
+            settings: RenderSettings::default(),
+            events: tokio::sync::broadcast::channel(16).0,
+            max_recovery_attempts: MAX_RECOVERY_ATTEMPTS,
+            frame: None,
+        }
+    }
+
+    /// Image of the last frame if it was drawn on the CPU
+    pub fn frame(&self) -> Option<&image::RgbaImage> {
+        self.frame.as_ref()
+    }
+
+    /// Device recreation attempts before falling back to the CPU backend
+    pub fn with_max_recovery_attempts(mut self, attempts: u32) -> Self {
+        self.max_recovery_attempts = attempts.max(1);
+        self
+    }
+
+    /// Device loss, restoration and CPU fallback events
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<RenderEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: RenderEvent) {
+        tracing::info!("{}", event);
+        let _ = self.events.send(event);
+    }
+
+    /// Replace a lost context with a new one on the same adapter
+    ///
+    /// Shaders and pipelines are rebuilt from their retained sources and the
+    /// buffer cache is dropped, so the next frame re-uploads the scene. If
+    /// every attempt fails the renderer continues on the CPU backend, which
+    /// keeps snapshot output working without a GPU.
+    pub async fn recover(&mut self) -> Result<(), crate::Error> {
+        let adapter = self.context.adapter().map(|a| a.to_string());
+        self.emit(RenderEvent::DeviceLost { adapter });
+        // Buffers of the old device cannot be destroyed through it any more
+        self.cache.invalidate();
+
+        let mut error = String::new();
+        for attempt in 1..=self.max_recovery_attempts {
+            tokio::time::sleep(RECOVERY_BACKOFF * 2u32.pow(attempt - 1)).await;
+            match self.context.recreate().await {
+                Ok(context) => {
+                    let context = Arc::new(context);
+                    self.pipeline.rebuild(context.clone())?;
+                    self.context = context;
+                    let adapter = self.context.adapter().map(|a| a.to_string());
+                    self.emit(RenderEvent::DeviceRestored { adapter, attempts: attempt });
+                    return Ok(());
+                }
+                Err(e) => {
+                    tracing::warn!("Recreating the render context failed (attempt {}): {}", attempt, e);
+                    error = e.to_string();
+                }
+            }
+        }
+
+        let context = Arc::new(RenderContext::new(RenderBackend::Cpu).await?);
+        self.pipeline.rebuild(context.clone())?;
+        self.context = context;
+        self.emit(RenderEvent::FellBackToCpu { attempts: self.max_recovery_attempts, error });
+        Ok(())
+    }
+
+    pub fn with_settings(mut self, settings: RenderSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Hits, misses, evictions and resident bytes of the GPU buffer cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Release all cached GPU buffers
+    pub fn flush(&mut self) {
+        self.cache.flush();
+    }
+}
+
+#[async_trait::async_trait]
+impl Renderer for WgpuRenderer {
+    async fn render(&mut self, scene: &Scene, target: &RenderTarget) -> Result<(), crate::Error> {
+        if let Some(config) = self.pipeline.config(OBJECT_PIPELINE) {
+            for object in scene.objects() {
+                object.clipping(&self.settings).check_pipeline(OBJECT_PIPELINE, config)?;
+            }
+        }
+        if self.context.is_lost() {
+            self.recover().await?;
+        }
+        if let (Some(device), Some(queue)) = (self.context.device(), self.context.queue()) {
+            self.cache.prepare(device, queue, scene, &self.settings);
+            // Surfaces errors of the uploads, including a device lost meanwhile
+            device.poll(wgpu::Maintain::Poll);
+        }
+        if self.context.is_lost() {
+            self.recover().await?;
+            if let (Some(device), Some(queue)) = (self.context.device(), self.context.queue()) {
+                self.cache.prepare(device, queue, scene, &self.settings);
+            }
+        }
+
+        // Opaque pass with depth writes, then transparent objects without
+        let order = draw_order(scene, self.settings.transparency);
+        tracing::debug!(
+            "Drawing {} opaque and {} transparent objects ({:?})",
+            order.opaque.len(), order.transparent.len(), self.settings.transparency
+        );
+
+        if self.context.device().is_none() {
+            self.frame = Some(Rasterizer::new(target.width, target.height).render(scene, &self.settings));
+            return Ok(());
+        }
+
+        // Rendering logic would go here
+        // This is a placeholder implementation
+        tracing::info!("Rendering scene with {} objects", scene.objects().len());
+        Ok(())
+    }
+
+    fn context(&self) -> &RenderContext {
+        &self.context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn lost_cpu_renderer() -> WgpuRenderer {
+        let context = Arc::new(RenderContext::new(RenderBackend::Cpu).await.unwrap());
+        context.mark_lost();
+        WgpuRenderer::with_context(context)
+    }
+
+    #[tokio::test]
+    async fn rendering_on_a_lost_context_recreates_it() {
+        let mut renderer = lost_cpu_renderer().await;
+        let mut events = renderer.subscribe();
+        let target = RenderTarget { width: 64, height: 64, format: wgpu::TextureFormat::Rgba8Unorm };
+
+        renderer.render(&Scene::new(Camera::default()), &target).await.unwrap();
+
+        assert_eq!(events.try_recv().unwrap(), RenderEvent::DeviceLost { adapter: None });
+        assert_eq!(events.try_recv().unwrap(), RenderEvent::DeviceRestored { adapter: None, attempts: 1 });
+        assert!(events.try_recv().is_err());
+        assert!(!renderer.context().is_lost());
+    }
+
+    #[tokio::test]
+    async fn recovery_keeps_shader_sources_and_drops_cached_buffers() {
+        let mut renderer = lost_cpu_renderer().await;
+        renderer.recover().await.unwrap();
+
+        assert!(!renderer.context().is_lost());
+        assert!(renderer.pipeline.sources.contains_key("scalar_lut"));
+        assert_eq!(renderer.cache_stats().resident_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn a_healthy_context_renders_without_recovery() {
+        let context = Arc::new(RenderContext::new(RenderBackend::Cpu).await.unwrap());
+        let mut renderer = WgpuRenderer::with_context(context.clone());
+        let mut events = renderer.subscribe();
+        let target = RenderTarget { width: 64, height: 64, format: wgpu::TextureFormat::Rgba8Unorm };
+
+        renderer.render(&Scene::new(Camera::default()), &target).await.unwrap();
+
+        assert!(events.try_recv().is_err());
+        assert!(Arc::ptr_eq(&renderer.context, &context));
+    }
+
+    #[tokio::test]
+    async fn contexts_without_a_device_draw_on_the_cpu() {
+        let context = Arc::new(RenderContext::new(RenderBackend::Cpu).await.unwrap());
+        let mut renderer = WgpuRenderer::with_context(context);
+        let target = RenderTarget { width: 16, height: 8, format: wgpu::TextureFormat::Rgba8Unorm };
+        let triangle = Geometry::Triangles {
+            positions: vec![
+                nalgebra::Vector3::new(-2.0, -2.0, 0.0),
+                nalgebra::Vector3::new(2.0, -2.0, 0.0),
+                nalgebra::Vector3::new(0.0, 2.0, 0.0),
+            ],
+            indices: vec![0, 1, 2],
+        };
+        let scene = Scene::new(Camera::new(2.0))
+            .with_object(SceneObject::new(triangle, Material::new(nalgebra::Vector4::new(0.0, 1.0, 0.0, 1.0))));
+
+        renderer.render(&scene, &target).await.unwrap();
+
+        let frame = renderer.frame().unwrap();
+        assert_eq!(frame.dimensions(), (16, 8));
+        assert_eq!(frame.get_pixel(8, 4).0, [0, 255, 0, 255]);
+    }
+
+    #[tokio::test]
+    async fn capped_objects_are_refused_by_culling_pipelines() {
+        let context = Arc::new(RenderContext::new(RenderBackend::Cpu).await.unwrap());
+        let mut renderer = WgpuRenderer::with_context(context)
+            .with_settings(RenderSettings::default().with_clipping(ClipPlanes::default().with_capping(ClipCapping::Backfaces)));
+        let target = RenderTarget { width: 4, height: 4, format: wgpu::TextureFormat::Rgba8Unorm };
+        let scene = Scene::new(Camera::default())
+            .with_object(SceneObject::new(Geometry::Points { positions: vec![] }, Material::default()));
+
+        renderer.render(&scene, &target).await.unwrap();
+
+        let mut config = renderer.pipeline.config(OBJECT_PIPELINE).unwrap().clone();
+        config.cull_mode = Some(wgpu::Face::Back);
+        renderer.pipeline.create_pipeline(OBJECT_PIPELINE, config).unwrap();
+        let error = renderer.render(&scene, &target).await.unwrap_err();
+        assert!(error.to_string().contains("culls Back faces"), "{}", error);
+    }
+}
@@ -0,0 +1,416 @@
+//! Several viewports of one scene in a grid, sharing a linked camera
+
+use std::path::Path;
+
+use image::{Rgba, RgbaImage};
+
+use crate::render::{
+    Camera, CategoricalPalette, ColorMapLibrary, ColorMode, Renderer, RenderTarget, Scene, MAX_LEGEND_SWATCHES,
+};
+
+/// Pixel rectangle of a viewport within the composited image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewportRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What one viewport shows
+#[derive(Debug, Clone)]
+pub struct Viewport {
+    pub title: String,
+    /// Names of the scene objects shown; None shows all of them
+    pub objects: Option<Vec<String>>,
+    /// Colormap from the `ColorMapLibrary` and the data range it spans
+    pub colormap: Option<(String, [f32; 2])>,
+    /// Categorical viewports show one swatch per label in the range instead of a ramp
+    pub color_mode: ColorMode,
+    pub palette: CategoricalPalette,
+}
+
+impl Viewport {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            objects: None,
+            colormap: None,
+            color_mode: ColorMode::Linear,
+            palette: CategoricalPalette::default(),
+        }
+    }
+
+    pub fn with_objects<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.objects = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn with_colormap(mut self, name: &str, range: [f32; 2]) -> Self {
+        self.colormap = Some((name.to_string(), range));
+        self
+    }
+
+    /// Colormap over `[-max_abs, max_abs]`, so zero falls on the middle of a diverging map
+    pub fn with_centered_colormap(self, name: &str, max_abs: f32) -> Self {
+        let max_abs = max_abs.abs();
+        self.with_colormap(name, [-max_abs, max_abs])
+    }
+
+    pub fn with_color_mode(mut self, mode: ColorMode) -> Self {
+        self.color_mode = mode;
+        self
+    }
+
+    pub fn with_palette(mut self, palette: CategoricalPalette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    fn shows(&self, name: &str) -> bool {
+        self.objects.as_ref().is_none_or(|names| names.iter().any(|n| n == name))
+    }
+}
+
+/// One scene rendered into N viewports laid out in a grid
+///
+/// All viewports look through the same camera, so whatever controller
+/// moves `camera_mut()` moves every view at once; only the aspect ratio is
+/// adapted to each viewport.
+#[derive(Debug, Clone)]
+pub struct MultiView {
+    width: u32,
+    height: u32,
+    /// Fixed number of columns; None picks a near-square grid
+    columns: Option<u32>,
+    viewports: Vec<Viewport>,
+    camera: Camera,
+    legends: bool,
+    background: Rgba<u8>,
+}
+
+impl MultiView {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            columns: None,
+            viewports: Vec::new(),
+            camera: Camera::new(width as f32 / height.max(1) as f32),
+            legends: true,
+            background: Rgba([255, 255, 255, 255]),
+        }
+    }
+
+    pub fn with_columns(mut self, columns: u32) -> Self {
+        self.columns = Some(columns.max(1));
+        self
+    }
+
+    pub fn with_viewport(mut self, viewport: Viewport) -> Self {
+        self.viewports.push(viewport);
+        self
+    }
+
+    pub fn with_camera(mut self, camera: Camera) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    pub fn with_legends(mut self, legends: bool) -> Self {
+        self.legends = legends;
+        self
+    }
+
+    pub fn with_background(mut self, background: [u8; 4]) -> Self {
+        self.background = Rgba(background);
+        self
+    }
+
+    /// Size of the composited image
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn viewports(&self) -> &[Viewport] {
+        &self.viewports
+    }
+
+    pub fn viewport_mut(&mut self, index: usize) -> Option<&mut Viewport> {
+        self.viewports.get_mut(index)
+    }
+
+    /// The camera shared by all viewports
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Columns and rows of the viewport grid
+    pub fn grid(&self) -> (u32, u32) {
+        let count = self.viewports.len().max(1) as u32;
+        let columns = self.columns
+            .unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+            .min(count);
+        (columns, count.div_ceil(columns))
+    }
+
+    /// Where viewport `index` lies in the composited image; the last column and row take the remainder
+    pub fn rect(&self, index: usize) -> Option<ViewportRect> {
+        if index >= self.viewports.len() {
+            return None;
+        }
+        let (columns, rows) = self.grid();
+        let (column, row) = (index as u32 % columns, index as u32 / columns);
+        let (cell_width, cell_height) = (self.width / columns, self.height / rows);
+        Some(ViewportRect {
+            x: column * cell_width,
+            y: row * cell_height,
+            width: if column + 1 == columns { self.width - column * cell_width } else { cell_width },
+            height: if row + 1 == rows { self.height - row * cell_height } else { cell_height },
+        })
+    }
+
+    /// The linked camera with the aspect ratio of viewport `index`
+    pub fn viewport_camera(&self, index: usize) -> Option<Camera> {
+        let rect = self.rect(index)?;
+        let mut camera = self.camera.clone();
+        camera.aspect_ratio = rect.width as f32 / rect.height.max(1) as f32;
+        Some(camera)
+    }
+
+    /// The objects of `scene` shown in viewport `index`, seen through the linked camera
+    pub fn viewport_scene(&self, scene: &Scene, index: usize) -> Option<Scene> {
+        let viewport = self.viewports.get(index)?;
+        let mut view = Scene::new(self.viewport_camera(index)?).with_lights(scene.lights().to_vec());
+        for object in scene.objects().iter().filter(|o| viewport.shows(&o.name)) {
+            view.add_object(object.clone());
+        }
+        Some(view)
+    }
+
+    /// Render every viewport with `renderer`, each into a target of its own size
+    pub async fn render(&self, renderer: &mut dyn Renderer, scene: &Scene, format: wgpu::TextureFormat) -> Result<(), crate::Error> {
+        for index in 0..self.viewports.len() {
+            let (Some(view), Some(rect)) = (self.viewport_scene(scene, index), self.rect(index)) else {
+                continue;
+            };
+            let target = RenderTarget { width: rect.width, height: rect.height, format };
+            renderer.render(&view, &target).await?;
+        }
+        Ok(())
+    }
+
+    /// Combine one image per viewport into the final image, with legends if enabled
+    ///
+    /// This is the image frame and snapshot output should use, so every
+    /// consumer sees the same layout.
+    pub fn composite(&self, tiles: &[RgbaImage]) -> Result<RgbaImage, crate::Error> {
+        if tiles.len() != self.viewports.len() {
+            return Err(crate::Error::Render(format!(
+                "Multi-view has {} viewports but got {} images",
+                self.viewports.len(), tiles.len()
+            )));
+        }
+        let mut image = RgbaImage::from_pixel(self.width, self.height, self.background);
+        for (index, tile) in tiles.iter().enumerate() {
+            let rect = self.rect(index).expect("index is within the viewports");
+            if tile.dimensions() != (rect.width, rect.height) {
+                return Err(crate::Error::Render(format!(
+                    "Image of viewport {} is {}x{}, expected {}x{}",
+                    index, tile.width(), tile.height(), rect.width, rect.height
+                )));
+            }
+            image::imageops::replace(&mut image, tile, rect.x as i64, rect.y as i64);
+        }
+        if self.legends {
+            self.draw_legends(&mut image);
+        }
+        Ok(image)
+    }
+
+    /// Composite the viewport images and write the result as a PNG snapshot
+    pub fn save_snapshot(&self, tiles: &[RgbaImage], path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let path = path.as_ref();
+        self.composite(tiles)?.save(path)
+            .map_err(|e| crate::Error::Render(format!("Failed to write {}: {}", path.display(), e)))
+    }
+
+    /// Draw one colormap bar into every viewport that has a colormap
+    ///
+    /// Categorical viewports get a row of swatches for the integer labels
+    /// in their range instead.
+    pub fn draw_legends(&self, image: &mut RgbaImage) {
+        let library = ColorMapLibrary::global();
+        for (index, viewport) in self.viewports.iter().enumerate() {
+            let Some((name, range)) = &viewport.colormap else {
+                continue;
+            };
+            if viewport.color_mode == ColorMode::Categorical {
+                let Some(rect) = self.rect(index) else {
+                    continue;
+                };
+                let first = range[0].ceil() as i64;
+                let last = (range[1].floor() as i64).min(first + MAX_LEGEND_SWATCHES as i64 - 1);
+                let colors: Vec<[f32; 4]> = (first..=last)
+                    .map(|label| {
+                        let [r, g, b] = viewport.palette.color(label);
+                        [r, g, b, 1.0]
+                    })
+                    .collect();
+                draw_legend_swatches(image, legend_rect(rect), &colors);
+                continue;
+            }
+            let (Some(rect), Some(colormap)) = (self.rect(index), library.get(name)) else {
+                tracing::warn!("No legend for viewport {}: unknown colormap {}", index, name);
+                continue;
+            };
+            draw_legend_bar(image, legend_rect(rect), |t| colormap.sample(t));
+        }
+    }
+}
+
+/// Horizontal bar along the bottom of a viewport, 80% of its width
+fn legend_rect(viewport: ViewportRect) -> ViewportRect {
+    let height = (viewport.height / 24).max(4).min(viewport.height);
+    let margin = viewport.height / 48 + 1;
+    let width = viewport.width * 4 / 5;
+    ViewportRect {
+        x: viewport.x + (viewport.width - width) / 2,
+        y: (viewport.y + viewport.height).saturating_sub(height + margin).max(viewport.y),
+        width,
+        height,
+    }
+}
+
+/// Fill `rect` with a left-to-right colormap ramp inside a one-pixel black frame
+///
+/// Range labels are left to the UI overlay, which has a text renderer.
+pub fn draw_legend_bar(image: &mut RgbaImage, rect: ViewportRect, sample: impl Fn(f32) -> [f32; 4]) {
+    let x_end = (rect.x + rect.width).min(image.width());
+    let y_end = (rect.y + rect.height).min(image.height());
+    for x in rect.x..x_end {
+        let t = if rect.width > 1 { (x - rect.x) as f32 / (rect.width - 1) as f32 } else { 0.5 };
+        let [r, g, b, a] = sample(t);
+        let a = a.clamp(0.0, 1.0);
+        for y in rect.y..y_end {
+            let frame = x == rect.x || x + 1 == x_end || y == rect.y || y + 1 == y_end;
+            let pixel = image.get_pixel_mut(x, y);
+            if frame {
+                *pixel = Rgba([0, 0, 0, 255]);
+                continue;
+            }
+            // Blend over what is below so transparent ends of the ramp stay visible as such
+            let blend = |c: f32, under: u8| (c.clamp(0.0, 1.0) * 255.0 * a + under as f32 * (1.0 - a)).round() as u8;
+            *pixel = Rgba([blend(r, pixel[0]), blend(g, pixel[1]), blend(b, pixel[2]), 255]);
+        }
+    }
+}
+
+/// Split `rect` into one framed swatch per color, left to right
+///
+/// Labels are left to the UI overlay, as for `draw_legend_bar`.
+pub fn draw_legend_swatches(image: &mut RgbaImage, rect: ViewportRect, colors: &[[f32; 4]]) {
+    if colors.is_empty() {
+        return;
+    }
+    let width = rect.width / colors.len() as u32;
+    if width < 3 {
+        tracing::warn!("Legend of {} pixels is too narrow for {} swatches", rect.width, colors.len());
+        return;
+    }
+    for (i, &color) in colors.iter().enumerate() {
+        let swatch = ViewportRect { x: rect.x + i as u32 * width, width, ..rect };
+        draw_legend_bar(image, swatch, |_| color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Geometry, Material, SceneObject};
+
+    fn object(name: &str) -> SceneObject {
+        SceneObject::new(Geometry::Points { positions: vec![nalgebra::Vector3::zeros()] }, Material::default()).with_name(name)
+    }
+
+    fn three_views() -> MultiView {
+        MultiView::new(101, 60)
+            .with_viewport(Viewport::new("a").with_objects(["a"]))
+            .with_viewport(Viewport::new("b").with_objects(["b"]))
+            .with_viewport(Viewport::new("difference").with_centered_colormap("Cool to Warm", -2.0))
+    }
+
+    #[test]
+    fn viewports_fill_a_near_square_grid() {
+        let view = three_views();
+        assert_eq!(view.grid(), (2, 2));
+        assert_eq!(view.rect(0), Some(ViewportRect { x: 0, y: 0, width: 50, height: 30 }));
+        // The last column takes the odd pixel
+        assert_eq!(view.rect(1), Some(ViewportRect { x: 50, y: 0, width: 51, height: 30 }));
+        assert_eq!(view.rect(2), Some(ViewportRect { x: 0, y: 30, width: 50, height: 30 }));
+        assert_eq!(view.rect(3), None);
+
+        assert_eq!(three_views().with_columns(3).grid(), (3, 1));
+        assert_eq!(MultiView::new(10, 10).grid(), (1, 1));
+    }
+
+    #[test]
+    fn viewports_share_the_camera_but_not_the_objects() {
+        let mut view = three_views();
+        view.camera_mut().position = nalgebra::Vector3::new(1.0, 2.0, 3.0);
+        let mut scene = Scene::new(Camera::default());
+        scene.add_object(object("a"));
+        scene.add_object(object("b"));
+
+        let names = |index: usize| -> Vec<String> {
+            view.viewport_scene(&scene, index).unwrap().objects().iter().map(|o| o.name.clone()).collect()
+        };
+        assert_eq!(names(0), ["a"]);
+        assert_eq!(names(1), ["b"]);
+        assert_eq!(names(2), ["a", "b"]);
+
+        let camera = view.viewport_camera(1).unwrap();
+        assert_eq!(camera.position, view.camera().position);
+        assert_eq!(camera.aspect_ratio, 51.0 / 30.0);
+        assert_eq!(view.viewports()[2].colormap, Some(("Cool to Warm".to_string(), [-2.0, 2.0])));
+    }
+
+    #[test]
+    fn tiles_are_composited_with_legends() {
+        let view = three_views();
+        let tile = |index: usize, value: u8| {
+            let rect = view.rect(index).unwrap();
+            RgbaImage::from_pixel(rect.width, rect.height, Rgba([value, value, value, 255]))
+        };
+        let image = view.composite(&[tile(0, 10), tile(1, 20), tile(2, 30)]).unwrap();
+        assert_eq!(image.dimensions(), (101, 60));
+        assert_eq!(image.get_pixel(5, 5), &Rgba([10, 10, 10, 255]));
+        assert_eq!(image.get_pixel(95, 5), &Rgba([20, 20, 20, 255]));
+        // The empty grid cell shows the background
+        assert_eq!(image.get_pixel(95, 45), &Rgba([255, 255, 255, 255]));
+
+        // Only the viewport with a colormap gets a framed legend
+        let legend = legend_rect(view.rect(2).unwrap());
+        assert_eq!(image.get_pixel(legend.x, legend.y), &Rgba([0, 0, 0, 255]));
+        assert_ne!(image.get_pixel(legend.x + legend.width / 2, legend.y + legend.height / 2), &Rgba([30, 30, 30, 255]));
+        let unlabeled = legend_rect(view.rect(0).unwrap());
+        assert_eq!(image.get_pixel(unlabeled.x, unlabeled.y), &Rgba([10, 10, 10, 255]));
+    }
+
+    #[test]
+    fn tiles_must_match_their_viewports() {
+        let view = three_views();
+        let tile = RgbaImage::new(50, 30);
+        assert!(matches!(view.composite(&[tile.clone(), tile.clone()]), Err(crate::Error::Render(_))));
+        // Viewport 1 is a pixel wider
+        assert!(matches!(view.composite(&[tile.clone(), tile.clone(), tile]), Err(crate::Error::Render(_))));
+    }
+}
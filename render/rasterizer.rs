@@ -0,0 +1,302 @@
+//! CPU rasterizer for contexts without a GPU and for reference images
+//!
+//! Draws the triangles of a scene unlit in their material or vertex colors,
+//! with a depth buffer and the clipping and capping of the shaders: fragments
+//! outside `ClipPlanes::keeps` are dropped and, with `ClipCapping::Backfaces`,
+//! back faces are filled with the cap color. Points, lines and custom
+//! geometry are skipped, as are triangles reaching behind the camera.
+//! Transparent objects are drawn like opaque ones.
+
+use image::{Rgba, RgbaImage};
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+
+use super::{ClipCapping, Geometry, RenderSettings, Scene, SceneObject};
+
+/// Clip-space `w` below which a vertex counts as behind the camera
+const MIN_W: f32 = 1e-6;
+
+/// Software rasterizer producing RGBA8 images
+#[derive(Debug, Clone)]
+pub struct Rasterizer {
+    width: u32,
+    height: u32,
+    /// Linear RGBA of pixels no triangle covers
+    background: [f32; 4],
+}
+
+/// Vertex of a triangle after the transforms
+#[derive(Debug, Clone, Copy)]
+struct RasterVertex {
+    world: Vector3<f32>,
+    screen: Vector2<f32>,
+    /// Depth in wgpu's 0 to 1 range
+    depth: f32,
+    /// Reciprocal of clip-space `w`, for perspective-correct interpolation
+    inv_w: f32,
+    color: Vector4<f32>,
+}
+
+impl Rasterizer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            background: [0.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    pub fn with_background(mut self, color: [f32; 4]) -> Self {
+        self.background = color;
+        self
+    }
+
+    /// Draw the visible objects of `scene` as seen by its camera
+    pub fn render(&self, scene: &Scene, settings: &RenderSettings) -> RgbaImage {
+        let camera = scene.camera();
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        let pixels = self.width as usize * self.height as usize;
+        let mut color = vec![Vector4::from(self.background); pixels];
+        let mut depth = vec![f32::INFINITY; pixels];
+
+        for object in scene.objects().iter().filter(|o| o.visible) {
+            if let Geometry::Triangles { positions, indices } = &object.geometry {
+                let vertices: Vec<Option<RasterVertex>> = (0..positions.len())
+                    .map(|i| self.vertex(object, &view_proj, positions, i))
+                    .collect();
+                for triangle in indices.chunks_exact(3) {
+                    let corners = [triangle[0], triangle[1], triangle[2]]
+                        .map(|i| vertices.get(i as usize).copied().flatten());
+                    if let [Some(a), Some(b), Some(c)] = corners {
+                        self.triangle(object, settings, [a, b, c], &mut color, &mut depth);
+                    }
+                }
+            }
+        }
+
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let c = color[(y * self.width + x) as usize];
+            Rgba([c.x, c.y, c.z, c.w].map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8))
+        })
+    }
+
+    fn vertex(&self, object: &SceneObject, view_proj: &Matrix4<f32>, positions: &[Vector3<f32>], i: usize) -> Option<RasterVertex> {
+        let world = object.transform * positions[i].push(1.0);
+        let world = world.xyz() / world.w;
+        let clip = view_proj * world.push(1.0);
+        if clip.w < MIN_W {
+            return None;
+        }
+        let ndc = clip.xyz() / clip.w;
+        let color = object.vertex_colors.as_ref()
+            .and_then(|colors| colors.get(i))
+            .map(|&c| Vector4::from(c))
+            .unwrap_or(object.material.color);
+        Some(RasterVertex {
+            world,
+            screen: Vector2::new(
+                (ndc.x + 1.0) * 0.5 * self.width as f32,
+                (1.0 - ndc.y) * 0.5 * self.height as f32,
+            ),
+            depth: ndc.z,
+            inv_w: 1.0 / clip.w,
+            color,
+        })
+    }
+
+    fn triangle(
+        &self,
+        object: &SceneObject,
+        settings: &RenderSettings,
+        [a, b, c]: [RasterVertex; 3],
+        color: &mut [Vector4<f32>],
+        depth: &mut [f32],
+    ) {
+        let area = edge(a.screen, b.screen, c.screen);
+        if area == 0.0 {
+            return;
+        }
+        // Screen y points down, so counter-clockwise front faces have negative area
+        let front_facing = area < 0.0;
+        let clipping = object.clipping(settings);
+        let capped = !front_facing && clipping.capping == ClipCapping::Backfaces;
+
+        let min = a.screen.inf(&b.screen).inf(&c.screen);
+        let max = a.screen.sup(&b.screen).sup(&c.screen);
+        let x0 = min.x.floor().max(0.0) as u32;
+        let y0 = min.y.floor().max(0.0) as u32;
+        let x1 = (max.x.ceil().max(0.0) as u32).min(self.width);
+        let y1 = (max.y.ceil().max(0.0) as u32).min(self.height);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let weights = [edge(b.screen, c.screen, p), edge(c.screen, a.screen, p), edge(a.screen, b.screen, p)]
+                    .map(|w| w / area);
+                if weights.iter().any(|&w| w < 0.0) {
+                    continue;
+                }
+                let z = weights[0] * a.depth + weights[1] * b.depth + weights[2] * c.depth;
+                let index = (y * self.width + x) as usize;
+                // wgpu clips depth to 0..1
+                if !(0.0..=1.0).contains(&z) || z >= depth[index] {
+                    continue;
+                }
+
+                let perspective = [weights[0] * a.inv_w, weights[1] * b.inv_w, weights[2] * c.inv_w];
+                let sum: f32 = perspective.iter().sum();
+                let [wa, wb, wc] = perspective.map(|w| w / sum);
+                let world = a.world * wa + b.world * wb + c.world * wc;
+                if !clipping.keeps(&world) {
+                    continue;
+                }
+
+                depth[index] = z;
+                color[index] = if capped {
+                    Vector4::from(clipping.cap_color)
+                } else {
+                    a.color * wa + b.color * wb + c.color * wc
+                };
+            }
+        }
+    }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p`
+fn edge(a: Vector2<f32>, b: Vector2<f32>, p: Vector2<f32>) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::testing::{compare_images, Tolerance};
+    use crate::render::{Camera, ClipPlane, ClipPlanes, Material};
+
+    const SIZE: u32 = 64;
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const CAP: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+    /// Unit sphere with outward, counter-clockwise triangles
+    fn sphere(rings: u32, segments: u32) -> Geometry {
+        let mut positions = Vec::new();
+        for ring in 0..=rings {
+            let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+            for segment in 0..segments {
+                let phi = std::f32::consts::TAU * segment as f32 / segments as f32;
+                positions.push(Vector3::new(theta.sin() * phi.cos(), theta.cos(), -theta.sin() * phi.sin()));
+            }
+        }
+        let mut indices = Vec::new();
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let next = (segment + 1) % segments;
+                let quad = [ring * segments + segment, ring * segments + next, (ring + 1) * segments + next, (ring + 1) * segments + segment];
+                for [i, j, k] in [[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]] {
+                    let [p, q, r] = [i, j, k].map(|i| positions[i as usize]);
+                    let normal = (q - p).cross(&(r - p));
+                    if normal.norm() < 1e-9 {
+                        continue;
+                    }
+                    if normal.dot(&(p + q + r)) > 0.0 {
+                        indices.extend([i, j, k]);
+                    } else {
+                        indices.extend([i, k, j]);
+                    }
+                }
+            }
+        }
+        Geometry::Triangles { positions, indices }
+    }
+
+    fn scene(clipping: ClipPlanes) -> (Scene, RenderSettings) {
+        let object = SceneObject::new(sphere(48, 96), Material::new(Vector4::from(RED)));
+        let scene = Scene::new(Camera::default()).with_object(object);
+        (scene, RenderSettings::default().with_clipping(clipping))
+    }
+
+    /// Pixels a ray cast at the exact sphere shows: the first kept hit, in
+    /// the cap color if it is on the inside and capping is on
+    fn reference(camera: &Camera, clipping: &ClipPlanes) -> RgbaImage {
+        let inverse = (camera.projection_matrix() * camera.view_matrix()).try_inverse().unwrap();
+        RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+            let ndc = Vector4::new(
+                (x as f32 + 0.5) / SIZE as f32 * 2.0 - 1.0,
+                1.0 - (y as f32 + 0.5) / SIZE as f32 * 2.0,
+                0.5,
+                1.0,
+            );
+            let point = inverse * ndc;
+            let direction = (point.xyz() / point.w - camera.position).normalize();
+            // |o + t d| = 1
+            let b = camera.position.dot(&direction);
+            let c = camera.position.norm_squared() - 1.0;
+            let discriminant = b * b - c;
+            let color = if discriminant < 0.0 {
+                [0.0; 4]
+            } else {
+                let hits = [-b - discriminant.sqrt(), -b + discriminant.sqrt()];
+                match hits.iter().position(|&t| clipping.keeps(&(camera.position + direction * t))) {
+                    Some(0) => RED,
+                    Some(_) if clipping.capping == ClipCapping::Backfaces => CAP,
+                    Some(_) => RED,
+                    None => [0.0; 4],
+                }
+            };
+            Rgba(color.map(|v| (v * 255.0) as u8))
+        })
+    }
+
+    fn assert_matches_reference(clipping: ClipPlanes) -> RgbaImage {
+        let (scene, settings) = scene(clipping.clone());
+        let image = Rasterizer::new(SIZE, SIZE).render(&scene, &settings);
+        let expected = reference(scene.camera(), &clipping);
+        // The tessellated silhouette deviates from the exact one along its edge
+        let tolerance = Tolerance::default().with_max_differing_pixels(SIZE as usize);
+        let diff = compare_images(&image, &expected, &tolerance).unwrap();
+        assert!(diff.within(&tolerance), "{}", diff);
+        image
+    }
+
+    fn pixel(image: &RgbaImage, x: u32, y: u32) -> [u8; 4] {
+        image.get_pixel(x, y).0
+    }
+
+    #[test]
+    fn an_unclipped_sphere_shows_a_disc() {
+        let image = assert_matches_reference(ClipPlanes::default().with_capping(ClipCapping::Backfaces));
+        assert_eq!(pixel(&image, SIZE / 2, SIZE / 2), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 0, 0), [0; 4]);
+        // No back face shows through a closed surface
+        assert!(!image.pixels().any(|p| p.0 == [0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn a_plane_through_the_center_cuts_the_silhouette_in_half() {
+        let clipping = ClipPlanes::new(vec![ClipPlane::new(Vector3::x(), 0.0)]);
+        let image = assert_matches_reference(clipping);
+        assert_eq!(pixel(&image, SIZE / 2 + 8, SIZE / 2), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, SIZE / 2 - 8, SIZE / 2), [0; 4]);
+    }
+
+    #[test]
+    fn capping_fills_the_cut_facing_the_camera() {
+        // Keep the far half, so the cut faces the camera
+        let plane = ClipPlane::new(-Vector3::z(), 0.0);
+
+        let image = assert_matches_reference(ClipPlanes::new(vec![plane]));
+        assert_eq!(pixel(&image, SIZE / 2, SIZE / 2), [255, 0, 0, 255]);
+
+        let image = assert_matches_reference(ClipPlanes::new(vec![plane]).with_capping(ClipCapping::Backfaces).with_cap_color(CAP));
+        assert_eq!(pixel(&image, SIZE / 2, SIZE / 2), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn disabled_and_degenerate_planes_clip_nothing() {
+        let clipping = ClipPlanes::new(vec![
+            ClipPlane::new(Vector3::x(), -10.0).disabled(),
+            ClipPlane { normal: Vector3::zeros(), distance: -1.0, enabled: true },
+        ]);
+        let image = assert_matches_reference(clipping);
+        assert_eq!(pixel(&image, SIZE / 2 - 8, SIZE / 2), [255, 0, 0, 255]);
+    }
+}
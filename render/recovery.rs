@@ -0,0 +1,84 @@
+//! Detection of and recovery from lost GPU devices
+//!
+//! Drivers reset the GPU on updates or suspend/resume, after which every
+//! call on the old `wgpu::Device` fails. A `RenderContext` notices through
+//! its uncaptured-error handler and is marked lost; the renderer then
+//! recreates the context on the same adapter and re-uploads the scene,
+//! whose CPU-side objects are the source of every GPU buffer.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Recreation attempts before a renderer falls back to the CPU backend
+pub const MAX_RECOVERY_ATTEMPTS: u32 = 3;
+
+/// Wait before the first recreation attempt; doubled for each further one
+pub const RECOVERY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Notable changes of a renderer's GPU state, for the UI to show
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RenderEvent {
+    /// The device stopped working; recreation is under way
+    DeviceLost { adapter: Option<String> },
+    /// A new device is in use and the scene will be re-uploaded on the next frame
+    DeviceRestored { adapter: Option<String>, attempts: u32 },
+    /// Recreation kept failing; rendering continues on the CPU backend
+    FellBackToCpu { attempts: u32, error: String },
+}
+
+impl std::fmt::Display for RenderEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let adapter = |adapter: &Option<String>| adapter.clone().unwrap_or_else(|| "GPU".to_string());
+        match self {
+            RenderEvent::DeviceLost { adapter: a } => write!(f, "Lost {}, recreating the device", adapter(a)),
+            RenderEvent::DeviceRestored { adapter: a, attempts } => {
+                write!(f, "Restored rendering on {} after {} attempt(s)", adapter(a), attempts)
+            }
+            RenderEvent::FellBackToCpu { attempts, error } => {
+                write!(f, "GPU unavailable after {} attempts ({}); rendering on the CPU", attempts, error)
+            }
+        }
+    }
+}
+
+/// Whether an error reported by wgpu means the device itself is gone
+///
+/// wgpu 0.18 has no device-lost callback; calls on a lost device fail with
+/// validation errors naming the lost or invalid parent device.
+pub fn is_device_loss(error: &wgpu::Error) -> bool {
+    match error {
+        wgpu::Error::Validation { description, .. } => {
+            let description = description.to_lowercase();
+            description.contains("device is lost") || description.contains("device is invalid")
+        }
+        wgpu::Error::OutOfMemory { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validation(description: &str) -> wgpu::Error {
+        wgpu::Error::Validation { source: Box::new(std::fmt::Error), description: description.into() }
+    }
+
+    #[test]
+    fn lost_and_invalid_devices_count_as_device_loss() {
+        assert!(is_device_loss(&validation("Parent device is lost")));
+        assert!(is_device_loss(&validation("Device is INVALID")));
+        assert!(!is_device_loss(&validation("Buffer is too small for the copy")));
+        assert!(!is_device_loss(&wgpu::Error::OutOfMemory { source: Box::new(std::fmt::Error) }));
+    }
+
+    #[test]
+    fn events_name_the_adapter_or_fall_back_to_gpu() {
+        let lost = RenderEvent::DeviceLost { adapter: Some("RTX 4090 (Vulkan)".to_string()) };
+        assert_eq!(lost.to_string(), "Lost RTX 4090 (Vulkan), recreating the device");
+        let restored = RenderEvent::DeviceRestored { adapter: None, attempts: 2 };
+        assert_eq!(restored.to_string(), "Restored rendering on GPU after 2 attempt(s)");
+        let fallback = RenderEvent::FellBackToCpu { attempts: 3, error: "no adapter".to_string() };
+        assert_eq!(fallback.to_string(), "GPU unavailable after 3 attempts (no adapter); rendering on the CPU");
+    }
+}
@@ -0,0 +1,253 @@
+//! Image differencing and golden-image checks for rendered output
+
+use std::path::Path;
+
+use image::{Rgba, RgbaImage};
+
+/// Environment variable that makes `assert_matches_golden` (re)write golden images
+pub const UPDATE_GOLDENS_ENV: &str = "UPDATE_GOLDENS";
+
+/// Allowed deviation when comparing images
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tolerance {
+    /// Largest per-channel difference (0-255) that still counts as equal
+    pub channel: u8,
+    /// Number of pixels allowed to exceed the channel tolerance
+    pub max_differing_pixels: usize,
+    pub ignore_alpha: bool,
+}
+
+impl Tolerance {
+    pub fn channel(delta: u8) -> Self {
+        Self {
+            channel: delta,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_differing_pixels(mut self, count: usize) -> Self {
+        self.max_differing_pixels = count;
+        self
+    }
+
+    pub fn ignoring_alpha(mut self) -> Self {
+        self.ignore_alpha = true;
+        self
+    }
+}
+
+/// Result of comparing two images
+#[derive(Debug, Clone)]
+pub struct ImageDiff {
+    pub max_channel_delta: u8,
+    /// Root mean square over all compared channels, in 0-255 units
+    pub rms: f64,
+    /// Pixels with at least one channel above the tolerance
+    pub differing_pixels: usize,
+    /// Per-pixel maximum channel delta, scaled to a black-red-yellow ramp
+    pub heatmap: RgbaImage,
+}
+
+impl ImageDiff {
+    /// Check the diff against a tolerance
+    pub fn within(&self, tolerance: &Tolerance) -> bool {
+        self.differing_pixels <= tolerance.max_differing_pixels
+    }
+
+    /// Write the heatmap as a PNG
+    pub fn write_heatmap(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        self.heatmap.save(path.as_ref())
+            .map_err(|e| crate::Error::Render(format!("Failed to write {}: {}", path.as_ref().display(), e)))
+    }
+}
+
+impl std::fmt::Display for ImageDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} differing pixels, max channel delta {}, rms {:.3}",
+            self.differing_pixels, self.max_channel_delta, self.rms
+        )
+    }
+}
+
+fn heat(delta: u8) -> Rgba<u8> {
+    // Black for equal, through red, to yellow for the largest differences
+    let red = delta.saturating_mul(2);
+    let green = delta.saturating_sub(128).saturating_mul(2);
+    Rgba([red, green, 0, 255])
+}
+
+/// Compare two images channel by channel
+///
+/// Images of different dimensions cannot be compared and produce an error.
+pub fn compare_images(a: &RgbaImage, b: &RgbaImage, tolerance: &Tolerance) -> Result<ImageDiff, crate::Error> {
+    if a.dimensions() != b.dimensions() {
+        return Err(crate::Error::Render(format!(
+            "Image dimensions differ: {}x{} vs {}x{}",
+            a.width(), a.height(), b.width(), b.height()
+        )));
+    }
+
+    let channels = if tolerance.ignore_alpha { 3 } else { 4 };
+    let mut heatmap = RgbaImage::new(a.width(), a.height());
+    let mut max_channel_delta = 0u8;
+    let mut differing_pixels = 0;
+    let mut sum_squares = 0.0f64;
+
+    for ((pa, pb), out) in a.pixels().zip(b.pixels()).zip(heatmap.pixels_mut()) {
+        let mut pixel_delta = 0u8;
+        for c in 0..channels {
+            let delta = pa[c].abs_diff(pb[c]);
+            pixel_delta = pixel_delta.max(delta);
+            sum_squares += (delta as f64).powi(2);
+        }
+        max_channel_delta = max_channel_delta.max(pixel_delta);
+        if pixel_delta > tolerance.channel {
+            differing_pixels += 1;
+        }
+        *out = heat(pixel_delta);
+    }
+
+    let samples = a.width() as f64 * a.height() as f64 * channels as f64;
+    let rms = if samples > 0.0 { (sum_squares / samples).sqrt() } else { 0.0 };
+
+    Ok(ImageDiff {
+        max_channel_delta,
+        rms,
+        differing_pixels,
+        heatmap,
+    })
+}
+
+/// Load an image from disk as RGBA
+pub fn load_image(path: impl AsRef<Path>) -> Result<RgbaImage, crate::Error> {
+    image::open(path.as_ref())
+        .map(|i| i.to_rgba8())
+        .map_err(|e| crate::Error::Render(format!("Failed to load {}: {}", path.as_ref().display(), e)))
+}
+
+/// Panic unless an image matches its golden reference
+///
+/// With `UPDATE_GOLDENS=1` the golden is written (or overwritten) instead.
+/// On mismatch a `<golden>.diff.png` heatmap is written next to the golden.
+pub fn assert_matches_golden(image: &RgbaImage, golden_path: impl AsRef<Path>, tolerance: &Tolerance) {
+    let golden_path = golden_path.as_ref();
+
+    if std::env::var(UPDATE_GOLDENS_ENV).map(|v| v == "1").unwrap_or(false) {
+        if let Some(parent) = golden_path.parent() {
+            std::fs::create_dir_all(parent)
+                .unwrap_or_else(|e| panic!("Failed to create {}: {}", parent.display(), e));
+        }
+        image.save(golden_path)
+            .unwrap_or_else(|e| panic!("Failed to write golden {}: {}", golden_path.display(), e));
+        return;
+    }
+
+    if !golden_path.exists() {
+        panic!(
+            "Golden image {} does not exist; rerun with {}=1 to create it",
+            golden_path.display(), UPDATE_GOLDENS_ENV
+        );
+    }
+
+    let golden = load_image(golden_path).unwrap_or_else(|e| panic!("{}", e));
+    let diff = compare_images(image, &golden, tolerance)
+        .unwrap_or_else(|e| panic!("Cannot compare against {}: {}", golden_path.display(), e));
+
+    if !diff.within(tolerance) {
+        let diff_path = golden_path.with_extension("diff.png");
+        let note = match diff.write_heatmap(&diff_path) {
+            Ok(()) => format!("diff written to {}", diff_path.display()),
+            Err(e) => format!("failed to write diff: {}", e),
+        };
+        panic!("Image does not match golden {}: {} ({})", golden_path.display(), diff, note);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba(color))
+    }
+
+    fn temp_golden() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("vistle_golden_{}", uuid::Uuid::new_v4().simple())).join("image.png")
+    }
+
+    #[test]
+    fn identical_images_do_not_differ() {
+        let image = solid(4, 4, [10, 20, 30, 255]);
+        let diff = compare_images(&image, &image, &Tolerance::default()).unwrap();
+        assert_eq!(diff.max_channel_delta, 0);
+        assert_eq!(diff.rms, 0.0);
+        assert_eq!(diff.differing_pixels, 0);
+        assert_eq!(diff.heatmap.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn channel_tolerance_decides_which_pixels_differ() {
+        let a = solid(2, 2, [100, 100, 100, 255]);
+        let mut b = a.clone();
+        b.put_pixel(0, 0, Rgba([103, 100, 100, 255]));
+        b.put_pixel(1, 1, Rgba([100, 110, 100, 255]));
+
+        let diff = compare_images(&a, &b, &Tolerance::channel(3)).unwrap();
+        assert_eq!(diff.max_channel_delta, 10);
+        assert_eq!(diff.differing_pixels, 1);
+        assert!(!diff.within(&Tolerance::channel(3)));
+        assert!(diff.within(&Tolerance::channel(3).with_max_differing_pixels(1)));
+        // Two deltas over 16 channels
+        assert!((diff.rms - ((9.0 + 100.0) / 16.0f64).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn alpha_can_be_ignored() {
+        let a = solid(2, 2, [0, 0, 0, 255]);
+        let b = solid(2, 2, [0, 0, 0, 0]);
+        assert_eq!(compare_images(&a, &b, &Tolerance::default()).unwrap().differing_pixels, 4);
+        let diff = compare_images(&a, &b, &Tolerance::default().ignoring_alpha()).unwrap();
+        assert_eq!(diff.differing_pixels, 0);
+        assert_eq!(diff.max_channel_delta, 0);
+    }
+
+    #[test]
+    fn differing_dimensions_are_an_error() {
+        let error = compare_images(&solid(2, 2, [0; 4]), &solid(2, 3, [0; 4]), &Tolerance::default()).unwrap_err();
+        assert!(error.to_string().contains("2x2 vs 2x3"), "{}", error);
+    }
+
+    #[test]
+    fn images_matching_their_golden_pass() {
+        let golden = temp_golden();
+        std::fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        let image = solid(3, 3, [1, 2, 3, 255]);
+        image.save(&golden).unwrap();
+
+        assert_matches_golden(&image, &golden, &Tolerance::default());
+        std::fs::remove_dir_all(golden.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn mismatches_write_a_diff_next_to_the_golden() {
+        let golden = temp_golden();
+        std::fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        solid(3, 3, [0, 0, 0, 255]).save(&golden).unwrap();
+
+        let result = std::panic::catch_unwind(|| {
+            assert_matches_golden(&solid(3, 3, [200, 0, 0, 255]), &golden, &Tolerance::default())
+        });
+        assert!(result.is_err());
+        let heatmap = load_image(golden.with_extension("diff.png")).unwrap();
+        assert_eq!(heatmap.get_pixel(1, 1), &heat(200));
+        std::fs::remove_dir_all(golden.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "does not exist")]
+    fn missing_goldens_fail_with_a_hint() {
+        assert_matches_golden(&solid(1, 1, [0; 4]), temp_golden(), &Tolerance::default());
+    }
+}
@@ -0,0 +1,299 @@
+//! Images drawn as textured quads
+//!
+//! An image becomes two triangles spanning its rectangle, with its pixels
+//! in an RGBA8 texture. Texels are sRGB-encoded and uploaded as
+//! `Rgba8UnormSrgb`, so the GPU decodes them to linear light before
+//! filtering and blending and the sRGB surface encodes the result again:
+//! 8-bit images show their file colors unchanged, and linear filtering does
+//! not darken edges. 8-bit pixels are sRGB already; f32 color pixels are
+//! linear and get encoded. Single-channel f32 images go through a colormap,
+//! whose colors are display colors like those of 8-bit files.
+//! `SceneTexture::sample` mirrors the GPU filtering for backends without a
+//! GPU and for comparisons.
+
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{ImageData, ImageView};
+use crate::render::{ColorMap, ColorMapLibrary, Geometry, Material, SceneObject};
+
+/// Shader drawing textured objects
+///
+/// Vertex attribute 0 is the position, attribute 1 the texture coordinate.
+/// The object uniforms are `GpuResourceCache`'s; the material color tints
+/// the texture and the clipping planes cut it like any other object.
+pub const IMAGE_SHADER: &str = r#"
+struct Camera {
+    view_proj: mat4x4<f32>,
+};
+
+struct Object {
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+    // Scalar coloring parameters, unused here
+    scalar: array<vec4<f32>, 2>,
+    clip_planes: array<vec4<f32>, 6>,
+    clip: vec4<f32>,
+    cap_color: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+@group(1) @binding(0) var<uniform> object: Object;
+@group(1) @binding(1) var image: texture_2d<f32>;
+@group(1) @binding(2) var image_sampler: sampler;
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) world: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @location(1) uv: vec2<f32>) -> VertexOut {
+    var out: VertexOut;
+    let world = object.transform * vec4<f32>(position, 1.0);
+    out.position = camera.view_proj * world;
+    out.uv = uv;
+    out.world = world.xyz / world.w;
+    return out;
+}
+
+// Mirrors ClipPlanes::keeps
+fn clipped(world: vec3<f32>) -> bool {
+    for (var i = 0u; i < u32(object.clip.x); i++) {
+        let plane = object.clip_planes[i];
+        if dot(plane.xyz, world) + plane.w < 0.0 {
+            return true;
+        }
+    }
+    return false;
+}
+
+@fragment
+fn fs_main(in: VertexOut, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    if clipped(in.world) {
+        discard;
+    }
+    if !front_facing && object.clip.y > 0.5 {
+        return object.cap_color;
+    }
+    // The sRGB texture format decodes texels to linear before filtering
+    return textureSample(image, image_sampler, in.uv) * object.color;
+}
+"#;
+
+/// Filtering between texels when an image is magnified or minified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TextureFilter {
+    /// Hard pixel edges, showing each value as it is
+    Nearest,
+    #[default]
+    Linear,
+}
+
+impl TextureFilter {
+    pub fn filter_mode(self) -> wgpu::FilterMode {
+        match self {
+            TextureFilter::Nearest => wgpu::FilterMode::Nearest,
+            TextureFilter::Linear => wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+/// sRGB-encoded value of a linear intensity in [0, 1]
+pub fn linear_to_srgb(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear intensity of an sRGB-encoded value in [0, 1]
+pub fn srgb_to_linear(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_byte(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Texture of a scene object with a coordinate per vertex
+///
+/// Texels are shared, so cloning a texture to change its filter does not
+/// copy the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneTexture {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA8 texels, sRGB-encoded, top row first
+    pub texels: Arc<[u8]>,
+    /// One coordinate per vertex; (0, 0) is the top left corner of the texture
+    pub texcoords: Vec<[f32; 2]>,
+    pub filter: TextureFilter,
+}
+
+impl SceneTexture {
+    /// Format the texels are uploaded in
+    pub fn format(&self) -> wgpu::TextureFormat {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    }
+
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        let expected = self.width as usize * self.height as usize * 4;
+        if self.width == 0 || self.height == 0 || self.texels.len() != expected {
+            return Err(crate::Error::Render(format!(
+                "Texture of {}x{} texels has {} bytes, expected {}",
+                self.width, self.height, self.texels.len(), expected
+            )));
+        }
+        Ok(())
+    }
+
+    /// Linear RGBA of texel (x, y)
+    fn texel(&self, x: usize, y: usize) -> [f32; 4] {
+        let i = (y * self.width as usize + x) * 4;
+        let t = &self.texels[i..i + 4];
+        let [r, g, b] = [t[0], t[1], t[2]].map(|c| srgb_to_linear(c as f32 / 255.0));
+        [r, g, b, t[3] as f32 / 255.0]
+    }
+
+    /// Linear RGBA the GPU samples at texture coordinate `uv`, clamped to the edges
+    pub fn sample(&self, uv: [f32; 2]) -> [f32; 4] {
+        let (w, h) = (self.width as usize, self.height as usize);
+        if w == 0 || h == 0 {
+            return [0.0; 4];
+        }
+        let [u, v] = uv.map(|c| c.clamp(0.0, 1.0));
+        let (x, y) = (u * w as f32, v * h as f32);
+        match self.filter {
+            TextureFilter::Nearest => self.texel((x as usize).min(w - 1), (y as usize).min(h - 1)),
+            TextureFilter::Linear => {
+                // Texel centers lie at half-integer coordinates
+                let (x, y) = ((x - 0.5).clamp(0.0, (w - 1) as f32), (y - 0.5).clamp(0.0, (h - 1) as f32));
+                let (x0, y0) = (x as usize, y as usize);
+                let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+                let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+                let [a, b, c, d] = [self.texel(x0, y0), self.texel(x1, y0), self.texel(x0, y1), self.texel(x1, y1)];
+                std::array::from_fn(|i| {
+                    let top = a[i] + (b[i] - a[i]) * fx;
+                    let bottom = c[i] + (d[i] - c[i]) * fx;
+                    top + (bottom - top) * fy
+                })
+            }
+        }
+    }
+}
+
+/// How images are turned into textures
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageRendering {
+    pub filter: TextureFilter,
+    /// Colormap from the `ColorMapLibrary` for single-channel f32 images
+    pub colormap: String,
+    /// Values mapped to the ends of the colormap; the finite range of the image if unset
+    pub range: Option<[f32; 2]>,
+}
+
+impl Default for ImageRendering {
+    fn default() -> Self {
+        Self {
+            filter: TextureFilter::Linear,
+            colormap: "Viridis".to_string(),
+            range: None,
+        }
+    }
+}
+
+impl ImageRendering {
+    pub fn with_filter(mut self, filter: TextureFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_colormap(mut self, colormap: &str) -> Self {
+        self.colormap = colormap.to_string();
+        self
+    }
+
+    pub fn with_range(mut self, range: [f32; 2]) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// sRGB-encoded RGBA8 texels of an image, top row first
+    pub fn texels(&self, image: &ImageView) -> Result<Vec<u8>, crate::Error> {
+        let pixels = (0..image.height()).flat_map(|row| (0..image.width()).map(move |col| (col, row)));
+        let texels = match (image.data(), image.channels()) {
+            (ImageData::U8(_), channels) => pixels
+                .flat_map(|(col, row)| {
+                    let value = |c: usize| image.value(col, row, c) as u8;
+                    match channels {
+                        1 => [value(0), value(0), value(0), 255],
+                        2 => [value(0), value(0), value(0), value(1)],
+                        3 => [value(0), value(1), value(2), 255],
+                        _ => [value(0), value(1), value(2), value(3)],
+                    }
+                })
+                .collect(),
+            (ImageData::F32(_), 1) => {
+                let map = ColorMapLibrary::global().get(&self.colormap)
+                    .ok_or_else(|| crate::Error::Render(format!("Unknown colormap {}", self.colormap)))?;
+                let [min, max] = self.range.or_else(|| image.range(0).map(|(min, max)| [min, max])).unwrap_or([0.0, 1.0]);
+                pixels.flat_map(|(col, row)| colormapped(&map, image.value(col, row, 0), min, max)).collect()
+            }
+            (ImageData::F32(_), channels) => pixels
+                .flat_map(|(col, row)| {
+                    let srgb = |c: usize| to_byte(linear_to_srgb(image.value(col, row, c)));
+                    let alpha = |c: usize| to_byte(image.value(col, row, c));
+                    match channels {
+                        2 => [srgb(0), srgb(0), srgb(0), alpha(1)],
+                        3 => [srgb(0), srgb(1), srgb(2), 255],
+                        _ => [srgb(0), srgb(1), srgb(2), alpha(3)],
+                    }
+                })
+                .collect(),
+        };
+        Ok(texels)
+    }
+}
+
+/// Texel of a value through a colormap; NaN takes the map's NaN color or is transparent
+fn colormapped(map: &ColorMap, value: f32, min: f32, max: f32) -> [u8; 4] {
+    if value.is_nan() {
+        return match map.nan_color {
+            Some(color) => [to_byte(color[0]), to_byte(color[1]), to_byte(color[2]), 255],
+            None => [0; 4],
+        };
+    }
+    let t = if max > min { (value - min) / (max - min) } else { 0.5 };
+    map.sample(t).map(to_byte)
+}
+
+/// Scene object drawing an image as a textured quad in its rectangle
+pub fn image_scene_object(image: &ImageView, rendering: &ImageRendering, material: Material) -> Result<SceneObject, crate::Error> {
+    let positions: Vec<Vector3<f32>> = image.corners().to_vec();
+    // Corners from the lower left, counter-clockwise; texture row 0 is the top row
+    let texcoords = vec![[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+    let mut object = SceneObject::new(
+        Geometry::Triangles { positions, indices: vec![0, 1, 2, 0, 2, 3] },
+        material,
+    );
+    object.bind_texture(SceneTexture {
+        width: image.width() as u32,
+        height: image.height() as u32,
+        texels: rendering.texels(image)?.into(),
+        texcoords,
+        filter: rendering.filter,
+    })?;
+    Ok(object)
+}
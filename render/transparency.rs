@@ -0,0 +1,238 @@
+//! Ordering and compositing of semi-transparent geometry
+
+use nalgebra::{Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+
+use crate::core::transform;
+use super::{Camera, ClipPlanes, Geometry, Scene, SceneObject};
+
+/// How transparent objects are composited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TransparencyMode {
+    /// Back-to-front per-object sort with premultiplied "over" blending
+    #[default]
+    Sorted,
+    /// Weighted blended order-independent transparency
+    WeightedBlended,
+}
+
+/// Renderer settings shared by all backends
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RenderSettings {
+    pub transparency: TransparencyMode,
+    /// Clipping planes of objects without their own, see `SceneObject::clip_planes`
+    #[serde(default)]
+    pub clipping: ClipPlanes,
+}
+
+impl RenderSettings {
+    pub fn with_transparency(mut self, mode: TransparencyMode) -> Self {
+        self.transparency = mode;
+        self
+    }
+
+    pub fn with_clipping(mut self, clipping: ClipPlanes) -> Self {
+        self.clipping = clipping;
+        self
+    }
+}
+
+/// Opacity below which an object is drawn in the transparent pass
+const OPAQUE_THRESHOLD: f32 = 1.0 - 1e-4;
+
+impl SceneObject {
+    pub fn is_transparent(&self) -> bool {
+        self.material.color.w < OPAQUE_THRESHOLD
+    }
+
+    /// Centroid of the object's vertices in world space
+    pub fn centroid(&self) -> Option<Vector3<f32>> {
+        let positions = match &self.geometry {
+            Geometry::Points { positions }
+            | Geometry::Lines { positions, .. }
+            | Geometry::Triangles { positions, .. } => positions,
+            Geometry::Custom { .. } => return None,
+        };
+        if positions.is_empty() {
+            return None;
+        }
+        let local = positions.iter().sum::<Vector3<f32>>() / positions.len() as f32;
+        Some(transform::transform_point(&self.transform, &local))
+    }
+}
+
+/// Indices into `Scene::objects` in the order they should be drawn
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DrawOrder {
+    /// Drawn first, with depth writes
+    pub opaque: Vec<usize>,
+    /// Drawn afterwards, depth-tested without depth writes
+    pub transparent: Vec<usize>,
+}
+
+/// Split a scene into opaque and transparent passes
+///
+/// In `Sorted` mode transparent objects are ordered back to front by the view
+/// depth of their centroids; weighted blending needs no order.
+pub fn draw_order(scene: &Scene, mode: TransparencyMode) -> DrawOrder {
+    let mut order = DrawOrder::default();
+    for (i, object) in scene.objects().iter().enumerate() {
+        if !object.visible {
+            continue;
+        }
+        if object.is_transparent() {
+            order.transparent.push(i);
+        } else {
+            order.opaque.push(i);
+        }
+    }
+
+    if mode == TransparencyMode::Sorted {
+        let depth = |i: &usize| view_depth(scene.camera(), &scene.objects()[*i]);
+        order.transparent.sort_by(|a, b| depth(b).total_cmp(&depth(a)));
+    }
+    order
+}
+
+/// Distance in front of the camera along its view direction
+fn view_depth(camera: &Camera, object: &SceneObject) -> f32 {
+    let view = camera.view_matrix();
+    object.centroid()
+        .map(|c| -transform::transform_point(&view, &c).z)
+        .unwrap_or(0.0)
+}
+
+/// Blend state for a pass
+pub fn blend_state(transparent: bool) -> Option<wgpu::BlendState> {
+    transparent.then_some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING)
+}
+
+/// Depth state for a pass: transparent geometry is tested but not written
+pub fn depth_stencil_state(format: wgpu::TextureFormat, transparent: bool) -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format,
+        depth_write_enabled: !transparent,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    }
+}
+
+/// Convert a straight-alpha color to premultiplied alpha
+pub fn premultiply(color: Vector4<f32>) -> Vector4<f32> {
+    Vector4::new(color.x * color.w, color.y * color.w, color.z * color.w, color.w)
+}
+
+/// Porter-Duff "over" for premultiplied colors: `src` in front of `dst`
+pub fn over(src: Vector4<f32>, dst: Vector4<f32>) -> Vector4<f32> {
+    src + dst * (1.0 - src.w)
+}
+
+/// Composite straight-alpha layers ordered back to front over a background
+///
+/// This is the reference the CPU backend and golden tests compare against.
+pub fn composite_sorted(background: Vector4<f32>, layers: &[Vector4<f32>]) -> Vector4<f32> {
+    layers.iter().fold(premultiply(background), |dst, &src| over(premultiply(src), dst))
+}
+
+/// Weighted blended order-independent transparency (McGuire & Bavoil 2013)
+///
+/// `fragments` are straight-alpha colors with their view depth; the result
+/// is premultiplied and independent of fragment order.
+pub fn composite_weighted(background: Vector4<f32>, fragments: &[(Vector4<f32>, f32)]) -> Vector4<f32> {
+    let mut accum = Vector4::zeros();
+    let mut revealage = 1.0f32;
+    for &(color, depth) in fragments {
+        let c = premultiply(color);
+        let weight = (c.w * 3e3 * (1.0 - depth.clamp(0.0, 1.0)).powi(3)).clamp(1e-2, 3e3);
+        accum += c * weight;
+        revealage *= 1.0 - c.w;
+    }
+
+    let background = premultiply(background);
+    if accum.w <= f32::EPSILON {
+        return background;
+    }
+    let average = accum.xyz() / accum.w;
+    let coverage = 1.0 - revealage;
+    let front = Vector4::new(average.x * coverage, average.y * coverage, average.z * coverage, coverage);
+    over(front, background)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Material;
+
+    fn quad(z: f32, color: Vector4<f32>) -> SceneObject {
+        let positions = vec![
+            Vector3::new(-1.0, -1.0, z),
+            Vector3::new(1.0, -1.0, z),
+            Vector3::new(1.0, 1.0, z),
+            Vector3::new(-1.0, 1.0, z),
+        ];
+        SceneObject::new(Geometry::Triangles { positions, indices: vec![0, 1, 2, 0, 2, 3] }, Material::new(color))
+    }
+
+    fn close(a: Vector4<f32>, b: Vector4<f32>) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    #[test]
+    fn overlapping_translucent_quads_blend_back_to_front() {
+        let red = Vector4::new(1.0, 0.0, 0.0, 0.5);
+        let blue = Vector4::new(0.0, 0.0, 1.0, 0.5);
+        // Added front first; the default camera looks down -z from z = 5
+        let scene = Scene::new(Camera::default())
+            .with_object(quad(1.0, blue))
+            .with_object(quad(0.0, Vector4::new(0.0, 1.0, 0.0, 1.0)))
+            .with_object(quad(-1.0, red));
+
+        let order = draw_order(&scene, TransparencyMode::Sorted);
+        assert_eq!(order.opaque, vec![1]);
+        assert_eq!(order.transparent, vec![2, 0]);
+
+        // Red over white gives (1, 0.5, 0.5); blue over that halves it and adds 0.5 blue
+        let layers: Vec<Vector4<f32>> = order.transparent.iter().map(|&i| scene.objects()[i].material.color).collect();
+        let white = Vector4::new(1.0, 1.0, 1.0, 1.0);
+        assert!(close(composite_sorted(white, &layers), Vector4::new(0.5, 0.25, 0.75, 1.0)));
+    }
+
+    #[test]
+    fn weighted_blending_does_not_depend_on_order() {
+        let a = (Vector4::new(1.0, 0.0, 0.0, 0.5), 0.2);
+        let b = (Vector4::new(0.0, 0.0, 1.0, 0.5), 0.8);
+        let background = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let forward = composite_weighted(background, &[a, b]);
+        assert!(close(forward, composite_weighted(background, &[b, a])));
+        // Coverage of two half-transparent layers is 3/4 whatever the weights
+        assert!((forward.w - 1.0).abs() < 1e-6);
+        assert!((forward.x + forward.y + forward.z - 0.75).abs() < 1e-5);
+        // The nearer fragment weighs more
+        assert!(forward.x > forward.z);
+    }
+
+    #[test]
+    fn nothing_transparent_leaves_the_background() {
+        let background = Vector4::new(0.2, 0.4, 0.6, 1.0);
+        assert!(close(composite_sorted(background, &[]), background));
+        assert!(close(composite_weighted(background, &[]), background));
+    }
+
+    #[test]
+    fn transparent_passes_keep_depth_but_do_not_write_it() {
+        let format = wgpu::TextureFormat::Depth32Float;
+        assert!(depth_stencil_state(format, false).depth_write_enabled);
+        assert!(!depth_stencil_state(format, true).depth_write_enabled);
+        assert_eq!(blend_state(true), Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING));
+        assert_eq!(blend_state(false), None);
+    }
+
+    #[test]
+    fn hidden_objects_are_not_drawn() {
+        let mut hidden = quad(0.0, Vector4::new(1.0, 1.0, 1.0, 0.5));
+        hidden.visible = false;
+        let scene = Scene::new(Camera::default()).with_object(hidden);
+        assert_eq!(draw_order(&scene, TransparencyMode::WeightedBlended), DrawOrder::default());
+    }
+}
@@ -0,0 +1,418 @@
+//! Incremental scene updates from module outputs
+//!
+//! A `SceneBridge` subscribes to a workflow's module outputs and converts
+//! each block into scene objects on a background task, so a viewer shows
+//! blocks as they arrive instead of waiting for the whole module. Blocks are
+//! keyed by the module, port and block number that produced them, which stay
+//! the same when a module runs again, while object ids do not. Every
+//! execution of a module gets a higher generation; `Scene::apply_update`
+//! ignores updates older than what it shows, so the scene ends up the same
+//! whatever order the conversions finish in.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, mpsc};
+
+use crate::compute::{OutputEvent, WorkflowExecutor};
+use super::convert::to_scene_objects;
+use super::{Material, Scene, SceneHandle, SceneObject};
+
+/// Identity of a block of module output in a scene
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SceneKey {
+    pub module_id: u32,
+    pub port: String,
+    pub block: i32,
+}
+
+impl std::fmt::Display for SceneKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{} block {}", self.module_id, self.port, self.block)
+    }
+}
+
+/// A change to the keyed objects of a scene
+#[derive(Debug, Clone)]
+pub enum SceneUpdate {
+    /// Show `objects` for a block, replacing what an older generation showed
+    Put {
+        key: SceneKey,
+        generation: u64,
+        objects: Vec<SceneObject>,
+    },
+    /// Remove a block; a put of the same generation arriving later is ignored
+    Remove { key: SceneKey, generation: u64 },
+    /// Remove the blocks a module produced before `generation`, and ignore any arriving later
+    Retire { module_id: u32, generation: u64 },
+}
+
+/// Scene objects shown for one key
+#[derive(Debug, Clone)]
+pub(crate) struct KeyedObjects {
+    generation: u64,
+    handles: Vec<SceneHandle>,
+    /// Kept after a removal so late puts of the same generation stay removed
+    removed: bool,
+}
+
+impl Scene {
+    /// Apply an update, returning whether the scene changed and needs a repaint
+    pub fn apply_update(&mut self, update: SceneUpdate) -> bool {
+        match update {
+            SceneUpdate::Put { key, generation, objects } => {
+                if generation < self.retired_generation(key.module_id) {
+                    return false;
+                }
+                let newer = match self.keyed.get(&key) {
+                    Some(current) => generation > current.generation || (generation == current.generation && !current.removed),
+                    None => true,
+                };
+                if !newer {
+                    tracing::debug!("Ignoring generation {} of {}, showing a newer one", generation, key);
+                    return false;
+                }
+                self.remove_keyed(&key);
+                let handles = objects.iter().map(SceneObject::handle).collect();
+                self.objects.extend(objects);
+                self.keyed.insert(key, KeyedObjects { generation, handles, removed: false });
+                true
+            }
+            SceneUpdate::Remove { key, generation } => {
+                if generation < self.retired_generation(key.module_id)
+                    || self.keyed.get(&key).is_some_and(|current| current.generation > generation)
+                {
+                    return false;
+                }
+                let changed = self.remove_keyed(&key);
+                self.keyed.insert(key, KeyedObjects { generation, handles: Vec::new(), removed: true });
+                changed
+            }
+            SceneUpdate::Retire { module_id, generation } => {
+                if generation <= self.retired_generation(module_id) {
+                    return false;
+                }
+                self.retired.insert(module_id, generation);
+                let stale: Vec<SceneKey> = self.keyed.iter()
+                    .filter(|(key, current)| key.module_id == module_id && current.generation < generation)
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                let mut changed = false;
+                for key in stale {
+                    changed |= self.remove_keyed(&key);
+                    self.keyed.remove(&key);
+                }
+                changed
+            }
+        }
+    }
+
+    /// Objects shown for a key
+    pub fn keyed_objects(&self, key: &SceneKey) -> impl Iterator<Item = &SceneObject> {
+        let handles = self.keyed.get(key).map(|k| k.handles.as_slice()).unwrap_or_default();
+        self.objects.iter().filter(move |o| handles.contains(&o.handle()))
+    }
+
+    /// Keys with objects shown, and the generation they come from
+    pub fn keys(&self) -> BTreeMap<SceneKey, u64> {
+        self.keyed.iter()
+            .filter(|(_, current)| !current.removed)
+            .map(|(key, current)| (key.clone(), current.generation))
+            .collect()
+    }
+
+    fn retired_generation(&self, module_id: u32) -> u64 {
+        self.retired.get(&module_id).copied().unwrap_or(0)
+    }
+
+    /// Remove the objects shown for a key, returning whether there were any
+    fn remove_keyed(&mut self, key: &SceneKey) -> bool {
+        let Some(current) = self.keyed.get_mut(key) else {
+            return false;
+        };
+        let handles = std::mem::take(&mut current.handles);
+        let before = self.objects.len();
+        self.objects.retain(|o| !handles.contains(&o.handle()));
+        self.objects.len() != before
+    }
+}
+
+/// Converts the module outputs of one workflow into scene updates
+pub struct SceneBridge {
+    workflow_id: String,
+    material: Material,
+    repaint: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+impl SceneBridge {
+    pub fn new(workflow_id: &str) -> Self {
+        Self {
+            workflow_id: workflow_id.to_string(),
+            material: Material::default(),
+            repaint: None,
+        }
+    }
+
+    /// Material of the converted objects
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// Called whenever updates are ready, e.g. to request a repaint of the viewport
+    pub fn with_repaint(mut self, repaint: impl Fn() + Send + Sync + 'static) -> Self {
+        self.repaint = Some(Arc::new(repaint));
+        self
+    }
+
+    /// Start converting the executor's outputs; conversion stops when the returned updates are dropped
+    pub fn spawn(self, executor: &WorkflowExecutor) -> SceneUpdates {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(self.run(executor.subscribe_outputs(), sender));
+        SceneUpdates { receiver, task }
+    }
+
+    async fn run(self, mut events: broadcast::Receiver<OutputEvent>, sender: mpsc::UnboundedSender<SceneUpdate>) {
+        let mut generations: BTreeMap<u32, u64> = BTreeMap::new();
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!("Scene bridge for {} missed {} module outputs", self.workflow_id, missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if event.workflow_id != self.workflow_id {
+                continue;
+            }
+            let generation = generations.entry(event.module_id).or_default();
+            *generation += 1;
+            let generation = *generation;
+
+            let sender = sender.clone();
+            let material = self.material.clone();
+            let repaint = self.repaint.clone();
+            tokio::task::spawn_blocking(move || {
+                for update in convert_outputs(&event, generation, &material) {
+                    if sender.send(update).is_err() {
+                        return;
+                    }
+                }
+                if let Some(repaint) = repaint {
+                    repaint();
+                }
+            });
+        }
+    }
+}
+
+/// Updates showing every block of a module execution, then retiring older blocks
+fn convert_outputs(event: &OutputEvent, generation: u64, material: &Material) -> Vec<SceneUpdate> {
+    let mut blocks: BTreeMap<SceneKey, Vec<SceneObject>> = BTreeMap::new();
+    for (port, objects) in &event.outputs {
+        for object in objects {
+            let key = SceneKey {
+                module_id: event.module_id,
+                port: port.clone(),
+                block: object.meta().block,
+            };
+            match to_scene_objects(object.as_ref(), material.clone()) {
+                Ok(converted) => blocks.entry(key.clone()).or_default().extend(
+                    converted.into_iter().map(|o| o.with_name(&key.to_string())),
+                ),
+                Err(e) => tracing::warn!("Cannot show {} of workflow {}: {}", key, event.workflow_id, e),
+            }
+        }
+    }
+
+    let mut updates: Vec<SceneUpdate> = blocks.into_iter()
+        .map(|(key, objects)| SceneUpdate::Put { key, generation, objects })
+        .collect();
+    updates.push(SceneUpdate::Retire {
+        module_id: event.module_id,
+        generation,
+    });
+    updates
+}
+
+/// Scene updates from a running `SceneBridge`
+pub struct SceneUpdates {
+    receiver: mpsc::UnboundedReceiver<SceneUpdate>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl SceneUpdates {
+    /// Next update, waiting for one; None once the executor is gone
+    pub async fn recv(&mut self) -> Option<SceneUpdate> {
+        self.receiver.recv().await
+    }
+
+    /// Apply the updates ready without waiting, returning whether the scene changed
+    pub fn apply_pending(&mut self, scene: &mut Scene) -> bool {
+        let mut changed = false;
+        while let Ok(update) = self.receiver.try_recv() {
+            changed |= scene.apply_update(update);
+        }
+        changed
+    }
+}
+
+impl Drop for SceneUpdates {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::compute::{ModuleRegistry, RunKind, TaskExecutor};
+    use crate::core::{MessageRouter, Object, PointsBuilder};
+    use crate::render::{Camera, Geometry};
+
+    fn key(module_id: u32, block: i32) -> SceneKey {
+        SceneKey { module_id, port: "grid_out".to_string(), block }
+    }
+
+    fn point() -> SceneObject {
+        SceneObject::new(Geometry::Points { positions: vec![nalgebra::Vector3::zeros()] }, Material::default())
+    }
+
+    fn put(key: SceneKey, generation: u64, objects: usize) -> SceneUpdate {
+        SceneUpdate::Put { key, generation, objects: (0..objects).map(|_| point()).collect() }
+    }
+
+    #[test]
+    fn newer_generations_replace_a_block_and_older_ones_are_ignored() {
+        let mut scene = Scene::new(Camera::new(1.0));
+        assert!(scene.apply_update(put(key(1, 0), 2, 2)));
+        assert!(!scene.apply_update(put(key(1, 0), 1, 1)));
+        assert_eq!(scene.keyed_objects(&key(1, 0)).count(), 2);
+
+        assert!(scene.apply_update(put(key(1, 0), 3, 1)));
+        assert_eq!(scene.objects().len(), 1);
+        assert_eq!(scene.keys(), BTreeMap::from([(key(1, 0), 3)]));
+    }
+
+    #[test]
+    fn removed_blocks_stay_removed_for_their_generation() {
+        let mut scene = Scene::new(Camera::new(1.0));
+        assert!(!scene.apply_update(SceneUpdate::Remove { key: key(1, 0), generation: 1 }));
+        assert!(!scene.apply_update(put(key(1, 0), 1, 1)));
+        assert!(scene.keys().is_empty());
+
+        assert!(scene.apply_update(put(key(1, 0), 2, 1)));
+        assert!(!scene.apply_update(SceneUpdate::Remove { key: key(1, 0), generation: 1 }));
+        assert!(scene.apply_update(SceneUpdate::Remove { key: key(1, 0), generation: 2 }));
+        assert!(scene.objects().is_empty());
+    }
+
+    #[test]
+    fn retiring_removes_older_blocks_of_the_module_only() {
+        let mut scene = Scene::new(Camera::new(1.0));
+        scene.apply_update(put(key(1, 0), 1, 1));
+        scene.apply_update(put(key(1, 1), 1, 1));
+        scene.apply_update(put(key(1, 0), 2, 1));
+        scene.apply_update(put(key(2, 0), 1, 1));
+
+        assert!(scene.apply_update(SceneUpdate::Retire { module_id: 1, generation: 2 }));
+        assert_eq!(scene.keys(), BTreeMap::from([(key(1, 0), 2), (key(2, 0), 1)]));
+        assert!(!scene.apply_update(put(key(1, 1), 1, 1)));
+        assert!(!scene.apply_update(SceneUpdate::Retire { module_id: 1, generation: 1 }));
+        assert_eq!(scene.objects().len(), 2);
+    }
+
+    #[test]
+    fn late_updates_give_the_same_scene_in_any_order() {
+        let updates = [
+            put(key(1, 0), 1, 1),
+            put(key(1, 1), 1, 1),
+            SceneUpdate::Retire { module_id: 1, generation: 1 },
+            put(key(1, 0), 2, 3),
+            SceneUpdate::Retire { module_id: 1, generation: 2 },
+        ];
+        let expected = BTreeMap::from([(key(1, 0), 2)]);
+        for order in [[0, 1, 2, 3, 4], [3, 4, 0, 1, 2], [1, 3, 0, 4, 2], [4, 2, 1, 0, 3]] {
+            let mut scene = Scene::new(Camera::new(1.0));
+            for i in order {
+                scene.apply_update(updates[i].clone());
+            }
+            assert_eq!(scene.keys(), expected, "{:?}", order);
+            assert_eq!(scene.objects().len(), 3, "{:?}", order);
+        }
+    }
+
+    fn points_block(block: i32) -> Arc<dyn Object> {
+        let mut points = PointsBuilder::new().coordinates([[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]]).build().unwrap();
+        points.meta_mut().block = block;
+        Arc::new(points)
+    }
+
+    fn event(workflow_id: &str, module_id: u32, blocks: &[i32]) -> OutputEvent {
+        OutputEvent {
+            workflow_id: workflow_id.to_string(),
+            module_id,
+            outputs: HashMap::from([("grid_out".to_string(), blocks.iter().map(|&b| points_block(b)).collect())]),
+            kind: RunKind::Full,
+        }
+    }
+
+    #[test]
+    fn outputs_become_one_put_per_block_then_a_retire() {
+        let updates = convert_outputs(&event("w", 3, &[1, 0, 1]), 4, &Material::default());
+        let puts: Vec<(&SceneKey, u64, usize)> = updates.iter()
+            .filter_map(|update| match update {
+                SceneUpdate::Put { key, generation, objects } => Some((key, *generation, objects.len())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(puts, [(&key(3, 0), 4, 1), (&key(3, 1), 4, 2)]);
+        if let SceneUpdate::Put { objects, .. } = &updates[1] {
+            assert!(objects.iter().all(|o| o.name == "3:grid_out block 1"));
+        }
+        assert!(matches!(updates[2], SceneUpdate::Retire { module_id: 3, generation: 4 }));
+    }
+
+    #[tokio::test]
+    async fn the_bridge_numbers_executions_of_its_workflow() {
+        let executor = WorkflowExecutor::new(
+            Arc::new(ModuleRegistry::new()),
+            Arc::new(TaskExecutor::new(1)),
+            Arc::new(MessageRouter::new()),
+        );
+        let repaints = Arc::new(AtomicUsize::new(0));
+        let counted = repaints.clone();
+        let mut updates = SceneBridge::new("w")
+            .with_repaint(move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+            })
+            .spawn(&executor);
+        tokio::task::yield_now().await;
+
+        executor.publish_output(event("other", 1, &[0]));
+        executor.publish_output(event("w", 1, &[0]));
+        executor.publish_output(event("w", 1, &[0]));
+
+        let mut scene = Scene::new(Camera::new(1.0));
+        let mut generations = Vec::new();
+        for _ in 0..4 {
+            let update = tokio::time::timeout(std::time::Duration::from_secs(5), updates.recv()).await.unwrap().unwrap();
+            if let SceneUpdate::Retire { generation, .. } = update {
+                generations.push(generation);
+            }
+            scene.apply_update(update);
+        }
+        generations.sort_unstable();
+        assert_eq!(generations, [1, 2]);
+        assert_eq!(scene.keys(), BTreeMap::from([(key(1, 0), 2)]));
+        assert_eq!(scene.objects().len(), 1);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(repaints.load(Ordering::SeqCst), 2);
+        assert!(!updates.apply_pending(&mut scene));
+    }
+}
@@ -0,0 +1,249 @@
+//! The sample workflow run by a hub on a module host in another process
+
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::process::{Child, Command};
+
+use vistle::hub::{Hub, ModuleHost};
+use vistle::{
+    ComputeContext, ExecutionStats, InputPort, Module, ModuleInfo, MessageRouter, ModuleRegistry, ObjectType, OutputPorts,
+    ParameterSet, Port, PortSet, TaskExecutor, WorkflowBuilder, WorkflowExecutor, WorkflowSpec,
+};
+
+/// How long a host process may take to start and register
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Hub address given to a copy of this test binary running `slow_host_process`
+const SLOW_HOST_ENV: &str = "VISTLE_TEST_SLOW_HOST";
+
+/// The workflow `vistle` runs by default: reader, isosurface and renderer
+fn sample_workflow() -> WorkflowSpec {
+    WorkflowBuilder::new("sample_workflow", "Sample Scientific Visualization")
+        .add_module("DataReader", "Load Data")
+            .parameter("filename", "sample_data.vtk")
+            .parameter("format", "VTK")
+        .add_module("IsoSurface", "Extract Surface")
+            .parameter("iso_value", "0.5")
+            .depends_on(1)
+        .add_module("Renderer", "Render Results")
+            .depends_on(2)
+        .connect(1, "data_out", 2, "data_in")
+        .connect(2, "surface_out", 3, "geometry_in")
+        .build()
+}
+
+/// Hub on an ephemeral port with one `vistle --module-host` process registered
+async fn hub_with_host() -> (Arc<Hub>, Child) {
+    let hub = Hub::listen("127.0.0.1:0").await.unwrap();
+    let host = Command::new(env!("CARGO_BIN_EXE_vistle"))
+        .arg("--module-host")
+        .arg(hub.local_addr().to_string())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("failed to start the module host");
+
+    tokio::time::timeout(REGISTRATION_TIMEOUT, async {
+        while hub.hosts().is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the module host did not register");
+    (hub, host)
+}
+
+/// Module that takes a minute, so its host can be killed while it runs
+struct Sleep {
+    info: ModuleInfo,
+    parameters: ParameterSet,
+    ports: PortSet,
+    stats: ExecutionStats,
+}
+
+impl Sleep {
+    fn new() -> Self {
+        let mut ports = PortSet::new();
+        ports.add(Port::new_input("data_in", "Ignored").optional());
+        ports.add(Port::new_output("data_out", "Never produced in time"));
+        Self {
+            info: ModuleInfo::new(0, "Sleep", 0, 1),
+            parameters: ParameterSet::new(),
+            ports,
+            stats: ExecutionStats::new(0),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for Sleep {
+    fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    fn parameters(&self) -> &ParameterSet {
+        &self.parameters
+    }
+
+    fn ports(&self) -> &PortSet {
+        &self.ports
+    }
+
+    async fn set_input(&mut self, _port_name: &str, _objects: InputPort) -> Result<(), vistle::Error> {
+        Ok(())
+    }
+
+    async fn compute(&mut self, _ctx: &ComputeContext) -> Result<OutputPorts, vistle::Error> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok(OutputPorts::new())
+    }
+
+    fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+}
+
+/// Module host offering `Sleep` when this binary is started by `hub_with_slow_host`, else nothing
+#[tokio::test]
+async fn slow_host_process() {
+    let Ok(addr) = std::env::var(SLOW_HOST_ENV) else {
+        return;
+    };
+    let registry = Arc::new(ModuleRegistry::new());
+    registry.register("Sleep", Sleep::new).await;
+    ModuleHost::new(registry).run(addr.as_str()).await.unwrap();
+}
+
+/// Hub with a copy of this test binary registered as a host offering `Sleep`
+async fn hub_with_slow_host() -> (Arc<Hub>, Child) {
+    let hub = Hub::listen("127.0.0.1:0").await.unwrap();
+    let host = Command::new(std::env::current_exe().unwrap())
+        .args(["slow_host_process", "--exact", "--nocapture"])
+        .env(SLOW_HOST_ENV, hub.local_addr().to_string())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("failed to start the slow module host");
+
+    tokio::time::timeout(REGISTRATION_TIMEOUT, async {
+        while hub.hosts().is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the slow module host did not register");
+    (hub, host)
+}
+
+/// Executor without local modules, so everything has to run on the host
+fn remote_executor(hub: Arc<Hub>) -> WorkflowExecutor {
+    WorkflowExecutor::new(
+        Arc::new(ModuleRegistry::new()),
+        Arc::new(TaskExecutor::new(2)),
+        Arc::new(MessageRouter::new()),
+    )
+    .with_hub(hub)
+}
+
+#[tokio::test]
+async fn sample_workflow_runs_on_a_host_process() {
+    let (hub, mut host) = hub_with_host().await;
+    let capabilities = &hub.hosts()[0].capabilities;
+    for module_type in ["DataReader", "IsoSurface", "Renderer"] {
+        assert!(capabilities.module_types.iter().any(|t| t == module_type), "host lacks {}", module_type);
+    }
+
+    let executor = remote_executor(hub.clone());
+    let result = executor.execute_workflow(sample_workflow(), Some(Duration::from_secs(60))).await.unwrap();
+    assert!(result.success, "{:?}", result.task_results);
+    assert_eq!(result.task_results.len(), 3);
+    assert!(result.task_results.iter().all(|r| r.success));
+
+    // The surface came back across the process boundary
+    let surface = result.task_results.iter()
+        .find(|r| r.module_id == Some(2))
+        .and_then(|r| r.outputs.as_ref())
+        .and_then(|ports| ports.get("surface_out"))
+        .expect("isosurface output");
+    assert_eq!(surface.len(), 1);
+    assert_eq!(surface[0].object_type(), ObjectType::Triangles);
+    assert_eq!(surface[0].payload().unwrap().num_triangles(), 1);
+
+    hub.shutdown();
+    let status = tokio::time::timeout(Duration::from_secs(10), host.wait()).await
+        .expect("the host did not exit on shutdown")
+        .unwrap();
+    assert!(status.success());
+}
+
+#[tokio::test]
+async fn a_host_exiting_fails_the_workflow_instead_of_hanging() {
+    let (hub, mut host) = hub_with_host().await;
+    host.kill().await.unwrap();
+    tokio::time::timeout(REGISTRATION_TIMEOUT, async {
+        while !hub.hosts().is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the hub did not notice the host exiting");
+
+    let executor = remote_executor(hub);
+    let result = tokio::time::timeout(
+        Duration::from_secs(30),
+        executor.execute_workflow(sample_workflow(), Some(Duration::from_secs(20))),
+    )
+    .await
+    .expect("the workflow hung without a host");
+    match result {
+        Ok(result) => {
+            assert!(!result.success);
+            // The reader finds no host, and nothing downstream is dispatched
+            let reader = result.task_results.iter().find(|r| r.module_id == Some(1)).unwrap();
+            assert!(reader.error.as_deref().unwrap().contains("No connected host provides module type DataReader"));
+            assert!(result.task_results.iter().filter(|r| r.module_id != Some(1)).all(|r| {
+                r.error.as_deref().is_some_and(|e| e.contains("Upstream module"))
+            }));
+        }
+        Err(e) => panic!("execution failed instead of reporting failed tasks: {}", e),
+    }
+}
+
+#[tokio::test]
+async fn killing_the_host_mid_task_fails_the_task_and_everything_downstream() {
+    let (hub, mut host) = hub_with_slow_host().await;
+    let workflow = WorkflowBuilder::new("killed_host", "Killed host")
+        .add_module("Sleep", "Running")
+        .add_module("Sleep", "Downstream")
+        .add_module("Sleep", "Further downstream")
+        .connect(1, "data_out", 2, "data_in")
+        .connect(2, "data_out", 3, "data_in")
+        .build();
+
+    let executor = remote_executor(hub.clone());
+    let run = tokio::spawn(async move { executor.execute_workflow(workflow, None).await });
+    tokio::time::timeout(REGISTRATION_TIMEOUT, async {
+        while hub.hosts()[0].reported_load == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("the first module was not dispatched");
+    host.kill().await.unwrap();
+
+    let result = tokio::time::timeout(Duration::from_secs(10), run)
+        .await
+        .expect("the workflow hung after its host was killed")
+        .unwrap()
+        .unwrap();
+    assert!(!result.success);
+    assert_eq!(result.task_results.len(), 3);
+    for task in &result.task_results {
+        let error = task.error.as_deref().unwrap_or_default();
+        assert!(error.contains("disconnected while running task"), "module {:?}: {}", task.module_id, error);
+    }
+    let downstream = result.task_results.iter().find(|r| r.module_id == Some(3)).unwrap();
+    assert!(downstream.error.as_deref().unwrap().contains("Upstream module 2 failed"));
+}
@@ -0,0 +1,34 @@
+//! Submission of a staged job to a real SLURM installation
+//!
+//! Ignored by default; run with `cargo test --test launch -- --ignored` on
+//! a machine where `sbatch` can submit to the default partition.
+
+use std::time::Duration;
+
+use vistle::launch::{JobBackend, JobSpec, SlurmBackend};
+use vistle::WorkflowBuilder;
+
+#[tokio::test]
+#[ignore = "needs a SLURM installation"]
+async fn slurm_accepts_and_tracks_a_staged_job() {
+    let dir = std::env::temp_dir().join(format!("vistle_slurm_{}", uuid::Uuid::new_v4().simple()));
+    std::fs::create_dir_all(&dir).unwrap();
+    WorkflowBuilder::new("slurm_job", "SLURM job")
+        .add_module("DataReader", "Load Data")
+        .build()
+        .save(dir.join("slurm_job.json"))
+        .await
+        .unwrap();
+    let spec = JobSpec::new(dir.join("slurm_job.json"), dir.join("job"))
+        .with_time_limit(Duration::from_secs(60))
+        .with_executable(env!("CARGO_BIN_EXE_vistle"));
+
+    let backend = SlurmBackend::new();
+    let job = backend.submit(&spec).await.unwrap();
+    assert!(job.job_id.chars().all(|c| c.is_ascii_digit()), "{}", job.job_id);
+    assert!(job.script_path.exists());
+
+    let status = backend.status(&job.job_id).await.unwrap();
+    assert_eq!(status.job_id, job.job_id);
+    std::fs::remove_dir_all(dir).ok();
+}
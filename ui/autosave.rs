@@ -0,0 +1,406 @@
+//! Background autosave and crash recovery for editor sessions
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::render::{ColorMap, ColorMapLibrary};
+use super::{EditorSnapshot, WorkflowEditor};
+
+/// Prefix of autosave file names
+pub const AUTOSAVE_PREFIX: &str = "autosave-";
+
+/// File in the autosave directory holding the time of the last explicit save
+pub const SAVED_MARKER: &str = "last-save";
+
+/// Autosave configuration
+#[derive(Debug, Clone)]
+pub struct AutosaveConfig {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    /// Number of autosave files kept; older ones are pruned
+    pub retention: usize,
+}
+
+impl Default for AutosaveConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_autosave_dir(),
+            interval: Duration::from_secs(60),
+            retention: 5,
+        }
+    }
+}
+
+impl AutosaveConfig {
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_retention(mut self, retention: usize) -> Self {
+        self.retention = retention.max(1);
+        self
+    }
+}
+
+/// Autosave directory in the user data dir
+pub fn default_autosave_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_else(std::env::temp_dir);
+    base.join("vistle").join("autosave")
+}
+
+/// Contents of an autosave file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    /// Milliseconds since the Unix epoch
+    pub saved_at: u64,
+    pub editor: EditorSnapshot,
+    /// Imported and user-edited colormaps
+    #[serde(default)]
+    pub colormaps: Vec<ColorMap>,
+}
+
+/// An autosave found on startup that is newer than the last explicit save
+#[derive(Debug, Clone)]
+pub struct RecoveryCandidate {
+    pub path: PathBuf,
+    pub saved_at: SystemTime,
+}
+
+/// Periodically writes editor snapshots without blocking the UI thread
+///
+/// Snapshots are taken on the calling thread (cheap `Arc` clones) and
+/// serialized and written on a background tokio task.
+pub struct AutosaveManager {
+    config: AutosaveConfig,
+    runtime: tokio::runtime::Handle,
+    last_save: Instant,
+    last_revision: u64,
+    in_flight: Arc<AtomicBool>,
+}
+
+impl AutosaveManager {
+    /// Create a manager; must be called from within a tokio runtime
+    pub fn new(config: AutosaveConfig) -> Self {
+        Self {
+            config,
+            runtime: tokio::runtime::Handle::current(),
+            last_save: Instant::now(),
+            last_revision: 0,
+            in_flight: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn config(&self) -> &AutosaveConfig {
+        &self.config
+    }
+
+    /// Autosave if the editor changed and the interval elapsed, or immediately
+    /// after a significant edit. Returns whether a save was started.
+    pub fn maybe_autosave(&mut self, editor: &WorkflowEditor, significant: bool) -> bool {
+        if editor.revision() == self.last_revision {
+            return false;
+        }
+        if !significant && self.last_save.elapsed() < self.config.interval {
+            return false;
+        }
+        // Skip rather than queue while the previous write is still running
+        if self.in_flight.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+
+        self.last_save = Instant::now();
+        self.last_revision = editor.revision();
+
+        let snapshot = SessionSnapshot {
+            saved_at: unix_millis(SystemTime::now()),
+            editor: editor.snapshot(),
+            colormaps: ColorMapLibrary::global().custom(),
+        };
+        let dir = self.config.dir.clone();
+        let retention = self.config.retention;
+        let in_flight = self.in_flight.clone();
+
+        self.runtime.spawn(async move {
+            if let Err(e) = write_autosave(&dir, &snapshot, retention).await {
+                tracing::warn!("Autosave to {} failed: {}", dir.display(), e);
+            }
+            in_flight.store(false, Ordering::Release);
+        });
+        true
+    }
+
+    /// Record an explicit save so recovery is not offered for older autosaves
+    pub fn mark_saved(&mut self, editor: &WorkflowEditor) {
+        self.last_revision = editor.revision();
+        self.last_save = Instant::now();
+
+        let dir = self.config.dir.clone();
+        let saved_at = SystemTime::now();
+        self.runtime.spawn(async move {
+            if let Err(e) = record_explicit_save(&dir, saved_at).await {
+                tracing::warn!("Recording the save in {} failed: {}", dir.display(), e);
+            }
+        });
+    }
+
+    /// Write the session to `path` and mark it saved
+    pub fn save_session(&mut self, editor: &WorkflowEditor, path: PathBuf) {
+        let snapshot = SessionSnapshot {
+            saved_at: unix_millis(SystemTime::now()),
+            editor: editor.snapshot(),
+            colormaps: ColorMapLibrary::global().custom(),
+        };
+        self.runtime.spawn(async move {
+            if let Err(e) = write_session(&path, &snapshot).await {
+                tracing::warn!("Saving the session to {} failed: {}", path.display(), e);
+            }
+        });
+        self.mark_saved(editor);
+    }
+}
+
+/// Session file in the user data dir
+pub fn default_session_path() -> PathBuf {
+    default_autosave_dir().with_file_name("session.json")
+}
+
+async fn write_session(path: &Path, snapshot: &SessionSnapshot) -> Result<(), crate::Error> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let text = serde_json::to_string(snapshot)
+        .map_err(|e| crate::Error::Config(format!("Failed to serialize session: {}", e)))?;
+    crate::util::io::write_text(path, &text).await
+}
+
+/// Persist the time of an explicit save, read back by `last_explicit_save`
+pub async fn record_explicit_save(dir: impl AsRef<Path>, saved_at: SystemTime) -> Result<(), crate::Error> {
+    tokio::fs::create_dir_all(dir.as_ref()).await?;
+    crate::util::io::write_text(dir.as_ref().join(SAVED_MARKER), &unix_millis(saved_at).to_string()).await
+}
+
+/// Time of the last explicit save or orderly shutdown, if one was recorded
+pub async fn last_explicit_save(dir: impl AsRef<Path>) -> Result<Option<SystemTime>, crate::Error> {
+    let text = match tokio::fs::read_to_string(dir.as_ref().join(SAVED_MARKER)).await {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let millis = text.trim().parse::<u64>()
+        .map_err(|e| crate::Error::Config(format!("Invalid save marker in {}: {}", dir.as_ref().display(), e)))?;
+    Ok(Some(UNIX_EPOCH + Duration::from_millis(millis)))
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+async fn write_autosave(dir: &Path, snapshot: &SessionSnapshot, retention: usize) -> Result<(), crate::Error> {
+    tokio::fs::create_dir_all(dir).await?;
+
+    let text = serde_json::to_string(snapshot)
+        .map_err(|e| crate::Error::Config(format!("Failed to serialize autosave: {}", e)))?;
+
+    // Write to a temporary name first so a crash never leaves a truncated autosave
+    let name = format!("{}{:016}.json", AUTOSAVE_PREFIX, snapshot.saved_at);
+    let tmp = dir.join(format!("{}.tmp", name));
+    crate::util::io::write_text(&tmp, &text).await?;
+    tokio::fs::rename(&tmp, dir.join(&name)).await?;
+
+    prune_autosaves(dir, retention).await
+}
+
+/// Autosave files in a directory, newest first
+pub async fn list_autosaves(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>, crate::Error> {
+    let mut entries = match tokio::fs::read_dir(dir.as_ref()).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut files = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_autosave = path.file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.starts_with(AUTOSAVE_PREFIX) && n.ends_with(".json"))
+            .unwrap_or(false);
+        if is_autosave {
+            files.push(path);
+        }
+    }
+
+    // Names embed a zero-padded timestamp, so they sort chronologically
+    files.sort();
+    files.reverse();
+    Ok(files)
+}
+
+/// Remove all but the newest `retention` autosaves
+pub async fn prune_autosaves(dir: impl AsRef<Path>, retention: usize) -> Result<(), crate::Error> {
+    for path in list_autosaves(dir).await?.into_iter().skip(retention) {
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            tracing::warn!("Failed to prune autosave {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Find the newest autosave written after the last explicit save, if any
+pub async fn find_recovery(
+    dir: impl AsRef<Path>,
+    last_explicit_save: Option<SystemTime>,
+) -> Result<Option<RecoveryCandidate>, crate::Error> {
+    for path in list_autosaves(dir).await? {
+        match load_snapshot(&path).await {
+            Ok(snapshot) => {
+                let saved_at = UNIX_EPOCH + Duration::from_millis(snapshot.saved_at);
+                let newer = last_explicit_save.map(|t| saved_at > t).unwrap_or(true);
+                return Ok(newer.then_some(RecoveryCandidate { path, saved_at }));
+            }
+            Err(e) => tracing::warn!("Ignoring unreadable autosave {}: {}", path.display(), e),
+        }
+    }
+    Ok(None)
+}
+
+/// Load an autosave file
+pub async fn load_snapshot(path: impl AsRef<Path>) -> Result<SessionSnapshot, crate::Error> {
+    let text = crate::util::io::read_text(path.as_ref()).await?;
+    serde_json::from_str(&text)
+        .map_err(|e| crate::Error::Config(format!("Invalid autosave {}: {}", path.as_ref().display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::WorkflowNode;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("vistle_autosave_{}", uuid::Uuid::new_v4().simple()))
+    }
+
+    fn editor(titles: &[&str]) -> WorkflowEditor {
+        let mut editor = WorkflowEditor::new();
+        for title in titles {
+            editor.add_node(WorkflowNode::new(title, "", "ConstantField"));
+        }
+        editor
+    }
+
+    fn snapshot(saved_at: u64, editor: &WorkflowEditor) -> SessionSnapshot {
+        SessionSnapshot {
+            saved_at,
+            editor: editor.snapshot(),
+            colormaps: Vec::new(),
+        }
+    }
+
+    fn titles(snapshot: &SessionSnapshot) -> Vec<&str> {
+        snapshot.editor.nodes.iter().map(|n| n.title.as_str()).collect()
+    }
+
+    /// Wait for a write spawned in the background to show up
+    async fn eventually<F: std::future::Future<Output = bool>>(mut check: impl FnMut() -> F) {
+        for _ in 0..200 {
+            if check().await {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the background write never finished");
+    }
+
+    #[tokio::test]
+    async fn edits_are_autosaved_once_and_restore() {
+        let dir = temp_dir();
+        let mut manager = AutosaveManager::new(AutosaveConfig::default().with_dir(&dir).with_interval(Duration::from_secs(3600)));
+        let editor = editor(&["Reader", "IsoSurface"]);
+
+        // Within the interval only significant edits save
+        assert!(!manager.maybe_autosave(&editor, false));
+        assert!(manager.maybe_autosave(&editor, true));
+        eventually(|| async { !list_autosaves(&dir).await.unwrap().is_empty() }).await;
+        assert!(!manager.maybe_autosave(&editor, true), "an unchanged editor was saved again");
+
+        let candidate = find_recovery(&dir, None).await.unwrap().unwrap();
+        let recovered = load_snapshot(&candidate.path).await.unwrap();
+        assert_eq!(titles(&recovered), ["Reader", "IsoSurface"]);
+        let mut restored = WorkflowEditor::new();
+        restored.restore(recovered.editor);
+        assert_eq!(restored.snapshot().nodes.len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn old_autosaves_are_pruned() {
+        let dir = temp_dir();
+        let editor = editor(&["Reader"]);
+        for saved_at in [1_000, 2_000, 3_000] {
+            write_autosave(&dir, &snapshot(saved_at, &editor), 2).await.unwrap();
+        }
+        let kept = list_autosaves(&dir).await.unwrap();
+        assert_eq!(kept.len(), 2);
+        assert!(kept[0].to_string_lossy().contains("0000000000003000"));
+        assert!(kept[1].to_string_lossy().contains("0000000000002000"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn recovery_is_offered_only_for_autosaves_after_the_last_save() {
+        let dir = temp_dir();
+        write_autosave(&dir, &snapshot(1_000, &editor(&["Old"])), 5).await.unwrap();
+        write_autosave(&dir, &snapshot(2_000, &editor(&["New"])), 5).await.unwrap();
+        let at = |millis| UNIX_EPOCH + Duration::from_millis(millis);
+
+        let candidate = find_recovery(&dir, None).await.unwrap().unwrap();
+        assert_eq!(candidate.saved_at, at(2_000));
+        assert_eq!(titles(&load_snapshot(&candidate.path).await.unwrap()), ["New"]);
+        assert!(find_recovery(&dir, Some(at(1_500))).await.unwrap().is_some());
+        assert!(find_recovery(&dir, Some(at(2_000))).await.unwrap().is_none());
+
+        // An unreadable newest autosave falls back to the one before it
+        std::fs::write(dir.join(format!("{}{:016}.json", AUTOSAVE_PREFIX, 3_000)), "{").unwrap();
+        assert_eq!(find_recovery(&dir, None).await.unwrap().unwrap().saved_at, at(2_000));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn explicit_saves_are_remembered_across_sessions() {
+        let dir = temp_dir();
+        assert_eq!(last_explicit_save(&dir).await.unwrap(), None);
+        record_explicit_save(&dir, UNIX_EPOCH + Duration::from_millis(5_000)).await.unwrap();
+        assert_eq!(last_explicit_save(&dir).await.unwrap(), Some(UNIX_EPOCH + Duration::from_millis(5_000)));
+
+        // Saving the session writes it and moves the marker past earlier autosaves
+        write_autosave(&dir, &snapshot(unix_millis(SystemTime::now()), &editor(&["Autosaved"])), 5).await.unwrap();
+        let session = dir.join("session.json");
+        let mut manager = AutosaveManager::new(AutosaveConfig::default().with_dir(&dir));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        manager.save_session(&editor(&["Saved"]), session.clone());
+        eventually(|| async {
+            let saved = last_explicit_save(&dir).await.ok().flatten();
+            saved.is_some_and(|t| t > UNIX_EPOCH + Duration::from_millis(5_000)) && session.exists()
+        }).await;
+        assert!(find_recovery(&dir, last_explicit_save(&dir).await.unwrap()).await.unwrap().is_none());
+        eventually(|| async { load_snapshot(&session).await.is_ok() }).await;
+        assert_eq!(titles(&load_snapshot(&session).await.unwrap()), ["Saved"]);
+
+        std::fs::write(dir.join(SAVED_MARKER), "yesterday").unwrap();
+        assert!(last_explicit_save(&dir).await.is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
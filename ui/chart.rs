@@ -0,0 +1,253 @@
+//! Line charts of curve objects, e.g. probes over time
+
+use std::path::{Path, PathBuf};
+
+use egui_plot::{Corner, CoordinatesFormatter, Legend, Line, Plot, PlotPoints};
+
+use crate::compute::OutputEvent;
+use crate::core::CurveView;
+use crate::render::CategoricalPalette;
+use super::UiContext;
+
+/// One plotted curve, split at its NaN samples
+#[derive(Debug, Clone)]
+struct ChartCurve {
+    key: String,
+    name: String,
+    x_label: String,
+    y_label: String,
+    segments: Vec<Vec<[f64; 2]>>,
+}
+
+/// Panel plotting curves with a shared pair of axes
+///
+/// Curves may cover different x ranges; the axes span all of them. Each
+/// curve is drawn as separate line pieces between its NaN samples, listed
+/// once in the legend. Feed it `WorkflowExecutor::subscribe_outputs`
+/// events to follow a timeseries run while it executes.
+pub struct ChartPanel {
+    title: String,
+    curves: Vec<ChartCurve>,
+    palette: CategoricalPalette,
+    /// Screen area of the plot in the last frame, for exports
+    plot_rect: Option<egui::Rect>,
+    /// Export waiting for the screenshot of the next frame
+    pending_export: Option<PathBuf>,
+    last_export: Option<Result<PathBuf, String>>,
+}
+
+impl ChartPanel {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            curves: Vec::new(),
+            palette: CategoricalPalette::default(),
+            plot_rect: None,
+            pending_export: None,
+            last_export: None,
+        }
+    }
+
+    /// Add or replace the curve stored under `key`
+    pub fn set_curve(&mut self, key: &str, curve: CurveView) {
+        let chart_curve = ChartCurve {
+            key: key.to_string(),
+            name: curve.y_label.to_string(),
+            x_label: curve.x_label.to_string(),
+            y_label: curve.y_label.to_string(),
+            segments: curve.segments(),
+        };
+        match self.curves.iter_mut().find(|c| c.key == key) {
+            Some(existing) => *existing = chart_curve,
+            None => self.curves.push(chart_curve),
+        }
+    }
+
+    /// Take the curves among a module's outputs, replacing earlier ones of the same port
+    pub fn update(&mut self, event: &OutputEvent) {
+        for (port, objects) in &event.outputs {
+            for (index, object) in objects.iter().enumerate() {
+                if let Some(curve) = object.as_curve() {
+                    self.set_curve(&format!("{}:{}:{}", event.module_id, port, index), curve);
+                }
+            }
+        }
+    }
+
+    pub fn remove_curve(&mut self, key: &str) {
+        self.curves.retain(|c| c.key != key);
+    }
+
+    pub fn clear(&mut self) {
+        self.curves.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.curves.is_empty()
+    }
+
+    /// Save the chart as shown as a PNG once the next frame is drawn
+    pub fn export_png(&mut self, path: impl Into<PathBuf>) {
+        self.pending_export = Some(path.into());
+    }
+
+    /// Outcome of the last export, if any
+    pub fn last_export(&self) -> Option<&Result<PathBuf, String>> {
+        self.last_export.as_ref()
+    }
+
+    /// Smallest and largest x of all curves' finite samples
+    pub fn x_range(&self) -> Option<(f64, f64)> {
+        self.curves.iter()
+            .flat_map(|c| c.segments.iter().flatten())
+            .map(|&[x, _]| (x, x))
+            .reduce(|(lo, hi), (x, _)| (lo.min(x), hi.max(x)))
+    }
+
+    /// Axis label shared by all curves, or the labels joined if they differ
+    fn axis_label(&self, label: impl Fn(&ChartCurve) -> &str) -> String {
+        let mut labels: Vec<&str> = self.curves.iter().map(label).filter(|l| !l.is_empty()).collect();
+        labels.dedup();
+        labels.join(", ")
+    }
+
+    pub fn draw(&mut self, ui: &mut UiContext) {
+        self.finish_export(ui.ctx);
+
+        let x_label = self.axis_label(|c| c.x_label.as_str());
+        let y_label = if self.curves.len() == 1 { self.curves[0].y_label.clone() } else { String::new() };
+        let x_range = self.x_range();
+        let mut export_clicked = false;
+        let mut plot_rect = None;
+
+        egui::Window::new(&self.title).show(ui.ctx, |window| {
+            window.horizontal(|row| {
+                export_clicked = row.button("Export PNG").clicked();
+                match &self.last_export {
+                    Some(Ok(path)) => { row.label(format!("Saved {}", path.display())); }
+                    Some(Err(error)) => { row.colored_label(egui::Color32::RED, error); }
+                    None => {}
+                }
+            });
+
+            let mut plot = Plot::new(("chart", &self.title)).legend(Legend::default());
+            // Curves covering different x ranges share axes spanning all of them
+            if let Some((lo, hi)) = x_range {
+                plot = plot.include_x(lo).include_x(hi);
+            }
+            let response = plot
+                .x_axis_label(x_label)
+                .y_axis_label(y_label)
+                .label_formatter(|name, point| {
+                    if name.is_empty() {
+                        format!("x = {:.6}\ny = {:.6}", point.x, point.y)
+                    } else {
+                        format!("{}\nx = {:.6}\ny = {:.6}", name, point.x, point.y)
+                    }
+                })
+                .coordinates_formatter(Corner::LeftBottom, CoordinatesFormatter::default())
+                .show(window, |plot| {
+                    for (index, curve) in self.curves.iter().enumerate() {
+                        let [r, g, b] = self.palette.color(index as i64);
+                        let color = egui::Color32::from_rgb((r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8);
+                        // Pieces share name and color, so the legend lists the curve once
+                        for segment in &curve.segments {
+                            plot.line(Line::new(PlotPoints::from(segment.clone())).name(&curve.name).color(color));
+                        }
+                    }
+                });
+            plot_rect = Some(response.response.rect);
+        });
+
+        self.plot_rect = plot_rect;
+        if export_clicked {
+            let name = format!("{}.png", self.title.replace(|c: char| !c.is_alphanumeric(), "_"));
+            self.export_png(name);
+        }
+        if self.pending_export.is_some() {
+            ui.ctx.send_viewport_cmd(egui::ViewportCommand::Screenshot);
+        }
+    }
+
+    /// Crop the screenshot requested for a pending export to the plot and save it
+    fn finish_export(&mut self, ctx: &egui::Context) {
+        let Some(path) = self.pending_export.clone() else {
+            return;
+        };
+        let screenshot = ctx.input(|i| {
+            i.events.iter().find_map(|event| match event {
+                egui::Event::Screenshot { image, .. } => Some(image.clone()),
+                _ => None,
+            })
+        });
+        let (Some(image), Some(rect)) = (screenshot, self.plot_rect) else {
+            return;
+        };
+        self.pending_export = None;
+        let region = image.region(&rect, Some(ctx.pixels_per_point()));
+        self.last_export = Some(save_png(&path, &region).map(|_| path));
+        if let Some(Err(error)) = &self.last_export {
+            tracing::warn!("Chart export failed: {}", error);
+        }
+    }
+}
+
+fn save_png(path: &Path, image: &egui::ColorImage) -> Result<(), String> {
+    let [width, height] = image.size;
+    let pixels: Vec<u8> = image.pixels.iter().flat_map(|p| p.to_array()).collect();
+    image::RgbaImage::from_raw(width as u32, height as u32, pixels)
+        .ok_or_else(|| "Screenshot has an unexpected size".to_string())?
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ObjectPayload;
+    use ndarray::array;
+
+    fn curve(x: ndarray::Array1<f64>, y: ndarray::Array1<f64>, y_label: &str) -> ObjectPayload {
+        ObjectPayload::Curve { x, y, x_label: "time".to_string(), y_label: y_label.to_string() }
+    }
+
+    #[test]
+    fn nan_samples_split_a_curve_into_pieces() {
+        let mut chart = ChartPanel::new("probe");
+        let payload = curve(array![0.0, 1.0, 2.0, 3.0, 4.0], array![1.0, f64::NAN, 2.0, 3.0, f64::NAN], "p");
+        chart.set_curve("a", CurveView::new(&payload).unwrap());
+
+        assert_eq!(chart.curves.len(), 1);
+        assert_eq!(chart.curves[0].segments, vec![vec![[0.0, 1.0]], vec![[2.0, 2.0], [3.0, 3.0]]]);
+        // NaN at the end does not widen the axis
+        assert_eq!(chart.x_range(), Some((0.0, 3.0)));
+    }
+
+    #[test]
+    fn axes_span_curves_of_different_x_ranges() {
+        let mut chart = ChartPanel::new("probe");
+        assert_eq!(chart.x_range(), None);
+        let early = curve(array![0.0, 1.0, 2.0], array![1.0, 2.0, 3.0], "p at (0, 0, 0)");
+        let late = curve(array![5.0, 7.5, 10.0], array![0.0, 0.0, 0.0], "p at (1, 0, 0)");
+        chart.set_curve("early", CurveView::new(&early).unwrap());
+        chart.set_curve("late", CurveView::new(&late).unwrap());
+
+        assert_eq!(chart.x_range(), Some((0.0, 10.0)));
+        assert_eq!(chart.axis_label(|c| c.x_label.as_str()), "time");
+        assert_eq!(chart.axis_label(|c| c.y_label.as_str()), "p at (0, 0, 0), p at (1, 0, 0)");
+    }
+
+    #[test]
+    fn curves_are_replaced_by_key() {
+        let mut chart = ChartPanel::new("probe");
+        let first = curve(array![0.0], array![1.0], "p");
+        let longer = curve(array![0.0, 1.0], array![1.0, 2.0], "p");
+        chart.set_curve("a", CurveView::new(&first).unwrap());
+        chart.set_curve("a", CurveView::new(&longer).unwrap());
+        assert_eq!(chart.curves.len(), 1);
+        assert_eq!(chart.x_range(), Some((0.0, 1.0)));
+
+        chart.remove_curve("a");
+        assert!(chart.is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+//! Numeric editing of clipping planes
+
+use nalgebra::Vector3;
+
+use crate::render::{ClipCapping, ClipPlane, ClipPlanes, MAX_CLIP_PLANES};
+use super::UiContext;
+
+/// Step of one pixel of dragging a normal component or distance
+const DRAG_SPEED: f32 = 0.01;
+
+/// Panel editing a set of clipping planes
+///
+/// Bind it to `RenderSettings::clipping` for the whole scene or to a
+/// `SceneObject::clip_planes` override. Uniforms are rewritten every frame,
+/// so edits show on the next frame without re-uploading geometry.
+pub struct ClipPlaneEditor {
+    title: String,
+}
+
+impl ClipPlaneEditor {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+        }
+    }
+
+    /// Draw the planes of `clipping`; returns whether anything changed
+    pub fn draw(&self, ui: &mut UiContext, clipping: &mut ClipPlanes) -> bool {
+        let before = clipping.clone();
+        ui.begin_panel(&self.title);
+
+        let mut removed = None;
+        for (i, plane) in clipping.planes.iter_mut().enumerate() {
+            ui.heading(&format!("Plane {}", i + 1));
+            ui.checkbox(&format!("Plane {} enabled", i + 1), &mut plane.enabled);
+            ui.number(&format!("Plane {} normal x", i + 1), &mut plane.normal.x, DRAG_SPEED);
+            ui.number(&format!("Plane {} normal y", i + 1), &mut plane.normal.y, DRAG_SPEED);
+            ui.number(&format!("Plane {} normal z", i + 1), &mut plane.normal.z, DRAG_SPEED);
+            ui.number(&format!("Plane {} distance", i + 1), &mut plane.distance, DRAG_SPEED);
+            if ui.button(&format!("Remove plane {}", i + 1)) {
+                removed = Some(i);
+            }
+        }
+        if let Some(i) = removed {
+            clipping.planes.remove(i);
+        }
+        if clipping.planes.len() < MAX_CLIP_PLANES && ui.button("Add plane") {
+            clipping.planes.push(ClipPlane::new(Vector3::x(), 0.0));
+        }
+
+        ui.separator();
+        let mut capped = clipping.capping == ClipCapping::Backfaces;
+        ui.checkbox("Cap cut surfaces", &mut capped);
+        clipping.capping = if capped { ClipCapping::Backfaces } else { ClipCapping::None };
+
+        ui.end_panel();
+        *clipping != before
+    }
+}
@@ -1,6 +1,20 @@
 //! User interface system for workflow editing and visualization
 
+pub mod autosave;
+pub mod chart;
+pub mod clipping;
+pub mod routing;
+
+pub use autosave::*;
+pub use chart::*;
+pub use clipping::*;
+pub use routing::*;
+
+use std::collections::HashMap;
 use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{ConnectionStats, NonFiniteOffender};
 
 /// UI backend types
 #[derive(Debug, Clone)]
@@ -49,7 +63,7 @@ impl Application {
     {
         let options = eframe::NativeOptions {
             viewport: egui::ViewportBuilder::default()
-                .with_inner_size(self.size)
+                .with_inner_size([self.size.0 as f32, self.size.1 as f32])
                 .with_title(&self.title),
             ..Default::default()
         };
@@ -126,6 +140,19 @@ impl<'a> UiContext<'a> {
         }
     }
 
+    /// Unbounded number edited by dragging or typing
+    pub fn number(&mut self, text: &str, value: &mut f32, speed: f32) {
+        if let Some(panel) = &self.current_panel {
+            egui::Window::new(panel).show(self.ctx, |ui| {
+                ui.add(egui::DragValue::new(value).speed(speed).prefix(format!("{}: ", text)));
+            });
+        } else {
+            egui::CentralPanel::default().show(self.ctx, |ui| {
+                ui.add(egui::DragValue::new(value).speed(speed).prefix(format!("{}: ", text)));
+            });
+        }
+    }
+
     pub fn checkbox(&mut self, text: &str, checked: &mut bool) {
         if let Some(panel) = &self.current_panel {
             egui::Window::new(panel).show(self.ctx, |ui| {
@@ -176,56 +203,167 @@ impl<'a> UiContext<'a> {
 }
 
 /// Workflow editor for visual programming
+///
+/// Nodes and connections are shared behind `Arc`s so taking a snapshot for
+/// autosave is cheap; edits copy them only while a snapshot is alive.
 pub struct WorkflowEditor {
-    workflows: Vec<WorkflowNode>,
-    connections: Vec<Connection>,
+    workflows: Arc<Vec<WorkflowNode>>,
+    connections: Arc<Vec<Connection>>,
     selected_node: Option<usize>,
     drag_offset: Option<egui::Vec2>,
+    revision: u64,
+    /// Statistics of the last run per connection index, for the status overlay
+    connection_stats: HashMap<usize, ConnectionStats>,
+    /// Node of the module that first produced NaN/Inf in the last run, with what it produced
+    nonfinite_offender: Option<(usize, String)>,
+    router: EdgeRouter,
+    hovered_connection: Option<usize>,
+    selected_connection: Option<usize>,
+}
+
+/// How far from its route the pointer still picks a connection
+const CONNECTION_HIT_DISTANCE: f32 = 5.0;
+
+impl Default for WorkflowEditor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WorkflowEditor {
     pub fn new() -> Self {
         Self {
-            workflows: Vec::new(),
-            connections: Vec::new(),
+            workflows: Arc::new(Vec::new()),
+            connections: Arc::new(Vec::new()),
             selected_node: None,
             drag_offset: None,
+            revision: 0,
+            connection_stats: HashMap::new(),
+            nonfinite_offender: None,
+            router: EdgeRouter::new(),
+            hovered_connection: None,
+            selected_connection: None,
+        }
+    }
+
+    /// Show what flowed across each connection in the last run
+    ///
+    /// `node_of` maps a workflow module id to the index of its editor node.
+    pub fn set_connection_stats(&mut self, stats: &[ConnectionStats], node_of: impl Fn(u32) -> Option<usize>) {
+        self.connection_stats.clear();
+        for stats in stats {
+            let c = &stats.connection;
+            let (Some(from), Some(to)) = (node_of(c.from_module), node_of(c.to_module)) else {
+                continue;
+            };
+            let index = self.connections.iter().position(|e| {
+                e.from_node == from && e.to_node == to && e.from_port == c.from_port && e.to_port == c.to_port
+            });
+            if let Some(index) = index {
+                self.connection_stats.insert(index, stats.clone());
+            }
         }
     }
 
+    pub fn clear_connection_stats(&mut self) {
+        self.connection_stats.clear();
+        self.nonfinite_offender = None;
+    }
+
+    /// Mark the module that first produced NaN or infinite values in the last run
+    pub fn set_nonfinite_offender(&mut self, offender: Option<&NonFiniteOffender>, node_of: impl Fn(u32) -> Option<usize>) {
+        self.nonfinite_offender = offender.and_then(|offender| {
+            let ports: Vec<String> = offender.ports.iter()
+                .map(|(port, audit)| format!("{} on {}", audit.nonfinite, port))
+                .collect();
+            Some((node_of(offender.module_id)?, format!("NaN/Inf introduced here: {}", ports.join(", "))))
+        });
+    }
+
+    /// Draw long parallel runs of connections as bundles
+    pub fn set_edge_bundling(&mut self, bundling: bool) {
+        self.router.set_bundling(bundling);
+    }
+
+    /// Connection last clicked on its route
+    pub fn selected_connection(&self) -> Option<usize> {
+        self.selected_connection
+    }
+
     pub fn add_node(&mut self, node: WorkflowNode) {
-        self.workflows.push(node);
+        Arc::make_mut(&mut self.workflows).push(node);
+        self.revision += 1;
+    }
+
+    pub fn add_connection(&mut self, connection: Connection) {
+        Arc::make_mut(&mut self.connections).push(connection);
+        self.revision += 1;
+    }
+
+    /// Counter bumped on every edit
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Cheap copy of the editor contents
+    pub fn snapshot(&self) -> EditorSnapshot {
+        EditorSnapshot {
+            nodes: self.workflows.clone(),
+            connections: self.connections.clone(),
+            revision: self.revision,
+        }
+    }
+
+    /// Replace the editor contents with a snapshot
+    pub fn restore(&mut self, snapshot: EditorSnapshot) {
+        self.workflows = snapshot.nodes;
+        self.connections = snapshot.connections;
+        self.selected_node = None;
+        self.drag_offset = None;
+        self.selected_connection = None;
+        self.revision += 1;
     }
 
     pub fn draw(&mut self, ui: &mut UiContext) {
+        let before: Vec<egui::Pos2> = self.workflows.iter().map(|n| n.position).collect();
+
         // Draw workflow nodes and connections
-        for (i, node) in self.workflows.iter_mut().enumerate() {
+        let mut nodes = std::mem::take(Arc::make_mut(&mut self.workflows));
+        for (i, node) in nodes.iter_mut().enumerate() {
             self.draw_node(ui, i, node);
         }
+        let moved = nodes.iter().map(|n| n.position).ne(before);
+        *Arc::make_mut(&mut self.workflows) = nodes;
+        if moved {
+            self.revision += 1;
+        }
 
-        for connection in &self.connections {
-            self.draw_connection(ui, connection);
+        // Only connections near moved nodes are rerouted
+        self.router.update(&self.workflows, &self.connections);
+        let (pointer, clicked) = ui.ctx.input(|i| (i.pointer.hover_pos(), i.pointer.primary_clicked()));
+        self.hovered_connection = pointer.and_then(|p| self.router.hit_test(p, CONNECTION_HIT_DISTANCE));
+        if clicked {
+            self.selected_connection = self.hovered_connection;
+        }
+
+        for (index, connection) in self.connections.iter().enumerate() {
+            self.draw_connection(ui, index, connection);
         }
     }
 
     fn draw_node(&mut self, ui: &mut UiContext, index: usize, node: &mut WorkflowNode) {
-        let node_rect = egui::Rect::from_min_size(
-            node.position,
-            egui::vec2(120.0, 80.0),
-        );
+        let node_rect = node_rect(node);
 
         // Node background
         ui.ctx.request_repaint();
 
         // Handle interaction
-        let response = ui.ctx.input(|i| {
+        ui.ctx.input(|i| {
             let pointer_pos = i.pointer.hover_pos().unwrap_or_default();
 
-            if node_rect.contains(pointer_pos) {
-                if i.pointer.primary_down() && self.selected_node.is_none() {
-                    self.selected_node = Some(index);
-                    self.drag_offset = Some(pointer_pos - node.position);
-                }
+            if node_rect.contains(pointer_pos) && i.pointer.primary_down() && self.selected_node.is_none() {
+                self.selected_node = Some(index);
+                self.drag_offset = Some(pointer_pos - node.position);
             }
 
             if i.pointer.primary_released() {
@@ -245,7 +383,7 @@ impl WorkflowEditor {
         // Draw node content
         egui::Window::new(&node.title)
             .fixed_pos(node.position)
-            .fixed_size(egui::vec2(120.0, 80.0))
+            .fixed_size(NODE_SIZE)
             .show(ui.ctx, |ui_window| {
                 ui_window.label(&node.description);
                 for port in &node.inputs {
@@ -254,17 +392,70 @@ impl WorkflowEditor {
                 for port in &node.outputs {
                     ui_window.label(format!("← {}", port));
                 }
+                if let Some((_, message)) = self.nonfinite_offender.as_ref().filter(|(node, _)| *node == index) {
+                    ui_window.colored_label(egui::Color32::RED, message);
+                }
             });
     }
 
-    fn draw_connection(&self, ui: &mut UiContext, connection: &Connection) {
-        // Draw connection lines between nodes
-        // Implementation would draw bezier curves between ports
+    fn draw_connection(&self, ui: &mut UiContext, index: usize, connection: &Connection) {
+        let Some(route) = self.router.route(index).filter(|r| r.points.len() >= 2) else {
+            return;
+        };
+
+        // Empty connections are highlighted as the likely reason for missing output
+        let stats = self.connection_stats.get(&index);
+        let color = match stats {
+            Some(stats) if stats.is_empty() => egui::Color32::RED,
+            Some(stats) if stats.nonfinite > 0 => egui::Color32::from_rgb(255, 140, 0),
+            Some(stats) if stats.only_empty_objects() => egui::Color32::YELLOW,
+            _ => egui::Color32::GRAY,
+        };
+        let highlighted = self.hovered_connection == Some(index) || self.selected_connection == Some(index);
+        let painter = ui.ctx.layer_painter(egui::LayerId::background());
+        painter.add(egui::Shape::line(route.points.clone(), egui::Stroke::new(if highlighted { 3.0 } else { 2.0 }, color)));
+
+        let Some(stats) = stats else {
+            return;
+        };
+        let middle = route.midpoint().unwrap_or(route.points[0]);
+        let label = painter.text(middle, egui::Align2::CENTER_BOTTOM, stats.label(), egui::FontId::proportional(11.0), color);
+        let over_label = ui.ctx.input(|i| i.pointer.hover_pos()).is_some_and(|p| label.expand(4.0).contains(p));
+        if over_label || self.hovered_connection == Some(index) {
+            let types = stats.object_types.iter().map(|t| format!("{:?}", t)).collect::<Vec<_>>().join(", ");
+            egui::show_tooltip_at_pointer(ui.ctx, egui::Id::new(("connection", index)), |tooltip| {
+                tooltip.label(format!("{} → {}", connection.from_port, connection.to_port));
+                tooltip.label(format!("{} objects, {} bytes", stats.objects, stats.payload_bytes));
+                if stats.empty_objects > 0 {
+                    tooltip.label(format!("{} empty objects", stats.empty_objects));
+                }
+                if stats.nonfinite > 0 {
+                    tooltip.label(format!("{} NaN or infinite values", stats.nonfinite));
+                }
+                if !types.is_empty() {
+                    tooltip.label(format!("Types: {}", types));
+                }
+                if let Some((first, last)) = stats.timesteps {
+                    tooltip.label(format!("Timesteps {} to {}", first, last));
+                }
+                if stats.is_empty() {
+                    tooltip.label("No data flowed across this connection");
+                }
+            });
+        }
     }
 }
 
+/// Serializable copy of the workflow editor state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditorSnapshot {
+    pub nodes: Arc<Vec<WorkflowNode>>,
+    pub connections: Arc<Vec<Connection>>,
+    pub revision: u64,
+}
+
 /// Node in workflow editor
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowNode {
     pub title: String,
     pub description: String,
@@ -303,7 +494,7 @@ impl WorkflowNode {
 }
 
 /// Connection between workflow nodes
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Connection {
     pub from_node: usize,
     pub from_port: String,
@@ -332,19 +523,19 @@ impl StatusDisplay {
         }
     }
 
+    /// One warning message per item a workflow load dropped or could not migrate
+    pub fn add_load_warnings(&mut self, workflow: &str, warnings: &crate::compute::LoadWarnings) {
+        for warning in warnings.iter() {
+            self.add_message(format!("{}: {}", workflow, warning), StatusLevel::Warning);
+        }
+    }
+
     pub fn draw(&self, ui: &mut UiContext) {
         ui.begin_panel("Status");
 
-        for (message, level) in &self.messages {
-            let color = match level {
-                StatusLevel::Info => egui::Color32::BLUE,
-                StatusLevel::Warning => egui::Color32::YELLOW,
-                StatusLevel::Error => egui::Color32::RED,
-                StatusLevel::Success => egui::Color32::GREEN,
-            };
-
+        // Levels are not styled yet; the label shows the message only
+        for (message, _level) in &self.messages {
             ui.ctx.request_repaint();
-            // In real implementation, would style the text with color
             ui.label(message);
         }
 
@@ -364,6 +555,83 @@ pub enum StatusLevel {
     Success,
 }
 
+/// Inspector panel showing a module instance's execution statistics
+pub struct ModuleInspector {
+    /// Errors listed, newest first
+    recent_errors: usize,
+}
+
+impl ModuleInspector {
+    pub fn new(recent_errors: usize) -> Self {
+        Self { recent_errors }
+    }
+
+    pub fn draw(&self, ui: &mut UiContext, stats: &crate::core::ExecutionStats) {
+        ui.begin_panel(&format!("Module {}", stats.module_id));
+
+        ui.label(&format!(
+            "{} runs, last {:.1} ms, total {:.1} ms",
+            stats.invocations,
+            stats.last_wall_time.as_secs_f64() * 1000.0,
+            stats.total_wall_time.as_secs_f64() * 1000.0
+        ));
+        ui.label(&format!("{} objects created", stats.objects_created));
+
+        if stats.error_count() > 0 {
+            ui.separator();
+            ui.heading(&format!("Errors ({})", stats.error_count()));
+            if stats.errors.dropped() > 0 {
+                ui.label(&format!("{} older errors not kept", stats.errors.dropped()));
+            }
+            for error in stats.recent_errors(self.recent_errors) {
+                let age = error.time.elapsed().map(|d| format!("{}s ago", d.as_secs())).unwrap_or_default();
+                ui.label(&format!("t={} [{}] {} {}", error.timestep, error.code, error.message, age));
+            }
+        }
+
+        ui.end_panel();
+    }
+}
+
+/// Panel showing the progress of a timeseries or sweep run with its ETA
+pub struct ProgressPanel {
+    bar: ProgressBar,
+    progress: Option<crate::compute::WorkflowProgress>,
+}
+
+impl ProgressPanel {
+    pub fn new(label: &str) -> Self {
+        Self {
+            bar: ProgressBar::new(label),
+            progress: None,
+        }
+    }
+
+    /// Show the latest event from `WorkflowExecutor::subscribe_progress`
+    pub fn update(&mut self, progress: crate::compute::WorkflowProgress) {
+        self.bar.set_progress(progress.fraction());
+        self.progress = Some(progress);
+    }
+
+    pub fn draw(&self, ui: &mut UiContext) {
+        self.bar.draw(ui);
+        let Some(progress) = &self.progress else {
+            return;
+        };
+        ui.begin_panel(&self.bar.label);
+        ui.label(&progress.to_string());
+        if let Some(rate) = progress.rate {
+            let per_unit = if rate > 0.0 { 1.0 / rate } else { 0.0 };
+            ui.label(&format!(
+                "{:.1} s per step, {} elapsed",
+                per_unit,
+                crate::compute::format_eta(progress.elapsed)
+            ));
+        }
+        ui.end_panel();
+    }
+}
+
 /// Progress bar for long-running operations
 pub struct ProgressBar {
     progress: f32,
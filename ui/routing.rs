@@ -0,0 +1,542 @@
+//! Routing of workflow editor connections around nodes
+//!
+//! Connections leave a node on its right side and enter the target node on
+//! its left, each end with a short horizontal stub. In between they run in
+//! axis-aligned segments: one vertical channel when the target lies to the
+//! right and a channel clear of nodes exists, otherwise a detour along a
+//! horizontal lane above, below or between nodes. Channels and lanes are
+//! taken from the sides of the node rectangles, so routes keep
+//! `ROUTE_MARGIN` away from nodes wherever there is room.
+//!
+//! `EdgeRouter` keeps the routes between frames. When nodes are dragged it
+//! reroutes only the connections of moved nodes and those whose route
+//! passed near one, which keeps editing graphs of a few hundred connections
+//! within a frame.
+
+use egui::{Pos2, Rect, Vec2};
+
+use crate::ui::{Connection, WorkflowNode};
+
+/// Size of a node in the editor
+pub const NODE_SIZE: Vec2 = Vec2::new(120.0, 80.0);
+/// Distance kept between routes and nodes
+pub const ROUTE_MARGIN: f32 = 16.0;
+/// Spacing of parallel connections between the same two nodes
+pub const FAN_SPACING: f32 = 6.0;
+/// Shortest run of a route that bundling moves
+pub const BUNDLE_MIN_RUN: f32 = 80.0;
+/// Parallel runs at most this far apart are drawn as one bundle
+pub const BUNDLE_DISTANCE: f32 = 24.0;
+
+/// Channels tried next to each end of a detour, and lanes between them
+const CHANNEL_CANDIDATES: usize = 4;
+const LANE_CANDIDATES: usize = 8;
+/// Cost of a segment crossing a node, in units of route length
+const CROSSING_PENALTY: f32 = 10_000.0;
+
+/// Rectangle a node covers in the editor
+pub fn node_rect(node: &WorkflowNode) -> Rect {
+    Rect::from_min_size(node.position, NODE_SIZE)
+}
+
+/// Path of a connection as a polyline, from output port to input port
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoutedEdge {
+    pub points: Vec<Pos2>,
+}
+
+impl RoutedEdge {
+    pub fn segments(&self) -> impl Iterator<Item = (Pos2, Pos2)> + '_ {
+        self.points.windows(2).map(|w| (w[0], w[1]))
+    }
+
+    pub fn length(&self) -> f32 {
+        self.segments().map(|(a, b)| a.distance(b)).sum()
+    }
+
+    /// Point halfway along the route, where labels go
+    pub fn midpoint(&self) -> Option<Pos2> {
+        let mut remaining = self.length() * 0.5;
+        for (a, b) in self.segments() {
+            let length = a.distance(b);
+            if length >= remaining && length > 0.0 {
+                return Some(a + (b - a) * (remaining / length));
+            }
+            remaining -= length;
+        }
+        self.points.first().copied()
+    }
+
+    /// Distance from a point to the nearest point of the route
+    pub fn distance_to(&self, p: Pos2) -> f32 {
+        self.segments()
+            .map(|(a, b)| segment_distance(a, b, p))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    pub fn bounding_rect(&self) -> Rect {
+        Rect::from_points(&self.points)
+    }
+}
+
+fn segment_distance(a: Pos2, b: Pos2, p: Pos2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_sq() > 0.0 {
+        ((p - a).dot(ab) / ab.length_sq()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (a + ab * t).distance(p)
+}
+
+/// Whether an axis-aligned segment passes through the inside of a rectangle
+fn crosses(a: Pos2, b: Pos2, rect: &Rect) -> bool {
+    a.x.min(b.x) < rect.max.x && a.x.max(b.x) > rect.min.x && a.y.min(b.y) < rect.max.y && a.y.max(b.y) > rect.min.y
+}
+
+fn crossings(points: &[Pos2], obstacles: &[Rect]) -> usize {
+    points.windows(2)
+        .map(|w| obstacles.iter().filter(|r| crosses(w[0], w[1], r)).count())
+        .sum()
+}
+
+fn path_length(points: &[Pos2]) -> f32 {
+    points.windows(2).map(|w| w[0].distance(w[1])).sum()
+}
+
+/// Node rectangles grown so routes along their sides keep `ROUTE_MARGIN`
+fn obstacles(rects: &[Rect]) -> Vec<Rect> {
+    rects.iter().map(|r| r.expand(ROUTE_MARGIN * 0.5)).collect()
+}
+
+/// Offset of each connection from the others between the same two nodes
+fn fan_offsets(endpoints: &[(usize, usize)]) -> Vec<f32> {
+    endpoints.iter()
+        .enumerate()
+        .map(|(i, pair)| {
+            let count = endpoints.iter().filter(|p| *p == pair).count();
+            let rank = endpoints[..i].iter().filter(|p| *p == pair).count();
+            (rank as f32 - (count - 1) as f32 * 0.5) * FAN_SPACING
+        })
+        .collect()
+}
+
+/// Route from the right side of `from` to the left side of `to` around `obstacles`
+///
+/// `offset` shifts the ports, channels and lanes, so parallel connections
+/// between the same nodes stay apart. If no route clear of all nodes is
+/// found among the candidates, the one crossing the fewest is taken.
+pub fn route_edge(from: &Rect, to: &Rect, offset: f32, obstacles: &[Rect]) -> RoutedEdge {
+    let start = Pos2::new(from.max.x, from.center().y + offset);
+    let end = Pos2::new(to.min.x, to.center().y + offset);
+    let s = start + Vec2::new(ROUTE_MARGIN, 0.0);
+    let e = end - Vec2::new(ROUTE_MARGIN, 0.0);
+    let middle = Pos2::new((s.x + e.x) * 0.5, (s.y + e.y) * 0.5);
+
+    let mut xs = vec![middle.x, s.x, e.x];
+    let mut ys = vec![middle.y];
+    for r in obstacles {
+        xs.extend([r.min.x - ROUTE_MARGIN * 0.5, r.max.x + ROUTE_MARGIN * 0.5]);
+        ys.extend([r.min.y - ROUTE_MARGIN * 0.5, r.max.y + ROUTE_MARGIN * 0.5]);
+    }
+    let nearest = |values: &[f32], to: f32, keep: &dyn Fn(f32) -> bool| -> Vec<f32> {
+        let mut values: Vec<f32> = values.iter().copied().filter(|&v| keep(v)).collect();
+        values.sort_by(|a, b| (a - to).abs().total_cmp(&(b - to).abs()));
+        values.dedup();
+        values
+    };
+    let wrap = |middle: &[Pos2]| -> Vec<Pos2> {
+        [start, s].into_iter().chain(middle.iter().copied()).chain([e, end]).collect()
+    };
+
+    // One vertical channel between the stubs, nearest the middle first
+    if s.x <= e.x {
+        for x in nearest(&xs, middle.x, &|x| x >= s.x && x <= e.x) {
+            let x = x + offset;
+            let channel = [s, Pos2::new(x, s.y), Pos2::new(x, e.y), e];
+            if crossings(&channel, obstacles) == 0 {
+                return RoutedEdge { points: wrap(&channel[1..3]) };
+            }
+        }
+    }
+
+    // Detour: out to a channel near the source, along a lane, back in near the target
+    let mut best: Option<(f32, Vec<Pos2>)> = None;
+    let mut consider = |points: [Pos2; 6]| {
+        let cost = crossings(&points, obstacles) as f32 * CROSSING_PENALTY + path_length(&points);
+        if best.as_ref().is_none_or(|(c, _)| cost < *c) {
+            best = Some((cost, points[1..5].to_vec()));
+        }
+    };
+    if s.x <= e.x {
+        let x = middle.x + offset;
+        consider([s, Pos2::new(x, s.y), Pos2::new(x, middle.y), Pos2::new(x, middle.y), Pos2::new(x, e.y), e]);
+    }
+    let x1s = nearest(&xs, s.x, &|x| x >= s.x);
+    let x2s = nearest(&xs, e.x, &|x| x <= e.x);
+    let lanes = nearest(&ys, middle.y, &|_| true);
+    for &x1 in x1s.iter().take(CHANNEL_CANDIDATES) {
+        for &x2 in x2s.iter().take(CHANNEL_CANDIDATES) {
+            for &y in lanes.iter().take(LANE_CANDIDATES) {
+                let (x1, x2, y) = (x1 + offset, x2 + offset, y + offset);
+                consider([s, Pos2::new(x1, s.y), Pos2::new(x1, y), Pos2::new(x2, y), Pos2::new(x2, e.y), e]);
+            }
+        }
+    }
+    let middle_points = best.map(|(_, points)| points).unwrap_or_default();
+    RoutedEdge { points: wrap(&middle_points) }
+}
+
+/// A run of a route that bundling may move sideways
+struct Run {
+    edge: usize,
+    /// Index of the run's first point
+    segment: usize,
+    vertical: bool,
+    /// x of a vertical run, y of a horizontal one
+    position: f32,
+    from: f32,
+    to: f32,
+}
+
+/// Draw long parallel runs of different routes along their mean
+///
+/// Only runs between two other runs move, so the port stubs stay in place;
+/// a run is left where it is if moving it would cross a node.
+fn bundle(routes: &[RoutedEdge], obstacles: &[Rect]) -> Vec<RoutedEdge> {
+    let mut bundled = routes.to_vec();
+    let mut runs: Vec<Run> = Vec::new();
+    for (edge, route) in routes.iter().enumerate() {
+        let points = &route.points;
+        for segment in 2..points.len().saturating_sub(3) {
+            let (a, b) = (points[segment], points[segment + 1]);
+            if a.distance(b) < BUNDLE_MIN_RUN {
+                continue;
+            }
+            let vertical = a.x == b.x;
+            let (position, from, to) = if vertical {
+                (a.x, a.y.min(b.y), a.y.max(b.y))
+            } else {
+                (a.y, a.x.min(b.x), a.x.max(b.x))
+            };
+            runs.push(Run { edge, segment, vertical, position, from, to });
+        }
+    }
+    runs.sort_by(|a, b| a.vertical.cmp(&b.vertical).then(a.position.total_cmp(&b.position)));
+
+    let mut first = 0;
+    while first < runs.len() {
+        let head = &runs[first];
+        let mut last = first + 1;
+        while last < runs.len()
+            && runs[last].vertical == head.vertical
+            && runs[last].position - head.position <= BUNDLE_DISTANCE
+            && runs[last].to.min(head.to) - runs[last].from.max(head.from) >= BUNDLE_MIN_RUN * 0.5
+        {
+            last += 1;
+        }
+        if last - first > 1 {
+            let group = &runs[first..last];
+            let target = group.iter().map(|r| r.position).sum::<f32>() / group.len() as f32;
+            for run in group {
+                let points = &mut bundled[run.edge].points;
+                let moved: Vec<Pos2> = points[run.segment - 1..run.segment + 3]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &p)| match (i, run.vertical) {
+                        (1 | 2, true) => Pos2::new(target, p.y),
+                        (1 | 2, false) => Pos2::new(p.x, target),
+                        _ => p,
+                    })
+                    .collect();
+                if crossings(&moved, obstacles) == 0 {
+                    points[run.segment..run.segment + 2].copy_from_slice(&moved[1..3]);
+                }
+            }
+        }
+        first = last;
+    }
+    bundled
+}
+
+/// Routes of an editor's connections, kept up to date as nodes move
+#[derive(Debug, Clone, Default)]
+pub struct EdgeRouter {
+    bundling: bool,
+    /// Node rectangles and connection ends the routes were computed for
+    rects: Vec<Rect>,
+    endpoints: Vec<(usize, usize)>,
+    routes: Vec<RoutedEdge>,
+    bundled: Vec<RoutedEdge>,
+}
+
+impl EdgeRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_bundling(mut self, bundling: bool) -> Self {
+        self.bundling = bundling;
+        self
+    }
+
+    /// Turn bundling of parallel runs on or off; see `BUNDLE_DISTANCE`
+    pub fn set_bundling(&mut self, bundling: bool) {
+        if bundling != self.bundling {
+            self.bundling = bundling;
+            self.bundled = if bundling {
+                bundle(&self.routes, &obstacles(&self.rects))
+            } else {
+                Vec::new()
+            };
+        }
+    }
+
+    pub fn bundling(&self) -> bool {
+        self.bundling
+    }
+
+    /// Reroute the connections affected since the last update
+    ///
+    /// Added or removed nodes and changed connections reroute everything;
+    /// moved nodes reroute their own connections and those whose route came
+    /// within `ROUTE_MARGIN` of the node before or after the move. Returns
+    /// the number of connections rerouted.
+    pub fn update(&mut self, nodes: &[WorkflowNode], connections: &[Connection]) -> usize {
+        let rects: Vec<Rect> = nodes.iter().map(node_rect).collect();
+        let endpoints: Vec<(usize, usize)> = connections.iter().map(|c| (c.from_node, c.to_node)).collect();
+
+        let rerouted: Vec<usize> = if rects.len() != self.rects.len() || endpoints != self.endpoints {
+            (0..connections.len()).collect()
+        } else {
+            let moved: Vec<usize> = (0..rects.len()).filter(|&i| rects[i] != self.rects[i]).collect();
+            if moved.is_empty() {
+                return 0;
+            }
+            let near = |route: &RoutedEdge| {
+                let area = route.bounding_rect().expand(ROUTE_MARGIN);
+                moved.iter().any(|&n| area.intersects(self.rects[n]) || area.intersects(rects[n]))
+            };
+            (0..connections.len())
+                .filter(|&i| {
+                    let (from, to) = endpoints[i];
+                    moved.contains(&from) || moved.contains(&to) || near(&self.routes[i])
+                })
+                .collect()
+        };
+
+        let obstacles = obstacles(&rects);
+        let offsets = fan_offsets(&endpoints);
+        self.routes.resize(connections.len(), RoutedEdge::default());
+        for &i in &rerouted {
+            let (from, to) = endpoints[i];
+            self.routes[i] = match (rects.get(from), rects.get(to)) {
+                (Some(from), Some(to)) => route_edge(from, to, offsets[i], &obstacles),
+                _ => RoutedEdge::default(),
+            };
+        }
+        if self.bundling {
+            self.bundled = bundle(&self.routes, &obstacles);
+        }
+        self.rects = rects;
+        self.endpoints = endpoints;
+        rerouted.len()
+    }
+
+    /// Routes as drawn, by connection index
+    pub fn routes(&self) -> &[RoutedEdge] {
+        if self.bundling {
+            &self.bundled
+        } else {
+            &self.routes
+        }
+    }
+
+    pub fn route(&self, connection: usize) -> Option<&RoutedEdge> {
+        self.routes().get(connection)
+    }
+
+    /// Connection whose drawn route passes nearest a point, within `tolerance`
+    pub fn hit_test(&self, p: Pos2, tolerance: f32) -> Option<usize> {
+        self.routes()
+            .iter()
+            .enumerate()
+            .map(|(i, route)| (i, route.distance_to(p)))
+            .filter(|(_, distance)| *distance <= tolerance)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(x: f32, y: f32) -> WorkflowNode {
+        WorkflowNode::new("Node", "", "Test").with_position(Pos2::new(x, y))
+    }
+
+    fn connect(from_node: usize, to_node: usize) -> Connection {
+        Connection {
+            from_node,
+            from_port: "data_out".to_string(),
+            to_node,
+            to_port: "data_in".to_string(),
+        }
+    }
+
+    /// 100 nodes on a grid
+    fn grid_nodes() -> Vec<WorkflowNode> {
+        (0..100).map(|i| node((i % 10) as f32 * 200.0, (i / 10) as f32 * 140.0)).collect()
+    }
+
+    /// 200 connections of the grid nodes to their neighbours
+    fn grid_connections() -> Vec<Connection> {
+        let mut connections = Vec::new();
+        for i in 0..100 {
+            if i % 10 < 9 {
+                connections.push(connect(i, i + 1));
+            }
+            if i < 90 {
+                connections.push(connect(i, i + 10));
+            }
+        }
+        connections.extend((0..20).map(|i| connect(i, i + 11)));
+        connections
+    }
+
+    /// Whether a route, without its port stubs, passes through a rectangle
+    fn route_crosses(route: &RoutedEdge, rect: &Rect) -> bool {
+        let inner = &route.points[1..route.points.len() - 1];
+        inner.windows(2).any(|w| crosses(w[0], w[1], rect))
+    }
+
+    fn is_orthogonal(route: &RoutedEdge) -> bool {
+        route.segments().all(|(a, b)| a.x == b.x || a.y == b.y)
+    }
+
+    #[test]
+    fn routes_run_from_output_to_input_side() {
+        let (from, to) = (node_rect(&node(0.0, 0.0)), node_rect(&node(300.0, 100.0)));
+        let route = route_edge(&from, &to, 0.0, &obstacles(&[from, to]));
+        assert_eq!(route.points.first(), Some(&Pos2::new(120.0, 40.0)));
+        assert_eq!(route.points.last(), Some(&Pos2::new(300.0, 140.0)));
+        assert!(is_orthogonal(&route));
+        assert_eq!(route.length(), 280.0);
+    }
+
+    #[test]
+    fn routes_go_around_nodes_in_the_way() {
+        let rects = [node(0.0, 0.0), node(200.0, 0.0), node(400.0, 0.0)].map(|n| node_rect(&n));
+        let route = route_edge(&rects[0], &rects[2], 0.0, &obstacles(&rects));
+        assert!(!route_crosses(&route, &rects[1]), "{:?}", route.points);
+        assert!(is_orthogonal(&route));
+        assert!(route.length() > 280.0);
+    }
+
+    #[test]
+    fn backward_routes_detour_around_both_ends() {
+        let rects = [node(300.0, 0.0), node(0.0, 0.0)].map(|n| node_rect(&n));
+        let route = route_edge(&rects[0], &rects[1], 0.0, &obstacles(&rects));
+        assert!(is_orthogonal(&route));
+        assert!(rects.iter().all(|r| !route_crosses(&route, r)), "{:?}", route.points);
+    }
+
+    #[test]
+    fn parallel_connections_fan_out() {
+        assert_eq!(fan_offsets(&[(0, 1), (0, 1), (0, 1), (1, 2)]), [-FAN_SPACING, 0.0, FAN_SPACING, 0.0]);
+
+        let nodes = [node(0.0, 0.0), node(300.0, 0.0)];
+        let mut router = EdgeRouter::new();
+        router.update(&nodes, &[connect(0, 1), connect(0, 1)]);
+        let (a, b) = (router.route(0).unwrap(), router.route(1).unwrap());
+        assert_eq!(b.points[0].y - a.points[0].y, FAN_SPACING);
+        assert!(a.segments().zip(b.segments()).all(|((a0, a1), (b0, b1))| a0 != b0 || a1 != b1));
+    }
+
+    #[test]
+    fn dragging_reroutes_only_affected_connections() {
+        let mut nodes = vec![node(0.0, 0.0), node(300.0, 0.0), node(0.0, 400.0), node(300.0, 400.0)];
+        let connections = [connect(0, 1), connect(2, 3)];
+        let mut router = EdgeRouter::new();
+        assert_eq!(router.update(&nodes, &connections), 2);
+        assert_eq!(router.update(&nodes, &connections), 0);
+
+        let before = router.route(0).cloned();
+        nodes[3].position += Vec2::new(20.0, 20.0);
+        assert_eq!(router.update(&nodes, &connections), 1);
+        assert_eq!(router.route(0).cloned(), before);
+        assert_eq!(router.route(1).unwrap().points.last(), Some(&Pos2::new(320.0, 460.0)));
+
+        nodes.push(node(600.0, 0.0));
+        assert_eq!(router.update(&nodes, &connections), 2);
+        assert_eq!(router.update(&nodes, &connections[..1]), 1);
+        assert_eq!(router.routes().len(), 1);
+    }
+
+    #[test]
+    fn hit_testing_follows_the_route() {
+        let nodes = [node(0.0, 0.0), node(200.0, 0.0), node(400.0, 0.0)];
+        let mut router = EdgeRouter::new();
+        router.update(&nodes, &[connect(0, 2)]);
+        let route = router.route(0).unwrap();
+
+        // The straight line runs through the middle node, the route does not
+        assert_eq!(router.hit_test(Pos2::new(260.0, 40.0), 4.0), None);
+        assert_eq!(router.hit_test(route.midpoint().unwrap(), 4.0), Some(0));
+        assert_eq!(router.hit_test(route.midpoint().unwrap() + Vec2::new(0.0, 3.0), 4.0), Some(0));
+    }
+
+    #[test]
+    fn bundling_draws_close_parallel_runs_together() {
+        let nodes = [node(0.0, 0.0), node(300.0, 300.0), node(0.0, 100.0), node(320.0, 400.0)];
+        let connections = [connect(0, 1), connect(2, 3)];
+        let mut router = EdgeRouter::new();
+        router.update(&nodes, &connections);
+        let channels = |router: &EdgeRouter| router.routes().iter().map(|r| r.points[2].x).collect::<Vec<_>>();
+        assert_eq!(channels(&router), [210.0, 220.0]);
+
+        router.set_bundling(true);
+        assert_eq!(channels(&router), [215.0, 215.0]);
+        assert!(router.routes().iter().all(is_orthogonal));
+        assert!(router.hit_test(Pos2::new(215.0, 200.0), 1.0).is_some());
+
+        router.set_bundling(false);
+        assert_eq!(channels(&router), [210.0, 220.0]);
+    }
+
+    #[test]
+    fn dragging_a_node_reroutes_only_the_connections_around_it() {
+        let mut nodes = grid_nodes();
+        let connections = grid_connections();
+        let dragged = 55;
+        let touching: Vec<usize> = (0..connections.len())
+            .filter(|&i| connections[i].from_node == dragged || connections[i].to_node == dragged)
+            .collect();
+        assert_eq!(touching.len(), 4);
+
+        let mut router = EdgeRouter::new();
+        router.update(&nodes, &connections);
+        for _ in 0..60 {
+            let before = router.routes().to_vec();
+            let was = node_rect(&nodes[dragged]);
+            nodes[dragged].position += Vec2::new(2.0, 1.0);
+            let now = node_rect(&nodes[dragged]);
+
+            let rerouted = router.update(&nodes, &connections);
+            assert!(rerouted >= touching.len() && rerouted < connections.len() / 4, "{} rerouted", rerouted);
+            // Routes away from the dragged node stay as they were
+            for (i, (old, new)) in before.iter().zip(router.routes()).enumerate() {
+                let area = old.bounding_rect().expand(ROUTE_MARGIN);
+                assert!(old == new || touching.contains(&i) || area.intersects(was) || area.intersects(now), "route {} changed", i);
+            }
+        }
+
+        // The connections of the dragged node end up where a full reroute puts them
+        let mut fresh = EdgeRouter::new();
+        fresh.update(&nodes, &connections);
+        for &i in &touching {
+            assert_eq!(router.routes()[i], fresh.routes()[i]);
+        }
+    }
+}
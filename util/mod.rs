@@ -1,326 +1,1687 @@
-//! Utility functions and macros
-
-use std::collections::HashMap;
-
-/// Collection of utility macros for Vistle development
-pub mod macros {
-    /// Helper macro for implementing common module patterns
-    #[macro_export]
-    macro_rules! vistle_module_base {
-        ($name:ident) => {
-            impl $name {
-                pub fn base_setup(&mut self) {
-                    // Common setup logic
-                }
-            }
-        };
-    }
-
-    /// Macro for creating parameter builders
-    #[macro_export]
-    macro_rules! param_builder {
-        ($($param:ident: $type:ty),*) => {
-            pub struct ParamBuilder {
-                $(pub $param: Option<$type>),*
-            }
-
-            impl ParamBuilder {
-                pub fn new() -> Self {
-                    Self {
-                        $($param: None),*
-                    }
-                }
-
-                $(
-                    pub fn $param(mut self, value: $type) -> Self {
-                        self.$param = Some(value);
-                        self
-                    }
-                )*
-
-                pub fn build(self) -> Result<($($type),*), String> {
-                    Ok((
-                        $(self.$param.ok_or_else(|| format!("Missing parameter: {}", stringify!($param)))?),*
-                    ))
-                }
-            }
-        };
-    }
-
-    /// Macro for timing code execution
-    #[macro_export]
-    macro_rules! time_execution {
-        ($name:expr, $code:block) => {{
-            let start = std::time::Instant::now();
-            let result = $code;
-            let duration = start.elapsed();
-            tracing::info!("{} completed in {:?}", $name, duration);
-            result
-        }};
-    }
-}
-
-/// Performance monitoring utilities
-pub struct PerformanceMonitor {
-    timings: HashMap<String, Vec<std::time::Duration>>,
-}
-
-impl PerformanceMonitor {
-    pub fn new() -> Self {
-        Self {
-            timings: HashMap::new(),
-        }
-    }
-
-    pub fn start_timer(&self, name: &str) -> Timer {
-        Timer::new(name.to_string())
-    }
-
-    pub fn record_timing(&mut self, name: String, duration: std::time::Duration) {
-        self.timings.entry(name).or_insert_with(Vec::new).push(duration);
-    }
-
-    pub fn get_average(&self, name: &str) -> Option<std::time::Duration> {
-        self.timings.get(name).and_then(|durations| {
-            if durations.is_empty() {
-                None
-            } else {
-                let total: std::time::Duration = durations.iter().sum();
-                Some(total / durations.len() as u32)
-            }
-        })
-    }
-
-    pub fn get_stats(&self, name: &str) -> Option<TimingStats> {
-        self.timings.get(name).map(|durations| {
-            if durations.is_empty() {
-                return TimingStats {
-                    count: 0,
-                    average: std::time::Duration::ZERO,
-                    min: std::time::Duration::ZERO,
-                    max: std::time::Duration::ZERO,
-                };
-            }
-
-            let count = durations.len();
-            let total: std::time::Duration = durations.iter().sum();
-            let average = total / count as u32;
-            let min = durations.iter().min().unwrap().clone();
-            let max = durations.iter().max().unwrap().clone();
-
-            TimingStats { count, average, min, max }
-        })
-    }
-
-    pub fn clear(&mut self) {
-        self.timings.clear();
-    }
-}
-
-pub struct Timer {
-    name: String,
-    start: std::time::Instant,
-}
-
-impl Timer {
-    pub fn new(name: String) -> Self {
-        Self {
-            name,
-            start: std::time::Instant::now(),
-        }
-    }
-
-    pub fn elapsed(&self) -> std::time::Duration {
-        self.start.elapsed()
-    }
-}
-
-impl Drop for Timer {
-    fn drop(&mut self) {
-        let duration = self.elapsed();
-        tracing::debug!("Timer '{}' finished in {:?}", self.name, duration);
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct TimingStats {
-    pub count: usize,
-    pub average: std::time::Duration,
-    pub min: std::time::Duration,
-    pub max: std::time::Duration,
-}
-
-/// Memory usage tracking
-pub struct MemoryTracker {
-    initial_memory: usize,
-    peak_memory: usize,
-}
-
-impl MemoryTracker {
-    pub fn new() -> Self {
-        let initial = get_current_memory_usage();
-        Self {
-            initial_memory: initial,
-            peak_memory: initial,
-        }
-    }
-
-    pub fn update(&mut self) {
-        let current = get_current_memory_usage();
-        if current > self.peak_memory {
-            self.peak_memory = current;
-        }
-    }
-
-    pub fn current_usage(&self) -> usize {
-        get_current_memory_usage()
-    }
-
-    pub fn peak_usage(&self) -> usize {
-        self.peak_memory
-    }
-
-    pub fn initial_usage(&self) -> usize {
-        self.initial_memory
-    }
-
-    pub fn reset_peak(&mut self) {
-        self.peak_memory = get_current_memory_usage();
-    }
-}
-
-fn get_current_memory_usage() -> usize {
-    // Platform-specific memory usage detection
-    // This is a simplified implementation
-    0 // Placeholder
-}
-
-/// Configuration utilities
-pub mod config {
-    use std::collections::HashMap;
-    use serde::{Deserialize, Serialize};
-
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct SystemConfig {
-        pub max_threads: usize,
-        pub shared_memory_size: usize,
-        pub enable_gpu: bool,
-        pub log_level: String,
-    }
-
-    impl Default for SystemConfig {
-        fn default() -> Self {
-            Self {
-                max_threads: num_cpus::get(),
-                shared_memory_size: 1024 * 1024 * 1024, // 1GB
-                enable_gpu: true,
-                log_level: "info".to_string(),
-            }
-        }
-    }
-
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct ModuleConfig {
-        pub enabled_modules: Vec<String>,
-        pub module_paths: Vec<String>,
-        pub default_parameters: HashMap<String, HashMap<String, String>>,
-    }
-
-    impl Default for ModuleConfig {
-        fn default() -> Self {
-            Self {
-                enabled_modules: vec![
-                    "ReadData".to_string(),
-                    "Filter".to_string(),
-                    "Renderer".to_string(),
-                ],
-                module_paths: vec!["modules".to_string()],
-                default_parameters: HashMap::new(),
-            }
-        }
-    }
-}
-
-/// Math utilities for scientific computing
-pub mod math {
-    use ndarray::{Array1, Array2};
-
-    /// Compute basic statistics for an array
-    pub fn compute_stats(data: &Array1<f32>) -> ArrayStats {
-        if data.is_empty() {
-            return ArrayStats {
-                min: 0.0,
-                max: 0.0,
-                mean: 0.0,
-                std_dev: 0.0,
-            };
-        }
-
-        let min = data.fold(f32::INFINITY, |a, &b| a.min(b));
-        let max = data.fold(f32::NEG_INFINITY, |a, &b| a.max(b));
-        let sum: f32 = data.sum();
-        let mean = sum / data.len() as f32;
-
-        let variance: f32 = data.iter().map(|&x| (x - mean).powi(2)).sum::<f32>() / data.len() as f32;
-        let std_dev = variance.sqrt();
-
-        ArrayStats { min, max, mean, std_dev }
-    }
-
-    #[derive(Debug, Clone)]
-    pub struct ArrayStats {
-        pub min: f32,
-        pub max: f32,
-        pub mean: f32,
-        pub std_dev: f32,
-    }
-
-    /// Normalize array to [0, 1] range
-    pub fn normalize(data: &mut Array1<f32>) {
-        let stats = compute_stats(data);
-        let range = stats.max - stats.min;
-
-        if range > 0.0 {
-            data.mapv_inplace(|x| (x - stats.min) / range);
-        }
-    }
-
-    /// Clamp array values to range
-    pub fn clamp(data: &mut Array1<f32>, min: f32, max: f32) {
-        data.mapv_inplace(|x| x.clamp(min, max));
-    }
-}
-
-/// File I/O utilities
-pub mod io {
-    use std::path::Path;
-    use tokio::fs;
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
-
-    /// Read binary data from file
-    pub async fn read_binary<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, crate::Error> {
-        let mut file = fs::File::open(path).await?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).await?;
-        Ok(buffer)
-    }
-
-    /// Write binary data to file
-    pub async fn write_binary<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<(), crate::Error> {
-        let mut file = fs::File::create(path).await?;
-        file.write_all(data).await?;
-        Ok(())
-    }
-
-    /// Read text from file
-    pub async fn read_text<P: AsRef<Path>>(path: P) -> Result<String, crate::Error> {
-        let content = fs::read_to_string(path).await?;
-        Ok(content)
-    }
-
-    /// Write text to file
-    pub async fn write_text<P: AsRef<Path>>(path: P, text: &str) -> Result<(), crate::Error> {
-        fs::write(path, text).await?;
-        Ok(())
-    }
-}
+//! Utility functions and macros
+
+use std::collections::HashMap;
+
+/// Collection of utility macros for Vistle development
+pub mod macros {
+    /// Helper macro for implementing common module patterns
+    #[macro_export]
+    macro_rules! vistle_module_base {
+        ($name:ident) => {
+            impl $name {
+                pub fn base_setup(&mut self) {
+                    // Common setup logic
+                }
+            }
+        };
+    }
+
+    /// Macro for creating parameter builders
+    #[macro_export]
+    macro_rules! param_builder {
+        ($($param:ident: $type:ty),*) => {
+            pub struct ParamBuilder {
+                $(pub $param: Option<$type>),*
+            }
+
+            impl ParamBuilder {
+                pub fn new() -> Self {
+                    Self {
+                        $($param: None),*
+                    }
+                }
+
+                $(
+                    pub fn $param(mut self, value: $type) -> Self {
+                        self.$param = Some(value);
+                        self
+                    }
+                )*
+
+                pub fn build(self) -> Result<($($type),*), String> {
+                    Ok((
+                        $(self.$param.ok_or_else(|| format!("Missing parameter: {}", stringify!($param)))?),*
+                    ))
+                }
+            }
+        };
+    }
+
+    /// Macro for timing code execution
+    #[macro_export]
+    macro_rules! time_execution {
+        ($name:expr, $code:block) => {{
+            let start = std::time::Instant::now();
+            let result = $code;
+            let duration = start.elapsed();
+            tracing::info!("{} completed in {:?}", $name, duration);
+            result
+        }};
+    }
+}
+
+/// Performance monitoring utilities
+pub struct PerformanceMonitor {
+    timings: HashMap<String, Vec<std::time::Duration>>,
+}
+
+impl Default for PerformanceMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PerformanceMonitor {
+    pub fn new() -> Self {
+        Self {
+            timings: HashMap::new(),
+        }
+    }
+
+    pub fn start_timer(&self, name: &str) -> Timer {
+        Timer::new(name.to_string())
+    }
+
+    pub fn record_timing(&mut self, name: String, duration: std::time::Duration) {
+        self.timings.entry(name).or_default().push(duration);
+    }
+
+    pub fn get_average(&self, name: &str) -> Option<std::time::Duration> {
+        self.timings.get(name).and_then(|durations| {
+            if durations.is_empty() {
+                None
+            } else {
+                let total: std::time::Duration = durations.iter().sum();
+                Some(total / durations.len() as u32)
+            }
+        })
+    }
+
+    pub fn get_stats(&self, name: &str) -> Option<TimingStats> {
+        self.timings.get(name).map(|durations| {
+            if durations.is_empty() {
+                return TimingStats {
+                    count: 0,
+                    average: std::time::Duration::ZERO,
+                    min: std::time::Duration::ZERO,
+                    max: std::time::Duration::ZERO,
+                };
+            }
+
+            let count = durations.len();
+            let total: std::time::Duration = durations.iter().sum();
+            let average = total / count as u32;
+            let min = *durations.iter().min().unwrap();
+            let max = *durations.iter().max().unwrap();
+
+            TimingStats { count, average, min, max }
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.timings.clear();
+    }
+}
+
+pub struct Timer {
+    name: String,
+    start: std::time::Instant,
+}
+
+impl Timer {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> std::time::Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let duration = self.elapsed();
+        tracing::debug!("Timer '{}' finished in {:?}", self.name, duration);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimingStats {
+    pub count: usize,
+    pub average: std::time::Duration,
+    pub min: std::time::Duration,
+    pub max: std::time::Duration,
+}
+
+/// Memory usage tracking
+pub struct MemoryTracker {
+    initial_memory: usize,
+    peak_memory: usize,
+}
+
+impl Default for MemoryTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryTracker {
+    pub fn new() -> Self {
+        let initial = get_current_memory_usage();
+        Self {
+            initial_memory: initial,
+            peak_memory: initial,
+        }
+    }
+
+    pub fn update(&mut self) {
+        let current = get_current_memory_usage();
+        if current > self.peak_memory {
+            self.peak_memory = current;
+        }
+    }
+
+    pub fn current_usage(&self) -> usize {
+        get_current_memory_usage()
+    }
+
+    pub fn peak_usage(&self) -> usize {
+        self.peak_memory
+    }
+
+    pub fn initial_usage(&self) -> usize {
+        self.initial_memory
+    }
+
+    pub fn reset_peak(&mut self) {
+        self.peak_memory = get_current_memory_usage();
+    }
+}
+
+fn get_current_memory_usage() -> usize {
+    SystemMemory.resident()
+}
+
+/// Source of the memory figures admission control works from
+///
+/// `SystemMemory` queries the platform; tests and simulations substitute
+/// their own reports.
+pub trait MemoryProvider: Send + Sync {
+    /// Bytes the node can still hand out without swapping
+    fn available(&self) -> usize;
+
+    /// Resident set size of this process in bytes
+    fn resident(&self) -> usize;
+}
+
+/// Memory figures of this node, from `/proc` on Linux
+///
+/// Other platforms report unlimited available memory and no resident
+/// set, which leaves admission control without effect.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemMemory;
+
+impl MemoryProvider for SystemMemory {
+    fn available(&self) -> usize {
+        #[cfg(target_os = "linux")]
+        {
+            let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap_or_default();
+            meminfo.lines()
+                .find_map(|line| line.strip_prefix("MemAvailable:"))
+                .and_then(|rest| rest.trim().trim_end_matches("kB").trim().parse::<usize>().ok())
+                .map_or(usize::MAX, |kib| kib * 1024)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            usize::MAX
+        }
+    }
+
+    fn resident(&self) -> usize {
+        #[cfg(target_os = "linux")]
+        {
+            // SAFETY: sysconf has no preconditions
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as usize;
+            std::fs::read_to_string("/proc/self/statm").ok()
+                .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<usize>().ok())
+                .map_or(0, |pages| pages * page_size)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            0
+        }
+    }
+}
+
+/// Configuration utilities
+pub mod config {
+    use std::collections::HashMap;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SystemConfig {
+        pub max_threads: usize,
+        pub shared_memory_size: usize,
+        pub enable_gpu: bool,
+        pub log_level: String,
+    }
+
+    impl Default for SystemConfig {
+        fn default() -> Self {
+            Self {
+                max_threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+                shared_memory_size: 1024 * 1024 * 1024, // 1GB
+                enable_gpu: true,
+                log_level: "info".to_string(),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ModuleConfig {
+        pub enabled_modules: Vec<String>,
+        pub module_paths: Vec<String>,
+        pub default_parameters: HashMap<String, HashMap<String, String>>,
+    }
+
+    impl Default for ModuleConfig {
+        fn default() -> Self {
+            Self {
+                enabled_modules: vec![
+                    "ReadData".to_string(),
+                    "Filter".to_string(),
+                    "Renderer".to_string(),
+                ],
+                module_paths: vec!["modules".to_string()],
+                default_parameters: HashMap::new(),
+            }
+        }
+    }
+}
+
+/// Math utilities for scientific computing
+pub mod math {
+    use ndarray::Array1;
+
+    /// Compute basic statistics for an array
+    pub fn compute_stats(data: &Array1<f32>) -> ArrayStats {
+        if data.is_empty() {
+            return ArrayStats {
+                min: 0.0,
+                max: 0.0,
+                mean: 0.0,
+                std_dev: 0.0,
+            };
+        }
+
+        let min = data.fold(f32::INFINITY, |a, &b| a.min(b));
+        let max = data.fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+        let sum: f32 = data.sum();
+        let mean = sum / data.len() as f32;
+
+        let variance: f32 = data.iter().map(|&x| (x - mean).powi(2)).sum::<f32>() / data.len() as f32;
+        let std_dev = variance.sqrt();
+
+        ArrayStats { min, max, mean, std_dev }
+    }
+
+    /// Compute basic statistics for a double-precision array, in double precision
+    pub fn compute_stats_f64(data: &Array1<f64>) -> ArrayStats<f64> {
+        if data.is_empty() {
+            return ArrayStats {
+                min: 0.0,
+                max: 0.0,
+                mean: 0.0,
+                std_dev: 0.0,
+            };
+        }
+
+        let min = data.fold(f64::INFINITY, |a, &b| a.min(b));
+        let max = data.fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        let mean = data.sum() / data.len() as f64;
+
+        let variance: f64 = data.iter().map(|&x| (x - mean).powi(2)).sum::<f64>() / data.len() as f64;
+        let std_dev = variance.sqrt();
+
+        ArrayStats { min, max, mean, std_dev }
+    }
+
+    /// Compute bounds and distinct values of an integer array
+    pub fn compute_int_stats<T: Copy + Into<i64>>(data: &Array1<T>) -> IntStats {
+        let mut distinct = std::collections::HashSet::new();
+        let (mut min, mut max) = (i64::MAX, i64::MIN);
+        for &value in data {
+            let value = value.into();
+            min = min.min(value);
+            max = max.max(value);
+            distinct.insert(value);
+        }
+        if data.is_empty() {
+            (min, max) = (0, 0);
+        }
+
+        IntStats { min, max, count: data.len(), distinct: distinct.len() }
+    }
+
+    /// Occurrences of each value of an integer array, e.g. cells per material ID
+    pub fn count_values<T: Copy + Into<i64>>(data: &Array1<T>) -> std::collections::BTreeMap<i64, usize> {
+        let mut counts = std::collections::BTreeMap::new();
+        for &value in data {
+            *counts.entry(value.into()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IntStats {
+        pub min: i64,
+        pub max: i64,
+        pub count: usize,
+        /// Number of different values
+        pub distinct: usize,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct ArrayStats<T = f32> {
+        pub min: T,
+        pub max: T,
+        pub mean: T,
+        pub std_dev: T,
+    }
+
+    /// Normalize array to [0, 1] range
+    pub fn normalize(data: &mut Array1<f32>) {
+        let stats = compute_stats(data);
+        let range = stats.max - stats.min;
+
+        if range > 0.0 {
+            data.mapv_inplace(|x| (x - stats.min) / range);
+        }
+    }
+
+    /// Normalize a double-precision array to [0, 1] range
+    pub fn normalize_f64(data: &mut Array1<f64>) {
+        let stats = compute_stats_f64(data);
+        let range = stats.max - stats.min;
+
+        if range > 0.0 {
+            data.mapv_inplace(|x| (x - stats.min) / range);
+        }
+    }
+
+    /// Clamp array values to range
+    pub fn clamp(data: &mut Array1<f32>, min: f32, max: f32) {
+        data.mapv_inplace(|x| x.clamp(min, max));
+    }
+
+    /// Number of NaN and infinite values
+    ///
+    /// A value is not finite when all its exponent bits are set. On x86_64
+    /// four values are tested at once with SSE2, which every x86_64 CPU has.
+    pub fn count_nonfinite(data: &[f32]) -> usize {
+        #[cfg(target_arch = "x86_64")]
+        {
+            count_nonfinite_sse2(data)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            count_nonfinite_scalar(data)
+        }
+    }
+
+    const EXPONENT_MASK: u32 = 0x7f80_0000;
+
+    fn count_nonfinite_scalar(data: &[f32]) -> usize {
+        data.iter().filter(|v| v.to_bits() & EXPONENT_MASK == EXPONENT_MASK).count()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn count_nonfinite_sse2(data: &[f32]) -> usize {
+        use std::arch::x86_64::*;
+
+        let chunks = data.chunks_exact(4);
+        let rest = count_nonfinite_scalar(chunks.remainder());
+        // SAFETY: SSE2 is part of the x86_64 baseline, and loads are unaligned ones of 4 values
+        let counted = unsafe {
+            let mask = _mm_set1_epi32(EXPONENT_MASK as i32);
+            chunks
+                .map(|chunk| {
+                    let bits = _mm_castps_si128(_mm_loadu_ps(chunk.as_ptr()));
+                    let nonfinite = _mm_cmpeq_epi32(_mm_and_si128(bits, mask), mask);
+                    _mm_movemask_ps(_mm_castsi128_ps(nonfinite)).count_ones() as usize
+                })
+                .sum::<usize>()
+        };
+        counted + rest
+    }
+
+    /// Number of NaN and infinite values in double precision data
+    pub fn count_nonfinite_f64(data: &[f64]) -> usize {
+        data.iter().filter(|v| !v.is_finite()).count()
+    }
+
+    /// Counts of values in equal-width bins between the finite minimum and maximum
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Histogram {
+        pub min: f32,
+        pub max: f32,
+        pub counts: Vec<u64>,
+    }
+
+    impl Histogram {
+        /// Histogram of the finite values in `data`; NaN and infinities are skipped
+        pub fn new(data: &[f32], bins: usize) -> Self {
+            let finite = || data.iter().copied().filter(|v| v.is_finite());
+            let min = finite().fold(f32::INFINITY, f32::min);
+            let max = finite().fold(f32::NEG_INFINITY, f32::max);
+            let mut histogram = Self {
+                min: if min.is_finite() { min } else { 0.0 },
+                max: if max.is_finite() { max } else { 0.0 },
+                counts: vec![0; bins.max(1)],
+            };
+            for value in finite() {
+                let bin = histogram.bin(value);
+                histogram.counts[bin] += 1;
+            }
+            histogram
+        }
+
+        pub fn total(&self) -> u64 {
+            self.counts.iter().sum()
+        }
+
+        /// Bin holding `value`, clamped to the first and last bin
+        pub fn bin(&self, value: f32) -> usize {
+            let range = self.max - self.min;
+            if range <= 0.0 {
+                return 0;
+            }
+            let bins = self.counts.len();
+            (((value - self.min) / range * bins as f32) as usize).min(bins - 1)
+        }
+
+        /// Cumulative fraction of values up to the end of each bin; the last entry is 1
+        pub fn cdf(&self) -> Vec<f32> {
+            let total = self.total().max(1) as f64;
+            let mut seen = 0u64;
+            self.counts.iter()
+                .map(|&count| {
+                    seen += count;
+                    (seen as f64 / total) as f32
+                })
+                .collect()
+        }
+    }
+}
+
+/// Locale-independent float formatting and parsing
+///
+/// Files and parameter strings always use '.' as decimal separator,
+/// whatever the user's locale. Formatting gives the shortest text that
+/// parses back to the same value.
+pub mod fmt {
+    /// Shortest text parsing back to `value`; `NaN`, `inf` and `-inf` for non-finite values
+    pub fn format_f32(value: f32) -> String {
+        if value.is_finite() {
+            ryu::Buffer::new().format_finite(value).to_string()
+        } else {
+            non_finite(value.is_nan(), value.is_sign_negative())
+        }
+    }
+
+    /// Shortest text parsing back to `value`; `NaN`, `inf` and `-inf` for non-finite values
+    pub fn format_f64(value: f64) -> String {
+        if value.is_finite() {
+            ryu::Buffer::new().format_finite(value).to_string()
+        } else {
+            non_finite(value.is_nan(), value.is_sign_negative())
+        }
+    }
+
+    fn non_finite(nan: bool, negative: bool) -> String {
+        match (nan, negative) {
+            (true, _) => "NaN",
+            (false, false) => "inf",
+            (false, true) => "-inf",
+        }
+        .to_string()
+    }
+
+    /// Check the text is a plain decimal or scientific number before handing it to `str::parse`
+    fn check(text: &str) -> Result<&str, crate::Error> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(crate::Error::Config("Expected a number, got an empty string".to_string()));
+        }
+        if text.contains(',') {
+            return Err(crate::Error::Config(format!(
+                "'{}' is not a number: use '.' as decimal separator, e.g. '{}'",
+                text, text.replace(',', ".")
+            )));
+        }
+        let special = text.trim_start_matches(['+', '-']).to_ascii_lowercase();
+        let plain = text.chars().all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+        if !plain && !matches!(special.as_str(), "nan" | "inf" | "infinity") {
+            return Err(crate::Error::Config(format!("'{}' is not a number", text)));
+        }
+        Ok(text)
+    }
+
+    /// Parse a number with '.' as decimal separator, optionally in scientific notation
+    ///
+    /// Finite text beyond the range of `f32` is an error rather than infinity.
+    pub fn parse_f32(text: &str) -> Result<f32, crate::Error> {
+        let text = check(text)?;
+        let value: f32 = text.parse().map_err(|_| crate::Error::Config(format!("'{}' is not a number", text)))?;
+        if value.is_infinite() && text.chars().any(|c| c.is_ascii_digit()) {
+            return Err(crate::Error::Config(format!("'{}' is out of range for a 32-bit float", text)));
+        }
+        Ok(value)
+    }
+
+    /// Parse a number with '.' as decimal separator, optionally in scientific notation
+    ///
+    /// Finite text beyond the range of `f64` is an error rather than infinity.
+    pub fn parse_f64(text: &str) -> Result<f64, crate::Error> {
+        let text = check(text)?;
+        let value: f64 = text.parse().map_err(|_| crate::Error::Config(format!("'{}' is not a number", text)))?;
+        if value.is_infinite() && text.chars().any(|c| c.is_ascii_digit()) {
+            return Err(crate::Error::Config(format!("'{}' is out of range for a 64-bit float", text)));
+        }
+        Ok(value)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn formatted_values_parse_back_exactly() {
+            for value in [0.1f32, -3.5e-12, f32::MAX, f32::MIN_POSITIVE, 1.0 / 3.0] {
+                assert_eq!(parse_f32(&format_f32(value)).unwrap(), value);
+            }
+            for value in [0.1f64, 6.02214076e23, f64::MAX, -f64::MIN_POSITIVE, 1.0 / 3.0] {
+                assert_eq!(parse_f64(&format_f64(value)).unwrap(), value);
+            }
+            assert_eq!(format_f64(0.1), "0.1");
+            assert_eq!(format_f32(2.0), "2.0");
+        }
+
+        /// SplitMix64, seeded so failures reproduce
+        fn bits(seed: u64) -> impl Iterator<Item = u64> {
+            let mut state = seed;
+            std::iter::repeat_with(move || {
+                state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^ (z >> 31)
+            })
+        }
+
+        fn round_trips_f64(value: f64) -> bool {
+            let parsed = parse_f64(&format_f64(value)).unwrap();
+            if value.is_nan() { parsed.is_nan() } else { parsed.to_bits() == value.to_bits() }
+        }
+
+        fn round_trips_f32(value: f32) -> bool {
+            let parsed = parse_f32(&format_f32(value)).unwrap();
+            if value.is_nan() { parsed.is_nan() } else { parsed.to_bits() == value.to_bits() }
+        }
+
+        #[test]
+        fn random_and_boundary_values_round_trip() {
+            let edges = [
+                f64::MIN_POSITIVE / 2.0, 5e-324, -5e-324, f64::MIN_POSITIVE, f64::from_bits(0x000f_ffff_ffff_ffff),
+                f64::MAX, f64::MIN, 2f64.powi(1023), 2f64.powi(-1022), 1e22, 1e23, 9007199254740993.0,
+                0.0, -0.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY,
+            ];
+            for value in edges {
+                assert!(round_trips_f64(value), "{:e} ({:#x})", value, value.to_bits());
+                let narrow = value as f32;
+                assert!(round_trips_f32(narrow), "{:e} ({:#x})", narrow, narrow.to_bits());
+            }
+            for value in [f32::MIN_POSITIVE / 2.0, f32::from_bits(1), f32::from_bits(0x007f_ffff), f32::MAX, 2f32.powi(-126)] {
+                assert!(round_trips_f32(value), "{:e} ({:#x})", value, value.to_bits());
+            }
+
+            // Arbitrary bit patterns cover every exponent, subnormals and NaN payloads alike
+            for bits in bits(0x5eed).take(20_000) {
+                let value = f64::from_bits(bits);
+                assert!(round_trips_f64(value), "{:e} ({:#x})", value, bits);
+                let value = f32::from_bits(bits as u32);
+                assert!(round_trips_f32(value), "{:e} ({:#x})", value, bits as u32);
+            }
+            // Subnormals are too rare among random bits to rely on
+            for bits in bits(0x5b).take(2_000) {
+                let value = f64::from_bits(bits & 0x800f_ffff_ffff_ffff);
+                assert!(round_trips_f64(value), "{:e} ({:#x})", value, value.to_bits());
+                let value = f32::from_bits(bits as u32 & 0x807f_ffff);
+                assert!(round_trips_f32(value), "{:e} ({:#x})", value, value.to_bits());
+            }
+        }
+
+        #[test]
+        fn non_finite_values_round_trip() {
+            assert_eq!(format_f32(f32::NAN), "NaN");
+            assert_eq!(format_f64(f64::INFINITY), "inf");
+            assert_eq!(format_f64(f64::NEG_INFINITY), "-inf");
+            assert!(parse_f64("NaN").unwrap().is_nan());
+            assert_eq!(parse_f32("-inf").unwrap(), f32::NEG_INFINITY);
+            assert_eq!(parse_f64("Infinity").unwrap(), f64::INFINITY);
+        }
+
+        #[test]
+        fn scientific_notation_and_whitespace_are_accepted() {
+            assert_eq!(parse_f64(" 1.5e3 ").unwrap(), 1500.0);
+            assert_eq!(parse_f32("-2E-2").unwrap(), -0.02);
+            assert_eq!(parse_f64("+7").unwrap(), 7.0);
+        }
+
+        #[test]
+        fn a_decimal_comma_is_rejected_with_a_hint() {
+            let message = parse_f64("3,14").unwrap_err().to_string();
+            assert!(message.contains("'3.14'"), "{}", message);
+        }
+
+        #[test]
+        fn malformed_and_empty_text_is_rejected() {
+            assert!(parse_f64("").is_err());
+            assert!(parse_f64("   ").is_err());
+            assert!(parse_f64("0x10").is_err());
+            assert!(parse_f32("1.2.3").is_err());
+            assert!(parse_f64("nanx").is_err());
+        }
+
+        #[test]
+        fn finite_text_beyond_the_range_is_an_error() {
+            assert!(parse_f32("1e39").unwrap_err().to_string().contains("32-bit"));
+            assert!(parse_f64("1e39").is_ok());
+            assert!(parse_f64("1e309").unwrap_err().to_string().contains("64-bit"));
+        }
+    }
+}
+
+/// File I/O utilities
+pub mod io {
+    use std::path::{Path, PathBuf};
+    use tokio::fs;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::sync::CancellationToken;
+
+    /// Chunk size of the cancellable readers and writers
+    pub const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+    /// Read binary data from file
+    pub async fn read_binary<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, crate::Error> {
+        let mut file = fs::File::open(path).await?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer).await?;
+        Ok(buffer)
+    }
+
+    /// Write binary data to file
+    pub async fn write_binary<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<(), crate::Error> {
+        let mut file = fs::File::create(path).await?;
+        file.write_all(data).await?;
+        // The last write may still be in flight until flushed
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Read text from file
+    pub async fn read_text<P: AsRef<Path>>(path: P) -> Result<String, crate::Error> {
+        let content = fs::read_to_string(path).await?;
+        Ok(content)
+    }
+
+    /// Write text to file
+    pub async fn write_text<P: AsRef<Path>>(path: P, text: &str) -> Result<(), crate::Error> {
+        fs::write(path, text).await?;
+        Ok(())
+    }
+
+    fn cancelled(action: &str, path: &Path) -> crate::Error {
+        crate::Error::Cancelled(format!("{} {}", action, path.display()))
+    }
+
+    /// Read a file in chunks of `chunk_size`, stopping when `token` is cancelled
+    ///
+    /// The token is checked while waiting for every chunk, so cancellation
+    /// takes effect within one chunk read. Returns the number of bytes read.
+    pub async fn read_chunks<P, F>(
+        path: P,
+        chunk_size: usize,
+        token: &CancellationToken,
+        mut on_chunk: F,
+    ) -> Result<u64, crate::Error>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&[u8]) -> Result<(), crate::Error>,
+    {
+        let path = path.as_ref();
+        let mut file = fs::File::open(path).await?;
+        let mut buffer = vec![0u8; chunk_size.max(1)];
+        let mut total = 0;
+        loop {
+            let read = tokio::select! {
+                biased;
+                _ = token.cancelled() => return Err(cancelled("reading", path)),
+                read = file.read(&mut buffer) => read?,
+            };
+            if read == 0 {
+                return Ok(total);
+            }
+            on_chunk(&buffer[..read])?;
+            total += read as u64;
+        }
+    }
+
+    /// Read binary data from file, aborting when `token` is cancelled
+    pub async fn read_binary_cancellable<P: AsRef<Path>>(
+        path: P,
+        token: &CancellationToken,
+    ) -> Result<Vec<u8>, crate::Error> {
+        let path = path.as_ref();
+        let mut data = Vec::with_capacity(fs::metadata(path).await.map(|m| m.len() as usize).unwrap_or(0));
+        read_chunks(path, CHUNK_SIZE, token, |chunk| {
+            data.extend_from_slice(chunk);
+            Ok(())
+        }).await?;
+        Ok(data)
+    }
+
+    /// Output file that is removed again unless committed
+    ///
+    /// Writers create one before writing, so a cancelled, failed or dropped
+    /// write never leaves a truncated file behind.
+    pub struct PartialFile {
+        path: PathBuf,
+        committed: bool,
+    }
+
+    impl PartialFile {
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            Self {
+                path: path.into(),
+                committed: false,
+            }
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Keep the file
+        pub fn commit(mut self) {
+            self.committed = true;
+        }
+    }
+
+    impl Drop for PartialFile {
+        fn drop(&mut self) {
+            if !self.committed {
+                if let Err(e) = std::fs::remove_file(&self.path) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::warn!("Failed to remove partial file {}: {}", self.path.display(), e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write binary data to file in chunks, removing the file if `token` is cancelled
+    pub async fn write_binary_cancellable<P: AsRef<Path>>(
+        path: P,
+        data: &[u8],
+        token: &CancellationToken,
+    ) -> Result<(), crate::Error> {
+        let path = path.as_ref();
+        let guard = PartialFile::new(path);
+        let mut file = fs::File::create(path).await?;
+        for chunk in data.chunks(CHUNK_SIZE) {
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => return Err(cancelled("writing", path)),
+                written = file.write_all(chunk) => written?,
+            }
+        }
+        file.flush().await?;
+        guard.commit();
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn scratch(name: &str) -> PathBuf {
+            std::env::temp_dir().join(format!("vistle_io_{}_{}", name, uuid::Uuid::new_v4().simple()))
+        }
+
+        #[tokio::test]
+        async fn cancellable_io_round_trips_without_cancellation() {
+            let path = scratch("round_trip");
+            let data: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+            let token = CancellationToken::new();
+
+            write_binary_cancellable(&path, &data, &token).await.unwrap();
+            assert_eq!(read_binary_cancellable(&path, &token).await.unwrap(), data);
+
+            let mut chunks = 0;
+            let total = read_chunks(&path, 4096, &token, |_| {
+                chunks += 1;
+                Ok(())
+            }).await.unwrap();
+            assert_eq!((total, chunks), (10_000, 3));
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[tokio::test]
+        async fn cancelling_a_read_stops_at_the_next_chunk() {
+            let path = scratch("read");
+            std::fs::write(&path, vec![7u8; 1000]).unwrap();
+            let token = CancellationToken::new();
+
+            let mut seen = 0;
+            let result = read_chunks(&path, 100, &token, |chunk| {
+                seen += chunk.len();
+                token.cancel();
+                Ok(())
+            }).await;
+            assert!(matches!(result, Err(crate::Error::Cancelled(_))));
+            assert_eq!(seen, 100);
+            assert!(matches!(read_binary_cancellable(&path, &token).await, Err(crate::Error::Cancelled(_))));
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[tokio::test]
+        async fn a_cancelled_write_leaves_no_file_behind() {
+            let path = scratch("write");
+            let token = CancellationToken::new();
+            token.cancel();
+
+            let result = write_binary_cancellable(&path, &[1, 2, 3], &token).await;
+            assert!(matches!(result, Err(crate::Error::Cancelled(_))));
+            assert_eq!(result.unwrap_err().code(), "cancelled");
+            assert!(!path.exists());
+        }
+
+        #[test]
+        fn uncommitted_partial_files_are_removed() {
+            let kept = scratch("kept");
+            let dropped = scratch("dropped");
+            std::fs::write(&kept, b"kept").unwrap();
+            std::fs::write(&dropped, b"dropped").unwrap();
+
+            PartialFile::new(&kept).commit();
+            drop(PartialFile::new(&dropped));
+            assert!(kept.exists());
+            assert!(!dropped.exists());
+            std::fs::remove_file(&kept).unwrap();
+        }
+    }
+}
+
+/// Sorting more records than fit in memory
+///
+/// `external_sort_by_key` collects records up to a memory budget, sorts
+/// them and spills each run to a file, then merges the runs k ways while
+/// the result is read. More than `MAX_FAN_IN` runs are first merged in
+/// groups into intermediate runs, over as many passes as it takes. Records have a fixed-size encoding, see
+/// `FixedRecord`. Run files live in a directory of their own below the
+/// scratch directory, which is removed when the result has been read, when
+/// sorting fails or is cancelled, and when the future or result is dropped.
+pub mod sort {
+    use std::cmp::Ordering;
+    use std::collections::BinaryHeap;
+    use std::path::{Path, PathBuf};
+
+    use futures::{Stream, StreamExt};
+    use ndarray::Axis;
+    use tokio::fs;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+    use tokio_util::sync::CancellationToken;
+
+    use crate::core::ObjectPayload;
+
+    /// Runs merged at once, bounding open files and read buffers
+    pub const MAX_FAN_IN: usize = 64;
+
+    /// Record with an encoding of `SIZE` bytes
+    pub trait FixedRecord: Sized {
+        const SIZE: usize;
+        fn encode(&self, out: &mut [u8]);
+        fn decode(bytes: &[u8]) -> Self;
+    }
+
+    macro_rules! fixed_record_le {
+        ($($t:ty),*) => {$(
+            impl FixedRecord for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+
+                fn encode(&self, out: &mut [u8]) {
+                    out.copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> Self {
+                    <$t>::from_le_bytes(bytes.try_into().expect("record size matches"))
+                }
+            }
+        )*};
+    }
+
+    fixed_record_le!(u32, u64, i32, i64, f32, f64);
+
+    impl<A: FixedRecord, B: FixedRecord> FixedRecord for (A, B) {
+        const SIZE: usize = A::SIZE + B::SIZE;
+
+        fn encode(&self, out: &mut [u8]) {
+            let (a, b) = out.split_at_mut(A::SIZE);
+            self.0.encode(a);
+            self.1.encode(b);
+        }
+
+        fn decode(bytes: &[u8]) -> Self {
+            let (a, b) = bytes.split_at(A::SIZE);
+            (A::decode(a), B::decode(b))
+        }
+    }
+
+    /// Directory of spilled runs, removed with its files when dropped
+    struct ScratchDir {
+        path: PathBuf,
+    }
+
+    impl ScratchDir {
+        async fn create(parent: &Path) -> Result<Self, crate::Error> {
+            let path = parent.join(format!("vistle-sort-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&path).await?;
+            Ok(Self { path })
+        }
+    }
+
+    impl Drop for ScratchDir {
+        fn drop(&mut self) {
+            if let Err(e) = std::fs::remove_dir_all(&self.path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("Failed to remove sort scratch directory {}: {}", self.path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Scratch directory, created on the first spill
+    async fn scratch_path<'a>(scratch: &'a mut Option<ScratchDir>, parent: &Path) -> Result<&'a Path, crate::Error> {
+        if scratch.is_none() {
+            *scratch = Some(ScratchDir::create(parent).await?);
+        }
+        Ok(&scratch.as_ref().expect("created above").path)
+    }
+
+    /// One spilled run being merged
+    struct Run {
+        reader: BufReader<fs::File>,
+        buffer: Vec<u8>,
+    }
+
+    impl Run {
+        async fn next<T: FixedRecord>(&mut self) -> Result<Option<T>, crate::Error> {
+            match self.reader.read_exact(&mut self.buffer).await {
+                Ok(_) => Ok(Some(T::decode(&self.buffer))),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+
+    /// Smallest unread record of a run; ties go to the earlier run, keeping the sort stable
+    struct Head<K, T> {
+        key: K,
+        run: usize,
+        record: T,
+    }
+
+    impl<K: Ord, T> Ord for Head<K, T> {
+        fn cmp(&self, other: &Self) -> Ordering {
+            // BinaryHeap pops the largest entry
+            other.key.cmp(&self.key).then(other.run.cmp(&self.run))
+        }
+    }
+
+    impl<K: Ord, T> PartialOrd for Head<K, T> {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl<K: Ord, T> PartialEq for Head<K, T> {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+
+    impl<K: Ord, T> Eq for Head<K, T> {}
+
+    enum Source<K, T> {
+        /// The input fit in the budget and was never spilled
+        Memory(std::vec::IntoIter<T>),
+        Merge {
+            runs: Vec<Run>,
+            heap: BinaryHeap<Head<K, T>>,
+            _scratch: ScratchDir,
+        },
+    }
+
+    /// Records of an external sort, read in key order
+    pub struct SortedRecords<T, K, F> {
+        source: Source<K, T>,
+        key_fn: F,
+        len: u64,
+        runs: usize,
+        passes: usize,
+        token: CancellationToken,
+    }
+
+    impl<T, K, F> SortedRecords<T, K, F>
+    where
+        T: FixedRecord,
+        K: Ord,
+        F: Fn(&T) -> K,
+    {
+        /// Records sorted
+        pub fn len(&self) -> u64 {
+            self.len
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        /// Runs spilled to disk, 0 if the input fit in the memory budget
+        pub fn runs(&self) -> usize {
+            self.runs
+        }
+
+        /// Passes merging groups of runs into intermediate runs before the final merge
+        pub fn passes(&self) -> usize {
+            self.passes
+        }
+
+        /// Next record in key order, None once all were read
+        pub async fn next(&mut self) -> Result<Option<T>, crate::Error> {
+            if self.token.is_cancelled() {
+                return Err(crate::Error::Cancelled("Merging sorted runs".to_string()));
+            }
+            match &mut self.source {
+                Source::Memory(records) => Ok(records.next()),
+                Source::Merge { runs, heap, .. } => {
+                    let Some(head) = heap.pop() else {
+                        // Remove the run files as soon as they are read
+                        self.source = Source::Memory(Vec::new().into_iter());
+                        return Ok(None);
+                    };
+                    if let Some(record) = runs[head.run].next::<T>().await? {
+                        heap.push(Head { key: (self.key_fn)(&record), run: head.run, record });
+                    }
+                    Ok(Some(head.record))
+                }
+            }
+        }
+
+        /// All records in key order; only for results that fit in memory
+        pub async fn collect(mut self) -> Result<Vec<T>, crate::Error> {
+            let mut records = Vec::with_capacity(self.len as usize);
+            while let Some(record) = self.next().await? {
+                records.push(record);
+            }
+            Ok(records)
+        }
+    }
+
+    /// Sort run and write it to a new file in `dir`
+    async fn spill<T, K, F>(run: &mut Vec<T>, key_fn: &F, dir: &Path, index: usize, token: &CancellationToken) -> Result<PathBuf, crate::Error>
+    where
+        T: FixedRecord,
+        K: Ord,
+        F: Fn(&T) -> K,
+    {
+        run.sort_by_key(key_fn);
+        let mut bytes = vec![0u8; run.len() * T::SIZE];
+        for (record, out) in run.iter().zip(bytes.chunks_exact_mut(T::SIZE)) {
+            record.encode(out);
+        }
+        run.clear();
+        let path = dir.join(format!("run-{}.bin", index));
+        super::io::write_binary_cancellable(&path, &bytes, token).await?;
+        Ok(path)
+    }
+
+    /// Open runs with read buffers of `buffer_size` bytes, along with the first record of each
+    async fn open_runs<T, K, F>(paths: &[PathBuf], key_fn: &F, buffer_size: usize) -> Result<(Vec<Run>, BinaryHeap<Head<K, T>>), crate::Error>
+    where
+        T: FixedRecord,
+        K: Ord,
+        F: Fn(&T) -> K,
+    {
+        let mut runs = Vec::with_capacity(paths.len());
+        let mut heap = BinaryHeap::with_capacity(paths.len());
+        for (index, path) in paths.iter().enumerate() {
+            let mut run = Run {
+                reader: BufReader::with_capacity(buffer_size, fs::File::open(path).await?),
+                buffer: vec![0u8; T::SIZE],
+            };
+            if let Some(record) = run.next::<T>().await? {
+                heap.push(Head { key: key_fn(&record), run: index, record });
+            }
+            runs.push(run);
+        }
+        Ok((runs, heap))
+    }
+
+    /// Merge the runs at `paths` into a new run at `path`, removing them once merged
+    async fn merge_runs<T, K, F>(paths: &[PathBuf], key_fn: &F, path: &Path, buffer_size: usize, token: &CancellationToken) -> Result<(), crate::Error>
+    where
+        T: FixedRecord,
+        K: Ord,
+        F: Fn(&T) -> K,
+    {
+        let (mut runs, mut heap) = open_runs(paths, key_fn, buffer_size).await?;
+        let mut writer = BufWriter::with_capacity(buffer_size, fs::File::create(path).await?);
+        let mut bytes = vec![0u8; T::SIZE];
+        while let Some(head) = heap.pop() {
+            if token.is_cancelled() {
+                return Err(crate::Error::Cancelled("Merging sorted runs".to_string()));
+            }
+            head.record.encode(&mut bytes);
+            writer.write_all(&bytes).await?;
+            if let Some(record) = runs[head.run].next::<T>().await? {
+                heap.push(Head { key: key_fn(&record), run: head.run, record });
+            }
+        }
+        writer.flush().await?;
+        drop(runs);
+        for path in paths {
+            fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    /// Sort `input` by `key_fn` within about `memory_budget` bytes
+    ///
+    /// Runs hold as many records as fit in the budget along with their
+    /// encoding; each merge splits the budget among the read buffers of at
+    /// most `MAX_FAN_IN` runs. Equal keys keep their input order. Nothing
+    /// is written to `scratch_dir` if the whole input fits in the budget.
+    pub async fn external_sort_by_key<T, K, S, F>(
+        input: S,
+        key_fn: F,
+        scratch_dir: impl AsRef<Path>,
+        memory_budget: usize,
+        token: &CancellationToken,
+    ) -> Result<SortedRecords<T, K, F>, crate::Error>
+    where
+        T: FixedRecord,
+        K: Ord,
+        S: Stream<Item = T>,
+        F: Fn(&T) -> K,
+    {
+        if T::SIZE == 0 {
+            return Err(crate::Error::Config("Records of size 0 cannot be sorted externally".to_string()));
+        }
+        let capacity = (memory_budget / (std::mem::size_of::<T>() + T::SIZE)).max(1);
+
+        let mut input = std::pin::pin!(input);
+        let mut scratch = None;
+        let mut paths = Vec::new();
+        let mut run = Vec::new();
+        let mut len = 0u64;
+        loop {
+            let record = tokio::select! {
+                biased;
+                _ = token.cancelled() => return Err(crate::Error::Cancelled("Sorting records".to_string())),
+                record = input.next() => record,
+            };
+            let Some(record) = record else {
+                break;
+            };
+            run.push(record);
+            len += 1;
+            if run.len() == capacity {
+                let dir = scratch_path(&mut scratch, scratch_dir.as_ref()).await?;
+                paths.push(spill(&mut run, &key_fn, dir, paths.len(), token).await?);
+            }
+        }
+
+        let Some(scratch) = scratch else {
+            run.sort_by_key(&key_fn);
+            return Ok(SortedRecords {
+                source: Source::Memory(run.into_iter()),
+                key_fn,
+                len,
+                runs: 0,
+                passes: 0,
+                token: token.clone(),
+            });
+        };
+        if !run.is_empty() {
+            paths.push(spill(&mut run, &key_fn, &scratch.path, paths.len(), token).await?);
+        }
+        drop(run);
+
+        // Consecutive groups merge in order, so equal keys keep their input order across passes
+        let spilled = paths.len();
+        let mut passes = 0;
+        while paths.len() > MAX_FAN_IN {
+            passes += 1;
+            // Read buffers of a group plus the write buffer of its merged run
+            let buffer_size = (memory_budget / (MAX_FAN_IN + 1)).max(T::SIZE);
+            let mut merged = Vec::with_capacity(paths.len().div_ceil(MAX_FAN_IN));
+            for group in paths.chunks(MAX_FAN_IN) {
+                let path = scratch.path.join(format!("pass-{}-run-{}.bin", passes, merged.len()));
+                merge_runs(group, &key_fn, &path, buffer_size, token).await?;
+                merged.push(path);
+            }
+            tracing::debug!("Merge pass {} left {} of {} runs", passes, merged.len(), paths.len());
+            paths = merged;
+        }
+
+        let buffer_size = (memory_budget / paths.len()).max(T::SIZE);
+        let (runs, heap) = open_runs(&paths, &key_fn, buffer_size).await?;
+        tracing::debug!("Merging {} records from {} sorted runs", len, runs.len());
+
+        Ok(SortedRecords {
+            runs: spilled,
+            passes,
+            source: Source::Merge { runs, heap, _scratch: scratch },
+            key_fn,
+            len,
+            token: token.clone(),
+        })
+    }
+
+    /// Interleave the bits of three coordinates of up to 21 bits into a Morton code
+    pub fn morton_code(coordinates: [usize; 3]) -> u64 {
+        let mut code = 0u64;
+        for bit in 0..21 {
+            for (axis, &c) in coordinates.iter().enumerate() {
+                code |= (((c as u64) >> bit) & 1) << (3 * bit + axis);
+            }
+        }
+        code
+    }
+
+    /// Points reordered along a Morton curve
+    #[derive(Debug, Clone)]
+    pub struct MortonOrder {
+        /// Points payload in Morton order
+        pub payload: ObjectPayload,
+        /// Original index of each point; reorder fields on the points with `reorder_field`
+        pub permutation: Vec<usize>,
+    }
+
+    /// Sort a Points payload by the Morton codes of its points within their bounds
+    ///
+    /// Only the codes and indices go through the external sort, so
+    /// `memory_budget` bounds the sort and not the payloads.
+    pub async fn sort_points_by_morton(
+        payload: &ObjectPayload,
+        scratch_dir: impl AsRef<Path>,
+        memory_budget: usize,
+        token: &CancellationToken,
+    ) -> Result<MortonOrder, crate::Error> {
+        let ObjectPayload::Points { coordinates } = payload else {
+            return Err(crate::Error::Compute("Morton sorting needs a Points payload".to_string()));
+        };
+        if coordinates.ncols() != 3 {
+            return Err(crate::Error::Compute(format!(
+                "Points of shape {:?} are not N x 3",
+                coordinates.shape()
+            )));
+        }
+
+        let mut lo = [f32::INFINITY; 3];
+        let mut hi = [f32::NEG_INFINITY; 3];
+        for point in coordinates.rows() {
+            for (axis, &c) in point.iter().enumerate() {
+                lo[axis] = lo[axis].min(c);
+                hi[axis] = hi[axis].max(c);
+            }
+        }
+        let cells = ((1u64 << 21) - 1) as f32;
+        let scale: [f32; 3] = std::array::from_fn(|a| if hi[a] > lo[a] { cells / (hi[a] - lo[a]) } else { 0.0 });
+
+        let codes = futures::stream::iter(coordinates.rows().into_iter().enumerate().map(|(i, point)| {
+            // NaN coordinates land in cell 0
+            let cell = std::array::from_fn(|a| ((point[a] - lo[a]) * scale[a]).clamp(0.0, cells) as usize);
+            (morton_code(cell), i as u64)
+        }));
+        let mut sorted = external_sort_by_key(codes, |record: &(u64, u64)| *record, scratch_dir, memory_budget, token).await?;
+
+        let mut permutation = Vec::with_capacity(coordinates.nrows());
+        while let Some((_, index)) = sorted.next().await? {
+            permutation.push(index as usize);
+        }
+        Ok(MortonOrder {
+            payload: ObjectPayload::Points { coordinates: coordinates.select(Axis(0), &permutation) },
+            permutation,
+        })
+    }
+
+    /// Per-point field reordered by a permutation of `sort_points_by_morton`
+    pub fn reorder_field(payload: &ObjectPayload, permutation: &[usize]) -> Result<ObjectPayload, crate::Error> {
+        let check = |len: usize| {
+            if len == permutation.len() {
+                Ok(())
+            } else {
+                Err(crate::Error::Compute(format!(
+                    "Field of {} values cannot be reordered by a permutation of {} points",
+                    len, permutation.len()
+                )))
+            }
+        };
+        let reordered = match payload {
+            ObjectPayload::VecScalar { data } => {
+                check(data.len())?;
+                ObjectPayload::VecScalar { data: data.select(Axis(0), permutation) }
+            }
+            ObjectPayload::VecScalarF64 { data } => {
+                check(data.len())?;
+                ObjectPayload::VecScalarF64 { data: data.select(Axis(0), permutation) }
+            }
+            ObjectPayload::VecVec3 { data } => {
+                check(data.nrows())?;
+                ObjectPayload::VecVec3 { data: data.select(Axis(0), permutation) }
+            }
+            ObjectPayload::VecVec3F64 { data } => {
+                check(data.nrows())?;
+                ObjectPayload::VecVec3F64 { data: data.select(Axis(0), permutation) }
+            }
+            ObjectPayload::VecInt32 { data } => {
+                check(data.len())?;
+                ObjectPayload::VecInt32 { data: data.select(Axis(0), permutation) }
+            }
+            ObjectPayload::VecInt64 { data } => {
+                check(data.len())?;
+                ObjectPayload::VecInt64 { data: data.select(Axis(0), permutation) }
+            }
+            _ => return Err(crate::Error::Compute("Only per-point fields can be reordered".to_string())),
+        };
+        Ok(reordered)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use ndarray::{array, Array2};
+
+        /// Parent of the sort's scratch directories, not created by the test
+        fn scratch_parent() -> PathBuf {
+            std::env::temp_dir().join(format!("vistle_sort_{}", uuid::Uuid::new_v4().simple()))
+        }
+
+        /// Entries left in a scratch parent, 0 if it was never created
+        fn leftovers(parent: &Path) -> usize {
+            std::fs::read_dir(parent).map(|entries| entries.count()).unwrap_or(0)
+        }
+
+        fn pseudo_random(n: usize) -> Vec<u64> {
+            let mut state = 0x2545_f491_4f6c_dd1du64;
+            (0..n)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    state >> 16
+                })
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn inputs_larger_than_the_budget_are_merged_from_runs() {
+            let parent = scratch_parent();
+            let values = pseudo_random(10_000);
+            // 256 records of 8 bytes, plus their encoding, per run
+            let budget = 4096;
+            let token = CancellationToken::new();
+
+            let sorted = external_sort_by_key(futures::stream::iter(values.clone()), |v: &u64| *v, &parent, budget, &token)
+                .await
+                .unwrap();
+            assert_eq!(sorted.len(), 10_000);
+            assert_eq!(sorted.runs(), 40);
+            assert_eq!(leftovers(&parent), 1);
+
+            let mut expected = values;
+            expected.sort_unstable();
+            assert_eq!(sorted.collect().await.unwrap(), expected);
+            assert_eq!(leftovers(&parent), 0);
+            std::fs::remove_dir_all(parent).ok();
+        }
+
+        #[tokio::test]
+        async fn more_runs_than_the_fan_in_merge_over_several_passes() {
+            let parent = scratch_parent();
+            // One record per run: 4196 runs merge into 66, those into the 2 the final merge reads
+            let records: Vec<(u32, u32)> = pseudo_random(MAX_FAN_IN * MAX_FAN_IN + 100).into_iter()
+                .enumerate()
+                .map(|(i, v)| ((v % 97) as u32, i as u32))
+                .collect();
+            let token = CancellationToken::new();
+
+            let sorted = external_sort_by_key(futures::stream::iter(records.clone()), |r: &(u32, u32)| r.0, &parent, 1, &token)
+                .await
+                .unwrap();
+            assert_eq!(sorted.runs(), records.len());
+            assert_eq!(sorted.passes(), 2);
+            // Intermediate runs are removed once merged
+            let scratch = std::fs::read_dir(&parent).unwrap().next().unwrap().unwrap().path();
+            assert_eq!(std::fs::read_dir(&scratch).unwrap().count(), 2);
+
+            let mut expected = records;
+            expected.sort_by_key(|r| r.0);
+            assert_eq!(sorted.collect().await.unwrap(), expected);
+            assert_eq!(leftovers(&parent), 0);
+            std::fs::remove_dir_all(parent).ok();
+        }
+
+        #[tokio::test]
+        async fn equal_keys_keep_their_input_order() {
+            let parent = scratch_parent();
+            let records: Vec<(u32, u32)> = (0..3000).map(|i| (i % 7, i)).collect();
+            let token = CancellationToken::new();
+
+            let sorted = external_sort_by_key(futures::stream::iter(records), |r: &(u32, u32)| r.0, &parent, 1024, &token)
+                .await
+                .unwrap();
+            assert!(sorted.runs() > 1);
+            let sorted = sorted.collect().await.unwrap();
+            assert_eq!(sorted.len(), 3000);
+            assert!(sorted.windows(2).all(|w| w[0].0 < w[1].0 || (w[0].0 == w[1].0 && w[0].1 < w[1].1)));
+            std::fs::remove_dir_all(parent).ok();
+        }
+
+        #[tokio::test]
+        async fn inputs_within_the_budget_are_sorted_in_memory() {
+            let parent = scratch_parent();
+            let token = CancellationToken::new();
+            let sorted = external_sort_by_key(futures::stream::iter([3i32, -1, 2]), |v: &i32| *v, &parent, 1 << 20, &token)
+                .await
+                .unwrap();
+            assert_eq!(sorted.runs(), 0);
+            assert_eq!(sorted.collect().await.unwrap(), [-1, 2, 3]);
+            assert!(!parent.exists());
+
+            let empty = external_sort_by_key(futures::stream::iter(Vec::<u64>::new()), |v: &u64| *v, &parent, 0, &token)
+                .await
+                .unwrap();
+            assert!(empty.is_empty());
+        }
+
+        #[tokio::test]
+        async fn runs_are_removed_when_a_merge_is_abandoned() {
+            let parent = scratch_parent();
+            let token = CancellationToken::new();
+            let mut sorted = external_sort_by_key(futures::stream::iter(pseudo_random(2000)), |v: &u64| *v, &parent, 1024, &token)
+                .await
+                .unwrap();
+            assert!(sorted.next().await.unwrap().is_some());
+            assert_eq!(leftovers(&parent), 1);
+            drop(sorted);
+            assert_eq!(leftovers(&parent), 0);
+            std::fs::remove_dir_all(parent).ok();
+        }
+
+        #[tokio::test]
+        async fn cancelling_while_reading_input_removes_spilled_runs() {
+            let parent = scratch_parent();
+            let token = CancellationToken::new();
+            let cancel = token.clone();
+            let input = futures::stream::iter(pseudo_random(5000).into_iter().enumerate()).map(move |(i, v)| {
+                if i == 3000 {
+                    cancel.cancel();
+                }
+                v
+            });
+
+            let result = external_sort_by_key(input, |v: &u64| *v, &parent, 1024, &token).await;
+            assert!(matches!(result, Err(crate::Error::Cancelled(_))));
+            assert_eq!(leftovers(&parent), 0);
+            std::fs::remove_dir_all(parent).ok();
+        }
+
+        #[tokio::test]
+        async fn cancelling_a_merge_fails_the_next_read() {
+            let parent = scratch_parent();
+            let token = CancellationToken::new();
+            let mut sorted = external_sort_by_key(futures::stream::iter(pseudo_random(2000)), |v: &u64| *v, &parent, 1024, &token)
+                .await
+                .unwrap();
+            sorted.next().await.unwrap();
+            token.cancel();
+            assert!(matches!(sorted.next().await, Err(crate::Error::Cancelled(_))));
+            drop(sorted);
+            assert_eq!(leftovers(&parent), 0);
+            std::fs::remove_dir_all(parent).ok();
+        }
+
+        #[tokio::test]
+        async fn failing_spills_leave_nothing_behind() {
+            // The scratch parent is a file, so no run can be written
+            let parent = scratch_parent();
+            std::fs::write(&parent, "not a directory").unwrap();
+            let token = CancellationToken::new();
+            let result = external_sort_by_key(futures::stream::iter(pseudo_random(1000)), |v: &u64| *v, &parent, 64, &token).await;
+            assert!(result.is_err());
+            std::fs::remove_file(parent).ok();
+        }
+
+        #[tokio::test]
+        async fn records_need_a_size() {
+            struct Nothing;
+            impl FixedRecord for Nothing {
+                const SIZE: usize = 0;
+                fn encode(&self, _: &mut [u8]) {}
+                fn decode(_: &[u8]) -> Self {
+                    Nothing
+                }
+            }
+            let token = CancellationToken::new();
+            let result = external_sort_by_key(futures::stream::iter([Nothing]), |_: &Nothing| 0, scratch_parent(), 64, &token).await;
+            assert!(matches!(result, Err(crate::Error::Config(_))));
+        }
+
+        #[test]
+        fn morton_codes_interleave_x_fastest() {
+            assert_eq!(morton_code([1, 0, 0]), 1);
+            assert_eq!(morton_code([0, 1, 0]), 2);
+            assert_eq!(morton_code([0, 0, 1]), 4);
+            assert_eq!(morton_code([3, 0, 0]), 0b1001);
+            assert_eq!(morton_code([2, 3, 1]), 0b11110);
+            assert_eq!(morton_code([(1 << 21) - 1; 3]), (1 << 63) - 1);
+        }
+
+        #[tokio::test]
+        async fn cube_corners_sort_along_the_curve() {
+            // Corners of the unit cube, in Morton order
+            let corners = [
+                [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0],
+            ];
+            let shuffled = [5, 2, 7, 0, 3, 6, 1, 4];
+            let coordinates = Array2::from_shape_fn((8, 3), |(p, a)| corners[shuffled[p]][a]);
+            let points = ObjectPayload::Points { coordinates };
+            let token = CancellationToken::new();
+
+            let order = sort_points_by_morton(&points, scratch_parent(), 1 << 20, &token).await.unwrap();
+            assert_eq!(order.permutation, [3, 6, 1, 4, 7, 0, 5, 2]);
+            let ObjectPayload::Points { coordinates } = &order.payload else {
+                panic!("sorting did not give points");
+            };
+            for (p, corner) in corners.iter().enumerate() {
+                assert_eq!(coordinates.row(p).to_vec(), corner.to_vec());
+            }
+
+            let labels = ObjectPayload::VecInt32 { data: array![5, 2, 7, 0, 3, 6, 1, 4] };
+            let ObjectPayload::VecInt32 { data } = reorder_field(&labels, &order.permutation).unwrap() else {
+                panic!("reordering changed the field type");
+            };
+            assert_eq!(data.to_vec(), [0, 1, 2, 3, 4, 5, 6, 7]);
+        }
+
+        #[tokio::test]
+        async fn point_clouds_larger_than_the_budget_sort_by_morton_code() {
+            let parent = scratch_parent();
+            let values = pseudo_random(3 * 3000);
+            let mut coordinates = Array2::from_shape_fn((3000, 3), |(p, a)| (values[3 * p + a] % 1000) as f32 / 10.0);
+            // Pin the bounds so the codes below match the sort's
+            let (lo, hi) = (0.0f32, 100.0f32);
+            coordinates.row_mut(0).fill(lo);
+            coordinates.row_mut(1).fill(hi);
+            let points = ObjectPayload::Points { coordinates: coordinates.clone() };
+            let token = CancellationToken::new();
+
+            let order = sort_points_by_morton(&points, &parent, 2048, &token).await.unwrap();
+            let mut seen = order.permutation.clone();
+            seen.sort_unstable();
+            assert_eq!(seen, (0..3000).collect::<Vec<_>>());
+
+            let ObjectPayload::Points { coordinates: sorted } = &order.payload else {
+                panic!("sorting did not give points");
+            };
+            assert_eq!(*sorted, coordinates.select(Axis(0), &order.permutation));
+
+            let cells = ((1u64 << 21) - 1) as f32;
+            let codes: Vec<u64> = sorted
+                .rows()
+                .into_iter()
+                .map(|p| morton_code(std::array::from_fn(|a| ((p[a] - lo) * (cells / (hi - lo))).clamp(0.0, cells) as usize)))
+                .collect();
+            assert!(codes.windows(2).all(|w| w[0] <= w[1]));
+            assert_eq!(leftovers(&parent), 0);
+            std::fs::remove_dir_all(parent).ok();
+        }
+
+        #[test]
+        fn fields_must_match_the_permutation() {
+            let scalars = ObjectPayload::VecScalar { data: array![1.0, 2.0, 3.0] };
+            let ObjectPayload::VecScalar { data } = reorder_field(&scalars, &[2, 0, 1]).unwrap() else {
+                panic!("reordering changed the field type");
+            };
+            assert_eq!(data.to_vec(), [3.0, 1.0, 2.0]);
+
+            let message = reorder_field(&scalars, &[1, 0]).unwrap_err().to_string();
+            assert!(message.contains("Field of 3 values cannot be reordered by a permutation of 2 points"), "{}", message);
+            let points = ObjectPayload::Points { coordinates: Array2::zeros((3, 3)) };
+            assert!(reorder_field(&points, &[0, 1, 2]).is_err());
+        }
+
+        #[tokio::test]
+        async fn only_points_sort_by_morton_code() {
+            let token = CancellationToken::new();
+            let scalars = ObjectPayload::VecScalar { data: array![1.0] };
+            assert!(sort_points_by_morton(&scalars, scratch_parent(), 64, &token).await.is_err());
+        }
+    }
+}